@@ -523,6 +523,7 @@ struct SettingsView: View {
     @State private var openAIAPIKey = ""
     @State private var serverHost = ""
     @State private var serverPort = ""
+    @StateObject private var serverDiscovery = ServerDiscovery()
     @State private var hasGoalDate = false
     @State private var goalDate = Date()
     @State private var profileEstimate: ProfilePhysioEstimate?
@@ -701,6 +702,46 @@ struct SettingsView: View {
         UserDefaults.standard.set(trimmed, forKey: RemoteHTTPRepository.serverURLDefaultsKey)
     }
 
+    @ViewBuilder
+    private var discoveredServersList: some View {
+        Text(L10n.choose(simplifiedChinese: "局域网中的服务端", english: "Servers on This Network"))
+            .font(.subheadline)
+        if serverDiscovery.servers.isEmpty {
+            Text(
+                serverDiscovery.lastMessage ?? L10n.choose(
+                    simplifiedChinese: "尚未发现开启 FRICU_MDNS=1 的服务端。",
+                    english: "No server with FRICU_MDNS=1 found yet."
+                )
+            )
+            .font(.caption)
+            .foregroundStyle(.secondary)
+        }
+        ForEach(serverDiscovery.servers) { server in
+            HStack {
+                Text(server.name)
+                Text(server.baseURL?.absoluteString ?? "-")
+                    .font(.caption)
+                    .foregroundStyle(.secondary)
+                Spacer()
+                Button(L10n.choose(simplifiedChinese: "使用", english: "Use")) {
+                    useDiscoveredServer(server)
+                }
+                .buttonStyle(.bordered)
+                // The store's client only speaks plain HTTP to the host and port fields.
+                .disabled(server.usesTLS || server.endpointHost == nil)
+            }
+        }
+    }
+
+    private func useDiscoveredServer(_ server: DiscoveredServer) {
+        guard let host = server.endpointHost else { return }
+        serverDiscovery.use(server)
+        loadServerURLField()
+        serverHost = host
+        serverPort = String(server.port)
+        store.updateServerEndpoint(host: host, port: serverPort)
+    }
+
     private func loadServerURLField() {
         serverBaseURL = UserDefaults.standard.string(forKey: RemoteHTTPRepository.serverURLDefaultsKey)
             ?? RemoteHTTPRepository.fallbackServerURLString
@@ -811,6 +852,10 @@ struct SettingsView: View {
         .onAppear {
             loadFieldsFromProfile()
             loadServerURLField()
+            serverDiscovery.start()
+        }
+        .onDisappear {
+            serverDiscovery.stop()
         }
         .onChange(of: store.currentAccountID) { _, _ in
             profileEstimate = nil
@@ -1359,6 +1404,7 @@ struct SettingsView: View {
                     ))
                     .font(.caption)
                     .foregroundStyle(.secondary)
+                    discoveredServersList
                 }
             }
         }
@@ -758,13 +758,16 @@ struct WorkoutSegment: Codable, Identifiable {
     var intensityPercentFTP: Int
     var cadence: Int?
     var note: String
+    /// The leg's sport in a brick or multi-sport workout; nil means the workout's sport.
+    var sport: SportType?
 
-    init(id: UUID = UUID(), minutes: Int, intensityPercentFTP: Int, cadence: Int? = nil, note: String = "") {
+    init(id: UUID = UUID(), minutes: Int, intensityPercentFTP: Int, cadence: Int? = nil, note: String = "", sport: SportType? = nil) {
         self.id = id
         self.minutes = minutes
         self.intensityPercentFTP = intensityPercentFTP
         self.cadence = cadence
         self.note = note
+        self.sport = sport
     }
 }
 
@@ -0,0 +1,165 @@
+import Foundation
+import Network
+
+/// A fricu-server found on the local network through its `_fricu._tcp` DNS-SD advertisement
+/// (the server's `FRICU_MDNS=1`).
+struct DiscoveredServer: Identifiable, Equatable {
+    var name: String
+    var host: String
+    var port: Int
+    var usesTLS: Bool
+    var protocolVersion: Int?
+
+    var id: String { name }
+
+    var baseURL: URL? {
+        Self.baseURL(host: host, port: port, usesTLS: usesTLS)
+    }
+
+    var endpointHost: String? {
+        Self.endpointHost(host)
+    }
+
+    /// The host as a URL authority: IPv6 literals are bracketed and a link-local zone (`%en0`) or the
+    /// trailing dot of a `.local.` name is dropped. This is also what the Server Connection fields take.
+    static func endpointHost(_ host: String) -> String? {
+        var normalized = host.trimmingCharacters(in: .whitespacesAndNewlines)
+        if let zone = normalized.firstIndex(of: "%") {
+            normalized = String(normalized[..<zone])
+        }
+        if normalized.hasSuffix(".") {
+            normalized.removeLast()
+        }
+        guard !normalized.isEmpty else { return nil }
+        return normalized.contains(":") ? "[\(normalized)]" : normalized
+    }
+
+    /// Builds `http(s)://host:port` from a resolved endpoint.
+    static func baseURL(host: String, port: Int, usesTLS: Bool) -> URL? {
+        guard let authority = endpointHost(host), (1...65_535).contains(port) else { return nil }
+        return URL(string: "\(usesTLS ? "https" : "http")://\(authority):\(port)")
+    }
+
+    /// Reads the advertisement's TXT keys: `tls=1` and `protocol=<sync protocol version>`.
+    static func parseTXT(_ entries: [String: String]) -> (usesTLS: Bool, protocolVersion: Int?) {
+        let tls = entries["tls"]?.trimmingCharacters(in: .whitespaces) == "1"
+        let version = entries["protocol"].flatMap { Int($0.trimmingCharacters(in: .whitespaces)) }
+        return (tls, version)
+    }
+}
+
+/// Browses for fricu-servers on the LAN so the user can pick one instead of typing an address.
+/// Each found service is resolved to a host and port with a short-lived connection. Settings lists
+/// the results under Server Connection; choosing one stores its URL under
+/// `RemoteHTTPRepository.serverURLDefaultsKey` and applies its host and port to the store.
+final class ServerDiscovery: ObservableObject {
+    static let serviceType = "_fricu._tcp"
+
+    @Published private(set) var servers: [DiscoveredServer] = []
+    @Published private(set) var isBrowsing = false
+    @Published private(set) var lastMessage: String?
+
+    private let queue = DispatchQueue(label: "fricu.server-discovery")
+    private var browser: NWBrowser?
+    private var resolvers: [String: NWConnection] = [:]
+
+    func start() {
+        guard browser == nil else { return }
+        let parameters = NWParameters()
+        parameters.includePeerToPeer = false
+        let browser = NWBrowser(for: .bonjourWithTXTRecord(type: Self.serviceType, domain: nil), using: parameters)
+        browser.stateUpdateHandler = { [weak self] state in
+            DispatchQueue.main.async {
+                switch state {
+                case .ready:
+                    self?.isBrowsing = true
+                case .failed(let error):
+                    self?.isBrowsing = false
+                    self?.lastMessage = "Discovery failed: \(error.localizedDescription)"
+                case .cancelled:
+                    self?.isBrowsing = false
+                default:
+                    break
+                }
+            }
+        }
+        browser.browseResultsChangedHandler = { [weak self] results, _ in
+            self?.handle(results: results)
+        }
+        self.browser = browser
+        browser.start(queue: queue)
+    }
+
+    func stop() {
+        browser?.cancel()
+        browser = nil
+        queue.async { [weak self] in
+            self?.resolvers.values.forEach { $0.cancel() }
+            self?.resolvers.removeAll()
+        }
+        servers = []
+    }
+
+    /// Makes the chosen server the one `RemoteHTTPRepository` talks to from now on.
+    func use(_ server: DiscoveredServer, defaults: UserDefaults = .standard) {
+        guard let url = server.baseURL else { return }
+        defaults.set(url.absoluteString, forKey: RemoteHTTPRepository.serverURLDefaultsKey)
+    }
+
+    private func handle(results: Set<NWBrowser.Result>) {
+        var names = Set<String>()
+        for result in results {
+            guard case let .service(name, _, _, _) = result.endpoint else { continue }
+            names.insert(name)
+            var txt: [String: String] = [:]
+            if case let .bonjour(record) = result.metadata {
+                txt = record.dictionary
+            }
+            resolve(name: name, endpoint: result.endpoint, txt: txt)
+        }
+        for (name, connection) in resolvers where !names.contains(name) {
+            connection.cancel()
+            resolvers[name] = nil
+        }
+        DispatchQueue.main.async {
+            self.servers.removeAll { !names.contains($0.name) }
+        }
+    }
+
+    private func resolve(name: String, endpoint: NWEndpoint, txt: [String: String]) {
+        guard resolvers[name] == nil else { return }
+        let connection = NWConnection(to: endpoint, using: .tcp)
+        resolvers[name] = connection
+        connection.stateUpdateHandler = { [weak self, weak connection] state in
+            guard let self, let connection else { return }
+            switch state {
+            case .ready:
+                if case let .hostPort(host, port) = connection.currentPath?.remoteEndpoint {
+                    let (tls, version) = DiscoveredServer.parseTXT(txt)
+                    let server = DiscoveredServer(
+                        name: name,
+                        host: "\(host)",
+                        port: Int(port.rawValue),
+                        usesTLS: tls,
+                        protocolVersion: version
+                    )
+                    DispatchQueue.main.async {
+                        self.servers.removeAll { $0.name == name }
+                        self.servers.append(server)
+                        self.servers.sort { $0.name < $1.name }
+                    }
+                }
+                connection.cancel()
+            case .failed, .cancelled:
+                self.queue.async {
+                    if self.resolvers[name] === connection {
+                        self.resolvers[name] = nil
+                    }
+                }
+            default:
+                break
+            }
+        }
+        connection.start(queue: queue)
+    }
+}
@@ -0,0 +1,63 @@
+import XCTest
+@testable import FricuApp
+
+/// Unit tests for turning a resolved `_fricu._tcp` advertisement into a server URL.
+final class ServerDiscoveryTests: XCTestCase {
+    /// Verifies IPv4 and `.local` hosts become plain http URLs with the advertised port.
+    func testBaseURLForIPv4AndLocalHosts() {
+        XCTAssertEqual(
+            DiscoveredServer.baseURL(host: "192.168.1.20", port: 8080, usesTLS: false)?.absoluteString,
+            "http://192.168.1.20:8080"
+        )
+        XCTAssertEqual(
+            DiscoveredServer.baseURL(host: "garage.local.", port: 8443, usesTLS: true)?.absoluteString,
+            "https://garage.local:8443"
+        )
+    }
+
+    /// Verifies IPv6 literals are bracketed and their interface zone is dropped.
+    func testBaseURLBracketsIPv6AndDropsZone() {
+        XCTAssertEqual(
+            DiscoveredServer.baseURL(host: "fe80::1%en0", port: 8080, usesTLS: false)?.absoluteString,
+            "http://[fe80::1]:8080"
+        )
+    }
+
+    /// Verifies the host handed to the Server Connection fields matches the URL's authority.
+    func testEndpointHostMatchesBaseURLAuthority() {
+        let server = DiscoveredServer(name: "Fricu on garage", host: "fe80::1%en0", port: 8080, usesTLS: false, protocolVersion: 1)
+        XCTAssertEqual(server.endpointHost, "[fe80::1]")
+        XCTAssertEqual(DiscoveredServer.endpointHost("garage.local."), "garage.local")
+        XCTAssertNil(DiscoveredServer.endpointHost(" "))
+        XCTAssertEqual(URL(string: "http://\(server.endpointHost!):\(server.port)"), server.baseURL)
+    }
+
+    /// Verifies empty hosts and out-of-range ports yield no URL.
+    func testBaseURLRejectsInvalidEndpoints() {
+        XCTAssertNil(DiscoveredServer.baseURL(host: " ", port: 8080, usesTLS: false))
+        XCTAssertNil(DiscoveredServer.baseURL(host: "192.168.1.20", port: 0, usesTLS: false))
+        XCTAssertNil(DiscoveredServer.baseURL(host: "192.168.1.20", port: 70_000, usesTLS: false))
+    }
+
+    /// Verifies the TXT record's tls and protocol keys are read, with defaults when absent.
+    func testParseTXTReadsTLSAndProtocol() {
+        let advertised = DiscoveredServer.parseTXT(["txtvers": "1", "path": "/", "tls": "1", "protocol": "1"])
+        XCTAssertTrue(advertised.usesTLS)
+        XCTAssertEqual(advertised.protocolVersion, 1)
+
+        let bare = DiscoveredServer.parseTXT([:])
+        XCTAssertFalse(bare.usesTLS)
+        XCTAssertNil(bare.protocolVersion)
+    }
+
+    /// Verifies choosing a server stores its URL where RemoteHTTPRepository reads it.
+    func testUseStoresServerURL() throws {
+        let defaults = try XCTUnwrap(UserDefaults(suiteName: "ServerDiscoveryTests"))
+        defer { defaults.removePersistentDomain(forName: "ServerDiscoveryTests") }
+        let server = DiscoveredServer(name: "Fricu on garage", host: "192.168.1.20", port: 8080, usesTLS: false, protocolVersion: 1)
+
+        ServerDiscovery().use(server, defaults: defaults)
+
+        XCTAssertEqual(defaults.string(forKey: RemoteHTTPRepository.serverURLDefaultsKey), "http://192.168.1.20:8080")
+    }
+}
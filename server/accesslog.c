@@ -0,0 +1,91 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * One access log entry per request, written when the request is done: method, path, route,
+ * status, latency, request and response body bytes, the account, the client address and the
+ * request id. The request id is the caller's X-Request-Id (or X-Log-Id) when it sent a usable
+ * one and a generated id otherwise; it is returned in both headers and is the logid= of every
+ * other line the request logs, so a failed sync can be found from either side. With
+ * FRICU_LOG_FORMAT=json the entry is an object with those fields ("event":"http_request");
+ * FRICU_ACCESS_LOG=0 turns the entry off.
+ */
+
+static __thread double g_started_ms;
+static __thread int g_status;
+static __thread size_t g_response_bytes;
+
+void accesslog_begin(void) {
+    g_started_ms = slowlog_now_ms();
+    g_status = 0;
+    g_response_bytes = 0;
+}
+
+void accesslog_note_response(int code, size_t body_len) {
+    g_status = code;
+    g_response_bytes = body_len;
+}
+
+void accesslog_end(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *enabled = getenv("FRICU_ACCESS_LOG");
+    if (enabled && strcmp(enabled, "0") == 0) return;
+    double latency_ms = slowlog_now_ms() - g_started_ms;
+    int status = deadline_expired() ? 504 : g_status;
+    char route[160] = {0};
+    char client[64] = {0};
+    slowlog_endpoint_label(req->method, req->path, route, sizeof(route));
+    const char *route_path = strchr(route, ' ');
+    request_client_address(fd, req, client, sizeof(client));
+    const char *account = ctx->account_id[0] != '\0' ? ctx->account_id : "-";
+
+    char message[1024] = {0};
+    snprintf(
+        message,
+        sizeof(message),
+        "ACCESS %s %s -> %d latency_ms=%.3f bytes_in=%zu bytes_out=%zu request_id=%s account=%s client=%s",
+        req->method,
+        req->path,
+        status,
+        latency_ms,
+        req->body_len,
+        g_response_bytes,
+        ctx->log_id,
+        account,
+        client);
+    strbuf_t fields;
+    strbuf_init(&fields);
+    strbuf_append(&fields, "\"event\":\"http_request\",\"request_id\":", 36);
+    strbuf_append_json_string(&fields, ctx->log_id);
+    strbuf_append(&fields, ",\"method\":", 10);
+    strbuf_append_json_string(&fields, req->method);
+    strbuf_append(&fields, ",\"path\":", 8);
+    strbuf_append_json_string(&fields, req->path);
+    strbuf_append(&fields, ",\"route\":", 9);
+    strbuf_append_json_string(&fields, route_path ? route_path + 1 : req->path);
+    strbuf_appendf(
+        &fields,
+        ",\"status\":%d,\"latency_ms\":%.3f,\"bytes_in\":%zu,\"bytes_out\":%zu,\"retry\":%d,\"account\":",
+        status,
+        latency_ms,
+        req->body_len,
+        g_response_bytes,
+        ctx->retry_attempt);
+    strbuf_append_json_string(&fields, account);
+    strbuf_append(&fields, ",\"client\":", 10);
+    strbuf_append_json_string(&fields, client);
+    const char *level = "INFO";
+    if (status >= 500) {
+        level = "ERROR";
+    } else if (status >= 400) {
+        level = "WARN";
+    }
+    log_event(level, message, fields.failed ? "" : strbuf_cstr(&fields));
+    strbuf_free(&fields);
+}
@@ -0,0 +1,345 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <time.h>
+
+/*
+ * Summaries of device activity files (FIT and TCX) for server-side imports. Only what an activity
+ * record needs is read: start time, sport, duration, distance, average heart rate and the 1 Hz power
+ * and heart-rate samples; the session message wins over values derived from the records.
+ */
+
+#define FIT_EPOCH_OFFSET 631065600LL
+#define FIT_MESG_FILE_ID 0
+#define FIT_MESG_SESSION 18
+#define FIT_MESG_RECORD 20
+#define ACTIVITY_FILE_MAX_SAMPLES (48 * 3600)
+
+typedef struct {
+    uint16_t global;
+    int big_endian;
+    int field_count;
+    unsigned char nums[255];
+    unsigned char sizes[255];
+    size_t dev_size;
+    size_t total_size;
+    int defined;
+} fit_definition_t;
+
+static int push_sample(double **values, size_t *count, size_t *cap, double value) {
+    if (*count >= ACTIVITY_FILE_MAX_SAMPLES) return 0;
+    if (*count == *cap) {
+        size_t next = *cap ? *cap * 2 : 1024;
+        double *grown = realloc(*values, next * sizeof(double));
+        if (!grown) return -1;
+        *values = grown;
+        *cap = next;
+    }
+    (*values)[(*count)++] = value;
+    return 0;
+}
+
+static void format_epoch(long long epoch, char *out, size_t out_len) {
+    time_t t = (time_t)epoch;
+    struct tm tm_utc;
+    gmtime_r(&t, &tm_utc);
+    strftime(out, out_len, "%Y-%m-%dT%H:%M:%SZ", &tm_utc);
+}
+
+static uint32_t fit_read(const unsigned char *p, size_t size, int big_endian) {
+    uint32_t v = 0;
+    for (size_t i = 0; i < size && i < 4; i++) {
+        v |= (uint32_t)p[big_endian ? size - 1 - i : i] << (8 * i);
+    }
+    return v;
+}
+
+/* Returns 1 with the value when the field is present with the expected width and not the FIT "invalid" marker. */
+static int fit_field(const unsigned char *p, size_t size, int big_endian, size_t width, uint32_t *out) {
+    if (size != width) return 0;
+    uint32_t v = fit_read(p, size, big_endian);
+    uint32_t invalid = width == 1 ? 0xFFu : width == 2 ? 0xFFFFu : 0xFFFFFFFFu;
+    if (v == invalid) return 0;
+    *out = v;
+    return 1;
+}
+
+static const char *fit_sport(uint32_t sport, uint32_t sub_sport) {
+    switch (sport) {
+    case 1:
+        return "running";
+    case 2:
+        return "cycling";
+    case 5:
+        return "swimming";
+    case 10:
+        return sub_sport == 20 ? "strength" : NULL;
+    default:
+        return NULL;
+    }
+}
+
+static int summarize_fit(const unsigned char *data, size_t len, activity_file_summary_t *out) {
+    if (len < 12) return -1;
+    size_t header_size = data[0];
+    if ((header_size != 12 && header_size != 14) || len < header_size || memcmp(data + 8, ".FIT", 4) != 0) return -1;
+    size_t data_size = fit_read(data + 4, 4, 0);
+    size_t end = header_size + data_size;
+    if (end > len) end = len;
+
+    fit_definition_t *defs = calloc(16, sizeof(fit_definition_t));
+    if (!defs) return -1;
+    size_t pos = header_size;
+    uint32_t last_timestamp = 0;
+    uint32_t first_record = 0;
+    uint32_t last_record = 0;
+    uint32_t created = 0;
+    double record_distance_m = 0.0;
+    double hr_sum = 0.0;
+    size_t hr_n = 0;
+    size_t power_cap = 0;
+    size_t hr_cap = 0;
+    int session = 0;
+    int rc = 0;
+    while (pos < end && rc == 0) {
+        unsigned char header = data[pos++];
+        if (!(header & 0x80) && (header & 0x40)) {
+            fit_definition_t *def = &defs[header & 0x0F];
+            if (pos + 5 > end) break;
+            def->big_endian = data[pos + 1] == 1;
+            def->global = (uint16_t)fit_read(data + pos + 2, 2, def->big_endian);
+            def->field_count = data[pos + 4];
+            pos += 5;
+            if (pos + (size_t)def->field_count * 3 > end) break;
+            def->total_size = 0;
+            for (int i = 0; i < def->field_count; i++) {
+                def->nums[i] = data[pos];
+                def->sizes[i] = data[pos + 1];
+                def->total_size += data[pos + 1];
+                pos += 3;
+            }
+            def->dev_size = 0;
+            if (header & 0x20) {
+                if (pos >= end) break;
+                int dev_count = data[pos++];
+                if (pos + (size_t)dev_count * 3 > end) break;
+                for (int i = 0; i < dev_count; i++) {
+                    def->dev_size += data[pos + 1];
+                    pos += 3;
+                }
+            }
+            def->defined = 1;
+            continue;
+        }
+
+        fit_definition_t *def = &defs[header & 0x80 ? (header >> 5) & 0x03 : header & 0x0F];
+        if (!def->defined || pos + def->total_size + def->dev_size > end) break;
+        uint32_t timestamp = 0;
+        int has_timestamp = 0;
+        if (header & 0x80) {
+            uint32_t offset = header & 0x1F;
+            timestamp = (last_timestamp & ~0x1Fu) + offset;
+            if (offset < (last_timestamp & 0x1Fu)) timestamp += 0x20;
+            has_timestamp = last_timestamp != 0;
+        }
+        const unsigned char *field = data + pos;
+        uint32_t v = 0;
+        uint32_t sport = 0xFF;
+        uint32_t sub_sport = 0;
+        double power = -1.0;
+        double hr = -1.0;
+        for (int i = 0; i < def->field_count; i++) {
+            size_t size = def->sizes[i];
+            unsigned char num = def->nums[i];
+            if (num == 253 && fit_field(field, size, def->big_endian, 4, &v)) {
+                timestamp = v;
+                has_timestamp = 1;
+            } else if (def->global == FIT_MESG_FILE_ID && num == 4 && fit_field(field, size, def->big_endian, 4, &v)) {
+                created = v;
+            } else if (def->global == FIT_MESG_SESSION) {
+                if (num == 2 && fit_field(field, size, def->big_endian, 4, &v)) {
+                    format_epoch((long long)v + FIT_EPOCH_OFFSET, out->date, sizeof(out->date));
+                } else if (num == 5 && fit_field(field, size, def->big_endian, 1, &v)) {
+                    sport = v;
+                } else if (num == 6 && fit_field(field, size, def->big_endian, 1, &v)) {
+                    sub_sport = v;
+                } else if (num == 8 && fit_field(field, size, def->big_endian, 4, &v)) {
+                    out->duration_sec = v / 1000.0;
+                } else if (num == 7 && out->duration_sec <= 0.0 && fit_field(field, size, def->big_endian, 4, &v)) {
+                    out->duration_sec = v / 1000.0;
+                } else if (num == 9 && fit_field(field, size, def->big_endian, 4, &v)) {
+                    out->distance_km = v / 100000.0;
+                } else if (num == 16 && fit_field(field, size, def->big_endian, 1, &v)) {
+                    out->avg_hr = (int)v;
+                } else if (num == 34 && fit_field(field, size, def->big_endian, 2, &v)) {
+                    out->normalized_power = v;
+                } else if (num == 35 && fit_field(field, size, def->big_endian, 2, &v)) {
+                    out->tss = v / 10.0;
+                }
+            } else if (def->global == FIT_MESG_RECORD) {
+                if (num == 7 && fit_field(field, size, def->big_endian, 2, &v)) {
+                    power = v;
+                } else if (num == 3 && fit_field(field, size, def->big_endian, 1, &v)) {
+                    hr = v;
+                } else if (num == 5 && fit_field(field, size, def->big_endian, 4, &v)) {
+                    record_distance_m = v / 100.0;
+                }
+            }
+            field += size;
+        }
+        pos += def->total_size + def->dev_size;
+        if (has_timestamp) last_timestamp = timestamp;
+        if (def->global == FIT_MESG_SESSION) {
+            session = 1;
+            const char *name = sport != 0xFF ? fit_sport(sport, sub_sport) : NULL;
+            if (name) snprintf(out->sport, sizeof(out->sport), "%s", name);
+        } else if (def->global == FIT_MESG_RECORD) {
+            if (has_timestamp) {
+                if (first_record == 0) first_record = timestamp;
+                last_record = timestamp;
+            }
+            if (power >= 0.0 && push_sample(&out->power, &out->power_count, &power_cap, power) != 0) rc = -1;
+            if (hr > 0.0) {
+                if (push_sample(&out->heart_rate, &out->heart_rate_count, &hr_cap, hr) != 0) rc = -1;
+                hr_sum += hr;
+                hr_n++;
+            }
+        }
+    }
+    free(defs);
+    if (rc != 0) return -1;
+
+    uint32_t start = first_record ? first_record : created;
+    if (out->date[0] == '\0' && start) format_epoch((long long)start + FIT_EPOCH_OFFSET, out->date, sizeof(out->date));
+    if (out->duration_sec <= 0.0 && last_record > first_record) out->duration_sec = last_record - first_record;
+    if (out->distance_km <= 0.0) out->distance_km = record_distance_m / 1000.0;
+    if (out->avg_hr <= 0 && hr_n > 0) out->avg_hr = (int)(hr_sum / hr_n + 0.5);
+    return session || first_record || created ? 0 : -1;
+}
+
+/* Text between <tag> and </tag> (namespace prefixes on the tag are tolerated) inside [from, limit). */
+static const char *tcx_tag(const char *from, const char *limit, const char *tag, char *out, size_t out_len) {
+    size_t tag_len = strlen(tag);
+    for (const char *p = from; p && p < limit; p++) {
+        p = memchr(p, '<', (size_t)(limit - p));
+        if (!p) return NULL;
+        const char *name = p + 1;
+        const char *colon = NULL;
+        const char *q = name;
+        while (q < limit && *q != '>' && *q != ' ' && *q != '/') {
+            if (*q == ':') colon = q;
+            q++;
+        }
+        if (colon) name = colon + 1;
+        if ((size_t)(q - name) != tag_len || strncmp(name, tag, tag_len) != 0 || p[1] == '/') continue;
+        const char *gt = memchr(q, '>', (size_t)(limit - q));
+        if (!gt) return NULL;
+        const char *close = memchr(gt, '<', (size_t)(limit - gt));
+        if (!close) return NULL;
+        size_t n = (size_t)(close - gt - 1);
+        if (out_len > 0) {
+            if (n >= out_len) n = out_len - 1;
+            memcpy(out, gt + 1, n);
+            out[n] = '\0';
+        }
+        return close;
+    }
+    return NULL;
+}
+
+static int summarize_tcx(const unsigned char *data, size_t len, activity_file_summary_t *out) {
+    const char *text = (const char *)data;
+    const char *end = text + len;
+    const char *activity = memmem(text, len, "<Activity ", 10);
+    if (!activity) return -1;
+    const char *sport = memmem(activity, (size_t)(end - activity), "Sport=\"", 7);
+    if (sport) {
+        sport += 7;
+        if (strncasecmp(sport, "Biking", 6) == 0) snprintf(out->sport, sizeof(out->sport), "cycling");
+        if (strncasecmp(sport, "Running", 7) == 0) snprintf(out->sport, sizeof(out->sport), "running");
+    }
+    char value[64] = {0};
+    if (tcx_tag(activity, end, "Id", value, sizeof(value))) {
+        int y = 0, mo = 0, d = 0, h = 0, mi = 0, s = 0;
+        if (sscanf(value, "%d-%d-%dT%d:%d:%d", &y, &mo, &d, &h, &mi, &s) == 6) {
+            snprintf(out->date, sizeof(out->date), "%04d-%02d-%02dT%02d:%02d:%02dZ", y, mo, d, h, mi, s);
+        }
+    }
+
+    double hr_weighted = 0.0;
+    double hr_seconds = 0.0;
+    size_t power_cap = 0;
+    size_t hr_cap = 0;
+    const char *lap = activity;
+    while ((lap = memmem(lap, (size_t)(end - lap), "<Lap", 4)) != NULL) {
+        const char *lap_end = memmem(lap, (size_t)(end - lap), "</Lap>", 6);
+        if (!lap_end) lap_end = end;
+        const char *track = memmem(lap, (size_t)(lap_end - lap), "<Track", 6);
+        const char *summary_end = track ? track : lap_end;
+        double lap_sec = 0.0;
+        if (tcx_tag(lap, summary_end, "TotalTimeSeconds", value, sizeof(value))) lap_sec = atof(value);
+        if (tcx_tag(lap, summary_end, "DistanceMeters", value, sizeof(value))) out->distance_km += atof(value) / 1000.0;
+        const char *avg = memmem(lap, (size_t)(summary_end - lap), "AverageHeartRateBpm", 19);
+        if (avg && tcx_tag(avg, summary_end, "Value", value, sizeof(value)) && lap_sec > 0.0) {
+            hr_weighted += atof(value) * lap_sec;
+            hr_seconds += lap_sec;
+        }
+        out->duration_sec += lap_sec;
+
+        for (const char *point = summary_end; point && point < lap_end;) {
+            point = memmem(point, (size_t)(lap_end - point), "<Trackpoint>", 12);
+            if (!point) break;
+            const char *point_end = memmem(point, (size_t)(lap_end - point), "</Trackpoint>", 13);
+            if (!point_end) point_end = lap_end;
+            const char *hr = memmem(point, (size_t)(point_end - point), "<HeartRateBpm", 13);
+            if (hr && tcx_tag(hr, point_end, "Value", value, sizeof(value)) &&
+                push_sample(&out->heart_rate, &out->heart_rate_count, &hr_cap, atof(value)) != 0) {
+                return -1;
+            }
+            if (tcx_tag(point, point_end, "Watts", value, sizeof(value)) &&
+                push_sample(&out->power, &out->power_count, &power_cap, atof(value)) != 0) {
+                return -1;
+            }
+            point = point_end;
+        }
+        lap = lap_end;
+    }
+    if (hr_seconds > 0.0) out->avg_hr = (int)(hr_weighted / hr_seconds + 0.5);
+    return out->date[0] != '\0' || out->duration_sec > 0.0 ? 0 : -1;
+}
+
+const char *activity_file_type(const char *file_name, const unsigned char *data, size_t len) {
+    size_t n = file_name ? strlen(file_name) : 0;
+    if (n > 4 && strcasecmp(file_name + n - 4, ".fit") == 0) return "fit";
+    if (n > 4 && strcasecmp(file_name + n - 4, ".tcx") == 0) return "tcx";
+    if (data && len >= 12 && (data[0] == 12 || data[0] == 14) && memcmp(data + 8, ".FIT", 4) == 0) return "fit";
+    return NULL;
+}
+
+int activity_file_summarize(const char *type, const unsigned char *data, size_t len, activity_file_summary_t *out) {
+    memset(out, 0, sizeof(*out));
+    int rc = -1;
+    if (type && strcmp(type, "fit") == 0) rc = summarize_fit(data, len, out);
+    if (type && strcmp(type, "tcx") == 0) rc = summarize_tcx(data, len, out);
+    if (rc == 0 && out->normalized_power <= 0.0 && out->power_count > 0) {
+        out->normalized_power = compute_normalized_power(out->power, out->power_count);
+    }
+    if (rc != 0) activity_file_summary_free(out);
+    return rc;
+}
+
+void activity_file_summary_free(activity_file_summary_t *summary) {
+    free(summary->power);
+    free(summary->heart_rate);
+    summary->power = NULL;
+    summary->heart_rate = NULL;
+    summary->power_count = 0;
+    summary->heart_rate_count = 0;
+}
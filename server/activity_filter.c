@@ -0,0 +1,145 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Server-side filtering for GET /v1/data/activities. With any of ?from=, ?to= (YYYY-MM-DD, both
+ * inclusive, compared with the first ten characters of "date" like the batch filters), ?sport=
+ * (case-insensitive) or ?fields=date,tss,durationSec the response is the matching activities only,
+ * each reduced to the listed top-level fields, so a chart does not download years of history to
+ * draw one month. Fields an activity lacks are left out of its object. The result is a read-only
+ * view: it carries the document's X-Fricu-Version but no ETag, and is not a valid PUT base.
+ */
+
+#define ACTIVITY_FILTER_MAX_FIELDS 32
+
+/* ?2 from, ?3 to, ?4 sport, ?5 JSON array of field names, applied to json_each row "a". */
+static const char *ACTIVITY_FILTER_SQL =
+    "SELECT k.data_value, (SELECT json_group_array(json(p.item)) FROM ("
+    "  SELECT CASE WHEN ?5 IS NULL THEN a.value ELSE (SELECT json_group_object(f.key,"
+    "   CASE f.type WHEN 'object' THEN json(f.value) WHEN 'array' THEN json(f.value)"
+    "    WHEN 'true' THEN json('true') WHEN 'false' THEN json('false') ELSE f.value END)"
+    "   FROM json_each(a.value) f WHERE f.key IN (SELECT value FROM json_each(?5))) END AS item"
+    "  FROM json_each(k.data_value) a WHERE a.type = 'object'"
+    "   AND (?2 IS NULL OR substr(json_extract(a.value, '$.date'), 1, 10) >= ?2)"
+    "   AND (?3 IS NULL OR substr(json_extract(a.value, '$.date'), 1, 10) <= ?3)"
+    "   AND (?4 IS NULL OR lower(json_extract(a.value, '$.sport')) = lower(?4))"
+    "  ORDER BY a.key) p), (SELECT count(*) FROM json_each(k.data_value))"
+    " FROM kv_store k WHERE k.data_key = ?1 AND json_type(k.data_value) = 'array'";
+
+int activity_filter_requested(const http_request_t *req) {
+    char raw[8] = {0};
+    return query_param(req->query, "from", raw, sizeof(raw)) || query_param(req->query, "to", raw, sizeof(raw)) ||
+           query_param(req->query, "sport", raw, sizeof(raw)) || query_param(req->query, "fields", raw, sizeof(raw));
+}
+
+static int is_field_name(const char *name, size_t len) {
+    if (len == 0 || len > 64) return 0;
+    for (size_t i = 0; i < len; i++) {
+        char c = name[i];
+        if (!((c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '_')) return 0;
+    }
+    return 1;
+}
+
+/* Turns "date,tss" into ["date","tss"]; -1 for an empty list, a bad name or too many fields. */
+static int parse_fields(const char *list, strbuf_t *out) {
+    strbuf_append(out, "[", 1);
+    int count = 0;
+    const char *p = list;
+    while (1) {
+        size_t len = strcspn(p, ",");
+        if (!is_field_name(p, len) || ++count > ACTIVITY_FILTER_MAX_FIELDS) return -1;
+        if (count > 1) strbuf_append(out, ",", 1);
+        strbuf_appendf(out, "\"%.*s\"", (int)len, p);
+        if (p[len] == '\0') break;
+        p += len + 1;
+    }
+    strbuf_append(out, "]", 1);
+    return out->failed ? -1 : 0;
+}
+
+int handle_get_activities_filtered(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    char sport[64] = {0};
+    char field_list[1024] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    int has_from = query_param(req->query, "from", from, sizeof(from));
+    int has_to = query_param(req->query, "to", to, sizeof(to));
+    if ((has_from && (strlen(from) != 10 || parse_iso_day(from, &from_day) != 0)) ||
+        (has_to && (strlen(to) != 10 || parse_iso_day(to, &to_day) != 0))) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    if (has_from && has_to && to_day < from_day) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"to must not be before from\"}", ctx);
+        return 400;
+    }
+    int has_sport = query_param(req->query, "sport", sport, sizeof(sport)) && sport[0] != '\0';
+    strbuf_t fields;
+    strbuf_init(&fields);
+    int has_fields = query_param(req->query, "fields", field_list, sizeof(field_list));
+    if (has_fields && parse_fields(field_list, &fields) != 0) {
+        strbuf_free(&fields);
+        send_response_with_log_context(
+            fd, 400, "Bad Request", "{\"error\":\"fields must be a comma-separated list of up to 32 top-level field names\"}", ctx);
+        return 400;
+    }
+
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        sqlite3_prepare_v2(db->db, ACTIVITY_FILTER_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        strbuf_free(&fields);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    if (has_from) sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    if (has_to) sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    if (has_sport) sqlite3_bind_text(stmt, 4, sport, -1, SQLITE_TRANSIENT);
+    if (has_fields) sqlite3_bind_text(stmt, 5, strbuf_cstr(&fields), -1, SQLITE_TRANSIENT);
+
+    char version[32] = {0};
+    snprintf(version, sizeof(version), "%s", SYNC_MISSING_VERSION);
+    char *body = NULL;
+    long long total = 0;
+    int rc = sqlite3_step(stmt);
+    if (rc == SQLITE_ROW) {
+        content_version((const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0), version, sizeof(version));
+        const char *items = (const char *)sqlite3_column_text(stmt, 1);
+        body = strdup(items ? items : "[]");
+        total = sqlite3_column_int64(stmt, 2);
+    } else if (rc == SQLITE_DONE) {
+        body = strdup("[]");
+    }
+    sqlite3_finalize(stmt);
+    strbuf_free(&fields);
+    if (!body) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", rc == SQLITE_DONE || rc == SQLITE_ROW ? "{\"error\":\"oom\"}" : "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+
+    size_t body_len = strlen(body);
+    long long matched = response_count_items(db->db, body, body_len);
+    if (response_exceeds_limits(matched, body_len)) {
+        free(body);
+        return response_guard(fd, matched, body_len, "narrow from/to, filter by sport or request fewer fields", ctx);
+    }
+    char headers[128] = {0};
+    snprintf(headers, sizeof(headers), SYNC_VERSION_HEADER ": %s\r\nX-Fricu-Total-Items: %lld\r\n", version, total);
+    send_http_response(fd, 200, "OK", "application/json", headers, body, body_len, ctx);
+    free(body);
+    log_info(
+        "DATA READ key=activities source=filtered matched=%lld total=%lld account=%s logid=%s", matched, total, ctx->account_id, ctx->log_id);
+    return 200;
+}
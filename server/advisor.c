@@ -0,0 +1,238 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Index advisor: GET /v1/admin/indexes runs EXPLAIN QUERY PLAN for the statements the request
+ * paths issue most often, against the live database, and flags every full table scan or temporary
+ * sort together with the table's current row count. Where an index would turn the scan into a
+ * search the report names it; POST /v1/admin/indexes?confirm=1 creates the recommended indexes
+ * that are still missing, and without confirm=1 only lists what it would create.
+ */
+
+typedef struct {
+    const char *name;
+    const char *table;
+    const char *sql;
+    const char *index_name;
+    const char *index_sql;
+    const char *note;
+} hot_query_t;
+
+static const hot_query_t HOT_QUERIES[] = {
+    {"activities_by_date",
+     "kv_store",
+     "SELECT substr(json_extract(a.value, '$.date'), 1, 10), json_extract(a.value, '$.tss') FROM kv_store k, json_each(k.data_value) a"
+     " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3",
+     NULL,
+     NULL,
+     "activities are one JSON array per account, so a date range reads every element; keep the array bounded or archive old years"},
+    {"sync_manifest",
+     "kv_store",
+     "SELECT substr(data_key, length(?1) + 1), updated_at FROM kv_store"
+     " WHERE substr(data_key, 1, length(?1)) = ?1 AND updated_at > ?2 ORDER BY data_key",
+     NULL,
+     NULL,
+     "the substr() prefix test cannot seek the data_key index; an index on updated_at does not help because the ORDER BY wins"},
+    {"notifications_recent",
+     "notifications",
+     "SELECT id, kind, message, created_at FROM notifications WHERE account_id = ?1 AND (?2 = '' OR kind = ?2)"
+     " ORDER BY created_at DESC, id DESC LIMIT ?3",
+     "idx_notifications_recent",
+     "CREATE INDEX IF NOT EXISTS idx_notifications_recent ON notifications(account_id, created_at)",
+     NULL},
+    {"snapshots_prune",
+     "key_snapshots",
+     "SELECT data_key, day FROM key_snapshots WHERE day < ?1",
+     "idx_key_snapshots_day",
+     "CREATE INDEX IF NOT EXISTS idx_key_snapshots_day ON key_snapshots(day)",
+     NULL},
+    {"snapshot_as_of",
+     "key_snapshots",
+     "SELECT day, data_value FROM key_snapshots WHERE data_key = ?1 AND day <= ?2 ORDER BY day DESC LIMIT 1",
+     NULL,
+     NULL,
+     NULL},
+    {"activity_metrics",
+     "activity_metrics",
+     "SELECT activity_id, value FROM activity_metrics WHERE account_id = ?1 AND metric = ?2",
+     NULL,
+     NULL,
+     NULL},
+    {"journal_range",
+     "journal_entries",
+     "SELECT day, body FROM journal_entries WHERE account_id = ?1 AND day BETWEEN ?2 AND ?3 ORDER BY day",
+     NULL,
+     NULL,
+     NULL},
+    {"live_session_samples",
+     "live_samples",
+     "SELECT t, power, hr FROM live_samples WHERE account_id = ?1 AND session_id = ?2 ORDER BY t",
+     NULL,
+     NULL,
+     NULL},
+    {"data_events_by_key",
+     "data_events",
+     "SELECT seq, payload FROM data_events WHERE data_key = ?1 ORDER BY seq DESC LIMIT 1",
+     NULL,
+     NULL,
+     NULL},
+};
+
+#define HOT_QUERY_COUNT (sizeof(HOT_QUERIES) / sizeof(HOT_QUERIES[0]))
+
+static long long table_rows(sqlite3 *db, const char *table) {
+    char sql[128] = {0};
+    snprintf(sql, sizeof(sql), "SELECT COUNT(*) FROM %s", table);
+    sqlite3_stmt *stmt = NULL;
+    long long rows = -1;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW) rows = sqlite3_column_int64(stmt, 0);
+    sqlite3_finalize(stmt);
+    return rows;
+}
+
+static int index_exists(sqlite3 *db, const char *name) {
+    sqlite3_stmt *stmt = NULL;
+    int exists = 0;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, name, -1, SQLITE_TRANSIENT);
+        exists = sqlite3_step(stmt) == SQLITE_ROW;
+    }
+    sqlite3_finalize(stmt);
+    return exists;
+}
+
+/* Every "SCAN" step visits all rows: of the table, of a whole index (USING ... INDEX), or of a json_each over one document. */
+static int plan_is_full_scan(const char *detail) {
+    return strncmp(detail, "SCAN ", 5) == 0;
+}
+
+/* Appends {"name":...,"plan":[...],...}; returns 1 when the plan is suspicious, -1 when it cannot be planned. */
+static int append_query_report(sqlite3 *db, const hot_query_t *q, int first, strbuf_t *sb) {
+    char sql[1024] = {0};
+    snprintf(sql, sizeof(sql), "EXPLAIN QUERY PLAN %s", q->sql);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        log_warn("INDEXES cannot plan %s: %s", q->name, sqlite3_errmsg(db));
+        return -1;
+    }
+    if (!first) strbuf_append(sb, ",", 1);
+    strbuf_append(sb, "{\"name\":", 8);
+    strbuf_append_json_string(sb, q->name);
+    strbuf_append(sb, ",\"table\":", 9);
+    strbuf_append_json_string(sb, q->table);
+    strbuf_appendf(sb, ",\"rows\":%lld,\"plan\":[", table_rows(db, q->table));
+    int full_scan = 0;
+    int temp_sort = 0;
+    int steps = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *detail = (const char *)sqlite3_column_text(stmt, 3);
+        if (!detail) continue;
+        if (plan_is_full_scan(detail)) full_scan = 1;
+        if (strncmp(detail, "USE TEMP B-TREE", 15) == 0) temp_sort = 1;
+        if (steps++ > 0) strbuf_append(sb, ",", 1);
+        strbuf_append_json_string(sb, detail);
+    }
+    sqlite3_finalize(stmt);
+    int suspicious = full_scan || temp_sort;
+    strbuf_appendf(sb, "],\"full_scan\":%s,\"temp_sort\":%s,\"recommendation\":", full_scan ? "true" : "false", temp_sort ? "true" : "false");
+    if (suspicious && q->index_name) {
+        strbuf_append(sb, "{\"index\":", 9);
+        strbuf_append_json_string(sb, q->index_name);
+        strbuf_append(sb, ",\"sql\":", 7);
+        strbuf_append_json_string(sb, q->index_sql);
+        strbuf_appendf(sb, ",\"present\":%s}", index_exists(db, q->index_name) ? "true" : "false");
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+    if (suspicious && q->note) {
+        strbuf_append(sb, ",\"note\":", 8);
+        strbuf_append_json_string(sb, q->note);
+    }
+    strbuf_append(sb, "}", 1);
+    return suspicious;
+}
+
+/* Recommendations can repeat across queries; each index is created (or listed) once. */
+static int index_listed_before(size_t i) {
+    for (size_t j = 0; j < i; j++) {
+        if (HOT_QUERIES[j].index_name && strcmp(HOT_QUERIES[j].index_name, HOT_QUERIES[i].index_name) == 0) return 1;
+    }
+    return 0;
+}
+
+static int send_index_report(int fd, sqlite3 *db, const char *prefix, const request_log_context_t *ctx) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{%s\"queries\":[", prefix);
+    int suspicions = 0;
+    int reported = 0;
+    for (size_t i = 0; i < HOT_QUERY_COUNT; i++) {
+        int rc = append_query_report(db, &HOT_QUERIES[i], reported == 0, &sb);
+        if (rc < 0) continue;
+        reported++;
+        if (rc == 1) suspicions++;
+    }
+    strbuf_appendf(&sb, "],\"suspicions\":%d}", suspicions);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_create_indexes(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw[8] = {0};
+    int confirm = query_param(req->query, "confirm", raw, sizeof(raw)) && strcmp(raw, "1") == 0;
+    if (confirm && !cluster_is_writer()) return cluster_reject_write(fd, ctx);
+
+    strbuf_t prefix;
+    strbuf_init(&prefix);
+    strbuf_appendf(&prefix, "\"confirmed\":%s,\"%s\":[", confirm ? "true" : "false", confirm ? "created" : "would_create");
+    int listed = 0;
+    for (size_t i = 0; i < HOT_QUERY_COUNT; i++) {
+        const hot_query_t *q = &HOT_QUERIES[i];
+        if (!q->index_name || index_listed_before(i) || index_exists(db->db, q->index_name)) continue;
+        if (confirm) {
+            char *err = NULL;
+            if (sqlite3_exec(db->db, q->index_sql, NULL, NULL, &err) != SQLITE_OK) {
+                log_error("INDEXES create %s failed: %s", q->index_name, err ? err : "unknown");
+                sqlite3_free(err);
+                strbuf_free(&prefix);
+                send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+                return 500;
+            }
+            log_info("INDEXES created %s logid=%s", q->index_name, ctx->log_id);
+        }
+        if (listed++ > 0) strbuf_append(&prefix, ",", 1);
+        strbuf_append_json_string(&prefix, q->index_name);
+    }
+    strbuf_append(&prefix, "],", 2);
+    if (prefix.failed) {
+        strbuf_free(&prefix);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    int status = send_index_report(fd, db->db, strbuf_cstr(&prefix), ctx);
+    strbuf_free(&prefix);
+    return status;
+}
+
+int handle_admin_indexes(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->method, "GET") == 0) return send_index_report(fd, db->db, "", ctx);
+    if (strcmp(req->method, "POST") == 0) return handle_create_indexes(fd, db, req, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -0,0 +1,1112 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define CTL_TIME_CONSTANT 42.0
+#define ATL_TIME_CONSTANT 7.0
+#define RISK_RAMP_RATE_LIMIT 8.0
+#define RISK_ACWR_LIMIT 1.5
+#define RISK_MONOTONY_LIMIT 2.0
+#define RISK_MONOTONY_CAP 10.0
+#define RISK_DEFAULT_WEEKS 12
+#define RISK_MAX_WEEKS 104
+#define SIMULATE_MAX_DAYS 365
+#define COACH_COMPARE_MAX_ATHLETES 10
+#define COACH_COMPARE_ATHLETE_MAX 128
+#define COACH_COMPARE_DEFAULT_DAYS 90
+#define COACH_COMPARE_MAX_DAYS 365
+
+int today_day(void) {
+    return (int)(time(NULL) / 86400);
+}
+
+int week_start_for_day(int day) {
+    return week_start_on(day, 0);
+}
+
+/* The first day of the week holding day, for weeks starting on start_weekday (0 = Monday). */
+int week_start_on(int day, int start_weekday) {
+    int weekday = ((day + 3) % 7 + 7) % 7;
+    return day - ((weekday - start_weekday) % 7 + 7) % 7;
+}
+
+static const char *const WEEKDAY_NAMES[] = {"monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"};
+
+/* 0 for "monday" through 6 for "sunday"; -1 for anything else. */
+int weekday_from_name(const char *name) {
+    for (int i = 0; name && i < 7; i++) {
+        if (strcmp(name, WEEKDAY_NAMES[i]) == 0) return i;
+    }
+    return -1;
+}
+
+/* Monday weeks rolling over at midnight unless the profile sets weekStartDay or weekRolloverHour. */
+void load_training_week(sqlite3 *db, const char *account_id, training_week_t *out) {
+    out->start_weekday = 0;
+    out->rollover_hour = 0;
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return;
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = "SELECT json_extract(data_value, '$.weekStartDay'), json_extract(data_value, '$.weekRolloverHour')"
+                      " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value) AND json_type(data_value) = 'object'";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        int weekday = weekday_from_name((const char *)sqlite3_column_text(stmt, 0));
+        int hour = sqlite3_column_int(stmt, 1);
+        if (weekday >= 0) out->start_weekday = weekday;
+        if (hour > 0 && hour < 24) out->rollover_hour = hour;
+    }
+    sqlite3_finalize(stmt);
+}
+
+/* The training day in progress: until the rollover hour it is still yesterday. */
+int training_today(const training_week_t *week) {
+    return (int)((time(NULL) - (time_t)week->rollover_hour * 3600) / 86400);
+}
+
+int load_daily_tss(sqlite3 *db, const char *account_id, daily_load_t **out, size_t *out_count) {
+    return load_daily_model_load(db, account_id, NULL, out, out_count);
+}
+
+/* model NULL sums each activity's primary load ("tss"); otherwise that model's entry in "loads". Days are training days. */
+int load_daily_model_load(sqlite3 *db, const char *account_id, const char *model, daily_load_t **out, size_t *out_count) {
+    if (!db || !out || !out_count) return -1;
+    *out = NULL;
+    *out_count = 0;
+
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+
+    training_week_t week;
+    load_training_week(db, account_id, &week);
+    const char *sql =
+        "SELECT " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?3") " AS day,"
+        " SUM(COALESCE(CASE WHEN ?2 IS NULL THEN json_extract(a.value, '$.tss') ELSE json_extract(a.value, '$.loads.' || ?2) END, 0))"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " GROUP BY day ORDER BY day";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("analytics failed to prepare load query: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    if (model) sqlite3_bind_text(stmt, 2, model, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, week.rollover_hour);
+
+    size_t cap = 0;
+    daily_load_t *loads = NULL;
+    int rc;
+    while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+        int day = 0;
+        if (!day_text || parse_iso_day(day_text, &day) != 0) continue;
+        if (*out_count == cap) {
+            size_t next = cap ? cap * 2 : 64;
+            daily_load_t *grown = (daily_load_t *)realloc(loads, next * sizeof(daily_load_t));
+            if (!grown) {
+                free(loads);
+                sqlite3_finalize(stmt);
+                *out_count = 0;
+                return -1;
+            }
+            loads = grown;
+            cap = next;
+        }
+        loads[*out_count].day = day;
+        loads[*out_count].tss = sqlite3_column_double(stmt, 1);
+        (*out_count)++;
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        free(loads);
+        *out_count = 0;
+        return -1;
+    }
+    *out = loads;
+    return 0;
+}
+
+double *expand_daily_tss(const daily_load_t *loads, size_t count, int first_day, int last_day) {
+    if (last_day < first_day) return NULL;
+    size_t days = (size_t)(last_day - first_day + 1);
+    double *daily = (double *)calloc(days, sizeof(double));
+    if (!daily) return NULL;
+    for (size_t i = 0; i < count; i++) {
+        if (loads[i].day < first_day || loads[i].day > last_day) continue;
+        daily[loads[i].day - first_day] += loads[i].tss;
+    }
+    return daily;
+}
+
+void compute_pmc_series(const double *daily_tss, size_t days, const pmc_point_t *seed, pmc_point_t *out) {
+    double ctl = seed ? seed->ctl : 0.0;
+    double atl = seed ? seed->atl : 0.0;
+    for (size_t i = 0; i < days; i++) {
+        double tsb = ctl - atl;
+        ctl += (daily_tss[i] - ctl) / CTL_TIME_CONSTANT;
+        atl += (daily_tss[i] - atl) / ATL_TIME_CONSTANT;
+        out[i].ctl = ctl;
+        out[i].atl = atl;
+        out[i].tsb = tsb;
+    }
+}
+
+static double window_sum(const double *daily_tss, size_t end_idx, size_t window) {
+    double sum = 0.0;
+    for (size_t i = 0; i < window && i <= end_idx; i++) {
+        sum += daily_tss[end_idx - i];
+    }
+    return sum;
+}
+
+size_t compute_training_risk(const double *daily_tss, size_t days, int first_day, int start_weekday, training_risk_week_t *out, size_t max_weeks) {
+    if (!daily_tss || days == 0 || !out || max_weeks == 0) return 0;
+
+    pmc_point_t *pmc = (pmc_point_t *)calloc(days, sizeof(pmc_point_t));
+    if (!pmc) return 0;
+    compute_pmc_series(daily_tss, days, NULL, pmc);
+
+    int last_day = first_day + (int)days - 1;
+    int week_start = week_start_on(first_day, start_weekday);
+    size_t total_weeks = (size_t)((last_day - week_start) / 7 + 1);
+    size_t skip = total_weeks > max_weeks ? total_weeks - max_weeks : 0;
+
+    size_t count = 0;
+    for (size_t w = 0; w < total_weeks; w++, week_start += 7) {
+        if (w < skip) continue;
+        int week_end = week_start + 6 < last_day ? week_start + 6 : last_day;
+        size_t end_idx = (size_t)(week_end - first_day);
+
+        double week_loads[7] = {0};
+        double sum = 0.0;
+        for (int d = 0; d < 7; d++) {
+            int day = week_start + d;
+            if (day < first_day || day > last_day) continue;
+            week_loads[d] = daily_tss[day - first_day];
+            sum += week_loads[d];
+        }
+        double mean = sum / 7.0;
+        double var = 0.0;
+        for (int d = 0; d < 7; d++) var += (week_loads[d] - mean) * (week_loads[d] - mean);
+        double sd = sqrt(var / 7.0);
+
+        training_risk_week_t *week = &out[count++];
+        memset(week, 0, sizeof(*week));
+        week->week_start_day = week_start;
+        week->tss = sum;
+        if (sd > 1e-9) {
+            week->monotony = mean / sd;
+            if (week->monotony > RISK_MONOTONY_CAP) week->monotony = RISK_MONOTONY_CAP;
+        } else {
+            week->monotony = mean > 0.0 ? RISK_MONOTONY_CAP : 0.0;
+        }
+        week->strain = sum * week->monotony;
+
+        int prev_end = week_start - 1;
+        double prev_ctl = prev_end >= first_day ? pmc[prev_end - first_day].ctl : 0.0;
+        week->ctl_ramp = pmc[end_idx].ctl - prev_ctl;
+
+        double acute = window_sum(daily_tss, end_idx, 7);
+        double chronic = window_sum(daily_tss, end_idx, 28) / 4.0;
+        week->acwr = chronic > 0.0 ? acute / chronic : 0.0;
+
+        if (week->ctl_ramp > RISK_RAMP_RATE_LIMIT) week->flags |= RISK_FLAG_RAMP_RATE;
+        if (week->acwr > RISK_ACWR_LIMIT) week->flags |= RISK_FLAG_ACWR;
+        if (week->monotony > RISK_MONOTONY_LIMIT && sum > 0.0) week->flags |= RISK_FLAG_MONOTONY;
+    }
+
+    free(pmc);
+    return count;
+}
+
+static void append_risk_flags(strbuf_t *sb, int flags) {
+    strbuf_append(sb, "[", 1);
+    int first = 1;
+    if (flags & RISK_FLAG_RAMP_RATE) {
+        strbuf_appendf(sb, "%s\"ramp_rate\"", first ? "" : ",");
+        first = 0;
+    }
+    if (flags & RISK_FLAG_ACWR) {
+        strbuf_appendf(sb, "%s\"acwr\"", first ? "" : ",");
+        first = 0;
+    }
+    if (flags & RISK_FLAG_MONOTONY) {
+        strbuf_appendf(sb, "%s\"monotony\"", first ? "" : ",");
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+/* Catalog keys and arguments for a flagged week; args point into the struct. */
+typedef struct {
+    char keys[64];
+    char values[7][16];
+    i18n_arg_t args[7];
+    size_t arg_count;
+} risk_message_t;
+
+static void risk_message_arg(risk_message_t *m, const char *name, const char *format, double value) {
+    snprintf(m->values[m->arg_count], sizeof(m->values[0]), format, value);
+    m->args[m->arg_count].name = name;
+    m->args[m->arg_count].value = m->values[m->arg_count];
+    m->arg_count++;
+}
+
+static void build_risk_message(const training_risk_week_t *week, risk_message_t *m) {
+    memset(m, 0, sizeof(*m));
+    snprintf(m->keys, sizeof(m->keys), "risk.week");
+    format_iso_day(week->week_start_day, m->values[0], sizeof(m->values[0]));
+    m->args[0].name = "week";
+    m->args[0].value = m->values[0];
+    m->arg_count = 1;
+    if (week->flags & RISK_FLAG_RAMP_RATE) {
+        strcat(m->keys, " risk.ramp");
+        risk_message_arg(m, "ramp", "%.1f", week->ctl_ramp);
+        risk_message_arg(m, "ramp_limit", "%.0f", RISK_RAMP_RATE_LIMIT);
+    }
+    if (week->flags & RISK_FLAG_ACWR) {
+        strcat(m->keys, " risk.acwr");
+        risk_message_arg(m, "acwr", "%.2f", week->acwr);
+        risk_message_arg(m, "acwr_limit", "%.1f", RISK_ACWR_LIMIT);
+    }
+    if (week->flags & RISK_FLAG_MONOTONY) {
+        strcat(m->keys, " risk.monotony");
+        risk_message_arg(m, "monotony", "%.2f", week->monotony);
+        risk_message_arg(m, "monotony_limit", "%.1f", RISK_MONOTONY_LIMIT);
+    }
+}
+
+static int load_account_daily_tss(
+    sqlite3 *db, const char *account_id, const char *model, int last_day, int *out_first_day, double **out_daily, size_t *out_days) {
+    *out_daily = NULL;
+    *out_days = 0;
+    *out_first_day = last_day;
+
+    daily_load_t *loads = NULL;
+    size_t load_count = 0;
+    if (load_daily_model_load(db, account_id, model, &loads, &load_count) != 0) return -1;
+    if (load_count == 0 || loads[0].day > last_day) {
+        free(loads);
+        return 0;
+    }
+
+    int first_day = loads[0].day;
+    double *daily = expand_daily_tss(loads, load_count, first_day, last_day);
+    free(loads);
+    if (!daily) return -1;
+    *out_first_day = first_day;
+    *out_daily = daily;
+    *out_days = (size_t)(last_day - first_day + 1);
+    return 0;
+}
+
+int compute_account_pmc_today(sqlite3 *db, const char *account_id, pmc_point_t *out_latest) {
+    memset(out_latest, 0, sizeof(*out_latest));
+    int first_day = 0;
+    double *daily = NULL;
+    size_t days = 0;
+    if (load_account_daily_tss(db, account_id, NULL, today_day(), &first_day, &daily, &days) != 0) return -1;
+    if (days == 0) return 0;
+
+    pmc_point_t *pmc = (pmc_point_t *)calloc(days, sizeof(pmc_point_t));
+    if (!pmc) {
+        free(daily);
+        return -1;
+    }
+    compute_pmc_series(daily, days, NULL, pmc);
+    *out_latest = pmc[days - 1];
+    free(pmc);
+    free(daily);
+    return 0;
+}
+
+static int compute_account_risk(
+    sqlite3 *db,
+    const char *account_id,
+    const char *model,
+    size_t max_weeks,
+    training_risk_week_t *weeks,
+    size_t *out_weeks,
+    pmc_point_t *out_latest) {
+    *out_weeks = 0;
+    memset(out_latest, 0, sizeof(*out_latest));
+
+    training_week_t week;
+    load_training_week(db, account_id, &week);
+    int first_day = 0;
+    double *daily = NULL;
+    size_t days = 0;
+    if (load_account_daily_tss(db, account_id, model, training_today(&week), &first_day, &daily, &days) != 0) return -1;
+    if (days == 0) return 0;
+
+    pmc_point_t *pmc = (pmc_point_t *)calloc(days, sizeof(pmc_point_t));
+    if (!pmc) {
+        free(daily);
+        return -1;
+    }
+    compute_pmc_series(daily, days, NULL, pmc);
+    *out_latest = pmc[days - 1];
+    *out_weeks = compute_training_risk(daily, days, first_day, week.start_weekday, weeks, max_weeks);
+    free(pmc);
+    free(daily);
+    return 0;
+}
+
+int analytics_refresh_risk_notifications(sqlite3 *db, const char *account_id) {
+    training_risk_week_t weeks[2];
+    size_t week_count = 0;
+    pmc_point_t latest;
+    if (compute_account_risk(db, account_id, NULL, 2, weeks, &week_count, &latest) != 0) return -1;
+
+    for (size_t i = 0; i < week_count; i++) {
+        if (weeks[i].flags == 0) continue;
+        char week_label[16] = {0};
+        char dedupe_key[64] = {0};
+        risk_message_t message;
+        format_iso_day(weeks[i].week_start_day, week_label, sizeof(week_label));
+        snprintf(dedupe_key, sizeof(dedupe_key), "training_risk:%s:%d", week_label, weeks[i].flags);
+        build_risk_message(&weeks[i], &message);
+        notification_post_message(db, account_id, "training_risk", dedupe_key, message.keys, message.args, message.arg_count);
+    }
+    return 0;
+}
+
+/* ?model= picks a load model other than each activity's primary one; "" means primary. */
+static int parse_load_model(const http_request_t *req, char *model, size_t model_len) {
+    model[0] = '\0';
+    if (!query_param(req->query, "model", model, model_len) || strcmp(model, "primary") == 0) {
+        model[0] = '\0';
+        return 0;
+    }
+    return sport_load_model_known(model) ? 0 : -1;
+}
+
+/* ,"load_model":...,"available_models":[...] with the models any activity has a load for. */
+static void append_load_models(strbuf_t *sb, sqlite3 *db, const char *account_id, const char *model) {
+    strbuf_appendf(sb, ",\"load_model\":\"%s\",\"available_models\":[", model[0] != '\0' ? model : "primary");
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) == 0 &&
+        sqlite3_prepare_v2(
+            db,
+            "SELECT DISTINCT l.key FROM kv_store k, json_each(k.data_value) a, json_each(a.value, '$.loads') l"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND json_type(a.value, '$.loads') = 'object' ORDER BY l.key",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (count++ > 0) strbuf_append(sb, ",", 1);
+            strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+        }
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(sb, "]", 1);
+}
+
+int handle_get_analytics_risk(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    size_t max_weeks = RISK_DEFAULT_WEEKS;
+    char raw_weeks[16] = {0};
+    if (query_param(req->query, "weeks", raw_weeks, sizeof(raw_weeks))) {
+        long parsed = strtol(raw_weeks, NULL, 10);
+        if (parsed <= 0 || parsed > RISK_MAX_WEEKS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"weeks must be in 1..104\"}", ctx);
+            return 400;
+        }
+        max_weeks = (size_t)parsed;
+    }
+    char model[16] = {0};
+    if (parse_load_model(req, model, sizeof(model)) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"unknown load model\"}", ctx);
+        return 400;
+    }
+
+    training_risk_week_t weeks[RISK_MAX_WEEKS];
+    size_t week_count = 0;
+    pmc_point_t latest;
+    if (compute_account_risk(db->db, ctx->account_id, model[0] != '\0' ? model : NULL, max_weeks, weeks, &week_count, &latest) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+
+    training_week_t week;
+    load_training_week(db->db, ctx->account_id, &week);
+    char as_of[16] = {0};
+    format_iso_day(training_today(&week), as_of, sizeof(as_of));
+    strbuf_t sb;
+    strbuf_init(&sb);
+    const training_risk_week_t *current = week_count > 0 ? &weeks[week_count - 1] : NULL;
+    strbuf_appendf(
+        &sb,
+        "{\"as_of\":\"%s\",\"ctl\":%.1f,\"atl\":%.1f,\"tsb\":%.1f,\"ramp_rate\":%.1f,\"acwr\":%.2f,"
+        "\"thresholds\":{\"ramp_rate\":%.1f,\"acwr\":%.2f,\"monotony\":%.2f},\"weeks\":[",
+        as_of,
+        latest.ctl,
+        latest.atl,
+        latest.tsb,
+        current ? current->ctl_ramp : 0.0,
+        current ? current->acwr : 0.0,
+        RISK_RAMP_RATE_LIMIT,
+        RISK_ACWR_LIMIT,
+        RISK_MONOTONY_LIMIT);
+    for (size_t i = 0; i < week_count; i++) {
+        char week_label[16] = {0};
+        format_iso_day(weeks[i].week_start_day, week_label, sizeof(week_label));
+        strbuf_appendf(
+            &sb,
+            "%s{\"week_start\":\"%s\",\"tss\":%.1f,\"ctl_ramp\":%.1f,\"acwr\":%.2f,\"monotony\":%.2f,\"strain\":%.1f,\"flags\":",
+            i == 0 ? "" : ",",
+            week_label,
+            weeks[i].tss,
+            weeks[i].ctl_ramp,
+            weeks[i].acwr,
+            weeks[i].monotony,
+            weeks[i].strain);
+        append_risk_flags(&sb, weeks[i].flags);
+        strbuf_append(&sb, "}", 1);
+    }
+    strbuf_append(&sb, "],\"warnings\":[", 14);
+    int first_warning = 1;
+    for (size_t i = 0; i < week_count; i++) {
+        if (weeks[i].flags == 0) continue;
+        risk_message_t message;
+        const char *lang = i18n_negotiate(db->db, req, ctx->account_id);
+        strbuf_t text;
+        strbuf_init(&text);
+        build_risk_message(&weeks[i], &message);
+        i18n_format_keys(lang, message.keys, message.args, message.arg_count, &text);
+        if (!first_warning) strbuf_append(&sb, ",", 1);
+        strbuf_append_json_string(&sb, strbuf_cstr(&text));
+        strbuf_free(&text);
+        first_warning = 0;
+    }
+    strbuf_append(&sb, "]", 1);
+    append_load_models(&sb, db->db, ctx->account_id, model);
+    cycle_model_t cycle;
+    strbuf_append(&sb, ",\"cycle\":", 9);
+    if (cycle_load_model(db->db, ctx->account_id, today_day(), &cycle) == 0) {
+        cycle_append_json(&sb, &cycle, today_day());
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_append(&sb, "}", 1);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    log_info("ANALYTICS risk account=%s weeks=%zu logid=%s", ctx->account_id, week_count, ctx->log_id);
+    return 200;
+}
+
+int load_planned_workout_tss(sqlite3 *db, const char *account_id, int from_day, int to_day, double *daily, size_t days) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "workouts", storage_key, sizeof(storage_key)) != 0) return -1;
+
+    const char *sql =
+        "SELECT substr(json_extract(w.value, '$.scheduledDate'), 1, 10),"
+        " SUM(COALESCE(json_extract(s.value, '$.minutes'), 0) / 60.0"
+        " * (COALESCE(json_extract(s.value, '$.intensityPercentFTP'), 0) / 100.0)"
+        " * (COALESCE(json_extract(s.value, '$.intensityPercentFTP'), 0) / 100.0) * 100.0)"
+        " FROM kv_store k, json_each(k.data_value) w, json_each(w.value, '$.segments') s"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(w.value, '$.scheduledDate') IS NOT NULL"
+        " GROUP BY w.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("analytics failed to prepare planned workout query: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int added = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+        int day = 0;
+        if (!day_text || parse_iso_day(day_text, &day) != 0) continue;
+        if (day < from_day || day > to_day || (size_t)(day - from_day) >= days) continue;
+        daily[day - from_day] += sqlite3_column_double(stmt, 1);
+        added++;
+    }
+    sqlite3_finalize(stmt);
+    return added;
+}
+
+static void append_pmc_point(strbuf_t *sb, int day, double tss, const pmc_point_t *point, const char *cycle_phase) {
+    char date[16] = {0};
+    format_iso_day(day, date, sizeof(date));
+    strbuf_appendf(sb, "{\"date\":\"%s\",\"tss\":%.1f,\"ctl\":%.1f,\"atl\":%.1f,\"tsb\":%.1f", date, tss, point->ctl, point->atl, point->tsb);
+    if (cycle_phase) strbuf_appendf(sb, ",\"cycle_phase\":\"%s\"", cycle_phase);
+    strbuf_append(sb, "}", 1);
+}
+
+int handle_post_analytics_simulate(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *header_sql =
+        "SELECT json_valid(?1), json_extract(?1, '$.target_date'), COALESCE(json_extract(?1, '$.use_planned_workouts'), 0),"
+        " json_type(?1, '$.workouts'),"
+        " json_type(?1, '$.cycle_load_factors') IS NULL OR (json_type(?1, '$.cycle_load_factors') = 'object'"
+        "  AND NOT EXISTS (SELECT 1 FROM json_each(?1, '$.cycle_load_factors') WHERE key NOT IN ('menstrual', 'follicular', 'ovulatory', 'luteal')"
+        "   OR type NOT IN ('integer', 'real') OR value < 0 OR value > 2))";
+    if (sqlite3_prepare_v2(db->db, header_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    int valid = 0;
+    int use_planned = 0;
+    int target_day = 0;
+    int has_target = 0;
+    int workouts_ok = 1;
+    int factors_ok = 1;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        const char *target_text = (const char *)sqlite3_column_text(stmt, 1);
+        has_target = target_text && parse_iso_day(target_text, &target_day) == 0;
+        use_planned = sqlite3_column_int(stmt, 2);
+        const char *workouts_type = (const char *)sqlite3_column_text(stmt, 3);
+        workouts_ok = !workouts_type || strcmp(workouts_type, "array") == 0;
+        factors_ok = sqlite3_column_int(stmt, 4);
+    }
+    sqlite3_finalize(stmt);
+
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json payload\"}", ctx);
+        return 400;
+    }
+    int start_day = today_day();
+    if (!has_target || target_day <= start_day || target_day - start_day > SIMULATE_MAX_DAYS) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"target_date must be a future YYYY-MM-DD within 365 days\"}", ctx);
+        return 400;
+    }
+    if (!workouts_ok) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"workouts must be an array\"}", ctx);
+        return 400;
+    }
+    if (!factors_ok) {
+        send_response_with_log_context(
+            fd, 400, "Bad Request", "{\"error\":\"cycle_load_factors maps menstrual|follicular|ovulatory|luteal to a factor in 0..2\"}", ctx);
+        return 400;
+    }
+
+    size_t days = (size_t)(target_day - start_day);
+    double *daily = (double *)calloc(days, sizeof(double));
+    pmc_point_t *pmc = (pmc_point_t *)calloc(days, sizeof(pmc_point_t));
+    if (!daily || !pmc) {
+        free(daily);
+        free(pmc);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+
+    int hypothetical = 0;
+    const char *workouts_sql =
+        "SELECT substr(json_extract(value, '$.date'), 1, 10), COALESCE(json_extract(value, '$.tss'), 0)"
+        " FROM json_each(?1, '$.workouts')";
+    if (sqlite3_prepare_v2(db->db, workouts_sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+            int day = 0;
+            if (!day_text || parse_iso_day(day_text, &day) != 0) continue;
+            if (day <= start_day || day > target_day) continue;
+            daily[day - start_day - 1] += sqlite3_column_double(stmt, 1);
+            hypothetical++;
+        }
+        sqlite3_finalize(stmt);
+    }
+
+    int planned = 0;
+    if (use_planned) {
+        planned = load_planned_workout_tss(db->db, ctx->account_id, start_day + 1, target_day, daily, days);
+        if (planned < 0) planned = 0;
+    }
+
+    /* With cycle tracking, each projected day carries its expected phase, and a plan can scale the
+     * load it schedules in a phase (e.g. {"menstrual": 0.8}). */
+    cycle_model_t cycle;
+    if (cycle_load_model(db->db, ctx->account_id, start_day, &cycle) != 0) cycle.tracked = 0;
+    int cycle_scaled = 0;
+    if (cycle.tracked && sqlite3_prepare_v2(db->db, "SELECT key, value FROM json_each(?1, '$.cycle_load_factors')", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            const char *phase = (const char *)sqlite3_column_text(stmt, 0);
+            double factor = sqlite3_column_double(stmt, 1);
+            for (size_t i = 0; i < days; i++) {
+                const char *day_phase = cycle_phase_on(&cycle, start_day + 1 + (int)i, NULL);
+                if (!day_phase || strcmp(day_phase, phase) != 0) continue;
+                daily[i] *= factor;
+                cycle_scaled = 1;
+            }
+        }
+        sqlite3_finalize(stmt);
+    }
+
+    pmc_point_t start;
+    if (compute_account_pmc_today(db->db, ctx->account_id, &start) != 0) {
+        free(daily);
+        free(pmc);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+    compute_pmc_series(daily, days, &start, pmc);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"start\":", 9);
+    append_pmc_point(&sb, start_day, 0.0, &start, NULL);
+    strbuf_appendf(
+        &sb,
+        ",\"hypothetical_workouts\":%d,\"planned_workouts\":%d,\"cycle_adjusted\":%s,\"days\":[",
+        hypothetical,
+        planned,
+        cycle_scaled ? "true" : "false");
+    for (size_t i = 0; i < days; i++) {
+        if (i > 0) strbuf_append(&sb, ",", 1);
+        append_pmc_point(&sb, start_day + 1 + (int)i, daily[i], &pmc[i], cycle_phase_on(&cycle, start_day + 1 + (int)i, NULL));
+    }
+    strbuf_append(&sb, "],\"final\":", 10);
+    append_pmc_point(&sb, target_day, daily[days - 1], &pmc[days - 1], NULL);
+    strbuf_append(&sb, "}", 1);
+    free(daily);
+    free(pmc);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    log_info("ANALYTICS simulate account=%s days=%zu hypothetical=%d planned=%d logid=%s", ctx->account_id, days, hypothetical, planned, ctx->log_id);
+    return 200;
+}
+
+static const int POWER_CURVE_DURATIONS[] = {5, 60, 300, 1200, 3600};
+#define POWER_CURVE_POINTS (sizeof(POWER_CURVE_DURATIONS) / sizeof(POWER_CURVE_DURATIONS[0]))
+#define COMPARE_MAX_SPAN_DAYS 732
+
+typedef struct {
+    char label[48];
+    int from_day;
+    int to_day;
+    int activities;
+    double duration_sec;
+    double tss;
+    double distance_km;
+    size_t week_count;
+    double *week_hours;
+    double *week_tss;
+    double power_curve[POWER_CURVE_POINTS];
+    int altitude_efforts;
+} compare_period_t;
+
+int parse_analytics_period(const char *text, int *out_from_day, int *out_to_day) {
+    if (!text || !out_from_day || !out_to_day) return -1;
+    size_t len = strlen(text);
+    if (len == 4) {
+        char start[16] = {0};
+        char end[16] = {0};
+        for (size_t i = 0; i < 4; i++) {
+            if (text[i] < '0' || text[i] > '9') return -1;
+        }
+        snprintf(start, sizeof(start), "%.4s-01-01", text);
+        snprintf(end, sizeof(end), "%.4s-12-31", text);
+        if (parse_iso_day(start, out_from_day) != 0 || parse_iso_day(end, out_to_day) != 0) return -1;
+        return 0;
+    }
+    const char *sep = strstr(text, "..");
+    if (!sep || sep - text != 10 || strlen(sep + 2) != 10) return -1;
+    if (parse_iso_day(text, out_from_day) != 0 || parse_iso_day(sep + 2, out_to_day) != 0) return -1;
+    if (*out_to_day < *out_from_day || *out_to_day - *out_from_day > COMPARE_MAX_SPAN_DAYS) return -1;
+    return 0;
+}
+
+static int load_compare_period(sqlite3 *db, const char *storage_key, const char *model, const travel_log_t *travel, compare_period_t *period) {
+    char from[16] = {0};
+    char to[16] = {0};
+    format_iso_day(period->from_day, from, sizeof(from));
+    format_iso_day(period->to_day, to, sizeof(to));
+
+    period->week_count = (size_t)((period->to_day - period->from_day) / 7 + 1);
+    period->week_hours = (double *)calloc(period->week_count, sizeof(double));
+    period->week_tss = (double *)calloc(period->week_count, sizeof(double));
+    if (!period->week_hours || !period->week_tss) return -1;
+
+    const char *sql =
+        "SELECT substr(json_extract(a.value, '$.date'), 1, 10) AS day,"
+        " COALESCE(json_extract(a.value, '$.durationSec'), 0),"
+        " COALESCE(CASE WHEN ?4 = '' THEN json_extract(a.value, '$.tss') ELSE json_extract(a.value, '$.loads.' || ?4) END, 0),"
+        " COALESCE(json_extract(a.value, '$.distanceKm'), 0)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND day BETWEEN ?2 AND ?3";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, model, -1, SQLITE_TRANSIENT);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = 0;
+        const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+        if (!day_text || parse_iso_day(day_text, &day) != 0) continue;
+        double duration = sqlite3_column_double(stmt, 1);
+        double tss = sqlite3_column_double(stmt, 2);
+        size_t week = (size_t)((day - period->from_day) / 7);
+        period->activities++;
+        period->duration_sec += duration;
+        period->tss += tss;
+        period->distance_km += sqlite3_column_double(stmt, 3);
+        if (week < period->week_count) {
+            period->week_hours[week] += duration / 3600.0;
+            period->week_tss[week] += tss;
+        }
+    }
+    sqlite3_finalize(stmt);
+
+    /* Best efforts per duration at their sea-level equivalent when recorded on a travel stay. */
+    const char *curve_sql =
+        "SELECT substr(json_extract(a.value, '$.date'), 1, 10), json_extract(i.value, '$.actualPower'), json_extract(i.value, '$.durationSec')"
+        " FROM kv_store k, json_each(k.data_value) a, json_each(a.value, '$.intervals') i"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3"
+        " AND json_extract(i.value, '$.actualPower') IS NOT NULL"
+        " UNION ALL"
+        " SELECT substr(json_extract(a.value, '$.date'), 1, 10), json_extract(a.value, '$.normalizedPower'), json_extract(a.value, '$.durationSec')"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3"
+        " AND json_extract(a.value, '$.normalizedPower') IS NOT NULL";
+    for (size_t i = 0; i < POWER_CURVE_POINTS; i++) period->power_curve[i] = -1.0;
+    if (sqlite3_prepare_v2(db, curve_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = 0;
+        const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+        double factor = day_text && parse_iso_day(day_text, &day) == 0 ? travel_power_factor(travel, day, NULL) : 1.0;
+        double watts = sqlite3_column_double(stmt, 1) / factor;
+        double duration = sqlite3_column_double(stmt, 2);
+        if (factor < 1.0) period->altitude_efforts++;
+        for (size_t i = 0; i < POWER_CURVE_POINTS; i++) {
+            if (duration >= POWER_CURVE_DURATIONS[i] && watts > period->power_curve[i]) period->power_curve[i] = watts;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+static void append_optional_watts(strbuf_t *sb, double watts) {
+    if (watts < 0.0) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_appendf(sb, "%.0f", watts);
+    }
+}
+
+static void append_compare_period(strbuf_t *sb, sqlite3 *db, const char *account_id, const compare_period_t *period) {
+    char from[16] = {0};
+    char to[16] = {0};
+    format_iso_day(period->from_day, from, sizeof(from));
+    format_iso_day(period->to_day, to, sizeof(to));
+    double weeks = (double)period->week_count;
+    strbuf_append(sb, "{\"label\":", 9);
+    strbuf_append_json_string(sb, period->label);
+    strbuf_appendf(
+        sb,
+        ",\"from\":\"%s\",\"to\":\"%s\",\"activities\":%d,\"hours\":%.1f,\"tss\":%.0f,\"distance_km\":%.1f,"
+        "\"weekly_hours\":%.2f,\"weekly_tss\":%.1f,\"weeks\":[",
+        from,
+        to,
+        period->activities,
+        period->duration_sec / 3600.0,
+        period->tss,
+        period->distance_km,
+        period->duration_sec / 3600.0 / weeks,
+        period->tss / weeks);
+    for (size_t w = 0; w < period->week_count; w++) {
+        strbuf_appendf(sb, "%s{\"week\":%zu,\"hours\":%.2f,\"tss\":%.0f}", w == 0 ? "" : ",", w + 1, period->week_hours[w], period->week_tss[w]);
+    }
+    strbuf_append(sb, "],\"power_curve\":[", 17);
+    for (size_t i = 0; i < POWER_CURVE_POINTS; i++) {
+        strbuf_appendf(sb, "%s{\"duration_sec\":%d,\"watts\":", i == 0 ? "" : ",", POWER_CURVE_DURATIONS[i]);
+        append_optional_watts(sb, period->power_curve[i]);
+        strbuf_append(sb, "}", 1);
+    }
+    strbuf_appendf(sb, "],\"altitude_corrected_efforts\":%d,\"calibrations\":", period->altitude_efforts);
+    calibration_append_annotations(sb, db, account_id, period->from_day, period->to_day);
+    strbuf_append(sb, "}", 1);
+}
+
+int handle_get_analytics_compare(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw_periods[128] = {0};
+    if (!query_param(req->query, "periods", raw_periods, sizeof(raw_periods))) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"periods query parameter is required\"}", ctx);
+        return 400;
+    }
+
+    compare_period_t periods[2];
+    memset(periods, 0, sizeof(periods));
+    char *comma = strchr(raw_periods, ',');
+    if (!comma || strchr(comma + 1, ',')) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"periods must list exactly two periods\"}", ctx);
+        return 400;
+    }
+    *comma = '\0';
+    const char *labels[2] = {raw_periods, comma + 1};
+    for (int i = 0; i < 2; i++) {
+        if (parse_analytics_period(labels[i], &periods[i].from_day, &periods[i].to_day) != 0) {
+            send_response_with_log_context(
+                fd, 400, "Bad Request", "{\"error\":\"each period must be YYYY or YYYY-MM-DD..YYYY-MM-DD (max 732 days)\"}", ctx);
+            return 400;
+        }
+        snprintf(periods[i].label, sizeof(periods[i].label), "%s", labels[i]);
+    }
+    char model[16] = {0};
+    if (parse_load_model(req, model, sizeof(model)) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"unknown load model\"}", ctx);
+        return 400;
+    }
+
+    char storage_key[256] = {0};
+    int status = 200;
+    travel_log_t *travel = (travel_log_t *)malloc(sizeof(travel_log_t));
+    if (!travel || travel_load_log(db->db, ctx->account_id, travel) != 0 ||
+        build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        load_compare_period(db->db, storage_key, model, travel, &periods[0]) != 0 ||
+        load_compare_period(db->db, storage_key, model, travel, &periods[1]) != 0) {
+        status = 500;
+    }
+    free(travel);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (status == 200) {
+        const compare_period_t *a = &periods[0];
+        const compare_period_t *b = &periods[1];
+        strbuf_append(&sb, "{\"periods\":[", 12);
+        append_compare_period(&sb, db->db, ctx->account_id, a);
+        strbuf_append(&sb, ",", 1);
+        append_compare_period(&sb, db->db, ctx->account_id, b);
+        strbuf_appendf(
+            &sb,
+            "],\"deltas\":{\"activities\":%d,\"hours\":%.1f,\"tss\":%.0f,\"weekly_hours\":%.2f,\"weekly_tss\":%.1f,\"power_curve\":[",
+            b->activities - a->activities,
+            (b->duration_sec - a->duration_sec) / 3600.0,
+            b->tss - a->tss,
+            b->duration_sec / 3600.0 / (double)b->week_count - a->duration_sec / 3600.0 / (double)a->week_count,
+            b->tss / (double)b->week_count - a->tss / (double)a->week_count);
+        for (size_t i = 0; i < POWER_CURVE_POINTS; i++) {
+            strbuf_appendf(&sb, "%s{\"duration_sec\":%d,\"watts\":", i == 0 ? "" : ",", POWER_CURVE_DURATIONS[i]);
+            if (a->power_curve[i] < 0.0 || b->power_curve[i] < 0.0) {
+                strbuf_append(&sb, "null,\"pct\":null}", 16);
+            } else {
+                double delta = b->power_curve[i] - a->power_curve[i];
+                strbuf_appendf(&sb, "%.0f,\"pct\":%.1f}", delta, a->power_curve[i] > 0.0 ? delta * 100.0 / a->power_curve[i] : 0.0);
+            }
+        }
+        strbuf_append(&sb, "]},\"pr_changes\":[", 17);
+        int first = 1;
+        for (size_t i = 0; i < POWER_CURVE_POINTS; i++) {
+            if (b->power_curve[i] < 0.0 || b->power_curve[i] <= a->power_curve[i]) continue;
+            strbuf_appendf(&sb, "%s{\"duration_sec\":%d,\"from\":", first ? "" : ",", POWER_CURVE_DURATIONS[i]);
+            append_optional_watts(&sb, a->power_curve[i]);
+            strbuf_appendf(&sb, ",\"to\":%.0f}", b->power_curve[i]);
+            first = 0;
+        }
+        strbuf_append(&sb, "]", 1);
+        append_load_models(&sb, db->db, ctx->account_id, model);
+        strbuf_append(&sb, "}", 1);
+        if (sb.failed) status = 500;
+    }
+
+    for (int i = 0; i < 2; i++) {
+        free(periods[i].week_hours);
+        free(periods[i].week_tss);
+    }
+    if (status != 200) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* Fills out[0..to_day-from_day] with one PMC metric; days before the first activity read 0. */
+static int load_metric_window(
+    sqlite3 *db, const char *account_id, const char *model, const char *metric, int from_day, int to_day, double *out) {
+    int first_day = 0;
+    double *daily = NULL;
+    size_t days = 0;
+    size_t window = (size_t)(to_day - from_day + 1);
+    memset(out, 0, window * sizeof(double));
+    if (load_account_daily_tss(db, account_id, model, to_day, &first_day, &daily, &days) != 0) return -1;
+    if (days == 0) return 0;
+
+    pmc_point_t *pmc = (pmc_point_t *)calloc(days, sizeof(pmc_point_t));
+    if (!pmc) {
+        free(daily);
+        return -1;
+    }
+    compute_pmc_series(daily, days, NULL, pmc);
+    for (size_t i = 0; i < window; i++) {
+        int day = from_day + (int)i;
+        if (day < first_day) continue;
+        size_t index = (size_t)(day - first_day);
+        if (strcmp(metric, "tss") == 0) {
+            out[i] = daily[index];
+        } else if (strcmp(metric, "atl") == 0) {
+            out[i] = pmc[index].atl;
+        } else if (strcmp(metric, "tsb") == 0) {
+            out[i] = pmc[index].tsb;
+        } else {
+            out[i] = pmc[index].ctl;
+        }
+    }
+    free(pmc);
+    free(daily);
+    return 0;
+}
+
+static int valid_athlete_id(const char *athlete) {
+    size_t len = strlen(athlete);
+    if (len == 0 || len >= COACH_COMPARE_ATHLETE_MAX) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char c = (unsigned char)athlete[i];
+        if (!isalnum(c) && c != '-' && c != '_' && c != '.') return 0;
+    }
+    return 1;
+}
+
+/*
+ * Coach overlay: one metric per athlete on a shared date axis ending today. The caller's own
+ * account is always readable; every other athlete needs one of their coach tokens in X-Coach-Token.
+ */
+int handle_get_coach_compare(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw_athletes[COACH_COMPARE_MAX_ATHLETES * COACH_COMPARE_ATHLETE_MAX] = {0};
+    if (!query_param(req->query, "athletes", raw_athletes, sizeof(raw_athletes)) || raw_athletes[0] == '\0') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"athletes query parameter is required\"}", ctx);
+        return 400;
+    }
+    const char *athletes[COACH_COMPARE_MAX_ATHLETES];
+    size_t athlete_count = 0;
+    for (char *save = NULL, *athlete = strtok_r(raw_athletes, ",", &save); athlete; athlete = strtok_r(NULL, ",", &save)) {
+        if (athlete_count == COACH_COMPARE_MAX_ATHLETES) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"at most 10 athletes can be compared\"}", ctx);
+            return 400;
+        }
+        if (!valid_athlete_id(athlete)) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid athlete id\"}", ctx);
+            return 400;
+        }
+        athletes[athlete_count++] = athlete;
+    }
+    if (athlete_count == 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"athletes query parameter is required\"}", ctx);
+        return 400;
+    }
+
+    char metric[8] = "ctl";
+    char raw_metric[16] = {0};
+    if (query_param(req->query, "metric", raw_metric, sizeof(raw_metric))) {
+        if (strcmp(raw_metric, "ctl") != 0 && strcmp(raw_metric, "atl") != 0 && strcmp(raw_metric, "tsb") != 0 &&
+            strcmp(raw_metric, "tss") != 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"metric must be ctl, atl, tsb or tss\"}", ctx);
+            return 400;
+        }
+        snprintf(metric, sizeof(metric), "%s", raw_metric);
+    }
+    int days = COACH_COMPARE_DEFAULT_DAYS;
+    char raw_days[16] = {0};
+    if (query_param(req->query, "days", raw_days, sizeof(raw_days))) {
+        long parsed = strtol(raw_days, NULL, 10);
+        if (parsed <= 0 || parsed > COACH_COMPARE_MAX_DAYS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"days must be in 1..365\"}", ctx);
+            return 400;
+        }
+        days = (int)parsed;
+    }
+    char model[16] = {0};
+    if (parse_load_model(req, model, sizeof(model)) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"unknown load model\"}", ctx);
+        return 400;
+    }
+
+    for (size_t i = 0; i < athlete_count; i++) {
+        if (strcmp(athletes[i], ctx->account_id) == 0) continue;
+        int allowed = coach_grant_allows(db->db, req, athletes[i]);
+        if (allowed < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        if (!allowed) {
+            char body[256] = {0};
+            snprintf(body, sizeof(body), "{\"error\":\"no sharing grant for athlete\",\"athlete\":\"%s\"}", athletes[i]);
+            log_info("ANALYTICS coach_compare denied athlete=%s account=%s logid=%s", athletes[i], ctx->account_id, ctx->log_id);
+            send_response_with_log_context(fd, 403, "Forbidden", body, ctx);
+            return 403;
+        }
+    }
+
+    int to_day = today_day();
+    int from_day = to_day - days + 1;
+    double *values = (double *)malloc((size_t)days * sizeof(double));
+    if (!values) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+    char from_label[16] = {0};
+    char to_label[16] = {0};
+    format_iso_day(from_day, from_label, sizeof(from_label));
+    format_iso_day(to_day, to_label, sizeof(to_label));
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb, "{\"metric\":\"%s\",\"days\":%d,\"from\":\"%s\",\"to\":\"%s\",\"dates\":[", metric, days, from_label, to_label);
+    for (int i = 0; i < days; i++) {
+        char label[16] = {0};
+        format_iso_day(from_day + i, label, sizeof(label));
+        strbuf_appendf(&sb, "%s\"%s\"", i == 0 ? "" : ",", label);
+    }
+    strbuf_append(&sb, "],\"athletes\":[", 14);
+    int status = 200;
+    for (size_t a = 0; a < athlete_count; a++) {
+        if (load_metric_window(db->db, athletes[a], model[0] != '\0' ? model : NULL, metric, from_day, to_day, values) != 0) {
+            status = 500;
+            break;
+        }
+        strbuf_appendf(&sb, "%s{\"athlete\":\"%s\",\"values\":[", a == 0 ? "" : ",", athletes[a]);
+        for (int i = 0; i < days; i++) strbuf_appendf(&sb, "%s%.1f", i == 0 ? "" : ",", values[i]);
+        strbuf_append(&sb, "]}", 2);
+    }
+    free(values);
+    strbuf_append(&sb, "]}", 2);
+    if (status != 200 || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+    log_info("ANALYTICS coach_compare account=%s athletes=%zu metric=%s days=%d logid=%s", ctx->account_id, athlete_count, metric, days, ctx->log_id);
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
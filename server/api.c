@@ -0,0 +1,375 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define API_ITEM_ID_MAX 128
+#define API_ITEMS_DEFAULT_LIMIT 100
+#define API_ITEMS_MAX_LIMIT 1000
+
+int api_key_is_collection(const char *key) {
+    return strcmp(key, "profile") != 0 && strcmp(key, "app_settings") != 0;
+}
+
+/* Whole-document v1 access to collections is superseded by the v2 item routes. */
+void api_v1_declare_deprecation(const char *key) {
+    if (!api_key_is_collection(key)) return;
+    char endpoint[96] = {0};
+    char successor[96] = {0};
+    snprintf(endpoint, sizeof(endpoint), "/v1/data/%s", key);
+    snprintf(successor, sizeof(successor), "/v2/data/%s/items", key);
+    deprecation_declare(endpoint, API_V1_DATA_SUNSET, successor, NULL);
+}
+
+int is_valid_item_id(const char *id) {
+    size_t len = strlen(id);
+    if (len == 0 || len > API_ITEM_ID_MAX) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)id[i];
+        if (!(isalnum(ch) || ch == '-' || ch == '_' || ch == '.' || ch == ':')) return 0;
+    }
+    return 1;
+}
+
+/* Loads the stored collection document, or "[]" when the key has never been written. */
+static char *load_collection(sqlite3 *db, const char *storage_key, int *out_is_array) {
+    *out_is_array = 1;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_value, json_valid(data_value) AND json_type(data_value) = 'array' FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    char *doc = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        doc = strdup((const char *)sqlite3_column_text(stmt, 0));
+        *out_is_array = sqlite3_column_int(stmt, 1);
+    } else {
+        doc = strdup("[]");
+    }
+    sqlite3_finalize(stmt);
+    return doc;
+}
+
+static int find_item_index(sqlite3 *db, const char *doc, const char *item_id) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT key FROM json_each(?1) WHERE json_extract(value, '$.id') = ?2 ORDER BY key LIMIT 1", -1, &stmt, NULL) != SQLITE_OK) {
+        return -2;
+    }
+    sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, item_id, -1, SQLITE_TRANSIENT);
+    int index = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW) index = sqlite3_column_int(stmt, 0);
+    sqlite3_finalize(stmt);
+    return index;
+}
+
+static void send_versioned_json(int fd, int code, const char *status, const char *body, const char *doc, const request_log_context_t *ctx) {
+    char version[32] = {0};
+    char headers[64] = {0};
+    content_version(doc, strlen(doc), version, sizeof(version));
+    snprintf(headers, sizeof(headers), SYNC_VERSION_HEADER ": %s\r\n", version);
+    send_http_response(fd, code, status, "application/json", headers, body, body ? strlen(body) : 0, ctx);
+}
+
+static int handle_list_items(int fd, worker_db_t *db, const http_request_t *req, const char *key, const char *doc, const request_log_context_t *ctx) {
+    int offset = 0;
+    int limit = API_ITEMS_DEFAULT_LIMIT;
+    char raw[16] = {0};
+    if (query_param(req->query, "offset", raw, sizeof(raw))) offset = atoi(raw);
+    if (query_param(req->query, "limit", raw, sizeof(raw))) limit = atoi(raw);
+    if (offset < 0 || limit <= 0 || limit > API_ITEMS_MAX_LIMIT) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"offset must be >= 0 and limit in 1..1000\"}", ctx);
+        return 400;
+    }
+
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_array_length(?1), (SELECT json_group_array(json(value)) FROM"
+            " (SELECT value FROM json_each(?1) ORDER BY key LIMIT ?3 OFFSET ?2))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, offset);
+    sqlite3_bind_int(stmt, 3, limit);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        strbuf_appendf(&sb, "{\"key\":\"%s\",\"total\":%d,\"offset\":%d,\"limit\":%d,\"items\":", key, sqlite3_column_int(stmt, 0), offset, limit);
+        const char *items = (const char *)sqlite3_column_text(stmt, 1);
+        strbuf_append(&sb, items ? items : "[]", items ? strlen(items) : 2);
+        strbuf_append(&sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    if (sb.failed || sb.len == 0) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    /* Already paginated, so only the byte limit applies to a page. */
+    int refused = response_guard(fd, -1, sb.len, "request fewer items per page with a smaller limit", ctx);
+    if (refused != 0) {
+        strbuf_free(&sb);
+        return refused;
+    }
+    send_versioned_json(fd, 200, "OK", strbuf_cstr(&sb), doc, ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_get_item(int fd, worker_db_t *db, const char *doc, const char *item_id, const request_log_context_t *ctx) {
+    int index = find_item_index(db->db, doc, item_id);
+    if (index < 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown item\"}", ctx);
+        return 404;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_extract(?1, '$[' || ?2 || ']')", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, index);
+    int status = 500;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        send_versioned_json(fd, 200, "OK", (const char *)sqlite3_column_text(stmt, 0), doc, ctx);
+        status = 200;
+    } else {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+    }
+    sqlite3_finalize(stmt);
+    return status;
+}
+
+/* Builds the next document for an upsert (body != NULL) or delete of one item. */
+static char *apply_item_change(sqlite3 *db, const char *doc, int index, const char *item_id, const char *body, size_t body_len) {
+    const char *sql = NULL;
+    if (!body) {
+        sql = "SELECT json_remove(?1, '$[' || ?2 || ']')";
+    } else if (index >= 0) {
+        sql = "SELECT json_set(?1, '$[' || ?2 || ']', json_set(json(?3), '$.id', ?4))";
+    } else {
+        sql = "SELECT json_insert(?1, '$[#]', json_set(json(?3), '$.id', ?4))";
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return NULL;
+    sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, index);
+    if (body) {
+        sqlite3_bind_text(stmt, 3, body, (int)body_len, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 4, item_id, -1, SQLITE_TRANSIENT);
+    }
+    char *next = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        next = strdup((const char *)sqlite3_column_text(stmt, 0));
+    }
+    sqlite3_finalize(stmt);
+    return next;
+}
+
+static int validate_item_body(sqlite3 *db, const http_request_t *req, const char *item_id, int fd, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT json_valid(?1) AND json_type(?1) = 'object', json_extract(?1, '$.id')", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    int status = 0;
+    if (sqlite3_step(stmt) != SQLITE_ROW || !sqlite3_column_int(stmt, 0)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"item must be a JSON object\"}", ctx);
+        status = 400;
+    } else if (sqlite3_column_type(stmt, 1) != SQLITE_NULL && strcmp((const char *)sqlite3_column_text(stmt, 1), item_id) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"item id does not match path\"}", ctx);
+        status = 400;
+    }
+    sqlite3_finalize(stmt);
+    return status;
+}
+
+/* create_only (POST) refuses to replace an item that already has this id. */
+static int handle_write_item(
+    int fd, worker_db_t *db, const http_request_t *req, const char *key, const char *storage_key, const char *item_id, int create_only, const request_log_context_t *ctx) {
+    int deleting = strcmp(req->method, "DELETE") == 0;
+    if (!deleting) {
+        int invalid = validate_item_body(db->db, req, item_id, fd, ctx);
+        if (invalid != 0) return invalid;
+    }
+
+    sync_document_lock();
+    int conflict = sync_check_base_version(db, req, key, fd, ctx);
+    if (conflict != 0) {
+        sync_document_unlock();
+        return conflict;
+    }
+    int is_array = 1;
+    char *doc = load_collection(db->db, storage_key, &is_array);
+    if (!doc || !is_array) {
+        sync_document_unlock();
+        free(doc);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+        return 409;
+    }
+    int index = find_item_index(db->db, doc, item_id);
+    if (deleting && index < 0) {
+        sync_document_unlock();
+        free(doc);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown item\"}", ctx);
+        return 404;
+    }
+    if (create_only && index >= 0) {
+        sync_document_unlock();
+        free(doc);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"item already exists\"}", ctx);
+        return 409;
+    }
+    char *next = apply_item_change(db->db, doc, index, item_id, deleting ? NULL : req->body, req->body_len);
+    free(doc);
+    if (!next) {
+        sync_document_unlock();
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+
+    char *prepared = data_prepare_payload(db->db, ctx->account_id, key, next, strlen(next));
+    if (prepared) {
+        free(next);
+        next = prepared;
+    }
+    char error_body[512] = {0};
+    int status = store_account_data(db, key, next, strlen(next), ctx, error_body, sizeof(error_body));
+    sync_document_unlock();
+    if (status == 202) {
+        free(next);
+        send_response_with_log_context(fd, 202, "Accepted", error_body, ctx);
+        return 202;
+    }
+    if (status != 204) {
+        free(next);
+        send_response_with_log_context(fd, status, status == 400 ? "Bad Request" : status == 422 ? "Unprocessable Entity" : "Internal Server Error", error_body, ctx);
+        return status;
+    }
+
+    log_info("DATA ITEM %s key=%s item=%s account=%s logid=%s", deleting ? "deleted" : index >= 0 ? "replaced" : "created", key, item_id, ctx->account_id, ctx->log_id);
+    if (deleting) {
+        send_versioned_json(fd, 204, "No Content", NULL, next, ctx);
+        free(next);
+        return 204;
+    }
+    char body[256] = {0};
+    snprintf(body, sizeof(body), "{\"id\":\"%s\",\"created\":%s}", item_id, index >= 0 ? "false" : "true");
+    int code = index >= 0 ? 200 : 201;
+    send_versioned_json(fd, code, code == 201 ? "Created" : "OK", body, next, ctx);
+    free(next);
+    return code;
+}
+
+/* POST .../items takes the body's "id" when it has one, otherwise assigns a UUID. */
+static int handle_create_item(int fd, worker_db_t *db, const http_request_t *req, const char *key, const char *storage_key, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1) AND json_type(?1) = 'object', json_type(?1, '$.id'), json_extract(?1, '$.id')", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    char item_id[API_ITEM_ID_MAX + 1] = {0};
+    int status = 0;
+    if (sqlite3_step(stmt) != SQLITE_ROW || !sqlite3_column_int(stmt, 0)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"item must be a JSON object\"}", ctx);
+        status = 400;
+    } else if (sqlite3_column_type(stmt, 1) != SQLITE_NULL) {
+        const char *given = (const char *)sqlite3_column_text(stmt, 2);
+        if (strcmp((const char *)sqlite3_column_text(stmt, 1), "text") != 0 || !is_valid_item_id(given)) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"item id must be 1-128 chars of [A-Za-z0-9._:-]\"}", ctx);
+            status = 400;
+        } else {
+            snprintf(item_id, sizeof(item_id), "%s", given);
+        }
+    } else if (generate_uuid_v4(item_id, sizeof(item_id)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"could not assign id\"}", ctx);
+        status = 500;
+    }
+    sqlite3_finalize(stmt);
+    if (status != 0) return status;
+    return handle_write_item(fd, db, req, key, storage_key, item_id, 1, ctx);
+}
+
+/* /v1/data/<key>/items[/<id>] and /v2/data/<key>/items[/<id>] share one implementation. */
+static int route_items(int fd, worker_db_t *db, const http_request_t *req, const char *prefix, const request_log_context_t *ctx) {
+    if (strncmp(req->path, prefix, strlen(prefix)) != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+
+    char key[128] = {0};
+    const char *rest = req->path + strlen(prefix);
+    const char *slash = strchr(rest, '/');
+    if (!slash || (size_t)(slash - rest) >= sizeof(key) || strncmp(slash, "/items", 6) != 0 || (slash[6] != '\0' && slash[6] != '/')) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    memcpy(key, rest, (size_t)(slash - rest));
+    const char *item_id = slash[6] == '/' ? slash + 7 : NULL;
+    if (!is_valid_key(key) || !api_key_is_collection(key)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown collection\"}", ctx);
+        return 404;
+    }
+    if (item_id && !is_valid_item_id(item_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"item id must be 1-128 chars of [A-Za-z0-9._:-]\"}", ctx);
+        return 400;
+    }
+    int negotiated = sync_negotiate_protocol(req, fd, ctx);
+    if (negotiated != 0) return negotiated;
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    if (strcmp(req->method, "GET") == 0) {
+        int is_array = 1;
+        char *doc = load_collection(db->db, storage_key, &is_array);
+        if (!doc || !is_array) {
+            free(doc);
+            send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+            return 409;
+        }
+        int status = item_id ? handle_get_item(fd, db, doc, item_id, ctx) : handle_list_items(fd, db, req, key, doc, ctx);
+        free(doc);
+        return status;
+    }
+    if (item_id && (strcmp(req->method, "PUT") == 0 || strcmp(req->method, "DELETE") == 0)) {
+        return handle_write_item(fd, db, req, key, storage_key, item_id, 0, ctx);
+    }
+    if (!item_id && strcmp(req->method, "POST") == 0) return handle_create_item(fd, db, req, key, storage_key, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
+
+int route_v2(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    return route_items(fd, db, req, "/v2/data/", ctx);
+}
+
+int route_v1_items(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    return route_items(fd, db, req, "/v1/data/", ctx);
+}
+
+/* True for /v1/data/<key>/items and /v1/data/<key>/items/<id>. */
+int api_is_v1_items_path(const char *path) {
+    if (strncmp(path, "/v1/data/", 9) != 0) return 0;
+    const char *slash = strchr(path + 9, '/');
+    return slash && strncmp(slash, "/items", 6) == 0 && (slash[6] == '\0' || slash[6] == '/');
+}
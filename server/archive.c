@@ -0,0 +1,320 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#ifdef FRICU_HAVE_ZLIB
+#include <zlib.h>
+#endif
+
+/*
+ * Zip archives for the full data export and import. Entries are deflated when the server has zlib
+ * and that makes them smaller, and stored otherwise, so any unzip tool opens the result either way.
+ * Names are UTF-8 (flag bit 11) and times are the entry's UTC modification time in DOS format. There
+ * is no zip64: an archive stops growing at 4 GiB or 65535 entries and zip_writer_add fails instead.
+ * The reader finds entries through the central directory and checks each one's CRC; it reads
+ * stored entries anywhere and deflated ones when built with zlib, and refuses encrypted entries.
+ */
+
+#define ZIP_VERSION 20
+#define ZIP_FLAG_UTF8 0x0800
+#define ZIP_METHOD_STORED 0
+#define ZIP_METHOD_DEFLATE 8
+#define ZIP_MAX_ENTRIES 65535
+
+static uint32_t g_crc_table[256];
+static pthread_once_t g_crc_once = PTHREAD_ONCE_INIT;
+
+static void crc_table_init(void) {
+    for (uint32_t i = 0; i < 256; i++) {
+        uint32_t c = i;
+        for (int k = 0; k < 8; k++) c = (c & 1) ? 0xEDB88320u ^ (c >> 1) : c >> 1;
+        g_crc_table[i] = c;
+    }
+}
+
+uint32_t zip_crc32(const void *data, size_t len) {
+    pthread_once(&g_crc_once, crc_table_init);
+    const unsigned char *p = (const unsigned char *)data;
+    uint32_t c = 0xFFFFFFFFu;
+    for (size_t i = 0; i < len; i++) c = g_crc_table[(c ^ p[i]) & 0xFF] ^ (c >> 8);
+    return c ^ 0xFFFFFFFFu;
+}
+
+static void put_u16(unsigned char *p, unsigned v) {
+    p[0] = (unsigned char)(v & 0xFF);
+    p[1] = (unsigned char)((v >> 8) & 0xFF);
+}
+
+static void put_u32(unsigned char *p, uint32_t v) {
+    put_u16(p, v & 0xFFFF);
+    put_u16(p + 2, v >> 16);
+}
+
+static void dos_time(long long unix_time, unsigned *out_time, unsigned *out_date) {
+    time_t t = (time_t)unix_time;
+    struct tm tm;
+    if (unix_time < 315532800LL || !gmtime_r(&t, &tm)) {
+        /* DOS dates start in 1980. */
+        *out_time = 0;
+        *out_date = (1 << 5) | 1;
+        return;
+    }
+    *out_time = (unsigned)((tm.tm_hour << 11) | (tm.tm_min << 5) | (tm.tm_sec / 2));
+    *out_date = (unsigned)(((tm.tm_year - 80) << 9) | ((tm.tm_mon + 1) << 5) | tm.tm_mday);
+}
+
+/* Raw deflate of data when it comes out smaller; NULL means store it. */
+static unsigned char *deflate_entry(const char *data, size_t len, size_t *out_len) {
+#ifdef FRICU_HAVE_ZLIB
+    if (len < 64) return NULL;
+    z_stream zs;
+    memset(&zs, 0, sizeof(zs));
+    if (deflateInit2(&zs, 6, Z_DEFLATED, -15, 8, Z_DEFAULT_STRATEGY) != Z_OK) return NULL;
+    unsigned long bound = deflateBound(&zs, (unsigned long)len);
+    unsigned char *out = (unsigned char *)malloc(bound);
+    if (!out) {
+        deflateEnd(&zs);
+        return NULL;
+    }
+    zs.next_in = (unsigned char *)data;
+    zs.avail_in = (unsigned)len;
+    zs.next_out = out;
+    zs.avail_out = (unsigned)bound;
+    int rc = deflate(&zs, Z_FINISH);
+    *out_len = zs.total_out;
+    deflateEnd(&zs);
+    if (rc != Z_STREAM_END || *out_len >= len) {
+        free(out);
+        return NULL;
+    }
+    return out;
+#else
+    (void)data;
+    (void)len;
+    (void)out_len;
+    return NULL;
+#endif
+}
+
+void zip_writer_init(zip_writer_t *zw) {
+    memset(zw, 0, sizeof(*zw));
+    strbuf_init(&zw->out);
+    strbuf_init(&zw->central);
+}
+
+int zip_writer_add(zip_writer_t *zw, const char *name, const char *data, size_t len, long long mtime) {
+    size_t name_len = strlen(name);
+    if (zw->failed || name_len == 0 || name_len > 0xFFFF || zw->entries >= ZIP_MAX_ENTRIES || len > 0xFFFFFFFFu || zw->out.len > 0xFFFFFFFFu) {
+        zw->failed = 1;
+        return -1;
+    }
+    size_t packed_len = 0;
+    unsigned char *packed = deflate_entry(data, len, &packed_len);
+    unsigned method = packed ? ZIP_METHOD_DEFLATE : ZIP_METHOD_STORED;
+    const char *payload = packed ? (const char *)packed : data;
+    if (!packed) packed_len = len;
+    uint32_t crc = zip_crc32(data, len);
+    unsigned mod_time = 0;
+    unsigned mod_date = 0;
+    dos_time(mtime, &mod_time, &mod_date);
+
+    unsigned char local[30];
+    put_u32(local, 0x04034b50u);
+    put_u16(local + 4, ZIP_VERSION);
+    put_u16(local + 6, ZIP_FLAG_UTF8);
+    put_u16(local + 8, method);
+    put_u16(local + 10, mod_time);
+    put_u16(local + 12, mod_date);
+    put_u32(local + 14, crc);
+    put_u32(local + 18, (uint32_t)packed_len);
+    put_u32(local + 22, (uint32_t)len);
+    put_u16(local + 26, (unsigned)name_len);
+    put_u16(local + 28, 0);
+
+    unsigned char central[46];
+    put_u32(central, 0x02014b50u);
+    /* Made by Unix, so the external attributes below are read as a mode. */
+    put_u16(central + 4, (3 << 8) | ZIP_VERSION);
+    memcpy(central + 6, local + 4, 26);
+    put_u16(central + 32, 0);
+    put_u16(central + 34, 0);
+    put_u16(central + 36, 0);
+    put_u32(central + 38, (uint32_t)0100644 << 16);
+    put_u32(central + 42, (uint32_t)zw->out.len);
+
+    if (strbuf_append(&zw->out, (const char *)local, sizeof(local)) != 0 || strbuf_append(&zw->out, name, name_len) != 0 ||
+        strbuf_append(&zw->out, payload, packed_len) != 0 || strbuf_append(&zw->central, (const char *)central, sizeof(central)) != 0 ||
+        strbuf_append(&zw->central, name, name_len) != 0) {
+        zw->failed = 1;
+    }
+    free(packed);
+    zw->entries++;
+    return zw->failed ? -1 : 0;
+}
+
+int zip_writer_finish(zip_writer_t *zw) {
+    if (zw->failed || zw->out.len + zw->central.len > 0xFFFFFFFFu) {
+        zw->failed = 1;
+        return -1;
+    }
+    unsigned char end[22];
+    put_u32(end, 0x06054b50u);
+    put_u16(end + 4, 0);
+    put_u16(end + 6, 0);
+    put_u16(end + 8, (unsigned)zw->entries);
+    put_u16(end + 10, (unsigned)zw->entries);
+    put_u32(end + 12, (uint32_t)zw->central.len);
+    put_u32(end + 16, (uint32_t)zw->out.len);
+    put_u16(end + 20, 0);
+    if (strbuf_append(&zw->out, zw->central.data ? zw->central.data : "", zw->central.len) != 0 ||
+        strbuf_append(&zw->out, (const char *)end, sizeof(end)) != 0) {
+        zw->failed = 1;
+        return -1;
+    }
+    return 0;
+}
+
+void zip_writer_free(zip_writer_t *zw) {
+    strbuf_free(&zw->out);
+    strbuf_free(&zw->central);
+}
+
+static unsigned get_u16(const unsigned char *p) {
+    return (unsigned)p[0] | ((unsigned)p[1] << 8);
+}
+
+static uint32_t get_u32(const unsigned char *p) {
+    return (uint32_t)get_u16(p) | ((uint32_t)get_u16(p + 2) << 16);
+}
+
+int zip_reader_open(zip_reader_t *zr, const char *data, size_t len) {
+    memset(zr, 0, sizeof(*zr));
+    if (!data || len < 22) return -1;
+    const unsigned char *bytes = (const unsigned char *)data;
+    /* The end record sits at the end unless the archive has a comment (at most 65535 bytes). */
+    size_t lowest = len > 22 + 0xFFFF ? len - 22 - 0xFFFF : 0;
+    for (size_t pos = len - 22 + 1; pos-- > lowest;) {
+        const unsigned char *end = bytes + pos;
+        if (get_u32(end) != 0x06054b50u || pos + 22 + get_u16(end + 20) != len) continue;
+        size_t central_len = get_u32(end + 12);
+        size_t central = get_u32(end + 16);
+        if (central > pos || central_len > pos - central) return -1;
+        zr->data = bytes;
+        zr->len = len;
+        zr->central = central;
+        zr->central_len = central_len;
+        zr->entries = (int)get_u16(end + 10);
+        return 0;
+    }
+    return -1;
+}
+
+static int inflate_entry(const unsigned char *in, size_t in_len, char *out, size_t out_len) {
+#ifdef FRICU_HAVE_ZLIB
+    z_stream zs;
+    memset(&zs, 0, sizeof(zs));
+    if (inflateInit2(&zs, -15) != Z_OK) return -1;
+    zs.next_in = (unsigned char *)in;
+    zs.avail_in = (unsigned)in_len;
+    zs.next_out = (unsigned char *)out;
+    zs.avail_out = (unsigned)out_len;
+    int rc = inflate(&zs, Z_FINISH);
+    size_t produced = zs.total_out;
+    inflateEnd(&zs);
+    return rc == Z_STREAM_END && produced == out_len ? 0 : -1;
+#else
+    (void)in;
+    (void)in_len;
+    (void)out;
+    (void)out_len;
+    return -1;
+#endif
+}
+
+int zip_reader_find(const zip_reader_t *zr, const char *name, size_t max_len, char **out, size_t *out_len, char *err, size_t err_len) {
+    *out = NULL;
+    *out_len = 0;
+    size_t name_len = strlen(name);
+    const unsigned char *p = zr->data + zr->central;
+    const unsigned char *end = p + zr->central_len;
+    for (int i = 0; i < zr->entries; i++) {
+        if ((size_t)(end - p) < 46 || get_u32(p) != 0x02014b50u) {
+            snprintf(err, err_len, "damaged central directory");
+            return -1;
+        }
+        size_t entry_name_len = get_u16(p + 28);
+        size_t record_len = 46 + entry_name_len + get_u16(p + 30) + get_u16(p + 32);
+        if (record_len > (size_t)(end - p)) {
+            snprintf(err, err_len, "damaged central directory");
+            return -1;
+        }
+        if (entry_name_len != name_len || memcmp(p + 46, name, name_len) != 0) {
+            p += record_len;
+            continue;
+        }
+        unsigned flags = get_u16(p + 8);
+        unsigned method = get_u16(p + 10);
+        uint32_t crc = get_u32(p + 16);
+        size_t packed_len = get_u32(p + 20);
+        size_t len = get_u32(p + 24);
+        size_t local = get_u32(p + 42);
+        if (flags & 1) {
+            snprintf(err, err_len, "%s is encrypted", name);
+            return -1;
+        }
+        if (method != ZIP_METHOD_STORED && method != ZIP_METHOD_DEFLATE) {
+            snprintf(err, err_len, "%s uses an unsupported compression method", name);
+            return -1;
+        }
+#ifndef FRICU_HAVE_ZLIB
+        if (method == ZIP_METHOD_DEFLATE) {
+            snprintf(err, err_len, "%s is deflated; this server was built without zlib", name);
+            return -1;
+        }
+#endif
+        if (len > max_len) {
+            snprintf(err, err_len, "%s is larger than %zu bytes", name, max_len);
+            return -1;
+        }
+        if (local > zr->central || zr->central - local < 30 || get_u32(zr->data + local) != 0x04034b50u) {
+            snprintf(err, err_len, "damaged entry %s", name);
+            return -1;
+        }
+        size_t data_start = local + 30 + get_u16(zr->data + local + 26) + get_u16(zr->data + local + 28);
+        if (data_start > zr->central || packed_len > zr->central - data_start || (method == ZIP_METHOD_STORED && packed_len != len)) {
+            snprintf(err, err_len, "damaged entry %s", name);
+            return -1;
+        }
+        char *buf = (char *)malloc(len + 1);
+        if (!buf) {
+            snprintf(err, err_len, "out of memory");
+            return -1;
+        }
+        const unsigned char *packed = zr->data + data_start;
+        int ok = 1;
+        if (method == ZIP_METHOD_STORED) {
+            memcpy(buf, packed, len);
+        } else {
+            ok = inflate_entry(packed, packed_len, buf, len) == 0;
+        }
+        if (!ok || zip_crc32(buf, len) != crc) {
+            free(buf);
+            snprintf(err, err_len, "%s is damaged (checksum mismatch)", name);
+            return -1;
+        }
+        buf[len] = '\0';
+        *out = buf;
+        *out_len = len;
+        return 0;
+    }
+    return 1;
+}
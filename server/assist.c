@@ -0,0 +1,338 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * Voice-assistant briefing. GET /v1/assist/briefing folds today's calendar (events, planned
+ * workouts, what is already done), the current form from the PMC and, when a location is known, the
+ * current weather into one sentence that Home Assistant can hand straight to TTS. Weather comes from
+ * an Open-Meteo compatible endpoint, keyed by ?lat=&lon= or the profile's latitude/longitude, and is
+ * cached per location so a chatty automation does not hit the provider on every call.
+ */
+
+#define ASSIST_WEATHER_URL "https://api.open-meteo.com/v1/forecast"
+#define ASSIST_WEATHER_TTL_SEC 1800
+#define ASSIST_WEATHER_CACHE_SIZE 16
+
+typedef struct {
+    char location[48];
+    time_t fetched_at;
+    double temperature_c;
+    int code;
+} weather_entry_t;
+
+static pthread_mutex_t weather_lock = PTHREAD_MUTEX_INITIALIZER;
+static weather_entry_t weather_cache[ASSIST_WEATHER_CACHE_SIZE];
+
+static const char *weather_url(void) {
+    const char *url = getenv("FRICU_WEATHER_URL");
+    return url && url[0] != '\0' ? url : ASSIST_WEATHER_URL;
+}
+
+/* WMO weather interpretation codes as Open-Meteo reports them. */
+static const char *describe_weather_code(int code) {
+    if (code == 0) return "clear sky";
+    if (code <= 2) return "partly cloudy";
+    if (code == 3) return "overcast";
+    if (code == 45 || code == 48) return "fog";
+    if (code >= 51 && code <= 57) return "drizzle";
+    if ((code >= 61 && code <= 67) || (code >= 80 && code <= 82)) return "rain";
+    if ((code >= 71 && code <= 77) || code == 85 || code == 86) return "snow";
+    if (code >= 95) return "thunderstorms";
+    return "mixed weather";
+}
+
+static int load_profile_location(sqlite3 *db, const char *account_id, double *lat, double *lon) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return -1;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(data_value, '$.latitude'), json_extract(data_value, '$.longitude')"
+            " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int rc = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) != SQLITE_NULL && sqlite3_column_type(stmt, 1) != SQLITE_NULL) {
+        *lat = sqlite3_column_double(stmt, 0);
+        *lon = sqlite3_column_double(stmt, 1);
+        rc = 0;
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+/* Current temperature and weather code at a location; 0 on success. */
+static int fetch_weather(sqlite3 *db, double lat, double lon, double *temperature_c, int *code) {
+    const char *enabled = getenv("FRICU_WEATHER");
+    if (enabled && strcmp(enabled, "0") == 0) return -1;
+    char location[48] = {0};
+    snprintf(location, sizeof(location), "%.2f,%.2f", lat, lon);
+    time_t now = time(NULL);
+
+    pthread_mutex_lock(&weather_lock);
+    weather_entry_t *slot = &weather_cache[0];
+    for (size_t i = 0; i < ASSIST_WEATHER_CACHE_SIZE; i++) {
+        weather_entry_t *e = &weather_cache[i];
+        if (strcmp(e->location, location) == 0) {
+            slot = e;
+            break;
+        }
+        if (e->fetched_at < slot->fetched_at) slot = e;
+    }
+    if (strcmp(slot->location, location) == 0 && now - slot->fetched_at < ASSIST_WEATHER_TTL_SEC) {
+        *temperature_c = slot->temperature_c;
+        *code = slot->code;
+        pthread_mutex_unlock(&weather_lock);
+        return 0;
+    }
+    pthread_mutex_unlock(&weather_lock);
+
+    char url[1024] = {0};
+    char err[256] = {0};
+    snprintf(url, sizeof(url), "%s?latitude=%.4f&longitude=%.4f&current=temperature_2m,weather_code", weather_url(), lat, lon);
+    strbuf_t body;
+    strbuf_init(&body);
+    int status = outbound_get(url, &body, err, sizeof(err));
+    int rc = -1;
+    sqlite3_stmt *stmt = NULL;
+    if (status == 200 && !body.failed &&
+        sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(?1, '$.current.temperature_2m'), json_extract(?1, '$.current.weather_code') WHERE json_valid(?1)",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, strbuf_cstr(&body), (int)body.len, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) != SQLITE_NULL) {
+            *temperature_c = sqlite3_column_double(stmt, 0);
+            *code = sqlite3_column_type(stmt, 1) == SQLITE_NULL ? -1 : sqlite3_column_int(stmt, 1);
+            rc = 0;
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_free(&body);
+    if (rc != 0) {
+        log_warn("event=assist_weather_failed location=%s status=%d error=\"%s\"", location, status, err);
+        return -1;
+    }
+
+    pthread_mutex_lock(&weather_lock);
+    snprintf(slot->location, sizeof(slot->location), "%s", location);
+    slot->fetched_at = now;
+    slot->temperature_c = *temperature_c;
+    slot->code = *code;
+    pthread_mutex_unlock(&weather_lock);
+    return 0;
+}
+
+static const char *sport_noun(const char *sport) {
+    if (!sport) return "workout";
+    if (strcmp(sport, "cycling") == 0) return "ride";
+    if (strcmp(sport, "running") == 0) return "run";
+    if (strcmp(sport, "swimming") == 0) return "swim";
+    if (strcmp(sport, "strength") == 0) return "strength session";
+    return "workout";
+}
+
+static void append_clause(strbuf_t *text, int *clauses, const char *joiner) {
+    if ((*clauses)++ > 0) strbuf_append(text, joiner, strlen(joiner));
+}
+
+int handle_get_assist_briefing(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char format[16] = {0};
+    char lat_text[32] = {0};
+    char lon_text[32] = {0};
+    query_param(req->query, "format", format, sizeof(format));
+    query_param(req->query, "lat", lat_text, sizeof(lat_text));
+    query_param(req->query, "lon", lon_text, sizeof(lon_text));
+    if (format[0] != '\0' && strcmp(format, "json") != 0 && strcmp(format, "text") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"format must be json or text\"}", ctx);
+        return 400;
+    }
+    double lat = 0.0;
+    double lon = 0.0;
+    int have_location = 0;
+    if (lat_text[0] != '\0' || lon_text[0] != '\0') {
+        char *lat_end = NULL;
+        char *lon_end = NULL;
+        lat = strtod(lat_text, &lat_end);
+        lon = strtod(lon_text, &lon_end);
+        if (lat_end == lat_text || *lat_end != '\0' || lon_end == lon_text || *lon_end != '\0' || fabs(lat) > 90.0 || fabs(lon) > 180.0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"lat and lon must be valid coordinates\"}", ctx);
+            return 400;
+        }
+        have_location = 1;
+    } else {
+        have_location = load_profile_location(db->db, ctx->account_id, &lat, &lon) == 0;
+    }
+
+    char today[16] = {0};
+    char events_key[256] = {0};
+    char workouts_key[256] = {0};
+    char activities_key[256] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+    if (build_storage_key(ctx->account_id, "events", events_key, sizeof(events_key)) != 0 ||
+        build_storage_key(ctx->account_id, "workouts", workouts_key, sizeof(workouts_key)) != 0 ||
+        build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid account\"}", ctx);
+        return 400;
+    }
+    const char *sql =
+        "SELECT 0, json_extract(e.value, '$.name'), NULL, 0, 0"
+        " FROM kv_store k, json_each(k.data_value) e"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(e.value, '$.startDate'), 1, 10) = ?4"
+        " UNION ALL"
+        " SELECT 1, COALESCE(json_extract(w.value, '$.name'), 'Workout'), json_extract(w.value, '$.sport'),"
+        " (SELECT COALESCE(SUM(json_extract(s.value, '$.minutes')), 0) FROM json_each(w.value, '$.segments') s), 0"
+        " FROM kv_store k, json_each(k.data_value) w"
+        " WHERE k.data_key = ?2 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(w.value, '$.scheduledDate'), 1, 10) = ?4"
+        " UNION ALL"
+        " SELECT 2, NULL, json_extract(a.value, '$.sport'), COALESCE(json_extract(a.value, '$.durationSec'), 0) / 60.0,"
+        " COALESCE(json_extract(a.value, '$.tss'), 0)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?3 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) = ?4";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"query_failed\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, events_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, workouts_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, today, -1, SQLITE_TRANSIENT);
+
+    strbuf_t text;
+    strbuf_t events;
+    strbuf_t planned;
+    strbuf_init(&text);
+    strbuf_init(&events);
+    strbuf_init(&planned);
+    strbuf_append(&text, "Today: ", 7);
+    int clauses = 0;
+    int planned_count = 0;
+    int event_count = 0;
+    int done_count = 0;
+    double done_minutes = 0.0;
+    double done_tss = 0.0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int kind = sqlite3_column_int(stmt, 0);
+        const char *name = (const char *)sqlite3_column_text(stmt, 1);
+        const char *sport = (const char *)sqlite3_column_text(stmt, 2);
+        double minutes = sqlite3_column_double(stmt, 3);
+        if (kind == 0 && name) {
+            append_clause(&text, &clauses, ", ");
+            strbuf_append(&text, name, strlen(name));
+            if (event_count++ > 0) strbuf_append(&events, ",", 1);
+            strbuf_append_json_string(&events, name);
+        } else if (kind == 1) {
+            const char *noun = sport_noun(sport);
+            append_clause(&text, &clauses, planned_count > 0 ? " and " : ", ");
+            strbuf_appendf(&text, "%.0f min %s", minutes, name);
+            if (!strcasestr(name, noun)) strbuf_appendf(&text, " %s", noun);
+            if (planned_count++ > 0) strbuf_append(&planned, ",", 1);
+            strbuf_append(&planned, "{\"name\":", 8);
+            strbuf_append_json_string(&planned, name);
+            strbuf_append(&planned, ",\"sport\":", 9);
+            if (sport) {
+                strbuf_append_json_string(&planned, sport);
+            } else {
+                strbuf_append(&planned, "null", 4);
+            }
+            strbuf_appendf(&planned, ",\"minutes\":%.0f}", minutes);
+        } else if (kind == 2) {
+            done_count++;
+            done_minutes += minutes;
+            done_tss += sqlite3_column_double(stmt, 4);
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (planned_count == 0) {
+        append_clause(&text, &clauses, ", ");
+        strbuf_append(&text, "rest day", 8);
+    }
+    if (done_count > 0) {
+        append_clause(&text, &clauses, ", ");
+        strbuf_appendf(&text, "already done %.0f min", done_minutes);
+    }
+
+    pmc_point_t pmc;
+    int have_pmc = compute_account_pmc_today(db->db, ctx->account_id, &pmc) == 0;
+    if (have_pmc) {
+        append_clause(&text, &clauses, ", ");
+        strbuf_appendf(&text, "TSB %ld", lround(pmc.tsb));
+    }
+    double temperature_c = 0.0;
+    int weather_code = -1;
+    int have_weather = have_location && fetch_weather(db->db, lat, lon, &temperature_c, &weather_code) == 0;
+    if (have_weather) {
+        append_clause(&text, &clauses, ", ");
+        strbuf_appendf(&text, "weather %ld°C and %s", lround(temperature_c), describe_weather_code(weather_code));
+    }
+    strbuf_append(&text, ".", 1);
+
+    if (text.failed || events.failed || planned.failed) {
+        strbuf_free(&text);
+        strbuf_free(&events);
+        strbuf_free(&planned);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (strcmp(format, "text") == 0) {
+        send_http_response(fd, 200, "OK", "text/plain; charset=utf-8", NULL, strbuf_cstr(&text), text.len, ctx);
+        strbuf_free(&text);
+        strbuf_free(&events);
+        strbuf_free(&planned);
+        return 200;
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"date\":\"", 9);
+    strbuf_append(&sb, today, strlen(today));
+    strbuf_append(&sb, "\",\"text\":", 9);
+    strbuf_append_json_string(&sb, strbuf_cstr(&text));
+    strbuf_append(&sb, ",\"events\":[", 11);
+    strbuf_append(&sb, strbuf_cstr(&events), events.len);
+    strbuf_append(&sb, "],\"planned\":[", 13);
+    strbuf_append(&sb, strbuf_cstr(&planned), planned.len);
+    strbuf_appendf(&sb, "],\"completed\":{\"count\":%d,\"minutes\":%.0f,\"tss\":%.1f}", done_count, done_minutes, done_tss);
+    if (have_pmc) {
+        strbuf_appendf(&sb, ",\"pmc\":{\"ctl\":%.1f,\"atl\":%.1f,\"tsb\":%.1f}", pmc.ctl, pmc.atl, pmc.tsb);
+    } else {
+        strbuf_append(&sb, ",\"pmc\":null", 11);
+    }
+    if (have_weather) {
+        strbuf_appendf(&sb, ",\"weather\":{\"temperature_c\":%.1f,\"code\":%d,\"summary\":\"%s\"}}", temperature_c, weather_code, describe_weather_code(weather_code));
+    } else {
+        strbuf_append(&sb, ",\"weather\":null}", 16);
+    }
+    strbuf_free(&text);
+    strbuf_free(&events);
+    strbuf_free(&planned);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
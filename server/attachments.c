@@ -0,0 +1,871 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <fcntl.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+#ifdef FRICU_HAVE_IMAGE_CODECS
+#include <jpeglib.h>
+#include <png.h>
+#include <setjmp.h>
+#endif
+
+/*
+ * Photos, route screenshots and PDFs linked to an activity or event. The attachments table keeps
+ * only metadata; the bytes (and the thumbnail) are files named by their SHA-256 under
+ * attachment_dir, so the database and its backups stay small and identical uploads share a file.
+ * Uploads and deletes run on the writer thread, which is the only place files are added or
+ * removed, so a delete never unlinks a file an upload is about to reference. JPEG/PNG uploads get
+ * a JPEG thumbnail when the server is built with libjpeg/libpng. The shared flag decides whether
+ * share links may expose an attachment.
+ */
+
+#define ATTACHMENT_ID_BYTES 12
+#define ATTACHMENT_FILENAME_MAX 128
+#define ATTACHMENT_LIST_MAX 200
+#define THUMBNAIL_MAX_EDGE 320
+#define THUMBNAIL_JPEG_QUALITY 80
+#define IMAGE_MAX_PIXELS (40LL * 1000 * 1000)
+#define ATTACHMENT_WRITE_WAIT_MS 5000
+
+typedef struct {
+    const char *content_type;
+    const char *magic;
+    size_t magic_len;
+    size_t magic_offset;
+} attachment_type_t;
+
+static const attachment_type_t ATTACHMENT_TYPES[] = {
+    {"image/jpeg", "\xff\xd8\xff", 3, 0},
+    {"image/png", "\x89PNG\r\n\x1a\n", 8, 0},
+    {"image/webp", "WEBP", 4, 8},
+    {"image/heic", "ftyp", 4, 4},
+    {"application/pdf", "%PDF-", 5, 0},
+};
+
+static const attachment_type_t *attachment_type_lookup(const char *content_type) {
+    for (size_t i = 0; i < sizeof(ATTACHMENT_TYPES) / sizeof(ATTACHMENT_TYPES[0]); i++) {
+        if (strcmp(ATTACHMENT_TYPES[i].content_type, content_type) == 0) return &ATTACHMENT_TYPES[i];
+    }
+    return NULL;
+}
+
+static int attachment_owner_key(const char *owner_type, const char **out_key) {
+    if (strcmp(owner_type, "activity") == 0) {
+        *out_key = "activities";
+        return 0;
+    }
+    if (strcmp(owner_type, "event") == 0) {
+        *out_key = "events";
+        return 0;
+    }
+    return -1;
+}
+
+#ifdef FRICU_HAVE_IMAGE_CODECS
+typedef struct {
+    struct jpeg_error_mgr base;
+    jmp_buf jump;
+} jpeg_error_t;
+
+static void jpeg_error_exit(j_common_ptr cinfo) {
+    jpeg_error_t *err = (jpeg_error_t *)cinfo->err;
+    longjmp(err->jump, 1);
+}
+
+/* Decodes at a reduced DCT scale where possible; width/height are the decoded size, full_* the original. */
+static unsigned char *decode_jpeg(const unsigned char *data, size_t len, int *width, int *height, int *full_width, int *full_height) {
+    struct jpeg_decompress_struct cinfo;
+    jpeg_error_t err;
+    unsigned char *volatile rgb = NULL;
+    cinfo.err = jpeg_std_error(&err.base);
+    err.base.error_exit = jpeg_error_exit;
+    if (setjmp(err.jump)) {
+        jpeg_destroy_decompress(&cinfo);
+        free(rgb);
+        return NULL;
+    }
+    jpeg_create_decompress(&cinfo);
+    jpeg_mem_src(&cinfo, data, (unsigned long)len);
+    jpeg_read_header(&cinfo, TRUE);
+    if ((long long)cinfo.image_width * cinfo.image_height > IMAGE_MAX_PIXELS) {
+        jpeg_destroy_decompress(&cinfo);
+        return NULL;
+    }
+    *full_width = (int)cinfo.image_width;
+    *full_height = (int)cinfo.image_height;
+    /* Let the decoder drop DCT detail we would average away anyway. */
+    unsigned int longest = cinfo.image_width > cinfo.image_height ? cinfo.image_width : cinfo.image_height;
+    cinfo.scale_num = 1;
+    cinfo.scale_denom = 1;
+    while (cinfo.scale_denom < 8 && longest / (cinfo.scale_denom * 2) >= THUMBNAIL_MAX_EDGE) cinfo.scale_denom *= 2;
+    cinfo.out_color_space = JCS_RGB;
+    jpeg_start_decompress(&cinfo);
+    size_t stride = (size_t)cinfo.output_width * 3;
+    rgb = (unsigned char *)malloc(stride * cinfo.output_height);
+    if (!rgb) {
+        jpeg_destroy_decompress(&cinfo);
+        return NULL;
+    }
+    while (cinfo.output_scanline < cinfo.output_height) {
+        JSAMPROW row = rgb + (size_t)cinfo.output_scanline * stride;
+        jpeg_read_scanlines(&cinfo, &row, 1);
+    }
+    *width = (int)cinfo.output_width;
+    *height = (int)cinfo.output_height;
+    jpeg_finish_decompress(&cinfo);
+    jpeg_destroy_decompress(&cinfo);
+    return rgb;
+}
+
+static unsigned char *decode_png(const unsigned char *data, size_t len, int *width, int *height) {
+    png_image image;
+    memset(&image, 0, sizeof(image));
+    image.version = PNG_IMAGE_VERSION;
+    if (!png_image_begin_read_from_memory(&image, data, len)) return NULL;
+    if ((long long)image.width * image.height > IMAGE_MAX_PIXELS) {
+        png_image_free(&image);
+        return NULL;
+    }
+    image.format = PNG_FORMAT_RGB;
+    unsigned char *rgb = (unsigned char *)malloc(PNG_IMAGE_SIZE(image));
+    /* Transparent areas are flattened onto white. */
+    png_color background = {255, 255, 255};
+    if (!rgb || !png_image_finish_read(&image, &background, rgb, 0, NULL)) {
+        free(rgb);
+        png_image_free(&image);
+        return NULL;
+    }
+    *width = (int)image.width;
+    *height = (int)image.height;
+    return rgb;
+}
+
+int image_encode_jpeg(const unsigned char *rgb, int width, int height, int quality, unsigned char **out, size_t *out_len) {
+    struct jpeg_compress_struct cinfo;
+    jpeg_error_t err;
+    unsigned char *buffer = NULL;
+    unsigned long buffer_len = 0;
+    cinfo.err = jpeg_std_error(&err.base);
+    err.base.error_exit = jpeg_error_exit;
+    if (setjmp(err.jump)) {
+        jpeg_destroy_compress(&cinfo);
+        free(buffer);
+        return -1;
+    }
+    jpeg_create_compress(&cinfo);
+    jpeg_mem_dest(&cinfo, &buffer, &buffer_len);
+    cinfo.image_width = (JDIMENSION)width;
+    cinfo.image_height = (JDIMENSION)height;
+    cinfo.input_components = 3;
+    cinfo.in_color_space = JCS_RGB;
+    jpeg_set_defaults(&cinfo);
+    jpeg_set_quality(&cinfo, quality, TRUE);
+    jpeg_start_compress(&cinfo, TRUE);
+    while (cinfo.next_scanline < cinfo.image_height) {
+        JSAMPROW row = (JSAMPROW)(rgb + (size_t)cinfo.next_scanline * (size_t)width * 3);
+        jpeg_write_scanlines(&cinfo, &row, 1);
+    }
+    jpeg_finish_compress(&cinfo);
+    jpeg_destroy_compress(&cinfo);
+    *out = buffer;
+    *out_len = (size_t)buffer_len;
+    return 0;
+}
+
+/* Box-filter downscale so the longest edge fits max_edge; never upscales. */
+static unsigned char *downscale_rgb(const unsigned char *rgb, int width, int height, int max_edge, int *out_width, int *out_height) {
+    int longest = width > height ? width : height;
+    int tw = width;
+    int th = height;
+    if (longest > max_edge) {
+        tw = (int)((long long)width * max_edge / longest);
+        th = (int)((long long)height * max_edge / longest);
+        if (tw < 1) tw = 1;
+        if (th < 1) th = 1;
+    }
+    unsigned char *out = (unsigned char *)malloc((size_t)tw * (size_t)th * 3);
+    if (!out) return NULL;
+    for (int y = 0; y < th; y++) {
+        int y0 = (int)((long long)y * height / th);
+        int y1 = (int)((long long)(y + 1) * height / th);
+        if (y1 <= y0) y1 = y0 + 1;
+        for (int x = 0; x < tw; x++) {
+            int x0 = (int)((long long)x * width / tw);
+            int x1 = (int)((long long)(x + 1) * width / tw);
+            if (x1 <= x0) x1 = x0 + 1;
+            unsigned long sum[3] = {0, 0, 0};
+            for (int sy = y0; sy < y1; sy++) {
+                const unsigned char *p = rgb + ((size_t)sy * (size_t)width + (size_t)x0) * 3;
+                for (int sx = x0; sx < x1; sx++, p += 3) {
+                    sum[0] += p[0];
+                    sum[1] += p[1];
+                    sum[2] += p[2];
+                }
+            }
+            unsigned long count = (unsigned long)(x1 - x0) * (unsigned long)(y1 - y0);
+            unsigned char *dst = out + ((size_t)y * (size_t)tw + (size_t)x) * 3;
+            for (int c = 0; c < 3; c++) dst[c] = (unsigned char)(sum[c] / count);
+        }
+    }
+    *out_width = tw;
+    *out_height = th;
+    return out;
+}
+#endif
+
+int attachment_make_thumbnail(
+    const unsigned char *data,
+    size_t len,
+    const char *content_type,
+    unsigned char **out,
+    size_t *out_len,
+    int *width,
+    int *height) {
+    *out = NULL;
+    *out_len = 0;
+    *width = 0;
+    *height = 0;
+#ifdef FRICU_HAVE_IMAGE_CODECS
+    int decoded_w = 0;
+    int decoded_h = 0;
+    unsigned char *rgb = NULL;
+    if (strcmp(content_type, "image/jpeg") == 0) {
+        rgb = decode_jpeg(data, len, &decoded_w, &decoded_h, width, height);
+    } else if (strcmp(content_type, "image/png") == 0) {
+        rgb = decode_png(data, len, &decoded_w, &decoded_h);
+        *width = decoded_w;
+        *height = decoded_h;
+    } else {
+        return 0;
+    }
+    if (!rgb) return -1;
+    int tw = 0;
+    int th = 0;
+    unsigned char *scaled = downscale_rgb(rgb, decoded_w, decoded_h, THUMBNAIL_MAX_EDGE, &tw, &th);
+    free(rgb);
+    if (!scaled) return -1;
+    int rc = image_encode_jpeg(scaled, tw, th, THUMBNAIL_JPEG_QUALITY, out, out_len);
+    free(scaled);
+    return rc == 0 ? 1 : -1;
+#else
+    (void)data;
+    (void)len;
+    (void)content_type;
+    return 0;
+#endif
+}
+
+int attachment_dir(const char *db_path, char *out, size_t out_len) {
+    const char *dir = getenv("FRICU_ATTACHMENT_DIR");
+    int n = dir && dir[0] != '\0' ? snprintf(out, out_len, "%s", dir) : snprintf(out, out_len, "%s-attachments", db_path);
+    return n > 0 && (size_t)n < out_len ? 0 : -1;
+}
+
+/* <dir>/<first two hex digits>/<sha256>; the fan-out keeps directories small. */
+static int blob_path(const char *dir, const char *hash, char *out, size_t out_len) {
+    int n = snprintf(out, out_len, "%s/%.2s/%s", dir, hash, hash);
+    return n > 0 && (size_t)n < out_len ? 0 : -1;
+}
+
+static int ensure_dir(const char *path) {
+    if (mkdir(path, 0700) == 0 || errno == EEXIST) return 0;
+    return -1;
+}
+
+static int fsync_path(const char *path) {
+    int fd = open(path, O_RDONLY);
+    if (fd < 0) return -1;
+    int rc = fsync(fd);
+    close(fd);
+    return rc;
+}
+
+/* Stores data under its hash unless that file already exists; the file appears whole or not at all. */
+int attachment_blob_write(const char *dir, const void *data, size_t len, char *out_hash, size_t out_hash_len) {
+    sha256_hex(data, len, out_hash, out_hash_len);
+    char sub[600] = {0};
+    char path[700] = {0};
+    char tmp[720] = {0};
+    struct stat st;
+    if (snprintf(sub, sizeof(sub), "%s/%.2s", dir, out_hash) >= (int)sizeof(sub) || blob_path(dir, out_hash, path, sizeof(path)) != 0) return -1;
+    if (stat(path, &st) == 0 && (size_t)st.st_size == len) return 0;
+    if (ensure_dir(dir) != 0 || ensure_dir(sub) != 0) return -1;
+    snprintf(tmp, sizeof(tmp), "%s.tmp", path);
+    int fd = open(tmp, O_WRONLY | O_CREAT | O_TRUNC, 0600);
+    if (fd < 0) return -1;
+    const unsigned char *p = (const unsigned char *)data;
+    size_t written = 0;
+    while (written < len) {
+        ssize_t n = write(fd, p + written, len - written);
+        if (n < 0 && errno == EINTR) continue;
+        if (n <= 0) break;
+        written += (size_t)n;
+    }
+    int ok = written == len && fsync(fd) == 0;
+    if (close(fd) != 0) ok = 0;
+    if (!ok || rename(tmp, path) != 0) {
+        unlink(tmp);
+        return -1;
+    }
+    return fsync_path(sub);
+}
+
+static unsigned char *blob_read(const char *dir, const char *hash, size_t *out_len) {
+    char path[700] = {0};
+    if (blob_path(dir, hash, path, sizeof(path)) != 0) return NULL;
+    FILE *f = fopen(path, "rb");
+    if (!f) return NULL;
+    struct stat st;
+    unsigned char *data = NULL;
+    if (fstat(fileno(f), &st) == 0 && (data = (unsigned char *)malloc((size_t)st.st_size + 1)) != NULL) {
+        if (fread(data, 1, (size_t)st.st_size, f) != (size_t)st.st_size) {
+            free(data);
+            data = NULL;
+        }
+        *out_len = (size_t)st.st_size;
+    }
+    fclose(f);
+    return data;
+}
+
+/* Removes a blob once no attachment refers to it any more. Writer thread only. */
+static void blob_release(sqlite3 *db, const char *dir, const char *hash) {
+    if (!hash || hash[0] == '\0') return;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM attachments WHERE blob_sha256 = ?1 OR thumbnail_sha256 = ?1 LIMIT 1", -1, &stmt, NULL) != SQLITE_OK) return;
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    int referenced = sqlite3_step(stmt) != SQLITE_DONE;
+    sqlite3_finalize(stmt);
+    char path[700] = {0};
+    if (referenced || blob_path(dir, hash, path, sizeof(path)) != 0) return;
+    if (unlink(path) != 0 && errno != ENOENT) log_warn("ATTACHMENT blob not removed path=%s errno=%d", path, errno);
+}
+
+/* An upload or delete handed to the writer thread. */
+typedef struct {
+    char dir[512];
+    char id[8 + ATTACHMENT_ID_BYTES * 2];
+    char account_id[128];
+    char owner_type[16];
+    char owner_id[128];
+    char filename[ATTACHMENT_FILENAME_MAX + 1];
+    char content_type[64];
+    char log_id[96];
+    unsigned char *data;
+    size_t len;
+    unsigned char *thumbnail;
+    size_t thumbnail_len;
+    int width;
+    int height;
+    int shared;
+} attachment_write_t;
+
+static void attachment_write_free(void *arg) {
+    attachment_write_t *w = (attachment_write_t *)arg;
+    if (!w) return;
+    free(w->data);
+    free(w->thumbnail);
+    free(w);
+}
+
+static int attachment_store_on_writer(sqlite3 *db, void *arg) {
+    attachment_write_t *w = (attachment_write_t *)arg;
+    char hash[65] = {0};
+    char thumb_hash[65] = {0};
+    if (attachment_blob_write(w->dir, w->data, w->len, hash, sizeof(hash)) != 0 ||
+        (w->thumbnail && attachment_blob_write(w->dir, w->thumbnail, w->thumbnail_len, thumb_hash, sizeof(thumb_hash)) != 0)) {
+        log_error("ATTACHMENT blob write failed dir=%s errno=%d account=%s logid=%s", w->dir, errno, w->account_id, w->log_id);
+        blob_release(db, w->dir, hash);
+        return 500;
+    }
+
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "INSERT INTO attachments (id, account_id, owner_type, owner_id, filename, content_type, size, width, height, blob_sha256, thumbnail_sha256, shared, created_at)"
+        " VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, strftime('%s', 'now'))";
+    int rc = sqlite3_prepare_v2(db, sql, -1, &stmt, NULL);
+    if (rc == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, w->id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, w->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, w->owner_type, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 4, w->owner_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 5, w->filename, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 6, w->content_type, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 7, (sqlite3_int64)w->len);
+        if (w->thumbnail) {
+            sqlite3_bind_int(stmt, 8, w->width);
+            sqlite3_bind_int(stmt, 9, w->height);
+            sqlite3_bind_text(stmt, 11, thumb_hash, -1, SQLITE_TRANSIENT);
+        }
+        sqlite3_bind_text(stmt, 10, hash, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 12, w->shared);
+        rc = sqlite3_step(stmt);
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("ATTACHMENT store failed account=%s err=%s logid=%s", w->account_id, sqlite3_errmsg(db), w->log_id);
+        blob_release(db, w->dir, hash);
+        blob_release(db, w->dir, thumb_hash);
+        return 500;
+    }
+    return 201;
+}
+
+static int attachment_delete_on_writer(sqlite3 *db, void *arg) {
+    attachment_write_t *w = (attachment_write_t *)arg;
+    char hash[65] = {0};
+    char thumb_hash[65] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "DELETE FROM attachments WHERE account_id = ?1 AND id = ?2 RETURNING blob_sha256, thumbnail_sha256", -1, &stmt, NULL) != SQLITE_OK) {
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, w->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, w->id, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    if (rc == SQLITE_ROW) {
+        snprintf(hash, sizeof(hash), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        if (sqlite3_column_type(stmt, 1) != SQLITE_NULL) snprintf(thumb_hash, sizeof(thumb_hash), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        rc = sqlite3_step(stmt);
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("ATTACHMENT delete failed account=%s err=%s logid=%s", w->account_id, sqlite3_errmsg(db), w->log_id);
+        return 500;
+    }
+    if (hash[0] == '\0') return 404;
+    blob_release(db, w->dir, hash);
+    blob_release(db, w->dir, thumb_hash);
+    return 204;
+}
+
+static attachment_write_t *attachment_write_new(const worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    attachment_write_t *w = (attachment_write_t *)calloc(1, sizeof(attachment_write_t));
+    if (!w) return NULL;
+    if (attachment_dir(db->db_path, w->dir, sizeof(w->dir)) != 0) {
+        free(w);
+        return NULL;
+    }
+    snprintf(w->id, sizeof(w->id), "%s", id);
+    snprintf(w->account_id, sizeof(w->account_id), "%s", ctx->account_id);
+    snprintf(w->log_id, sizeof(w->log_id), "%s", ctx->log_id);
+    return w;
+}
+
+/* Hands w to the writer thread; the response is sent only once it ran, or 202 if it is still queued. */
+static int attachment_write_dispatch(int fd, write_call_fn call, attachment_write_t *w, const request_log_context_t *ctx) {
+    char id[sizeof(w->id)];
+    snprintf(id, sizeof(id), "%s", w->id);
+    write_dispatch_result_t result;
+    int rc = write_dispatch_call(call, w, attachment_write_free, ctx->account_id, ctx->log_id, ATTACHMENT_WRITE_WAIT_MS, &result);
+    if (rc == 0) attachment_write_free(w);
+    if (rc < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"write queue unavailable\"}", ctx);
+        return 500;
+    }
+    if (rc > 0) {
+        char body[256] = {0};
+        snprintf(body, sizeof(body), "{\"status\":\"queued\",\"id\":\"%s\",\"logid\":\"%s\"}", id, ctx->log_id);
+        log_warn("ATTACHMENT write queued id=%s reason=writer_backlog account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 202, "Accepted", body, ctx);
+        return 202;
+    }
+    if (result.status_code == 404) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown attachment\"}", ctx);
+    } else if (result.status_code >= 500) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"storage error\"}", ctx);
+    }
+    return result.status_code;
+}
+
+static int owner_exists(sqlite3 *db, const char *account_id, const char *owner_type, const char *owner_id) {
+    const char *key = NULL;
+    if (attachment_owner_key(owner_type, &key) != 0) return 0;
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, key, storage_key, sizeof(storage_key)) != 0) return 0;
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT 1 FROM kv_store k, json_each(k.data_value) e"
+        " WHERE k.data_key = ?1 AND CAST(json_extract(e.value, '$.id') AS TEXT) = ?2 LIMIT 1";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, owner_id, -1, SQLITE_TRANSIENT);
+    int found = sqlite3_step(stmt) == SQLITE_ROW;
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static void sanitize_filename(const char *raw, char *out, size_t out_len) {
+    size_t n = 0;
+    for (const char *p = raw; *p && n + 1 < out_len; p++) {
+        unsigned char ch = (unsigned char)*p;
+        if (ch < 0x20 || ch == '"' || ch == '\\' || ch == '/' || ch == 0x7f) continue;
+        out[n++] = (char)ch;
+    }
+    out[n] = '\0';
+    if (n == 0) snprintf(out, out_len, "attachment");
+}
+
+static void append_attachment_json(strbuf_t *sb, sqlite3_stmt *stmt) {
+    /* Columns: id, owner_type, owner_id, filename, content_type, size, width, height, has_thumbnail, shared, created_at */
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"owner_type\":", 14);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 1));
+    strbuf_append(sb, ",\"owner_id\":", 12);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 2));
+    strbuf_append(sb, ",\"filename\":", 12);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 3));
+    strbuf_append(sb, ",\"content_type\":", 16);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 4));
+    strbuf_appendf(sb, ",\"size\":%lld", sqlite3_column_int64(stmt, 5));
+    if (sqlite3_column_type(stmt, 6) == SQLITE_NULL) {
+        strbuf_append(sb, ",\"width\":null,\"height\":null", 27);
+    } else {
+        strbuf_appendf(sb, ",\"width\":%d,\"height\":%d", sqlite3_column_int(stmt, 6), sqlite3_column_int(stmt, 7));
+    }
+    strbuf_appendf(
+        sb,
+        ",\"has_thumbnail\":%s,\"shared\":%s,\"created_at\":%lld}",
+        sqlite3_column_int(stmt, 8) ? "true" : "false",
+        sqlite3_column_int(stmt, 9) ? "true" : "false",
+        sqlite3_column_int64(stmt, 10));
+}
+
+#define ATTACHMENT_COLUMNS \
+    "id, owner_type, owner_id, filename, content_type, size, width, height, thumbnail_sha256 IS NOT NULL, shared, created_at"
+
+static int send_attachment_metadata(int fd, sqlite3 *db, const char *account_id, const char *id, int code, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT " ATTACHMENT_COLUMNS " FROM attachments WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown attachment\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_attachment_json(&sb, stmt);
+    sqlite3_finalize(stmt);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, code == 201 ? "Created" : "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return code;
+}
+
+static int handle_post_attachment(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char owner_type[16] = {0};
+    char owner_id[128] = {0};
+    char raw_filename[ATTACHMENT_FILENAME_MAX * 3] = {0};
+    char shared_raw[8] = {0};
+    const char *owner_key = NULL;
+    if (!query_param(req->query, "owner_type", owner_type, sizeof(owner_type)) || attachment_owner_key(owner_type, &owner_key) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"owner_type must be activity or event\"}", ctx);
+        return 400;
+    }
+    if (!query_param(req->query, "owner_id", owner_id, sizeof(owner_id)) || owner_id[0] == '\0') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"owner_id is required\"}", ctx);
+        return 400;
+    }
+    query_param(req->query, "filename", raw_filename, sizeof(raw_filename));
+    query_param(req->query, "shared", shared_raw, sizeof(shared_raw));
+    char filename[ATTACHMENT_FILENAME_MAX + 1] = {0};
+    sanitize_filename(raw_filename, filename, sizeof(filename));
+
+    char content_type[64] = {0};
+    http_request_header(req, "Content-Type", content_type, sizeof(content_type));
+    content_type[strcspn(content_type, "; ")] = '\0';
+    const attachment_type_t *type = attachment_type_lookup(content_type);
+    if (!type) {
+        send_response_with_log_context(
+            fd,
+            415,
+            "Unsupported Media Type",
+            "{\"error\":\"unsupported content type\",\"supported\":[\"image/jpeg\",\"image/png\",\"image/webp\",\"image/heic\",\"application/pdf\"]}",
+            ctx);
+        return 415;
+    }
+    const unsigned char *data = (const unsigned char *)req->body;
+    if (req->body_len < type->magic_offset + type->magic_len || memcmp(data + type->magic_offset, type->magic, type->magic_len) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body does not match content type\"}", ctx);
+        return 400;
+    }
+
+    int exists = owner_exists(db->db, ctx->account_id, owner_type, owner_id);
+    if (exists <= 0) {
+        if (exists < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        send_response_with_log_context(fd, 404, "Not Found", strcmp(owner_type, "activity") == 0 ? "{\"error\":\"unknown activity\"}" : "{\"error\":\"unknown event\"}", ctx);
+        return 404;
+    }
+
+    unsigned char *thumbnail = NULL;
+    size_t thumbnail_len = 0;
+    int width = 0;
+    int height = 0;
+    int thumb_rc = attachment_make_thumbnail(data, req->body_len, content_type, &thumbnail, &thumbnail_len, &width, &height);
+    if (thumb_rc < 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"image could not be decoded\"}", ctx);
+        return 400;
+    }
+
+    unsigned char raw_id[ATTACHMENT_ID_BYTES];
+    char id[8 + ATTACHMENT_ID_BYTES * 2] = "att_";
+    if (fill_random_bytes(raw_id, sizeof(raw_id)) != 0) {
+        free(thumbnail);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"id generation failed\"}", ctx);
+        return 500;
+    }
+    for (size_t i = 0; i < sizeof(raw_id); i++) snprintf(id + 4 + i * 2, 3, "%02x", raw_id[i]);
+
+    attachment_write_t *w = attachment_write_new(db, id, ctx);
+    unsigned char *copy = w ? (unsigned char *)malloc(req->body_len + 1) : NULL;
+    if (!copy) {
+        attachment_write_free(w);
+        free(thumbnail);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    memcpy(copy, data, req->body_len);
+    w->data = copy;
+    w->len = req->body_len;
+    if (thumb_rc > 0) {
+        w->thumbnail = thumbnail;
+        w->thumbnail_len = thumbnail_len;
+        w->width = width;
+        w->height = height;
+    } else {
+        free(thumbnail);
+    }
+    snprintf(w->owner_type, sizeof(w->owner_type), "%s", owner_type);
+    snprintf(w->owner_id, sizeof(w->owner_id), "%s", owner_id);
+    snprintf(w->filename, sizeof(w->filename), "%s", filename);
+    snprintf(w->content_type, sizeof(w->content_type), "%s", content_type);
+    w->shared = strcmp(shared_raw, "1") == 0 || strcmp(shared_raw, "true") == 0;
+    int status = attachment_write_dispatch(fd, attachment_store_on_writer, w, ctx);
+    if (status != 201) return status;
+    log_info(
+        "ATTACHMENT stored id=%s owner=%s:%s type=%s bytes=%zu thumbnail=%s account=%s logid=%s",
+        id,
+        owner_type,
+        owner_id,
+        content_type,
+        req->body_len,
+        thumb_rc > 0 ? "yes" : "no",
+        ctx->account_id,
+        ctx->log_id);
+    return send_attachment_metadata(fd, db->db, ctx->account_id, id, 201, ctx);
+}
+
+static int handle_list_attachments(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char owner_type[16] = {0};
+    char owner_id[128] = {0};
+    char shared_raw[8] = {0};
+    const char *owner_key = NULL;
+    int has_type = query_param(req->query, "owner_type", owner_type, sizeof(owner_type));
+    if (has_type && attachment_owner_key(owner_type, &owner_key) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"owner_type must be activity or event\"}", ctx);
+        return 400;
+    }
+    int has_id = query_param(req->query, "owner_id", owner_id, sizeof(owner_id));
+    if (has_id && !has_type) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"owner_id requires owner_type\"}", ctx);
+        return 400;
+    }
+    int shared_only = query_param(req->query, "shared", shared_raw, sizeof(shared_raw)) && (strcmp(shared_raw, "1") == 0 || strcmp(shared_raw, "true") == 0);
+
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT " ATTACHMENT_COLUMNS " FROM attachments WHERE account_id = ?1"
+        " AND (?2 IS NULL OR owner_type = ?2) AND (?3 IS NULL OR owner_id = ?3) AND (?4 = 0 OR shared = 1)"
+        " ORDER BY created_at, id LIMIT ?5";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    if (has_type) sqlite3_bind_text(stmt, 2, owner_type, -1, SQLITE_TRANSIENT);
+    if (has_id) sqlite3_bind_text(stmt, 3, owner_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 4, shared_only);
+    sqlite3_bind_int(stmt, 5, ATTACHMENT_LIST_MAX);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"attachments\":[", 16);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_attachment_json(&sb, stmt);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_get_attachment_content(int fd, worker_db_t *db, const char *id, int thumbnail, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = thumbnail ? "SELECT thumbnail_sha256, 'image/jpeg', filename FROM attachments WHERE account_id = ?1 AND id = ?2"
+                                : "SELECT blob_sha256, content_type, filename FROM attachments WHERE account_id = ?1 AND id = ?2";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW || sqlite3_column_type(stmt, 0) == SQLITE_NULL) {
+        int found = sqlite3_data_count(stmt) > 0;
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", found ? "{\"error\":\"no thumbnail\"}" : "{\"error\":\"unknown attachment\"}", ctx);
+        return 404;
+    }
+    char dir[512] = {0};
+    size_t blob_len = 0;
+    unsigned char *blob = NULL;
+    if (attachment_dir(db->db_path, dir, sizeof(dir)) == 0) blob = blob_read(dir, (const char *)sqlite3_column_text(stmt, 0), &blob_len);
+    if (!blob) {
+        log_error("ATTACHMENT blob unreadable id=%s hash=%s dir=%s account=%s logid=%s", id, (const char *)sqlite3_column_text(stmt, 0), dir, ctx->account_id, ctx->log_id);
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"attachment content unavailable\"}", ctx);
+        return 500;
+    }
+    char content_type[64] = {0};
+    snprintf(content_type, sizeof(content_type), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    char headers[384] = {0};
+    snprintf(
+        headers,
+        sizeof(headers),
+        "Content-Disposition: inline; filename=\"%s\"\r\nCache-Control: private, max-age=31536000, immutable\r\n",
+        (const char *)sqlite3_column_text(stmt, 2));
+    sqlite3_finalize(stmt);
+    send_http_response(fd, 200, "OK", content_type, headers, (const char *)blob, blob_len, ctx);
+    free(blob);
+    return 200;
+}
+
+static int handle_patch_attachment(int fd, worker_db_t *db, const char *id, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_valid(?1) AND json_type(?1) = 'object', json_type(?1, '$.shared'), json_extract(?1, '$.filename')",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    int valid = 0;
+    int shared = -1;
+    char filename[ATTACHMENT_FILENAME_MAX + 1] = {0};
+    int has_filename = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        const char *shared_type = (const char *)sqlite3_column_text(stmt, 1);
+        if (shared_type && strcmp(shared_type, "true") == 0) shared = 1;
+        if (shared_type && strcmp(shared_type, "false") == 0) shared = 0;
+        if (shared_type && shared < 0) valid = 0;
+        if (sqlite3_column_type(stmt, 2) == SQLITE_TEXT) {
+            sanitize_filename((const char *)sqlite3_column_text(stmt, 2), filename, sizeof(filename));
+            has_filename = 1;
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"expected {\\\"shared\\\": bool, \\\"filename\\\": string}\"}", ctx);
+        return 400;
+    }
+
+    if (sqlite3_prepare_v2(
+            db->db,
+            "UPDATE attachments SET shared = CASE WHEN ?3 < 0 THEN shared ELSE ?3 END, filename = COALESCE(?4, filename)"
+            " WHERE account_id = ?1 AND id = ?2",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, shared);
+    if (has_filename) sqlite3_bind_text(stmt, 4, filename, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown attachment\"}", ctx);
+        return 404;
+    }
+    return send_attachment_metadata(fd, db->db, ctx->account_id, id, 200, ctx);
+}
+
+static int handle_delete_attachment(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    attachment_write_t *w = attachment_write_new(db, id, ctx);
+    if (!w) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    int status = attachment_write_dispatch(fd, attachment_delete_on_writer, w, ctx);
+    if (status != 204) return status;
+    log_info("ATTACHMENT deleted id=%s account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_attachments(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *collection = "/v1/attachments";
+    if (strcmp(req->path, collection) == 0) {
+        if (strcmp(method, "POST") == 0) return handle_post_attachment(fd, db, req, ctx);
+        if (strcmp(method, "GET") == 0) return handle_list_attachments(fd, db, req, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+
+    char id[64] = {0};
+    const char *rest = req->path + strlen(collection) + 1;
+    size_t id_len = strcspn(rest, "/");
+    if (id_len == 0 || id_len >= sizeof(id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown attachment\"}", ctx);
+        return 404;
+    }
+    memcpy(id, rest, id_len);
+    const char *suffix = rest + id_len;
+
+    if (strcmp(suffix, "/thumbnail") == 0 && strcmp(method, "GET") == 0) return handle_get_attachment_content(fd, db, id, 1, ctx);
+    if (suffix[0] != '\0') {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(method, "GET") == 0) return handle_get_attachment_content(fd, db, id, 0, ctx);
+    if (strcmp(method, "PATCH") == 0) return handle_patch_attachment(fd, db, id, req, ctx);
+    if (strcmp(method, "DELETE") == 0) return handle_delete_attachment(fd, db, id, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -0,0 +1,376 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+/*
+ * Bearer-token authentication for the /v1 and /v2 APIs. Tokens are issued per account through the
+ * admin API (POST /v1/admin/tokens with X-Admin-Token) and only their SHA-256 is stored, so the
+ * plain value is shown once. Once any token exists, or FRICU_API_AUTH=required is set, every /v1 and
+ * /v2 request must carry `Authorization: Bearer <token>` and runs as the token's account; requests
+ * naming another account in X-Account-Id are refused. With OIDC configured (oidc.c) JWT bearer
+ * tokens from the identity provider are accepted as well and authentication is always required.
+ * /health stays open, the admin routes and /v1/today/workout keep their own X-Admin-Token /
+ * X-Device-Token checks, and POST /v1/pair is authenticated by its pairing code (pairing.c).
+ */
+
+#define API_TOKEN_BYTES 32
+#define API_TOKEN_NAME_MAX 64
+
+/* The credential that authenticated the current request: a token id, "oidc:<account>", or empty. */
+static __thread char g_client_id[160];
+
+static int api_token_generate(char *out, size_t out_len) {
+    unsigned char raw[API_TOKEN_BYTES];
+    if (out_len < 4 + API_TOKEN_BYTES * 2 + 1) return -1;
+    if (fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    memcpy(out, "fat_", 4);
+    for (size_t i = 0; i < sizeof(raw); i++) {
+        snprintf(out + 4 + i * 2, 3, "%02x", raw[i]);
+    }
+    return 0;
+}
+
+int api_account_id_valid(const char *id) {
+    size_t len = strlen(id);
+    if (len == 0 || len >= 128) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)id[i];
+        if (!isalnum(ch) && ch != '-' && ch != '_' && ch != '.') return 0;
+    }
+    return 1;
+}
+
+/* 1 when requests must authenticate, 0 when the API is still open, -1 on database errors. */
+static int api_auth_required(sqlite3 *db) {
+    const char *mode = getenv("FRICU_API_AUTH");
+    if (mode && strcmp(mode, "required") == 0) return 1;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT EXISTS (SELECT 1 FROM api_tokens)", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    int required = sqlite3_step(stmt) == SQLITE_ROW ? sqlite3_column_int(stmt, 0) : -1;
+    sqlite3_finalize(stmt);
+    return required;
+}
+
+typedef struct {
+    char token_hash[65];
+} api_token_touch_t;
+
+static int api_token_touch(sqlite3 *db, void *arg) {
+    const api_token_touch_t *touch = (const api_token_touch_t *)arg;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "UPDATE api_tokens SET last_used_at = strftime('%s', 'now')"
+            " WHERE token_hash = ?1 AND (last_used_at IS NULL OR last_used_at < strftime('%s', 'now') - 60)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, touch->token_hash, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 204 : 500;
+}
+
+/* Looks up the account for a presented token; 1 found, 0 unknown, -1 on database errors. */
+static int api_token_account(sqlite3 *db, const char *token, const char *log_id, char *out_account_id, size_t out_len) {
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT account_id, token_id, last_used_at IS NULL OR last_used_at < strftime('%s', 'now') - 60"
+        " FROM api_tokens WHERE token_hash = ?1";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    int stale = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(out_account_id, out_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(g_client_id, sizeof(g_client_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        stale = sqlite3_column_int(stmt, 2);
+        found = 1;
+    }
+    sqlite3_finalize(stmt);
+    /* Touched at most once a minute, and by the writer thread, so authenticated reads stay reads here. */
+    api_token_touch_t *touch = stale ? (api_token_touch_t *)calloc(1, sizeof(api_token_touch_t)) : NULL;
+    if (touch) {
+        snprintf(touch->token_hash, sizeof(touch->token_hash), "%s", hash);
+        write_dispatch_result_t result;
+        if (write_dispatch_call(api_token_touch, touch, free, out_account_id, log_id, 0, &result) == 0) free(touch);
+    }
+    return found;
+}
+
+static int admin_token_matches(const http_request_t *req) {
+    const char *expected = config_secret("FRICU_ADMIN_TOKEN");
+    if (!expected || expected[0] == '\0') return 0;
+    char provided[256] = {0};
+    if (!http_request_header(req, "X-Admin-Token", provided, sizeof(provided))) return 0;
+    size_t expected_len = strlen(expected);
+    size_t provided_len = strlen(provided);
+    unsigned char diff = (unsigned char)(expected_len != provided_len);
+    for (size_t i = 0; i < provided_len; i++) {
+        diff |= (unsigned char)(provided[i] ^ expected[i % expected_len]);
+    }
+    return diff == 0;
+}
+
+int admin_authorize(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *expected = config_secret("FRICU_ADMIN_TOKEN");
+    if (!expected || expected[0] == '\0') {
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"admin API disabled; set FRICU_ADMIN_TOKEN\"}", ctx);
+        return 403;
+    }
+    if (!admin_token_matches(req)) {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid X-Admin-Token\"}", ctx);
+        return 401;
+    }
+    return 0;
+}
+
+const char *api_auth_client_id(void) {
+    return g_client_id;
+}
+
+int api_auth_enforce(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx) {
+    const char *path = req->path;
+    g_client_id[0] = '\0';
+    if (strncmp(path, "/v1/", 4) != 0 && strncmp(path, "/v2/", 4) != 0) return 0;
+    if (strncmp(path, "/v1/admin/", 10) == 0 || strcmp(path, "/v1/today/workout") == 0 || strcmp(path, "/v1/pair") == 0) return 0;
+    int required = oidc_configured() ? 1 : api_auth_required(db->db);
+    if (required == 0) return 0;
+    if (required < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+
+    static const char CHALLENGE[] = "WWW-Authenticate: Bearer realm=\"fricu\"\r\n";
+    char header[8192] = {0};
+    if (!http_request_header(req, "Authorization", header, sizeof(header)) || strncasecmp(header, "Bearer ", 7) != 0 || header[7] == '\0') {
+        static const char body[] = "{\"error\":\"missing bearer token\"}";
+        send_http_response(fd, 401, "Unauthorized", "application/json", CHALLENGE, body, sizeof(body) - 1, ctx);
+        return 401;
+    }
+    char account_id[128] = {0};
+    const char *token = header + 7;
+    if (oidc_configured() && oidc_token_shaped(token)) {
+        char reason[160] = {0};
+        int valid = oidc_verify_token(db->db, token, account_id, sizeof(account_id), reason, sizeof(reason));
+        if (valid <= 0) {
+            strbuf_t sb;
+            strbuf_init(&sb);
+            if (valid < 0) {
+                strbuf_append(&sb, "{\"error\":\"identity provider unavailable\",\"reason\":", 50);
+            } else {
+                strbuf_append(&sb, "{\"error\":\"invalid bearer token\",\"reason\":", 41);
+            }
+            strbuf_append_json_string(&sb, reason);
+            strbuf_append(&sb, "}", 1);
+            const char *body = sb.failed ? "{\"error\":\"invalid bearer token\"}" : strbuf_cstr(&sb);
+            if (valid < 0) {
+                send_response_with_log_context(fd, 503, "Service Unavailable", body, ctx);
+            } else {
+                send_http_response(fd, 401, "Unauthorized", "application/json", CHALLENGE, body, strlen(body), ctx);
+            }
+            strbuf_free(&sb);
+            log_warn("AUTH rejected reason=oidc detail=%s path=%s logid=%s", reason, path, ctx->log_id);
+            return valid < 0 ? 503 : 401;
+        }
+        snprintf(g_client_id, sizeof(g_client_id), "oidc:%s", account_id);
+    } else {
+        int found = api_token_account(db->db, token, ctx->log_id, account_id, sizeof(account_id));
+        if (found < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        if (found == 0) {
+            static const char body[] = "{\"error\":\"invalid bearer token\"}";
+            send_http_response(fd, 401, "Unauthorized", "application/json", CHALLENGE, body, sizeof(body) - 1, ctx);
+            log_warn("AUTH rejected reason=invalid_token path=%s logid=%s", path, ctx->log_id);
+            return 401;
+        }
+    }
+    if (ctx->account_id[0] != '\0' && strcmp(ctx->account_id, account_id) != 0) {
+        g_client_id[0] = '\0';
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"token does not belong to X-Account-Id\"}", ctx);
+        log_warn("AUTH rejected reason=account_mismatch account=%s logid=%s", ctx->account_id, ctx->log_id);
+        return 403;
+    }
+    snprintf(ctx->account_id, sizeof(ctx->account_id), "%s", account_id);
+    return 0;
+}
+
+/* Creates a token for account_id; 0 on success, -2 when no random bytes, -1 on database errors. */
+int api_token_issue(sqlite3 *db, const char *account_id, const char *name, char *token, size_t token_len, char *token_id, size_t token_id_len) {
+    char hash[65] = {0};
+    if (api_token_generate(token, token_len) != 0) return -2;
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    snprintf(token_id, token_id_len, "tok_%.12s", hash);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "INSERT INTO api_tokens (token_hash, token_id, account_id, name, created_at) VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, token_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, name, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+static int handle_post_token(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1), json_extract(?1, '$.account'), json_extract(?1, '$.name')", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char account_id[128] = {0};
+    char name[API_TOKEN_NAME_MAX + 1] = "api";
+    int valid = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        const char *raw_account = (const char *)sqlite3_column_text(stmt, 1);
+        const char *raw_name = (const char *)sqlite3_column_text(stmt, 2);
+        if (raw_account) snprintf(account_id, sizeof(account_id), "%s", raw_account);
+        if (raw_name && raw_name[0] != '\0') snprintf(name, sizeof(name), "%s", raw_name);
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+        return 400;
+    }
+    if (!api_account_id_valid(account_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"account is required\"}", ctx);
+        return 400;
+    }
+
+    char token[80] = {0};
+    char token_id[20] = {0};
+    int issued = api_token_issue(db->db, account_id, name, token, sizeof(token), token_id, sizeof(token_id));
+    if (issued != 0) {
+        if (issued == -2) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        } else {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        }
+        return 500;
+    }
+    log_info("AUTH token issued id=%s account=%s name=%s", token_id, account_id, name);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"token\":\"%s\",\"id\":\"%s\",\"account\":", token, token_id);
+    strbuf_append_json_string(&sb, account_id);
+    strbuf_append(&sb, ",\"name\":", 8);
+    strbuf_append_json_string(&sb, name);
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 201;
+}
+
+static int handle_list_tokens(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char account[128] = {0};
+    query_param(req->query, "account", account, sizeof(account));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT token_id, account_id, name, created_at, last_used_at FROM api_tokens"
+            " WHERE ?1 = '' OR account_id = ?1 ORDER BY created_at, token_id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, account, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"required\":%s,\"tokens\":[", api_auth_required(db->db) > 0 ? "true" : "false");
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_appendf(&sb, "{\"id\":\"%s\",\"account\":", (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(&sb, ",\"name\":", 8);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 2));
+        strbuf_appendf(&sb, ",\"created_at\":%lld,\"last_used_at\":", sqlite3_column_int64(stmt, 3));
+        if (sqlite3_column_type(stmt, 4) == SQLITE_NULL) {
+            strbuf_append(&sb, "null}", 5);
+        } else {
+            strbuf_appendf(&sb, "%lld}", sqlite3_column_int64(stmt, 4));
+        }
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_delete_token(int fd, worker_db_t *db, const char *token_id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM api_tokens WHERE token_id = ?1", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, token_id, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown token\"}", ctx);
+        return 404;
+    }
+    log_info("AUTH token revoked id=%s", token_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_admin_tokens(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    const char *method = req->method;
+    if (strcmp(req->path, "/v1/admin/tokens") == 0) {
+        if (strcmp(method, "GET") == 0) return handle_list_tokens(fd, db, req, ctx);
+        if (strcmp(method, "POST") == 0) {
+            if (!cluster_is_writer()) return cluster_reject_write(fd, ctx);
+            return handle_post_token(fd, db, req, ctx);
+        }
+    } else if (strcmp(method, "DELETE") == 0) {
+        if (!cluster_is_writer()) return cluster_reject_write(fd, ctx);
+        return handle_delete_token(fd, db, req->path + strlen("/v1/admin/tokens/"), ctx);
+    }
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -0,0 +1,1120 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <dirent.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Page-level backups. POST /v1/admin/backups?kind=full|incremental|differential copies the database
+ * through the SQLite backup API (one read transaction, writers keep going) into FRICU_BACKUP_DIR,
+ * default <db>-backups. A full backup keeps the whole image as <id>.db; an incremental one keeps
+ * only the pages that differ from the previous backup and a differential one those that differ from
+ * the last full backup, as <id>.diff records of a 4-byte big-endian page number and the page. Every
+ * backup also has <id>.pages, the SHA-256 of each page, which the next backup diffs against, and
+ * <id>.json, written last, with its parent and three checksums: of the payload file, of the image
+ * (SHA-256 of <id>.pages) and of the parent's image. Without ?kind= an incremental backup is taken
+ * once a full one exists. `fricu-server --restore <id> <file>` copies the full backup and applies
+ * each increment on the way to <id>, checking every payload before and the image after each step,
+ * then runs integrity_check before the file appears under its name.
+ */
+
+#define BACKUP_ID_LEN 24
+#define BACKUP_HASH_LEN 64
+#define BACKUP_MAX_CHAIN 4096
+#define BACKUP_IO_CHUNK (1024 * 1024)
+
+typedef struct {
+    char id[BACKUP_ID_LEN];
+    char kind[16];
+    char parent[BACKUP_ID_LEN];
+    long long created_at;
+    long long page_size;
+    long long page_count;
+    long long pages_written;
+    long long bytes;
+    char checksum[BACKUP_HASH_LEN + 1];
+    char payload_checksum[BACKUP_HASH_LEN + 1];
+    char parent_checksum[BACKUP_HASH_LEN + 1];
+} backup_manifest_t;
+
+static pthread_mutex_t g_backup_mutex = PTHREAD_MUTEX_INITIALIZER;
+
+int backup_dir(const char *db_path, char *out, size_t out_len) {
+    const char *dir = getenv("FRICU_BACKUP_DIR");
+    int n = dir && dir[0] != '\0' ? snprintf(out, out_len, "%s", dir) : snprintf(out, out_len, "%s-backups", db_path);
+    return n > 0 && (size_t)n < out_len ? 0 : -1;
+}
+
+static int backup_path(const char *dir, const char *id, const char *suffix, char *out, size_t out_len) {
+    int n = snprintf(out, out_len, "%s/%s%s", dir, id, suffix);
+    return n > 0 && (size_t)n < out_len ? 0 : -1;
+}
+
+static int is_backup_id(const char *id) {
+    size_t len = strlen(id);
+    if (len == 0 || len >= BACKUP_ID_LEN) return 0;
+    for (size_t i = 0; i < len; i++) {
+        if (id[i] < '0' || id[i] > '9') return 0;
+    }
+    return 1;
+}
+
+static char *read_file(const char *path, size_t *out_len) {
+    FILE *f = fopen(path, "rb");
+    if (!f) return NULL;
+    strbuf_t sb;
+    strbuf_init(&sb);
+    char chunk[8192];
+    size_t n = 0;
+    while ((n = fread(chunk, 1, sizeof(chunk), f)) > 0) strbuf_append(&sb, chunk, n);
+    int failed = ferror(f) || sb.failed;
+    fclose(f);
+    if (failed) {
+        strbuf_free(&sb);
+        return NULL;
+    }
+    *out_len = sb.len;
+    return sb.data ? sb.data : strdup("");
+}
+
+/* Writes data to path via a temporary file, so a reader sees either nothing or all of it. */
+static int write_file_atomic(const char *path, const void *data, size_t len) {
+    char tmp[1024] = {0};
+    if (snprintf(tmp, sizeof(tmp), "%s.tmp", path) >= (int)sizeof(tmp)) return -1;
+    FILE *f = fopen(tmp, "wb");
+    if (!f) return -1;
+    int ok = fwrite(data, 1, len, f) == len && fflush(f) == 0 && fsync(fileno(f)) == 0;
+    ok = fclose(f) == 0 && ok;
+    if (!ok || rename(tmp, path) != 0) {
+        unlink(tmp);
+        return -1;
+    }
+    return 0;
+}
+
+static int file_checksum(const char *path, char *out, size_t out_len, long long *out_bytes) {
+    FILE *f = fopen(path, "rb");
+    if (!f) return -1;
+    unsigned char *chunk = (unsigned char *)malloc(BACKUP_IO_CHUNK);
+    if (!chunk) {
+        fclose(f);
+        return -1;
+    }
+    sha256_ctx_t sha;
+    sha256_init(&sha);
+    size_t n = 0;
+    long long bytes = 0;
+    while ((n = fread(chunk, 1, BACKUP_IO_CHUNK, f)) > 0) {
+        sha256_update(&sha, chunk, n);
+        bytes += (long long)n;
+    }
+    int failed = ferror(f);
+    fclose(f);
+    free(chunk);
+    if (failed) return -1;
+    sha256_final_hex(&sha, out, out_len);
+    if (out_bytes) *out_bytes = bytes;
+    return 0;
+}
+
+static void manifest_json(const backup_manifest_t *m, strbuf_t *sb) {
+    strbuf_appendf(sb, "{\"id\":\"%s\",\"kind\":\"%s\",\"parent\":", m->id, m->kind);
+    if (m->parent[0] != '\0') {
+        strbuf_appendf(sb, "\"%s\"", m->parent);
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+    strbuf_appendf(
+        sb,
+        ",\"created_at\":%lld,\"page_size\":%lld,\"page_count\":%lld,\"pages_written\":%lld,\"bytes\":%lld,"
+        "\"checksum\":\"%s\",\"payload_checksum\":\"%s\",\"parent_checksum\":",
+        m->created_at,
+        m->page_size,
+        m->page_count,
+        m->pages_written,
+        m->bytes,
+        m->checksum,
+        m->payload_checksum);
+    if (m->parent_checksum[0] != '\0') {
+        strbuf_appendf(sb, "\"%s\"}", m->parent_checksum);
+    } else {
+        strbuf_append(sb, "null}", 5);
+    }
+}
+
+static void copy_column(sqlite3_stmt *stmt, int col, char *out, size_t out_len) {
+    const unsigned char *text = sqlite3_column_text(stmt, col);
+    snprintf(out, out_len, "%s", text ? (const char *)text : "");
+}
+
+/* parser is any connection; only its JSON functions are used. */
+static int manifest_read(sqlite3 *parser, const char *dir, const char *id, backup_manifest_t *out) {
+    memset(out, 0, sizeof(*out));
+    char path[1024] = {0};
+    size_t len = 0;
+    char *json = NULL;
+    if (!is_backup_id(id) || backup_path(dir, id, ".json", path, sizeof(path)) != 0 || !(json = read_file(path, &len))) return -1;
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT json_extract(?1, '$.id'), json_extract(?1, '$.kind'), json_extract(?1, '$.parent'),"
+        " json_extract(?1, '$.created_at'), json_extract(?1, '$.page_size'), json_extract(?1, '$.page_count'),"
+        " json_extract(?1, '$.pages_written'), json_extract(?1, '$.bytes'), json_extract(?1, '$.checksum'),"
+        " json_extract(?1, '$.payload_checksum'), json_extract(?1, '$.parent_checksum') WHERE json_valid(?1)";
+    int rc = sqlite3_prepare_v2(parser, sql, -1, &stmt, NULL);
+    if (rc == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, json, (int)len, SQLITE_TRANSIENT);
+        rc = sqlite3_step(stmt);
+    }
+    free(json);
+    if (rc != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    copy_column(stmt, 0, out->id, sizeof(out->id));
+    copy_column(stmt, 1, out->kind, sizeof(out->kind));
+    copy_column(stmt, 2, out->parent, sizeof(out->parent));
+    out->created_at = sqlite3_column_int64(stmt, 3);
+    out->page_size = sqlite3_column_int64(stmt, 4);
+    out->page_count = sqlite3_column_int64(stmt, 5);
+    out->pages_written = sqlite3_column_int64(stmt, 6);
+    out->bytes = sqlite3_column_int64(stmt, 7);
+    copy_column(stmt, 8, out->checksum, sizeof(out->checksum));
+    copy_column(stmt, 9, out->payload_checksum, sizeof(out->payload_checksum));
+    copy_column(stmt, 10, out->parent_checksum, sizeof(out->parent_checksum));
+    sqlite3_finalize(stmt);
+    int full = strcmp(out->kind, "full") == 0;
+    if (strcmp(out->id, id) != 0 || (!full && strcmp(out->kind, "incremental") != 0 && strcmp(out->kind, "differential") != 0) ||
+        full != (out->parent[0] == '\0') || out->page_size < 512 || out->page_size > 65536 || out->page_count < 1) {
+        return -1;
+    }
+    return 0;
+}
+
+static int compare_ids(const void *a, const void *b) {
+    return strcmp(((const backup_manifest_t *)a)->id, ((const backup_manifest_t *)b)->id);
+}
+
+/* Every backup whose manifest parses, oldest first; ids are zero-padded so they sort by age. */
+static backup_manifest_t *manifest_list(sqlite3 *parser, const char *dir, size_t *out_count) {
+    *out_count = 0;
+    size_t cap = 16;
+    backup_manifest_t *list = (backup_manifest_t *)malloc(cap * sizeof(*list));
+    DIR *d = opendir(dir);
+    if (!list || !d) {
+        if (d) closedir(d);
+        return list;
+    }
+    struct dirent *entry = NULL;
+    while ((entry = readdir(d)) != NULL) {
+        char id[BACKUP_ID_LEN] = {0};
+        size_t len = strlen(entry->d_name);
+        if (len <= 5 || len - 5 >= sizeof(id) || strcmp(entry->d_name + len - 5, ".json") != 0) continue;
+        memcpy(id, entry->d_name, len - 5);
+        if (*out_count == cap) {
+            backup_manifest_t *grown = (backup_manifest_t *)realloc(list, cap * 2 * sizeof(*list));
+            if (!grown) break;
+            list = grown;
+            cap *= 2;
+        }
+        if (manifest_read(parser, dir, id, &list[*out_count]) == 0) (*out_count)++;
+    }
+    closedir(d);
+    qsort(list, *out_count, sizeof(*list), compare_ids);
+    return list;
+}
+
+static long long header_page_size(const unsigned char *header) {
+    long long size = (long long)header[16] << 8 | header[17];
+    return size == 1 ? 65536 : size;
+}
+
+/* Hashes every page of an image file; hashes is page_count * BACKUP_HASH_LEN hex characters. */
+static char *hash_pages(FILE *f, long long page_size, long long page_count) {
+    char *hashes = (char *)malloc((size_t)page_count * BACKUP_HASH_LEN + 1);
+    unsigned char *page = (unsigned char *)malloc((size_t)page_size);
+    if (!hashes || !page || fseeko(f, 0, SEEK_SET) != 0) {
+        free(hashes);
+        free(page);
+        return NULL;
+    }
+    for (long long i = 0; i < page_count; i++) {
+        char hex[BACKUP_HASH_LEN + 1];
+        if (fread(page, 1, (size_t)page_size, f) != (size_t)page_size) {
+            free(hashes);
+            free(page);
+            return NULL;
+        }
+        sha256_hex(page, (size_t)page_size, hex, sizeof(hex));
+        memcpy(hashes + i * BACKUP_HASH_LEN, hex, BACKUP_HASH_LEN);
+    }
+    hashes[page_count * BACKUP_HASH_LEN] = '\0';
+    free(page);
+    return hashes;
+}
+
+static char *read_page_hashes(const char *dir, const backup_manifest_t *m) {
+    char path[1024] = {0};
+    size_t len = 0;
+    char *hashes = backup_path(dir, m->id, ".pages", path, sizeof(path)) == 0 ? read_file(path, &len) : NULL;
+    char checksum[BACKUP_HASH_LEN + 1] = {0};
+    if (hashes) sha256_hex(hashes, len, checksum, sizeof(checksum));
+    if (hashes && (len != (size_t)m->page_count * BACKUP_HASH_LEN || strcmp(checksum, m->checksum) != 0)) {
+        free(hashes);
+        return NULL;
+    }
+    return hashes;
+}
+
+/* Copies the live database into path as one consistent image. */
+static int snapshot_database(sqlite3 *src, const char *path) {
+    sqlite3 *dst = NULL;
+    unlink(path);
+    if (sqlite3_open_v2(path, &dst, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, NULL) != SQLITE_OK) {
+        sqlite3_close(dst);
+        return -1;
+    }
+    sqlite3_backup *backup = sqlite3_backup_init(dst, "main", src, "main");
+    int rc = backup ? sqlite3_backup_step(backup, -1) : SQLITE_ERROR;
+    if (backup) sqlite3_backup_finish(backup);
+    if (rc != SQLITE_DONE) log_error("backup snapshot failed: %s", sqlite3_errmsg(dst));
+    sqlite3_close(dst);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+/* Writes the pages of the snapshot whose hashes differ from the parent's, or lie past its end. */
+static int write_diff(FILE *image, const char *path, const char *hashes, const char *parent_hashes, const backup_manifest_t *parent, backup_manifest_t *m) {
+    FILE *out = fopen(path, "wb");
+    unsigned char *page = (unsigned char *)malloc((size_t)m->page_size);
+    if (!out || !page) {
+        if (out) fclose(out);
+        free(page);
+        return -1;
+    }
+    int ok = fseeko(image, 0, SEEK_SET) == 0;
+    for (long long i = 0; ok && i < m->page_count; i++) {
+        ok = fread(page, 1, (size_t)m->page_size, image) == (size_t)m->page_size;
+        if (!ok || (i < parent->page_count && memcmp(hashes + i * BACKUP_HASH_LEN, parent_hashes + i * BACKUP_HASH_LEN, BACKUP_HASH_LEN) == 0)) {
+            continue;
+        }
+        unsigned long long number = (unsigned long long)i + 1;
+        unsigned char prefix[4] = {(unsigned char)(number >> 24), (unsigned char)(number >> 16), (unsigned char)(number >> 8), (unsigned char)number};
+        ok = fwrite(prefix, 1, 4, out) == 4 && fwrite(page, 1, (size_t)m->page_size, out) == (size_t)m->page_size;
+        m->pages_written++;
+    }
+    free(page);
+    ok = ok && fflush(out) == 0 && fsync(fileno(out)) == 0;
+    ok = fclose(out) == 0 && ok;
+    return ok ? 0 : -1;
+}
+
+static int create_backup(sqlite3 *db, const char *dir, const char *kind, backup_manifest_t *m, char *err, size_t err_len) {
+    memset(m, 0, sizeof(*m));
+    sqlite3 *parser = NULL;
+    if (mkdir(dir, 0700) != 0 && errno != EEXIST) {
+        snprintf(err, err_len, "cannot create backup directory");
+        return 500;
+    }
+    if (sqlite3_open(":memory:", &parser) != SQLITE_OK) {
+        sqlite3_close(parser);
+        snprintf(err, err_len, "database error");
+        return 500;
+    }
+    size_t count = 0;
+    backup_manifest_t *list = manifest_list(parser, dir, &count);
+    sqlite3_close(parser);
+    if (!list) {
+        snprintf(err, err_len, "oom");
+        return 500;
+    }
+    const backup_manifest_t *last_full = NULL;
+    for (size_t i = 0; i < count; i++) {
+        if (strcmp(list[i].kind, "full") == 0) last_full = &list[i];
+    }
+    if (kind[0] == '\0') kind = last_full ? "incremental" : "full";
+    backup_manifest_t parent = {0};
+    if (strcmp(kind, "incremental") == 0 && last_full) parent = list[count - 1];
+    if (strcmp(kind, "differential") == 0 && last_full) parent = *last_full;
+    snprintf(m->id, sizeof(m->id), "%06lld", count > 0 ? atoll(list[count - 1].id) + 1 : 1);
+    free(list);
+    if (strcmp(kind, "full") != 0 && parent.id[0] == '\0') {
+        snprintf(err, err_len, "no full backup to build on; take a full backup first");
+        return 409;
+    }
+    snprintf(m->kind, sizeof(m->kind), "%s", kind);
+    snprintf(m->parent, sizeof(m->parent), "%s", parent.id);
+    snprintf(m->parent_checksum, sizeof(m->parent_checksum), "%s", parent.checksum);
+    m->created_at = (long long)time(NULL);
+
+    char snapshot[1024] = {0};
+    char payload[1024] = {0};
+    char pages[1024] = {0};
+    char manifest[1024] = {0};
+    int full = parent.id[0] == '\0';
+    if (backup_path(dir, m->id, ".snapshot.tmp", snapshot, sizeof(snapshot)) != 0 || backup_path(dir, m->id, full ? ".db" : ".diff", payload, sizeof(payload)) != 0 ||
+        backup_path(dir, m->id, ".pages", pages, sizeof(pages)) != 0 || backup_path(dir, m->id, ".json", manifest, sizeof(manifest)) != 0) {
+        snprintf(err, err_len, "backup path too long");
+        return 500;
+    }
+    if (snapshot_database(db, snapshot) != 0) {
+        unlink(snapshot);
+        snprintf(err, err_len, "could not snapshot the database");
+        return 500;
+    }
+
+    int status = 500;
+    char *hashes = NULL;
+    char *parent_hashes = NULL;
+    unsigned char header[100] = {0};
+    struct stat st;
+    FILE *image = fopen(snapshot, "rb");
+    if (!image || fread(header, 1, sizeof(header), image) != sizeof(header) || fstat(fileno(image), &st) != 0) {
+        snprintf(err, err_len, "could not read the snapshot");
+        goto done;
+    }
+    m->page_size = header_page_size(header);
+    m->page_count = (long long)st.st_size / m->page_size;
+    if (!(hashes = hash_pages(image, m->page_size, m->page_count))) {
+        snprintf(err, err_len, "could not read the snapshot");
+        goto done;
+    }
+    sha256_hex(hashes, (size_t)m->page_count * BACKUP_HASH_LEN, m->checksum, sizeof(m->checksum));
+    if (full) {
+        m->pages_written = m->page_count;
+        fclose(image);
+        image = NULL;
+        if (rename(snapshot, payload) != 0) {
+            snprintf(err, err_len, "could not store the backup");
+            goto done;
+        }
+    } else {
+        if (parent.page_size != m->page_size) {
+            snprintf(err, err_len, "page size changed since backup %s; take a full backup", parent.id);
+            status = 409;
+            goto done;
+        }
+        if (!(parent_hashes = read_page_hashes(dir, &parent))) {
+            snprintf(err, err_len, "page index of backup %s is missing or corrupt", parent.id);
+            status = 409;
+            goto done;
+        }
+        if (write_diff(image, payload, hashes, parent_hashes, &parent, m) != 0) {
+            unlink(payload);
+            snprintf(err, err_len, "could not store the backup");
+            goto done;
+        }
+    }
+    if (file_checksum(payload, m->payload_checksum, sizeof(m->payload_checksum), &m->bytes) != 0 ||
+        write_file_atomic(pages, hashes, (size_t)m->page_count * BACKUP_HASH_LEN) != 0) {
+        unlink(payload);
+        snprintf(err, err_len, "could not store the backup");
+        goto done;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    manifest_json(m, &sb);
+    if (sb.failed || write_file_atomic(manifest, sb.data, sb.len) != 0) {
+        unlink(payload);
+        unlink(pages);
+        snprintf(err, err_len, "could not store the backup");
+    } else {
+        status = 201;
+    }
+    strbuf_free(&sb);
+
+done:
+    if (image) fclose(image);
+    unlink(snapshot);
+    free(hashes);
+    free(parent_hashes);
+    return status;
+}
+
+static int handle_list_backups(int fd, const char *dir, const request_log_context_t *ctx) {
+    sqlite3 *parser = NULL;
+    if (sqlite3_open(":memory:", &parser) != SQLITE_OK) {
+        sqlite3_close(parser);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    size_t count = 0;
+    backup_manifest_t *list = manifest_list(parser, dir, &count);
+    sqlite3_close(parser);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"backups\":[", 12);
+    for (size_t i = 0; list && i < count; i++) {
+        if (i > 0) strbuf_append(&sb, ",", 1);
+        manifest_json(&list[i], &sb);
+    }
+    strbuf_append(&sb, "]}", 2);
+    free(list);
+    if (!list || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* GET lists the backups; POST takes one. */
+int route_admin_backups(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    char dir[512] = {0};
+    if (backup_dir(db->db_path, dir, sizeof(dir)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"backup path too long\"}", ctx);
+        return 500;
+    }
+    if (strcmp(req->method, "GET") == 0) return handle_list_backups(fd, dir, ctx);
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char kind[16] = {0};
+    if (query_param(req->query, "kind", kind, sizeof(kind)) && strcmp(kind, "full") != 0 && strcmp(kind, "incremental") != 0 &&
+        strcmp(kind, "differential") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"kind must be full, incremental or differential\"}", ctx);
+        return 400;
+    }
+
+    backup_manifest_t m;
+    char err[160] = {0};
+    double started_ms = slowlog_now_ms();
+    pthread_mutex_lock(&g_backup_mutex);
+    int status = create_backup(db->db, dir, kind, &m, err, sizeof(err));
+    pthread_mutex_unlock(&g_backup_mutex);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (status == 201) {
+        manifest_json(&m, &sb);
+    } else {
+        strbuf_append(&sb, "{\"error\":", 9);
+        strbuf_append_json_string(&sb, err);
+        strbuf_append(&sb, "}", 1);
+    }
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (status == 201) {
+        send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+        log_info(
+            "BACKUP id=%s kind=%s parent=%s pages=%lld/%lld bytes=%lld ms=%.0f logid=%s",
+            m.id,
+            m.kind,
+            m.parent[0] ? m.parent : "-",
+            m.pages_written,
+            m.page_count,
+            m.bytes,
+            slowlog_now_ms() - started_ms,
+            ctx->log_id);
+    } else if (status == 409) {
+        send_response_with_log_context(fd, 409, "Conflict", strbuf_cstr(&sb), ctx);
+    } else {
+        send_response_with_log_context(fd, 500, "Internal Server Error", strbuf_cstr(&sb), ctx);
+        log_error("BACKUP failed kind=%s error=%s logid=%s", kind[0] ? kind : "auto", err, ctx->log_id);
+    }
+    strbuf_free(&sb);
+    return status;
+}
+
+/*
+ * Snapshot backups. POST /v1/admin/backup runs VACUUM INTO, which reads the database in one
+ * transaction and writes a compacted, self-contained copy that opens as it is, no restore step.
+ * The copy lands in the backup directory as snapshot-<UTC time>.db. With FRICU_BACKUP_INTERVAL_SEC
+ * set, a background thread takes one every interval and keeps the newest FRICU_BACKUP_KEEP
+ * (default 7) snapshot files, counting those taken by hand.
+ */
+
+#define SNAPSHOT_PREFIX "snapshot-"
+#define SNAPSHOT_DEFAULT_KEEP 7
+
+typedef struct {
+    char name[64];
+    long long created_at;
+    long long bytes;
+    char checksum[BACKUP_HASH_LEN + 1];
+} backup_snapshot_t;
+
+static int is_snapshot_name(const char *name) {
+    size_t len = strlen(name);
+    return len > strlen(SNAPSHOT_PREFIX) + 3 && strncmp(name, SNAPSHOT_PREFIX, strlen(SNAPSHOT_PREFIX)) == 0 && strcmp(name + len - 3, ".db") == 0;
+}
+
+/* VACUUM INTO a temporary file, then links it under its final name so a name is never a partial copy. */
+static int take_snapshot(sqlite3 *db, const char *dir, backup_snapshot_t *out, char *err, size_t err_len) {
+    memset(out, 0, sizeof(*out));
+    if (mkdir(dir, 0700) != 0 && errno != EEXIST) {
+        snprintf(err, err_len, "cannot create backup directory");
+        return 500;
+    }
+    struct timespec now;
+    clock_gettime(CLOCK_REALTIME, &now);
+    struct tm tm;
+    gmtime_r(&now.tv_sec, &tm);
+    char stamp[32] = {0};
+    strftime(stamp, sizeof(stamp), "%Y%m%dT%H%M%S", &tm);
+    snprintf(out->name, sizeof(out->name), SNAPSHOT_PREFIX "%s.%03ldZ.db", stamp, now.tv_nsec / 1000000);
+    out->created_at = (long long)now.tv_sec;
+    char path[1024] = {0};
+    char tmp[1024] = {0};
+    if (backup_path(dir, out->name, "", path, sizeof(path)) != 0 || backup_path(dir, out->name, ".tmp", tmp, sizeof(tmp)) != 0) {
+        snprintf(err, err_len, "backup path too long");
+        return 500;
+    }
+    unlink(tmp);
+    sqlite3_stmt *stmt = NULL;
+    int rc = sqlite3_prepare_v2(db, "VACUUM INTO ?1", -1, &stmt, NULL);
+    if (rc == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, tmp, -1, SQLITE_TRANSIENT);
+        rc = sqlite3_step(stmt);
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("backup snapshot failed: %s", sqlite3_errmsg(db));
+        unlink(tmp);
+        snprintf(err, err_len, "could not snapshot the database");
+        return 500;
+    }
+    int linked = link(tmp, path);
+    int exists = linked != 0 && errno == EEXIST;
+    unlink(tmp);
+    if (linked != 0) {
+        snprintf(err, err_len, exists ? "a snapshot with this name already exists" : "could not store the backup");
+        return exists ? 409 : 500;
+    }
+    if (file_checksum(path, out->checksum, sizeof(out->checksum), &out->bytes) != 0) {
+        snprintf(err, err_len, "could not read the snapshot");
+        return 500;
+    }
+    return 201;
+}
+
+static int compare_names(const void *a, const void *b) {
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+int backup_prune_snapshots(const char *dir, int keep) {
+    DIR *d = opendir(dir);
+    if (!d) return errno == ENOENT ? 0 : -1;
+    size_t count = 0;
+    size_t cap = 16;
+    char **names = (char **)malloc(cap * sizeof(*names));
+    struct dirent *entry = NULL;
+    while (names && (entry = readdir(d)) != NULL) {
+        if (!is_snapshot_name(entry->d_name)) continue;
+        if (count == cap) {
+            char **grown = (char **)realloc(names, cap * 2 * sizeof(*names));
+            if (!grown) break;
+            names = grown;
+            cap *= 2;
+        }
+        if ((names[count] = strdup(entry->d_name)) != NULL) count++;
+    }
+    closedir(d);
+    if (!names) return -1;
+    /* The names are UTC timestamps, so they sort oldest first. */
+    qsort(names, count, sizeof(*names), compare_names);
+    int pruned = 0;
+    for (size_t i = 0; i < count; i++) {
+        char path[1024] = {0};
+        if (keep >= 0 && i + (size_t)keep < count && backup_path(dir, names[i], "", path, sizeof(path)) == 0 && unlink(path) == 0) pruned++;
+        free(names[i]);
+    }
+    free(names);
+    return pruned;
+}
+
+int handle_post_admin_backup(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char dir[512] = {0};
+    if (backup_dir(db->db_path, dir, sizeof(dir)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"backup path too long\"}", ctx);
+        return 500;
+    }
+    backup_snapshot_t snapshot;
+    char err[160] = {0};
+    double started_ms = slowlog_now_ms();
+    pthread_mutex_lock(&g_backup_mutex);
+    int status = take_snapshot(db->db, dir, &snapshot, err, sizeof(err));
+    pthread_mutex_unlock(&g_backup_mutex);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (status == 201) {
+        strbuf_appendf(
+            &sb,
+            "{\"name\":\"%s\",\"created_at\":%lld,\"bytes\":%lld,\"checksum\":\"%s\",\"path\":",
+            snapshot.name,
+            snapshot.created_at,
+            snapshot.bytes,
+            snapshot.checksum);
+        char path[1024] = {0};
+        backup_path(dir, snapshot.name, "", path, sizeof(path));
+        strbuf_append_json_string(&sb, path);
+        strbuf_append(&sb, "}", 1);
+    } else {
+        strbuf_append(&sb, "{\"error\":", 9);
+        strbuf_append_json_string(&sb, err);
+        strbuf_append(&sb, "}", 1);
+    }
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (status == 201) {
+        send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+        log_info("BACKUP snapshot name=%s bytes=%lld ms=%.0f logid=%s", snapshot.name, snapshot.bytes, slowlog_now_ms() - started_ms, ctx->log_id);
+    } else if (status == 409) {
+        send_response_with_log_context(fd, 409, "Conflict", strbuf_cstr(&sb), ctx);
+    } else {
+        send_response_with_log_context(fd, 500, "Internal Server Error", strbuf_cstr(&sb), ctx);
+        log_error("BACKUP snapshot failed error=%s logid=%s", err, ctx->log_id);
+    }
+    strbuf_free(&sb);
+    return status;
+}
+
+typedef struct {
+    char *db_path;
+    int interval_sec;
+    int keep;
+} backup_schedule_args_t;
+
+static void *backup_schedule_entry(void *arg) {
+    backup_schedule_args_t *args = (backup_schedule_args_t *)arg;
+    sqlite3 *db = NULL;
+    char dir[512] = {0};
+    if (backup_dir(args->db_path, dir, sizeof(dir)) != 0 ||
+        sqlite3_open_v2(args->db_path, &db, SQLITE_OPEN_READONLY | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("backup thread failed to open db: %s", db ? sqlite3_errmsg(db) : "backup path too long");
+        if (db) sqlite3_close(db);
+        free(args->db_path);
+        free(args);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=5000;", NULL, NULL, NULL);
+    for (;;) {
+        sleep((unsigned)args->interval_sec);
+        backup_snapshot_t snapshot;
+        char err[160] = {0};
+        double started_ms = slowlog_now_ms();
+        pthread_mutex_lock(&g_backup_mutex);
+        int status = take_snapshot(db, dir, &snapshot, err, sizeof(err));
+        int pruned = status == 201 ? backup_prune_snapshots(dir, args->keep) : 0;
+        pthread_mutex_unlock(&g_backup_mutex);
+        if (status == 201) {
+            log_info("BACKUP scheduled name=%s bytes=%lld pruned=%d ms=%.0f", snapshot.name, snapshot.bytes, pruned, slowlog_now_ms() - started_ms);
+        } else {
+            log_warn("BACKUP scheduled snapshot failed error=%s", err);
+        }
+    }
+    return NULL;
+}
+
+int backup_schedule_start(const char *db_path) {
+    const char *interval_env = getenv("FRICU_BACKUP_INTERVAL_SEC");
+    int interval_sec = interval_env ? atoi(interval_env) : 0;
+    if (interval_sec <= 0) return 0;
+    const char *keep_env = getenv("FRICU_BACKUP_KEEP");
+    int keep = keep_env ? atoi(keep_env) : SNAPSHOT_DEFAULT_KEEP;
+    if (keep <= 0) keep = SNAPSHOT_DEFAULT_KEEP;
+
+    backup_schedule_args_t *args = calloc(1, sizeof(*args));
+    if (args) {
+        args->db_path = strdup(db_path);
+        args->interval_sec = interval_sec;
+        args->keep = keep;
+    }
+    pthread_t thread;
+    if (!args || !args->db_path || pthread_create(&thread, NULL, backup_schedule_entry, args) != 0) {
+        if (args) free(args->db_path);
+        free(args);
+        log_error("failed to start backup thread");
+        return -1;
+    }
+    pthread_detach(thread);
+    log_info("BACKUP scheduled snapshots enabled interval_sec=%d keep=%d", interval_sec, keep);
+    return 0;
+}
+
+/* Applies one .diff to the image open as fd and updates the page hashes; the payload is verified first. */
+static int apply_diff(int fd, const char *dir, const backup_manifest_t *m, char **hashes, char *err, size_t err_len) {
+    char path[1024] = {0};
+    char checksum[BACKUP_HASH_LEN + 1] = {0};
+    long long bytes = 0;
+    if (backup_path(dir, m->id, ".diff", path, sizeof(path)) != 0 || file_checksum(path, checksum, sizeof(checksum), &bytes) != 0) {
+        snprintf(err, err_len, "backup %s: payload is missing", m->id);
+        return -1;
+    }
+    long long record = m->page_size + 4;
+    if (strcmp(checksum, m->payload_checksum) != 0 || bytes != m->bytes || bytes % record != 0 || bytes / record != m->pages_written) {
+        snprintf(err, err_len, "backup %s: payload checksum mismatch", m->id);
+        return -1;
+    }
+    char *grown = (char *)realloc(*hashes, (size_t)m->page_count * BACKUP_HASH_LEN + 1);
+    unsigned char *buf = (unsigned char *)malloc((size_t)record);
+    FILE *f = fopen(path, "rb");
+    if (grown) *hashes = grown;
+    int ok = grown && buf && f;
+    for (long long i = 0; ok && i < m->pages_written; i++) {
+        ok = fread(buf, 1, (size_t)record, f) == (size_t)record;
+        long long number = ok ? (long long)buf[0] << 24 | (long long)buf[1] << 16 | (long long)buf[2] << 8 | buf[3] : 0;
+        ok = ok && number >= 1 && number <= m->page_count;
+        ok = ok && pwrite(fd, buf + 4, (size_t)m->page_size, (off_t)((number - 1) * m->page_size)) == (ssize_t)m->page_size;
+        if (ok) {
+            char hex[BACKUP_HASH_LEN + 1];
+            sha256_hex(buf + 4, (size_t)m->page_size, hex, sizeof(hex));
+            memcpy(*hashes + (number - 1) * BACKUP_HASH_LEN, hex, BACKUP_HASH_LEN);
+        }
+    }
+    if (f) fclose(f);
+    free(buf);
+    if (!ok || ftruncate(fd, (off_t)(m->page_count * m->page_size)) != 0) {
+        snprintf(err, err_len, "backup %s: could not apply payload", m->id);
+        return -1;
+    }
+    return 0;
+}
+
+/* Copies a full backup to fd and returns its page hashes after checking both checksums. */
+static char *restore_base(int fd, const char *dir, const backup_manifest_t *m, char *err, size_t err_len) {
+    char path[1024] = {0};
+    char checksum[BACKUP_HASH_LEN + 1] = {0};
+    FILE *f = backup_path(dir, m->id, ".db", path, sizeof(path)) == 0 ? fopen(path, "rb") : NULL;
+    unsigned char *chunk = (unsigned char *)malloc(BACKUP_IO_CHUNK);
+    if (!f || !chunk) {
+        if (f) fclose(f);
+        free(chunk);
+        snprintf(err, err_len, "backup %s: payload is missing", m->id);
+        return NULL;
+    }
+    sha256_ctx_t sha;
+    sha256_init(&sha);
+    size_t n = 0;
+    long long bytes = 0;
+    int ok = 1;
+    while (ok && (n = fread(chunk, 1, BACKUP_IO_CHUNK, f)) > 0) {
+        sha256_update(&sha, chunk, n);
+        ok = write(fd, chunk, n) == (ssize_t)n;
+        bytes += (long long)n;
+    }
+    free(chunk);
+    sha256_final_hex(&sha, checksum, sizeof(checksum));
+    char *hashes = ok && !ferror(f) ? hash_pages(f, m->page_size, m->page_count) : NULL;
+    fclose(f);
+    char image[BACKUP_HASH_LEN + 1] = {0};
+    if (hashes) sha256_hex(hashes, (size_t)m->page_count * BACKUP_HASH_LEN, image, sizeof(image));
+    if (!hashes || bytes != m->bytes || strcmp(checksum, m->payload_checksum) != 0 || strcmp(image, m->checksum) != 0) {
+        free(hashes);
+        snprintf(err, err_len, "backup %s: payload checksum mismatch", m->id);
+        return NULL;
+    }
+    return hashes;
+}
+
+static int integrity_ok(const char *path) {
+    sqlite3 *db = NULL;
+    sqlite3_stmt *stmt = NULL;
+    int ok = sqlite3_open_v2(path, &db, SQLITE_OPEN_READWRITE, NULL) == SQLITE_OK &&
+             sqlite3_prepare_v2(db, "PRAGMA integrity_check", -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW &&
+             strcmp((const char *)sqlite3_column_text(stmt, 0), "ok") == 0;
+    sqlite3_finalize(stmt);
+    sqlite3_close(db);
+    return ok;
+}
+
+int backup_restore(const char *dir, const char *id, const char *output, char *err, size_t err_len) {
+    sqlite3 *parser = NULL;
+    if (sqlite3_open(":memory:", &parser) != SQLITE_OK) {
+        sqlite3_close(parser);
+        snprintf(err, err_len, "database error");
+        return -1;
+    }
+    /* Walk from the target back to its full backup, then apply forwards. */
+    backup_manifest_t *chain = (backup_manifest_t *)malloc(BACKUP_MAX_CHAIN * sizeof(*chain));
+    size_t depth = 0;
+    const char *next = id;
+    int ok = chain != NULL;
+    while (ok && depth < BACKUP_MAX_CHAIN) {
+        if (manifest_read(parser, dir, next, &chain[depth]) != 0) {
+            snprintf(err, err_len, "backup %s not found or its manifest is corrupt", next);
+            ok = 0;
+            break;
+        }
+        next = chain[depth].parent;
+        if (next[0] == '\0') break;
+        depth++;
+    }
+    sqlite3_close(parser);
+    if (ok && depth == BACKUP_MAX_CHAIN) {
+        snprintf(err, err_len, "backup chain of %s is too long", id);
+        ok = 0;
+    }
+    char tmp[1024] = {0};
+    if (ok && snprintf(tmp, sizeof(tmp), "%s.restore-tmp", output) >= (int)sizeof(tmp)) {
+        snprintf(err, err_len, "output path too long");
+        ok = 0;
+    }
+    int fd = ok ? open(tmp, O_RDWR | O_CREAT | O_TRUNC, 0600) : -1;
+    if (ok && fd < 0) {
+        snprintf(err, err_len, "cannot create %s", tmp);
+        ok = 0;
+    }
+    char *hashes = ok ? restore_base(fd, dir, &chain[depth], err, err_len) : NULL;
+    ok = hashes != NULL;
+    for (size_t i = depth; ok && i-- > 0;) {
+        const backup_manifest_t *m = &chain[i];
+        if (strcmp(m->parent_checksum, chain[i + 1].checksum) != 0 || m->page_size != chain[i + 1].page_size) {
+            snprintf(err, err_len, "backup %s was not taken from backup %s as restored", m->id, m->parent);
+            ok = 0;
+            break;
+        }
+        ok = apply_diff(fd, dir, m, &hashes, err, err_len) == 0;
+        char image[BACKUP_HASH_LEN + 1] = {0};
+        if (ok) sha256_hex(hashes, (size_t)m->page_count * BACKUP_HASH_LEN, image, sizeof(image));
+        if (ok && strcmp(image, m->checksum) != 0) {
+            snprintf(err, err_len, "backup %s: image checksum mismatch after applying it", m->id);
+            ok = 0;
+        }
+    }
+    free(hashes);
+    if (fd >= 0) {
+        ok = fsync(fd) == 0 && ok;
+        ok = close(fd) == 0 && ok;
+    }
+    if (ok && !integrity_ok(tmp)) {
+        snprintf(err, err_len, "restored image fails integrity_check");
+        ok = 0;
+    }
+    if (ok && rename(tmp, output) != 0) {
+        snprintf(err, err_len, "cannot rename %s to %s", tmp, output);
+        ok = 0;
+    }
+    if (!ok && tmp[0] != '\0') unlink(tmp);
+    if (ok) log_info("BACKUP restored id=%s chain=%zu output=%s", id, depth + 1, output);
+    free(chain);
+    return ok ? 0 : -1;
+}
+
+/*
+ * Restore by swap. POST /v1/admin/restore takes the database to restore as the request body (a
+ * SQLite file such as a downloaded snapshot; the body counts against FRICU_MAX_BODY_BYTES), or as
+ * JSON naming one already on the server: {"snapshot":"snapshot-....db"} in the backup directory,
+ * {"backup":"<id>"} rebuilt from the page backups, or {"path":"<file>"}. The candidate must be a
+ * SQLite file that passes integrity_check and has kv_store. The live database is saved as a
+ * snapshot first, then the backup API copies the candidate over it in one write transaction, so a
+ * reader sees the old content or the new and a writer waits for the lock. The schema is brought up
+ * to date as at startup and every pooled connection is reopened before its next request.
+ * `fricu-server --restore-db <file>` does the same to FRICU_DB_PATH from the command line.
+ */
+
+/* 0, or the status and reason the candidate cannot be restored. */
+static int verify_candidate(const char *path, char *err, size_t err_len) {
+    unsigned char header[16] = {0};
+    FILE *f = fopen(path, "rb");
+    size_t n = f ? fread(header, 1, sizeof(header), f) : 0;
+    if (f) fclose(f);
+    if (!f) {
+        snprintf(err, err_len, "cannot read the file to restore");
+        return 404;
+    }
+    if (n != sizeof(header) || memcmp(header, "SQLite format 3", 16) != 0) {
+        snprintf(err, err_len, "not a SQLite database");
+        return 422;
+    }
+    sqlite3 *db = NULL;
+    sqlite3_stmt *stmt = NULL;
+    int status = 422;
+    if (sqlite3_open_v2(path, &db, SQLITE_OPEN_READONLY, NULL) != SQLITE_OK ||
+        sqlite3_prepare_v2(db, "PRAGMA integrity_check", -1, &stmt, NULL) != SQLITE_OK) {
+        snprintf(err, err_len, "cannot open the database to restore");
+    } else if (sqlite3_step(stmt) != SQLITE_ROW || strcmp((const char *)sqlite3_column_text(stmt, 0), "ok") != 0) {
+        const char *problem = (const char *)sqlite3_column_text(stmt, 0);
+        snprintf(err, err_len, "integrity check failed: %.100s", problem ? problem : sqlite3_errmsg(db));
+    } else {
+        sqlite3_finalize(stmt);
+        stmt = NULL;
+        if (sqlite3_prepare_v2(db, "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'kv_store'", -1, &stmt, NULL) == SQLITE_OK &&
+            sqlite3_step(stmt) == SQLITE_ROW) {
+            status = 0;
+        } else {
+            snprintf(err, err_len, "not a fricu database (no kv_store table)");
+        }
+    }
+    sqlite3_finalize(stmt);
+    sqlite3_close(db);
+    return status;
+}
+
+int backup_swap_in(const char *db_path, const char *source, char *previous, size_t previous_len, char *err, size_t err_len) {
+    char live_real[PATH_MAX] = {0};
+    char source_real[PATH_MAX] = {0};
+    if (realpath(db_path, live_real) && realpath(source, source_real) && strcmp(live_real, source_real) == 0) {
+        snprintf(err, err_len, "that file is the live database");
+        return 400;
+    }
+    int status = verify_candidate(source, err, err_len);
+    if (status != 0) return status;
+    char dir[512] = {0};
+    if (backup_dir(db_path, dir, sizeof(dir)) != 0) {
+        snprintf(err, err_len, "backup path too long");
+        return 500;
+    }
+    sqlite3 *live = NULL;
+    sqlite3 *src = NULL;
+    if (sqlite3_open_v2(db_path, &live, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK ||
+        sqlite3_open_v2(source, &src, SQLITE_OPEN_READONLY, NULL) != SQLITE_OK) {
+        sqlite3_close(live);
+        sqlite3_close(src);
+        snprintf(err, err_len, "database error");
+        return 500;
+    }
+    sqlite3_busy_timeout(live, 5000);
+    backup_snapshot_t saved;
+    pthread_mutex_lock(&g_backup_mutex);
+    status = take_snapshot(live, dir, &saved, err, err_len);
+    if (status == 201) {
+        snprintf(previous, previous_len, "%s", saved.name);
+        sqlite3_backup *backup = sqlite3_backup_init(live, "main", src, "main");
+        int rc = backup ? sqlite3_backup_step(backup, -1) : SQLITE_ERROR;
+        if (backup) sqlite3_backup_finish(backup);
+        status = 200;
+        if (rc == SQLITE_BUSY || rc == SQLITE_LOCKED) {
+            snprintf(err, err_len, "database busy; try again");
+            status = 503;
+        } else if (rc == SQLITE_READONLY) {
+            snprintf(err, err_len, "page size differs from the live database; VACUUM the file with the live page size first");
+            status = 409;
+        } else if (rc != SQLITE_DONE) {
+            snprintf(err, err_len, "restore failed: %s", sqlite3_errmsg(live));
+            status = 500;
+        }
+    }
+    pthread_mutex_unlock(&g_backup_mutex);
+    sqlite3_close(src);
+    sqlite3_close(live);
+    if (status != 200) {
+        log_error("RESTORE failed source=%s error=%s", source, err);
+        return status;
+    }
+    if (init_db(db_path) != 0) {
+        snprintf(err, err_len, "restored, but the schema upgrade failed");
+        status = 500;
+    }
+    db_pool_invalidate();
+    log_info("RESTORE swapped in %s; previous database saved as %s", source, previous);
+    return status;
+}
+
+int handle_post_admin_restore(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char dir[512] = {0};
+    char source[1024] = {0};
+    char upload[1024] = {0};
+    char kind[16] = "upload";
+    char err[256] = {0};
+    int status = 0;
+    if (backup_dir(db->db_path, dir, sizeof(dir)) != 0 || snprintf(upload, sizeof(upload), "%s.restore.tmp", db->db_path) >= (int)sizeof(upload)) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"backup path too long\"}", ctx);
+        return 500;
+    }
+    char content_type[64] = {0};
+    http_request_header(req, "Content-Type", content_type, sizeof(content_type));
+    if (strncmp(content_type, "application/json", 16) == 0) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(
+                db->db,
+                "SELECT json_valid(?1), json_extract(?1, '$.snapshot'), json_extract(?1, '$.backup'), json_extract(?1, '$.path')",
+                -1,
+                &stmt,
+                NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        const char *snapshot = NULL;
+        const char *backup = NULL;
+        const char *path = NULL;
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+            snapshot = (const char *)sqlite3_column_text(stmt, 1);
+            backup = (const char *)sqlite3_column_text(stmt, 2);
+            path = (const char *)sqlite3_column_text(stmt, 3);
+        }
+        if ((snapshot != NULL) + (backup != NULL) + (path != NULL) != 1) {
+            snprintf(err, sizeof(err), "body must name exactly one of snapshot, backup or path");
+            status = 400;
+        } else if (snapshot && (!is_snapshot_name(snapshot) || strchr(snapshot, '/'))) {
+            snprintf(err, sizeof(err), "unknown snapshot");
+            status = 404;
+        } else if (snapshot) {
+            snprintf(kind, sizeof(kind), "snapshot");
+            backup_path(dir, snapshot, "", source, sizeof(source));
+        } else if (backup && !is_backup_id(backup)) {
+            snprintf(err, sizeof(err), "unknown backup");
+            status = 404;
+        } else if (backup) {
+            snprintf(kind, sizeof(kind), "backup");
+            snprintf(source, sizeof(source), "%s", upload);
+            if (backup_restore(dir, backup, upload, err, sizeof(err)) != 0) status = strstr(err, "not found") ? 404 : 422;
+        } else {
+            snprintf(kind, sizeof(kind), "path");
+            snprintf(source, sizeof(source), "%s", path);
+        }
+        sqlite3_finalize(stmt);
+    } else {
+        FILE *f = fopen(upload, "wb");
+        int ok = f && fwrite(req->body, 1, req->body_len, f) == req->body_len && fflush(f) == 0 && fsync(fileno(f)) == 0;
+        ok = f && fclose(f) == 0 && ok;
+        if (!ok) {
+            snprintf(err, sizeof(err), "cannot store the upload");
+            status = 500;
+        }
+        snprintf(source, sizeof(source), "%s", upload);
+    }
+
+    char previous[64] = {0};
+    if (status == 0) status = backup_swap_in(db->db_path, source, previous, sizeof(previous), err, sizeof(err));
+    if (strcmp(source, upload) == 0 || strcmp(kind, "backup") == 0) unlink(upload);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (status == 200) {
+        strbuf_appendf(&sb, "{\"restored\":true,\"source\":\"%s\",\"previous\":\"%s\"}", kind, previous);
+    } else {
+        strbuf_append(&sb, "{\"error\":", 9);
+        strbuf_append_json_string(&sb, err);
+        strbuf_append(&sb, "}", 1);
+    }
+    const char *body = sb.failed ? "{\"error\":\"oom\"}" : strbuf_cstr(&sb);
+    if (status != 200 && status != 400 && status != 404 && status != 409 && status != 422 && status != 503) status = 500;
+    send_response_with_log_context(fd, status, http_status_text(status), body, ctx);
+    strbuf_free(&sb);
+    log_info("RESTORE source=%s status=%d logid=%s", kind, status, ctx->log_id);
+    return status;
+}
@@ -0,0 +1,240 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Bulk activity removal. POST /v1/activities/batch-delete and /batch-archive take a filter
+ * {"from","to","sport","tag","source"} and act on every matching activity in one write, so a
+ * botched bulk import is undone in a single call. Archiving moves the items into the
+ * archived_activities key, out of the analytics but still restorable by the client. "source"
+ * matches an import run id or the source prefix of the externalID ("goldencheetah", "email").
+ * At least one filter is required; "dry_run":true only reports what would match.
+ */
+
+/* ?2 from, ?3 to, ?4 sport, ?5 tag, ?6 source, applied to json_each row "a"; never NULL so NOT is safe. */
+#define BATCH_MATCH_SQL                                                                                           \
+    "COALESCE(a.type = 'object'"                                                                                \
+    " AND (?2 IS NULL OR substr(json_extract(a.value, '$.date'), 1, 10) >= ?2)"                                 \
+    " AND (?3 IS NULL OR substr(json_extract(a.value, '$.date'), 1, 10) <= ?3)"                                 \
+    " AND (?4 IS NULL OR lower(json_extract(a.value, '$.sport')) = lower(?4))"                                  \
+    " AND (?5 IS NULL OR EXISTS (SELECT 1 FROM json_each(a.value, '$.tags') t WHERE t.value = ?5))"             \
+    " AND (?6 IS NULL OR json_extract(a.value, '$.importRunID') = ?6"                                           \
+    "  OR substr(json_extract(a.value, '$.externalID'), 1, length(?6) + 1) = ?6 || ':'), 0)"
+
+typedef struct {
+    char from[16];
+    char to[16];
+    char sport[32];
+    char tag[128];
+    char source[128];
+    int dry_run;
+} batch_filter_t;
+
+static void bind_filter(sqlite3_stmt *stmt, const batch_filter_t *f) {
+    const char *values[] = {f->from, f->to, f->sport, f->tag, f->source};
+    for (int i = 0; i < 5; i++) {
+        if (values[i][0] != '\0') sqlite3_bind_text(stmt, i + 2, values[i], -1, SQLITE_TRANSIENT);
+    }
+}
+
+static int parse_filter(sqlite3 *db, const http_request_t *req, batch_filter_t *f, char *err, size_t err_len) {
+    memset(f, 0, sizeof(*f));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_type(?1), json_extract(?1, '$.from'), json_extract(?1, '$.to'), json_extract(?1, '$.sport'),"
+            " json_extract(?1, '$.tag'), json_extract(?1, '$.source'), COALESCE(json_extract(?1, '$.dry_run'), 0)"
+            " WHERE json_valid(?1)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        snprintf(err, err_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body_len > 0 ? req->body : "{}", req->body_len > 0 ? (int)req->body_len : 2, SQLITE_TRANSIENT);
+    int status = 400;
+    snprintf(err, err_len, "{\"error\":\"body must be a JSON object\"}");
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0) && strcmp((const char *)sqlite3_column_text(stmt, 0), "object") == 0) {
+        char *fields[] = {f->from, f->to, f->sport, f->tag, f->source};
+        size_t sizes[] = {sizeof(f->from), sizeof(f->to), sizeof(f->sport), sizeof(f->tag), sizeof(f->source)};
+        for (int i = 0; i < 5; i++) {
+            const char *value = (const char *)sqlite3_column_text(stmt, i + 1);
+            if (value) snprintf(fields[i], sizes[i], "%s", value);
+        }
+        f->dry_run = sqlite3_column_int(stmt, 6) != 0;
+        int day = 0;
+        int to_day = 0;
+        if ((f->from[0] != '\0' && (strlen(f->from) != 10 || parse_iso_day(f->from, &day) != 0)) ||
+            (f->to[0] != '\0' && (strlen(f->to) != 10 || parse_iso_day(f->to, &to_day) != 0))) {
+            snprintf(err, err_len, "{\"error\":\"from and to must be YYYY-MM-DD\"}");
+        } else if (f->from[0] != '\0' && f->to[0] != '\0' && to_day < day) {
+            snprintf(err, err_len, "{\"error\":\"to must not be before from\"}");
+        } else if (f->from[0] == '\0' && f->to[0] == '\0' && f->sport[0] == '\0' && f->tag[0] == '\0' && f->source[0] == '\0') {
+            snprintf(err, err_len, "{\"error\":\"at least one of from, to, sport, tag or source is required\"}");
+        } else {
+            status = 0;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return status;
+}
+
+/* Runs sql (?1 = document, ?7 = second document) with the filter bound; returns the text result. */
+static char *batch_query(sqlite3 *db, const char *sql, const char *doc, const char *other, const batch_filter_t *f) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return NULL;
+    sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+    bind_filter(stmt, f);
+    if (other) sqlite3_bind_text(stmt, 7, other, -1, SQLITE_TRANSIENT);
+    char *out = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) out = strdup((const char *)sqlite3_column_text(stmt, 0));
+    sqlite3_finalize(stmt);
+    return out;
+}
+
+static char *load_array(sqlite3 *db, const char *account_id, const char *key) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, key, storage_key, sizeof(storage_key)) != 0) return NULL;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE((SELECT data_value FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)"
+            " AND json_type(data_value) = 'array'), CASE WHEN EXISTS (SELECT 1 FROM kv_store WHERE data_key = ?1) THEN NULL ELSE '[]' END)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    char *doc = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) doc = strdup((const char *)sqlite3_column_text(stmt, 0));
+    sqlite3_finalize(stmt);
+    return doc;
+}
+
+static int handle_activity_batch(int fd, worker_db_t *db, const http_request_t *req, int archive, const request_log_context_t *ctx) {
+    batch_filter_t filter;
+    char err[256] = {0};
+    int status = parse_filter(db->db, req, &filter, err, sizeof(err));
+    if (status != 0) {
+        send_response_with_log_context(fd, status, status == 400 ? "Bad Request" : "Internal Server Error", err, ctx);
+        return status;
+    }
+    if (!filter.dry_run) {
+        int locked = locks_enforce_key(fd, db, req, "activities", ctx);
+        if (locked == 0 && archive) locked = locks_enforce_key(fd, db, req, "archived_activities", ctx);
+        if (locked != 0) return locked;
+    }
+
+    sync_document_lock();
+    char *doc = load_array(db->db, ctx->account_id, "activities");
+    char *archived = archive ? load_array(db->db, ctx->account_id, "archived_activities") : NULL;
+    if (!doc || (archive && !archived)) {
+        sync_document_unlock();
+        free(doc);
+        free(archived);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+        return 409;
+    }
+    char *matched = batch_query(
+        db->db,
+        "SELECT json_object('count', COUNT(*), 'ids', json_group_array(json_extract(value, '$.id'))) FROM"
+        " (SELECT a.value FROM json_each(?1) a WHERE " BATCH_MATCH_SQL " ORDER BY a.key)",
+        doc,
+        NULL,
+        &filter);
+    char *remaining = NULL;
+    char *moved = NULL;
+    int count = 0;
+    sqlite3_stmt *stmt = NULL;
+    if (matched && sqlite3_prepare_v2(db->db, "SELECT json_extract(?1, '$.count')", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, matched, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW) count = sqlite3_column_int(stmt, 0);
+        sqlite3_finalize(stmt);
+    }
+    status = matched ? 200 : 500;
+    if (status == 200 && count > 0 && !filter.dry_run) {
+        remaining = batch_query(
+            db->db,
+            "SELECT json_group_array(CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END) FROM"
+            " (SELECT a.value, a.type FROM json_each(?1) a WHERE NOT " BATCH_MATCH_SQL " ORDER BY a.key)",
+            doc,
+            NULL,
+            &filter);
+        if (archive) {
+            moved = batch_query(
+                db->db,
+                "SELECT json_group_array(CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END) FROM ("
+                " SELECT value, type, 0 AS part, key AS position FROM json_each(?7)"
+                " UNION ALL SELECT a.value, a.type, 1, a.key FROM json_each(?1) a WHERE " BATCH_MATCH_SQL
+                " ORDER BY part, position)",
+                doc,
+                archived,
+                &filter);
+        }
+        char out[512] = {0};
+        if (!remaining || (archive && !moved)) {
+            status = 500;
+        } else if (archive && (status = store_account_data(db, "archived_activities", moved, strlen(moved), ctx, out, sizeof(out))) != 204 &&
+                   status != 202) {
+            log_warn("BATCH archive failed status=%d body=%s account=%s logid=%s", status, out, ctx->account_id, ctx->log_id);
+        } else if ((status = store_account_data(db, "activities", remaining, strlen(remaining), ctx, out, sizeof(out))) != 204 && status != 202) {
+            log_warn("BATCH %s failed status=%d body=%s account=%s logid=%s", archive ? "archive" : "delete", status, out, ctx->account_id, ctx->log_id);
+        } else {
+            status = 200;
+        }
+    }
+    sync_document_unlock();
+    free(doc);
+    free(archived);
+    free(remaining);
+    free(moved);
+    if (status != 200 && status != 202) {
+        free(matched);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"batch write failed\"}", ctx);
+        return 500;
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb, "{\"action\":\"%s\",\"dry_run\":%s,\"matched\":%d,\"ids\":", archive ? "archive" : "delete", filter.dry_run ? "true" : "false", count);
+    const char *ids = matched ? strstr(matched, "\"ids\":") : NULL;
+    if (ids) {
+        strbuf_append(&sb, ids + 6, strlen(ids + 6) - 1);
+    } else {
+        strbuf_append(&sb, "[]", 2);
+    }
+    strbuf_append(&sb, "}", 1);
+    free(matched);
+    log_info(
+        "BATCH %s matched=%d dry_run=%d account=%s logid=%s", archive ? "archive" : "delete", count, filter.dry_run, ctx->account_id, ctx->log_id);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+int route_activity_batch(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int archive = strcmp(req->path, "/v1/activities/batch-archive") == 0;
+    if (!archive && strcmp(req->path, "/v1/activities/batch-delete") != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    return handle_activity_batch(fd, db, req, archive, ctx);
+}
@@ -0,0 +1,773 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/evp.h>
+#endif
+
+/*
+ * Chat bots. An account links a Telegram chat (bot token + chat id) or a Discord channel (webhook
+ * URL, plus the application public key to answer slash commands). The bot answers /today, /week and
+ * /tsb from the same analytics the API serves: Telegram posts updates to /bots/telegram/<token> and
+ * gets the reply in the webhook response, Discord interactions arrive at /bots/discord/<token> and
+ * are verified with Ed25519. A background thread pushes new notifications (training risk, power
+ * records) and a daily reminder for the planned workout.
+ */
+
+#define BOT_TOKEN_BYTES 24
+#define BOT_PUSH_INTERVAL_SEC 60
+#define BOT_PUSH_BATCH 20
+#define BOT_DEFAULT_REMINDER_HOUR 7
+#define BOT_TELEGRAM_API_URL "https://api.telegram.org"
+
+static int generate_bot_token(const char *prefix, char *out, size_t out_len) {
+    unsigned char raw[BOT_TOKEN_BYTES];
+    size_t prefix_len = strlen(prefix);
+    if (out_len < prefix_len + BOT_TOKEN_BYTES * 2 + 1) return -1;
+    if (fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    memcpy(out, prefix, prefix_len);
+    for (size_t i = 0; i < sizeof(raw); i++) {
+        snprintf(out + prefix_len + i * 2, 3, "%02x", raw[i]);
+    }
+    return 0;
+}
+
+static const char *telegram_api_url(void) {
+    const char *url = getenv("FRICU_TELEGRAM_API_URL");
+    return url && url[0] != '\0' ? url : BOT_TELEGRAM_API_URL;
+}
+
+static void append_minutes(strbuf_t *sb, double seconds) {
+    long long minutes = (long long)(seconds / 60.0 + 0.5);
+    if (minutes >= 60) {
+        strbuf_appendf(sb, "%lldh%02lld", minutes / 60, minutes % 60);
+    } else {
+        strbuf_appendf(sb, "%lld min", minutes);
+    }
+}
+
+/* Planned workouts scheduled in [from, to] as "name (N min)" joined by ", "; returns how many. */
+static int append_planned(sqlite3 *db, const char *account_id, const char *from, const char *to, strbuf_t *sb, double *out_minutes) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "workouts", storage_key, sizeof(storage_key)) != 0) return 0;
+    const char *sql =
+        "SELECT COALESCE(json_extract(w.value, '$.name'), 'Workout'),"
+        " (SELECT COALESCE(SUM(json_extract(s.value, '$.minutes')), 0) FROM json_each(w.value, '$.segments') s)"
+        " FROM kv_store k, json_each(k.data_value) w"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(w.value, '$.scheduledDate'), 1, 10) BETWEEN ?2 AND ?3"
+        " ORDER BY json_extract(w.value, '$.scheduledDate'), w.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return 0;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    int count = 0;
+    double minutes = 0.0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (sb) {
+            if (count > 0) strbuf_append(sb, ", ", 2);
+            strbuf_append(sb, (const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0));
+            strbuf_appendf(sb, " (%.0f min)", sqlite3_column_double(stmt, 1));
+        }
+        minutes += sqlite3_column_double(stmt, 1);
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    if (out_minutes) *out_minutes = minutes;
+    return count;
+}
+
+static void append_tsb(sqlite3 *db, const char *account_id, strbuf_t *sb) {
+    pmc_point_t pmc;
+    if (compute_account_pmc_today(db, account_id, &pmc) != 0) {
+        strbuf_append(sb, "Training load is unavailable right now.", 39);
+        return;
+    }
+    strbuf_appendf(sb, "Fitness (CTL) %.1f, fatigue (ATL) %.1f, form (TSB) %+.1f.", pmc.ctl, pmc.atl, pmc.tsb);
+}
+
+static void answer_today(sqlite3 *db, const char *account_id, strbuf_t *sb) {
+    char today[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+    strbuf_appendf(sb, "Today %s: ", today);
+    if (append_planned(db, account_id, today, today, NULL, NULL) == 0) {
+        strbuf_append(sb, "nothing planned", 15);
+    } else {
+        strbuf_append(sb, "planned ", 8);
+        append_planned(db, account_id, today, today, sb, NULL);
+    }
+
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) == 0 &&
+        sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE(json_extract(a.value, '$.sport'), 'activity'), COALESCE(json_extract(a.value, '$.durationSec'), 0),"
+            " COALESCE(json_extract(a.value, '$.tss'), 0)"
+            " FROM kv_store k, json_each(k.data_value) a"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND substr(json_extract(a.value, '$.date'), 1, 10) = ?2 ORDER BY json_extract(a.value, '$.date')",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, today, -1, SQLITE_TRANSIENT);
+        int done = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (done++ == 0) {
+                strbuf_append(sb, ". Done: ", 8);
+            } else {
+                strbuf_append(sb, ", ", 2);
+            }
+            strbuf_append(sb, (const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0));
+            strbuf_append(sb, " ", 1);
+            append_minutes(sb, sqlite3_column_double(stmt, 1));
+            strbuf_appendf(sb, ", TSS %.0f", sqlite3_column_double(stmt, 2));
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(sb, ". ", 2);
+    append_tsb(db, account_id, sb);
+}
+
+static void answer_week(sqlite3 *db, const char *account_id, strbuf_t *sb) {
+    training_week_t week;
+    load_training_week(db, account_id, &week);
+    int today = training_today(&week);
+    int first = week_start_on(today, week.start_weekday);
+    char from[16] = {0};
+    char to[16] = {0};
+    char tomorrow[16] = {0};
+    format_iso_day(first, from, sizeof(from));
+    format_iso_day(today, to, sizeof(to));
+    format_iso_day(today + 1, tomorrow, sizeof(tomorrow));
+    int activities = 0;
+    double seconds = 0.0;
+    double tss = 0.0;
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) == 0 &&
+        sqlite3_prepare_v2(
+            db,
+            "SELECT COUNT(*), COALESCE(SUM(json_extract(a.value, '$.durationSec')), 0), COALESCE(SUM(json_extract(a.value, '$.tss')), 0)"
+            " FROM kv_store k, json_each(k.data_value) a"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?4") " BETWEEN ?2 AND ?3",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 4, week.rollover_hour);
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            activities = sqlite3_column_int(stmt, 0);
+            seconds = sqlite3_column_double(stmt, 1);
+            tss = sqlite3_column_double(stmt, 2);
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_appendf(sb, "Week of %s: %d %s, ", from, activities, activities == 1 ? "activity" : "activities");
+    append_minutes(sb, seconds);
+    strbuf_appendf(sb, ", TSS %.0f", tss);
+    char last[16] = {0};
+    format_iso_day(first + 6, last, sizeof(last));
+    double planned_minutes = 0.0;
+    int planned = today < first + 6 ? append_planned(db, account_id, tomorrow, last, NULL, &planned_minutes) : 0;
+    if (planned > 0) strbuf_appendf(sb, "; %d more planned (%.0f min)", planned, planned_minutes);
+    strbuf_append(sb, ".", 1);
+}
+
+/* Answers a chat command ("/tsb", "/week@fricu_bot", "today"); unknown input gets the help text. */
+void bots_answer(sqlite3 *db, const char *account_id, const char *command, strbuf_t *sb) {
+    char name[32] = {0};
+    const char *p = command ? command : "";
+    while (*p == ' ' || *p == '/') p++;
+    size_t n = 0;
+    while (p[n] && p[n] != ' ' && p[n] != '@' && p[n] != '\n' && n + 1 < sizeof(name)) {
+        name[n] = p[n];
+        n++;
+    }
+    if (strcmp(name, "today") == 0) {
+        answer_today(db, account_id, sb);
+    } else if (strcmp(name, "week") == 0) {
+        answer_week(db, account_id, sb);
+    } else if (strcmp(name, "tsb") == 0) {
+        append_tsb(db, account_id, sb);
+    } else {
+        strbuf_append(sb, "Commands: /today (plan and rides), /week (totals so far), /tsb (fitness, fatigue, form).", 88);
+    }
+}
+
+static int bot_send(const char *kind, const char *target, const char *secret, const char *text, char *err, size_t err_len) {
+    strbuf_t body;
+    strbuf_init(&body);
+    char url[1024] = {0};
+    if (strcmp(kind, "telegram") == 0) {
+        snprintf(url, sizeof(url), "%s/bot%s/sendMessage", telegram_api_url(), secret);
+        strbuf_append(&body, "{\"chat_id\":", 11);
+        strbuf_append_json_string(&body, target);
+        strbuf_append(&body, ",\"text\":", 8);
+    } else {
+        snprintf(url, sizeof(url), "%s", target);
+        strbuf_append(&body, "{\"content\":", 11);
+    }
+    strbuf_append_json_string(&body, text);
+    strbuf_append(&body, "}", 1);
+    if (body.failed) {
+        strbuf_free(&body);
+        snprintf(err, err_len, "oom");
+        return -1;
+    }
+    int status = outbound_request("POST", url, "Content-Type: application/json\r\n", (const unsigned char *)strbuf_cstr(&body), body.len, err, err_len);
+    strbuf_free(&body);
+    if (status < 0) return -1;
+    if (status < 200 || status >= 300) {
+        snprintf(err, err_len, "%s returned HTTP %d", strcmp(kind, "telegram") == 0 ? "Telegram sendMessage" : "Discord webhook", status);
+        return -1;
+    }
+    return 0;
+}
+
+static void record_bot_result(sqlite3 *db, const char *id, const char *error) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "UPDATE chat_bots SET last_error = ?2, last_sent_at = CASE WHEN ?2 IS NULL THEN strftime('%s', 'now') ELSE last_sent_at END WHERE id = ?1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return;
+    }
+    sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+    if (error) {
+        sqlite3_bind_text(stmt, 2, error, -1, SQLITE_TRANSIENT);
+    } else {
+        sqlite3_bind_null(stmt, 2);
+    }
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+}
+
+typedef struct {
+    char id[64];
+    char account_id[128];
+    char kind[16];
+    char target[512];
+    char secret[256];
+    int reminder_hour;
+    long long last_notification_id;
+    int last_reminder_day;
+} bot_row_t;
+
+static int push_bot(sqlite3 *db, const bot_row_t *bot, int today, int hour) {
+    int sent = 0;
+    char err[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db, "SELECT id, message, message_key, message_args FROM notifications WHERE account_id = ?1 AND id > ?2 ORDER BY id LIMIT ?3", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        return 0;
+    }
+    const char *lang = i18n_profile_language(db, bot->account_id);
+    sqlite3_bind_text(stmt, 1, bot->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, bot->last_notification_id);
+    sqlite3_bind_int(stmt, 3, BOT_PUSH_BATCH);
+    long long last_id = bot->last_notification_id;
+    int failed = 0;
+    while (!failed && sqlite3_step(stmt) == SQLITE_ROW) {
+        strbuf_t text;
+        strbuf_init(&text);
+        notification_render(
+            db, lang, (const char *)sqlite3_column_text(stmt, 1), (const char *)sqlite3_column_text(stmt, 2), (const char *)sqlite3_column_text(stmt, 3), &text);
+        if (text.failed || bot_send(bot->kind, bot->target, bot->secret, strbuf_cstr(&text), err, sizeof(err)) != 0) {
+            failed = 1;
+        } else {
+            last_id = sqlite3_column_int64(stmt, 0);
+            sent++;
+        }
+        strbuf_free(&text);
+    }
+    sqlite3_finalize(stmt);
+
+    int reminder_day = bot->last_reminder_day;
+    if (!failed && hour >= bot->reminder_hour && reminder_day < today) {
+        char day[16] = {0};
+        format_iso_day(today, day, sizeof(day));
+        strbuf_t text;
+        strbuf_init(&text);
+        strbuf_append(&text, "Reminder: today's plan is ", 26);
+        if (append_planned(db, bot->account_id, day, day, &text, NULL) == 0) {
+            reminder_day = today;
+        } else {
+            strbuf_append(&text, ".", 1);
+            if (!text.failed && bot_send(bot->kind, bot->target, bot->secret, strbuf_cstr(&text), err, sizeof(err)) == 0) {
+                reminder_day = today;
+                sent++;
+            } else {
+                failed = 1;
+            }
+        }
+        strbuf_free(&text);
+    }
+
+    if (sqlite3_prepare_v2(db, "UPDATE chat_bots SET last_notification_id = ?2, last_reminder_day = ?3 WHERE id = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, bot->id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 2, last_id);
+        sqlite3_bind_int(stmt, 3, reminder_day);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+    }
+    if (failed) {
+        log_warn("BOT push failed id=%s kind=%s account=%s error=%s", bot->id, bot->kind, bot->account_id, err);
+        record_bot_result(db, bot->id, err);
+    } else if (sent > 0) {
+        log_info("BOT push id=%s kind=%s account=%s messages=%d", bot->id, bot->kind, bot->account_id, sent);
+        record_bot_result(db, bot->id, NULL);
+    }
+    return sent;
+}
+
+/* One push pass over every bot; returns the number of messages delivered. */
+int bots_run_pushes(sqlite3 *db) {
+    time_t now = time(NULL);
+    struct tm tm_now;
+    gmtime_r(&now, &tm_now);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT id, account_id, kind, target, secret, reminder_hour, last_notification_id, last_reminder_day FROM chat_bots ORDER BY created_at, id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return 0;
+    }
+    bot_row_t *bots = NULL;
+    size_t count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        bot_row_t *grown = realloc(bots, (count + 1) * sizeof(bot_row_t));
+        if (!grown) break;
+        bots = grown;
+        bot_row_t *bot = &bots[count++];
+        memset(bot, 0, sizeof(*bot));
+        snprintf(bot->id, sizeof(bot->id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(bot->account_id, sizeof(bot->account_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(bot->kind, sizeof(bot->kind), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        snprintf(bot->target, sizeof(bot->target), "%s", (const char *)sqlite3_column_text(stmt, 3));
+        snprintf(bot->secret, sizeof(bot->secret), "%s", (const char *)sqlite3_column_text(stmt, 4));
+        bot->reminder_hour = sqlite3_column_int(stmt, 5);
+        bot->last_notification_id = sqlite3_column_int64(stmt, 6);
+        bot->last_reminder_day = sqlite3_column_int(stmt, 7);
+    }
+    sqlite3_finalize(stmt);
+    int sent = 0;
+    for (size_t i = 0; i < count; i++) sent += push_bot(db, &bots[i], today_day(), tm_now.tm_hour);
+    free(bots);
+    return sent;
+}
+
+static void *bot_thread_entry(void *arg) {
+    char *db_path = (char *)arg;
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("bot thread failed to open db: %s", db ? sqlite3_errmsg(db) : "unknown");
+        if (db) sqlite3_close(db);
+        free(db_path);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=1000;", NULL, NULL, NULL);
+    for (;;) {
+        sleep(BOT_PUSH_INTERVAL_SEC);
+        if (cluster_is_writer()) bots_run_pushes(db);
+    }
+    return NULL;
+}
+
+int bots_start(const char *db_path) {
+    const char *enabled = getenv("FRICU_BOTS");
+    if (enabled && strcmp(enabled, "0") == 0) return 0;
+    char *path_copy = strdup(db_path);
+    pthread_t thread;
+    if (!path_copy || pthread_create(&thread, NULL, bot_thread_entry, path_copy) != 0) {
+        free(path_copy);
+        log_error("failed to start bot thread");
+        return -1;
+    }
+    pthread_detach(thread);
+    return 0;
+}
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+/* Looks up the bot behind an inbound path token; fills the account into ctx. */
+static int load_inbound_bot(sqlite3 *db, const char *kind, const char *token, request_log_context_t *ctx, char *target, size_t target_len, char *secret, size_t secret_len) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT account_id, target, secret FROM chat_bots WHERE kind = ?1 AND inbound_token = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, kind, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, token, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(ctx->account_id, sizeof(ctx->account_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(target, target_len, "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(secret, secret_len, "%s", (const char *)sqlite3_column_text(stmt, 2));
+        found = 1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static int handle_telegram_update(int fd, worker_db_t *db, const http_request_t *req, const char *token, const request_log_context_t *ctx) {
+    request_log_context_t account_ctx = *ctx;
+    char chat_id[512] = {0};
+    char bot_token[256] = {0};
+    int found = load_inbound_bot(db->db, "telegram", token, &account_ctx, chat_id, sizeof(chat_id), bot_token, sizeof(bot_token));
+    if (found <= 0) {
+        send_response_with_log_context(fd, found < 0 ? 500 : 404, found < 0 ? "Internal Server Error" : "Not Found", found < 0 ? "{\"error\":\"database error\"}" : "{\"error\":\"unknown bot\"}", ctx);
+        return found < 0 ? 500 : 404;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT CAST(json_extract(?1, '$.message.chat.id') AS TEXT), json_extract(?1, '$.message.text') WHERE json_valid(?1)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", &account_ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char from_chat[64] = {0};
+    char text[256] = {0};
+    int valid = sqlite3_step(stmt) == SQLITE_ROW;
+    if (valid) {
+        if (sqlite3_column_text(stmt, 0)) snprintf(from_chat, sizeof(from_chat), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        if (sqlite3_column_text(stmt, 1)) snprintf(text, sizeof(text), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", &account_ctx);
+        return 400;
+    }
+    /* Updates from other chats (or without text) are acknowledged and ignored. */
+    if (strcmp(from_chat, chat_id) != 0 || text[0] == '\0') {
+        send_response_with_log_context(fd, 200, "OK", "{}", &account_ctx);
+        return 200;
+    }
+    strbuf_t answer;
+    strbuf_init(&answer);
+    bots_answer(db->db, account_ctx.account_id, text, &answer);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"method\":\"sendMessage\",\"chat_id\":", 34);
+    strbuf_append_json_string(&sb, chat_id);
+    strbuf_append(&sb, ",\"text\":", 8);
+    strbuf_append_json_string(&sb, answer.failed ? "" : strbuf_cstr(&answer));
+    strbuf_append(&sb, "}", 1);
+    strbuf_free(&answer);
+    log_info("BOT telegram command=%s account=%s logid=%s", text, account_ctx.account_id, ctx->log_id);
+    return send_strbuf(fd, 200, "OK", &sb, &account_ctx);
+}
+
+#ifdef FRICU_HAVE_OPENSSL
+static int hex_decode(const char *hex, unsigned char *out, size_t out_len) {
+    if (strlen(hex) != out_len * 2) return -1;
+    for (size_t i = 0; i < out_len; i++) {
+        unsigned int byte = 0;
+        if (sscanf(hex + i * 2, "%2x", &byte) != 1) return -1;
+        out[i] = (unsigned char)byte;
+    }
+    return 0;
+}
+
+static int verify_discord_signature(const char *public_key_hex, const char *signature_hex, const char *timestamp, const char *body, size_t body_len) {
+    unsigned char key[32];
+    unsigned char sig[64];
+    if (hex_decode(public_key_hex, key, sizeof(key)) != 0 || hex_decode(signature_hex, sig, sizeof(sig)) != 0) return 0;
+    size_t ts_len = strlen(timestamp);
+    unsigned char *message = malloc(ts_len + body_len + 1);
+    if (!message) return 0;
+    memcpy(message, timestamp, ts_len);
+    if (body_len > 0) memcpy(message + ts_len, body, body_len);
+    EVP_PKEY *pkey = EVP_PKEY_new_raw_public_key(EVP_PKEY_ED25519, NULL, key, sizeof(key));
+    EVP_MD_CTX *md = EVP_MD_CTX_new();
+    int ok = pkey && md && EVP_DigestVerifyInit(md, NULL, NULL, NULL, pkey) == 1 &&
+             EVP_DigestVerify(md, sig, sizeof(sig), message, ts_len + body_len) == 1;
+    EVP_MD_CTX_free(md);
+    EVP_PKEY_free(pkey);
+    free(message);
+    return ok;
+}
+#endif
+
+static int handle_discord_interaction(int fd, worker_db_t *db, const http_request_t *req, const char *token, const request_log_context_t *ctx) {
+    request_log_context_t account_ctx = *ctx;
+    char webhook_url[512] = {0};
+    char public_key[256] = {0};
+    int found = load_inbound_bot(db->db, "discord", token, &account_ctx, webhook_url, sizeof(webhook_url), public_key, sizeof(public_key));
+    if (found <= 0) {
+        send_response_with_log_context(fd, found < 0 ? 500 : 404, found < 0 ? "Internal Server Error" : "Not Found", found < 0 ? "{\"error\":\"database error\"}" : "{\"error\":\"unknown bot\"}", ctx);
+        return found < 0 ? 500 : 404;
+    }
+#ifdef FRICU_HAVE_OPENSSL
+    char signature[160] = {0};
+    char timestamp[64] = {0};
+    if (public_key[0] == '\0') {
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"bot has no public_key for interactions\"}", &account_ctx);
+        return 409;
+    }
+    if (!http_request_header(req, "X-Signature-Ed25519", signature, sizeof(signature)) ||
+        !http_request_header(req, "X-Signature-Timestamp", timestamp, sizeof(timestamp)) ||
+        !verify_discord_signature(public_key, signature, timestamp, req->body ? req->body : "", req->body_len)) {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid request signature\"}", &account_ctx);
+        return 401;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_extract(?1, '$.type'), json_extract(?1, '$.data.name') WHERE json_valid(?1)", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", &account_ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    int type = 0;
+    char command[64] = {0};
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        type = sqlite3_column_int(stmt, 0);
+        if (sqlite3_column_text(stmt, 1)) snprintf(command, sizeof(command), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    }
+    sqlite3_finalize(stmt);
+    if (type == 1) {
+        send_response_with_log_context(fd, 200, "OK", "{\"type\":1}", &account_ctx);
+        return 200;
+    }
+    if (type != 2) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"unsupported interaction type\"}", &account_ctx);
+        return 400;
+    }
+    strbuf_t answer;
+    strbuf_init(&answer);
+    bots_answer(db->db, account_ctx.account_id, command, &answer);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"type\":4,\"data\":{\"content\":", 28);
+    strbuf_append_json_string(&sb, answer.failed ? "" : strbuf_cstr(&answer));
+    strbuf_append(&sb, "}}", 2);
+    strbuf_free(&answer);
+    log_info("BOT discord command=%s account=%s logid=%s", command, account_ctx.account_id, ctx->log_id);
+    return send_strbuf(fd, 200, "OK", &sb, &account_ctx);
+#else
+    (void)req;
+    send_response_with_log_context(fd, 501, "Not Implemented", "{\"error\":\"Discord interactions need a server built with OpenSSL\"}", &account_ctx);
+    return 501;
+#endif
+}
+
+/* /bots/telegram/<token> and /bots/discord/<token>; the token in the path is the shared secret. */
+int route_bot_webhooks(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    if (strncmp(req->path, "/bots/telegram/", 15) == 0) return handle_telegram_update(fd, db, req, req->path + 15, ctx);
+    if (strncmp(req->path, "/bots/discord/", 14) == 0) return handle_discord_interaction(fd, db, req, req->path + 14, ctx);
+    send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+    return 404;
+}
+
+static void append_bot_json(strbuf_t *sb, sqlite3_stmt *stmt) {
+    const char *kind = (const char *)sqlite3_column_text(stmt, 1);
+    int telegram = strcmp(kind, "telegram") == 0;
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"kind\":", 8);
+    strbuf_append_json_string(sb, kind);
+    if (telegram) {
+        strbuf_append(sb, ",\"chat_id\":", 11);
+        strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 2));
+    } else {
+        strbuf_appendf(sb, ",\"interactions\":%s", sqlite3_column_bytes(stmt, 3) > 0 ? "true" : "false");
+    }
+    capture_omit_response();
+    strbuf_appendf(sb, ",\"webhook_path\":\"/bots/%s/%s\"", kind, (const char *)sqlite3_column_text(stmt, 4));
+    strbuf_appendf(sb, ",\"reminder_hour\":%d,\"last_sent_at\":", sqlite3_column_int(stmt, 5));
+    if (sqlite3_column_type(stmt, 6) == SQLITE_NULL) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_appendf(sb, "%lld", sqlite3_column_int64(stmt, 6));
+    }
+    strbuf_append(sb, ",\"last_error\":", 14);
+    if (sqlite3_column_type(stmt, 7) == SQLITE_NULL) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 7));
+    }
+    strbuf_appendf(sb, ",\"created_at\":%lld}", sqlite3_column_int64(stmt, 8));
+}
+
+#define BOT_COLUMNS "id, kind, target, secret, inbound_token, reminder_hour, last_sent_at, last_error, created_at"
+
+static int handle_list_bots(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT " BOT_COLUMNS " FROM chat_bots WHERE account_id = ?1 ORDER BY created_at, id", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"bots\":[", 9);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_bot_json(&sb, stmt);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_post_bot(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT json_valid(?1), json_extract(?1, '$.kind'), CAST(json_extract(?1, '$.chat_id') AS TEXT), json_extract(?1, '$.bot_token'),"
+        " json_extract(?1, '$.webhook_url'), COALESCE(json_extract(?1, '$.public_key'), ''), json_type(?1, '$.reminder_hour'),"
+        " json_extract(?1, '$.reminder_hour')";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char kind[16] = {0};
+    char target[512] = {0};
+    char secret[256] = {0};
+    int reminder_hour = BOT_DEFAULT_REMINDER_HOUR;
+    const char *error = "{\"error\":\"invalid json\"}";
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+        const char *raw_kind = (const char *)sqlite3_column_text(stmt, 1);
+        const char *chat_id = (const char *)sqlite3_column_text(stmt, 2);
+        const char *bot_token = (const char *)sqlite3_column_text(stmt, 3);
+        const char *webhook_url = (const char *)sqlite3_column_text(stmt, 4);
+        const char *public_key = (const char *)sqlite3_column_text(stmt, 5);
+        const char *hour_type = (const char *)sqlite3_column_text(stmt, 6);
+        if (hour_type) reminder_hour = strcmp(hour_type, "integer") == 0 ? sqlite3_column_int(stmt, 7) : -1;
+        if (reminder_hour < 0 || reminder_hour > 23) {
+            error = "{\"error\":\"reminder_hour must be an integer 0-23 (UTC)\"}";
+        } else if (raw_kind && strcmp(raw_kind, "telegram") == 0) {
+            if (!chat_id || chat_id[0] == '\0' || !bot_token || bot_token[0] == '\0' || strlen(bot_token) >= sizeof(secret) || strchr(bot_token, '/')) {
+                error = "{\"error\":\"telegram bots need bot_token and chat_id\"}";
+            } else {
+                snprintf(kind, sizeof(kind), "telegram");
+                snprintf(target, sizeof(target), "%s", chat_id);
+                snprintf(secret, sizeof(secret), "%s", bot_token);
+                error = NULL;
+            }
+        } else if (raw_kind && strcmp(raw_kind, "discord") == 0) {
+            if (!webhook_url || (strncmp(webhook_url, "https://", 8) != 0 && strncmp(webhook_url, "http://", 7) != 0) || strlen(webhook_url) >= sizeof(target)) {
+                error = "{\"error\":\"discord bots need an http(s) webhook_url\"}";
+            } else if (public_key[0] != '\0' && strlen(public_key) != 64) {
+                error = "{\"error\":\"public_key must be the 64-character hex application key\"}";
+            } else {
+                snprintf(kind, sizeof(kind), "discord");
+                snprintf(target, sizeof(target), "%s", webhook_url);
+                snprintf(secret, sizeof(secret), "%s", public_key);
+                error = NULL;
+            }
+        } else {
+            error = "{\"error\":\"kind must be telegram or discord\"}";
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (error) {
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+
+    char id[40] = {0};
+    char inbound_token[64] = {0};
+    if (generate_uuid_v4(id, sizeof(id)) != 0 || generate_bot_token("bot_", inbound_token, sizeof(inbound_token)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        return 500;
+    }
+    /* Start after the newest notification so linking a bot does not replay the whole feed. */
+    if (sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO chat_bots (id, account_id, kind, target, secret, inbound_token, reminder_hour, last_notification_id, created_at)"
+            " VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, (SELECT COALESCE(MAX(id), 0) FROM notifications WHERE account_id = ?2), strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, kind, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, target, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 5, secret, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 6, inbound_token, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 7, reminder_hour);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("BOT linked id=%s kind=%s account=%s", id, kind, ctx->account_id);
+
+    if (sqlite3_prepare_v2(db->db, "SELECT " BOT_COLUMNS " FROM chat_bots WHERE id = ?1", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (sqlite3_step(stmt) == SQLITE_ROW) append_bot_json(&sb, stmt);
+    sqlite3_finalize(stmt);
+    return send_strbuf(fd, 201, "Created", &sb, ctx);
+}
+
+int route_bots(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *id = req->path[8] == '/' ? req->path + 9 : NULL;
+    if (!id) {
+        if (strcmp(req->method, "GET") == 0) return handle_list_bots(fd, db, ctx);
+        if (strcmp(req->method, "POST") == 0) return handle_post_bot(fd, db, req, ctx);
+    } else if (strcmp(req->method, "DELETE") == 0) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, "DELETE FROM chat_bots WHERE id = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+        if (sqlite3_changes(db->db) == 0) {
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown bot\"}", ctx);
+            return 404;
+        }
+        log_info("BOT unlinked id=%s account=%s", id, ctx->account_id);
+        send_response_with_log_context(fd, 204, "No Content", "", ctx);
+        return 204;
+    }
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
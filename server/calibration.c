@@ -0,0 +1,323 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Sensor calibration log: zero offsets, slope changes, battery swaps and other service events per
+ * gear item, where a gear item is the "gear" name activities carry (see profile.sports.defaultGear).
+ * Each event is annotated with the mean power of that gear's rides in the two weeks before and
+ * after it, and flagged when the two differ by 5% or more, so a step in recorded power can be
+ * traced to the meter rather than to fitness. Analytics charts list the events in their range.
+ */
+
+#define CALIBRATION_GEAR_MAX 128
+#define CALIBRATION_NOTES_MAX 1000
+#define CALIBRATION_WINDOW_DAYS 14
+#define CALIBRATION_JUMP_PCT 5.0
+
+#define CALIBRATION_COLUMNS "id, gear, day, kind, value, notes, created_at, updated_at"
+
+static int parse_day_strict(const char *text, int *out_day) {
+    return text && strlen(text) == 10 && parse_iso_day(text, out_day) == 0 ? 0 : -1;
+}
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+/* Mean ride power (NP, else average power) for a gear over [from_day, to_day]; -1 without rides. */
+static double gear_mean_power(sqlite3 *db, const char *storage_key, const char *gear, int from_day, int to_day, int *out_rides) {
+    char from[16] = {0};
+    char to[16] = {0};
+    format_iso_day(from_day, from, sizeof(from));
+    format_iso_day(to_day, to, sizeof(to));
+    const char *sql =
+        "SELECT AVG(COALESCE(json_extract(a.value, '$.normalizedPower'), json_extract(a.value, '$.avgPower'))), COUNT(*)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.gear') = ?2 AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?3 AND ?4"
+        " AND COALESCE(json_extract(a.value, '$.normalizedPower'), json_extract(a.value, '$.avgPower')) > 0";
+    sqlite3_stmt *stmt = NULL;
+    *out_rides = 0;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1.0;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, gear, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, to, -1, SQLITE_TRANSIENT);
+    double mean = -1.0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 1) > 0) {
+        mean = sqlite3_column_double(stmt, 0);
+        *out_rides = sqlite3_column_int(stmt, 1);
+    }
+    sqlite3_finalize(stmt);
+    return mean;
+}
+
+static void append_power_window(strbuf_t *sb, const char *name, double watts, int rides) {
+    if (watts < 0.0) {
+        strbuf_appendf(sb, ",\"%s\":null", name);
+    } else {
+        strbuf_appendf(sb, ",\"%s\":{\"watts\":%.0f,\"rides\":%d}", name, watts, rides);
+    }
+}
+
+static void append_event_json(strbuf_t *sb, sqlite3 *db, const char *storage_key, sqlite3_stmt *stmt, int full) {
+    /* Columns: CALIBRATION_COLUMNS */
+    const char *gear = (const char *)sqlite3_column_text(stmt, 1);
+    int day = 0;
+    parse_iso_day((const char *)sqlite3_column_text(stmt, 2), &day);
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"gear\":", 8);
+    strbuf_append_json_string(sb, gear);
+    strbuf_append(sb, ",\"date\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 2));
+    strbuf_append(sb, ",\"kind\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 3));
+    if (sqlite3_column_type(stmt, 4) == SQLITE_NULL) {
+        strbuf_append(sb, ",\"value\":null", 13);
+    } else {
+        strbuf_appendf(sb, ",\"value\":%g", sqlite3_column_double(stmt, 4));
+    }
+    if (full) {
+        strbuf_append(sb, ",\"notes\":", 9);
+        strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 5));
+        strbuf_appendf(sb, ",\"created_at\":%lld,\"updated_at\":%lld", sqlite3_column_int64(stmt, 6), sqlite3_column_int64(stmt, 7));
+    }
+    int rides_before = 0;
+    int rides_after = 0;
+    double before = gear_mean_power(db, storage_key, gear ? gear : "", day - CALIBRATION_WINDOW_DAYS, day - 1, &rides_before);
+    double after = gear_mean_power(db, storage_key, gear ? gear : "", day, day + CALIBRATION_WINDOW_DAYS - 1, &rides_after);
+    append_power_window(sb, "power_before", before, rides_before);
+    append_power_window(sb, "power_after", after, rides_after);
+    if (before > 0.0 && after >= 0.0) {
+        double change = (after - before) * 100.0 / before;
+        strbuf_appendf(sb, ",\"change_pct\":%.1f,\"suspicious_jump\":%s}", change, fabs(change) >= CALIBRATION_JUMP_PCT ? "true" : "false");
+    } else {
+        strbuf_append(sb, ",\"change_pct\":null,\"suspicious_jump\":false}", 43);
+    }
+}
+
+void calibration_append_annotations(strbuf_t *sb, sqlite3 *db, const char *account_id, int from_day, int to_day) {
+    char storage_key[256] = {0};
+    char from[16] = {0};
+    char to[16] = {0};
+    format_iso_day(from_day, from, sizeof(from));
+    format_iso_day(to_day, to, sizeof(to));
+    strbuf_append(sb, "[", 1);
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) == 0 &&
+        sqlite3_prepare_v2(
+            db,
+            "SELECT " CALIBRATION_COLUMNS " FROM calibration_events WHERE account_id = ?1 AND day BETWEEN ?2 AND ?3 ORDER BY day, created_at",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (count++ > 0) strbuf_append(sb, ",", 1);
+            append_event_json(sb, db, storage_key, stmt, 0);
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+static int handle_list_calibrations(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char gear[CALIBRATION_GEAR_MAX + 1] = {0};
+    char from[16] = {0};
+    char to[16] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    int has_gear = query_param(req->query, "gear", gear, sizeof(gear));
+    if ((query_param(req->query, "from", from, sizeof(from)) && parse_day_strict(from, &from_day) != 0) ||
+        (query_param(req->query, "to", to, sizeof(to)) && parse_day_strict(to, &to_day) != 0)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        sqlite3_prepare_v2(
+            db->db,
+            "SELECT " CALIBRATION_COLUMNS " FROM calibration_events WHERE account_id = ?1 AND (?2 IS NULL OR gear = ?2)"
+            " AND (?3 IS NULL OR day >= ?3) AND (?4 IS NULL OR day <= ?4) ORDER BY day, created_at",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    if (has_gear) sqlite3_bind_text(stmt, 2, gear, -1, SQLITE_TRANSIENT);
+    if (from[0] != '\0') sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+    if (to[0] != '\0') sqlite3_bind_text(stmt, 4, to, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"events\":[", 11);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_event_json(&sb, db->db, storage_key, stmt, 1);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_get_calibration(int fd, worker_db_t *db, const char *id, int code, const request_log_context_t *ctx) {
+    char storage_key[256] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        sqlite3_prepare_v2(db->db, "SELECT " CALIBRATION_COLUMNS " FROM calibration_events WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) !=
+            SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"calibration event not found\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_event_json(&sb, db->db, storage_key, stmt, 1);
+    sqlite3_finalize(stmt);
+    return send_strbuf(fd, code, code == 201 ? "Created" : "OK", &sb, ctx);
+}
+
+static int handle_store_calibration(int fd, worker_db_t *db, const http_request_t *req, const char *existing_id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *check_sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object'"
+        " AND json_type(?1, '$.gear') = 'text' AND length(trim(json_extract(?1, '$.gear'))) BETWEEN 1 AND ?2"
+        " AND json_type(?1, '$.date') = 'text'"
+        " AND json_extract(?1, '$.kind') IN ('zero_offset', 'slope_change', 'battery_swap', 'other')"
+        " AND (COALESCE(json_type(?1, '$.value'), 'null') = 'null' OR json_type(?1, '$.value') IN ('integer', 'real'))"
+        " AND NOT (json_extract(?1, '$.kind') = 'battery_swap' AND json_extract(?1, '$.value') IS NOT NULL)"
+        " AND COALESCE(json_type(?1, '$.notes'), 'text') IN ('text', 'null') AND length(COALESCE(json_extract(?1, '$.notes'), '')) <= ?3"
+        " AND NOT EXISTS (SELECT 1 FROM json_each(?1) WHERE key NOT IN ('gear', 'date', 'kind', 'value', 'notes')),"
+        " json_extract(?1, '$.date')";
+    if (sqlite3_prepare_v2(db->db, check_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, CALIBRATION_GEAR_MAX);
+    sqlite3_bind_int(stmt, 3, CALIBRATION_NOTES_MAX);
+    char day[16] = {0};
+    int parsed_day = 0;
+    int valid = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+        snprintf(day, sizeof(day), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        valid = parse_day_strict(day, &parsed_day) == 0;
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(
+            fd,
+            400,
+            "Bad Request",
+            "{\"error\":\"expected {\\\"gear\\\": string, \\\"date\\\": YYYY-MM-DD, \\\"kind\\\": zero_offset|slope_change|battery_swap|other, "
+            "\\\"value\\\": number or null, \\\"notes\\\": string}\"}",
+            ctx);
+        return 400;
+    }
+
+    char id[64] = {0};
+    if (existing_id) {
+        snprintf(id, sizeof(id), "%s", existing_id);
+    } else if (generate_uuid_v4(id, sizeof(id)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"id generation failed\"}", ctx);
+        return 500;
+    }
+    const char *sql = existing_id
+                          ? "UPDATE calibration_events SET gear = trim(json_extract(?3, '$.gear')), day = ?4, kind = json_extract(?3, '$.kind'),"
+                            " value = json_extract(?3, '$.value'), notes = COALESCE(json_extract(?3, '$.notes'), ''), updated_at = strftime('%s', 'now')"
+                            " WHERE account_id = ?1 AND id = ?2"
+                          : "INSERT INTO calibration_events (account_id, id, gear, day, kind, value, notes, created_at, updated_at)"
+                            " VALUES (?1, ?2, trim(json_extract(?3, '$.gear')), ?4, json_extract(?3, '$.kind'), json_extract(?3, '$.value'),"
+                            " COALESCE(json_extract(?3, '$.notes'), ''), strftime('%s', 'now'), strftime('%s', 'now'))";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, day, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("CALIBRATION store failed id=%s account=%s err=%s logid=%s", id, ctx->account_id, sqlite3_errmsg(db->db), ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (existing_id && sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"calibration event not found\"}", ctx);
+        return 404;
+    }
+    log_info("CALIBRATION stored id=%s day=%s account=%s logid=%s", id, day, ctx->account_id, ctx->log_id);
+    return handle_get_calibration(fd, db, id, existing_id ? 200 : 201, ctx);
+}
+
+static int handle_delete_calibration(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM calibration_events WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"calibration event not found\"}", ctx);
+        return 404;
+    }
+    log_info("CALIBRATION deleted id=%s account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_gear_calibrations(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *collection = "/v1/gear/calibrations";
+    if (strcmp(req->path, collection) == 0) {
+        if (strcmp(method, "GET") == 0) return handle_list_calibrations(fd, db, req, ctx);
+        if (strcmp(method, "POST") == 0) return handle_store_calibration(fd, db, req, NULL, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+
+    const char *id = req->path + strlen(collection) + 1;
+    if (!is_valid_item_id(id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"calibration event not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(method, "GET") == 0) return handle_get_calibration(fd, db, id, 200, ctx);
+    if (strcmp(method, "PUT") == 0) return handle_store_calibration(fd, db, req, id, ctx);
+    if (strcmp(method, "DELETE") == 0) return handle_delete_calibration(fd, db, id, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
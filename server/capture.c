@@ -0,0 +1,367 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <time.h>
+
+#define CAPTURE_RING_SIZE 64
+#define CAPTURE_BODY_MAX 4096
+#define CAPTURE_HEADERS_MAX 2048
+#define CAPTURE_DEFAULT_LIMIT 100
+#define CAPTURE_MAX_LIMIT 10000
+
+typedef struct {
+    long long seq;
+    long long captured_at;
+    char log_id[96];
+    char account_id[128];
+    char method[8];
+    char path[512];
+    char query[512];
+    char request_headers[CAPTURE_HEADERS_MAX];
+    char request_body[CAPTURE_BODY_MAX + 1];
+    size_t request_body_len;
+    int status;
+    char response_body[CAPTURE_BODY_MAX + 1];
+    size_t response_body_len;
+} capture_entry_t;
+
+typedef struct {
+    int enabled;
+    int remaining;
+    char account_id[128];
+    char key[64];
+    char device_token[96];
+} capture_filter_t;
+
+static pthread_mutex_t g_capture_mutex = PTHREAD_MUTEX_INITIALIZER;
+static capture_filter_t g_capture_filter;
+static capture_entry_t g_capture_ring[CAPTURE_RING_SIZE];
+static long long g_capture_next_seq = 1;
+
+/* The request currently being recorded on this worker thread, if it matched the filter. */
+static __thread capture_entry_t g_capture_active;
+static __thread int g_capture_active_set;
+static __thread int g_capture_omit_response;
+
+static const char *REDACTED_HEADERS[] = {"Authorization", "Cookie", "X-Device-Token", "X-Admin-Token", "X-Coach-Token"};
+static const char *REDACTED_QUERY_PARAMS[] = {"token", "access_token"};
+/* JSON members whose string values are replaced, matched as the whole name or its last "_" part. */
+static const char *REDACTED_JSON_FIELDS[] = {"password", "token", "secret", "refresh_token"};
+/* Webhook paths whose last segment is the credential. */
+static const char *SECRET_PATH_PREFIXES[] = {"/bots/telegram/", "/bots/discord/", "/inbound/email/"};
+
+/* Copies at most max bytes, dropping a trailing partial UTF-8 sequence so the JSON output stays valid. */
+static size_t copy_truncated(char *out, const char *src, size_t len, size_t max) {
+    size_t n = len < max ? len : max;
+    if (n < len) {
+        size_t cut = n;
+        while (cut > 0 && ((unsigned char)src[cut] & 0xC0) == 0x80) cut--;
+        n = cut;
+    }
+    memcpy(out, src, n);
+    out[n] = '\0';
+    return n;
+}
+
+static int is_redacted_json_field(const char *name, size_t name_len) {
+    for (size_t i = 0; i < sizeof(REDACTED_JSON_FIELDS) / sizeof(REDACTED_JSON_FIELDS[0]); i++) {
+        size_t field_len = strlen(REDACTED_JSON_FIELDS[i]);
+        if (field_len > name_len || strncasecmp(name + name_len - field_len, REDACTED_JSON_FIELDS[i], field_len) != 0) continue;
+        if (field_len == name_len || name[name_len - field_len - 1] == '_') return 1;
+    }
+    return 0;
+}
+
+/*
+ * Copies a (possibly truncated) JSON body, replacing the string value of every secret member with
+ * "[redacted]". Works on the text, so bodies that are cut off or not JSON at all still come out scrubbed.
+ */
+static void scrub_json_body(char *out, size_t out_len, const char *src, size_t len) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    size_t i = 0;
+    while (i < len && sb.len < out_len) {
+        if (src[i] != '"') {
+            strbuf_append(&sb, src + i, 1);
+            i++;
+            continue;
+        }
+        size_t end = i + 1;
+        while (end < len && src[end] != '"') end += src[end] == '\\' ? 2 : 1;
+        if (end > len) end = len;
+        size_t name_start = i + 1;
+        size_t name_len = end - name_start;
+        strbuf_append(&sb, src + i, end < len ? end - i + 1 : end - i);
+        i = end < len ? end + 1 : end;
+        size_t colon = i;
+        while (colon < len && (src[colon] == ' ' || src[colon] == '\t' || src[colon] == '\r' || src[colon] == '\n')) colon++;
+        if (colon >= len || src[colon] != ':' || !is_redacted_json_field(src + name_start, name_len)) continue;
+        size_t value = colon + 1;
+        while (value < len && (src[value] == ' ' || src[value] == '\t' || src[value] == '\r' || src[value] == '\n')) value++;
+        if (value >= len || src[value] != '"') continue;
+        size_t value_end = value + 1;
+        while (value_end < len && src[value_end] != '"') value_end += src[value_end] == '\\' ? 2 : 1;
+        strbuf_append(&sb, src + i, value - i);
+        strbuf_append(&sb, "\"[redacted]\"", 12);
+        i = value_end < len ? value_end + 1 : len;
+    }
+    copy_truncated(out, sb.failed ? "" : strbuf_cstr(&sb), sb.failed ? 0 : sb.len, out_len - 1);
+    strbuf_free(&sb);
+}
+
+static void sanitize_path(const char *path, char *out, size_t out_len) {
+    for (size_t i = 0; i < sizeof(SECRET_PATH_PREFIXES) / sizeof(SECRET_PATH_PREFIXES[0]); i++) {
+        size_t prefix_len = strlen(SECRET_PATH_PREFIXES[i]);
+        if (strncmp(path, SECRET_PATH_PREFIXES[i], prefix_len) == 0 && path[prefix_len] != '\0') {
+            snprintf(out, out_len, "%s[redacted]", SECRET_PATH_PREFIXES[i]);
+            return;
+        }
+    }
+    snprintf(out, out_len, "%s", path);
+}
+
+static int is_redacted_header(const char *name, size_t name_len) {
+    for (size_t i = 0; i < sizeof(REDACTED_HEADERS) / sizeof(REDACTED_HEADERS[0]); i++) {
+        if (strlen(REDACTED_HEADERS[i]) == name_len && strncasecmp(REDACTED_HEADERS[i], name, name_len) == 0) return 1;
+    }
+    return 0;
+}
+
+static void sanitize_headers(const http_request_t *req, char *out, size_t out_len) {
+    size_t used = 0;
+    out[0] = '\0';
+    const char *line = strstr(req->raw, "\r\n");
+    while (line && line < req->header_end) {
+        line += 2;
+        const char *eol = strstr(line, "\r\n");
+        if (!eol || eol > req->header_end) eol = req->header_end;
+        const char *colon = memchr(line, ':', (size_t)(eol - line));
+        int n = 0;
+        if (colon && is_redacted_header(line, (size_t)(colon - line))) {
+            n = snprintf(out + used, out_len - used, "%.*s: [redacted]\n", (int)(colon - line), line);
+        } else {
+            n = snprintf(out + used, out_len - used, "%.*s\n", (int)(eol - line), line);
+        }
+        if (n < 0 || (size_t)n >= out_len - used) break;
+        used += (size_t)n;
+        line = eol;
+    }
+}
+
+static void sanitize_query(const char *query, char *out, size_t out_len) {
+    size_t used = 0;
+    out[0] = '\0';
+    const char *p = query;
+    while (*p != '\0') {
+        const char *amp = strchr(p, '&');
+        size_t part_len = amp ? (size_t)(amp - p) : strlen(p);
+        const char *eq = memchr(p, '=', part_len);
+        size_t name_len = eq ? (size_t)(eq - p) : part_len;
+        int redact = 0;
+        for (size_t i = 0; i < sizeof(REDACTED_QUERY_PARAMS) / sizeof(REDACTED_QUERY_PARAMS[0]); i++) {
+            if (strlen(REDACTED_QUERY_PARAMS[i]) == name_len && strncmp(REDACTED_QUERY_PARAMS[i], p, name_len) == 0) redact = 1;
+        }
+        int n = redact ? snprintf(out + used, out_len - used, "%s%.*s=[redacted]", used ? "&" : "", (int)name_len, p)
+                       : snprintf(out + used, out_len - used, "%s%.*s", used ? "&" : "", (int)part_len, p);
+        if (n < 0 || (size_t)n >= out_len - used) break;
+        used += (size_t)n;
+        if (!amp) break;
+        p = amp + 1;
+    }
+}
+
+static int filter_matches(const capture_filter_t *filter, const http_request_t *req, const request_log_context_t *ctx) {
+    if (filter->account_id[0] != '\0' && strcmp(filter->account_id, ctx->account_id) != 0) return 0;
+    if (filter->key[0] != '\0') {
+        char key[64] = {0};
+        request_data_key(req->path, key, sizeof(key));
+        if (strcmp(filter->key, key) != 0) return 0;
+    }
+    if (filter->device_token[0] != '\0') {
+        char token[96] = {0};
+        if (!http_request_header(req, "X-Device-Token", token, sizeof(token))) query_param(req->query, "token", token, sizeof(token));
+        if (strcmp(filter->device_token, token) != 0) return 0;
+    }
+    return 1;
+}
+
+void capture_begin(const http_request_t *req, const request_log_context_t *ctx) {
+    g_capture_active_set = 0;
+    g_capture_omit_response = 0;
+    if (strncmp(req->path, "/v1/admin/", 10) == 0) return;
+
+    pthread_mutex_lock(&g_capture_mutex);
+    int matched = g_capture_filter.enabled && g_capture_filter.remaining > 0 && filter_matches(&g_capture_filter, req, ctx);
+    if (matched) {
+        g_capture_filter.remaining--;
+        if (g_capture_filter.remaining == 0) {
+            g_capture_filter.enabled = 0;
+            log_info("CAPTURE limit reached, recording disabled");
+        }
+    }
+    pthread_mutex_unlock(&g_capture_mutex);
+    if (!matched) return;
+
+    capture_entry_t *entry = &g_capture_active;
+    memset(entry, 0, sizeof(*entry));
+    entry->captured_at = (long long)time(NULL);
+    snprintf(entry->log_id, sizeof(entry->log_id), "%s", ctx->log_id);
+    snprintf(entry->account_id, sizeof(entry->account_id), "%s", ctx->account_id);
+    snprintf(entry->method, sizeof(entry->method), "%s", req->method);
+    sanitize_path(req->path, entry->path, sizeof(entry->path));
+    sanitize_query(req->query, entry->query, sizeof(entry->query));
+    sanitize_headers(req, entry->request_headers, sizeof(entry->request_headers));
+    scrub_json_body(entry->request_body, sizeof(entry->request_body), req->body, req->body_len);
+    entry->request_body_len = req->body_len;
+    g_capture_active_set = 1;
+}
+
+void capture_omit_response(void) {
+    g_capture_omit_response = 1;
+}
+
+void capture_record_response(int code, const char *body, size_t body_len) {
+    if (!g_capture_active_set) return;
+    g_capture_active.status = code;
+    g_capture_active.response_body_len = body ? body_len : 0;
+    if (g_capture_omit_response) {
+        snprintf(g_capture_active.response_body, sizeof(g_capture_active.response_body), "[omitted: response carries a credential]");
+        return;
+    }
+    scrub_json_body(g_capture_active.response_body, sizeof(g_capture_active.response_body), body ? body : "", body ? body_len : 0);
+}
+
+void capture_end(void) {
+    if (!g_capture_active_set) return;
+    g_capture_active_set = 0;
+    pthread_mutex_lock(&g_capture_mutex);
+    g_capture_active.seq = g_capture_next_seq++;
+    g_capture_ring[g_capture_active.seq % CAPTURE_RING_SIZE] = g_capture_active;
+    pthread_mutex_unlock(&g_capture_mutex);
+}
+
+static int handle_post_captures(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object',"
+        " coalesce(json_extract(?1, '$.account_id'), ''), coalesce(json_extract(?1, '$.key'), ''),"
+        " coalesce(json_extract(?1, '$.device_token'), ''), coalesce(json_extract(?1, '$.limit'), ?2)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, CAPTURE_DEFAULT_LIMIT);
+    capture_filter_t filter;
+    memset(&filter, 0, sizeof(filter));
+    int valid = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    if (valid) {
+        snprintf(filter.account_id, sizeof(filter.account_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(filter.key, sizeof(filter.key), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        snprintf(filter.device_token, sizeof(filter.device_token), "%s", (const char *)sqlite3_column_text(stmt, 3));
+        filter.remaining = sqlite3_column_int(stmt, 4);
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body must be a JSON object\"}", ctx);
+        return 400;
+    }
+    if (filter.account_id[0] == '\0' && filter.key[0] == '\0' && filter.device_token[0] == '\0') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"set at least one of account_id, key, device_token\"}", ctx);
+        return 400;
+    }
+    if (filter.remaining <= 0 || filter.remaining > CAPTURE_MAX_LIMIT) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"limit must be in 1..10000\"}", ctx);
+        return 400;
+    }
+    filter.enabled = 1;
+
+    pthread_mutex_lock(&g_capture_mutex);
+    g_capture_filter = filter;
+    pthread_mutex_unlock(&g_capture_mutex);
+    log_info(
+        "CAPTURE enabled account=%s key=%s device_token=%s limit=%d logid=%s",
+        filter.account_id[0] ? filter.account_id : "-",
+        filter.key[0] ? filter.key : "-",
+        filter.device_token[0] ? "set" : "-",
+        filter.remaining,
+        ctx->log_id);
+    send_response_with_log_context(fd, 200, "OK", "{\"enabled\":true}", ctx);
+    return 200;
+}
+
+static int handle_delete_captures(int fd, const request_log_context_t *ctx) {
+    pthread_mutex_lock(&g_capture_mutex);
+    memset(&g_capture_filter, 0, sizeof(g_capture_filter));
+    memset(g_capture_ring, 0, sizeof(g_capture_ring));
+    pthread_mutex_unlock(&g_capture_mutex);
+    log_info("CAPTURE disabled and cleared logid=%s", ctx->log_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+static int handle_get_captures(int fd, const request_log_context_t *ctx) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    pthread_mutex_lock(&g_capture_mutex);
+    strbuf_appendf(&sb, "{\"enabled\":%s,\"remaining\":%d,\"filter\":{\"account_id\":", g_capture_filter.enabled ? "true" : "false", g_capture_filter.remaining);
+    strbuf_append_json_string(&sb, g_capture_filter.account_id);
+    strbuf_append(&sb, ",\"key\":", 7);
+    strbuf_append_json_string(&sb, g_capture_filter.key);
+    strbuf_appendf(&sb, ",\"device_token\":%s},\"entries\":[", g_capture_filter.device_token[0] ? "\"[redacted]\"" : "\"\"");
+    long long first = g_capture_next_seq - CAPTURE_RING_SIZE;
+    if (first < 1) first = 1;
+    int count = 0;
+    for (long long seq = first; seq < g_capture_next_seq; seq++) {
+        const capture_entry_t *entry = &g_capture_ring[seq % CAPTURE_RING_SIZE];
+        if (entry->seq != seq) continue;
+        strbuf_appendf(&sb, "%s{\"seq\":%lld,\"captured_at\":%lld,\"log_id\":", count == 0 ? "" : ",", entry->seq, entry->captured_at);
+        strbuf_append_json_string(&sb, entry->log_id);
+        strbuf_append(&sb, ",\"account_id\":", 14);
+        strbuf_append_json_string(&sb, entry->account_id);
+        strbuf_append(&sb, ",\"method\":", 10);
+        strbuf_append_json_string(&sb, entry->method);
+        strbuf_append(&sb, ",\"path\":", 8);
+        strbuf_append_json_string(&sb, entry->path);
+        strbuf_append(&sb, ",\"query\":", 9);
+        strbuf_append_json_string(&sb, entry->query);
+        strbuf_append(&sb, ",\"request_headers\":", 19);
+        strbuf_append_json_string(&sb, entry->request_headers);
+        strbuf_append(&sb, ",\"request_body\":", 16);
+        strbuf_append_json_string(&sb, entry->request_body);
+        strbuf_appendf(&sb, ",\"request_body_bytes\":%zu,\"status\":%d,\"response_body\":", entry->request_body_len, entry->status);
+        strbuf_append_json_string(&sb, entry->response_body);
+        strbuf_appendf(&sb, ",\"response_body_bytes\":%zu}", entry->response_body_len);
+        count++;
+    }
+    pthread_mutex_unlock(&g_capture_mutex);
+    strbuf_append(&sb, "]}", 2);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+int handle_admin_captures(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->method, "GET") == 0) return handle_get_captures(fd, ctx);
+    if (strcmp(req->method, "POST") == 0) return handle_post_captures(fd, db, req, ctx);
+    if (strcmp(req->method, "DELETE") == 0) return handle_delete_captures(fd, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -0,0 +1,220 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Multi-instance coordination. Replicas share one database file; the instance holding the
+ * "writer" lease row applies writes and background work, the others serve reads only.
+ * The lease is renewed every ttl/3 seconds and is taken over once it has expired.
+ */
+
+#define CLUSTER_DEFAULT_TTL_SEC 15
+#define CLUSTER_MIN_TTL_SEC 3
+#define CLUSTER_MAX_TTL_SEC 300
+
+typedef struct {
+    int enabled;
+    int ttl_sec;
+    char instance_id[128];
+    char advertise_url[256];
+    int is_leader;
+    char leader_id[128];
+    char leader_url[256];
+    long long lease_expires_at;
+} cluster_state_t;
+
+static pthread_mutex_t g_cluster_mutex = PTHREAD_MUTEX_INITIALIZER;
+static cluster_state_t g_cluster;
+
+void cluster_configure(const char *instance_id, const char *advertise_url, int ttl_sec) {
+    pthread_mutex_lock(&g_cluster_mutex);
+    memset(&g_cluster, 0, sizeof(g_cluster));
+    if (instance_id && instance_id[0] != '\0') {
+        g_cluster.enabled = 1;
+        g_cluster.ttl_sec = ttl_sec >= CLUSTER_MIN_TTL_SEC && ttl_sec <= CLUSTER_MAX_TTL_SEC ? ttl_sec : CLUSTER_DEFAULT_TTL_SEC;
+        snprintf(g_cluster.instance_id, sizeof(g_cluster.instance_id), "%s", instance_id);
+        snprintf(g_cluster.advertise_url, sizeof(g_cluster.advertise_url), "%s", advertise_url ? advertise_url : "");
+    }
+    pthread_mutex_unlock(&g_cluster_mutex);
+}
+
+int cluster_renew(sqlite3 *db) {
+    cluster_state_t snapshot;
+    pthread_mutex_lock(&g_cluster_mutex);
+    snapshot = g_cluster;
+    pthread_mutex_unlock(&g_cluster_mutex);
+    if (!snapshot.enabled) return 0;
+
+    const char *acquire_sql =
+        "INSERT INTO cluster_lease (name, holder, advertise_url, expires_at, acquired_at)"
+        " VALUES ('writer', ?1, ?2, strftime('%s', 'now') + ?3, strftime('%s', 'now'))"
+        " ON CONFLICT(name) DO UPDATE SET holder = excluded.holder, advertise_url = excluded.advertise_url,"
+        " expires_at = excluded.expires_at,"
+        " acquired_at = CASE WHEN cluster_lease.holder = excluded.holder THEN cluster_lease.acquired_at ELSE excluded.acquired_at END"
+        " WHERE cluster_lease.holder = excluded.holder OR cluster_lease.expires_at < strftime('%s', 'now')";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, acquire_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("cluster lease prepare failed: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, snapshot.instance_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, snapshot.advertise_url, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, snapshot.ttl_sec);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_warn("cluster lease renew failed: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+
+    if (sqlite3_prepare_v2(db, "SELECT holder, advertise_url, expires_at FROM cluster_lease WHERE name = 'writer'", -1, &stmt, NULL) != SQLITE_OK) {
+        return -1;
+    }
+    char holder[128] = {0};
+    char holder_url[256] = {0};
+    long long expires_at = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(holder, sizeof(holder), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(holder_url, sizeof(holder_url), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        expires_at = sqlite3_column_int64(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+
+    pthread_mutex_lock(&g_cluster_mutex);
+    int was_leader = g_cluster.is_leader;
+    g_cluster.is_leader = strcmp(holder, g_cluster.instance_id) == 0;
+    snprintf(g_cluster.leader_id, sizeof(g_cluster.leader_id), "%s", holder);
+    snprintf(g_cluster.leader_url, sizeof(g_cluster.leader_url), "%s", holder_url);
+    g_cluster.lease_expires_at = expires_at;
+    int is_leader = g_cluster.is_leader;
+    pthread_mutex_unlock(&g_cluster_mutex);
+
+    if (is_leader != was_leader) {
+        log_info("CLUSTER role=%s instance=%s leader=%s", is_leader ? "leader" : "follower", snapshot.instance_id, holder);
+    }
+    return is_leader;
+}
+
+int cluster_is_writer(void) {
+    pthread_mutex_lock(&g_cluster_mutex);
+    /* A leader that cannot renew steps down on its own once the lease it last saw has run out. */
+    int writer = !g_cluster.enabled || (g_cluster.is_leader && (long long)time(NULL) < g_cluster.lease_expires_at);
+    pthread_mutex_unlock(&g_cluster_mutex);
+    return writer;
+}
+
+static const char *role_name(const cluster_state_t *state) {
+    if (!state->enabled) return "standalone";
+    return state->is_leader && (long long)time(NULL) < state->lease_expires_at ? "leader" : "follower";
+}
+
+void cluster_append_status(strbuf_t *sb) {
+    cluster_state_t snapshot;
+    pthread_mutex_lock(&g_cluster_mutex);
+    snapshot = g_cluster;
+    pthread_mutex_unlock(&g_cluster_mutex);
+    strbuf_appendf(sb, "\"role\":\"%s\"", role_name(&snapshot));
+    if (!snapshot.enabled) return;
+    strbuf_append(sb, ",\"instance\":", 12);
+    strbuf_append_json_string(sb, snapshot.instance_id);
+    strbuf_append(sb, ",\"leader\":", 10);
+    strbuf_append_json_string(sb, snapshot.leader_id);
+    strbuf_append(sb, ",\"leader_url\":", 14);
+    strbuf_append_json_string(sb, snapshot.leader_url);
+    strbuf_appendf(sb, ",\"lease_expires_at\":%lld", snapshot.lease_expires_at);
+}
+
+int cluster_reject_write(int fd, const request_log_context_t *ctx) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"error\":\"read-only replica\",", 29);
+    cluster_append_status(&sb);
+    strbuf_append(&sb, "}", 1);
+    send_http_response(
+        fd,
+        503,
+        "Service Unavailable",
+        "application/json",
+        "Retry-After: 1\r\nX-Fricu-Role: follower\r\n",
+        strbuf_cstr(&sb),
+        strlen(strbuf_cstr(&sb)),
+        ctx);
+    strbuf_free(&sb);
+    return 503;
+}
+
+static void *cluster_thread_entry(void *arg) {
+    char *db_path = (char *)arg;
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("cluster lease thread failed to open db: %s", db ? sqlite3_errmsg(db) : "unknown");
+        if (db) sqlite3_close(db);
+        free(db_path);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=1000;", NULL, NULL, NULL);
+    for (;;) {
+        cluster_renew(db);
+        pthread_mutex_lock(&g_cluster_mutex);
+        int interval = g_cluster.ttl_sec / 3;
+        pthread_mutex_unlock(&g_cluster_mutex);
+        sleep((unsigned)(interval > 0 ? interval : 1));
+    }
+    return NULL;
+}
+
+const char *server_instance_id(void) {
+    static char default_id[128];
+    pthread_mutex_lock(&g_cluster_mutex);
+    if (g_cluster.enabled) {
+        pthread_mutex_unlock(&g_cluster_mutex);
+        return g_cluster.instance_id;
+    }
+    if (default_id[0] == '\0') {
+        const char *id_env = getenv("FRICU_INSTANCE_ID");
+        if (id_env && id_env[0] != '\0') {
+            snprintf(default_id, sizeof(default_id), "%s", id_env);
+        } else {
+            char host[64] = {0};
+            if (gethostname(host, sizeof(host) - 1) != 0) snprintf(host, sizeof(host), "fricu");
+            snprintf(default_id, sizeof(default_id), "%s-%ld", host, (long)getpid());
+        }
+    }
+    pthread_mutex_unlock(&g_cluster_mutex);
+    return default_id;
+}
+
+int cluster_start(const char *db_path) {
+    const char *mode = getenv("FRICU_CLUSTER_MODE");
+    if (!mode || mode[0] == '\0' || strcmp(mode, "standalone") == 0) return 0;
+    if (strcmp(mode, "lease") != 0) {
+        log_error("invalid FRICU_CLUSTER_MODE: %s (supported: standalone, lease)", mode);
+        return -1;
+    }
+
+    char instance_id[128] = {0};
+    snprintf(instance_id, sizeof(instance_id), "%s", server_instance_id());
+    const char *ttl_env = getenv("FRICU_LEASE_TTL_SEC");
+    cluster_configure(instance_id, getenv("FRICU_ADVERTISE_URL"), ttl_env ? atoi(ttl_env) : CLUSTER_DEFAULT_TTL_SEC);
+
+    char *path_copy = strdup(db_path);
+    pthread_t thread;
+    if (!path_copy || pthread_create(&thread, NULL, cluster_thread_entry, path_copy) != 0) {
+        free(path_copy);
+        log_error("failed to start cluster lease thread");
+        return -1;
+    }
+    pthread_detach(thread);
+    log_info("CLUSTER lease mode instance=%s", instance_id);
+    return 0;
+}
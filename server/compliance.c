@@ -0,0 +1,272 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Planned-workout compliance, including bricks. A segment may carry its own "sport" (falling back
+ * to the workout's), so a bike+run brick or a triathlon simulation is one workout whose consecutive
+ * same-sport segments form legs; {"repeat": N, "segments": [...]} groups count N times. GET
+ * /v1/workouts/<id>/compliance matches the legs, in order, against the activities of the workout's
+ * scheduled day: each leg takes the unused activity of its sport, starting no earlier than the
+ * previous leg's, whose duration and load are closest to the plan. A leg scores 100 x min(actual /
+ * planned, planned / actual) on duration, blended 60/40 with the same ratio on load when both sides
+ * have one (planned load is minutes / 60 x IF^2 x 100); a leg with no activity scores 0. The workout
+ * score averages the legs weighted by planned duration, and the gap between one leg's end and the
+ * next one's start is reported as the transition.
+ */
+
+#define COMPLIANCE_MAX_LEGS 16
+#define COMPLIANCE_MAX_ACTIVITIES 64
+#define COMPLIANCE_COMPLETED_SCORE 70.0
+
+typedef struct {
+    const char *sport;
+    double planned_sec;
+    double planned_load;
+    int activity;
+    double score;
+} compliance_leg_t;
+
+typedef struct {
+    char id[128];
+    const char *sport;
+    long long start;
+    double duration_sec;
+    double load;
+    int used;
+} compliance_activity_t;
+
+/* Reads the legs of segments_json; returns the count, or -1 on error. */
+static int load_legs(sqlite3 *db, const char *segments_json, const char *workout_sport, compliance_leg_t *legs) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT COALESCE(json_extract(COALESCE(i.value, o.value), '$.sport'), json_extract(o.value, '$.sport')),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.minutes'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.intensityPercentFTP'), 0),"
+        " CASE WHEN i.key IS NULL THEN 1 ELSE MAX(COALESCE(json_extract(o.value, '$.repeat'), 1), 1) END"
+        " FROM json_each(?1) o"
+        " LEFT JOIN json_each(CASE WHEN json_type(o.value, '$.segments') = 'array' THEN json_extract(o.value, '$.segments') ELSE '[]' END) i"
+        " WHERE json_type(o.value, '$.segments') IS NOT 'array' OR i.key IS NOT NULL"
+        " ORDER BY o.key, i.key";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, segments_json, -1, SQLITE_TRANSIENT);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        double minutes = sqlite3_column_double(stmt, 1);
+        if (minutes <= 0.0) continue;
+        const char *sport = sport_canonical_name((const char *)sqlite3_column_text(stmt, 0));
+        if (!sport) sport = workout_sport;
+        if (count == 0 || strcmp(legs[count - 1].sport, sport) != 0) {
+            if (count == COMPLIANCE_MAX_LEGS) break;
+            legs[count++] = (compliance_leg_t){sport, 0.0, 0.0, -1, 0.0};
+        }
+        double intensity = sqlite3_column_double(stmt, 2) / 100.0;
+        double repeat = sqlite3_column_double(stmt, 3);
+        legs[count - 1].planned_sec += minutes * 60.0 * repeat;
+        legs[count - 1].planned_load += minutes / 60.0 * intensity * intensity * 100.0 * repeat;
+    }
+    sqlite3_finalize(stmt);
+    return count;
+}
+
+/* The activities whose training day is day, oldest first; returns the count, or -1 on error. */
+static int load_day_activities(sqlite3 *db, const char *account_id, const char *day, compliance_activity_t *out) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+    training_week_t week;
+    load_training_week(db, account_id, &week);
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT CAST(json_extract(a.value, '$.id') AS TEXT), json_extract(a.value, '$.sport'),"
+        " COALESCE(CAST(strftime('%s', json_extract(a.value, '$.date')) AS INTEGER), 0),"
+        " COALESCE(json_extract(a.value, '$.durationSec'), 0), COALESCE(json_extract(a.value, '$.tss'), 0)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?3") " = ?2"
+        " ORDER BY 3, a.key";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, week.rollover_hour);
+    int count = 0;
+    while (count < COMPLIANCE_MAX_ACTIVITIES && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *sport = sport_canonical_name((const char *)sqlite3_column_text(stmt, 1));
+        if (!sport) continue;
+        compliance_activity_t *a = &out[count++];
+        memset(a, 0, sizeof(*a));
+        const char *id = (const char *)sqlite3_column_text(stmt, 0);
+        snprintf(a->id, sizeof(a->id), "%s", id ? id : "");
+        a->sport = sport;
+        a->start = sqlite3_column_int64(stmt, 2);
+        a->duration_sec = sqlite3_column_double(stmt, 3);
+        a->load = sqlite3_column_double(stmt, 4);
+    }
+    sqlite3_finalize(stmt);
+    return count;
+}
+
+/* 100 when actual equals planned, falling off the same way above and below; -1 when there is no plan. */
+static double ratio_score(double actual, double planned) {
+    if (planned <= 0.0) return -1.0;
+    if (actual <= 0.0) return 0.0;
+    return 100.0 * fmin(actual / planned, planned / actual);
+}
+
+static double match_error(const compliance_leg_t *leg, const compliance_activity_t *a) {
+    double error = fabs(a->duration_sec - leg->planned_sec) / fmax(600.0, leg->planned_sec);
+    if (leg->planned_load > 0.0 && a->load > 0.0) error += fabs(a->load - leg->planned_load) / fmax(10.0, leg->planned_load);
+    return error;
+}
+
+static void match_legs(compliance_leg_t *legs, int leg_count, compliance_activity_t *activities, int activity_count) {
+    long long not_before = 0;
+    for (int l = 0; l < leg_count; l++) {
+        int best = -1;
+        for (int i = 0; i < activity_count; i++) {
+            const compliance_activity_t *a = &activities[i];
+            if (a->used || strcmp(a->sport, legs[l].sport) != 0 || a->start < not_before) continue;
+            if (best < 0 || match_error(&legs[l], a) < match_error(&legs[l], &activities[best])) best = i;
+        }
+        if (best < 0) continue;
+        activities[best].used = 1;
+        legs[l].activity = best;
+        not_before = activities[best].start;
+        double duration = ratio_score(activities[best].duration_sec, legs[l].planned_sec);
+        double load = activities[best].load > 0.0 ? ratio_score(activities[best].load, legs[l].planned_load) : -1.0;
+        legs[l].score = load >= 0.0 ? 0.6 * duration + 0.4 * load : duration;
+    }
+}
+
+static const char *leg_status(const compliance_leg_t *leg) {
+    if (leg->activity < 0) return "missed";
+    return leg->score >= COMPLIANCE_COMPLETED_SCORE ? "completed" : "partial";
+}
+
+int handle_get_workout_compliance(int fd, worker_db_t *db, const char *workout_id, const request_log_context_t *ctx) {
+    if (!is_valid_item_id(workout_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid workout id\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "workouts", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT COALESCE(json_extract(w.value, '$.name'), ''), json_extract(w.value, '$.sport'),"
+            " substr(json_extract(w.value, '$.scheduledDate'), 1, 10), COALESCE(json_extract(w.value, '$.segments'), '[]')"
+            " FROM kv_store k, json_each(k.data_value) w"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND CAST(json_extract(w.value, '$.id') AS TEXT) = ?2 LIMIT 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, workout_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown workout\"}", ctx);
+        return 404;
+    }
+    char name[256] = {0};
+    char day[16] = {0};
+    snprintf(name, sizeof(name), "%s", (const char *)sqlite3_column_text(stmt, 0));
+    const char *sport = sport_canonical_name((const char *)sqlite3_column_text(stmt, 1));
+    if (!sport) sport = "cycling";
+    const char *day_text = (const char *)sqlite3_column_text(stmt, 2);
+    int day_number = 0;
+    int scheduled = day_text && parse_iso_day(day_text, &day_number) == 0;
+    if (scheduled) snprintf(day, sizeof(day), "%s", day_text);
+    char *segments = strdup((const char *)sqlite3_column_text(stmt, 3));
+    sqlite3_finalize(stmt);
+    if (!segments) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (!scheduled) {
+        free(segments);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"workout has no scheduledDate\"}", ctx);
+        return 409;
+    }
+
+    compliance_leg_t legs[COMPLIANCE_MAX_LEGS];
+    compliance_activity_t activities[COMPLIANCE_MAX_ACTIVITIES];
+    int leg_count = load_legs(db->db, segments, sport, legs);
+    free(segments);
+    int activity_count = leg_count >= 0 ? load_day_activities(db->db, ctx->account_id, day, activities) : -1;
+    if (leg_count < 0 || activity_count < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    match_legs(legs, leg_count, activities, activity_count);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"workout_id\":\"%s\",\"name\":", workout_id);
+    strbuf_append_json_string(&sb, name);
+    strbuf_appendf(&sb, ",\"date\":\"%s\",\"legs\":[", day);
+    double weighted = 0.0;
+    double planned_total = 0.0;
+    int matched = 0;
+    int completed = 0;
+    for (int l = 0; l < leg_count; l++) {
+        const compliance_leg_t *leg = &legs[l];
+        weighted += leg->score * leg->planned_sec;
+        planned_total += leg->planned_sec;
+        if (l > 0) strbuf_append(&sb, ",", 1);
+        strbuf_appendf(
+            &sb,
+            "{\"index\":%d,\"sport\":\"%s\",\"planned_sec\":%.0f,\"planned_load\":%.1f,\"activity_id\":",
+            l,
+            leg->sport,
+            leg->planned_sec,
+            leg->planned_load);
+        if (leg->activity < 0) {
+            strbuf_append(&sb, "null,\"status\":\"missed\",\"score\":0}", 33);
+            continue;
+        }
+        const compliance_activity_t *a = &activities[leg->activity];
+        matched++;
+        if (strcmp(leg_status(leg), "completed") == 0) completed++;
+        strbuf_append_json_string(&sb, a->id);
+        strbuf_appendf(&sb, ",\"status\":\"%s\",\"score\":%.1f,\"actual_sec\":%.0f,\"actual_load\":%.1f", leg_status(leg), leg->score, a->duration_sec, a->load);
+        /* The gap after the previous leg, when both legs were done and timed. */
+        if (l > 0 && legs[l - 1].activity >= 0 && a->start > 0 && activities[legs[l - 1].activity].start > 0) {
+            const compliance_activity_t *prev = &activities[legs[l - 1].activity];
+            strbuf_appendf(&sb, ",\"transition_sec\":%.0f", fmax(0.0, (double)(a->start - prev->start) - prev->duration_sec));
+        }
+        strbuf_append(&sb, "}", 1);
+    }
+    double score = planned_total > 0.0 ? weighted / planned_total : 0.0;
+    const char *status = "partial";
+    if (matched == 0) {
+        status = "missed";
+    } else if (completed == leg_count) {
+        status = "completed";
+    }
+    strbuf_appendf(
+        &sb, "],\"multisport\":%s,\"matched\":%d,\"score\":%.1f,\"status\":\"%s\"}", leg_count > 1 ? "true" : "false", matched, score, status);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    log_info(
+        "COMPLIANCE workout=%s legs=%d matched=%d score=%.1f account=%s logid=%s", workout_id, leg_count, matched, score, ctx->account_id, ctx->log_id);
+    return 200;
+}
@@ -0,0 +1,292 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+#ifdef FRICU_HAVE_ZLIB
+#include <zlib.h>
+#endif
+#ifdef FRICU_HAVE_BROTLI
+#include <brotli/decode.h>
+#include <brotli/encode.h>
+#endif
+
+/*
+ * HTTP content coding. Responses of at least COMPRESSION_MIN_BYTES with a text-like type are sent
+ * as br or gzip, whichever Accept-Encoding prefers (br on a tie), with Vary: Accept-Encoding; a body
+ * that would not shrink goes out as-is. Request bodies sent with Content-Encoding: gzip, deflate or
+ * br are decoded before dispatch, up to the same body limit a plain body has, so handlers
+ * never see the coding. Each codec needs its library at build time (zlib, libbrotlienc/dec; `make`
+ * detects them, FRICU_COMPRESSION=0 turns both off); without it that coding is not offered and a
+ * body using it is refused with 415.
+ */
+
+#define COMPRESSION_MIN_BYTES 1024
+#define COMPRESSION_GZIP_LEVEL 6
+#define COMPRESSION_BROTLI_QUALITY 5
+
+typedef enum { CODING_IDENTITY, CODING_GZIP, CODING_BROTLI } coding_t;
+
+static __thread coding_t g_accepted;
+static __thread char *g_decoded_body;
+static __thread char *g_encoded_body;
+
+/* q-value of one coding in an Accept-Encoding list; "*" stands in for codings not listed. -1 if absent. */
+static double accept_quality(const char *header, const char *coding) {
+    double wildcard = -1.0;
+    const char *p = header;
+    while (*p) {
+        p += strspn(p, " \t,");
+        size_t len = strcspn(p, " \t;,");
+        if (len == 0) break;
+        double q = 1.0;
+        const char *params = p + len;
+        const char *end = params + strcspn(params, ",");
+        const char *qpos = strstr(params, "q=");
+        if (qpos && qpos < end) q = atof(qpos + 2);
+        if (len == strlen(coding) && strncasecmp(p, coding, len) == 0) return q;
+        if (len == 1 && *p == '*') wildcard = q;
+        p = end;
+    }
+    return wildcard;
+}
+
+static int coding_available(coding_t coding) {
+#ifdef FRICU_HAVE_ZLIB
+    if (coding == CODING_GZIP) return 1;
+#endif
+#ifdef FRICU_HAVE_BROTLI
+    if (coding == CODING_BROTLI) return 1;
+#endif
+    return coding == CODING_IDENTITY;
+}
+
+void compression_begin(const http_request_t *req) {
+    g_accepted = CODING_IDENTITY;
+    char header[256] = {0};
+    if (!http_request_header(req, "Accept-Encoding", header, sizeof(header))) return;
+    double br = coding_available(CODING_BROTLI) ? accept_quality(header, "br") : -1.0;
+    double gzip = coding_available(CODING_GZIP) ? accept_quality(header, "gzip") : -1.0;
+    if (br > 0 && br >= gzip) {
+        g_accepted = CODING_BROTLI;
+    } else if (gzip > 0) {
+        g_accepted = CODING_GZIP;
+    }
+}
+
+void compression_end(void) {
+    g_accepted = CODING_IDENTITY;
+    free(g_decoded_body);
+    g_decoded_body = NULL;
+    free(g_encoded_body);
+    g_encoded_body = NULL;
+}
+
+static int compressible_type(const char *content_type) {
+    if (!content_type) return 0;
+    return strncmp(content_type, "text/", 5) == 0 || strncmp(content_type, "application/json", 16) == 0 ||
+           strncmp(content_type, "application/openmetrics-text", 28) == 0 || strncmp(content_type, "application/xml", 15) == 0 ||
+           strstr(content_type, "+xml") != NULL || strstr(content_type, "+json") != NULL;
+}
+
+#ifdef FRICU_HAVE_ZLIB
+static char *gzip_encode(const char *body, size_t body_len, size_t *out_len) {
+    z_stream stream;
+    memset(&stream, 0, sizeof(stream));
+    if (deflateInit2(&stream, COMPRESSION_GZIP_LEVEL, Z_DEFLATED, 15 + 16, 8, Z_DEFAULT_STRATEGY) != Z_OK) return NULL;
+    size_t cap = deflateBound(&stream, (uLong)body_len);
+    char *out = (char *)malloc(cap);
+    if (!out) {
+        deflateEnd(&stream);
+        return NULL;
+    }
+    stream.next_in = (Bytef *)body;
+    stream.avail_in = (uInt)body_len;
+    stream.next_out = (Bytef *)out;
+    stream.avail_out = (uInt)cap;
+    int rc = deflate(&stream, Z_FINISH);
+    *out_len = stream.total_out;
+    deflateEnd(&stream);
+    if (rc != Z_STREAM_END) {
+        free(out);
+        return NULL;
+    }
+    return out;
+}
+#endif
+
+#ifdef FRICU_HAVE_BROTLI
+static char *brotli_encode(const char *body, size_t body_len, size_t *out_len) {
+    size_t cap = BrotliEncoderMaxCompressedSize(body_len);
+    char *out = cap > 0 ? (char *)malloc(cap) : NULL;
+    if (!out) return NULL;
+    *out_len = cap;
+    if (!BrotliEncoderCompress(
+            COMPRESSION_BROTLI_QUALITY, BROTLI_DEFAULT_WINDOW, BROTLI_MODE_TEXT, body_len, (const uint8_t *)body, out_len, (uint8_t *)out)) {
+        free(out);
+        return NULL;
+    }
+    return out;
+}
+#endif
+
+int compression_encode_response(
+    int code, const char *content_type, const char *extra_headers, const char **body, size_t *body_len, char *headers, size_t headers_len) {
+    headers[0] = '\0';
+    if (code < 200 || code == 204 || code == 304 || !compressible_type(content_type)) return 0;
+    /* Already encoded by the handler (a stored .gz, a proxied body): leave it alone. */
+    if (extra_headers && strcasestr(extra_headers, "Content-Encoding:")) return 0;
+    int n = snprintf(headers, headers_len, "Vary: Accept-Encoding\r\n");
+    if (g_accepted == CODING_IDENTITY || *body_len < COMPRESSION_MIN_BYTES || !*body) return n;
+
+    size_t encoded_len = 0;
+    char *encoded = NULL;
+    const char *name = "";
+#ifdef FRICU_HAVE_BROTLI
+    if (g_accepted == CODING_BROTLI) {
+        encoded = brotli_encode(*body, *body_len, &encoded_len);
+        name = "br";
+    }
+#endif
+#ifdef FRICU_HAVE_ZLIB
+    if (g_accepted == CODING_GZIP) {
+        encoded = gzip_encode(*body, *body_len, &encoded_len);
+        name = "gzip";
+    }
+#endif
+    if (!encoded || encoded_len >= *body_len) {
+        free(encoded);
+        return n;
+    }
+    free(g_encoded_body);
+    g_encoded_body = encoded;
+    *body = encoded;
+    *body_len = encoded_len;
+    n += snprintf(headers + n, headers_len - (size_t)n, "Content-Encoding: %s\r\n", name);
+    return n;
+}
+
+#ifdef FRICU_HAVE_ZLIB
+/* gzip when gzip is set, otherwise zlib-wrapped deflate as RFC 9110 defines "deflate". */
+static int zlib_decode(const char *in, size_t in_len, int gzip, size_t max, char **out, size_t *out_len) {
+    z_stream stream;
+    memset(&stream, 0, sizeof(stream));
+    if (inflateInit2(&stream, gzip ? 15 + 16 : 15) != Z_OK) return -1;
+    size_t cap = in_len * 4 + 1024;
+    if (cap > max) cap = max;
+    char *buf = (char *)malloc(cap + 1);
+    int rc = buf ? Z_OK : Z_MEM_ERROR;
+    stream.next_in = (Bytef *)in;
+    stream.avail_in = (uInt)in_len;
+    while (rc == Z_OK) {
+        if (stream.total_out == cap) {
+            if (cap >= max) {
+                rc = Z_BUF_ERROR;
+                break;
+            }
+            size_t next = cap * 2 > max ? max : cap * 2;
+            char *grown = (char *)realloc(buf, next + 1);
+            if (!grown) {
+                rc = Z_MEM_ERROR;
+                break;
+            }
+            buf = grown;
+            cap = next;
+        }
+        stream.next_out = (Bytef *)(buf + stream.total_out);
+        stream.avail_out = (uInt)(cap - stream.total_out);
+        rc = inflate(&stream, Z_NO_FLUSH);
+        if (rc == Z_BUF_ERROR && stream.avail_in == 0) break;
+    }
+    *out_len = stream.total_out;
+    inflateEnd(&stream);
+    if (rc != Z_STREAM_END || stream.avail_in != 0) {
+        free(buf);
+        return rc == Z_BUF_ERROR && stream.avail_in != 0 ? -2 : -1;
+    }
+    *out = buf;
+    return 0;
+}
+#endif
+
+#ifdef FRICU_HAVE_BROTLI
+static int brotli_decode(const char *in, size_t in_len, size_t max, char **out, size_t *out_len) {
+    BrotliDecoderState *state = BrotliDecoderCreateInstance(NULL, NULL, NULL);
+    if (!state) return -1;
+    size_t cap = in_len * 4 + 1024;
+    if (cap > max) cap = max;
+    char *buf = (char *)malloc(cap + 1);
+    size_t available_in = in_len;
+    const uint8_t *next_in = (const uint8_t *)in;
+    size_t total = 0;
+    int status = buf ? 0 : -1;
+    while (status == 0) {
+        size_t available_out = cap - total;
+        uint8_t *next_out = (uint8_t *)buf + total;
+        BrotliDecoderResult result = BrotliDecoderDecompressStream(state, &available_in, &next_in, &available_out, &next_out, NULL);
+        total = cap - available_out;
+        if (result == BROTLI_DECODER_RESULT_SUCCESS) break;
+        if (result != BROTLI_DECODER_RESULT_NEEDS_MORE_OUTPUT) {
+            status = -1;
+        } else if (cap >= max) {
+            status = -2;
+        } else {
+            size_t next = cap * 2 > max ? max : cap * 2;
+            char *grown = (char *)realloc(buf, next + 1);
+            if (!grown) {
+                status = -1;
+            } else {
+                buf = grown;
+                cap = next;
+            }
+        }
+    }
+    BrotliDecoderDestroyInstance(state);
+    if (status == 0 && available_in != 0) status = -1;
+    if (status != 0) {
+        free(buf);
+        return status;
+    }
+    *out = buf;
+    *out_len = total;
+    return 0;
+}
+#endif
+
+int compression_decode_request(int fd, http_request_t *req, const request_log_context_t *ctx) {
+    char coding[64] = {0};
+    if (!http_request_header(req, "Content-Encoding", coding, sizeof(coding)) || strcasecmp(coding, "identity") == 0) return 0;
+    char *decoded = NULL;
+    size_t decoded_len = 0;
+    int rc = 1;
+#ifdef FRICU_HAVE_ZLIB
+    if (strcasecmp(coding, "gzip") == 0 || strcasecmp(coding, "x-gzip") == 0 || strcasecmp(coding, "deflate") == 0) {
+        rc = zlib_decode(req->body, req->body_len, strcasecmp(coding, "deflate") != 0, (size_t)request_body_limit(req->path, NULL, 0), &decoded, &decoded_len);
+    }
+#endif
+#ifdef FRICU_HAVE_BROTLI
+    if (strcasecmp(coding, "br") == 0) rc = brotli_decode(req->body, req->body_len, (size_t)request_body_limit(req->path, NULL, 0), &decoded, &decoded_len);
+#endif
+    if (rc == 1) {
+        send_response_with_log_context(fd, 415, "Unsupported Media Type", "{\"error\":\"unsupported Content-Encoding\"}", ctx);
+        return 415;
+    }
+    if (rc == -2) return request_body_guard(fd, req->path, -1, ctx);
+    if (rc != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body does not match its Content-Encoding\"}", ctx);
+        return 400;
+    }
+    decoded[decoded_len] = '\0';
+    log_info("HTTP body decoded encoding=%s bytes=%zu decoded_bytes=%zu logid=%s", coding, req->body_len, decoded_len, ctx->log_id);
+    free(g_decoded_body);
+    g_decoded_body = decoded;
+    req->body = decoded;
+    req->body_len = decoded_len;
+    return 0;
+}
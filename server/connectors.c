@@ -0,0 +1,1103 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netdb.h>
+#include <netinet/in.h>
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <time.h>
+#include <unistd.h>
+
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/err.h>
+#include <openssl/ssl.h>
+#endif
+
+/*
+ * Outbound export connectors. Each account may register WebDAV or Dropbox targets; original
+ * activity files (sourceFileBase64) and a JSON bundle of every completed week are copied there.
+ * Work is queued in connector_jobs (one row per connector, kind and ref) and drained by a
+ * background thread, so a failing target is retried with backoff instead of blocking writes.
+ * Activity writes wake the thread through the change-event hub; POST .../sync forces a pass.
+ * With FRICU_EXPORT_PASSPHRASE set every upload is age-encrypted and gets a .age suffix.
+ * Account-supplied WebDAV targets must resolve to public addresses, both when registered and on
+ * every connect, unless FRICU_CONNECTOR_ALLOW_PRIVATE=1 (a NAS on the LAN).
+ */
+
+#define CONNECTOR_MAX_ATTEMPTS 8
+#define CONNECTOR_MAX_BACKOFF_SEC (6 * 3600)
+#define CONNECTOR_BATCH 20
+#define CONNECTOR_IDLE_SEC 60
+#define CONNECTOR_IO_TIMEOUT_SEC 30
+#define CONNECTOR_MAX_RESPONSE_BYTES (1 << 20)
+#define CONNECTOR_PENDING_ACCOUNTS 64
+#define CONNECTOR_DROPBOX_UPLOAD_URL "https://content.dropboxapi.com/2/files/upload"
+
+typedef struct {
+    int tls;
+    char host[256];
+    int port;
+    char path[1024];
+} outbound_url_t;
+
+static int parse_outbound_url(const char *url, outbound_url_t *out) {
+    memset(out, 0, sizeof(*out));
+    const char *p = NULL;
+    if (strncmp(url, "http://", 7) == 0) {
+        p = url + 7;
+        out->port = 80;
+    } else if (strncmp(url, "https://", 8) == 0) {
+        p = url + 8;
+        out->tls = 1;
+        out->port = 443;
+    } else {
+        return -1;
+    }
+    size_t host_len = strcspn(p, ":/");
+    if (host_len == 0 || host_len >= sizeof(out->host)) return -1;
+    memcpy(out->host, p, host_len);
+    p += host_len;
+    if (*p == ':') {
+        char *end = NULL;
+        long port = strtol(p + 1, &end, 10);
+        if (port <= 0 || port > 65535) return -1;
+        out->port = (int)port;
+        p = end;
+    }
+    if (*p != '\0' && *p != '/') return -1;
+    snprintf(out->path, sizeof(out->path), "%s", *p ? p : "/");
+    return 0;
+}
+
+/* Loopback, link-local, private, shared (CGNAT), multicast and unspecified addresses. */
+static int address_is_private(const struct sockaddr *sa) {
+    if (sa->sa_family == AF_INET) {
+        uint32_t a = ntohl(((const struct sockaddr_in *)sa)->sin_addr.s_addr);
+        return (a >> 24) == 0 || (a >> 24) == 10 || (a >> 24) == 127 || (a >> 16) == 0xA9FE || (a >> 20) == 0xAC1 ||
+               (a >> 16) == 0xC0A8 || (a >> 22) == 0x191 || (a >> 28) >= 0xE;
+    }
+    if (sa->sa_family != AF_INET6) return 1;
+    const struct in6_addr *a6 = &((const struct sockaddr_in6 *)sa)->sin6_addr;
+    if (IN6_IS_ADDR_V4MAPPED(a6)) {
+        struct sockaddr_in v4;
+        memset(&v4, 0, sizeof(v4));
+        v4.sin_family = AF_INET;
+        memcpy(&v4.sin_addr, a6->s6_addr + 12, 4);
+        return address_is_private((const struct sockaddr *)&v4);
+    }
+    return IN6_IS_ADDR_UNSPECIFIED(a6) || IN6_IS_ADDR_LOOPBACK(a6) || IN6_IS_ADDR_LINKLOCAL(a6) || IN6_IS_ADDR_SITELOCAL(a6) ||
+           IN6_IS_ADDR_MULTICAST(a6) || (a6->s6_addr[0] & 0xFE) == 0xFC;
+}
+
+static int connector_private_allowed(void) {
+    const char *raw = getenv("FRICU_CONNECTOR_ALLOW_PRIVATE");
+    return raw && strcmp(raw, "1") == 0;
+}
+
+/* -1 with err set when any address the host resolves to is private; 0 otherwise. */
+static int refuse_private_addresses(const struct addrinfo *res, const char *host, char *err, size_t err_len) {
+    for (const struct addrinfo *ai = res; ai; ai = ai->ai_next) {
+        if (!address_is_private(ai->ai_addr)) continue;
+        snprintf(err, err_len, "%s resolves to a private address (set FRICU_CONNECTOR_ALLOW_PRIVATE=1 to allow)", host);
+        return -1;
+    }
+    return 0;
+}
+
+/* Registration-time check; a host that does not resolve yet is left to the sync to report. */
+static int connector_target_is_private(const outbound_url_t *url) {
+    char port[16] = {0};
+    char err[sizeof(url->host) + 128] = {0};
+    snprintf(port, sizeof(port), "%d", url->port);
+    struct addrinfo hints;
+    struct addrinfo *res = NULL;
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_family = AF_UNSPEC;
+    hints.ai_socktype = SOCK_STREAM;
+    if (getaddrinfo(url->host, port, &hints, &res) != 0) return 0;
+    int refused = refuse_private_addresses(res, url->host, err, sizeof(err)) != 0;
+    freeaddrinfo(res);
+    return refused;
+}
+
+static const char *tls_unavailable(const outbound_url_t *url) {
+#ifdef FRICU_HAVE_OPENSSL
+    (void)url;
+    return NULL;
+#else
+    return url->tls ? "https targets need a server built with OpenSSL" : NULL;
+#endif
+}
+
+typedef struct {
+    int fd;
+#ifdef FRICU_HAVE_OPENSSL
+    SSL_CTX *ctx;
+    SSL *ssl;
+#endif
+} outbound_conn_t;
+
+static void outbound_close(outbound_conn_t *c) {
+#ifdef FRICU_HAVE_OPENSSL
+    if (c->ssl) {
+        SSL_shutdown(c->ssl);
+        SSL_free(c->ssl);
+    }
+    if (c->ctx) SSL_CTX_free(c->ctx);
+#endif
+    if (c->fd >= 0) close(c->fd);
+    memset(c, 0, sizeof(*c));
+    c->fd = -1;
+}
+
+/* public_only refuses hosts with a private address, checked on what connect() will actually use. */
+static int outbound_open(outbound_conn_t *c, const outbound_url_t *url, int public_only, char *err, size_t err_len) {
+    memset(c, 0, sizeof(*c));
+    c->fd = -1;
+    char port[16] = {0};
+    snprintf(port, sizeof(port), "%d", url->port);
+    struct addrinfo hints;
+    struct addrinfo *res = NULL;
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_family = AF_UNSPEC;
+    hints.ai_socktype = SOCK_STREAM;
+    if (getaddrinfo(url->host, port, &hints, &res) != 0) {
+        snprintf(err, err_len, "cannot resolve %s", url->host);
+        return -1;
+    }
+    if (public_only && refuse_private_addresses(res, url->host, err, err_len) != 0) {
+        freeaddrinfo(res);
+        return -1;
+    }
+    for (struct addrinfo *ai = res; ai && c->fd < 0; ai = ai->ai_next) {
+        int fd = socket(ai->ai_family, ai->ai_socktype, ai->ai_protocol);
+        if (fd < 0) continue;
+        struct timeval tv = {.tv_sec = CONNECTOR_IO_TIMEOUT_SEC, .tv_usec = 0};
+        setsockopt(fd, SOL_SOCKET, SO_RCVTIMEO, &tv, sizeof(tv));
+        setsockopt(fd, SOL_SOCKET, SO_SNDTIMEO, &tv, sizeof(tv));
+        if (connect(fd, ai->ai_addr, ai->ai_addrlen) == 0) {
+            c->fd = fd;
+        } else {
+            close(fd);
+        }
+    }
+    freeaddrinfo(res);
+    if (c->fd < 0) {
+        snprintf(err, err_len, "cannot connect to %s:%d", url->host, url->port);
+        return -1;
+    }
+    if (!url->tls) return 0;
+#ifdef FRICU_HAVE_OPENSSL
+    c->ctx = SSL_CTX_new(TLS_client_method());
+    if (c->ctx) {
+        SSL_CTX_set_default_verify_paths(c->ctx);
+        SSL_CTX_set_verify(c->ctx, SSL_VERIFY_PEER, NULL);
+        c->ssl = SSL_new(c->ctx);
+    }
+    if (!c->ssl || SSL_set_tlsext_host_name(c->ssl, url->host) != 1 || SSL_set1_host(c->ssl, url->host) != 1 ||
+        SSL_set_fd(c->ssl, c->fd) != 1 || SSL_connect(c->ssl) != 1) {
+        snprintf(err, err_len, "TLS handshake with %s failed", url->host);
+        outbound_close(c);
+        return -1;
+    }
+    return 0;
+#else
+    snprintf(err, err_len, "%s", tls_unavailable(url));
+    outbound_close(c);
+    return -1;
+#endif
+}
+
+static int outbound_write(outbound_conn_t *c, const void *data, size_t len) {
+    const char *p = (const char *)data;
+    while (len > 0) {
+        ssize_t n;
+#ifdef FRICU_HAVE_OPENSSL
+        if (c->ssl) {
+            n = SSL_write(c->ssl, p, len > 1 << 20 ? 1 << 20 : (int)len);
+        } else
+#endif
+        {
+            n = send(c->fd, p, len, MSG_NOSIGNAL);
+        }
+        if (n <= 0) {
+            if (n < 0 && errno == EINTR) continue;
+            return -1;
+        }
+        p += n;
+        len -= (size_t)n;
+    }
+    return 0;
+}
+
+static ssize_t outbound_read(outbound_conn_t *c, char *buf, size_t len) {
+#ifdef FRICU_HAVE_OPENSSL
+    if (c->ssl) return SSL_read(c->ssl, buf, (int)len);
+#endif
+    return recv(c->fd, buf, len, 0);
+}
+
+/* Undoes Transfer-Encoding: chunked in place; returns the decoded length. */
+static size_t dechunk(char *data, size_t len) {
+    size_t in = 0;
+    size_t out = 0;
+    while (in < len) {
+        char *end = NULL;
+        unsigned long chunk = strtoul(data + in, &end, 16);
+        const char *line_end = strstr(data + in, "\r\n");
+        if (!end || end == data + in || !line_end || chunk == 0) break;
+        in = (size_t)(line_end - data) + 2;
+        if (chunk > len - in) chunk = len - in;
+        memmove(data + out, data + in, chunk);
+        out += chunk;
+        in += chunk + 2;
+    }
+    data[out] = '\0';
+    return out;
+}
+
+/*
+ * Sends one request and returns the HTTP status, or -1 with err filled on transport failure. When
+ * response is non-NULL the body is read to the end of the connection and stored there.
+ */
+static int outbound_exchange(
+    const char *method,
+    const char *url_text,
+    const char *extra_headers,
+    const unsigned char *body,
+    size_t body_len,
+    strbuf_t *response,
+    int public_only,
+    char *err,
+    size_t err_len) {
+    outbound_url_t url;
+    if (parse_outbound_url(url_text, &url) != 0) {
+        snprintf(err, err_len, "invalid url");
+        return -1;
+    }
+    outbound_conn_t conn;
+    if (outbound_open(&conn, &url, public_only, err, err_len) != 0) return -1;
+    strbuf_t head;
+    strbuf_init(&head);
+    strbuf_appendf(
+        &head,
+        "%s %s HTTP/1.1\r\nHost: %s:%d\r\nUser-Agent: fricu-server\r\nContent-Length: %zu\r\nConnection: close\r\n%s\r\n",
+        method,
+        url.path,
+        url.host,
+        url.port,
+        body_len,
+        extra_headers ? extra_headers : "");
+    int status = -1;
+    if (head.failed || outbound_write(&conn, strbuf_cstr(&head), head.len) != 0 || (body_len > 0 && outbound_write(&conn, body, body_len) != 0)) {
+        snprintf(err, err_len, "write to %s failed", url.host);
+    } else if (!response) {
+        char reply[512] = {0};
+        size_t got = 0;
+        while (got < sizeof(reply) - 1 && !strstr(reply, "\r\n")) {
+            ssize_t n = outbound_read(&conn, reply + got, sizeof(reply) - 1 - got);
+            if (n <= 0) break;
+            got += (size_t)n;
+        }
+        if (sscanf(reply, "HTTP/%*d.%*d %d", &status) != 1) {
+            status = -1;
+            snprintf(err, err_len, "no HTTP response from %s", url.host);
+        }
+    } else {
+        strbuf_t reply;
+        strbuf_init(&reply);
+        char chunk[4096];
+        ssize_t n;
+        while (reply.len < CONNECTOR_MAX_RESPONSE_BYTES && (n = outbound_read(&conn, chunk, sizeof(chunk))) > 0) {
+            strbuf_append(&reply, chunk, (size_t)n);
+        }
+        char *text = reply.data;
+        char *head_end = reply.failed || reply.len == 0 ? NULL : strstr(text, "\r\n\r\n");
+        if (!head_end || sscanf(text, "HTTP/%*d.%*d %d", &status) != 1) {
+            status = -1;
+            snprintf(err, err_len, "no HTTP response from %s", url.host);
+        } else {
+            *head_end = '\0';
+            char *payload = head_end + 4;
+            size_t payload_len = reply.len - (size_t)(payload - text);
+            if (strcasestr(text, "\r\nTransfer-Encoding: chunked")) payload_len = dechunk(payload, payload_len);
+            strbuf_append(response, payload, payload_len);
+        }
+        strbuf_free(&reply);
+    }
+    strbuf_free(&head);
+    outbound_close(&conn);
+    return status;
+}
+
+int outbound_request(
+    const char *method,
+    const char *url_text,
+    const char *extra_headers,
+    const unsigned char *body,
+    size_t body_len,
+    char *err,
+    size_t err_len) {
+    return outbound_exchange(method, url_text, extra_headers, body, body_len, NULL, 0, err, err_len);
+}
+
+int outbound_get(const char *url_text, strbuf_t *response, char *err, size_t err_len) {
+    return outbound_exchange("GET", url_text, NULL, NULL, 0, response, 0, err, err_len);
+}
+
+/* outbound_request for a target an account supplied. */
+static int connector_request(
+    const char *method,
+    const char *url_text,
+    const char *extra_headers,
+    const unsigned char *body,
+    size_t body_len,
+    char *err,
+    size_t err_len) {
+    return outbound_exchange(method, url_text, extra_headers, body, body_len, NULL, !connector_private_allowed(), err, err_len);
+}
+
+/* Remote names keep [A-Za-z0-9._-]; anything else becomes '_'. */
+static void sanitize_remote_name(const char *in, char *out, size_t out_len) {
+    size_t o = 0;
+    for (const char *p = in; *p && o + 1 < out_len; p++) {
+        char ch = *p;
+        int keep = (ch >= 'A' && ch <= 'Z') || (ch >= 'a' && ch <= 'z') || (ch >= '0' && ch <= '9') || ch == '.' || ch == '-' || ch == '_';
+        out[o++] = keep ? ch : '_';
+    }
+    out[o] = '\0';
+}
+
+static const char *dropbox_upload_url(void) {
+    const char *env = getenv("FRICU_DROPBOX_CONTENT_URL");
+    return env && env[0] ? env : CONNECTOR_DROPBOX_UPLOAD_URL;
+}
+
+typedef struct {
+    long long job_id;
+    char connector_id[64];
+    char account_id[128];
+    char job_kind[32];
+    char ref[128];
+    int attempts;
+    char kind[16];
+    char target[1024];
+    char username[256];
+    char secret[1024];
+} connector_job_t;
+
+static int upload_webdav(const connector_job_t *job, const char *remote_path, const unsigned char *data, size_t len, const char *content_type, char *err, size_t err_len) {
+    char auth[1024] = {0};
+    if (job->username[0] != '\0') {
+        char credentials[1300] = {0};
+        char encoded[1800] = {0};
+        snprintf(credentials, sizeof(credentials), "%s:%s", job->username, job->secret);
+        base64_encode((const unsigned char *)credentials, strlen(credentials), encoded, sizeof(encoded));
+        snprintf(auth, sizeof(auth), "Authorization: Basic %s\r\n", encoded);
+    }
+    char url[2048] = {0};
+    const char *slash = strrchr(remote_path, '/');
+    if (slash) {
+        /* Collections may already exist (405) or redirect to their slash form; the PUT decides. */
+        snprintf(url, sizeof(url), "%s/%.*s/", job->target, (int)(slash - remote_path), remote_path);
+        connector_request("MKCOL", url, auth, NULL, 0, err, err_len);
+    }
+    char headers[1200] = {0};
+    snprintf(headers, sizeof(headers), "%sContent-Type: %s\r\n", auth, content_type);
+    snprintf(url, sizeof(url), "%s/%s", job->target, remote_path);
+    int status = connector_request("PUT", url, headers, data, len, err, err_len);
+    if (status >= 200 && status < 300) return 0;
+    if (status > 0) snprintf(err, err_len, "WebDAV PUT returned HTTP %d", status);
+    return -1;
+}
+
+static int upload_dropbox(const connector_job_t *job, const char *remote_path, const unsigned char *data, size_t len, char *err, size_t err_len) {
+    char headers[2048] = {0};
+    int n = snprintf(
+        headers,
+        sizeof(headers),
+        "Authorization: Bearer %s\r\nDropbox-API-Arg: {\"path\":\"%s/%s\",\"mode\":\"overwrite\",\"mute\":true}\r\nContent-Type: application/octet-stream\r\n",
+        job->secret,
+        job->target,
+        remote_path);
+    if (n < 0 || (size_t)n >= sizeof(headers)) {
+        snprintf(err, err_len, "Dropbox request headers too long");
+        return -1;
+    }
+    int status = outbound_request("POST", dropbox_upload_url(), headers, data, len, err, err_len);
+    if (status == 200) return 0;
+    if (status > 0) snprintf(err, err_len, "Dropbox upload returned HTTP %d", status);
+    return -1;
+}
+
+/* ?1 account id, ?2 first day, ?3 last day of the week, ?4 data minimization, ?5 rollover hour. Embedded files stay out of the
+ * bundle; activities count on their training day. */
+#define CONNECTOR_WEEK_ITEMS_SQL(key, day, transform)                                                           \
+    "(SELECT json_group_array(" transform ") FROM kv_store k, json_each(k.data_value) a"                          \
+    " WHERE k.data_key = ?1 || '::" key "' AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"   \
+    " AND " day " BETWEEN ?2 AND ?3)"
+
+static const char *CONNECTOR_WEEKLY_BUNDLE_SQL =
+    "SELECT json_object('format', 'fricu-weekly-v1', 'week_start', ?2, 'week_end', ?3,"
+    " 'activities', " CONNECTOR_WEEK_ITEMS_SQL(
+        "activities",
+        TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?5"),
+        "json(CASE WHEN ?4 THEN json_remove(a.value, '$.bikeComputerScreenshotBase64', " DATA_MINIMIZED_PATHS_SQL ")"
+        " ELSE json_remove(a.value, '$.sourceFileBase64', '$.bikeComputerScreenshotBase64') END)") ","
+    " 'workouts', " CONNECTOR_WEEK_ITEMS_SQL("workouts", "substr(json_extract(a.value, '$.scheduledDate'), 1, 10)", "json(a.value)") ","
+    " 'events', " CONNECTOR_WEEK_ITEMS_SQL("events", "substr(json_extract(a.value, '$.startDate'), 1, 10)", "json(a.value)") ","
+    " 'wellness', " CONNECTOR_WEEK_ITEMS_SQL("wellness_samples", "substr(json_extract(a.value, '$.date'), 1, 10)", "json(a.value)") ")";
+
+/* Builds the upload for a job; returns 1 with data, 0 when there is nothing left to copy, -1 on error. */
+static int build_job_payload(sqlite3 *db, const connector_job_t *job, unsigned char **out, size_t *out_len, char *remote_path, size_t remote_len, const char **content_type) {
+    sqlite3_stmt *stmt = NULL;
+    if (strcmp(job->job_kind, "weekly_bundle") == 0) {
+        int week_start = 0;
+        if (parse_iso_day(job->ref, &week_start) != 0) return 0;
+        char week_end[16] = {0};
+        format_iso_day(week_start + 6, week_end, sizeof(week_end));
+        training_week_t week;
+        load_training_week(db, job->account_id, &week);
+        if (sqlite3_prepare_v2(db, CONNECTOR_WEEKLY_BUNDLE_SQL, -1, &stmt, NULL) != SQLITE_OK) return -1;
+        sqlite3_bind_text(stmt, 1, job->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, job->ref, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, week_end, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 4, data_minimization_enabled(db, job->account_id));
+        sqlite3_bind_int(stmt, 5, week.rollover_hour);
+        int found = -1;
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+            *out = (unsigned char *)strdup((const char *)sqlite3_column_text(stmt, 0));
+            *out_len = *out ? strlen((const char *)*out) : 0;
+            found = *out ? 1 : -1;
+        }
+        sqlite3_finalize(stmt);
+        snprintf(remote_path, remote_len, "weekly/fricu-week-%s.json", job->ref);
+        *content_type = "application/json";
+        return found;
+    }
+
+    /* Original files carry the GPS track; under data minimization there is nothing left to copy. */
+    if (data_minimization_enabled(db, job->account_id)) return 0;
+    const char *sql =
+        "SELECT json_extract(a.value, '$.sourceFileBase64'), COALESCE(json_extract(a.value, '$.sourceFileName'), ''),"
+        " COALESCE(json_extract(a.value, '$.sourceFileType'), 'bin'), substr(json_extract(a.value, '$.date'), 1, 10)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 || '::activities' AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.id') = ?2 LIMIT 1";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, job->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, job->ref, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        const char *encoded = (const char *)sqlite3_column_text(stmt, 0);
+        *out = base64_decode(encoded, strlen(encoded), out_len);
+        found = *out && *out_len > 0 ? 1 : 0;
+        char name[256] = {0};
+        const char *file_name = (const char *)sqlite3_column_text(stmt, 1);
+        if (file_name[0] != '\0') {
+            sanitize_remote_name(file_name, name, sizeof(name));
+        } else {
+            char fallback[256] = {0};
+            snprintf(fallback, sizeof(fallback), "%s.%s", job->ref, (const char *)sqlite3_column_text(stmt, 2));
+            sanitize_remote_name(fallback, name, sizeof(name));
+        }
+        const char *day = (const char *)sqlite3_column_text(stmt, 3);
+        snprintf(remote_path, remote_len, "activities/%s-%s", day ? day : "undated", name);
+    }
+    sqlite3_finalize(stmt);
+    *content_type = "application/octet-stream";
+    return found;
+}
+
+static void finish_job(sqlite3 *db, const connector_job_t *job, const char *state, const char *remote_path, const char *error) {
+    long long now = (long long)time(NULL);
+    int attempts = job->attempts + 1;
+    long long backoff = 60LL << (attempts < 10 ? attempts - 1 : 9);
+    if (backoff > CONNECTOR_MAX_BACKOFF_SEC) backoff = CONNECTOR_MAX_BACKOFF_SEC;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "UPDATE connector_jobs SET state = ?2, attempts = ?3, next_attempt_at = ?4, remote_path = COALESCE(?5, remote_path),"
+            " last_error = ?6, updated_at = ?7 WHERE id = ?1",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_int64(stmt, 1, job->job_id);
+        sqlite3_bind_text(stmt, 2, state, -1, SQLITE_STATIC);
+        sqlite3_bind_int(stmt, 3, attempts);
+        sqlite3_bind_int64(stmt, 4, now + backoff);
+        if (remote_path) sqlite3_bind_text(stmt, 5, remote_path, -1, SQLITE_TRANSIENT);
+        if (error) sqlite3_bind_text(stmt, 6, error, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 7, now);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+    }
+    if (sqlite3_prepare_v2(
+            db,
+            "UPDATE export_connectors SET last_attempt_at = ?2, last_success_at = CASE WHEN ?3 IS NULL THEN ?2 ELSE last_success_at END,"
+            " last_error = ?3 WHERE id = ?1",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, job->connector_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 2, now);
+        if (error) sqlite3_bind_text(stmt, 3, error, -1, SQLITE_TRANSIENT);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+    }
+}
+
+int connectors_run_due(sqlite3 *db, int limit) {
+    connector_job_t *jobs = calloc((size_t)(limit > 0 ? limit : 1), sizeof(connector_job_t));
+    if (!jobs) return -1;
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT j.id, j.connector_id, j.account_id, j.kind, j.ref, j.attempts, c.kind, c.target, c.username, c.secret"
+        " FROM connector_jobs j JOIN export_connectors c ON c.id = j.connector_id"
+        " WHERE j.state = 'pending' AND j.next_attempt_at <= strftime('%s', 'now') ORDER BY j.next_attempt_at, j.id LIMIT ?1";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        free(jobs);
+        return -1;
+    }
+    sqlite3_bind_int(stmt, 1, limit);
+    int count = 0;
+    while (count < limit && sqlite3_step(stmt) == SQLITE_ROW) {
+        connector_job_t *job = &jobs[count++];
+        job->job_id = sqlite3_column_int64(stmt, 0);
+        snprintf(job->connector_id, sizeof(job->connector_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(job->account_id, sizeof(job->account_id), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        snprintf(job->job_kind, sizeof(job->job_kind), "%s", (const char *)sqlite3_column_text(stmt, 3));
+        snprintf(job->ref, sizeof(job->ref), "%s", (const char *)sqlite3_column_text(stmt, 4));
+        job->attempts = sqlite3_column_int(stmt, 5);
+        snprintf(job->kind, sizeof(job->kind), "%s", (const char *)sqlite3_column_text(stmt, 6));
+        snprintf(job->target, sizeof(job->target), "%s", (const char *)sqlite3_column_text(stmt, 7));
+        snprintf(job->username, sizeof(job->username), "%s", (const char *)sqlite3_column_text(stmt, 8));
+        snprintf(job->secret, sizeof(job->secret), "%s", (const char *)sqlite3_column_text(stmt, 9));
+    }
+    sqlite3_finalize(stmt);
+
+    for (int i = 0; i < count; i++) {
+        const connector_job_t *job = &jobs[i];
+        unsigned char *data = NULL;
+        size_t len = 0;
+        char remote_path[512] = {0};
+        const char *content_type = NULL;
+        char err[256] = {0};
+        int built = build_job_payload(db, job, &data, &len, remote_path, sizeof(remote_path), &content_type);
+        if (built == 0) {
+            finish_job(db, job, "skipped", NULL, NULL);
+            free(data);
+            continue;
+        }
+        /* Off-site copies are sealed when a passphrase is configured; never fall back to plaintext. */
+        if (built > 0 && export_passphrase()) {
+            unsigned char *sealed = NULL;
+            size_t sealed_len = 0;
+            built = export_encrypt(data, len, &sealed, &sealed_len) == 0 ? 1 : -2;
+            free(data);
+            data = sealed;
+            len = sealed_len;
+            size_t path_len = strlen(remote_path);
+            snprintf(remote_path + path_len, sizeof(remote_path) - path_len, ".age");
+            content_type = "application/octet-stream";
+        }
+        int rc = -1;
+        if (built == -2) {
+            snprintf(err, sizeof(err), "could not encrypt upload%s", export_encryption_available() ? "" : " (server built without OpenSSL)");
+        } else if (built < 0) {
+            snprintf(err, sizeof(err), "could not build upload");
+        } else if (strcmp(job->kind, "dropbox") == 0) {
+            rc = upload_dropbox(job, remote_path, data, len, err, sizeof(err));
+        } else {
+            rc = upload_webdav(job, remote_path, data, len, content_type, err, sizeof(err));
+        }
+        free(data);
+        if (rc == 0) {
+            finish_job(db, job, "done", remote_path, NULL);
+            log_info("CONNECTOR upload connector=%s kind=%s path=%s bytes=%zu", job->connector_id, job->kind, remote_path, len);
+        } else {
+            const char *state = job->attempts + 1 >= CONNECTOR_MAX_ATTEMPTS ? "failed" : "pending";
+            finish_job(db, job, state, NULL, err);
+            log_warn("CONNECTOR upload failed connector=%s job=%lld attempt=%d state=%s: %s", job->connector_id, job->job_id, job->attempts + 1, state, err);
+        }
+    }
+    free(jobs);
+    return count;
+}
+
+/* Queues missing activity files and last week's bundle; returns the number of new jobs. */
+int connectors_enqueue(sqlite3 *db, const char *account_id, const char *connector_id) {
+    training_week_t week;
+    load_training_week(db, account_id, &week);
+    char week_start[16] = {0};
+    format_iso_day(week_start_on(training_today(&week), week.start_weekday) - 7, week_start, sizeof(week_start));
+    const char *sql[] = {
+        "INSERT OR IGNORE INTO connector_jobs (connector_id, account_id, kind, ref, next_attempt_at, created_at, updated_at)"
+        " SELECT c.id, c.account_id, 'activity_file', json_extract(a.value, '$.id'), strftime('%s', 'now'), strftime('%s', 'now'), strftime('%s', 'now')"
+        " FROM export_connectors c, kv_store k, json_each(k.data_value) a"
+        " WHERE c.account_id = ?1 AND (?2 IS NULL OR c.id = ?2) AND k.data_key = c.account_id || '::activities'"
+        " AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND a.type = 'object'"
+        " AND json_extract(a.value, '$.id') IS NOT NULL AND COALESCE(json_extract(a.value, '$.sourceFileBase64'), '') <> ''",
+        "INSERT OR IGNORE INTO connector_jobs (connector_id, account_id, kind, ref, next_attempt_at, created_at, updated_at)"
+        " SELECT c.id, c.account_id, 'weekly_bundle', ?3, strftime('%s', 'now'), strftime('%s', 'now'), strftime('%s', 'now')"
+        " FROM export_connectors c WHERE c.account_id = ?1 AND (?2 IS NULL OR c.id = ?2)",
+    };
+    int inserted = 0;
+    for (size_t i = 0; i < sizeof(sql) / sizeof(sql[0]); i++) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db, sql[i], -1, &stmt, NULL) != SQLITE_OK) return -1;
+        sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+        if (connector_id) sqlite3_bind_text(stmt, 2, connector_id, -1, SQLITE_TRANSIENT);
+        if (i == 1) sqlite3_bind_text(stmt, 3, week_start, -1, SQLITE_TRANSIENT);
+        int rc = sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+        if (rc != SQLITE_DONE) return -1;
+        inserted += sqlite3_changes(db);
+    }
+    return inserted;
+}
+
+static struct {
+    pthread_mutex_t mutex;
+    pthread_cond_t cond;
+    int running;
+    int woken;
+    int scan_all;
+    char accounts[CONNECTOR_PENDING_ACCOUNTS][128];
+    size_t account_count;
+} g_connectors = {.mutex = PTHREAD_MUTEX_INITIALIZER, .cond = PTHREAD_COND_INITIALIZER};
+
+static void connectors_wake(const char *account_id) {
+    pthread_mutex_lock(&g_connectors.mutex);
+    if (account_id) {
+        size_t i = 0;
+        while (i < g_connectors.account_count && strcmp(g_connectors.accounts[i], account_id) != 0) i++;
+        if (i == g_connectors.account_count) {
+            if (i < CONNECTOR_PENDING_ACCOUNTS) {
+                snprintf(g_connectors.accounts[i], sizeof(g_connectors.accounts[i]), "%s", account_id);
+                g_connectors.account_count++;
+            } else {
+                g_connectors.scan_all = 1;
+            }
+        }
+    }
+    g_connectors.woken = 1;
+    pthread_cond_signal(&g_connectors.cond);
+    pthread_mutex_unlock(&g_connectors.mutex);
+}
+
+static void on_change_event(const change_event_t *event, void *user) {
+    (void)user;
+    if (strcmp(event->key, "activities") == 0) connectors_wake(event->account_id);
+}
+
+static void enqueue_all_accounts(sqlite3 *db) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT DISTINCT account_id FROM export_connectors", -1, &stmt, NULL) != SQLITE_OK) return;
+    while (sqlite3_step(stmt) == SQLITE_ROW) connectors_enqueue(db, (const char *)sqlite3_column_text(stmt, 0), NULL);
+    sqlite3_finalize(stmt);
+}
+
+static void *connector_thread_entry(void *arg) {
+    char *db_path = (char *)arg;
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("connector thread failed to open db: %s", db ? sqlite3_errmsg(db) : "unknown");
+        if (db) sqlite3_close(db);
+        free(db_path);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=1000;", NULL, NULL, NULL);
+    int last_day = -1;
+    for (;;) {
+        char accounts[CONNECTOR_PENDING_ACCOUNTS][128];
+        pthread_mutex_lock(&g_connectors.mutex);
+        if (!g_connectors.woken) {
+            struct timespec deadline;
+            clock_gettime(CLOCK_REALTIME, &deadline);
+            deadline.tv_sec += CONNECTOR_IDLE_SEC;
+            pthread_cond_timedwait(&g_connectors.cond, &g_connectors.mutex, &deadline);
+        }
+        size_t account_count = g_connectors.account_count;
+        memcpy(accounts, g_connectors.accounts, sizeof(accounts));
+        int scan_all = g_connectors.scan_all;
+        g_connectors.account_count = 0;
+        g_connectors.scan_all = 0;
+        g_connectors.woken = 0;
+        pthread_mutex_unlock(&g_connectors.mutex);
+
+        if (!cluster_is_writer()) continue;
+        /* A new day may close a week, so every connector gets a chance to queue its bundle. */
+        if (scan_all || today_day() != last_day) {
+            enqueue_all_accounts(db);
+            last_day = today_day();
+        } else {
+            for (size_t i = 0; i < account_count; i++) connectors_enqueue(db, accounts[i], NULL);
+        }
+        while (connectors_run_due(db, CONNECTOR_BATCH) == CONNECTOR_BATCH) {
+        }
+    }
+    return NULL;
+}
+
+int connectors_start(const char *db_path) {
+    const char *enabled = getenv("FRICU_CONNECTORS");
+    if (enabled && strcmp(enabled, "0") == 0) return 0;
+    char *path_copy = strdup(db_path);
+    pthread_t thread;
+    if (!path_copy || pthread_create(&thread, NULL, connector_thread_entry, path_copy) != 0) {
+        free(path_copy);
+        log_error("failed to start connector thread");
+        return -1;
+    }
+    pthread_detach(thread);
+    change_events_subscribe(on_change_event, NULL);
+    pthread_mutex_lock(&g_connectors.mutex);
+    g_connectors.running = 1;
+    pthread_mutex_unlock(&g_connectors.mutex);
+    return 0;
+}
+
+static void append_nullable_int(strbuf_t *sb, sqlite3_stmt *stmt, int col) {
+    if (sqlite3_column_type(stmt, col) == SQLITE_NULL) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_appendf(sb, "%lld", sqlite3_column_int64(stmt, col));
+    }
+}
+
+static void append_nullable_text(strbuf_t *sb, sqlite3_stmt *stmt, int col) {
+    if (sqlite3_column_type(stmt, col) == SQLITE_NULL) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, col));
+    }
+}
+
+/* Appends a connector object without its closing brace; secrets are never echoed. */
+static void append_connector_json(sqlite3 *db, strbuf_t *sb, sqlite3_stmt *stmt) {
+    /* Columns: id, kind, target, username, created_at, last_attempt_at, last_success_at, last_error */
+    const char *id = (const char *)sqlite3_column_text(stmt, 0);
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, id);
+    strbuf_append(sb, ",\"kind\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 1));
+    strbuf_append(sb, ",\"target\":", 10);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 2));
+    strbuf_append(sb, ",\"username\":", 12);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 3));
+    strbuf_appendf(sb, ",\"created_at\":%lld,\"last_attempt_at\":", sqlite3_column_int64(stmt, 4));
+    append_nullable_int(sb, stmt, 5);
+    strbuf_append(sb, ",\"last_success_at\":", 19);
+    append_nullable_int(sb, stmt, 6);
+    strbuf_append(sb, ",\"last_error\":", 14);
+    append_nullable_text(sb, stmt, 7);
+    sqlite3_stmt *counts = NULL;
+    strbuf_append(sb, ",\"jobs\":{", 9);
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT SUM(state = 'pending'), SUM(state = 'done'), SUM(state = 'failed'), SUM(state = 'skipped') FROM connector_jobs WHERE connector_id = ?1",
+            -1,
+            &counts,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(counts, 1, id, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(counts) == SQLITE_ROW) {
+            strbuf_appendf(
+                sb,
+                "\"pending\":%d,\"done\":%d,\"failed\":%d,\"skipped\":%d",
+                sqlite3_column_int(counts, 0),
+                sqlite3_column_int(counts, 1),
+                sqlite3_column_int(counts, 2),
+                sqlite3_column_int(counts, 3));
+        }
+        sqlite3_finalize(counts);
+    }
+    strbuf_append(sb, "}", 1);
+}
+
+#define CONNECTOR_COLUMNS "id, kind, target, username, created_at, last_attempt_at, last_success_at, last_error"
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+static int handle_list_connectors(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT " CONNECTOR_COLUMNS " FROM export_connectors WHERE account_id = ?1 ORDER BY created_at, id", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"connectors\":[", 15);
+    int n = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (n++ > 0) strbuf_append(&sb, ",", 1);
+        append_connector_json(db->db, &sb, stmt);
+        strbuf_append(&sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_get_connector(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT " CONNECTOR_COLUMNS " FROM export_connectors WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown connector\"}", ctx);
+        return 404;
+    }
+    append_connector_json(db->db, &sb, stmt);
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, ",\"recent_jobs\":[", 16);
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT kind, ref, state, attempts, next_attempt_at, remote_path, last_error, updated_at FROM connector_jobs"
+            " WHERE connector_id = ?1 ORDER BY updated_at DESC, id DESC LIMIT 20",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+        int n = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (n++ > 0) strbuf_append(&sb, ",", 1);
+            strbuf_append(&sb, "{\"kind\":", 8);
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+            strbuf_append(&sb, ",\"ref\":", 7);
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+            strbuf_append(&sb, ",\"state\":", 9);
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 2));
+            strbuf_appendf(&sb, ",\"attempts\":%d,\"next_attempt_at\":%lld,\"remote_path\":", sqlite3_column_int(stmt, 3), sqlite3_column_int64(stmt, 4));
+            append_nullable_text(&sb, stmt, 5);
+            strbuf_append(&sb, ",\"last_error\":", 14);
+            append_nullable_text(&sb, stmt, 6);
+            strbuf_appendf(&sb, ",\"updated_at\":%lld}", sqlite3_column_int64(stmt, 7));
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_post_connector(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object', COALESCE(json_extract(?1, '$.kind'), ''),"
+        " COALESCE(json_extract(?1, '$.url'), json_extract(?1, '$.folder'), ''), COALESCE(json_extract(?1, '$.username'), ''),"
+        " COALESCE(json_extract(?1, '$.password'), json_extract(?1, '$.access_token'), '')";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    const char *error = "{\"error\":\"body must be a JSON object\"}";
+    char kind[16] = {0};
+    char target[1024] = {0};
+    char username[256] = {0};
+    char secret[1024] = {0};
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+        snprintf(kind, sizeof(kind), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(target, sizeof(target), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        snprintf(username, sizeof(username), "%s", (const char *)sqlite3_column_text(stmt, 3));
+        snprintf(secret, sizeof(secret), "%s", (const char *)sqlite3_column_text(stmt, 4));
+        error = NULL;
+    }
+    sqlite3_finalize(stmt);
+
+    size_t target_len = strlen(target);
+    while (target_len > 0 && target[target_len - 1] == '/') target[--target_len] = '\0';
+    outbound_url_t url;
+    if (error) {
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+    if (strcmp(kind, "webdav") == 0) {
+        if (parse_outbound_url(target, &url) != 0) {
+            error = "{\"error\":\"webdav connectors need an http:// or https:// url\"}";
+        } else if (tls_unavailable(&url)) {
+            error = "{\"error\":\"https targets need a server built with OpenSSL\"}";
+        } else if (!connector_private_allowed() && connector_target_is_private(&url)) {
+            error = "{\"error\":\"webdav url resolves to a private address\"}";
+        }
+    } else if (strcmp(kind, "dropbox") == 0) {
+        if (secret[0] == '\0') {
+            error = "{\"error\":\"dropbox connectors need an access_token\"}";
+        } else if (target[0] != '/' || strchr(target, '"') || strchr(target, '\\')) {
+            error = "{\"error\":\"dropbox folder must be an absolute path such as /Fricu\"}";
+        } else if (parse_outbound_url(dropbox_upload_url(), &url) != 0 || tls_unavailable(&url)) {
+            error = "{\"error\":\"dropbox uploads need a server built with OpenSSL\"}";
+        }
+    } else {
+        error = "{\"error\":\"kind must be webdav or dropbox\"}";
+    }
+    if (error) {
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+
+    char id[40] = {0};
+    if (generate_uuid_v4(id, sizeof(id)) != 0 ||
+        sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO export_connectors (id, account_id, kind, target, username, secret, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, kind, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, target, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 5, username, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 6, secret, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    int queued = connectors_enqueue(db->db, ctx->account_id, id);
+    connectors_wake(NULL);
+    log_info("CONNECTOR created id=%s kind=%s queued=%d account=%s logid=%s", id, kind, queued, ctx->account_id, ctx->log_id);
+    char body[128] = {0};
+    snprintf(body, sizeof(body), "{\"id\":\"%s\",\"queued\":%d}", id, queued > 0 ? queued : 0);
+    send_response_with_log_context(fd, 201, "Created", body, ctx);
+    return 201;
+}
+
+static int connector_exists(sqlite3 *db, const char *account_id, const char *id) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM export_connectors WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    int found = sqlite3_step(stmt) == SQLITE_ROW;
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static int handle_delete_connector(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    const char *sql[] = {
+        "DELETE FROM connector_jobs WHERE connector_id = ?2 AND EXISTS (SELECT 1 FROM export_connectors WHERE id = ?2 AND account_id = ?1)",
+        "DELETE FROM export_connectors WHERE account_id = ?1 AND id = ?2",
+    };
+    int removed = 0;
+    for (size_t i = 0; i < 2; i++) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, sql[i], -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+        removed = sqlite3_changes(db->db);
+    }
+    if (removed == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown connector\"}", ctx);
+        return 404;
+    }
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+static int handle_sync_connector(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    int exists = connector_exists(db->db, ctx->account_id, id);
+    if (exists < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (exists == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown connector\"}", ctx);
+        return 404;
+    }
+    int queued = connectors_enqueue(db->db, ctx->account_id, id);
+    sqlite3_stmt *stmt = NULL;
+    int retried = 0;
+    /* Sync now also gives failed and backing-off jobs a fresh attempt budget right away. */
+    if (queued >= 0 &&
+        sqlite3_prepare_v2(
+            db->db,
+            "UPDATE connector_jobs SET state = 'pending', attempts = CASE WHEN state = 'failed' THEN 0 ELSE attempts END,"
+            " next_attempt_at = strftime('%s', 'now'), updated_at = strftime('%s', 'now')"
+            " WHERE connector_id = ?1 AND (state = 'failed' OR (state = 'pending' AND next_attempt_at > strftime('%s', 'now')))",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, id, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_DONE) retried = sqlite3_changes(db->db);
+        sqlite3_finalize(stmt);
+    }
+    if (queued < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    connectors_wake(NULL);
+    pthread_mutex_lock(&g_connectors.mutex);
+    int running = g_connectors.running;
+    pthread_mutex_unlock(&g_connectors.mutex);
+    char body[128] = {0};
+    snprintf(body, sizeof(body), "{\"queued\":%d,\"retried\":%d,\"worker\":%s}", queued, retried, running ? "true" : "false");
+    send_response_with_log_context(fd, 202, "Accepted", body, ctx);
+    return 202;
+}
+
+int route_connectors(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    if (strcmp(req->path, "/v1/connectors") == 0) {
+        if (strcmp(method, "GET") == 0) return handle_list_connectors(fd, db, ctx);
+        if (strcmp(method, "POST") == 0) return handle_post_connector(fd, db, req, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char id[64] = {0};
+    const char *rest = req->path + strlen("/v1/connectors/");
+    size_t id_len = strcspn(rest, "/");
+    if (id_len == 0 || id_len >= sizeof(id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown connector\"}", ctx);
+        return 404;
+    }
+    memcpy(id, rest, id_len);
+    const char *suffix = rest + id_len;
+    if (strcmp(suffix, "/sync") == 0) {
+        if (strcmp(method, "POST") == 0) return handle_sync_connector(fd, db, id, ctx);
+    } else if (suffix[0] == '\0') {
+        if (strcmp(method, "GET") == 0) return handle_get_connector(fd, db, id, ctx);
+        if (strcmp(method, "DELETE") == 0) return handle_delete_connector(fd, db, id, ctx);
+    } else {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
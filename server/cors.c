@@ -0,0 +1,126 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+/*
+ * CORS for browser clients of the /v1/ and /v2/ routes (not /v1/admin/..., whose token should not
+ * live in a web page). FRICU_CORS_ORIGINS lists the allowed origins, comma-separated, or "*"; unset
+ * turns CORS off, so browsers keep refusing cross-origin reads as before. A request whose Origin is
+ * allowed gets Access-Control-Allow-Origin (the origin itself plus Vary: Origin unless "*") and
+ * Access-Control-Expose-Headers for the server's own response headers, errors included. A
+ * preflight (OPTIONS with Access-Control-Request-Method) is answered before authentication: 204
+ * with the allowed methods (FRICU_CORS_METHODS) and request headers (FRICU_CORS_HEADERS), or 403
+ * for an origin or method that is not allowed. FRICU_CORS_CREDENTIALS=1 adds
+ * Access-Control-Allow-Credentials; "*" then echoes the origin, as the spec requires.
+ */
+
+#define CORS_DEFAULT_METHODS "GET, HEAD, POST, PUT, PATCH, DELETE"
+#define CORS_DEFAULT_HEADERS                                                                                          \
+    "Authorization, Content-Type, Content-Encoding, X-Account-Id, X-Coach-Token, X-Device-Id, X-Fricu-Base-Version, " \
+    "X-Fricu-Sync-Protocol, X-Fricu-Debug-Timing, If-Match, If-None-Match, If-Modified-Since, If-Unmodified-Since, "  \
+    "Last-Event-ID, traceparent, X-Request-Id, X-Log-Id"
+#define CORS_EXPOSE_HEADERS                                                                                              \
+    "X-Log-Id, X-Fricu-Version, ETag, X-Fricu-Warnings, X-Fricu-Total-Items, X-Fricu-Quarantined, X-Snapshot-Seq, " \
+    "Retry-After, Deprecation, Sunset, Link, Server-Timing, X-Request-Id"
+#define CORS_MAX_AGE_SEC 600
+
+static __thread int g_allowed;
+static __thread char g_origin[256];
+
+static int cors_in_scope(const char *path) {
+    return (strncmp(path, "/v1/", 4) == 0 || strncmp(path, "/v2/", 4) == 0) && strncmp(path, "/v1/admin/", 10) != 0;
+}
+
+static const char *cors_setting(const char *name, const char *fallback) {
+    const char *value = getenv(name);
+    return value && value[0] != '\0' ? value : fallback;
+}
+
+/* Case-insensitive membership in a comma-separated list; "*" in the list matches anything. */
+static int list_contains(const char *list, const char *item, int trailing_slash) {
+    size_t item_len = strlen(item);
+    const char *p = list;
+    while (*p) {
+        p += strspn(p, " \t,");
+        size_t len = strcspn(p, ",");
+        while (len > 0 && (p[len - 1] == ' ' || p[len - 1] == '\t')) len--;
+        if (trailing_slash && len > 1 && p[len - 1] == '/') len--;
+        if ((len == 1 && *p == '*') || (len == item_len && len > 0 && strncasecmp(p, item, len) == 0)) return 1;
+        p += strcspn(p, ",");
+    }
+    return 0;
+}
+
+static int cors_credentials(void) {
+    const char *value = getenv("FRICU_CORS_CREDENTIALS");
+    return value && strcmp(value, "1") == 0;
+}
+
+void cors_begin(const http_request_t *req) {
+    g_allowed = 0;
+    g_origin[0] = '\0';
+    const char *origins = getenv("FRICU_CORS_ORIGINS");
+    if (!origins || origins[0] == '\0' || !cors_in_scope(req->path)) return;
+    if (!http_request_header(req, "Origin", g_origin, sizeof(g_origin)) || g_origin[0] == '\0') return;
+    g_allowed = list_contains(origins, g_origin, 1);
+}
+
+void cors_end(void) {
+    g_allowed = 0;
+    g_origin[0] = '\0';
+}
+
+int cors_headers(char *out, size_t out_len) {
+    out[0] = '\0';
+    if (!g_allowed) return 0;
+    int credentials = cors_credentials();
+    int n = 0;
+    if (strcmp(cors_setting("FRICU_CORS_ORIGINS", ""), "*") == 0 && !credentials) {
+        n = snprintf(out, out_len, "Access-Control-Allow-Origin: *\r\n");
+    } else {
+        n = snprintf(out, out_len, "Access-Control-Allow-Origin: %s\r\nVary: Origin\r\n", g_origin);
+    }
+    if (credentials && n > 0 && (size_t)n < out_len) n += snprintf(out + n, out_len - (size_t)n, "Access-Control-Allow-Credentials: true\r\n");
+    if (n > 0 && (size_t)n < out_len) n += snprintf(out + n, out_len - (size_t)n, "Access-Control-Expose-Headers: " CORS_EXPOSE_HEADERS "\r\n");
+    if (n <= 0 || (size_t)n >= out_len) {
+        out[0] = '\0';
+        return 0;
+    }
+    return n;
+}
+
+int cors_preflight(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    char requested[32] = {0};
+    const char *origins = getenv("FRICU_CORS_ORIGINS");
+    if (strcmp(req->method, "OPTIONS") != 0 || !origins || origins[0] == '\0' || !cors_in_scope(req->path) ||
+        !http_request_header(req, "Access-Control-Request-Method", requested, sizeof(requested))) {
+        return 0;
+    }
+    if (!g_allowed) {
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"origin not allowed\"}", ctx);
+        log_warn("CORS preflight refused origin=%s path=%s logid=%s", g_origin[0] ? g_origin : "-", req->path, ctx->log_id);
+        return 403;
+    }
+    const char *methods = cors_setting("FRICU_CORS_METHODS", CORS_DEFAULT_METHODS);
+    if (!list_contains(methods, requested, 0)) {
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"method not allowed for cross-origin requests\"}", ctx);
+        return 403;
+    }
+    char headers[1024] = {0};
+    snprintf(
+        headers,
+        sizeof(headers),
+        "Access-Control-Allow-Methods: %s\r\nAccess-Control-Allow-Headers: %s\r\nAccess-Control-Max-Age: %d\r\n",
+        methods,
+        cors_setting("FRICU_CORS_HEADERS", CORS_DEFAULT_HEADERS),
+        CORS_MAX_AGE_SEC);
+    send_http_response(fd, 204, "No Content", "application/json", headers, NULL, 0, ctx);
+    return 204;
+}
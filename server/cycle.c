@@ -0,0 +1,357 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Optional menstrual cycle tracking: one entry per account and day with the flow, an explicit
+ * period-start marker, symptoms and a note. A period starts on a marked day or on the first
+ * bleeding day after at least ten without one. The phase of any day follows from the latest start
+ * and the athlete's own averages (cycle length from up to six recent cycles, default 28 days;
+ * period length from the bleeding days after each start, default 5), with ovulation put 14 days
+ * before the next expected start. Days past the expected next start are projected forward and
+ * flagged "predicted"; tracking lapses once the latest start is two cycle lengths old.
+ */
+
+#define CYCLE_LIST_MAX_DAYS 366
+#define CYCLE_NOTES_MAX 1000
+#define CYCLE_SYMPTOMS_MAX 16
+#define CYCLE_SYMPTOM_MAX_LEN 32
+#define CYCLE_HISTORY 6
+#define CYCLE_DEFAULT_LENGTH 28
+#define CYCLE_DEFAULT_PERIOD 5
+#define CYCLE_MIN_LENGTH 21
+#define CYCLE_MAX_LENGTH 45
+#define CYCLE_RESTART_GAP 10
+#define CYCLE_LUTEAL_DAYS 14
+
+static int parse_day_strict(const char *text, int *out_day) {
+    return strlen(text) == 10 && parse_iso_day(text, out_day) == 0 ? 0 : -1;
+}
+
+int cycle_load_model(sqlite3 *db, const char *account_id, int as_of_day, cycle_model_t *out) {
+    memset(out, 0, sizeof(*out));
+    out->cycle_length = CYCLE_DEFAULT_LENGTH;
+    out->period_length = CYCLE_DEFAULT_PERIOD;
+    char as_of[16] = {0};
+    format_iso_day(as_of_day, as_of, sizeof(as_of));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT day, flow IN ('light', 'medium', 'heavy'), period_start FROM cycle_entries"
+            " WHERE account_id = ?1 AND day <= ?2 ORDER BY day",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, as_of, -1, SQLITE_TRANSIENT);
+
+    /* The most recent starts, oldest first, with the bleeding days counted after each. */
+    int starts[CYCLE_HISTORY + 1];
+    int bleeding[CYCLE_HISTORY + 1];
+    size_t count = 0;
+    int last_bleeding = -1000000;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = 0;
+        if (parse_iso_day((const char *)sqlite3_column_text(stmt, 0), &day) != 0) continue;
+        int bleeds = sqlite3_column_int(stmt, 1);
+        int marked = sqlite3_column_int(stmt, 2);
+        if (marked && count > 0 && day - starts[count - 1] < CYCLE_RESTART_GAP) {
+            /* An explicit marker moves a start inferred from flow a few days earlier. */
+            starts[count - 1] = day;
+        } else if (marked || (bleeds && day - last_bleeding > CYCLE_RESTART_GAP)) {
+            if (count == CYCLE_HISTORY + 1) {
+                memmove(starts, starts + 1, CYCLE_HISTORY * sizeof(int));
+                memmove(bleeding, bleeding + 1, CYCLE_HISTORY * sizeof(int));
+                count--;
+            }
+            starts[count] = day;
+            bleeding[count] = 0;
+            count++;
+        }
+        if (bleeds) {
+            last_bleeding = day;
+            if (count > 0 && day - starts[count - 1] < CYCLE_RESTART_GAP) bleeding[count - 1]++;
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (count == 0) return 0;
+
+    int length_sum = 0;
+    int lengths = 0;
+    for (size_t i = 1; i < count; i++) {
+        int length = starts[i] - starts[i - 1];
+        if (length < CYCLE_MIN_LENGTH || length > CYCLE_MAX_LENGTH) continue;
+        length_sum += length;
+        lengths++;
+    }
+    if (lengths > 0) out->cycle_length = (length_sum + lengths / 2) / lengths;
+    int period_sum = 0;
+    int periods = 0;
+    for (size_t i = 0; i < count; i++) {
+        if (bleeding[i] == 0) continue;
+        period_sum += bleeding[i];
+        periods++;
+    }
+    if (periods > 0) out->period_length = (period_sum + periods / 2) / periods;
+    if (out->period_length < 2) out->period_length = 2;
+    if (out->period_length > 8) out->period_length = 8;
+    out->last_start_day = starts[count - 1];
+    out->tracked = as_of_day - out->last_start_day < 2 * out->cycle_length;
+    return 0;
+}
+
+const char *cycle_phase_on(const cycle_model_t *model, int day, int *out_cycle_day) {
+    if (!model->tracked || day < model->last_start_day) return NULL;
+    int cycle_day = (day - model->last_start_day) % model->cycle_length + 1;
+    if (out_cycle_day) *out_cycle_day = cycle_day;
+    int ovulation = model->cycle_length - CYCLE_LUTEAL_DAYS;
+    if (cycle_day <= model->period_length) return "menstrual";
+    if (cycle_day < ovulation - 1) return "follicular";
+    if (cycle_day <= ovulation + 1) return "ovulatory";
+    return "luteal";
+}
+
+void cycle_append_json(strbuf_t *sb, const cycle_model_t *model, int day) {
+    int cycle_day = 0;
+    const char *phase = cycle_phase_on(model, day, &cycle_day);
+    if (!phase) {
+        strbuf_append(sb, "null", 4);
+        return;
+    }
+    int elapsed = day - model->last_start_day;
+    char next[16] = {0};
+    format_iso_day(model->last_start_day + (elapsed / model->cycle_length + 1) * model->cycle_length, next, sizeof(next));
+    strbuf_appendf(
+        sb,
+        "{\"phase\":\"%s\",\"cycle_day\":%d,\"cycle_length\":%d,\"period_length\":%d,\"next_period\":\"%s\",\"predicted\":%s}",
+        phase,
+        cycle_day,
+        model->cycle_length,
+        model->period_length,
+        next,
+        elapsed >= model->cycle_length ? "true" : "false");
+}
+
+static void append_cycle_entry(strbuf_t *sb, sqlite3_stmt *stmt) {
+    /* Columns: day, flow, period_start, symptoms, notes, updated_at */
+    strbuf_append(sb, "{\"date\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"flow\":", 8);
+    if (sqlite3_column_type(stmt, 1) == SQLITE_NULL) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 1));
+    }
+    const char *symptoms = (const char *)sqlite3_column_text(stmt, 3);
+    strbuf_appendf(sb, ",\"period_start\":%s,\"symptoms\":", sqlite3_column_int(stmt, 2) ? "true" : "false");
+    strbuf_append(sb, symptoms ? symptoms : "[]", symptoms ? strlen(symptoms) : 2);
+    strbuf_append(sb, ",\"notes\":", 9);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 4));
+    strbuf_appendf(sb, ",\"updated_at\":%lld}", sqlite3_column_int64(stmt, 5));
+}
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+static int handle_list_cycle(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    if (!query_param(req->query, "from", from, sizeof(from)) || !query_param(req->query, "to", to, sizeof(to)) ||
+        parse_day_strict(from, &from_day) != 0 || parse_day_strict(to, &to_day) != 0 || to_day < from_day ||
+        to_day - from_day >= CYCLE_LIST_MAX_DAYS) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD, at most 366 days apart\"}", ctx);
+        return 400;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT day, flow, period_start, symptoms, notes, updated_at FROM cycle_entries"
+            " WHERE account_id = ?1 AND day BETWEEN ?2 AND ?3 ORDER BY day",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"entries\":[", 12);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_cycle_entry(&sb, stmt);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_get_cycle_phase(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char date[16] = {0};
+    int day = today_day();
+    if (query_param(req->query, "date", date, sizeof(date)) && parse_day_strict(date, &day) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"date must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    format_iso_day(day, date, sizeof(date));
+    cycle_model_t model;
+    if (cycle_load_model(db->db, ctx->account_id, day, &model) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"date\":\"%s\",\"cycle\":", date);
+    cycle_append_json(&sb, &model, day);
+    strbuf_append(&sb, "}", 1);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_get_cycle_entry(int fd, worker_db_t *db, const char *day, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT day, flow, period_start, symptoms, notes, updated_at FROM cycle_entries WHERE account_id = ?1 AND day = ?2",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no cycle entry for this day\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_cycle_entry(&sb, stmt);
+    sqlite3_finalize(stmt);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_put_cycle_entry(int fd, worker_db_t *db, const char *day, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *check_sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object'"
+        " AND (json_type(?1, '$.flow') IS NULL OR json_type(?1, '$.flow') = 'null'"
+        "      OR json_extract(?1, '$.flow') IN ('none', 'spotting', 'light', 'medium', 'heavy'))"
+        " AND COALESCE(json_type(?1, '$.period_start'), 'false') IN ('true', 'false')"
+        " AND COALESCE(json_type(?1, '$.notes'), 'text') IN ('text', 'null') AND length(COALESCE(json_extract(?1, '$.notes'), '')) <= ?2"
+        " AND COALESCE(json_type(?1, '$.symptoms'), 'array') IN ('array', 'null')"
+        " AND (json_type(?1, '$.symptoms') IS NOT 'array' OR (json_array_length(?1, '$.symptoms') <= ?3"
+        "      AND NOT EXISTS (SELECT 1 FROM json_each(?1, '$.symptoms') WHERE type <> 'text' OR length(value) NOT BETWEEN 1 AND ?4)))"
+        " AND NOT EXISTS (SELECT 1 FROM json_each(?1) WHERE key NOT IN ('flow', 'period_start', 'symptoms', 'notes'))";
+    if (sqlite3_prepare_v2(db->db, check_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, CYCLE_NOTES_MAX);
+    sqlite3_bind_int(stmt, 3, CYCLE_SYMPTOMS_MAX);
+    sqlite3_bind_int(stmt, 4, CYCLE_SYMPTOM_MAX_LEN);
+    int valid = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(
+            fd,
+            400,
+            "Bad Request",
+            "{\"error\":\"expected {\\\"flow\\\": none|spotting|light|medium|heavy or null, \\\"period_start\\\": bool, "
+            "\\\"symptoms\\\": [string, ...], \\\"notes\\\": string}\"}",
+            ctx);
+        return 400;
+    }
+
+    const char *upsert_sql =
+        "INSERT INTO cycle_entries (account_id, day, flow, period_start, symptoms, notes, created_at, updated_at)"
+        " VALUES (?1, ?2, json_extract(?3, '$.flow'), COALESCE(json_extract(?3, '$.period_start'), 0),"
+        " COALESCE((SELECT json_group_array(value) FROM (SELECT value, MIN(key) AS first_key FROM json_each(?3, '$.symptoms')"
+        "   GROUP BY value ORDER BY first_key)), '[]'),"
+        " COALESCE(json_extract(?3, '$.notes'), ''), strftime('%s', 'now'), strftime('%s', 'now'))"
+        " ON CONFLICT(account_id, day) DO UPDATE SET flow = excluded.flow, period_start = excluded.period_start,"
+        " symptoms = excluded.symptoms, notes = excluded.notes, updated_at = excluded.updated_at";
+    if (sqlite3_prepare_v2(db->db, upsert_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("CYCLE store failed day=%s account=%s err=%s logid=%s", day, ctx->account_id, sqlite3_errmsg(db->db), ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("CYCLE stored day=%s account=%s logid=%s", day, ctx->account_id, ctx->log_id);
+    return handle_get_cycle_entry(fd, db, day, ctx);
+}
+
+static int handle_delete_cycle_entry(int fd, worker_db_t *db, const char *day, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM cycle_entries WHERE account_id = ?1 AND day = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no cycle entry for this day\"}", ctx);
+        return 404;
+    }
+    log_info("CYCLE deleted day=%s account=%s logid=%s", day, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_cycle(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *collection = "/v1/cycle";
+    if (strcmp(req->path, collection) == 0 || strcmp(req->path, "/v1/cycle/phase") == 0) {
+        if (strcmp(method, "GET") != 0) {
+            send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+            return 405;
+        }
+        return strcmp(req->path, collection) == 0 ? handle_list_cycle(fd, db, req, ctx) : handle_get_cycle_phase(fd, db, req, ctx);
+    }
+
+    const char *day = req->path + strlen(collection) + 1;
+    int parsed_day = 0;
+    if (parse_day_strict(day, &parsed_day) != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"cycle entries are addressed by YYYY-MM-DD\"}", ctx);
+        return 404;
+    }
+    if (strcmp(method, "GET") == 0) return handle_get_cycle_entry(fd, db, day, ctx);
+    if (strcmp(method, "PUT") == 0) return handle_put_cycle_entry(fd, db, day, req, ctx);
+    if (strcmp(method, "DELETE") == 0) return handle_delete_cycle_entry(fd, db, day, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -103,6 +103,123 @@ static int extract_pending_key_from_name(const char *name, char *out_key, size_t
     return 1;
 }
 
+/* Adds a column to a table created by an older build; CREATE TABLE IF NOT EXISTS leaves those alone. */
+static int ensure_column(sqlite3 *db, const char *table, const char *column, const char *definition) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, table, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, column, -1, SQLITE_TRANSIENT);
+    int present = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) > 0;
+    sqlite3_finalize(stmt);
+    if (present) return 0;
+    char sql[256] = {0};
+    snprintf(sql, sizeof(sql), "ALTER TABLE %s ADD COLUMN %s %s", table, column, definition);
+    char *err = NULL;
+    if (sqlite3_exec(db, sql, NULL, NULL, &err) != SQLITE_OK) {
+        log_error("failed to add column %s.%s: %s", table, column, err ? err : "unknown");
+        sqlite3_free(err);
+        return -1;
+    }
+    log_info("DB schema added column %s.%s", table, column);
+    return 0;
+}
+
+static void sql_sha256_hex(sqlite3_context *context, int argc, sqlite3_value **argv) {
+    (void)argc;
+    const unsigned char *text = sqlite3_value_text(argv[0]);
+    char hash[65] = {0};
+    sha256_hex(text ? (const char *)text : "", (size_t)sqlite3_value_bytes(argv[0]), hash, sizeof(hash));
+    sqlite3_result_text(context, hash, -1, SQLITE_TRANSIENT);
+}
+
+/*
+ * Older builds kept bearer tokens in a plaintext `token` column. Replaces each with its SHA-256 and
+ * renames the column to token_hash, in one transaction, so a copy of the database grants nothing.
+ */
+static int hash_token_column(sqlite3 *db, const char *table) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = 'token'", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, table, -1, SQLITE_TRANSIENT);
+    int plaintext = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) > 0;
+    sqlite3_finalize(stmt);
+    if (!plaintext) return 0;
+
+    char sql[256] = {0};
+    snprintf(
+        sql,
+        sizeof(sql),
+        "BEGIN IMMEDIATE; UPDATE %s SET token = sha256_hex(token); ALTER TABLE %s RENAME COLUMN token TO token_hash; COMMIT;",
+        table,
+        table);
+    char *err = NULL;
+    if (sqlite3_create_function(db, "sha256_hex", 1, SQLITE_UTF8 | SQLITE_DETERMINISTIC, NULL, sql_sha256_hex, NULL, NULL) != SQLITE_OK ||
+        sqlite3_exec(db, sql, NULL, NULL, &err) != SQLITE_OK) {
+        log_error("failed to hash %s tokens: %s", table, err ? err : sqlite3_errmsg(db));
+        sqlite3_free(err);
+        if (!sqlite3_get_autocommit(db)) sqlite3_exec(db, "ROLLBACK", NULL, NULL, NULL);
+        return -1;
+    }
+    log_info("DB schema hashed %d %s tokens", sqlite3_changes(db), table);
+    return 0;
+}
+
+/*
+ * Older builds kept attachment bytes in attachments.data/thumbnail. Writes each blob to the
+ * attachment directory, records its hash and drops the columns, in one transaction; the space
+ * they took is reused by SQLite but only returned to the filesystem by a VACUUM.
+ */
+static int move_attachment_blobs(sqlite3 *db, const char *db_path) {
+    if (ensure_column(db, "attachments", "blob_sha256", "TEXT NOT NULL DEFAULT ''") != 0 || ensure_column(db, "attachments", "thumbnail_sha256", "TEXT") != 0 ||
+        sqlite3_exec(db, "CREATE INDEX IF NOT EXISTS idx_attachments_blob ON attachments(blob_sha256)", NULL, NULL, NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT COUNT(*) FROM pragma_table_info('attachments') WHERE name = 'data'", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    int inline_blobs = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) > 0;
+    sqlite3_finalize(stmt);
+    if (!inline_blobs) return 0;
+
+    char dir[512] = {0};
+    if (attachment_dir(db_path, dir, sizeof(dir)) != 0 || sqlite3_exec(db, "BEGIN IMMEDIATE", NULL, NULL, NULL) != SQLITE_OK) return -1;
+    sqlite3_stmt *update = NULL;
+    int rc = sqlite3_prepare_v2(db, "SELECT id, data, thumbnail FROM attachments", -1, &stmt, NULL);
+    if (rc == SQLITE_OK) rc = sqlite3_prepare_v2(db, "UPDATE attachments SET blob_sha256 = ?2, thumbnail_sha256 = ?3 WHERE id = ?1", -1, &update, NULL);
+    int moved = 0;
+    while (rc == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW) {
+        char hash[65] = {0};
+        char thumb_hash[65] = {0};
+        if (attachment_blob_write(dir, sqlite3_column_blob(stmt, 1), (size_t)sqlite3_column_bytes(stmt, 1), hash, sizeof(hash)) != 0 ||
+            (sqlite3_column_type(stmt, 2) != SQLITE_NULL &&
+             attachment_blob_write(dir, sqlite3_column_blob(stmt, 2), (size_t)sqlite3_column_bytes(stmt, 2), thumb_hash, sizeof(thumb_hash)) != 0)) {
+            log_error("failed to write attachment %s to %s", (const char *)sqlite3_column_text(stmt, 0), dir);
+            rc = SQLITE_ERROR;
+            break;
+        }
+        sqlite3_reset(update);
+        sqlite3_bind_text(update, 1, (const char *)sqlite3_column_text(stmt, 0), -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(update, 2, hash, -1, SQLITE_TRANSIENT);
+        if (thumb_hash[0] != '\0') {
+            sqlite3_bind_text(update, 3, thumb_hash, -1, SQLITE_TRANSIENT);
+        } else {
+            sqlite3_bind_null(update, 3);
+        }
+        if (sqlite3_step(update) != SQLITE_DONE) rc = SQLITE_ERROR;
+        moved++;
+    }
+    sqlite3_finalize(update);
+    sqlite3_finalize(stmt);
+    char *err = NULL;
+    if (rc != SQLITE_OK ||
+        sqlite3_exec(db, "ALTER TABLE attachments DROP COLUMN data; ALTER TABLE attachments DROP COLUMN thumbnail; COMMIT;", NULL, NULL, &err) != SQLITE_OK) {
+        log_error("failed to move attachment blobs: %s", err ? err : sqlite3_errmsg(db));
+        sqlite3_free(err);
+        if (!sqlite3_get_autocommit(db)) sqlite3_exec(db, "ROLLBACK", NULL, NULL, NULL);
+        return -1;
+    }
+    log_info("DB schema moved %d attachment blobs to %s", moved, dir);
+    return 0;
+}
+
 static int replay_pending_writes(sqlite3 *db) {
     if (ensure_pending_writes_dir() != 0) {
         log_error("failed to ensure pending writes dir");
@@ -212,6 +329,39 @@ static int replay_pending_writes(sqlite3 *db) {
     return rc;
 }
 
+/* Records changed items with the document's updated_at, then tombstones ids that left the document. */
+#define ITEM_CHANGES_UPSERT_SQL \
+    "INSERT INTO item_changes (data_key, item_id, item_value, updated_at, deleted)" \
+    " SELECT new.data_key, CAST(json_extract(value, '$.id') AS TEXT), value, new.updated_at, 0 FROM json_each(new.data_value)" \
+    " WHERE json_type(value) = 'object' AND json_extract(value, '$.id') IS NOT NULL" \
+    " ON CONFLICT(data_key, item_id) DO UPDATE SET item_value = excluded.item_value, updated_at = excluded.updated_at, deleted = 0" \
+    " WHERE item_value IS NOT excluded.item_value OR deleted = 1;" \
+    " UPDATE item_changes SET deleted = 1, item_value = NULL, updated_at = new.updated_at" \
+    " WHERE data_key = new.data_key AND deleted = 0 AND item_id NOT IN" \
+    " (SELECT CAST(json_extract(value, '$.id') AS TEXT) FROM json_each(new.data_value) WHERE json_extract(value, '$.id') IS NOT NULL);"
+
+/* Moves a key to the head of key_changes; seq only ever grows, so it doubles as the SSE event id. */
+#define KEY_CHANGES_UPSERT_SQL(row, updated_at, deleted)                                                          \
+    "INSERT INTO key_changes (data_key, seq, updated_at, deleted)"                                                 \
+    " VALUES (" row ".data_key, (SELECT COALESCE(MAX(seq), 0) + 1 FROM key_changes), " updated_at ", " deleted ")" \
+    " ON CONFLICT(data_key) DO UPDATE SET seq = excluded.seq, updated_at = excluded.updated_at, deleted = excluded.deleted;"
+
+/* A database from before item_changes existed gets every current item once, stamped with its document's time. */
+static int backfill_item_changes(sqlite3 *db) {
+    const char *sql =
+        "INSERT OR IGNORE INTO item_changes (data_key, item_id, item_value, updated_at, deleted)"
+        " SELECT k.data_key, CAST(json_extract(e.value, '$.id') AS TEXT), e.value, k.updated_at, 0 FROM kv_store k, json_each(k.data_value) e"
+        " WHERE json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND json_type(e.value) = 'object' AND json_extract(e.value, '$.id') IS NOT NULL";
+    char *err = NULL;
+    if (sqlite3_exec(db, sql, NULL, NULL, &err) != SQLITE_OK) {
+        log_error("failed to backfill item_changes: %s", err ? err : "unknown");
+        sqlite3_free(err);
+        return -1;
+    }
+    log_info("DB schema backfilled item_changes rows=%d", sqlite3_changes(db));
+    return 0;
+}
+
 int init_db(const char *db_path) {
     sqlite3 *db = NULL;
     if (sqlite3_open_v2(db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
@@ -220,6 +370,13 @@ int init_db(const char *db_path) {
         return -1;
     }
 
+    sqlite3_stmt *probe = NULL;
+    int had_item_changes = 0;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'item_changes'", -1, &probe, NULL) == SQLITE_OK) {
+        had_item_changes = sqlite3_step(probe) == SQLITE_ROW;
+    }
+    sqlite3_finalize(probe);
+
     const char *schema_sql =
         "PRAGMA journal_mode=WAL;"
         "PRAGMA synchronous=FULL;"
@@ -231,7 +388,324 @@ int init_db(const char *db_path) {
         "data_key TEXT PRIMARY KEY,"
         "data_value TEXT NOT NULL,"
         "updated_at INTEGER NOT NULL"
-        ");";
+        ");"
+        "CREATE TABLE IF NOT EXISTS notifications ("
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,"
+        "account_id TEXT NOT NULL,"
+        "kind TEXT NOT NULL,"
+        "dedupe_key TEXT NOT NULL,"
+        "message TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "message_key TEXT,"
+        "message_args TEXT,"
+        "UNIQUE(account_id, dedupe_key)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS device_tokens ("
+        "token_hash TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "name TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "last_used_at INTEGER"
+        ");"
+        "CREATE TABLE IF NOT EXISTS device_clock_skew ("
+        "account_id TEXT NOT NULL,"
+        "device_id TEXT NOT NULL,"
+        "skewed_requests INTEGER NOT NULL DEFAULT 0,"
+        "future_dates INTEGER NOT NULL DEFAULT 0,"
+        "last_offset_seconds INTEGER,"
+        "last_warning_at INTEGER NOT NULL,"
+        "PRIMARY KEY (account_id, device_id)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS activity_quarantine ("
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,"
+        "account_id TEXT NOT NULL,"
+        "item_id TEXT,"
+        "activity_date TEXT,"
+        "reason TEXT NOT NULL,"
+        "item TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_activity_quarantine_account ON activity_quarantine(account_id, id);"
+        "CREATE TABLE IF NOT EXISTS live_sessions ("
+        "account_id TEXT NOT NULL,"
+        "session_id TEXT NOT NULL,"
+        "sport TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, session_id)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS live_samples ("
+        "account_id TEXT NOT NULL,"
+        "session_id TEXT NOT NULL,"
+        "t INTEGER NOT NULL,"
+        "power REAL,"
+        "hr REAL,"
+        "cadence REAL,"
+        "PRIMARY KEY(account_id, session_id, t)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS activity_metrics ("
+        "account_id TEXT NOT NULL,"
+        "activity_id TEXT NOT NULL,"
+        "metric TEXT NOT NULL,"
+        "value REAL NOT NULL,"
+        "computed_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, activity_id, metric)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS cp_history ("
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,"
+        "account_id TEXT NOT NULL,"
+        "fitted_at INTEGER NOT NULL,"
+        "model INTEGER NOT NULL,"
+        "weeks INTEGER NOT NULL,"
+        "cp REAL NOT NULL,"
+        "w_prime REAL NOT NULL,"
+        "k REAL NOT NULL,"
+        "cp_ci_low REAL NOT NULL,"
+        "cp_ci_high REAL NOT NULL,"
+        "w_prime_ci_low REAL NOT NULL,"
+        "w_prime_ci_high REAL NOT NULL,"
+        "points INTEGER NOT NULL"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_cp_history_account ON cp_history(account_id, fitted_at);"
+        "CREATE TABLE IF NOT EXISTS vo2max_trend ("
+        "account_id TEXT NOT NULL,"
+        "day TEXT NOT NULL,"
+        "estimate REAL NOT NULL,"
+        "smoothed REAL NOT NULL,"
+        "activities INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, day)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS cluster_lease ("
+        "name TEXT PRIMARY KEY,"
+        "holder TEXT NOT NULL,"
+        "advertise_url TEXT NOT NULL DEFAULT '',"
+        "expires_at INTEGER NOT NULL,"
+        "acquired_at INTEGER NOT NULL"
+        ");"
+        "CREATE TABLE IF NOT EXISTS attachments ("
+        "id TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "owner_type TEXT NOT NULL,"
+        "owner_id TEXT NOT NULL,"
+        "filename TEXT NOT NULL,"
+        "content_type TEXT NOT NULL,"
+        "size INTEGER NOT NULL,"
+        "width INTEGER,"
+        "height INTEGER,"
+        "blob_sha256 TEXT NOT NULL,"
+        "thumbnail_sha256 TEXT,"
+        "shared INTEGER NOT NULL DEFAULT 0,"
+        "created_at INTEGER NOT NULL"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_attachments_owner ON attachments(account_id, owner_type, owner_id);"
+        "CREATE TABLE IF NOT EXISTS journal_entries ("
+        "account_id TEXT NOT NULL,"
+        "day TEXT NOT NULL,"
+        "body TEXT NOT NULL DEFAULT '',"
+        "mood INTEGER,"
+        "tags TEXT NOT NULL DEFAULT '[]',"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, day)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS coach_tokens ("
+        "token_hash TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "name TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL"
+        ");"
+        "CREATE TABLE IF NOT EXISTS key_locks ("
+        "account_id TEXT NOT NULL,"
+        "data_key TEXT NOT NULL,"
+        "locked_by TEXT NOT NULL,"
+        "reason TEXT NOT NULL DEFAULT '',"
+        "locked_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, data_key)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS research_consent ("
+        "account_id TEXT PRIMARY KEY,"
+        "opted_in_at INTEGER NOT NULL"
+        ");"
+        "CREATE TABLE IF NOT EXISTS key_snapshots ("
+        "data_key TEXT NOT NULL,"
+        "day TEXT NOT NULL,"
+        "data_value TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "PRIMARY KEY(data_key, day)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS export_connectors ("
+        "id TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "kind TEXT NOT NULL,"
+        "target TEXT NOT NULL,"
+        "username TEXT NOT NULL DEFAULT '',"
+        "secret TEXT NOT NULL DEFAULT '',"
+        "created_at INTEGER NOT NULL,"
+        "last_attempt_at INTEGER,"
+        "last_success_at INTEGER,"
+        "last_error TEXT"
+        ");"
+        "CREATE TABLE IF NOT EXISTS connector_jobs ("
+        "id INTEGER PRIMARY KEY AUTOINCREMENT,"
+        "connector_id TEXT NOT NULL,"
+        "account_id TEXT NOT NULL,"
+        "kind TEXT NOT NULL,"
+        "ref TEXT NOT NULL,"
+        "state TEXT NOT NULL DEFAULT 'pending',"
+        "attempts INTEGER NOT NULL DEFAULT 0,"
+        "next_attempt_at INTEGER NOT NULL,"
+        "remote_path TEXT,"
+        "last_error TEXT,"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "UNIQUE(connector_id, kind, ref)"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_connector_jobs_due ON connector_jobs(state, next_attempt_at);"
+        "CREATE TABLE IF NOT EXISTS mail_inboxes ("
+        "account_id TEXT PRIMARY KEY,"
+        "token TEXT NOT NULL UNIQUE,"
+        "senders TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "last_received_at INTEGER,"
+        "received_count INTEGER NOT NULL DEFAULT 0"
+        ");"
+        "CREATE TABLE IF NOT EXISTS chat_bots ("
+        "id TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "kind TEXT NOT NULL,"
+        "target TEXT NOT NULL,"
+        "secret TEXT NOT NULL DEFAULT '',"
+        "inbound_token TEXT NOT NULL UNIQUE,"
+        "reminder_hour INTEGER NOT NULL DEFAULT 7,"
+        "last_notification_id INTEGER NOT NULL DEFAULT 0,"
+        "last_reminder_day INTEGER NOT NULL DEFAULT -1,"
+        "last_sent_at INTEGER,"
+        "last_error TEXT,"
+        "created_at INTEGER NOT NULL"
+        ");"
+        "CREATE TABLE IF NOT EXISTS import_runs ("
+        "id TEXT PRIMARY KEY,"
+        "account_id TEXT NOT NULL,"
+        "source TEXT NOT NULL,"
+        "data_key TEXT NOT NULL,"
+        "file_names TEXT NOT NULL DEFAULT '[]',"
+        "item_ids TEXT NOT NULL DEFAULT '[]',"
+        "imported INTEGER NOT NULL DEFAULT 0,"
+        "duplicates INTEGER NOT NULL DEFAULT 0,"
+        "skipped INTEGER NOT NULL DEFAULT 0,"
+        "created_at INTEGER NOT NULL,"
+        "rolled_back_at INTEGER,"
+        "removed INTEGER"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_import_runs_account ON import_runs(account_id, created_at);"
+        "CREATE TABLE IF NOT EXISTS cycle_entries ("
+        "account_id TEXT NOT NULL,"
+        "day TEXT NOT NULL,"
+        "flow TEXT,"
+        "period_start INTEGER NOT NULL DEFAULT 0,"
+        "symptoms TEXT NOT NULL DEFAULT '[]',"
+        "notes TEXT NOT NULL DEFAULT '',"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, day)"
+        ");"
+        "CREATE TABLE IF NOT EXISTS travel_stays ("
+        "account_id TEXT NOT NULL,"
+        "id TEXT NOT NULL,"
+        "start_day TEXT NOT NULL,"
+        "end_day TEXT NOT NULL,"
+        "location TEXT NOT NULL DEFAULT '',"
+        "altitude_m INTEGER NOT NULL,"
+        "power_factor REAL,"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, id)"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_travel_stays_days ON travel_stays(account_id, start_day);"
+        "CREATE TABLE IF NOT EXISTS calibration_events ("
+        "account_id TEXT NOT NULL,"
+        "id TEXT NOT NULL,"
+        "gear TEXT NOT NULL,"
+        "day TEXT NOT NULL,"
+        "kind TEXT NOT NULL,"
+        "value REAL,"
+        "notes TEXT NOT NULL DEFAULT '',"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "PRIMARY KEY(account_id, id)"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_calibration_events_day ON calibration_events(account_id, day);"
+        "CREATE TABLE IF NOT EXISTS users ("
+        "account_id TEXT PRIMARY KEY,"
+        "display_name TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL"
+        ");"
+        "CREATE TABLE IF NOT EXISTS api_tokens ("
+        "token_hash TEXT PRIMARY KEY,"
+        "token_id TEXT NOT NULL UNIQUE,"
+        "account_id TEXT NOT NULL,"
+        "name TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "last_used_at INTEGER"
+        ");"
+        "CREATE TABLE IF NOT EXISTS pairing_codes ("
+        "code_hash TEXT PRIMARY KEY,"
+        "pairing_id TEXT NOT NULL UNIQUE,"
+        "account_id TEXT NOT NULL,"
+        "scope TEXT NOT NULL,"
+        "name TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL,"
+        "expires_at INTEGER NOT NULL,"
+        "used_at INTEGER"
+        ");"
+        "CREATE TABLE IF NOT EXISTS data_events ("
+        "seq INTEGER PRIMARY KEY AUTOINCREMENT,"
+        "data_key TEXT NOT NULL,"
+        "op TEXT NOT NULL,"
+        "payload TEXT NOT NULL,"
+        "created_at INTEGER NOT NULL"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_data_events_key ON data_events(data_key, seq);"
+        "CREATE TRIGGER IF NOT EXISTS data_events_no_update BEFORE UPDATE ON data_events BEGIN SELECT RAISE(ABORT, 'data_events is append-only'); END;"
+        "CREATE TRIGGER IF NOT EXISTS data_events_no_delete BEFORE DELETE ON data_events BEGIN SELECT RAISE(ABORT, 'data_events is append-only'); END;"
+        "CREATE TABLE IF NOT EXISTS store_sequence ("
+        "id INTEGER PRIMARY KEY CHECK (id = 1),"
+        "seq INTEGER NOT NULL"
+        ");"
+        "INSERT OR IGNORE INTO store_sequence (id, seq) VALUES (1, 0);"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_seq_insert AFTER INSERT ON kv_store BEGIN UPDATE store_sequence SET seq = seq + 1 WHERE id = 1; END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_seq_update AFTER UPDATE ON kv_store BEGIN UPDATE store_sequence SET seq = seq + 1 WHERE id = 1; END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_seq_delete AFTER DELETE ON kv_store BEGIN UPDATE store_sequence SET seq = seq + 1 WHERE id = 1; END;"
+        /* Per-item change times for list documents (items with an "id"), kept in step with kv_store for GET /v1/sync. */
+        "CREATE TABLE IF NOT EXISTS item_changes ("
+        "data_key TEXT NOT NULL,"
+        "item_id TEXT NOT NULL,"
+        "item_value TEXT,"
+        "updated_at INTEGER NOT NULL,"
+        "deleted INTEGER NOT NULL DEFAULT 0,"
+        "PRIMARY KEY (data_key, item_id)"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_item_changes_updated ON item_changes(data_key, updated_at);"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_items_insert AFTER INSERT ON kv_store"
+        " WHEN json_valid(new.data_value) AND json_type(new.data_value) = 'array' BEGIN " ITEM_CHANGES_UPSERT_SQL " END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_items_update AFTER UPDATE ON kv_store"
+        " WHEN json_valid(new.data_value) AND json_type(new.data_value) = 'array' BEGIN " ITEM_CHANGES_UPSERT_SQL " END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_items_delete AFTER DELETE ON kv_store BEGIN"
+        " UPDATE item_changes SET deleted = 1, item_value = NULL, updated_at = strftime('%s', 'now') WHERE data_key = old.data_key AND deleted = 0;"
+        " END;"
+        /* Latest change per key in write order, replayed to SSE clients resuming with Last-Event-ID. */
+        "CREATE TABLE IF NOT EXISTS key_changes ("
+        "data_key TEXT PRIMARY KEY,"
+        "seq INTEGER NOT NULL,"
+        "updated_at INTEGER NOT NULL,"
+        "deleted INTEGER NOT NULL DEFAULT 0"
+        ");"
+        "CREATE INDEX IF NOT EXISTS idx_key_changes_seq ON key_changes(seq);"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_key_changes_insert AFTER INSERT ON kv_store BEGIN " KEY_CHANGES_UPSERT_SQL("new", "new.updated_at", "0") " END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_key_changes_update AFTER UPDATE ON kv_store BEGIN " KEY_CHANGES_UPSERT_SQL("new", "new.updated_at", "0") " END;"
+        "CREATE TRIGGER IF NOT EXISTS kv_store_key_changes_delete AFTER DELETE ON kv_store BEGIN "
+        KEY_CHANGES_UPSERT_SQL("old", "strftime('%s', 'now')", "1") " END;";
 
     char *err = NULL;
     if (sqlite3_exec(db, schema_sql, NULL, NULL, &err) != SQLITE_OK) {
@@ -240,6 +714,28 @@ int init_db(const char *db_path) {
         sqlite3_close(db);
         return -1;
     }
+    if (ensure_column(db, "notifications", "message_key", "TEXT") != 0 || ensure_column(db, "notifications", "message_args", "TEXT") != 0) {
+        sqlite3_close(db);
+        return -1;
+    }
+    if (hash_token_column(db, "coach_tokens") != 0 || hash_token_column(db, "device_tokens") != 0) {
+        sqlite3_close(db);
+        return -1;
+    }
+    if (move_attachment_blobs(db, db_path) != 0) {
+        sqlite3_close(db);
+        return -1;
+    }
+
+    if (event_log_configure(db) != 0) {
+        sqlite3_close(db);
+        return -1;
+    }
+
+    if (!had_item_changes && backfill_item_changes(db) != 0) {
+        sqlite3_close(db);
+        return -1;
+    }
 
     if (replay_pending_writes(db) != 0) {
         log_error("failed to replay pending writes");
@@ -275,25 +771,32 @@ int init_db(const char *db_path) {
     return 0;
 }
 
-int worker_db_open(worker_db_t *db, const char *db_path) {
-    memset(db, 0, sizeof(*db));
+/*
+ * Restoring a backup swaps the database content under every open connection. SQLite copes, but
+ * the restore bumps this generation so each worker and the write thread open a fresh connection
+ * before their next request, and nothing keeps state from the old file.
+ */
+static long long g_db_generation;
+
+void db_pool_invalidate(void) {
+    __atomic_add_fetch(&g_db_generation, 1, __ATOMIC_RELEASE);
+}
+
+long long db_pool_generation(void) {
+    return __atomic_load_n(&g_db_generation, __ATOMIC_ACQUIRE);
+}
+
+static int worker_db_connect(worker_db_t *db, const char *db_path) {
+    db->generation = db_pool_generation();
     if (sqlite3_open_v2(db_path, &db->db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_NOMUTEX, NULL) != SQLITE_OK) {
         log_error("worker failed to open db: %s", sqlite3_errmsg(db->db));
         if (db->db) sqlite3_close(db->db);
+        db->db = NULL;
         return -1;
     }
 
-    char resolved_path[PATH_MAX] = {0};
-    const char *effective_path = db_path;
-    if (realpath(db_path, resolved_path)) {
-        effective_path = resolved_path;
-    }
-    if (snprintf(db->db_path, sizeof(db->db_path), "%s", effective_path) <= 0 ||
-        strlen(effective_path) >= sizeof(db->db_path)) {
-        worker_db_close(db);
-        return -1;
-    }
-
+    slowlog_attach(db->db);
+    deadline_attach(db->db);
     sqlite3_exec(db->db, "PRAGMA busy_timeout=5000;", NULL, NULL, NULL);
     sqlite3_exec(db->db, "PRAGMA synchronous=FULL;", NULL, NULL, NULL);
     sqlite3_exec(db->db, "PRAGMA fullfsync=ON;", NULL, NULL, NULL);
@@ -302,9 +805,37 @@ int worker_db_open(worker_db_t *db, const char *db_path) {
     sqlite3_exec(db->db, "PRAGMA mmap_size=268435456;", NULL, NULL, NULL);
     sqlite3_exec(db->db, "PRAGMA cache_size=-32768;", NULL, NULL, NULL);
 
-    if (sqlite3_prepare_v2(db->db, "SELECT data_value FROM kv_store WHERE data_key=?1", -1, &db->get_stmt, NULL) != SQLITE_OK ||
+    if (sqlite3_prepare_v2(db->db, "SELECT data_value, updated_at FROM kv_store WHERE data_key=?1", -1, &db->get_stmt, NULL) != SQLITE_OK ||
         sqlite3_prepare_v2(db->db, "SELECT json_valid(?1)", -1, &db->json_valid_stmt, NULL) != SQLITE_OK) {
         log_error("worker failed to prepare statements: %s", sqlite3_errmsg(db->db));
+        return -1;
+    }
+    return 0;
+}
+
+static void worker_db_disconnect(worker_db_t *db) {
+    sqlite3_finalize(db->get_stmt);
+    sqlite3_finalize(db->json_valid_stmt);
+    if (db->db) sqlite3_close(db->db);
+    db->get_stmt = NULL;
+    db->json_valid_stmt = NULL;
+    db->db = NULL;
+}
+
+int worker_db_open(worker_db_t *db, const char *db_path) {
+    memset(db, 0, sizeof(*db));
+    if (worker_db_connect(db, db_path) != 0) {
+        worker_db_disconnect(db);
+        return -1;
+    }
+
+    char resolved_path[PATH_MAX] = {0};
+    const char *effective_path = db_path;
+    if (realpath(db_path, resolved_path)) {
+        effective_path = resolved_path;
+    }
+    if (snprintf(db->db_path, sizeof(db->db_path), "%s", effective_path) <= 0 ||
+        strlen(effective_path) >= sizeof(db->db_path)) {
         worker_db_close(db);
         return -1;
     }
@@ -318,12 +849,80 @@ int worker_db_open(worker_db_t *db, const char *db_path) {
     return 0;
 }
 
+int worker_db_refresh(worker_db_t *db) {
+    if (db->db && db->generation == db_pool_generation()) return 0;
+    worker_db_disconnect(db);
+    if (worker_db_connect(db, db->db_path) != 0) {
+        worker_db_disconnect(db);
+        return -1;
+    }
+    log_info("worker reconnected to %s after a database swap", db->db_path);
+    return 0;
+}
+
 void worker_db_close(worker_db_t *db) {
-    sqlite3_finalize(db->get_stmt);
-    sqlite3_finalize(db->json_valid_stmt);
-    if (db->db) sqlite3_close(db->db);
+    worker_db_disconnect(db);
     if (db->db_path[0] != '\0') {
         write_dispatcher_release();
     }
     memset(db, 0, sizeof(*db));
 }
+
+/*
+ * Copies every WAL frame into the database file and truncates the -wal file, so a stopped server
+ * leaves one self-contained file behind. Readers still open elsewhere in the process can make it
+ * give up after the busy timeout; the WAL is then simply replayed by the next open.
+ */
+int db_checkpoint(const char *db_path) {
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(db_path, &db, SQLITE_OPEN_READWRITE, NULL) != SQLITE_OK) {
+        log_warn("WAL checkpoint skipped, cannot open %s: %s", db_path, db ? sqlite3_errmsg(db) : "out of memory");
+        if (db) sqlite3_close(db);
+        return -1;
+    }
+    sqlite3_busy_timeout(db, 5000);
+    sqlite3_stmt *stmt = NULL;
+    int rc = sqlite3_prepare_v2(db, "PRAGMA wal_checkpoint(TRUNCATE)", -1, &stmt, NULL);
+    int busy = 1;
+    if (rc == SQLITE_OK && (rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        busy = sqlite3_column_int(stmt, 0);
+        rc = SQLITE_OK;
+    }
+    sqlite3_finalize(stmt);
+    if (rc == SQLITE_OK && !busy) {
+        log_info("WAL checkpoint done for %s", db_path);
+    } else {
+        log_warn("WAL checkpoint incomplete: %s", rc == SQLITE_OK ? "database busy" : sqlite3_errmsg(db));
+        rc = SQLITE_BUSY;
+    }
+    sqlite3_close(db);
+    return rc == SQLITE_OK ? 0 : -1;
+}
+
+/*
+ * Opens a deferred read transaction and reads store_sequence inside it, which pins the WAL snapshot
+ * to that sequence: every kv_store write bumps it, so two responses carrying the same value were
+ * computed from identical data even while an import is committing in between.
+ */
+int db_read_snapshot_begin(sqlite3 *db, long long *seq) {
+    *seq = 0;
+    if (!sqlite3_get_autocommit(db) || sqlite3_exec(db, "BEGIN DEFERRED;", NULL, NULL, NULL) != SQLITE_OK) return -1;
+    sqlite3_stmt *stmt = NULL;
+    int rc = sqlite3_prepare_v2(db, "SELECT seq FROM store_sequence WHERE id = 1", -1, &stmt, NULL);
+    if (rc == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW) {
+        *seq = sqlite3_column_int64(stmt, 0);
+    } else {
+        rc = SQLITE_ERROR;
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_OK) {
+        sqlite3_exec(db, "ROLLBACK;", NULL, NULL, NULL);
+        return -1;
+    }
+    return 0;
+}
+
+void db_read_snapshot_end(sqlite3 *db) {
+    if (sqlite3_get_autocommit(db)) return;
+    if (sqlite3_exec(db, "COMMIT;", NULL, NULL, NULL) != SQLITE_OK) sqlite3_exec(db, "ROLLBACK;", NULL, NULL, NULL);
+}
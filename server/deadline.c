@@ -0,0 +1,80 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Per-request time budgets: GET and HEAD get FRICU_READ_TIMEOUT_MS (default 2000), every other
+ * method FRICU_WRITE_TIMEOUT_MS (default 10000); imports are exempt because they are tracked as
+ * import runs and may legitimately take longer, and so are CPU profiles, which last as long as asked. The budget is enforced cooperatively through a
+ * progress handler on the worker connection: once it is spent the running statement is interrupted,
+ * whatever the handler then sends is replaced by 504, and any transaction it left open is rolled
+ * back so the connection goes back to the worker clean. Work done outside SQLite is not cut short.
+ */
+
+#define DEADLINE_DEFAULT_READ_MS 2000
+#define DEADLINE_DEFAULT_WRITE_MS 10000
+#define DEADLINE_PROGRESS_OPS 1000
+
+static __thread double g_deadline_ms;
+static __thread int g_budget_ms;
+static __thread int g_expired;
+
+static int env_budget(const char *name, int fallback) {
+    const char *raw = getenv(name);
+    if (!raw || raw[0] == '\0') return fallback;
+    char *end = NULL;
+    long value = strtol(raw, &end, 10);
+    return end && *end == '\0' && value >= 0 && value <= 3600000 ? (int)value : fallback;
+}
+
+/* Budget in ms for one request; 0 means unbounded. */
+int deadline_budget_ms(const char *method, const char *path) {
+    if (strcmp(path, "/v1/import") == 0 || strncmp(path, "/v1/import/", 11) == 0 || strcmp(path, "/v1/imports") == 0 || strncmp(path, "/v1/imports/", 12) == 0) return 0;
+    if (strncmp(path, "/v1/admin/pprof/", 16) == 0 || strcmp(path, "/v1/admin/backups") == 0 || strcmp(path, "/v1/admin/backup") == 0 ||
+        strcmp(path, "/v1/admin/restore") == 0)
+        return 0;
+    if (strcmp(method, "GET") == 0 || strcmp(method, "HEAD") == 0) return env_budget("FRICU_READ_TIMEOUT_MS", DEADLINE_DEFAULT_READ_MS);
+    return env_budget("FRICU_WRITE_TIMEOUT_MS", DEADLINE_DEFAULT_WRITE_MS);
+}
+
+static int deadline_progress(void *user) {
+    (void)user;
+    if (g_deadline_ms <= 0 || g_expired) return g_expired;
+    if (slowlog_now_ms() < g_deadline_ms) return 0;
+    g_expired = 1;
+    return 1;
+}
+
+void deadline_attach(sqlite3 *db) {
+    sqlite3_progress_handler(db, DEADLINE_PROGRESS_OPS, deadline_progress, NULL);
+}
+
+void deadline_begin(const http_request_t *req) {
+    g_budget_ms = deadline_budget_ms(req->method, req->path);
+    g_deadline_ms = g_budget_ms > 0 ? slowlog_now_ms() + g_budget_ms : 0;
+    g_expired = 0;
+}
+
+int deadline_expired(void) {
+    return g_expired;
+}
+
+int deadline_budget(void) {
+    return g_budget_ms;
+}
+
+void deadline_end(sqlite3 *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int expired = g_expired;
+    g_deadline_ms = 0;
+    g_expired = 0;
+    if (!expired) return;
+    log_warn("TIMEOUT %s %s budget=%dms account=%s logid=%s", req->method, req->path, g_budget_ms, ctx->account_id, ctx->log_id);
+    if (db && !sqlite3_get_autocommit(db)) sqlite3_exec(db, "ROLLBACK", NULL, NULL, NULL);
+}
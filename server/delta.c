@@ -0,0 +1,373 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+/*
+ * PUT /v1/data/<key> with Content-Type: application/x-fricu-delta carries an RFC 6902 JSON Patch
+ * against the document named by X-Fricu-Base-Version. Operations are applied in order with
+ * SQLite's JSON1 functions and the result is stored through the normal write path.
+ */
+
+#define DELTA_MAX_OPS 10000
+
+typedef struct {
+    strbuf_t path;
+    strbuf_t parent;
+    int has_parent;
+    int parent_is_array;
+    long index;
+    int exists;
+} json_target_t;
+
+typedef struct {
+    sqlite3 *db;
+    char *doc;
+    char error[160];
+} patch_state_t;
+
+int delta_is_request(const http_request_t *req) {
+    char content_type[128] = {0};
+    if (!http_request_header(req, "Content-Type", content_type, sizeof(content_type))) return 0;
+    size_t len = strlen(DELTA_CONTENT_TYPE);
+    return strncasecmp(content_type, DELTA_CONTENT_TYPE, len) == 0 && (content_type[len] == '\0' || content_type[len] == ';');
+}
+
+static char *query_text(sqlite3 *db, const char *sql, const char *a, const char *b, const char *c, long n) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return NULL;
+    if (a) sqlite3_bind_text(stmt, 1, a, -1, SQLITE_TRANSIENT);
+    if (b) sqlite3_bind_text(stmt, 2, b, -1, SQLITE_TRANSIENT);
+    if (c) sqlite3_bind_text(stmt, 3, c, -1, SQLITE_TRANSIENT);
+    if (sqlite3_bind_parameter_count(stmt) >= 4) sqlite3_bind_int64(stmt, 4, n);
+    char *out = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) != SQLITE_NULL) {
+        out = strdup((const char *)sqlite3_column_text(stmt, 0));
+    }
+    sqlite3_finalize(stmt);
+    return out;
+}
+
+static void target_free(json_target_t *t) {
+    strbuf_free(&t->path);
+    strbuf_free(&t->parent);
+}
+
+/* Converts a JSON Pointer into a SQLite JSON path, checking each container type along the way. */
+static int resolve_pointer(patch_state_t *st, const char *pointer, json_target_t *out) {
+    memset(out, 0, sizeof(*out));
+    strbuf_init(&out->path);
+    strbuf_init(&out->parent);
+    strbuf_append(&out->path, "$", 1);
+    out->index = -1;
+    if (pointer[0] == '\0') {
+        out->exists = 1;
+        return 0;
+    }
+    if (pointer[0] != '/') {
+        snprintf(st->error, sizeof(st->error), "pointer must start with '/': %.64s", pointer);
+        return -1;
+    }
+
+    const char *p = pointer + 1;
+    for (;;) {
+        size_t raw_len = strcspn(p, "/");
+        int last = p[raw_len] == '\0';
+        char token[256] = {0};
+        size_t len = 0;
+        for (size_t i = 0; i < raw_len; i++) {
+            char ch = p[i];
+            if (ch == '~' && i + 1 < raw_len && (p[i + 1] == '0' || p[i + 1] == '1')) {
+                ch = p[i + 1] == '0' ? '~' : '/';
+                i++;
+            } else if (ch == '~' || ch == '"') {
+                snprintf(st->error, sizeof(st->error), "unsupported pointer token: %.64s", pointer);
+                return -1;
+            }
+            if (len + 1 >= sizeof(token)) {
+                snprintf(st->error, sizeof(st->error), "pointer token too long");
+                return -1;
+            }
+            token[len++] = ch;
+        }
+
+        char *type = query_text(st->db, "SELECT json_type(?1, ?2)", st->doc, strbuf_cstr(&out->path), NULL, 0);
+        if (!type || (strcmp(type, "array") != 0 && strcmp(type, "object") != 0)) {
+            free(type);
+            snprintf(st->error, sizeof(st->error), "path not found: %.64s", pointer);
+            return -1;
+        }
+        int is_array = strcmp(type, "array") == 0;
+        free(type);
+        if (last) {
+            strbuf_append(&out->parent, strbuf_cstr(&out->path), out->path.len);
+            out->has_parent = 1;
+            out->parent_is_array = is_array;
+        }
+
+        if (is_array) {
+            if (last && strcmp(token, "-") == 0) {
+                out->index = -1;
+                strbuf_append(&out->path, "[#]", 3);
+            } else {
+                int digits = len > 0 && (len == 1 || token[0] != '0');
+                for (size_t i = 0; i < len && digits; i++) digits = token[i] >= '0' && token[i] <= '9';
+                if (!digits || len > 9) {
+                    snprintf(st->error, sizeof(st->error), "invalid array index in %.64s", pointer);
+                    return -1;
+                }
+                out->index = atol(token);
+                strbuf_appendf(&out->path, "[%ld]", out->index);
+            }
+        } else {
+            strbuf_appendf(&out->path, ".\"%s\"", token);
+        }
+        if (last) break;
+        p += raw_len + 1;
+    }
+
+    char *exists = query_text(st->db, "SELECT json_type(?1, ?2) IS NOT NULL", st->doc, strbuf_cstr(&out->path), NULL, 0);
+    out->exists = exists && strcmp(exists, "1") == 0;
+    free(exists);
+    return 0;
+}
+
+static int replace_doc(patch_state_t *st, char *next) {
+    if (!next) {
+        snprintf(st->error, sizeof(st->error), "database error applying patch");
+        return -1;
+    }
+    free(st->doc);
+    st->doc = next;
+    return 0;
+}
+
+static int op_add(patch_state_t *st, const char *pointer, const char *value) {
+    json_target_t t;
+    int rc = resolve_pointer(st, pointer, &t);
+    if (rc == 0 && !t.has_parent) {
+        rc = replace_doc(st, query_text(st->db, "SELECT json(?1)", value, NULL, NULL, 0));
+    } else if (rc == 0 && !t.parent_is_array) {
+        rc = replace_doc(st, query_text(st->db, "SELECT json_set(?1, ?2, json(?3))", st->doc, strbuf_cstr(&t.path), value, 0));
+    } else if (rc == 0) {
+        char *length = query_text(st->db, "SELECT json_array_length(?1, ?2)", st->doc, strbuf_cstr(&t.parent), NULL, 0);
+        long count = length ? atol(length) : 0;
+        free(length);
+        if (t.index > count) {
+            snprintf(st->error, sizeof(st->error), "array index out of range: %.64s", pointer);
+            rc = -1;
+        } else if (t.index < 0 || t.index == count) {
+            char append_path[512] = {0};
+            snprintf(append_path, sizeof(append_path), "%s[#]", strbuf_cstr(&t.parent));
+            rc = replace_doc(st, query_text(st->db, "SELECT json_insert(?1, ?2, json(?3))", st->doc, append_path, value, 0));
+        } else {
+            /* JSON1 cannot shift array elements, so rebuild the array with the new element spliced in. */
+            const char *sql =
+                "SELECT json_set(?1, ?2, json((SELECT json_group_array(json(j)) FROM"
+                " (SELECT j FROM (SELECT ?1 -> fullkey AS j, key * 2 AS o FROM json_each(?1, ?2)"
+                " UNION ALL SELECT ?3, ?4 * 2 - 1) ORDER BY o))))";
+            rc = replace_doc(st, query_text(st->db, sql, st->doc, strbuf_cstr(&t.parent), value, t.index));
+        }
+    }
+    target_free(&t);
+    return rc;
+}
+
+static int op_remove(patch_state_t *st, const char *pointer, char **out_removed) {
+    json_target_t t;
+    int rc = resolve_pointer(st, pointer, &t);
+    if (rc == 0 && !t.has_parent) {
+        snprintf(st->error, sizeof(st->error), "cannot remove document root");
+        rc = -1;
+    } else if (rc == 0 && !t.exists) {
+        snprintf(st->error, sizeof(st->error), "path not found: %.64s", pointer);
+        rc = -1;
+    }
+    if (rc == 0 && out_removed) *out_removed = query_text(st->db, "SELECT ?1 -> ?2", st->doc, strbuf_cstr(&t.path), NULL, 0);
+    if (rc == 0) rc = replace_doc(st, query_text(st->db, "SELECT json_remove(?1, ?2)", st->doc, strbuf_cstr(&t.path), NULL, 0));
+    target_free(&t);
+    return rc;
+}
+
+static int op_replace(patch_state_t *st, const char *pointer, const char *value) {
+    json_target_t t;
+    int rc = resolve_pointer(st, pointer, &t);
+    if (rc == 0 && !t.exists) {
+        snprintf(st->error, sizeof(st->error), "path not found: %.64s", pointer);
+        rc = -1;
+    }
+    if (rc == 0 && !t.has_parent) {
+        rc = replace_doc(st, query_text(st->db, "SELECT json(?1)", value, NULL, NULL, 0));
+    } else if (rc == 0) {
+        rc = replace_doc(st, query_text(st->db, "SELECT json_replace(?1, ?2, json(?3))", st->doc, strbuf_cstr(&t.path), value, 0));
+    }
+    target_free(&t);
+    return rc;
+}
+
+static int op_test(patch_state_t *st, const char *pointer, const char *value) {
+    json_target_t t;
+    int rc = resolve_pointer(st, pointer, &t);
+    if (rc == 0) {
+        char *same = query_text(st->db, "SELECT (?1 -> ?2) = json(?3)", st->doc, strbuf_cstr(&t.path), value, 0);
+        if (!t.exists || !same || strcmp(same, "1") != 0) {
+            snprintf(st->error, sizeof(st->error), "test failed at %.64s", pointer);
+            rc = -1;
+        }
+        free(same);
+    }
+    target_free(&t);
+    return rc;
+}
+
+static int op_copy_or_move(patch_state_t *st, const char *from, const char *pointer, int move) {
+    size_t from_len = strlen(from);
+    if (move && strncmp(pointer, from, from_len) == 0 && pointer[from_len] == '/') {
+        snprintf(st->error, sizeof(st->error), "cannot move %.64s into itself", from);
+        return -1;
+    }
+    char *value = NULL;
+    int rc = 0;
+    if (move) {
+        rc = op_remove(st, from, &value);
+    } else {
+        json_target_t t;
+        rc = resolve_pointer(st, from, &t);
+        if (rc == 0 && !t.exists) {
+            snprintf(st->error, sizeof(st->error), "path not found: %.64s", from);
+            rc = -1;
+        }
+        if (rc == 0) value = query_text(st->db, "SELECT ?1 -> ?2", st->doc, strbuf_cstr(&t.path), NULL, 0);
+        target_free(&t);
+    }
+    if (rc == 0 && !value) {
+        snprintf(st->error, sizeof(st->error), "path not found: %.64s", from);
+        rc = -1;
+    }
+    if (rc == 0) rc = op_add(st, pointer, value);
+    free(value);
+    return rc;
+}
+
+/* Applies every operation to st->doc; on failure returns the failing op index and fills st->error. */
+static int apply_patch(patch_state_t *st, const char *patch, size_t patch_len, int *out_ops) {
+    const char *sql =
+        "SELECT key, json_extract(value, '$.op'), json_extract(value, '$.path'), json_extract(value, '$.from'),"
+        " json_type(value, '$.value') IS NOT NULL, value -> '$.value', json_type(value) FROM json_each(?1)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(st->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        snprintf(st->error, sizeof(st->error), "database error reading patch");
+        return 0;
+    }
+    sqlite3_bind_text(stmt, 1, patch, (int)patch_len, SQLITE_TRANSIENT);
+    int failed_at = -1;
+    int ops = 0;
+    while (failed_at < 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        int index = sqlite3_column_int(stmt, 0);
+        const char *op = (const char *)sqlite3_column_text(stmt, 1);
+        const char *path = (const char *)sqlite3_column_text(stmt, 2);
+        const char *from = (const char *)sqlite3_column_text(stmt, 3);
+        int has_value = sqlite3_column_int(stmt, 4);
+        const char *value = (const char *)sqlite3_column_text(stmt, 5);
+        const char *type = (const char *)sqlite3_column_text(stmt, 6);
+        int rc = -1;
+        if (++ops > DELTA_MAX_OPS) {
+            snprintf(st->error, sizeof(st->error), "too many operations (max %d)", DELTA_MAX_OPS);
+        } else if (!type || strcmp(type, "object") != 0 || !op || !path) {
+            snprintf(st->error, sizeof(st->error), "operation must be an object with op and path");
+        } else if ((strcmp(op, "add") == 0 || strcmp(op, "replace") == 0 || strcmp(op, "test") == 0) && !has_value) {
+            snprintf(st->error, sizeof(st->error), "%s requires value", op);
+        } else if ((strcmp(op, "move") == 0 || strcmp(op, "copy") == 0) && !from) {
+            snprintf(st->error, sizeof(st->error), "%s requires from", op);
+        } else if (strcmp(op, "add") == 0) {
+            rc = op_add(st, path, value);
+        } else if (strcmp(op, "remove") == 0) {
+            rc = op_remove(st, path, NULL);
+        } else if (strcmp(op, "replace") == 0) {
+            rc = op_replace(st, path, value);
+        } else if (strcmp(op, "test") == 0) {
+            rc = op_test(st, path, value);
+        } else if (strcmp(op, "move") == 0 || strcmp(op, "copy") == 0) {
+            rc = op_copy_or_move(st, from, path, strcmp(op, "move") == 0);
+        } else {
+            snprintf(st->error, sizeof(st->error), "unsupported op: %.32s", op);
+        }
+        if (rc != 0) failed_at = index;
+    }
+    sqlite3_finalize(stmt);
+    *out_ops = ops;
+    return failed_at;
+}
+
+int handle_put_data_delta(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx) {
+    char base[64] = {0};
+    if (!http_request_header(req, SYNC_BASE_VERSION_HEADER, base, sizeof(base))) {
+        send_response_with_log_context(fd, 428, "Precondition Required", "{\"error\":\"delta uploads require X-Fricu-Base-Version\"}", ctx);
+        return 428;
+    }
+    json_stream_result_t parsed;
+    if (json_stream_validate(req->body, req->body_len, &parsed) != 0 || parsed.root != '[') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"delta body must be a JSON Patch array\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    sync_document_lock();
+    int conflict = sync_check_base_version(db, req, key, fd, ctx);
+    if (conflict != 0) {
+        sync_document_unlock();
+        return conflict;
+    }
+    patch_state_t st = {.db = db->db, .doc = NULL};
+    st.doc = query_text(db->db, "SELECT data_value FROM kv_store WHERE data_key = ?1", storage_key, NULL, NULL, 0);
+    if (!st.doc) st.doc = strdup(api_key_is_collection(key) ? "[]" : "{}");
+    int ops = 0;
+    int failed_at = st.doc ? apply_patch(&st, req->body, req->body_len, &ops) : 0;
+    if (failed_at >= 0) {
+        sync_document_unlock();
+        strbuf_t err;
+        strbuf_init(&err);
+        strbuf_append(&err, "{\"error\":", 9);
+        strbuf_append_json_string(&err, st.error[0] ? st.error : "patch failed");
+        strbuf_appendf(&err, ",\"op_index\":%d}", failed_at);
+        send_response_with_log_context(fd, 422, "Unprocessable Entity", strbuf_cstr(&err), ctx);
+        log_warn("DATA DELTA rejected key=%s op_index=%d reason=\"%s\" account=%s logid=%s", key, failed_at, st.error, ctx->account_id, ctx->log_id);
+        strbuf_free(&err);
+        free(st.doc);
+        return 422;
+    }
+
+    char *prepared = data_prepare_payload(db->db, ctx->account_id, key, st.doc, strlen(st.doc));
+    if (prepared) {
+        free(st.doc);
+        st.doc = prepared;
+    }
+    char body[512] = {0};
+    size_t doc_len = strlen(st.doc);
+    int status = store_account_data(db, key, st.doc, doc_len, ctx, body, sizeof(body));
+    sync_document_unlock();
+    if (status != 204) {
+        free(st.doc);
+        send_response_with_log_context(fd, status, status == 202 ? "Accepted" : status == 400 ? "Bad Request" : status == 422 ? "Unprocessable Entity" : "Internal Server Error", body, ctx);
+        return status;
+    }
+    log_info("DATA DELTA applied key=%s ops=%d patch_bytes=%zu result_bytes=%zu account=%s logid=%s", key, ops, req->body_len, doc_len, ctx->account_id, ctx->log_id);
+    char version[32] = {0};
+    char headers[192] = {0};
+    content_version(st.doc, doc_len, version, sizeof(version));
+    snprintf(headers, sizeof(headers), SYNC_VERSION_HEADERS_FMT, version, version);
+    free(st.doc);
+    send_http_response(fd, 204, "No Content", "application/json", headers, NULL, 0, ctx);
+    return 204;
+}
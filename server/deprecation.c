@@ -0,0 +1,151 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * Endpoint deprecation: a route or handler that is on its way out calls deprecation_declare with
+ * its sunset date and successor, and every response to that request, errors included, then carries
+ * Deprecation, Sunset and Link (rel="successor-version", plus rel="deprecation" when a notice URL is
+ * given). Each use is counted per endpoint and client, the client being the API token id, the OIDC
+ * account or, without authentication, the X-Account-Id; the first use by a client is logged, and
+ * GET /v1/admin/stats lists who is still calling which deprecated endpoint so they can be chased
+ * before the sunset. Counters are per process and reset on restart, like the other admin stats.
+ */
+
+#define DEPRECATION_MAX_CLIENTS 256
+
+typedef struct {
+    int declared;
+    char endpoint[96];
+    char sunset[40];
+    char successor[160];
+    char notice[160];
+} deprecation_t;
+
+typedef struct {
+    char endpoint[128];
+    char client[160];
+    char account_id[128];
+    long long requests;
+    long long first_seen;
+    long long last_seen;
+} deprecated_usage_t;
+
+static __thread deprecation_t g_current;
+
+static pthread_mutex_t g_usage_mutex = PTHREAD_MUTEX_INITIALIZER;
+static deprecated_usage_t g_usage[DEPRECATION_MAX_CLIENTS];
+static size_t g_usage_count;
+static long long g_untracked_requests;
+
+void deprecation_begin(void) {
+    g_current.declared = 0;
+}
+
+void deprecation_declare(const char *endpoint, const char *sunset, const char *successor, const char *notice) {
+    g_current.declared = 1;
+    snprintf(g_current.endpoint, sizeof(g_current.endpoint), "%s", endpoint);
+    snprintf(g_current.sunset, sizeof(g_current.sunset), "%s", sunset ? sunset : "");
+    snprintf(g_current.successor, sizeof(g_current.successor), "%s", successor ? successor : "");
+    snprintf(g_current.notice, sizeof(g_current.notice), "%s", notice ? notice : "");
+}
+
+int deprecation_headers(char *out, size_t out_len) {
+    out[0] = '\0';
+    if (!g_current.declared) return 0;
+    int n = snprintf(out, out_len, "Deprecation: true\r\n");
+    if (g_current.sunset[0] && n > 0 && (size_t)n < out_len) n += snprintf(out + n, out_len - (size_t)n, "Sunset: %s\r\n", g_current.sunset);
+    if (g_current.successor[0] && n > 0 && (size_t)n < out_len) {
+        n += snprintf(out + n, out_len - (size_t)n, "Link: <%s>; rel=\"successor-version\"\r\n", g_current.successor);
+    }
+    if (g_current.notice[0] && n > 0 && (size_t)n < out_len) n += snprintf(out + n, out_len - (size_t)n, "Link: <%s>; rel=\"deprecation\"\r\n", g_current.notice);
+    if (n <= 0 || (size_t)n >= out_len) {
+        out[0] = '\0';
+        return 0;
+    }
+    return n;
+}
+
+void deprecation_end(const http_request_t *req, const request_log_context_t *ctx) {
+    if (!g_current.declared) return;
+    g_current.declared = 0;
+    char endpoint[128] = {0};
+    char client[160] = {0};
+    snprintf(endpoint, sizeof(endpoint), "%s %s", req->method, g_current.endpoint);
+    const char *authenticated = api_auth_client_id();
+    if (authenticated[0] != '\0') {
+        snprintf(client, sizeof(client), "%s", authenticated);
+    } else {
+        snprintf(client, sizeof(client), "account:%s", ctx->account_id[0] ? ctx->account_id : "-");
+    }
+
+    long long now = (long long)time(NULL);
+    int first = 0;
+    pthread_mutex_lock(&g_usage_mutex);
+    deprecated_usage_t *entry = NULL;
+    for (size_t i = 0; i < g_usage_count && !entry; i++) {
+        if (strcmp(g_usage[i].endpoint, endpoint) == 0 && strcmp(g_usage[i].client, client) == 0) entry = &g_usage[i];
+    }
+    if (!entry && g_usage_count < DEPRECATION_MAX_CLIENTS) {
+        entry = &g_usage[g_usage_count++];
+        snprintf(entry->endpoint, sizeof(entry->endpoint), "%s", endpoint);
+        snprintf(entry->client, sizeof(entry->client), "%s", client);
+        entry->first_seen = now;
+        first = 1;
+    }
+    if (entry) {
+        snprintf(entry->account_id, sizeof(entry->account_id), "%s", ctx->account_id);
+        entry->requests++;
+        entry->last_seen = now;
+    } else {
+        g_untracked_requests++;
+    }
+    pthread_mutex_unlock(&g_usage_mutex);
+    if (first) log_warn("DEPRECATED %s used by client=%s account=%s sunset=\"%s\" logid=%s", endpoint, client, ctx->account_id, g_current.sunset, ctx->log_id);
+}
+
+static int compare_usage_recent_first(const void *a, const void *b) {
+    const deprecated_usage_t *lhs = (const deprecated_usage_t *)a;
+    const deprecated_usage_t *rhs = (const deprecated_usage_t *)b;
+    if (lhs->last_seen != rhs->last_seen) return lhs->last_seen < rhs->last_seen ? 1 : -1;
+    int by_endpoint = strcmp(lhs->endpoint, rhs->endpoint);
+    return by_endpoint != 0 ? by_endpoint : strcmp(lhs->client, rhs->client);
+}
+
+/* Appends "deprecated_usage":{...} for the admin stats report. */
+void deprecation_append_usage(strbuf_t *sb) {
+    deprecated_usage_t *snapshot = (deprecated_usage_t *)malloc(sizeof(g_usage));
+    if (!snapshot) {
+        sb->failed = 1;
+        return;
+    }
+    pthread_mutex_lock(&g_usage_mutex);
+    size_t count = g_usage_count;
+    memcpy(snapshot, g_usage, count * sizeof(deprecated_usage_t));
+    long long untracked = g_untracked_requests;
+    pthread_mutex_unlock(&g_usage_mutex);
+    qsort(snapshot, count, sizeof(deprecated_usage_t), compare_usage_recent_first);
+
+    strbuf_appendf(sb, "\"deprecated_usage\":{\"untracked_requests\":%lld,\"clients\":[", untracked);
+    for (size_t i = 0; i < count; i++) {
+        const deprecated_usage_t *entry = &snapshot[i];
+        if (i > 0) strbuf_append(sb, ",", 1);
+        strbuf_append(sb, "{\"endpoint\":", 12);
+        strbuf_append_json_string(sb, entry->endpoint);
+        strbuf_append(sb, ",\"client\":", 10);
+        strbuf_append_json_string(sb, entry->client);
+        strbuf_append(sb, ",\"account\":", 11);
+        strbuf_append_json_string(sb, entry->account_id);
+        strbuf_appendf(sb, ",\"requests\":%lld,\"first_seen\":%lld,\"last_seen\":%lld}", entry->requests, entry->first_seen, entry->last_seen);
+    }
+    strbuf_append(sb, "]}", 2);
+    free(snapshot);
+}
@@ -0,0 +1,303 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define DEVICE_TOKEN_BYTES 24
+#define DEVICE_NAME_MAX 64
+
+int generate_device_token(char *out, size_t out_len) {
+    unsigned char raw[DEVICE_TOKEN_BYTES];
+    if (out_len < 4 + DEVICE_TOKEN_BYTES * 2 + 1) return -1;
+    if (fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    memcpy(out, "dev_", 4);
+    for (size_t i = 0; i < sizeof(raw); i++) {
+        snprintf(out + 4 + i * 2, 3, "%02x", raw[i]);
+    }
+    return 0;
+}
+
+int device_token_account(sqlite3 *db, const char *token, char *out_account_id, size_t out_len) {
+    if (!token || token[0] == '\0') return 0;
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT account_id FROM device_tokens WHERE token_hash = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(out_account_id, out_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+        found = 1;
+    }
+    sqlite3_finalize(stmt);
+    if (found && sqlite3_prepare_v2(db, "UPDATE device_tokens SET last_used_at = strftime('%s', 'now') WHERE token_hash = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+    }
+    return found;
+}
+
+/* Creates a device token for account_id, storing only its hash; 0 on success, -2 when no random bytes, -1 on database errors. */
+int device_token_issue(sqlite3 *db, const char *account_id, const char *name, char *token, size_t token_len) {
+    char hash[65] = {0};
+    if (generate_device_token(token, token_len) != 0) return -2;
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "INSERT INTO device_tokens (token_hash, account_id, name, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, name, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+int handle_post_devices(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char name[DEVICE_NAME_MAX + 1] = "trainer";
+    if (req->body_len > 0) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1), json_extract(?1, '$.name')", -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        int valid = 0;
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            valid = sqlite3_column_int(stmt, 0);
+            const char *raw_name = (const char *)sqlite3_column_text(stmt, 1);
+            if (raw_name && raw_name[0] != '\0') snprintf(name, sizeof(name), "%s", raw_name);
+        }
+        sqlite3_finalize(stmt);
+        if (!valid) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+            return 400;
+        }
+    }
+
+    char token[64] = {0};
+    int issued = device_token_issue(db->db, ctx->account_id, name, token, sizeof(token));
+    if (issued != 0) {
+        if (issued == -2) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        } else {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        }
+        return 500;
+    }
+    log_info("DEVICE token issued account=%s name=%s", ctx->account_id, name);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    capture_omit_response();
+    strbuf_appendf(&sb, "{\"token\":\"%s\",\"name\":", token);
+    strbuf_append_json_string(&sb, name);
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 201;
+}
+
+int handle_delete_device(int fd, worker_db_t *db, const char *token, const request_log_context_t *ctx) {
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM device_tokens WHERE token_hash = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown device\"}", ctx);
+        return 404;
+    }
+    log_info("DEVICE token revoked account=%s", ctx->account_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+static double load_workout_ftp(sqlite3 *db, const char *account_id) {
+    double ftp = load_profile_ftp(db, account_id);
+    if (ftp <= 0.0) {
+        double w_prime = 0.0;
+        load_profile_power_model(db, account_id, &ftp, &w_prime);
+    }
+    return ftp;
+}
+
+int handle_get_today_workout(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx) {
+    char token[128] = {0};
+    if (!http_request_header(req, "X-Device-Token", token, sizeof(token))) {
+        query_param(req->query, "token", token, sizeof(token));
+    }
+    if (device_token_account(db->db, token, ctx->account_id, sizeof(ctx->account_id)) != 1) {
+        ctx->account_id[0] = '\0';
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid device token\"}", ctx);
+        return 401;
+    }
+
+    char format[16] = {0};
+    int erg = 0;
+    if (query_param(req->query, "format", format, sizeof(format))) {
+        erg = strcmp(format, "erg") == 0;
+    } else {
+        snprintf(format, sizeof(format), "json");
+    }
+    if (!erg && strcmp(format, "json") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"format must be erg or json\"}", ctx);
+        return 400;
+    }
+    char day[16] = {0};
+    if (query_param(req->query, "date", day, sizeof(day))) {
+        int parsed = 0;
+        if (parse_iso_day(day, &parsed) != 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"date must be YYYY-MM-DD\"}", ctx);
+            return 400;
+        }
+        format_iso_day(parsed, day, sizeof(day));
+    } else {
+        format_iso_day(today_day(), day, sizeof(day));
+    }
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "workouts", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    const char *sql =
+        "SELECT w.key, COALESCE(json_extract(w.value, '$.name'), 'Workout'), COALESCE(json_extract(w.value, '$.sport'), 'cycling')"
+        " FROM kv_store k, json_each(k.data_value) w"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(w.value, '$.scheduledDate'), 1, 10) = ?2"
+        " ORDER BY json_extract(w.value, '$.scheduledDate'), w.key LIMIT 1";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no workout scheduled\"}", ctx);
+        return 404;
+    }
+    int workout_index = sqlite3_column_int(stmt, 0);
+    char name[128] = {0};
+    char sport[32] = {0};
+    snprintf(name, sizeof(name), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    snprintf(sport, sizeof(sport), "%s", (const char *)sqlite3_column_text(stmt, 2));
+    sqlite3_finalize(stmt);
+
+    double ftp = load_workout_ftp(db->db, ctx->account_id);
+    const char *segment_sql =
+        "SELECT COALESCE(json_extract(s.value, '$.minutes'), 0), COALESCE(json_extract(s.value, '$.intensityPercentFTP'), 0),"
+        " json_extract(s.value, '$.cadence'), json_extract(s.value, '$.note')"
+        " FROM kv_store k, json_each(k.data_value, '$[' || ?2 || '].segments') s"
+        " WHERE k.data_key = ?1 ORDER BY s.key";
+    if (sqlite3_prepare_v2(db->db, segment_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, workout_index);
+
+    /* ERG names are single-line header values; strip anything that would break the file. */
+    for (char *p = name; *p; p++) {
+        if (*p == '\r' || *p == '\n' || *p == '[' || *p == ']') *p = ' ';
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (erg) {
+        strbuf_appendf(
+            &sb,
+            "[COURSE HEADER]\r\nVERSION = 2\r\nUNITS = ENGLISH\r\nDESCRIPTION = %s\r\nFILE NAME = %s.erg\r\nFTP = %.0f\r\nMINUTES WATTS\r\n"
+            "[END COURSE HEADER]\r\n[COURSE DATA]\r\n",
+            name,
+            day,
+            ftp);
+    } else {
+        strbuf_appendf(&sb, "{\"date\":\"%s\",\"name\":", day);
+        strbuf_append_json_string(&sb, name);
+        strbuf_append(&sb, ",\"sport\":", 9);
+        strbuf_append_json_string(&sb, sport);
+        strbuf_appendf(&sb, ",\"ftp_watts\":%.0f,\"segments\":[", ftp);
+    }
+    double start_min = 0.0;
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        double minutes = sqlite3_column_double(stmt, 0);
+        double percent = sqlite3_column_double(stmt, 1);
+        double watts = ftp * percent / 100.0;
+        if (minutes <= 0.0) continue;
+        if (erg) {
+            strbuf_appendf(&sb, "%.2f\t%.0f\r\n%.2f\t%.0f\r\n", start_min, watts, start_min + minutes, watts);
+        } else {
+            strbuf_appendf(
+                &sb,
+                "%s{\"start_sec\":%.0f,\"duration_sec\":%.0f,\"percent_ftp\":%.1f,\"target_watts\":%.0f,\"cadence\":",
+                count == 0 ? "" : ",",
+                start_min * 60.0,
+                minutes * 60.0,
+                percent,
+                watts);
+            if (sqlite3_column_type(stmt, 2) == SQLITE_NULL) {
+                strbuf_append(&sb, "null", 4);
+            } else {
+                strbuf_appendf(&sb, "%d", sqlite3_column_int(stmt, 2));
+            }
+            strbuf_append(&sb, ",\"note\":", 8);
+            const char *note = (const char *)sqlite3_column_text(stmt, 3);
+            if (note) {
+                strbuf_append_json_string(&sb, note);
+            } else {
+                strbuf_append(&sb, "null", 4);
+            }
+            strbuf_append(&sb, "}", 1);
+        }
+        start_min += minutes;
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    if (erg) {
+        strbuf_append(&sb, "[END COURSE DATA]\r\n", 19);
+    } else {
+        strbuf_appendf(&sb, "],\"total_sec\":%.0f}", start_min * 60.0);
+    }
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    const char *body = strbuf_cstr(&sb);
+    if (erg) {
+        send_http_response(fd, 200, "OK", "text/plain; charset=utf-8", NULL, body, strlen(body), ctx);
+    } else {
+        send_response_with_log_context(fd, 200, "OK", body, ctx);
+    }
+    strbuf_free(&sb);
+    return 200;
+}
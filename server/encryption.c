@@ -0,0 +1,392 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/crypto.h>
+#include <openssl/evp.h>
+#include <openssl/hmac.h>
+#include <openssl/kdf.h>
+#include <openssl/rand.h>
+#endif
+
+/*
+ * Passphrase encryption for data that leaves the server. With FRICU_EXPORT_PASSPHRASE set (a
+ * secret, so _FILE and FRICU_SECRETS_FILE work too) connector uploads are written as <name>.age and
+ * GET /v1/export/...?encrypt=age answers with an encrypted attachment. The format is age v1 with a
+ * single scrypt stanza, the one `age -p` writes, so a copy can be opened with `age -d` (or
+ * `fricu-server --decrypt`) without this server; imports accept such a file in place of the JSON.
+ * FRICU_EXPORT_SCRYPT_WORK_FACTOR is log2 of the scrypt cost (default 16, 64 MiB; 10..20). Needs
+ * OpenSSL at build time; without it encryption is refused rather than falling back to plaintext.
+ */
+
+#define AGE_MAGIC "age-encryption.org/v1\n"
+#define AGE_SCRYPT_LABEL "age-encryption.org/v1/scrypt"
+#define AGE_CHUNK_BYTES 65536
+#define AGE_TAG_BYTES 16
+#define AGE_FILE_KEY_BYTES 16
+#define AGE_NONCE_BYTES 16
+#define AGE_COLUMNS 64
+#define EXPORT_DEFAULT_WORK_FACTOR 16
+#define EXPORT_MIN_WORK_FACTOR 10
+#define EXPORT_MAX_WORK_FACTOR 20
+
+int export_encryption_available(void) {
+#ifdef FRICU_HAVE_OPENSSL
+    return 1;
+#else
+    return 0;
+#endif
+}
+
+const char *export_passphrase(void) {
+    const char *passphrase = config_secret("FRICU_EXPORT_PASSPHRASE");
+    return passphrase && passphrase[0] != '\0' ? passphrase : NULL;
+}
+
+static int export_work_factor(void) {
+    const char *env = getenv("FRICU_EXPORT_SCRYPT_WORK_FACTOR");
+    if (!env || env[0] == '\0') return EXPORT_DEFAULT_WORK_FACTOR;
+    char *end = NULL;
+    long value = strtol(env, &end, 10);
+    if (!end || *end != '\0' || value < EXPORT_MIN_WORK_FACTOR || value > EXPORT_MAX_WORK_FACTOR) {
+        log_warn("FRICU_EXPORT_SCRYPT_WORK_FACTOR=%s ignored: expected %d..%d", env, EXPORT_MIN_WORK_FACTOR, EXPORT_MAX_WORK_FACTOR);
+        return EXPORT_DEFAULT_WORK_FACTOR;
+    }
+    return (int)value;
+}
+
+int export_is_encrypted(const char *data, size_t len) {
+    return data && len >= sizeof(AGE_MAGIC) - 1 && memcmp(data, AGE_MAGIC, sizeof(AGE_MAGIC) - 1) == 0;
+}
+
+#ifdef FRICU_HAVE_OPENSSL
+/* age writes base64 without padding. */
+static void base64_raw(const unsigned char *in, size_t len, char *out, size_t out_len) {
+    base64_encode(in, len, out, out_len);
+    out[strcspn(out, "=")] = '\0';
+}
+
+static int decode_exact(const char *text, size_t text_len, unsigned char *out, size_t want) {
+    size_t len = 0;
+    if (memchr(text, '=', text_len)) return -1;
+    unsigned char *decoded = base64_decode(text, text_len, &len);
+    int ok = decoded && len == want;
+    if (ok) memcpy(out, decoded, want);
+    free(decoded);
+    return ok ? 0 : -1;
+}
+
+static int hkdf_sha256(const unsigned char *key, size_t key_len, const unsigned char *salt, size_t salt_len, const char *info, unsigned char out[32]) {
+    EVP_PKEY_CTX *pctx = EVP_PKEY_CTX_new_id(EVP_PKEY_HKDF, NULL);
+    size_t out_len = 32;
+    int ok = pctx && EVP_PKEY_derive_init(pctx) > 0 && EVP_PKEY_CTX_set_hkdf_md(pctx, EVP_sha256()) > 0 &&
+             (salt_len == 0 || EVP_PKEY_CTX_set1_hkdf_salt(pctx, salt, (int)salt_len) > 0) &&
+             EVP_PKEY_CTX_set1_hkdf_key(pctx, key, (int)key_len) > 0 &&
+             EVP_PKEY_CTX_add1_hkdf_info(pctx, (const unsigned char *)info, (int)strlen(info)) > 0 && EVP_PKEY_derive(pctx, out, &out_len) > 0;
+    EVP_PKEY_CTX_free(pctx);
+    return ok && out_len == 32 ? 0 : -1;
+}
+
+/* ChaCha20-Poly1305; out receives len bytes plus the 16-byte tag. */
+static int aead_seal(const unsigned char key[32], const unsigned char nonce[12], const unsigned char *in, size_t len, unsigned char *out) {
+    EVP_CIPHER_CTX *cipher = EVP_CIPHER_CTX_new();
+    int n = 0;
+    int final_len = 0;
+    int ok = cipher && EVP_EncryptInit_ex(cipher, EVP_chacha20_poly1305(), NULL, key, nonce) == 1 &&
+             (len == 0 || EVP_EncryptUpdate(cipher, out, &n, in, (int)len) == 1) && EVP_EncryptFinal_ex(cipher, out + n, &final_len) == 1 &&
+             EVP_CIPHER_CTX_ctrl(cipher, EVP_CTRL_AEAD_GET_TAG, AGE_TAG_BYTES, out + len) == 1;
+    EVP_CIPHER_CTX_free(cipher);
+    return ok ? 0 : -1;
+}
+
+/* in holds len bytes of ciphertext followed by the tag; fails when the tag does not match. */
+static int aead_open(const unsigned char key[32], const unsigned char nonce[12], const unsigned char *in, size_t len, unsigned char *out) {
+    EVP_CIPHER_CTX *cipher = EVP_CIPHER_CTX_new();
+    int n = 0;
+    int final_len = 0;
+    int ok = cipher && EVP_DecryptInit_ex(cipher, EVP_chacha20_poly1305(), NULL, key, nonce) == 1 &&
+             (len == 0 || EVP_DecryptUpdate(cipher, out, &n, in, (int)len) == 1) &&
+             EVP_CIPHER_CTX_ctrl(cipher, EVP_CTRL_AEAD_SET_TAG, AGE_TAG_BYTES, (void *)(in + len)) == 1 &&
+             EVP_DecryptFinal_ex(cipher, out + n, &final_len) == 1;
+    EVP_CIPHER_CTX_free(cipher);
+    return ok ? 0 : -1;
+}
+
+static int scrypt_wrap_key(const char *passphrase, const unsigned char salt[16], int work_factor, unsigned char out[32]) {
+    unsigned char labelled[sizeof(AGE_SCRYPT_LABEL) - 1 + 16];
+    memcpy(labelled, AGE_SCRYPT_LABEL, sizeof(AGE_SCRYPT_LABEL) - 1);
+    memcpy(labelled + sizeof(AGE_SCRYPT_LABEL) - 1, salt, 16);
+    uint64_t n = (uint64_t)1 << work_factor;
+    /* scrypt needs 128 * r * N bytes; leave room for OpenSSL's own bookkeeping. */
+    uint64_t max_mem = 128 * 8 * n * 2;
+    return EVP_PBE_scrypt(passphrase, strlen(passphrase), labelled, sizeof(labelled), n, 8, 1, max_mem, out, 32) == 1 ? 0 : -1;
+}
+
+/* STREAM nonce: 11-byte big-endian chunk counter, then 1 on the final chunk. */
+static void chunk_nonce(uint64_t counter, int last, unsigned char nonce[12]) {
+    memset(nonce, 0, 12);
+    for (int i = 10; i >= 3 && counter > 0; i--) {
+        nonce[i] = (unsigned char)(counter & 0xFF);
+        counter >>= 8;
+    }
+    nonce[11] = last ? 1 : 0;
+}
+#endif
+
+int age_encrypt(const char *passphrase, int work_factor, const unsigned char *in, size_t len, unsigned char **out, size_t *out_len) {
+    *out = NULL;
+    *out_len = 0;
+#ifdef FRICU_HAVE_OPENSSL
+    unsigned char file_key[AGE_FILE_KEY_BYTES];
+    unsigned char salt[16];
+    unsigned char nonce[AGE_NONCE_BYTES];
+    unsigned char wrap_key[32];
+    unsigned char wrapped[AGE_FILE_KEY_BYTES + AGE_TAG_BYTES];
+    unsigned char zero_nonce[12] = {0};
+    if (!passphrase || passphrase[0] == '\0' || RAND_bytes(file_key, sizeof(file_key)) != 1 || RAND_bytes(salt, sizeof(salt)) != 1 ||
+        RAND_bytes(nonce, sizeof(nonce)) != 1 || scrypt_wrap_key(passphrase, salt, work_factor, wrap_key) != 0 ||
+        aead_seal(wrap_key, zero_nonce, file_key, sizeof(file_key), wrapped) != 0) {
+        OPENSSL_cleanse(wrap_key, sizeof(wrap_key));
+        return -1;
+    }
+    OPENSSL_cleanse(wrap_key, sizeof(wrap_key));
+
+    char salt_b64[32] = {0};
+    char wrapped_b64[64] = {0};
+    char header[256] = {0};
+    base64_raw(salt, sizeof(salt), salt_b64, sizeof(salt_b64));
+    base64_raw(wrapped, sizeof(wrapped), wrapped_b64, sizeof(wrapped_b64));
+    int header_len = snprintf(header, sizeof(header), AGE_MAGIC "-> scrypt %s %d\n%s\n---", salt_b64, work_factor, wrapped_b64);
+    unsigned char mac_key[32];
+    unsigned char mac[32];
+    unsigned char payload_key[32];
+    unsigned int mac_len = 0;
+    int ok = header_len > 0 && (size_t)header_len < sizeof(header) - 48 && hkdf_sha256(file_key, sizeof(file_key), NULL, 0, "header", mac_key) == 0 &&
+             HMAC(EVP_sha256(), mac_key, sizeof(mac_key), (const unsigned char *)header, (size_t)header_len, mac, &mac_len) != NULL &&
+             hkdf_sha256(file_key, sizeof(file_key), nonce, sizeof(nonce), "payload", payload_key) == 0;
+    OPENSSL_cleanse(file_key, sizeof(file_key));
+    if (!ok) return -1;
+    char mac_b64[64] = {0};
+    base64_raw(mac, mac_len, mac_b64, sizeof(mac_b64));
+    header_len += snprintf(header + header_len, sizeof(header) - (size_t)header_len, " %s\n", mac_b64);
+
+    size_t chunks = len == 0 ? 1 : (len + AGE_CHUNK_BYTES - 1) / AGE_CHUNK_BYTES;
+    size_t total = (size_t)header_len + AGE_NONCE_BYTES + len + chunks * AGE_TAG_BYTES;
+    unsigned char *buf = (unsigned char *)malloc(total);
+    if (!buf) {
+        OPENSSL_cleanse(payload_key, sizeof(payload_key));
+        return -1;
+    }
+    memcpy(buf, header, (size_t)header_len);
+    memcpy(buf + header_len, nonce, sizeof(nonce));
+    size_t o = (size_t)header_len + AGE_NONCE_BYTES;
+    for (size_t i = 0; i < chunks && ok; i++) {
+        size_t offset = i * AGE_CHUNK_BYTES;
+        size_t chunk = len - offset < AGE_CHUNK_BYTES ? len - offset : AGE_CHUNK_BYTES;
+        unsigned char chunk_iv[12];
+        chunk_nonce(i, i + 1 == chunks, chunk_iv);
+        ok = aead_seal(payload_key, chunk_iv, in + offset, chunk, buf + o) == 0;
+        o += chunk + AGE_TAG_BYTES;
+    }
+    OPENSSL_cleanse(payload_key, sizeof(payload_key));
+    if (!ok) {
+        free(buf);
+        return -1;
+    }
+    *out = buf;
+    *out_len = total;
+    return 0;
+#else
+    (void)passphrase;
+    (void)work_factor;
+    (void)in;
+    (void)len;
+    return -1;
+#endif
+}
+
+#ifdef FRICU_HAVE_OPENSSL
+/* Next header line of an age file: [*p, end of line) with *p moved past the newline. */
+static const char *next_line(const char **p, const char *end, size_t *len) {
+    const char *line = *p;
+    const char *nl = memchr(line, '\n', (size_t)(end - line));
+    if (!nl) return NULL;
+    *len = (size_t)(nl - line);
+    *p = nl + 1;
+    return line;
+}
+#endif
+
+int age_decrypt(const char *passphrase, const char *in, size_t len, char **out, size_t *out_len, char *err, size_t err_len) {
+    *out = NULL;
+    *out_len = 0;
+#ifdef FRICU_HAVE_OPENSSL
+    if (!export_is_encrypted(in, len)) {
+        snprintf(err, err_len, "not an age file");
+        return -1;
+    }
+    if (!passphrase || passphrase[0] == '\0') {
+        snprintf(err, err_len, "no passphrase configured");
+        return -1;
+    }
+    const char *end = in + len;
+    const char *p = in + sizeof(AGE_MAGIC) - 1;
+    size_t line_len = 0;
+    const char *line = NULL;
+    int have_scrypt = 0;
+    int work_factor = 0;
+    unsigned char salt[16] = {0};
+    unsigned char wrapped[AGE_FILE_KEY_BYTES + AGE_TAG_BYTES];
+    /* Stanzas: "-> type args" lines, each followed by base64 body lines ending with a short one. */
+    while ((line = next_line(&p, end, &line_len)) && line_len >= 3 && strncmp(line, "-> ", 3) == 0) {
+        char args[256] = {0};
+        snprintf(args, sizeof(args), "%.*s", (int)(line_len - 3), line + 3);
+        strbuf_t body;
+        strbuf_init(&body);
+        size_t body_line_len = AGE_COLUMNS;
+        const char *body_line = NULL;
+        while (body_line_len == AGE_COLUMNS && (body_line = next_line(&p, end, &body_line_len)) && body_line_len <= AGE_COLUMNS) {
+            strbuf_append(&body, body_line, body_line_len);
+        }
+        if (!body_line || body_line_len > AGE_COLUMNS) {
+            strbuf_free(&body);
+            line = NULL;
+            break;
+        }
+        char salt_b64[64] = {0};
+        char extra = 0;
+        if (strncmp(args, "scrypt ", 7) == 0) {
+            if (have_scrypt || sscanf(args + 7, "%63s %d%c", salt_b64, &work_factor, &extra) != 2 ||
+                decode_exact(salt_b64, strlen(salt_b64), salt, sizeof(salt)) != 0 ||
+                decode_exact(strbuf_cstr(&body), body.len, wrapped, sizeof(wrapped)) != 0) {
+                strbuf_free(&body);
+                snprintf(err, err_len, "malformed scrypt stanza");
+                return -1;
+            }
+            have_scrypt = 1;
+        }
+        strbuf_free(&body);
+    }
+    unsigned char mac[32];
+    if (!line || line_len != 4 + 43 || strncmp(line, "--- ", 4) != 0 || decode_exact(line + 4, 43, mac, sizeof(mac)) != 0) {
+        snprintf(err, err_len, "malformed header");
+        return -1;
+    }
+    if (!have_scrypt) {
+        snprintf(err, err_len, "not encrypted with a passphrase");
+        return -1;
+    }
+    if (work_factor < 1 || work_factor > EXPORT_MAX_WORK_FACTOR) {
+        snprintf(err, err_len, "scrypt work factor %d outside 1..%d", work_factor, EXPORT_MAX_WORK_FACTOR);
+        return -1;
+    }
+
+    unsigned char wrap_key[32];
+    unsigned char file_key[AGE_FILE_KEY_BYTES];
+    unsigned char zero_nonce[12] = {0};
+    int unwrapped = scrypt_wrap_key(passphrase, salt, work_factor, wrap_key) == 0 && aead_open(wrap_key, zero_nonce, wrapped, AGE_FILE_KEY_BYTES, file_key) == 0;
+    OPENSSL_cleanse(wrap_key, sizeof(wrap_key));
+    if (!unwrapped) {
+        snprintf(err, err_len, "wrong passphrase");
+        return -1;
+    }
+    unsigned char mac_key[32];
+    unsigned char expected[32];
+    unsigned char payload_key[32];
+    unsigned int mac_len = 0;
+    const unsigned char *payload = (const unsigned char *)p;
+    size_t payload_len = (size_t)(end - p);
+    int ok = hkdf_sha256(file_key, sizeof(file_key), NULL, 0, "header", mac_key) == 0 &&
+             HMAC(EVP_sha256(), mac_key, sizeof(mac_key), (const unsigned char *)in, (size_t)(line + 3 - in), expected, &mac_len) != NULL &&
+             mac_len == sizeof(expected) && CRYPTO_memcmp(expected, mac, sizeof(mac)) == 0;
+    if (ok && payload_len < AGE_NONCE_BYTES + AGE_TAG_BYTES) ok = 0;
+    if (ok) ok = hkdf_sha256(file_key, sizeof(file_key), payload, AGE_NONCE_BYTES, "payload", payload_key) == 0;
+    OPENSSL_cleanse(file_key, sizeof(file_key));
+    if (!ok) {
+        snprintf(err, err_len, "header is corrupt");
+        return -1;
+    }
+
+    payload += AGE_NONCE_BYTES;
+    payload_len -= AGE_NONCE_BYTES;
+    char *buf = (char *)malloc(payload_len + 1);
+    size_t o = 0;
+    uint64_t counter = 0;
+    ok = buf != NULL;
+    while (ok && payload_len > 0) {
+        int last = payload_len <= AGE_CHUNK_BYTES + AGE_TAG_BYTES;
+        size_t chunk = last ? payload_len : AGE_CHUNK_BYTES + AGE_TAG_BYTES;
+        unsigned char chunk_iv[12];
+        chunk_nonce(counter++, last, chunk_iv);
+        /* An empty final chunk is only valid for an empty file. */
+        ok = chunk >= AGE_TAG_BYTES && (chunk > AGE_TAG_BYTES || counter == 1) &&
+             aead_open(payload_key, chunk_iv, payload, chunk - AGE_TAG_BYTES, (unsigned char *)buf + o) == 0;
+        o += chunk - AGE_TAG_BYTES;
+        payload += chunk;
+        payload_len -= chunk;
+    }
+    OPENSSL_cleanse(payload_key, sizeof(payload_key));
+    if (!ok) {
+        free(buf);
+        snprintf(err, err_len, "payload is corrupt or truncated");
+        return -1;
+    }
+    buf[o] = '\0';
+    *out = buf;
+    *out_len = o;
+    return 0;
+#else
+    (void)passphrase;
+    (void)in;
+    (void)len;
+    snprintf(err, err_len, "server built without OpenSSL");
+    return -1;
+#endif
+}
+
+int export_encrypt(const unsigned char *in, size_t len, unsigned char **out, size_t *out_len) {
+    return age_encrypt(export_passphrase(), export_work_factor(), in, len, out, out_len);
+}
+
+int export_decrypt(const char *in, size_t len, char **out, size_t *out_len, char *err, size_t err_len) {
+    return age_decrypt(export_passphrase(), in, len, out, out_len, err, err_len);
+}
+
+int export_decrypt_file(const char *path, FILE *out) {
+    FILE *fp = fopen(path, "rb");
+    if (!fp) {
+        fprintf(stderr, "cannot open %s\n", path);
+        return -1;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    char chunk[65536];
+    size_t n = 0;
+    while ((n = fread(chunk, 1, sizeof(chunk), fp)) > 0) strbuf_append(&sb, chunk, n);
+    fclose(fp);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        fprintf(stderr, "out of memory reading %s\n", path);
+        return -1;
+    }
+    char *plain = NULL;
+    size_t plain_len = 0;
+    char err[128] = {0};
+    int rc = export_decrypt(strbuf_cstr(&sb), sb.len, &plain, &plain_len, err, sizeof(err));
+    strbuf_free(&sb);
+    if (rc != 0) {
+        fprintf(stderr, "cannot decrypt %s: %s\n", path, err);
+        return -1;
+    }
+    rc = fwrite(plain, 1, plain_len, out) == plain_len && fflush(out) == 0 ? 0 : -1;
+    free(plain);
+    return rc;
+}
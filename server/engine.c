@@ -0,0 +1,310 @@
+#define _GNU_SOURCE
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netinet/in.h>
+#include <pthread.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/resource.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+#include "fricu.h"
+#include "logger.h"
+#include "server.h"
+#include "server_internal.h"
+
+/*
+ * The server proper, as library code: opens the database, starts the background threads, binds
+ * the listening socket and runs the workers. Everything except argument and environment handling
+ * lives in libfricu.a, which fricu-server and the unit tests link. engine_run serves until the
+ * workers exit; fricu_run_embedded (fricu.h) is the same engine for a desktop app that bundles
+ * the server in-process: it listens on the loopback with a free port by default, returns once
+ * the socket is open, and fricu_server_stop wakes every worker through a pipe, joins them and
+ * closes the listener. Either way a stop drains: workers stop accepting, finish the requests
+ * they are receiving (event_loop.c), and once they are joined the WAL is checkpointed so the
+ * database file is complete on its own. engine_run starts that drain on SIGTERM or SIGINT
+ * (with_graceful_shutdown); a second signal kills the process. The draining flag is the server's
+ * own, so in a process with several servers only the one stopping reports it. With FRICU_MDNS=1 a server also advertises itself on the LAN (mdns.c) until
+ * it stops. The background threads (snapshots, connectors, bots, cluster lease, Redis)
+ * are started by the first server in a process and live as long as the process.
+ */
+
+#define EMBEDDED_DEFAULT_WORKERS 4
+
+typedef struct {
+    int listen_fd;
+    int stop_fd;
+    char db_path[512];
+    size_t max_fds;
+    volatile sig_atomic_t *draining;
+} worker_ctx_t;
+
+struct fricu_server {
+    int listen_fd;
+    int stop_pipe[2];
+    int port;
+    char base_url[160];
+    char db_path[512];
+    size_t worker_count;
+    worker_ctx_t *workers;
+    pthread_t *threads;
+    mdns_responder_t *mdns;
+    volatile sig_atomic_t draining;
+};
+
+static pthread_mutex_t g_background_mutex = PTHREAD_MUTEX_INITIALIZER;
+static int g_background_started;
+static int g_signal_stop_fd = -1;
+static volatile sig_atomic_t *g_signal_draining;
+static __thread volatile sig_atomic_t *g_worker_draining;
+
+int engine_draining(void) {
+    return g_worker_draining && *g_worker_draining != 0;
+}
+
+int engine_server_draining(const fricu_server_t *server) {
+    return server && server->draining != 0;
+}
+
+static void *worker_entry(void *arg) {
+    worker_ctx_t *ctx = (worker_ctx_t *)arg;
+    g_worker_draining = ctx->draining;
+    if (run_worker_loop(ctx->listen_fd, ctx->stop_fd, ctx->db_path, ctx->max_fds) != 0) {
+        log_error("worker loop exited with error");
+    }
+    return NULL;
+}
+
+static int start_background(const char *db_path) {
+    pthread_mutex_lock(&g_background_mutex);
+    int rc = 0;
+    if (!g_background_started) {
+        if (cluster_start(db_path) != 0 || redis_start() != 0 || snapshots_start(db_path) != 0 || connectors_start(db_path) != 0 ||
+            bots_start(db_path) != 0 || backup_schedule_start(db_path) != 0) {
+            rc = -1;
+        }
+        g_background_started = rc == 0;
+    }
+    pthread_mutex_unlock(&g_background_mutex);
+    return rc;
+}
+
+/* Binds host:port (0 picks a free port) and reports the port actually bound. */
+static int open_listener(const char *host, int port, int *out_port) {
+    int server_fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (server_fd < 0) {
+        log_error("socket creation failed: errno=%d", errno);
+        return -1;
+    }
+
+    int opt = 1;
+    setsockopt(server_fd, SOL_SOCKET, SO_REUSEADDR, &opt, sizeof(opt));
+#ifdef SO_REUSEPORT
+    /* Two embedded servers on one random port would split each other's connections. */
+    if (port != 0) setsockopt(server_fd, SOL_SOCKET, SO_REUSEPORT, &opt, sizeof(opt));
+#endif
+
+    int backlog = 65535;
+    setsockopt(server_fd, SOL_SOCKET, SO_RCVBUF, &backlog, sizeof(backlog));
+    setsockopt(server_fd, SOL_SOCKET, SO_SNDBUF, &backlog, sizeof(backlog));
+
+    if (set_nonblocking(server_fd) != 0) {
+        log_error("set_nonblocking failed: errno=%d", errno);
+        close(server_fd);
+        return -1;
+    }
+
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons((uint16_t)port);
+    if (inet_pton(AF_INET, host, &addr.sin_addr) <= 0) {
+        log_error("invalid bind host: %s", host);
+        close(server_fd);
+        return -1;
+    }
+
+    if (bind(server_fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) {
+        log_error("bind failed: errno=%d", errno);
+        close(server_fd);
+        return -1;
+    }
+
+    if (listen(server_fd, 65535) < 0) {
+        log_error("listen failed: errno=%d", errno);
+        close(server_fd);
+        return -1;
+    }
+
+    socklen_t addr_len = sizeof(addr);
+    if (getsockname(server_fd, (struct sockaddr *)&addr, &addr_len) != 0) {
+        log_error("getsockname failed: errno=%d", errno);
+        close(server_fd);
+        return -1;
+    }
+    *out_port = ntohs(addr.sin_port);
+    return server_fd;
+}
+
+static void server_free(fricu_server_t *server) {
+    mdns_stop(server->mdns);
+    if (server->listen_fd >= 0) close(server->listen_fd);
+    if (server->stop_pipe[0] >= 0) close(server->stop_pipe[0]);
+    if (server->stop_pipe[1] >= 0) close(server->stop_pipe[1]);
+    free(server->workers);
+    free(server->threads);
+    free(server);
+}
+
+static void server_join(fricu_server_t *server, size_t started) {
+    for (size_t i = 0; i < started; i++) {
+        pthread_join(server->threads[i], NULL);
+    }
+}
+
+/* The byte is never read, so the pipe stays readable and wakes every worker. */
+static void wake_workers(int stop_fd) {
+    char wake = 1;
+    while (write(stop_fd, &wake, 1) < 0 && errno == EINTR) {
+    }
+}
+
+/* After the workers are joined every answered write is committed; fold the WAL into the file. */
+static void server_finish(fricu_server_t *server) {
+    server_join(server, server->worker_count);
+    otel_flush();
+    db_checkpoint(server->db_path);
+    server->draining = 0;
+}
+
+static void on_shutdown_signal(int sig) {
+    (void)sig;
+    int saved = errno;
+    if (g_signal_draining) *g_signal_draining = 1;
+    if (g_signal_stop_fd >= 0) wake_workers(g_signal_stop_fd);
+    errno = saved;
+}
+
+static void with_graceful_shutdown(fricu_server_t *server) {
+    g_signal_stop_fd = server->stop_pipe[1];
+    g_signal_draining = &server->draining;
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_handler = on_shutdown_signal;
+    sigemptyset(&sa.sa_mask);
+    sa.sa_flags = SA_RESTART | SA_RESETHAND;
+    sigaction(SIGTERM, &sa, NULL);
+    sigaction(SIGINT, &sa, NULL);
+}
+
+static fricu_server_t *server_start(const char *host, int port, const char *db_path, size_t worker_count) {
+    if (tune_fd_limit() != 0) {
+        log_warn("failed to tune fd limit, continuing");
+    }
+
+    if (init_db(db_path) != 0) return NULL;
+    if (start_background(db_path) != 0) return NULL;
+    if (tls_configure() != 0) return NULL;
+
+    fricu_server_t *server = (fricu_server_t *)calloc(1, sizeof(fricu_server_t));
+    if (!server) return NULL;
+    server->listen_fd = -1;
+    server->stop_pipe[0] = -1;
+    server->stop_pipe[1] = -1;
+    server->worker_count = worker_count;
+    snprintf(server->db_path, sizeof(server->db_path), "%s", db_path);
+    if (pipe(server->stop_pipe) != 0 || (server->listen_fd = open_listener(host, port, &server->port)) < 0) {
+        server_free(server);
+        return NULL;
+    }
+    snprintf(server->base_url, sizeof(server->base_url), "%s://%s:%d", tls_enabled() ? "https" : "http", host, server->port);
+
+    const char *redirect_env = getenv("FRICU_TLS_REDIRECT_BIND");
+    if (tls_enabled() && redirect_env && redirect_env[0] != '\0' && tls_redirect_start(redirect_env, server->port) != 0) {
+        server_free(server);
+        return NULL;
+    }
+
+    struct rlimit lim;
+    if (getrlimit(RLIMIT_NOFILE, &lim) != 0) {
+        log_warn("getrlimit failed, using fallback max_fds");
+        lim.rlim_cur = 65535;
+    }
+    size_t max_fds = (size_t)lim.rlim_cur;
+
+    server->workers = (worker_ctx_t *)calloc(worker_count, sizeof(worker_ctx_t));
+    server->threads = (pthread_t *)calloc(worker_count, sizeof(pthread_t));
+    if (!server->workers || !server->threads) {
+        log_error("failed to allocate worker structures");
+        server_free(server);
+        return NULL;
+    }
+
+    for (size_t i = 0; i < worker_count; i++) {
+        server->workers[i].listen_fd = server->listen_fd;
+        server->workers[i].stop_fd = server->stop_pipe[0];
+        server->workers[i].max_fds = max_fds;
+        server->workers[i].draining = &server->draining;
+        snprintf(server->workers[i].db_path, sizeof(server->workers[i].db_path), "%s", db_path);
+        if (pthread_create(&server->threads[i], NULL, worker_entry, &server->workers[i]) != 0) {
+            log_error("failed to start worker %zu", i);
+            server->worker_count = i;
+            fricu_server_stop(server);
+            return NULL;
+        }
+    }
+    server->mdns = mdns_start(host, server->port);
+    return server;
+}
+
+int engine_run(const char *bind_addr_str, const char *db_path, size_t worker_count) {
+    char host[128] = {0};
+    int port = 8080;
+    if (parse_bind_addr(bind_addr_str, host, sizeof(host), &port) != 0) {
+        log_error("invalid FRICU_SERVER_BIND: %s", bind_addr_str);
+        return 1;
+    }
+    fricu_server_t *server = server_start(host, port, db_path, worker_count);
+    if (!server) return 1;
+
+    with_graceful_shutdown(server);
+    log_info("fricu-server listening on %s (workers=%zu, async_io=auto, tls=%s)", bind_addr_str, worker_count, tls_enabled() ? "on" : "off");
+    server_finish(server);
+    g_signal_stop_fd = -1;
+    g_signal_draining = NULL;
+    log_info("fricu-server stopped on %s", bind_addr_str);
+    server_free(server);
+    return 0;
+}
+
+fricu_server_t *fricu_run_embedded(const fricu_embedded_config_t *config) {
+    if (!config || !config->db_path || config->db_path[0] == '\0' || config->port < 0 || config->port > 65535) {
+        log_error("embedded server needs a db_path and a port in 0..65535");
+        return NULL;
+    }
+    const char *host = config->host && config->host[0] != '\0' ? config->host : "127.0.0.1";
+    size_t workers = config->workers > 0 && config->workers <= 1024 ? config->workers : EMBEDDED_DEFAULT_WORKERS;
+    fricu_server_t *server = server_start(host, config->port, config->db_path, workers);
+    if (server) log_info("fricu-server embedded at %s (workers=%zu)", server->base_url, workers);
+    return server;
+}
+
+const char *fricu_server_base_url(const fricu_server_t *server) {
+    return server ? server->base_url : "";
+}
+
+int fricu_server_port(const fricu_server_t *server) {
+    return server ? server->port : 0;
+}
+
+void fricu_server_stop(fricu_server_t *server) {
+    if (!server) return;
+    server->draining = 1;
+    wake_workers(server->stop_pipe[1]);
+    server_finish(server);
+    log_info("fricu-server stopped at %s", server->base_url);
+    server_free(server);
+}
@@ -19,8 +19,17 @@
 #error "Unsupported platform: only Linux and macOS are supported"
 #endif
 
-static void close_conn(int qfd, conn_t **conns, int fd) {
-    if (fd < 0) return;
+/*
+ * Stopping (the stop pipe becomes readable): the worker takes the listener and the stop pipe out
+ * of its queue, so it accepts nothing more, and drops the connections that have not sent a byte.
+ * Connections in the middle of a request keep being served until they are answered, or until
+ * FRICU_SHUTDOWN_TIMEOUT_SEC (default 10, inside the service units' 30 s stop timeout) runs out
+ * and whatever is left is dropped. Every response closes its connection, so nothing lingers.
+ */
+
+#define DRAIN_DEFAULT_TIMEOUT_SEC 10
+
+static void unregister_fd(int qfd, int fd) {
 #if defined(__linux__)
     epoll_ctl(qfd, EPOLL_CTL_DEL, fd, NULL);
 #elif defined(__APPLE__)
@@ -28,7 +37,14 @@ static void close_conn(int qfd, conn_t **conns, int fd) {
     EV_SET(&ev, fd, EVFILT_READ, EV_DELETE, 0, 0, NULL);
     kevent(qfd, &ev, 1, NULL, 0, NULL);
 #endif
+}
+
+static void close_conn(int qfd, conn_t **conns, int fd) {
+    if (fd < 0) return;
+    unregister_fd(qfd, fd);
     if (conns[fd]) {
+        profiling_note_connection(-1, -(long long)conns[fd]->cap);
+        tls_session_free(conns[fd]->tls);
         free(conns[fd]->buf);
         free(conns[fd]);
         conns[fd] = NULL;
@@ -36,17 +52,21 @@ static void close_conn(int qfd, conn_t **conns, int fd) {
     close(fd);
 }
 
-static int register_listen_fd(int qfd, int listen_fd) {
+/* The listener wakes one worker per connection; the stop pipe has to wake them all. */
+static int register_listen_fd(int qfd, int listen_fd, int exclusive) {
 #if defined(__linux__)
     struct epoll_event ev;
     memset(&ev, 0, sizeof(ev));
     ev.events = EPOLLIN;
 #ifdef EPOLLEXCLUSIVE
-    ev.events |= EPOLLEXCLUSIVE;
+    if (exclusive) ev.events |= EPOLLEXCLUSIVE;
+#else
+    (void)exclusive;
 #endif
     ev.data.fd = listen_fd;
     return epoll_ctl(qfd, EPOLL_CTL_ADD, listen_fd, &ev);
 #elif defined(__APPLE__)
+    (void)exclusive;
     struct kevent ev;
     EV_SET(&ev, listen_fd, EVFILT_READ, EV_ADD | EV_ENABLE, 0, 0, NULL);
     return kevent(qfd, &ev, 1, NULL, 0, NULL);
@@ -67,10 +87,11 @@ static int register_client_fd(int qfd, int fd) {
 #endif
 }
 
-static int queue_wait(int qfd, int listen_fd, int *fds, int *is_err, int max_events) {
+/* timeout_ms < 0 waits for an event; otherwise 0 means the timeout passed. */
+static int queue_wait(int qfd, int listen_fd, int *fds, int *is_err, int max_events, int timeout_ms) {
 #if defined(__linux__)
     struct epoll_event events[EVENT_MAX_EVENTS];
-    int n = epoll_wait(qfd, events, max_events, -1);
+    int n = epoll_wait(qfd, events, max_events, timeout_ms);
     if (n < 0) return n;
     for (int i = 0; i < n; i++) {
         fds[i] = events[i].data.fd;
@@ -79,7 +100,8 @@ static int queue_wait(int qfd, int listen_fd, int *fds, int *is_err, int max_eve
     return n;
 #elif defined(__APPLE__)
     struct kevent events[EVENT_MAX_EVENTS];
-    int n = kevent(qfd, NULL, 0, events, max_events, NULL);
+    struct timespec timeout = {.tv_sec = timeout_ms / 1000, .tv_nsec = (long)(timeout_ms % 1000) * 1000000L};
+    int n = kevent(qfd, NULL, 0, events, max_events, timeout_ms < 0 ? NULL : &timeout);
     if (n < 0) return n;
     for (int i = 0; i < n; i++) {
         fds[i] = (int)events[i].ident;
@@ -105,7 +127,44 @@ static int accept_client(int listen_fd) {
     return fd;
 }
 
-int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
+static size_t close_all(int qfd, conn_t **conns, size_t max_fds) {
+    size_t closed = 0;
+    for (size_t fd = 0; fd <= max_fds; fd++) {
+        if (conns[fd]) {
+            close_conn(qfd, conns, (int)fd);
+            closed++;
+        }
+    }
+    return closed;
+}
+
+static size_t count_conns(conn_t **conns, size_t max_fds) {
+    size_t open = 0;
+    for (size_t fd = 0; fd <= max_fds; fd++) {
+        if (conns[fd]) open++;
+    }
+    return open;
+}
+
+static int drain_timeout_ms(void) {
+    const char *env = getenv("FRICU_SHUTDOWN_TIMEOUT_SEC");
+    int sec = env && env[0] != '\0' ? atoi(env) : DRAIN_DEFAULT_TIMEOUT_SEC;
+    if (sec < 0) sec = DRAIN_DEFAULT_TIMEOUT_SEC;
+    if (sec > 3600) sec = 3600;
+    return sec * 1000;
+}
+
+/* A connection is idle when nothing of a request has arrived, not even unread in the socket. */
+static void close_idle_conns(int qfd, conn_t **conns, size_t max_fds) {
+    for (size_t fd = 0; fd <= max_fds; fd++) {
+        char peek;
+        if (conns[fd] && conns[fd]->len == 0 && recv((int)fd, &peek, 1, MSG_PEEK | MSG_DONTWAIT) <= 0) {
+            close_conn(qfd, conns, (int)fd);
+        }
+    }
+}
+
+int run_worker_loop(int listen_fd, int stop_fd, const char *db_path, size_t max_fds) {
     worker_db_t db;
     if (worker_db_open(&db, db_path) != 0) return -1;
 
@@ -127,7 +186,7 @@ int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
         return -1;
     }
 
-    if (register_listen_fd(qfd, listen_fd) != 0) {
+    if (register_listen_fd(qfd, listen_fd, 1) != 0 || (stop_fd >= 0 && register_listen_fd(qfd, stop_fd, 0) != 0)) {
         log_error("failed to register listen fd in event queue: errno=%d", errno);
         free(conns);
         close(qfd);
@@ -137,19 +196,39 @@ int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
 
     int fds[EVENT_MAX_EVENTS];
     int errs[EVENT_MAX_EVENTS];
+    size_t high_fd = 0;
+    int draining = 0;
+    double drain_deadline = 0;
 
     while (1) {
-        int n = queue_wait(qfd, listen_fd, fds, errs, EVENT_MAX_EVENTS);
+        int timeout_ms = -1;
+        if (draining) {
+            double left = drain_deadline - slowlog_now_ms();
+            if (left <= 0 || count_conns(conns, high_fd) == 0) break;
+            timeout_ms = (int)left + 1;
+        }
+        int n = queue_wait(qfd, listen_fd, fds, errs, EVENT_MAX_EVENTS, timeout_ms);
         if (n < 0) {
             if (errno == EINTR) continue;
             log_warn("event wait error: errno=%d", errno);
             continue;
         }
+        slowlog_note_wakeup();
 
         for (int i = 0; i < n; i++) {
             int fd = fds[i];
+            if (stop_fd >= 0 && fd == stop_fd) {
+                if (!draining) {
+                    draining = 1;
+                    drain_deadline = slowlog_now_ms() + drain_timeout_ms();
+                    unregister_fd(qfd, listen_fd);
+                    unregister_fd(qfd, stop_fd);
+                    close_idle_conns(qfd, conns, high_fd);
+                }
+                continue;
+            }
             if (fd == listen_fd) {
-                while (1) {
+                while (!draining) {
                     int client_fd = accept_client(listen_fd);
                     if (client_fd < 0) {
                         if (errno == EAGAIN || errno == EWOULDBLOCK) break;
@@ -182,7 +261,15 @@ int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
                         continue;
                     }
                     conn->fd = client_fd;
+                    if (tls_enabled() && !(conn->tls = tls_session_new(client_fd))) {
+                        free(conn->buf);
+                        free(conn);
+                        close(client_fd);
+                        continue;
+                    }
                     conns[client_fd] = conn;
+                    if ((size_t)client_fd > high_fd) high_fd = (size_t)client_fd;
+                    profiling_note_connection(1, (long long)conn->cap);
 
                     if (register_client_fd(qfd, client_fd) != 0) {
                         close_conn(qfd, conns, client_fd);
@@ -202,24 +289,28 @@ int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
                 continue;
             }
 
+            tls_set_current(fd, conn->tls);
+            size_t buffer_max = request_buffer_max();
             while (1) {
-                if (conn->len == conn->cap && conn->cap < REQ_BUF_SIZE) {
+                if (conn->len == conn->cap && conn->cap < buffer_max) {
                     size_t next = conn->cap * 2;
-                    if (next > REQ_BUF_SIZE) next = REQ_BUF_SIZE;
+                    if (next > buffer_max) next = buffer_max;
                     char *nb = (char *)realloc(conn->buf, next + 1);
                     if (!nb) {
                         send_response(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}");
                         close_conn(qfd, conns, fd);
                         break;
                     }
+                    profiling_note_connection(0, (long long)(next - conn->cap));
                     conn->buf = nb;
                     conn->cap = next;
                 }
 
-                ssize_t r = recv(fd, conn->buf + conn->len, conn->cap - conn->len, 0);
+                ssize_t r = conn->tls ? tls_recv(conn->tls, conn->buf + conn->len, conn->cap - conn->len)
+                                      : recv(fd, conn->buf + conn->len, conn->cap - conn->len, 0);
                 if (r > 0) {
                     conn->len += (size_t)r;
-                    if (conn->len >= REQ_BUF_SIZE) {
+                    if (conn->len >= buffer_max) {
                         send_response(fd, 413, "Payload Too Large", "{\"error\":\"request too large\"}");
                         close_conn(qfd, conns, fd);
                         break;
@@ -241,6 +332,14 @@ int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds) {
                 close_conn(qfd, conns, fd);
                 break;
             }
+            tls_set_current(-1, NULL);
         }
     }
+
+    size_t dropped = close_all(qfd, conns, high_fd);
+    if (dropped > 0) log_warn("shutdown timeout: dropped %zu unfinished connections", dropped);
+    free(conns);
+    close(qfd);
+    worker_db_close(&db);
+    return 0;
 }
@@ -0,0 +1,249 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Optional event-sourced storage (FRICU_EVENT_SOURCING=1). Every document write becomes an
+ * immutable row in data_events, appended by triggers on kv_store inside the same statement as the
+ * write, so no write path can skip it; kv_store is then only the projection "latest event per key".
+ * Rows written while the mode was off are captured as `baseline` events when it is switched on.
+ * data_events rejects UPDATE and DELETE in every mode. POST /v1/admin/events/rebuild replays the
+ * log into kv_store (?dry_run=1 only reports the keys whose stored document differs from the
+ * projection) and GET /v1/admin/events lists the log for audits and delta sync.
+ */
+
+#define EVENT_LIST_DEFAULT_LIMIT 100
+#define EVENT_LIST_MAX_LIMIT 1000
+#define EVENT_MISMATCHES_MAX 20
+
+#define EVENT_TRIGGERS_SQL                                                                                        \
+    "CREATE TRIGGER IF NOT EXISTS kv_store_event_insert AFTER INSERT ON kv_store BEGIN"                           \
+    " INSERT INTO data_events (data_key, op, payload, created_at) VALUES (NEW.data_key, 'put', NEW.data_value, NEW.updated_at);" \
+    " END;"                                                                                                       \
+    "CREATE TRIGGER IF NOT EXISTS kv_store_event_update AFTER UPDATE OF data_value ON kv_store"                   \
+    " WHEN OLD.data_value IS NOT NEW.data_value BEGIN"                                                            \
+    " INSERT INTO data_events (data_key, op, payload, created_at) VALUES (NEW.data_key, 'put', NEW.data_value, NEW.updated_at);" \
+    " END;"
+
+#define EVENT_DROP_TRIGGERS_SQL "DROP TRIGGER IF EXISTS kv_store_event_insert; DROP TRIGGER IF EXISTS kv_store_event_update;"
+
+/* The projection: the newest event of every key. */
+#define EVENT_PROJECTION_SQL \
+    "SELECT e.data_key, e.payload, e.created_at FROM data_events e WHERE e.seq = (SELECT MAX(seq) FROM data_events WHERE data_key = e.data_key)"
+
+int event_sourcing_enabled(void) {
+    const char *env = getenv("FRICU_EVENT_SOURCING");
+    return env && strcmp(env, "1") == 0;
+}
+
+int event_log_configure(sqlite3 *db) {
+    char *err = NULL;
+    const char *sql = event_sourcing_enabled()
+                          ? "INSERT INTO data_events (data_key, op, payload, created_at)"
+                            " SELECT k.data_key, 'baseline', k.data_value, k.updated_at FROM kv_store k"
+                            " WHERE k.data_value IS NOT (SELECT payload FROM data_events e WHERE e.data_key = k.data_key ORDER BY e.seq DESC LIMIT 1)"
+                            " ORDER BY k.data_key;" EVENT_TRIGGERS_SQL
+                          : EVENT_DROP_TRIGGERS_SQL;
+    if (sqlite3_exec(db, sql, NULL, NULL, &err) != SQLITE_OK) {
+        log_error("failed to configure event log: %s", err ? err : "unknown");
+        sqlite3_free(err);
+        return -1;
+    }
+    if (event_sourcing_enabled()) log_info("DB event sourcing enabled baseline_events=%d", sqlite3_changes(db));
+    return 0;
+}
+
+static int handle_list_events(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char account[128] = {0};
+    char key[128] = {0};
+    char raw[32] = {0};
+    long long after = 0;
+    int limit = EVENT_LIST_DEFAULT_LIMIT;
+    query_param(req->query, "account", account, sizeof(account));
+    query_param(req->query, "key", key, sizeof(key));
+    if (query_param(req->query, "after", raw, sizeof(raw))) after = strtoll(raw, NULL, 10);
+    if (query_param(req->query, "limit", raw, sizeof(raw))) limit = atoi(raw);
+    if (after < 0 || limit < 1 || limit > EVENT_LIST_MAX_LIMIT) {
+        char body[96] = {0};
+        snprintf(body, sizeof(body), "{\"error\":\"after must be >= 0 and limit 1..%d\"}", EVENT_LIST_MAX_LIMIT);
+        send_response_with_log_context(fd, 400, "Bad Request", body, ctx);
+        return 400;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT seq, data_key, op, length(CAST(payload AS BLOB)), created_at FROM data_events"
+            " WHERE seq > ?1 AND (?2 = '' OR substr(data_key, 1, length(?2) + 2) = ?2 || '::')"
+            " AND (?3 = '' OR data_key = ?3 OR substr(data_key, -length(?3) - 2) = '::' || ?3)"
+            " ORDER BY seq LIMIT ?4",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_int64(stmt, 1, after);
+    sqlite3_bind_text(stmt, 2, account, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 4, limit);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"enabled\":%s,\"events\":[", event_sourcing_enabled() ? "true" : "false");
+    int count = 0;
+    long long last_seq = after;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *data_key = (const char *)sqlite3_column_text(stmt, 1);
+        const char *sep = strstr(data_key, "::");
+        last_seq = sqlite3_column_int64(stmt, 0);
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_appendf(&sb, "{\"seq\":%lld,\"account\":", last_seq);
+        if (sep) {
+            strbuf_appendf(&sb, "\"%.*s\",\"key\":", (int)(sep - data_key), data_key);
+            strbuf_append_json_string(&sb, sep + 2);
+        } else {
+            strbuf_append(&sb, "null,\"key\":", 11);
+            strbuf_append_json_string(&sb, data_key);
+        }
+        strbuf_appendf(
+            &sb,
+            ",\"op\":\"%s\",\"bytes\":%d,\"created_at\":%lld}",
+            (const char *)sqlite3_column_text(stmt, 2),
+            sqlite3_column_int(stmt, 3),
+            sqlite3_column_int64(stmt, 4));
+    }
+    sqlite3_finalize(stmt);
+    strbuf_appendf(&sb, "],\"next_after\":%lld}", last_seq);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* Announces the rewritten documents so caches and streams see the restored state. */
+static void emit_rebuilt_keys(sqlite3 *db) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT k.data_key, k.data_value FROM temp.event_rebuild_keys r JOIN kv_store k ON k.data_key = r.data_key ORDER BY k.data_key",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return;
+    }
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *data_key = (const char *)sqlite3_column_text(stmt, 0);
+        const char *payload = (const char *)sqlite3_column_text(stmt, 1);
+        const char *sep = strstr(data_key, "::");
+        if (!sep || !payload) continue;
+        char account_id[128] = {0};
+        snprintf(account_id, sizeof(account_id), "%.*s", (int)(sep - data_key), data_key);
+        change_events_emit_local(account_id, sep + 2, payload, strlen(payload));
+    }
+    sqlite3_finalize(stmt);
+}
+
+static int handle_rebuild_events(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw[8] = {0};
+    int dry_run = query_param(req->query, "dry_run", raw, sizeof(raw)) && strcmp(raw, "1") == 0;
+    if (!dry_run && !cluster_is_writer()) return cluster_reject_write(fd, ctx);
+    static const char MISMATCH_SQL[] = "SELECT p.data_key, p.payload FROM (" EVENT_PROJECTION_SQL ") p"
+                                       " LEFT JOIN kv_store k ON k.data_key = p.data_key WHERE k.data_value IS NOT p.payload ORDER BY p.data_key";
+
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT (SELECT COUNT(*) FROM data_events), (SELECT COUNT(DISTINCT data_key) FROM data_events),"
+            " (SELECT COUNT(*) FROM kv_store k WHERE NOT EXISTS (SELECT 1 FROM data_events e WHERE e.data_key = k.data_key))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    long long events = 0, keys = 0, unprojected = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        events = sqlite3_column_int64(stmt, 0);
+        keys = sqlite3_column_int64(stmt, 1);
+        unprojected = sqlite3_column_int64(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"dry_run\":%s,\"events\":%lld,\"keys\":%lld,\"unprojected\":%lld,\"mismatches\":[", dry_run ? "true" : "false", events, keys, unprojected);
+    int mismatches = 0;
+    if (sqlite3_prepare_v2(db->db, MISMATCH_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (mismatches++ >= EVENT_MISMATCHES_MAX) continue;
+        if (mismatches > 1) strbuf_append(&sb, ",", 1);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+    }
+    sqlite3_finalize(stmt);
+
+    if (!dry_run && mismatches > 0) {
+        /* The triggers are dropped for the replay so restoring the projection does not append events. */
+        char *err = NULL;
+        int rc = sqlite3_exec(
+            db->db,
+            "BEGIN IMMEDIATE;" EVENT_DROP_TRIGGERS_SQL
+            "CREATE TEMP TABLE event_rebuild_keys AS SELECT p.data_key FROM (" EVENT_PROJECTION_SQL ") p"
+            " LEFT JOIN kv_store k ON k.data_key = p.data_key WHERE k.data_value IS NOT p.payload;"
+            "INSERT INTO kv_store (data_key, data_value, updated_at) SELECT data_key, payload, created_at FROM (" EVENT_PROJECTION_SQL ") WHERE 1"
+            " ON CONFLICT(data_key) DO UPDATE SET data_value = excluded.data_value, updated_at = excluded.updated_at"
+            " WHERE kv_store.data_value IS NOT excluded.data_value;",
+            NULL,
+            NULL,
+            &err);
+        if (rc == SQLITE_OK && event_sourcing_enabled()) rc = sqlite3_exec(db->db, EVENT_TRIGGERS_SQL, NULL, NULL, &err);
+        if (rc == SQLITE_OK) rc = sqlite3_exec(db->db, "COMMIT;", NULL, NULL, &err);
+        if (rc != SQLITE_OK) {
+            log_error("EVENTS rebuild failed: %s", err ? err : "unknown");
+            sqlite3_free(err);
+            sqlite3_exec(db->db, "ROLLBACK; DROP TABLE IF EXISTS temp.event_rebuild_keys;", NULL, NULL, NULL);
+            strbuf_free(&sb);
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        emit_rebuilt_keys(db->db);
+        sqlite3_exec(db->db, "DROP TABLE IF EXISTS temp.event_rebuild_keys;", NULL, NULL, NULL);
+    }
+    strbuf_appendf(&sb, "],\"mismatch_count\":%d,\"rebuilt\":%d}", mismatches, dry_run ? 0 : mismatches);
+    log_info("EVENTS rebuild dry_run=%d events=%lld keys=%lld mismatches=%d logid=%s", dry_run, events, keys, mismatches, ctx->log_id);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+int route_admin_events(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->path, "/v1/admin/events") == 0 && strcmp(req->method, "GET") == 0) return handle_list_events(fd, db, req, ctx);
+    if (strcmp(req->path, "/v1/admin/events/rebuild") == 0 && strcmp(req->method, "POST") == 0) return handle_rebuild_events(fd, db, req, ctx);
+    if (strcmp(req->path, "/v1/admin/events") == 0 || strcmp(req->path, "/v1/admin/events/rebuild") == 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+    return 404;
+}
@@ -0,0 +1,73 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * In-process change-event hub. Every stored document write is announced here; listeners
+ * (stream endpoints, caches) subscribe once and receive both local writes and, when Redis is
+ * configured, writes applied by other instances.
+ */
+
+#define CHANGE_EVENTS_MAX_LISTENERS 32
+
+typedef struct {
+    int id;
+    change_listener_fn fn;
+    void *user;
+} change_listener_t;
+
+static pthread_mutex_t g_events_mutex = PTHREAD_MUTEX_INITIALIZER;
+static change_listener_t g_listeners[CHANGE_EVENTS_MAX_LISTENERS];
+static int g_next_listener_id = 1;
+
+int change_events_subscribe(change_listener_fn fn, void *user) {
+    pthread_mutex_lock(&g_events_mutex);
+    int id = -1;
+    for (size_t i = 0; i < CHANGE_EVENTS_MAX_LISTENERS; i++) {
+        if (g_listeners[i].fn) continue;
+        g_listeners[i].id = g_next_listener_id++;
+        g_listeners[i].fn = fn;
+        g_listeners[i].user = user;
+        id = g_listeners[i].id;
+        break;
+    }
+    pthread_mutex_unlock(&g_events_mutex);
+    return id;
+}
+
+void change_events_unsubscribe(int id) {
+    pthread_mutex_lock(&g_events_mutex);
+    for (size_t i = 0; i < CHANGE_EVENTS_MAX_LISTENERS; i++) {
+        if (g_listeners[i].id == id) memset(&g_listeners[i], 0, sizeof(g_listeners[i]));
+    }
+    pthread_mutex_unlock(&g_events_mutex);
+}
+
+void change_events_dispatch(const change_event_t *event) {
+    change_listener_t listeners[CHANGE_EVENTS_MAX_LISTENERS];
+    pthread_mutex_lock(&g_events_mutex);
+    memcpy(listeners, g_listeners, sizeof(listeners));
+    pthread_mutex_unlock(&g_events_mutex);
+    for (size_t i = 0; i < CHANGE_EVENTS_MAX_LISTENERS; i++) {
+        if (listeners[i].fn) listeners[i].fn(event, listeners[i].user);
+    }
+}
+
+void change_events_emit_local(const char *account_id, const char *key, const char *payload, size_t payload_len) {
+    change_event_t event;
+    memset(&event, 0, sizeof(event));
+    snprintf(event.account_id, sizeof(event.account_id), "%s", account_id);
+    snprintf(event.key, sizeof(event.key), "%s", key);
+    content_version(payload, payload_len, event.version, sizeof(event.version));
+    event.updated_at = (long long)time(NULL);
+    snprintf(event.origin, sizeof(event.origin), "%s", server_instance_id());
+    change_events_dispatch(&event);
+    redis_publish_change(&event);
+}
@@ -0,0 +1,417 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * Research export: an opt-in, anonymized copy of an account's activities and wellness samples.
+ * Fields are whitelisted rather than stripped, so identity (ids, names, notes, external ids),
+ * GPS and source files never leave the server; dates become day offsets from the account's
+ * first record so the relative timeline survives.
+ */
+
+#define ANONYMIZED_EXPORT_FORMAT "fricu-anonymized-v1"
+
+/* ?encrypt=age seals an export with the server's FRICU_EXPORT_PASSPHRASE; 0 when it may proceed. */
+static int check_export_encryption(int fd, const http_request_t *req, int *out_encrypt, const request_log_context_t *ctx) {
+    char raw[16] = {0};
+    *out_encrypt = 0;
+    if (!query_param(req->query, "encrypt", raw, sizeof(raw))) return 0;
+    if (strcmp(raw, "age") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"encrypt must be age\"}", ctx);
+        return 400;
+    }
+    if (!export_encryption_available()) {
+        send_response_with_log_context(fd, 501, "Not Implemented", "{\"error\":\"encrypted exports need a server built with OpenSSL\"}", ctx);
+        return 501;
+    }
+    if (!export_passphrase()) {
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"encrypted exports need FRICU_EXPORT_PASSPHRASE on the server\"}", ctx);
+        return 409;
+    }
+    *out_encrypt = 1;
+    return 0;
+}
+
+static int send_export(
+    int fd, int encrypt, const char *filename, const char *content_type, const char *body, size_t len, const request_log_context_t *ctx) {
+    char headers[160] = {0};
+    if (!encrypt) {
+        snprintf(headers, sizeof(headers), "Content-Disposition: attachment; filename=\"%s\"\r\n", filename);
+        send_http_response(fd, 200, "OK", content_type, headers, body, len, ctx);
+        return 200;
+    }
+    unsigned char *sealed = NULL;
+    size_t sealed_len = 0;
+    if (export_encrypt((const unsigned char *)body, len, &sealed, &sealed_len) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"encryption failed\"}", ctx);
+        return 500;
+    }
+    snprintf(headers, sizeof(headers), "Content-Disposition: attachment; filename=\"%s.age\"\r\n", filename);
+    send_http_response(fd, 200, "OK", "application/octet-stream", headers, (const char *)sealed, sealed_len, ctx);
+    free(sealed);
+    return 200;
+}
+
+static int research_consent_state(sqlite3 *db, const char *account_id, long long *out_opted_in_at) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT opted_in_at FROM research_consent WHERE account_id = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        found = 1;
+        if (out_opted_in_at) *out_opted_in_at = sqlite3_column_int64(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static int handle_research_consent(int fd, worker_db_t *db, const char *method, const request_log_context_t *ctx) {
+    if (strcmp(method, "PUT") == 0 || strcmp(method, "DELETE") == 0) {
+        int opt_in = strcmp(method, "PUT") == 0;
+        sqlite3_stmt *stmt = NULL;
+        const char *sql = opt_in ? "INSERT OR IGNORE INTO research_consent (account_id, opted_in_at) VALUES (?1, strftime('%s', 'now'))"
+                                 : "DELETE FROM research_consent WHERE account_id = ?1";
+        if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        int rc = sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+        if (rc != SQLITE_DONE) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        log_info("EXPORT research consent %s account=%s logid=%s", opt_in ? "granted" : "revoked", ctx->account_id, ctx->log_id);
+    } else if (strcmp(method, "GET") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+
+    long long opted_in_at = 0;
+    int state = research_consent_state(db->db, ctx->account_id, &opted_in_at);
+    if (state < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    char body[96] = {0};
+    if (state) {
+        snprintf(body, sizeof(body), "{\"opted_in\":true,\"opted_in_at\":%lld}", opted_in_at);
+    } else {
+        snprintf(body, sizeof(body), "{\"opted_in\":false}");
+    }
+    send_response_with_log_context(fd, 200, "OK", body, ctx);
+    return 200;
+}
+
+/* ?1 activities key, ?2 wellness key; the baseline is the earliest dated record in either. */
+static const char *ANONYMIZED_EXPORT_SQL =
+    "WITH base AS (SELECT MIN(day) AS day FROM ("
+    "  SELECT substr(json_extract(a.value, '$.date'), 1, 10) AS day FROM kv_store k, json_each(k.data_value) a"
+    "  WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+    "  UNION ALL"
+    "  SELECT substr(json_extract(w.value, '$.date'), 1, 10) FROM kv_store k, json_each(k.data_value) w"
+    "  WHERE k.data_key = ?2 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array')"
+    "  WHERE day IS NOT NULL)"
+    " SELECT"
+    " (SELECT json_group_array(json(item)) FROM (SELECT json_object("
+    "   'day_offset', CAST(julianday(substr(json_extract(a.value, '$.date'), 1, 10)) - julianday(base.day) AS INTEGER),"
+    "   'sport', json_extract(a.value, '$.sport'),"
+    "   'duration_sec', json_extract(a.value, '$.durationSec'),"
+    "   'distance_km', json_extract(a.value, '$.distanceKm'),"
+    "   'tss', json_extract(a.value, '$.tss'),"
+    "   'normalized_power', json_extract(a.value, '$.normalizedPower'),"
+    "   'avg_heart_rate', json_extract(a.value, '$.avgHeartRate'),"
+    "   'intervals', (SELECT json_group_array(json_object('duration_sec', json_extract(i.value, '$.durationSec'),"
+    "     'target_power', json_extract(i.value, '$.targetPower'), 'actual_power', json_extract(i.value, '$.actualPower')))"
+    "     FROM json_each(a.value, '$.intervals') i)) AS item"
+    "   FROM kv_store k, json_each(k.data_value) a, base"
+    "   WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+    "   AND json_extract(a.value, '$.date') IS NOT NULL"
+    "   ORDER BY json_extract(a.value, '$.date'), a.key)),"
+    " (SELECT json_group_array(json(item)) FROM (SELECT json_object("
+    "   'day_offset', CAST(julianday(substr(json_extract(w.value, '$.date'), 1, 10)) - julianday(base.day) AS INTEGER),"
+    "   'hrv', json_extract(w.value, '$.hrv'),"
+    "   'resting_hr', json_extract(w.value, '$.restingHR'),"
+    "   'weight_kg', json_extract(w.value, '$.weightKg'),"
+    "   'sleep_hours', json_extract(w.value, '$.sleepHours'),"
+    "   'sleep_score', json_extract(w.value, '$.sleepScore')) AS item"
+    "   FROM kv_store k, json_each(k.data_value) w, base"
+    "   WHERE k.data_key = ?2 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+    "   AND json_extract(w.value, '$.date') IS NOT NULL"
+    "   ORDER BY json_extract(w.value, '$.date'), w.key))";
+
+static int handle_get_anonymized_export(int fd, worker_db_t *db, int encrypt, const request_log_context_t *ctx) {
+    int state = research_consent_state(db->db, ctx->account_id, NULL);
+    if (state <= 0) {
+        if (state < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        send_response_with_log_context(
+            fd, 403, "Forbidden", "{\"error\":\"research export is opt-in; PUT /v1/export/anonymized/consent first\"}", ctx);
+        return 403;
+    }
+
+    char activities_key[256] = {0};
+    char wellness_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0 ||
+        build_storage_key(ctx->account_id, "wellness_samples", wellness_key, sizeof(wellness_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, ANONYMIZED_EXPORT_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("EXPORT anonymized prepare failed: %s", sqlite3_errmsg(db->db));
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, wellness_key, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *activities = (const char *)sqlite3_column_text(stmt, 0);
+        const char *wellness = (const char *)sqlite3_column_text(stmt, 1);
+        strbuf_append(&sb, "{\"format\":\"" ANONYMIZED_EXPORT_FORMAT "\",\"timeline\":\"day_offset counts days from the first record\",\"activities\":", 103);
+        strbuf_append(&sb, activities ? activities : "[]", activities ? strlen(activities) : 2);
+        strbuf_append(&sb, ",\"wellness\":", 12);
+        strbuf_append(&sb, wellness ? wellness : "[]", wellness ? strlen(wellness) : 2);
+        strbuf_append(&sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    if (sb.failed || sb.len == 0) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"export error\"}", ctx);
+        return 500;
+    }
+    int status = send_export(fd, encrypt, "fricu-anonymized.json", "application/json", strbuf_cstr(&sb), sb.len, ctx);
+    log_info("EXPORT anonymized bytes=%zu encrypted=%d account=%s logid=%s", sb.len, encrypt, ctx->account_id, ctx->log_id);
+    strbuf_free(&sb);
+    return status;
+}
+
+/*
+ * Plan template export: the planned workouts of a block of whole weeks, with dates replaced by
+ * (week, weekday) relative to the block's first Monday and only %FTP targets kept, so the plan can
+ * be scheduled for another athlete on any server. "reference" records the thresholds it was
+ * written against. Segments without a duration are dropped and
+ * workouts left without segments are skipped, so an export always imports cleanly.
+ */
+static const char *PLAN_TEMPLATE_EXPORT_SQL =
+    "WITH w AS ("
+    "  SELECT w.key AS i, w.value AS v, substr(json_extract(w.value, '$.scheduledDate'), 1, 10) AS day"
+    "  FROM kv_store k, json_each(k.data_value) w"
+    "  WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND w.type = 'object'"
+    "  AND substr(json_extract(w.value, '$.scheduledDate'), 1, 10) BETWEEN ?2 AND ?3),"
+    " t AS ("
+    "  SELECT day, i, json_object("
+    "   'week', CAST((julianday(day) - julianday(?2)) / 7 AS INTEGER) + 1,"
+    "   'day', (CAST(strftime('%w', day) AS INTEGER) + 6) % 7 + 1,"
+    "   'name', substr(COALESCE(NULLIF(trim(json_extract(v, '$.name')), ''), 'Workout'), 1, 128),"
+    "   'sport', substr(COALESCE(json_extract(v, '$.sport'), 'cycling'), 1, 32),"
+    "   'segments', json((SELECT json_group_array(json_object("
+    "     'minutes', json_extract(s.value, '$.minutes'),"
+    "     'intensityPercentFTP', MIN(MAX(COALESCE(json_extract(s.value, '$.intensityPercentFTP'), 0), 0), 300),"
+    "     'cadence', json_extract(s.value, '$.cadence'), 'note', json_extract(s.value, '$.note')))"
+    "    FROM json_each(v, '$.segments') s WHERE s.type = 'object' AND json_extract(s.value, '$.minutes') > 0"
+    "    AND json_extract(s.value, '$.minutes') <= 600))) AS o"
+    "  FROM w)"
+    " SELECT json_object('format', ?4, 'name', ?5, 'description', ?6,"
+    " 'reference', json_object('ftpWatts', ?8, 'thresholdPaceSecPerKm', ?9, 'cssSecPer100m', ?10), 'weeks', ?7,"
+    " 'workouts', json((SELECT json_group_array(json(o)) FROM (SELECT o FROM t WHERE json_array_length(o, '$.segments') > 0 ORDER BY day, i)))),"
+    " (SELECT COUNT(*) FROM t WHERE json_array_length(o, '$.segments') > 0)";
+
+/* GET /v1/export/plan-template?from=YYYY-MM-DD&weeks=12&name=...&description=... */
+static int handle_get_plan_template_export(int fd, worker_db_t *db, const http_request_t *req, int encrypt, const request_log_context_t *ctx) {
+    int from_day = today_day();
+    char raw[256] = {0};
+    if (query_param(req->query, "from", raw, sizeof(raw)) && parse_iso_day(raw, &from_day) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    from_day = week_start_for_day(from_day);
+    int weeks = 12;
+    if (query_param(req->query, "weeks", raw, sizeof(raw))) {
+        long parsed = strtol(raw, NULL, 10);
+        if (parsed <= 0 || parsed > PLAN_TEMPLATE_MAX_WEEKS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"weeks must be in 1..52\"}", ctx);
+            return 400;
+        }
+        weeks = (int)parsed;
+    }
+    char name[256] = "Training plan";
+    char description[512] = {0};
+    if (query_param(req->query, "name", raw, sizeof(raw)) && raw[0] != '\0') snprintf(name, sizeof(name), "%.128s", raw);
+    int has_description = query_param(req->query, "description", description, sizeof(description));
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "workouts", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    char from_label[16] = {0};
+    char to_label[16] = {0};
+    format_iso_day(from_day, from_label, sizeof(from_label));
+    format_iso_day(from_day + weeks * 7 - 1, to_label, sizeof(to_label));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, PLAN_TEMPLATE_EXPORT_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("EXPORT plan template prepare failed: %s", sqlite3_errmsg(db->db));
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from_label, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to_label, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, PLAN_TEMPLATE_FORMAT, -1, SQLITE_STATIC);
+    sqlite3_bind_text(stmt, 5, name, -1, SQLITE_TRANSIENT);
+    if (has_description) {
+        sqlite3_bind_text(stmt, 6, description, -1, SQLITE_TRANSIENT);
+    } else {
+        sqlite3_bind_null(stmt, 6);
+    }
+    sqlite3_bind_int(stmt, 7, weeks);
+    /* The exporter's thresholds, so an importer can see how the plan scales to them. */
+    static const char *const reference_sports[] = {"cycling", "running", "swimming"};
+    for (int i = 0; i < 3; i++) {
+        sport_settings_t settings;
+        load_sport_settings(db->db, ctx->account_id, reference_sports[i], &settings);
+        double value = i == 0 ? (settings.ftp_watts > 0.0 ? settings.ftp_watts : load_profile_ftp(db->db, ctx->account_id))
+                              : (i == 1 ? settings.threshold_pace_sec_per_km : settings.css_sec_per_100m);
+        if (value > 0.0) {
+            sqlite3_bind_double(stmt, 8 + i, value);
+        } else {
+            sqlite3_bind_null(stmt, 8 + i);
+        }
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    int workouts = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        strbuf_append(&sb, (const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0));
+        workouts = sqlite3_column_int(stmt, 1);
+    }
+    sqlite3_finalize(stmt);
+    if (workouts < 0 || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"export error\"}", ctx);
+        return 500;
+    }
+    if (workouts == 0) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no planned workouts with segments in range\"}", ctx);
+        return 404;
+    }
+    int status = send_export(fd, encrypt, "fricu-plan-template.json", "application/json", strbuf_cstr(&sb), sb.len, ctx);
+    log_info(
+        "EXPORT plan template from=%s weeks=%d workouts=%d encrypted=%d account=%s logid=%s", from_label, weeks, workouts, encrypt, ctx->account_id, ctx->log_id);
+    strbuf_free(&sb);
+    return status;
+}
+
+/*
+ * Full export: GET /v1/export answers with a zip of everything the account has stored, so the data
+ * can move to another server or sit in an offline copy. Each data key is data/<key>.json holding the
+ * document exactly as stored, and manifest.json lists every key with its file, version (the same
+ * content hash GET /v1/data sends as X-Fricu-Version), updated_at and size. All of it is read by one
+ * statement, so the archive is a consistent snapshot even while the account keeps syncing.
+ * ?encrypt=age seals the archive like the other exports.
+ */
+static const char *ARCHIVE_EXPORT_SQL =
+    "SELECT substr(data_key, length(?1) + 1), data_value, updated_at FROM kv_store"
+    " WHERE substr(data_key, 1, length(?1)) = ?1 ORDER BY data_key";
+
+/* Key to file name; keys are already safe except for exported_file_ suffixes. */
+static void archive_entry_name(const char *key, char *out, size_t out_len) {
+    int n = snprintf(out, out_len, "data/%.200s.json", key);
+    for (int i = 5; n > 0 && i < n - 5 && out[i] != '\0'; i++) {
+        char c = out[i];
+        int safe = (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '_' || c == '-' || c == '.';
+        if (!safe || (c == '.' && out[i + 1] == '.')) out[i] = '_';
+    }
+}
+
+static int handle_get_archive_export(int fd, worker_db_t *db, int encrypt, const request_log_context_t *ctx) {
+    char prefix[160] = {0};
+    snprintf(prefix, sizeof(prefix), "%s::", ctx->account_id);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, ARCHIVE_EXPORT_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("EXPORT archive prepare failed: %s", sqlite3_errmsg(db->db));
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, prefix, -1, SQLITE_TRANSIENT);
+    long long now = (long long)time(NULL);
+    zip_writer_t zw;
+    zip_writer_init(&zw);
+    strbuf_t manifest;
+    strbuf_init(&manifest);
+    strbuf_appendf(&manifest, "{\"format\":\"%s\",\"account_id\":", ARCHIVE_FORMAT);
+    strbuf_append_json_string(&manifest, ctx->account_id);
+    strbuf_appendf(&manifest, ",\"exported_at\":%lld,\"keys\":[", now);
+    int count = 0;
+    int rc = SQLITE_ROW;
+    while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        if (!key || !is_valid_key(key)) continue;
+        const char *value = (const char *)sqlite3_column_text(stmt, 1);
+        size_t value_len = (size_t)sqlite3_column_bytes(stmt, 1);
+        long long updated_at = sqlite3_column_int64(stmt, 2);
+        char name[256] = {0};
+        char version[32] = {0};
+        archive_entry_name(key, name, sizeof(name));
+        content_version(value ? value : "", value_len, version, sizeof(version));
+        zip_writer_add(&zw, name, value ? value : "", value_len, updated_at);
+        if (count > 0) strbuf_append(&manifest, ",", 1);
+        strbuf_append(&manifest, "{\"key\":", 7);
+        strbuf_append_json_string(&manifest, key);
+        strbuf_appendf(&manifest, ",\"file\":\"%s\",\"version\":\"%s\",\"updated_at\":%lld,\"bytes\":%zu}", name, version, updated_at, value_len);
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&manifest, "]}", 2);
+    if (rc == SQLITE_DONE && !manifest.failed) zip_writer_add(&zw, "manifest.json", manifest.data, manifest.len, now);
+    int ok = rc == SQLITE_DONE && !manifest.failed && zip_writer_finish(&zw) == 0;
+    strbuf_free(&manifest);
+    if (!ok) {
+        zip_writer_free(&zw);
+        log_error("EXPORT archive failed keys=%d account=%s logid=%s", count, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"export error\"}", ctx);
+        return 500;
+    }
+    char filename[64] = {0};
+    char day[16] = {0};
+    format_iso_day(today_day(), day, sizeof(day));
+    snprintf(filename, sizeof(filename), "fricu-export-%s.zip", day);
+    int status = send_export(fd, encrypt, filename, "application/zip", zw.out.data, zw.out.len, ctx);
+    log_info("EXPORT archive keys=%d bytes=%zu encrypted=%d account=%s logid=%s", count, zw.out.len, encrypt, ctx->account_id, ctx->log_id);
+    zip_writer_free(&zw);
+    return status;
+}
+
+int route_export(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->path, "/v1/export/anonymized/consent") == 0) return handle_research_consent(fd, db, req->method, ctx);
+    int anonymized = strcmp(req->path, "/v1/export/anonymized") == 0;
+    int archive = strcmp(req->path, "/v1/export") == 0;
+    if (anonymized || archive || strcmp(req->path, "/v1/export/plan-template") == 0) {
+        if (strcmp(req->method, "GET") == 0) {
+            int encrypt = 0;
+            int refused = check_export_encryption(fd, req, &encrypt, ctx);
+            if (refused != 0) return refused;
+            if (archive) return handle_get_archive_export(fd, db, encrypt, ctx);
+            return anonymized ? handle_get_anonymized_export(fd, db, encrypt, ctx) : handle_get_plan_template_export(fd, db, req, encrypt, ctx);
+        }
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+    return 404;
+}
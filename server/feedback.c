@@ -0,0 +1,439 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Post-activity feedback and readiness. PATCH /v1/activities/<id>/feedback sets any of
+ *   {"rpe": 0-10, "feel": 1-5, "comments": "..."}
+ * on one activity (null clears a field) and keeps the activity's session-RPE load ("loads.srpe",
+ * RPE x minutes) in step. sRPE becomes the activity's primary load when the sport's tssModel is
+ * srpe or when nothing else scored it, which is the case for athletes training without sensors.
+ *
+ * GET /v1/analytics/readiness scores today from form (TSB), HRV against its baseline, and the
+ * subjective trend: the last 7 days of feel and RPE against the 28 days before them. Feel dropping
+ * or the same sessions feeling harder pull the score down before the load numbers show fatigue.
+ */
+
+#define FEEDBACK_COMMENTS_MAX 2000
+#define READINESS_RECENT_DAYS 7
+#define READINESS_BASELINE_DAYS 28
+#define READINESS_MIN_RATINGS 2
+
+static int validate_feedback(sqlite3 *db, const http_request_t *req, char *err, size_t err_len) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_type(?1), f.key, f.type, f.value FROM (SELECT 1)"
+            " LEFT JOIN json_each(CASE json_type(?1) WHEN 'object' THEN ?1 ELSE '{}' END) f WHERE json_valid(?1) ORDER BY f.id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        snprintf(err, err_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body_len > 0 ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    int status = 400;
+    int fields = 0;
+    snprintf(err, err_len, "{\"error\":\"body must be a JSON object\"}");
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *root = (const char *)sqlite3_column_text(stmt, 0);
+        const char *field = (const char *)sqlite3_column_text(stmt, 1);
+        const char *type = (const char *)sqlite3_column_text(stmt, 2);
+        if (!root || strcmp(root, "object") != 0) break;
+        status = 0;
+        if (!field) break;
+        fields++;
+        int is_null = strcmp(type, "null") == 0;
+        int is_number = strcmp(type, "integer") == 0 || strcmp(type, "real") == 0;
+        double number = sqlite3_column_double(stmt, 3);
+        if (strcmp(field, "rpe") == 0) {
+            if (!is_null && (!is_number || number < 0.0 || number > 10.0)) {
+                snprintf(err, err_len, "{\"error\":\"rpe must be a number in 0..10 or null\",\"field\":\"rpe\"}");
+                status = 400;
+            }
+        } else if (strcmp(field, "feel") == 0) {
+            if (!is_null && (strcmp(type, "integer") != 0 || number < 1.0 || number > 5.0)) {
+                snprintf(err, err_len, "{\"error\":\"feel must be an integer in 1..5 or null\",\"field\":\"feel\"}");
+                status = 400;
+            }
+        } else if (strcmp(field, "comments") == 0) {
+            if (!is_null && (strcmp(type, "text") != 0 || sqlite3_column_bytes(stmt, 3) > FEEDBACK_COMMENTS_MAX)) {
+                snprintf(err, err_len, "{\"error\":\"comments must be a string of at most %d bytes or null\",\"field\":\"comments\"}", FEEDBACK_COMMENTS_MAX);
+                status = 400;
+            }
+        } else {
+            snprintf(err, err_len, "{\"error\":\"unknown field (expected rpe, feel or comments)\"}");
+            status = 400;
+        }
+        if (status != 0) break;
+    }
+    sqlite3_finalize(stmt);
+    if (status == 0 && fields == 0) {
+        snprintf(err, err_len, "{\"error\":\"at least one of rpe, feel or comments is required\"}");
+        status = 400;
+    }
+    return status;
+}
+
+/*
+ * The activity with the feedback merged in (null removes a field) and its loads updated. The
+ * primary load moves to sRPE when the sport prefers it or the activity had no primary load; when
+ * RPE is cleared from an sRPE-scored activity it falls back to the preferred model, then hrss.
+ */
+static char *apply_feedback(sqlite3 *db, const char *account_id, const char *item, const char *body, size_t body_len) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(m, '$.sport'), json_extract(m, '$.rpe'), COALESCE(json_extract(m, '$.durationSec'), 0),"
+            " json_extract(m, '$.loadModel'), COALESCE(json_extract(m, '$.tss'), 0), m"
+            " FROM (SELECT json_patch(?1, ?2) AS m)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, item, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, body, (int)body_len, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW || !sqlite3_column_text(stmt, 5)) {
+        sqlite3_finalize(stmt);
+        return NULL;
+    }
+    sport_settings_t settings;
+    load_sport_settings(db, account_id, (const char *)sqlite3_column_text(stmt, 0), &settings);
+    double rpe = sqlite3_column_type(stmt, 1) == SQLITE_NULL ? 0.0 : sqlite3_column_double(stmt, 1);
+    double srpe = rpe * sqlite3_column_double(stmt, 2) / 60.0;
+    char load_model[16] = {0};
+    if (sqlite3_column_text(stmt, 3)) snprintf(load_model, sizeof(load_model), "%s", (const char *)sqlite3_column_text(stmt, 3));
+    int unscored = load_model[0] == '\0' && sqlite3_column_double(stmt, 4) <= 0.0;
+    char *merged = strdup((const char *)sqlite3_column_text(stmt, 5));
+    sqlite3_finalize(stmt);
+    if (!merged) return NULL;
+
+    /* ?2 srpe (NULL removes it), ?3 "srpe" to make it primary, ?4 preferred model for the fallback. */
+    const char *sql =
+        "SELECT CASE"
+        "  WHEN ?3 IS NOT NULL THEN json_set(l, '$.tss', CAST(round(?2) AS INTEGER), '$.loadModel', 'srpe')"
+        "  WHEN json_extract(l, '$.loadModel') = 'srpe' THEN"
+        "   CASE WHEN json_extract(l, '$.loads.' || ?4) IS NOT NULL"
+        "     THEN json_set(l, '$.tss', json_extract(l, '$.loads.' || ?4), '$.loadModel', ?4)"
+        "    WHEN json_extract(l, '$.loads.hrss') IS NOT NULL"
+        "     THEN json_set(l, '$.tss', json_extract(l, '$.loads.hrss'), '$.loadModel', 'hrss')"
+        "    ELSE json_remove(json_set(l, '$.tss', 0), '$.loadModel') END"
+        "  ELSE l END"
+        " FROM (SELECT CASE WHEN ?2 IS NULL THEN"
+        "   CASE WHEN json_type(?1, '$.loads') = 'object' THEN json_remove(?1, '$.loads.srpe') ELSE ?1 END"
+        "   WHEN json_type(?1, '$.loads') = 'object' THEN json_set(?1, '$.loads.srpe', CAST(round(?2) AS INTEGER))"
+        "   ELSE json_set(?1, '$.loads', json_object('srpe', CAST(round(?2) AS INTEGER))) END AS l)";
+    char *out = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, merged, -1, SQLITE_TRANSIENT);
+        if (srpe > 0.0) {
+            sqlite3_bind_double(stmt, 2, srpe);
+            if (strcmp(settings.tss_model, "srpe") == 0 || unscored || strcmp(load_model, "srpe") == 0) sqlite3_bind_text(stmt, 3, "srpe", -1, SQLITE_STATIC);
+        }
+        sqlite3_bind_text(stmt, 4, settings.tss_model, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) out = strdup((const char *)sqlite3_column_text(stmt, 0));
+        sqlite3_finalize(stmt);
+    }
+    free(merged);
+    return out;
+}
+
+static int handle_patch_feedback(int fd, worker_db_t *db, const http_request_t *req, const char *activity_id, const request_log_context_t *ctx) {
+    char err[256] = {0};
+    int status = validate_feedback(db->db, req, err, sizeof(err));
+    if (status != 0) {
+        send_response_with_log_context(fd, status, status == 400 ? "Bad Request" : "Internal Server Error", err, ctx);
+        return status;
+    }
+    int locked = locks_enforce_key(fd, db, req, "activities", ctx);
+    if (locked != 0) return locked;
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    sync_document_lock();
+    sqlite3_stmt *stmt = NULL;
+    char *item = NULL;
+    long long index = -1;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT a.key, a.value FROM kv_store k, json_each(k.data_value) a"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND a.type = 'object' AND json_extract(a.value, '$.id') = ?2 LIMIT 1",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, activity_id, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            index = sqlite3_column_int64(stmt, 0);
+            item = strdup((const char *)sqlite3_column_text(stmt, 1));
+        }
+        sqlite3_finalize(stmt);
+    }
+    if (!item) {
+        sync_document_unlock();
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"activity not found\"}", ctx);
+        return 404;
+    }
+
+    char *updated = apply_feedback(db->db, ctx->account_id, item, req->body, req->body_len);
+    char *doc = NULL;
+    if (updated && sqlite3_prepare_v2(
+                       db->db,
+                       "SELECT json_set(data_value, '$[' || ?2 || ']', json(?3)) FROM kv_store WHERE data_key = ?1",
+                       -1,
+                       &stmt,
+                       NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 2, index);
+        sqlite3_bind_text(stmt, 3, updated, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) doc = strdup((const char *)sqlite3_column_text(stmt, 0));
+        sqlite3_finalize(stmt);
+    }
+    char out[512] = {0};
+    status = doc ? store_account_data(db, "activities", doc, strlen(doc), ctx, out, sizeof(out)) : 500;
+    sync_document_unlock();
+    free(item);
+    free(doc);
+    if (status != 204 && status != 202) {
+        free(updated);
+        log_warn("FEEDBACK write failed status=%d body=%s account=%s logid=%s", status, out, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"feedback write failed\"}", ctx);
+        return 500;
+    }
+
+    char *response = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_object('id', json_extract(?1, '$.id'), 'rpe', json_extract(?1, '$.rpe'), 'feel', json_extract(?1, '$.feel'),"
+            " 'comments', json_extract(?1, '$.comments'), 'tss', json_extract(?1, '$.tss'), 'loadModel', json_extract(?1, '$.loadModel'),"
+            " 'loads', json_extract(?1, '$.loads'))",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, updated, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) response = strdup((const char *)sqlite3_column_text(stmt, 0));
+        sqlite3_finalize(stmt);
+    }
+    free(updated);
+    if (!response) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", response, ctx);
+    free(response);
+    log_info("FEEDBACK activity=%s account=%s logid=%s", activity_id, ctx->account_id, ctx->log_id);
+    return 200;
+}
+
+int route_activity_feedback(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    /* /v1/activities/<id>/feedback */
+    const char *rest = req->path + strlen("/v1/activities/");
+    const char *slash = strchr(rest, '/');
+    char activity_id[160] = {0};
+    if (!slash || strcmp(slash, "/feedback") != 0 || (size_t)(slash - rest) >= sizeof(activity_id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    memcpy(activity_id, rest, (size_t)(slash - rest));
+    if (!is_valid_item_id(activity_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"activity id must be 1-128 chars of [A-Za-z0-9._:-]\"}", ctx);
+        return 400;
+    }
+    if (strcmp(req->method, "PATCH") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    return handle_patch_feedback(fd, db, req, activity_id, ctx);
+}
+
+typedef struct {
+    double feel;
+    double rpe;
+    int rated;
+} feedback_window_t;
+
+/* Mean feel and RPE of the activities dated from_day..to_day that carry them. */
+static void load_feedback_window(sqlite3 *db, const char *storage_key, int from_day, int to_day, feedback_window_t *out) {
+    memset(out, 0, sizeof(*out));
+    char from[16] = {0};
+    char to[16] = {0};
+    format_iso_day(from_day, from, sizeof(from));
+    format_iso_day(to_day, to, sizeof(to));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT AVG(json_extract(a.value, '$.feel')), AVG(json_extract(a.value, '$.rpe')),"
+            " COUNT(CASE WHEN json_extract(a.value, '$.feel') IS NOT NULL OR json_extract(a.value, '$.rpe') IS NOT NULL THEN 1 END)"
+            " FROM kv_store k, json_each(k.data_value) a"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        out->feel = sqlite3_column_type(stmt, 0) == SQLITE_NULL ? -1.0 : sqlite3_column_double(stmt, 0);
+        out->rpe = sqlite3_column_type(stmt, 1) == SQLITE_NULL ? -1.0 : sqlite3_column_double(stmt, 1);
+        out->rated = sqlite3_column_int(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+}
+
+/* Latest HRV over the profile's hrvBaseline, or over the 28-day mean without one; -1 when unknown. */
+static double load_hrv_ratio(sqlite3 *db, const char *account_id, int today) {
+    char wellness_key[256] = {0};
+    char profile_key[256] = {0};
+    if (build_storage_key(account_id, "wellness_samples", wellness_key, sizeof(wellness_key)) != 0 ||
+        build_storage_key(account_id, "profile", profile_key, sizeof(profile_key)) != 0) {
+        return -1.0;
+    }
+    char from[16] = {0};
+    format_iso_day(today - READINESS_BASELINE_DAYS, from, sizeof(from));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "WITH h AS (SELECT substr(json_extract(w.value, '$.date'), 1, 10) AS day, json_extract(w.value, '$.hrv') AS hrv"
+            "  FROM kv_store k, json_each(k.data_value) w"
+            "  WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            "  AND json_extract(w.value, '$.hrv') > 0)"
+            " SELECT (SELECT hrv FROM h ORDER BY day DESC LIMIT 1),"
+            " COALESCE((SELECT json_extract(data_value, '$.hrvBaseline') FROM kv_store WHERE data_key = ?2 AND json_valid(data_value)"
+            "  AND json_extract(data_value, '$.hrvBaseline') > 0), (SELECT AVG(hrv) FROM h WHERE day >= ?3))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1.0;
+    }
+    sqlite3_bind_text(stmt, 1, wellness_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, profile_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+    double ratio = -1.0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) != SQLITE_NULL && sqlite3_column_double(stmt, 1) > 0.0) {
+        ratio = sqlite3_column_double(stmt, 0) / sqlite3_column_double(stmt, 1);
+    }
+    sqlite3_finalize(stmt);
+    return ratio;
+}
+
+static void append_optional(strbuf_t *sb, const char *name, double value, const char *format) {
+    strbuf_appendf(sb, ",\"%s\":", name);
+    if (value < 0.0) {
+        strbuf_append(sb, "null", 4);
+    } else {
+        strbuf_appendf(sb, format, value);
+    }
+}
+
+int handle_get_analytics_readiness(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    (void)req;
+    int today = today_day();
+    pmc_point_t pmc;
+    char storage_key[256] = {0};
+    if (compute_account_pmc_today(db->db, ctx->account_id, &pmc) != 0 ||
+        build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"analytics error\"}", ctx);
+        return 500;
+    }
+    double hrv_ratio = load_hrv_ratio(db->db, ctx->account_id, today);
+    feedback_window_t recent;
+    feedback_window_t baseline;
+    load_feedback_window(db->db, storage_key, today - READINESS_RECENT_DAYS + 1, today, &recent);
+    load_feedback_window(db->db, storage_key, today - READINESS_RECENT_DAYS - READINESS_BASELINE_DAYS + 1, today - READINESS_RECENT_DAYS, &baseline);
+    int subjective = recent.rated >= READINESS_MIN_RATINGS && baseline.rated >= READINESS_MIN_RATINGS;
+    double feel_delta = subjective && recent.feel >= 0.0 && baseline.feel >= 0.0 ? recent.feel - baseline.feel : 0.0;
+    double rpe_delta = subjective && recent.rpe >= 0.0 && baseline.rpe >= 0.0 ? recent.rpe - baseline.rpe : 0.0;
+
+    /* Same bands as the app's readiness score for form and HRV, plus the subjective trend. */
+    const char *factors[6];
+    size_t factor_count = 0;
+    int score = 70;
+    if (pmc.tsb < -25.0) {
+        score -= 30;
+        factors[factor_count++] = "fatigue_high";
+    } else if (pmc.tsb < -15.0) {
+        score -= 18;
+        factors[factor_count++] = "fatigue_high";
+    } else if (pmc.tsb < -5.0) {
+        score -= 8;
+    } else if (pmc.tsb <= 15.0) {
+        score += 8;
+    } else {
+        score += 2;
+    }
+    if (hrv_ratio >= 0.0 && hrv_ratio < 0.85) {
+        score -= 24;
+        factors[factor_count++] = "hrv_low";
+    } else if (hrv_ratio >= 0.0 && hrv_ratio < 0.92) {
+        score -= 12;
+        factors[factor_count++] = "hrv_low";
+    } else if (hrv_ratio > 1.08) {
+        score += 4;
+    }
+    if (feel_delta <= -0.75) {
+        score -= 10;
+        factors[factor_count++] = "feel_declining";
+    } else if (feel_delta <= -0.4) {
+        score -= 5;
+        factors[factor_count++] = "feel_declining";
+    } else if (feel_delta >= 0.4) {
+        score += 3;
+    }
+    if (rpe_delta >= 1.5) {
+        score -= 8;
+        factors[factor_count++] = "rpe_rising";
+    } else if (rpe_delta >= 0.75) {
+        score -= 4;
+        factors[factor_count++] = "rpe_rising";
+    }
+    score = score < 1 ? 1 : score > 100 ? 100 : score;
+
+    char as_of[16] = {0};
+    format_iso_day(today, as_of, sizeof(as_of));
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"as_of\":\"%s\",\"score\":%d,\"tsb\":%.1f", as_of, score, pmc.tsb);
+    append_optional(&sb, "hrv_ratio", hrv_ratio, "%.2f");
+    strbuf_append(&sb, ",\"subjective\":{\"recent\":{\"rated\":", 33);
+    strbuf_appendf(&sb, "%d", recent.rated);
+    append_optional(&sb, "feel", recent.feel, "%.2f");
+    append_optional(&sb, "rpe", recent.rpe, "%.2f");
+    strbuf_appendf(&sb, "},\"baseline\":{\"rated\":%d", baseline.rated);
+    append_optional(&sb, "feel", baseline.feel, "%.2f");
+    append_optional(&sb, "rpe", baseline.rpe, "%.2f");
+    strbuf_appendf(&sb, "},\"used\":%s},\"factors\":[", subjective ? "true" : "false");
+    for (size_t i = 0; i < factor_count; i++) strbuf_appendf(&sb, "%s\"%s\"", i == 0 ? "" : ",", factors[i]);
+    /* Cycle phase is context for reading the score, not an input to it. */
+    cycle_model_t cycle;
+    strbuf_append(&sb, "],\"cycle\":", 10);
+    if (cycle_load_model(db->db, ctx->account_id, today, &cycle) == 0) {
+        cycle_append_json(&sb, &cycle, today);
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
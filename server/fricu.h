@@ -0,0 +1,30 @@
+#ifndef FRICU_H
+#define FRICU_H
+
+#include <stddef.h>
+
+/*
+ * Embedding API: link libfricu.a (and the libraries `make` links fricu-server with) to run the
+ * server inside another process, e.g. a desktop companion app, instead of shipping the daemon.
+ * Configuration other than these fields comes from the same FRICU_* environment variables.
+ */
+
+typedef struct {
+    const char *db_path; /* required */
+    const char *host;    /* NULL: 127.0.0.1 */
+    int port;            /* 0: any free port */
+    size_t workers;      /* 0: 4 */
+} fricu_embedded_config_t;
+
+typedef struct fricu_server fricu_server_t;
+
+/* Starts serving and returns once the socket is listening; NULL on failure (see the log). */
+fricu_server_t *fricu_run_embedded(const fricu_embedded_config_t *config);
+/* "http://127.0.0.1:<port>", or https:// when FRICU_TLS_CERT/KEY are set. */
+const char *fricu_server_base_url(const fricu_server_t *server);
+int fricu_server_port(const fricu_server_t *server);
+/* Stops accepting, lets each worker finish the requests it is receiving (up to FRICU_SHUTDOWN_TIMEOUT_SEC),
+ * joins them, checkpoints the WAL and frees the handle. */
+void fricu_server_stop(fricu_server_t *server);
+
+#endif
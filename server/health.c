@@ -0,0 +1,114 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <libgen.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+/*
+ * Health checks for orchestrators. GET /health/live answers 200 as long as the process can serve
+ * a request and touches nothing else, so a liveness probe never restarts the server because the
+ * disk is slow. GET /health/ready answers 200 only when the server can do its job: the database
+ * file is still on disk, SELECT 1 runs on this worker's connection, the database is in WAL mode
+ * with the file, its -wal file and its directory writable, and the write thread is running with
+ * fewer than FRICU_READY_MAX_WRITE_QUEUE (default 1000) writes waiting. Otherwise it answers 503
+ * with the failing check, so a load balancer stops routing to the instance until it recovers, and
+ * it answers 503 with "shutdown":"draining" once the server has begun to stop.
+ * The older GET /health is unchanged.
+ */
+
+#define READY_DEFAULT_MAX_WRITE_QUEUE 1000
+
+int handle_get_health_live(int fd, const request_log_context_t *ctx) {
+    send_response_with_log_context(fd, 200, "OK", "{\"status\":\"live\"}", ctx);
+    return 200;
+}
+
+static void append_check(strbuf_t *sb, int *count, const char *name, const char *result) {
+    strbuf_appendf(sb, "%s\"%s\":", *count > 0 ? "," : "", name);
+    strbuf_append_json_string(sb, result);
+    (*count)++;
+}
+
+/* "ok", or why the database file cannot be used. */
+static const char *check_database_file(const char *path) {
+    struct stat st;
+    if (stat(path, &st) != 0) return errno == ENOENT ? "database file missing" : "database file unreadable";
+    if (access(path, R_OK | W_OK) != 0) return "database file not writable";
+    return "ok";
+}
+
+static const char *check_select(sqlite3 *db) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1", -1, &stmt, NULL) != SQLITE_OK) return "prepare failed";
+    int rc = sqlite3_step(stmt);
+    int value = rc == SQLITE_ROW ? sqlite3_column_int(stmt, 0) : 0;
+    sqlite3_finalize(stmt);
+    return value == 1 ? "ok" : "query failed";
+}
+
+static const char *check_wal(sqlite3 *db, const char *path) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "PRAGMA journal_mode", -1, &stmt, NULL) != SQLITE_OK) return "journal mode unknown";
+    int wal = sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), "wal") == 0;
+    sqlite3_finalize(stmt);
+    if (!wal) return "not in WAL mode";
+    char wal_path[600] = {0};
+    snprintf(wal_path, sizeof(wal_path), "%s-wal", path);
+    if (access(wal_path, F_OK) == 0 && access(wal_path, W_OK) != 0) return "WAL file not writable";
+    /* SQLite creates the -wal and -shm files next to the database. */
+    char dir[512] = {0};
+    snprintf(dir, sizeof(dir), "%s", path);
+    if (access(dirname(dir), W_OK) != 0) return "database directory not writable";
+    return "ok";
+}
+
+int handle_get_health_ready(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    const char *max_env = getenv("FRICU_READY_MAX_WRITE_QUEUE");
+    int max_queue = max_env && atoi(max_env) > 0 ? atoi(max_env) : READY_DEFAULT_MAX_WRITE_QUEUE;
+    const char *database = check_database_file(db->db_path);
+    const char *select = check_select(db->db);
+    const char *wal = check_wal(db->db, db->db_path);
+    write_dispatch_diagnostics_t diag;
+    write_dispatch_diagnostics_snapshot(&diag);
+    const char *writer = "ok";
+    if (!diag.running) {
+        writer = "write thread not running";
+    } else if (diag.queue_depth >= max_queue) {
+        writer = "write queue full";
+    }
+    const char *shutdown = engine_draining() ? "draining" : "ok";
+    int ready = strcmp(database, "ok") == 0 && strcmp(select, "ok") == 0 && strcmp(wal, "ok") == 0 && strcmp(writer, "ok") == 0 &&
+                strcmp(shutdown, "ok") == 0;
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"status\":\"%s\",\"checks\":{", ready ? "ready" : "not ready");
+    int count = 0;
+    append_check(&sb, &count, "database", database);
+    append_check(&sb, &count, "select", select);
+    append_check(&sb, &count, "wal", wal);
+    append_check(&sb, &count, "write_queue", writer);
+    append_check(&sb, &count, "shutdown", shutdown);
+    strbuf_appendf(&sb, "},\"write_queue_depth\":%d,", diag.queue_depth);
+    cluster_append_status(&sb);
+    strbuf_append(&sb, "}", 1);
+    const char *body = sb.failed ? (ready ? "{\"status\":\"ready\"}" : "{\"status\":\"not ready\"}") : strbuf_cstr(&sb);
+    if (ready) {
+        send_response_with_log_context(fd, 200, "OK", body, ctx);
+    } else {
+        send_http_response(fd, 503, "Service Unavailable", "application/json", "Retry-After: 5\r\n", body, strlen(body), ctx);
+        log_warn(
+            "READY check failed database=%s select=%s wal=%s write_queue=%s shutdown=%s logid=%s", database, select, wal, writer, shutdown, ctx->log_id);
+    }
+    strbuf_free(&sb);
+    return ready ? 200 : 503;
+}
@@ -20,14 +20,9 @@
 #include <unistd.h>
 
 #define PENDING_WRITES_DIR "pending_writes"
-#define LOG_ID_MAX_LEN 96
-#define ACCOUNT_ID_MAX_LEN 128
 
-typedef struct {
-    char log_id[LOG_ID_MAX_LEN];
-    char account_id[ACCOUNT_ID_MAX_LEN];
-    int retry_attempt;
-} request_log_context_t;
+/* store_sequence value the current analytics read is pinned to, or -1 outside a snapshot. */
+static __thread long long g_snapshot_seq = -1;
 
 static int fsync_directory(const char *dir_path) {
     DIR *d = opendir(dir_path);
@@ -156,14 +151,23 @@ static int read_header_value(
     return 0;
 }
 
+int http_request_header(const http_request_t *req, const char *name, char *out, size_t out_len) {
+    if (!req) return 0;
+    return read_header_value(req->raw, req->header_end, name, out, out_len);
+}
+
 static request_log_context_t build_request_log_context(const char *req, const char *header_end) {
     request_log_context_t context;
     memset(&context, 0, sizeof(context));
 
+    /* X-Request-Id is the same id under the name proxies and most clients use. */
     char raw_log_id[256] = {0};
     if (read_header_value(req, header_end, "X-Log-Id", raw_log_id, sizeof(raw_log_id))) {
         sanitize_log_id(raw_log_id, context.log_id, sizeof(context.log_id));
     }
+    if (context.log_id[0] == '\0' && read_header_value(req, header_end, "X-Request-Id", raw_log_id, sizeof(raw_log_id))) {
+        sanitize_log_id(raw_log_id, context.log_id, sizeof(context.log_id));
+    }
     if (context.log_id[0] == '\0') {
         generate_server_log_id(context.log_id, sizeof(context.log_id));
     }
@@ -185,7 +189,20 @@ static request_log_context_t build_request_log_context(const char *req, const ch
     return context;
 }
 
-static int build_storage_key(
+/* Extracts <key> from /v1/data/<key> or /v2/data/<key>/items... */
+void request_data_key(const char *path, char *out, size_t out_len) {
+    out[0] = '\0';
+    const char *rest = NULL;
+    if (strncmp(path, "/v1/data/", 9) == 0) rest = path + 9;
+    if (strncmp(path, "/v2/data/", 9) == 0) rest = path + 9;
+    if (!rest) return;
+    size_t len = strcspn(rest, "/");
+    if (len == 0 || len >= out_len) return;
+    memcpy(out, rest, len);
+    out[len] = '\0';
+}
+
+int build_storage_key(
     const char *account_id,
     const char *logical_key,
     char *out_storage_key,
@@ -196,7 +213,7 @@ static int build_storage_key(
     return 0;
 }
 
-static int create_pending_write(
+int create_pending_write(
     const char *key,
     const char *payload,
     size_t payload_len,
@@ -265,7 +282,7 @@ static int send_all(int fd, const char *buf, size_t len) {
     size_t sent = 0;
     int retry = 0;
     while (sent < len) {
-        ssize_t n = send(fd, buf + sent, len - sent, socket_send_flags());
+        ssize_t n = tls_send(fd, buf + sent, len - sent);
         if (n > 0) {
             sent += (size_t)n;
             continue;
@@ -282,14 +299,40 @@ static int send_all(int fd, const char *buf, size_t len) {
     return 0;
 }
 
-static void send_response_with_log_context(
+void send_http_response(
     int fd,
     int code,
     const char *status,
+    const char *content_type,
+    const char *extra_headers,
     const char *body,
+    size_t body_len,
     const request_log_context_t *ctx) {
-    size_t body_len = body ? strlen(body) : 0;
     char header[HEADER_BUF_SIZE];
+    char server_timing[1536];
+    char timeout_body[64];
+    /* An interrupted handler may have answered from a partial result; the budget overrides it. */
+    if (deadline_expired()) {
+        code = 504;
+        status = "Gateway Timeout";
+        content_type = "application/json";
+        extra_headers = NULL;
+        int n = snprintf(timeout_body, sizeof(timeout_body), "{\"error\":\"timeout\",\"budget_ms\":%d}", deadline_budget());
+        body = timeout_body;
+        body_len = n > 0 ? (size_t)n : 0;
+    }
+    int timing_len = slowlog_server_timing_header(server_timing, sizeof(server_timing));
+    if (g_snapshot_seq >= 0) {
+        int n = snprintf(server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len, "X-Snapshot-Seq: %lld\r\n", g_snapshot_seq);
+        if (n > 0 && (size_t)(timing_len + n) < sizeof(server_timing)) timing_len += n;
+    }
+    timing_len += skew_warning_headers(server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len);
+    timing_len += quarantine_headers(server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len);
+    timing_len += deprecation_headers(server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len);
+    timing_len += cors_headers(server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len);
+    /* Captures keep the plain body; what goes on the wire may be encoded. */
+    capture_record_response(code, body, body_len);
+    compression_encode_response(code, content_type, extra_headers, &body, &body_len, server_timing + timing_len, sizeof(server_timing) - (size_t)timing_len);
     const char *log_id = (ctx && ctx->log_id[0] != '\0') ? ctx->log_id : NULL;
     int header_len = 0;
     if (log_id) {
@@ -297,34 +340,58 @@ static void send_response_with_log_context(
             header,
             sizeof(header),
             "HTTP/1.1 %d %s\r\n"
-            "Content-Type: application/json\r\n"
+            "Content-Type: %s\r\n"
             "X-Log-Id: %s\r\n"
+            "X-Request-Id: %s\r\n"
+            "%s"
+            "%s"
             "Content-Length: %zu\r\n"
             "Connection: close\r\n\r\n",
             code,
             status,
+            content_type,
+            log_id,
             log_id,
+            extra_headers ? extra_headers : "",
+            server_timing,
             body_len);
     } else {
         header_len = snprintf(
             header,
             sizeof(header),
             "HTTP/1.1 %d %s\r\n"
-            "Content-Type: application/json\r\n"
+            "Content-Type: %s\r\n"
+            "%s"
+            "%s"
             "Content-Length: %zu\r\n"
             "Connection: close\r\n\r\n",
             code,
             status,
+            content_type,
+            extra_headers ? extra_headers : "",
+            server_timing,
             body_len);
     }
+    slowlog_note_response(code, body_len);
+    otel_note_response(code, body_len);
+    accesslog_note_response(code, body_len);
     if (header_len > 0 && (size_t)header_len < sizeof(header)) {
         send_all(fd, header, (size_t)header_len);
     }
-    if (body_len > 0) {
+    if (body && body_len > 0) {
         send_all(fd, body, body_len);
     }
 }
 
+void send_response_with_log_context(
+    int fd,
+    int code,
+    const char *status,
+    const char *body,
+    const request_log_context_t *ctx) {
+    send_http_response(fd, code, status, "application/json", NULL, body, body ? strlen(body) : 0, ctx);
+}
+
 void send_response(int fd, int code, const char *status, const char *body) {
     send_response_with_log_context(fd, code, status, body, NULL);
 }
@@ -337,6 +404,7 @@ static int json_is_valid(worker_db_t *db, const char *json) {
     sqlite3_bind_text(stmt, 1, json, -1, SQLITE_TRANSIENT);
     int ok = 0;
     if (sqlite3_step(stmt) == SQLITE_ROW) ok = sqlite3_column_int(stmt, 0);
+    sqlite3_reset(stmt);
     return ok;
 }
 
@@ -346,6 +414,7 @@ static void log_http_request(
     int status_code,
     size_t payload_bytes,
     const request_log_context_t *ctx) {
+    if (deadline_expired()) status_code = 504;
     const char *log_id = (ctx && ctx->log_id[0] != '\0') ? ctx->log_id : "-";
     const char *account_id = (ctx && ctx->account_id[0] != '\0') ? ctx->account_id : "-";
     int retry_attempt = ctx ? ctx->retry_attempt : 0;
@@ -358,7 +427,50 @@ static void log_http_request(
     }
 }
 
-static int handle_get_data(int fd, worker_db_t *db, const char *key, const request_log_context_t *ctx) {
+/* IMF-fixdate, the only form Last-Modified is sent in. */
+static void format_http_date(long long epoch, char *out, size_t out_len) {
+    time_t t = (time_t)epoch;
+    struct tm tm_utc;
+    gmtime_r(&t, &tm_utc);
+    strftime(out, out_len, "%a, %d %b %Y %H:%M:%S GMT", &tm_utc);
+}
+
+/* Parses an IMF-fixdate header value into Unix seconds; -1 for anything else. */
+int http_parse_date(const char *value, long long *out) {
+    struct tm tm_value;
+    memset(&tm_value, 0, sizeof(tm_value));
+    const char *rest = strptime(value, "%a, %d %b %Y %H:%M:%S GMT", &tm_value);
+    if (!rest || *rest != '\0') return -1;
+    *out = (long long)timegm(&tm_value);
+    return 0;
+}
+
+/*
+ * Conditional GET per RFC 9110: If-None-Match, when sent, decides alone; otherwise If-Modified-Since
+ * matches when the key has not changed since that second. Unparseable dates never match.
+ */
+static int request_not_modified(const http_request_t *req, const char *version, long long updated_at) {
+    char header[256] = {0};
+    if (http_request_header(req, "If-None-Match", header, sizeof(header))) {
+        const char *p = header;
+        while (*p) {
+            p += strspn(p, " \t,");
+            if (*p == '*') return 1;
+            if (strncmp(p, "W/", 2) == 0) p += 2;
+            if (*p != '"') return 0;
+            const char *end = strchr(p + 1, '"');
+            if (!end) return 0;
+            if ((size_t)(end - p - 1) == strlen(version) && strncmp(p + 1, version, strlen(version)) == 0) return 1;
+            p = end + 1;
+        }
+        return 0;
+    }
+    long long since = 0;
+    if (!http_request_header(req, "If-Modified-Since", header, sizeof(header)) || http_parse_date(header, &since) != 0) return 0;
+    return updated_at <= since;
+}
+
+static int handle_get_data(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx) {
     sqlite3_stmt *stmt = db->get_stmt;
     if (!stmt) {
         send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
@@ -373,16 +485,48 @@ static int handle_get_data(int fd, worker_db_t *db, const char *key, const reque
         return 500;
     }
     sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    long long span_started = otel_span_start();
     int rc = sqlite3_step(stmt);
     if (rc == SQLITE_ROW) {
-        const unsigned char *value = sqlite3_column_text(stmt, 0);
-        send_response_with_log_context(fd, 200, "OK", (const char *)value, ctx);
+        const unsigned char *text = sqlite3_column_text(stmt, 0);
+        size_t value_len = (size_t)sqlite3_column_bytes(stmt, 0);
+        otel_note_db("read", key, value_len, span_started, -1.0);
+        long long updated_at = sqlite3_column_int64(stmt, 1);
+        char version[32] = {0};
+        char last_modified[64] = {0};
+        char headers[256] = {0};
+        content_version((const char *)text, value_len, version, sizeof(version));
+        format_http_date(updated_at, last_modified, sizeof(last_modified));
+        snprintf(headers, sizeof(headers), SYNC_VERSION_HEADERS_FMT "Last-Modified: %s\r\n", version, version, last_modified);
+        if (request_not_modified(req, version, updated_at)) {
+            sqlite3_reset(stmt);
+            send_http_response(fd, 304, "Not Modified", "application/json", headers, "", 0, ctx);
+            log_info("DATA READ key=%s source=not_modified account=%s logid=%s", key, ctx->account_id, ctx->log_id);
+            return 304;
+        }
+        long long items = api_key_is_collection(key) ? response_count_items(db->db, (const char *)text, value_len) : -1;
+        if (response_exceeds_limits(items, value_len)) {
+            sqlite3_reset(stmt);
+            char hint[192] = {0};
+            snprintf(hint, sizeof(hint), "fetch this key in pages with GET /v2/data/%s/items?offset=0&limit=1000", key);
+            return response_guard(fd, items, value_len, hint, ctx);
+        }
+        char *value = (char *)malloc(value_len + 1);
+        if (value && text) memcpy(value, text, value_len);
+        /* Finish the statement before sending: it releases the read snapshot and closes out its SQL timing. */
+        sqlite3_reset(stmt);
+        if (!value) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+            return 500;
+        }
+        value[value_len] = '\0';
+        send_http_response(fd, 200, "OK", "application/json", headers, value, value_len, ctx);
+        free(value);
         log_info("DATA READ key=%s source=db account=%s logid=%s", key, ctx->account_id, ctx->log_id);
         return 200;
     } else {
-        int is_object_key = strcmp(key, "profile") == 0 || strcmp(key, "app_settings") == 0;
-        const char *default_json = is_object_key ? "{}" : "[]";
-        send_response_with_log_context(fd, 200, "OK", default_json, ctx);
+        const char *default_json = api_key_is_collection(key) ? "[]" : "{}";
+        send_http_response(fd, 200, "OK", "application/json", SYNC_VERSION_HEADER ": " SYNC_MISSING_VERSION "\r\n", default_json, strlen(default_json), ctx);
         log_info("DATA READ key=%s source=default account=%s logid=%s", key, ctx->account_id, ctx->log_id);
         return 200;
     }
@@ -405,33 +549,86 @@ static int handle_get_write_queue_diagnostics(int fd, const request_log_context_
     return 200;
 }
 
-static int handle_put_data(
-    int fd,
+int data_validate_document(
     worker_db_t *db,
     const char *key,
     const char *payload,
     size_t payload_len,
-    const request_log_context_t *ctx) {
-    if (!json_is_valid(db, payload)) {
-        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json payload\"}", ctx);
+    const request_log_context_t *ctx,
+    char *out_body,
+    size_t out_body_len) {
+    out_body[0] = '\0';
+    if (payload_len >= JSON_STREAM_MIN_BYTES) {
+        /* Large bodies skip SQLite's json_valid, which would copy and parse the whole payload. */
+        json_stream_result_t parsed;
+        if (json_stream_validate(payload, payload_len, &parsed) != 0) {
+            snprintf(out_body, out_body_len, "{\"error\":\"invalid json payload\",\"detail\":\"%s\",\"offset\":%zu}", parsed.error, parsed.error_offset);
+            log_warn(
+                "DATA WRITE rejected key=%s reason=invalid_json detail=\"%s\" offset=%zu bytes=%zu logid=%s",
+                key,
+                parsed.error,
+                parsed.error_offset,
+                payload_len,
+                ctx->log_id);
+            return 400;
+        }
+    } else if (!json_is_valid(db, payload)) {
+        snprintf(out_body, out_body_len, "{\"error\":\"invalid json payload\"}");
         log_warn("DATA WRITE rejected key=%s reason=invalid_json bytes=%zu logid=%s", key, payload_len, ctx->log_id);
         return 400;
     }
+    size_t root_pos = strspn(payload, " \t\r\n");
+    char expected_root = api_key_is_collection(key) ? '[' : '{';
+    if (root_pos >= payload_len || payload[root_pos] != expected_root) {
+        snprintf(out_body, out_body_len, "{\"error\":\"%s must be a JSON %s\"}", key, expected_root == '[' ? "array" : "object");
+        log_warn("DATA WRITE rejected key=%s reason=wrong_root_type bytes=%zu logid=%s", key, payload_len, ctx->log_id);
+        return 400;
+    }
+    if (strcmp(key, "profile") == 0) {
+        int invalid = profile_validate_sports(db->db, payload, payload_len, out_body, out_body_len);
+        if (invalid != 0) {
+            log_warn("DATA WRITE rejected key=%s reason=invalid_sports body=%s logid=%s", key, out_body, ctx->log_id);
+            return invalid;
+        }
+    }
+    /* profile_validate_sports owns the sports section and reports it with field-level detail first. */
+    int schema_status = data_schema_validate(db->db, key, payload, payload_len, out_body, out_body_len);
+    if (schema_status != 0) {
+        log_warn("DATA WRITE rejected key=%s reason=schema status=%d bytes=%zu logid=%s", key, schema_status, payload_len, ctx->log_id);
+        return schema_status;
+    }
+    return 0;
+}
+
+static int store_account_document(
+    worker_db_t *db,
+    const char *key,
+    const char *payload,
+    size_t payload_len,
+    const request_log_context_t *ctx,
+    char *out_body,
+    size_t out_body_len) {
+    int invalid = data_validate_document(db, key, payload, payload_len, ctx, out_body, out_body_len);
+    if (invalid != 0) return invalid;
+    if (strcmp(key, "activities") == 0) skew_note_future_dates(skew_count_future_dates(db->db, payload, payload_len));
 
     char storage_key[256] = {0};
     if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
-        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        snprintf(out_body, out_body_len, "{\"error\":\"invalid account key\"}");
         return 500;
     }
 
     char pending_path[512] = {0};
     if (create_pending_write(storage_key, payload, payload_len, ctx, pending_path, sizeof(pending_path)) != 0) {
-        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"durable journal error\"}", ctx);
+        snprintf(out_body, out_body_len, "{\"error\":\"durable journal error\"}");
         log_error("DATA WRITE failed key=%s reason=pending_write_create_failed bytes=%zu account=%s logid=%s", key, payload_len, ctx->account_id, ctx->log_id);
         return 500;
     }
 
     write_dispatch_result_t result;
+    memset(&result, 0, sizeof(result));
+    double write_started_ms = slowlog_now_ms();
+    long long span_started = otel_span_start();
     int dispatch_rc = write_dispatch_submit(
         key,
         storage_key,
@@ -442,21 +639,21 @@ static int handle_put_data(
         ctx->log_id,
         150,
         &result);
+    slowlog_note_write(slowlog_now_ms() - write_started_ms, result.queue_ms);
+    otel_note_db("write", key, payload_len, span_started, result.queue_ms);
     if (dispatch_rc < 0) {
-        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"write queue unavailable\"}", ctx);
+        snprintf(out_body, out_body_len, "{\"error\":\"write queue unavailable\"}");
         log_error("DATA WRITE failed key=%s reason=dispatch_enqueue_failed bytes=%zu account=%s logid=%s", key, payload_len, ctx->account_id, ctx->log_id);
         return 500;
     }
 
     if (dispatch_rc > 0) {
-        char response_body[512] = {0};
         snprintf(
-            response_body,
-            sizeof(response_body),
+            out_body,
+            out_body_len,
             "{\"status\":\"queued\",\"logid\":\"%s\",\"pending\":\"%s\"}",
             ctx->log_id,
             pending_path);
-        send_response_with_log_context(fd, 202, "Accepted", response_body, ctx);
         log_warn(
             "DATA WRITE queued key=%s reason=writer_backlog bytes=%zu pending=%s account=%s logid=%s",
             key,
@@ -464,103 +661,796 @@ static int handle_put_data(
             pending_path,
             ctx->account_id,
             ctx->log_id);
+        data_queue_refresh(ctx->account_id, key);
         return 202;
     }
 
     if (result.status_code != 204) {
-        char response_body[512] = {0};
         if (result.backup_path[0] != '\0') {
             snprintf(
-                response_body,
-                sizeof(response_body),
+                out_body,
+                out_body_len,
                 "{\"error\":\"database error\",\"rc\":%d,\"ext\":%d,\"backup\":\"%s\"}",
                 result.sqlite_rc,
                 result.sqlite_ext,
                 result.backup_path);
         } else {
-            snprintf(response_body, sizeof(response_body), "{\"error\":\"database error\",\"rc\":%d,\"ext\":%d}", result.sqlite_rc, result.sqlite_ext);
+            snprintf(out_body, out_body_len, "{\"error\":\"database error\",\"rc\":%d,\"ext\":%d}", result.sqlite_rc, result.sqlite_ext);
         }
-        send_response_with_log_context(fd, 500, "Internal Server Error", response_body, ctx);
         return 500;
     }
 
-    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    data_queue_refresh(ctx->account_id, key);
     return 204;
 }
 
-int try_process_client(int fd, worker_db_t *db, conn_t *conn) {
-    conn->buf[conn->len] = '\0';
-    char *header_end = strstr(conn->buf, "\r\n\r\n");
-    if (!header_end) return 0;
-    request_log_context_t log_ctx = build_request_log_context(conn->buf, header_end);
+static int data_key_has_derived(const char *key) {
+    return strcmp(key, "activities") == 0 || strcmp(key, "profile") == 0 || strcmp(key, "travel") == 0;
+}
 
-    size_t header_len = (size_t)(header_end - conn->buf) + 4;
-    char method[8] = {0};
-    char path[512] = {0};
-    if (sscanf(conn->buf, "%7s %511s", method, path) != 2) {
-        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"malformed request line\"}", &log_ctx);
-        log_http_request("UNKNOWN", "/", 400, 0, &log_ctx);
+void data_queue_refresh(const char *account_id, const char *key) {
+    if (!data_key_has_derived(key)) return;
+    if (write_dispatch_refresh(account_id, key) != 0) log_warn("DATA REFRESH not queued key=%s account=%s", key, account_id);
+}
+
+void data_refresh_derived(sqlite3 *db, const char *account_id, const char *key) {
+    if (strcmp(key, "activities") == 0) {
+        analytics_refresh_risk_notifications(db, account_id);
+        physiology_refresh_cp_fit(db, account_id);
+        physiology_refresh_activity_metrics(db, account_id);
+        physiology_refresh_heart_metrics(db, account_id);
+        physiology_refresh_power_records(db, account_id);
+    }
+    if (strcmp(key, "activities") == 0 || strcmp(key, "profile") == 0) {
+        physiology_refresh_vo2max(db, account_id);
+    }
+    if (strcmp(key, "travel") == 0) physiology_refresh_cp_fit(db, account_id);
+}
+
+int store_account_data(
+    worker_db_t *db,
+    const char *key,
+    const char *payload,
+    size_t payload_len,
+    const request_log_context_t *ctx,
+    char *out_body,
+    size_t out_body_len) {
+    char *prepared = data_prepare_payload(db->db, ctx->account_id, key, payload, payload_len);
+    int status = prepared ? store_account_document(db, key, prepared, strlen(prepared), ctx, out_body, out_body_len)
+                          : store_account_document(db, key, payload, payload_len, ctx, out_body, out_body_len);
+    free(prepared);
+    quarantine_flush(db->db, ctx->account_id, status == 204 || status == 202);
+    return status;
+}
+
+/* The document as it will be stored: out-of-range activities quarantined, then minimized; NULL when unchanged. */
+char *data_prepare_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len) {
+    char *kept = activity_quarantine_payload(db, account_id, key, payload, payload_len);
+    char *minimized = kept ? data_minimize_payload(db, account_id, key, kept, strlen(kept)) : data_minimize_payload(db, account_id, key, payload, payload_len);
+    if (!minimized) return kept;
+    free(kept);
+    return minimized;
+}
+
+const char *http_status_text(int status) {
+    switch (status) {
+        case 200:
+            return "OK";
+        case 201:
+            return "Created";
+        case 202:
+            return "Accepted";
+        case 204:
+            return "No Content";
+        case 400:
+            return "Bad Request";
+        case 404:
+            return "Not Found";
+        case 409:
+            return "Conflict";
+        case 422:
+            return "Unprocessable Entity";
+        case 503:
+            return "Service Unavailable";
+        default:
+            return "Internal Server Error";
+    }
+}
+
+static int handle_put_data(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx) {
+    if (delta_is_request(req)) return handle_put_data_delta(fd, db, key, req, ctx);
+
+    /* The base-version check and the write must not interleave with another writer of the same document. */
+    sync_document_lock();
+    int conflict = sync_check_base_version(db, req, key, fd, ctx);
+    if (conflict != 0) {
+        sync_document_unlock();
+        return conflict;
+    }
+
+    /* Prepare here rather than only in store_account_data so the returned version matches what was stored. */
+    char *prepared = data_prepare_payload(db->db, ctx->account_id, key, req->body, req->body_len);
+    const char *doc = prepared ? prepared : req->body;
+    size_t doc_len = prepared ? strlen(prepared) : req->body_len;
+    char body[512] = {0};
+    int status = store_account_data(db, key, doc, doc_len, ctx, body, sizeof(body));
+    sync_document_unlock();
+    if (status == 204) {
+        char version[32] = {0};
+        char headers[192] = {0};
+        content_version(doc, doc_len, version, sizeof(version));
+        free(prepared);
+        snprintf(headers, sizeof(headers), SYNC_VERSION_HEADERS_FMT, version, version);
+        send_http_response(fd, status, http_status_text(status), "application/json", headers, NULL, 0, ctx);
+        return status;
+    }
+    free(prepared);
+    send_response_with_log_context(fd, status, http_status_text(status), body, ctx);
+    return status;
+}
+
+static int route_analytics(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *log_ctx) {
+    const char *method = req->method;
+    const char *path = req->path;
+
+    if (strcmp(path, "/v1/analytics/risk") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_risk(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
         return 1;
     }
 
-    if (strcmp(path, "/health") == 0 && strcmp(method, "GET") == 0) {
-        send_response_with_log_context(fd, 200, "OK", "{\"status\":\"ok\"}", &log_ctx);
-        log_http_request(method, path, 200, 0, &log_ctx);
+    if (strcmp(path, "/v1/analytics/compare") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_compare(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
         return 1;
     }
 
-    if ((strcmp(path, "/debug/write-queue") == 0 || strcmp(path, "/v1/debug/write-queue") == 0) &&
+    if (strcmp(path, "/v1/analytics/readiness") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_readiness(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/simulate") == 0 && strcmp(method, "POST") == 0) {
+        int status = handle_post_analytics_simulate(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/profile") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_profile(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/heart") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_heart(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/altitude") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_altitude(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/cp") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_analytics_cp(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/analytics/cp/fit") == 0 && strcmp(method, "POST") == 0) {
+        int status = handle_post_analytics_cp_fit(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    const char *activity_analytics_prefix = "/v1/analytics/activities/";
+    if (strncmp(path, activity_analytics_prefix, strlen(activity_analytics_prefix)) == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_activity_analytics(fd, db, req, path + strlen(activity_analytics_prefix), log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    return 0;
+}
+
+static int route_v1(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *log_ctx) {
+    const char *method = req->method;
+    const char *path = req->path;
+
+    if (strncmp(path, "/v1/analytics/", 14) == 0) {
+        /* Reads see one consistent snapshot even while an import commits; POSTs write and skip it. */
+        long long seq = 0;
+        int in_snapshot = strcmp(method, "GET") == 0 && db_read_snapshot_begin(db->db, &seq) == 0;
+        if (in_snapshot) g_snapshot_seq = seq;
+        int handled = route_analytics(fd, db, req, log_ctx);
+        g_snapshot_seq = -1;
+        if (in_snapshot) db_read_snapshot_end(db->db);
+        if (handled) return 1;
+    }
+
+    if (strcmp(path, "/v1/events/stream") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_events_stream(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/ws") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_ws(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/sync") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_sync_changes(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/sync/manifest") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_sync_manifest(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/sync/reconcile") == 0 && strcmp(method, "POST") == 0) {
+        int status = handle_post_sync_reconcile(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/live/ingest") == 0 && strcmp(method, "POST") == 0) {
+        int status = handle_post_live_ingest(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/devices") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_devices(fd, db, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/devices") == 0 && strcmp(method, "POST") == 0) {
+        int status = handle_post_devices(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strncmp(path, "/v1/quarantine/activities", 25) == 0 && (path[25] == '\0' || path[25] == '/')) {
+        int status = route_quarantine(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    const char *devices_prefix = "/v1/devices/";
+    if (strncmp(path, devices_prefix, strlen(devices_prefix)) == 0 && strcmp(method, "DELETE") == 0) {
+        int status = handle_delete_device(fd, db, path + strlen(devices_prefix), log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    /* GET /v1/workouts/<id>/export, /v1/workouts/<id>/compliance and /v1/workouts/<id>/timer. */
+    const char *workouts_prefix = "/v1/workouts/";
+    size_t workouts_prefix_len = strlen(workouts_prefix);
+    const char *workout_action = strncmp(path, workouts_prefix, workouts_prefix_len) == 0 ? strchr(path + workouts_prefix_len, '/') : NULL;
+    if (workout_action &&
+        (strcmp(workout_action, "/export") == 0 || strcmp(workout_action, "/compliance") == 0 || strcmp(workout_action, "/timer") == 0) &&
         strcmp(method, "GET") == 0) {
-        int status = handle_get_write_queue_diagnostics(fd, &log_ctx);
-        log_http_request(method, path, status, 0, &log_ctx);
+        char workout_id[256] = {0};
+        size_t id_len = (size_t)(workout_action - path) - workouts_prefix_len;
+        if (id_len < sizeof(workout_id)) memcpy(workout_id, path + workouts_prefix_len, id_len);
+        int status = 0;
+        if (strcmp(workout_action, "/export") == 0) {
+            status = handle_get_workout_export(fd, db, req, workout_id, log_ctx);
+        } else if (strcmp(workout_action, "/timer") == 0) {
+            status = handle_get_workout_timer(fd, db, req, workout_id, log_ctx);
+        } else {
+            status = handle_get_workout_compliance(fd, db, workout_id, log_ctx);
+        }
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    const char *attachments_path = "/v1/attachments";
+    if (strncmp(path, attachments_path, strlen(attachments_path)) == 0 && (path[strlen(attachments_path)] == '\0' || path[strlen(attachments_path)] == '/')) {
+        int status = route_attachments(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    const char *journal_path = "/v1/journal";
+    if (strncmp(path, journal_path, strlen(journal_path)) == 0 && (path[strlen(journal_path)] == '\0' || path[strlen(journal_path)] == '/')) {
+        int status = route_journal(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    const char *cycle_path = "/v1/cycle";
+    if (strncmp(path, cycle_path, strlen(cycle_path)) == 0 && (path[strlen(cycle_path)] == '\0' || path[strlen(cycle_path)] == '/')) {
+        int status = route_cycle(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    const char *travel_path = "/v1/travel";
+    if (strncmp(path, travel_path, strlen(travel_path)) == 0 && (path[strlen(travel_path)] == '\0' || path[strlen(travel_path)] == '/')) {
+        int status = route_travel(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    const char *calibrations_path = "/v1/gear/calibrations";
+    if (strncmp(path, calibrations_path, strlen(calibrations_path)) == 0 &&
+        (path[strlen(calibrations_path)] == '\0' || path[strlen(calibrations_path)] == '/')) {
+        int status = route_gear_calibrations(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if ((strcmp(path, "/v1/schemas") == 0 || strncmp(path, "/v1/schemas/", 12) == 0) && strcmp(method, "GET") == 0) {
+        int status = handle_get_data_schema(fd, path[11] == '/' ? path + 12 : NULL, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+    if (strcmp(path, "/v1/search") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_search(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/calendar") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_calendar(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/reports/season") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_season_report(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    const char *export_prefix = "/v1/export/";
+    if (strcmp(path, "/v1/export") == 0 || strncmp(path, export_prefix, strlen(export_prefix)) == 0) {
+        int status = route_export(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strncmp(path, "/v1/coach/", 10) == 0 || strcmp(path, "/v1/locks") == 0 || strncmp(path, "/v1/locks/", 10) == 0) {
+        int status = route_coach(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/connectors") == 0 || strncmp(path, "/v1/connectors/", 15) == 0) {
+        int status = route_connectors(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/bots") == 0 || strncmp(path, "/v1/bots/", 9) == 0) {
+        int status = route_bots(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/imports") == 0 || strncmp(path, "/v1/imports/", 12) == 0) {
+        int status = route_import_runs(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/import") == 0 || strncmp(path, "/v1/import/", 11) == 0) {
+        int status = route_import(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strncmp(path, "/v1/activities/batch-", 21) == 0) {
+        int status = route_activity_batch(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strncmp(path, "/v1/activities/", 15) == 0) {
+        int status = route_activity_feedback(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/assist/briefing") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_assist_briefing(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/notifications") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_notifications(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
         return 1;
     }
 
     const char *prefix = "/v1/data/";
     if (strncmp(path, prefix, strlen(prefix)) != 0) {
-        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", &log_ctx);
-        log_http_request(method, path, 404, 0, &log_ctx);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", log_ctx);
+        log_http_request(method, path, 404, 0, log_ctx);
+        return 1;
+    }
+
+    if (api_is_v1_items_path(path)) {
+        int status = route_v1_items(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
         return 1;
     }
 
     const char *key = path + strlen(prefix);
+    const char *diff_suffix = strstr(key, "/diff");
+    if (diff_suffix && strcmp(diff_suffix, "/diff") == 0) {
+        char diff_key[64] = {0};
+        size_t key_len = (size_t)(diff_suffix - key);
+        int status = 404;
+        if (key_len < sizeof(diff_key)) memcpy(diff_key, key, key_len);
+        if (!is_valid_key(diff_key)) {
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown key\"}", log_ctx);
+        } else if (strcmp(method, "GET") != 0) {
+            send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", log_ctx);
+            status = 405;
+        } else {
+            status = handle_get_data_diff(fd, db, diff_key, req, log_ctx);
+        }
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
     if (!is_valid_key(key)) {
-        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown key\"}", &log_ctx);
-        log_http_request(method, path, 404, 0, &log_ctx);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown key\"}", log_ctx);
+        log_http_request(method, path, 404, 0, log_ctx);
+        return 1;
+    }
+    api_v1_declare_deprecation(key);
+
+    int negotiated = sync_negotiate_protocol(req, fd, log_ctx);
+    if (negotiated != 0) {
+        log_http_request(method, path, negotiated, 0, log_ctx);
         return 1;
     }
 
-    if (log_ctx.account_id[0] == '\0') {
-        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"missing X-Account-Id\"}", &log_ctx);
-        log_http_request(method, path, 401, 0, &log_ctx);
+    char as_of[64] = {0};
+    if (strcmp(method, "GET") == 0 && query_param(req->query, "as_of", as_of, sizeof(as_of))) {
+        int status = handle_get_data_as_of(fd, db, key, as_of, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(method, "GET") == 0 && strcmp(key, "activities") == 0 && activity_filter_requested(req)) {
+        int status = handle_get_activities_filtered(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
         return 1;
     }
 
     if (strcmp(method, "GET") == 0) {
-        int status = handle_get_data(fd, db, key, &log_ctx);
-        log_http_request(method, path, status, 0, &log_ctx);
+        int status = handle_get_data(fd, db, key, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
         return 1;
     }
 
     if (strcmp(method, "PUT") == 0) {
-        int content_length = read_content_length(conn->buf, header_end);
-        if (content_length < 0 || (size_t)content_length > REQ_BUF_SIZE - header_len) {
-            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid content length\"}", &log_ctx);
-            log_http_request(method, path, 400, 0, &log_ctx);
+        int status = handle_put_data(fd, db, key, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", log_ctx);
+    log_http_request(method, path, 405, 0, log_ctx);
+    return 1;
+}
+
+static int dispatch_authorized(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *log_ctx);
+
+static int dispatch_request(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *log_ctx) {
+    const char *method = req->method;
+    const char *path = req->path;
+
+    if (strcmp(path, "/health") == 0 && strcmp(method, "GET") == 0) {
+        strbuf_t sb;
+        strbuf_init(&sb);
+        strbuf_append(&sb, "{\"status\":\"ok\",", 15);
+        cluster_append_status(&sb);
+        redis_append_status(&sb);
+        strbuf_append(&sb, "}", 1);
+        send_response_with_log_context(fd, 200, "OK", sb.failed ? "{\"status\":\"ok\"}" : strbuf_cstr(&sb), log_ctx);
+        strbuf_free(&sb);
+        log_http_request(method, path, 200, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/health/live") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_health_live(fd, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/health/ready") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_health_ready(fd, db, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    int limited = rate_limit_ip(fd, req, log_ctx);
+    if (limited != 0) {
+        log_http_request(method, path, limited, req->body_len, log_ctx);
+        return 1;
+    }
+
+    /* Browsers send preflights without credentials, so they are answered before authentication. */
+    int preflight = cors_preflight(fd, req, log_ctx);
+    if (preflight != 0) {
+        log_http_request(method, path, preflight, 0, log_ctx);
+        return 1;
+    }
+
+    int auth_status = api_auth_enforce(fd, db, req, log_ctx);
+    if (auth_status != 0) {
+        log_http_request(method, path, auth_status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    limited = rate_limit_client(fd, log_ctx);
+    if (limited != 0) {
+        log_http_request(method, path, limited, req->body_len, log_ctx);
+        return 1;
+    }
+    return dispatch_authorized(fd, db, req, log_ctx);
+}
+
+/* The routes past health checks, rate limits, CORS and authentication; /v1/users/<id>/data/... re-enters here as that user. */
+static int dispatch_authorized(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *log_ctx) {
+    const char *method = req->method;
+    const char *path = req->path;
+
+    if ((strcmp(path, "/debug/write-queue") == 0 || strcmp(path, "/v1/debug/write-queue") == 0) &&
+        strcmp(method, "GET") == 0) {
+        int status = handle_get_write_queue_diagnostics(fd, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/today/workout") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_today_workout(fd, db, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/pair") == 0) {
+        int status = handle_post_pair(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/debug/heap") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_debug_heap(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/debug/runtime") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_debug_runtime(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/pprof/cpu") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_admin_pprof_cpu(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/stats") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_admin_stats(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/metrics") == 0 && strcmp(method, "GET") == 0) {
+        int status = handle_get_admin_metrics(fd, req, log_ctx);
+        log_http_request(method, path, status, 0, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/validate") == 0) {
+        int status = handle_admin_validate(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/events") == 0 || strncmp(path, "/v1/admin/events/", 17) == 0) {
+        int status = route_admin_events(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/indexes") == 0) {
+        int status = handle_admin_indexes(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/tokens") == 0 || strncmp(path, "/v1/admin/tokens/", 17) == 0) {
+        int status = route_admin_tokens(fd, db, req, log_ctx);
+        log_http_request(method, strncmp(path, "/v1/admin/tokens/", 17) == 0 ? "/v1/admin/tokens/<id>" : path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/pairing") == 0) {
+        int status = route_admin_pairing(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/captures") == 0) {
+        int status = handle_admin_captures(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/restore") == 0) {
+        int status = handle_post_admin_restore(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/backup") == 0) {
+        int status = handle_post_admin_backup(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(path, "/v1/admin/backups") == 0) {
+        int status = route_admin_backups(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    /* A coach token alone is enough here, so this runs before the X-Account-Id check. */
+    if (strcmp(path, "/v1/users") == 0 || strncmp(path, "/v1/users/", 10) == 0) {
+        http_request_t scoped;
+        char scoped_path[512] = {0};
+        int status = route_users(fd, db, req, log_ctx, &scoped, scoped_path, sizeof(scoped_path));
+        if (status == 0) return dispatch_authorized(fd, db, &scoped, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if ((strncmp(path, "/v1/", 4) == 0 || strncmp(path, "/v2/", 4) == 0) && log_ctx->account_id[0] == '\0') {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"missing X-Account-Id\"}", log_ctx);
+        log_http_request(method, path, 401, 0, log_ctx);
+        return 1;
+    }
+
+    /* Chat commands only read, so any instance can answer them. */
+    if (strncmp(path, "/bots/", 6) == 0) {
+        int status = route_bot_webhooks(fd, db, req, log_ctx);
+        const char *logged_path = strncmp(path, "/bots/telegram/", 15) == 0 ? "/bots/telegram/<token>"
+                                  : strncmp(path, "/bots/discord/", 14) == 0 ? "/bots/discord/<token>"
+                                                                             : path;
+        log_http_request(method, logged_path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(method, "GET") != 0 && strcmp(method, "HEAD") != 0 && !cluster_is_writer()) {
+        int status = cluster_reject_write(fd, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    if (strcmp(method, "GET") != 0 && strcmp(method, "HEAD") != 0 &&
+        (strncmp(path, "/v1/data/", 9) == 0 || strncmp(path, "/v2/data/", 9) == 0)) {
+        int status = locks_enforce(fd, db, req, log_ctx);
+        if (status != 0) {
+            log_http_request(method, path, status, req->body_len, log_ctx);
             return 1;
         }
-        if ((size_t)content_length > conn->len - header_len) {
-            return 0;
-        }
+    }
 
-        char *body = conn->buf + header_len;
-        body[content_length] = '\0';
-        int status = handle_put_data(fd, db, key, body, (size_t)content_length, &log_ctx);
-        log_http_request(method, path, status, (size_t)content_length, &log_ctx);
+    if (strncmp(path, "/inbound/email/", 15) == 0) {
+        int status = handle_post_inbound_email(fd, db, req, path + 15, log_ctx);
+        log_http_request(method, "/inbound/email/<token>", status, req->body_len, log_ctx);
         return 1;
     }
 
-    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", &log_ctx);
-    log_http_request(method, path, 405, 0, &log_ctx);
-    return 1;
+    if (strncmp(path, "/v2/", 4) == 0) {
+        int status = route_v2(fd, db, req, log_ctx);
+        log_http_request(method, path, status, req->body_len, log_ctx);
+        return 1;
+    }
+
+    return route_v1(fd, db, req, log_ctx);
+}
+
+int try_process_client(int fd, worker_db_t *db, conn_t *conn) {
+    conn->buf[conn->len] = '\0';
+    char *header_end = strstr(conn->buf, "\r\n\r\n");
+    if (!header_end) return 0;
+    request_log_context_t log_ctx = build_request_log_context(conn->buf, header_end);
+
+    size_t header_len = (size_t)(header_end - conn->buf) + 4;
+    char method[8] = {0};
+    char path[512] = {0};
+    if (sscanf(conn->buf, "%7s %511s", method, path) != 2) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"malformed request line\"}", &log_ctx);
+        log_http_request("UNKNOWN", "/", 400, 0, &log_ctx);
+        return 1;
+    }
+
+    const char *query = "";
+    char *query_start = strchr(path, '?');
+    if (query_start) {
+        *query_start = '\0';
+        query = query_start + 1;
+    }
+
+    int content_length = read_content_length(conn->buf, header_end);
+    if (content_length >= 0 && request_body_guard(fd, path, content_length, &log_ctx) != 0) {
+        log_http_request(method, path, 413, 0, &log_ctx);
+        return 1;
+    }
+    if (content_length < 0 || (size_t)content_length > request_buffer_max() - header_len) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid content length\"}", &log_ctx);
+        log_http_request(method, path, 400, 0, &log_ctx);
+        return 1;
+    }
+    if ((size_t)content_length > conn->len - header_len) {
+        return 0;
+    }
+    if (worker_db_refresh(db) != 0) {
+        send_response_with_log_context(fd, 503, "Service Unavailable", "{\"error\":\"database unavailable\"}", &log_ctx);
+        log_http_request(method, path, 503, 0, &log_ctx);
+        return 1;
+    }
+
+    char *body = conn->buf + header_len;
+    body[content_length] = '\0';
+    http_request_t req = {
+        .method = method,
+        .path = path,
+        .query = query,
+        .raw = conn->buf,
+        .header_end = header_end,
+        .body = body,
+        .body_len = (size_t)content_length,
+    };
+
+    profiling_note_request(1);
+    accesslog_begin();
+    slowlog_request_begin(&req, &log_ctx);
+    otel_request_begin(&req);
+    capture_begin(&req, &log_ctx);
+    deadline_begin(&req);
+    skew_begin(&req);
+    quarantine_begin();
+    deprecation_begin();
+    cors_begin(&req);
+    compression_begin(&req);
+    int handled = 1;
+    int refused = compression_decode_request(fd, &req, &log_ctx);
+    if (refused != 0) {
+        log_http_request(method, path, refused, req.body_len, &log_ctx);
+    } else {
+        handled = dispatch_request(fd, db, &req, &log_ctx);
+    }
+    compression_end();
+    cors_end();
+    deprecation_end(&req, &log_ctx);
+    deadline_end(db->db, &req, &log_ctx);
+    skew_end(db->db, &req, &log_ctx);
+    capture_end();
+    otel_request_end(&req, &log_ctx);
+    slowlog_request_end(&req, &log_ctx);
+    accesslog_end(fd, &req, &log_ctx);
+    profiling_note_request(-1);
+    return handled;
 }
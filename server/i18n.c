@@ -0,0 +1,243 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+/*
+ * Message catalog for text the server writes itself (notifications, risk warnings, season report
+ * labels). Templates use {name} placeholders filled from named arguments, so translations can
+ * reorder them. Notifications store their message key and arguments next to the English text and
+ * are rendered in the reader's language: ?lang=, then Accept-Language, then the profile's
+ * "language", then English. A key missing in a language falls back to English.
+ */
+
+#define I18N_MAX_ARGS 16
+
+static const char *const I18N_LANGUAGES[] = {"en", "zh", "de"};
+#define I18N_LANGUAGE_COUNT (sizeof(I18N_LANGUAGES) / sizeof(I18N_LANGUAGES[0]))
+
+typedef struct {
+    const char *key;
+    const char *text[I18N_LANGUAGE_COUNT];
+} i18n_entry_t;
+
+static const i18n_entry_t I18N_CATALOG[] = {
+    {"risk.week", {"Week of {week}:", "{week} 当周：", "Woche ab {week}:"}},
+    {"risk.ramp",
+     {"CTL ramp {ramp}/week exceeds {ramp_limit}.", "CTL 周增幅 {ramp} 超过 {ramp_limit}。", "CTL-Anstieg {ramp}/Woche übersteigt {ramp_limit}."}},
+    {"risk.acwr",
+     {"Acute:chronic ratio {acwr} exceeds {acwr_limit}.", "急性/慢性负荷比 {acwr} 超过 {acwr_limit}。", "Akut:chronisch-Verhältnis {acwr} übersteigt {acwr_limit}."}},
+    {"risk.monotony", {"Monotony {monotony} exceeds {monotony_limit}.", "训练单调性 {monotony} 超过 {monotony_limit}。", "Monotonie {monotony} übersteigt {monotony_limit}."}},
+    {"pr.power",
+     {"New {duration} power record on {date}: {watts} W (previous best {previous} W)",
+      "{date} 创下 {duration} 功率新纪录：{watts} W（此前最佳 {previous} W）",
+      "Neuer {duration}-Leistungsrekord am {date}: {watts} W (bisherige Bestleistung {previous} W)"}},
+    {"report.title", {"Season report {year}", "{year} 赛季报告", "Saisonbericht {year}"}},
+    {"report.summary",
+     {"{activities} activities &middot; {hours} h &middot; {tss} TSS &middot; {km} km",
+      "{activities} 次训练 &middot; {hours} 小时 &middot; {tss} TSS &middot; {km} 公里",
+      "{activities} Einheiten &middot; {hours} h &middot; {tss} TSS &middot; {km} km"}},
+    {"report.weekly_volume", {"Weekly volume", "每周训练量", "Wochenumfang"}},
+    {"report.week_bar", {"Week {week}: {hours} h, {tss} TSS", "第 {week} 周：{hours} 小时，{tss} TSS", "Woche {week}: {hours} h, {tss} TSS"}},
+    {"report.max_week", {"max {hours} h/week", "最多 {hours} 小时/周", "max. {hours} h/Woche"}},
+    {"report.power_prs", {"Power PRs", "功率纪录", "Leistungsrekorde"}},
+    {"report.duration", {"Duration", "时长", "Dauer"}},
+    {"report.watts", {"Watts", "功率 (W)", "Watt"}},
+    {"report.date", {"Date", "日期", "Datum"}},
+    {"report.biggest_weeks", {"Biggest weeks", "训练量最大的周", "Umfangreichste Wochen"}},
+    {"report.week_of", {"Week of", "起始日", "Woche ab"}},
+    {"report.hours", {"Hours", "小时", "Stunden"}},
+    {"report.race_results", {"Race results", "比赛成绩", "Rennergebnisse"}},
+    {"report.race", {"Race", "比赛", "Rennen"}},
+    {"report.priority", {"Priority", "优先级", "Priorität"}},
+    {"report.time", {"Time", "用时", "Zeit"}},
+    {"timer.warmup", {"Warm-up", "热身", "Aufwärmen"}},
+    {"timer.work", {"Work", "训练", "Belastung"}},
+    {"timer.recovery", {"Recovery", "恢复", "Erholung"}},
+    {"timer.cooldown", {"Cool-down", "放松", "Ausklang"}},
+    {"timer.round", {"{label} {round} of {rounds}", "{label}第 {round} 组（共 {rounds} 组）", "{label} {round} von {rounds}"}},
+    {"timer.seconds", {"{seconds} seconds", "{seconds} 秒", "{seconds} Sekunden"}},
+    {"timer.one_minute", {"1 minute", "1 分钟", "1 Minute"}},
+    {"timer.minutes", {"{minutes} minutes", "{minutes} 分钟", "{minutes} Minuten"}},
+    {"timer.minutes_seconds", {"{minutes} minutes {seconds} seconds", "{minutes} 分 {seconds} 秒", "{minutes} Minuten {seconds} Sekunden"}},
+    {"timer.at_power", {"at {value} watts", "功率 {value} 瓦", "bei {value} Watt"}},
+    {"timer.at_percent", {"at {percent} percent of FTP", "{percent}% FTP", "bei {percent} Prozent FTP"}},
+    {"timer.at_pace", {"at {value} per kilometer", "配速每公里 {value}", "Pace {value} pro Kilometer"}},
+    {"timer.at_swim_pace", {"at {value} per 100 meters", "每 100 米 {value}", "{value} pro 100 Meter"}},
+    {"timer.at_heart_rate", {"at heart rate {value}", "心率 {value}", "bei Puls {value}"}},
+    {"timer.at_cadence", {"cadence {cadence}", "踏频 {cadence}", "Trittfrequenz {cadence}"}},
+    {"timer.step", {"{label}, {duration} {target}.", "{label}，{duration}，{target}。", "{label}, {duration} {target}."}},
+    {"timer.step_open", {"{label}, {duration}.", "{label}，{duration}。", "{label}, {duration}."}},
+    {"timer.next", {"Next: {step}", "下一段：{step}", "Gleich: {step}"}},
+    {"timer.halfway", {"Halfway", "已过半", "Halbzeit"}},
+    {"timer.done", {"Workout complete", "训练完成", "Training beendet"}},
+};
+
+static int language_index(const char *lang) {
+    for (size_t i = 0; lang && i < I18N_LANGUAGE_COUNT; i++) {
+        if (strcmp(I18N_LANGUAGES[i], lang) == 0) return (int)i;
+    }
+    return 0;
+}
+
+/* "zh-Hant-TW" -> "zh"; NULL when the primary subtag is not in the catalog. */
+static const char *match_language(const char *tag, size_t len) {
+    size_t primary = 0;
+    while (primary < len && isalpha((unsigned char)tag[primary])) primary++;
+    for (size_t i = 0; i < I18N_LANGUAGE_COUNT; i++) {
+        if (primary == strlen(I18N_LANGUAGES[i]) && strncasecmp(tag, I18N_LANGUAGES[i], primary) == 0) return I18N_LANGUAGES[i];
+    }
+    return NULL;
+}
+
+/* Highest-q supported language from an Accept-Language value; ties keep header order. */
+static const char *accept_language(const char *header) {
+    const char *best = NULL;
+    double best_q = 0.0;
+    const char *p = header;
+    while (*p != '\0') {
+        while (*p == ' ' || *p == ',') p++;
+        const char *tag = p;
+        while (*p != '\0' && *p != ';' && *p != ',' && *p != ' ') p++;
+        size_t tag_len = (size_t)(p - tag);
+        double q = 1.0;
+        while (*p == ' ') p++;
+        if (*p == ';') {
+            const char *param = strstr(p, "q=");
+            const char *next = strchr(p, ',');
+            if (param && (!next || param < next)) q = strtod(param + 2, NULL);
+        }
+        while (*p != '\0' && *p != ',') p++;
+        const char *lang = tag_len > 0 ? match_language(tag, tag_len) : NULL;
+        if (lang && q > best_q) {
+            best = lang;
+            best_q = q;
+        }
+    }
+    return best;
+}
+
+const char *i18n_profile_language(sqlite3 *db, const char *account_id) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return I18N_LANGUAGES[0];
+    sqlite3_stmt *stmt = NULL;
+    const char *lang = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT json_extract(data_value, '$.language') FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)", -1, &stmt, NULL) ==
+        SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) == SQLITE_TEXT) {
+            const char *value = (const char *)sqlite3_column_text(stmt, 0);
+            lang = match_language(value, strlen(value));
+        }
+        sqlite3_finalize(stmt);
+    }
+    return lang ? lang : I18N_LANGUAGES[0];
+}
+
+const char *i18n_negotiate(sqlite3 *db, const http_request_t *req, const char *account_id) {
+    char value[256] = {0};
+    const char *lang = NULL;
+    if (query_param(req->query, "lang", value, sizeof(value))) lang = match_language(value, strlen(value));
+    if (!lang && http_request_header(req, "Accept-Language", value, sizeof(value))) lang = accept_language(value);
+    if (!lang && account_id && account_id[0] != '\0') lang = i18n_profile_language(db, account_id);
+    return lang ? lang : I18N_LANGUAGES[0];
+}
+
+const char *i18n_text(const char *lang, const char *key) {
+    for (size_t i = 0; i < sizeof(I18N_CATALOG) / sizeof(I18N_CATALOG[0]); i++) {
+        if (strcmp(I18N_CATALOG[i].key, key) != 0) continue;
+        const char *text = I18N_CATALOG[i].text[language_index(lang)];
+        return text ? text : I18N_CATALOG[i].text[0];
+    }
+    return NULL;
+}
+
+int i18n_format(const char *lang, const char *key, const i18n_arg_t *args, size_t arg_count, strbuf_t *out) {
+    const char *text = i18n_text(lang, key);
+    if (!text) return -1;
+    const char *p = text;
+    while (*p != '\0') {
+        const char *open = strchr(p, '{');
+        const char *close = open ? strchr(open, '}') : NULL;
+        if (!open || !close) {
+            strbuf_append(out, p, strlen(p));
+            break;
+        }
+        strbuf_append(out, p, (size_t)(open - p));
+        size_t name_len = (size_t)(close - open - 1);
+        const char *value = NULL;
+        for (size_t i = 0; i < arg_count; i++) {
+            if (strlen(args[i].name) == name_len && strncmp(args[i].name, open + 1, name_len) == 0) value = args[i].value;
+        }
+        if (value) {
+            strbuf_append(out, value, strlen(value));
+        } else {
+            strbuf_append(out, open, (size_t)(close - open + 1));
+        }
+        p = close + 1;
+    }
+    return out->failed ? -1 : 0;
+}
+
+int i18n_format_keys(const char *lang, const char *keys, const i18n_arg_t *args, size_t arg_count, strbuf_t *out) {
+    /* Sentences of a composite message; Chinese runs them together without spaces. */
+    const char *separator = strcmp(lang, "zh") == 0 ? "" : " ";
+    int rendered = 0;
+    const char *p = keys;
+    while (*p != '\0') {
+        while (*p == ' ') p++;
+        const char *end = p;
+        while (*end != '\0' && *end != ' ') end++;
+        char key[64] = {0};
+        if (end > p && (size_t)(end - p) < sizeof(key)) {
+            memcpy(key, p, (size_t)(end - p));
+            if (rendered > 0) strbuf_append(out, separator, strlen(separator));
+            if (i18n_format(lang, key, args, arg_count, out) != 0) return -1;
+            rendered++;
+        }
+        p = end;
+    }
+    return rendered > 0 && !out->failed ? 0 : -1;
+}
+
+int i18n_args_json(const i18n_arg_t *args, size_t arg_count, strbuf_t *out) {
+    strbuf_append(out, "{", 1);
+    for (size_t i = 0; i < arg_count; i++) {
+        if (i > 0) strbuf_append(out, ",", 1);
+        strbuf_append_json_string(out, args[i].name);
+        strbuf_append(out, ":", 1);
+        strbuf_append_json_string(out, args[i].value);
+    }
+    strbuf_append(out, "}", 1);
+    return out->failed ? -1 : 0;
+}
+
+int i18n_format_stored(sqlite3 *db, const char *lang, const char *keys, const char *args_json, strbuf_t *out) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT key, CAST(value AS TEXT) FROM json_each(?1) WHERE json_valid(?1) LIMIT 16", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, args_json ? args_json : "{}", -1, SQLITE_TRANSIENT);
+    i18n_arg_t args[I18N_MAX_ARGS];
+    char *owned[I18N_MAX_ARGS * 2];
+    size_t count = 0;
+    while (count < I18N_MAX_ARGS && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *name = (const char *)sqlite3_column_text(stmt, 0);
+        const char *value = (const char *)sqlite3_column_text(stmt, 1);
+        owned[count * 2] = strdup(name ? name : "");
+        owned[count * 2 + 1] = strdup(value ? value : "");
+        args[count].name = owned[count * 2] ? owned[count * 2] : "";
+        args[count].value = owned[count * 2 + 1] ? owned[count * 2 + 1] : "";
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    int rc = i18n_format_keys(lang, keys, args, count, out);
+    for (size_t i = 0; i < count * 2; i++) free(owned[i]);
+    return rc;
+}
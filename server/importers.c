@@ -0,0 +1,2234 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Importers for other self-hosted platforms. Each source is converted into native activities or
+ * planned workouts carrying an externalID ("goldencheetah:<start>", "wger:<workout>:<day>"), so
+ * re-importing the same export only adds what is new.
+ *
+ * GoldenCheetah: ride files ({"RIDE":{...}}, or an array of them) with 1 Hz-ish SAMPLES and
+ * INTERVALS, and rideDB.json summaries ({"RIDES":[...]} with METRICS). wger: the canonical workout
+ * representation (obj / day_list / set_list / exercise_list), one planned workout per day.
+ * Device files (FIT/TCX, e.g. from the email gateway) keep the original file and are keyed by a
+ * content hash ("<source>:<hash>"). Plan templates (PLAN_TEMPLATE_FORMAT) are scheduled from a
+ * start date and keyed by "plan-template:<name>:<start>:<index>". Any of the JSON sources may be
+ * posted age-encrypted; it is opened with FRICU_EXPORT_PASSPHRASE first.
+ *
+ * Every commit is recorded in import_runs and the items it adds carry its id as importRunID, so
+ * GET /v1/imports lists the history and POST /v1/imports/<id>/rollback takes one run back out.
+ */
+
+#define IMPORT_MAX_RIDE_SEC (48 * 3600)
+#define PLAN_TEMPLATE_MAX_WORKOUTS 1000
+#define PLAN_TEMPLATE_MAX_PROBLEMS 20
+
+typedef struct {
+    strbuf_t items;
+    int item_count;
+    strbuf_t skipped;
+    int skipped_count;
+    strbuf_t files;
+    int file_count;
+} import_batch_t;
+
+static void import_batch_init(import_batch_t *batch) {
+    memset(batch, 0, sizeof(*batch));
+    strbuf_init(&batch->items);
+    strbuf_init(&batch->skipped);
+    strbuf_init(&batch->files);
+}
+
+static void import_batch_free(import_batch_t *batch) {
+    strbuf_free(&batch->items);
+    strbuf_free(&batch->skipped);
+    strbuf_free(&batch->files);
+}
+
+/* Records a source file name for the run history. */
+static void import_note_file(import_batch_t *batch, const char *name) {
+    if (batch->file_count++ > 0) strbuf_append(&batch->files, ",", 1);
+    strbuf_append_json_string(&batch->files, name);
+}
+
+static void import_skip(import_batch_t *batch, int index, const char *reason) {
+    if (batch->skipped_count++ > 0) strbuf_append(&batch->skipped, ",", 1);
+    strbuf_appendf(&batch->skipped, "{\"index\":%d,\"reason\":", index);
+    strbuf_append_json_string(&batch->skipped, reason);
+    strbuf_append(&batch->skipped, "}", 1);
+}
+
+/* Opens the next item; the caller appends the object body and the closing brace. */
+static void import_begin_item(import_batch_t *batch) {
+    if (batch->item_count++ > 0) strbuf_append(&batch->items, ",", 1);
+}
+
+static void iso_now(char *out, size_t out_len) {
+    time_t now = time(NULL);
+    struct tm tm_now;
+    gmtime_r(&now, &tm_now);
+    strftime(out, out_len, "%Y-%m-%dT%H:%M:%SZ", &tm_now);
+}
+
+/* GoldenCheetah writes "2025/05/01 07:00:00 UTC"; ISO dates are accepted too. */
+static int gc_parse_start(const char *text, char *out, size_t out_len) {
+    int y = 0, mo = 0, d = 0, h = 0, mi = 0, s = 0;
+    if (!text) return -1;
+    if (sscanf(text, "%d/%d/%d %d:%d:%d", &y, &mo, &d, &h, &mi, &s) != 6 &&
+        sscanf(text, "%d-%d-%dT%d:%d:%d", &y, &mo, &d, &h, &mi, &s) != 6) {
+        return -1;
+    }
+    if (y < 1970 || y > 9999 || mo < 1 || mo > 12 || d < 1 || d > 31 || h < 0 || h > 23 || mi < 0 || mi > 59 || s < 0 || s > 59) return -1;
+    snprintf(out, out_len, "%04d-%02d-%02dT%02d:%02d:%02dZ", y, mo, d, h, mi, s);
+    return 0;
+}
+
+static const char *gc_sport(const char *tag) {
+    if (!tag || tag[0] == '\0') return "cycling";
+    return sport_canonical_name(tag);
+}
+
+static void append_activity_head(
+    import_batch_t *batch,
+    const sport_settings_t *settings,
+    const char *id,
+    const char *date,
+    const char *sport,
+    long long duration_sec,
+    double distance_km,
+    const sport_load_t *load,
+    double np,
+    int avg_hr) {
+    import_begin_item(batch);
+    strbuf_appendf(
+        &batch->items,
+        "{\"id\":\"%s\",\"date\":\"%s\",\"sport\":\"%s\",\"athleteName\":\"\",\"durationSec\":%lld,\"distanceKm\":%.3f,\"tss\":%d,",
+        id,
+        date,
+        sport,
+        duration_sec,
+        distance_km,
+        (int)(load->tss + 0.5));
+    if (np > 0.0) strbuf_appendf(&batch->items, "\"normalizedPower\":%d,", (int)(np + 0.5));
+    if (avg_hr > 0) strbuf_appendf(&batch->items, "\"avgHeartRate\":%d,", avg_hr);
+    if (settings->default_gear[0] != '\0') {
+        strbuf_append(&batch->items, "\"gear\":", 7);
+        strbuf_append_json_string(&batch->items, settings->default_gear);
+        strbuf_append(&batch->items, ",", 1);
+    }
+    sport_append_loads(&batch->items, load);
+}
+
+static void append_samples(strbuf_t *sb, const char *name, const double *values, size_t count) {
+    strbuf_appendf(sb, ",\"%s\":[", name);
+    for (size_t i = 0; i < count; i++) {
+        strbuf_appendf(sb, i == 0 ? "%.0f" : ",%.0f", values[i]);
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+static int gc_import_ride(sqlite3 *db, import_batch_t *batch, int index, const char *ride, const char *account_id) {
+    sqlite3_stmt *stmt = NULL;
+    const char *header_sql =
+        "SELECT json_extract(?1, '$.STARTTIME'), CAST(COALESCE(json_extract(?1, '$.RECINTSECS'), 1) AS REAL),"
+        " json_extract(?1, '$.TAGS.Sport'), COALESCE(json_extract(?1, '$.TAGS.Notes'), ''),"
+        " (SELECT MAX(CAST(json_extract(value, '$.SECS') AS REAL)) FROM json_each(?1, '$.SAMPLES')),"
+        " (SELECT MAX(CAST(json_extract(value, '$.KM') AS REAL)) FROM json_each(?1, '$.SAMPLES'))";
+    if (sqlite3_prepare_v2(db, header_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, ride, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    char date[32] = {0};
+    char sport_tag[64] = {0};
+    char *notes = NULL;
+    int has_start = gc_parse_start((const char *)sqlite3_column_text(stmt, 0), date, sizeof(date)) == 0;
+    double recint = sqlite3_column_double(stmt, 1);
+    if (sqlite3_column_text(stmt, 2)) snprintf(sport_tag, sizeof(sport_tag), "%s", (const char *)sqlite3_column_text(stmt, 2));
+    notes = strdup((const char *)sqlite3_column_text(stmt, 3));
+    int has_samples = sqlite3_column_type(stmt, 4) != SQLITE_NULL;
+    double last_sec = sqlite3_column_double(stmt, 4);
+    double distance_km = sqlite3_column_double(stmt, 5);
+    sqlite3_finalize(stmt);
+
+    const char *sport = gc_sport(sport_tag);
+    const char *reason = !notes ? "oom"
+                         : !has_start ? "missing or invalid STARTTIME"
+                         : !sport ? "unsupported sport"
+                         : !has_samples ? "ride has no SAMPLES"
+                         : last_sec + recint > IMPORT_MAX_RIDE_SEC ? "ride is longer than 48 h"
+                                                                      : NULL;
+    if (reason) {
+        free(notes);
+        import_skip(batch, index, reason);
+        return 0;
+    }
+    if (recint < 1.0) recint = 1.0;
+    size_t span = (size_t)(last_sec + recint);
+    double *power = calloc(span, sizeof(double));
+    double *hr = calloc(span, sizeof(double));
+    double *cadence = calloc(span, sizeof(double));
+    if (!power || !hr || !cadence ||
+        sqlite3_prepare_v2(
+            db,
+            "SELECT CAST(json_extract(value, '$.SECS') AS REAL), COALESCE(json_extract(value, '$.WATTS'), 0),"
+            " COALESCE(json_extract(value, '$.HR'), 0), COALESCE(json_extract(value, '$.CAD'), 0)"
+            " FROM json_each(?1, '$.SAMPLES') WHERE json_extract(value, '$.SECS') >= 0",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        free(power);
+        free(hr);
+        free(cadence);
+        free(notes);
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, ride, -1, SQLITE_TRANSIENT);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        size_t from = (size_t)sqlite3_column_double(stmt, 0);
+        /* Recording intervals above one second hold each sample until the next. */
+        for (size_t t = from; t < from + (size_t)recint && t < span; t++) {
+            power[t] = sqlite3_column_double(stmt, 1);
+            hr[t] = sqlite3_column_double(stmt, 2);
+            cadence[t] = sqlite3_column_double(stmt, 3);
+        }
+    }
+    sqlite3_finalize(stmt);
+
+    double power_sum = 0.0;
+    double hr_sum = 0.0;
+    size_t hr_n = 0;
+    for (size_t i = 0; i < span; i++) {
+        power_sum += power[i];
+        if (hr[i] > 0.0) {
+            hr_sum += hr[i];
+            hr_n++;
+        }
+    }
+    double np = compute_normalized_power(power, span);
+    if (np <= 0.0) np = power_sum / (double)span;
+    int avg_hr = hr_n > 0 ? (int)(hr_sum / (double)hr_n + 0.5) : 0;
+
+    sport_settings_t settings;
+    load_sport_settings(db, account_id, sport, &settings);
+    sport_load_input_t load_input = {.duration_sec = (double)span, .distance_km = distance_km, .normalized_power = np, .avg_heart_rate = avg_hr};
+    sport_load_t load;
+    sport_compute_loads(&settings, &load_input, &load);
+    char activity_id[40] = {0};
+    int rc = generate_uuid_v4(activity_id, sizeof(activity_id));
+    if (rc == 0) {
+        append_activity_head(batch, &settings, activity_id, date, sport, (long long)span, distance_km, &load, np, avg_hr);
+        strbuf_append(&batch->items, "\"intervals\":[", 13);
+        if (sqlite3_prepare_v2(
+                db,
+                "SELECT COALESCE(json_extract(value, '$.NAME'), 'Interval'), CAST(json_extract(value, '$.START') AS REAL),"
+                " CAST(json_extract(value, '$.STOP') AS REAL) FROM json_each(?1, '$.INTERVALS')"
+                " WHERE json_extract(value, '$.STOP') > json_extract(value, '$.START') ORDER BY 2",
+                -1,
+                &stmt,
+                NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, ride, -1, SQLITE_TRANSIENT);
+            int n = 0;
+            while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+                size_t start = (size_t)sqlite3_column_double(stmt, 1);
+                size_t stop = (size_t)sqlite3_column_double(stmt, 2);
+                if (stop > span) stop = span;
+                if (start >= stop) continue;
+                double sum = 0.0;
+                for (size_t t = start; t < stop; t++) sum += power[t];
+                char interval_id[40] = {0};
+                rc = generate_uuid_v4(interval_id, sizeof(interval_id));
+                strbuf_appendf(&batch->items, "%s{\"id\":\"%s\",\"name\":", n++ > 0 ? "," : "", interval_id);
+                strbuf_append_json_string(&batch->items, (const char *)sqlite3_column_text(stmt, 0));
+                strbuf_appendf(&batch->items, ",\"durationSec\":%zu", stop - start);
+                if (sum > 0.0) strbuf_appendf(&batch->items, ",\"actualPower\":%d", (int)(sum / (double)(stop - start) + 0.5));
+                strbuf_append(&batch->items, "}", 1);
+            }
+            sqlite3_finalize(stmt);
+        } else {
+            rc = -1;
+        }
+        strbuf_append(&batch->items, "],\"notes\":", 10);
+        strbuf_append_json_string(&batch->items, notes);
+        strbuf_appendf(&batch->items, ",\"externalID\":\"goldencheetah:%s\",\"sourceFileType\":\"goldencheetah\"", date);
+        if (power_sum > 0.0) append_samples(&batch->items, "powerSamples", power, span);
+        if (hr_n > 0) append_samples(&batch->items, "heartRateSamples", hr, span);
+        append_samples(&batch->items, "cadenceSamples", cadence, span);
+        strbuf_append(&batch->items, "}", 1);
+    }
+    free(power);
+    free(hr);
+    free(cadence);
+    free(notes);
+    return rc;
+}
+
+/* rideDB.json metrics are strings, numbers or [value, count] pairs. */
+#define GC_METRIC(name) \
+    "CAST(COALESCE(json_extract(?1, '$.METRICS." name "[0]'), json_extract(?1, '$.METRICS." name "'), 0) AS REAL)"
+
+static int gc_import_summary(sqlite3 *db, import_batch_t *batch, int index, const char *ride, const char *account_id) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT json_extract(?1, '$.date'), COALESCE(json_extract(?1, '$.sport'), json_extract(?1, '$.TAGS.Sport')),"
+        " COALESCE(json_extract(?1, '$.TAGS.Notes'), ''), " GC_METRIC("workout_time") ", " GC_METRIC("total_distance") ","
+        " " GC_METRIC("coggan_np") ", " GC_METRIC("coggan_tss") ", " GC_METRIC("average_hr");
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, ride, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    char date[32] = {0};
+    const char *sport = gc_sport((const char *)sqlite3_column_text(stmt, 1));
+    double duration = sqlite3_column_double(stmt, 3);
+    const char *reason = gc_parse_start((const char *)sqlite3_column_text(stmt, 0), date, sizeof(date)) != 0 ? "missing or invalid date"
+                         : !sport                                                                           ? "unsupported sport"
+                         : duration <= 0.0                                                                  ? "missing workout_time"
+                                                                                                            : NULL;
+    if (reason) {
+        sqlite3_finalize(stmt);
+        import_skip(batch, index, reason);
+        return 0;
+    }
+    double np = sqlite3_column_double(stmt, 5);
+    sport_settings_t settings;
+    load_sport_settings(db, account_id, sport, &settings);
+    sport_load_input_t load_input = {
+        .duration_sec = duration,
+        .distance_km = sqlite3_column_double(stmt, 4),
+        .normalized_power = np,
+        .avg_heart_rate = sqlite3_column_double(stmt, 7),
+        .source_tss = sqlite3_column_double(stmt, 6)};
+    sport_load_t load;
+    sport_compute_loads(&settings, &load_input, &load);
+    char activity_id[40] = {0};
+    int rc = generate_uuid_v4(activity_id, sizeof(activity_id));
+    if (rc == 0) {
+        append_activity_head(
+            batch, &settings, activity_id, date, sport, (long long)(duration + 0.5), sqlite3_column_double(stmt, 4), &load, np, (int)(sqlite3_column_double(stmt, 7) + 0.5));
+        strbuf_append(&batch->items, "\"intervals\":[],\"notes\":", 23);
+        strbuf_append_json_string(&batch->items, (const char *)sqlite3_column_text(stmt, 2));
+        strbuf_appendf(&batch->items, ",\"externalID\":\"goldencheetah:%s\",\"sourceFileType\":\"goldencheetah\"}", date);
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+static int import_goldencheetah(sqlite3 *db, const char *body, const char *account_id, import_batch_t *batch) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT 'ride', json_extract(?1, '$.RIDE') WHERE json_type(?1, '$.RIDE') = 'object'"
+        " UNION ALL SELECT 'ride', json_extract(value, '$.RIDE') FROM json_each(?1)"
+        "  WHERE json_type(?1) = 'array' AND type = 'object' AND json_type(value, '$.RIDE') = 'object'"
+        " UNION ALL SELECT 'summary', value FROM json_each(?1, '$.RIDES') WHERE type = 'object'";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, body, -1, SQLITE_TRANSIENT);
+    int index = 0;
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *kind = (const char *)sqlite3_column_text(stmt, 0);
+        const char *ride = (const char *)sqlite3_column_text(stmt, 1);
+        rc = strcmp(kind, "ride") == 0 ? gc_import_ride(db, batch, index, ride, account_id) : gc_import_summary(db, batch, index, ride, account_id);
+        index++;
+    }
+    sqlite3_finalize(stmt);
+    return rc != 0 ? -1 : index;
+}
+
+static int wger_import_day(sqlite3 *db, import_batch_t *batch, int index, const char *workout_name, const char *workout_id, const char *day_json, const char *created_at) {
+    sqlite3_stmt *stmt = NULL;
+    const char *day_sql =
+        "SELECT COALESCE(json_extract(?1, '$.obj.description'), json_extract(?1, '$.obj.name'), 'Day'),"
+        " CAST(COALESCE(json_extract(?1, '$.obj.id'), json_extract(?1, '$.obj.description')) AS TEXT)";
+    if (sqlite3_prepare_v2(db, day_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, day_json, -1, SQLITE_TRANSIENT);
+    char day_name[256] = {0};
+    char day_id[128] = {0};
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(day_name, sizeof(day_name), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        if (sqlite3_column_text(stmt, 1)) snprintf(day_id, sizeof(day_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    }
+    sqlite3_finalize(stmt);
+
+    const char *exercise_sql =
+        "SELECT MAX(CAST(COALESCE(json_extract(s.value, '$.obj.sets'), 1) AS INTEGER), 1),"
+        " COALESCE(json_extract(e.value, '$.obj.name'), json_extract(e.value, '$.name'), 'Exercise'),"
+        " json_extract(e.value, '$.setting_text'), json_extract(e.value, '$.setting_obj_list[0].reps'),"
+        " json_extract(e.value, '$.setting_obj_list[0].weight')"
+        " FROM json_each(?1, '$.set_list') s, json_each(s.value, '$.exercise_list') e ORDER BY s.key, e.key";
+    if (sqlite3_prepare_v2(db, exercise_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, day_json, -1, SQLITE_TRANSIENT);
+    strbuf_t segments;
+    strbuf_init(&segments);
+    int count = 0;
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        int sets = sqlite3_column_int(stmt, 0);
+        char note[512] = {0};
+        const char *name = (const char *)sqlite3_column_text(stmt, 1);
+        const char *setting_text = (const char *)sqlite3_column_text(stmt, 2);
+        const char *weight = (const char *)sqlite3_column_text(stmt, 4);
+        if (setting_text) {
+            snprintf(note, sizeof(note), "%s %s", name, setting_text);
+        } else if (sqlite3_column_type(stmt, 3) != SQLITE_NULL) {
+            snprintf(note, sizeof(note), "%s %dx%d%s%s%s", name, sets, sqlite3_column_int(stmt, 3), weight ? " @ " : "", weight ? weight : "", weight ? " kg" : "");
+        } else {
+            snprintf(note, sizeof(note), "%s %d sets", name, sets);
+        }
+        char segment_id[40] = {0};
+        rc = generate_uuid_v4(segment_id, sizeof(segment_id));
+        /* Strength sets have no %FTP target; about two minutes per set including rest. */
+        strbuf_appendf(&segments, "%s{\"id\":\"%s\",\"minutes\":%d,\"intensityPercentFTP\":0,\"note\":", count++ > 0 ? "," : "", segment_id, sets * 2);
+        strbuf_append_json_string(&segments, note);
+        strbuf_append(&segments, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+
+    char workout_uuid[40] = {0};
+    if (rc == 0 && count == 0) {
+        import_skip(batch, index, "day has no exercises");
+    } else if (rc == 0 && (rc = generate_uuid_v4(workout_uuid, sizeof(workout_uuid))) == 0) {
+        char name[600] = {0};
+        char external_id[300] = {0};
+        snprintf(name, sizeof(name), "%s - %s", workout_name, day_name);
+        snprintf(external_id, sizeof(external_id), "wger:%s:%s", workout_id, day_id);
+        import_begin_item(batch);
+        strbuf_appendf(&batch->items, "{\"id\":\"%s\",\"createdAt\":\"%s\",\"name\":", workout_uuid, created_at);
+        strbuf_append_json_string(&batch->items, name);
+        strbuf_append(&batch->items, ",\"sport\":\"strength\",\"athleteName\":\"\",\"segments\":[", 49);
+        strbuf_append(&batch->items, strbuf_cstr(&segments), segments.len);
+        strbuf_append(&batch->items, "],\"externalID\":", 15);
+        strbuf_append_json_string(&batch->items, external_id);
+        strbuf_append(&batch->items, "}", 1);
+    }
+    strbuf_free(&segments);
+    return rc;
+}
+
+static int import_wger(sqlite3 *db, const char *body, import_batch_t *batch) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "WITH workouts AS ("
+        "  SELECT ?1 AS w WHERE json_type(?1, '$.day_list') = 'array'"
+        "  UNION ALL SELECT value FROM json_each(?1) WHERE json_type(?1) = 'array' AND type = 'object'"
+        "  UNION ALL SELECT value FROM json_each(?1, '$.results') WHERE type = 'object')"
+        " SELECT COALESCE(json_extract(w, '$.obj.name'), json_extract(w, '$.name'), 'wger workout'),"
+        " CAST(COALESCE(json_extract(w, '$.obj_id'), json_extract(w, '$.obj.id'), json_extract(w, '$.id'), '') AS TEXT),"
+        " d.value"
+        " FROM workouts, json_each(w, '$.day_list') d";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, body, -1, SQLITE_TRANSIENT);
+    char created_at[32] = {0};
+    iso_now(created_at, sizeof(created_at));
+    int index = 0;
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        rc = wger_import_day(
+            db, batch, index++, (const char *)sqlite3_column_text(stmt, 0), (const char *)sqlite3_column_text(stmt, 1), (const char *)sqlite3_column_text(stmt, 2), created_at);
+    }
+    sqlite3_finalize(stmt);
+    return rc != 0 ? -1 : index;
+}
+
+/* Appends the batch to the stored collection, skipping items whose externalID is already there. */
+/* The stored array an import merges into ("[]" when the key is unset), or NULL if it is not an array. */
+static char *load_import_document(sqlite3 *db, const char *storage_key) {
+    sqlite3_stmt *stmt = NULL;
+    char *doc = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE((SELECT data_value FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)"
+            " AND json_type(data_value) = 'array'), CASE WHEN EXISTS (SELECT 1 FROM kv_store WHERE data_key = ?1) THEN NULL ELSE '[]' END)",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) doc = strdup((const char *)sqlite3_column_text(stmt, 0));
+        sqlite3_finalize(stmt);
+    }
+    return doc;
+}
+
+/* item_ids is the JSON array of the ids the run added; file names come from the batch. */
+static int import_record_run(
+    sqlite3 *db, const char *run_id, const char *source, const char *key, const import_batch_t *batch, const char *item_ids, int imported, int duplicates, const char *account_id) {
+    strbuf_t files;
+    strbuf_init(&files);
+    strbuf_append(&files, "[", 1);
+    strbuf_append(&files, strbuf_cstr(&batch->files), batch->files.len);
+    strbuf_append(&files, "]", 1);
+    sqlite3_stmt *stmt = NULL;
+    int rc = -1;
+    if (!files.failed &&
+        sqlite3_prepare_v2(
+            db,
+            "INSERT INTO import_runs (id, account_id, source, data_key, file_names, item_ids, imported, duplicates, skipped, created_at)"
+            " VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, run_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, source, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 4, key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 5, strbuf_cstr(&files), (int)files.len, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 6, item_ids, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 7, imported);
+        sqlite3_bind_int(stmt, 8, duplicates);
+        sqlite3_bind_int(stmt, 9, batch->skipped_count);
+        rc = sqlite3_step(stmt) == SQLITE_DONE ? 0 : -1;
+        sqlite3_finalize(stmt);
+    }
+    strbuf_free(&files);
+    return rc;
+}
+
+static int import_commit(int fd, worker_db_t *db, const char *source, const char *key, import_batch_t *batch, const request_log_context_t *ctx) {
+    char storage_key[256] = {0};
+    char run_id[40] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0 || generate_uuid_v4(run_id, sizeof(run_id)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    strbuf_t items;
+    strbuf_init(&items);
+    strbuf_append(&items, "[", 1);
+    strbuf_append(&items, strbuf_cstr(&batch->items), batch->items.len);
+    strbuf_append(&items, "]", 1);
+
+    sync_document_lock();
+    sqlite3_stmt *stmt = NULL;
+    char *doc = load_import_document(db->db, storage_key);
+    if (!doc || items.failed) {
+        sync_document_unlock();
+        free(doc);
+        strbuf_free(&items);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+        return 409;
+    }
+
+    strbuf_t imported;
+    strbuf_t duplicates;
+    strbuf_t run_ids;
+    strbuf_init(&imported);
+    strbuf_init(&duplicates);
+    strbuf_init(&run_ids);
+    strbuf_append(&run_ids, "[", 1);
+    int imported_count = 0;
+    int duplicate_count = 0;
+    int failed = 0;
+    const char *merge_sql =
+        "SELECT n.value, json_extract(n.value, '$.id'), json_extract(n.value, '$.externalID'),"
+        " EXISTS (SELECT 1 FROM json_each(?1) s WHERE s.type = 'object' AND json_extract(s.value, '$.externalID') = json_extract(n.value, '$.externalID'))"
+        " FROM json_each(?2) n WHERE n.key = ?3";
+    for (int i = 0; !failed && i < batch->item_count; i++) {
+        if (sqlite3_prepare_v2(db->db, merge_sql, -1, &stmt, NULL) != SQLITE_OK) {
+            failed = 1;
+            break;
+        }
+        sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, strbuf_cstr(&items), (int)items.len, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 3, i);
+        if (sqlite3_step(stmt) != SQLITE_ROW) {
+            failed = 1;
+        } else if (sqlite3_column_int(stmt, 3)) {
+            if (duplicate_count++ > 0) strbuf_append(&duplicates, ",", 1);
+            strbuf_append_json_string(&duplicates, (const char *)sqlite3_column_text(stmt, 2));
+        } else {
+            sqlite3_stmt *insert = NULL;
+            char *next = NULL;
+            if (sqlite3_prepare_v2(db->db, "SELECT json_insert(?1, '$[#]', json_set(json(?2), '$.importRunID', ?3))", -1, &insert, NULL) == SQLITE_OK) {
+                sqlite3_bind_text(insert, 1, doc, -1, SQLITE_TRANSIENT);
+                sqlite3_bind_text(insert, 2, (const char *)sqlite3_column_text(stmt, 0), -1, SQLITE_TRANSIENT);
+                sqlite3_bind_text(insert, 3, run_id, -1, SQLITE_TRANSIENT);
+                if (sqlite3_step(insert) == SQLITE_ROW && sqlite3_column_text(insert, 0)) next = strdup((const char *)sqlite3_column_text(insert, 0));
+                sqlite3_finalize(insert);
+            }
+            if (!next) {
+                failed = 1;
+            } else {
+                free(doc);
+                doc = next;
+                if (imported_count > 0) strbuf_append(&run_ids, ",", 1);
+                strbuf_append_json_string(&run_ids, (const char *)sqlite3_column_text(stmt, 1));
+                if (imported_count++ > 0) strbuf_append(&imported, ",", 1);
+                strbuf_append(&imported, "{\"id\":", 6);
+                strbuf_append_json_string(&imported, (const char *)sqlite3_column_text(stmt, 1));
+                strbuf_append(&imported, ",\"externalID\":", 14);
+                strbuf_append_json_string(&imported, (const char *)sqlite3_column_text(stmt, 2));
+                strbuf_append(&imported, "}", 1);
+            }
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_free(&items);
+    strbuf_append(&run_ids, "]", 1);
+    failed = failed || run_ids.failed;
+
+    int status = 200;
+    char error_body[512] = {0};
+    if (!failed && imported_count > 0) status = store_account_data(db, key, doc, strlen(doc), ctx, error_body, sizeof(error_body));
+    sync_document_unlock();
+    free(doc);
+    int recorded = !failed && (status == 204 || status == 202 || status == 200) &&
+                   import_record_run(db->db, run_id, source, key, batch, strbuf_cstr(&run_ids), imported_count, duplicate_count, ctx->account_id) == 0;
+    strbuf_free(&run_ids);
+    if (!recorded && !failed && (status == 204 || status == 202 || status == 200)) {
+        log_warn("IMPORT run not recorded run=%s account=%s logid=%s err=%s", run_id, ctx->account_id, ctx->log_id, sqlite3_errmsg(db->db));
+    }
+    if (failed || (status != 204 && status != 202 && status != 200)) {
+        strbuf_free(&imported);
+        strbuf_free(&duplicates);
+        if (failed) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        send_response_with_log_context(fd, status, status == 400 ? "Bad Request" : status == 422 ? "Unprocessable Entity" : "Internal Server Error", error_body, ctx);
+        return status;
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"source\":", 10);
+    strbuf_append_json_string(&sb, source);
+    strbuf_append(&sb, ",\"key\":", 7);
+    strbuf_append_json_string(&sb, key);
+    strbuf_append(&sb, ",\"import_run_id\":", 17);
+    if (recorded) {
+        strbuf_append_json_string(&sb, run_id);
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_appendf(&sb, ",\"queued\":%s,\"imported\":[", status == 202 ? "true" : "false");
+    strbuf_append(&sb, strbuf_cstr(&imported), imported.len);
+    strbuf_append(&sb, "],\"duplicates\":[", 16);
+    strbuf_append(&sb, strbuf_cstr(&duplicates), duplicates.len);
+    strbuf_append(&sb, "],\"skipped\":[", 13);
+    strbuf_append(&sb, strbuf_cstr(&batch->skipped), batch->skipped.len);
+    strbuf_append(&sb, "]}", 2);
+    strbuf_free(&imported);
+    strbuf_free(&duplicates);
+    log_info(
+        "IMPORT run=%s source=%s key=%s imported=%d duplicates=%d skipped=%d account=%s logid=%s",
+        run_id,
+        source,
+        key,
+        imported_count,
+        duplicate_count,
+        batch->skipped_count,
+        ctx->account_id,
+        ctx->log_id);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* keep_file 0 leaves the original file out of the record; its samples and summary are kept either way. */
+static int import_activity_file(
+    sqlite3 *db,
+    import_batch_t *batch,
+    int index,
+    const char *source,
+    const import_file_t *file,
+    const char *fallback_date,
+    int keep_file,
+    const char *account_id) {
+    const char *type = activity_file_type(file->name, file->data, file->len);
+    import_note_file(batch, file->name && file->name[0] != '\0' ? file->name : type ? type : "file");
+    if (!type) {
+        import_skip(batch, index, "unsupported file type (expected .fit or .tcx)");
+        return 0;
+    }
+    activity_file_summary_t summary;
+    if (activity_file_summarize(type, file->data, file->len, &summary) != 0) {
+        import_skip(batch, index, "could not read activity file");
+        return 0;
+    }
+    if (summary.duration_sec <= 0.0) {
+        activity_file_summary_free(&summary);
+        import_skip(batch, index, "activity file has no duration");
+        return 0;
+    }
+    char date[32] = {0};
+    if (summary.date[0] != '\0') {
+        snprintf(date, sizeof(date), "%s", summary.date);
+    } else if (fallback_date && fallback_date[0] != '\0') {
+        snprintf(date, sizeof(date), "%s", fallback_date);
+    } else {
+        iso_now(date, sizeof(date));
+    }
+    const char *sport = summary.sport[0] != '\0' ? summary.sport : "cycling";
+    sport_settings_t settings;
+    load_sport_settings(db, account_id, sport, &settings);
+    sport_load_input_t load_input = {
+        .duration_sec = summary.duration_sec,
+        .distance_km = summary.distance_km,
+        .normalized_power = summary.normalized_power,
+        .avg_heart_rate = summary.avg_hr,
+        .source_tss = summary.tss};
+    sport_load_t load;
+    sport_compute_loads(&settings, &load_input, &load);
+    char hash[32] = {0};
+    content_version((const char *)file->data, file->len, hash, sizeof(hash));
+    size_t encoded_len = keep_file ? (file->len + 2) / 3 * 4 + 1 : 1;
+    char *encoded = malloc(encoded_len);
+    char activity_id[40] = {0};
+    int rc = encoded && generate_uuid_v4(activity_id, sizeof(activity_id)) == 0 ? 0 : -1;
+    if (rc == 0) {
+        encoded[0] = '\0';
+        if (keep_file) base64_encode(file->data, file->len, encoded, encoded_len);
+        append_activity_head(
+            batch,
+            &settings,
+            activity_id,
+            date,
+            sport,
+            (long long)(summary.duration_sec + 0.5),
+            summary.distance_km,
+            &load,
+            summary.normalized_power,
+            summary.avg_hr);
+        strbuf_appendf(&batch->items, "\"intervals\":[],\"notes\":\"\",\"externalID\":\"%s:%s\",\"sourceFileName\":", source, hash);
+        strbuf_append_json_string(&batch->items, file->name && file->name[0] != '\0' ? file->name : type);
+        strbuf_appendf(&batch->items, ",\"sourceFileType\":\"%s\"", type);
+        if (keep_file) {
+            strbuf_append(&batch->items, ",\"sourceFileBase64\":\"", 21);
+            strbuf_append(&batch->items, encoded, strlen(encoded));
+            strbuf_append(&batch->items, "\"", 1);
+        }
+        if (summary.power_count > 0) append_samples(&batch->items, "powerSamples", summary.power, summary.power_count);
+        if (summary.heart_rate_count > 0) append_samples(&batch->items, "heartRateSamples", summary.heart_rate, summary.heart_rate_count);
+        strbuf_append(&batch->items, "}", 1);
+    }
+    free(encoded);
+    activity_file_summary_free(&summary);
+    return rc;
+}
+
+int import_activity_files(
+    int fd,
+    worker_db_t *db,
+    const char *source,
+    const import_file_t *files,
+    size_t count,
+    const char *fallback_date,
+    int keep_files,
+    const request_log_context_t *ctx) {
+    import_batch_t batch;
+    import_batch_init(&batch);
+    int rc = 0;
+    for (size_t i = 0; rc == 0 && i < count; i++) {
+        rc = import_activity_file(db->db, &batch, (int)i, source, &files[i], fallback_date, keep_files, ctx->account_id);
+    }
+    int status = 0;
+    if (rc != 0 || batch.items.failed || batch.skipped.failed) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"could not build activities\"}", ctx);
+        status = 500;
+    } else {
+        status = import_commit(fd, db, source, "activities", &batch, ctx);
+    }
+    import_batch_free(&batch);
+    return status;
+}
+
+/*
+ * Reports what import_commit would do with a batch of activities without writing: the summary of
+ * each one, the stored activity it duplicates (same externalID, so the import would skip it) and,
+ * separately, a stored activity starting in the same minute with a similar duration (the same ride
+ * synced from another source), which the import does not catch.
+ */
+/* extra, when set, is more members for the answer (the diary wizard's column mapping). */
+static int import_preview(int fd, worker_db_t *db, const char *source, import_batch_t *batch, const char *extra, const request_log_context_t *ctx) {
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    strbuf_t items;
+    strbuf_init(&items);
+    strbuf_append(&items, "[", 1);
+    strbuf_append(&items, strbuf_cstr(&batch->items), batch->items.len);
+    strbuf_append(&items, "]", 1);
+    char *doc = load_import_document(db->db, storage_key);
+    if (!doc || items.failed) {
+        free(doc);
+        strbuf_free(&items);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+        return 409;
+    }
+    /* ?3: file uploads match on the content hash whichever source stored the file first. */
+    const char *sql =
+        "SELECT CASE WHEN duplicate THEN json_set(item, '$.possible_duplicate_of', NULL) ELSE item END, duplicate FROM ("
+        "SELECT item, json_extract(item, '$.duplicate_of') IS NOT NULL AS duplicate FROM ("
+        " SELECT json_object('date', json_extract(n.value, '$.date'), 'sport', json_extract(n.value, '$.sport'),"
+        " 'duration_sec', json_extract(n.value, '$.durationSec'), 'distance_km', json_extract(n.value, '$.distanceKm'),"
+        " 'tss', json_extract(n.value, '$.tss'), 'normalized_power', json_extract(n.value, '$.normalizedPower'),"
+        " 'avg_heart_rate', json_extract(n.value, '$.avgHeartRate'), 'external_id', json_extract(n.value, '$.externalID'),"
+        " 'source_file_name', json_extract(n.value, '$.sourceFileName'),"
+        " 'power_samples', json_array_length(n.value, '$.powerSamples'), 'heart_rate_samples', json_array_length(n.value, '$.heartRateSamples'),"
+        " 'duplicate_of', (SELECT json_extract(s.value, '$.id') FROM json_each(?1) s WHERE s.type = 'object'"
+        "  AND (json_extract(s.value, '$.externalID') = json_extract(n.value, '$.externalID') OR (?3 AND"
+        "   substr(json_extract(s.value, '$.externalID'), instr(json_extract(s.value, '$.externalID'), ':'))"
+        "   = substr(json_extract(n.value, '$.externalID'), instr(json_extract(n.value, '$.externalID'), ':')))) LIMIT 1),"
+        " 'possible_duplicate_of', (SELECT json_extract(s.value, '$.id') FROM json_each(?1) s WHERE s.type = 'object'"
+        "  AND substr(json_extract(s.value, '$.date'), 1, 16) = substr(json_extract(n.value, '$.date'), 1, 16)"
+        "  AND abs(COALESCE(json_extract(s.value, '$.durationSec'), 0) - json_extract(n.value, '$.durationSec'))"
+        "   <= max(60, 0.05 * json_extract(n.value, '$.durationSec')) LIMIT 1)) AS item"
+        " FROM json_each(?2) n ORDER BY n.key))";
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"source\":", 10);
+    strbuf_append_json_string(&sb, source);
+    strbuf_append(&sb, ",\"key\":\"activities\",", 20);
+    if (extra) {
+        strbuf_append(&sb, extra, strlen(extra));
+        strbuf_append(&sb, ",", 1);
+    }
+    strbuf_append(&sb, "\"items\":[", 9);
+    int new_count = 0;
+    int duplicate_count = 0;
+    int failed = 1;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, strbuf_cstr(&items), (int)items.len, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 3, strcmp(source, "file") == 0);
+        int row = 0;
+        int rc;
+        while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+            if (row++ > 0) strbuf_append(&sb, ",", 1);
+            strbuf_append(&sb, (const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0));
+            if (sqlite3_column_int(stmt, 1)) {
+                duplicate_count++;
+            } else {
+                new_count++;
+            }
+        }
+        failed = rc != SQLITE_DONE;
+        sqlite3_finalize(stmt);
+    }
+    free(doc);
+    strbuf_free(&items);
+    strbuf_appendf(&sb, "],\"new\":%d,\"duplicates\":%d,\"skipped\":[", new_count, duplicate_count);
+    strbuf_append(&sb, strbuf_cstr(&batch->skipped), batch->skipped.len);
+    strbuf_append(&sb, "]}", 2);
+    if (failed || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/*
+ * POST /v1/import/diary takes a hand-kept training log as CSV (comma, semicolon or tab separated,
+ * with a header row) and turns each row into a summary-only activity flagged "manualEntry":true.
+ * Its load is session RPE (RPE x minutes) unless the sport's own model can score it, which without
+ * sensors it rarely can, so decades of paper logs feed the same load and fitness analytics. The
+ * wizard step is POST /v1/import/preview?source=diary with the same body and query: it answers
+ * with the header row, the column each field was read from and the activities a commit would
+ * create, so a wrong mapping is fixed before anything is written.
+ *
+ * Columns are found by header name (date, sport, duration, rpe, notes and common synonyms) or
+ * named with ?date_column=, ?sport_column=, ?duration_column=, ?rpe_column= and ?notes_column=.
+ * Dates are YYYY-MM-DD unless ?date_format=dmy or mdy (with -, / or . between the parts);
+ * durations are h:mm, h:mm:ss or minutes, and a trailing h, min or s picks another unit. ?sport=
+ * is the sport of a log without a sport column. Rows that cannot be read are skipped and
+ * reported. Each row is keyed "diary:<date>:<sport>:<n>", n counting that day's sessions of the
+ * sport in the file, so importing a diary again after adding pages only adds the new rows.
+ */
+
+#define DIARY_MAX_COLUMNS 32
+#define DIARY_FIELD_MAX 512
+#define DIARY_MAX_ROWS 50000
+
+typedef enum { DIARY_DATE, DIARY_SPORT, DIARY_DURATION, DIARY_RPE, DIARY_NOTES, DIARY_FIELD_COUNT } diary_field_t;
+
+static const struct {
+    const char *name;
+    const char *synonyms[6];
+} DIARY_FIELDS[DIARY_FIELD_COUNT] = {
+    {"date", {"date", "day", "datum", "fecha", "日期", NULL}},
+    {"sport", {"sport", "type", "activity", "discipline", "运动", NULL}},
+    {"duration", {"duration", "time", "minutes", "dauer", "时长", NULL}},
+    {"rpe", {"rpe", "effort", "borg", "intensity", "强度", NULL}},
+    {"notes", {"notes", "note", "comment", "comments", "备注", NULL}},
+};
+
+typedef struct {
+    char fields[DIARY_MAX_COLUMNS][DIARY_FIELD_MAX];
+    int count;
+} diary_record_t;
+
+/* Reads one CSV record (RFC 4180 quoting); 0 at the end of the input. Overlong fields are cut. */
+static int diary_read_record(const char **cursor, const char *end, char delim, diary_record_t *out) {
+    const char *p = *cursor;
+    out->count = 0;
+    if (p >= end) return 0;
+    for (;;) {
+        char *field = out->count < DIARY_MAX_COLUMNS ? out->fields[out->count] : NULL;
+        size_t len = 0;
+        int quoted = p < end && *p == '"';
+        if (quoted) p++;
+        while (p < end) {
+            char c = *p;
+            if (quoted) {
+                if (c == '"' && p + 1 < end && p[1] == '"') {
+                    p++;
+                } else if (c == '"') {
+                    quoted = 0;
+                    p++;
+                    continue;
+                }
+            } else if (c == delim || c == '\n' || c == '\r') {
+                break;
+            }
+            if (field && len + 1 < DIARY_FIELD_MAX) field[len++] = c;
+            p++;
+        }
+        if (field) {
+            while (len > 0 && (field[len - 1] == ' ' || field[len - 1] == '\t')) len--;
+            size_t lead = strspn(field, " \t");
+            if (lead > len) lead = len;
+            memmove(field, field + lead, len - lead);
+            field[len - lead] = '\0';
+            out->count++;
+        }
+        if (p < end && *p == delim) {
+            p++;
+            continue;
+        }
+        if (p < end && *p == '\r') p++;
+        if (p < end && *p == '\n') p++;
+        break;
+    }
+    *cursor = p;
+    return 1;
+}
+
+static int diary_record_blank(const diary_record_t *record) {
+    for (int i = 0; i < record->count; i++) {
+        if (record->fields[i][0] != '\0') return 0;
+    }
+    return 1;
+}
+
+/* The separator used most in the header line. */
+static char diary_detect_delimiter(const char *data, const char *end) {
+    int commas = 0, semicolons = 0, tabs = 0;
+    for (const char *p = data; p < end && *p != '\n'; p++) {
+        commas += *p == ',';
+        semicolons += *p == ';';
+        tabs += *p == '\t';
+    }
+    if (tabs > commas && tabs > semicolons) return '\t';
+    return semicolons > commas ? ';' : ',';
+}
+
+/* "1:30" and "1:30:00" are h:mm(:ss); a bare number is minutes unless it ends in h, min or s. */
+static double diary_parse_duration(const char *text) {
+    int h = 0, m = 0, s = 0;
+    char tail = 0;
+    if (sscanf(text, "%d:%d:%d%c", &h, &m, &s, &tail) == 3 && h >= 0 && m >= 0 && m < 60 && s >= 0 && s < 60) return h * 3600.0 + m * 60.0 + s;
+    if (sscanf(text, "%d:%d%c", &h, &m, &tail) == 2 && h >= 0 && m >= 0 && m < 60) return h * 3600.0 + m * 60.0;
+    char *unit = NULL;
+    double value = strtod(text, &unit);
+    if (unit == text || !(value > 0.0) || !isfinite(value)) return -1.0;
+    unit += strspn(unit, " ");
+    if (unit[0] == '\0' || strcasecmp(unit, "min") == 0 || strcasecmp(unit, "m") == 0) return value * 60.0;
+    if (strcasecmp(unit, "h") == 0) return value * 3600.0;
+    if (strcasecmp(unit, "s") == 0) return value;
+    return -1.0;
+}
+
+/* Normalises a diary date to YYYY-MM-DD, rejecting days that do not exist. */
+static int diary_parse_date(const char *text, const char *format, char *out, size_t out_len, int *out_day) {
+    int a = 0, b = 0, c = 0;
+    char sep1 = 0, sep2 = 0;
+    if (sscanf(text, "%d%c%d%c%d", &a, &sep1, &b, &sep2, &c) != 5 || sep1 != sep2 || !strchr("-/.", sep1)) return -1;
+    int y = a, m = b, d = c;
+    if (strcmp(format, "dmy") == 0) {
+        y = c;
+        d = a;
+    } else if (strcmp(format, "mdy") == 0) {
+        y = c;
+        m = a;
+        d = b;
+    }
+    if (y < 1000 || y > 9999 || m < 1 || m > 12 || d < 1 || d > 31) return -1;
+    char iso[16] = {0};
+    snprintf(iso, sizeof(iso), "%04d-%02d-%02d", y % 10000, m % 100, d % 100);
+    int day = 0;
+    char check[16] = {0};
+    if (parse_iso_day(iso, &day) != 0) return -1;
+    format_iso_day(day, check, sizeof(check));
+    if (strcmp(check, iso) != 0) return -1;
+    snprintf(out, out_len, "%s", iso);
+    *out_day = day;
+    return 0;
+}
+
+typedef struct {
+    int columns[DIARY_FIELD_COUNT];
+    char default_sport[32];
+    char date_format[8];
+} diary_mapping_t;
+
+/*
+ * Maps each field to a header column from ?<field>_column= or by name; 0 when the log can be read,
+ * otherwise an error body for a 400.
+ */
+static int diary_map_columns(const http_request_t *req, const diary_record_t *header, diary_mapping_t *map, char *err, size_t err_len) {
+    char raw[DIARY_FIELD_MAX] = {0};
+    memset(map, 0, sizeof(*map));
+    snprintf(map->date_format, sizeof(map->date_format), "ymd");
+    if (query_param(req->query, "date_format", raw, sizeof(raw))) {
+        if (strcmp(raw, "ymd") != 0 && strcmp(raw, "dmy") != 0 && strcmp(raw, "mdy") != 0) {
+            snprintf(err, err_len, "{\"error\":\"date_format must be ymd, dmy or mdy\"}");
+            return -1;
+        }
+        snprintf(map->date_format, sizeof(map->date_format), "%s", raw);
+    }
+    if (query_param(req->query, "sport", raw, sizeof(raw))) {
+        const char *sport = sport_canonical_name(raw);
+        if (!sport) {
+            snprintf(err, err_len, "{\"error\":\"unknown sport\"}");
+            return -1;
+        }
+        snprintf(map->default_sport, sizeof(map->default_sport), "%s", sport);
+    }
+    for (int f = 0; f < DIARY_FIELD_COUNT; f++) {
+        char param[32] = {0};
+        snprintf(param, sizeof(param), "%s_column", DIARY_FIELDS[f].name);
+        map->columns[f] = -1;
+        int named = query_param(req->query, param, raw, sizeof(raw));
+        for (int c = 0; c < header->count && map->columns[f] < 0; c++) {
+            if (named) {
+                if (strcasecmp(header->fields[c], raw) == 0) map->columns[f] = c;
+                continue;
+            }
+            for (int s = 0; s < 6 && DIARY_FIELDS[f].synonyms[s]; s++) {
+                if (strcasecmp(header->fields[c], DIARY_FIELDS[f].synonyms[s]) == 0) map->columns[f] = c;
+            }
+        }
+        if (named && map->columns[f] < 0) {
+            snprintf(err, err_len, "{\"error\":\"%s is not a column of the header row\",\"field\":\"%s\"}", param, DIARY_FIELDS[f].name);
+            return -1;
+        }
+    }
+    const char *missing = map->columns[DIARY_DATE] < 0       ? "date"
+                          : map->columns[DIARY_DURATION] < 0 ? "duration"
+                          : map->columns[DIARY_SPORT] < 0 && map->default_sport[0] == '\0' ? "sport"
+                                                                                            : NULL;
+    if (missing) {
+        snprintf(
+            err,
+            err_len,
+            "{\"error\":\"no %s column in the header row; name it with ?%s_column=%s\",\"field\":\"%s\"}",
+            missing,
+            missing,
+            strcmp(missing, "sport") == 0 ? " or give ?sport=" : "",
+            missing);
+        return -1;
+    }
+    return 0;
+}
+
+/* The wizard's view of the mapping: the header row and the column each field came from. */
+static void diary_append_mapping(strbuf_t *sb, const diary_record_t *header, const diary_mapping_t *map) {
+    strbuf_append(sb, "\"header\":[", 10);
+    for (int c = 0; c < header->count; c++) {
+        if (c > 0) strbuf_append(sb, ",", 1);
+        strbuf_append_json_string(sb, header->fields[c]);
+    }
+    strbuf_append(sb, "],\"columns\":{", 13);
+    for (int f = 0; f < DIARY_FIELD_COUNT; f++) {
+        strbuf_appendf(sb, "%s\"%s\":", f > 0 ? "," : "", DIARY_FIELDS[f].name);
+        if (map->columns[f] >= 0) {
+            strbuf_append_json_string(sb, header->fields[map->columns[f]]);
+        } else {
+            strbuf_append(sb, "null", 4);
+        }
+    }
+    strbuf_appendf(sb, "},\"date_format\":\"%s\",\"sport\":", map->date_format);
+    if (map->default_sport[0] != '\0') {
+        strbuf_append_json_string(sb, map->default_sport);
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+}
+
+static const char *diary_field(const diary_record_t *record, const diary_mapping_t *map, diary_field_t field) {
+    int c = map->columns[field];
+    return c >= 0 && c < record->count ? record->fields[c] : "";
+}
+
+/* Sessions counted per (day, sport) while reading, for the ordinal in the external id. */
+#define DIARY_SLOTS 65536
+
+typedef struct {
+    int day;
+    const char *sport;
+    int count;
+} diary_slot_t;
+
+static int diary_next_ordinal(diary_slot_t *slots, int day, const char *sport) {
+    size_t i = ((size_t)(unsigned)day * 2654435761u ^ (size_t)(uintptr_t)sport) & (DIARY_SLOTS - 1);
+    while (slots[i].sport && (slots[i].day != day || slots[i].sport != sport)) i = (i + 1) & (DIARY_SLOTS - 1);
+    slots[i].day = day;
+    slots[i].sport = sport;
+    return ++slots[i].count;
+}
+
+/* Turns the rows after the header into activities; the row count, or -1 when the log is too long. */
+static int import_diary_rows(sqlite3 *db, const char **cursor, const char *end, char delim, const diary_mapping_t *map, const char *account_id, import_batch_t *batch) {
+    static __thread diary_record_t record;
+    diary_slot_t *slots = calloc(DIARY_SLOTS, sizeof(*slots));
+    if (!slots) return -1;
+    /* sport_canonical_name hands back the table's own string, so slots compare sports by pointer. */
+    const char *default_sport = map->default_sport[0] != '\0' ? sport_canonical_name(map->default_sport) : NULL;
+    int index = 0;
+    int rc = 0;
+    while (rc == 0 && diary_read_record(cursor, end, delim, &record)) {
+        if (diary_record_blank(&record)) continue;
+        if (index >= DIARY_MAX_ROWS) {
+            rc = -1;
+            break;
+        }
+        char date[16] = {0};
+        int day = 0;
+        const char *sport_text = diary_field(&record, map, DIARY_SPORT);
+        const char *sport = sport_text[0] != '\0' ? sport_canonical_name(sport_text) : default_sport;
+        double duration = diary_parse_duration(diary_field(&record, map, DIARY_DURATION));
+        const char *rpe_text = diary_field(&record, map, DIARY_RPE);
+        char *rpe_end = NULL;
+        double rpe = rpe_text[0] != '\0' ? strtod(rpe_text, &rpe_end) : 0.0;
+        int rpe_bad = rpe_text[0] != '\0' && (rpe_end == rpe_text || *rpe_end != '\0' || !(rpe >= 0.0 && rpe <= 10.0));
+        const char *reason = diary_parse_date(diary_field(&record, map, DIARY_DATE), map->date_format, date, sizeof(date), &day) != 0 ? "missing or invalid date"
+                             : !sport                                                                                              ? "unsupported sport"
+                             : duration <= 0.0 || duration > IMPORT_MAX_RIDE_SEC                                                    ? "missing or invalid duration"
+                             : rpe_bad                                                                                             ? "rpe must be a number in 0..10"
+                                                                                                                                   : NULL;
+        if (reason) {
+            import_skip(batch, index++, reason);
+            continue;
+        }
+        sport_settings_t settings;
+        load_sport_settings(db, account_id, sport, &settings);
+        sport_load_input_t load_input = {.duration_sec = duration, .rpe = rpe};
+        sport_load_t load;
+        sport_compute_loads(&settings, &load_input, &load);
+        /* As with feedback, sRPE becomes the primary load when nothing else scored the session. */
+        if (load.model[0] == '\0' && rpe > 0.0) {
+            load.tss = rpe * duration / 60.0;
+            snprintf(load.model, sizeof(load.model), "srpe");
+        }
+        char activity_id[40] = {0};
+        char start[32] = {0};
+        rc = generate_uuid_v4(activity_id, sizeof(activity_id));
+        if (rc != 0) break;
+        /* A diary has no start time; noon UTC keeps the calendar day in every time zone. */
+        snprintf(start, sizeof(start), "%sT12:00:00Z", date);
+        append_activity_head(batch, &settings, activity_id, start, sport, (long long)(duration + 0.5), 0.0, &load, 0.0, 0);
+        if (rpe_text[0] != '\0') strbuf_appendf(&batch->items, "\"rpe\":%g,", rpe);
+        strbuf_append(&batch->items, "\"intervals\":[],\"manualEntry\":true,\"notes\":", 42);
+        strbuf_append_json_string(&batch->items, diary_field(&record, map, DIARY_NOTES));
+        strbuf_appendf(
+            &batch->items, ",\"externalID\":\"diary:%s:%s:%d\",\"sourceFileType\":\"diary\"}", date, sport, diary_next_ordinal(slots, day, sport));
+        index++;
+    }
+    free(slots);
+    return rc != 0 ? -1 : index;
+}
+
+/* Reads the header and the rows of a diary upload; 0, or the status of the error already sent. */
+static int import_diary(int fd, sqlite3 *db, const http_request_t *req, import_batch_t *batch, strbuf_t *mapping, const request_log_context_t *ctx) {
+    static __thread diary_record_t header;
+    const char *cursor = req->body;
+    const char *end = req->body + req->body_len;
+    if (req->body_len >= 3 && memcmp(cursor, "\xEF\xBB\xBF", 3) == 0) cursor += 3;
+    char delim = diary_detect_delimiter(cursor, end);
+    int found = 0;
+    while (!found && diary_read_record(&cursor, end, delim, &header)) found = !diary_record_blank(&header);
+    if (!found) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body must be a CSV diary with a header row\"}", ctx);
+        return 400;
+    }
+    diary_mapping_t map;
+    char err[256] = {0};
+    if (diary_map_columns(req, &header, &map, err, sizeof(err)) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", err, ctx);
+        return 400;
+    }
+    diary_append_mapping(mapping, &header, &map);
+    int rows = import_diary_rows(db, &cursor, end, delim, &map, ctx->account_id, batch);
+    if (rows < 0 || batch->items.failed || batch->skipped.failed || mapping->failed) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"could not read diary (at most 50000 rows)\"}", ctx);
+        return 400;
+    }
+    if (rows == 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"no diary rows after the header\"}", ctx);
+        return 400;
+    }
+    return 0;
+}
+
+static int handle_import_diary(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int locked = locks_enforce_key(fd, db, req, "activities", ctx);
+    if (locked != 0) return locked;
+    import_batch_t batch;
+    import_batch_init(&batch);
+    char filename[256] = {0};
+    if (query_param(req->query, "filename", filename, sizeof(filename)) && filename[0] != '\0') import_note_file(&batch, filename);
+    strbuf_t mapping;
+    strbuf_init(&mapping);
+    int status = import_diary(fd, db->db, req, &batch, &mapping, ctx);
+    if (status == 0) status = import_commit(fd, db, "diary", "activities", &batch, ctx);
+    strbuf_free(&mapping);
+    import_batch_free(&batch);
+    return status;
+}
+
+/*
+ * POST /v1/import/preview?filename=ride.fit takes the raw FIT/TCX file (or, with
+ * ?source=goldencheetah, a GoldenCheetah export, or with ?source=diary, a CSV diary) and answers
+ * with the activities it would create.
+ */
+static int handle_import_preview(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char source[32] = {0};
+    char filename[256] = {0};
+    query_param(req->query, "source", source, sizeof(source));
+    query_param(req->query, "filename", filename, sizeof(filename));
+    int goldencheetah = strcmp(source, "goldencheetah") == 0;
+    int diary = strcmp(source, "diary") == 0;
+    if (source[0] != '\0' && !goldencheetah && !diary && strcmp(source, "file") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"source must be file, goldencheetah or diary\"}", ctx);
+        return 400;
+    }
+    if (req->body_len == 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"request body must be the file to preview\"}", ctx);
+        return 400;
+    }
+    import_batch_t batch;
+    import_batch_init(&batch);
+    strbuf_t mapping;
+    strbuf_init(&mapping);
+    int status = 0;
+    if (diary) {
+        status = import_diary(fd, db->db, req, &batch, &mapping, ctx);
+    } else if (goldencheetah) {
+        char *body = strndup(req->body, req->body_len);
+        int entries = body ? import_goldencheetah(db->db, body, ctx->account_id, &batch) : -1;
+        free(body);
+        if (entries <= 0 || batch.items.failed || batch.skipped.failed) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"no GoldenCheetah rides found (expected RIDE or RIDES)\"}", ctx);
+            status = 400;
+        }
+    } else {
+        import_file_t file = {filename, (const unsigned char *)req->body, req->body_len};
+        if (import_activity_file(db->db, &batch, 0, "file", &file, NULL, 1, ctx->account_id) != 0 || batch.items.failed || batch.skipped.failed) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"could not build activities\"}", ctx);
+            status = 500;
+        }
+    }
+    const char *name = diary ? "diary" : goldencheetah ? "goldencheetah" : "file";
+    if (status == 0) status = import_preview(fd, db, name, &batch, diary ? strbuf_cstr(&mapping) : NULL, ctx);
+    strbuf_free(&mapping);
+    import_batch_free(&batch);
+    return status;
+}
+
+/* Every way a plan template breaks the format, as (path, problem) rows; ?1 is valid JSON. */
+static const char *PLAN_TEMPLATE_PROBLEMS_SQL =
+    "WITH w AS (SELECT key AS i, value AS v, type AS t FROM json_each(?1, '$.workouts') WHERE json_type(?1, '$.workouts') = 'array'),"
+    " s AS (SELECT w.i, seg.key AS j, seg.value AS v, seg.type AS t FROM w, json_each(w.v, '$.segments') seg"
+    "  WHERE w.t = 'object' AND json_type(w.v, '$.segments') = 'array')"
+    " SELECT path, problem FROM ("
+    " SELECT 0 AS i, -1 AS j, '$' AS path, 'must be an object' AS problem WHERE json_type(?1) <> 'object'"
+    " UNION ALL SELECT 0, -1, '$.' || key, 'unknown field' FROM json_each(?1)"
+    "  WHERE json_type(?1) = 'object' AND key NOT IN ('format', 'name', 'description', 'reference', 'weeks', 'workouts')"
+    " UNION ALL SELECT 0, -1, '$.format', 'must be \"" PLAN_TEMPLATE_FORMAT "\"' WHERE json_extract(?1, '$.format') IS NOT ?2"
+    " UNION ALL SELECT 0, -1, '$.name', 'must be a non-empty string of at most 128 characters'"
+    "  WHERE json_type(?1, '$.name') IS NOT 'text' OR trim(json_extract(?1, '$.name')) = '' OR length(json_extract(?1, '$.name')) > 128"
+    " UNION ALL SELECT 0, -1, '$.description', 'must be a string or null' WHERE json_type(?1, '$.description') NOT IN ('text', 'null')"
+    " UNION ALL SELECT 0, -1, '$.reference', 'must be an object or null' WHERE json_type(?1, '$.reference') NOT IN ('object', 'null')"
+    " UNION ALL SELECT 0, -1, '$.reference.' || key, 'unknown field' FROM json_each(?1, '$.reference')"
+    "  WHERE json_type(?1, '$.reference') = 'object' AND key NOT IN ('ftpWatts', 'thresholdPaceSecPerKm', 'cssSecPer100m')"
+    " UNION ALL SELECT 0, -1, '$.reference.' || key, 'must be a positive number or null' FROM json_each(?1, '$.reference')"
+    "  WHERE json_type(?1, '$.reference') = 'object' AND key IN ('ftpWatts', 'thresholdPaceSecPerKm', 'cssSecPer100m')"
+    "  AND (type NOT IN ('integer', 'real', 'null') OR (type <> 'null' AND value <= 0))"
+    " UNION ALL SELECT 0, -1, '$.weeks', 'must be an integer in 1..52'"
+    "  WHERE json_type(?1, '$.weeks') IS NOT 'integer' OR json_extract(?1, '$.weeks') NOT BETWEEN 1 AND ?3"
+    " UNION ALL SELECT 0, -1, '$.workouts', 'must be a non-empty array of at most 1000 workouts'"
+    "  WHERE json_type(?1, '$.workouts') IS NOT 'array' OR json_array_length(?1, '$.workouts') NOT BETWEEN 1 AND ?4"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || ']', 'must be an object' FROM w WHERE t <> 'object'"
+    " UNION ALL SELECT w.i, -1, '$.workouts[' || w.i || '].' || f.key, 'unknown field' FROM w, json_each(w.v) f"
+    "  WHERE w.t = 'object' AND f.key NOT IN ('week', 'day', 'name', 'sport', 'segments')"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || '].week', 'must be an integer in 1..weeks' FROM w WHERE t = 'object'"
+    "  AND (json_type(v, '$.week') IS NOT 'integer' OR json_extract(v, '$.week') < 1 OR json_extract(v, '$.week') > json_extract(?1, '$.weeks'))"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || '].day', 'must be an integer in 1..7 (1 = Monday)' FROM w WHERE t = 'object'"
+    "  AND (json_type(v, '$.day') IS NOT 'integer' OR json_extract(v, '$.day') NOT BETWEEN 1 AND 7)"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || '].name', 'must be a non-empty string of at most 128 characters' FROM w WHERE t = 'object'"
+    "  AND (json_type(v, '$.name') IS NOT 'text' OR trim(json_extract(v, '$.name')) = '' OR length(json_extract(v, '$.name')) > 128)"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || '].sport', 'must be a string of at most 32 characters' FROM w WHERE t = 'object'"
+    "  AND json_type(v, '$.sport') IS NOT NULL AND (json_type(v, '$.sport') <> 'text' OR length(json_extract(v, '$.sport')) > 32)"
+    " UNION ALL SELECT i, -1, '$.workouts[' || i || '].segments', 'must be a non-empty array' FROM w WHERE t = 'object'"
+    "  AND (json_type(v, '$.segments') IS NOT 'array' OR json_array_length(v, '$.segments') = 0)"
+    " UNION ALL SELECT i, j, '$.workouts[' || i || '].segments[' || j || ']', 'must be an object' FROM s WHERE t <> 'object'"
+    " UNION ALL SELECT s.i, s.j, '$.workouts[' || s.i || '].segments[' || s.j || '].' || f.key, 'unknown field' FROM s, json_each(s.v) f"
+    "  WHERE s.t = 'object' AND f.key NOT IN ('minutes', 'intensityPercentFTP', 'cadence', 'note')"
+    " UNION ALL SELECT i, j, '$.workouts[' || i || '].segments[' || j || '].minutes', 'must be a number in (0, 600]' FROM s WHERE t = 'object'"
+    "  AND (json_type(v, '$.minutes') IS NULL OR json_type(v, '$.minutes') NOT IN ('integer', 'real') OR json_extract(v, '$.minutes') <= 0"
+    "  OR json_extract(v, '$.minutes') > 600)"
+    " UNION ALL SELECT i, j, '$.workouts[' || i || '].segments[' || j || '].intensityPercentFTP', 'must be a number in 0..300 (% of FTP)'"
+    "  FROM s WHERE t = 'object' AND (json_type(v, '$.intensityPercentFTP') IS NULL"
+    "  OR json_type(v, '$.intensityPercentFTP') NOT IN ('integer', 'real') OR json_extract(v, '$.intensityPercentFTP') NOT BETWEEN 0 AND 300)"
+    " UNION ALL SELECT i, j, '$.workouts[' || i || '].segments[' || j || '].cadence', 'must be an integer in 0..250 or null' FROM s WHERE t = 'object'"
+    "  AND (json_type(v, '$.cadence') NOT IN ('integer', 'null') OR (json_type(v, '$.cadence') = 'integer' AND json_extract(v, '$.cadence') NOT BETWEEN 0 AND 250))"
+    " UNION ALL SELECT i, j, '$.workouts[' || i || '].segments[' || j || '].note', 'must be a string of at most 256 characters or null' FROM s"
+    "  WHERE t = 'object' AND (json_type(v, '$.note') NOT IN ('text', 'null') OR length(json_extract(v, '$.note')) > 256)"
+    ") ORDER BY i, j LIMIT ?5";
+
+/* Appends {"path":...,"error":...} entries to problems; returns how many, or -1. */
+static int plan_template_problems(sqlite3 *db, const char *body, strbuf_t *problems) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, PLAN_TEMPLATE_PROBLEMS_SQL, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("IMPORT plan template validation prepare failed: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, body, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, PLAN_TEMPLATE_FORMAT, -1, SQLITE_STATIC);
+    sqlite3_bind_int(stmt, 3, PLAN_TEMPLATE_MAX_WEEKS);
+    sqlite3_bind_int(stmt, 4, PLAN_TEMPLATE_MAX_WORKOUTS);
+    sqlite3_bind_int(stmt, 5, PLAN_TEMPLATE_MAX_PROBLEMS);
+    int count = 0;
+    int rc;
+    while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(problems, ",", 1);
+        strbuf_append(problems, "{\"path\":", 8);
+        strbuf_append_json_string(problems, (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_append(problems, ",\"error\":", 9);
+        strbuf_append_json_string(problems, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(problems, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? count : -1;
+}
+
+/*
+ * Target scaling at import. %FTP stays the portable anchor; each segment also gets the absolute
+ * target for the importing athlete's current threshold (targetWatts from the sport's FTP, or
+ * targetPaceSecPerKm / targetPaceSecPer100m from threshold pace / CSS). With a CP cap, a step whose
+ * watts exceed what the latest CP fit allows for its duration (CP + W'/t) is lowered to that limit
+ * and keeps its template value as cappedFromPercentFTP. Each workout records the inputs in
+ * targetScaling, with factor comparing the athlete to the template's reference fitness.
+ */
+typedef struct {
+    int scale;
+    int cap_by_cp;
+    double cp;
+    double w_prime;
+} plan_scaling_t;
+
+typedef struct {
+    int pace_per_100m;
+    double ftp_watts;
+    double threshold_pace;
+    double reference;
+    int capped_segments;
+} plan_workout_targets_t;
+
+static void plan_targets_for_sport(sqlite3 *db, const char *account_id, const char *sport, sqlite3_stmt *row, plan_workout_targets_t *out) {
+    memset(out, 0, sizeof(*out));
+    sport_settings_t settings;
+    load_sport_settings(db, account_id, sport, &settings);
+    if (strcmp(sport, "running") == 0) {
+        out->threshold_pace = settings.threshold_pace_sec_per_km;
+        out->reference = sqlite3_column_double(row, 12);
+    } else if (strcmp(sport, "swimming") == 0) {
+        out->pace_per_100m = 1;
+        out->threshold_pace = settings.css_sec_per_100m;
+        out->reference = sqlite3_column_double(row, 13);
+    } else {
+        out->ftp_watts = settings.ftp_watts > 0.0 ? settings.ftp_watts : load_profile_ftp(db, account_id);
+        out->reference = sqlite3_column_double(row, 11);
+    }
+}
+
+static void plan_append_scaling(strbuf_t *sb, const plan_scaling_t *scaling, const plan_workout_targets_t *targets) {
+    strbuf_append(sb, ",\"targetScaling\":{", 18);
+    if (targets->ftp_watts > 0.0) {
+        strbuf_appendf(sb, "\"basis\":\"ftp\",\"ftpWatts\":%.0f,\"referenceFtpWatts\":", targets->ftp_watts);
+    } else if (targets->threshold_pace > 0.0) {
+        strbuf_appendf(
+            sb,
+            "\"basis\":\"%s\",\"%s\":%.0f,\"%s\":",
+            targets->pace_per_100m ? "css" : "threshold_pace",
+            targets->pace_per_100m ? "cssSecPer100m" : "thresholdPaceSecPerKm",
+            targets->threshold_pace,
+            targets->pace_per_100m ? "referenceCssSecPer100m" : "referenceThresholdPaceSecPerKm");
+    } else {
+        strbuf_append(sb, "\"basis\":null,\"reference\":", 25);
+    }
+    if (targets->reference > 0.0) {
+        /* Faster pace is fitter, so pace factors invert. */
+        double factor = 0.0;
+        if (targets->ftp_watts > 0.0) factor = targets->ftp_watts / targets->reference;
+        if (targets->ftp_watts <= 0.0 && targets->threshold_pace > 0.0) factor = targets->reference / targets->threshold_pace;
+        strbuf_appendf(sb, "%.0f,\"factor\":", targets->reference);
+        if (factor > 0.0) {
+            strbuf_appendf(sb, "%.3f", factor);
+        } else {
+            strbuf_append(sb, "null", 4);
+        }
+    } else {
+        strbuf_append(sb, "null,\"factor\":null", 18);
+    }
+    if (scaling->cap_by_cp && targets->ftp_watts > 0.0) {
+        strbuf_appendf(
+            sb, ",\"cap\":{\"model\":\"cp\",\"cp\":%.0f,\"wPrimeJoules\":%.0f,\"cappedSegments\":%d}}", scaling->cp, scaling->w_prime, targets->capped_segments);
+    } else {
+        strbuf_append(sb, ",\"cap\":null}", 12);
+    }
+}
+
+static void plan_append_segment_targets(strbuf_t *sb, const plan_scaling_t *scaling, plan_workout_targets_t *targets, double minutes, double percent) {
+    if (targets->ftp_watts > 0.0) {
+        double watts = targets->ftp_watts * percent / 100.0;
+        double limit = scaling->cap_by_cp ? scaling->cp + scaling->w_prime / (minutes * 60.0) : 0.0;
+        if (scaling->cap_by_cp && watts > limit) {
+            targets->capped_segments++;
+            strbuf_appendf(sb, "%g,\"cappedFromPercentFTP\":%g,\"targetWatts\":%.0f", round(limit * 1000.0 / targets->ftp_watts) / 10.0, percent, limit);
+        } else {
+            strbuf_appendf(sb, "%g,\"targetWatts\":%.0f", percent, watts);
+        }
+    } else if (targets->threshold_pace > 0.0 && percent > 0.0) {
+        strbuf_appendf(sb, "%g,\"%s\":%.0f", percent, targets->pace_per_100m ? "targetPaceSecPer100m" : "targetPaceSecPerKm", targets->threshold_pace * 100.0 / percent);
+    } else {
+        strbuf_appendf(sb, "%g", percent);
+    }
+}
+
+/* One planned workout per template entry, week 1 starting on start_day (a Monday). */
+static int import_plan_template(sqlite3 *db, const char *body, int start_day, const char *account_id, const plan_scaling_t *scaling, import_batch_t *batch) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT w.key, json_extract(w.value, '$.week'), json_extract(w.value, '$.day'), json_extract(w.value, '$.name'),"
+        " COALESCE(json_extract(w.value, '$.sport'), 'cycling'), json_extract(?1, '$.name'), s.key,"
+        " json_extract(s.value, '$.minutes'), json_extract(s.value, '$.intensityPercentFTP'),"
+        " json_extract(s.value, '$.cadence'), json_extract(s.value, '$.note'),"
+        " json_extract(?1, '$.reference.ftpWatts'), json_extract(?1, '$.reference.thresholdPaceSecPerKm'),"
+        " json_extract(?1, '$.reference.cssSecPer100m')"
+        " FROM json_each(?1, '$.workouts') w, json_each(w.value, '$.segments') s ORDER BY w.key, s.key";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, body, -1, SQLITE_TRANSIENT);
+    char created_at[32] = {0};
+    char start_label[16] = {0};
+    iso_now(created_at, sizeof(created_at));
+    format_iso_day(start_day, start_label, sizeof(start_label));
+    plan_workout_targets_t targets;
+    memset(&targets, 0, sizeof(targets));
+    int rc = 0;
+    int workouts = 0;
+    int current = -1;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        int index = sqlite3_column_int(stmt, 0);
+        if (index != current) {
+            if (current >= 0) {
+                strbuf_append(&batch->items, "]", 1);
+                if (scaling->scale) plan_append_scaling(&batch->items, scaling, &targets);
+                strbuf_append(&batch->items, "}", 1);
+            }
+            current = index;
+            char workout_uuid[40] = {0};
+            char day_label[16] = {0};
+            char external_id[300] = {0};
+            if ((rc = generate_uuid_v4(workout_uuid, sizeof(workout_uuid))) != 0) break;
+            format_iso_day(start_day + (sqlite3_column_int(stmt, 1) - 1) * 7 + sqlite3_column_int(stmt, 2) - 1, day_label, sizeof(day_label));
+            snprintf(external_id, sizeof(external_id), "plan-template:%s:%s:%d", (const char *)sqlite3_column_text(stmt, 5), start_label, index);
+            if (scaling->scale) plan_targets_for_sport(db, account_id, (const char *)sqlite3_column_text(stmt, 4), stmt, &targets);
+            import_begin_item(batch);
+            strbuf_appendf(&batch->items, "{\"id\":\"%s\",\"createdAt\":\"%s\",\"scheduledDate\":\"%sT00:00:00Z\",\"name\":", workout_uuid, created_at, day_label);
+            strbuf_append_json_string(&batch->items, (const char *)sqlite3_column_text(stmt, 3));
+            strbuf_append(&batch->items, ",\"sport\":", 9);
+            strbuf_append_json_string(&batch->items, (const char *)sqlite3_column_text(stmt, 4));
+            strbuf_append(&batch->items, ",\"athleteName\":\"\",\"externalID\":", 31);
+            strbuf_append_json_string(&batch->items, external_id);
+            strbuf_append(&batch->items, ",\"segments\":[", 13);
+            workouts++;
+        } else {
+            strbuf_append(&batch->items, ",", 1);
+        }
+        char segment_id[40] = {0};
+        if ((rc = generate_uuid_v4(segment_id, sizeof(segment_id))) != 0) break;
+        double minutes = sqlite3_column_double(stmt, 7);
+        double percent = sqlite3_column_double(stmt, 8);
+        strbuf_appendf(&batch->items, "{\"id\":\"%s\",\"minutes\":%g,\"intensityPercentFTP\":", segment_id, minutes);
+        if (scaling->scale) {
+            plan_append_segment_targets(&batch->items, scaling, &targets, minutes, percent);
+        } else {
+            strbuf_appendf(&batch->items, "%g", percent);
+        }
+        if (sqlite3_column_type(stmt, 9) != SQLITE_NULL) strbuf_appendf(&batch->items, ",\"cadence\":%d", sqlite3_column_int(stmt, 9));
+        if (sqlite3_column_type(stmt, 10) != SQLITE_NULL) {
+            strbuf_append(&batch->items, ",\"note\":", 8);
+            strbuf_append_json_string(&batch->items, (const char *)sqlite3_column_text(stmt, 10));
+        }
+        strbuf_append(&batch->items, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    if (rc == 0 && current >= 0) {
+        strbuf_append(&batch->items, "]", 1);
+        if (scaling->scale) plan_append_scaling(&batch->items, scaling, &targets);
+        strbuf_append(&batch->items, "}", 1);
+    }
+    return rc != 0 ? -1 : workouts;
+}
+
+/*
+ * POST /v1/import/plan-template?start_date=YYYY-MM-DD schedules a template with week 1 starting on
+ * the Monday of start_date's week; ?validate_only=true only checks the template. Invalid templates
+ * answer 400 with every problem (up to 20) by JSON path. Targets are scaled to the athlete unless
+ * ?scale=false; ?cap=cp also caps them by the latest CP fit.
+ */
+static int handle_import_plan_template(int fd, worker_db_t *db, const http_request_t *req, const char *body, const request_log_context_t *ctx) {
+    char raw[32] = {0};
+    int validate_only = query_param(req->query, "validate_only", raw, sizeof(raw)) && (strcmp(raw, "true") == 0 || strcmp(raw, "1") == 0);
+    int start_day = 0;
+    if (!validate_only && (!query_param(req->query, "start_date", raw, sizeof(raw)) || parse_iso_day(raw, &start_day) != 0)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"start_date must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    plan_scaling_t scaling = {1, 0, 0.0, 0.0};
+    if (query_param(req->query, "scale", raw, sizeof(raw))) {
+        if (strcmp(raw, "true") != 0 && strcmp(raw, "false") != 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"scale must be true or false\"}", ctx);
+            return 400;
+        }
+        scaling.scale = strcmp(raw, "true") == 0;
+    }
+    if (query_param(req->query, "cap", raw, sizeof(raw))) {
+        cp_fit_t fit;
+        if (strcmp(raw, "cp") != 0 || !scaling.scale) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"cap must be cp and needs scale=true\"}", ctx);
+            return 400;
+        }
+        int found = load_latest_cp_fit(db->db, ctx->account_id, &fit);
+        if (found < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        if (found == 0 || fit.cp <= 0.0) {
+            send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"cap=cp needs a CP fit; run POST /v1/analytics/cp/fit first\"}", ctx);
+            return 409;
+        }
+        scaling.cap_by_cp = 1;
+        scaling.cp = fit.cp;
+        scaling.w_prime = fit.w_prime;
+    }
+    strbuf_t problems;
+    strbuf_init(&problems);
+    int problem_count = plan_template_problems(db->db, body, &problems);
+    if (problem_count != 0 || problems.failed) {
+        int status = problem_count < 0 || problems.failed ? 500 : 400;
+        if (status == 500) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        } else {
+            strbuf_t sb;
+            strbuf_init(&sb);
+            strbuf_append(&sb, "{\"error\":\"invalid plan template\",\"problems\":[", 45);
+            strbuf_append(&sb, strbuf_cstr(&problems), problems.len);
+            strbuf_append(&sb, "]}", 2);
+            send_response_with_log_context(fd, 400, "Bad Request", sb.failed ? "{\"error\":\"invalid plan template\"}" : strbuf_cstr(&sb), ctx);
+            strbuf_free(&sb);
+        }
+        strbuf_free(&problems);
+        return status;
+    }
+    strbuf_free(&problems);
+
+    import_batch_t batch;
+    import_batch_init(&batch);
+    int workouts = import_plan_template(db->db, body, week_start_for_day(start_day), ctx->account_id, &scaling, &batch);
+    int status = 0;
+    if (workouts <= 0 || batch.items.failed) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"could not build workouts\"}", ctx);
+        status = 500;
+    } else if (validate_only) {
+        char response[64] = {0};
+        snprintf(response, sizeof(response), "{\"valid\":true,\"workouts\":%d}", workouts);
+        send_response_with_log_context(fd, 200, "OK", response, ctx);
+        status = 200;
+    } else {
+        status = import_commit(fd, db, "plan-template", "workouts", &batch, ctx);
+    }
+    import_batch_free(&batch);
+    return status;
+}
+
+/*
+ * POST /v1/import takes back the archive GET /v1/export produced (age-encrypted or not) and makes
+ * it the account's data. Every key in manifest.json must be a data key, its file must be in the
+ * archive and hash to the listed version, and the document must pass the checks a PUT makes
+ * (root type, sports, schema). Any problem answers 422 with the full list and writes nothing;
+ * otherwise all keys are journaled and handed to the writer thread as one batch, written in a
+ * single transaction, so an interrupted import leaves the account as it was. Keys the archive does not list are left alone, and documents that already
+ * match are reported "unchanged" without being rewritten. ?dry_run=true runs the same checks and
+ * reports what each key would do (create, replace, unchanged) without writing.
+ */
+
+#define ARCHIVE_IMPORT_MAX_ENTRY_BYTES (64u * 1024u * 1024u)
+#define ARCHIVE_IMPORT_MAX_KEYS 4096
+#define ARCHIVE_IMPORT_WAIT_MS 5000
+
+typedef struct {
+    char key[160];
+    char version[64];
+    char *doc;
+    const char *action;
+    char storage_key[256];
+    char pending_path[512];
+} archive_import_entry_t;
+
+static void archive_import_problem(strbuf_t *problems, int *count, const char *key, const char *file, const char *error, const char *detail) {
+    if ((*count)++ > 0) strbuf_append(problems, ",", 1);
+    strbuf_append(problems, "{\"key\":", 7);
+    strbuf_append_json_string(problems, key);
+    strbuf_append(problems, ",\"file\":", 8);
+    strbuf_append_json_string(problems, file);
+    strbuf_append(problems, ",\"error\":", 9);
+    strbuf_append_json_string(problems, error);
+    if (detail && detail[0] == '{') {
+        strbuf_append(problems, ",\"detail\":", 10);
+        strbuf_append(problems, detail, strlen(detail));
+    }
+    strbuf_append(problems, "}", 1);
+}
+
+/* Sets each entry's action against what is stored now; -1 on a database error. */
+static int archive_import_plan(sqlite3 *db, const char *account_id, archive_import_entry_t *entries, int count, int totals[3]) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_value FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    totals[0] = totals[1] = totals[2] = 0;
+    int rc = 0;
+    for (int i = 0; i < count && rc == 0; i++) {
+        char storage_key[256] = {0};
+        if (build_storage_key(account_id, entries[i].key, storage_key, sizeof(storage_key)) != 0) {
+            rc = -1;
+            break;
+        }
+        sqlite3_reset(stmt);
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        int step = sqlite3_step(stmt);
+        if (step == SQLITE_ROW) {
+            const char *stored = (const char *)sqlite3_column_text(stmt, 0);
+            size_t stored_len = (size_t)sqlite3_column_bytes(stmt, 0);
+            int same = stored && stored_len == strlen(entries[i].doc) && memcmp(stored, entries[i].doc, stored_len) == 0;
+            entries[i].action = same ? "unchanged" : "replace";
+            totals[same ? 2 : 1]++;
+        } else if (step == SQLITE_DONE) {
+            entries[i].action = "create";
+            totals[0]++;
+        } else {
+            rc = -1;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+static const char *archive_import_action_name(write_batch_action_t action) {
+    if (action == WRITE_BATCH_CREATED) return "create";
+    return action == WRITE_BATCH_REPLACED ? "replace" : "unchanged";
+}
+
+/*
+ * Journals every entry, then submits them to the writer thread as one batch, which writes all
+ * that changed in one transaction and sets their actions; 200, 202 while still queued, or 500.
+ */
+static int archive_import_store(archive_import_entry_t *entries, int count, const request_log_context_t *ctx, int totals[3]) {
+    if (count == 0) return 200;
+    write_batch_item_t *items = (write_batch_item_t *)calloc((size_t)count, sizeof(*items));
+    if (!items) return 500;
+    int journaled = 0;
+    int status = 0;
+    for (; journaled < count; journaled++) {
+        archive_import_entry_t *entry = &entries[journaled];
+        size_t doc_len = strlen(entry->doc);
+        if (build_storage_key(ctx->account_id, entry->key, entry->storage_key, sizeof(entry->storage_key)) != 0 ||
+            create_pending_write(entry->storage_key, entry->doc, doc_len, ctx, entry->pending_path, sizeof(entry->pending_path)) != 0) {
+            status = 500;
+            break;
+        }
+        items[journaled] = (write_batch_item_t){entry->key, entry->storage_key, entry->doc, doc_len, entry->pending_path, WRITE_BATCH_CREATED};
+    }
+
+    write_dispatch_result_t result;
+    memset(&result, 0, sizeof(result));
+    int dispatch_rc = status == 0 ? write_dispatch_submit_batch(items, (size_t)count, ctx->account_id, ctx->log_id, ARCHIVE_IMPORT_WAIT_MS, &result) : -1;
+    if (dispatch_rc < 0) {
+        /* Never queued: drop what was journaled so a restart does not replay part of it. */
+        for (int i = 0; i < journaled; i++) unlink(entries[i].pending_path);
+        free(items);
+        return 500;
+    }
+    if (dispatch_rc > 0) {
+        free(items);
+        return 202;
+    }
+    if (result.status_code == 204) {
+        totals[0] = totals[1] = totals[2] = 0;
+        for (int i = 0; i < count; i++) {
+            entries[i].action = archive_import_action_name(items[i].action);
+            totals[items[i].action]++;
+        }
+    }
+    free(items);
+    return result.status_code == 204 ? 200 : 500;
+}
+
+static void archive_import_entries_free(archive_import_entry_t *entries, int count) {
+    for (int i = 0; i < count; i++) free(entries[i].doc);
+    free(entries);
+}
+
+static int handle_post_archive_import(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw[16] = {0};
+    int dry_run = query_param(req->query, "dry_run", raw, sizeof(raw)) && (strcmp(raw, "true") == 0 || strcmp(raw, "1") == 0);
+    char *decrypted = NULL;
+    const char *data = req->body;
+    size_t data_len = req->body_len;
+    if (export_is_encrypted(req->body, req->body_len)) {
+        char err[128] = {0};
+        if (export_decrypt(req->body, req->body_len, &decrypted, &data_len, err, sizeof(err)) != 0) {
+            char error[192] = {0};
+            snprintf(error, sizeof(error), "{\"error\":\"cannot decrypt export: %s\"}", err);
+            send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+            return 400;
+        }
+        data = decrypted;
+    }
+    zip_reader_t zr;
+    if (!data || zip_reader_open(&zr, data, data_len) != 0) {
+        free(decrypted);
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body must be an archive from GET /v1/export\"}", ctx);
+        return 400;
+    }
+    char *manifest = NULL;
+    size_t manifest_len = 0;
+    char err[128] = {0};
+    int found = zip_reader_find(&zr, "manifest.json", ARCHIVE_IMPORT_MAX_ENTRY_BYTES, &manifest, &manifest_len, err, sizeof(err));
+    sqlite3_stmt *stmt = NULL;
+    int manifest_ok = 0;
+    if (found == 0 &&
+        sqlite3_prepare_v2(
+            db->db,
+            "SELECT CASE WHEN json_valid(?1) THEN json_extract(?1, '$.format') IS ?2 AND json_type(?1, '$.keys') = 'array' ELSE 0 END",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, manifest, (int)manifest_len, SQLITE_STATIC);
+        sqlite3_bind_text(stmt, 2, ARCHIVE_FORMAT, -1, SQLITE_STATIC);
+        manifest_ok = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    stmt = NULL;
+    if (!manifest_ok) {
+        char error[256] = {0};
+        if (found == 0) {
+            snprintf(error, sizeof(error), "{\"error\":\"manifest.json is not a %s manifest\"}", ARCHIVE_FORMAT);
+        } else if (found > 0) {
+            snprintf(error, sizeof(error), "{\"error\":\"archive has no manifest.json\"}");
+        } else {
+            snprintf(error, sizeof(error), "{\"error\":\"cannot read manifest.json: %s\"}", err);
+        }
+        free(manifest);
+        free(decrypted);
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_extract(value, '$.key'), json_extract(value, '$.file'), json_extract(value, '$.version') FROM json_each(?1, '$.keys')",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        free(manifest);
+        free(decrypted);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, manifest, (int)manifest_len, SQLITE_STATIC);
+    archive_import_entry_t *entries = NULL;
+    int count = 0;
+    int capacity = 0;
+    strbuf_t problems;
+    strbuf_init(&problems);
+    int problem_count = 0;
+    int status = 0;
+    int coach = 0;
+    int coach_checked = 0;
+    while (status == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        const char *file = (const char *)sqlite3_column_text(stmt, 1);
+        const char *listed_version = (const char *)sqlite3_column_text(stmt, 2);
+        if (count + problem_count >= ARCHIVE_IMPORT_MAX_KEYS) {
+            status = 413;
+            break;
+        }
+        if (!key || !is_valid_key(key) || strlen(key) >= sizeof(entries[0].key)) {
+            archive_import_problem(&problems, &problem_count, key, file, "not a data key", NULL);
+            continue;
+        }
+        int duplicate = 0;
+        for (int i = 0; i < count && !duplicate; i++) duplicate = strcmp(entries[i].key, key) == 0;
+        if (duplicate) {
+            archive_import_problem(&problems, &problem_count, key, file, "key is listed twice", NULL);
+            continue;
+        }
+        if (!file) {
+            archive_import_problem(&problems, &problem_count, key, file, "manifest entry has no file", NULL);
+            continue;
+        }
+        char *doc = NULL;
+        size_t doc_len = 0;
+        err[0] = '\0';
+        int read = zip_reader_find(&zr, file, ARCHIVE_IMPORT_MAX_ENTRY_BYTES, &doc, &doc_len, err, sizeof(err));
+        if (read != 0) {
+            archive_import_problem(&problems, &problem_count, key, file, read > 0 ? "file is missing from the archive" : err, NULL);
+            continue;
+        }
+        char version[64] = {0};
+        content_version(doc, doc_len, version, sizeof(version));
+        if (listed_version && strcmp(listed_version, version) != 0) {
+            archive_import_problem(&problems, &problem_count, key, file, "file does not match the manifest version", NULL);
+            free(doc);
+            continue;
+        }
+        char *prepared = data_prepare_payload(db->db, ctx->account_id, key, doc, doc_len);
+        if (prepared) {
+            free(doc);
+            doc = prepared;
+            doc_len = strlen(doc);
+        }
+        char detail[2048] = {0};
+        if (data_validate_document(db, key, doc, doc_len, ctx, detail, sizeof(detail)) != 0) {
+            archive_import_problem(&problems, &problem_count, key, file, "document is invalid", detail);
+            free(doc);
+            continue;
+        }
+        int locked = key_is_locked(db->db, ctx->account_id, key);
+        if (locked > 0 && !coach_checked) {
+            coach = coach_request_identity(db->db, req, ctx->account_id, NULL, 0);
+            coach_checked = 1;
+        }
+        if (locked < 0 || (locked > 0 && coach < 0)) {
+            status = locked < 0 || coach == -2 ? 500 : 401;
+            free(doc);
+            break;
+        }
+        if (locked > 0 && coach == 0) {
+            archive_import_problem(&problems, &problem_count, key, file, "key is locked by coach", NULL);
+            free(doc);
+            continue;
+        }
+        if (count == capacity) {
+            int next = capacity > 0 ? capacity * 2 : 16;
+            archive_import_entry_t *grown = realloc(entries, (size_t)next * sizeof(*entries));
+            if (!grown) {
+                status = 500;
+                free(doc);
+                break;
+            }
+            entries = grown;
+            capacity = next;
+        }
+        archive_import_entry_t *entry = &entries[count++];
+        memset(entry, 0, sizeof(*entry));
+        snprintf(entry->key, sizeof(entry->key), "%s", key);
+        content_version(doc, doc_len, entry->version, sizeof(entry->version));
+        entry->doc = doc;
+    }
+    sqlite3_finalize(stmt);
+    free(manifest);
+    free(decrypted);
+
+    if (status != 0 || problem_count > 0 || problems.failed) {
+        quarantine_flush(db->db, ctx->account_id, 0);
+        archive_import_entries_free(entries, count);
+        if (status == 413) {
+            send_response_with_log_context(fd, 413, "Payload Too Large", "{\"error\":\"manifest lists too many keys\"}", ctx);
+        } else if (status == 401) {
+            send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid X-Coach-Token\"}", ctx);
+        } else if (status != 0 || problems.failed) {
+            status = 500;
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        } else {
+            status = 422;
+            strbuf_t sb;
+            strbuf_init(&sb);
+            strbuf_appendf(&sb, "{\"error\":\"archive did not validate\",\"dry_run\":%s,\"problems\":[", dry_run ? "true" : "false");
+            strbuf_append(&sb, strbuf_cstr(&problems), problems.len);
+            strbuf_append(&sb, "]}", 2);
+            const char *body = sb.failed ? "{\"error\":\"archive did not validate\"}" : strbuf_cstr(&sb);
+            send_response_with_log_context(fd, 422, http_status_text(422), body, ctx);
+            strbuf_free(&sb);
+            log_warn("IMPORT archive rejected problems=%d account=%s logid=%s", problem_count, ctx->account_id, ctx->log_id);
+        }
+        strbuf_free(&problems);
+        return status;
+    }
+    strbuf_free(&problems);
+
+    int totals[3] = {0, 0, 0};
+    if (dry_run) {
+        status = archive_import_plan(db->db, ctx->account_id, entries, count, totals) == 0 ? 200 : 500;
+        quarantine_flush(db->db, ctx->account_id, 0);
+    } else {
+        sync_document_lock();
+        status = archive_import_store(entries, count, ctx, totals);
+        quarantine_flush(db->db, ctx->account_id, status == 200 || status == 202);
+        sync_document_unlock();
+        for (int i = 0; status == 200 && i < count; i++) {
+            if (strcmp(entries[i].action, "unchanged") == 0) continue;
+            size_t doc_len = strlen(entries[i].doc);
+            if (strcmp(entries[i].key, "activities") == 0) skew_note_future_dates(skew_count_future_dates(db->db, entries[i].doc, doc_len));
+            data_queue_refresh(ctx->account_id, entries[i].key);
+        }
+    }
+    if (status == 202) {
+        archive_import_entries_free(entries, count);
+        char body[256] = {0};
+        snprintf(body, sizeof(body), "{\"status\":\"queued\",\"logid\":\"%s\",\"keys\":%d}", ctx->log_id, count);
+        log_warn("IMPORT archive queued reason=writer_backlog keys=%d account=%s logid=%s", count, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 202, "Accepted", body, ctx);
+        return 202;
+    }
+    if (status != 200) {
+        archive_import_entries_free(entries, count);
+        log_error("IMPORT archive failed status=%d account=%s logid=%s", status, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return status;
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"%s\":true,\"keys\":[", dry_run ? "dry_run" : "imported");
+    for (int i = 0; i < count; i++) {
+        if (i > 0) strbuf_append(&sb, ",", 1);
+        strbuf_append(&sb, "{\"key\":", 7);
+        strbuf_append_json_string(&sb, entries[i].key);
+        strbuf_appendf(&sb, ",\"action\":\"%s\",\"version\":\"%s\",\"bytes\":%zu}", entries[i].action, entries[i].version, strlen(entries[i].doc));
+    }
+    strbuf_appendf(&sb, "],\"created\":%d,\"replaced\":%d,\"unchanged\":%d}", totals[0], totals[1], totals[2]);
+    send_response_with_log_context(fd, 200, "OK", sb.failed ? "{\"error\":\"out of memory\"}" : strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    log_info(
+        "IMPORT archive%s keys=%d created=%d replaced=%d unchanged=%d account=%s logid=%s",
+        dry_run ? " dry_run" : "",
+        count,
+        totals[0],
+        totals[1],
+        totals[2],
+        ctx->account_id,
+        ctx->log_id);
+    archive_import_entries_free(entries, count);
+    return 200;
+}
+
+/*
+ * POST /v1/import/fit: a multipart/form-data upload of up to UPLOAD_MAX_FILES .fit files (TCX is
+ * read too). Each one becomes an activity with its duration, distance, NP, heart rate, samples and
+ * load, like an emailed file. The original file is kept as sourceFileBase64 so the streams can be
+ * analysed again later, unless ?store_file=false.
+ */
+static int handle_import_fit(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char content_type[256] = {0};
+    http_request_header(req, "Content-Type", content_type, sizeof(content_type));
+    upload_files_t upload;
+    int count = multipart_read_files(content_type, req->body, req->body_len, &upload);
+    if (count <= 0) {
+        if (count == 0) upload_files_free(&upload);
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"expected a multipart/form-data upload with .fit files\"}", ctx);
+        return 400;
+    }
+    char raw[8] = {0};
+    int keep_files = !(query_param(req->query, "store_file", raw, sizeof(raw)) && (strcmp(raw, "false") == 0 || strcmp(raw, "0") == 0));
+    int status = import_activity_files(fd, db, "fit", upload.files, upload.count, NULL, keep_files, ctx);
+    upload_files_free(&upload);
+    return status;
+}
+
+int route_import(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->path, "/v1/import/email") == 0) return route_mail_inbox(fd, db, req, ctx);
+    if (strcmp(req->path, "/v1/import/preview") == 0) return handle_import_preview(fd, db, req, ctx);
+    int archive = strcmp(req->path, "/v1/import") == 0;
+    int goldencheetah = strcmp(req->path, "/v1/import/goldencheetah") == 0;
+    int plan_template = strcmp(req->path, "/v1/import/plan-template") == 0;
+    int diary = strcmp(req->path, "/v1/import/diary") == 0;
+    int fit = strcmp(req->path, "/v1/import/fit") == 0;
+    if (!archive && !goldencheetah && !plan_template && !diary && !fit && strcmp(req->path, "/v1/import/wger") != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown import source\"}", ctx);
+        return 404;
+    }
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    if (archive) return handle_post_archive_import(fd, db, req, ctx);
+    if (diary) return handle_import_diary(fd, db, req, ctx);
+    int locked = locks_enforce_key(fd, db, req, goldencheetah || fit ? "activities" : "workouts", ctx);
+    if (locked != 0) return locked;
+    if (fit) return handle_import_fit(fd, db, req, ctx);
+    char *body = NULL;
+    if (export_is_encrypted(req->body, req->body_len)) {
+        /* An encrypted export from this (or another) server: open it with FRICU_EXPORT_PASSPHRASE. */
+        size_t body_len = 0;
+        char err[128] = {0};
+        if (export_decrypt(req->body, req->body_len, &body, &body_len, err, sizeof(err)) != 0) {
+            char error[192] = {0};
+            snprintf(error, sizeof(error), "{\"error\":\"cannot decrypt export: %s\"}", err);
+            send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+            return 400;
+        }
+        log_info("IMPORT decrypted source=%s bytes=%zu logid=%s", req->path + 11, body_len, ctx->log_id);
+    } else if (req->body_len > 0) {
+        body = strndup(req->body, req->body_len);
+    }
+    sqlite3_stmt *stmt = NULL;
+    int valid = 0;
+    if (body && sqlite3_prepare_v2(db->db, "SELECT json_valid(?1)", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, body, -1, SQLITE_TRANSIENT);
+        valid = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+        sqlite3_finalize(stmt);
+    }
+    if (!valid) {
+        free(body);
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body must be a JSON export\"}", ctx);
+        return 400;
+    }
+    if (plan_template) {
+        int status = handle_import_plan_template(fd, db, req, body, ctx);
+        free(body);
+        return status;
+    }
+
+    import_batch_t batch;
+    import_batch_init(&batch);
+    char filename[256] = {0};
+    if (query_param(req->query, "filename", filename, sizeof(filename)) && filename[0] != '\0') import_note_file(&batch, filename);
+    int entries = goldencheetah ? import_goldencheetah(db->db, body, ctx->account_id, &batch) : import_wger(db->db, body, &batch);
+    free(body);
+    int status = 0;
+    if (entries < 0 || batch.items.failed || batch.skipped.failed) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"could not read export\"}", ctx);
+        status = 400;
+    } else if (entries == 0) {
+        send_response_with_log_context(
+            fd,
+            400,
+            "Bad Request",
+            goldencheetah ? "{\"error\":\"no GoldenCheetah rides found (expected RIDE or RIDES)\"}" : "{\"error\":\"no wger workout days found (expected day_list)\"}",
+            ctx);
+        status = 400;
+    } else {
+        status = import_commit(fd, db, goldencheetah ? "goldencheetah" : "wger", goldencheetah ? "activities" : "workouts", &batch, ctx);
+    }
+    import_batch_free(&batch);
+    return status;
+}
+
+/* Columns shared by the history list and the rollback answer. */
+#define IMPORT_RUN_JSON                                                                                                         \
+    "json_object('id', id, 'source', source, 'key', data_key, 'files', json(file_names), 'imported', imported,"              \
+    " 'duplicates', duplicates, 'skipped', skipped, 'created_at', created_at, 'rolled_back_at', rolled_back_at, 'removed', removed)"
+
+static int handle_list_import_runs(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    char *body = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_object('imports', (SELECT json_group_array(json(run)) FROM"
+            " (SELECT " IMPORT_RUN_JSON " AS run FROM import_runs WHERE account_id = ?1 ORDER BY created_at DESC, rowid DESC)))",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) body = strdup((const char *)sqlite3_column_text(stmt, 0));
+        sqlite3_finalize(stmt);
+    }
+    if (!body) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", body, ctx);
+    free(body);
+    return 200;
+}
+
+/*
+ * Removes the items the run added: those still tagged with its importRunID, and those listed in
+ * item_ids in case a client rewrote the item without the tag. Anything synced later stays.
+ */
+static int handle_rollback_import_run(int fd, worker_db_t *db, const http_request_t *req, const char *run_id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    char key[64] = {0};
+    char *item_ids = NULL;
+    int found = 0;
+    int rolled_back = 0;
+    if (sqlite3_prepare_v2(db->db, "SELECT data_key, item_ids, rolled_back_at IS NOT NULL FROM import_runs WHERE id = ?1 AND account_id = ?2", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, run_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        found = 1;
+        snprintf(key, sizeof(key), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        item_ids = strdup(sqlite3_column_text(stmt, 1) ? (const char *)sqlite3_column_text(stmt, 1) : "[]");
+        rolled_back = sqlite3_column_int(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+    if (!found) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"import run not found\"}", ctx);
+        return 404;
+    }
+    if (rolled_back || !item_ids) {
+        free(item_ids);
+        send_response_with_log_context(
+            fd, rolled_back ? 409 : 500, rolled_back ? "Conflict" : "Internal Server Error", rolled_back ? "{\"error\":\"import run already rolled back\"}" : "{\"error\":\"oom\"}", ctx);
+        return rolled_back ? 409 : 500;
+    }
+    int locked = locks_enforce_key(fd, db, req, key, ctx);
+    char storage_key[256] = {0};
+    if (locked != 0 || build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
+        free(item_ids);
+        if (locked != 0) return locked;
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    sync_document_lock();
+    char *doc = load_import_document(db->db, storage_key);
+    if (!doc) {
+        sync_document_unlock();
+        free(item_ids);
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stored document is not a JSON array\"}", ctx);
+        return 409;
+    }
+    char *remaining = NULL;
+    char *removed_ids = NULL;
+    int removed = 0;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "WITH items AS (SELECT a.key AS position, a.value, a.type, a.type = 'object' AND (json_extract(a.value, '$.importRunID') = ?2"
+            "  OR json_extract(a.value, '$.id') IN (SELECT value FROM json_each(?3))) AS created FROM json_each(?1) a)"
+            " SELECT (SELECT json_group_array(CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END) FROM"
+            "  (SELECT value, type FROM items WHERE NOT created ORDER BY position)),"
+            " (SELECT json_group_array(json_extract(value, '$.id')) FROM (SELECT value FROM items WHERE created ORDER BY position)),"
+            " (SELECT COUNT(*) FROM items WHERE created)",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, doc, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, run_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, item_ids, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0) && sqlite3_column_text(stmt, 1)) {
+            remaining = strdup((const char *)sqlite3_column_text(stmt, 0));
+            removed_ids = strdup((const char *)sqlite3_column_text(stmt, 1));
+            removed = sqlite3_column_int(stmt, 2);
+        }
+        sqlite3_finalize(stmt);
+    }
+    free(doc);
+    free(item_ids);
+    int status = remaining && removed_ids ? 200 : 500;
+    char error_body[512] = {0};
+    if (status == 200 && removed > 0) status = store_account_data(db, key, remaining, strlen(remaining), ctx, error_body, sizeof(error_body));
+    sync_document_unlock();
+    free(remaining);
+    if (status != 200 && status != 202 && status != 204) {
+        free(removed_ids);
+        if (error_body[0] == '\0') snprintf(error_body, sizeof(error_body), "{\"error\":\"database error\"}");
+        send_response_with_log_context(fd, status == 400 ? 400 : 500, status == 400 ? "Bad Request" : status == 422 ? "Unprocessable Entity" : "Internal Server Error", error_body, ctx);
+        return status == 400 ? 400 : 500;
+    }
+
+    char *body = NULL;
+    if (sqlite3_prepare_v2(
+            db->db, "UPDATE import_runs SET rolled_back_at = strftime('%s', 'now'), removed = ?3 WHERE id = ?1 AND account_id = ?2", -1, &stmt, NULL) ==
+        SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, run_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 3, removed);
+        if (sqlite3_step(stmt) == SQLITE_DONE) {
+            sqlite3_finalize(stmt);
+            stmt = NULL;
+            if (sqlite3_prepare_v2(
+                    db->db, "SELECT json_set(" IMPORT_RUN_JSON ", '$.removed_ids', json(?2)) FROM import_runs WHERE id = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+                sqlite3_bind_text(stmt, 1, run_id, -1, SQLITE_TRANSIENT);
+                sqlite3_bind_text(stmt, 2, removed_ids, -1, SQLITE_TRANSIENT);
+                if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) body = strdup((const char *)sqlite3_column_text(stmt, 0));
+            }
+        }
+        sqlite3_finalize(stmt);
+    }
+    free(removed_ids);
+    log_info("IMPORT rollback run=%s key=%s removed=%d account=%s logid=%s", run_id, key, removed, ctx->account_id, ctx->log_id);
+    if (!body) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", body, ctx);
+    free(body);
+    return 200;
+}
+
+int route_import_runs(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->path, "/v1/imports") == 0) {
+        if (strcmp(req->method, "GET") != 0) {
+            send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+            return 405;
+        }
+        return handle_list_import_runs(fd, db, ctx);
+    }
+    const char *id = req->path + strlen("/v1/imports/");
+    const char *suffix = strchr(id, '/');
+    char run_id[64] = {0};
+    if (!suffix || strcmp(suffix, "/rollback") != 0 || suffix == id || (size_t)(suffix - id) >= sizeof(run_id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    memcpy(run_id, id, (size_t)(suffix - id));
+    return handle_rollback_import_run(fd, db, req, run_id, ctx);
+}
@@ -0,0 +1,450 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Training diary: one markdown entry per account and day with an optional mood (1-5) and tags.
+ * Entries are joined to the activities stored for the same UTC day, searched together with
+ * activity and event notes, and merged with activities, planned workouts and events into the
+ * per-day calendar view.
+ */
+
+#define JOURNAL_BODY_MAX (64 * 1024)
+#define JOURNAL_TAGS_MAX 32
+#define JOURNAL_TAG_MAX_LEN 64
+#define JOURNAL_LIST_MAX_DAYS 366
+#define CALENDAR_MAX_DAYS 93
+#define SEARCH_QUERY_MAX 256
+#define SEARCH_TERMS_MAX 8
+#define SEARCH_DEFAULT_LIMIT 20
+#define SEARCH_MAX_LIMIT 100
+#define SEARCH_SNIPPET_CHARS 160
+
+/* Activities of the entry's day, as a JSON array; ?2 is the account's activities storage key. */
+#define JOURNAL_DAY_ACTIVITIES_SQL                                                                                    \
+    "(SELECT json_group_array(json_object('id', json_extract(a.value, '$.id'), 'sport', json_extract(a.value, '$.sport')," \
+    " 'duration_sec', json_extract(a.value, '$.durationSec'), 'tss', json_extract(a.value, '$.tss')))"                   \
+    " FROM kv_store k, json_each(k.data_value) a"                                                                        \
+    " WHERE k.data_key = ?2 AND substr(json_extract(a.value, '$.date'), 1, 10) = j.day)"
+
+#define JOURNAL_COLUMNS "j.day, j.body, j.mood, j.tags, j.created_at, j.updated_at, " JOURNAL_DAY_ACTIVITIES_SQL
+
+static int parse_day_strict(const char *text, int *out_day) {
+    return strlen(text) == 10 && parse_iso_day(text, out_day) == 0 ? 0 : -1;
+}
+
+static void append_journal_json(strbuf_t *sb, sqlite3_stmt *stmt) {
+    /* Columns: day, body, mood, tags, created_at, updated_at, activities */
+    strbuf_append(sb, "{\"date\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"body\":", 8);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 1));
+    if (sqlite3_column_type(stmt, 2) == SQLITE_NULL) {
+        strbuf_append(sb, ",\"mood\":null", 12);
+    } else {
+        strbuf_appendf(sb, ",\"mood\":%d", sqlite3_column_int(stmt, 2));
+    }
+    const char *tags = (const char *)sqlite3_column_text(stmt, 3);
+    strbuf_append(sb, ",\"tags\":", 8);
+    strbuf_append(sb, tags ? tags : "[]", tags ? strlen(tags) : 2);
+    strbuf_appendf(sb, ",\"created_at\":%lld,\"updated_at\":%lld", sqlite3_column_int64(stmt, 4), sqlite3_column_int64(stmt, 5));
+    const char *activities = (const char *)sqlite3_column_text(stmt, 6);
+    strbuf_append(sb, ",\"activities\":", 14);
+    strbuf_append(sb, activities ? activities : "[]", activities ? strlen(activities) : 2);
+    strbuf_append(sb, "}", 1);
+}
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+static int handle_get_journal_entry(int fd, worker_db_t *db, const char *day, const request_log_context_t *ctx) {
+    char activities_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT " JOURNAL_COLUMNS " FROM journal_entries j WHERE j.account_id = ?1 AND j.day = ?3", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, day, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no journal entry for this day\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_journal_json(&sb, stmt);
+    sqlite3_finalize(stmt);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_list_journal(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    if (!query_param(req->query, "from", from, sizeof(from)) || !query_param(req->query, "to", to, sizeof(to)) ||
+        parse_day_strict(from, &from_day) != 0 || parse_day_strict(to, &to_day) != 0 || to_day < from_day ||
+        to_day - from_day >= JOURNAL_LIST_MAX_DAYS) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD, at most 366 days apart\"}", ctx);
+        return 400;
+    }
+    char activities_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT " JOURNAL_COLUMNS " FROM journal_entries j WHERE j.account_id = ?1 AND j.day BETWEEN ?3 AND ?4 ORDER BY j.day",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, to, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"entries\":[", 12);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_journal_json(&sb, stmt);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+static int handle_put_journal_entry(int fd, worker_db_t *db, const char *day, const http_request_t *req, const request_log_context_t *ctx) {
+    if (req->body_len > JOURNAL_BODY_MAX) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"journal entry exceeds 64 KiB\"}", ctx);
+        return 400;
+    }
+    /* Checks the shape in one pass: body string, mood 1-5 or null, tags an array of short strings. */
+    sqlite3_stmt *stmt = NULL;
+    const char *check_sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object'"
+        " AND COALESCE(json_type(?1, '$.body'), 'text') IN ('text', 'null')"
+        " AND (json_type(?1, '$.mood') IS NULL OR json_type(?1, '$.mood') = 'null'"
+        "      OR (json_type(?1, '$.mood') = 'integer' AND json_extract(?1, '$.mood') BETWEEN 1 AND 5))"
+        " AND COALESCE(json_type(?1, '$.tags'), 'array') IN ('array', 'null')"
+        " AND (json_type(?1, '$.tags') IS NOT 'array' OR (json_array_length(?1, '$.tags') <= ?2"
+        "      AND NOT EXISTS (SELECT 1 FROM json_each(?1, '$.tags') WHERE type <> 'text' OR length(value) NOT BETWEEN 1 AND ?3)))";
+    if (sqlite3_prepare_v2(db->db, check_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, JOURNAL_TAGS_MAX);
+    sqlite3_bind_int(stmt, 3, JOURNAL_TAG_MAX_LEN);
+    int valid = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(
+            fd,
+            400,
+            "Bad Request",
+            "{\"error\":\"expected {\\\"body\\\": markdown string, \\\"mood\\\": 1-5 or null, \\\"tags\\\": [string, ...]}\"}",
+            ctx);
+        return 400;
+    }
+
+    /* Duplicate tags are dropped; the rest keep the client's order. */
+    const char *upsert_sql =
+        "INSERT INTO journal_entries (account_id, day, body, mood, tags, created_at, updated_at)"
+        " VALUES (?1, ?2, COALESCE(json_extract(?3, '$.body'), ''), json_extract(?3, '$.mood'),"
+        " COALESCE((SELECT json_group_array(value) FROM (SELECT value, MIN(key) AS first_key FROM json_each(?3, '$.tags')"
+        "   GROUP BY value ORDER BY first_key)), '[]'),"
+        " strftime('%s', 'now'), strftime('%s', 'now'))"
+        " ON CONFLICT(account_id, day) DO UPDATE SET body = excluded.body, mood = excluded.mood, tags = excluded.tags,"
+        " updated_at = excluded.updated_at";
+    if (sqlite3_prepare_v2(db->db, upsert_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("JOURNAL store failed day=%s account=%s err=%s logid=%s", day, ctx->account_id, sqlite3_errmsg(db->db), ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("JOURNAL stored day=%s bytes=%zu account=%s logid=%s", day, req->body_len, ctx->account_id, ctx->log_id);
+    return handle_get_journal_entry(fd, db, day, ctx);
+}
+
+static int handle_delete_journal_entry(int fd, worker_db_t *db, const char *day, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM journal_entries WHERE account_id = ?1 AND day = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no journal entry for this day\"}", ctx);
+        return 404;
+    }
+    log_info("JOURNAL deleted day=%s account=%s logid=%s", day, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_journal(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *collection = "/v1/journal";
+    if (strcmp(req->path, collection) == 0) {
+        if (strcmp(method, "GET") == 0) return handle_list_journal(fd, db, req, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+
+    const char *day = req->path + strlen(collection) + 1;
+    int parsed_day = 0;
+    if (parse_day_strict(day, &parsed_day) != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"journal entries are addressed by YYYY-MM-DD\"}", ctx);
+        return 404;
+    }
+    if (strcmp(method, "GET") == 0) return handle_get_journal_entry(fd, db, day, ctx);
+    if (strcmp(method, "PUT") == 0) return handle_put_journal_entry(fd, db, day, req, ctx);
+    if (strcmp(method, "DELETE") == 0) return handle_delete_journal_entry(fd, db, day, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
+
+/* Splits the query on whitespace into a JSON array of lowercase terms; returns the term count. */
+static int build_search_terms(const char *query, strbuf_t *out) {
+    char term[SEARCH_QUERY_MAX + 1];
+    int count = 0;
+    strbuf_append(out, "[", 1);
+    const char *p = query;
+    while (*p && count < SEARCH_TERMS_MAX) {
+        while (*p && isspace((unsigned char)*p)) p++;
+        size_t len = 0;
+        while (p[len] && !isspace((unsigned char)p[len]) && len < SEARCH_QUERY_MAX) {
+            term[len] = (char)tolower((unsigned char)p[len]);
+            len++;
+        }
+        if (len == 0) break;
+        term[len] = '\0';
+        if (count++ > 0) strbuf_append(out, ",", 1);
+        strbuf_append_json_string(out, term);
+        p += len;
+    }
+    strbuf_append(out, "]", 1);
+    return count;
+}
+
+int handle_get_search(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char query[SEARCH_QUERY_MAX + 1] = {0};
+    char raw_limit[16] = {0};
+    int limit = SEARCH_DEFAULT_LIMIT;
+    if (query_param(req->query, "limit", raw_limit, sizeof(raw_limit))) limit = atoi(raw_limit);
+    if (limit <= 0 || limit > SEARCH_MAX_LIMIT) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"limit must be in 1..100\"}", ctx);
+        return 400;
+    }
+    strbuf_t terms;
+    strbuf_init(&terms);
+    if (!query_param(req->query, "q", query, sizeof(query)) || build_search_terms(query, &terms) == 0) {
+        strbuf_free(&terms);
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"q is required\"}", ctx);
+        return 400;
+    }
+
+    char activities_key[256] = {0};
+    char events_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0 ||
+        build_storage_key(ctx->account_id, "events", events_key, sizeof(events_key)) != 0) {
+        strbuf_free(&terms);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    /* Every term must occur (case-insensitively for ASCII) somewhere in the searched text. */
+    const char *sql =
+        "WITH docs(type, id, day, title, text) AS ("
+        " SELECT 'journal', j.day, j.day, NULL, j.body || ' ' || j.tags FROM journal_entries j WHERE j.account_id = ?1"
+        " UNION ALL"
+        " SELECT 'activity', CAST(json_extract(a.value, '$.id') AS TEXT), substr(json_extract(a.value, '$.date'), 1, 10),"
+        "  json_extract(a.value, '$.sport'), COALESCE(json_extract(a.value, '$.notes'), '')"
+        " FROM kv_store k, json_each(k.data_value) a WHERE k.data_key = ?2"
+        " UNION ALL"
+        " SELECT 'event', CAST(json_extract(e.value, '$.id') AS TEXT), substr(json_extract(e.value, '$.startDate'), 1, 10),"
+        "  json_extract(e.value, '$.name'), COALESCE(json_extract(e.value, '$.name'), '') || ' ' || COALESCE(json_extract(e.value, '$.notes'), '')"
+        " FROM kv_store k, json_each(k.data_value) e WHERE k.data_key = ?3)"
+        " SELECT type, id, day, title, substr(text, 1, ?6) FROM docs"
+        " WHERE NOT EXISTS (SELECT 1 FROM json_each(?4) t WHERE instr(lower(docs.text), t.value) = 0)"
+        " ORDER BY day DESC, type, id LIMIT ?5";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        strbuf_free(&terms);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, events_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, strbuf_cstr(&terms), -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 5, limit);
+    sqlite3_bind_int(stmt, 6, SEARCH_SNIPPET_CHARS);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"query\":", 9);
+    strbuf_append_json_string(&sb, query);
+    strbuf_append(&sb, ",\"terms\":", 9);
+    strbuf_append(&sb, strbuf_cstr(&terms), terms.len);
+    strbuf_append(&sb, ",\"results\":[", 12);
+    strbuf_free(&terms);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_append(&sb, "{\"type\":", 8);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_append(&sb, ",\"id\":", 6);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(&sb, ",\"date\":", 8);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 2));
+        strbuf_append(&sb, ",\"title\":", 9);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 3));
+        strbuf_append(&sb, ",\"snippet\":", 11);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 4));
+        strbuf_append(&sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+#define CALENDAR_KIND_JOURNAL 3
+
+static void close_calendar_day(strbuf_t *sb, int kind_open) {
+    if (kind_open >= 0 && kind_open < CALENDAR_KIND_JOURNAL) strbuf_append(sb, "]", 1);
+    strbuf_append(sb, "}", 1);
+}
+
+int handle_get_calendar(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    if (!query_param(req->query, "from", from, sizeof(from)) || !query_param(req->query, "to", to, sizeof(to)) ||
+        parse_day_strict(from, &from_day) != 0 || parse_day_strict(to, &to_day) != 0 || to_day < from_day ||
+        to_day - from_day >= CALENDAR_MAX_DAYS) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD, at most 93 days apart\"}", ctx);
+        return 400;
+    }
+    char activities_key[256] = {0};
+    char workouts_key[256] = {0};
+    char events_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", activities_key, sizeof(activities_key)) != 0 ||
+        build_storage_key(ctx->account_id, "workouts", workouts_key, sizeof(workouts_key)) != 0 ||
+        build_storage_key(ctx->account_id, "events", events_key, sizeof(events_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    /* One row per item, grouped into days below; kind orders the sections inside a day. */
+    const char *sql =
+        "SELECT day, kind, item FROM ("
+        " SELECT substr(json_extract(a.value, '$.date'), 1, 10) AS day, 0 AS kind,"
+        "  json_object('id', json_extract(a.value, '$.id'), 'sport', json_extract(a.value, '$.sport'),"
+        "  'duration_sec', json_extract(a.value, '$.durationSec'), 'tss', json_extract(a.value, '$.tss')) AS item"
+        " FROM kv_store k, json_each(k.data_value) a WHERE k.data_key = ?2"
+        " UNION ALL"
+        " SELECT substr(json_extract(w.value, '$.scheduledDate'), 1, 10), 1,"
+        "  json_object('id', json_extract(w.value, '$.id'), 'name', json_extract(w.value, '$.name'), 'sport', json_extract(w.value, '$.sport'))"
+        " FROM kv_store k, json_each(k.data_value) w WHERE k.data_key = ?3"
+        " UNION ALL"
+        " SELECT substr(json_extract(e.value, '$.startDate'), 1, 10), 2,"
+        "  json_object('id', json_extract(e.value, '$.id'), 'name', json_extract(e.value, '$.name'), 'type', json_extract(e.value, '$.type'),"
+        "  'category', json_extract(e.value, '$.category'))"
+        " FROM kv_store k, json_each(k.data_value) e WHERE k.data_key = ?4"
+        " UNION ALL"
+        " SELECT day, 3, json_object('body', body, 'mood', mood, 'tags', json(tags), 'updated_at', updated_at)"
+        " FROM journal_entries WHERE account_id = ?1)"
+        " WHERE day BETWEEN ?5 AND ?6 ORDER BY day, kind";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, workouts_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, events_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 5, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 6, to, -1, SQLITE_TRANSIENT);
+
+    static const char *const sections[] = {"activities", "workouts", "events"};
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"from\":\"%s\",\"to\":\"%s\",\"days\":[", from, to);
+    char current[16] = {0};
+    int kind_open = -1;
+    int days = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *day = (const char *)sqlite3_column_text(stmt, 0);
+        int kind = sqlite3_column_int(stmt, 1);
+        const char *item = (const char *)sqlite3_column_text(stmt, 2);
+        if (strcmp(day, current) != 0) {
+            if (days > 0) close_calendar_day(&sb, kind_open);
+            strbuf_appendf(&sb, "%s{\"date\":\"%s\"", days++ > 0 ? "," : "", day);
+            snprintf(current, sizeof(current), "%s", day);
+            kind_open = -1;
+        }
+        if (kind == CALENDAR_KIND_JOURNAL) {
+            if (kind_open >= 0) strbuf_append(&sb, "]", 1);
+            strbuf_append(&sb, ",\"journal\":", 11);
+            strbuf_append(&sb, item, strlen(item));
+            kind_open = CALENDAR_KIND_JOURNAL;
+            continue;
+        }
+        if (kind != kind_open) {
+            if (kind_open >= 0) strbuf_append(&sb, "]", 1);
+            strbuf_appendf(&sb, ",\"%s\":[", sections[kind]);
+            kind_open = kind;
+        } else {
+            strbuf_append(&sb, ",", 1);
+        }
+        strbuf_append(&sb, item, strlen(item));
+    }
+    sqlite3_finalize(stmt);
+    if (days > 0) close_calendar_day(&sb, kind_open);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
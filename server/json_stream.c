@@ -0,0 +1,199 @@
+#define _GNU_SOURCE
+
+#include "server_internal.h"
+
+#include <string.h>
+
+/*
+ * Single-pass JSON validator that works directly on the request buffer. It keeps only a fixed
+ * container stack, so large uploads are checked without building a parse tree next to the body.
+ */
+
+#define JSON_OK 0
+#define JSON_FAIL -1
+
+typedef struct {
+    const char *data;
+    size_t len;
+    size_t pos;
+    const char *error;
+} json_cursor_t;
+
+static int fail(json_cursor_t *c, const char *error) {
+    c->error = error;
+    return JSON_FAIL;
+}
+
+static char peek(const json_cursor_t *c) {
+    return c->pos < c->len ? c->data[c->pos] : '\0';
+}
+
+static void skip_ws(json_cursor_t *c) {
+    while (c->pos < c->len) {
+        char ch = c->data[c->pos];
+        if (ch != ' ' && ch != '\t' && ch != '\n' && ch != '\r') break;
+        c->pos++;
+    }
+}
+
+static int is_hex(char ch) {
+    return (ch >= '0' && ch <= '9') || (ch >= 'a' && ch <= 'f') || (ch >= 'A' && ch <= 'F');
+}
+
+static int scan_string(json_cursor_t *c) {
+    if (peek(c) != '"') return fail(c, "expected string");
+    c->pos++;
+    while (c->pos < c->len) {
+        unsigned char ch = (unsigned char)c->data[c->pos];
+        if (ch == '"') {
+            c->pos++;
+            return JSON_OK;
+        }
+        if (ch < 0x20) return fail(c, "control character in string");
+        if (ch == '\\') {
+            c->pos++;
+            char esc = peek(c);
+            if (esc == 'u') {
+                for (int i = 1; i <= 4; i++) {
+                    if (c->pos + (size_t)i >= c->len || !is_hex(c->data[c->pos + (size_t)i])) return fail(c, "invalid unicode escape");
+                }
+                c->pos += 4;
+            } else if (!esc || !strchr("\"\\/bfnrt", esc)) {
+                return fail(c, "invalid escape");
+            }
+        }
+        c->pos++;
+    }
+    return fail(c, "unterminated string");
+}
+
+static int scan_digits(json_cursor_t *c) {
+    size_t start = c->pos;
+    while (peek(c) >= '0' && peek(c) <= '9') c->pos++;
+    return c->pos > start ? JSON_OK : fail(c, "invalid number");
+}
+
+static int scan_number(json_cursor_t *c) {
+    if (peek(c) == '-') c->pos++;
+    if (peek(c) == '0') {
+        c->pos++;
+    } else if (scan_digits(c) != JSON_OK) {
+        return JSON_FAIL;
+    }
+    if (peek(c) == '.') {
+        c->pos++;
+        if (scan_digits(c) != JSON_OK) return JSON_FAIL;
+    }
+    if (peek(c) == 'e' || peek(c) == 'E') {
+        c->pos++;
+        if (peek(c) == '+' || peek(c) == '-') c->pos++;
+        if (scan_digits(c) != JSON_OK) return JSON_FAIL;
+    }
+    return JSON_OK;
+}
+
+static int scan_literal(json_cursor_t *c, const char *literal) {
+    size_t n = strlen(literal);
+    if (c->len - c->pos < n || memcmp(c->data + c->pos, literal, n) != 0) return fail(c, "invalid literal");
+    c->pos += n;
+    return JSON_OK;
+}
+
+static int scan_key(json_cursor_t *c) {
+    skip_ws(c);
+    if (scan_string(c) != JSON_OK) return fail(c, "expected object key");
+    skip_ws(c);
+    if (peek(c) != ':') return fail(c, "expected ':'");
+    c->pos++;
+    return JSON_OK;
+}
+
+int json_stream_validate(const char *data, size_t len, json_stream_result_t *out) {
+    memset(out, 0, sizeof(*out));
+    json_cursor_t c = {.data = data, .len = len, .pos = 0, .error = NULL};
+    char stack[JSON_STREAM_MAX_DEPTH];
+    int depth = 0;
+
+    skip_ws(&c);
+    if (c.pos >= c.len) {
+        c.error = "empty document";
+        goto invalid;
+    }
+    out->root = c.data[c.pos];
+
+    for (;;) {
+        skip_ws(&c);
+        char ch = peek(&c);
+        int closed_empty = 0;
+        if (ch == '{' || ch == '[') {
+            if (depth >= JSON_STREAM_MAX_DEPTH) {
+                c.error = "nesting too deep";
+                goto invalid;
+            }
+            stack[depth++] = ch;
+            if (depth > out->max_depth) out->max_depth = depth;
+            c.pos++;
+            skip_ws(&c);
+            if (peek(&c) == (ch == '{' ? '}' : ']')) {
+                c.pos++;
+                depth--;
+                closed_empty = 1;
+            } else {
+                if (ch == '{' && scan_key(&c) != JSON_OK) goto invalid;
+                continue;
+            }
+        }
+        if (!closed_empty) {
+            int rc = JSON_OK;
+            if (ch == '"') {
+                rc = scan_string(&c);
+            } else if (ch == '-' || (ch >= '0' && ch <= '9')) {
+                rc = scan_number(&c);
+            } else if (ch == 't') {
+                rc = scan_literal(&c, "true");
+            } else if (ch == 'f') {
+                rc = scan_literal(&c, "false");
+            } else if (ch == 'n') {
+                rc = scan_literal(&c, "null");
+            } else {
+                rc = fail(&c, "expected value");
+            }
+            if (rc != JSON_OK) goto invalid;
+        }
+
+        /* A value just completed; close any containers it ends and find the next value. */
+        for (;;) {
+            if (depth == 0) {
+                skip_ws(&c);
+                if (c.pos != c.len) {
+                    c.error = "trailing characters";
+                    goto invalid;
+                }
+                out->valid = 1;
+                return 0;
+            }
+            if (depth == 1) out->top_level_items++;
+            skip_ws(&c);
+            char top = stack[depth - 1];
+            char next = peek(&c);
+            if (next == ',') {
+                c.pos++;
+                if (top == '{' && scan_key(&c) != JSON_OK) goto invalid;
+                break;
+            }
+            if (next == (top == '{' ? '}' : ']')) {
+                c.pos++;
+                depth--;
+                continue;
+            }
+            c.error = top == '{' ? "expected ',' or '}'" : "expected ',' or ']'";
+            goto invalid;
+        }
+    }
+
+invalid:
+    out->valid = 0;
+    out->error = c.error ? c.error : "invalid json";
+    out->error_offset = c.pos;
+    return -1;
+}
@@ -0,0 +1,174 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Response guardrails: a handler that is about to send a whole document or an unbounded list
+ * asks response_guard first, and anything over FRICU_RESPONSE_MAX_ITEMS items or
+ * FRICU_RESPONSE_MAX_BYTES bytes is refused with 413 and a hint naming the paginated route to
+ * use instead. One buggy client fetching a runaway collection then costs a short error rather
+ * than a multi-hundred-megabyte copy per worker. Either limit set to 0 turns that check off.
+ */
+
+#define RESPONSE_DEFAULT_MAX_ITEMS 50000
+#define RESPONSE_DEFAULT_MAX_BYTES (32LL * 1024 * 1024)
+
+static long long env_limit(const char *name, long long fallback) {
+    const char *raw = getenv(name);
+    if (!raw || raw[0] == '\0') return fallback;
+    char *end = NULL;
+    long long value = strtoll(raw, &end, 10);
+    return end && *end == '\0' && value >= 0 ? value : fallback;
+}
+
+static void response_limits(long long *max_items, long long *max_bytes) {
+    *max_items = env_limit("FRICU_RESPONSE_MAX_ITEMS", RESPONSE_DEFAULT_MAX_ITEMS);
+    *max_bytes = env_limit("FRICU_RESPONSE_MAX_BYTES", RESPONSE_DEFAULT_MAX_BYTES);
+}
+
+/* Top-level item count of a JSON array document, or -1 when it cannot reach the item limit. */
+long long response_count_items(sqlite3 *db, const char *doc, size_t len) {
+    long long max_items = 0;
+    long long max_bytes = 0;
+    response_limits(&max_items, &max_bytes);
+    /* An array of n items is at least 2n + 1 bytes, so smaller documents skip the parse. */
+    if (max_items <= 0 || (long long)len < 2 * max_items + 1) return -1;
+    sqlite3_stmt *stmt = NULL;
+    long long items = -1;
+    if (sqlite3_prepare_v2(db, "SELECT CASE WHEN json_valid(?1) AND json_type(?1) = 'array' THEN json_array_length(?1) END", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, doc, (int)len, SQLITE_STATIC);
+        if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) != SQLITE_NULL) items = sqlite3_column_int64(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return items;
+}
+
+int response_exceeds_limits(long long items, size_t bytes) {
+    long long max_items = 0;
+    long long max_bytes = 0;
+    response_limits(&max_items, &max_bytes);
+    return (max_items > 0 && items > max_items) || (max_bytes > 0 && (long long)bytes > max_bytes);
+}
+
+/* Sends the 413 when items (-1 if not counted) or bytes are over the limits; returns 0 otherwise. */
+int response_guard(int fd, long long items, size_t bytes, const char *hint, const request_log_context_t *ctx) {
+    if (!response_exceeds_limits(items, bytes)) return 0;
+    long long max_items = 0;
+    long long max_bytes = 0;
+    response_limits(&max_items, &max_bytes);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "{\"error\":\"response too large\",\"items\":%lld,\"bytes\":%zu,\"max_items\":%lld,\"max_bytes\":%lld,\"hint\":",
+        items,
+        bytes,
+        max_items,
+        max_bytes);
+    strbuf_append_json_string(&sb, hint);
+    strbuf_append(&sb, "}", 1);
+    log_warn("RESPONSE refused items=%lld bytes=%zu max_items=%lld max_bytes=%lld account=%s logid=%s", items, bytes, max_items, max_bytes, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 413, "Payload Too Large", sb.failed ? "{\"error\":\"response too large\"}" : strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 413;
+}
+
+/*
+ * Request bodies: FRICU_MAX_BODY_BYTES caps every request body (default just under the 8 MiB
+ * request buffer) and FRICU_MAX_BODY_BYTES_<KEY> overrides it for one document key, e.g.
+ * FRICU_MAX_BODY_BYTES_ACTIVITIES=67108864 for a long history. The check runs as soon as the
+ * headers carry a Content-Length, and again on the decoded size of a compressed body, so an
+ * oversize upload gets a 413 naming the limit and the key instead of being buffered first. The
+ * request buffer grows to the largest configured limit, up to 1 GiB.
+ */
+
+#define REQUEST_HEADER_ROOM (64 * 1024)
+#define REQUEST_BODY_DEFAULT_MAX ((long long)REQ_BUF_SIZE - REQUEST_HEADER_ROOM)
+#define REQUEST_BODY_CEILING (1024LL * 1024 * 1024)
+#define REQUEST_BODY_ENV "FRICU_MAX_BODY_BYTES"
+
+extern char **environ;
+
+static long long body_limit_value(const char *raw, long long fallback) {
+    if (!raw || raw[0] == '\0') return fallback;
+    char *end = NULL;
+    long long value = strtoll(raw, &end, 10);
+    if (!end || *end != '\0' || value <= 0) return fallback;
+    return value > REQUEST_BODY_CEILING ? REQUEST_BODY_CEILING : value;
+}
+
+/* The document key of /v1/data/<key>... and /v2/data/<key>...; empty for every other route. */
+static void body_key(const char *path, char *out, size_t out_len) {
+    out[0] = '\0';
+    if (strncmp(path, "/v1/data/", 9) != 0 && strncmp(path, "/v2/data/", 9) != 0) return;
+    const char *key = path + 9;
+    snprintf(out, out_len, "%.*s", (int)strcspn(key, "/"), key);
+}
+
+/* FRICU_MAX_BODY_BYTES, or FRICU_MAX_BODY_BYTES_<KEY> with the key upper-cased. */
+static void body_setting(const char *key, char *out, size_t out_len) {
+    size_t o = (size_t)snprintf(out, out_len, "%s%s", REQUEST_BODY_ENV, key[0] ? "_" : "");
+    for (const char *p = key; *p && o + 1 < out_len; p++) {
+        char c = *p;
+        out[o++] = (c >= 'a' && c <= 'z') ? (char)(c - 'a' + 'A') : (((c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9')) ? c : '_');
+    }
+    out[o] = '\0';
+}
+
+long long request_body_limit(const char *path, char *key, size_t key_len) {
+    char scratch[128] = {0};
+    if (!key) {
+        key = scratch;
+        key_len = sizeof(scratch);
+    }
+    body_key(path, key, key_len);
+    long long limit = body_limit_value(getenv(REQUEST_BODY_ENV), REQUEST_BODY_DEFAULT_MAX);
+    if (key[0] == '\0') return limit;
+    char setting[192] = {0};
+    body_setting(key, setting, sizeof(setting));
+    return body_limit_value(getenv(setting), limit);
+}
+
+size_t request_buffer_max(void) {
+    long long largest = body_limit_value(getenv(REQUEST_BODY_ENV), REQUEST_BODY_DEFAULT_MAX);
+    size_t prefix_len = strlen(REQUEST_BODY_ENV "_");
+    for (char **env = environ; env && *env; env++) {
+        if (strncmp(*env, REQUEST_BODY_ENV "_", prefix_len) != 0) continue;
+        const char *eq = strchr(*env, '=');
+        long long value = eq ? body_limit_value(eq + 1, 0) : 0;
+        if (value > largest) largest = value;
+    }
+    size_t max = (size_t)largest + REQUEST_HEADER_ROOM;
+    return max > REQ_BUF_SIZE ? max : REQ_BUF_SIZE;
+}
+
+/* Sends the 413 when bytes (-1 if only known to exceed the limit) is over path's body limit. */
+int request_body_guard(int fd, const char *path, long long bytes, const request_log_context_t *ctx) {
+    char key[128] = {0};
+    long long limit = request_body_limit(path, key, sizeof(key));
+    if (bytes >= 0 && bytes <= limit) return 0;
+    char setting[192] = {0};
+    body_setting(key, setting, sizeof(setting));
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"error\":\"request body too large\",\"key\":", 40);
+    if (key[0] != '\0') {
+        strbuf_append_json_string(&sb, key);
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_appendf(&sb, ",\"bytes\":%lld,\"max_bytes\":%lld,\"setting\":\"%s\"}", bytes, limit, setting);
+    log_warn("REQUEST body refused path=%s key=%s bytes=%lld max_bytes=%lld account=%s logid=%s", path, key[0] ? key : "-", bytes, limit, ctx->account_id, ctx->log_id);
+    send_response_with_log_context(fd, 413, "Payload Too Large", sb.failed ? "{\"error\":\"request body too large\"}" : strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 413;
+}
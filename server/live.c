@@ -0,0 +1,403 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define LIVE_SESSION_ID_MAX 64
+#define LIVE_MAX_BATCH_SAMPLES 3600
+#define LIVE_MAX_SESSION_SEC (12 * 3600)
+#define LIVE_GAP_FILL_SEC 5
+#define LIVE_RESPONSE_BODY_MAX 512
+
+static int is_valid_session_id(const char *id) {
+    size_t len = id ? strlen(id) : 0;
+    if (len == 0 || len > LIVE_SESSION_ID_MAX) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)id[i];
+        if (!(isalnum(ch) || ch == '-' || ch == '_' || ch == '.')) return 0;
+    }
+    return 1;
+}
+
+static int is_live_sport(const char *sport) {
+    return strcmp(sport, "cycling") == 0 || strcmp(sport, "running") == 0 || strcmp(sport, "swimming") == 0 || strcmp(sport, "strength") == 0;
+}
+
+static int exec_session_stmt(sqlite3 *db, const char *sql, const char *account_id, const char *session_id) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+static void append_sample_array(strbuf_t *sb, const char *name, const double *values, size_t count) {
+    strbuf_appendf(sb, ",\"%s\":[", name);
+    for (size_t i = 0; i < count; i++) {
+        strbuf_appendf(sb, "%s%.0f", i == 0 ? "" : ",", values[i]);
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+/* Turns the buffered samples into a 1 Hz activity, appends it to `activities` through the normal write path. */
+static int finalize_live_session(
+    worker_db_t *db,
+    const char *session_id,
+    const request_log_context_t *ctx,
+    strbuf_t *response,
+    char *error_body,
+    size_t error_body_len) {
+    sqlite3_stmt *stmt = NULL;
+    const char *session_sql =
+        "SELECT s.sport, MIN(x.t), MAX(x.t), COUNT(x.t) FROM live_sessions s"
+        " LEFT JOIN live_samples x ON x.account_id = s.account_id AND x.session_id = s.session_id"
+        " WHERE s.account_id = ?1 AND s.session_id = ?2 GROUP BY s.session_id";
+    if (sqlite3_prepare_v2(db->db, session_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        snprintf(error_body, error_body_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        snprintf(error_body, error_body_len, "{\"error\":\"unknown session\"}");
+        return 404;
+    }
+    char sport[16] = {0};
+    snprintf(sport, sizeof(sport), "%s", (const char *)sqlite3_column_text(stmt, 0));
+    long long first_t = sqlite3_column_int64(stmt, 1);
+    long long last_t = sqlite3_column_int64(stmt, 2);
+    int sample_rows = sqlite3_column_int(stmt, 3);
+    sqlite3_finalize(stmt);
+    if (sample_rows == 0) {
+        exec_session_stmt(db->db, "DELETE FROM live_sessions WHERE account_id = ?1 AND session_id = ?2", ctx->account_id, session_id);
+        snprintf(error_body, error_body_len, "{\"error\":\"session has no samples\"}");
+        return 400;
+    }
+
+    size_t span = (size_t)(last_t - first_t + 1);
+    double *power = (double *)calloc(span, sizeof(double));
+    double *hr = (double *)calloc(span, sizeof(double));
+    double *cadence = (double *)calloc(span, sizeof(double));
+    if (!power || !hr || !cadence) {
+        free(power);
+        free(hr);
+        free(cadence);
+        snprintf(error_body, error_body_len, "{\"error\":\"oom\"}");
+        return 500;
+    }
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT t, COALESCE(power, 0), COALESCE(hr, 0), COALESCE(cadence, 0) FROM live_samples"
+            " WHERE account_id = ?1 AND session_id = ?2 ORDER BY t",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+        long long prev = -1;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            long long offset = sqlite3_column_int64(stmt, 0) - first_t;
+            power[offset] = sqlite3_column_double(stmt, 1);
+            hr[offset] = sqlite3_column_double(stmt, 2);
+            cadence[offset] = sqlite3_column_double(stmt, 3);
+            /* Short dropouts hold the last reading; longer gaps stay at zero like a paused recording. */
+            if (prev >= 0 && offset - prev > 1 && offset - prev <= LIVE_GAP_FILL_SEC) {
+                for (long long i = prev + 1; i < offset; i++) {
+                    power[i] = power[prev];
+                    hr[i] = hr[prev];
+                    cadence[i] = cadence[prev];
+                }
+            }
+            prev = offset;
+        }
+        sqlite3_finalize(stmt);
+    }
+
+    double power_sum = 0.0;
+    double hr_sum = 0.0;
+    size_t hr_n = 0;
+    for (size_t i = 0; i < span; i++) {
+        power_sum += power[i];
+        if (hr[i] > 0.0) {
+            hr_sum += hr[i];
+            hr_n++;
+        }
+    }
+    double avg_power = power_sum / (double)span;
+    double np = compute_normalized_power(power, span);
+    if (np <= 0.0) np = avg_power;
+    int avg_hr = hr_n > 0 ? (int)(hr_sum / (double)hr_n + 0.5) : 0;
+    sport_settings_t settings;
+    load_sport_settings(db->db, ctx->account_id, sport, &settings);
+    sport_load_input_t load_input = {.duration_sec = (double)span, .normalized_power = np, .avg_heart_rate = avg_hr};
+    sport_load_t load;
+    sport_compute_loads(&settings, &load_input, &load);
+
+    char activity_id[40] = {0};
+    if (generate_uuid_v4(activity_id, sizeof(activity_id)) != 0) {
+        free(power);
+        free(hr);
+        free(cadence);
+        snprintf(error_body, error_body_len, "{\"error\":\"id generation failed\"}");
+        return 500;
+    }
+    char date[32] = {0};
+    time_t start = (time_t)first_t;
+    struct tm tm_start;
+    gmtime_r(&start, &tm_start);
+    strftime(date, sizeof(date), "%Y-%m-%dT%H:%M:%SZ", &tm_start);
+
+    strbuf_t activity;
+    strbuf_init(&activity);
+    strbuf_appendf(
+        &activity,
+        "{\"id\":\"%s\",\"date\":\"%s\",\"sport\":\"%s\",\"athleteName\":\"\",\"durationSec\":%zu,\"distanceKm\":0,\"tss\":%d,",
+        activity_id,
+        date,
+        sport,
+        span,
+        (int)(load.tss + 0.5));
+    if (np > 0.0) {
+        strbuf_appendf(&activity, "\"normalizedPower\":%d,", (int)(np + 0.5));
+    }
+    if (avg_hr > 0) {
+        strbuf_appendf(&activity, "\"avgHeartRate\":%d,", avg_hr);
+    }
+    if (settings.default_gear[0] != '\0') {
+        strbuf_append(&activity, "\"gear\":", 7);
+        strbuf_append_json_string(&activity, settings.default_gear);
+        strbuf_append(&activity, ",", 1);
+    }
+    sport_append_loads(&activity, &load);
+    strbuf_append(&activity, "\"intervals\":[],\"notes\":\"Live session\",\"externalID\":", 51);
+    char external_id[LIVE_SESSION_ID_MAX + 8] = {0};
+    snprintf(external_id, sizeof(external_id), "live:%s", session_id);
+    strbuf_append_json_string(&activity, external_id);
+    if (power_sum > 0.0) append_sample_array(&activity, "powerSamples", power, span);
+    if (hr_n > 0) append_sample_array(&activity, "heartRateSamples", hr, span);
+    append_sample_array(&activity, "cadenceSamples", cadence, span);
+    strbuf_append(&activity, "}", 1);
+    free(power);
+    free(hr);
+    free(cadence);
+    if (activity.failed) {
+        strbuf_free(&activity);
+        snprintf(error_body, error_body_len, "{\"error\":\"oom\"}");
+        return 500;
+    }
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_insert(COALESCE((SELECT data_value FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)"
+            " AND json_type(data_value) = 'array'), '[]'), '$[#]', json(?2))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        strbuf_free(&activity);
+        snprintf(error_body, error_body_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, strbuf_cstr(&activity), -1, SQLITE_TRANSIENT);
+    char *payload = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        payload = strdup((const char *)sqlite3_column_text(stmt, 0));
+    }
+    sqlite3_finalize(stmt);
+    strbuf_free(&activity);
+    if (!payload) {
+        snprintf(error_body, error_body_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+
+    int status = store_account_data(db, "activities", payload, strlen(payload), ctx, error_body, error_body_len);
+    free(payload);
+    if (status != 204 && status != 202) return status;
+
+    exec_session_stmt(db->db, "DELETE FROM live_samples WHERE account_id = ?1 AND session_id = ?2", ctx->account_id, session_id);
+    exec_session_stmt(db->db, "DELETE FROM live_sessions WHERE account_id = ?1 AND session_id = ?2", ctx->account_id, session_id);
+    log_info(
+        "LIVE session finalized account=%s session=%s activity=%s duration=%zu np=%.0f tss=%.0f",
+        ctx->account_id,
+        session_id,
+        activity_id,
+        span,
+        np,
+        load.tss);
+
+    strbuf_appendf(
+        response,
+        "{\"session_id\":\"%s\",\"status\":\"%s\",\"activity\":{\"id\":\"%s\",\"date\":\"%s\",\"durationSec\":%zu,\"tss\":%d,"
+        "\"normalizedPower\":%d,\"avgPower\":%.0f,\"avgHeartRate\":%d,\"samples\":%d}}",
+        session_id,
+        status == 204 ? "finalized" : "queued",
+        activity_id,
+        date,
+        span,
+        (int)(load.tss + 0.5),
+        (int)(np + 0.5),
+        avg_power,
+        avg_hr,
+        sample_rows);
+    return 201;
+}
+
+int handle_post_live_ingest(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *header_sql =
+        "SELECT json_valid(?1), json_extract(?1, '$.session_id'), COALESCE(json_extract(?1, '$.sport'), 'cycling'),"
+        " COALESCE(json_extract(?1, '$.close'), 0), json_type(?1, '$.samples'), json_array_length(?1, '$.samples'),"
+        " (SELECT COUNT(*) FROM json_each(?1, '$.samples') WHERE json_type(value, '$.t') NOT IN ('integer', 'real'))";
+    if (sqlite3_prepare_v2(db->db, header_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    int valid = 0;
+    char session_id[LIVE_SESSION_ID_MAX + 2] = {0};
+    char sport[16] = {0};
+    int close_session = 0;
+    int samples_is_array = 0;
+    int sample_count = 0;
+    int bad_samples = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        if (valid) {
+            const char *raw_session = (const char *)sqlite3_column_text(stmt, 1);
+            const char *raw_sport = (const char *)sqlite3_column_text(stmt, 2);
+            if (raw_session) snprintf(session_id, sizeof(session_id), "%s", raw_session);
+            if (raw_sport) snprintf(sport, sizeof(sport), "%s", raw_sport);
+            close_session = sqlite3_column_int(stmt, 3);
+            const char *samples_type = (const char *)sqlite3_column_text(stmt, 4);
+            samples_is_array = samples_type && strcmp(samples_type, "array") == 0;
+            sample_count = sqlite3_column_int(stmt, 5);
+            bad_samples = sqlite3_column_int(stmt, 6);
+        }
+    }
+    sqlite3_finalize(stmt);
+
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+        return 400;
+    }
+    if (!is_valid_session_id(session_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"session_id must be 1-64 chars of [A-Za-z0-9._-]\"}", ctx);
+        return 400;
+    }
+    if (!is_live_sport(sport)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"unknown sport\"}", ctx);
+        return 400;
+    }
+    if (!samples_is_array && !close_session) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"samples must be an array\"}", ctx);
+        return 400;
+    }
+    if (sample_count > LIVE_MAX_BATCH_SAMPLES || bad_samples > 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"samples need a numeric t and at most 3600 per batch\"}", ctx);
+        return 400;
+    }
+
+    if (samples_is_array && sample_count > 0) {
+        sqlite3_exec(db->db, "BEGIN IMMEDIATE;", NULL, NULL, NULL);
+        if (sqlite3_prepare_v2(
+                db->db,
+                "INSERT INTO live_sessions (account_id, session_id, sport, created_at, updated_at)"
+                " VALUES (?1, ?2, ?3, strftime('%s', 'now'), strftime('%s', 'now'))"
+                " ON CONFLICT(account_id, session_id) DO UPDATE SET updated_at = excluded.updated_at",
+                -1,
+                &stmt,
+                NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt, 3, sport, -1, SQLITE_TRANSIENT);
+            sqlite3_step(stmt);
+            sqlite3_finalize(stmt);
+        }
+        if (sqlite3_prepare_v2(
+                db->db,
+                "INSERT OR REPLACE INTO live_samples (account_id, session_id, t, power, hr, cadence)"
+                " SELECT ?1, ?2, CAST(json_extract(value, '$.t') AS INTEGER), json_extract(value, '$.power'),"
+                " json_extract(value, '$.hr'), json_extract(value, '$.cadence') FROM json_each(?3, '$.samples')",
+                -1,
+                &stmt,
+                NULL) != SQLITE_OK) {
+            sqlite3_exec(db->db, "ROLLBACK;", NULL, NULL, NULL);
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        int rc = sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+
+        long long span = 0;
+        if (rc == SQLITE_DONE &&
+            sqlite3_prepare_v2(
+                db->db,
+                "SELECT MAX(t) - MIN(t) FROM live_samples WHERE account_id = ?1 AND session_id = ?2",
+                -1,
+                &stmt,
+                NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+            if (sqlite3_step(stmt) == SQLITE_ROW) span = sqlite3_column_int64(stmt, 0);
+            sqlite3_finalize(stmt);
+        }
+        if (rc != SQLITE_DONE || span >= LIVE_MAX_SESSION_SEC) {
+            sqlite3_exec(db->db, "ROLLBACK;", NULL, NULL, NULL);
+            if (rc != SQLITE_DONE) {
+                send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+                return 500;
+            }
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"live sessions are limited to 12 hours\"}", ctx);
+            return 400;
+        }
+        sqlite3_exec(db->db, "COMMIT;", NULL, NULL, NULL);
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    int status = 200;
+    if (close_session) {
+        char error_body[LIVE_RESPONSE_BODY_MAX] = {0};
+        status = finalize_live_session(db, session_id, ctx, &sb, error_body, sizeof(error_body));
+        if (status != 201) {
+            strbuf_free(&sb);
+            send_response_with_log_context(
+                fd, status, status == 404 ? "Not Found" : status == 400 ? "Bad Request" : "Internal Server Error", error_body, ctx);
+            return status;
+        }
+    } else {
+        int buffered = 0;
+        if (sqlite3_prepare_v2(db->db, "SELECT COUNT(*) FROM live_samples WHERE account_id = ?1 AND session_id = ?2", -1, &stmt, NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt, 2, session_id, -1, SQLITE_TRANSIENT);
+            if (sqlite3_step(stmt) == SQLITE_ROW) buffered = sqlite3_column_int(stmt, 0);
+            sqlite3_finalize(stmt);
+        }
+        strbuf_appendf(&sb, "{\"session_id\":\"%s\",\"status\":\"open\",\"accepted\":%d,\"buffered\":%d}", session_id, sample_count, buffered);
+    }
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, status, status == 201 ? "Created" : "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return status;
+}
@@ -0,0 +1,358 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Coach-managed data keys. An account issues coach tokens; requests carrying a valid
+ * X-Coach-Token act as the coach and may lock a key (e.g. "workouts" mid-block). Writes to a
+ * locked key without the coach token get 423 Locked, reads are unaffected.
+ */
+
+#define COACH_TOKEN_BYTES 24
+#define COACH_NAME_MAX 64
+#define LOCK_REASON_MAX 200
+#define COACH_GRANT_HEADER_MAX 1024
+
+static int generate_coach_token(char *out, size_t out_len) {
+    unsigned char raw[COACH_TOKEN_BYTES];
+    if (out_len < 6 + COACH_TOKEN_BYTES * 2 + 1) return -1;
+    if (fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    memcpy(out, "coach_", 6);
+    for (size_t i = 0; i < sizeof(raw); i++) {
+        snprintf(out + 6 + i * 2, 3, "%02x", raw[i]);
+    }
+    return 0;
+}
+
+int coach_request_identity(sqlite3 *db, const http_request_t *req, const char *account_id, char *out_name, size_t out_len) {
+    char token[96] = {0};
+    if (!http_request_header(req, "X-Coach-Token", token, sizeof(token)) || token[0] == '\0') return 0;
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT name FROM coach_tokens WHERE token_hash = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) return -2;
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, account_id, -1, SQLITE_TRANSIENT);
+    int found = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (out_name) snprintf(out_name, out_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+        found = 1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+int coach_grant_allows(sqlite3 *db, const http_request_t *req, const char *athlete_id) {
+    /* A coach following several athletes sends one token per athlete, comma-separated. */
+    char tokens[COACH_GRANT_HEADER_MAX] = {0};
+    if (!http_request_header(req, "X-Coach-Token", tokens, sizeof(tokens))) return 0;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM coach_tokens WHERE token_hash = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    int allowed = 0;
+    for (char *save = NULL, *token = strtok_r(tokens, ", ", &save); token && !allowed; token = strtok_r(NULL, ", ", &save)) {
+        char hash[65] = {0};
+        sha256_hex(token, strlen(token), hash, sizeof(hash));
+        sqlite3_reset(stmt);
+        sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, athlete_id, -1, SQLITE_TRANSIENT);
+        allowed = sqlite3_step(stmt) == SQLITE_ROW;
+    }
+    sqlite3_finalize(stmt);
+    return allowed;
+}
+
+int key_is_locked(sqlite3 *db, const char *account_id, const char *key) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM key_locks WHERE account_id = ?1 AND data_key = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, key, -1, SQLITE_TRANSIENT);
+    int locked = sqlite3_step(stmt) == SQLITE_ROW;
+    sqlite3_finalize(stmt);
+    return locked;
+}
+
+void locks_append_keys(sqlite3 *db, const char *account_id, strbuf_t *sb) {
+    strbuf_append(sb, "[", 1);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_key FROM key_locks WHERE account_id = ?1 ORDER BY data_key", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (count++ > 0) strbuf_append(sb, ",", 1);
+            strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+int locks_enforce_key(int fd, worker_db_t *db, const http_request_t *req, const char *key, const request_log_context_t *ctx) {
+    int locked = key_is_locked(db->db, ctx->account_id, key);
+    if (locked == 0) return 0;
+    if (locked < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    int coach = coach_request_identity(db->db, req, ctx->account_id, NULL, 0);
+    if (coach > 0) return 0;
+    if (coach == -2) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (coach == -1) {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid X-Coach-Token\"}", ctx);
+        return 401;
+    }
+
+    sqlite3_stmt *stmt = NULL;
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"error\":\"key is locked by coach\",\"key\":", 40);
+    strbuf_append_json_string(&sb, key);
+    if (sqlite3_prepare_v2(db->db, "SELECT locked_by, reason, locked_at FROM key_locks WHERE account_id = ?1 AND data_key = ?2", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, key, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            strbuf_append(&sb, ",\"locked_by\":", 13);
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+            strbuf_append(&sb, ",\"reason\":", 10);
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+            strbuf_appendf(&sb, ",\"locked_at\":%lld", sqlite3_column_int64(stmt, 2));
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(&sb, "}", 1);
+    send_response_with_log_context(fd, 423, "Locked", sb.failed ? "{\"error\":\"key is locked by coach\"}" : strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    log_warn("DATA WRITE rejected key=%s reason=coach_lock account=%s logid=%s", key, ctx->account_id, ctx->log_id);
+    return 423;
+}
+
+int locks_enforce(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char key[128] = {0};
+    request_data_key(req->path, key, sizeof(key));
+    if (key[0] == '\0') return 0;
+    return locks_enforce_key(fd, db, req, key, ctx);
+}
+
+static int handle_post_coach_token(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char name[COACH_NAME_MAX + 1] = "coach";
+    if (req->body_len > 0) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1), json_extract(?1, '$.name')", -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+        int valid = 0;
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            valid = sqlite3_column_int(stmt, 0);
+            const char *raw_name = (const char *)sqlite3_column_text(stmt, 1);
+            if (raw_name && raw_name[0] != '\0') snprintf(name, sizeof(name), "%s", raw_name);
+        }
+        sqlite3_finalize(stmt);
+        if (!valid) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+            return 400;
+        }
+    }
+
+    char token[64] = {0};
+    if (generate_coach_token(token, sizeof(token)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO coach_tokens (token_hash, account_id, name, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    /* Only the hash is kept; the token itself is shown once, in this response. */
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, name, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("COACH token issued account=%s name=%s", ctx->account_id, name);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    capture_omit_response();
+    strbuf_appendf(&sb, "{\"token\":\"%s\",\"name\":", token);
+    strbuf_append_json_string(&sb, name);
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 201;
+}
+
+static int handle_delete_coach_token(int fd, worker_db_t *db, const char *token, const request_log_context_t *ctx) {
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM coach_tokens WHERE token_hash = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown coach token\"}", ctx);
+        return 404;
+    }
+    log_info("COACH token revoked account=%s", ctx->account_id);
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+static int handle_list_locks(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT data_key, locked_by, reason, locked_at FROM key_locks WHERE account_id = ?1 ORDER BY data_key", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"locks\":[", 10);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_append(&sb, "{\"key\":", 7);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_append(&sb, ",\"locked_by\":", 13);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(&sb, ",\"reason\":", 10);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 2));
+        strbuf_appendf(&sb, ",\"locked_at\":%lld}", sqlite3_column_int64(stmt, 3));
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_change_lock(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx) {
+    char coach_name[COACH_NAME_MAX + 1] = {0};
+    int coach = coach_request_identity(db->db, req, ctx->account_id, coach_name, sizeof(coach_name));
+    if (coach == -2) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (coach == -1) {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid X-Coach-Token\"}", ctx);
+        return 401;
+    }
+    if (coach == 0) {
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"locks can only be changed with X-Coach-Token\"}", ctx);
+        return 403;
+    }
+
+    int lock = strcmp(req->method, "PUT") == 0;
+    sqlite3_stmt *stmt = NULL;
+    if (lock) {
+        char reason[LOCK_REASON_MAX + 1] = {0};
+        if (req->body_len > 0) {
+            if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1), json_extract(?1, '$.reason')", -1, &stmt, NULL) != SQLITE_OK) {
+                send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+                return 500;
+            }
+            sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+            int valid = 0;
+            if (sqlite3_step(stmt) == SQLITE_ROW) {
+                valid = sqlite3_column_int(stmt, 0);
+                const char *raw_reason = (const char *)sqlite3_column_text(stmt, 1);
+                if (raw_reason) snprintf(reason, sizeof(reason), "%s", raw_reason);
+            }
+            sqlite3_finalize(stmt);
+            if (!valid) {
+                send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+                return 400;
+            }
+        }
+        if (sqlite3_prepare_v2(
+                db->db,
+                "INSERT INTO key_locks (account_id, data_key, locked_by, reason, locked_at) VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))"
+                " ON CONFLICT(account_id, data_key) DO UPDATE SET locked_by = excluded.locked_by, reason = excluded.reason",
+                -1,
+                &stmt,
+                NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 3, coach_name, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 4, reason, -1, SQLITE_TRANSIENT);
+    } else if (sqlite3_prepare_v2(db->db, "DELETE FROM key_locks WHERE account_id = ?1 AND data_key = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, key, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (!lock && sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"key is not locked\"}", ctx);
+        return 404;
+    }
+    log_info("LOCK %s key=%s coach=%s account=%s logid=%s", lock ? "set" : "cleared", key, coach_name, ctx->account_id, ctx->log_id);
+    return handle_list_locks(fd, db, ctx);
+}
+
+int route_coach(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *path = req->path;
+    if (strcmp(path, "/v1/coach/tokens") == 0 && strcmp(method, "POST") == 0) return handle_post_coach_token(fd, db, req, ctx);
+    const char *tokens_prefix = "/v1/coach/tokens/";
+    if (strncmp(path, tokens_prefix, strlen(tokens_prefix)) == 0 && strcmp(method, "DELETE") == 0) {
+        return handle_delete_coach_token(fd, db, path + strlen(tokens_prefix), ctx);
+    }
+    if (strcmp(path, "/v1/coach/compare") == 0 && strcmp(method, "GET") == 0) return handle_get_coach_compare(fd, db, req, ctx);
+    if (strcmp(path, "/v1/locks") == 0 && strcmp(method, "GET") == 0) return handle_list_locks(fd, db, ctx);
+    const char *locks_prefix = "/v1/locks/";
+    if (strncmp(path, locks_prefix, strlen(locks_prefix)) == 0 && (strcmp(method, "PUT") == 0 || strcmp(method, "DELETE") == 0)) {
+        const char *key = path + strlen(locks_prefix);
+        if (!is_valid_key(key)) {
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown key\"}", ctx);
+            return 404;
+        }
+        return handle_change_lock(fd, db, key, req, ctx);
+    }
+    send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+    return 404;
+}
@@ -1,28 +1,176 @@
+#define _GNU_SOURCE
+
 #include "logger.h"
 
+#include <pthread.h>
 #include <stdarg.h>
 #include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
 #include <time.h>
 
-static void log_v(const char *level, const char *fmt, va_list ap) {
-    time_t now = time(NULL);
-    struct tm tm_now;
-#if defined(__APPLE__) || defined(__linux__)
-    localtime_r(&now, &tm_now);
-#else
-    struct tm *tmp = localtime(&now);
-    if (!tmp) return;
-    tm_now = *tmp;
-#endif
+#define LOG_REDACT_MAX 32
+#define LOG_REDACT_MIN_LEN 4
+#define LOG_REDACTED "[REDACTED]"
+
+static pthread_mutex_t g_redact_mutex = PTHREAD_MUTEX_INITIALIZER;
+static char *g_redact_values[LOG_REDACT_MAX];
+static size_t g_redact_count;
+
+void log_redact_value(const char *value) {
+    /* Very short values would blank out ordinary words, so they are not worth masking. */
+    if (!value || strlen(value) < LOG_REDACT_MIN_LEN) return;
+    pthread_mutex_lock(&g_redact_mutex);
+    int known = 0;
+    for (size_t i = 0; i < g_redact_count; i++) {
+        if (strcmp(g_redact_values[i], value) == 0) known = 1;
+    }
+    if (!known && g_redact_count < LOG_REDACT_MAX) {
+        char *copy = strdup(value);
+        if (copy) g_redact_values[g_redact_count++] = copy;
+    }
+    pthread_mutex_unlock(&g_redact_mutex);
+}
+
+/* Writes s; with json set as the inside of a JSON string. */
+static void write_text(const char *s, size_t len, int json) {
+    if (!json) {
+        fwrite(s, 1, len, stderr);
+        return;
+    }
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)s[i];
+        if (ch == '"' || ch == '\\') {
+            fputc('\\', stderr);
+            fputc(ch, stderr);
+        } else if (ch == '\n') {
+            fputs("\\n", stderr);
+        } else if (ch < 0x20) {
+            fprintf(stderr, "\\u%04x", ch);
+        } else {
+            fputc(ch, stderr);
+        }
+    }
+}
+
+/* Writes line to stderr with registered secrets replaced; the caller holds g_redact_mutex. */
+static void write_redacted(const char *line, int json) {
+    const char *p = line;
+    while (*p) {
+        const char *next = NULL;
+        size_t next_len = 0;
+        for (size_t i = 0; i < g_redact_count; i++) {
+            const char *hit = strstr(p, g_redact_values[i]);
+            if (hit && (!next || hit < next)) {
+                next = hit;
+                next_len = strlen(g_redact_values[i]);
+            }
+        }
+        if (!next) {
+            write_text(p, strlen(p), json);
+            break;
+        }
+        write_text(p, (size_t)(next - p), json);
+        fputs(LOG_REDACTED, stderr);
+        p = next + next_len;
+    }
+}
 
-    char ts[32];
-    if (strftime(ts, sizeof(ts), "%Y-%m-%d %H:%M:%S", &tm_now) == 0) {
-        ts[0] = '\0';
+static pthread_once_t g_journal_once = PTHREAD_ONCE_INIT;
+static int g_journal;
+
+/* systemd sets JOURNAL_STREAM to the device:inode of the stream it connected to stderr. */
+static void detect_journal(void) {
+    const char *stream = getenv("JOURNAL_STREAM");
+    unsigned long long dev = 0;
+    unsigned long long ino = 0;
+    struct stat st;
+    if (!stream || sscanf(stream, "%llu:%llu", &dev, &ino) != 2 || fstat(2, &st) != 0) return;
+    g_journal = (unsigned long long)st.st_dev == dev && (unsigned long long)st.st_ino == ino;
+}
+
+static int syslog_priority(const char *level) {
+    if (strcmp(level, "ERROR") == 0) return 3;
+    if (strcmp(level, "WARN") == 0) return 4;
+    return 6;
+}
+
+static int json_format(void) {
+    const char *format = getenv("FRICU_LOG_FORMAT");
+    return format && strcmp(format, "json") == 0;
+}
+
+/*
+ * One log line. Text: "[local time] [LEVEL] message". FRICU_LOG_FORMAT=json: one object per line,
+ * {"ts":"<UTC ISO 8601>","level":"info","msg":"message"} followed by fields, a JSON member list
+ * the caller built (log_event). The journal timestamps lines itself, so under systemd the text
+ * timestamp gives way to a <priority> prefix that sets the entry's level.
+ */
+static void emit(const char *level, const char *message, const char *fields) {
+    struct timespec now;
+    clock_gettime(CLOCK_REALTIME, &now);
+    struct tm tm_now;
+    int json = json_format();
+    char ts[48];
+    if (json) {
+        gmtime_r(&now.tv_sec, &tm_now);
+        size_t n = strftime(ts, sizeof(ts), "%Y-%m-%dT%H:%M:%S", &tm_now);
+        snprintf(ts + n, sizeof(ts) - n, ".%03ldZ", now.tv_nsec / 1000000);
+    } else {
+        localtime_r(&now.tv_sec, &tm_now);
+        if (strftime(ts, sizeof(ts), "%Y-%m-%d %H:%M:%S", &tm_now) == 0) ts[0] = '\0';
     }
 
-    fprintf(stderr, "[%s] [%s] ", ts, level);
-    vfprintf(stderr, fmt, ap);
+    pthread_once(&g_journal_once, detect_journal);
+    pthread_mutex_lock(&g_redact_mutex);
+    if (g_journal) fprintf(stderr, "<%d>", syslog_priority(level));
+    if (json) {
+        fprintf(stderr, "{\"ts\":\"%s\",\"level\":\"", ts);
+        for (const char *p = level; *p; p++) fputc(*p >= 'A' && *p <= 'Z' ? *p - 'A' + 'a' : *p, stderr);
+        fputs("\",\"msg\":\"", stderr);
+        write_redacted(message, 1);
+        fputc('"', stderr);
+        if (fields && fields[0] != '\0') {
+            fputc(',', stderr);
+            write_redacted(fields, 0);
+        }
+        fputc('}', stderr);
+    } else {
+        if (g_journal) {
+            fprintf(stderr, "[%s] ", level);
+        } else {
+            fprintf(stderr, "[%s] [%s] ", ts, level);
+        }
+        write_redacted(message, 0);
+    }
     fputc('\n', stderr);
+    pthread_mutex_unlock(&g_redact_mutex);
+}
+
+static void log_v(const char *level, const char *fmt, va_list ap) {
+    /* Format first so the whole message can be checked against the redaction list. */
+    char stack_line[2048];
+    char *line = stack_line;
+    va_list copy;
+    va_copy(copy, ap);
+    int len = vsnprintf(stack_line, sizeof(stack_line), fmt, copy);
+    va_end(copy);
+    if (len < 0) return;
+    if ((size_t)len >= sizeof(stack_line)) {
+        line = (char *)malloc((size_t)len + 1);
+        if (line) {
+            vsnprintf(line, (size_t)len + 1, fmt, ap);
+        } else {
+            line = stack_line;
+        }
+    }
+    emit(level, line, NULL);
+    if (line != stack_line) free(line);
+}
+
+void log_event(const char *level, const char *message, const char *fields) {
+    emit(level, message, fields);
 }
 
 void log_info(const char *fmt, ...) {
@@ -4,5 +4,12 @@
 void log_info(const char *fmt, ...);
 void log_warn(const char *fmt, ...);
 void log_error(const char *fmt, ...);
+/*
+ * A log line carrying structured fields: message is the whole text-format line, fields a JSON
+ * member list ("\"status\":200,...") appended to the object when FRICU_LOG_FORMAT=json.
+ */
+void log_event(const char *level, const char *message, const char *fields);
+/* Masks every later occurrence of value in log lines; used for secrets loaded at startup. */
+void log_redact_value(const char *value);
 
 #endif
@@ -0,0 +1,534 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <time.h>
+
+/*
+ * Email-in gateway for devices that can only mail their exports. An account registers the sender
+ * addresses it trusts (PUT /v1/import/email) and gets a secret webhook path; the mail service
+ * (Mailgun/SendGrid "raw MIME" forwarding, or Postmark's inbound JSON) posts each message to
+ * /inbound/email/<token>. Messages from unlisted senders, or that the receiving service marked as
+ * failing DMARC/SPF, are rejected; FIT/TCX attachments are imported as activities.
+ */
+
+#define MAIL_TOKEN_BYTES 24
+#define MAIL_MAX_SENDERS 20
+#define MAIL_MAX_DEPTH 4
+#define MAIL_ADDRESS_MAX 256
+
+typedef struct {
+    char from[MAIL_ADDRESS_MAX];
+    char date[32];
+    int auth_failed;
+    upload_files_t attachments;
+} mail_message_t;
+
+void upload_files_free(upload_files_t *files) {
+    for (size_t i = 0; i < files->count; i++) free(files->owned[i]);
+    files->count = 0;
+}
+
+static int generate_mail_token(char *out, size_t out_len) {
+    unsigned char raw[MAIL_TOKEN_BYTES];
+    if (out_len < 5 + MAIL_TOKEN_BYTES * 2 + 1) return -1;
+    if (fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    memcpy(out, "mail_", 5);
+    for (size_t i = 0; i < sizeof(raw); i++) {
+        snprintf(out + 5 + i * 2, 3, "%02x", raw[i]);
+    }
+    return 0;
+}
+
+/* "Name <user@example.com>" or a bare address, lowercased. */
+static void mail_address(const char *value, char *out, size_t out_len) {
+    const char *start = value;
+    const char *end = value + strlen(value);
+    const char *lt = strrchr(value, '<');
+    if (lt && strchr(lt, '>')) {
+        start = lt + 1;
+        end = strchr(lt, '>');
+    }
+    while (start < end && isspace((unsigned char)*start)) start++;
+    while (end > start && isspace((unsigned char)end[-1])) end--;
+    size_t n = 0;
+    for (const char *p = start; p < end && n + 1 < out_len; p++) out[n++] = (char)tolower((unsigned char)*p);
+    out[n] = '\0';
+}
+
+/* RFC 5322 date ("Tue, 14 Oct 2025 07:30:00 +0200") as an ISO UTC timestamp. */
+static void mail_date(const char *value, char *out, size_t out_len) {
+    struct tm tm_value;
+    memset(&tm_value, 0, sizeof(tm_value));
+    const char *p = strchr(value, ',') ? strchr(value, ',') + 1 : value;
+    const char *rest = strptime(p, " %d %b %Y %H:%M:%S %z", &tm_value);
+    if (!rest) return;
+    long offset = tm_value.tm_gmtoff;
+    time_t utc = timegm(&tm_value) - offset;
+    struct tm tm_utc;
+    gmtime_r(&utc, &tm_utc);
+    strftime(out, out_len, "%Y-%m-%dT%H:%M:%SZ", &tm_utc);
+}
+
+static void mail_check_authentication(mail_message_t *msg, const char *results) {
+    if (!results) return;
+    if (strcasestr(results, "dmarc=fail") || (strcasestr(results, "spf=fail") && !strcasestr(results, "dkim=pass"))) msg->auth_failed = 1;
+}
+
+/* Unfolded value of the first header called name in [headers, headers + len). */
+static int mail_header(const char *headers, size_t len, const char *name, char *out, size_t out_len) {
+    size_t name_len = strlen(name);
+    const char *end = headers + len;
+    for (const char *line = headers; line < end;) {
+        const char *eol = memchr(line, '\n', (size_t)(end - line));
+        if (!eol) eol = end;
+        if ((size_t)(eol - line) > name_len && strncasecmp(line, name, name_len) == 0 && line[name_len] == ':') {
+            size_t n = 0;
+            const char *p = line + name_len + 1;
+            for (;;) {
+                while (p < eol && (*p == ' ' || *p == '\t') && n == 0) p++;
+                for (; p < eol && *p != '\r' && n + 1 < out_len; p++) out[n++] = *p;
+                if (eol + 1 >= end || (eol[1] != ' ' && eol[1] != '\t')) break;
+                if (n + 1 < out_len) out[n++] = ' ';
+                p = eol + 1;
+                while (p < end && (*p == ' ' || *p == '\t')) p++;
+                eol = memchr(p, '\n', (size_t)(end - p));
+                if (!eol) eol = end;
+            }
+            out[n] = '\0';
+            return 1;
+        }
+        line = eol + 1;
+    }
+    return 0;
+}
+
+/* Parameter of a structured header value: boundary="x", filename=a.fit, ... */
+static int mail_header_param(const char *value, const char *param, char *out, size_t out_len) {
+    size_t param_len = strlen(param);
+    for (const char *p = value; (p = strcasestr(p, param)) != NULL; p += param_len) {
+        if (p != value && p[-1] != ';' && p[-1] != ' ' && p[-1] != '\t') continue;
+        const char *v = p + param_len;
+        while (*v == ' ') v++;
+        if (*v != '=') continue;
+        v++;
+        while (*v == ' ') v++;
+        size_t n = 0;
+        if (*v == '"') {
+            for (v++; *v && *v != '"' && n + 1 < out_len; v++) out[n++] = *v;
+        } else {
+            for (; *v && *v != ';' && *v != ' ' && n + 1 < out_len; v++) out[n++] = *v;
+        }
+        out[n] = '\0';
+        return n > 0;
+    }
+    return 0;
+}
+
+static int hex_digit(char c) {
+    if (c >= '0' && c <= '9') return c - '0';
+    if (c >= 'A' && c <= 'F') return c - 'A' + 10;
+    if (c >= 'a' && c <= 'f') return c - 'a' + 10;
+    return -1;
+}
+
+static unsigned char *decode_quoted_printable(const char *in, size_t len, size_t *out_len) {
+    unsigned char *out = malloc(len + 1);
+    if (!out) return NULL;
+    size_t o = 0;
+    for (size_t i = 0; i < len; i++) {
+        if (in[i] != '=') {
+            out[o++] = (unsigned char)in[i];
+        } else if (i + 1 < len && in[i + 1] == '\n') {
+            i += 1;
+        } else if (i + 2 < len && in[i + 1] == '\r' && in[i + 2] == '\n') {
+            i += 2;
+        } else if (i + 2 < len && hex_digit(in[i + 1]) >= 0 && hex_digit(in[i + 2]) >= 0) {
+            out[o++] = (unsigned char)(hex_digit(in[i + 1]) * 16 + hex_digit(in[i + 2]));
+            i += 2;
+        } else {
+            out[o++] = '=';
+        }
+    }
+    *out_len = o;
+    return out;
+}
+
+static int mail_add_file(mail_message_t *msg, const char *name, unsigned char *data, size_t len) {
+    upload_files_t *files = &msg->attachments;
+    if (files->count >= UPLOAD_MAX_FILES) {
+        free(data);
+        return 0;
+    }
+    size_t i = files->count++;
+    snprintf(files->names[i], sizeof(files->names[i]), "%s", name);
+    files->owned[i] = data;
+    files->files[i].name = files->names[i];
+    files->files[i].data = data;
+    files->files[i].len = len;
+    return 0;
+}
+
+static void mail_split(const char *part, size_t len, const char **body, size_t *body_len) {
+    if (len >= 2 && part[0] == '\r' && part[1] == '\n') {
+        *body = part + 2;
+    } else if (len >= 1 && part[0] == '\n') {
+        *body = part + 1;
+    } else {
+        const char *crlf = memmem(part, len, "\r\n\r\n", 4);
+        const char *lf = memmem(part, len, "\n\n", 2);
+        if (crlf && (!lf || crlf < lf)) {
+            *body = crlf + 4;
+        } else if (lf) {
+            *body = lf + 2;
+        } else {
+            *body = part + len;
+        }
+    }
+    *body_len = len - (size_t)(*body - part);
+}
+
+static int mail_parse_part(mail_message_t *msg, const char *part, size_t len, int depth);
+
+/* Reads each part between the boundary delimiters of a multipart body. */
+static int mail_parse_multipart(mail_message_t *msg, const char *boundary, const char *body, size_t body_len, int depth) {
+    char delimiter[132];
+    int delimiter_len = snprintf(delimiter, sizeof(delimiter), "--%s", boundary);
+    const char *end = body + body_len;
+    const char *p = memmem(body, body_len, delimiter, (size_t)delimiter_len);
+    while (p) {
+        p += delimiter_len;
+        if (end - p >= 2 && p[0] == '-' && p[1] == '-') break;
+        const char *start = memchr(p, '\n', (size_t)(end - p));
+        if (!start) break;
+        start++;
+        const char *next = start;
+        const char *stop = NULL;
+        while ((next = memmem(next, (size_t)(end - next), delimiter, (size_t)delimiter_len)) != NULL) {
+            if (next > start && next[-1] == '\n') {
+                stop = next - 1;
+                break;
+            }
+            next += delimiter_len;
+        }
+        if (!stop) stop = end;
+        if (stop > start && stop[-1] == '\r') stop--;
+        if (mail_parse_part(msg, start, (size_t)(stop - start), depth + 1) != 0) return -1;
+        p = next;
+    }
+    return 0;
+}
+
+static int mail_parse_part(mail_message_t *msg, const char *part, size_t len, int depth) {
+    if (depth > MAIL_MAX_DEPTH) return 0;
+    const char *body = NULL;
+    size_t body_len = 0;
+    mail_split(part, len, &body, &body_len);
+    size_t header_len = (size_t)(body - part);
+
+    char content_type[512] = {0};
+    char boundary[128] = {0};
+    mail_header(part, header_len, "Content-Type", content_type, sizeof(content_type));
+    if (strncasecmp(content_type, "multipart/", 10) == 0 && mail_header_param(content_type, "boundary", boundary, sizeof(boundary))) {
+        return mail_parse_multipart(msg, boundary, body, body_len, depth);
+    }
+
+    char disposition[512] = {0};
+    char name[128] = {0};
+    mail_header(part, header_len, "Content-Disposition", disposition, sizeof(disposition));
+    if (!mail_header_param(disposition, "filename", name, sizeof(name))) mail_header_param(content_type, "name", name, sizeof(name));
+    if (name[0] == '\0' && strncasecmp(content_type, "application/", 12) != 0) return 0;
+
+    char encoding[64] = {0};
+    mail_header(part, header_len, "Content-Transfer-Encoding", encoding, sizeof(encoding));
+    size_t data_len = 0;
+    unsigned char *data = NULL;
+    if (strcasecmp(encoding, "base64") == 0) {
+        data = base64_decode(body, body_len, &data_len);
+    } else if (strcasecmp(encoding, "quoted-printable") == 0) {
+        data = decode_quoted_printable(body, body_len, &data_len);
+    } else {
+        data = malloc(body_len + 1);
+        if (data) memcpy(data, body, body_len);
+        data_len = body_len;
+    }
+    if (!data) return body_len > 0 && strcasecmp(encoding, "base64") == 0 ? 0 : -1;
+    if (name[0] == '\0' && !activity_file_type(NULL, data, data_len)) {
+        free(data);
+        return 0;
+    }
+    return mail_add_file(msg, name, data, data_len);
+}
+
+/* Form uploads (multipart/form-data) carry their files as parts; returns the count, or -1 when the body is not multipart. */
+int multipart_read_files(const char *content_type, const char *body, size_t len, upload_files_t *out) {
+    memset(out, 0, sizeof(*out));
+    char boundary[128] = {0};
+    if (!content_type || strncasecmp(content_type, "multipart/", 10) != 0 || !mail_header_param(content_type, "boundary", boundary, sizeof(boundary))) return -1;
+    mail_message_t msg;
+    memset(&msg, 0, sizeof(msg));
+    int rc = mail_parse_multipart(&msg, boundary, body, len, 1);
+    *out = msg.attachments;
+    for (size_t i = 0; i < out->count; i++) out->files[i].name = out->names[i];
+    if (rc == 0) return (int)out->count;
+    upload_files_free(out);
+    return -1;
+}
+
+static int mail_parse_mime(mail_message_t *msg, const char *raw, size_t len) {
+    const char *body = NULL;
+    size_t body_len = 0;
+    mail_split(raw, len, &body, &body_len);
+    size_t header_len = (size_t)(body - raw);
+    char value[1024] = {0};
+    if (!mail_header(raw, header_len, "From", value, sizeof(value))) return -1;
+    mail_address(value, msg->from, sizeof(msg->from));
+    if (mail_header(raw, header_len, "Date", value, sizeof(value))) mail_date(value, msg->date, sizeof(msg->date));
+    if (mail_header(raw, header_len, "Authentication-Results", value, sizeof(value))) mail_check_authentication(msg, value);
+    return mail_parse_part(msg, raw, len, 0);
+}
+
+/* Postmark inbound JSON: From/FromFull, Date, Headers[{Name,Value}], Attachments[{Name,Content}]. */
+static int mail_parse_json(sqlite3 *db, mail_message_t *msg, const char *body, size_t len) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT COALESCE(json_extract(?1, '$.FromFull.Email'), json_extract(?1, '$.From')), json_extract(?1, '$.Date'),"
+        " (SELECT json_extract(value, '$.Value') FROM json_each(?1, '$.Headers')"
+        "  WHERE lower(json_extract(value, '$.Name')) = 'authentication-results' LIMIT 1)"
+        " WHERE json_valid(?1)";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, body, (int)len, SQLITE_TRANSIENT);
+    int rc = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        mail_address((const char *)sqlite3_column_text(stmt, 0), msg->from, sizeof(msg->from));
+        if (sqlite3_column_text(stmt, 1)) mail_date((const char *)sqlite3_column_text(stmt, 1), msg->date, sizeof(msg->date));
+        mail_check_authentication(msg, (const char *)sqlite3_column_text(stmt, 2));
+        rc = 0;
+    }
+    sqlite3_finalize(stmt);
+    if (rc != 0) return -1;
+
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE(json_extract(value, '$.Name'), ''), json_extract(value, '$.Content') FROM json_each(?1, '$.Attachments')"
+            " WHERE json_type(value, '$.Content') = 'text'",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, body, (int)len, SQLITE_TRANSIENT);
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        size_t data_len = 0;
+        const char *content = (const char *)sqlite3_column_text(stmt, 1);
+        unsigned char *data = base64_decode(content, (size_t)sqlite3_column_bytes(stmt, 1), &data_len);
+        if (data) rc = mail_add_file(msg, (const char *)sqlite3_column_text(stmt, 0), data, data_len);
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+static int sender_is_verified(sqlite3 *db, const char *senders_json, const char *from) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT 1 FROM json_each(?1) WHERE value = ?2", -1, &stmt, NULL) != SQLITE_OK) return 0;
+    sqlite3_bind_text(stmt, 1, senders_json, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    int found = sqlite3_step(stmt) == SQLITE_ROW;
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+int handle_post_inbound_email(int fd, worker_db_t *db, const http_request_t *req, const char *token, const request_log_context_t *ctx) {
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    request_log_context_t account_ctx = *ctx;
+    char senders[4096] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT account_id, senders FROM mail_inboxes WHERE token = ?1", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, token, -1, SQLITE_TRANSIENT);
+    int found = sqlite3_step(stmt) == SQLITE_ROW;
+    if (found) {
+        snprintf(account_ctx.account_id, sizeof(account_ctx.account_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(senders, sizeof(senders), "%s", (const char *)sqlite3_column_text(stmt, 1));
+    }
+    sqlite3_finalize(stmt);
+    if (!found || token[0] == '\0') {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown inbox\"}", ctx);
+        return 404;
+    }
+
+    char content_type[256] = {0};
+    http_request_header(req, "Content-Type", content_type, sizeof(content_type));
+    mail_message_t msg;
+    memset(&msg, 0, sizeof(msg));
+    int parsed = strcasestr(content_type, "json") ? mail_parse_json(db->db, &msg, req->body, req->body_len)
+                                                  : mail_parse_mime(&msg, req->body, req->body_len);
+    int status = 0;
+    if (parsed != 0 || msg.from[0] == '\0') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"could not parse email (expected raw MIME or inbound JSON)\"}", &account_ctx);
+        status = 400;
+    } else if (!sender_is_verified(db->db, senders, msg.from)) {
+        log_warn("MAILIN rejected unverified sender=%s account=%s logid=%s", msg.from, account_ctx.account_id, ctx->log_id);
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"sender is not a verified address\"}", &account_ctx);
+        status = 403;
+    } else if (msg.auth_failed) {
+        log_warn("MAILIN rejected failed authentication sender=%s account=%s logid=%s", msg.from, account_ctx.account_id, ctx->log_id);
+        send_response_with_log_context(fd, 403, "Forbidden", "{\"error\":\"sender authentication failed\"}", &account_ctx);
+        status = 403;
+    } else {
+        status = locks_enforce_key(fd, db, req, "activities", &account_ctx);
+        if (status == 0) {
+            status = import_activity_files(fd, db, "email", msg.attachments.files, msg.attachments.count, msg.date, 1, &account_ctx);
+            if (sqlite3_prepare_v2(
+                    db->db,
+                    "UPDATE mail_inboxes SET last_received_at = strftime('%s', 'now'), received_count = received_count + 1 WHERE token = ?1",
+                    -1,
+                    &stmt,
+                    NULL) == SQLITE_OK) {
+                sqlite3_bind_text(stmt, 1, token, -1, SQLITE_TRANSIENT);
+                sqlite3_step(stmt);
+                sqlite3_finalize(stmt);
+            }
+            log_info("MAILIN message sender=%s attachments=%zu status=%d account=%s logid=%s", msg.from, msg.attachments.count, status, account_ctx.account_id, ctx->log_id);
+        }
+    }
+    upload_files_free(&msg.attachments);
+    return status;
+}
+
+static int send_mail_inbox(int fd, sqlite3 *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT token, senders, created_at, last_received_at, received_count FROM mail_inboxes WHERE account_id = ?1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"email import is not configured\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    capture_omit_response();
+    strbuf_appendf(&sb, "{\"path\":\"/inbound/email/%s\",\"senders\":%s,", (const char *)sqlite3_column_text(stmt, 0), (const char *)sqlite3_column_text(stmt, 1));
+    strbuf_appendf(&sb, "\"created_at\":%lld,\"last_received_at\":", sqlite3_column_int64(stmt, 2));
+    if (sqlite3_column_type(stmt, 3) == SQLITE_NULL) {
+        strbuf_append(&sb, "null", 4);
+    } else {
+        strbuf_appendf(&sb, "%lld", sqlite3_column_int64(stmt, 3));
+    }
+    strbuf_appendf(&sb, ",\"received\":%d}", sqlite3_column_int(stmt, 4));
+    sqlite3_finalize(stmt);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_put_mail_inbox(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT json_valid(?1), json_type(?1, '$.senders'), json_array_length(?1, '$.senders'),"
+        " (SELECT COUNT(*) FROM json_each(?1, '$.senders') WHERE type != 'text' OR instr(value, '@') < 2 OR length(value) > 254),"
+        " (SELECT json_group_array(DISTINCT lower(trim(value))) FROM json_each(?1, '$.senders')),"
+        " COALESCE(json_extract(?1, '$.rotate'), 0)";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char senders[4096] = {0};
+    int rotate = 0;
+    const char *error = "{\"error\":\"invalid json\"}";
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+        const char *type = (const char *)sqlite3_column_text(stmt, 1);
+        int count = sqlite3_column_int(stmt, 2);
+        if (!type || strcmp(type, "array") != 0 || count < 1 || count > MAIL_MAX_SENDERS || sqlite3_column_int(stmt, 3) > 0) {
+            error = "{\"error\":\"senders must be an array of 1-20 email addresses\"}";
+        } else {
+            snprintf(senders, sizeof(senders), "%s", (const char *)sqlite3_column_text(stmt, 4));
+            rotate = sqlite3_column_int(stmt, 5);
+            error = NULL;
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (error) {
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+
+    char token[64] = {0};
+    if (generate_mail_token(token, sizeof(token)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        return 500;
+    }
+    if (sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO mail_inboxes (account_id, token, senders, created_at) VALUES (?1, ?2, ?3, strftime('%s', 'now'))"
+            " ON CONFLICT(account_id) DO UPDATE SET senders = excluded.senders,"
+            " token = CASE WHEN ?4 THEN excluded.token ELSE mail_inboxes.token END",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, token, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, senders, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 4, rotate);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("MAILIN inbox configured account=%s senders=%s rotated=%d", ctx->account_id, senders, rotate);
+    return send_mail_inbox(fd, db->db, ctx);
+}
+
+int route_mail_inbox(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->method, "GET") == 0) return send_mail_inbox(fd, db->db, ctx);
+    if (strcmp(req->method, "PUT") == 0) return handle_put_mail_inbox(fd, db, req, ctx);
+    if (strcmp(req->method, "DELETE") == 0) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, "DELETE FROM mail_inboxes WHERE account_id = ?1", -1, &stmt, NULL) != SQLITE_OK) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_step(stmt);
+        sqlite3_finalize(stmt);
+        if (sqlite3_changes(db->db) == 0) {
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"email import is not configured\"}", ctx);
+            return 404;
+        }
+        send_response_with_log_context(fd, 204, "No Content", "", ctx);
+        return 204;
+    }
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
@@ -1,36 +1,82 @@
 #define _GNU_SOURCE
-#include <arpa/inet.h>
-#include <errno.h>
-#include <netinet/in.h>
-#include <netinet/tcp.h>
-#include <pthread.h>
+#include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
-#include <sys/resource.h>
-#include <sys/socket.h>
-#include <time.h>
-#include <unistd.h>
 
 #include "logger.h"
 #include "server.h"
 #include "server_internal.h"
 
-typedef struct {
-    int listen_fd;
-    char db_path[512];
-    size_t max_fds;
-} worker_ctx_t;
-
-static void *worker_entry(void *arg) {
-    worker_ctx_t *ctx = (worker_ctx_t *)arg;
-    if (run_worker_loop(ctx->listen_fd, ctx->db_path, ctx->max_fds) != 0) {
-        log_error("worker loop exited with error");
+int main(int argc, char **argv) {
+    int debug_profiling = 0;
+    int check_config = 0;
+    const char *decrypt_path = NULL;
+    const char *restore_id = NULL;
+    const char *restore_output = NULL;
+    const char *restore_file = NULL;
+    int install_service = 0;
+    const char *print_service = NULL;
+    for (int i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "--debug-profiling") == 0) {
+            debug_profiling = 1;
+        } else if (strcmp(argv[i], "--check-config") == 0) {
+            check_config = 1;
+        } else if (strcmp(argv[i], "--decrypt") == 0 && i + 1 < argc) {
+            decrypt_path = argv[++i];
+        } else if (strcmp(argv[i], "--restore") == 0 && i + 2 < argc) {
+            restore_id = argv[++i];
+            restore_output = argv[++i];
+        } else if (strcmp(argv[i], "--restore-db") == 0 && i + 1 < argc) {
+            restore_file = argv[++i];
+        } else if (strcmp(argv[i], "--install-service") == 0) {
+            install_service = 1;
+        } else if (strcmp(argv[i], "--print-service") == 0 && i + 1 < argc) {
+            print_service = argv[++i];
+        } else {
+            log_error(
+                "unknown argument: %s (supported: --debug-profiling, --check-config, --decrypt <file>, --restore <backup-id> <file>, "
+                "--restore-db <file>, --install-service, --print-service systemd|launchd)",
+                argv[i]);
+            return 1;
+        }
     }
-    return NULL;
-}
+    if (check_config) return config_check(stdout) == 0 ? 0 : 1;
+    if (install_service) return service_install(argv[0], stdout) == 0 ? 0 : 1;
+    if (print_service) {
+        strbuf_t unit;
+        strbuf_init(&unit);
+        int rc = service_render(print_service, argv[0], &unit);
+        if (rc == 0) fputs(strbuf_cstr(&unit), stdout);
+        strbuf_free(&unit);
+        return rc == 0 ? 0 : 1;
+    }
+    /* Restoring an off-site copy: writes the plaintext of an encrypted export or upload to stdout. */
+    if (decrypt_path) return export_decrypt_file(decrypt_path, stdout) == 0 ? 0 : 1;
+    if (restore_id) {
+        char dir[512] = {0};
+        char err[256] = {0};
+        const char *restore_db = getenv("FRICU_DB_PATH");
+        if (backup_dir(restore_db ? restore_db : "fricu_server.db", dir, sizeof(dir)) == 0 &&
+            backup_restore(dir, restore_id, restore_output, err, sizeof(err)) == 0) {
+            return 0;
+        }
+        log_error("restore failed: %s", err[0] ? err : "backup path too long");
+        return 1;
+    }
+    /* Swaps a verified SQLite file in as FRICU_DB_PATH; safe while a server has it open. */
+    if (restore_file) {
+        char previous[64] = {0};
+        char err[256] = {0};
+        const char *live_db = getenv("FRICU_DB_PATH");
+        if (backup_swap_in(live_db ? live_db : "fricu_server.db", restore_file, previous, sizeof(previous), err, sizeof(err)) == 200) return 0;
+        log_error("restore failed: %s", err);
+        return 1;
+    }
+    /* Resolve secrets before anything logs so their values are masked from the first line. */
+    config_secrets_load();
+    const char *profiling_env = getenv("FRICU_DEBUG_PROFILING");
+    if (profiling_env && strcmp(profiling_env, "1") == 0) debug_profiling = 1;
 
-#ifndef FRICU_UNIT_TEST
-int main(void) {
     const char *bind_env = getenv("FRICU_SERVER_BIND");
     const char *db_env = getenv("FRICU_DB_PATH");
     const char *workers_env = getenv("FRICU_SERVER_WORKERS");
@@ -40,104 +86,11 @@ int main(void) {
     size_t worker_count = workers_env ? (size_t)strtoul(workers_env, NULL, 10) : DEFAULT_WORKERS;
     if (worker_count == 0 || worker_count > 1024) worker_count = DEFAULT_WORKERS;
 
-    if (tune_fd_limit() != 0) {
-        log_warn("failed to tune fd limit, continuing");
-    }
-
-    if (init_db(db_path) != 0) return 1;
-
-    char host[128] = {0};
-    int port = 8080;
-    if (parse_bind_addr(bind_addr_str, host, sizeof(host), &port) != 0) {
-        log_error("invalid FRICU_SERVER_BIND: %s", bind_addr_str);
-        return 1;
-    }
-
-    int server_fd = socket(AF_INET, SOCK_STREAM, 0);
-    if (server_fd < 0) {
-        log_error("socket creation failed: errno=%d", errno);
-        return 1;
-    }
-
-    int opt = 1;
-    setsockopt(server_fd, SOL_SOCKET, SO_REUSEADDR, &opt, sizeof(opt));
-#ifdef SO_REUSEPORT
-    setsockopt(server_fd, SOL_SOCKET, SO_REUSEPORT, &opt, sizeof(opt));
-#endif
-
-    int backlog = 65535;
-    setsockopt(server_fd, SOL_SOCKET, SO_RCVBUF, &backlog, sizeof(backlog));
-    setsockopt(server_fd, SOL_SOCKET, SO_SNDBUF, &backlog, sizeof(backlog));
-
-    if (set_nonblocking(server_fd) != 0) {
-        log_error("set_nonblocking failed: errno=%d", errno);
-        close(server_fd);
-        return 1;
-    }
-
-    struct sockaddr_in addr;
-    memset(&addr, 0, sizeof(addr));
-    addr.sin_family = AF_INET;
-    addr.sin_port = htons((uint16_t)port);
-    if (inet_pton(AF_INET, host, &addr.sin_addr) <= 0) {
-        log_error("invalid bind host: %s", host);
-        close(server_fd);
-        return 1;
-    }
-
-    if (bind(server_fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) {
-        log_error("bind failed: errno=%d", errno);
-        close(server_fd);
-        return 1;
-    }
-
-    if (listen(server_fd, 65535) < 0) {
-        log_error("listen failed: errno=%d", errno);
-        close(server_fd);
-        return 1;
-    }
-
-    struct rlimit lim;
-    if (getrlimit(RLIMIT_NOFILE, &lim) != 0) {
-        log_warn("getrlimit failed, using fallback max_fds");
-        lim.rlim_cur = 65535;
-    }
-    size_t max_fds = (size_t)lim.rlim_cur;
+    const char *slow_request_env = getenv("FRICU_SLOW_REQUEST_MS");
+    const char *slow_query_env = getenv("FRICU_SLOW_QUERY_MS");
+    slowlog_configure(slow_request_env ? strtod(slow_request_env, NULL) : -1, slow_query_env ? strtod(slow_query_env, NULL) : -1);
 
-    worker_ctx_t *workers = (worker_ctx_t *)calloc(worker_count, sizeof(worker_ctx_t));
-    pthread_t *threads = (pthread_t *)calloc(worker_count, sizeof(pthread_t));
-    if (!workers || !threads) {
-        log_error("failed to allocate worker structures");
-        close(server_fd);
-        free(workers);
-        free(threads);
-        return 1;
-    }
+    if (debug_profiling) profiling_enable(worker_count);
 
-    for (size_t i = 0; i < worker_count; i++) {
-        workers[i].listen_fd = server_fd;
-        workers[i].max_fds = max_fds;
-        strncpy(workers[i].db_path, db_path, sizeof(workers[i].db_path) - 1);
-        workers[i].db_path[sizeof(workers[i].db_path) - 1] = '\0';
-        if (pthread_create(&threads[i], NULL, worker_entry, &workers[i]) != 0) {
-            log_error("failed to start worker %zu", i);
-            close(server_fd);
-            free(workers);
-            free(threads);
-            return 1;
-        }
-    }
-
-    log_info("fricu-server listening on %s (workers=%zu, async_io=auto)", bind_addr_str, worker_count);
-
-    for (size_t i = 0; i < worker_count; i++) {
-        pthread_join(threads[i], NULL);
-    }
-
-    close(server_fd);
-    free(workers);
-    free(threads);
-    return 0;
+    return engine_run(bind_addr_str, db_path, worker_count) == 0 ? 0 : 1;
 }
-
-#endif
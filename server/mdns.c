@@ -0,0 +1,422 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <arpa/inet.h>
+#include <errno.h>
+#include <ifaddrs.h>
+#include <net/if.h>
+#include <netinet/in.h>
+#include <poll.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <sys/socket.h>
+#include <unistd.h>
+
+/*
+ * mDNS / DNS-SD advertisement (RFC 6762/6763) so apps on the LAN find the server without an IP
+ * address. With FRICU_MDNS=1 the server announces "<name>._fricu._tcp.local" (FRICU_MDNS_NAME,
+ * default "Fricu on <hostname>") with an SRV record for its port, a TXT record (txtvers, path,
+ * tls, protocol = the sync protocol version) and an A record per IPv4 interface address (only the
+ * bound one when FRICU_SERVER_BIND names an address), and answers queries for the service type,
+ * the instance, the host and service enumeration. It shares UDP 5353 with a system responder
+ * (Avahi, mDNSResponder), sends two announcements at startup and a goodbye when the server stops.
+ * A server bound to the loopback is not advertised.
+ */
+
+#define MDNS_PORT 5353
+#define MDNS_GROUP "224.0.0.251"
+#define MDNS_SERVICE_TYPE "_fricu._tcp.local"
+#define MDNS_ENUMERATION "_services._dns-sd._udp.local"
+#define MDNS_TTL_HOST 120
+#define MDNS_TTL_OTHER 4500
+#define MDNS_TYPE_A 1
+#define MDNS_TYPE_PTR 12
+#define MDNS_TYPE_TXT 16
+#define MDNS_TYPE_SRV 33
+#define MDNS_TYPE_ANY 255
+#define MDNS_CLASS_IN 1
+#define MDNS_CACHE_FLUSH 0x8000
+#define MDNS_PACKET_MAX 1500
+
+struct mdns_responder {
+    int fd;
+    volatile int stop;
+    pthread_t thread;
+    mdns_service_t service;
+};
+
+/* DNS labels keep letters, digits and '-'; anything else becomes '-'. */
+static void host_label(const char *in, char *out, size_t out_len) {
+    size_t o = 0;
+    for (const char *p = in; *p && *p != '.' && o + 1 < out_len && o < 63; p++) {
+        char ch = *p;
+        int keep = (ch >= 'A' && ch <= 'Z') || (ch >= 'a' && ch <= 'z') || (ch >= '0' && ch <= '9') || ch == '-';
+        out[o++] = keep ? ch : '-';
+    }
+    out[o] = '\0';
+    if (o == 0) snprintf(out, out_len, "fricu");
+}
+
+int mdns_service_init(mdns_service_t *svc, const char *bind_host, int port) {
+    memset(svc, 0, sizeof(*svc));
+    char hostname[256] = {0};
+    if (gethostname(hostname, sizeof(hostname) - 1) != 0) hostname[0] = '\0';
+    host_label(hostname, svc->host, sizeof(svc->host));
+    const char *name = getenv("FRICU_MDNS_NAME");
+    if (name && name[0] != '\0') {
+        snprintf(svc->instance, sizeof(svc->instance), "%s", name);
+    } else {
+        snprintf(svc->instance, sizeof(svc->instance), "Fricu on %s", svc->host);
+    }
+    /* An instance name is a single label: dots would split it. */
+    for (char *p = svc->instance; *p; p++) {
+        if (*p == '.') *p = '-';
+    }
+    svc->port = port;
+    svc->tls = tls_enabled();
+
+    struct in_addr bound;
+    if (bind_host && inet_pton(AF_INET, bind_host, &bound) == 1 && bound.s_addr != htonl(INADDR_ANY)) {
+        if ((ntohl(bound.s_addr) >> 24) == 127) return -1;
+        svc->addrs[svc->addr_count++] = bound.s_addr;
+        return 0;
+    }
+    struct ifaddrs *ifs = NULL;
+    if (getifaddrs(&ifs) != 0) return -1;
+    for (struct ifaddrs *ifa = ifs; ifa && svc->addr_count < MDNS_MAX_ADDRS; ifa = ifa->ifa_next) {
+        if (!ifa->ifa_addr || ifa->ifa_addr->sa_family != AF_INET || (ifa->ifa_flags & IFF_LOOPBACK) || !(ifa->ifa_flags & IFF_UP)) continue;
+        svc->addrs[svc->addr_count++] = ((struct sockaddr_in *)ifa->ifa_addr)->sin_addr.s_addr;
+    }
+    freeifaddrs(ifs);
+    return svc->addr_count > 0 ? 0 : -1;
+}
+
+typedef struct {
+    unsigned char *data;
+    size_t cap;
+    size_t len;
+    int failed;
+} packet_t;
+
+static void put_bytes(packet_t *pkt, const void *bytes, size_t n) {
+    if (pkt->failed || pkt->len + n > pkt->cap) {
+        pkt->failed = 1;
+        return;
+    }
+    memcpy(pkt->data + pkt->len, bytes, n);
+    pkt->len += n;
+}
+
+static void put_u16(packet_t *pkt, unsigned v) {
+    unsigned char b[2] = {(unsigned char)(v >> 8), (unsigned char)v};
+    put_bytes(pkt, b, 2);
+}
+
+static void put_u32(packet_t *pkt, unsigned long v) {
+    unsigned char b[4] = {(unsigned char)(v >> 24), (unsigned char)(v >> 16), (unsigned char)(v >> 8), (unsigned char)v};
+    put_bytes(pkt, b, 4);
+}
+
+/* The first label is taken whole (an instance name may contain spaces); the rest split on dots. */
+static void put_name(packet_t *pkt, const char *first, const char *rest) {
+    if (first) {
+        size_t n = strlen(first);
+        if (n > 63) n = 63;
+        unsigned char len = (unsigned char)n;
+        put_bytes(pkt, &len, 1);
+        put_bytes(pkt, first, n);
+    }
+    const char *p = rest;
+    while (p && *p) {
+        size_t n = strcspn(p, ".");
+        unsigned char len = (unsigned char)n;
+        put_bytes(pkt, &len, 1);
+        put_bytes(pkt, p, n);
+        p += n;
+        if (*p == '.') p++;
+    }
+    put_bytes(pkt, "", 1);
+}
+
+static void put_record_head(packet_t *pkt, const char *first, const char *rest, unsigned type, unsigned klass, unsigned long ttl) {
+    put_name(pkt, first, rest);
+    put_u16(pkt, type);
+    put_u16(pkt, klass);
+    put_u32(pkt, ttl);
+}
+
+/* Reserves the rdlength field; returns its offset for end_rdata. */
+static size_t begin_rdata(packet_t *pkt) {
+    size_t at = pkt->len;
+    put_u16(pkt, 0);
+    return at;
+}
+
+static void end_rdata(packet_t *pkt, size_t at) {
+    if (pkt->failed) return;
+    size_t n = pkt->len - at - 2;
+    pkt->data[at] = (unsigned char)(n >> 8);
+    pkt->data[at + 1] = (unsigned char)n;
+}
+
+static void put_ptr(packet_t *pkt, const mdns_service_t *svc, int scale) {
+    put_record_head(pkt, NULL, MDNS_SERVICE_TYPE, MDNS_TYPE_PTR, MDNS_CLASS_IN, (unsigned long)(MDNS_TTL_OTHER * scale));
+    size_t at = begin_rdata(pkt);
+    put_name(pkt, svc->instance, MDNS_SERVICE_TYPE);
+    end_rdata(pkt, at);
+}
+
+static void put_enumeration_ptr(packet_t *pkt, int scale) {
+    put_record_head(pkt, NULL, MDNS_ENUMERATION, MDNS_TYPE_PTR, MDNS_CLASS_IN, (unsigned long)(MDNS_TTL_OTHER * scale));
+    size_t at = begin_rdata(pkt);
+    put_name(pkt, NULL, MDNS_SERVICE_TYPE);
+    end_rdata(pkt, at);
+}
+
+static void put_srv(packet_t *pkt, const mdns_service_t *svc, int scale) {
+    put_record_head(pkt, svc->instance, MDNS_SERVICE_TYPE, MDNS_TYPE_SRV, MDNS_CLASS_IN | MDNS_CACHE_FLUSH, (unsigned long)(MDNS_TTL_HOST * scale));
+    size_t at = begin_rdata(pkt);
+    put_u16(pkt, 0);
+    put_u16(pkt, 0);
+    put_u16(pkt, (unsigned)svc->port);
+    put_name(pkt, svc->host, "local");
+    end_rdata(pkt, at);
+}
+
+static void put_txt_string(packet_t *pkt, const char *s) {
+    unsigned char len = (unsigned char)strlen(s);
+    put_bytes(pkt, &len, 1);
+    put_bytes(pkt, s, len);
+}
+
+static void put_txt(packet_t *pkt, const mdns_service_t *svc, int scale) {
+    char protocol[32] = {0};
+    snprintf(protocol, sizeof(protocol), "protocol=%d", SYNC_PROTOCOL_VERSION);
+    put_record_head(pkt, svc->instance, MDNS_SERVICE_TYPE, MDNS_TYPE_TXT, MDNS_CLASS_IN | MDNS_CACHE_FLUSH, (unsigned long)(MDNS_TTL_OTHER * scale));
+    size_t at = begin_rdata(pkt);
+    put_txt_string(pkt, "txtvers=1");
+    put_txt_string(pkt, "path=/");
+    put_txt_string(pkt, svc->tls ? "tls=1" : "tls=0");
+    put_txt_string(pkt, protocol);
+    end_rdata(pkt, at);
+}
+
+static void put_addresses(packet_t *pkt, const mdns_service_t *svc, int scale) {
+    for (size_t i = 0; i < svc->addr_count; i++) {
+        put_record_head(pkt, svc->host, "local", MDNS_TYPE_A, MDNS_CLASS_IN | MDNS_CACHE_FLUSH, (unsigned long)(MDNS_TTL_HOST * scale));
+        put_u16(pkt, 4);
+        put_bytes(pkt, &svc->addrs[i], 4);
+    }
+}
+
+static void put_header(packet_t *pkt, unsigned id, unsigned answers, unsigned additional) {
+    put_u16(pkt, id);
+    put_u16(pkt, 0x8400); /* response, authoritative */
+    put_u16(pkt, 0);
+    put_u16(pkt, answers);
+    put_u16(pkt, 0);
+    put_u16(pkt, additional);
+}
+
+size_t mdns_announcement(const mdns_service_t *svc, int goodbye, unsigned char *out, size_t out_len) {
+    packet_t pkt = {.data = out, .cap = out_len};
+    int scale = goodbye ? 0 : 1;
+    put_header(&pkt, 0, (unsigned)(3 + svc->addr_count), 0);
+    put_ptr(&pkt, svc, scale);
+    put_srv(&pkt, svc, scale);
+    put_txt(&pkt, svc, scale);
+    put_addresses(&pkt, svc, scale);
+    return pkt.failed ? 0 : pkt.len;
+}
+
+/* Reads a possibly compressed name at *pos as lowercase dotted text; -1 if malformed. */
+static int read_name(const unsigned char *msg, size_t len, size_t *pos, char *out, size_t out_len) {
+    size_t p = *pos;
+    size_t o = 0;
+    int jumped = 0;
+    int hops = 0;
+    while (p < len) {
+        unsigned char n = msg[p];
+        if (n == 0) {
+            if (!jumped) *pos = p + 1;
+            out[o] = '\0';
+            return 0;
+        }
+        if ((n & 0xC0) == 0xC0) {
+            if (p + 1 >= len || ++hops > 16) return -1;
+            if (!jumped) *pos = p + 2;
+            jumped = 1;
+            p = ((size_t)(n & 0x3F) << 8) | msg[p + 1];
+            continue;
+        }
+        if ((n & 0xC0) != 0 || p + 1 + n > len || o + n + 2 > out_len) return -1;
+        if (o > 0) out[o++] = '.';
+        for (size_t i = 0; i < n; i++) {
+            char ch = (char)msg[p + 1 + i];
+            out[o++] = ch >= 'A' && ch <= 'Z' ? (char)(ch - 'A' + 'a') : ch;
+        }
+        p += 1 + n;
+    }
+    return -1;
+}
+
+#define WANT_PTR 1
+#define WANT_SRV_TXT 2
+#define WANT_A 4
+#define WANT_ENUMERATION 8
+
+size_t mdns_answer(const unsigned char *query, size_t len, int legacy, const mdns_service_t *svc, unsigned char *out, size_t out_len) {
+    if (len < 12 || (query[2] & 0x80) != 0) return 0; /* responses are not questions */
+    unsigned id = ((unsigned)query[0] << 8) | query[1];
+    unsigned questions = ((unsigned)query[4] << 8) | query[5];
+    char instance[256] = {0};
+    char host[96] = {0};
+    char lowered[256] = {0};
+    snprintf(lowered, sizeof(lowered), "%s.%s", svc->instance, MDNS_SERVICE_TYPE);
+    for (size_t i = 0; lowered[i] && i + 1 < sizeof(instance); i++) {
+        instance[i] = lowered[i] >= 'A' && lowered[i] <= 'Z' ? (char)(lowered[i] - 'A' + 'a') : lowered[i];
+    }
+    snprintf(lowered, sizeof(lowered), "%s.local", svc->host);
+    for (size_t i = 0; lowered[i] && i + 1 < sizeof(host); i++) {
+        host[i] = lowered[i] >= 'A' && lowered[i] <= 'Z' ? (char)(lowered[i] - 'A' + 'a') : lowered[i];
+    }
+
+    int want = 0;
+    size_t pos = 12;
+    for (unsigned q = 0; q < questions; q++) {
+        char name[256] = {0};
+        if (read_name(query, len, &pos, name, sizeof(name)) != 0 || pos + 4 > len) return 0;
+        unsigned type = ((unsigned)query[pos] << 8) | query[pos + 1];
+        pos += 4;
+        int any = type == MDNS_TYPE_ANY;
+        if (strcmp(name, MDNS_SERVICE_TYPE) == 0 && (any || type == MDNS_TYPE_PTR)) want |= WANT_PTR | WANT_SRV_TXT | WANT_A;
+        if (strcmp(name, MDNS_ENUMERATION) == 0 && (any || type == MDNS_TYPE_PTR)) want |= WANT_ENUMERATION;
+        if (strcmp(name, instance) == 0 && (any || type == MDNS_TYPE_SRV || type == MDNS_TYPE_TXT)) want |= WANT_SRV_TXT | WANT_A;
+        if (strcmp(name, host) == 0 && (any || type == MDNS_TYPE_A)) want |= WANT_A;
+    }
+    if (!want) return 0;
+
+    unsigned answers = 0;
+    unsigned additional = 0;
+    if (want & WANT_ENUMERATION) answers++;
+    if (want & WANT_PTR) answers++;
+    if (want & WANT_SRV_TXT) {
+        if (want & WANT_PTR) {
+            additional += 2;
+        } else {
+            answers += 2;
+        }
+    }
+    if (want & WANT_A) {
+        if (want & (WANT_PTR | WANT_SRV_TXT)) {
+            additional += (unsigned)svc->addr_count;
+        } else {
+            answers += (unsigned)svc->addr_count;
+        }
+    }
+    packet_t pkt = {.data = out, .cap = out_len};
+    /* Legacy (one-shot, not port 5353) queriers match the answer to their query id. */
+    put_header(&pkt, legacy ? id : 0, answers, additional);
+    if (want & WANT_ENUMERATION) put_enumeration_ptr(&pkt, 1);
+    if (want & WANT_PTR) put_ptr(&pkt, svc, 1);
+    if (want & WANT_SRV_TXT) {
+        put_srv(&pkt, svc, 1);
+        put_txt(&pkt, svc, 1);
+    }
+    if (want & WANT_A) put_addresses(&pkt, svc, 1);
+    return pkt.failed ? 0 : pkt.len;
+}
+
+static void send_multicast(int fd, const unsigned char *packet, size_t len) {
+    struct sockaddr_in group;
+    memset(&group, 0, sizeof(group));
+    group.sin_family = AF_INET;
+    group.sin_port = htons(MDNS_PORT);
+    inet_pton(AF_INET, MDNS_GROUP, &group.sin_addr);
+    if (sendto(fd, packet, len, 0, (struct sockaddr *)&group, sizeof(group)) < 0) log_warn("mDNS send failed: errno=%d", errno);
+}
+
+static void *mdns_thread_entry(void *arg) {
+    mdns_responder_t *responder = (mdns_responder_t *)arg;
+    unsigned char packet[MDNS_PACKET_MAX];
+    unsigned char reply[MDNS_PACKET_MAX];
+    size_t len = mdns_announcement(&responder->service, 0, packet, sizeof(packet));
+    int announcements = 2;
+    while (!responder->stop) {
+        if (announcements > 0 && len > 0) {
+            send_multicast(responder->fd, packet, len);
+            announcements--;
+        }
+        struct pollfd pfd = {.fd = responder->fd, .events = POLLIN};
+        if (poll(&pfd, 1, 1000) <= 0) continue;
+        unsigned char query[MDNS_PACKET_MAX];
+        struct sockaddr_in from;
+        socklen_t from_len = sizeof(from);
+        ssize_t n = recvfrom(responder->fd, query, sizeof(query), 0, (struct sockaddr *)&from, &from_len);
+        if (n <= 0) continue;
+        int legacy = ntohs(from.sin_port) != MDNS_PORT;
+        size_t reply_len = mdns_answer(query, (size_t)n, legacy, &responder->service, reply, sizeof(reply));
+        if (reply_len == 0) continue;
+        if (legacy) {
+            sendto(responder->fd, reply, reply_len, 0, (struct sockaddr *)&from, from_len);
+        } else {
+            send_multicast(responder->fd, reply, reply_len);
+        }
+    }
+    len = mdns_announcement(&responder->service, 1, packet, sizeof(packet));
+    if (len > 0) send_multicast(responder->fd, packet, len);
+    return NULL;
+}
+
+mdns_responder_t *mdns_start(const char *bind_host, int port) {
+    const char *enabled = getenv("FRICU_MDNS");
+    if (!enabled || strcmp(enabled, "1") != 0) return NULL;
+    mdns_responder_t *responder = (mdns_responder_t *)calloc(1, sizeof(mdns_responder_t));
+    if (!responder) return NULL;
+    if (mdns_service_init(&responder->service, bind_host, port) != 0) {
+        log_warn("mDNS: no LAN address to advertise (bound to %s), not advertising", bind_host);
+        free(responder);
+        return NULL;
+    }
+    responder->fd = socket(AF_INET, SOCK_DGRAM, 0);
+    int opt = 1;
+    unsigned char ttl = 255;
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(MDNS_PORT);
+    addr.sin_addr.s_addr = htonl(INADDR_ANY);
+    struct ip_mreq membership;
+    memset(&membership, 0, sizeof(membership));
+    inet_pton(AF_INET, MDNS_GROUP, &membership.imr_multiaddr);
+    membership.imr_interface.s_addr = htonl(INADDR_ANY);
+    int ok = responder->fd >= 0 && setsockopt(responder->fd, SOL_SOCKET, SO_REUSEADDR, &opt, sizeof(opt)) == 0;
+#ifdef SO_REUSEPORT
+    if (ok) setsockopt(responder->fd, SOL_SOCKET, SO_REUSEPORT, &opt, sizeof(opt));
+#endif
+    ok = ok && bind(responder->fd, (struct sockaddr *)&addr, sizeof(addr)) == 0 &&
+         setsockopt(responder->fd, IPPROTO_IP, IP_ADD_MEMBERSHIP, &membership, sizeof(membership)) == 0 &&
+         setsockopt(responder->fd, IPPROTO_IP, IP_MULTICAST_TTL, &ttl, sizeof(ttl)) == 0;
+    if (!ok || pthread_create(&responder->thread, NULL, mdns_thread_entry, responder) != 0) {
+        log_warn("mDNS: cannot listen on UDP %d: errno=%d, not advertising", MDNS_PORT, errno);
+        if (responder->fd >= 0) close(responder->fd);
+        free(responder);
+        return NULL;
+    }
+    log_info("mDNS: advertising \"%s\" as %s port %d", responder->service.instance, MDNS_SERVICE_TYPE, port);
+    return responder;
+}
+
+void mdns_stop(mdns_responder_t *responder) {
+    if (!responder) return;
+    responder->stop = 1;
+    pthread_join(responder->thread, NULL);
+    close(responder->fd);
+    free(responder);
+}
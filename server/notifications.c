@@ -0,0 +1,132 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define NOTIFICATIONS_DEFAULT_LIMIT 50
+#define NOTIFICATIONS_MAX_LIMIT 500
+
+/* message is the English text; message_keys/message_args (may be NULL) let readers get it translated. */
+static int notification_insert(
+    sqlite3 *db, const char *account_id, const char *kind, const char *dedupe_key, const char *message, const char *message_keys, const char *message_args) {
+    if (!db || !account_id || account_id[0] == '\0' || !kind || !dedupe_key || !message) return -1;
+
+    const char *sql =
+        "INSERT OR IGNORE INTO notifications (account_id, kind, dedupe_key, message, created_at, message_key, message_args)"
+        " VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'), ?5, ?6)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        log_error("notifications failed to prepare insert: %s", sqlite3_errmsg(db));
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, kind, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, dedupe_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, message, -1, SQLITE_TRANSIENT);
+    if (message_keys) sqlite3_bind_text(stmt, 5, message_keys, -1, SQLITE_TRANSIENT);
+    if (message_args) sqlite3_bind_text(stmt, 6, message_args, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    int inserted = sqlite3_changes(db);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("notifications failed to insert kind=%s account=%s: %s", kind, account_id, sqlite3_errmsg(db));
+        return -1;
+    }
+    if (inserted > 0) {
+        log_info("NOTIFICATION posted kind=%s account=%s key=%s", kind, account_id, dedupe_key);
+    }
+    return inserted > 0 ? 1 : 0;
+}
+
+int notification_post(sqlite3 *db, const char *account_id, const char *kind, const char *dedupe_key, const char *message) {
+    return notification_insert(db, account_id, kind, dedupe_key, message, NULL, NULL);
+}
+
+int notification_post_message(
+    sqlite3 *db, const char *account_id, const char *kind, const char *dedupe_key, const char *message_keys, const i18n_arg_t *args, size_t arg_count) {
+    strbuf_t message;
+    strbuf_t json;
+    strbuf_init(&message);
+    strbuf_init(&json);
+    int rc = -1;
+    if (i18n_format_keys("en", message_keys, args, arg_count, &message) == 0 && i18n_args_json(args, arg_count, &json) == 0) {
+        rc = notification_insert(db, account_id, kind, dedupe_key, strbuf_cstr(&message), message_keys, strbuf_cstr(&json));
+    }
+    strbuf_free(&message);
+    strbuf_free(&json);
+    return rc;
+}
+
+void notification_render(sqlite3 *db, const char *lang, const char *message, const char *message_keys, const char *message_args, strbuf_t *out) {
+    strbuf_t translated;
+    strbuf_init(&translated);
+    if (message_keys && strcmp(lang, "en") != 0 && i18n_format_stored(db, lang, message_keys, message_args, &translated) == 0) {
+        strbuf_append(out, strbuf_cstr(&translated), translated.len);
+    } else if (message) {
+        /* Plain messages, and any that fail to render, keep the stored English text. */
+        strbuf_append(out, message, strlen(message));
+    }
+    strbuf_free(&translated);
+}
+
+int handle_get_notifications(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int limit = NOTIFICATIONS_DEFAULT_LIMIT;
+    char raw_limit[16] = {0};
+    if (query_param(req->query, "limit", raw_limit, sizeof(raw_limit))) {
+        limit = atoi(raw_limit);
+        if (limit <= 0 || limit > NOTIFICATIONS_MAX_LIMIT) limit = NOTIFICATIONS_DEFAULT_LIMIT;
+    }
+    char kind[64] = {0};
+    query_param(req->query, "kind", kind, sizeof(kind));
+
+    const char *sql =
+        "SELECT id, kind, message, created_at, message_key, message_args FROM notifications"
+        " WHERE account_id = ?1 AND (?2 = '' OR kind = ?2)"
+        " ORDER BY created_at DESC, id DESC LIMIT ?3";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, kind, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, limit);
+
+    const char *lang = i18n_negotiate(db->db, req, ctx->account_id);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"notifications\":[", 18);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        strbuf_appendf(&sb, "%s{\"id\":%lld,\"kind\":", count == 0 ? "" : ",", (long long)sqlite3_column_int64(stmt, 0));
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(&sb, ",\"message\":", 11);
+        strbuf_t text;
+        strbuf_init(&text);
+        notification_render(
+            db->db, lang, (const char *)sqlite3_column_text(stmt, 2), (const char *)sqlite3_column_text(stmt, 4), (const char *)sqlite3_column_text(stmt, 5), &text);
+        strbuf_append_json_string(&sb, text.failed ? (const char *)sqlite3_column_text(stmt, 2) : strbuf_cstr(&text));
+        strbuf_free(&text);
+        strbuf_appendf(&sb, ",\"created_at\":%lld}", (long long)sqlite3_column_int64(stmt, 3));
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    char headers[64] = {0};
+    snprintf(headers, sizeof(headers), "Content-Language: %s\r\n", lang);
+    send_http_response(fd, 200, "OK", "application/json", headers, strbuf_cstr(&sb), sb.len, ctx);
+    strbuf_free(&sb);
+    return 200;
+}
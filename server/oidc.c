@@ -0,0 +1,341 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/bn.h>
+#include <openssl/evp.h>
+#if OPENSSL_VERSION_NUMBER >= 0x30000000L
+#include <openssl/core_names.h>
+#include <openssl/param_build.h>
+#else
+#include <openssl/rsa.h>
+#endif
+#endif
+
+/*
+ * OIDC access tokens as an alternative to the static API tokens. With FRICU_OIDC_ISSUER and
+ * FRICU_OIDC_AUDIENCE set, a bearer token shaped like a JWT is checked against the issuer's JWKS
+ * (found through /.well-known/openid-configuration, or FRICU_OIDC_JWKS_URL): RS256 signature, `iss`,
+ * `aud`, `exp` and `nbf` with a minute of clock leeway. The request then runs as the account named
+ * by the `sub` claim (FRICU_OIDC_ACCOUNT_CLAIM picks another one, e.g. `preferred_username`). A claim
+ * made only of [A-Za-z0-9._-] is the account id as is; any other claim (Auth0's "auth0|123") maps to
+ * "oidc_" and the first 40 hex digits of SHA-256(iss "\n" claim), so two claims never share an
+ * account, and plain claims starting with "oidc_" are refused. The JWKS is cached for an hour and refetched
+ * early, at most once a minute, when a token names an unknown `kid` (key rotation).
+ */
+
+#define OIDC_CLOCK_LEEWAY_SEC 60
+#define OIDC_JWKS_TTL_SEC 3600
+#define OIDC_JWKS_RETRY_SEC 60
+#define OIDC_HASHED_PREFIX "oidc_"
+#define OIDC_HASHED_HEX 40
+
+static pthread_mutex_t g_oidc_mutex = PTHREAD_MUTEX_INITIALIZER;
+static char *g_jwks;
+static time_t g_jwks_fetched_at;
+static time_t g_jwks_attempted_at;
+
+int oidc_configured(void) {
+    const char *issuer = getenv("FRICU_OIDC_ISSUER");
+    return issuer && issuer[0] != '\0';
+}
+
+int oidc_token_shaped(const char *token) {
+    const char *first = strchr(token, '.');
+    return first && strchr(first + 1, '.') && !strchr(strchr(first + 1, '.') + 1, '.');
+}
+
+void oidc_reset_cache(void) {
+    pthread_mutex_lock(&g_oidc_mutex);
+    free(g_jwks);
+    g_jwks = NULL;
+    g_jwks_fetched_at = 0;
+    g_jwks_attempted_at = 0;
+    pthread_mutex_unlock(&g_oidc_mutex);
+}
+
+#ifdef FRICU_HAVE_OPENSSL
+static unsigned char *base64url_decode(const char *in, size_t len, size_t *out_len) {
+    char *copy = malloc(len + 1);
+    if (!copy) return NULL;
+    for (size_t i = 0; i < len; i++) copy[i] = in[i] == '-' ? '+' : in[i] == '_' ? '/' : in[i];
+    copy[len] = '\0';
+    unsigned char *out = base64_decode(copy, len, out_len);
+    free(copy);
+    return out;
+}
+
+/* Runs a one-column text query over the JSON bound to ?1; returns a malloc'd copy or NULL. */
+static char *json_query_text(sqlite3 *db, const char *sql, const char *json, int json_len) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return NULL;
+    sqlite3_bind_text(stmt, 1, json, json_len, SQLITE_STATIC);
+    char *value = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) value = strdup((const char *)sqlite3_column_text(stmt, 0));
+    sqlite3_finalize(stmt);
+    return value;
+}
+
+static int fetch_json(const char *url, strbuf_t *out, char *err, size_t err_len) {
+    strbuf_init(out);
+    int status = outbound_get(url, out, err, err_len);
+    if (status == 200 && !out->failed) return 0;
+    if (status > 0) snprintf(err, err_len, "%s answered %d", url, status);
+    strbuf_free(out);
+    return -1;
+}
+
+/* Called with g_oidc_mutex held. */
+static int refresh_jwks(sqlite3 *db, char *err, size_t err_len) {
+    time_t now = time(NULL);
+    if (g_jwks && now - g_jwks_attempted_at < OIDC_JWKS_RETRY_SEC) return 0;
+    if (!g_jwks && g_jwks_attempted_at != 0 && now - g_jwks_attempted_at < OIDC_JWKS_RETRY_SEC / 4) {
+        snprintf(err, err_len, "identity provider keys unavailable");
+        return -1;
+    }
+    g_jwks_attempted_at = now;
+    char jwks_url[1024] = {0};
+    const char *configured = getenv("FRICU_OIDC_JWKS_URL");
+    if (configured && configured[0] != '\0') {
+        snprintf(jwks_url, sizeof(jwks_url), "%s", configured);
+    } else {
+        const char *issuer = getenv("FRICU_OIDC_ISSUER");
+        size_t issuer_len = strlen(issuer);
+        if (issuer_len > 0 && issuer[issuer_len - 1] == '/') issuer_len--;
+        char discovery_url[1024] = {0};
+        snprintf(discovery_url, sizeof(discovery_url), "%.*s/.well-known/openid-configuration", (int)issuer_len, issuer);
+        strbuf_t discovery;
+        if (fetch_json(discovery_url, &discovery, err, err_len) != 0) return -1;
+        char *uri = json_query_text(db, "SELECT json_extract(?1, '$.jwks_uri') WHERE json_valid(?1)", discovery.data, (int)discovery.len);
+        strbuf_free(&discovery);
+        if (!uri) {
+            snprintf(err, err_len, "openid-configuration has no jwks_uri");
+            return -1;
+        }
+        snprintf(jwks_url, sizeof(jwks_url), "%s", uri);
+        free(uri);
+    }
+    strbuf_t jwks;
+    if (fetch_json(jwks_url, &jwks, err, err_len) != 0) return -1;
+    sqlite3_stmt *stmt = NULL;
+    int valid = 0;
+    if (sqlite3_prepare_v2(db, "SELECT json_valid(?1) AND json_type(?1, '$.keys') = 'array'", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, jwks.data, (int)jwks.len, SQLITE_STATIC);
+        valid = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+        sqlite3_finalize(stmt);
+    }
+    if (!valid) {
+        strbuf_free(&jwks);
+        snprintf(err, err_len, "%s is not a JWKS document", jwks_url);
+        return -1;
+    }
+    free(g_jwks);
+    g_jwks = strdup(strbuf_cstr(&jwks));
+    g_jwks_fetched_at = now;
+    strbuf_free(&jwks);
+    log_info("OIDC keys loaded from %s", jwks_url);
+    return g_jwks ? 0 : -1;
+}
+
+static EVP_PKEY *rsa_public_key(const unsigned char *n, size_t n_len, const unsigned char *e, size_t e_len) {
+    BIGNUM *bn_n = BN_bin2bn(n, (int)n_len, NULL);
+    BIGNUM *bn_e = BN_bin2bn(e, (int)e_len, NULL);
+    EVP_PKEY *pkey = NULL;
+#if OPENSSL_VERSION_NUMBER >= 0x30000000L
+    OSSL_PARAM_BLD *bld = OSSL_PARAM_BLD_new();
+    OSSL_PARAM *params = NULL;
+    EVP_PKEY_CTX *ctx = EVP_PKEY_CTX_new_from_name(NULL, "RSA", NULL);
+    if (bn_n && bn_e && bld && ctx && OSSL_PARAM_BLD_push_BN(bld, OSSL_PKEY_PARAM_RSA_N, bn_n) == 1 &&
+        OSSL_PARAM_BLD_push_BN(bld, OSSL_PKEY_PARAM_RSA_E, bn_e) == 1 && (params = OSSL_PARAM_BLD_to_param(bld)) != NULL &&
+        EVP_PKEY_fromdata_init(ctx) == 1 && EVP_PKEY_fromdata(ctx, &pkey, EVP_PKEY_PUBLIC_KEY, params) != 1) {
+        pkey = NULL;
+    }
+    OSSL_PARAM_free(params);
+    OSSL_PARAM_BLD_free(bld);
+    EVP_PKEY_CTX_free(ctx);
+#else
+    RSA *rsa = RSA_new();
+    if (rsa && bn_n && bn_e && RSA_set0_key(rsa, bn_n, bn_e, NULL) == 1) {
+        bn_n = bn_e = NULL;
+        pkey = EVP_PKEY_new();
+        if (pkey && EVP_PKEY_assign_RSA(pkey, rsa) == 1) {
+            rsa = NULL;
+        } else {
+            EVP_PKEY_free(pkey);
+            pkey = NULL;
+        }
+    }
+    RSA_free(rsa);
+#endif
+    BN_free(bn_n);
+    BN_free(bn_e);
+    return pkey;
+}
+
+/* Finds the signing key for kid in the cached JWKS; called with g_oidc_mutex held. */
+static EVP_PKEY *jwks_key(sqlite3 *db, const char *kid) {
+    if (!g_jwks) return NULL;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(value, '$.n'), json_extract(value, '$.e') FROM json_each(?1, '$.keys')"
+            " WHERE json_extract(value, '$.kty') = 'RSA' AND COALESCE(json_extract(value, '$.use'), 'sig') = 'sig'"
+            " AND (?2 = '' OR json_extract(value, '$.kid') = ?2) LIMIT 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, g_jwks, -1, SQLITE_STATIC);
+    sqlite3_bind_text(stmt, 2, kid, -1, SQLITE_STATIC);
+    EVP_PKEY *pkey = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0) && sqlite3_column_text(stmt, 1)) {
+        const char *n64 = (const char *)sqlite3_column_text(stmt, 0);
+        const char *e64 = (const char *)sqlite3_column_text(stmt, 1);
+        size_t n_len = 0, e_len = 0;
+        unsigned char *n = base64url_decode(n64, strlen(n64), &n_len);
+        unsigned char *e = base64url_decode(e64, strlen(e64), &e_len);
+        if (n && e) pkey = rsa_public_key(n, n_len, e, e_len);
+        free(n);
+        free(e);
+    }
+    sqlite3_finalize(stmt);
+    return pkey;
+}
+
+/* 1 when the claims are acceptable, with the account copied out; 0 with a reason otherwise. */
+static int check_claims(sqlite3 *db, const char *claims, size_t claims_len, char *out_account, size_t out_len, const char **reason) {
+    const char *issuer = getenv("FRICU_OIDC_ISSUER");
+    const char *audience = getenv("FRICU_OIDC_AUDIENCE");
+    const char *claim = getenv("FRICU_OIDC_ACCOUNT_CLAIM");
+    if (!claim || claim[0] == '\0') claim = "sub";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(?1, '$.iss'), EXISTS (SELECT 1 FROM json_each(?1, '$.aud') WHERE value = ?2),"
+            " json_extract(?1, '$.exp'), json_extract(?1, '$.nbf'), json_extract(?1, '$.\"' || ?3 || '\"')"
+            " WHERE json_valid(?1) AND json_type(?1) = 'object'",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        *reason = "unreadable claims";
+        return 0;
+    }
+    sqlite3_bind_text(stmt, 1, claims, (int)claims_len, SQLITE_STATIC);
+    sqlite3_bind_text(stmt, 2, audience, -1, SQLITE_STATIC);
+    sqlite3_bind_text(stmt, 3, claim, -1, SQLITE_STATIC);
+    long long now = (long long)time(NULL);
+    int ok = 0;
+    *reason = "unreadable claims";
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *iss = (const char *)sqlite3_column_text(stmt, 0);
+        const char *account = (const char *)sqlite3_column_text(stmt, 4);
+        if (!iss || strcmp(iss, issuer) != 0) {
+            *reason = "wrong issuer";
+        } else if (!sqlite3_column_int(stmt, 1)) {
+            *reason = "wrong audience";
+        } else if (sqlite3_column_type(stmt, 2) == SQLITE_NULL || sqlite3_column_int64(stmt, 2) + OIDC_CLOCK_LEEWAY_SEC < now) {
+            *reason = "token expired";
+        } else if (sqlite3_column_type(stmt, 3) != SQLITE_NULL && sqlite3_column_int64(stmt, 3) - OIDC_CLOCK_LEEWAY_SEC > now) {
+            *reason = "token not yet valid";
+        } else if (!account || account[0] == '\0') {
+            *reason = "missing account claim";
+        } else if (api_account_id_valid(account) && strncmp(account, OIDC_HASHED_PREFIX, strlen(OIDC_HASHED_PREFIX)) == 0) {
+            *reason = "reserved account claim";
+        } else if (api_account_id_valid(account)) {
+            ok = snprintf(out_account, out_len, "%s", account) < (int)out_len;
+            if (!ok) *reason = "missing account claim";
+        } else {
+            strbuf_t exact;
+            strbuf_init(&exact);
+            strbuf_appendf(&exact, "%s\n%s", iss, account);
+            char hash[65] = {0};
+            sha256_hex(strbuf_cstr(&exact), exact.len, hash, sizeof(hash));
+            ok = !exact.failed && snprintf(out_account, out_len, "%s%.*s", OIDC_HASHED_PREFIX, OIDC_HASHED_HEX, hash) < (int)out_len;
+            strbuf_free(&exact);
+            if (!ok) *reason = "unreadable claims";
+        }
+    }
+    sqlite3_finalize(stmt);
+    return ok;
+}
+#endif
+
+int oidc_verify_token(sqlite3 *db, const char *token, char *out_account, size_t out_len, char *err, size_t err_len) {
+    const char *audience = getenv("FRICU_OIDC_AUDIENCE");
+    if (!audience || audience[0] == '\0') {
+        snprintf(err, err_len, "FRICU_OIDC_AUDIENCE is not set");
+        return -1;
+    }
+#ifdef FRICU_HAVE_OPENSSL
+    const char *dot1 = strchr(token, '.');
+    const char *dot2 = dot1 ? strchr(dot1 + 1, '.') : NULL;
+    if (!dot2) {
+        snprintf(err, err_len, "malformed token");
+        return 0;
+    }
+    size_t header_len = 0, claims_len = 0, sig_len = 0;
+    unsigned char *header = base64url_decode(token, (size_t)(dot1 - token), &header_len);
+    unsigned char *claims = base64url_decode(dot1 + 1, (size_t)(dot2 - dot1 - 1), &claims_len);
+    unsigned char *sig = base64url_decode(dot2 + 1, strlen(dot2 + 1), &sig_len);
+    char *alg = header ? json_query_text(db, "SELECT json_extract(?1, '$.alg') WHERE json_valid(?1)", (const char *)header, (int)header_len) : NULL;
+    char *kid = header ? json_query_text(db, "SELECT COALESCE(json_extract(?1, '$.kid'), '') WHERE json_valid(?1)", (const char *)header, (int)header_len) : NULL;
+    int rc = 0;
+    if (!header || !claims || !sig || !alg || !kid) {
+        snprintf(err, err_len, "malformed token");
+    } else if (strcmp(alg, "RS256") != 0) {
+        snprintf(err, err_len, "unsupported alg");
+    } else {
+        pthread_mutex_lock(&g_oidc_mutex);
+        /* A failed refresh keeps serving the previous keys. */
+        if (!g_jwks || time(NULL) - g_jwks_fetched_at > OIDC_JWKS_TTL_SEC) refresh_jwks(db, err, err_len);
+        EVP_PKEY *pkey = jwks_key(db, kid);
+        if (!pkey && g_jwks && refresh_jwks(db, err, err_len) == 0) pkey = jwks_key(db, kid);
+        int have_keys = g_jwks != NULL;
+        pthread_mutex_unlock(&g_oidc_mutex);
+        if (!pkey && !have_keys) {
+            if (err[0] == '\0') snprintf(err, err_len, "identity provider keys unavailable");
+            rc = -1;
+        } else if (!pkey) {
+            snprintf(err, err_len, "unknown signing key");
+        } else {
+            EVP_MD_CTX *md = EVP_MD_CTX_new();
+            int verified = md && EVP_DigestVerifyInit(md, NULL, EVP_sha256(), NULL, pkey) == 1 &&
+                           EVP_DigestVerify(md, sig, sig_len, (const unsigned char *)token, (size_t)(dot2 - token)) == 1;
+            EVP_MD_CTX_free(md);
+            EVP_PKEY_free(pkey);
+            const char *reason = NULL;
+            if (!verified) {
+                snprintf(err, err_len, "bad signature");
+            } else if (!check_claims(db, (const char *)claims, claims_len, out_account, out_len, &reason)) {
+                snprintf(err, err_len, "%s", reason);
+            } else {
+                rc = 1;
+            }
+        }
+    }
+    free(header);
+    free(claims);
+    free(sig);
+    free(alg);
+    free(kid);
+    return rc;
+#else
+    (void)db;
+    (void)token;
+    (void)out_account;
+    (void)out_len;
+    snprintf(err, err_len, "OIDC needs a server built with OpenSSL");
+    return -1;
+#endif
+}
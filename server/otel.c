@@ -0,0 +1,322 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * OpenTelemetry traces over OTLP/HTTP (JSON encoding, which Jaeger, Tempo and the Collector all
+ * accept on :4318). With FRICU_OTLP_ENDPOINT set, every request becomes a SERVER span named after
+ * its route, a child of the caller's W3C traceparent when one was sent (and sampled) and a new
+ * root otherwise, kept at FRICU_OTLP_SAMPLE_RATIO (0..1, default 1). Reads and writes of a data
+ * document add an INTERNAL db.read / db.write child span, the write spanning the wait for the
+ * write dispatcher thread and the transaction it runs, with the data key and the row size in bytes
+ * as attributes. Spans are batched by one exporter thread and POSTed to <endpoint>/v1/traces;
+ * FRICU_OTLP_HEADERS ("name=value,...", resolved as a secret) adds e.g. an Authorization header.
+ * A collector that is down costs dropped spans, never request latency: at most
+ * OTEL_MAX_PENDING_SPANS wait for export.
+ */
+
+#define OTEL_DEFAULT_SERVICE "fricu-server"
+#define OTEL_EXPORT_INTERVAL_MS 1000
+#define OTEL_BATCH_SPANS 256
+#define OTEL_MAX_PENDING_SPANS 4096
+#define OTEL_KIND_INTERNAL 1
+#define OTEL_KIND_SERVER 2
+
+typedef struct {
+    int active;
+    char trace_id[33];
+    char span_id[17];
+    char parent_id[17];
+    long long start_ns;
+    int status;
+    size_t response_bytes;
+    char data_key[64];
+    size_t row_bytes;
+    int db_spans;
+    strbuf_t children;
+} otel_request_t;
+
+static pthread_mutex_t g_otel_mutex = PTHREAD_MUTEX_INITIALIZER;
+static pthread_cond_t g_otel_cond = PTHREAD_COND_INITIALIZER;
+static strbuf_t g_pending;
+static size_t g_pending_spans;
+static int g_exporting;
+static int g_thread_started;
+static long long g_dropped_spans;
+
+static __thread otel_request_t g_span;
+
+static long long wall_clock_ns(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_REALTIME, &ts);
+    return (long long)ts.tv_sec * 1000000000LL + ts.tv_nsec;
+}
+
+static const char *otel_endpoint(void) {
+    const char *endpoint = getenv("FRICU_OTLP_ENDPOINT");
+    return endpoint && endpoint[0] != '\0' ? endpoint : NULL;
+}
+
+static void random_hex_id(char *out, size_t bytes) {
+    unsigned char raw[16] = {0};
+    if (fill_random_bytes(raw, bytes) != 0) {
+        long long now = wall_clock_ns();
+        memcpy(raw, &now, sizeof(now));
+    }
+    for (size_t i = 0; i < bytes; i++) snprintf(out + i * 2, 3, "%02x", raw[i]);
+}
+
+static int is_valid_hex_id(const char *s, size_t len) {
+    int nonzero = 0;
+    for (size_t i = 0; i < len; i++) {
+        if (!((s[i] >= '0' && s[i] <= '9') || (s[i] >= 'a' && s[i] <= 'f'))) return 0;
+        if (s[i] != '0') nonzero = 1;
+    }
+    return nonzero;
+}
+
+/* 1 sampled, 0 not sampled, -1 no usable traceparent (the sampling ratio decides). */
+static int inherit_traceparent(const http_request_t *req) {
+    char value[128] = {0};
+    if (!http_request_header(req, "traceparent", value, sizeof(value)) || strlen(value) < 55) return -1;
+    if (value[2] != '-' || value[35] != '-' || value[52] != '-' || strncmp(value, "ff", 2) == 0) return -1;
+    if (!is_valid_hex_id(value + 3, 32) || !is_valid_hex_id(value + 36, 16)) return -1;
+    memcpy(g_span.trace_id, value + 3, 32);
+    g_span.trace_id[32] = '\0';
+    memcpy(g_span.parent_id, value + 36, 16);
+    g_span.parent_id[16] = '\0';
+    return (strtol(value + 53, NULL, 16) & 1) != 0;
+}
+
+static int sample_new_trace(void) {
+    const char *ratio_env = getenv("FRICU_OTLP_SAMPLE_RATIO");
+    if (!ratio_env || ratio_env[0] == '\0') return 1;
+    double ratio = atof(ratio_env);
+    if (ratio >= 1.0) return 1;
+    if (ratio <= 0.0) return 0;
+    unsigned int draw = 0;
+    if (fill_random_bytes(&draw, sizeof(draw)) != 0) return 1;
+    return (double)draw / 4294967296.0 < ratio;
+}
+
+void otel_request_begin(const http_request_t *req) {
+    strbuf_free(&g_span.children);
+    memset(&g_span, 0, sizeof(g_span));
+    if (!otel_endpoint()) return;
+    int sampled = inherit_traceparent(req);
+    if (sampled < 0) {
+        g_span.trace_id[0] = '\0';
+        g_span.parent_id[0] = '\0';
+        sampled = sample_new_trace();
+        random_hex_id(g_span.trace_id, 16);
+    }
+    if (!sampled) return;
+    random_hex_id(g_span.span_id, 8);
+    g_span.start_ns = wall_clock_ns();
+    strbuf_init(&g_span.children);
+    g_span.active = 1;
+}
+
+long long otel_span_start(void) {
+    return g_span.active ? wall_clock_ns() : 0;
+}
+
+static void append_string_attribute(strbuf_t *sb, const char *key, const char *value) {
+    strbuf_appendf(sb, ",{\"key\":\"%s\",\"value\":{\"stringValue\":", key);
+    strbuf_append_json_string(sb, value);
+    strbuf_append(sb, "}}", 2);
+}
+
+static void append_int_attribute(strbuf_t *sb, const char *key, long long value) {
+    strbuf_appendf(sb, ",{\"key\":\"%s\",\"value\":{\"intValue\":\"%lld\"}}", key, value);
+}
+
+/* Opens a span object up to its attribute list; the caller writes the first attribute without a leading comma. */
+static void append_span_head(strbuf_t *sb, const char *name, const char *span_id, const char *parent_id, int kind, long long start_ns, long long end_ns) {
+    strbuf_appendf(sb, "{\"traceId\":\"%s\",\"spanId\":\"%s\",", g_span.trace_id, span_id);
+    if (parent_id[0] != '\0') strbuf_appendf(sb, "\"parentSpanId\":\"%s\",", parent_id);
+    strbuf_append(sb, "\"name\":", 7);
+    strbuf_append_json_string(sb, name);
+    strbuf_appendf(
+        sb, ",\"kind\":%d,\"startTimeUnixNano\":\"%lld\",\"endTimeUnixNano\":\"%lld\",\"attributes\":[", kind, start_ns, end_ns);
+}
+
+void otel_note_db(const char *operation, const char *key, size_t row_bytes, long long started_ns, double queue_ms) {
+    if (!g_span.active || started_ns <= 0) return;
+    char span_id[17] = {0};
+    char name[96] = {0};
+    random_hex_id(span_id, 8);
+    snprintf(name, sizeof(name), "db.%s %s", operation, key);
+    strbuf_t *sb = &g_span.children;
+    strbuf_append(sb, ",", 1);
+    append_span_head(sb, name, span_id, g_span.span_id, OTEL_KIND_INTERNAL, started_ns, wall_clock_ns());
+    strbuf_append(sb, "{\"key\":\"db.system\",\"value\":{\"stringValue\":\"sqlite\"}}", 52);
+    append_string_attribute(sb, "db.operation.name", operation);
+    append_string_attribute(sb, "fricu.data_key", key);
+    append_int_attribute(sb, "fricu.row_bytes", (long long)row_bytes);
+    if (queue_ms >= 0) strbuf_appendf(sb, ",{\"key\":\"fricu.write_queue_ms\",\"value\":{\"doubleValue\":%.3f}}", queue_ms);
+    strbuf_append(sb, "]}", 2);
+    snprintf(g_span.data_key, sizeof(g_span.data_key), "%s", key);
+    g_span.row_bytes += row_bytes;
+    g_span.db_spans++;
+}
+
+void otel_note_response(int code, size_t body_len) {
+    if (!g_span.active) return;
+    g_span.status = code;
+    g_span.response_bytes = body_len;
+}
+
+static void *otel_export_thread(void *arg);
+
+void otel_request_end(const http_request_t *req, const request_log_context_t *ctx) {
+    if (!g_span.active) return;
+    g_span.active = 0;
+    char label[160] = {0};
+    slowlog_endpoint_label(req->method, req->path, label, sizeof(label));
+    const char *route = strchr(label, ' ');
+    if (g_span.data_key[0] == '\0') request_data_key(req->path, g_span.data_key, sizeof(g_span.data_key));
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_span_head(&sb, label, g_span.span_id, g_span.parent_id, OTEL_KIND_SERVER, g_span.start_ns, wall_clock_ns());
+    strbuf_append(&sb, "{\"key\":\"http.request.method\",\"value\":{\"stringValue\":", 52);
+    strbuf_append_json_string(&sb, req->method);
+    strbuf_append(&sb, "}}", 2);
+    append_string_attribute(&sb, "url.path", req->path);
+    append_string_attribute(&sb, "http.route", route ? route + 1 : req->path);
+    append_int_attribute(&sb, "http.response.status_code", g_span.status);
+    append_int_attribute(&sb, "http.request.body.size", (long long)req->body_len);
+    append_int_attribute(&sb, "http.response.body.size", (long long)g_span.response_bytes);
+    append_string_attribute(&sb, "fricu.log_id", ctx->log_id);
+    if (g_span.data_key[0] != '\0') append_string_attribute(&sb, "fricu.data_key", g_span.data_key);
+    if (g_span.db_spans > 0) append_int_attribute(&sb, "fricu.row_bytes", (long long)g_span.row_bytes);
+    strbuf_append(&sb, "]", 1);
+    if (g_span.status >= 500) strbuf_append(&sb, ",\"status\":{\"code\":2}", 20);
+    strbuf_append(&sb, "}", 1);
+    if (g_span.children.len > 0) strbuf_append(&sb, strbuf_cstr(&g_span.children), g_span.children.len);
+    size_t spans = 1 + (size_t)g_span.db_spans;
+    strbuf_free(&g_span.children);
+
+    pthread_mutex_lock(&g_otel_mutex);
+    if (sb.failed || g_pending_spans + spans > OTEL_MAX_PENDING_SPANS) {
+        g_dropped_spans += (long long)spans;
+    } else {
+        if (g_pending_spans > 0) strbuf_append(&g_pending, ",", 1);
+        strbuf_append(&g_pending, strbuf_cstr(&sb), sb.len);
+        g_pending_spans += spans;
+        if (!g_thread_started) {
+            pthread_t thread;
+            g_thread_started = pthread_create(&thread, NULL, otel_export_thread, NULL) == 0;
+            if (g_thread_started) pthread_detach(thread);
+        }
+        if (g_pending_spans >= OTEL_BATCH_SPANS) pthread_cond_signal(&g_otel_cond);
+    }
+    pthread_mutex_unlock(&g_otel_mutex);
+    strbuf_free(&sb);
+}
+
+/* <endpoint>/v1/traces, unless the endpoint already names the traces path. */
+static void traces_url(const char *endpoint, char *out, size_t out_len) {
+    size_t len = strlen(endpoint);
+    while (len > 0 && endpoint[len - 1] == '/') len--;
+    if (len >= 10 && strncmp(endpoint + len - 10, "/v1/traces", 10) == 0) {
+        snprintf(out, out_len, "%.*s", (int)len, endpoint);
+    } else {
+        snprintf(out, out_len, "%.*s/v1/traces", (int)len, endpoint);
+    }
+}
+
+/* "name=value,name=value" (the OTEL_EXPORTER_OTLP_HEADERS format) as HTTP header lines. */
+static void append_export_headers(strbuf_t *sb, const char *list) {
+    const char *p = list;
+    while (p && *p) {
+        p += strspn(p, " ,");
+        size_t len = strcspn(p, ",");
+        const char *eq = memchr(p, '=', len);
+        if (eq && eq > p) {
+            strbuf_appendf(sb, "%.*s: %.*s\r\n", (int)(eq - p), p, (int)(len - (size_t)(eq - p) - 1), eq + 1);
+        }
+        p += len;
+    }
+}
+
+static int export_spans(const char *spans, size_t span_count) {
+    const char *endpoint = otel_endpoint();
+    if (!endpoint) return 0;
+    const char *service = getenv("FRICU_OTLP_SERVICE_NAME");
+    strbuf_t body;
+    strbuf_init(&body);
+    strbuf_append(&body, "{\"resourceSpans\":[{\"resource\":{\"attributes\":[{\"key\":\"service.name\",\"value\":{\"stringValue\":", 90);
+    strbuf_append_json_string(&body, service && service[0] != '\0' ? service : OTEL_DEFAULT_SERVICE);
+    strbuf_append(&body, "}}]},\"scopeSpans\":[{\"scope\":{\"name\":\"fricu-server\"},\"spans\":[", 61);
+    strbuf_append(&body, spans, strlen(spans));
+    strbuf_append(&body, "]}]}]}", 6);
+
+    strbuf_t headers;
+    strbuf_init(&headers);
+    strbuf_append(&headers, "Content-Type: application/json\r\n", 32);
+    append_export_headers(&headers, config_secret("FRICU_OTLP_HEADERS"));
+    char url[512] = {0};
+    traces_url(endpoint, url, sizeof(url));
+    char err[256] = {0};
+    int status = -1;
+    if (!body.failed && !headers.failed) {
+        status = outbound_request("POST", url, strbuf_cstr(&headers), (const unsigned char *)strbuf_cstr(&body), body.len, err, sizeof(err));
+    }
+    if (status < 200 || status >= 300) log_warn("OTLP export failed url=%s status=%d spans=%zu error=%s", url, status, span_count, err[0] ? err : "-");
+    strbuf_free(&headers);
+    strbuf_free(&body);
+    return status >= 200 && status < 300 ? 0 : -1;
+}
+
+/* Takes the pending batch and exports it; called with g_otel_mutex held, returns with it held. */
+static int export_pending_locked(void) {
+    if (g_pending_spans == 0) return 0;
+    strbuf_t batch = g_pending;
+    size_t span_count = g_pending_spans;
+    strbuf_init(&g_pending);
+    g_pending_spans = 0;
+    g_exporting = 1;
+    pthread_mutex_unlock(&g_otel_mutex);
+    int rc = batch.failed ? -1 : export_spans(strbuf_cstr(&batch), span_count);
+    strbuf_free(&batch);
+    pthread_mutex_lock(&g_otel_mutex);
+    g_exporting = 0;
+    pthread_cond_broadcast(&g_otel_cond);
+    return rc;
+}
+
+static void *otel_export_thread(void *arg) {
+    (void)arg;
+    pthread_mutex_lock(&g_otel_mutex);
+    for (;;) {
+        struct timespec deadline;
+        clock_gettime(CLOCK_REALTIME, &deadline);
+        deadline.tv_sec += OTEL_EXPORT_INTERVAL_MS / 1000;
+        while (g_pending_spans < OTEL_BATCH_SPANS && pthread_cond_timedwait(&g_otel_cond, &g_otel_mutex, &deadline) == 0) {
+        }
+        if (!g_exporting) export_pending_locked();
+        if (g_dropped_spans > 0) {
+            log_warn("OTLP exporter dropped %lld spans (queue full)", g_dropped_spans);
+            g_dropped_spans = 0;
+        }
+    }
+    return NULL;
+}
+
+int otel_flush(void) {
+    pthread_mutex_lock(&g_otel_mutex);
+    while (g_exporting) pthread_cond_wait(&g_otel_cond, &g_otel_mutex);
+    int rc = export_pending_locked();
+    pthread_mutex_unlock(&g_otel_mutex);
+    return rc;
+}
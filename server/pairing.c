@@ -0,0 +1,368 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <time.h>
+
+/*
+ * Pairing a new device without copying tokens around. POST /v1/admin/pairing (X-Admin-Token)
+ * creates a one-time pairing code for an account, valid for ttl_seconds (default 600, at most
+ * 3600), and returns it as text ("K7QF-9MXD-2HRT", for typing on a head unit), as a link
+ * (fricu://pair?server=<url>&code=<code>) and as that link drawn as a QR code (qr_svg). The
+ * device sends the code to POST /v1/pair, which needs no other credential, and gets a long-lived
+ * token for the account in exchange: an API bearer token for scope "api" (a phone) or an
+ * X-Device-Token for scope "device" (a head-unit bridge that only reads /v1/today/workout).
+ * A code works once; only its SHA-256 is stored; unknown, used and expired codes all answer the
+ * same 401. The server URL in the link is the request's "url", else FRICU_PUBLIC_URL, else the
+ * scheme and Host header the admin request came in on.
+ */
+
+#define PAIRING_DEFAULT_TTL 600
+#define PAIRING_MAX_TTL 3600
+#define PAIRING_MIN_TTL 30
+#define PAIRING_CODE_CHARS 12
+#define PAIRING_NAME_MAX 64
+
+/* Crockford base32: no I, L, O or U, so a code read aloud or off a small screen stays unambiguous. */
+static const char CODE_ALPHABET[] = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+static int generate_code(char *out, size_t out_len) {
+    unsigned char raw[PAIRING_CODE_CHARS];
+    if (out_len < PAIRING_CODE_CHARS + 3 || fill_random_bytes(raw, sizeof(raw)) != 0) return -1;
+    size_t o = 0;
+    for (size_t i = 0; i < PAIRING_CODE_CHARS; i++) {
+        if (i > 0 && i % 4 == 0) out[o++] = '-';
+        out[o++] = CODE_ALPHABET[raw[i] & 0x1F];
+    }
+    out[o] = '\0';
+    return 0;
+}
+
+/* Uppercases, drops separators and maps the look-alikes Crockford allows (O->0, I/L->1). */
+static int normalize_code(const char *in, char *out, size_t out_len) {
+    size_t o = 0;
+    for (const char *p = in; *p; p++) {
+        char ch = (char)toupper((unsigned char)*p);
+        if (ch == '-' || ch == ' ') continue;
+        if (ch == 'O') ch = '0';
+        if (ch == 'I' || ch == 'L') ch = '1';
+        if (!strchr(CODE_ALPHABET, ch) || o + 1 >= out_len) return -1;
+        out[o++] = ch;
+    }
+    out[o] = '\0';
+    return o == PAIRING_CODE_CHARS ? 0 : -1;
+}
+
+static void code_hash(const char *code, char *out, size_t out_len) {
+    char normalized[PAIRING_CODE_CHARS + 1] = {0};
+    if (normalize_code(code, normalized, sizeof(normalized)) != 0) normalized[0] = '\0';
+    sha256_hex(normalized, strlen(normalized), out, out_len);
+}
+
+static void append_url_component(strbuf_t *sb, const char *s) {
+    for (const unsigned char *p = (const unsigned char *)s; *p; p++) {
+        if (isalnum(*p) || *p == '-' || *p == '.' || *p == '_' || *p == '~') {
+            strbuf_append(sb, (const char *)p, 1);
+        } else {
+            strbuf_appendf(sb, "%%%02X", *p);
+        }
+    }
+}
+
+static int server_url_valid(const char *url) {
+    if (strncmp(url, "http://", 7) != 0 && strncmp(url, "https://", 8) != 0) return 0;
+    for (const char *p = url; *p; p++) {
+        if ((unsigned char)*p <= ' ' || *p == '"' || *p == '<' || *p == '>') return 0;
+    }
+    return strlen(url) < 256;
+}
+
+static void resolve_server_url(const http_request_t *req, const char *requested, char *out, size_t out_len) {
+    const char *configured = getenv("FRICU_PUBLIC_URL");
+    char host[256] = {0};
+    if (requested[0] != '\0') {
+        snprintf(out, out_len, "%s", requested);
+    } else if (configured && configured[0] != '\0') {
+        snprintf(out, out_len, "%s", configured);
+    } else if (http_request_header(req, "Host", host, sizeof(host)) && host[0] != '\0') {
+        snprintf(out, out_len, "%s://%s", tls_enabled() ? "https" : "http", host);
+    } else {
+        out[0] = '\0';
+    }
+    size_t len = strlen(out);
+    while (len > 0 && out[len - 1] == '/') out[--len] = '\0';
+}
+
+static int handle_post_pairing(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT json_valid(?1), json_extract(?1, '$.account'), coalesce(json_extract(?1, '$.scope'), 'api'),"
+            " json_extract(?1, '$.name'), coalesce(json_extract(?1, '$.ttl_seconds'), ?2), coalesce(json_extract(?1, '$.url'), '')",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, PAIRING_DEFAULT_TTL);
+    char account_id[128] = {0};
+    char scope[16] = {0};
+    char name[PAIRING_NAME_MAX + 1] = {0};
+    char requested_url[512] = {0};
+    long long ttl = 0;
+    int valid = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        const char *raw_account = (const char *)sqlite3_column_text(stmt, 1);
+        const char *raw_name = (const char *)sqlite3_column_text(stmt, 3);
+        if (raw_account) snprintf(account_id, sizeof(account_id), "%s", raw_account);
+        snprintf(scope, sizeof(scope), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        if (raw_name && raw_name[0] != '\0') snprintf(name, sizeof(name), "%s", raw_name);
+        ttl = sqlite3_column_int64(stmt, 4);
+        snprintf(requested_url, sizeof(requested_url), "%s", (const char *)sqlite3_column_text(stmt, 5));
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+        return 400;
+    }
+    if (!api_account_id_valid(account_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"account is required\"}", ctx);
+        return 400;
+    }
+    int device_scope = strcmp(scope, "device") == 0;
+    if (!device_scope && strcmp(scope, "api") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"scope must be api or device\"}", ctx);
+        return 400;
+    }
+    if (ttl < PAIRING_MIN_TTL || ttl > PAIRING_MAX_TTL) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"ttl_seconds must be between 30 and 3600\"}", ctx);
+        return 400;
+    }
+    if (name[0] == '\0') snprintf(name, sizeof(name), "%s", device_scope ? "head unit" : "paired device");
+    char server_url[256] = {0};
+    resolve_server_url(req, requested_url, server_url, sizeof(server_url));
+    if (!server_url_valid(server_url)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"url must be an http(s) server URL\"}", ctx);
+        return 400;
+    }
+
+    char code[PAIRING_CODE_CHARS + 4] = {0};
+    char hash[65] = {0};
+    char pairing_id[24] = {0};
+    if (generate_code(code, sizeof(code)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        return 500;
+    }
+    code_hash(code, hash, sizeof(hash));
+    /* Random rather than derived from the hash, so ids in logs say nothing about the code. */
+    unsigned char id_bytes[6];
+    if (fill_random_bytes(id_bytes, sizeof(id_bytes)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"token generation failed\"}", ctx);
+        return 500;
+    }
+    snprintf(pairing_id, sizeof(pairing_id), "pair_");
+    for (size_t i = 0; i < sizeof(id_bytes); i++) {
+        snprintf(pairing_id + 5 + i * 2, 3, "%02x", id_bytes[i]);
+    }
+    long long now = (long long)time(NULL);
+    long long expires_at = now + ttl;
+    sqlite3_exec(db->db, "DELETE FROM pairing_codes WHERE expires_at < strftime('%s', 'now')", NULL, NULL, NULL);
+    if (sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO pairing_codes (code_hash, pairing_id, account_id, scope, name, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, pairing_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, scope, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 5, name, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 6, now);
+    sqlite3_bind_int64(stmt, 7, expires_at);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+
+    strbuf_t link;
+    strbuf_init(&link);
+    strbuf_append(&link, "fricu://pair?server=", 20);
+    append_url_component(&link, server_url);
+    strbuf_appendf(&link, "&code=%s", code);
+    qr_code_t qr;
+    strbuf_t svg;
+    strbuf_init(&svg);
+    if (!link.failed && qr_encode(strbuf_cstr(&link), link.len, &qr) == 0) qr_render_svg(&qr, &svg);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"id\":\"%s\",\"code\":\"%s\",\"account\":", pairing_id, code);
+    strbuf_append_json_string(&sb, account_id);
+    strbuf_appendf(&sb, ",\"scope\":\"%s\",\"name\":", scope);
+    strbuf_append_json_string(&sb, name);
+    strbuf_appendf(&sb, ",\"expires_at\":%lld,\"server\":", expires_at);
+    strbuf_append_json_string(&sb, server_url);
+    strbuf_append(&sb, ",\"pair_url\":", 12);
+    strbuf_append_json_string(&sb, strbuf_cstr(&link));
+    strbuf_append(&sb, ",\"qr_svg\":", 10);
+    strbuf_append_json_string(&sb, strbuf_cstr(&svg));
+    strbuf_append(&sb, "}", 1);
+    int failed = sb.failed || link.failed || svg.failed || svg.len == 0;
+    strbuf_free(&link);
+    strbuf_free(&svg);
+    if (failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    log_info("PAIR code created id=%s account=%s scope=%s ttl=%lld", pairing_id, account_id, scope, ttl);
+    send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 201;
+}
+
+int route_admin_pairing(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    if (!cluster_is_writer()) return cluster_reject_write(fd, ctx);
+    return handle_post_pairing(fd, db, req, ctx);
+}
+
+/* Claims the code and issues the token in one transaction, so a failed issue leaves the code usable. */
+static int redeem_code(
+    sqlite3 *db, const char *hash, const char *device_name, char *account_id, size_t account_len, char *scope, size_t scope_len, char *name,
+    size_t name_len, char *token, size_t token_len, char *token_id, size_t token_id_len) {
+    if (sqlite3_exec(db, "BEGIN IMMEDIATE", NULL, NULL, NULL) != SQLITE_OK) return -1;
+    sqlite3_stmt *stmt = NULL;
+    int rc = -1;
+    long long now = (long long)time(NULL);
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT account_id, scope, name FROM pairing_codes WHERE code_hash = ?1 AND used_at IS NULL AND expires_at >= ?2",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 2, now);
+        int step = sqlite3_step(stmt);
+        if (step == SQLITE_ROW) {
+            snprintf(account_id, account_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+            snprintf(scope, scope_len, "%s", (const char *)sqlite3_column_text(stmt, 1));
+            snprintf(name, name_len, "%s", device_name[0] != '\0' ? device_name : (const char *)sqlite3_column_text(stmt, 2));
+            rc = 1;
+        } else if (step == SQLITE_DONE) {
+            rc = 0;
+        }
+        sqlite3_finalize(stmt);
+    }
+    if (rc == 1 && sqlite3_prepare_v2(db, "UPDATE pairing_codes SET used_at = ?2 WHERE code_hash = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int64(stmt, 2, now);
+        if (sqlite3_step(stmt) != SQLITE_DONE) rc = -1;
+        sqlite3_finalize(stmt);
+    } else if (rc == 1) {
+        rc = -1;
+    }
+    if (rc == 1) {
+        int issued = strcmp(scope, "device") == 0 ? device_token_issue(db, account_id, name, token, token_len)
+                                                  : api_token_issue(db, account_id, name, token, token_len, token_id, token_id_len);
+        if (issued != 0) rc = -1;
+    }
+    if (rc == 1 && sqlite3_exec(db, "COMMIT", NULL, NULL, NULL) == SQLITE_OK) return 1;
+    sqlite3_exec(db, "ROLLBACK", NULL, NULL, NULL);
+    return rc == 1 ? -1 : rc;
+}
+
+int handle_post_pair(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx) {
+    if (strcmp(req->method, "POST") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    if (!cluster_is_writer()) return cluster_reject_write(fd, ctx);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1), json_extract(?1, '$.code'), json_extract(?1, '$.name')", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char code[64] = {0};
+    char device_name[PAIRING_NAME_MAX + 1] = {0};
+    int valid = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        valid = sqlite3_column_int(stmt, 0);
+        const char *raw_code = (const char *)sqlite3_column_text(stmt, 1);
+        const char *raw_name = (const char *)sqlite3_column_text(stmt, 2);
+        if (raw_code) snprintf(code, sizeof(code), "%s", raw_code);
+        if (raw_name) snprintf(device_name, sizeof(device_name), "%s", raw_name);
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid json\"}", ctx);
+        return 400;
+    }
+    if (code[0] == '\0') {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"code is required\"}", ctx);
+        return 400;
+    }
+
+    char hash[65] = {0};
+    char account_id[128] = {0};
+    char scope[16] = {0};
+    char name[PAIRING_NAME_MAX + 1] = {0};
+    char token[80] = {0};
+    char token_id[20] = {0};
+    code_hash(code, hash, sizeof(hash));
+    int redeemed = redeem_code(
+        db->db, hash, device_name, account_id, sizeof(account_id), scope, sizeof(scope), name, sizeof(name), token, sizeof(token), token_id, sizeof(token_id));
+    if (redeemed < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (redeemed == 0) {
+        send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"invalid or expired pairing code\"}", ctx);
+        log_warn("PAIR rejected reason=unknown_code logid=%s", ctx->log_id);
+        return 401;
+    }
+    snprintf(ctx->account_id, sizeof(ctx->account_id), "%s", account_id);
+    log_info("PAIR device paired account=%s scope=%s name=%s", account_id, scope, name);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    capture_omit_response();
+    strbuf_appendf(&sb, "{\"token\":\"%s\",\"scope\":\"%s\",", token, scope);
+    if (token_id[0] != '\0') strbuf_appendf(&sb, "\"id\":\"%s\",", token_id);
+    strbuf_append(&sb, "\"account\":", 10);
+    strbuf_append_json_string(&sb, account_id);
+    strbuf_append(&sb, ",\"name\":", 8);
+    strbuf_append_json_string(&sb, name);
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 201, "Created", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 201;
+}
@@ -0,0 +1,1287 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define DEFAULT_W_PRIME_JOULES 20000.0
+#define DEFAULT_CRITICAL_POWER_WATTS 250.0
+#define ACTIVITY_MAX_SAMPLES (12 * 3600 * 2)
+#define WBAL_DEFAULT_STEP_SEC 5
+#define CP_FIT_DEFAULT_WEEKS 12
+#define CP_FIT_MAX_WEEKS 104
+#define CP_FIT_MIN_POINTS 3
+#define CP_FIT_WRITE_WAIT_MS 5000
+
+#define VO2MAX_MIN_DURATION_SEC 1200.0
+#define VO2MAX_MIN_HR_FRACTION 0.60
+#define VO2MAX_MAX_HR_FRACTION 0.90
+#define VO2MAX_SMOOTHING_TAU_DAYS 14.0
+#define VO2MAX_DEFAULT_DAYS 365
+#define VO2MAX_MAX_DAYS 3650
+
+#define HRR_WINDOW_SEC 60
+#define HRR_PEAK_FRACTION 0.85
+#define HRR_MIN_DROP_BPM 5.0
+#define CARDIAC_DRIFT_MIN_SAMPLES 1200
+#define HEART_DEFAULT_DAYS 90
+#define HEART_MAX_DAYS 730
+#define POWER_RECORD_RECENT_DAYS 7
+
+static const int CP_FIT_DURATIONS[] = {120, 180, 300, 480, 720, 1200};
+#define CP_FIT_DURATION_COUNT (sizeof(CP_FIT_DURATIONS) / sizeof(CP_FIT_DURATIONS[0]))
+
+void compute_wbal_series(const double *power, size_t count, double cp, double w_prime, double *out_wbal, wbal_summary_t *summary) {
+    memset(summary, 0, sizeof(*summary));
+    double wbal = w_prime;
+    summary->min_wbal = w_prime;
+    for (size_t i = 0; i < count; i++) {
+        double p = power[i] > 0.0 ? power[i] : 0.0;
+        if (p > cp) {
+            wbal -= p - cp;
+        } else if (w_prime > 0.0) {
+            wbal += (w_prime - wbal) * (cp - p) / w_prime;
+        }
+        if (out_wbal) out_wbal[i] = wbal;
+        if (wbal < summary->min_wbal) summary->min_wbal = wbal;
+        if (wbal < 0.0) summary->time_below_zero_sec++;
+    }
+}
+
+double load_profile_ftp(sqlite3 *db, const char *account_id) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return 0.0;
+    const char *sql =
+        "SELECT COALESCE(json_extract(data_value, '$.sports.cycling.ftpWatts'), json_extract(data_value, '$.cyclingFTPWatts'),"
+        " json_extract(data_value, '$.ftpWatts'))"
+        " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return 0.0;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    double ftp = 0.0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) ftp = sqlite3_column_double(stmt, 0);
+    sqlite3_finalize(stmt);
+    return ftp;
+}
+
+double compute_normalized_power(const double *power, size_t count) {
+    const size_t window = 30;
+    if (!power || count < window) return 0.0;
+    double rolling = 0.0;
+    double sum4 = 0.0;
+    size_t n = 0;
+    for (size_t i = 0; i < count; i++) {
+        rolling += power[i];
+        if (i >= window) rolling -= power[i - window];
+        if (i + 1 < window) continue;
+        double avg = rolling / (double)window;
+        sum4 += avg * avg * avg * avg;
+        n++;
+    }
+    return n > 0 ? pow(sum4 / (double)n, 0.25) : 0.0;
+}
+
+int load_profile_power_model(sqlite3 *db, const char *account_id, double *out_cp, double *out_w_prime) {
+    *out_cp = DEFAULT_CRITICAL_POWER_WATTS;
+    *out_w_prime = DEFAULT_W_PRIME_JOULES;
+
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return -1;
+    const char *sql =
+        "SELECT json_extract(data_value, '$.criticalPowerWatts'),"
+        " COALESCE(json_extract(data_value, '$.sports.cycling.ftpWatts'), json_extract(data_value, '$.cyclingFTPWatts'),"
+        " json_extract(data_value, '$.ftpWatts')),"
+        " json_extract(data_value, '$.wPrimeJoules')"
+        " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    double explicit_cp = 0.0;
+    double ftp = 0.0;
+    double explicit_w_prime = 0.0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        explicit_cp = sqlite3_column_double(stmt, 0);
+        ftp = sqlite3_column_double(stmt, 1);
+        explicit_w_prime = sqlite3_column_double(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+
+    cp_fit_t latest;
+    int has_fit = load_latest_cp_fit(db, account_id, &latest) == 1;
+    if (explicit_cp > 0.0) {
+        *out_cp = explicit_cp;
+    } else if (has_fit) {
+        *out_cp = latest.cp;
+    } else if (ftp > 0.0) {
+        *out_cp = ftp;
+    }
+    if (explicit_w_prime > 0.0) {
+        *out_w_prime = explicit_w_prime;
+    } else if (has_fit && explicit_cp <= 0.0) {
+        *out_w_prime = latest.w_prime;
+    }
+    return 0;
+}
+
+static int load_activity_samples(sqlite3 *db, const char *storage_key, const char *activity_id, const char *field, double **out, size_t *out_count) {
+    *out = NULL;
+    *out_count = 0;
+    const char *sql =
+        "SELECT COALESCE(s.value, 0) FROM kv_store k, json_each(k.data_value) a, json_each(a.value, ?3) s"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.id') = ?2 ORDER BY s.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activity_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, field, -1, SQLITE_TRANSIENT);
+
+    size_t cap = 0;
+    double *samples = NULL;
+    while (sqlite3_step(stmt) == SQLITE_ROW && *out_count < ACTIVITY_MAX_SAMPLES) {
+        if (*out_count == cap) {
+            size_t next = cap ? cap * 2 : 1024;
+            double *grown = (double *)realloc(samples, next * sizeof(double));
+            if (!grown) {
+                free(samples);
+                sqlite3_finalize(stmt);
+                *out_count = 0;
+                return -1;
+            }
+            samples = grown;
+            cap = next;
+        }
+        samples[(*out_count)++] = sqlite3_column_double(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    *out = samples;
+    return 0;
+}
+
+int activity_metric_upsert(sqlite3 *db, const char *account_id, const char *activity_id, const char *metric, double value) {
+    const char *sql =
+        "INSERT INTO activity_metrics (account_id, activity_id, metric, value, computed_at)"
+        " VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now'))"
+        " ON CONFLICT(account_id, activity_id, metric) DO UPDATE SET value=excluded.value, computed_at=excluded.computed_at";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activity_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, metric, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_double(stmt, 4, value);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+/*
+ * Refreshes write in their own transaction unless the caller already holds one. When the database
+ * stays locked the refresh is skipped; the next write of the key queues another.
+ */
+static int refresh_begin(sqlite3 *db, const char *what, const char *account_id, int *own_tx) {
+    *own_tx = sqlite3_get_autocommit(db);
+    if (!*own_tx || sqlite3_exec(db, "BEGIN IMMEDIATE;", NULL, NULL, NULL) == SQLITE_OK) return 0;
+    log_warn("PHYSIOLOGY skipped %s refresh account=%s reason=%s", what, account_id, sqlite3_errmsg(db));
+    *own_tx = 0;
+    return -1;
+}
+
+static int refresh_commit(sqlite3 *db, const char *what, const char *account_id, int own_tx) {
+    if (!own_tx || sqlite3_exec(db, "COMMIT;", NULL, NULL, NULL) == SQLITE_OK) return 0;
+    log_warn("PHYSIOLOGY dropped %s refresh account=%s reason=%s", what, account_id, sqlite3_errmsg(db));
+    sqlite3_exec(db, "ROLLBACK;", NULL, NULL, NULL);
+    return -1;
+}
+
+int physiology_refresh_activity_metrics(sqlite3 *db, const char *account_id) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+
+    double cp = 0.0;
+    double w_prime = 0.0;
+    load_profile_power_model(db, account_id, &cp, &w_prime);
+
+    const char *sql =
+        "SELECT json_extract(a.value, '$.id') FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_type(a.value, '$.powerSamples') = 'array' AND json_extract(a.value, '$.id') IS NOT NULL";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+
+    int refreshed = 0;
+    /* Analytics GETs already hold a snapshot transaction; join it rather than nest. */
+    int own_tx = 0;
+    if (refresh_begin(db, "wbal", account_id, &own_tx) != 0) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        char activity_id[128] = {0};
+        snprintf(activity_id, sizeof(activity_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        double *samples = NULL;
+        size_t count = 0;
+        if (load_activity_samples(db, storage_key, activity_id, "$.powerSamples", &samples, &count) != 0 || count == 0) {
+            free(samples);
+            continue;
+        }
+        wbal_summary_t summary;
+        compute_wbal_series(samples, count, cp, w_prime, NULL, &summary);
+        free(samples);
+        activity_metric_upsert(db, account_id, activity_id, "wbal_min_joules", summary.min_wbal);
+        activity_metric_upsert(db, account_id, activity_id, "wbal_time_below_zero_sec", (double)summary.time_below_zero_sec);
+        refreshed++;
+    }
+    sqlite3_finalize(stmt);
+    if (refresh_commit(db, "wbal", account_id, own_tx) != 0) return -1;
+    if (refreshed > 0) {
+        log_info("PHYSIOLOGY refreshed wbal account=%s activities=%d cp=%.0f w_prime=%.0f", account_id, refreshed, cp, w_prime);
+    }
+    return refreshed;
+}
+
+static int handle_get_activity_wbal(int fd, worker_db_t *db, const http_request_t *req, const char *activity_id, const request_log_context_t *ctx) {
+    int step = WBAL_DEFAULT_STEP_SEC;
+    char raw_step[16] = {0};
+    if (query_param(req->query, "step", raw_step, sizeof(raw_step))) {
+        step = atoi(raw_step);
+        if (step <= 0 || step > 3600) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"step must be in 1..3600\"}", ctx);
+            return 400;
+        }
+    }
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    double *samples = NULL;
+    size_t count = 0;
+    if (load_activity_samples(db->db, storage_key, activity_id, "$.powerSamples", &samples, &count) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (count == 0) {
+        free(samples);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"activity has no power samples\"}", ctx);
+        return 404;
+    }
+
+    double cp = 0.0;
+    double w_prime = 0.0;
+    load_profile_power_model(db->db, ctx->account_id, &cp, &w_prime);
+    double *wbal = (double *)malloc(count * sizeof(double));
+    if (!wbal) {
+        free(samples);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    wbal_summary_t summary;
+    compute_wbal_series(samples, count, cp, w_prime, wbal, &summary);
+    free(samples);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"activity_id\":", 15);
+    strbuf_append_json_string(&sb, activity_id);
+    strbuf_appendf(
+        &sb,
+        ",\"cp\":%.0f,\"w_prime\":%.0f,\"samples\":%zu,\"min_wbal\":%.0f,\"time_below_zero_sec\":%d,\"step_sec\":%d,\"series\":[",
+        cp,
+        w_prime,
+        count,
+        summary.min_wbal,
+        summary.time_below_zero_sec,
+        step);
+    for (size_t i = 0; i < count; i += (size_t)step) {
+        strbuf_appendf(&sb, "%s[%zu,%.0f]", i == 0 ? "" : ",", i, wbal[i]);
+    }
+    strbuf_append(&sb, "]}", 2);
+    free(wbal);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+int handle_get_activity_analytics(int fd, worker_db_t *db, const http_request_t *req, const char *subpath, const request_log_context_t *ctx) {
+    const char *slash = strchr(subpath, '/');
+    if (!slash || slash == subpath || (size_t)(slash - subpath) >= 128) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    char activity_id[128] = {0};
+    memcpy(activity_id, subpath, (size_t)(slash - subpath));
+    const char *metric = slash + 1;
+
+    if (strcmp(metric, "wbal") == 0) {
+        return handle_get_activity_wbal(fd, db, req, activity_id, ctx);
+    }
+    send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown activity analytics\"}", ctx);
+    return 404;
+}
+
+static double t_critical_95(int df) {
+    static const double table[] = {12.71, 4.30, 3.18, 2.78, 2.57, 2.45, 2.36, 2.31, 2.26, 2.23};
+    if (df <= 0) return 0.0;
+    if (df <= 10) return table[df - 1];
+    return 1.96;
+}
+
+static int linear_fit(const double *x, const double *y, size_t n, double *slope, double *intercept, double *slope_se, double *intercept_se, double *sse) {
+    if (n < 2) return -1;
+    double mean_x = 0.0;
+    double mean_y = 0.0;
+    for (size_t i = 0; i < n; i++) {
+        mean_x += x[i];
+        mean_y += y[i];
+    }
+    mean_x /= (double)n;
+    mean_y /= (double)n;
+    double sxx = 0.0;
+    double sxy = 0.0;
+    for (size_t i = 0; i < n; i++) {
+        sxx += (x[i] - mean_x) * (x[i] - mean_x);
+        sxy += (x[i] - mean_x) * (y[i] - mean_y);
+    }
+    if (sxx <= 0.0) return -1;
+    *slope = sxy / sxx;
+    *intercept = mean_y - *slope * mean_x;
+    *sse = 0.0;
+    for (size_t i = 0; i < n; i++) {
+        double r = y[i] - (*intercept + *slope * x[i]);
+        *sse += r * r;
+    }
+    double s2 = n > 2 ? *sse / (double)(n - 2) : 0.0;
+    *slope_se = sqrt(s2 / sxx);
+    *intercept_se = sqrt(s2 * (1.0 / (double)n + mean_x * mean_x / sxx));
+    return 0;
+}
+
+int fit_cp_model(const mmp_point_t *points, size_t count, int model, cp_fit_t *out) {
+    memset(out, 0, sizeof(*out));
+    if (!points || count < CP_FIT_MIN_POINTS || count > CP_FIT_DURATION_COUNT * 4 || (model != 2 && model != 3)) return -1;
+
+    double x[CP_FIT_DURATION_COUNT * 4];
+    double y[CP_FIT_DURATION_COUNT * 4];
+    double slope = 0.0, intercept = 0.0, slope_se = 0.0, intercept_se = 0.0, sse = 0.0;
+    out->model = model;
+    out->points = (int)count;
+
+    if (model == 2) {
+        for (size_t i = 0; i < count; i++) {
+            x[i] = points[i].duration_sec;
+            y[i] = points[i].watts * points[i].duration_sec;
+        }
+        if (linear_fit(x, y, count, &slope, &intercept, &slope_se, &intercept_se, &sse) != 0) return -1;
+        out->cp = slope;
+        out->w_prime = intercept;
+        out->cp_se = slope_se;
+        out->w_prime_se = intercept_se;
+    } else {
+        double min_t = points[0].duration_sec;
+        for (size_t i = 1; i < count; i++) {
+            if (points[i].duration_sec < min_t) min_t = points[i].duration_sec;
+        }
+        double best_sse = -1.0;
+        for (double k = -120.0; k < min_t && k <= 0.0; k += 1.0) {
+            for (size_t i = 0; i < count; i++) {
+                x[i] = 1.0 / (points[i].duration_sec - k);
+                y[i] = points[i].watts;
+            }
+            if (linear_fit(x, y, count, &slope, &intercept, &slope_se, &intercept_se, &sse) != 0) continue;
+            if (best_sse < 0.0 || sse < best_sse) {
+                best_sse = sse;
+                out->cp = intercept;
+                out->w_prime = slope;
+                out->k = k;
+                out->cp_se = intercept_se;
+                out->w_prime_se = slope_se;
+            }
+        }
+        if (best_sse < 0.0) return -1;
+    }
+
+    if (out->cp <= 0.0 || out->w_prime <= 0.0) return -1;
+    double t = t_critical_95((int)count - (model == 2 ? 2 : 3));
+    if (t <= 0.0) t = t_critical_95(1);
+    out->cp_ci_low = out->cp - t * out->cp_se;
+    out->cp_ci_high = out->cp + t * out->cp_se;
+    out->w_prime_ci_low = out->w_prime - t * out->w_prime_se;
+    out->w_prime_ci_high = out->w_prime + t * out->w_prime_se;
+    return 0;
+}
+
+static double mean_max_power(const double *samples, size_t count, size_t window) {
+    if (window == 0 || count < window) return -1.0;
+    double sum = 0.0;
+    for (size_t i = 0; i < window; i++) sum += samples[i];
+    double best = sum;
+    for (size_t i = window; i < count; i++) {
+        sum += samples[i] - samples[i - window];
+        if (sum > best) best = sum;
+    }
+    return best / (double)window;
+}
+
+size_t load_mean_max_efforts(sqlite3 *db, const char *account_id, int from_day, mmp_point_t *out, size_t max_points) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return 0;
+    char from[16] = {0};
+    format_iso_day(from_day, from, sizeof(from));
+    /* Efforts recorded on a travel stay count at their sea-level equivalent. */
+    travel_log_t *travel = (travel_log_t *)malloc(sizeof(travel_log_t));
+    if (travel && travel_load_log(db, account_id, travel) != 0) travel->count = 0;
+
+    double best[CP_FIT_DURATION_COUNT];
+    int at_altitude[CP_FIT_DURATION_COUNT];
+    for (size_t i = 0; i < CP_FIT_DURATION_COUNT; i++) {
+        best[i] = -1.0;
+        at_altitude[i] = 0;
+    }
+
+    const char *interval_sql =
+        "SELECT substr(json_extract(a.value, '$.date'), 1, 10), json_extract(i.value, '$.actualPower'), json_extract(i.value, '$.durationSec')"
+        " FROM kv_store k, json_each(k.data_value) a, json_each(a.value, '$.intervals') i"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) >= ?2 AND json_extract(i.value, '$.actualPower') IS NOT NULL"
+        " AND json_extract(i.value, '$.durationSec') >= ?3";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, interval_sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 3, CP_FIT_DURATIONS[0]);
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            int day = 0;
+            const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+            double factor = day_text && parse_iso_day(day_text, &day) == 0 ? travel_power_factor(travel, day, NULL) : 1.0;
+            double watts = sqlite3_column_double(stmt, 1) / factor;
+            double duration = sqlite3_column_double(stmt, 2);
+            for (size_t i = 0; i < CP_FIT_DURATION_COUNT; i++) {
+                if (duration < CP_FIT_DURATIONS[i] || watts <= best[i]) continue;
+                best[i] = watts;
+                at_altitude[i] = factor < 1.0;
+            }
+        }
+        sqlite3_finalize(stmt);
+    }
+
+    const char *samples_sql =
+        "SELECT json_extract(a.value, '$.id'), substr(json_extract(a.value, '$.date'), 1, 10) FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_type(a.value, '$.powerSamples') = 'array' AND json_extract(a.value, '$.id') IS NOT NULL"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) >= ?2";
+    if (sqlite3_prepare_v2(db, samples_sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            char activity_id[128] = {0};
+            snprintf(activity_id, sizeof(activity_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+            int day = 0;
+            const char *day_text = (const char *)sqlite3_column_text(stmt, 1);
+            double factor = day_text && parse_iso_day(day_text, &day) == 0 ? travel_power_factor(travel, day, NULL) : 1.0;
+            double *samples = NULL;
+            size_t count = 0;
+            if (load_activity_samples(db, storage_key, activity_id, "$.powerSamples", &samples, &count) == 0) {
+                for (size_t i = 0; i < CP_FIT_DURATION_COUNT; i++) {
+                    double mmp = mean_max_power(samples, count, (size_t)CP_FIT_DURATIONS[i]);
+                    if (mmp <= 0.0 || mmp / factor <= best[i]) continue;
+                    best[i] = mmp / factor;
+                    at_altitude[i] = factor < 1.0;
+                }
+            }
+            free(samples);
+        }
+        sqlite3_finalize(stmt);
+    }
+    free(travel);
+
+    size_t points = 0;
+    for (size_t i = 0; i < CP_FIT_DURATION_COUNT && points < max_points; i++) {
+        if (best[i] <= 0.0) continue;
+        out[points].duration_sec = CP_FIT_DURATIONS[i];
+        out[points].watts = best[i];
+        out[points].altitude_corrected = at_altitude[i];
+        points++;
+    }
+    return points;
+}
+
+int load_latest_cp_fit(sqlite3 *db, const char *account_id, cp_fit_t *out) {
+    memset(out, 0, sizeof(*out));
+    const char *sql =
+        "SELECT model, cp, w_prime, k, cp_ci_low, cp_ci_high, w_prime_ci_low, w_prime_ci_high, points"
+        " FROM cp_history WHERE account_id = ?1 ORDER BY fitted_at DESC, id DESC LIMIT 1";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        out->model = sqlite3_column_int(stmt, 0);
+        out->cp = sqlite3_column_double(stmt, 1);
+        out->w_prime = sqlite3_column_double(stmt, 2);
+        out->k = sqlite3_column_double(stmt, 3);
+        out->cp_ci_low = sqlite3_column_double(stmt, 4);
+        out->cp_ci_high = sqlite3_column_double(stmt, 5);
+        out->w_prime_ci_low = sqlite3_column_double(stmt, 6);
+        out->w_prime_ci_high = sqlite3_column_double(stmt, 7);
+        out->points = sqlite3_column_int(stmt, 8);
+        found = 1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static int store_cp_fit(sqlite3 *db, const char *account_id, const cp_fit_t *fit, int weeks) {
+    const char *sql =
+        "INSERT INTO cp_history (account_id, fitted_at, model, weeks, cp, w_prime, k, cp_ci_low, cp_ci_high,"
+        " w_prime_ci_low, w_prime_ci_high, points) VALUES (?1, strftime('%s', 'now'), ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, fit->model);
+    sqlite3_bind_int(stmt, 3, weeks);
+    sqlite3_bind_double(stmt, 4, fit->cp);
+    sqlite3_bind_double(stmt, 5, fit->w_prime);
+    sqlite3_bind_double(stmt, 6, fit->k);
+    sqlite3_bind_double(stmt, 7, fit->cp_ci_low);
+    sqlite3_bind_double(stmt, 8, fit->cp_ci_high);
+    sqlite3_bind_double(stmt, 9, fit->w_prime_ci_low);
+    sqlite3_bind_double(stmt, 10, fit->w_prime_ci_high);
+    sqlite3_bind_int(stmt, 11, fit->points);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? 0 : -1;
+}
+
+int physiology_run_cp_fit(sqlite3 *db, const char *account_id, int weeks, int model, cp_fit_t *out_fit, int *out_stored) {
+    *out_stored = 0;
+    mmp_point_t points[CP_FIT_DURATION_COUNT];
+    size_t count = load_mean_max_efforts(db, account_id, today_day() - weeks * 7, points, CP_FIT_DURATION_COUNT);
+    if (fit_cp_model(points, count, model, out_fit) != 0) return -1;
+    for (size_t i = 0; i < count; i++) out_fit->altitude_points += points[i].altitude_corrected;
+
+    cp_fit_t latest;
+    if (load_latest_cp_fit(db, account_id, &latest) == 1 && latest.model == out_fit->model &&
+        fabs(latest.cp - out_fit->cp) < 0.5 && fabs(latest.w_prime - out_fit->w_prime) < 50.0) {
+        return 0;
+    }
+    if (store_cp_fit(db, account_id, out_fit, weeks) != 0) return -1;
+    *out_stored = 1;
+    log_info("PHYSIOLOGY cp fit account=%s model=%d cp=%.0f w_prime=%.0f points=%d", account_id, out_fit->model, out_fit->cp, out_fit->w_prime, out_fit->points);
+    return 0;
+}
+
+int physiology_refresh_cp_fit(sqlite3 *db, const char *account_id) {
+    cp_fit_t fit;
+    int stored = 0;
+    if (physiology_run_cp_fit(db, account_id, CP_FIT_DEFAULT_WEEKS, 2, &fit, &stored) != 0) return 0;
+    return stored;
+}
+
+static void append_cp_fit(strbuf_t *sb, const cp_fit_t *fit) {
+    strbuf_appendf(
+        sb,
+        "{\"model\":%d,\"cp\":%.1f,\"w_prime\":%.0f,\"k\":%.1f,\"cp_ci\":[%.1f,%.1f],\"w_prime_ci\":[%.0f,%.0f],\"points\":%d}",
+        fit->model,
+        fit->cp,
+        fit->w_prime,
+        fit->k,
+        fit->cp_ci_low,
+        fit->cp_ci_high,
+        fit->w_prime_ci_low,
+        fit->w_prime_ci_high,
+        fit->points);
+}
+
+static void append_power_zones(strbuf_t *sb, double cp) {
+    static const struct {
+        const char *name;
+        double low;
+        double high;
+    } zones[] = {
+        {"Z1 Recovery", 0.0, 0.55},
+        {"Z2 Endurance", 0.56, 0.75},
+        {"Z3 Tempo", 0.76, 0.90},
+        {"Z4 Threshold", 0.91, 1.05},
+        {"Z5 VO2max", 1.06, 1.20},
+        {"Z6 Anaerobic", 1.21, 0.0},
+    };
+    strbuf_append(sb, "[", 1);
+    for (size_t i = 0; i < sizeof(zones) / sizeof(zones[0]); i++) {
+        strbuf_appendf(sb, "%s{\"name\":\"%s\",\"min_watts\":%.0f,\"max_watts\":", i == 0 ? "" : ",", zones[i].name, cp * zones[i].low);
+        if (zones[i].high > 0.0) {
+            strbuf_appendf(sb, "%.0f}", cp * zones[i].high);
+        } else {
+            strbuf_append(sb, "null}", 5);
+        }
+    }
+    strbuf_append(sb, "]", 1);
+}
+
+int handle_get_analytics_cp(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    (void)req;
+    double cp = 0.0;
+    double w_prime = 0.0;
+    load_profile_power_model(db->db, ctx->account_id, &cp, &w_prime);
+    cp_fit_t latest;
+    int has_fit = load_latest_cp_fit(db->db, ctx->account_id, &latest) == 1;
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"cp\":%.1f,\"w_prime\":%.0f,\"latest_fit\":", cp, w_prime);
+    if (has_fit) {
+        append_cp_fit(&sb, &latest);
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_append(&sb, ",\"zones\":", 9);
+    append_power_zones(&sb, cp);
+    strbuf_append(&sb, ",\"history\":[", 12);
+
+    const char *sql =
+        "SELECT fitted_at, model, weeks, cp, w_prime, cp_ci_low, cp_ci_high, w_prime_ci_low, w_prime_ci_high, points"
+        " FROM cp_history WHERE account_id = ?1 ORDER BY fitted_at DESC, id DESC LIMIT 100";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            strbuf_appendf(
+                &sb,
+                "%s{\"fitted_at\":%lld,\"model\":%d,\"weeks\":%d,\"cp\":%.1f,\"w_prime\":%.0f,\"cp_ci\":[%.1f,%.1f],"
+                "\"w_prime_ci\":[%.0f,%.0f],\"points\":%d}",
+                count == 0 ? "" : ",",
+                (long long)sqlite3_column_int64(stmt, 0),
+                sqlite3_column_int(stmt, 1),
+                sqlite3_column_int(stmt, 2),
+                sqlite3_column_double(stmt, 3),
+                sqlite3_column_double(stmt, 4),
+                sqlite3_column_double(stmt, 5),
+                sqlite3_column_double(stmt, 6),
+                sqlite3_column_double(stmt, 7),
+                sqlite3_column_double(stmt, 8),
+                sqlite3_column_int(stmt, 9));
+            count++;
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(&sb, "]}", 2);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+typedef struct {
+    char account_id[128];
+    int weeks;
+    int model;
+    int stored;
+    cp_fit_t fit;
+} cp_fit_write_t;
+
+/* Fits and stores on the writer connection, then rescores the activities against the new CP. */
+static int cp_fit_write(sqlite3 *db, void *arg) {
+    cp_fit_write_t *w = (cp_fit_write_t *)arg;
+    if (physiology_run_cp_fit(db, w->account_id, w->weeks, w->model, &w->fit, &w->stored) != 0) return 422;
+    physiology_refresh_activity_metrics(db, w->account_id);
+    return 200;
+}
+
+int handle_post_analytics_cp_fit(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    (void)db;
+    int weeks = CP_FIT_DEFAULT_WEEKS;
+    int model = 2;
+    char raw[16] = {0};
+    if (query_param(req->query, "weeks", raw, sizeof(raw))) {
+        weeks = atoi(raw);
+        if (weeks <= 0 || weeks > CP_FIT_MAX_WEEKS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"weeks must be in 1..104\"}", ctx);
+            return 400;
+        }
+    }
+    if (query_param(req->query, "model", raw, sizeof(raw))) {
+        model = atoi(raw);
+        if (model != 2 && model != 3) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"model must be 2 or 3\"}", ctx);
+            return 400;
+        }
+    }
+
+    cp_fit_write_t *w = (cp_fit_write_t *)calloc(1, sizeof(cp_fit_write_t));
+    if (!w) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    snprintf(w->account_id, sizeof(w->account_id), "%s", ctx->account_id);
+    w->weeks = weeks;
+    w->model = model;
+    write_dispatch_result_t result;
+    int rc = write_dispatch_call(cp_fit_write, w, free, ctx->account_id, ctx->log_id, CP_FIT_WRITE_WAIT_MS, &result);
+    if (rc < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"write queue unavailable\"}", ctx);
+        return 500;
+    }
+    if (rc > 0) {
+        char body[128] = {0};
+        snprintf(body, sizeof(body), "{\"status\":\"queued\",\"logid\":\"%s\"}", ctx->log_id);
+        log_warn("PHYSIOLOGY cp fit queued reason=writer_backlog account=%s logid=%s", ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 202, "Accepted", body, ctx);
+        return 202;
+    }
+    if (result.status_code != 200) {
+        free(w);
+        send_response_with_log_context(
+            fd, 422, "Unprocessable Entity", "{\"error\":\"not enough maximal efforts between 2 and 20 minutes to fit a CP model\"}", ctx);
+        return 422;
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"stored\":%s,\"weeks\":%d,\"fit\":", w->stored ? "true" : "false", weeks);
+    append_cp_fit(&sb, &w->fit);
+    strbuf_appendf(&sb, ",\"altitude_corrected_points\":%d}", w->fit.altitude_points);
+    free(w);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+double estimate_vo2max(const char *sport, double duration_sec, double distance_km, double power_watts, double avg_hr, double max_hr, double weight_kg) {
+    if (!sport || duration_sec < VO2MAX_MIN_DURATION_SEC || avg_hr <= 0.0 || max_hr <= 0.0) return 0.0;
+    double hr_fraction = avg_hr / max_hr;
+    if (hr_fraction < VO2MAX_MIN_HR_FRACTION || hr_fraction > VO2MAX_MAX_HR_FRACTION) return 0.0;
+
+    /* ACSM metabolic equations give the oxygen cost of the effort in ml/kg/min. */
+    double vo2 = 0.0;
+    if (strcmp(sport, "cycling") == 0) {
+        if (power_watts <= 0.0 || weight_kg <= 0.0) return 0.0;
+        vo2 = 10.8 * power_watts / weight_kg + 7.0;
+    } else if (strcmp(sport, "running") == 0) {
+        if (distance_km <= 0.0) return 0.0;
+        double meters_per_min = distance_km * 1000.0 / (duration_sec / 60.0);
+        vo2 = 0.2 * meters_per_min + 3.5;
+    } else {
+        return 0.0;
+    }
+
+    /* Swain et al.: %HRmax = 0.64 * %VO2max + 37. */
+    double vo2_fraction = (hr_fraction * 100.0 - 37.0) / 64.0;
+    if (vo2_fraction <= 0.0) return 0.0;
+    return vo2 / vo2_fraction;
+}
+
+int physiology_refresh_vo2max(sqlite3 *db, const char *account_id) {
+    char profile_key[256] = {0};
+    char activities_key[256] = {0};
+    if (build_storage_key(account_id, "profile", profile_key, sizeof(profile_key)) != 0) return -1;
+    if (build_storage_key(account_id, "activities", activities_key, sizeof(activities_key)) != 0) return -1;
+
+    double weight_kg = 0.0;
+    double cycling_max_hr = 0.0;
+    double running_max_hr = 0.0;
+    sqlite3_stmt *stmt = NULL;
+    const char *profile_sql =
+        "SELECT json_extract(data_value, '$.athleteWeightKg'), json_extract(data_value, '$.athleteAgeYears'),"
+        " json_extract(data_value, '$.cyclingMaxHeartRate'), json_extract(data_value, '$.runningMaxHeartRate')"
+        " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)";
+    if (sqlite3_prepare_v2(db, profile_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, profile_key, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        weight_kg = sqlite3_column_double(stmt, 0);
+        double age = sqlite3_column_double(stmt, 1);
+        double age_max_hr = age > 0.0 ? 220.0 - age : 0.0;
+        cycling_max_hr = sqlite3_column_double(stmt, 2) > 0.0 ? sqlite3_column_double(stmt, 2) : age_max_hr;
+        running_max_hr = sqlite3_column_double(stmt, 3) > 0.0 ? sqlite3_column_double(stmt, 3) : age_max_hr;
+    }
+    sqlite3_finalize(stmt);
+
+    const char *activity_sql =
+        "SELECT json_extract(a.value, '$.id'), substr(json_extract(a.value, '$.date'), 1, 10), json_extract(a.value, '$.sport'),"
+        " json_extract(a.value, '$.durationSec'), json_extract(a.value, '$.distanceKm'),"
+        " json_extract(a.value, '$.normalizedPower'), json_extract(a.value, '$.avgHeartRate')"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.id') IS NOT NULL AND json_extract(a.value, '$.date') IS NOT NULL"
+        " ORDER BY 2";
+    if (sqlite3_prepare_v2(db, activity_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, activities_key, -1, SQLITE_TRANSIENT);
+
+    int own_tx = 0;
+    if (refresh_begin(db, "vo2max", account_id, &own_tx) != 0) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    sqlite3_stmt *del = NULL;
+    if (sqlite3_prepare_v2(db, "DELETE FROM vo2max_trend WHERE account_id = ?1", -1, &del, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(del, 1, account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_step(del);
+        sqlite3_finalize(del);
+    }
+    sqlite3_stmt *ins = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "INSERT INTO vo2max_trend (account_id, day, estimate, smoothed, activities) VALUES (?1, ?2, ?3, ?4, ?5)",
+            -1,
+            &ins,
+            NULL) != SQLITE_OK) {
+        sqlite3_finalize(stmt);
+        if (own_tx) sqlite3_exec(db, "ROLLBACK;", NULL, NULL, NULL);
+        return -1;
+    }
+
+    int estimates = 0;
+    int current_day = -1;
+    double day_sum = 0.0;
+    int day_count = 0;
+    double smoothed = 0.0;
+    int smoothed_day = -1;
+    for (int more = sqlite3_step(stmt) == SQLITE_ROW;; more = sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = -1;
+        double estimate = 0.0;
+        if (more) {
+            const char *sport = (const char *)sqlite3_column_text(stmt, 2);
+            double max_hr = sport && strcmp(sport, "running") == 0 ? running_max_hr : cycling_max_hr;
+            estimate = estimate_vo2max(
+                sport,
+                sqlite3_column_double(stmt, 3),
+                sqlite3_column_double(stmt, 4),
+                sqlite3_column_double(stmt, 5),
+                sqlite3_column_double(stmt, 6),
+                max_hr,
+                weight_kg);
+            if (estimate <= 0.0 || parse_iso_day((const char *)sqlite3_column_text(stmt, 1), &day) != 0) continue;
+            activity_metric_upsert(db, account_id, (const char *)sqlite3_column_text(stmt, 0), "vo2max_estimate", estimate);
+            estimates++;
+        }
+
+        if (current_day >= 0 && (!more || day != current_day)) {
+            double day_mean = day_sum / (double)day_count;
+            if (smoothed_day < 0) {
+                smoothed = day_mean;
+            } else {
+                smoothed += (day_mean - smoothed) * (1.0 - exp(-(double)(current_day - smoothed_day) / VO2MAX_SMOOTHING_TAU_DAYS));
+            }
+            smoothed_day = current_day;
+            char iso[16] = {0};
+            format_iso_day(current_day, iso, sizeof(iso));
+            sqlite3_reset(ins);
+            sqlite3_bind_text(ins, 1, account_id, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(ins, 2, iso, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_double(ins, 3, day_mean);
+            sqlite3_bind_double(ins, 4, smoothed);
+            sqlite3_bind_int(ins, 5, day_count);
+            sqlite3_step(ins);
+            day_sum = 0.0;
+            day_count = 0;
+        }
+        if (!more) break;
+        current_day = day;
+        day_sum += estimate;
+        day_count++;
+    }
+    sqlite3_finalize(ins);
+    sqlite3_finalize(stmt);
+    if (refresh_commit(db, "vo2max", account_id, own_tx) != 0) return -1;
+    if (estimates > 0) {
+        log_info("PHYSIOLOGY refreshed vo2max account=%s estimates=%d smoothed=%.1f", account_id, estimates, smoothed);
+    }
+    return estimates;
+}
+
+int handle_get_analytics_profile(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int days = VO2MAX_DEFAULT_DAYS;
+    char raw_days[16] = {0};
+    if (query_param(req->query, "days", raw_days, sizeof(raw_days))) {
+        days = atoi(raw_days);
+        if (days <= 0 || days > VO2MAX_MAX_DAYS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"days must be in 1..3650\"}", ctx);
+            return 400;
+        }
+    }
+    char from[16] = {0};
+    format_iso_day(today_day() - days, from, sizeof(from));
+
+    double ftp = load_profile_ftp(db->db, ctx->account_id);
+    sqlite3_stmt *stmt = NULL;
+    double cp = 0.0;
+    double w_prime = 0.0;
+    load_profile_power_model(db->db, ctx->account_id, &cp, &w_prime);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"ftp_watts\":%.0f,\"cp\":%.1f,\"w_prime\":%.0f,\"cp_history\":[", ftp, cp, w_prime);
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT date(fitted_at, 'unixepoch'), model, cp, w_prime FROM cp_history"
+            " WHERE account_id = ?1 AND date(fitted_at, 'unixepoch') >= ?2 ORDER BY fitted_at, id",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            strbuf_appendf(
+                &sb,
+                "%s{\"date\":\"%s\",\"model\":%d,\"cp\":%.1f,\"w_prime\":%.0f}",
+                count == 0 ? "" : ",",
+                (const char *)sqlite3_column_text(stmt, 0),
+                sqlite3_column_int(stmt, 1),
+                sqlite3_column_double(stmt, 2),
+                sqlite3_column_double(stmt, 3));
+            count++;
+        }
+        sqlite3_finalize(stmt);
+    }
+
+    strbuf_append(&sb, "],\"vo2max_trend\":[", 18);
+    double latest = 0.0;
+    char latest_day[16] = {0};
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT day, estimate, smoothed, activities FROM vo2max_trend WHERE account_id = ?1 AND day >= ?2 ORDER BY day",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            snprintf(latest_day, sizeof(latest_day), "%s", (const char *)sqlite3_column_text(stmt, 0));
+            latest = sqlite3_column_double(stmt, 2);
+            strbuf_appendf(
+                &sb,
+                "%s{\"date\":\"%s\",\"estimate\":%.1f,\"smoothed\":%.1f,\"activities\":%d}",
+                count == 0 ? "" : ",",
+                latest_day,
+                sqlite3_column_double(stmt, 1),
+                latest,
+                sqlite3_column_int(stmt, 3));
+            count++;
+        }
+        sqlite3_finalize(stmt);
+    }
+    strbuf_append(&sb, "],\"vo2max\":", 11);
+    if (latest_day[0] != '\0') {
+        strbuf_appendf(&sb, "{\"value\":%.1f,\"as_of\":\"%s\"}", latest, latest_day);
+    } else {
+        strbuf_append(&sb, "null", 4);
+    }
+    strbuf_append(&sb, ",\"calibrations\":", 16);
+    calibration_append_annotations(&sb, db->db, ctx->account_id, today_day() - days, today_day());
+    strbuf_append(&sb, "}", 1);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+void compute_heart_rate_metrics(const double *hr, size_t hr_count, const double *power, size_t power_count, heart_metrics_t *out) {
+    memset(out, 0, sizeof(*out));
+    if (!hr || hr_count == 0) return;
+
+    double max_hr = 0.0;
+    for (size_t i = 0; i < hr_count; i++) {
+        if (hr[i] > max_hr) max_hr = hr[i];
+    }
+
+    /* A recovery is the drop over the minute after a local HR peak near the activity maximum. */
+    double drop_sum = 0.0;
+    for (size_t t = HRR_WINDOW_SEC; t + HRR_WINDOW_SEC < hr_count; t++) {
+        if (hr[t] < HRR_PEAK_FRACTION * max_hr) continue;
+        int is_peak = 1;
+        for (size_t j = t - HRR_WINDOW_SEC; j <= t + HRR_WINDOW_SEC && is_peak; j++) {
+            if (hr[j] > hr[t] || (j < t && hr[j] == hr[t])) is_peak = 0;
+        }
+        if (!is_peak) continue;
+        double drop = hr[t] - hr[t + HRR_WINDOW_SEC];
+        if (drop >= HRR_MIN_DROP_BPM) {
+            drop_sum += drop;
+            out->hrr_efforts++;
+        }
+        t += HRR_WINDOW_SEC;
+    }
+    if (out->hrr_efforts > 0) out->hrr60_bpm = drop_sum / (double)out->hrr_efforts;
+
+    if (hr_count < CARDIAC_DRIFT_MIN_SAMPLES) return;
+    int use_power = power && power_count == hr_count;
+    double first_hr = 0.0, second_hr = 0.0, first_power = 0.0, second_power = 0.0;
+    size_t first_n = 0, second_n = 0;
+    size_t half = hr_count / 2;
+    for (size_t i = 0; i < hr_count; i++) {
+        if (hr[i] <= 0.0) continue;
+        if (i < half) {
+            first_hr += hr[i];
+            first_power += use_power ? power[i] : 0.0;
+            first_n++;
+        } else {
+            second_hr += hr[i];
+            second_power += use_power ? power[i] : 0.0;
+            second_n++;
+        }
+    }
+    if (first_n == 0 || second_n == 0 || first_hr <= 0.0 || second_hr <= 0.0) return;
+    if (use_power && first_power > 0.0) {
+        /* Pa:HR decoupling: loss of power per heartbeat from the first to the second half. */
+        double first_ef = first_power / first_hr;
+        double second_ef = second_power / second_hr;
+        out->cardiac_drift_pct = (first_ef - second_ef) / first_ef * 100.0;
+        out->drift_uses_power = 1;
+    } else {
+        double first_mean = first_hr / (double)first_n;
+        double second_mean = second_hr / (double)second_n;
+        out->cardiac_drift_pct = (second_mean - first_mean) / first_mean * 100.0;
+    }
+    out->has_drift = 1;
+}
+
+int physiology_refresh_heart_metrics(sqlite3 *db, const char *account_id) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+
+    const char *sql =
+        "SELECT json_extract(a.value, '$.id') FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_type(a.value, '$.heartRateSamples') = 'array' AND json_extract(a.value, '$.id') IS NOT NULL";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+
+    int refreshed = 0;
+    int own_tx = 0;
+    if (refresh_begin(db, "heart metrics", account_id, &own_tx) != 0) {
+        sqlite3_finalize(stmt);
+        return -1;
+    }
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        char activity_id[128] = {0};
+        snprintf(activity_id, sizeof(activity_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        double *hr = NULL;
+        size_t hr_count = 0;
+        if (load_activity_samples(db, storage_key, activity_id, "$.heartRateSamples", &hr, &hr_count) != 0 || hr_count == 0) {
+            free(hr);
+            continue;
+        }
+        double *power = NULL;
+        size_t power_count = 0;
+        load_activity_samples(db, storage_key, activity_id, "$.powerSamples", &power, &power_count);
+
+        heart_metrics_t metrics;
+        compute_heart_rate_metrics(hr, hr_count, power, power_count, &metrics);
+        free(hr);
+        free(power);
+        if (metrics.hrr_efforts > 0) {
+            activity_metric_upsert(db, account_id, activity_id, "hrr60_bpm", metrics.hrr60_bpm);
+            activity_metric_upsert(db, account_id, activity_id, "hrr60_efforts", (double)metrics.hrr_efforts);
+        }
+        if (metrics.has_drift) {
+            activity_metric_upsert(db, account_id, activity_id, "cardiac_drift_pct", metrics.cardiac_drift_pct);
+        }
+        refreshed++;
+    }
+    sqlite3_finalize(stmt);
+    if (refresh_commit(db, "heart metrics", account_id, own_tx) != 0) return -1;
+    if (refreshed > 0) {
+        log_info("PHYSIOLOGY refreshed heart metrics account=%s activities=%d", account_id, refreshed);
+    }
+    return refreshed;
+}
+
+static const int POWER_RECORD_DURATIONS[] = {5, 60, 300, 1200};
+#define POWER_RECORD_DURATION_COUNT (sizeof(POWER_RECORD_DURATIONS) / sizeof(POWER_RECORD_DURATIONS[0]))
+
+static void describe_duration(int seconds, char *out, size_t out_len) {
+    if (seconds < 60) {
+        snprintf(out, out_len, "%d s", seconds);
+    } else {
+        snprintf(out, out_len, "%d min", seconds / 60);
+    }
+}
+
+/*
+ * Mean-max power records: walks activities oldest first and posts a "personal_record" notification
+ * when a recent activity beats every earlier one for a duration. Older history only sets the bar,
+ * so importing a back catalogue does not flood the feed.
+ */
+int physiology_refresh_power_records(sqlite3 *db, const char *account_id) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+    const char *sql =
+        "SELECT json_extract(a.value, '$.id'), substr(json_extract(a.value, '$.date'), 1, 10) FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_type(a.value, '$.powerSamples') = 'array' AND json_extract(a.value, '$.id') IS NOT NULL"
+        " ORDER BY json_extract(a.value, '$.date'), a.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+
+    double best[POWER_RECORD_DURATION_COUNT];
+    for (size_t i = 0; i < POWER_RECORD_DURATION_COUNT; i++) best[i] = -1.0;
+    int recent_from = today_day() - POWER_RECORD_RECENT_DAYS;
+    int posted = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        char activity_id[128] = {0};
+        char date[16] = {0};
+        snprintf(activity_id, sizeof(activity_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(date, sizeof(date), "%s", sqlite3_column_text(stmt, 1) ? (const char *)sqlite3_column_text(stmt, 1) : "");
+        int day = 0;
+        int recent = parse_iso_day(date, &day) == 0 && day >= recent_from;
+        double *samples = NULL;
+        size_t count = 0;
+        if (load_activity_samples(db, storage_key, activity_id, "$.powerSamples", &samples, &count) != 0) {
+            free(samples);
+            continue;
+        }
+        for (size_t i = 0; i < POWER_RECORD_DURATION_COUNT; i++) {
+            double mmp = mean_max_power(samples, count, (size_t)POWER_RECORD_DURATIONS[i]);
+            if (mmp <= 0.0 || mmp <= best[i]) continue;
+            if (recent && best[i] > 0.0) {
+                char duration[16] = {0};
+                char dedupe_key[192] = {0};
+                char watts[16] = {0};
+                char previous[16] = {0};
+                describe_duration(POWER_RECORD_DURATIONS[i], duration, sizeof(duration));
+                snprintf(dedupe_key, sizeof(dedupe_key), "pr:%s:%d", activity_id, POWER_RECORD_DURATIONS[i]);
+                snprintf(watts, sizeof(watts), "%.0f", mmp);
+                snprintf(previous, sizeof(previous), "%.0f", best[i]);
+                const i18n_arg_t args[] = {{"duration", duration}, {"date", date}, {"watts", watts}, {"previous", previous}};
+                if (notification_post_message(db, account_id, "personal_record", dedupe_key, "pr.power", args, 4) > 0) posted++;
+            }
+            best[i] = mmp;
+        }
+        free(samples);
+    }
+    sqlite3_finalize(stmt);
+    return posted;
+}
+
+static void append_optional_metric(strbuf_t *sb, const char *name, sqlite3_stmt *stmt, int column) {
+    if (sqlite3_column_type(stmt, column) == SQLITE_NULL) {
+        strbuf_appendf(sb, ",\"%s\":null", name);
+    } else {
+        strbuf_appendf(sb, ",\"%s\":%.1f", name, sqlite3_column_double(stmt, column));
+    }
+}
+
+int handle_get_analytics_heart(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int days = HEART_DEFAULT_DAYS;
+    char raw_days[16] = {0};
+    if (query_param(req->query, "days", raw_days, sizeof(raw_days))) {
+        days = atoi(raw_days);
+        if (days <= 0 || days > HEART_MAX_DAYS) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"days must be in 1..730\"}", ctx);
+            return 400;
+        }
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    char from[16] = {0};
+    format_iso_day(today_day() - days, from, sizeof(from));
+    training_week_t week;
+    load_training_week(db->db, ctx->account_id, &week);
+
+    /* SQLite's 'weekday N' counts from Sunday, so N = weekStartDay (from Monday) lands on the week's last day. */
+    const char *activity_sql =
+        "WITH acts AS ("
+        " SELECT json_extract(a.value, '$.id') AS id, " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?5") " AS day"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.id') IS NOT NULL AND substr(json_extract(a.value, '$.date'), 1, 10) >= ?3)"
+        " SELECT acts.id, acts.day,"
+        " MAX(CASE WHEN m.metric = 'hrr60_bpm' THEN m.value END),"
+        " MAX(CASE WHEN m.metric = 'hrr60_efforts' THEN m.value END),"
+        " MAX(CASE WHEN m.metric = 'cardiac_drift_pct' THEN m.value END)"
+        " FROM acts JOIN activity_metrics m ON m.account_id = ?2 AND m.activity_id = acts.id"
+        " AND m.metric IN ('hrr60_bpm', 'hrr60_efforts', 'cardiac_drift_pct')"
+        " GROUP BY acts.id, acts.day ORDER BY acts.day, acts.id";
+    const char *week_sql =
+        "WITH acts AS ("
+        " SELECT json_extract(a.value, '$.id') AS id, " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?5") " AS day"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND json_extract(a.value, '$.id') IS NOT NULL AND substr(json_extract(a.value, '$.date'), 1, 10) >= ?3)"
+        " SELECT date(acts.day, 'weekday ' || ?4, '-6 days') AS week,"
+        " AVG(CASE WHEN m.metric = 'hrr60_bpm' THEN m.value END),"
+        " AVG(CASE WHEN m.metric = 'cardiac_drift_pct' THEN m.value END)"
+        " FROM acts JOIN activity_metrics m ON m.account_id = ?2 AND m.activity_id = acts.id"
+        " AND m.metric IN ('hrr60_bpm', 'cardiac_drift_pct')"
+        " GROUP BY week ORDER BY week";
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"days\":%d,\"activities\":[", days);
+    for (int pass = 0; pass < 2; pass++) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, pass == 0 ? activity_sql : week_sql, -1, &stmt, NULL) != SQLITE_OK) {
+            strbuf_free(&sb);
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 4, week.start_weekday);
+        sqlite3_bind_int(stmt, 5, week.rollover_hour);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            if (count > 0) strbuf_append(&sb, ",", 1);
+            if (pass == 0) {
+                strbuf_append(&sb, "{\"id\":", 6);
+                strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+                strbuf_appendf(&sb, ",\"date\":\"%s\"", (const char *)sqlite3_column_text(stmt, 1));
+                append_optional_metric(&sb, "hrr60_bpm", stmt, 2);
+                strbuf_appendf(&sb, ",\"hrr60_efforts\":%d", sqlite3_column_int(stmt, 3));
+                append_optional_metric(&sb, "cardiac_drift_pct", stmt, 4);
+            } else {
+                strbuf_appendf(&sb, "{\"week_start\":\"%s\"", (const char *)sqlite3_column_text(stmt, 0));
+                append_optional_metric(&sb, "hrr60_bpm", stmt, 1);
+                append_optional_metric(&sb, "cardiac_drift_pct", stmt, 2);
+            }
+            strbuf_append(&sb, "}", 1);
+            count++;
+        }
+        sqlite3_finalize(stmt);
+        strbuf_append(&sb, pass == 0 ? "],\"weeks\":[" : "]}", pass == 0 ? 11 : 2);
+    }
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
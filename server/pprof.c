@@ -0,0 +1,698 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <dlfcn.h>
+#include <errno.h>
+#include <execinfo.h>
+#include <fcntl.h>
+#include <pthread.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/stat.h>
+#include <sys/time.h>
+#include <time.h>
+#include <unistd.h>
+
+#if defined(__linux__)
+#include <elf.h>
+#include <link.h>
+#endif
+
+/*
+ * On-demand CPU profiling. GET /v1/admin/pprof/cpu?seconds=10 samples the whole process with
+ * SIGPROF at PPROF_HZ for that long (1..60 s), then answers with a flamegraph SVG (default),
+ * ?format=pprof (profile.proto, readable by `go tool pprof`, which can re-symbolize static
+ * functions from the binary using the mappings) or ?format=folded (one "a;b;c count" line per
+ * stack, for flamegraph.pl or speedscope). Only one profile runs at a time; the worker that serves
+ * it waits out the interval. Frames in the server binary are named from its own symbol table
+ * (static functions included, unless it was stripped), others via dladdr; anything unresolved
+ * shows up as file+offset. Nothing is sampled between profiles.
+ */
+
+#define PPROF_HZ 99
+#define PPROF_DEFAULT_SECONDS 10
+#define PPROF_MAX_SECONDS 60
+#define PPROF_MAX_DEPTH 48
+#define PPROF_MAX_SAMPLES 16384
+/* The handler's own frame and the signal trampoline. */
+#define PPROF_SKIP_FRAMES 2
+#define PPROF_MAX_MAPPINGS 256
+#define FLAME_WIDTH 1200
+#define FLAME_ROW 16
+
+typedef struct {
+    int depth;
+    void *frames[PPROF_MAX_DEPTH];
+} cpu_sample_t;
+
+typedef struct {
+    uintptr_t address;
+    int function;
+    int mapping;
+} pprof_location_t;
+
+typedef struct {
+    uintptr_t start;
+    uintptr_t limit;
+    uintptr_t offset;
+    char path[256];
+} pprof_mapping_t;
+
+typedef struct {
+    int depth;
+    int locations[PPROF_MAX_DEPTH];
+    long long count;
+} pprof_stack_t;
+
+typedef struct {
+    int function;
+    int first_child;
+    int next_sibling;
+    long long count;
+} flame_node_t;
+
+typedef struct {
+    size_t location_count;
+    pprof_location_t *locations;
+    size_t function_count;
+    char **functions;
+    size_t mapping_count;
+    pprof_mapping_t mappings[PPROF_MAX_MAPPINGS];
+    size_t stack_count;
+    pprof_stack_t *stacks;
+    long long samples;
+} cpu_profile_t;
+
+typedef struct {
+    uintptr_t start;
+    uintptr_t size;
+    char *name;
+} elf_symbol_t;
+
+static int g_profile_running;
+static pthread_once_t g_symbols_once = PTHREAD_ONCE_INIT;
+static elf_symbol_t *g_symbols;
+static size_t g_symbol_count;
+static int g_handler_installed;
+static volatile sig_atomic_t g_sampling;
+static cpu_sample_t *g_samples;
+static int g_sample_count;
+static long long g_samples_dropped;
+
+static void sigprof_handler(int sig) {
+    (void)sig;
+    int saved_errno = errno;
+    if (g_sampling) {
+        int slot = __atomic_fetch_add(&g_sample_count, 1, __ATOMIC_RELAXED);
+        if (slot < PPROF_MAX_SAMPLES) {
+            g_samples[slot].depth = backtrace(g_samples[slot].frames, PPROF_MAX_DEPTH);
+        } else {
+            __atomic_add_fetch(&g_samples_dropped, 1, __ATOMIC_RELAXED);
+        }
+    }
+    errno = saved_errno;
+}
+
+static int set_profile_timer(int hz) {
+    struct itimerval timer;
+    memset(&timer, 0, sizeof(timer));
+    if (hz > 0) {
+        timer.it_interval.tv_usec = 1000000 / hz;
+        timer.it_value = timer.it_interval;
+    }
+    return setitimer(ITIMER_PROF, &timer, NULL);
+}
+
+static void sleep_seconds(int seconds) {
+    struct timespec remaining = {.tv_sec = seconds, .tv_nsec = 0};
+    while (nanosleep(&remaining, &remaining) != 0 && errno == EINTR) {
+    }
+}
+
+/* /proc/self/maps executable segments, so pprof can map addresses back to files. */
+static void load_mappings(cpu_profile_t *profile) {
+    FILE *fp = fopen("/proc/self/maps", "r");
+    if (!fp) return;
+    char line[512];
+    while (profile->mapping_count < PPROF_MAX_MAPPINGS && fgets(line, sizeof(line), fp)) {
+        unsigned long start = 0;
+        unsigned long limit = 0;
+        unsigned long offset = 0;
+        char perms[8] = {0};
+        char path[256] = {0};
+        if (sscanf(line, "%lx-%lx %7s %lx %*s %*s %255s", &start, &limit, perms, &offset, path) != 5 || perms[2] != 'x' || path[0] != '/') continue;
+        pprof_mapping_t *mapping = &profile->mappings[profile->mapping_count++];
+        mapping->start = (uintptr_t)start;
+        mapping->limit = (uintptr_t)limit;
+        mapping->offset = (uintptr_t)offset;
+        snprintf(mapping->path, sizeof(mapping->path), "%s", path);
+    }
+    fclose(fp);
+}
+
+static int find_mapping(const cpu_profile_t *profile, uintptr_t address) {
+    for (size_t i = 0; i < profile->mapping_count; i++) {
+        if (address >= profile->mappings[i].start && address < profile->mappings[i].limit) return (int)i;
+    }
+    return -1;
+}
+
+static int compare_symbol(const void *a, const void *b) {
+    const elf_symbol_t *lhs = (const elf_symbol_t *)a;
+    const elf_symbol_t *rhs = (const elf_symbol_t *)b;
+    return lhs->start < rhs->start ? -1 : lhs->start > rhs->start;
+}
+
+/* Function symbols of the running executable from its .symtab, relocated by the load bias. */
+static void load_executable_symbols(void) {
+#if defined(__linux__)
+    int fd = open("/proc/self/exe", O_RDONLY);
+    struct stat st;
+    if (fd < 0 || fstat(fd, &st) != 0 || (size_t)st.st_size < sizeof(ElfW(Ehdr))) {
+        if (fd >= 0) close(fd);
+        return;
+    }
+    size_t size = (size_t)st.st_size;
+    unsigned char *image = (unsigned char *)mmap(NULL, size, PROT_READ, MAP_PRIVATE, fd, 0);
+    close(fd);
+    if (image == MAP_FAILED) return;
+    const ElfW(Ehdr) *header = (const ElfW(Ehdr) *)image;
+    int valid = memcmp(header->e_ident, ELFMAG, SELFMAG) == 0 && header->e_ident[EI_CLASS] == (sizeof(void *) == 8 ? ELFCLASS64 : ELFCLASS32) &&
+                header->e_shoff > 0 && header->e_shoff + (size_t)header->e_shnum * sizeof(ElfW(Shdr)) <= size;
+    const ElfW(Shdr) *sections = valid ? (const ElfW(Shdr) *)(image + header->e_shoff) : NULL;
+    /* Position-independent executables are loaded at a bias; the first link-map entry is the executable. */
+    uintptr_t bias = header->e_type == ET_DYN ? (uintptr_t)_r_debug.r_map->l_addr : 0;
+    for (size_t i = 0; sections && i < header->e_shnum && !g_symbols; i++) {
+        if (sections[i].sh_type != SHT_SYMTAB || sections[i].sh_link >= header->e_shnum) continue;
+        const ElfW(Shdr) *strtab = &sections[sections[i].sh_link];
+        if (sections[i].sh_offset + sections[i].sh_size > size || strtab->sh_offset + strtab->sh_size > size) break;
+        const ElfW(Sym) *symbols = (const ElfW(Sym) *)(image + sections[i].sh_offset);
+        size_t count = sections[i].sh_size / sizeof(ElfW(Sym));
+        elf_symbol_t *out = (elf_symbol_t *)calloc(count + 1, sizeof(elf_symbol_t));
+        if (!out) break;
+        size_t kept = 0;
+        for (size_t s = 0; s < count; s++) {
+            if (ELF32_ST_TYPE(symbols[s].st_info) != STT_FUNC || symbols[s].st_value == 0 || symbols[s].st_name >= strtab->sh_size) continue;
+            out[kept].start = bias + (uintptr_t)symbols[s].st_value;
+            out[kept].size = (uintptr_t)symbols[s].st_size;
+            out[kept].name = strndup((const char *)image + strtab->sh_offset + symbols[s].st_name, 255);
+            if (out[kept].name) kept++;
+        }
+        qsort(out, kept, sizeof(elf_symbol_t), compare_symbol);
+        g_symbols = out;
+        g_symbol_count = kept;
+    }
+    munmap(image, size);
+#endif
+}
+
+static const char *executable_symbol(uintptr_t address) {
+    pthread_once(&g_symbols_once, load_executable_symbols);
+    size_t lo = 0;
+    size_t hi = g_symbol_count;
+    while (lo < hi) {
+        size_t mid = (lo + hi) / 2;
+        if (g_symbols[mid].start <= address) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if (lo == 0) return NULL;
+    const elf_symbol_t *symbol = &g_symbols[lo - 1];
+    return address < symbol->start + (symbol->size > 0 ? symbol->size : 1) ? symbol->name : NULL;
+}
+
+static char *symbol_name(uintptr_t address) {
+    Dl_info info;
+    char name[320] = {0};
+    const char *own = executable_symbol(address);
+    if (own) {
+        snprintf(name, sizeof(name), "%s", own);
+    } else if (dladdr((void *)address, &info) && info.dli_sname) {
+        snprintf(name, sizeof(name), "%s", info.dli_sname);
+    } else if (dladdr((void *)address, &info) && info.dli_fname) {
+        const char *base = strrchr(info.dli_fname, '/');
+        snprintf(name, sizeof(name), "%s+0x%lx", base ? base + 1 : info.dli_fname, (unsigned long)(address - (uintptr_t)info.dli_fbase));
+    } else {
+        snprintf(name, sizeof(name), "0x%lx", (unsigned long)address);
+    }
+    return strdup(name);
+}
+
+static int compare_address(const void *a, const void *b) {
+    uintptr_t lhs = *(const uintptr_t *)a;
+    uintptr_t rhs = *(const uintptr_t *)b;
+    return lhs < rhs ? -1 : lhs > rhs;
+}
+
+static int compare_name(const void *a, const void *b) {
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+static int compare_stack(const void *a, const void *b) {
+    const pprof_stack_t *lhs = (const pprof_stack_t *)a;
+    const pprof_stack_t *rhs = (const pprof_stack_t *)b;
+    if (lhs->depth != rhs->depth) return lhs->depth - rhs->depth;
+    return memcmp(lhs->locations, rhs->locations, (size_t)lhs->depth * sizeof(int));
+}
+
+/* Return addresses point after the call; one byte back lands inside it, as pprof expects. */
+static uintptr_t frame_address(const cpu_sample_t *sample, int i) {
+    uintptr_t address = (uintptr_t)sample->frames[i];
+    return i > PPROF_SKIP_FRAMES && address > 0 ? address - 1 : address;
+}
+
+static size_t location_index(const cpu_profile_t *profile, uintptr_t address) {
+    size_t lo = 0;
+    size_t hi = profile->location_count;
+    while (lo < hi) {
+        size_t mid = (lo + hi) / 2;
+        if (profile->locations[mid].address < address) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    return lo;
+}
+
+static void profile_free(cpu_profile_t *profile) {
+    for (size_t i = 0; i < profile->function_count; i++) free(profile->functions[i]);
+    free(profile->functions);
+    free(profile->locations);
+    free(profile->stacks);
+}
+
+/* Turns raw samples into unique locations, functions and counted stacks (leaf first). */
+static int profile_build(cpu_profile_t *profile, int sample_count) {
+    memset(profile, 0, sizeof(*profile));
+    load_mappings(profile);
+    size_t frame_total = 0;
+    for (int s = 0; s < sample_count; s++) {
+        if (g_samples[s].depth > PPROF_SKIP_FRAMES) frame_total += (size_t)(g_samples[s].depth - PPROF_SKIP_FRAMES);
+    }
+    uintptr_t *addresses = (uintptr_t *)malloc((frame_total + 1) * sizeof(uintptr_t));
+    profile->stacks = (pprof_stack_t *)calloc((size_t)sample_count + 1, sizeof(pprof_stack_t));
+    if (!addresses || !profile->stacks) {
+        free(addresses);
+        return -1;
+    }
+    size_t n = 0;
+    for (int s = 0; s < sample_count; s++) {
+        for (int i = PPROF_SKIP_FRAMES; i < g_samples[s].depth; i++) addresses[n++] = frame_address(&g_samples[s], i);
+    }
+    qsort(addresses, n, sizeof(uintptr_t), compare_address);
+    profile->locations = (pprof_location_t *)calloc(n + 1, sizeof(pprof_location_t));
+    char **names = (char **)calloc(n + 1, sizeof(char *));
+    if (!profile->locations || !names) {
+        free(addresses);
+        free(names);
+        return -1;
+    }
+    for (size_t i = 0; i < n; i++) {
+        if (i > 0 && addresses[i] == addresses[i - 1]) continue;
+        pprof_location_t *location = &profile->locations[profile->location_count];
+        location->address = addresses[i];
+        location->mapping = find_mapping(profile, addresses[i]);
+        names[profile->location_count++] = symbol_name(addresses[i]);
+    }
+    free(addresses);
+
+    /* Functions are the distinct names; each location points at its name's slot. */
+    profile->functions = (char **)calloc(profile->location_count + 1, sizeof(char *));
+    char **sorted = (char **)calloc(profile->location_count + 1, sizeof(char *));
+    if (!profile->functions || !sorted) {
+        for (size_t i = 0; i < profile->location_count; i++) free(names[i]);
+        free(names);
+        free(sorted);
+        return -1;
+    }
+    memcpy(sorted, names, profile->location_count * sizeof(char *));
+    qsort(sorted, profile->location_count, sizeof(char *), compare_name);
+    for (size_t i = 0; i < profile->location_count; i++) {
+        if (!sorted[i] || (profile->function_count > 0 && strcmp(sorted[i], profile->functions[profile->function_count - 1]) == 0)) continue;
+        profile->functions[profile->function_count++] = strdup(sorted[i]);
+    }
+    for (size_t i = 0; i < profile->location_count; i++) {
+        char **found = names[i] ? (char **)bsearch(&names[i], profile->functions, profile->function_count, sizeof(char *), compare_name) : NULL;
+        profile->locations[i].function = found ? (int)(found - profile->functions) : -1;
+        free(names[i]);
+    }
+    free(names);
+    free(sorted);
+
+    for (int s = 0; s < sample_count; s++) {
+        if (g_samples[s].depth <= PPROF_SKIP_FRAMES) continue;
+        pprof_stack_t *stack = &profile->stacks[profile->stack_count++];
+        for (int i = PPROF_SKIP_FRAMES; i < g_samples[s].depth; i++) {
+            stack->locations[stack->depth++] = (int)location_index(profile, frame_address(&g_samples[s], i));
+        }
+        stack->count = 1;
+        profile->samples++;
+    }
+    qsort(profile->stacks, profile->stack_count, sizeof(pprof_stack_t), compare_stack);
+    size_t unique = 0;
+    for (size_t i = 0; i < profile->stack_count; i++) {
+        if (unique > 0 && compare_stack(&profile->stacks[unique - 1], &profile->stacks[i]) == 0) {
+            profile->stacks[unique - 1].count++;
+        } else {
+            profile->stacks[unique++] = profile->stacks[i];
+        }
+    }
+    profile->stack_count = unique;
+    return 0;
+}
+
+static const char *location_name(const cpu_profile_t *profile, int location) {
+    int function = profile->locations[location].function;
+    return function >= 0 ? profile->functions[function] : "?";
+}
+
+static void render_folded(const cpu_profile_t *profile, strbuf_t *sb) {
+    for (size_t s = 0; s < profile->stack_count; s++) {
+        const pprof_stack_t *stack = &profile->stacks[s];
+        for (int i = stack->depth - 1; i >= 0; i--) {
+            const char *name = location_name(profile, stack->locations[i]);
+            strbuf_append(sb, name, strlen(name));
+            if (i > 0) strbuf_append(sb, ";", 1);
+        }
+        strbuf_appendf(sb, " %lld\n", stack->count);
+    }
+}
+
+static void append_xml_escaped(strbuf_t *sb, const char *s, size_t max_chars) {
+    for (size_t i = 0; s[i] && i < max_chars; i++) {
+        switch (s[i]) {
+            case '<':
+                strbuf_append(sb, "&lt;", 4);
+                break;
+            case '>':
+                strbuf_append(sb, "&gt;", 4);
+                break;
+            case '&':
+                strbuf_append(sb, "&amp;", 5);
+                break;
+            case '"':
+                strbuf_append(sb, "&quot;", 6);
+                break;
+            default:
+                strbuf_append(sb, s + i, 1);
+        }
+    }
+}
+
+/* Finds or adds the child of parent for a function; nodes[0] is the root ("all"). */
+static int flame_child(flame_node_t *nodes, int *count, int parent, int function) {
+    for (int child = nodes[parent].first_child; child >= 0; child = nodes[child].next_sibling) {
+        if (nodes[child].function == function) return child;
+    }
+    int node = (*count)++;
+    nodes[node].function = function;
+    nodes[node].first_child = -1;
+    nodes[node].next_sibling = nodes[parent].first_child;
+    nodes[node].count = 0;
+    nodes[parent].first_child = node;
+    return node;
+}
+
+static void render_flame_node(const cpu_profile_t *profile, const flame_node_t *nodes, int node, int depth, double x, double scale, int height, strbuf_t *sb) {
+    double width = (double)nodes[node].count * scale;
+    if (width < 0.5) return;
+    const char *name = nodes[node].function >= 0 ? profile->functions[nodes[node].function] : "all";
+    unsigned hash = 5381;
+    for (const char *p = name; *p; p++) hash = hash * 33 + (unsigned char)*p;
+    int y = height - (depth + 1) * FLAME_ROW - 10;
+    strbuf_append(sb, "<g><title>", 10);
+    append_xml_escaped(sb, name, 512);
+    strbuf_appendf(
+        sb,
+        " (%lld samples, %.2f%%)</title><rect x=\"%.1f\" y=\"%d\" width=\"%.1f\" height=\"%d\" fill=\"rgb(%u,%u,%u)\" rx=\"2\"/>",
+        nodes[node].count,
+        100.0 * (double)nodes[node].count / (double)(nodes[0].count > 0 ? nodes[0].count : 1),
+        x,
+        y,
+        width,
+        FLAME_ROW - 1,
+        205 + hash % 50,
+        (hash / 50) % 180,
+        (hash / 9000) % 55);
+    size_t fits = width > 12 ? (size_t)((width - 6) / 7) : 0;
+    if (fits > 1) {
+        strbuf_appendf(sb, "<text x=\"%.1f\" y=\"%d\">", x + 3, y + FLAME_ROW - 4);
+        append_xml_escaped(sb, name, strlen(name) > fits ? fits - 1 : fits);
+        if (strlen(name) > fits) strbuf_append(sb, "..", 2);
+        strbuf_append(sb, "</text>", 7);
+    }
+    strbuf_append(sb, "</g>\n", 5);
+    /* Children are prepended on insert; walking them gives a stable, if reversed, order. */
+    for (int child = nodes[node].first_child; child >= 0; child = nodes[child].next_sibling) {
+        render_flame_node(profile, nodes, child, depth + 1, x, scale, height, sb);
+        x += (double)nodes[child].count * scale;
+    }
+}
+
+static int render_flamegraph(const cpu_profile_t *profile, int seconds, strbuf_t *sb) {
+    size_t capacity = 1;
+    for (size_t s = 0; s < profile->stack_count; s++) capacity += (size_t)profile->stacks[s].depth;
+    flame_node_t *nodes = (flame_node_t *)calloc(capacity, sizeof(flame_node_t));
+    if (!nodes) return -1;
+    int count = 1;
+    nodes[0].function = -1;
+    nodes[0].first_child = -1;
+    nodes[0].next_sibling = -1;
+    int max_depth = 0;
+    for (size_t s = 0; s < profile->stack_count; s++) {
+        const pprof_stack_t *stack = &profile->stacks[s];
+        int node = 0;
+        nodes[0].count += stack->count;
+        for (int i = stack->depth - 1; i >= 0; i--) {
+            node = flame_child(nodes, &count, node, profile->locations[stack->locations[i]].function);
+            nodes[node].count += stack->count;
+        }
+        if (stack->depth > max_depth) max_depth = stack->depth;
+    }
+    int height = (max_depth + 1) * FLAME_ROW + 50;
+    strbuf_appendf(
+        sb,
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n"
+        "<svg version=\"1.1\" width=\"%d\" height=\"%d\" viewBox=\"0 0 %d %d\" xmlns=\"http://www.w3.org/2000/svg\">\n"
+        "<style>text{font-family:Verdana,sans-serif;font-size:12px;fill:#000}rect:hover{stroke:#000}</style>\n"
+        "<rect width=\"100%%\" height=\"100%%\" fill=\"#fdf6e3\"/>\n"
+        "<text x=\"%d\" y=\"24\" text-anchor=\"middle\" style=\"font-size:17px\">CPU flame graph: %lld samples over %d s at %d Hz</text>\n",
+        FLAME_WIDTH,
+        height,
+        FLAME_WIDTH,
+        height,
+        FLAME_WIDTH / 2,
+        profile->samples,
+        seconds,
+        PPROF_HZ);
+    if (nodes[0].count > 0) render_flame_node(profile, nodes, 0, 0, 10.0, (double)(FLAME_WIDTH - 20) / (double)nodes[0].count, height, sb);
+    strbuf_append(sb, "</svg>\n", 7);
+    free(nodes);
+    return 0;
+}
+
+static void pb_varint(strbuf_t *sb, uint64_t value) {
+    char buf[10];
+    size_t n = 0;
+    do {
+        buf[n] = (char)(value & 0x7f);
+        value >>= 7;
+        if (value) buf[n] |= (char)0x80;
+        n++;
+    } while (value);
+    strbuf_append(sb, buf, n);
+}
+
+static void pb_uint(strbuf_t *sb, int field, uint64_t value) {
+    pb_varint(sb, (uint64_t)field << 3);
+    pb_varint(sb, value);
+}
+
+static void pb_bytes(strbuf_t *sb, int field, const char *data, size_t len) {
+    pb_varint(sb, ((uint64_t)field << 3) | 2);
+    pb_varint(sb, len);
+    if (len > 0) strbuf_append(sb, data, len);
+}
+
+static void pb_message(strbuf_t *sb, int field, strbuf_t *message) {
+    pb_bytes(sb, field, message->data, message->len);
+    strbuf_free(message);
+    strbuf_init(message);
+}
+
+/* profile.proto string table: 0 "", 1-4 the value types, then functions, then mapping files. */
+#define PB_STR_SAMPLES 1
+#define PB_STR_COUNT 2
+#define PB_STR_CPU 3
+#define PB_STR_NANOSECONDS 4
+#define PB_STR_FIRST_FUNCTION 5
+
+static void render_pprof(const cpu_profile_t *profile, long long start_ns, long long duration_ns, strbuf_t *sb) {
+    long long period_ns = 1000000000LL / PPROF_HZ;
+    strbuf_t message;
+    strbuf_t inner;
+    strbuf_init(&message);
+    strbuf_init(&inner);
+    pb_uint(&message, 1, PB_STR_SAMPLES);
+    pb_uint(&message, 2, PB_STR_COUNT);
+    pb_message(sb, 1, &message);
+    pb_uint(&message, 1, PB_STR_CPU);
+    pb_uint(&message, 2, PB_STR_NANOSECONDS);
+    pb_message(sb, 1, &message);
+    for (size_t s = 0; s < profile->stack_count; s++) {
+        const pprof_stack_t *stack = &profile->stacks[s];
+        for (int i = 0; i < stack->depth; i++) pb_varint(&inner, (uint64_t)stack->locations[i] + 1);
+        pb_message(&message, 1, &inner);
+        pb_varint(&inner, (uint64_t)stack->count);
+        pb_varint(&inner, (uint64_t)(stack->count * period_ns));
+        pb_message(&message, 2, &inner);
+        pb_message(sb, 2, &message);
+    }
+    uint64_t mapping_strings = PB_STR_FIRST_FUNCTION + profile->function_count;
+    for (size_t m = 0; m < profile->mapping_count; m++) {
+        pb_uint(&message, 1, m + 1);
+        pb_uint(&message, 2, profile->mappings[m].start);
+        pb_uint(&message, 3, profile->mappings[m].limit);
+        pb_uint(&message, 4, profile->mappings[m].offset);
+        pb_uint(&message, 5, mapping_strings + m);
+        pb_message(sb, 3, &message);
+    }
+    for (size_t l = 0; l < profile->location_count; l++) {
+        const pprof_location_t *location = &profile->locations[l];
+        pb_uint(&message, 1, l + 1);
+        if (location->mapping >= 0) pb_uint(&message, 2, (uint64_t)location->mapping + 1);
+        pb_uint(&message, 3, location->address);
+        if (location->function >= 0) {
+            pb_uint(&inner, 1, (uint64_t)location->function + 1);
+            pb_message(&message, 4, &inner);
+        }
+        pb_message(sb, 4, &message);
+    }
+    for (size_t f = 0; f < profile->function_count; f++) {
+        pb_uint(&message, 1, f + 1);
+        pb_uint(&message, 2, PB_STR_FIRST_FUNCTION + f);
+        pb_uint(&message, 3, PB_STR_FIRST_FUNCTION + f);
+        pb_message(sb, 5, &message);
+    }
+    static const char *FIXED_STRINGS[] = {"", "samples", "count", "cpu", "nanoseconds"};
+    for (size_t i = 0; i < sizeof(FIXED_STRINGS) / sizeof(FIXED_STRINGS[0]); i++) pb_bytes(sb, 6, FIXED_STRINGS[i], strlen(FIXED_STRINGS[i]));
+    for (size_t f = 0; f < profile->function_count; f++) pb_bytes(sb, 6, profile->functions[f], strlen(profile->functions[f]));
+    for (size_t m = 0; m < profile->mapping_count; m++) pb_bytes(sb, 6, profile->mappings[m].path, strlen(profile->mappings[m].path));
+    pb_uint(sb, 9, (uint64_t)start_ns);
+    pb_uint(sb, 10, (uint64_t)duration_ns);
+    pb_uint(&message, 1, PB_STR_CPU);
+    pb_uint(&message, 2, PB_STR_NANOSECONDS);
+    pb_message(sb, 11, &message);
+    pb_uint(sb, 12, (uint64_t)period_ns);
+    strbuf_free(&message);
+    strbuf_free(&inner);
+}
+
+static int install_handler(void) {
+    if (__atomic_load_n(&g_handler_installed, __ATOMIC_ACQUIRE)) return 0;
+    /* backtrace() loads libgcc on first use, which must not happen inside the signal handler. */
+    void *warmup[2];
+    backtrace(warmup, 2);
+    struct sigaction action;
+    memset(&action, 0, sizeof(action));
+    action.sa_handler = sigprof_handler;
+    action.sa_flags = SA_RESTART;
+    sigemptyset(&action.sa_mask);
+    if (sigaction(SIGPROF, &action, NULL) != 0) return -1;
+    __atomic_store_n(&g_handler_installed, 1, __ATOMIC_RELEASE);
+    return 0;
+}
+
+int handle_get_admin_pprof_cpu(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    int seconds = PPROF_DEFAULT_SECONDS;
+    char raw[16] = {0};
+    if (query_param(req->query, "seconds", raw, sizeof(raw))) {
+        char *end = NULL;
+        long value = strtol(raw, &end, 10);
+        seconds = end && *end == '\0' && value >= 1 && value <= PPROF_MAX_SECONDS ? (int)value : -1;
+    }
+    char format[16] = {0};
+    if (!query_param(req->query, "format", format, sizeof(format)) || format[0] == '\0') snprintf(format, sizeof(format), "svg");
+    if (seconds < 0 || (strcmp(format, "svg") != 0 && strcmp(format, "pprof") != 0 && strcmp(format, "folded") != 0)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"seconds must be 1..60 and format svg, pprof or folded\"}", ctx);
+        return 400;
+    }
+    int idle = 0;
+    if (!__atomic_compare_exchange_n(&g_profile_running, &idle, 1, 0, __ATOMIC_ACQ_REL, __ATOMIC_ACQUIRE)) {
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"a CPU profile is already running\"}", ctx);
+        return 409;
+    }
+    g_samples = (cpu_sample_t *)calloc(PPROF_MAX_SAMPLES, sizeof(cpu_sample_t));
+    if (!g_samples || install_handler() != 0) {
+        free(g_samples);
+        g_samples = NULL;
+        __atomic_store_n(&g_profile_running, 0, __ATOMIC_RELEASE);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"cannot start profiler\"}", ctx);
+        return 500;
+    }
+    __atomic_store_n(&g_sample_count, 0, __ATOMIC_RELAXED);
+    __atomic_store_n(&g_samples_dropped, 0, __ATOMIC_RELAXED);
+    log_warn("CPU PROFILE started seconds=%d format=%s logid=%s", seconds, format, ctx->log_id);
+    struct timespec started;
+    clock_gettime(CLOCK_REALTIME, &started);
+    double start_ms = slowlog_now_ms();
+    g_sampling = 1;
+    set_profile_timer(PPROF_HZ);
+    sleep_seconds(seconds);
+    set_profile_timer(0);
+    g_sampling = 0;
+    long long duration_ns = (long long)((slowlog_now_ms() - start_ms) * 1e6);
+    /* Let a handler that claimed a slot just before the stop finish writing it. */
+    struct timespec settle = {.tv_sec = 0, .tv_nsec = 20 * 1000 * 1000};
+    nanosleep(&settle, NULL);
+
+    int sample_count = __atomic_load_n(&g_sample_count, __ATOMIC_RELAXED);
+    if (sample_count > PPROF_MAX_SAMPLES) sample_count = PPROF_MAX_SAMPLES;
+    long long dropped = __atomic_load_n(&g_samples_dropped, __ATOMIC_RELAXED);
+    cpu_profile_t profile;
+    int built = profile_build(&profile, sample_count);
+    free(g_samples);
+    g_samples = NULL;
+    __atomic_store_n(&g_profile_running, 0, __ATOMIC_RELEASE);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    const char *content_type = "image/svg+xml";
+    const char *disposition = "";
+    if (built == 0 && strcmp(format, "pprof") == 0) {
+        render_pprof(&profile, (long long)started.tv_sec * 1000000000LL + started.tv_nsec, duration_ns, &sb);
+        content_type = "application/octet-stream";
+        disposition = "Content-Disposition: attachment; filename=\"cpu.pb\"\r\n";
+    } else if (built == 0 && strcmp(format, "folded") == 0) {
+        render_folded(&profile, &sb);
+        content_type = "text/plain; charset=utf-8";
+    } else if (built == 0 && render_flamegraph(&profile, seconds, &sb) != 0) {
+        sb.failed = 1;
+    }
+    long long samples = built == 0 ? profile.samples : 0;
+    profile_free(&profile);
+    if (built != 0 || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    char headers[256] = {0};
+    snprintf(headers, sizeof(headers), "%sX-Fricu-Profile-Samples: %lld\r\nX-Fricu-Profile-Dropped: %lld\r\n", disposition, samples, dropped);
+    size_t bytes = sb.len;
+    send_http_response(fd, 200, "OK", content_type, headers, sb.data ? sb.data : "", bytes, ctx);
+    strbuf_free(&sb);
+    log_info("CPU PROFILE done samples=%lld dropped=%lld bytes=%zu logid=%s", samples, dropped, bytes, ctx->log_id);
+    return 200;
+}
@@ -0,0 +1,70 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Health data minimization. With FRICU_DATA_MINIMIZATION=1 (every account) or
+ * app_settings.dataMinimization = true (one account) the server keeps activity summaries only:
+ * raw heart-rate samples and the original device files (which carry the precise GPS track) are
+ * dropped from activities and archived_activities before they are stored, whichever path wrote
+ * them (sync, item API, imports, live sessions). Connectors stop copying original files and leave
+ * the samples out of weekly bundles, and the admin validate report lists stored items that still
+ * hold either so they can be stripped after the mode is switched on.
+ */
+
+int data_minimization_forced(void) {
+    const char *env = getenv("FRICU_DATA_MINIMIZATION");
+    return env && strcmp(env, "1") == 0;
+}
+
+int data_minimization_enabled(sqlite3 *db, const char *account_id) {
+    if (data_minimization_forced()) return 1;
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "app_settings", storage_key, sizeof(storage_key)) != 0) return 0;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_type(data_value, '$.dataMinimization') = 'true' FROM kv_store WHERE data_key = ?1 AND json_valid(data_value)",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return 0;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int enabled = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    sqlite3_finalize(stmt);
+    return enabled;
+}
+
+char *data_minimize_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len) {
+    if (strcmp(key, "activities") != 0 && strcmp(key, "archived_activities") != 0) return NULL;
+    if (!data_minimization_enabled(db, account_id)) return NULL;
+    /* Only rebuild when something is removed, so untouched documents keep their exact bytes and version. */
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT CASE WHEN json_type(?1) = 'array' AND EXISTS (SELECT 1 FROM json_each(?1) WHERE " DATA_MINIMIZED_ITEM_SQL("value", "type") ")"
+            " THEN (SELECT json_group_array(CASE WHEN type = 'object' THEN json_remove(value, " DATA_MINIMIZED_PATHS_SQL ")"
+            "  ELSE CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END END)"
+            "  FROM (SELECT value, type FROM json_each(?1) ORDER BY key)) END",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, payload, (int)payload_len, SQLITE_STATIC);
+    char *minimized = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        minimized = strdup((const char *)sqlite3_column_text(stmt, 0));
+        if (minimized) log_info("DATA WRITE minimized key=%s account=%s bytes=%zu->%zu", key, account_id, payload_len, strlen(minimized));
+    }
+    sqlite3_finalize(stmt);
+    return minimized;
+}
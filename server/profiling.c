@@ -0,0 +1,159 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/resource.h>
+#include <time.h>
+#include <unistd.h>
+
+#if defined(__APPLE__)
+#include <malloc/malloc.h>
+#elif defined(__GLIBC__)
+#include <malloc.h>
+#endif
+
+static int g_profiling_enabled;
+static size_t g_worker_count;
+static long long g_open_connections;
+static long long g_connection_buffer_bytes;
+static long long g_in_flight_requests;
+static long long g_requests_total;
+
+void profiling_enable(size_t worker_count) {
+    __atomic_store_n(&g_worker_count, worker_count, __ATOMIC_RELAXED);
+    __atomic_store_n(&g_profiling_enabled, 1, __ATOMIC_RELEASE);
+    log_warn("debug profiling enabled: /v1/admin/debug/* endpoints are active");
+}
+
+void profiling_disable(void) {
+    __atomic_store_n(&g_profiling_enabled, 0, __ATOMIC_RELEASE);
+}
+
+void profiling_note_connection(long long connections_delta, long long buffer_bytes_delta) {
+    __atomic_add_fetch(&g_open_connections, connections_delta, __ATOMIC_RELAXED);
+    __atomic_add_fetch(&g_connection_buffer_bytes, buffer_bytes_delta, __ATOMIC_RELAXED);
+}
+
+void profiling_note_request(int delta) {
+    __atomic_add_fetch(&g_in_flight_requests, delta, __ATOMIC_RELAXED);
+    if (delta > 0) __atomic_add_fetch(&g_requests_total, 1, __ATOMIC_RELAXED);
+}
+
+static long long current_rss_bytes(void) {
+#if defined(__linux__)
+    FILE *f = fopen("/proc/self/statm", "r");
+    if (!f) return -1;
+    long long pages_total = 0;
+    long long pages_resident = 0;
+    int matched = fscanf(f, "%lld %lld", &pages_total, &pages_resident);
+    fclose(f);
+    if (matched != 2) return -1;
+    return pages_resident * (long long)sysconf(_SC_PAGESIZE);
+#else
+    return -1;
+#endif
+}
+
+static long long peak_rss_bytes(void) {
+    struct rusage usage;
+    if (getrusage(RUSAGE_SELF, &usage) != 0) return -1;
+#if defined(__APPLE__)
+    return (long long)usage.ru_maxrss;
+#else
+    return (long long)usage.ru_maxrss * 1024LL;
+#endif
+}
+
+static void append_allocator_stats(strbuf_t *sb) {
+#if defined(__APPLE__)
+    malloc_statistics_t stats;
+    malloc_zone_statistics(NULL, &stats);
+    strbuf_appendf(
+        sb,
+        "\"allocator\":{\"impl\":\"darwin\",\"in_use_bytes\":%zu,\"allocated_bytes\":%zu,\"blocks_in_use\":%u}",
+        stats.size_in_use,
+        stats.size_allocated,
+        stats.blocks_in_use);
+#elif defined(__GLIBC__) && (__GLIBC__ > 2 || (__GLIBC__ == 2 && __GLIBC_MINOR__ >= 33))
+    struct mallinfo2 info = mallinfo2();
+    strbuf_appendf(
+        sb,
+        "\"allocator\":{\"impl\":\"glibc\",\"in_use_bytes\":%zu,\"free_bytes\":%zu,\"arena_bytes\":%zu,\"mmap_bytes\":%zu,\"releasable_bytes\":%zu}",
+        info.uordblks,
+        info.fordblks,
+        info.arena,
+        info.hblkhd,
+        info.keepcost);
+#else
+    strbuf_append(sb, "\"allocator\":null", 16);
+#endif
+}
+
+static int profiling_guard(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    if (!__atomic_load_n(&g_profiling_enabled, __ATOMIC_ACQUIRE)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"debug profiling disabled; start with --debug-profiling\"}", ctx);
+        return 404;
+    }
+    return 0;
+}
+
+static int send_strbuf(int fd, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return 200;
+}
+
+int handle_get_debug_heap(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int guarded = profiling_guard(fd, req, ctx);
+    if (guarded != 0) return guarded;
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "{\"captured_at\":%lld,\"rss_bytes\":%lld,\"peak_rss_bytes\":%lld,\"sqlite\":{\"memory_used_bytes\":%lld,\"memory_highwater_bytes\":%lld},",
+        (long long)time(NULL),
+        current_rss_bytes(),
+        peak_rss_bytes(),
+        (long long)sqlite3_memory_used(),
+        (long long)sqlite3_memory_highwater(0));
+    append_allocator_stats(&sb);
+    strbuf_append(&sb, "}", 1);
+    return send_strbuf(fd, &sb, ctx);
+}
+
+int handle_get_debug_runtime(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int guarded = profiling_guard(fd, req, ctx);
+    if (guarded != 0) return guarded;
+
+    write_dispatch_diagnostics_t diag;
+    write_dispatch_diagnostics_snapshot(&diag);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "{\"captured_at\":%lld,\"worker_threads\":%zu,\"open_connections\":%lld,\"connection_buffer_bytes\":%lld,"
+        "\"in_flight_requests\":%lld,\"requests_total\":%lld,\"write_queue\":{\"running\":%s,\"depth\":%d}}",
+        (long long)time(NULL),
+        __atomic_load_n(&g_worker_count, __ATOMIC_RELAXED),
+        __atomic_load_n(&g_open_connections, __ATOMIC_RELAXED),
+        __atomic_load_n(&g_connection_buffer_bytes, __ATOMIC_RELAXED),
+        __atomic_load_n(&g_in_flight_requests, __ATOMIC_RELAXED),
+        __atomic_load_n(&g_requests_total, __ATOMIC_RELAXED),
+        diag.running ? "true" : "false",
+        diag.queue_depth);
+    return send_strbuf(fd, &sb, ctx);
+}
@@ -0,0 +1,370 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * A small QR Code (ISO/IEC 18004) encoder for the pairing QR: byte mode, error correction level M,
+ * versions 1 through 10 (up to 213 bytes, which covers a pairing URL), the mask with the lowest
+ * penalty score. qr_render_svg draws the symbol with its four-module quiet zone as one SVG path.
+ */
+
+#define QR_ECC_FORMAT_BITS_M 0
+
+/* Indexed by version; level M only. */
+static const int ECC_CODEWORDS_PER_BLOCK[QR_MAX_VERSION + 1] = {0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26};
+static const int NUM_ECC_BLOCKS[QR_MAX_VERSION + 1] = {0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5};
+
+static int raw_data_modules(int version) {
+    int result = (16 * version + 128) * version + 64;
+    if (version >= 2) {
+        int align = version / 7 + 2;
+        result -= (25 * align - 10) * align - 55;
+        if (version >= 7) result -= 36;
+    }
+    return result;
+}
+
+static int data_codewords(int version) {
+    return raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * NUM_ECC_BLOCKS[version];
+}
+
+static unsigned char gf_multiply(unsigned char x, unsigned char y) {
+    int z = 0;
+    for (int i = 7; i >= 0; i--) {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y >> i) & 1) * x;
+    }
+    return (unsigned char)z;
+}
+
+static void rs_divisor(int degree, unsigned char *out) {
+    memset(out, 0, (size_t)degree);
+    out[degree - 1] = 1;
+    unsigned char root = 1;
+    for (int i = 0; i < degree; i++) {
+        for (int j = 0; j < degree; j++) {
+            out[j] = gf_multiply(out[j], root);
+            if (j + 1 < degree) out[j] ^= out[j + 1];
+        }
+        root = gf_multiply(root, 0x02);
+    }
+}
+
+void qr_rs_remainder(const unsigned char *data, size_t len, int degree, unsigned char *out) {
+    unsigned char divisor[32];
+    rs_divisor(degree, divisor);
+    memset(out, 0, (size_t)degree);
+    for (size_t i = 0; i < len; i++) {
+        unsigned char factor = data[i] ^ out[0];
+        memmove(out, out + 1, (size_t)degree - 1);
+        out[degree - 1] = 0;
+        for (int j = 0; j < degree; j++) {
+            out[j] ^= gf_multiply(divisor[j], factor);
+        }
+    }
+}
+
+static void set_function(qr_code_t *qr, unsigned char *is_function, int x, int y, int dark) {
+    qr->modules[y * qr->size + x] = (unsigned char)(dark ? 1 : 0);
+    is_function[y * qr->size + x] = 1;
+}
+
+static void draw_finder(qr_code_t *qr, unsigned char *is_function, int cx, int cy) {
+    for (int dy = -4; dy <= 4; dy++) {
+        for (int dx = -4; dx <= 4; dx++) {
+            int x = cx + dx;
+            int y = cy + dy;
+            int dist = abs(dx) > abs(dy) ? abs(dx) : abs(dy);
+            if (x >= 0 && x < qr->size && y >= 0 && y < qr->size) set_function(qr, is_function, x, y, dist != 2 && dist != 4);
+        }
+    }
+}
+
+static void draw_alignment(qr_code_t *qr, unsigned char *is_function, int cx, int cy) {
+    for (int dy = -2; dy <= 2; dy++) {
+        for (int dx = -2; dx <= 2; dx++) {
+            int dist = abs(dx) > abs(dy) ? abs(dx) : abs(dy);
+            set_function(qr, is_function, cx + dx, cy + dy, dist != 1);
+        }
+    }
+}
+
+static int alignment_positions(int version, int *out) {
+    if (version == 1) return 0;
+    int count = version / 7 + 2;
+    int step = (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2;
+    out[0] = 6;
+    for (int i = count - 1, pos = version * 4 + 10; i >= 1; i--, pos -= step) {
+        out[i] = pos;
+    }
+    return count;
+}
+
+static void draw_format(qr_code_t *qr, unsigned char *is_function, int mask) {
+    int data = QR_ECC_FORMAT_BITS_M << 3 | mask;
+    int rem = data;
+    for (int i = 0; i < 10; i++) {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    int bits = (data << 10 | rem) ^ 0x5412;
+    int size = qr->size;
+    for (int i = 0; i <= 5; i++) {
+        set_function(qr, is_function, 8, i, (bits >> i) & 1);
+    }
+    set_function(qr, is_function, 8, 7, (bits >> 6) & 1);
+    set_function(qr, is_function, 8, 8, (bits >> 7) & 1);
+    set_function(qr, is_function, 7, 8, (bits >> 8) & 1);
+    for (int i = 9; i < 15; i++) {
+        set_function(qr, is_function, 14 - i, 8, (bits >> i) & 1);
+    }
+    for (int i = 0; i < 8; i++) {
+        set_function(qr, is_function, size - 1 - i, 8, (bits >> i) & 1);
+    }
+    for (int i = 8; i < 15; i++) {
+        set_function(qr, is_function, 8, size - 15 + i, (bits >> i) & 1);
+    }
+    set_function(qr, is_function, 8, size - 8, 1);
+}
+
+static void draw_version(qr_code_t *qr, unsigned char *is_function) {
+    if (qr->version < 7) return;
+    int rem = qr->version;
+    for (int i = 0; i < 12; i++) {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+    }
+    long bits = (long)qr->version << 12 | rem;
+    for (int i = 0; i < 18; i++) {
+        int dark = (int)((bits >> i) & 1);
+        int a = qr->size - 11 + i % 3;
+        int b = i / 3;
+        set_function(qr, is_function, a, b, dark);
+        set_function(qr, is_function, b, a, dark);
+    }
+}
+
+static void draw_function_patterns(qr_code_t *qr, unsigned char *is_function) {
+    int size = qr->size;
+    for (int i = 0; i < size; i++) {
+        set_function(qr, is_function, 6, i, i % 2 == 0);
+        set_function(qr, is_function, i, 6, i % 2 == 0);
+    }
+    draw_finder(qr, is_function, 3, 3);
+    draw_finder(qr, is_function, size - 4, 3);
+    draw_finder(qr, is_function, 3, size - 4);
+    int positions[7];
+    int count = alignment_positions(qr->version, positions);
+    for (int i = 0; i < count; i++) {
+        for (int j = 0; j < count; j++) {
+            if ((i == 0 && j == 0) || (i == 0 && j == count - 1) || (i == count - 1 && j == 0)) continue;
+            draw_alignment(qr, is_function, positions[i], positions[j]);
+        }
+    }
+    draw_format(qr, is_function, 0);
+    draw_version(qr, is_function);
+}
+
+/* Splits data into blocks, appends each block's ECC and interleaves them (ISO 18004 7.6). */
+static void add_ecc_and_interleave(int version, const unsigned char *data, unsigned char *out) {
+    int blocks = NUM_ECC_BLOCKS[version];
+    int ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    int raw = raw_data_modules(version) / 8;
+    int short_blocks = blocks - raw % blocks;
+    int short_len = raw / blocks;
+    unsigned char block[QR_MAX_VERSION][160];
+    for (int i = 0, k = 0; i < blocks; i++) {
+        int dat_len = short_len - ecc_len + (i < short_blocks ? 0 : 1);
+        memset(block[i], 0, sizeof(block[i]));
+        memcpy(block[i], data + k, (size_t)dat_len);
+        k += dat_len;
+        /* Short blocks get a placeholder so every block has the long block's layout. */
+        unsigned char *ecc = block[i] + short_len + 1 - ecc_len;
+        qr_rs_remainder(data + k - dat_len, (size_t)dat_len, ecc_len, ecc);
+    }
+    int o = 0;
+    for (int i = 0; i <= short_len; i++) {
+        for (int j = 0; j < blocks; j++) {
+            if (i != short_len - ecc_len || j >= short_blocks) out[o++] = block[j][i];
+        }
+    }
+}
+
+static void draw_codewords(qr_code_t *qr, const unsigned char *is_function, const unsigned char *codewords, int len) {
+    int size = qr->size;
+    int i = 0;
+    for (int right = size - 1; right >= 1; right -= 2) {
+        if (right == 6) right = 5;
+        for (int vert = 0; vert < size; vert++) {
+            for (int j = 0; j < 2; j++) {
+                int x = right - j;
+                int upward = ((right + 1) & 2) == 0;
+                int y = upward ? size - 1 - vert : vert;
+                if (!is_function[y * size + x] && i < len * 8) {
+                    qr->modules[y * size + x] = (unsigned char)((codewords[i >> 3] >> (7 - (i & 7))) & 1);
+                    i++;
+                }
+            }
+        }
+    }
+}
+
+static int mask_bit(int mask, int x, int y) {
+    switch (mask) {
+        case 0: return (x + y) % 2 == 0;
+        case 1: return y % 2 == 0;
+        case 2: return x % 3 == 0;
+        case 3: return (x + y) % 3 == 0;
+        case 4: return (x / 3 + y / 2) % 2 == 0;
+        case 5: return x * y % 2 + x * y % 3 == 0;
+        case 6: return (x * y % 2 + x * y % 3) % 2 == 0;
+        default: return ((x + y) % 2 + x * y % 3) % 2 == 0;
+    }
+}
+
+static void apply_mask(qr_code_t *qr, const unsigned char *is_function, int mask) {
+    for (int y = 0; y < qr->size; y++) {
+        for (int x = 0; x < qr->size; x++) {
+            if (!is_function[y * qr->size + x] && mask_bit(mask, x, y)) qr->modules[y * qr->size + x] ^= 1;
+        }
+    }
+}
+
+static int module_at(const qr_code_t *qr, int x, int y) {
+    return qr->modules[y * qr->size + x];
+}
+
+/* 1:1:3:1:1 finder-like run with four light modules on one side, along a row or a column. */
+static int finder_like(const qr_code_t *qr, int x, int y, int horizontal) {
+    static const int PATTERN[11] = {1, 0, 1, 1, 1, 0, 1, 0, 0, 0, 0};
+    int forward = 1;
+    int backward = 1;
+    for (int i = 0; i < 11; i++) {
+        int fx = horizontal ? x + i : x;
+        int fy = horizontal ? y : y + i;
+        if (fx >= qr->size || fy >= qr->size || module_at(qr, fx, fy) != PATTERN[i]) forward = 0;
+        if (fx >= qr->size || fy >= qr->size || module_at(qr, fx, fy) != PATTERN[10 - i]) backward = 0;
+    }
+    return forward + backward;
+}
+
+/* ISO 18004 7.8.3 penalty rules N1 (runs), N2 (2x2 blocks), N3 (finder-like) and N4 (balance). */
+static long penalty_score(const qr_code_t *qr) {
+    int size = qr->size;
+    long score = 0;
+    for (int horizontal = 0; horizontal < 2; horizontal++) {
+        for (int a = 0; a < size; a++) {
+            int run = 0;
+            int color = -1;
+            for (int b = 0; b < size; b++) {
+                int m = horizontal ? module_at(qr, b, a) : module_at(qr, a, b);
+                if (m == color) {
+                    run++;
+                    if (run == 5) score += 3;
+                    if (run > 5) score++;
+                } else {
+                    color = m;
+                    run = 1;
+                }
+                if (horizontal ? finder_like(qr, b, a, 1) : finder_like(qr, a, b, 0)) score += 40;
+            }
+        }
+    }
+    long dark = 0;
+    for (int y = 0; y < size; y++) {
+        for (int x = 0; x < size; x++) {
+            int m = module_at(qr, x, y);
+            dark += m;
+            if (x + 1 < size && y + 1 < size && m == module_at(qr, x + 1, y) && m == module_at(qr, x, y + 1) && m == module_at(qr, x + 1, y + 1)) score += 3;
+        }
+    }
+    long total = (long)size * size;
+    long k = (labs(dark * 20 - total * 10) + total - 1) / total - 1;
+    if (k > 0) score += k * 10;
+    return score;
+}
+
+int qr_encode(const char *text, size_t len, qr_code_t *qr) {
+    memset(qr, 0, sizeof(*qr));
+    int version = 1;
+    while (version <= QR_MAX_VERSION) {
+        int count_bits = version < 10 ? 8 : 16;
+        if ((size_t)(4 + count_bits) + len * 8 <= (size_t)data_codewords(version) * 8) break;
+        version++;
+    }
+    if (version > QR_MAX_VERSION) return -1;
+    qr->version = version;
+    qr->size = version * 4 + 17;
+
+    int capacity = data_codewords(version);
+    unsigned char data[QR_MAX_DATA_CODEWORDS] = {0};
+    size_t bit = 0;
+#define PUT_BITS(value, count)                                                                  \
+    for (int b_ = (count) - 1; b_ >= 0; b_--, bit++) {                                          \
+        if (((value) >> b_) & 1) data[bit >> 3] |= (unsigned char)(0x80 >> (bit & 7));         \
+    }
+    PUT_BITS(0x4, 4);
+    PUT_BITS((unsigned)len, version < 10 ? 8 : 16);
+    for (size_t i = 0; i < len; i++) {
+        PUT_BITS((unsigned char)text[i], 8);
+    }
+    size_t capacity_bits = (size_t)capacity * 8;
+    size_t terminator = capacity_bits - bit < 4 ? capacity_bits - bit : 4;
+    bit += terminator;
+    bit = (bit + 7) / 8 * 8;
+#undef PUT_BITS
+    for (size_t i = bit / 8, pad = 0; i < (size_t)capacity; i++, pad++) {
+        data[i] = pad % 2 == 0 ? 0xEC : 0x11;
+    }
+
+    unsigned char codewords[QR_MAX_RAW_CODEWORDS] = {0};
+    add_ecc_and_interleave(version, data, codewords);
+    unsigned char is_function[QR_MAX_MODULES] = {0};
+    draw_function_patterns(qr, is_function);
+    draw_codewords(qr, is_function, codewords, raw_data_modules(version) / 8);
+
+    int best_mask = 0;
+    long best_score = -1;
+    for (int mask = 0; mask < 8; mask++) {
+        apply_mask(qr, is_function, mask);
+        draw_format(qr, is_function, mask);
+        long score = penalty_score(qr);
+        if (best_score < 0 || score < best_score) {
+            best_score = score;
+            best_mask = mask;
+        }
+        apply_mask(qr, is_function, mask);
+    }
+    apply_mask(qr, is_function, best_mask);
+    draw_format(qr, is_function, best_mask);
+    qr->mask = best_mask;
+    return 0;
+}
+
+void qr_render_svg(const qr_code_t *qr, strbuf_t *out) {
+    int border = 4;
+    int dim = qr->size + border * 2;
+    strbuf_appendf(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 %d %d\" shape-rendering=\"crispEdges\">"
+        "<rect width=\"%d\" height=\"%d\" fill=\"#ffffff\"/><path fill=\"#000000\" d=\"",
+        dim,
+        dim,
+        dim,
+        dim);
+    for (int y = 0; y < qr->size; y++) {
+        for (int x = 0; x < qr->size;) {
+            if (!module_at(qr, x, y)) {
+                x++;
+                continue;
+            }
+            int start = x;
+            while (x < qr->size && module_at(qr, x, y)) x++;
+            strbuf_appendf(out, "M%d %dh%dv1h-%dz", start + border, y + border, x - start, x - start);
+        }
+    }
+    strbuf_append(out, "\"/></svg>", 9);
+}
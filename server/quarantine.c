@@ -0,0 +1,352 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Activity date quarantine: every write of the activities document (sync, delta, item and import
+ * paths alike) passes through activity_quarantine_payload, which takes out activities dated before
+ * FRICU_ACTIVITY_MIN_DATE (default 1990-01-01) or after the latest calendar day anywhere on Earth
+ * plus FRICU_ACTIVITY_MAX_FUTURE_DAYS (default 0), comparing in UTC after applying the date's own
+ * offset. Either bound can be set to "off". Violators are parked in activity_quarantine once the
+ * write is stored, the response says how many with X-Fricu-Quarantined, and the review queue under
+ * /v1/quarantine/activities lists them, releases them back (optionally with a corrected date) or
+ * discards them.
+ */
+
+#define QUARANTINE_DEFAULT_MIN_DATE "1990-01-01"
+#define QUARANTINE_DEFAULT_MAX_FUTURE_DAYS 0
+#define QUARANTINE_LIST_LIMIT 200
+
+/* UTC calendar day of an activity; malformed dates yield NULL and are left to schema validation. */
+#define QUARANTINE_DAY_SQL(item) "date(json_extract(" item ", '$.date'))"
+#define QUARANTINE_VIOLATES_SQL(item, type) \
+    "(" type " = 'object' AND (" QUARANTINE_DAY_SQL(item) " < ?2 OR " QUARANTINE_DAY_SQL(item) " > ?3))"
+
+static __thread strbuf_t g_pending;
+static __thread int g_pending_count;
+static __thread int g_quarantined;
+
+/* Binds the bounds as ?2 (earliest day) and ?3 (latest day); NULL turns a bound off. */
+static void bind_bounds(sqlite3 *db, sqlite3_stmt *stmt) {
+    const char *min = getenv("FRICU_ACTIVITY_MIN_DATE");
+    if (!min || min[0] == '\0') min = QUARANTINE_DEFAULT_MIN_DATE;
+    int min_day = 0;
+    if (strcmp(min, "off") == 0 || parse_iso_day(min, &min_day) != 0) {
+        sqlite3_bind_null(stmt, 2);
+    } else {
+        sqlite3_bind_text(stmt, 2, min, 10, SQLITE_TRANSIENT);
+    }
+
+    const char *raw = getenv("FRICU_ACTIVITY_MAX_FUTURE_DAYS");
+    if (raw && strcmp(raw, "off") == 0) {
+        sqlite3_bind_null(stmt, 3);
+        return;
+    }
+    long days = QUARANTINE_DEFAULT_MAX_FUTURE_DAYS;
+    if (raw && raw[0] != '\0') {
+        char *end = NULL;
+        long parsed = strtol(raw, &end, 10);
+        if (end && *end == '\0' && parsed >= 0 && parsed <= 36500) days = parsed;
+    }
+    /* UTC+14 is the first zone into a new day, so no athlete's local today is later than this. */
+    sqlite3_stmt *max_stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT date('now', '+14 hours', ?1 || ' days')", -1, &max_stmt, NULL) == SQLITE_OK) {
+        char modifier[24] = {0};
+        snprintf(modifier, sizeof(modifier), "+%ld", days);
+        sqlite3_bind_text(max_stmt, 1, modifier, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(max_stmt) == SQLITE_ROW) sqlite3_bind_text(stmt, 3, (const char *)sqlite3_column_text(max_stmt, 0), -1, SQLITE_TRANSIENT);
+    }
+    sqlite3_finalize(max_stmt);
+}
+
+void quarantine_begin(void) {
+    strbuf_free(&g_pending);
+    strbuf_init(&g_pending);
+    g_pending_count = 0;
+    g_quarantined = 0;
+}
+
+char *activity_quarantine_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len) {
+    if (strcmp(key, "activities") != 0) return NULL;
+    /* Only rebuild when something is taken out, so untouched documents keep their exact bytes and version. */
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT (SELECT json_group_array(json(value)) FROM json_each(?1) WHERE " QUARANTINE_VIOLATES_SQL("value", "type") "),"
+            " (SELECT json_group_array(CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END)"
+            "  FROM (SELECT value, type FROM json_each(?1) WHERE NOT COALESCE(" QUARANTINE_VIOLATES_SQL("value", "type") ", 0) ORDER BY key))"
+            " WHERE json_valid(?1) AND json_type(?1) = 'array' AND EXISTS (SELECT 1 FROM json_each(?1) WHERE " QUARANTINE_VIOLATES_SQL("value", "type") ")",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        log_warn("QUARANTINE cannot check activities: %s", sqlite3_errmsg(db));
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, payload, (int)payload_len, SQLITE_STATIC);
+    bind_bounds(db, stmt);
+    char *kept = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 1)) {
+        const char *violators = (const char *)sqlite3_column_text(stmt, 0);
+        size_t violators_len = (size_t)sqlite3_column_bytes(stmt, 0);
+        kept = strdup((const char *)sqlite3_column_text(stmt, 1));
+        if (kept && violators_len > 2) {
+            if (g_pending.len > 0) strbuf_append(&g_pending, ",", 1);
+            strbuf_append(&g_pending, violators + 1, violators_len - 2);
+            g_pending_count++;
+            log_warn("QUARANTINE activities held back account=%s bytes=%zu->%zu", account_id, payload_len, strlen(kept));
+        }
+    }
+    sqlite3_finalize(stmt);
+    return kept;
+}
+
+/* Parks the activities taken out of this request's write once it is stored; a failed write keeps nothing. */
+void quarantine_flush(sqlite3 *db, const char *account_id, int stored) {
+    if (g_pending_count == 0) return;
+    if (stored && !g_pending.failed) {
+        strbuf_t items;
+        strbuf_init(&items);
+        strbuf_append(&items, "[", 1);
+        strbuf_append(&items, strbuf_cstr(&g_pending), g_pending.len);
+        strbuf_append(&items, "]", 1);
+        sqlite3_stmt *stmt = NULL;
+        if (!items.failed && sqlite3_prepare_v2(
+                                 db,
+                                 "INSERT INTO activity_quarantine (account_id, item_id, activity_date, reason, item, created_at)"
+                                 " SELECT ?4, CAST(json_extract(value, '$.id') AS TEXT), " QUARANTINE_DAY_SQL("value") ","
+                                 " CASE WHEN " QUARANTINE_DAY_SQL("value") " < ?2 THEN 'before_min_date' ELSE 'in_future' END, json(value), strftime('%s', 'now')"
+                                 " FROM json_each(?1) WHERE " QUARANTINE_VIOLATES_SQL("value", "type"),
+                                 -1,
+                                 &stmt,
+                                 NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, strbuf_cstr(&items), (int)items.len, SQLITE_STATIC);
+            bind_bounds(db, stmt);
+            sqlite3_bind_text(stmt, 4, account_id, -1, SQLITE_TRANSIENT);
+            if (sqlite3_step(stmt) == SQLITE_DONE) {
+                g_quarantined += sqlite3_changes(db);
+                /* They never reach store_account_document, so count their future dates for the clock report here. */
+                skew_note_future_dates(skew_count_future_dates(db, strbuf_cstr(&items), items.len));
+                log_warn("QUARANTINE stored activities=%d account=%s", sqlite3_changes(db), account_id);
+            } else {
+                log_error("QUARANTINE store failed account=%s: %s", account_id, sqlite3_errmsg(db));
+            }
+        } else {
+            log_error("QUARANTINE store failed account=%s: %s", account_id, sqlite3_errmsg(db));
+        }
+        sqlite3_finalize(stmt);
+        strbuf_free(&items);
+    }
+    strbuf_free(&g_pending);
+    strbuf_init(&g_pending);
+    g_pending_count = 0;
+}
+
+int quarantine_headers(char *out, size_t out_len) {
+    out[0] = '\0';
+    if (g_quarantined == 0) return 0;
+    int n = snprintf(out, out_len, "X-Fricu-Quarantined: %d\r\n", g_quarantined);
+    return n > 0 && (size_t)n < out_len ? n : 0;
+}
+
+static int handle_list_quarantine(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT id, item_id, activity_date, reason, item, created_at FROM activity_quarantine WHERE account_id = ?1 ORDER BY id LIMIT ?2",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, QUARANTINE_LIST_LIMIT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"items\":[", 10);
+    int rows = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (rows++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_appendf(&sb, "{\"id\":%lld,\"item_id\":", sqlite3_column_int64(stmt, 0));
+        if (sqlite3_column_type(stmt, 1) == SQLITE_NULL) {
+            strbuf_append(&sb, "null", 4);
+        } else {
+            strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        }
+        strbuf_append(&sb, ",\"activity_date\":", 17);
+        strbuf_append_json_string(&sb, sqlite3_column_text(stmt, 2) ? (const char *)sqlite3_column_text(stmt, 2) : "");
+        strbuf_append(&sb, ",\"reason\":", 10);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 3));
+        strbuf_append(&sb, ",\"item\":", 8);
+        strbuf_append(&sb, (const char *)sqlite3_column_text(stmt, 4), (size_t)sqlite3_column_bytes(stmt, 4));
+        strbuf_appendf(&sb, ",\"created_at\":%lld}", sqlite3_column_int64(stmt, 5));
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* The quarantined item, with req's {"date":...} applied when given; NULL with *status set otherwise. */
+static char *load_release_item(sqlite3 *db, long long id, const http_request_t *req, const request_log_context_t *ctx, int fd, int *status) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT CASE WHEN ?3 = '' OR NOT json_valid(?3) THEN item ELSE json_set(item, '$.date', json_extract(?3, '$.date')) END,"
+            " CASE WHEN ?3 = '' THEN 1 WHEN json_valid(?3) THEN json_type(?3, '$.date') IS 'text' ELSE 0 END"
+            " FROM activity_quarantine WHERE id = ?1 AND account_id = ?2",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        *status = 500;
+        return NULL;
+    }
+    sqlite3_bind_int64(stmt, 1, id);
+    sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, req->body_len > 0 ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char *item = NULL;
+    *status = 404;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        *status = sqlite3_column_int(stmt, 1) ? 0 : 400;
+        if (*status == 0) item = strdup((const char *)sqlite3_column_text(stmt, 0));
+    }
+    sqlite3_finalize(stmt);
+    if (*status == 404) send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown quarantined activity\"}", ctx);
+    if (*status == 400) send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"body must be {\\\"date\\\":\\\"...\\\"}\"}", ctx);
+    if (*status == 0 && !item) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        *status = 500;
+    }
+    return item;
+}
+
+static int item_within_bounds(sqlite3 *db, const char *item) {
+    sqlite3_stmt *stmt = NULL;
+    int ok = 0;
+    if (sqlite3_prepare_v2(db, "SELECT NOT COALESCE(" QUARANTINE_VIOLATES_SQL("?1", "json_type(?1)") ", 0)", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, item, -1, SQLITE_STATIC);
+        bind_bounds(db, stmt);
+        ok = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return ok;
+}
+
+/* Puts the item back into activities, replacing any stored activity with the same id. */
+static char *merge_released_item(sqlite3 *db, const char *storage_key, const char *item) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_insert(COALESCE((SELECT json_group_array(CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END)"
+            "  FROM (SELECT e.value, e.type FROM kv_store k, json_each(k.data_value) e WHERE k.data_key = ?1 AND json_valid(k.data_value)"
+            "  AND (json_extract(?2, '$.id') IS NULL OR json_extract(e.value, '$.id') IS NOT json_extract(?2, '$.id')) ORDER BY e.key)), '[]'),"
+            " '$[#]', json(?2))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return NULL;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, item, -1, SQLITE_STATIC);
+    char *doc = NULL;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) doc = strdup((const char *)sqlite3_column_text(stmt, 0));
+    sqlite3_finalize(stmt);
+    return doc;
+}
+
+static int delete_quarantined(sqlite3 *db, long long id, const char *account_id) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "DELETE FROM activity_quarantine WHERE id = ?1 AND account_id = ?2", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_int64(stmt, 1, id);
+    sqlite3_bind_text(stmt, 2, account_id, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt) == SQLITE_DONE ? sqlite3_changes(db) : -1;
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+static int handle_release_quarantined(int fd, worker_db_t *db, long long id, const http_request_t *req, const request_log_context_t *ctx) {
+    int status = 0;
+    char *item = load_release_item(db->db, id, req, ctx, fd, &status);
+    if (!item) return status;
+    if (!item_within_bounds(db->db, item)) {
+        free(item);
+        send_response_with_log_context(fd, 422, "Unprocessable Entity", "{\"error\":\"activity date is still outside the accepted range\"}", ctx);
+        return 422;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0) {
+        free(item);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+
+    sync_document_lock();
+    char *doc = merge_released_item(db->db, storage_key, item);
+    free(item);
+    if (!doc) {
+        sync_document_unlock();
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    char error_body[512] = {0};
+    status = store_account_data(db, "activities", doc, strlen(doc), ctx, error_body, sizeof(error_body));
+    sync_document_unlock();
+    free(doc);
+    if (status != 204 && status != 202) {
+        send_response_with_log_context(fd, status, status == 400 ? "Bad Request" : status == 422 ? "Unprocessable Entity" : "Internal Server Error", error_body, ctx);
+        return status;
+    }
+    delete_quarantined(db->db, id, ctx->account_id);
+    log_info("QUARANTINE released id=%lld account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+    char body[64] = {0};
+    snprintf(body, sizeof(body), "{\"released\":%lld}", id);
+    send_response_with_log_context(fd, 200, "OK", body, ctx);
+    return 200;
+}
+
+int route_quarantine(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *base = "/v1/quarantine/activities";
+    const char *rest = req->path + strlen(base);
+    if (*rest == '\0') {
+        if (strcmp(req->method, "GET") == 0) return handle_list_quarantine(fd, db, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    char *end = NULL;
+    long long id = *rest == '/' ? strtoll(rest + 1, &end, 10) : 0;
+    if (id <= 0 || !end || (*end != '\0' && strcmp(end, "/release") != 0)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(end, "/release") == 0 && strcmp(req->method, "POST") == 0) return handle_release_quarantined(fd, db, id, req, ctx);
+    if (*end == '\0' && strcmp(req->method, "DELETE") == 0) {
+        int deleted = delete_quarantined(db->db, id, ctx->account_id);
+        if (deleted < 0) {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+            return 500;
+        }
+        if (deleted == 0) {
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown quarantined activity\"}", ctx);
+            return 404;
+        }
+        log_info("QUARANTINE discarded id=%lld account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 204, "No Content", "", ctx);
+        return 204;
+    }
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
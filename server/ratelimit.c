@@ -0,0 +1,210 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <arpa/inet.h>
+#include <math.h>
+#include <netinet/in.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+
+/*
+ * Token-bucket rate limiting in front of the routes. FRICU_RATE_LIMIT_IP caps each client
+ * address and FRICU_RATE_LIMIT_TOKEN each authenticated client (API token id or OIDC account),
+ * both written as <requests>/<s|m|h>, e.g. 20/s or 1200/m; a bucket holds one period's worth
+ * unless FRICU_RATE_LIMIT_IP_BURST / FRICU_RATE_LIMIT_TOKEN_BURST says otherwise. Unset or "off"
+ * disables that limit (the default). The address limit applies before authentication, so it also
+ * slows down token guessing, and the client limit right after it. A request over either limit gets
+ * 429 with Retry-After. The address is the socket peer; behind a reverse proxy set
+ * FRICU_RATE_LIMIT_TRUST_PROXY=1 to use the last X-Forwarded-For hop instead. /health is never
+ * limited. Buckets live in a fixed table per process; when it is full the longest-idle bucket in
+ * the probe window is recycled.
+ */
+
+#define RATE_LIMIT_SLOTS 4096
+#define RATE_LIMIT_PROBE 16
+
+typedef struct {
+    char key[160];
+    double tokens;
+    double updated_s;
+} rate_bucket_t;
+
+typedef struct {
+    double per_second;
+    double burst;
+} rate_limit_t;
+
+static pthread_mutex_t g_rate_mutex = PTHREAD_MUTEX_INITIALIZER;
+static rate_bucket_t g_buckets[RATE_LIMIT_SLOTS];
+static char g_config[256];
+static long long g_limited_ip;
+static long long g_limited_token;
+
+/* "20/s", "1200/m", "5000/h"; 0 when the variable is unset, "off" or malformed. */
+static int parse_limit(const char *rate_var, const char *burst_var, rate_limit_t *out) {
+    memset(out, 0, sizeof(*out));
+    const char *raw = getenv(rate_var);
+    if (!raw || raw[0] == '\0' || strcmp(raw, "off") == 0) return 0;
+    char *end = NULL;
+    double requests = strtod(raw, &end);
+    double period = 0;
+    if (end && strcmp(end, "/s") == 0) period = 1;
+    if (end && strcmp(end, "/m") == 0) period = 60;
+    if (end && strcmp(end, "/h") == 0) period = 3600;
+    if (period == 0 || !(requests > 0)) {
+        log_warn("%s=%s ignored: expected <requests>/<s|m|h>", rate_var, raw);
+        return 0;
+    }
+    out->per_second = requests / period;
+    out->burst = requests;
+    const char *burst = getenv(burst_var);
+    if (burst && burst[0] != '\0') {
+        double value = strtod(burst, &end);
+        if (end && *end == '\0' && value >= 1) out->burst = floor(value);
+    }
+    if (out->burst < 1) out->burst = 1;
+    return 1;
+}
+
+static double monotonic_s(void) {
+    return slowlog_now_ms() / 1000.0;
+}
+
+static unsigned long hash_key(const char *key) {
+    unsigned long hash = 5381;
+    for (const char *p = key; *p; p++) hash = hash * 33 + (unsigned char)*p;
+    return hash;
+}
+
+/* Takes one token from key's bucket; returns 0, or the seconds until one is available. */
+static int take_token(const char *key, const rate_limit_t *limit) {
+    double now = monotonic_s();
+    unsigned long start = hash_key(key);
+    pthread_mutex_lock(&g_rate_mutex);
+    rate_bucket_t *bucket = NULL;
+    rate_bucket_t *idlest = NULL;
+    for (int i = 0; i < RATE_LIMIT_PROBE && !bucket; i++) {
+        rate_bucket_t *slot = &g_buckets[(start + (unsigned long)i) % RATE_LIMIT_SLOTS];
+        if (strcmp(slot->key, key) == 0) {
+            bucket = slot;
+        } else if (!idlest || (idlest->key[0] != '\0' && (slot->key[0] == '\0' || slot->updated_s < idlest->updated_s))) {
+            idlest = slot;
+        }
+    }
+    if (!bucket) {
+        bucket = idlest;
+        snprintf(bucket->key, sizeof(bucket->key), "%s", key);
+        bucket->tokens = limit->burst;
+        bucket->updated_s = now;
+    }
+    bucket->tokens += (now - bucket->updated_s) * limit->per_second;
+    if (bucket->tokens > limit->burst) bucket->tokens = limit->burst;
+    bucket->updated_s = now;
+    int retry_after = 0;
+    if (bucket->tokens >= 1) {
+        bucket->tokens -= 1;
+    } else {
+        retry_after = (int)ceil((1 - bucket->tokens) / limit->per_second);
+        if (retry_after < 1) retry_after = 1;
+    }
+    pthread_mutex_unlock(&g_rate_mutex);
+    return retry_after;
+}
+
+/* Forgets every bucket when the limits change, so a new configuration starts from full buckets. */
+static void sync_config(void) {
+    char config[256] = {0};
+    const char *vars[] = {"FRICU_RATE_LIMIT_IP", "FRICU_RATE_LIMIT_IP_BURST", "FRICU_RATE_LIMIT_TOKEN", "FRICU_RATE_LIMIT_TOKEN_BURST"};
+    size_t off = 0;
+    for (size_t i = 0; i < sizeof(vars) / sizeof(vars[0]) && off < sizeof(config); i++) {
+        const char *value = getenv(vars[i]);
+        int n = snprintf(config + off, sizeof(config) - off, "%s|", value ? value : "");
+        if (n > 0) off += (size_t)n;
+    }
+    pthread_mutex_lock(&g_rate_mutex);
+    if (strcmp(config, g_config) != 0) {
+        memset(g_buckets, 0, sizeof(g_buckets));
+        snprintf(g_config, sizeof(g_config), "%s", config);
+    }
+    pthread_mutex_unlock(&g_rate_mutex);
+}
+
+void request_client_address(int fd, const http_request_t *req, char *out, size_t out_len) {
+    const char *trust = getenv("FRICU_RATE_LIMIT_TRUST_PROXY");
+    char forwarded[512] = {0};
+    if (trust && strcmp(trust, "1") == 0 && http_request_header(req, "X-Forwarded-For", forwarded, sizeof(forwarded))) {
+        const char *hop = strrchr(forwarded, ',');
+        hop = hop ? hop + 1 : forwarded;
+        hop += strspn(hop, " \t");
+        if (*hop != '\0') {
+            snprintf(out, out_len, "%.*s", (int)strcspn(hop, " \t"), hop);
+            return;
+        }
+    }
+    struct sockaddr_storage peer;
+    socklen_t peer_len = sizeof(peer);
+    char text[INET6_ADDRSTRLEN] = {0};
+    if (getpeername(fd, (struct sockaddr *)&peer, &peer_len) != 0) {
+        snprintf(out, out_len, "unknown");
+    } else if (peer.ss_family == AF_INET && inet_ntop(AF_INET, &((struct sockaddr_in *)&peer)->sin_addr, text, sizeof(text))) {
+        snprintf(out, out_len, "%s", text);
+    } else if (peer.ss_family == AF_INET6 && inet_ntop(AF_INET6, &((struct sockaddr_in6 *)&peer)->sin6_addr, text, sizeof(text))) {
+        snprintf(out, out_len, "%s", text);
+    } else {
+        snprintf(out, out_len, "local");
+    }
+}
+
+static int refuse(int fd, const char *scope, int retry_after, const char *key, const request_log_context_t *ctx) {
+    char headers[64] = {0};
+    char body[128] = {0};
+    snprintf(headers, sizeof(headers), "Retry-After: %d\r\n", retry_after);
+    int n = snprintf(body, sizeof(body), "{\"error\":\"rate limit exceeded\",\"scope\":\"%s\",\"retry_after\":%d}", scope, retry_after);
+    send_http_response(fd, 429, "Too Many Requests", "application/json", headers, body, n > 0 ? (size_t)n : 0, ctx);
+    log_warn("RATE LIMITED scope=%s client=%s retry_after=%d logid=%s", scope, key, retry_after, ctx->log_id);
+    return 429;
+}
+
+int rate_limit_ip(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    if (strcmp(req->path, "/health") == 0 || strncmp(req->path, "/health/", 8) == 0) return 0;
+    sync_config();
+    rate_limit_t limit;
+    if (!parse_limit("FRICU_RATE_LIMIT_IP", "FRICU_RATE_LIMIT_IP_BURST", &limit)) return 0;
+    char address[128] = {0};
+    char key[160] = {0};
+    request_client_address(fd, req, address, sizeof(address));
+    snprintf(key, sizeof(key), "ip:%s", address);
+    int retry_after = take_token(key, &limit);
+    if (retry_after == 0) return 0;
+    __atomic_add_fetch(&g_limited_ip, 1, __ATOMIC_RELAXED);
+    return refuse(fd, "ip", retry_after, key, ctx);
+}
+
+int rate_limit_client(int fd, const request_log_context_t *ctx) {
+    const char *client = api_auth_client_id();
+    rate_limit_t limit;
+    if (client[0] == '\0' || !parse_limit("FRICU_RATE_LIMIT_TOKEN", "FRICU_RATE_LIMIT_TOKEN_BURST", &limit)) return 0;
+    char key[160] = {0};
+    snprintf(key, sizeof(key), "client:%s", client);
+    int retry_after = take_token(key, &limit);
+    if (retry_after == 0) return 0;
+    __atomic_add_fetch(&g_limited_token, 1, __ATOMIC_RELAXED);
+    return refuse(fd, "token", retry_after, key, ctx);
+}
+
+void rate_limit_append_metrics(strbuf_t *sb) {
+    strbuf_appendf(
+        sb,
+        "# TYPE fricu_rate_limited_requests counter\n"
+        "# HELP fricu_rate_limited_requests Requests refused with 429, by limit.\n"
+        "fricu_rate_limited_requests_total{scope=\"ip\"} %lld\n"
+        "fricu_rate_limited_requests_total{scope=\"token\"} %lld\n",
+        __atomic_load_n(&g_limited_ip, __ATOMIC_RELAXED),
+        __atomic_load_n(&g_limited_token, __ATOMIC_RELAXED));
+}
@@ -0,0 +1,458 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <netdb.h>
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <unistd.h>
+
+/*
+ * Optional Redis bridge for multi-instance mode, enabled by FRICU_REDIS_URL. Local change
+ * events are published to "<prefix>:changes"; a subscriber thread feeds events written by
+ * other instances back into the local change-event hub. The RESP client is a minimal built-in
+ * one so the server keeps a single sqlite3 dependency.
+ */
+
+#define REDIS_QUEUE_CAP 1024
+#define REDIS_REPLY_MAX (64 * 1024)
+#define REDIS_MAX_BACKOFF_SEC 10
+
+typedef struct {
+    int fd;
+    char buf[4096];
+    size_t len;
+    size_t pos;
+} redis_conn_t;
+
+typedef struct {
+    int enabled;
+    redis_config_t config;
+    char channel[160];
+    pthread_mutex_t mutex;
+    pthread_cond_t cond;
+    change_event_t queue[REDIS_QUEUE_CAP];
+    size_t head;
+    size_t count;
+    int publisher_connected;
+    int subscriber_connected;
+    long long published;
+    long long received;
+    long long dropped;
+} redis_bridge_t;
+
+static redis_bridge_t g_redis = {.mutex = PTHREAD_MUTEX_INITIALIZER, .cond = PTHREAD_COND_INITIALIZER};
+
+int redis_parse_url(const char *url, redis_config_t *out) {
+    memset(out, 0, sizeof(*out));
+    out->port = 6379;
+    const char *scheme = "redis://";
+    if (!url || strncmp(url, scheme, strlen(scheme)) != 0) return -1;
+    const char *p = url + strlen(scheme);
+
+    const char *at = strchr(p, '@');
+    if (at) {
+        const char *colon = memchr(p, ':', (size_t)(at - p));
+        const char *password = colon ? colon + 1 : p;
+        size_t password_len = (size_t)(at - password);
+        if (password_len >= sizeof(out->password)) return -1;
+        memcpy(out->password, password, password_len);
+        if (colon && colon > p) {
+            size_t user_len = (size_t)(colon - p);
+            if (user_len >= sizeof(out->username)) return -1;
+            memcpy(out->username, p, user_len);
+        }
+        p = at + 1;
+    }
+
+    size_t host_len = strcspn(p, ":/");
+    if (host_len == 0 || host_len >= sizeof(out->host)) return -1;
+    memcpy(out->host, p, host_len);
+    p += host_len;
+    if (*p == ':') {
+        char *end = NULL;
+        long port = strtol(p + 1, &end, 10);
+        if (end == p + 1 || port <= 0 || port > 65535) return -1;
+        out->port = (int)port;
+        p = end;
+    }
+    if (*p == '/' && p[1] != '\0') {
+        char *end = NULL;
+        long db = strtol(p + 1, &end, 10);
+        if (end == p + 1 || *end != '\0' || db < 0) return -1;
+        out->db = (int)db;
+        p = end;
+    } else if (*p == '/') {
+        p++;
+    }
+    return *p == '\0' ? 0 : -1;
+}
+
+int redis_encode_command(strbuf_t *sb, int argc, const char **argv) {
+    strbuf_appendf(sb, "*%d\r\n", argc);
+    for (int i = 0; i < argc; i++) {
+        size_t len = strlen(argv[i]);
+        strbuf_appendf(sb, "$%zu\r\n", len);
+        strbuf_append(sb, argv[i], len);
+        strbuf_append(sb, "\r\n", 2);
+    }
+    return sb->failed ? -1 : 0;
+}
+
+static int conn_send(int fd, const char *buf, size_t len) {
+#ifdef MSG_NOSIGNAL
+    int flags = MSG_NOSIGNAL;
+#else
+    int flags = 0;
+#endif
+    size_t sent = 0;
+    while (sent < len) {
+        ssize_t n = send(fd, buf + sent, len - sent, flags);
+        if (n > 0) {
+            sent += (size_t)n;
+            continue;
+        }
+        if (n < 0 && errno == EINTR) continue;
+        return -1;
+    }
+    return 0;
+}
+
+static int conn_fill(redis_conn_t *c) {
+    if (c->pos > 0) {
+        memmove(c->buf, c->buf + c->pos, c->len - c->pos);
+        c->len -= c->pos;
+        c->pos = 0;
+    }
+    if (c->len == sizeof(c->buf)) return -1;
+    ssize_t n = recv(c->fd, c->buf + c->len, sizeof(c->buf) - c->len, 0);
+    if (n <= 0) return -1;
+    c->len += (size_t)n;
+    return 0;
+}
+
+static int conn_read_line(redis_conn_t *c, char *out, size_t out_len) {
+    for (;;) {
+        char *eol = memmem(c->buf + c->pos, c->len - c->pos, "\r\n", 2);
+        if (eol) {
+            size_t n = (size_t)(eol - (c->buf + c->pos));
+            if (n >= out_len) return -1;
+            memcpy(out, c->buf + c->pos, n);
+            out[n] = '\0';
+            c->pos += n + 2;
+            return 0;
+        }
+        if (conn_fill(c) != 0) return -1;
+    }
+}
+
+static int conn_read_bytes(redis_conn_t *c, char *out, size_t n) {
+    size_t copied = 0;
+    while (copied < n) {
+        if (c->pos == c->len && conn_fill(c) != 0) return -1;
+        size_t chunk = c->len - c->pos;
+        if (chunk > n - copied) chunk = n - copied;
+        memcpy(out + copied, c->buf + c->pos, chunk);
+        copied += chunk;
+        c->pos += chunk;
+    }
+    return 0;
+}
+
+/* Reads one reply. Bulk strings and array items are joined into out as NUL-separated parts. */
+static int conn_read_reply(redis_conn_t *c, char *out, size_t out_len, int *out_parts) {
+    char line[128] = {0};
+    if (conn_read_line(c, line, sizeof(line)) != 0) return -1;
+    *out_parts = 0;
+    if (line[0] == '-') {
+        log_warn("redis error reply: %s", line + 1);
+        return -1;
+    }
+    if (line[0] == '+' || line[0] == ':') {
+        snprintf(out, out_len, "%s", line + 1);
+        *out_parts = 1;
+        return 0;
+    }
+    int items = 1;
+    int have_line = 1;
+    if (line[0] == '*') {
+        items = atoi(line + 1);
+        if (items < 0 || items > 8) return -1;
+        have_line = 0;
+    }
+    size_t used = 0;
+    for (int i = 0; i < items; i++) {
+        if (!have_line && conn_read_line(c, line, sizeof(line)) != 0) return -1;
+        have_line = 0;
+        if (line[0] == ':' || line[0] == '+') {
+            int n = snprintf(out + used, out_len - used, "%s", line + 1);
+            if (n < 0 || (size_t)n + 1 >= out_len - used) return -1;
+            used += (size_t)n + 1;
+            continue;
+        }
+        if (line[0] != '$') return -1;
+        long len = atol(line + 1);
+        if (len < 0 || (size_t)len + 1 >= out_len - used) return -1;
+        char crlf[2];
+        if (conn_read_bytes(c, out + used, (size_t)len) != 0 || conn_read_bytes(c, crlf, 2) != 0) return -1;
+        out[used + (size_t)len] = '\0';
+        used += (size_t)len + 1;
+    }
+    *out_parts = items;
+    return 0;
+}
+
+static int conn_command(redis_conn_t *c, int argc, const char **argv, char *reply, size_t reply_len) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (redis_encode_command(&sb, argc, argv) != 0 || conn_send(c->fd, sb.data, sb.len) != 0) {
+        strbuf_free(&sb);
+        return -1;
+    }
+    strbuf_free(&sb);
+    int parts = 0;
+    return conn_read_reply(c, reply, reply_len, &parts);
+}
+
+static int conn_open(redis_conn_t *c, const redis_config_t *config, int recv_timeout_sec) {
+    memset(c, 0, sizeof(*c));
+    c->fd = -1;
+    char port[16] = {0};
+    snprintf(port, sizeof(port), "%d", config->port);
+    struct addrinfo hints;
+    struct addrinfo *res = NULL;
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_family = AF_UNSPEC;
+    hints.ai_socktype = SOCK_STREAM;
+    if (getaddrinfo(config->host, port, &hints, &res) != 0) return -1;
+    for (struct addrinfo *ai = res; ai && c->fd < 0; ai = ai->ai_next) {
+        int fd = socket(ai->ai_family, ai->ai_socktype, ai->ai_protocol);
+        if (fd < 0) continue;
+        if (connect(fd, ai->ai_addr, ai->ai_addrlen) == 0) {
+            c->fd = fd;
+        } else {
+            close(fd);
+        }
+    }
+    freeaddrinfo(res);
+    if (c->fd < 0) return -1;
+
+    struct timeval tv = {.tv_sec = recv_timeout_sec, .tv_usec = 0};
+    setsockopt(c->fd, SOL_SOCKET, SO_RCVTIMEO, &tv, sizeof(tv));
+    setsockopt(c->fd, SOL_SOCKET, SO_SNDTIMEO, &tv, sizeof(tv));
+
+    char reply[256] = {0};
+    if (config->password[0] != '\0') {
+        const char *auth_user[] = {"AUTH", config->username, config->password};
+        const char *auth[] = {"AUTH", config->password};
+        int rc = config->username[0] ? conn_command(c, 3, auth_user, reply, sizeof(reply)) : conn_command(c, 2, auth, reply, sizeof(reply));
+        if (rc != 0) {
+            close(c->fd);
+            c->fd = -1;
+            return -1;
+        }
+    }
+    return 0;
+}
+
+static void conn_close(redis_conn_t *c) {
+    if (c->fd >= 0) close(c->fd);
+    c->fd = -1;
+}
+
+static void encode_event(strbuf_t *sb, const change_event_t *event) {
+    strbuf_append(sb, "{\"v\":1,\"origin\":", 16);
+    strbuf_append_json_string(sb, event->origin);
+    strbuf_append(sb, ",\"account_id\":", 14);
+    strbuf_append_json_string(sb, event->account_id);
+    strbuf_append(sb, ",\"key\":", 7);
+    strbuf_append_json_string(sb, event->key);
+    strbuf_appendf(sb, ",\"version\":\"%s\",\"updated_at\":%lld}", event->version, event->updated_at);
+}
+
+int redis_handle_message(const char *payload, const char *self_origin) {
+    static __thread sqlite3 *parser = NULL;
+    if (!parser && sqlite3_open(":memory:", &parser) != SQLITE_OK) return -1;
+    const char *sql =
+        "SELECT json_extract(?1, '$.origin'), json_extract(?1, '$.account_id'), json_extract(?1, '$.key'),"
+        " json_extract(?1, '$.version'), json_extract(?1, '$.updated_at') WHERE json_valid(?1) AND json_extract(?1, '$.v') = 1";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(parser, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, payload, -1, SQLITE_TRANSIENT);
+    int rc = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) == SQLITE_TEXT && sqlite3_column_type(stmt, 1) == SQLITE_TEXT &&
+        sqlite3_column_type(stmt, 2) == SQLITE_TEXT) {
+        change_event_t event;
+        memset(&event, 0, sizeof(event));
+        snprintf(event.origin, sizeof(event.origin), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(event.account_id, sizeof(event.account_id), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(event.key, sizeof(event.key), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        const unsigned char *version = sqlite3_column_text(stmt, 3);
+        snprintf(event.version, sizeof(event.version), "%s", version ? (const char *)version : "");
+        event.updated_at = sqlite3_column_int64(stmt, 4);
+        event.remote = 1;
+        if (strcmp(event.origin, self_origin) == 0) {
+            rc = 0;
+        } else {
+            change_events_dispatch(&event);
+            rc = 1;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+void redis_publish_change(const change_event_t *event) {
+    pthread_mutex_lock(&g_redis.mutex);
+    if (!g_redis.enabled) {
+        pthread_mutex_unlock(&g_redis.mutex);
+        return;
+    }
+    if (g_redis.count == REDIS_QUEUE_CAP) {
+        g_redis.head = (g_redis.head + 1) % REDIS_QUEUE_CAP;
+        g_redis.count--;
+        g_redis.dropped++;
+    }
+    g_redis.queue[(g_redis.head + g_redis.count) % REDIS_QUEUE_CAP] = *event;
+    g_redis.count++;
+    pthread_cond_signal(&g_redis.cond);
+    pthread_mutex_unlock(&g_redis.mutex);
+}
+
+static void *publisher_entry(void *arg) {
+    (void)arg;
+    redis_conn_t conn = {.fd = -1};
+    int backoff = 1;
+    for (;;) {
+        pthread_mutex_lock(&g_redis.mutex);
+        while (g_redis.count == 0) pthread_cond_wait(&g_redis.cond, &g_redis.mutex);
+        change_event_t event = g_redis.queue[g_redis.head];
+        pthread_mutex_unlock(&g_redis.mutex);
+
+        if (conn.fd < 0 && conn_open(&conn, &g_redis.config, 2) != 0) {
+            pthread_mutex_lock(&g_redis.mutex);
+            g_redis.publisher_connected = 0;
+            pthread_mutex_unlock(&g_redis.mutex);
+            sleep((unsigned)backoff);
+            if (backoff < REDIS_MAX_BACKOFF_SEC) backoff *= 2;
+            continue;
+        }
+        strbuf_t payload;
+        strbuf_init(&payload);
+        encode_event(&payload, &event);
+        const char *argv[] = {"PUBLISH", g_redis.channel, strbuf_cstr(&payload)};
+        char reply[64] = {0};
+        int rc = conn_command(&conn, 3, argv, reply, sizeof(reply));
+        strbuf_free(&payload);
+        if (rc != 0) {
+            log_warn("redis publish failed, reconnecting");
+            conn_close(&conn);
+            continue;
+        }
+        backoff = 1;
+        pthread_mutex_lock(&g_redis.mutex);
+        g_redis.publisher_connected = 1;
+        g_redis.published++;
+        g_redis.head = (g_redis.head + 1) % REDIS_QUEUE_CAP;
+        g_redis.count--;
+        pthread_mutex_unlock(&g_redis.mutex);
+    }
+    return NULL;
+}
+
+static void *subscriber_entry(void *arg) {
+    (void)arg;
+    int backoff = 1;
+    char *reply = (char *)malloc(REDIS_REPLY_MAX);
+    if (!reply) return NULL;
+    for (;;) {
+        redis_conn_t conn;
+        if (conn_open(&conn, &g_redis.config, 0) != 0) {
+            sleep((unsigned)backoff);
+            if (backoff < REDIS_MAX_BACKOFF_SEC) backoff *= 2;
+            continue;
+        }
+        strbuf_t cmd;
+        strbuf_init(&cmd);
+        const char *argv[] = {"SUBSCRIBE", g_redis.channel};
+        int ok = redis_encode_command(&cmd, 2, argv) == 0 && conn_send(conn.fd, cmd.data, cmd.len) == 0;
+        strbuf_free(&cmd);
+        if (ok) {
+            backoff = 1;
+            pthread_mutex_lock(&g_redis.mutex);
+            g_redis.subscriber_connected = 1;
+            pthread_mutex_unlock(&g_redis.mutex);
+            log_info("redis subscribed channel=%s host=%s:%d", g_redis.channel, g_redis.config.host, g_redis.config.port);
+        }
+        int parts = 0;
+        while (ok && conn_read_reply(&conn, reply, REDIS_REPLY_MAX, &parts) == 0) {
+            if (parts != 3 || strcmp(reply, "message") != 0) continue;
+            const char *channel = reply + strlen(reply) + 1;
+            const char *payload = channel + strlen(channel) + 1;
+            if (redis_handle_message(payload, server_instance_id()) == 1) {
+                pthread_mutex_lock(&g_redis.mutex);
+                g_redis.received++;
+                pthread_mutex_unlock(&g_redis.mutex);
+            }
+        }
+        pthread_mutex_lock(&g_redis.mutex);
+        g_redis.subscriber_connected = 0;
+        pthread_mutex_unlock(&g_redis.mutex);
+        log_warn("redis subscriber disconnected, retrying");
+        conn_close(&conn);
+        sleep((unsigned)backoff);
+    }
+    return NULL;
+}
+
+int redis_start(void) {
+    const char *url = config_secret("FRICU_REDIS_URL");
+    if (!url) return 0;
+    redis_config_t config;
+    if (redis_parse_url(url, &config) != 0) {
+        log_error("invalid FRICU_REDIS_URL (expected redis://[user:password@]host[:port][/db])");
+        return -1;
+    }
+    log_redact_value(config.password);
+    const char *prefix = getenv("FRICU_REDIS_PREFIX");
+    pthread_mutex_lock(&g_redis.mutex);
+    g_redis.config = config;
+    snprintf(g_redis.channel, sizeof(g_redis.channel), "%s:changes", prefix && prefix[0] ? prefix : "fricu");
+    g_redis.enabled = 1;
+    pthread_mutex_unlock(&g_redis.mutex);
+
+    pthread_t publisher;
+    pthread_t subscriber;
+    if (pthread_create(&publisher, NULL, publisher_entry, NULL) != 0 || pthread_create(&subscriber, NULL, subscriber_entry, NULL) != 0) {
+        log_error("failed to start redis threads");
+        return -1;
+    }
+    pthread_detach(publisher);
+    pthread_detach(subscriber);
+    log_info("redis bridge enabled host=%s:%d channel=%s", config.host, config.port, g_redis.channel);
+    return 0;
+}
+
+void redis_append_status(strbuf_t *sb) {
+    pthread_mutex_lock(&g_redis.mutex);
+    if (g_redis.enabled) {
+        strbuf_appendf(
+            sb,
+            ",\"redis\":{\"publisher_connected\":%s,\"subscriber_connected\":%s,\"published\":%lld,\"received\":%lld,\"pending\":%zu,\"dropped\":%lld}",
+            g_redis.publisher_connected ? "true" : "false",
+            g_redis.subscriber_connected ? "true" : "false",
+            g_redis.published,
+            g_redis.received,
+            g_redis.count,
+            g_redis.dropped);
+    }
+    pthread_mutex_unlock(&g_redis.mutex);
+}
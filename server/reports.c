@@ -0,0 +1,482 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Season report for one calendar year, rendered server-side so it can be handed to a coach or
+ * archived: weekly volume chart, power PRs, biggest weeks and race results. Weeks follow the
+ * profile's training week (weekStartDay, weekRolloverHour), so the first one may start in
+ * December. HTML is a single self-contained page with inline SVG; PDF is a one-page A4 document
+ * drawn with the base Helvetica font, so non-ASCII text is replaced there.
+ */
+
+/* Training weeks touching the year: up to six days before Jan 1 plus 365 or 366 days. */
+#define REPORT_WEEKS 54
+#define REPORT_TOP_WEEKS 5
+#define REPORT_MAX_RACES 12
+#define REPORT_TEXT_MAX 96
+
+static const int REPORT_PR_DURATIONS[] = {5, 60, 300, 1200, 3600};
+#define REPORT_PR_POINTS (sizeof(REPORT_PR_DURATIONS) / sizeof(REPORT_PR_DURATIONS[0]))
+
+typedef struct {
+    char date[16];
+    char name[REPORT_TEXT_MAX];
+    char category[16];
+    double duration_sec;
+    double tss;
+    int has_activity;
+} report_race_t;
+
+typedef struct {
+    int year;
+    int week_start;
+    int activities;
+    double hours;
+    double tss;
+    double distance_km;
+    double week_hours[REPORT_WEEKS];
+    double week_tss[REPORT_WEEKS];
+    int top_weeks[REPORT_TOP_WEEKS];
+    int top_week_count;
+    double pr_watts[REPORT_PR_POINTS];
+    char pr_date[REPORT_PR_POINTS][16];
+    report_race_t races[REPORT_MAX_RACES];
+    int race_count;
+} season_report_t;
+
+/* Activities count on their training day, in weeks starting on the profile's weekStartDay. */
+static int load_season_volume(
+    sqlite3 *db, const char *storage_key, const char *from, const char *to, const training_week_t *training_week, season_report_t *report) {
+    const char *sql =
+        "SELECT " TRAINING_DAY_SQL("json_extract(a.value, '$.date')", "?4") " AS day,"
+        " COALESCE(json_extract(a.value, '$.durationSec'), 0), COALESCE(json_extract(a.value, '$.tss'), 0),"
+        " COALESCE(json_extract(a.value, '$.distanceKm'), 0)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND day BETWEEN ?2 AND ?3";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 4, training_week->rollover_hour);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = 0;
+        const char *day_text = (const char *)sqlite3_column_text(stmt, 0);
+        if (!day_text || parse_iso_day(day_text, &day) != 0) continue;
+        double hours = sqlite3_column_double(stmt, 1) / 3600.0;
+        double tss = sqlite3_column_double(stmt, 2);
+        int week = (day - report->week_start) / 7;
+        report->activities++;
+        report->hours += hours;
+        report->tss += tss;
+        report->distance_km += sqlite3_column_double(stmt, 3);
+        if (week >= 0 && week < REPORT_WEEKS) {
+            report->week_hours[week] += hours;
+            report->week_tss[week] += tss;
+        }
+    }
+    sqlite3_finalize(stmt);
+
+    /* Biggest weeks by hours; ties go to the earlier week. */
+    int taken[REPORT_WEEKS] = {0};
+    while (report->top_week_count < REPORT_TOP_WEEKS) {
+        int best = -1;
+        for (int w = 0; w < REPORT_WEEKS; w++) {
+            if (!taken[w] && report->week_hours[w] > 0.0 && (best < 0 || report->week_hours[w] > report->week_hours[best])) best = w;
+        }
+        if (best < 0) break;
+        taken[best] = 1;
+        report->top_weeks[report->top_week_count++] = best;
+    }
+    return 0;
+}
+
+static int load_season_prs(sqlite3 *db, const char *storage_key, const char *from, const char *to, season_report_t *report) {
+    /* Same sources as the compare power curve: interval efforts, then whole-ride normalized power. */
+    const char *sql =
+        "SELECT p, day FROM ("
+        " SELECT json_extract(i.value, '$.actualPower') AS p, substr(json_extract(a.value, '$.date'), 1, 10) AS day"
+        " FROM kv_store k, json_each(k.data_value) a, json_each(a.value, '$.intervals') i"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3"
+        " AND json_extract(i.value, '$.durationSec') >= ?4"
+        " UNION ALL"
+        " SELECT json_extract(a.value, '$.normalizedPower'), substr(json_extract(a.value, '$.date'), 1, 10)"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND substr(json_extract(a.value, '$.date'), 1, 10) BETWEEN ?2 AND ?3"
+        " AND json_extract(a.value, '$.durationSec') >= ?4)"
+        " WHERE p IS NOT NULL ORDER BY p DESC, day LIMIT 1";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    for (size_t i = 0; i < REPORT_PR_POINTS; i++) {
+        sqlite3_reset(stmt);
+        sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_int(stmt, 4, REPORT_PR_DURATIONS[i]);
+        report->pr_watts[i] = -1.0;
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            report->pr_watts[i] = sqlite3_column_double(stmt, 0);
+            snprintf(report->pr_date[i], sizeof(report->pr_date[i]), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        }
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+static int load_season_races(sqlite3 *db, const char *account_id, const char *from, const char *to, season_report_t *report) {
+    char events_key[256] = {0};
+    char activities_key[256] = {0};
+    if (build_storage_key(account_id, "events", events_key, sizeof(events_key)) != 0 ||
+        build_storage_key(account_id, "activities", activities_key, sizeof(activities_key)) != 0) {
+        return -1;
+    }
+    /* Races are events typed as a race; the result is the longest activity recorded that day. */
+    const char *sql =
+        "SELECT substr(json_extract(e.value, '$.startDate'), 1, 10) AS day, COALESCE(json_extract(e.value, '$.name'), ''),"
+        " COALESCE(json_extract(e.value, '$.category'), ''),"
+        " (SELECT json_array(json_extract(a.value, '$.durationSec'), json_extract(a.value, '$.tss'))"
+        "  FROM kv_store ak, json_each(ak.data_value) a WHERE ak.data_key = ?2"
+        "  AND substr(json_extract(a.value, '$.date'), 1, 10) = substr(json_extract(e.value, '$.startDate'), 1, 10)"
+        "  ORDER BY json_extract(a.value, '$.durationSec') DESC LIMIT 1)"
+        " FROM kv_store k, json_each(k.data_value) e"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND lower(COALESCE(json_extract(e.value, '$.type'), '')) LIKE '%race%' AND day BETWEEN ?3 AND ?4"
+        " ORDER BY day LIMIT ?5";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, events_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, activities_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, to, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 5, REPORT_MAX_RACES);
+    while (sqlite3_step(stmt) == SQLITE_ROW && report->race_count < REPORT_MAX_RACES) {
+        report_race_t *race = &report->races[report->race_count++];
+        snprintf(race->date, sizeof(race->date), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        snprintf(race->name, sizeof(race->name), "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(race->category, sizeof(race->category), "%s", (const char *)sqlite3_column_text(stmt, 2));
+        const char *result = (const char *)sqlite3_column_text(stmt, 3);
+        if (result) {
+            race->has_activity = 1;
+            sscanf(result, "[%lf,%lf]", &race->duration_sec, &race->tss);
+        }
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+static int load_season_report(sqlite3 *db, const char *account_id, int year, season_report_t *report) {
+    memset(report, 0, sizeof(*report));
+    report->year = year;
+    char from[16] = {0};
+    char to[16] = {0};
+    snprintf(from, sizeof(from), "%04d-01-01", year);
+    snprintf(to, sizeof(to), "%04d-12-31", year);
+    int first_day = 0;
+    if (parse_iso_day(from, &first_day) != 0) return -1;
+    training_week_t training_week;
+    load_training_week(db, account_id, &training_week);
+    report->week_start = week_start_on(first_day, training_week.start_weekday);
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "activities", storage_key, sizeof(storage_key)) != 0) return -1;
+    if (load_season_volume(db, storage_key, from, to, &training_week, report) != 0) return -1;
+    if (load_season_prs(db, storage_key, from, to, report) != 0) return -1;
+    return load_season_races(db, account_id, from, to, report);
+}
+
+static void format_duration(double seconds, char *out, size_t out_len) {
+    int total = (int)(seconds + 0.5);
+    if (total >= 3600) {
+        snprintf(out, out_len, "%d:%02d:%02d", total / 3600, total / 60 % 60, total % 60);
+    } else {
+        snprintf(out, out_len, "%d:%02d", total / 60, total % 60);
+    }
+}
+
+static void format_pr_label(int seconds, char *out, size_t out_len) {
+    if (seconds < 60) {
+        snprintf(out, out_len, "%ds", seconds);
+    } else if (seconds < 3600) {
+        snprintf(out, out_len, "%dmin", seconds / 60);
+    } else {
+        snprintf(out, out_len, "%dh", seconds / 3600);
+    }
+}
+
+static double max_week_hours(const season_report_t *report) {
+    double max = 0.0;
+    for (int w = 0; w < REPORT_WEEKS; w++) {
+        if (report->week_hours[w] > max) max = report->week_hours[w];
+    }
+    return max;
+}
+
+static void append_html_text(strbuf_t *sb, const char *text) {
+    for (const char *p = text; *p; p++) {
+        switch (*p) {
+            case '&':
+                strbuf_append(sb, "&amp;", 5);
+                break;
+            case '<':
+                strbuf_append(sb, "&lt;", 4);
+                break;
+            case '>':
+                strbuf_append(sb, "&gt;", 4);
+                break;
+            case '"':
+                strbuf_append(sb, "&quot;", 6);
+                break;
+            default:
+                strbuf_append(sb, p, 1);
+        }
+    }
+}
+
+static void append_html_label(strbuf_t *sb, const char *open, const char *lang, const char *key, const char *close) {
+    strbuf_append(sb, open, strlen(open));
+    const char *text = i18n_text(lang, key);
+    strbuf_append(sb, text, strlen(text));
+    strbuf_append(sb, close, strlen(close));
+}
+
+static void render_season_report_html(const season_report_t *report, const char *lang, strbuf_t *sb) {
+    char year[8] = {0};
+    char activities[16] = {0};
+    char hours[32] = {0};
+    char tss[32] = {0};
+    char km[32] = {0};
+    snprintf(year, sizeof(year), "%d", report->year);
+    snprintf(activities, sizeof(activities), "%d", report->activities);
+    snprintf(hours, sizeof(hours), "%.1f", report->hours);
+    snprintf(tss, sizeof(tss), "%.0f", report->tss);
+    snprintf(km, sizeof(km), "%.1f", report->distance_km);
+    const i18n_arg_t title_args[] = {{"year", year}};
+    const i18n_arg_t summary_args[] = {{"activities", activities}, {"hours", hours}, {"tss", tss}, {"km", km}};
+
+    strbuf_appendf(sb, "<!DOCTYPE html>\n<html lang=\"%s\"><head><meta charset=\"utf-8\"><title>", lang);
+    i18n_format(lang, "report.title", title_args, 1, sb);
+    strbuf_append(
+        sb,
+        "</title>\n"
+        "<style>body{font-family:-apple-system,Helvetica,Arial,sans-serif;margin:32px;color:#222}"
+        "table{border-collapse:collapse;margin-bottom:24px}td,th{padding:4px 12px;border-bottom:1px solid #ddd;text-align:left}"
+        ".bar{fill:#2f7bd8}.axis{stroke:#999}</style></head><body>\n<h1>",
+        277);
+    i18n_format(lang, "report.title", title_args, 1, sb);
+    strbuf_append(sb, "</h1>\n<p>", 9);
+    i18n_format(lang, "report.summary", summary_args, 4, sb);
+    strbuf_append(sb, "</p>\n", 5);
+
+    double max_hours = max_week_hours(report);
+    append_html_label(sb, "<h2>", lang, "report.weekly_volume", "</h2>\n<svg width=\"640\" height=\"180\" viewBox=\"0 0 640 180\" role=\"img\">\n");
+    for (int w = 0; w < REPORT_WEEKS; w++) {
+        double height = max_hours > 0.0 ? report->week_hours[w] / max_hours * 150.0 : 0.0;
+        if (height <= 0.0) continue;
+        char week[8] = {0};
+        snprintf(week, sizeof(week), "%d", w + 1);
+        snprintf(hours, sizeof(hours), "%.1f", report->week_hours[w]);
+        snprintf(tss, sizeof(tss), "%.0f", report->week_tss[w]);
+        const i18n_arg_t bar_args[] = {{"week", week}, {"hours", hours}, {"tss", tss}};
+        strbuf_appendf(sb, "<rect class=\"bar\" x=\"%d\" y=\"%.1f\" width=\"9\" height=\"%.1f\"><title>", 20 + w * 11, 160.0 - height, height);
+        i18n_format(lang, "report.week_bar", bar_args, 3, sb);
+        strbuf_append(sb, "</title></rect>\n", 16);
+    }
+    snprintf(hours, sizeof(hours), "%.1f", max_hours);
+    const i18n_arg_t max_args[] = {{"hours", hours}};
+    strbuf_append(sb, "<line class=\"axis\" x1=\"18\" y1=\"160\" x2=\"614\" y2=\"160\"/>\n<text x=\"20\" y=\"176\" font-size=\"11\">", 92);
+    i18n_format(lang, "report.max_week", max_args, 1, sb);
+    strbuf_append(sb, "</text>\n</svg>\n", 15);
+
+    append_html_label(sb, "<h2>", lang, "report.power_prs", "</h2>\n<table><tr>");
+    append_html_label(sb, "<th>", lang, "report.duration", "</th>");
+    append_html_label(sb, "<th>", lang, "report.watts", "</th>");
+    append_html_label(sb, "<th>", lang, "report.date", "</th></tr>\n");
+    for (size_t i = 0; i < REPORT_PR_POINTS; i++) {
+        if (report->pr_watts[i] < 0.0) continue;
+        char label[16] = {0};
+        format_pr_label(REPORT_PR_DURATIONS[i], label, sizeof(label));
+        strbuf_appendf(sb, "<tr><td>%s</td><td>%.0f W</td><td>%s</td></tr>\n", label, report->pr_watts[i], report->pr_date[i]);
+    }
+    append_html_label(sb, "</table>\n<h2>", lang, "report.biggest_weeks", "</h2>\n<table><tr>");
+    append_html_label(sb, "<th>", lang, "report.week_of", "</th>");
+    append_html_label(sb, "<th>", lang, "report.hours", "</th>");
+    strbuf_append(sb, "<th>TSS</th></tr>\n", 18);
+    for (int i = 0; i < report->top_week_count; i++) {
+        int w = report->top_weeks[i];
+        char week_start[16] = {0};
+        format_iso_day(report->week_start + w * 7, week_start, sizeof(week_start));
+        strbuf_appendf(sb, "<tr><td>%s</td><td>%.1f</td><td>%.0f</td></tr>\n", week_start, report->week_hours[w], report->week_tss[w]);
+    }
+    append_html_label(sb, "</table>\n<h2>", lang, "report.race_results", "</h2>\n<table><tr>");
+    append_html_label(sb, "<th>", lang, "report.date", "</th>");
+    append_html_label(sb, "<th>", lang, "report.race", "</th>");
+    append_html_label(sb, "<th>", lang, "report.priority", "</th>");
+    append_html_label(sb, "<th>", lang, "report.time", "</th>");
+    strbuf_append(sb, "<th>TSS</th></tr>\n", 18);
+    for (int i = 0; i < report->race_count; i++) {
+        const report_race_t *race = &report->races[i];
+        strbuf_appendf(sb, "<tr><td>%s</td><td>", race->date);
+        append_html_text(sb, race->name);
+        strbuf_append(sb, "</td><td>", 9);
+        append_html_text(sb, race->category);
+        if (race->has_activity) {
+            char duration[16] = {0};
+            format_duration(race->duration_sec, duration, sizeof(duration));
+            strbuf_appendf(sb, "</td><td>%s</td><td>%.0f</td></tr>\n", duration, race->tss);
+        } else {
+            strbuf_append(sb, "</td><td>&ndash;</td><td>&ndash;</td></tr>\n", 43);
+        }
+    }
+    strbuf_append(sb, "</table>\n</body></html>\n", 24);
+}
+
+/* PDF literal strings: escape delimiters and fold anything outside printable ASCII. */
+static void append_pdf_text(strbuf_t *sb, const char *text) {
+    for (const unsigned char *p = (const unsigned char *)text; *p; p++) {
+        if (*p == '(' || *p == ')' || *p == '\\') {
+            char escaped[2] = {'\\', (char)*p};
+            strbuf_append(sb, escaped, 2);
+        } else if (*p >= 0x20 && *p < 0x7f) {
+            strbuf_append(sb, (const char *)p, 1);
+        } else if ((*p & 0xc0) != 0x80) {
+            strbuf_append(sb, "?", 1);
+        }
+    }
+}
+
+static void pdf_line(strbuf_t *content, double x, double y, int size, const char *text) {
+    strbuf_appendf(content, "BT /F1 %d Tf %.1f %.1f Td (", size, x, y);
+    append_pdf_text(content, text);
+    strbuf_append(content, ") Tj ET\n", 8);
+}
+
+static void render_season_report_pdf(const season_report_t *report, strbuf_t *sb) {
+    strbuf_t content;
+    strbuf_init(&content);
+    char line[256] = {0};
+    double y = 790.0;
+    snprintf(line, sizeof(line), "Season report %d", report->year);
+    pdf_line(&content, 50, y, 20, line);
+    y -= 24;
+    snprintf(line, sizeof(line), "%d activities - %.1f h - %.0f TSS - %.1f km", report->activities, report->hours, report->tss, report->distance_km);
+    pdf_line(&content, 50, y, 11, line);
+
+    y -= 30;
+    pdf_line(&content, 50, y, 14, "Weekly volume (hours)");
+    double max_hours = max_week_hours(report);
+    double chart_base = y - 130;
+    strbuf_append(&content, "0.18 0.48 0.85 rg\n", 18);
+    for (int w = 0; w < REPORT_WEEKS; w++) {
+        double height = max_hours > 0.0 ? report->week_hours[w] / max_hours * 110.0 : 0.0;
+        if (height > 0.0) strbuf_appendf(&content, "%.1f %.1f 7 %.1f re f\n", 50.0 + w * 9.0, chart_base, height);
+    }
+    strbuf_appendf(&content, "0 g 0.5 w 48 %.1f m 536 %.1f l S\n", chart_base, chart_base);
+    snprintf(line, sizeof(line), "max %.1f h/week", max_hours);
+    pdf_line(&content, 50, chart_base - 14, 9, line);
+
+    y = chart_base - 40;
+    pdf_line(&content, 50, y, 14, "Power PRs");
+    for (size_t i = 0; i < REPORT_PR_POINTS; i++) {
+        if (report->pr_watts[i] < 0.0) continue;
+        char label[16] = {0};
+        format_pr_label(REPORT_PR_DURATIONS[i], label, sizeof(label));
+        snprintf(line, sizeof(line), "%-6s %4.0f W   %s", label, report->pr_watts[i], report->pr_date[i]);
+        y -= 15;
+        pdf_line(&content, 60, y, 10, line);
+    }
+
+    y -= 28;
+    pdf_line(&content, 50, y, 14, "Biggest weeks");
+    for (int i = 0; i < report->top_week_count; i++) {
+        int w = report->top_weeks[i];
+        char week_start[16] = {0};
+        format_iso_day(report->week_start + w * 7, week_start, sizeof(week_start));
+        snprintf(line, sizeof(line), "Week of %s   %.1f h   %.0f TSS", week_start, report->week_hours[w], report->week_tss[w]);
+        y -= 15;
+        pdf_line(&content, 60, y, 10, line);
+    }
+
+    y -= 28;
+    pdf_line(&content, 50, y, 14, "Race results");
+    for (int i = 0; i < report->race_count; i++) {
+        const report_race_t *race = &report->races[i];
+        char duration[16] = "-";
+        if (race->has_activity) format_duration(race->duration_sec, duration, sizeof(duration));
+        snprintf(line, sizeof(line), "%s  %s  [%s]  %s", race->date, race->name, race->category[0] ? race->category : "-", duration);
+        y -= 15;
+        pdf_line(&content, 60, y, 10, line);
+    }
+
+    /* Objects: 1 catalog, 2 pages, 3 page, 4 font, 5 content stream. */
+    size_t offsets[6] = {0};
+    strbuf_append(sb, "%PDF-1.4\n", 9);
+    offsets[1] = sb->len;
+    strbuf_append(sb, "1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n", 49);
+    offsets[2] = sb->len;
+    strbuf_append(sb, "2 0 obj\n<< /Type /Pages /Kids [3 0 R] /Count 1 >>\nendobj\n", 57);
+    offsets[3] = sb->len;
+    strbuf_append(sb, "3 0 obj\n<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Resources << /Font << /F1 4 0 R >> >> /Contents 5 0 R >>\nendobj\n", 126);
+    offsets[4] = sb->len;
+    strbuf_append(sb, "4 0 obj\n<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>\nendobj\n", 97);
+    offsets[5] = sb->len;
+    strbuf_appendf(sb, "5 0 obj\n<< /Length %zu >>\nstream\n", content.len);
+    strbuf_append(sb, strbuf_cstr(&content), content.len);
+    strbuf_append(sb, "endstream\nendobj\n", 17);
+    size_t xref = sb->len;
+    strbuf_append(sb, "xref\n0 6\n0000000000 65535 f \n", 29);
+    for (int i = 1; i <= 5; i++) strbuf_appendf(sb, "%010zu 00000 n \n", offsets[i]);
+    strbuf_appendf(sb, "trailer\n<< /Size 6 /Root 1 0 R >>\nstartxref\n%zu\n%%%%EOF\n", xref);
+    if (content.failed) sb->failed = 1;
+    strbuf_free(&content);
+}
+
+int handle_get_season_report(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw_year[8] = {0};
+    char format[8] = {0};
+    if (!query_param(req->query, "year", raw_year, sizeof(raw_year)) || strlen(raw_year) != 4 || strspn(raw_year, "0123456789") != 4) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"year must be YYYY\"}", ctx);
+        return 400;
+    }
+    if (!query_param(req->query, "format", format, sizeof(format)) || format[0] == '\0') snprintf(format, sizeof(format), "html");
+    int pdf = strcmp(format, "pdf") == 0;
+    if (!pdf && strcmp(format, "html") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"format must be html or pdf\"}", ctx);
+        return 400;
+    }
+
+    const char *lang = pdf ? "en" : i18n_negotiate(db->db, req, ctx->account_id);
+    season_report_t report;
+    if (load_season_report(db->db, ctx->account_id, atoi(raw_year), &report) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"report error\"}", ctx);
+        return 500;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (pdf) {
+        render_season_report_pdf(&report, &sb);
+    } else {
+        render_season_report_html(&report, lang, &sb);
+    }
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    char headers[160] = {0};
+    snprintf(
+        headers, sizeof(headers), "Content-Disposition: inline; filename=\"season-%s.%s\"\r\nContent-Language: %s\r\n", raw_year, pdf ? "pdf" : "html", lang);
+    send_http_response(fd, 200, "OK", pdf ? "application/pdf" : "text/html; charset=utf-8", headers, strbuf_cstr(&sb), sb.len, ctx);
+    strbuf_free(&sb);
+    log_info("REPORT season year=%s format=%s activities=%d account=%s logid=%s", raw_year, format, report.activities, ctx->account_id, ctx->log_id);
+    return 200;
+}
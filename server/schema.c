@@ -0,0 +1,411 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Schema registry: one schema per entry in DATA_KEYS describing the fields the server reads. Every
+ * write through store_account_data is checked against it, so a client cannot store, say, a string
+ * where analytics expect a number or a list of strings where items must be objects; failures answer
+ * 422 with the offending JSON paths. Fields stay optional and may be null (clients omit or null out
+ * what they do not track) and unknown fields pass through unchecked, so the schemas only pin the
+ * type of what they name. Some fields also carry a value check: dates must start with a valid
+ * YYYY-MM-DD, durations, distances, loads and body metrics must not be negative, and sport must be a
+ * sport the server scores (or an alias sport_canonical_name maps to one), weekStartDay must name a
+ * weekday and weekRolloverHour must be an hour 0..23. GET /v1/schemas[/<key>]
+ * serves them as JSON Schema documents.
+ */
+
+#define SCHEMA_STRING 0x01
+#define SCHEMA_NUMBER 0x02
+#define SCHEMA_INTEGER 0x04
+#define SCHEMA_BOOLEAN 0x08
+#define SCHEMA_ARRAY 0x10
+#define SCHEMA_OBJECT 0x20
+/* Ids are strings, but older clients wrote numeric ones. */
+#define SCHEMA_ID (SCHEMA_STRING | SCHEMA_INTEGER)
+#define SCHEMA_MAX_ERRORS 8
+
+typedef enum { CHECK_NONE, CHECK_DATE, CHECK_NON_NEGATIVE, CHECK_SPORT, CHECK_WEEKDAY, CHECK_HOUR } schema_check_t;
+
+typedef struct {
+    const char *name;
+    int types;
+    schema_check_t check;
+} schema_field_t;
+
+typedef struct {
+    const char *key;
+    int is_object;
+    const schema_field_t *fields;
+} data_schema_t;
+
+static const schema_field_t ACTIVITY_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"date", SCHEMA_STRING, CHECK_DATE},
+    {"sport", SCHEMA_STRING, CHECK_SPORT},
+    {"name", SCHEMA_STRING, CHECK_NONE},
+    {"durationSec", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"distanceKm", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"tss", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"loadModel", SCHEMA_STRING, CHECK_NONE},
+    {"loads", SCHEMA_OBJECT, CHECK_NONE},
+    {"normalizedPower", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"avgPower", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"avgHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"maxHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"rpe", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"feel", SCHEMA_NUMBER, CHECK_NONE},
+    {"gear", SCHEMA_STRING, CHECK_NONE},
+    {"notes", SCHEMA_STRING, CHECK_NONE},
+    {"tags", SCHEMA_ARRAY, CHECK_NONE},
+    {"intervals", SCHEMA_ARRAY, CHECK_NONE},
+    {"powerSamples", SCHEMA_ARRAY, CHECK_NONE},
+    {"heartRateSamples", SCHEMA_ARRAY, CHECK_NONE},
+    {"externalID", SCHEMA_STRING, CHECK_NONE},
+    {"sourceFileName", SCHEMA_STRING, CHECK_NONE},
+    {"importRunID", SCHEMA_STRING, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t METRIC_INSIGHT_FIELDS[] = {
+    {"activityID", SCHEMA_ID, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t MEAL_PLAN_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"name", SCHEMA_STRING, CHECK_NONE},
+    {"date", SCHEMA_STRING, CHECK_DATE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t CUSTOM_FOOD_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"name", SCHEMA_STRING, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t WORKOUT_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"name", SCHEMA_STRING, CHECK_NONE},
+    {"sport", SCHEMA_STRING, CHECK_SPORT},
+    {"scheduledDate", SCHEMA_STRING, CHECK_DATE},
+    {"athleteName", SCHEMA_STRING, CHECK_NONE},
+    {"segments", SCHEMA_ARRAY, CHECK_NONE},
+    {"targetScaling", SCHEMA_OBJECT, CHECK_NONE},
+    {"externalID", SCHEMA_STRING, CHECK_NONE},
+    {"importRunID", SCHEMA_STRING, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t EVENT_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"name", SCHEMA_STRING, CHECK_NONE},
+    {"category", SCHEMA_STRING, CHECK_NONE},
+    {"status", SCHEMA_STRING, CHECK_NONE},
+    {"startDate", SCHEMA_STRING, CHECK_DATE},
+    {"endDate", SCHEMA_STRING, CHECK_DATE},
+    {"notes", SCHEMA_STRING, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t WELLNESS_FIELDS[] = {
+    {"date", SCHEMA_STRING, CHECK_DATE},
+    {"hrv", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"restingHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"weightKg", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"sleepHours", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"sleepScore", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t PROFILE_FIELDS[] = {
+    {"ftpWatts", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"cyclingFTPWatts", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"criticalPowerWatts", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"wPrimeJoules", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"thresholdHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"restingHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"maxHeartRate", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"hrvBaseline", SCHEMA_NUMBER, CHECK_NON_NEGATIVE},
+    {"sports", SCHEMA_OBJECT, CHECK_NONE},
+    {"altitudePowerFactors", SCHEMA_ARRAY, CHECK_NONE},
+    {"weekStartDay", SCHEMA_STRING, CHECK_WEEKDAY},
+    {"weekRolloverHour", SCHEMA_INTEGER, CHECK_HOUR},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t APP_SETTINGS_FIELDS[] = {
+    {"dataMinimization", SCHEMA_BOOLEAN, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const schema_field_t LACTATE_FIELDS[] = {
+    {"id", SCHEMA_ID, CHECK_NONE},
+    {"createdAt", SCHEMA_STRING, CHECK_NONE},
+    {NULL, 0, CHECK_NONE},
+};
+
+static const data_schema_t DATA_SCHEMAS[] = {
+    {"activities", 0, ACTIVITY_FIELDS},
+    {"activity_metric_insights", 0, METRIC_INSIGHT_FIELDS},
+    {"meal_plans", 0, MEAL_PLAN_FIELDS},
+    {"custom_foods", 0, CUSTOM_FOOD_FIELDS},
+    {"workouts", 0, WORKOUT_FIELDS},
+    {"events", 0, EVENT_FIELDS},
+    {"wellness_samples", 0, WELLNESS_FIELDS},
+    {"profile", 1, PROFILE_FIELDS},
+    {"app_settings", 1, APP_SETTINGS_FIELDS},
+    {"lactate_history_records", 0, LACTATE_FIELDS},
+    {"archived_activities", 0, ACTIVITY_FIELDS},
+};
+
+static const data_schema_t *find_schema(const char *key) {
+    for (size_t i = 0; i < sizeof(DATA_SCHEMAS) / sizeof(DATA_SCHEMAS[0]); i++) {
+        if (strcmp(DATA_SCHEMAS[i].key, key) == 0) return &DATA_SCHEMAS[i];
+    }
+    return NULL;
+}
+
+static int json_type_bit(const char *type) {
+    if (!type) return 0;
+    if (strcmp(type, "text") == 0) return SCHEMA_STRING;
+    if (strcmp(type, "integer") == 0) return SCHEMA_INTEGER | SCHEMA_NUMBER;
+    if (strcmp(type, "real") == 0) return SCHEMA_NUMBER;
+    if (strcmp(type, "true") == 0 || strcmp(type, "false") == 0) return SCHEMA_BOOLEAN;
+    if (strcmp(type, "array") == 0) return SCHEMA_ARRAY;
+    if (strcmp(type, "object") == 0) return SCHEMA_OBJECT;
+    return 0;
+}
+
+/* "string or integer" style description of a type mask. */
+static void describe_types(int types, char *out, size_t out_len) {
+    static const struct {
+        int bit;
+        const char *name;
+    } names[] = {
+        {SCHEMA_STRING, "string"},
+        {SCHEMA_NUMBER, "number"},
+        {SCHEMA_INTEGER, "integer"},
+        {SCHEMA_BOOLEAN, "boolean"},
+        {SCHEMA_ARRAY, "array"},
+        {SCHEMA_OBJECT, "object"},
+    };
+    out[0] = '\0';
+    size_t len = 0;
+    for (size_t i = 0; i < sizeof(names) / sizeof(names[0]); i++) {
+        if (!(types & names[i].bit) || (names[i].bit == SCHEMA_INTEGER && (types & SCHEMA_NUMBER))) continue;
+        int n = snprintf(out + len, out_len - len, "%s%s", len > 0 ? " or " : "", names[i].name);
+        if (n < 0 || (size_t)n >= out_len - len) break;
+        len += (size_t)n;
+    }
+}
+
+static const char *json_type_name(const char *type) {
+    if (strcmp(type, "text") == 0) return "string";
+    if (strcmp(type, "real") == 0) return "number";
+    if (strcmp(type, "true") == 0 || strcmp(type, "false") == 0) return "boolean";
+    return type;
+}
+
+/* Returns 1 and describes the problem when the (correctly typed) value fails the field's check. */
+static int check_value(schema_check_t check, sqlite3_stmt *stmt, int col, char *expected, size_t expected_len, char *got, size_t got_len) {
+    if (check == CHECK_NON_NEGATIVE) {
+        double value = sqlite3_column_double(stmt, col);
+        if (value >= 0) return 0;
+        snprintf(expected, expected_len, "non-negative number");
+        snprintf(got, got_len, "%g", value);
+        return 1;
+    }
+    if (check == CHECK_HOUR) {
+        sqlite3_int64 value = sqlite3_column_int64(stmt, col);
+        if (value >= 0 && value <= 23) return 0;
+        snprintf(expected, expected_len, "hour 0..23");
+        snprintf(got, got_len, "%lld", (long long)value);
+        return 1;
+    }
+    const char *text = (const char *)sqlite3_column_text(stmt, col);
+    /* An empty string is treated like a missing value. */
+    if (!text || text[0] == '\0' || check == CHECK_NONE) return 0;
+    int day = 0;
+    const char *wanted = "known sport";
+    if (check == CHECK_DATE) {
+        if (strlen(text) >= 10 && parse_iso_day(text, &day) == 0) return 0;
+        wanted = "YYYY-MM-DD date";
+    } else if (check == CHECK_WEEKDAY) {
+        if (weekday_from_name(text) >= 0) return 0;
+        wanted = "weekday name";
+    } else if (sport_canonical_name(text)) {
+        return 0;
+    }
+    snprintf(expected, expected_len, "%s", wanted);
+    /* Echo the value only when it cannot break the JSON body. */
+    size_t len = strlen(text);
+    int plain = len > 0 && len < got_len;
+    for (size_t i = 0; plain && i < len; i++) {
+        char c = text[i];
+        plain = (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '-' || c == '_' || c == ':' || c == '.' || c == ' ';
+    }
+    snprintf(got, got_len, "%s", plain ? text : "string");
+    return 1;
+}
+
+/* Appends {"path","expected","got"} while it fits, leaving room for the closing "]}". */
+static void append_error(char *out, size_t out_len, size_t *len, int *listed, const char *path, const char *expected, const char *got) {
+    if (*listed >= SCHEMA_MAX_ERRORS) return;
+    char entry[256] = {0};
+    int n = snprintf(entry, sizeof(entry), "%s{\"path\":\"%s\",\"expected\":\"%s\",\"got\":\"%s\"}", *listed > 0 ? "," : "", path, expected, got);
+    if (n < 0 || (size_t)n >= sizeof(entry) || *len + (size_t)n + 3 > out_len) return;
+    memcpy(out + *len, entry, (size_t)n + 1);
+    *len += (size_t)n;
+    (*listed)++;
+}
+
+int data_schema_validate(sqlite3 *db, const char *key, const char *payload, size_t payload_len, char *out_body, size_t out_len) {
+    const data_schema_t *schema = find_schema(key);
+    if (!schema) return 0;
+
+    /* One row per top-level field of each item (or of the object), plus one per non-object item. */
+    const char *sql = schema->is_object ? "SELECT NULL, 'object', key, type, value FROM json_each(?1)"
+                                        : "SELECT i.key, i.type, f.key, f.type, f.value FROM json_each(?1) i"
+                                          " LEFT JOIN json_each(CASE WHEN i.type = 'object' THEN i.value ELSE '{}' END) f ORDER BY i.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        snprintf(out_body, out_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, payload, (int)payload_len, SQLITE_STATIC);
+
+    char errors[SCHEMA_MAX_ERRORS * 160] = {0};
+    size_t errors_len = 0;
+    int listed = 0;
+    int error_count = 0;
+    int rc;
+    while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        char path[192] = {0};
+        int item = sqlite3_column_type(stmt, 0) != SQLITE_NULL;
+        const char *item_type = (const char *)sqlite3_column_text(stmt, 1);
+        if (item && strcmp(item_type, "object") != 0) {
+            snprintf(path, sizeof(path), "$[%d]", sqlite3_column_int(stmt, 0));
+            append_error(errors, sizeof(errors), &errors_len, &listed, path, "object", json_type_name(item_type));
+            error_count++;
+            continue;
+        }
+        const char *field = (const char *)sqlite3_column_text(stmt, 2);
+        const char *field_type = (const char *)sqlite3_column_text(stmt, 3);
+        if (!field || !field_type || strcmp(field_type, "null") == 0) continue;
+        for (const schema_field_t *f = schema->fields; f->name; f++) {
+            if (strcmp(f->name, field) != 0) continue;
+            char expected[64] = {0};
+            char got[48] = {0};
+            if (!(json_type_bit(field_type) & f->types)) {
+                describe_types(f->types, expected, sizeof(expected));
+                snprintf(got, sizeof(got), "%s", json_type_name(field_type));
+            } else if (!check_value(f->check, stmt, 4, expected, sizeof(expected), got, sizeof(got))) {
+                break;
+            }
+            if (item) {
+                snprintf(path, sizeof(path), "$[%d].%.128s", sqlite3_column_int(stmt, 0), field);
+            } else {
+                snprintf(path, sizeof(path), "$.%.128s", field);
+            }
+            append_error(errors, sizeof(errors), &errors_len, &listed, path, expected, got);
+            error_count++;
+            break;
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        snprintf(out_body, out_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    if (error_count == 0) return 0;
+
+    /* Drop listed errors from the end until the whole body fits the caller's buffer. */
+    for (;;) {
+        int n = snprintf(out_body, out_len, "{\"error\":\"schema validation failed\",\"key\":\"%s\",\"error_count\":%d,\"errors\":[%s]}", key, error_count, errors);
+        if (n >= 0 && (size_t)n < out_len) break;
+        char *last = strrchr(errors, '{');
+        if (!last || last == errors) {
+            snprintf(out_body, out_len, "{\"error\":\"schema validation failed\",\"key\":\"%s\",\"error_count\":%d,\"errors\":[]}", key, error_count);
+            break;
+        }
+        last[-1] = '\0';
+    }
+    return 422;
+}
+
+static void append_json_schema(strbuf_t *sb, const data_schema_t *schema) {
+    strbuf_appendf(
+        sb,
+        "{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"$id\":\"/v1/schemas/%s\",\"title\":\"%s\",\"type\":\"%s\",",
+        schema->key,
+        schema->key,
+        schema->is_object ? "object" : "array");
+    if (!schema->is_object) strbuf_append(sb, "\"items\":{\"type\":\"object\",", 25);
+    strbuf_append(sb, "\"properties\":{", 14);
+    int count = 0;
+    for (const schema_field_t *f = schema->fields; f->name; f++) {
+        strbuf_appendf(sb, "%s\"%s\":{\"type\":[", count++ > 0 ? "," : "", f->name);
+        if (f->types & SCHEMA_STRING) strbuf_append(sb, "\"string\",", 9);
+        if (f->types & SCHEMA_NUMBER) {
+            strbuf_append(sb, "\"number\",", 9);
+        } else if (f->types & SCHEMA_INTEGER) {
+            strbuf_append(sb, "\"integer\",", 10);
+        }
+        if (f->types & SCHEMA_BOOLEAN) strbuf_append(sb, "\"boolean\",", 10);
+        if (f->types & SCHEMA_ARRAY) strbuf_append(sb, "\"array\",", 8);
+        if (f->types & SCHEMA_OBJECT) strbuf_append(sb, "\"object\",", 9);
+        strbuf_append(sb, "\"null\"]", 7);
+        if (f->check == CHECK_DATE) {
+            strbuf_append(sb, ",\"pattern\":\"^\\\\d{4}-\\\\d{2}-\\\\d{2}\"", 34);
+        } else if (f->check == CHECK_NON_NEGATIVE) {
+            strbuf_append(sb, ",\"minimum\":0", 12);
+        } else if (f->check == CHECK_SPORT) {
+            strbuf_append(sb, ",\"description\":\"cycling, running, swimming or strength; common aliases such as ride or run are accepted\"", 104);
+        } else if (f->check == CHECK_WEEKDAY) {
+            strbuf_append(sb, ",\"enum\":[\"monday\",\"tuesday\",\"wednesday\",\"thursday\",\"friday\",\"saturday\",\"sunday\",null]", 85);
+        } else if (f->check == CHECK_HOUR) {
+            strbuf_append(sb, ",\"minimum\":0,\"maximum\":23", 25);
+        }
+        strbuf_append(sb, "}", 1);
+    }
+    strbuf_append(sb, "},\"additionalProperties\":true}", 30);
+    if (!schema->is_object) strbuf_append(sb, "}", 1);
+}
+
+int handle_get_data_schema(int fd, const char *key, const request_log_context_t *ctx) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (key) {
+        const data_schema_t *schema = find_schema(key);
+        if (!schema) {
+            strbuf_free(&sb);
+            send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown key\"}", ctx);
+            return 404;
+        }
+        append_json_schema(&sb, schema);
+    } else {
+        strbuf_append(&sb, "{\"schemas\":[", 12);
+        for (size_t i = 0; i < sizeof(DATA_SCHEMAS) / sizeof(DATA_SCHEMAS[0]); i++) {
+            if (i > 0) strbuf_append(&sb, ",", 1);
+            append_json_schema(&sb, &DATA_SCHEMAS[i]);
+        }
+        strbuf_append(&sb, "]}", 2);
+    }
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
@@ -0,0 +1,272 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Secrets such as FRICU_ADMIN_TOKEN and the password inside FRICU_REDIS_URL are resolved through
+ * config_secret instead of getenv, trying in order: the variable itself, <NAME>_FILE naming a file
+ * that holds the value (Docker/Kubernetes secrets; one trailing newline is dropped), and the
+ * FRICU_SECRETS_FILE dotenv file (NAME=value lines, as written by `sops -d --output-type dotenv` or
+ * a Vault Agent template). Every resolved value is registered with the logger so it is masked in
+ * all log lines, and --check-config reports only where each secret came from.
+ */
+
+#define SECRETS_MAX 32
+#define SECRET_VALUE_MAX 4096
+
+typedef struct {
+    char name[64];
+    char *value;
+    const char *source;
+    char origin[256];
+    char error[128];
+} secret_entry_t;
+
+static pthread_mutex_t g_secrets_mutex = PTHREAD_MUTEX_INITIALIZER;
+static secret_entry_t g_secrets[SECRETS_MAX];
+static size_t g_secret_count;
+
+/* Server settings that hold credentials; --check-config lists these without their values. */
+static const char *KNOWN_SECRETS[] = {"FRICU_ADMIN_TOKEN", "FRICU_REDIS_URL", "FRICU_EXPORT_PASSPHRASE", "FRICU_OTLP_HEADERS"};
+
+static int read_secret_file(const char *path, char **out, char *err, size_t err_len) {
+    FILE *fp = fopen(path, "rb");
+    if (!fp) {
+        snprintf(err, err_len, "cannot open %s", path);
+        return -1;
+    }
+    char *buf = (char *)malloc(SECRET_VALUE_MAX + 1);
+    if (!buf) {
+        fclose(fp);
+        snprintf(err, err_len, "out of memory");
+        return -1;
+    }
+    size_t n = fread(buf, 1, SECRET_VALUE_MAX + 1, fp);
+    fclose(fp);
+    if (n > SECRET_VALUE_MAX) {
+        free(buf);
+        snprintf(err, err_len, "%s is larger than %d bytes", path, SECRET_VALUE_MAX);
+        return -1;
+    }
+    buf[n] = '\0';
+    if (n > 0 && buf[n - 1] == '\n') buf[--n] = '\0';
+    if (n > 0 && buf[n - 1] == '\r') buf[--n] = '\0';
+    *out = buf;
+    return 0;
+}
+
+/* Finds NAME=value in a dotenv file; blank lines, # comments, `export ` and quotes are handled. */
+static int read_dotenv_value(const char *path, const char *name, char **out, char *err, size_t err_len) {
+    FILE *fp = fopen(path, "r");
+    if (!fp) {
+        snprintf(err, err_len, "cannot open %s", path);
+        return -1;
+    }
+    char line[SECRET_VALUE_MAX + 128];
+    size_t name_len = strlen(name);
+    int found = 0;
+    while (!found && fgets(line, sizeof(line), fp)) {
+        char *p = line + strspn(line, " \t");
+        if (strncmp(p, "export ", 7) == 0) p += 7 + strspn(p + 7, " \t");
+        if (*p == '#' || strncmp(p, name, name_len) != 0 || p[name_len] != '=') continue;
+        char *value = p + name_len + 1;
+        value[strcspn(value, "\r\n")] = '\0';
+        size_t len = strlen(value);
+        if (len >= 2 && (value[0] == '"' || value[0] == '\'') && value[len - 1] == value[0]) {
+            value[len - 1] = '\0';
+            value++;
+        }
+        *out = strdup(value);
+        found = 1;
+    }
+    fclose(fp);
+    if (found && !*out) {
+        snprintf(err, err_len, "out of memory");
+        return -1;
+    }
+    return found ? 1 : 0;
+}
+
+/* File-backed values are read once; the variable itself is checked on every call instead. */
+static void resolve_secret(secret_entry_t *entry) {
+    char file_var[80] = {0};
+    snprintf(file_var, sizeof(file_var), "%s_FILE", entry->name);
+    const char *file = getenv(file_var);
+    if (file && file[0] != '\0') {
+        entry->source = "file";
+        snprintf(entry->origin, sizeof(entry->origin), "%s", file);
+        if (read_secret_file(file, &entry->value, entry->error, sizeof(entry->error)) != 0) entry->value = NULL;
+        return;
+    }
+    const char *bundle = getenv("FRICU_SECRETS_FILE");
+    if (bundle && bundle[0] != '\0') {
+        int rc = read_dotenv_value(bundle, entry->name, &entry->value, entry->error, sizeof(entry->error));
+        if (rc != 0) {
+            entry->source = "secrets_file";
+            snprintf(entry->origin, sizeof(entry->origin), "%s", bundle);
+        }
+        if (rc < 0) entry->value = NULL;
+    }
+}
+
+static secret_entry_t *secret_lookup(const char *name) {
+    for (size_t i = 0; i < g_secret_count; i++) {
+        if (strcmp(g_secrets[i].name, name) == 0) return &g_secrets[i];
+    }
+    if (g_secret_count >= SECRETS_MAX || strlen(name) >= sizeof(g_secrets[0].name)) return NULL;
+    secret_entry_t *entry = &g_secrets[g_secret_count++];
+    memset(entry, 0, sizeof(*entry));
+    snprintf(entry->name, sizeof(entry->name), "%s", name);
+    resolve_secret(entry);
+    if (entry->error[0] != '\0') log_error("secret %s unavailable: %s", entry->name, entry->error);
+    if (entry->value) log_redact_value(entry->value);
+    return entry;
+}
+
+const char *config_secret(const char *name) {
+    const char *direct = getenv(name);
+    if (direct && direct[0] != '\0') {
+        log_redact_value(direct);
+        return direct;
+    }
+    pthread_mutex_lock(&g_secrets_mutex);
+    secret_entry_t *entry = secret_lookup(name);
+    const char *value = entry && entry->value && entry->value[0] != '\0' ? entry->value : NULL;
+    pthread_mutex_unlock(&g_secrets_mutex);
+    return value;
+}
+
+int config_secret_describe(const char *name, char *out, size_t out_len) {
+    const char *direct = getenv(name);
+    if (direct && direct[0] != '\0') {
+        snprintf(out, out_len, "%s=<redacted, %zu bytes, from env>", name, strlen(direct));
+        return 0;
+    }
+    pthread_mutex_lock(&g_secrets_mutex);
+    secret_entry_t *entry = secret_lookup(name);
+    int rc = 0;
+    if (!entry) {
+        snprintf(out, out_len, "%s=<unset>", name);
+    } else if (entry->error[0] != '\0') {
+        snprintf(out, out_len, "%s=<error: %s>", name, entry->error);
+        rc = -1;
+    } else if (!entry->value || entry->value[0] == '\0') {
+        snprintf(out, out_len, "%s=<unset>", name);
+    } else {
+        snprintf(out, out_len, "%s=<redacted, %zu bytes, from %s %s>", name, strlen(entry->value), entry->source, entry->origin);
+    }
+    pthread_mutex_unlock(&g_secrets_mutex);
+    return rc;
+}
+
+int config_is_secret(const char *name) {
+    for (size_t i = 0; i < sizeof(KNOWN_SECRETS) / sizeof(KNOWN_SECRETS[0]); i++) {
+        if (strcmp(name, KNOWN_SECRETS[i]) == 0) return 1;
+    }
+    return 0;
+}
+
+void config_secrets_load(void) {
+    for (size_t i = 0; i < sizeof(KNOWN_SECRETS) / sizeof(KNOWN_SECRETS[0]); i++) config_secret(KNOWN_SECRETS[i]);
+}
+
+int config_check(FILE *out) {
+    static const char *SETTINGS[] = {
+        "FRICU_SERVER_BIND",
+        "FRICU_DB_PATH",
+        "FRICU_SERVER_WORKERS",
+        "FRICU_CLUSTER_MODE",
+        "FRICU_REDIS_PREFIX",
+        "FRICU_SECRETS_FILE",
+        "FRICU_API_AUTH",
+        "FRICU_EVENT_SOURCING",
+        "FRICU_OIDC_ISSUER",
+        "FRICU_OIDC_AUDIENCE",
+        "FRICU_OIDC_JWKS_URL",
+        "FRICU_OIDC_ACCOUNT_CLAIM",
+        "FRICU_TLS_CERT",
+        "FRICU_TLS_KEY",
+        "FRICU_TLS_REDIRECT_BIND",
+        "FRICU_RESPONSE_MAX_ITEMS",
+        "FRICU_RESPONSE_MAX_BYTES",
+        "FRICU_MAX_BODY_BYTES",
+        "FRICU_READ_TIMEOUT_MS",
+        "FRICU_WRITE_TIMEOUT_MS",
+        "FRICU_CLOCK_SKEW_SECONDS",
+        "FRICU_ACTIVITY_MIN_DATE",
+        "FRICU_ACTIVITY_MAX_FUTURE_DAYS",
+        "FRICU_WS_MAX_CLIENTS",
+        "FRICU_SSE_MAX_CLIENTS",
+        "FRICU_RATE_LIMIT_IP",
+        "FRICU_RATE_LIMIT_IP_BURST",
+        "FRICU_RATE_LIMIT_TOKEN",
+        "FRICU_RATE_LIMIT_TOKEN_BURST",
+        "FRICU_RATE_LIMIT_TRUST_PROXY",
+        "FRICU_CORS_ORIGINS",
+        "FRICU_CORS_METHODS",
+        "FRICU_CORS_HEADERS",
+        "FRICU_CORS_CREDENTIALS",
+        "FRICU_EXPORT_SCRYPT_WORK_FACTOR",
+        "FRICU_BACKUP_DIR",
+        "FRICU_BACKUP_INTERVAL_SEC",
+        "FRICU_BACKUP_KEEP",
+        "FRICU_ATTACHMENT_DIR",
+        "FRICU_OTLP_ENDPOINT",
+        "FRICU_OTLP_SERVICE_NAME",
+        "FRICU_OTLP_SAMPLE_RATIO",
+        "FRICU_LOG_FORMAT",
+        "FRICU_ACCESS_LOG",
+        "FRICU_MDNS",
+        "FRICU_MDNS_NAME",
+        "FRICU_PUBLIC_URL",
+        "FRICU_READY_MAX_WRITE_QUEUE",
+        "FRICU_SHUTDOWN_TIMEOUT_SEC",
+    };
+    int errors = 0;
+    for (size_t i = 0; i < sizeof(SETTINGS) / sizeof(SETTINGS[0]); i++) {
+        const char *value = getenv(SETTINGS[i]);
+        fprintf(out, "%s=%s\n", SETTINGS[i], value && value[0] ? value : "<unset>");
+    }
+    const char *bind = getenv("FRICU_SERVER_BIND");
+    char host[128] = {0};
+    int port = 0;
+    if (bind && bind[0] && parse_bind_addr(bind, host, sizeof(host), &port) != 0) {
+        fprintf(out, "error: FRICU_SERVER_BIND must be host:port\n");
+        errors++;
+    }
+    const char *audience = getenv("FRICU_OIDC_AUDIENCE");
+    if (oidc_configured() && (!audience || audience[0] == '\0')) {
+        fprintf(out, "error: FRICU_OIDC_ISSUER needs FRICU_OIDC_AUDIENCE\n");
+        errors++;
+    }
+    const char *tls_cert = getenv("FRICU_TLS_CERT");
+    const char *tls_key = getenv("FRICU_TLS_KEY");
+    if ((tls_cert && tls_cert[0]) != (tls_key && tls_key[0])) {
+        fprintf(out, "error: FRICU_TLS_CERT and FRICU_TLS_KEY must be set together\n");
+        errors++;
+    }
+    for (size_t i = 0; i < sizeof(KNOWN_SECRETS) / sizeof(KNOWN_SECRETS[0]); i++) {
+        char line[512] = {0};
+        if (config_secret_describe(KNOWN_SECRETS[i], line, sizeof(line)) != 0) errors++;
+        fprintf(out, "%s\n", line);
+    }
+    if (config_secret("FRICU_EXPORT_PASSPHRASE") && !export_encryption_available()) {
+        fprintf(out, "error: FRICU_EXPORT_PASSPHRASE needs a server built with OpenSSL\n");
+        errors++;
+    }
+    const char *redis_url = config_secret("FRICU_REDIS_URL");
+    redis_config_t redis;
+    if (redis_url && redis_parse_url(redis_url, &redis) != 0) {
+        fprintf(out, "error: FRICU_REDIS_URL must be redis://[user:password@]host[:port][/db]\n");
+        errors++;
+    }
+    fprintf(out, "%s\n", errors == 0 ? "config ok" : "config invalid");
+    return errors == 0 ? 0 : -1;
+}
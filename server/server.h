@@ -3,6 +3,7 @@
 
 #include <stdbool.h>
 #include <stddef.h>
+#include <stdint.h>
 
 bool is_valid_key(const char *key);
 bool is_valid_storage_key(const char *key);
@@ -10,5 +11,26 @@ int parse_bind_addr(const char *bind_addr_str, char *host, size_t host_len, int
 int read_content_length(const char *req, const char *header_end);
 int socket_send_flags(void);
 int configure_socket_after_accept(int fd);
+int query_param(const char *query, const char *name, char *out, size_t out_len);
+int parse_iso_day(const char *s, int *out_day);
+void format_iso_day(int day, char *out, size_t out_len);
+int fill_random_bytes(void *out, size_t len);
+int generate_uuid_v4(char *out, size_t out_len);
+void base64_encode(const unsigned char *in, size_t len, char *out, size_t out_len);
+unsigned char *base64_decode(const char *in, size_t len, size_t *out_len);
+void content_version(const char *data, size_t len, char *out, size_t out_len);
+void sha256_hex(const void *data, size_t len, char *out, size_t out_len);
+
+typedef struct {
+    uint32_t state[8];
+    unsigned char block[64];
+    size_t block_len;
+    uint64_t total;
+} sha256_ctx_t;
+
+void sha256_init(sha256_ctx_t *ctx);
+void sha256_update(sha256_ctx_t *ctx, const void *data, size_t len);
+void sha256_final_hex(sha256_ctx_t *ctx, char *out, size_t out_len);
+void sha1_digest(const void *data, size_t len, unsigned char out[20]);
 
 #endif
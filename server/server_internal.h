@@ -3,9 +3,12 @@
 
 #include <sqlite3.h>
 #include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/types.h>
 
 #define REQ_BUF_SIZE (8 * 1024 * 1024)
-#define HEADER_BUF_SIZE 2048
+#define HEADER_BUF_SIZE 4096
 #define DEFAULT_WORKERS 64
 #define EVENT_MAX_EVENTS 1024
 #define CONN_INIT_BUF 8192
@@ -15,6 +18,7 @@ typedef struct {
     sqlite3_stmt *get_stmt;
     sqlite3_stmt *json_valid_stmt;
     char db_path[512];
+    long long generation;
 } worker_db_t;
 
 typedef struct {
@@ -22,8 +26,32 @@ typedef struct {
     size_t len;
     size_t cap;
     char *buf;
+    void *tls;
 } conn_t;
 
+typedef struct {
+    char *data;
+    size_t len;
+    size_t cap;
+    int failed;
+} strbuf_t;
+
+typedef struct {
+    char log_id[96];
+    char account_id[128];
+    int retry_attempt;
+} request_log_context_t;
+
+typedef struct {
+    const char *method;
+    const char *path;
+    const char *query;
+    const char *raw;
+    const char *header_end;
+    const char *body;
+    size_t body_len;
+} http_request_t;
+
 extern const char *DATA_KEYS[];
 extern const size_t DATA_KEYS_COUNT;
 
@@ -34,6 +62,63 @@ int configure_socket_after_accept(int fd);
 int init_db(const char *db_path);
 int worker_db_open(worker_db_t *db, const char *db_path);
 void worker_db_close(worker_db_t *db);
+/* Reconnects a worker whose connection predates the last db_pool_invalidate. */
+int worker_db_refresh(worker_db_t *db);
+void db_pool_invalidate(void);
+long long db_pool_generation(void);
+int db_checkpoint(const char *db_path);
+int db_read_snapshot_begin(sqlite3 *db, long long *seq);
+void db_read_snapshot_end(sqlite3 *db);
+long long response_count_items(sqlite3 *db, const char *doc, size_t len);
+int response_exceeds_limits(long long items, size_t bytes);
+int response_guard(int fd, long long items, size_t bytes, const char *hint, const request_log_context_t *ctx);
+long long request_body_limit(const char *path, char *key, size_t key_len);
+size_t request_buffer_max(void);
+int request_body_guard(int fd, const char *path, long long bytes, const request_log_context_t *ctx);
+int deadline_budget_ms(const char *method, const char *path);
+void deadline_attach(sqlite3 *db);
+void deadline_begin(const http_request_t *req);
+int deadline_expired(void);
+int deadline_budget(void);
+void deadline_end(sqlite3 *db, const http_request_t *req, const request_log_context_t *ctx);
+void skew_begin(const http_request_t *req);
+long long skew_count_future_dates(sqlite3 *db, const char *doc, size_t len);
+void skew_note_future_dates(long long count);
+int skew_warning_headers(char *out, size_t out_len);
+void skew_end(sqlite3 *db, const http_request_t *req, const request_log_context_t *ctx);
+int rate_limit_ip(int fd, const http_request_t *req, const request_log_context_t *ctx);
+/* The peer address, or the last X-Forwarded-For hop with FRICU_RATE_LIMIT_TRUST_PROXY=1. */
+void request_client_address(int fd, const http_request_t *req, char *out, size_t out_len);
+int rate_limit_client(int fd, const request_log_context_t *ctx);
+void rate_limit_append_metrics(strbuf_t *sb);
+void compression_begin(const http_request_t *req);
+void compression_end(void);
+int compression_decode_request(int fd, http_request_t *req, const request_log_context_t *ctx);
+int compression_encode_response(
+    int code, const char *content_type, const char *extra_headers, const char **body, size_t *body_len, char *headers, size_t headers_len);
+void deprecation_begin(void);
+void deprecation_declare(const char *endpoint, const char *sunset, const char *successor, const char *notice);
+int deprecation_headers(char *out, size_t out_len);
+void deprecation_end(const http_request_t *req, const request_log_context_t *ctx);
+void deprecation_append_usage(strbuf_t *sb);
+void cors_begin(const http_request_t *req);
+void cors_end(void);
+int cors_headers(char *out, size_t out_len);
+int cors_preflight(int fd, const http_request_t *req, const request_log_context_t *ctx);
+void quarantine_begin(void);
+char *activity_quarantine_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len);
+void quarantine_flush(sqlite3 *db, const char *account_id, int stored);
+int quarantine_headers(char *out, size_t out_len);
+int route_quarantine(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int tls_configure(void);
+int tls_enabled(void);
+void *tls_session_new(int fd);
+void tls_session_free(void *session);
+ssize_t tls_recv(void *session, char *buf, size_t len);
+void tls_set_current(int fd, void *session);
+ssize_t tls_send(int fd, const char *buf, size_t len);
+int tls_redirect_location(const char *request, int https_port, char *out, size_t out_len);
+int tls_redirect_start(const char *bind_addr, int https_port);
 
 typedef struct {
     int completed;
@@ -41,6 +126,7 @@ typedef struct {
     int sqlite_rc;
     int sqlite_ext;
     int retry_count;
+    double queue_ms;
     char backup_path[512];
 } write_dispatch_result_t;
 
@@ -51,6 +137,22 @@ typedef struct {
     char last_error_logid[96];
 } write_dispatch_diagnostics_t;
 
+typedef enum {
+    WRITE_BATCH_CREATED = 0,
+    WRITE_BATCH_REPLACED,
+    WRITE_BATCH_UNCHANGED,
+} write_batch_action_t;
+
+/* One document of a batch; action is set once the batch committed. */
+typedef struct {
+    const char *logical_key;
+    const char *storage_key;
+    const char *payload;
+    size_t payload_len;
+    const char *pending_path;
+    write_batch_action_t action;
+} write_batch_item_t;
+
 int write_dispatcher_acquire(const char *db_path);
 void write_dispatcher_release(void);
 int write_dispatch_submit(
@@ -63,11 +165,665 @@ int write_dispatch_submit(
     const char *log_id,
     int wait_timeout_ms,
     write_dispatch_result_t *out_result);
+/* Queues data_refresh_derived on the writer connection after the writes ahead of it; a queued one is reused. */
+int write_dispatch_refresh(const char *account_id, const char *logical_key);
+/* Waits until everything queued so far ran; 0 when it did, 1 on timeout. */
+int write_dispatch_drain(int wait_timeout_ms);
+/* Writes every item in one transaction on the writer thread, skipping unchanged ones; returns like write_dispatch_submit. */
+int write_dispatch_submit_batch(
+    write_batch_item_t *items,
+    size_t count,
+    const char *account_id,
+    const char *log_id,
+    int wait_timeout_ms,
+    write_dispatch_result_t *out_result);
+/* Returns the HTTP status of a write_dispatch_call job; runs on the writer connection. */
+typedef int (*write_call_fn)(sqlite3 *db, void *arg);
+/*
+ * Runs call(db, arg) in queue order; returns like write_dispatch_submit. On 0 arg is the caller's
+ * again (to read results from and free); otherwise the queue frees it with free_arg.
+ */
+int write_dispatch_call(
+    write_call_fn call,
+    void *arg,
+    void (*free_arg)(void *arg),
+    const char *account_id,
+    const char *log_id,
+    int wait_timeout_ms,
+    write_dispatch_result_t *out_result);
 void write_dispatch_diagnostics_snapshot(write_dispatch_diagnostics_t *out_diag);
 
+void strbuf_init(strbuf_t *sb);
+int strbuf_append(strbuf_t *sb, const char *s, size_t len);
+int strbuf_appendf(strbuf_t *sb, const char *fmt, ...) __attribute__((format(printf, 2, 3)));
+int strbuf_append_json_string(strbuf_t *sb, const char *s);
+const char *strbuf_cstr(const strbuf_t *sb);
+void strbuf_free(strbuf_t *sb);
+
 void send_response(int fd, int code, const char *status, const char *body);
+void send_response_with_log_context(int fd, int code, const char *status, const char *body, const request_log_context_t *ctx);
+void send_http_response(
+    int fd,
+    int code,
+    const char *status,
+    const char *content_type,
+    const char *extra_headers,
+    const char *body,
+    size_t body_len,
+    const request_log_context_t *ctx);
+void request_data_key(const char *path, char *out, size_t out_len);
+int http_request_header(const http_request_t *req, const char *name, char *out, size_t out_len);
+const char *http_status_text(int status);
+int http_parse_date(const char *value, long long *out);
+int store_account_data(
+    worker_db_t *db,
+    const char *key,
+    const char *payload,
+    size_t payload_len,
+    const request_log_context_t *ctx,
+    char *out_body,
+    size_t out_body_len);
+/* The checks a PUT makes before writing a document; 0, or the status with out_body set. */
+int data_validate_document(
+    worker_db_t *db,
+    const char *key,
+    const char *payload,
+    size_t payload_len,
+    const request_log_context_t *ctx,
+    char *out_body,
+    size_t out_body_len);
+/* Recomputes what is derived from a key (risk notifications, CP fit, records, VO2max); runs on the writer connection. */
+void data_refresh_derived(sqlite3 *db, const char *account_id, const char *key);
+/* Queues data_refresh_derived behind the write of a key that has derived tables. */
+void data_queue_refresh(const char *account_id, const char *key);
+int build_storage_key(const char *account_id, const char *logical_key, char *out_storage_key, size_t out_storage_key_len);
+/* Journals a write under pending_writes/ before it is queued, so a crash replays it; 0 with out_path set. */
+int create_pending_write(
+    const char *key,
+    const char *payload,
+    size_t payload_len,
+    const request_log_context_t *ctx,
+    char *out_path,
+    size_t out_path_len);
+
+typedef struct {
+    int day;
+    double tss;
+} daily_load_t;
+
+typedef struct {
+    double ctl;
+    double atl;
+    double tsb;
+} pmc_point_t;
+
+#define RISK_FLAG_RAMP_RATE 0x1
+#define RISK_FLAG_ACWR 0x2
+#define RISK_FLAG_MONOTONY 0x4
+
+typedef struct {
+    int week_start_day;
+    double tss;
+    double ctl_ramp;
+    double acwr;
+    double monotony;
+    double strain;
+    int flags;
+} training_risk_week_t;
+
+/* The account's training week from its profile: weekStartDay as 0 = Monday .. 6 = Sunday, and
+ * weekRolloverHour, the local hour at which one training day ends and the next begins. */
+typedef struct {
+    int start_weekday;
+    int rollover_hour;
+} training_week_t;
+
+/* The training day of an activity date: its calendar day, or the day before when the local time
+ * is earlier than hour (an SQL expression). Date-only values keep their day. */
+#define TRAINING_DAY_SQL(date, hour)                                                                      \
+    "(CASE WHEN " hour " > 0 AND length(" date ") >= 16"                                                 \
+    " THEN COALESCE(date(substr(" date ", 1, 16), '-' || " hour " || ' hours'), substr(" date ", 1, 10))" \
+    " ELSE substr(" date ", 1, 10) END)"
+
+int today_day(void);
+int week_start_for_day(int day);
+int week_start_on(int day, int start_weekday);
+int weekday_from_name(const char *name);
+void load_training_week(sqlite3 *db, const char *account_id, training_week_t *out);
+int training_today(const training_week_t *week);
+int load_daily_tss(sqlite3 *db, const char *account_id, daily_load_t **out, size_t *out_count);
+int load_daily_model_load(sqlite3 *db, const char *account_id, const char *model, daily_load_t **out, size_t *out_count);
+double *expand_daily_tss(const daily_load_t *loads, size_t count, int first_day, int last_day);
+void compute_pmc_series(const double *daily_tss, size_t days, const pmc_point_t *seed, pmc_point_t *out);
+int compute_account_pmc_today(sqlite3 *db, const char *account_id, pmc_point_t *out_latest);
+int load_planned_workout_tss(sqlite3 *db, const char *account_id, int from_day, int to_day, double *daily, size_t days);
+size_t compute_training_risk(const double *daily_tss, size_t days, int first_day, int start_weekday, training_risk_week_t *out, size_t max_weeks);
+int analytics_refresh_risk_notifications(sqlite3 *db, const char *account_id);
+int handle_get_analytics_risk(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int parse_analytics_period(const char *text, int *out_from_day, int *out_to_day);
+int handle_get_analytics_compare(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_coach_compare(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_post_analytics_simulate(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+typedef struct {
+    double min_wbal;
+    int time_below_zero_sec;
+} wbal_summary_t;
+
+typedef struct {
+    double duration_sec;
+    double watts;
+    int altitude_corrected;
+} mmp_point_t;
+
+typedef struct {
+    int model;
+    int points;
+    double cp;
+    double w_prime;
+    double k;
+    double cp_se;
+    double w_prime_se;
+    double cp_ci_low;
+    double cp_ci_high;
+    double w_prime_ci_low;
+    double w_prime_ci_high;
+    int altitude_points;
+} cp_fit_t;
+
+typedef struct {
+    double hrr60_bpm;
+    int hrr_efforts;
+    double cardiac_drift_pct;
+    int has_drift;
+    int drift_uses_power;
+} heart_metrics_t;
+
+void compute_wbal_series(const double *power, size_t count, double cp, double w_prime, double *out_wbal, wbal_summary_t *summary);
+double load_profile_ftp(sqlite3 *db, const char *account_id);
+
+typedef struct {
+    double ftp_watts;
+    double threshold_pace_sec_per_km;
+    double css_sec_per_100m;
+    double threshold_heart_rate;
+    double resting_heart_rate;
+    double max_heart_rate;
+    char default_gear[129];
+    char tss_model[16];
+} sport_settings_t;
+
+typedef struct {
+    double duration_sec;
+    double distance_km;
+    double normalized_power;
+    double avg_heart_rate;
+    double rpe;
+    double source_tss;
+} sport_load_input_t;
+
+typedef struct {
+    double tss;
+    char model[16];
+    char loads_json[160];
+} sport_load_t;
+
+int profile_validate_sports(sqlite3 *db, const char *payload, size_t payload_len, char *err, size_t err_len);
+int load_sport_settings(sqlite3 *db, const char *account_id, const char *sport, sport_settings_t *out);
+int sport_load_model_known(const char *model);
+const char *sport_canonical_name(const char *name);
+void sport_compute_loads(const sport_settings_t *settings, const sport_load_input_t *in, sport_load_t *out);
+void sport_append_loads(strbuf_t *sb, const sport_load_t *load);
+double compute_normalized_power(const double *power, size_t count);
+int load_profile_power_model(sqlite3 *db, const char *account_id, double *out_cp, double *out_w_prime);
+int activity_metric_upsert(sqlite3 *db, const char *account_id, const char *activity_id, const char *metric, double value);
+int physiology_refresh_activity_metrics(sqlite3 *db, const char *account_id);
+int fit_cp_model(const mmp_point_t *points, size_t count, int model, cp_fit_t *out);
+size_t load_mean_max_efforts(sqlite3 *db, const char *account_id, int from_day, mmp_point_t *out, size_t max_points);
+int load_latest_cp_fit(sqlite3 *db, const char *account_id, cp_fit_t *out);
+int physiology_run_cp_fit(sqlite3 *db, const char *account_id, int weeks, int model, cp_fit_t *out_fit, int *out_stored);
+int physiology_refresh_cp_fit(sqlite3 *db, const char *account_id);
+double estimate_vo2max(const char *sport, double duration_sec, double distance_km, double power_watts, double avg_hr, double max_hr, double weight_kg);
+int physiology_refresh_vo2max(sqlite3 *db, const char *account_id);
+int handle_get_analytics_profile(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+void compute_heart_rate_metrics(const double *hr, size_t hr_count, const double *power, size_t power_count, heart_metrics_t *out);
+int physiology_refresh_heart_metrics(sqlite3 *db, const char *account_id);
+int physiology_refresh_power_records(sqlite3 *db, const char *account_id);
+int handle_get_analytics_heart(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_analytics_cp(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_post_analytics_cp_fit(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_activity_analytics(int fd, worker_db_t *db, const http_request_t *req, const char *subpath, const request_log_context_t *ctx);
+
+int generate_device_token(char *out, size_t out_len);
+int device_token_account(sqlite3 *db, const char *token, char *out_account_id, size_t out_len);
+int device_token_issue(sqlite3 *db, const char *account_id, const char *name, char *token, size_t token_len);
+int handle_get_devices(int fd, worker_db_t *db, const request_log_context_t *ctx);
+int handle_post_devices(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_delete_device(int fd, worker_db_t *db, const char *token, const request_log_context_t *ctx);
+int handle_get_today_workout(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx);
+int handle_get_workout_export(int fd, worker_db_t *db, const http_request_t *req, const char *workout_id, const request_log_context_t *ctx);
+int handle_get_workout_compliance(int fd, worker_db_t *db, const char *workout_id, const request_log_context_t *ctx);
+int handle_get_workout_timer(int fd, worker_db_t *db, const http_request_t *req, const char *workout_id, const request_log_context_t *ctx);
+
+int admin_authorize(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int api_auth_enforce(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx);
+const char *api_auth_client_id(void);
+int api_account_id_valid(const char *id);
+int api_token_issue(sqlite3 *db, const char *account_id, const char *name, char *token, size_t token_len, char *token_id, size_t token_id_len);
+int oidc_configured(void);
+int oidc_token_shaped(const char *token);
+void oidc_reset_cache(void);
+int oidc_verify_token(sqlite3 *db, const char *token, char *out_account, size_t out_len, char *err, size_t err_len);
+int route_admin_tokens(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int event_sourcing_enabled(void);
+int event_log_configure(sqlite3 *db);
+int route_admin_events(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_admin_indexes(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_admin_captures(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+void capture_begin(const http_request_t *req, const request_log_context_t *ctx);
+/* Called by handlers whose success response hands out a credential; the capture keeps only its size. */
+void capture_omit_response(void);
+void capture_record_response(int code, const char *body, size_t body_len);
+void capture_end(void);
+
+void slowlog_configure(double slow_request_ms, double slow_query_ms);
+void slowlog_attach(sqlite3 *db);
+#define SERVER_TIMING_DEBUG_HEADER "X-Fricu-Debug-Timing"
+
+void slowlog_note_wakeup(void);
+void slowlog_request_begin(const http_request_t *req, const request_log_context_t *ctx);
+void slowlog_note_response(int code, size_t body_len);
+void slowlog_note_write(double ms, double queue_ms);
+void slowlog_note_checkout(double ms);
+int slowlog_server_timing_header(char *out, size_t out_len);
+double slowlog_now_ms(void);
+void slowlog_request_end(const http_request_t *req, const request_log_context_t *ctx);
+void slowlog_endpoint_label(const char *method, const char *path, char *out, size_t out_len);
+
+void otel_request_begin(const http_request_t *req);
+long long otel_span_start(void);
+void otel_note_db(const char *operation, const char *key, size_t row_bytes, long long started_ns, double queue_ms);
+void otel_note_response(int code, size_t body_len);
+void otel_request_end(const http_request_t *req, const request_log_context_t *ctx);
+int otel_flush(void);
+
+void accesslog_begin(void);
+void accesslog_note_response(int code, size_t body_len);
+void accesslog_end(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_admin_stats(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_admin_metrics(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_admin_pprof_cpu(int fd, const http_request_t *req, const request_log_context_t *ctx);
+
+void profiling_enable(size_t worker_count);
+void profiling_disable(void);
+void profiling_note_connection(long long connections_delta, long long buffer_bytes_delta);
+void profiling_note_request(int delta);
+int handle_get_debug_heap(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_debug_runtime(int fd, const http_request_t *req, const request_log_context_t *ctx);
+
+#define JSON_STREAM_MAX_DEPTH 512
+#define JSON_STREAM_MIN_BYTES (64 * 1024)
+
+typedef struct {
+    int valid;
+    char root;
+    int max_depth;
+    size_t top_level_items;
+    size_t error_offset;
+    const char *error;
+} json_stream_result_t;
+
+int json_stream_validate(const char *data, size_t len, json_stream_result_t *out);
+
+#define DELTA_CONTENT_TYPE "application/x-fricu-delta"
+
+int delta_is_request(const http_request_t *req);
+int handle_put_data_delta(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx);
+
+void cluster_configure(const char *instance_id, const char *advertise_url, int ttl_sec);
+int cluster_start(const char *db_path);
+int cluster_renew(sqlite3 *db);
+int cluster_is_writer(void);
+void cluster_append_status(strbuf_t *sb);
+int cluster_reject_write(int fd, const request_log_context_t *ctx);
+const char *server_instance_id(void);
+
+typedef struct {
+    char account_id[128];
+    char key[64];
+    char version[32];
+    long long updated_at;
+    char origin[128];
+    int remote;
+} change_event_t;
+
+typedef void (*change_listener_fn)(const change_event_t *event, void *user);
+
+int change_events_subscribe(change_listener_fn fn, void *user);
+void change_events_unsubscribe(int id);
+void change_events_dispatch(const change_event_t *event);
+void change_events_emit_local(const char *account_id, const char *key, const char *payload, size_t payload_len);
+void ws_accept_key(const char *key, char *out, size_t out_len);
+int handle_get_ws(int fd, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_events_stream(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+typedef struct {
+    char host[256];
+    int port;
+    int db;
+    char username[128];
+    char password[256];
+} redis_config_t;
+
+int redis_parse_url(const char *url, redis_config_t *out);
+int redis_encode_command(strbuf_t *sb, int argc, const char **argv);
+int redis_handle_message(const char *payload, const char *self_origin);
+void redis_publish_change(const change_event_t *event);
+void redis_append_status(strbuf_t *sb);
+int redis_start(void);
+
+int attachment_make_thumbnail(
+    const unsigned char *data,
+    size_t len,
+    const char *content_type,
+    unsigned char **out,
+    size_t *out_len,
+    int *width,
+    int *height);
+#ifdef FRICU_HAVE_IMAGE_CODECS
+int image_encode_jpeg(const unsigned char *rgb, int width, int height, int quality, unsigned char **out, size_t *out_len);
+#endif
+int route_attachments(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+/* Attachment bytes live in FRICU_ATTACHMENT_DIR (default <db>-attachments), one file per SHA-256. */
+int attachment_dir(const char *db_path, char *out, size_t out_len);
+int attachment_blob_write(const char *dir, const void *data, size_t len, char *out_hash, size_t out_hash_len);
+
+int route_journal(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_search(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_calendar(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_season_report(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_export(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+/* Whole-account archives: GET /v1/export writes them and POST /v1/import reads them back. */
+#define ARCHIVE_FORMAT "fricu-archive-v1"
+/* In-memory zip archives: zip_writer_add each entry, then zip_writer_finish leaves the archive in out. */
+typedef struct {
+    strbuf_t out;
+    strbuf_t central;
+    int entries;
+    int failed;
+} zip_writer_t;
+uint32_t zip_crc32(const void *data, size_t len);
+void zip_writer_init(zip_writer_t *zw);
+int zip_writer_add(zip_writer_t *zw, const char *name, const char *data, size_t len, long long mtime);
+int zip_writer_finish(zip_writer_t *zw);
+void zip_writer_free(zip_writer_t *zw);
+/* Reading: zip_reader_find unpacks one entry into a malloc'd, NUL-terminated buffer; 1 when it is absent. */
+typedef struct {
+    const unsigned char *data;
+    size_t len;
+    size_t central;
+    size_t central_len;
+    int entries;
+} zip_reader_t;
+int zip_reader_open(zip_reader_t *zr, const char *data, size_t len);
+int zip_reader_find(const zip_reader_t *zr, const char *name, size_t max_len, char **out, size_t *out_len, char *err, size_t err_len);
+
+int coach_request_identity(sqlite3 *db, const http_request_t *req, const char *account_id, char *out_name, size_t out_len);
+int coach_grant_allows(sqlite3 *db, const http_request_t *req, const char *athlete_id);
+int key_is_locked(sqlite3 *db, const char *account_id, const char *key);
+void locks_append_keys(sqlite3 *db, const char *account_id, strbuf_t *sb);
+int locks_enforce_key(int fd, worker_db_t *db, const http_request_t *req, const char *key, const request_log_context_t *ctx);
+int locks_enforce(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_users(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx, http_request_t *scoped, char *scoped_path, size_t scoped_path_len);
+int route_coach(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+int snapshots_take(sqlite3 *db, const char *day);
+int snapshots_prune(sqlite3 *db, const char *cutoff_day);
+int snapshots_start(const char *db_path);
+typedef struct {
+    char date[32];
+    char sport[16];
+    double duration_sec;
+    double distance_km;
+    double normalized_power;
+    double tss;
+    int avg_hr;
+    double *power;
+    size_t power_count;
+    double *heart_rate;
+    size_t heart_rate_count;
+} activity_file_summary_t;
+
+typedef struct {
+    const char *name;
+    const unsigned char *data;
+    size_t len;
+} import_file_t;
+
+#define UPLOAD_MAX_FILES 16
+
+/* Files read out of a multipart body (a mail's attachments or a form upload); upload_files_free releases the data. */
+typedef struct {
+    import_file_t files[UPLOAD_MAX_FILES];
+    char names[UPLOAD_MAX_FILES][128];
+    unsigned char *owned[UPLOAD_MAX_FILES];
+    size_t count;
+} upload_files_t;
+
+const char *activity_file_type(const char *file_name, const unsigned char *data, size_t len);
+int activity_file_summarize(const char *type, const unsigned char *data, size_t len, activity_file_summary_t *out);
+void activity_file_summary_free(activity_file_summary_t *summary);
+int import_activity_files(
+    int fd,
+    worker_db_t *db,
+    const char *source,
+    const import_file_t *files,
+    size_t count,
+    const char *fallback_date,
+    int keep_files,
+    const request_log_context_t *ctx);
+int multipart_read_files(const char *content_type, const char *body, size_t len, upload_files_t *out);
+void upload_files_free(upload_files_t *files);
+/* Portable plan templates: GET /v1/export/plan-template writes them, POST /v1/import/plan-template schedules them. */
+#define PLAN_TEMPLATE_FORMAT "fricu-plan-template-v1"
+#define PLAN_TEMPLATE_MAX_WEEKS 52
+int route_import(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_import_runs(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_mail_inbox(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_post_inbound_email(int fd, worker_db_t *db, const http_request_t *req, const char *token, const request_log_context_t *ctx);
+int outbound_request(
+    const char *method,
+    const char *url_text,
+    const char *extra_headers,
+    const unsigned char *body,
+    size_t body_len,
+    char *err,
+    size_t err_len);
+int outbound_get(const char *url_text, strbuf_t *response, char *err, size_t err_len);
+int connectors_enqueue(sqlite3 *db, const char *account_id, const char *connector_id);
+int connectors_run_due(sqlite3 *db, int limit);
+int connectors_start(const char *db_path);
+int route_connectors(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+void bots_answer(sqlite3 *db, const char *account_id, const char *command, strbuf_t *sb);
+int bots_run_pushes(sqlite3 *db);
+int bots_start(const char *db_path);
+int route_bot_webhooks(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_bots(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_assist_briefing(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_admin_validate(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_data_diff(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_data_as_of(int fd, worker_db_t *db, const char *key, const char *as_of, const request_log_context_t *ctx);
+int route_activity_batch(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int activity_filter_requested(const http_request_t *req);
+int handle_get_activities_filtered(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_activity_feedback(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_analytics_readiness(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+typedef struct {
+    int tracked;
+    int last_start_day;
+    int cycle_length;
+    int period_length;
+} cycle_model_t;
+
+int route_cycle(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int cycle_load_model(sqlite3 *db, const char *account_id, int as_of_day, cycle_model_t *out);
+const char *cycle_phase_on(const cycle_model_t *model, int day, int *out_cycle_day);
+void cycle_append_json(strbuf_t *sb, const cycle_model_t *model, int day);
+
+#define TRAVEL_MAX_STAYS 256
+#define TRAVEL_CURVE_MAX_POINTS 8
+
+typedef struct {
+    int start_day;
+    int end_day;
+    int altitude_m;
+    double power_factor;
+} travel_stay_t;
+
+typedef struct {
+    travel_stay_t stays[TRAVEL_MAX_STAYS];
+    size_t count;
+    double curve_altitude_m[TRAVEL_CURVE_MAX_POINTS];
+    double curve_factor[TRAVEL_CURVE_MAX_POINTS];
+    size_t curve_points;
+} travel_log_t;
+
+int route_travel(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_analytics_altitude(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int travel_load_log(sqlite3 *db, const char *account_id, travel_log_t *out);
+double travel_power_factor(const travel_log_t *log, int day, int *out_altitude_m);
+
+int route_gear_calibrations(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+void calibration_append_annotations(strbuf_t *sb, sqlite3 *db, const char *account_id, int from_day, int to_day);
+const char *config_secret(const char *name);
+int config_secret_describe(const char *name, char *out, size_t out_len);
+void config_secrets_load(void);
+int config_is_secret(const char *name);
+int config_check(FILE *out);
+int service_render(const char *kind, const char *argv0, strbuf_t *out);
+int service_install(const char *argv0, FILE *out);
+/* DNS-SD advertisement of _fricu._tcp on the LAN (FRICU_MDNS=1). */
+#define MDNS_MAX_ADDRS 8
+typedef struct {
+    char instance[64];
+    char host[64];
+    int port;
+    int tls;
+    uint32_t addrs[MDNS_MAX_ADDRS];
+    size_t addr_count;
+} mdns_service_t;
+typedef struct mdns_responder mdns_responder_t;
+int mdns_service_init(mdns_service_t *svc, const char *bind_host, int port);
+size_t mdns_announcement(const mdns_service_t *svc, int goodbye, unsigned char *out, size_t out_len);
+size_t mdns_answer(const unsigned char *query, size_t len, int legacy, const mdns_service_t *svc, unsigned char *out, size_t out_len);
+mdns_responder_t *mdns_start(const char *bind_host, int port);
+void mdns_stop(mdns_responder_t *responder);
+/* QR Code encoder (byte mode, level M, versions 1-10) for pairing QR codes. */
+#define QR_MAX_VERSION 10
+#define QR_MAX_MODULES (57 * 57)
+#define QR_MAX_DATA_CODEWORDS 216
+#define QR_MAX_RAW_CODEWORDS 346
+typedef struct {
+    int version;
+    int size;
+    int mask;
+    unsigned char modules[QR_MAX_MODULES];
+} qr_code_t;
+int qr_encode(const char *text, size_t len, qr_code_t *qr);
+void qr_rs_remainder(const unsigned char *data, size_t len, int degree, unsigned char *out);
+void qr_render_svg(const qr_code_t *qr, strbuf_t *out);
+int handle_get_health_live(int fd, const request_log_context_t *ctx);
+int handle_get_health_ready(int fd, worker_db_t *db, const request_log_context_t *ctx);
+int route_admin_pairing(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_post_pair(int fd, worker_db_t *db, const http_request_t *req, request_log_context_t *ctx);
+/* age (scrypt) encryption of exports and connector uploads with FRICU_EXPORT_PASSPHRASE. */
+int export_encryption_available(void);
+const char *export_passphrase(void);
+int export_is_encrypted(const char *data, size_t len);
+int age_encrypt(const char *passphrase, int work_factor, const unsigned char *in, size_t len, unsigned char **out, size_t *out_len);
+int age_decrypt(const char *passphrase, const char *in, size_t len, char **out, size_t *out_len, char *err, size_t err_len);
+int export_encrypt(const unsigned char *in, size_t len, unsigned char **out, size_t *out_len);
+int export_decrypt(const char *in, size_t len, char **out, size_t *out_len, char *err, size_t err_len);
+int export_decrypt_file(const char *path, FILE *out);
+/* Page-level full, incremental and differential backups in FRICU_BACKUP_DIR. */
+int backup_dir(const char *db_path, char *out, size_t out_len);
+int backup_restore(const char *dir, const char *id, const char *output, char *err, size_t err_len);
+int route_admin_backups(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+/* VACUUM INTO snapshots in the same directory, by hand or every FRICU_BACKUP_INTERVAL_SEC. */
+int handle_post_admin_backup(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int backup_prune_snapshots(const char *dir, int keep);
+int backup_schedule_start(const char *db_path);
+/* Verifies a SQLite file and copies it over the live database, saving the old content as a snapshot. */
+int backup_swap_in(const char *db_path, const char *source, char *previous, size_t previous_len, char *err, size_t err_len);
+int handle_post_admin_restore(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+/* Activity fields dropped under data minimization: raw heart-rate samples and the original device file. */
+#define DATA_MINIMIZED_PATHS_SQL "'$.heartRateSamples', '$.sourceFileBase64'"
+#define DATA_MINIMIZED_ITEM_SQL(value, type)                                                                     \
+    type " = 'object' AND (json_type(" value ", '$.heartRateSamples') IS NOT NULL OR json_type(" value ", '$.sourceFileBase64') IS NOT NULL)"
+int data_minimization_forced(void);
+int data_minimization_enabled(sqlite3 *db, const char *account_id);
+char *data_minimize_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len);
+char *data_prepare_payload(sqlite3 *db, const char *account_id, const char *key, const char *payload, size_t payload_len);
+int data_schema_validate(sqlite3 *db, const char *key, const char *payload, size_t payload_len, char *out_body, size_t out_len);
+int handle_get_data_schema(int fd, const char *key, const request_log_context_t *ctx);
+
+#define API_V1_DATA_SUNSET "Wed, 31 Mar 2027 00:00:00 GMT"
+
+int api_key_is_collection(const char *key);
+int is_valid_item_id(const char *id);
+void api_v1_declare_deprecation(const char *key);
+int route_v2(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int route_v1_items(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int api_is_v1_items_path(const char *path);
+
+#define SYNC_PROTOCOL_VERSION 1
+#define SYNC_PROTOCOL_MIN_VERSION 1
+#define SYNC_PROTOCOL_HEADER "X-Fricu-Sync-Protocol"
+#define SYNC_VERSION_HEADER "X-Fricu-Version"
+#define SYNC_BASE_VERSION_HEADER "X-Fricu-Base-Version"
+#define SYNC_MISSING_VERSION "0"
+/* The document version doubles as a strong ETag, so If-Match works with stock HTTP clients. */
+#define SYNC_VERSION_HEADERS_FMT SYNC_VERSION_HEADER ": %s\r\nETag: \"%s\"\r\n"
+
+void sync_document_lock(void);
+void sync_document_unlock(void);
+int sync_negotiate_protocol(const http_request_t *req, int fd, const request_log_context_t *ctx);
+int sync_current_version(sqlite3 *db, const char *storage_key, char *out_version, size_t out_len, long long *out_updated_at);
+int sync_check_base_version(worker_db_t *db, const http_request_t *req, const char *key, int fd, const request_log_context_t *ctx);
+int handle_get_sync_manifest(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_get_sync_changes(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+int handle_post_sync_reconcile(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+int handle_post_live_ingest(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
+
+typedef struct {
+    const char *name;
+    const char *value;
+} i18n_arg_t;
+
+const char *i18n_negotiate(sqlite3 *db, const http_request_t *req, const char *account_id);
+const char *i18n_profile_language(sqlite3 *db, const char *account_id);
+const char *i18n_text(const char *lang, const char *key);
+int i18n_format(const char *lang, const char *key, const i18n_arg_t *args, size_t arg_count, strbuf_t *out);
+int i18n_format_keys(const char *lang, const char *keys, const i18n_arg_t *args, size_t arg_count, strbuf_t *out);
+int i18n_format_stored(sqlite3 *db, const char *lang, const char *keys, const char *args_json, strbuf_t *out);
+int i18n_args_json(const i18n_arg_t *args, size_t arg_count, strbuf_t *out);
+
+int notification_post(sqlite3 *db, const char *account_id, const char *kind, const char *dedupe_key, const char *message);
+int notification_post_message(
+    sqlite3 *db, const char *account_id, const char *kind, const char *dedupe_key, const char *message_keys, const i18n_arg_t *args, size_t arg_count);
+void notification_render(sqlite3 *db, const char *lang, const char *message, const char *message_keys, const char *message_args, strbuf_t *out);
+int handle_get_notifications(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx);
 int try_process_client(int fd, worker_db_t *db, conn_t *conn);
 
-int run_worker_loop(int listen_fd, const char *db_path, size_t max_fds);
+int run_worker_loop(int listen_fd, int stop_fd, const char *db_path, size_t max_fds);
+/* Runs the server on bind_addr ("host:port") until SIGTERM/SIGINT has drained it or its workers exit; non-zero on a startup failure. */
+int engine_run(const char *bind_addr, const char *db_path, size_t worker_count);
+/* 1 on a worker of a server from the start of its stop until its workers are joined. */
+int engine_draining(void);
+struct fricu_server;
+/* The same for a given server, from any thread. */
+int engine_server_draining(const struct fricu_server *server);
 
 #endif
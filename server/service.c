@@ -0,0 +1,277 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+#if defined(__APPLE__)
+#include <mach-o/dyld.h>
+#endif
+
+/*
+ * Running as a managed background service without Docker. `--install-service` writes a unit for
+ * this platform's service manager: a systemd unit on Linux (/etc/systemd/system when run as root,
+ * ~/.config/systemd/user otherwise), a launchd property list on macOS (/Library/LaunchDaemons or
+ * ~/Library/LaunchAgents). The unit runs this binary from the current directory with the FRICU_*
+ * variables of the installing shell, restarts it when it fails and raises the open-file limit;
+ * secrets are never copied into it (point their _FILE variables or FRICU_SECRETS_FILE at a
+ * protected file instead). `--print-service systemd|launchd` writes the same unit to stdout for
+ * packaging. Under systemd the logger sees JOURNAL_STREAM and tags each line with its syslog
+ * priority so journalctl -p works; under launchd the log goes to ~/Library/Logs/fricu-server.log
+ * (or /Library/Logs for a daemon). There is no Windows service: the server does not build on
+ * Windows (the event loop is epoll/kqueue), so WSL with systemd is the way to run it there.
+ */
+
+#define SERVICE_NAME "fricu-server"
+#define SERVICE_LAUNCHD_LABEL "com.fricu.server"
+
+extern char **environ;
+
+typedef struct {
+    int launchd;
+    int system_wide;
+    char binary[PATH_MAX];
+    char workdir[PATH_MAX];
+    char log_path[PATH_MAX];
+} service_spec_t;
+
+static int resolve_binary(const char *argv0, char *out, size_t out_len) {
+    char raw[PATH_MAX] = {0};
+#if defined(__linux__)
+    ssize_t n = readlink("/proc/self/exe", raw, sizeof(raw) - 1);
+    if (n > 0) {
+        raw[n] = '\0';
+        snprintf(out, out_len, "%s", raw);
+        return 0;
+    }
+#elif defined(__APPLE__)
+    uint32_t size = sizeof(raw);
+    if (_NSGetExecutablePath(raw, &size) == 0 && realpath(raw, out)) return 0;
+#endif
+    if (!argv0 || !realpath(argv0, raw)) return -1;
+    snprintf(out, out_len, "%s", raw);
+    return 0;
+}
+
+/* Settings every service instance should keep: FRICU_* minus the secrets themselves. */
+static int copied_variable(const char *entry) {
+    const char *eq = strchr(entry, '=');
+    if (strncmp(entry, "FRICU_", 6) != 0 || !eq) return 0;
+    char name[128] = {0};
+    snprintf(name, sizeof(name), "%.*s", (int)(eq - entry), entry);
+    return !config_is_secret(name);
+}
+
+/* systemd quoting for Environment="...": backslash and quote escaped, % doubled as a specifier. */
+static void append_systemd_value(strbuf_t *sb, const char *s) {
+    for (const char *p = s; *p; p++) {
+        if (*p == '"' || *p == '\\') {
+            char escaped[2] = {'\\', *p};
+            strbuf_append(sb, escaped, 2);
+        } else if (*p == '%') {
+            strbuf_append(sb, "%%", 2);
+        } else if (*p != '\n') {
+            strbuf_append(sb, p, 1);
+        }
+    }
+}
+
+static void append_xml_text(strbuf_t *sb, const char *s, size_t len) {
+    for (size_t i = 0; i < len; i++) {
+        if (s[i] == '&') {
+            strbuf_append(sb, "&amp;", 5);
+        } else if (s[i] == '<') {
+            strbuf_append(sb, "&lt;", 4);
+        } else if (s[i] == '>') {
+            strbuf_append(sb, "&gt;", 4);
+        } else {
+            strbuf_append(sb, s + i, 1);
+        }
+    }
+}
+
+static void render_systemd(const service_spec_t *spec, strbuf_t *sb) {
+    strbuf_appendf(
+        sb,
+        "[Unit]\n"
+        "Description=Fricu training data server\n"
+        "After=network-online.target\n"
+        "Wants=network-online.target\n"
+        "\n"
+        "[Service]\n"
+        "Type=simple\n"
+        "ExecStart=\"");
+    append_systemd_value(sb, spec->binary);
+    strbuf_append(sb, "\"\nWorkingDirectory=\"", 20);
+    append_systemd_value(sb, spec->workdir);
+    strbuf_append(sb, "\"\n", 2);
+    for (char **env = environ; *env; env++) {
+        if (!copied_variable(*env)) continue;
+        strbuf_append(sb, "Environment=\"", 13);
+        append_systemd_value(sb, *env);
+        strbuf_append(sb, "\"\n", 2);
+    }
+    strbuf_appendf(
+        sb,
+        "Restart=on-failure\n"
+        "RestartSec=2\n"
+        "TimeoutStopSec=30\n"
+        "LimitNOFILE=65535\n"
+        "%s"
+        "\n"
+        "[Install]\n"
+        "WantedBy=%s\n",
+        spec->system_wide ? "NoNewPrivileges=true\n" : "",
+        spec->system_wide ? "multi-user.target" : "default.target");
+}
+
+static void append_plist_string(strbuf_t *sb, const char *key, const char *value) {
+    strbuf_appendf(sb, "    <key>%s</key>\n    <string>", key);
+    append_xml_text(sb, value, strlen(value));
+    strbuf_append(sb, "</string>\n", 10);
+}
+
+static void render_launchd(const service_spec_t *spec, strbuf_t *sb) {
+    strbuf_appendf(
+        sb,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n"
+        "<plist version=\"1.0\">\n"
+        "<dict>\n");
+    append_plist_string(sb, "Label", SERVICE_LAUNCHD_LABEL);
+    strbuf_appendf(sb, "    <key>ProgramArguments</key>\n    <array>\n        <string>");
+    append_xml_text(sb, spec->binary, strlen(spec->binary));
+    strbuf_appendf(sb, "</string>\n    </array>\n");
+    append_plist_string(sb, "WorkingDirectory", spec->workdir);
+    strbuf_appendf(sb, "    <key>EnvironmentVariables</key>\n    <dict>\n");
+    for (char **env = environ; *env; env++) {
+        if (!copied_variable(*env)) continue;
+        const char *eq = strchr(*env, '=');
+        strbuf_append(sb, "        <key>", 13);
+        append_xml_text(sb, *env, (size_t)(eq - *env));
+        strbuf_append(sb, "</key>\n        <string>", 23);
+        append_xml_text(sb, eq + 1, strlen(eq + 1));
+        strbuf_append(sb, "</string>\n", 10);
+    }
+    strbuf_appendf(
+        sb,
+        "    </dict>\n"
+        "    <key>RunAtLoad</key>\n    <true/>\n"
+        "    <key>KeepAlive</key>\n    <dict>\n        <key>SuccessfulExit</key>\n        <false/>\n    </dict>\n"
+        "    <key>ExitTimeOut</key>\n    <integer>30</integer>\n"
+        "    <key>SoftResourceLimits</key>\n    <dict>\n        <key>NumberOfFiles</key>\n        <integer>65535</integer>\n    </dict>\n");
+    append_plist_string(sb, "StandardOutPath", spec->log_path);
+    append_plist_string(sb, "StandardErrorPath", spec->log_path);
+    strbuf_appendf(sb, "</dict>\n</plist>\n");
+}
+
+static int service_spec_init(service_spec_t *spec, int launchd, const char *argv0, char *unit_path, size_t unit_path_len) {
+    memset(spec, 0, sizeof(*spec));
+    spec->launchd = launchd;
+    spec->system_wide = geteuid() == 0;
+    if (resolve_binary(argv0, spec->binary, sizeof(spec->binary)) != 0 || !getcwd(spec->workdir, sizeof(spec->workdir))) {
+        log_error("cannot resolve the server binary or the working directory: errno=%d", errno);
+        return -1;
+    }
+    const char *home = getenv("HOME");
+    if (!spec->system_wide && (!home || home[0] == '\0')) {
+        log_error("HOME is not set; cannot place a per-user service");
+        return -1;
+    }
+    if (launchd) {
+        if (spec->system_wide) {
+            snprintf(spec->log_path, sizeof(spec->log_path), "/Library/Logs/%s.log", SERVICE_NAME);
+            snprintf(unit_path, unit_path_len, "/Library/LaunchDaemons/%s.plist", SERVICE_LAUNCHD_LABEL);
+        } else {
+            snprintf(spec->log_path, sizeof(spec->log_path), "%s/Library/Logs/%s.log", home, SERVICE_NAME);
+            snprintf(unit_path, unit_path_len, "%s/Library/LaunchAgents/%s.plist", home, SERVICE_LAUNCHD_LABEL);
+        }
+    } else if (spec->system_wide) {
+        snprintf(unit_path, unit_path_len, "/etc/systemd/system/%s.service", SERVICE_NAME);
+    } else {
+        snprintf(unit_path, unit_path_len, "%s/.config/systemd/user/%s.service", home, SERVICE_NAME);
+    }
+    return 0;
+}
+
+int service_render(const char *kind, const char *argv0, strbuf_t *out) {
+    int launchd = strcmp(kind, "launchd") == 0;
+    if (!launchd && strcmp(kind, "systemd") != 0) {
+        log_error("unknown service kind: %s (supported: systemd, launchd)", kind);
+        return -1;
+    }
+    service_spec_t spec;
+    char unit_path[PATH_MAX] = {0};
+    if (service_spec_init(&spec, launchd, argv0, unit_path, sizeof(unit_path)) != 0) return -1;
+    if (launchd) {
+        render_launchd(&spec, out);
+    } else {
+        render_systemd(&spec, out);
+    }
+    return out->failed ? -1 : 0;
+}
+
+/* mkdir -p for the directory part of path. */
+static int make_parent_dirs(const char *path) {
+    char dir[PATH_MAX] = {0};
+    snprintf(dir, sizeof(dir), "%s", path);
+    for (char *p = dir + 1; *p; p++) {
+        if (*p != '/') continue;
+        *p = '\0';
+        if (mkdir(dir, 0755) != 0 && errno != EEXIST) return -1;
+        *p = '/';
+    }
+    return 0;
+}
+
+int service_install(const char *argv0, FILE *out) {
+#if defined(__APPLE__)
+    int launchd = 1;
+#else
+    int launchd = 0;
+#endif
+    service_spec_t spec;
+    char unit_path[PATH_MAX] = {0};
+    if (service_spec_init(&spec, launchd, argv0, unit_path, sizeof(unit_path)) != 0) return -1;
+    strbuf_t unit;
+    strbuf_init(&unit);
+    if (launchd) {
+        render_launchd(&spec, &unit);
+    } else {
+        render_systemd(&spec, &unit);
+    }
+    FILE *fp = NULL;
+    if (!unit.failed && make_parent_dirs(unit_path) == 0) fp = fopen(unit_path, "w");
+    int ok = fp && fwrite(strbuf_cstr(&unit), 1, unit.len, fp) == unit.len;
+    if (fp && fclose(fp) != 0) ok = 0;
+    strbuf_free(&unit);
+    if (!ok) {
+        log_error("cannot write %s: errno=%d", unit_path, errno);
+        return -1;
+    }
+    for (char **env = environ; *env; env++) {
+        char name[128] = {0};
+        const char *eq = strchr(*env, '=');
+        if (strncmp(*env, "FRICU_", 6) != 0 || !eq || copied_variable(*env)) continue;
+        snprintf(name, sizeof(name), "%.*s", (int)(eq - *env), *env);
+        fprintf(out, "note: %s was not copied into the unit; set %s_FILE or FRICU_SECRETS_FILE instead\n", name, name);
+    }
+    fprintf(out, "wrote %s\n", unit_path);
+    if (launchd) {
+        fprintf(out, "start it with: launchctl bootstrap %s %s\n", spec.system_wide ? "system" : "gui/$(id -u)", unit_path);
+    } else if (spec.system_wide) {
+        fprintf(out, "start it with: systemctl daemon-reload && systemctl enable --now %s\n", SERVICE_NAME);
+    } else {
+        fprintf(out, "start it with: systemctl --user daemon-reload && systemctl --user enable --now %s\n", SERVICE_NAME);
+        fprintf(out, "to keep it running after logout: loginctl enable-linger $USER\n");
+    }
+    return 0;
+}
@@ -0,0 +1,188 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * Client clock skew: a request's Date and If-Unmodified-Since headers are compared with the server
+ * clock, and activities written with a date after tomorrow (UTC) are counted as they are stored.
+ * Either finding is reported back on that response as X-Fricu-Warnings (clock_skew,
+ * future_activity_date), with X-Fricu-Clock-Offset giving the client's offset in seconds, so it
+ * reaches clients through 204s as well. Requests that name their device with X-Device-Id also
+ * add to that device's counters in device_clock_skew, which GET /v1/devices lists worst first.
+ */
+
+#define SKEW_DEFAULT_TOLERANCE_SECONDS 300
+#define SKEW_DEVICE_ID_MAX 64
+
+static __thread int g_skew_seen;
+static __thread long long g_offset_seconds;
+static __thread long long g_future_dates;
+
+static long long skew_tolerance(void) {
+    const char *raw = getenv("FRICU_CLOCK_SKEW_SECONDS");
+    if (!raw || raw[0] == '\0') return SKEW_DEFAULT_TOLERANCE_SECONDS;
+    char *end = NULL;
+    long long value = strtoll(raw, &end, 10);
+    return end && *end == '\0' && value > 0 ? value : SKEW_DEFAULT_TOLERANCE_SECONDS;
+}
+
+static int request_device_id(const http_request_t *req, char *out, size_t out_len) {
+    char raw[SKEW_DEVICE_ID_MAX + 2] = {0};
+    if (!http_request_header(req, "X-Device-Id", raw, sizeof(raw))) return 0;
+    size_t len = strlen(raw);
+    if (len == 0 || len > SKEW_DEVICE_ID_MAX) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)raw[i];
+        if (!isalnum(ch) && ch != '-' && ch != '_' && ch != '.' && ch != ':') return 0;
+    }
+    snprintf(out, out_len, "%s", raw);
+    return 1;
+}
+
+void skew_begin(const http_request_t *req) {
+    g_skew_seen = 0;
+    g_offset_seconds = 0;
+    g_future_dates = 0;
+    static const char *CLOCK_HEADERS[] = {"Date", "If-Unmodified-Since"};
+    long long now = (long long)time(NULL);
+    for (size_t i = 0; i < sizeof(CLOCK_HEADERS) / sizeof(CLOCK_HEADERS[0]); i++) {
+        char value[64] = {0};
+        long long client = 0;
+        if (!http_request_header(req, CLOCK_HEADERS[i], value, sizeof(value)) || http_parse_date(value, &client) != 0) continue;
+        long long offset = client - now;
+        if (llabs(offset) <= skew_tolerance()) continue;
+        g_skew_seen = 1;
+        g_offset_seconds = offset;
+        break;
+    }
+}
+
+/* Activities dated after tomorrow in UTC, so a client east of Greenwich is never flagged for its own today. */
+long long skew_count_future_dates(sqlite3 *db, const char *doc, size_t len) {
+    sqlite3_stmt *stmt = NULL;
+    long long count = 0;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT COUNT(*) FROM json_each(?1) WHERE json_type(value) = 'object'"
+            " AND substr(json_extract(value, '$.date'), 1, 10) > date('now', '+1 day')",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, doc, (int)len, SQLITE_STATIC);
+        if (sqlite3_step(stmt) == SQLITE_ROW) count = sqlite3_column_int64(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return count;
+}
+
+void skew_note_future_dates(long long count) {
+    if (count > 0) g_future_dates += count;
+}
+
+int skew_warning_headers(char *out, size_t out_len) {
+    out[0] = '\0';
+    if (!g_skew_seen && g_future_dates == 0) return 0;
+    int n = snprintf(
+        out,
+        out_len,
+        "X-Fricu-Warnings: %s%s%s\r\n",
+        g_skew_seen ? "clock_skew" : "",
+        g_skew_seen && g_future_dates > 0 ? "," : "",
+        g_future_dates > 0 ? "future_activity_date" : "");
+    if (g_skew_seen && n > 0 && (size_t)n < out_len) n += snprintf(out + n, out_len - (size_t)n, "X-Fricu-Clock-Offset: %lld\r\n", g_offset_seconds);
+    return n > 0 && (size_t)n < out_len ? n : 0;
+}
+
+void skew_end(sqlite3 *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int seen = g_skew_seen;
+    long long future = g_future_dates;
+    g_skew_seen = 0;
+    g_future_dates = 0;
+    if (!seen && future == 0) return;
+    char device_id[SKEW_DEVICE_ID_MAX + 1] = {0};
+    int named = request_device_id(req, device_id, sizeof(device_id));
+    log_warn(
+        "CLOCK skew=%s offset=%lld future_dates=%lld device=%s account=%s logid=%s",
+        seen ? "yes" : "no",
+        seen ? g_offset_seconds : 0,
+        future,
+        named ? device_id : "-",
+        ctx->account_id,
+        ctx->log_id);
+    if (!named || ctx->account_id[0] == '\0') return;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "INSERT INTO device_clock_skew (account_id, device_id, skewed_requests, future_dates, last_offset_seconds, last_warning_at)"
+            " VALUES (?1, ?2, ?3, ?4, ?5, strftime('%s', 'now'))"
+            " ON CONFLICT(account_id, device_id) DO UPDATE SET skewed_requests = skewed_requests + excluded.skewed_requests,"
+            " future_dates = future_dates + excluded.future_dates,"
+            " last_offset_seconds = COALESCE(excluded.last_offset_seconds, last_offset_seconds), last_warning_at = excluded.last_warning_at",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        log_warn("CLOCK cannot record device %s: %s", device_id, sqlite3_errmsg(db));
+        return;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, device_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 3, seen ? 1 : 0);
+    sqlite3_bind_int64(stmt, 4, future);
+    if (seen) {
+        sqlite3_bind_int64(stmt, 5, g_offset_seconds);
+    } else {
+        sqlite3_bind_null(stmt, 5);
+    }
+    if (sqlite3_step(stmt) != SQLITE_DONE) log_warn("CLOCK cannot record device %s: %s", device_id, sqlite3_errmsg(db));
+    sqlite3_finalize(stmt);
+}
+
+int handle_get_devices(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT device_id, skewed_requests, future_dates, last_offset_seconds, last_warning_at FROM device_clock_skew"
+            " WHERE account_id = ?1 ORDER BY skewed_requests + future_dates DESC, device_id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"clock_skew_tolerance_seconds\":%lld,\"devices\":[", skew_tolerance());
+    int rows = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (rows++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_append(&sb, "{\"device_id\":", 13);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_appendf(&sb, ",\"skewed_requests\":%lld,\"future_dates\":%lld,\"last_offset_seconds\":", sqlite3_column_int64(stmt, 1), sqlite3_column_int64(stmt, 2));
+        if (sqlite3_column_type(stmt, 3) == SQLITE_NULL) {
+            strbuf_append(&sb, "null", 4);
+        } else {
+            strbuf_appendf(&sb, "%lld", sqlite3_column_int64(stmt, 3));
+        }
+        strbuf_appendf(&sb, ",\"last_warning_at\":%lld}", sqlite3_column_int64(stmt, 4));
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
@@ -0,0 +1,479 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define SLOWLOG_DEFAULT_REQUEST_MS 500.0
+#define SLOWLOG_DEFAULT_QUERY_MS 100.0
+#define SLOWLOG_MAX_ENDPOINTS 64
+#define SLOWLOG_DEFAULT_TOP 10
+
+/*
+ * Latency histograms for GET /v1/admin/metrics (OpenMetrics). Every bucket remembers the last
+ * request that landed in it as an exemplar, labelled with the W3C traceparent trace and span id
+ * when the caller sent one and with the log id otherwise, so a p99 bucket in Grafana links straight
+ * to the trace (or the log lines) of a request that was that slow.
+ */
+static const double LATENCY_BUCKETS_S[] = {0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0};
+#define LATENCY_BUCKET_COUNT (sizeof(LATENCY_BUCKETS_S) / sizeof(LATENCY_BUCKETS_S[0]) + 1)
+
+typedef struct {
+    char labels[128];
+    double value_s;
+    double timestamp;
+} exemplar_t;
+
+typedef struct {
+    long long counts[LATENCY_BUCKET_COUNT];
+    exemplar_t exemplars[LATENCY_BUCKET_COUNT];
+} latency_histogram_t;
+
+typedef struct {
+    char label[160];
+    long long count;
+    long long slow_count;
+    double total_ms;
+    double max_ms;
+    char max_log_id[96];
+    latency_histogram_t histogram;
+} endpoint_stats_t;
+
+typedef struct {
+    int active;
+    double start_ms;
+    double sql_ms;
+    int sql_statements;
+    double write_ms;
+    double write_queue_ms;
+    double queue_ms;
+    double checkout_ms;
+    int server_timing;
+    int status;
+    size_t response_bytes;
+    char log_id[96];
+    char trace_id[33];
+    char span_id[17];
+} request_timing_t;
+
+static pthread_mutex_t g_slowlog_mutex = PTHREAD_MUTEX_INITIALIZER;
+static double g_slow_request_ms = SLOWLOG_DEFAULT_REQUEST_MS;
+static double g_slow_query_ms = SLOWLOG_DEFAULT_QUERY_MS;
+static endpoint_stats_t g_endpoints[SLOWLOG_MAX_ENDPOINTS];
+static size_t g_endpoint_count;
+static long long g_slow_request_total;
+static long long g_slow_query_total;
+
+static __thread request_timing_t g_timing;
+static __thread double g_wakeup_ms;
+
+/* SQLite reports profile times in whole milliseconds; statements are timed on the monotonic clock instead. */
+#define SLOWLOG_OPEN_STATEMENTS 8
+typedef struct {
+    void *stmt;
+    double started_ms;
+} open_statement_t;
+static __thread open_statement_t g_open_statements[SLOWLOG_OPEN_STATEMENTS];
+
+/* Path segments that carry identifiers are folded so the table stays bounded. */
+static const char *PARAM_ROUTE_PREFIXES[] = {"/v1/devices/", "/v1/analytics/activities/"};
+
+static double monotonic_ms(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return (double)ts.tv_sec * 1000.0 + (double)ts.tv_nsec / 1e6;
+}
+
+void slowlog_configure(double slow_request_ms, double slow_query_ms) {
+    pthread_mutex_lock(&g_slowlog_mutex);
+    g_slow_request_ms = slow_request_ms >= 0 ? slow_request_ms : SLOWLOG_DEFAULT_REQUEST_MS;
+    g_slow_query_ms = slow_query_ms >= 0 ? slow_query_ms : SLOWLOG_DEFAULT_QUERY_MS;
+    memset(g_endpoints, 0, sizeof(g_endpoints));
+    g_endpoint_count = 0;
+    g_slow_request_total = 0;
+    g_slow_query_total = 0;
+    pthread_mutex_unlock(&g_slowlog_mutex);
+}
+
+static int sql_profile_callback(unsigned type, void *user, void *p, void *x) {
+    (void)user;
+    if (type == SQLITE_TRACE_STMT) {
+        for (size_t i = 0; i < SLOWLOG_OPEN_STATEMENTS; i++) {
+            if (g_open_statements[i].stmt == p) return 0;
+        }
+        for (size_t i = 0; i < SLOWLOG_OPEN_STATEMENTS; i++) {
+            if (g_open_statements[i].stmt) continue;
+            g_open_statements[i].stmt = p;
+            g_open_statements[i].started_ms = monotonic_ms();
+            break;
+        }
+        return 0;
+    }
+    if (type != SQLITE_TRACE_PROFILE) return 0;
+    sqlite3_stmt *stmt = (sqlite3_stmt *)p;
+    double ms = (double)*(sqlite3_int64 *)x / 1e6;
+    for (size_t i = 0; i < SLOWLOG_OPEN_STATEMENTS; i++) {
+        if (g_open_statements[i].stmt != p) continue;
+        ms = monotonic_ms() - g_open_statements[i].started_ms;
+        g_open_statements[i].stmt = NULL;
+        break;
+    }
+    if (g_timing.active) {
+        g_timing.sql_ms += ms;
+        g_timing.sql_statements++;
+    }
+    pthread_mutex_lock(&g_slowlog_mutex);
+    int slow = ms >= g_slow_query_ms;
+    if (slow) g_slow_query_total++;
+    pthread_mutex_unlock(&g_slowlog_mutex);
+    if (slow) {
+        const char *sql = sqlite3_sql(stmt);
+        log_warn("SLOW QUERY %.1fms sql=%.240s logid=%s", ms, sql ? sql : "-", g_timing.active ? g_timing.log_id : "-");
+    }
+    return 0;
+}
+
+void slowlog_attach(sqlite3 *db) {
+    if (db) sqlite3_trace_v2(db, SQLITE_TRACE_STMT | SQLITE_TRACE_PROFILE, sql_profile_callback, NULL);
+}
+
+void slowlog_endpoint_label(const char *method, const char *path, char *out, size_t out_len) {
+    for (size_t i = 0; i < sizeof(PARAM_ROUTE_PREFIXES) / sizeof(PARAM_ROUTE_PREFIXES[0]); i++) {
+        size_t len = strlen(PARAM_ROUTE_PREFIXES[i]);
+        if (strncmp(path, PARAM_ROUTE_PREFIXES[i], len) == 0 && path[len] != '\0') {
+            snprintf(out, out_len, "%s %s:id", method, PARAM_ROUTE_PREFIXES[i]);
+            return;
+        }
+    }
+    const char *items = strncmp(path, "/v2/data/", 9) == 0 ? strstr(path, "/items/") : NULL;
+    if (items) {
+        snprintf(out, out_len, "%s %.*s/items/:id", method, (int)(items - path), path);
+        return;
+    }
+    snprintf(out, out_len, "%s %s", method, path);
+}
+
+void slowlog_note_wakeup(void) {
+    g_wakeup_ms = monotonic_ms();
+}
+
+static int is_lower_hex(const char *s, size_t len) {
+    int nonzero = 0;
+    for (size_t i = 0; i < len; i++) {
+        if (!((s[i] >= '0' && s[i] <= '9') || (s[i] >= 'a' && s[i] <= 'f'))) return 0;
+        if (s[i] != '0') nonzero = 1;
+    }
+    return nonzero;
+}
+
+/* traceparent: 00-<32 hex trace id>-<16 hex parent id>-<2 hex flags>; all-zero ids are invalid. */
+static void parse_traceparent(const http_request_t *req, char *trace_id, char *span_id) {
+    char value[128] = {0};
+    trace_id[0] = '\0';
+    span_id[0] = '\0';
+    if (!http_request_header(req, "traceparent", value, sizeof(value)) || strlen(value) < 55) return;
+    if (value[2] != '-' || value[35] != '-' || value[52] != '-' || strncmp(value, "ff", 2) == 0) return;
+    if (!is_lower_hex(value + 3, 32) || !is_lower_hex(value + 36, 16)) return;
+    memcpy(trace_id, value + 3, 32);
+    trace_id[32] = '\0';
+    memcpy(span_id, value + 36, 16);
+    span_id[16] = '\0';
+}
+
+void slowlog_request_begin(const http_request_t *req, const request_log_context_t *ctx) {
+    memset(&g_timing, 0, sizeof(g_timing));
+    g_timing.active = 1;
+    g_timing.start_ms = monotonic_ms();
+    /* Time spent behind other ready connections since this worker last woke up. */
+    g_timing.queue_ms = g_wakeup_ms > 0 && g_timing.start_ms > g_wakeup_ms ? g_timing.start_ms - g_wakeup_ms : 0.0;
+    char debug[16] = {0};
+    g_timing.server_timing = http_request_header(req, SERVER_TIMING_DEBUG_HEADER, debug, sizeof(debug)) && strcmp(debug, "0") != 0;
+    snprintf(g_timing.log_id, sizeof(g_timing.log_id), "%s", ctx->log_id);
+    parse_traceparent(req, g_timing.trace_id, g_timing.span_id);
+}
+
+static double wall_clock_s(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_REALTIME, &ts);
+    return (double)ts.tv_sec + (double)ts.tv_nsec / 1e9;
+}
+
+/* Appends s as an OpenMetrics label value: backslash, quote and newline are escaped. */
+static void append_label_value(strbuf_t *sb, const char *s) {
+    for (const char *p = s; *p; p++) {
+        if (*p == '\\' || *p == '"') {
+            char escaped[2] = {'\\', *p};
+            strbuf_append(sb, escaped, 2);
+        } else if (*p == '\n') {
+            strbuf_append(sb, "\\n", 2);
+        } else {
+            strbuf_append(sb, p, 1);
+        }
+    }
+}
+
+static void histogram_observe(latency_histogram_t *histogram, double total_ms) {
+    double value_s = total_ms / 1000.0;
+    size_t bucket = 0;
+    while (bucket < LATENCY_BUCKET_COUNT - 1 && value_s > LATENCY_BUCKETS_S[bucket]) bucket++;
+    histogram->counts[bucket]++;
+    exemplar_t *exemplar = &histogram->exemplars[bucket];
+    exemplar->value_s = value_s;
+    exemplar->timestamp = wall_clock_s();
+    if (g_timing.trace_id[0] != '\0') {
+        snprintf(exemplar->labels, sizeof(exemplar->labels), "trace_id=\"%s\",span_id=\"%s\"", g_timing.trace_id, g_timing.span_id);
+    } else {
+        strbuf_t sb;
+        strbuf_init(&sb);
+        strbuf_append(&sb, "log_id=\"", 8);
+        append_label_value(&sb, g_timing.log_id);
+        strbuf_append(&sb, "\"", 1);
+        /* OpenMetrics caps an exemplar's label set at 128 characters; long client log ids are dropped. */
+        snprintf(exemplar->labels, sizeof(exemplar->labels), "%s", !sb.failed && sb.len < sizeof(exemplar->labels) ? strbuf_cstr(&sb) : "");
+        strbuf_free(&sb);
+    }
+}
+
+void slowlog_note_response(int code, size_t body_len) {
+    if (!g_timing.active) return;
+    g_timing.status = code;
+    g_timing.response_bytes = body_len;
+}
+
+void slowlog_note_write(double ms, double queue_ms) {
+    if (!g_timing.active) return;
+    g_timing.write_ms += ms;
+    g_timing.write_queue_ms += queue_ms;
+    g_timing.checkout_ms += queue_ms;
+}
+
+void slowlog_note_checkout(double ms) {
+    if (g_timing.active) g_timing.checkout_ms += ms;
+}
+
+int slowlog_server_timing_header(char *out, size_t out_len) {
+    out[0] = '\0';
+    if (!g_timing.active || !g_timing.server_timing) return 0;
+    double elapsed = monotonic_ms() - g_timing.start_ms;
+    /* Write time spent waiting in the dispatcher queue is reported as checkout, not db. */
+    double db = g_timing.sql_ms + g_timing.write_ms - g_timing.write_queue_ms;
+    double serialize = elapsed - db - g_timing.checkout_ms;
+    int n = snprintf(
+        out,
+        out_len,
+        "Server-Timing: queue;dur=%.3f, checkout;dur=%.3f, db;dur=%.3f;desc=\"%d statements\", serialize;dur=%.3f, total;dur=%.3f\r\n",
+        g_timing.queue_ms,
+        g_timing.checkout_ms,
+        db,
+        g_timing.sql_statements,
+        serialize > 0 ? serialize : 0.0,
+        g_timing.queue_ms + elapsed);
+    return n > 0 && (size_t)n < out_len ? n : 0;
+}
+
+double slowlog_now_ms(void) {
+    return monotonic_ms();
+}
+
+void slowlog_request_end(const http_request_t *req, const request_log_context_t *ctx) {
+    if (!g_timing.active) return;
+    g_timing.active = 0;
+    double total_ms = monotonic_ms() - g_timing.start_ms;
+    char label[160] = {0};
+    slowlog_endpoint_label(req->method, req->path, label, sizeof(label));
+
+    pthread_mutex_lock(&g_slowlog_mutex);
+    int slow = total_ms >= g_slow_request_ms;
+    if (slow) g_slow_request_total++;
+    endpoint_stats_t *entry = NULL;
+    for (size_t i = 0; i < g_endpoint_count; i++) {
+        if (strcmp(g_endpoints[i].label, label) == 0) entry = &g_endpoints[i];
+    }
+    if (!entry && g_endpoint_count < SLOWLOG_MAX_ENDPOINTS) {
+        entry = &g_endpoints[g_endpoint_count++];
+        snprintf(entry->label, sizeof(entry->label), "%s", label);
+    }
+    if (entry) {
+        entry->count++;
+        entry->total_ms += total_ms;
+        if (slow) entry->slow_count++;
+        histogram_observe(&entry->histogram, total_ms);
+        if (total_ms > entry->max_ms) {
+            entry->max_ms = total_ms;
+            snprintf(entry->max_log_id, sizeof(entry->max_log_id), "%s", ctx->log_id);
+        }
+    }
+    pthread_mutex_unlock(&g_slowlog_mutex);
+
+    if (slow) {
+        char key[64] = {0};
+        request_data_key(req->path, key, sizeof(key));
+        log_warn(
+            "SLOW REQUEST %s -> %d total_ms=%.1f sql_ms=%.1f sql_statements=%d write_ms=%.1f other_ms=%.1f key=%s bytes_in=%zu bytes_out=%zu account=%s logid=%s",
+            label,
+            g_timing.status,
+            total_ms,
+            g_timing.sql_ms,
+            g_timing.sql_statements,
+            g_timing.write_ms,
+            total_ms - g_timing.sql_ms - g_timing.write_ms,
+            key[0] ? key : "-",
+            req->body_len,
+            g_timing.response_bytes,
+            ctx->account_id[0] ? ctx->account_id : "-",
+            ctx->log_id);
+    }
+}
+
+static int compare_endpoint_max_desc(const void *a, const void *b) {
+    const endpoint_stats_t *lhs = (const endpoint_stats_t *)a;
+    const endpoint_stats_t *rhs = (const endpoint_stats_t *)b;
+    if (lhs->max_ms < rhs->max_ms) return 1;
+    if (lhs->max_ms > rhs->max_ms) return -1;
+    return strcmp(lhs->label, rhs->label);
+}
+
+int handle_get_admin_stats(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    int top = SLOWLOG_DEFAULT_TOP;
+    char raw_top[16] = {0};
+    if (query_param(req->query, "top", raw_top, sizeof(raw_top))) {
+        top = atoi(raw_top);
+        if (top <= 0 || top > SLOWLOG_MAX_ENDPOINTS) top = SLOWLOG_DEFAULT_TOP;
+    }
+
+    endpoint_stats_t snapshot[SLOWLOG_MAX_ENDPOINTS];
+    pthread_mutex_lock(&g_slowlog_mutex);
+    size_t count = g_endpoint_count;
+    memcpy(snapshot, g_endpoints, count * sizeof(endpoint_stats_t));
+    double slow_request_ms = g_slow_request_ms;
+    double slow_query_ms = g_slow_query_ms;
+    long long slow_requests = g_slow_request_total;
+    long long slow_queries = g_slow_query_total;
+    pthread_mutex_unlock(&g_slowlog_mutex);
+    qsort(snapshot, count, sizeof(endpoint_stats_t), compare_endpoint_max_desc);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "{\"thresholds\":{\"slow_request_ms\":%.1f,\"slow_query_ms\":%.1f},\"slow_requests\":%lld,\"slow_queries\":%lld,\"endpoints\":[",
+        slow_request_ms,
+        slow_query_ms,
+        slow_requests,
+        slow_queries);
+    for (size_t i = 0; i < count && i < (size_t)top; i++) {
+        const endpoint_stats_t *entry = &snapshot[i];
+        strbuf_append(&sb, i == 0 ? "{\"endpoint\":" : ",{\"endpoint\":", i == 0 ? 12 : 13);
+        strbuf_append_json_string(&sb, entry->label);
+        strbuf_appendf(
+            &sb,
+            ",\"count\":%lld,\"slow_count\":%lld,\"avg_ms\":%.2f,\"max_ms\":%.2f,\"max_log_id\":",
+            entry->count,
+            entry->slow_count,
+            entry->total_ms / (double)entry->count,
+            entry->max_ms);
+        strbuf_append_json_string(&sb, entry->max_log_id);
+        strbuf_append(&sb, "}", 1);
+    }
+    strbuf_append(&sb, "],", 2);
+    deprecation_append_usage(&sb);
+    strbuf_append(&sb, "}", 1);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static void append_exemplar(strbuf_t *sb, const exemplar_t *exemplar) {
+    if (exemplar->labels[0] == '\0') {
+        strbuf_append(sb, "\n", 1);
+        return;
+    }
+    strbuf_appendf(sb, " # {%s} %.6f %.3f\n", exemplar->labels, exemplar->value_s, exemplar->timestamp);
+}
+
+/* GET /v1/admin/metrics: per-endpoint latency histograms with exemplars, in OpenMetrics text. */
+int handle_get_admin_metrics(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    endpoint_stats_t *snapshot = (endpoint_stats_t *)malloc(sizeof(g_endpoints));
+    if (!snapshot) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    pthread_mutex_lock(&g_slowlog_mutex);
+    size_t count = g_endpoint_count;
+    memcpy(snapshot, g_endpoints, count * sizeof(endpoint_stats_t));
+    long long slow_requests = g_slow_request_total;
+    long long slow_queries = g_slow_query_total;
+    pthread_mutex_unlock(&g_slowlog_mutex);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "# TYPE fricu_http_request_duration_seconds histogram\n"
+        "# UNIT fricu_http_request_duration_seconds seconds\n"
+        "# HELP fricu_http_request_duration_seconds Request latency by endpoint.\n");
+    for (size_t i = 0; i < count; i++) {
+        const endpoint_stats_t *entry = &snapshot[i];
+        long long cumulative = 0;
+        for (size_t b = 0; b < LATENCY_BUCKET_COUNT; b++) {
+            cumulative += entry->histogram.counts[b];
+            strbuf_append(&sb, "fricu_http_request_duration_seconds_bucket{endpoint=\"", 53);
+            append_label_value(&sb, entry->label);
+            if (b < LATENCY_BUCKET_COUNT - 1) {
+                strbuf_appendf(&sb, "\",le=\"%g\"} %lld", LATENCY_BUCKETS_S[b], cumulative);
+            } else {
+                strbuf_appendf(&sb, "\",le=\"+Inf\"} %lld", cumulative);
+            }
+            if (entry->histogram.counts[b] > 0) {
+                append_exemplar(&sb, &entry->histogram.exemplars[b]);
+            } else {
+                strbuf_append(&sb, "\n", 1);
+            }
+        }
+        strbuf_append(&sb, "fricu_http_request_duration_seconds_count{endpoint=\"", 52);
+        append_label_value(&sb, entry->label);
+        strbuf_appendf(&sb, "\"} %lld\n", entry->count);
+        strbuf_append(&sb, "fricu_http_request_duration_seconds_sum{endpoint=\"", 50);
+        append_label_value(&sb, entry->label);
+        strbuf_appendf(&sb, "\"} %.6f\n", entry->total_ms / 1000.0);
+    }
+    free(snapshot);
+    strbuf_appendf(
+        &sb,
+        "# TYPE fricu_slow_requests counter\n"
+        "# HELP fricu_slow_requests Requests slower than the slow-request threshold.\n"
+        "fricu_slow_requests_total %lld\n"
+        "# TYPE fricu_slow_queries counter\n"
+        "# HELP fricu_slow_queries SQL statements slower than the slow-query threshold.\n"
+        "fricu_slow_queries_total %lld\n",
+        slow_requests,
+        slow_queries);
+    rate_limit_append_metrics(&sb);
+    strbuf_append(&sb, "# EOF\n", 6);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_http_response(fd, 200, "OK", "application/openmetrics-text; version=1.0.0; charset=utf-8", NULL, strbuf_cstr(&sb), sb.len, ctx);
+    strbuf_free(&sb);
+    return 200;
+}
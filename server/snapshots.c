@@ -0,0 +1,444 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Daily key snapshots: once per UTC day the writer copies every kv_store value that changed since
+ * its previous snapshot into key_snapshots, labelled with that day. The value of a key "on" a day
+ * is its latest snapshot at or before the day, so unchanged keys cost nothing. GET
+ * /v1/data/<key>/diff compares two such states structurally, and GET /v1/data/<key>?as_of=<instant>
+ * serves the value a key had at an instant.
+ */
+
+#define SNAPSHOT_CHECK_INTERVAL_SEC 300
+#define SNAPSHOT_DEFAULT_RETENTION_DAYS 90
+#define SNAPSHOT_DIFF_MAX_CHANGES 1000
+
+int snapshots_take(sqlite3 *db, const char *day) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "INSERT OR IGNORE INTO key_snapshots (data_key, day, data_value, created_at)"
+        " SELECT k.data_key, ?1, k.data_value, strftime('%s', 'now') FROM kv_store k"
+        " WHERE k.data_value IS NOT (SELECT s.data_value FROM key_snapshots s"
+        "  WHERE s.data_key = k.data_key AND s.day <= ?1 ORDER BY s.day DESC LIMIT 1)";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, day, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? sqlite3_changes(db) : -1;
+}
+
+/* Drops snapshots older than the cutoff, keeping each key's newest one before it as the baseline. */
+int snapshots_prune(sqlite3 *db, const char *cutoff_day) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "DELETE FROM key_snapshots WHERE day < ?1 AND EXISTS (SELECT 1 FROM key_snapshots n"
+        " WHERE n.data_key = key_snapshots.data_key AND n.day > key_snapshots.day AND n.day <= ?1)";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, cutoff_day, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_DONE ? sqlite3_changes(db) : -1;
+}
+
+typedef struct {
+    char *db_path;
+    int retention_days;
+} snapshot_thread_args_t;
+
+static void *snapshot_thread_entry(void *arg) {
+    snapshot_thread_args_t *args = (snapshot_thread_args_t *)arg;
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(args->db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("snapshot thread failed to open db: %s", db ? sqlite3_errmsg(db) : "unknown");
+        if (db) sqlite3_close(db);
+        free(args->db_path);
+        free(args);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=1000;", NULL, NULL, NULL);
+    char last_day[16] = {0};
+    for (;;) {
+        char day[16] = {0};
+        format_iso_day(today_day(), day, sizeof(day));
+        if (strcmp(day, last_day) != 0 && cluster_is_writer()) {
+            int taken = snapshots_take(db, day);
+            if (taken >= 0) {
+                char cutoff[16] = {0};
+                format_iso_day(today_day() - args->retention_days, cutoff, sizeof(cutoff));
+                int pruned = snapshots_prune(db, cutoff);
+                log_info("SNAPSHOT day=%s keys=%d pruned=%d", day, taken, pruned);
+                snprintf(last_day, sizeof(last_day), "%s", day);
+            } else {
+                log_warn("SNAPSHOT day=%s failed: %s", day, sqlite3_errmsg(db));
+            }
+        }
+        sleep(SNAPSHOT_CHECK_INTERVAL_SEC);
+    }
+    return NULL;
+}
+
+int snapshots_start(const char *db_path) {
+    const char *enabled = getenv("FRICU_SNAPSHOTS");
+    if (enabled && strcmp(enabled, "0") == 0) return 0;
+    const char *retention_env = getenv("FRICU_SNAPSHOT_RETENTION_DAYS");
+    int retention_days = retention_env ? atoi(retention_env) : SNAPSHOT_DEFAULT_RETENTION_DAYS;
+    if (retention_days <= 0) retention_days = SNAPSHOT_DEFAULT_RETENTION_DAYS;
+
+    snapshot_thread_args_t *args = calloc(1, sizeof(*args));
+    if (args) {
+        args->db_path = strdup(db_path);
+        args->retention_days = retention_days;
+    }
+    pthread_t thread;
+    if (!args || !args->db_path || pthread_create(&thread, NULL, snapshot_thread_entry, args) != 0) {
+        if (args) free(args->db_path);
+        free(args);
+        log_error("failed to start snapshot thread");
+        return -1;
+    }
+    pthread_detach(thread);
+    log_info("SNAPSHOT daily snapshots enabled retention_days=%d", retention_days);
+    return 0;
+}
+
+/* Latest snapshot at or before day; returns 1 when found, 0 when none, -1 on error. */
+static int snapshot_at(sqlite3 *db, const char *storage_key, const char *day, char *out_day, size_t out_day_len, char **out_value) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = "SELECT day, data_value FROM key_snapshots WHERE data_key = ?1 AND day <= ?2 ORDER BY day DESC LIMIT 1";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, day, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        snprintf(out_day, out_day_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+        *out_value = strdup((const char *)sqlite3_column_text(stmt, 1));
+        found = *out_value ? 1 : -1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+static int current_value(sqlite3 *db, const char *storage_key, char **out_value) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_value FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        *out_value = strdup((const char *)sqlite3_column_text(stmt, 0));
+        found = *out_value ? 1 : -1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+/* Scalars and empty containers of a document, keyed by JSON path, each as JSON text. */
+#define SNAPSHOT_LEAVES_SQL(param)                                                                                  \
+    "SELECT fullkey AS k, CASE type WHEN 'true' THEN 'true' WHEN 'false' THEN 'false' WHEN 'null' THEN 'null'"      \
+    " WHEN 'object' THEN '{}' WHEN 'array' THEN '[]' ELSE json_quote(atom) END AS v FROM json_tree(" param ")"      \
+    " WHERE type NOT IN ('object', 'array') OR value IN ('{}', '[]')"
+
+/* ?1 old document, ?2 new document; rows are (path, old, new) with NULL for a missing side. */
+static const char *SNAPSHOT_LEAF_DIFF_SQL =
+    "WITH a AS (" SNAPSHOT_LEAVES_SQL("?1") "), b AS (" SNAPSHOT_LEAVES_SQL("?2") ")"
+    " SELECT a.k, a.v, b.v FROM a LEFT JOIN b ON b.k = a.k WHERE b.v IS NOT a.v"
+    " UNION ALL SELECT b.k, NULL, b.v FROM b WHERE b.k NOT IN (SELECT k FROM a)"
+    " ORDER BY 1";
+
+/* Elements of an array as (id, position, JSON text); items without an id are identified by position. */
+#define SNAPSHOT_ITEMS_SQL(param)                                                                                  \
+    "SELECT COALESCE(CASE type WHEN 'object' THEN CAST(json_extract(value, '$.id') AS TEXT) END, '#' || key) AS id," \
+    " key AS pos,"                                                                                                  \
+    " CASE type WHEN 'object' THEN value WHEN 'array' THEN value WHEN 'true' THEN 'true' WHEN 'false' THEN 'false'" \
+    " WHEN 'null' THEN 'null' ELSE json_quote(atom) END AS v FROM json_each(" param ")"
+
+/* ?1 old array, ?2 new array; items are matched by id. */
+static const char *SNAPSHOT_ITEM_DIFF_SQL =
+    "WITH a AS (" SNAPSHOT_ITEMS_SQL("?1") "), b AS (" SNAPSHOT_ITEMS_SQL("?2") ")"
+    " SELECT 'remove', a.id, a.pos, a.v, NULL FROM a WHERE a.id NOT IN (SELECT id FROM b)"
+    " UNION ALL SELECT 'add', b.id, b.pos, NULL, b.v FROM b WHERE b.id NOT IN (SELECT id FROM a)"
+    " UNION ALL SELECT 'replace', b.id, b.pos, a.v, b.v FROM a JOIN b ON b.id = a.id WHERE a.v IS NOT b.v"
+    " ORDER BY 3, 1";
+
+typedef struct {
+    strbuf_t *sb;
+    int count;
+    int truncated;
+} diff_writer_t;
+
+static int diff_writer_begin(diff_writer_t *w, const char *op) {
+    if (w->count >= SNAPSHOT_DIFF_MAX_CHANGES) {
+        w->truncated = 1;
+        return 0;
+    }
+    if (w->count++ > 0) strbuf_append(w->sb, ",", 1);
+    strbuf_append(w->sb, "{\"op\":", 6);
+    strbuf_append_json_string(w->sb, op);
+    return 1;
+}
+
+/* Appends leaf changes between two documents; id is set for items of a collection. */
+static int append_leaf_diff(sqlite3 *db, diff_writer_t *w, const char *id, const char *old_json, const char *new_json) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, SNAPSHOT_LEAF_DIFF_SQL, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, old_json, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, new_json, -1, SQLITE_TRANSIENT);
+    int rc;
+    while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        const char *old_value = (const char *)sqlite3_column_text(stmt, 1);
+        const char *new_value = (const char *)sqlite3_column_text(stmt, 2);
+        if (!diff_writer_begin(w, !old_value ? "add" : (!new_value ? "remove" : "replace"))) break;
+        if (id) {
+            strbuf_append(w->sb, ",\"id\":", 6);
+            strbuf_append_json_string(w->sb, id);
+        }
+        strbuf_append(w->sb, ",\"path\":", 8);
+        strbuf_append_json_string(w->sb, (const char *)sqlite3_column_text(stmt, 0));
+        if (old_value) {
+            strbuf_append(w->sb, ",\"old\":", 7);
+            strbuf_append(w->sb, old_value, strlen(old_value));
+        }
+        if (new_value) {
+            strbuf_append(w->sb, ",\"new\":", 7);
+            strbuf_append(w->sb, new_value, strlen(new_value));
+        }
+        strbuf_append(w->sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    return rc == SQLITE_ROW || rc == SQLITE_DONE ? 0 : -1;
+}
+
+static int append_item_diff(sqlite3 *db, diff_writer_t *w, const char *old_json, const char *new_json) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, SNAPSHOT_ITEM_DIFF_SQL, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, old_json, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, new_json, -1, SQLITE_TRANSIENT);
+    int rc;
+    int failed = 0;
+    while (!failed && !w->truncated && (rc = sqlite3_step(stmt)) == SQLITE_ROW) {
+        const char *op = (const char *)sqlite3_column_text(stmt, 0);
+        const char *id = (const char *)sqlite3_column_text(stmt, 1);
+        const char *old_item = (const char *)sqlite3_column_text(stmt, 3);
+        const char *new_item = (const char *)sqlite3_column_text(stmt, 4);
+        if (strcmp(op, "replace") == 0) {
+            failed = append_leaf_diff(db, w, id, old_item, new_item) != 0;
+            continue;
+        }
+        if (!diff_writer_begin(w, op)) break;
+        strbuf_append(w->sb, ",\"id\":", 6);
+        strbuf_append_json_string(w->sb, id);
+        strbuf_append(w->sb, ",\"value\":", 9);
+        const char *item = old_item ? old_item : new_item;
+        strbuf_append(w->sb, item, strlen(item));
+        strbuf_append(w->sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    return failed ? -1 : 0;
+}
+
+static int both_arrays(sqlite3 *db, const char *old_json, const char *new_json) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = "SELECT json_valid(?1) AND json_valid(?2) AND json_type(?1) = 'array' AND json_type(?2) = 'array'";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return 0;
+    sqlite3_bind_text(stmt, 1, old_json, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, new_json, -1, SQLITE_TRANSIENT);
+    int arrays = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    sqlite3_finalize(stmt);
+    return arrays;
+}
+
+int handle_get_data_diff(int fd, worker_db_t *db, const char *key, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    int from_day = 0;
+    int to_day = 0;
+    query_param(req->query, "from", from, sizeof(from));
+    query_param(req->query, "to", to, sizeof(to));
+    if (strlen(from) != 10 || parse_iso_day(from, &from_day) != 0 ||
+        (to[0] != '\0' && (strlen(to) != 10 || parse_iso_day(to, &to_day) != 0))) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from (and optional to) must be YYYY-MM-DD\"}", ctx);
+        return 400;
+    }
+    if (to[0] != '\0' && to_day < from_day) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"to must not be before from\"}", ctx);
+        return 400;
+    }
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    char from_snapshot[16] = {0};
+    char to_snapshot[16] = {0};
+    char *old_value = NULL;
+    char *new_value = NULL;
+    int old_found = snapshot_at(db->db, storage_key, from, from_snapshot, sizeof(from_snapshot), &old_value);
+    int new_found = to[0] != '\0' ? snapshot_at(db->db, storage_key, to, to_snapshot, sizeof(to_snapshot), &new_value)
+                                  : current_value(db->db, storage_key, &new_value);
+    if (old_found < 0 || new_found < 0) {
+        free(old_value);
+        free(new_value);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (!old_found) {
+        free(new_value);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no snapshot on or before from\"}", ctx);
+        return 404;
+    }
+    if (!new_found) new_value = strdup("null");
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"key\":", 7);
+    strbuf_append_json_string(&sb, key);
+    strbuf_append(&sb, ",\"from\":{\"date\":", 16);
+    strbuf_append_json_string(&sb, from);
+    strbuf_append(&sb, ",\"snapshot\":", 12);
+    strbuf_append_json_string(&sb, from_snapshot);
+    strbuf_append(&sb, "},\"to\":{\"date\":", 15);
+    if (to[0] != '\0') {
+        strbuf_append_json_string(&sb, to);
+        strbuf_append(&sb, ",\"snapshot\":", 12);
+        strbuf_append_json_string(&sb, to_snapshot);
+    } else {
+        strbuf_append(&sb, "null,\"snapshot\":\"current\"", 25);
+    }
+    strbuf_append(&sb, "},\"changes\":[", 13);
+    diff_writer_t writer = {.sb = &sb};
+    int rc = -1;
+    if (new_value) {
+        rc = both_arrays(db->db, old_value, new_value) ? append_item_diff(db->db, &writer, old_value, new_value)
+                                                       : append_leaf_diff(db->db, &writer, NULL, old_value, new_value);
+    }
+    free(old_value);
+    free(new_value);
+    strbuf_appendf(&sb, "],\"truncated\":%s}", writer.truncated ? "true" : "false");
+    if (rc != 0 || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"diff failed\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* YYYY-MM-DD, or YYYY-MM-DDTHH:MM[:SS[.fff]] with Z or a +HH:MM/-HH:MM offset, as unix seconds. */
+static int parse_as_of(const char *text, time_t *out) {
+    int day = 0;
+    if (parse_iso_day(text, &day) != 0) return -1;
+    if (text[10] == '\0') {
+        *out = (time_t)day * 86400;
+        return 0;
+    }
+    int hour = 0;
+    int minute = 0;
+    int second = 0;
+    int used = 0;
+    if (text[10] != 'T' || sscanf(text + 11, "%2d:%2d%n", &hour, &minute, &used) != 2 || used != 5) return -1;
+    const char *p = text + 16;
+    if (*p == ':') {
+        if (sscanf(p + 1, "%2d%n", &second, &used) != 1 || used != 2) return -1;
+        p += 3;
+        if (*p == '.') {
+            p++;
+            while (*p >= '0' && *p <= '9') p++;
+        }
+    }
+    int offset = 0;
+    if (*p == '+' || *p == '-') {
+        int off_hour = 0;
+        int off_minute = 0;
+        if (sscanf(p + 1, "%2d:%2d%n", &off_hour, &off_minute, &used) != 2 || used != 5 || p[6] != '\0') return -1;
+        offset = (off_hour * 60 + off_minute) * 60 * (*p == '-' ? -1 : 1);
+    } else if (strcmp(p, "Z") != 0) {
+        return -1;
+    }
+    if (hour > 23 || minute > 59 || second > 60) return -1;
+    *out = (time_t)day * 86400 + hour * 3600 + minute * 60 + second - offset;
+    return 0;
+}
+
+static void format_instant(time_t t, char *out, size_t out_len) {
+    struct tm tm_value;
+    gmtime_r(&t, &tm_value);
+    strftime(out, out_len, "%Y-%m-%dT%H:%M:%SZ", &tm_value);
+}
+
+/*
+ * The stored row answers when it was last written at or before the instant (exact). Otherwise the
+ * newest snapshot captured by then does, which is the state at its capture time: writes between a
+ * snapshot and the instant are not recorded, so the resolution is a day. A key that has never been
+ * stored reads as its default. The capture time and source come back in X-Fricu-As-Of(-Source).
+ */
+int handle_get_data_as_of(int fd, worker_db_t *db, const char *key, const char *as_of, const request_log_context_t *ctx) {
+    time_t instant = 0;
+    if (parse_as_of(as_of, &instant) != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"as_of must be an ISO-8601 date or timestamp\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    const char *sql =
+        "SELECT source, value, at FROM ("
+        " SELECT 0 AS rank, 'current' AS source, data_value AS value, updated_at AS at FROM kv_store WHERE data_key = ?1 AND updated_at <= ?2"
+        " UNION ALL SELECT * FROM (SELECT 1, 'snapshot', data_value, created_at FROM key_snapshots"
+        "  WHERE data_key = ?1 AND created_at <= ?2 ORDER BY created_at DESC LIMIT 1)"
+        " UNION ALL SELECT 2, 'default', NULL, NULL WHERE NOT EXISTS (SELECT 1 FROM kv_store WHERE data_key = ?1)"
+        "  AND NOT EXISTS (SELECT 1 FROM key_snapshots WHERE data_key = ?1))"
+        " ORDER BY rank LIMIT 1";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, (sqlite3_int64)instant);
+    char source[16] = {0};
+    char *value = NULL;
+    time_t resolved = 0;
+    int rc = sqlite3_step(stmt);
+    if (rc == SQLITE_ROW) {
+        snprintf(source, sizeof(source), "%s", (const char *)sqlite3_column_text(stmt, 0));
+        const char *text = (const char *)sqlite3_column_text(stmt, 1);
+        value = strdup(text ? text : api_key_is_collection(key) ? "[]" : "{}");
+        resolved = (time_t)sqlite3_column_int64(stmt, 2);
+    }
+    sqlite3_finalize(stmt);
+    if (rc == SQLITE_ROW && !value) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (rc != SQLITE_ROW) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"no history at or before as_of\"}", ctx);
+        return 404;
+    }
+
+    char headers[256] = {0};
+    if (strcmp(source, "default") == 0) {
+        snprintf(headers, sizeof(headers), "X-Fricu-As-Of-Source: default\r\n");
+    } else {
+        char resolved_text[32] = {0};
+        format_instant(resolved, resolved_text, sizeof(resolved_text));
+        snprintf(headers, sizeof(headers), "X-Fricu-As-Of: %s\r\nX-Fricu-As-Of-Source: %s\r\n", resolved_text, source);
+    }
+    send_http_response(fd, 200, "OK", "application/json", headers, value, strlen(value), ctx);
+    free(value);
+    log_info("DATA READ key=%s source=as_of:%s account=%s logid=%s", key, source, ctx->account_id, ctx->log_id);
+    return 200;
+}
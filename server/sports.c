@@ -0,0 +1,330 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+
+/*
+ * Sport-specific profile sections. The profile may carry
+ *   "sports": {"cycling": {"ftpWatts": 250, "defaultGear": "Road bike", "tssModel": "tss"},
+ *              "running": {"thresholdPaceSecPerKm": 270, "thresholdHeartRate": 172, "tssModel": "rtss"},
+ *              "swimming": {"cssSecPer100m": 95}}
+ * which is validated on write. Imports and live sessions use the section of the activity's sport,
+ * so a run is scored from threshold pace (or heart rate) instead of the cycling FTP, and new
+ * activities get the sport's default gear. The legacy top-level cyclingFTPWatts / ftpWatts still
+ * act as the cycling FTP when the cycling section has none.
+ */
+
+#define SPORT_CYCLING (1 << 0)
+#define SPORT_RUNNING (1 << 1)
+#define SPORT_SWIMMING (1 << 2)
+#define SPORT_STRENGTH (1 << 3)
+#define SPORT_ALL (SPORT_CYCLING | SPORT_RUNNING | SPORT_SWIMMING | SPORT_STRENGTH)
+
+static const struct {
+    const char *name;
+    int bit;
+    const char *default_model;
+} SPORTS[] = {
+    {"cycling", SPORT_CYCLING, "tss"},
+    {"running", SPORT_RUNNING, "rtss"},
+    {"swimming", SPORT_SWIMMING, "stss"},
+    {"strength", SPORT_STRENGTH, "hrss"},
+};
+
+/*
+ * Load models and the sports they make sense for. Every model whose inputs an activity has is
+ * computed at import and kept in the activity's "loads"; "tss" holds the primary one.
+ *   tss    Coggan TSS from normalized power and FTP
+ *   rtss   running TSS from threshold pace
+ *   stss   swimming TSS from critical swim speed (cubed, as swim drag grows with speed)
+ *   hrss   heart-rate TSS from threshold heart rate
+ *   trimp  Banister TRIMP from heart-rate reserve (resting and max heart rate)
+ *   srpe   Foster session RPE: RPE (0-10) x minutes, for sessions without sensors
+ */
+static const struct {
+    const char *name;
+    int sports;
+} SPORT_TSS_MODELS[] = {
+    {"tss", SPORT_CYCLING},
+    {"rtss", SPORT_RUNNING},
+    {"stss", SPORT_SWIMMING},
+    {"hrss", SPORT_ALL},
+    {"trimp", SPORT_ALL},
+    {"srpe", SPORT_ALL},
+};
+#define SPORT_TSS_MODEL_COUNT (sizeof(SPORT_TSS_MODELS) / sizeof(SPORT_TSS_MODELS[0]))
+
+typedef enum { FIELD_NUMBER, FIELD_TEXT, FIELD_MODEL } sport_field_kind_t;
+
+static const struct {
+    const char *name;
+    sport_field_kind_t kind;
+    int sports;
+    double min;
+    double max;
+} SPORT_FIELDS[] = {
+    {"ftpWatts", FIELD_NUMBER, SPORT_CYCLING, 30, 2000},
+    {"thresholdPaceSecPerKm", FIELD_NUMBER, SPORT_RUNNING, 120, 900},
+    {"cssSecPer100m", FIELD_NUMBER, SPORT_SWIMMING, 40, 300},
+    {"thresholdHeartRate", FIELD_NUMBER, SPORT_ALL, 60, 230},
+    {"restingHeartRate", FIELD_NUMBER, SPORT_ALL, 25, 120},
+    {"maxHeartRate", FIELD_NUMBER, SPORT_ALL, 100, 240},
+    {"defaultGear", FIELD_TEXT, SPORT_ALL, 0, 128},
+    {"tssModel", FIELD_MODEL, SPORT_ALL, 0, 0},
+};
+
+/* Names other tools use for the same sports (GoldenCheetah tags, Strava types); matched ignoring case. */
+static const struct {
+    const char *alias;
+    const char *sport;
+} SPORT_ALIASES[] = {
+    {"bike", "cycling"},
+    {"ride", "cycling"},
+    {"virtualride", "cycling"},
+    {"run", "running"},
+    {"swim", "swimming"},
+    {"weighttraining", "strength"},
+    {"gym", "strength"},
+};
+
+const char *sport_canonical_name(const char *name) {
+    if (!name) return NULL;
+    for (size_t i = 0; i < sizeof(SPORTS) / sizeof(SPORTS[0]); i++) {
+        if (strcasecmp(SPORTS[i].name, name) == 0) return SPORTS[i].name;
+    }
+    for (size_t i = 0; i < sizeof(SPORT_ALIASES) / sizeof(SPORT_ALIASES[0]); i++) {
+        if (strcasecmp(SPORT_ALIASES[i].alias, name) == 0) return SPORT_ALIASES[i].sport;
+    }
+    return NULL;
+}
+
+static int sport_index(const char *sport) {
+    for (size_t i = 0; sport && i < sizeof(SPORTS) / sizeof(SPORTS[0]); i++) {
+        if (strcmp(SPORTS[i].name, sport) == 0) return (int)i;
+    }
+    return -1;
+}
+
+/* Names echoed into error bodies; anything else is reported without the name. */
+static int is_plain_name(const char *name) {
+    size_t n = 0;
+    for (; name[n] != '\0'; n++) {
+        char c = name[n];
+        if (n >= 32 || !((c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '_')) return 0;
+    }
+    return n > 0;
+}
+
+static int check_field(const char *sport, int bit, const char *field, const char *type, sqlite3_value *value, char *err, size_t err_len) {
+    for (size_t i = 0; i < sizeof(SPORT_FIELDS) / sizeof(SPORT_FIELDS[0]); i++) {
+        if (strcmp(SPORT_FIELDS[i].name, field) != 0) continue;
+        if (!(SPORT_FIELDS[i].sports & bit)) {
+            snprintf(err, err_len, "{\"error\":\"%s does not apply to %s\",\"field\":\"sports.%s.%s\"}", field, sport, sport, field);
+            return -1;
+        }
+        if (SPORT_FIELDS[i].kind == FIELD_NUMBER) {
+            double number = sqlite3_value_double(value);
+            if ((strcmp(type, "integer") != 0 && strcmp(type, "real") != 0) || number < SPORT_FIELDS[i].min || number > SPORT_FIELDS[i].max) {
+                snprintf(
+                    err,
+                    err_len,
+                    "{\"error\":\"%s must be a number in %.0f..%.0f\",\"field\":\"sports.%s.%s\"}",
+                    field,
+                    SPORT_FIELDS[i].min,
+                    SPORT_FIELDS[i].max,
+                    sport,
+                    field);
+                return -1;
+            }
+            return 0;
+        }
+        const char *text = (const char *)sqlite3_value_text(value);
+        if (strcmp(type, "text") != 0 || !text) {
+            snprintf(err, err_len, "{\"error\":\"%s must be a string\",\"field\":\"sports.%s.%s\"}", field, sport, field);
+            return -1;
+        }
+        if (SPORT_FIELDS[i].kind == FIELD_TEXT) {
+            if (strlen(text) > (size_t)SPORT_FIELDS[i].max) {
+                snprintf(err, err_len, "{\"error\":\"%s is longer than %.0f bytes\",\"field\":\"sports.%s.%s\"}", field, SPORT_FIELDS[i].max, sport, field);
+                return -1;
+            }
+            return 0;
+        }
+        for (size_t m = 0; m < SPORT_TSS_MODEL_COUNT; m++) {
+            if (strcmp(SPORT_TSS_MODELS[m].name, text) == 0 && (SPORT_TSS_MODELS[m].sports & bit)) return 0;
+        }
+        snprintf(err, err_len, "{\"error\":\"tssModel is not available for %s\",\"field\":\"sports.%s.tssModel\"}", sport, sport);
+        return -1;
+    }
+    if (is_plain_name(field)) {
+        snprintf(err, err_len, "{\"error\":\"unknown field\",\"field\":\"sports.%s.%s\"}", sport, field);
+    } else {
+        snprintf(err, err_len, "{\"error\":\"unknown field\",\"field\":\"sports.%s\"}", sport);
+    }
+    return -1;
+}
+
+int profile_validate_sports(sqlite3 *db, const char *payload, size_t payload_len, char *err, size_t err_len) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_type(?1, '$.sports'), s.key, s.type, f.key, f.type, f.value FROM (SELECT 1)"
+            " LEFT JOIN json_each(CASE json_type(?1, '$.sports') WHEN 'object' THEN json_extract(?1, '$.sports') ELSE '{}' END) s"
+            " LEFT JOIN json_each(CASE s.type WHEN 'object' THEN s.value ELSE '{}' END) f ORDER BY s.id, f.id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        snprintf(err, err_len, "{\"error\":\"database error\"}");
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, payload, (int)payload_len, SQLITE_TRANSIENT);
+    int status = 0;
+    while (status == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *root = (const char *)sqlite3_column_text(stmt, 0);
+        const char *sport = (const char *)sqlite3_column_text(stmt, 1);
+        const char *section_type = (const char *)sqlite3_column_text(stmt, 2);
+        const char *field = (const char *)sqlite3_column_text(stmt, 3);
+        if (!root) break;
+        if (strcmp(root, "object") != 0) {
+            snprintf(err, err_len, "{\"error\":\"sports must be an object keyed by sport\",\"field\":\"sports\"}");
+            status = 400;
+        } else if (!sport) {
+            break;
+        } else if (sport_index(sport) < 0) {
+            snprintf(
+                err, err_len, "{\"error\":\"unknown sport (expected cycling, running, swimming or strength)\",\"field\":\"sports%s%s\"}", is_plain_name(sport) ? "." : "", is_plain_name(sport) ? sport : "");
+            status = 400;
+        } else if (strcmp(section_type, "object") != 0) {
+            snprintf(err, err_len, "{\"error\":\"sport section must be an object\",\"field\":\"sports.%s\"}", sport);
+            status = 400;
+        } else if (field && check_field(sport, SPORTS[sport_index(sport)].bit, field, (const char *)sqlite3_column_text(stmt, 4), sqlite3_column_value(stmt, 5), err, err_len) != 0) {
+            status = 400;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return status;
+}
+
+int load_sport_settings(sqlite3 *db, const char *account_id, const char *sport, sport_settings_t *out) {
+    memset(out, 0, sizeof(*out));
+    int index = sport_index(sport);
+    snprintf(out->tss_model, sizeof(out->tss_model), "%s", index >= 0 ? SPORTS[index].default_model : "hrss");
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return -1;
+    char path[64] = {0};
+    snprintf(path, sizeof(path), "$.sports.%s", index >= 0 ? SPORTS[index].name : "none");
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(s, '$.ftpWatts'), json_extract(s, '$.thresholdPaceSecPerKm'), json_extract(s, '$.cssSecPer100m'),"
+            " COALESCE(json_extract(s, '$.thresholdHeartRate'), json_extract(p, '$.thresholdHeartRate')), json_extract(s, '$.defaultGear'),"
+            " json_extract(s, '$.tssModel'), COALESCE(json_extract(p, '$.cyclingFTPWatts'), json_extract(p, '$.ftpWatts')),"
+            " COALESCE(json_extract(s, '$.restingHeartRate'), json_extract(p, '$.restingHeartRate')),"
+            " COALESCE(json_extract(s, '$.maxHeartRate'), json_extract(p, ?3), json_extract(p, '$.maxHeartRate'))"
+            " FROM (SELECT data_value AS p, CASE json_type(data_value, ?2) WHEN 'object' THEN json_extract(data_value, ?2) ELSE '{}' END AS s"
+            " FROM kv_store WHERE data_key = ?1 AND json_valid(data_value))",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, path, -1, SQLITE_TRANSIENT);
+    /* The older per-sport profile fields (cyclingMaxHeartRate, runningMaxHeartRate). */
+    char legacy_max_hr[64] = {0};
+    snprintf(legacy_max_hr, sizeof(legacy_max_hr), "$.%sMaxHeartRate", index >= 0 ? SPORTS[index].name : "none");
+    sqlite3_bind_text(stmt, 3, legacy_max_hr, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        out->ftp_watts = sqlite3_column_double(stmt, 0);
+        if (out->ftp_watts <= 0.0 && index >= 0 && SPORTS[index].bit == SPORT_CYCLING) out->ftp_watts = sqlite3_column_double(stmt, 6);
+        out->threshold_pace_sec_per_km = sqlite3_column_double(stmt, 1);
+        out->css_sec_per_100m = sqlite3_column_double(stmt, 2);
+        out->threshold_heart_rate = sqlite3_column_double(stmt, 3);
+        if (sqlite3_column_text(stmt, 4)) snprintf(out->default_gear, sizeof(out->default_gear), "%s", (const char *)sqlite3_column_text(stmt, 4));
+        if (sqlite3_column_text(stmt, 5)) snprintf(out->tss_model, sizeof(out->tss_model), "%s", (const char *)sqlite3_column_text(stmt, 5));
+        out->resting_heart_rate = sqlite3_column_double(stmt, 7);
+        out->max_heart_rate = sqlite3_column_double(stmt, 8);
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+static double sport_model_load(const char *model, const sport_settings_t *settings, const sport_load_input_t *in) {
+    double hours = in->duration_sec / 3600.0;
+    if (strcmp(model, "tss") == 0 && in->source_tss > 0.0) return in->source_tss;
+    if (strcmp(model, "tss") == 0 && settings->ftp_watts > 0.0 && in->normalized_power > 0.0) {
+        double intensity = in->normalized_power / settings->ftp_watts;
+        return hours * intensity * intensity * 100.0;
+    }
+    if (strcmp(model, "rtss") == 0 && settings->threshold_pace_sec_per_km > 0.0 && in->distance_km > 0.0) {
+        double intensity = settings->threshold_pace_sec_per_km / (in->duration_sec / in->distance_km);
+        return hours * intensity * intensity * 100.0;
+    }
+    if (strcmp(model, "stss") == 0 && settings->css_sec_per_100m > 0.0 && in->distance_km > 0.0) {
+        double intensity = settings->css_sec_per_100m / (in->duration_sec / (in->distance_km * 10.0));
+        return hours * intensity * intensity * intensity * 100.0;
+    }
+    if (strcmp(model, "hrss") == 0 && settings->threshold_heart_rate > 0.0 && in->avg_heart_rate > 0.0) {
+        double intensity = in->avg_heart_rate / settings->threshold_heart_rate;
+        return hours * intensity * intensity * 100.0;
+    }
+    if (strcmp(model, "trimp") == 0 && settings->max_heart_rate > settings->resting_heart_rate && settings->resting_heart_rate > 0.0 &&
+        in->avg_heart_rate > settings->resting_heart_rate) {
+        double reserve = (in->avg_heart_rate - settings->resting_heart_rate) / (settings->max_heart_rate - settings->resting_heart_rate);
+        if (reserve > 1.0) reserve = 1.0;
+        return hours * 60.0 * reserve * 0.64 * exp(1.92 * reserve);
+    }
+    if (strcmp(model, "srpe") == 0 && in->rpe > 0.0) return hours * 60.0 * in->rpe;
+    return -1.0;
+}
+
+int sport_load_model_known(const char *model) {
+    for (size_t m = 0; model && m < SPORT_TSS_MODEL_COUNT; m++) {
+        if (strcmp(SPORT_TSS_MODELS[m].name, model) == 0) return 1;
+    }
+    return 0;
+}
+
+void sport_compute_loads(const sport_settings_t *settings, const sport_load_input_t *in, sport_load_t *out) {
+    memset(out, 0, sizeof(*out));
+    if (in->duration_sec <= 0.0) return;
+    double values[SPORT_TSS_MODEL_COUNT];
+    size_t used = 0;
+    for (size_t m = 0; m < SPORT_TSS_MODEL_COUNT; m++) {
+        values[m] = sport_model_load(SPORT_TSS_MODELS[m].name, settings, in);
+        if (!(values[m] > 0.0) || !isfinite(values[m])) {
+            values[m] = -1.0;
+            continue;
+        }
+        used += (size_t)snprintf(
+            out->loads_json + used, sizeof(out->loads_json) - used, "%s\"%s\":%.0f", used == 0 ? "{" : ",", SPORT_TSS_MODELS[m].name, values[m]);
+        if (used >= sizeof(out->loads_json)) used = sizeof(out->loads_json) - 1;
+    }
+    if (used > 0 && used + 1 < sizeof(out->loads_json)) snprintf(out->loads_json + used, sizeof(out->loads_json) - used, "}");
+    /*
+     * The preferred model, then a TSS reported by the source file, then heart rate; never another
+     * sport's threshold model.
+     */
+    const char *order[] = {settings->tss_model, in->source_tss > 0.0 ? "tss" : NULL, "hrss"};
+    for (size_t o = 0; o < sizeof(order) / sizeof(order[0]) && out->model[0] == '\0'; o++) {
+        for (size_t m = 0; order[o] && m < SPORT_TSS_MODEL_COUNT; m++) {
+            if (strcmp(SPORT_TSS_MODELS[m].name, order[o]) != 0 || values[m] < 0.0) continue;
+            out->tss = values[m];
+            snprintf(out->model, sizeof(out->model), "%s", SPORT_TSS_MODELS[m].name);
+            break;
+        }
+    }
+}
+
+void sport_append_loads(strbuf_t *sb, const sport_load_t *load) {
+    if (load->model[0] == '\0') return;
+    strbuf_appendf(sb, "\"loadModel\":\"%s\",\"loads\":%s,", load->model, load->loads_json);
+}
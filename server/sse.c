@@ -0,0 +1,319 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <fcntl.h>
+#include <poll.h>
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Server-Sent Events for data changes, for web clients where a WebSocket is awkward. GET
+ * /v1/events/stream answers with text/event-stream and, like /v1/ws, hands a duplicate of the
+ * socket to a stream thread. Events come from key_changes, which db.c keeps with the latest change
+ * per key in write order: each is `id: <seq>`, `event: change` and data {"key","updated_at",
+ * "revision","deleted"}, revision being the current document version (null once deleted). A client
+ * reconnecting with Last-Event-ID (or ?last_event_id= where EventSource cannot set headers) first
+ * gets every key of its account changed after that id, newest state only, then live updates; a new
+ * stream without one starts from now. The change-event hub only wakes the thread, so writes from
+ * other instances arrive once they reach the shared database. Comments keep idle streams open
+ * through proxies; a client that cannot take an event without blocking is dropped and resumes from
+ * its last id. Not offered through the built-in TLS listener.
+ */
+
+#define SSE_DEFAULT_MAX_CLIENTS 256
+#define SSE_CLIENTS_CAP 4096
+#define SSE_KEEPALIVE_SEC 15
+#define SSE_RETRY_MS 3000
+#define SSE_REPLAY_LIMIT 500
+
+typedef struct {
+    int fd;
+    char account_id[128];
+    long long last_seq;
+    int dirty;
+    time_t opened_at;
+    time_t last_write;
+} sse_client_t;
+
+static pthread_mutex_t g_sse_mutex = PTHREAD_MUTEX_INITIALIZER;
+static pthread_once_t g_sse_once = PTHREAD_ONCE_INIT;
+static sse_client_t *g_clients;
+static size_t g_max_clients;
+static int g_wake_pipe[2] = {-1, -1};
+static char g_db_path[512];
+static int g_sse_running;
+
+static size_t sse_max_clients(void) {
+    const char *raw = getenv("FRICU_SSE_MAX_CLIENTS");
+    if (!raw || raw[0] == '\0') return SSE_DEFAULT_MAX_CLIENTS;
+    char *end = NULL;
+    long value = strtol(raw, &end, 10);
+    return end && *end == '\0' && value > 0 && value <= SSE_CLIENTS_CAP ? (size_t)value : SSE_DEFAULT_MAX_CLIENTS;
+}
+
+static void sse_wake(void) {
+    char byte = 1;
+    if (write(g_wake_pipe[1], &byte, 1) < 0 && errno != EAGAIN) log_warn("SSE wake failed: errno=%d", errno);
+}
+
+static int sse_send(int fd, const char *data, size_t len) {
+    ssize_t n = send(fd, data, len, MSG_DONTWAIT | socket_send_flags());
+    return n == (ssize_t)len ? 0 : -1;
+}
+
+/* Called with g_sse_mutex held. */
+static void sse_drop(sse_client_t *client, const char *reason) {
+    log_info("SSE closed reason=%s account=%s last_event_id=%lld open_for=%llds", reason, client->account_id, client->last_seq, (long long)(time(NULL) - client->opened_at));
+    close(client->fd);
+    memset(client, 0, sizeof(*client));
+    client->fd = -1;
+}
+
+static void on_change_event(const change_event_t *event, void *user) {
+    (void)user;
+    int woken = 0;
+    pthread_mutex_lock(&g_sse_mutex);
+    for (size_t i = 0; i < g_max_clients; i++) {
+        if (g_clients[i].fd < 0 || strcmp(g_clients[i].account_id, event->account_id) != 0) continue;
+        g_clients[i].dirty = 1;
+        woken = 1;
+    }
+    pthread_mutex_unlock(&g_sse_mutex);
+    if (woken) sse_wake();
+}
+
+static long long sse_current_seq(sqlite3 *db) {
+    sqlite3_stmt *stmt = NULL;
+    long long seq = 0;
+    if (sqlite3_prepare_v2(db, "SELECT COALESCE(MAX(seq), 0) FROM key_changes", -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW) {
+        seq = sqlite3_column_int64(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return seq;
+}
+
+/* Sends the account's changes after last_seq; called with g_sse_mutex held. */
+static void sse_flush_client(sqlite3 *db, sse_client_t *client) {
+    client->dirty = 0;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT c.seq, substr(c.data_key, length(?1) + 3), c.updated_at, c.deleted, k.data_value"
+            " FROM key_changes c LEFT JOIN kv_store k ON k.data_key = c.data_key AND c.deleted = 0"
+            " WHERE c.data_key > ?1 || '::' AND c.data_key < ?1 || ':;' AND c.seq > ?2 ORDER BY c.seq LIMIT ?3",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        log_warn("SSE cannot read changes: %s", sqlite3_errmsg(db));
+        return;
+    }
+    sqlite3_bind_text(stmt, 1, client->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, client->last_seq);
+    sqlite3_bind_int(stmt, 3, SSE_REPLAY_LIMIT);
+    int rows = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        rows++;
+        long long seq = sqlite3_column_int64(stmt, 0);
+        strbuf_t sb;
+        strbuf_init(&sb);
+        strbuf_appendf(&sb, "id: %lld\nevent: change\ndata: {\"key\":", seq);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_appendf(&sb, ",\"updated_at\":%lld,\"revision\":", sqlite3_column_int64(stmt, 2));
+        if (sqlite3_column_type(stmt, 4) == SQLITE_NULL) {
+            strbuf_append(&sb, "null", 4);
+        } else {
+            char version[32] = {0};
+            content_version((const char *)sqlite3_column_text(stmt, 4), (size_t)sqlite3_column_bytes(stmt, 4), version, sizeof(version));
+            strbuf_append_json_string(&sb, version);
+        }
+        if (sqlite3_column_int(stmt, 3)) {
+            strbuf_append(&sb, ",\"deleted\":true}\n\n", 18);
+        } else {
+            strbuf_append(&sb, ",\"deleted\":false}\n\n", 19);
+        }
+        int sent = !sb.failed && sse_send(client->fd, strbuf_cstr(&sb), sb.len) == 0;
+        strbuf_free(&sb);
+        if (!sent) {
+            sqlite3_finalize(stmt);
+            sse_drop(client, "slow_consumer");
+            return;
+        }
+        client->last_seq = seq;
+        client->last_write = time(NULL);
+    }
+    sqlite3_finalize(stmt);
+    /* A long backlog goes out in slices so one resuming client cannot hold the thread. */
+    if (rows == SSE_REPLAY_LIMIT) client->dirty = 1;
+}
+
+static void sse_drain_wakeups(void) {
+    char buf[256];
+    while (read(g_wake_pipe[0], buf, sizeof(buf)) > 0) {
+    }
+}
+
+static void *sse_thread_entry(void *arg) {
+    (void)arg;
+    sqlite3 *db = NULL;
+    if (sqlite3_open_v2(g_db_path, &db, SQLITE_OPEN_READWRITE | SQLITE_OPEN_FULLMUTEX, NULL) != SQLITE_OK) {
+        log_error("SSE thread failed to open db: %s", db ? sqlite3_errmsg(db) : "unknown");
+        if (db) sqlite3_close(db);
+        return NULL;
+    }
+    sqlite3_exec(db, "PRAGMA busy_timeout=1000;", NULL, NULL, NULL);
+    struct pollfd *pfds = (struct pollfd *)calloc(g_max_clients + 1, sizeof(struct pollfd));
+    if (!pfds) {
+        sqlite3_close(db);
+        return NULL;
+    }
+    for (;;) {
+        size_t count = 1;
+        pfds[0].fd = g_wake_pipe[0];
+        pfds[0].events = POLLIN;
+        pfds[0].revents = 0;
+        pthread_mutex_lock(&g_sse_mutex);
+        for (size_t i = 0; i < g_max_clients; i++) {
+            if (g_clients[i].fd < 0) continue;
+            pfds[count].fd = g_clients[i].fd;
+            pfds[count].events = POLLIN;
+            pfds[count].revents = 0;
+            count++;
+        }
+        pthread_mutex_unlock(&g_sse_mutex);
+
+        int ready = poll(pfds, (nfds_t)count, SSE_KEEPALIVE_SEC * 1000);
+        if (ready > 0 && pfds[0].revents) sse_drain_wakeups();
+        time_t now = time(NULL);
+        pthread_mutex_lock(&g_sse_mutex);
+        /* Clients never send anything after the request, so readable means gone. */
+        for (size_t i = 1; ready > 0 && i < count; i++) {
+            if (pfds[i].revents == 0) continue;
+            for (size_t j = 0; j < g_max_clients; j++) {
+                if (g_clients[j].fd != pfds[i].fd) continue;
+                char byte = 0;
+                ssize_t n = recv(g_clients[j].fd, &byte, 1, MSG_DONTWAIT | MSG_PEEK);
+                if (n == 0 || (n < 0 && errno != EAGAIN && errno != EWOULDBLOCK && errno != EINTR) || (pfds[i].revents & (POLLHUP | POLLERR))) {
+                    sse_drop(&g_clients[j], "disconnected");
+                } else if (n > 0) {
+                    char discard[256];
+                    while (recv(g_clients[j].fd, discard, sizeof(discard), MSG_DONTWAIT) > 0) {
+                    }
+                }
+            }
+        }
+        for (size_t i = 0; i < g_max_clients; i++) {
+            sse_client_t *client = &g_clients[i];
+            if (client->fd < 0) continue;
+            if (client->dirty) sse_flush_client(db, client);
+            if (client->fd >= 0 && now - client->last_write >= SSE_KEEPALIVE_SEC) {
+                if (sse_send(client->fd, ": keep-alive\n\n", 14) != 0) {
+                    sse_drop(client, "slow_consumer");
+                } else {
+                    client->last_write = now;
+                }
+            }
+            if (client->fd >= 0 && client->dirty) sse_wake();
+        }
+        pthread_mutex_unlock(&g_sse_mutex);
+    }
+    return NULL;
+}
+
+static void sse_start(void) {
+    size_t max_clients = sse_max_clients();
+    sse_client_t *clients = (sse_client_t *)calloc(max_clients, sizeof(sse_client_t));
+    if (!clients) return;
+    for (size_t i = 0; i < max_clients; i++) clients[i].fd = -1;
+    if (pipe(g_wake_pipe) != 0) {
+        free(clients);
+        return;
+    }
+    fcntl(g_wake_pipe[0], F_SETFL, fcntl(g_wake_pipe[0], F_GETFL) | O_NONBLOCK);
+    fcntl(g_wake_pipe[1], F_SETFL, fcntl(g_wake_pipe[1], F_GETFL) | O_NONBLOCK);
+    g_clients = clients;
+    g_max_clients = max_clients;
+    pthread_t thread;
+    if (pthread_create(&thread, NULL, sse_thread_entry, NULL) != 0) {
+        log_error("failed to start SSE thread");
+        return;
+    }
+    pthread_detach(thread);
+    change_events_subscribe(on_change_event, NULL);
+    g_sse_running = 1;
+}
+
+static int parse_event_id(const char *raw, long long *out) {
+    char *end = NULL;
+    long long value = strtoll(raw, &end, 10);
+    if (raw[0] == '\0' || !end || *end != '\0' || value < 0) return -1;
+    *out = value;
+    return 0;
+}
+
+int handle_get_events_stream(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char raw_id[32] = {0};
+    long long last_seq = -1;
+    if (http_request_header(req, "Last-Event-ID", raw_id, sizeof(raw_id)) || query_param(req->query, "last_event_id", raw_id, sizeof(raw_id))) {
+        if (parse_event_id(raw_id, &last_seq) != 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"Last-Event-ID must be an event id from this stream\"}", ctx);
+            return 400;
+        }
+    }
+    if (tls_enabled()) {
+        send_response_with_log_context(fd, 501, "Not Implemented", "{\"error\":\"event streams are not offered over the built-in TLS listener\"}", ctx);
+        return 501;
+    }
+    if (last_seq < 0) last_seq = sse_current_seq(db->db);
+
+    if (g_db_path[0] == '\0') snprintf(g_db_path, sizeof(g_db_path), "%s", db->db_path);
+    pthread_once(&g_sse_once, sse_start);
+    int client_fd = -1;
+    sse_client_t *slot = NULL;
+    pthread_mutex_lock(&g_sse_mutex);
+    for (size_t i = 0; g_sse_running && i < g_max_clients && !slot; i++) {
+        if (g_clients[i].fd < 0) slot = &g_clients[i];
+    }
+    if (slot) client_fd = dup(fd);
+    if (client_fd < 0) {
+        pthread_mutex_unlock(&g_sse_mutex);
+        send_response_with_log_context(fd, 503, "Service Unavailable", "{\"error\":\"too many event streams\"}", ctx);
+        return 503;
+    }
+
+    char cors[512] = {0};
+    char response[1024] = {0};
+    cors_headers(cors, sizeof(cors));
+    int n = snprintf(
+        response,
+        sizeof(response),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\nX-Accel-Buffering: no\r\n%s\r\n"
+        "retry: %d\n\n",
+        cors,
+        SSE_RETRY_MS);
+    accesslog_note_response(200, 0);
+    if (send(client_fd, response, (size_t)n, socket_send_flags()) != n) {
+        pthread_mutex_unlock(&g_sse_mutex);
+        close(client_fd);
+        return 500;
+    }
+    slot->fd = client_fd;
+    snprintf(slot->account_id, sizeof(slot->account_id), "%s", ctx->account_id);
+    slot->last_seq = last_seq;
+    slot->dirty = 1;
+    slot->opened_at = time(NULL);
+    slot->last_write = slot->opened_at;
+    pthread_mutex_unlock(&g_sse_mutex);
+    sse_wake();
+    log_info("SSE open last_event_id=%lld account=%s logid=%s", last_seq, ctx->account_id, ctx->log_id);
+    return 200;
+}
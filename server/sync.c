@@ -0,0 +1,651 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <pthread.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/* Partial writes (v2 items, deltas) and conditional PUTs are read-modify-write on the whole document; serialize them within the process. */
+static pthread_mutex_t g_document_write_mutex = PTHREAD_MUTEX_INITIALIZER;
+
+void sync_document_lock(void) {
+    double started_ms = slowlog_now_ms();
+    pthread_mutex_lock(&g_document_write_mutex);
+    slowlog_note_checkout(slowlog_now_ms() - started_ms);
+}
+
+void sync_document_unlock(void) {
+    pthread_mutex_unlock(&g_document_write_mutex);
+}
+
+int sync_negotiate_protocol(const http_request_t *req, int fd, const request_log_context_t *ctx) {
+    char raw[16] = {0};
+    if (!http_request_header(req, SYNC_PROTOCOL_HEADER, raw, sizeof(raw))) return 0;
+    int version = atoi(raw);
+    if (version >= SYNC_PROTOCOL_MIN_VERSION && version <= SYNC_PROTOCOL_VERSION) return 0;
+    char body[128] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "{\"error\":\"unsupported sync protocol\",\"supported\":[%d,%d]}",
+        SYNC_PROTOCOL_MIN_VERSION,
+        SYNC_PROTOCOL_VERSION);
+    send_response_with_log_context(fd, 400, "Bad Request", body, ctx);
+    return 400;
+}
+
+int sync_current_version(sqlite3 *db, const char *storage_key, char *out_version, size_t out_len, long long *out_updated_at) {
+    snprintf(out_version, out_len, "%s", SYNC_MISSING_VERSION);
+    if (out_updated_at) *out_updated_at = 0;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_value, updated_at FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int found = 0;
+    int rc = sqlite3_step(stmt);
+    if (rc == SQLITE_ROW) {
+        const char *value = (const char *)sqlite3_column_text(stmt, 0);
+        content_version(value, (size_t)sqlite3_column_bytes(stmt, 0), out_version, out_len);
+        if (out_updated_at) *out_updated_at = sqlite3_column_int64(stmt, 1);
+        found = 1;
+    } else if (rc != SQLITE_DONE) {
+        found = -1;
+    }
+    sqlite3_finalize(stmt);
+    return found;
+}
+
+/* If-Match is a list of strong ETags or "*"; weak tags (W/"...") never match, and nothing matches a missing document. */
+static int if_match_allows(const char *header, const char *current, int exists) {
+    if (!exists) return 0;
+    const char *p = header;
+    while (*p) {
+        p += strspn(p, " \t,");
+        if (*p == '*') return 1;
+        int weak = strncmp(p, "W/", 2) == 0;
+        if (weak) p += 2;
+        if (*p != '"') return 0;
+        const char *end = strchr(p + 1, '"');
+        if (!end) return 0;
+        if (!weak && (size_t)(end - p - 1) == strlen(current) && strncmp(p + 1, current, strlen(current)) == 0) return 1;
+        p = end + 1;
+    }
+    return 0;
+}
+
+static int reject_if_match(const char *key, const char *current, int exists, long long updated_at, int fd, const request_log_context_t *ctx) {
+    char body[256] = {0};
+    char headers[96] = {0};
+    if (exists) snprintf(headers, sizeof(headers), SYNC_VERSION_HEADERS_FMT, current, current);
+    snprintf(
+        body,
+        sizeof(body),
+        "{\"error\":\"precondition failed\",\"key\":\"%s\",\"current_version\":\"%s\",\"updated_at\":%lld}",
+        key,
+        current,
+        updated_at);
+    send_http_response(fd, 412, "Precondition Failed", "application/json", headers, body, strlen(body), ctx);
+    log_warn("DATA WRITE rejected key=%s reason=if_match account=%s logid=%s", key, ctx->account_id, ctx->log_id);
+    return 412;
+}
+
+int sync_check_base_version(worker_db_t *db, const http_request_t *req, const char *key, int fd, const request_log_context_t *ctx) {
+    char base[64] = {0};
+    char if_match[256] = {0};
+    int has_base = http_request_header(req, SYNC_BASE_VERSION_HEADER, base, sizeof(base));
+    int has_if_match = http_request_header(req, "If-Match", if_match, sizeof(if_match));
+    if (!has_base && !has_if_match) return 0;
+
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, key, storage_key, sizeof(storage_key)) != 0) return 0;
+    char current[32] = {0};
+    long long updated_at = 0;
+    int exists = sync_current_version(db->db, storage_key, current, sizeof(current), &updated_at);
+    if (exists < 0) {
+        /* Without the current version the precondition cannot be checked, so the write must not go ahead. */
+        log_error("DATA WRITE version check failed key=%s err=%s account=%s logid=%s", key, sqlite3_errmsg(db->db), ctx->account_id, ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (!has_base || strcmp(base, current) == 0) {
+        if (!has_if_match || if_match_allows(if_match, current, exists)) return 0;
+        return reject_if_match(key, current, exists, updated_at, fd, ctx);
+    }
+
+    char body[256] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "{\"error\":\"conflict\",\"key\":\"%s\",\"base_version\":\"%.32s\",\"current_version\":\"%s\",\"updated_at\":%lld}",
+        key,
+        base,
+        current,
+        updated_at);
+    send_response_with_log_context(fd, 409, "Conflict", body, ctx);
+    log_warn("DATA WRITE rejected key=%s reason=version_conflict account=%s logid=%s", key, ctx->account_id, ctx->log_id);
+    return 409;
+}
+
+int handle_get_sync_manifest(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int status = sync_negotiate_protocol(req, fd, ctx);
+    if (status != 0) return status;
+
+    long long since = 0;
+    char raw_since[32] = {0};
+    if (query_param(req->query, "since", raw_since, sizeof(raw_since))) {
+        char *end = NULL;
+        since = strtoll(raw_since, &end, 10);
+        if (!end || *end != '\0' || since < 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"since must be a unix timestamp\"}", ctx);
+            return 400;
+        }
+    }
+
+    char prefix[160] = {0};
+    snprintf(prefix, sizeof(prefix), "%s::", ctx->account_id);
+    const char *sql =
+        "SELECT substr(data_key, length(?1) + 1), data_value, updated_at FROM kv_store"
+        " WHERE substr(data_key, 1, length(?1)) = ?1 AND updated_at > ?2 ORDER BY data_key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, prefix, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, since);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"protocol\":%d,\"server_time\":%lld,\"since\":%lld,\"keys\":[", SYNC_PROTOCOL_VERSION, (long long)time(NULL), since);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        if (!key || !is_valid_key(key)) continue;
+        char version[32] = {0};
+        content_version((const char *)sqlite3_column_text(stmt, 1), (size_t)sqlite3_column_bytes(stmt, 1), version, sizeof(version));
+        strbuf_appendf(
+            &sb,
+            "%s{\"key\":\"%s\",\"version\":\"%s\",\"updated_at\":%lld,\"bytes\":%d,\"locked\":%s}",
+            count == 0 ? "" : ",",
+            key,
+            version,
+            (long long)sqlite3_column_int64(stmt, 2),
+            sqlite3_column_bytes(stmt, 1),
+            key_is_locked(db->db, ctx->account_id, key) > 0 ? "true" : "false");
+        count++;
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "],\"locked_keys\":", 16);
+    locks_append_keys(db->db, ctx->account_id, &sb);
+    strbuf_append(&sb, "}", 1);
+
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    char headers[64] = {0};
+    snprintf(headers, sizeof(headers), "%s: %d\r\n", SYNC_PROTOCOL_HEADER, SYNC_PROTOCOL_VERSION);
+    const char *body = strbuf_cstr(&sb);
+    send_http_response(fd, 200, "OK", "application/json", headers, body, strlen(body), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/* Appends "items":[...],"deleted":[...] for items of storage_key changed after since. */
+static int append_item_changes(sqlite3 *db, const char *storage_key, long long since, strbuf_t *sb) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT item_id, item_value, deleted FROM item_changes WHERE data_key = ?1 AND updated_at > ?2 ORDER BY deleted, item_id",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, since);
+    strbuf_append(sb, ",\"items\":[", 10);
+    int items = 0;
+    int deleted = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (sqlite3_column_int(stmt, 2)) {
+            if (deleted++ == 0) {
+                strbuf_append(sb, "],\"deleted\":[", 13);
+            } else {
+                strbuf_append(sb, ",", 1);
+            }
+            strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+            continue;
+        }
+        if (items++ > 0) strbuf_append(sb, ",", 1);
+        strbuf_append(sb, (const char *)sqlite3_column_text(stmt, 1), (size_t)sqlite3_column_bytes(stmt, 1));
+    }
+    sqlite3_finalize(stmt);
+    if (deleted == 0) strbuf_append(sb, "],\"deleted\":[", 13);
+    strbuf_append(sb, "]", 1);
+    return 0;
+}
+
+/* List documents whose items all carry an "id" can be sent as item changes; anything else goes whole. */
+static int document_has_item_ids(sqlite3 *db, const char *doc, size_t len) {
+    sqlite3_stmt *stmt = NULL;
+    int ok = 0;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_valid(?1) AND json_type(?1) = 'array' AND NOT EXISTS"
+            " (SELECT 1 FROM json_each(?1) WHERE json_type(value) <> 'object' OR json_extract(value, '$.id') IS NULL)",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, doc, (int)len, SQLITE_STATIC);
+        ok = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return ok;
+}
+
+static int build_sync_changes(sqlite3 *db, const char *account_id, long long since, long long now, strbuf_t *sb) {
+    char prefix[160] = {0};
+    snprintf(prefix, sizeof(prefix), "%s::", account_id);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT substr(data_key, length(?1) + 1), data_key, data_value, updated_at FROM kv_store"
+            " WHERE substr(data_key, 1, length(?1)) = ?1 AND updated_at > ?2 ORDER BY data_key",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, prefix, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int64(stmt, 2, since);
+    strbuf_appendf(
+        sb,
+        "{\"protocol\":%d,\"server_time\":%lld,\"since\":%lld,\"next_since\":%lld,\"keys\":[",
+        SYNC_PROTOCOL_VERSION,
+        now,
+        since,
+        now - 1);
+    int count = 0;
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        if (!key || !is_valid_key(key)) continue;
+        const char *doc = (const char *)sqlite3_column_text(stmt, 2);
+        size_t doc_len = (size_t)sqlite3_column_bytes(stmt, 2);
+        char version[32] = {0};
+        content_version(doc, doc_len, version, sizeof(version));
+        strbuf_appendf(
+            sb,
+            "%s{\"key\":\"%s\",\"version\":\"%s\",\"updated_at\":%lld",
+            count++ == 0 ? "" : ",",
+            key,
+            version,
+            (long long)sqlite3_column_int64(stmt, 3));
+        if (since > 0 && api_key_is_collection(key) && document_has_item_ids(db, doc, doc_len)) {
+            rc = append_item_changes(db, (const char *)sqlite3_column_text(stmt, 1), since, sb);
+        } else {
+            strbuf_append(sb, ",\"value\":", 9);
+            strbuf_append(sb, doc, doc_len);
+        }
+        strbuf_append(sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(sb, "]}", 2);
+    return rc;
+}
+
+int handle_get_sync_changes(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int status = sync_negotiate_protocol(req, fd, ctx);
+    if (status != 0) return status;
+
+    long long since = 0;
+    char raw_since[32] = {0};
+    if (query_param(req->query, "since", raw_since, sizeof(raw_since))) {
+        char *end = NULL;
+        since = strtoll(raw_since, &end, 10);
+        if (!end || *end != '\0' || since < 0) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"since must be a unix timestamp\"}", ctx);
+            return 400;
+        }
+    }
+
+    /* Documents and item_changes are read from one snapshot so a concurrent write cannot split them. */
+    long long seq = 0;
+    int in_snapshot = db_read_snapshot_begin(db->db, &seq) == 0;
+    long long now = (long long)time(NULL);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    int rc = build_sync_changes(db->db, ctx->account_id, since, now, &sb);
+    if (in_snapshot) db_read_snapshot_end(db->db);
+    if (rc != 0 || sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", rc != 0 ? "{\"error\":\"database error\"}" : "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (response_guard(fd, -1, sb.len, "sync from a later since, or fetch the largest keys in pages with GET /v2/data/<key>/items", ctx) != 0) {
+        strbuf_free(&sb);
+        return 413;
+    }
+    char headers[64] = {0};
+    snprintf(headers, sizeof(headers), "%s: %d\r\n", SYNC_PROTOCOL_HEADER, SYNC_PROTOCOL_VERSION);
+    send_http_response(fd, 200, "OK", "application/json", headers, strbuf_cstr(&sb), sb.len, ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+/*
+ * POST /v1/sync/reconcile: a one-round-trip resync for a client that was offline too long to trust
+ * since. The client sends everything it holds, {"keys":[{"key","version","dirty","items":[{"id",
+ * "version","dirty"}]}]}: version is the server version it last synced (X-Fricu-Version, or an
+ * item version from an earlier reconcile; "0" or absent when it never synced) and dirty says it
+ * changed the entity locally since. Every entity lands in push (only the client changed it), pull
+ * (only the server changed or deleted it, or the client lacks it), merge (both sides changed) or
+ * the unchanged count; pull and merge entries carry the server copy, so the client needs no
+ * further reads. Collections sent with items are compared item by item against item_changes,
+ * whose tombstones tell a delete on the server apart from an item the server never saw.
+ */
+
+typedef enum {
+    RECONCILE_UNCHANGED,
+    RECONCILE_PUSH,
+    RECONCILE_PULL,
+    RECONCILE_MERGE,
+} reconcile_action_t;
+
+typedef struct {
+    strbuf_t push;
+    strbuf_t pull;
+    strbuf_t merge;
+    int pushes;
+    int pulls;
+    int merges;
+    int unchanged;
+} reconcile_t;
+
+static reconcile_action_t reconcile_decide(int server_exists, const char *server_version, const char *base, int dirty) {
+    int synced = base && base[0] != '\0' && strcmp(base, SYNC_MISSING_VERSION) != 0;
+    if (!synced) return server_exists ? RECONCILE_MERGE : RECONCILE_PUSH;
+    if (!server_exists) return dirty ? RECONCILE_MERGE : RECONCILE_PULL;
+    if (strcmp(base, server_version) == 0) return dirty ? RECONCILE_PUSH : RECONCILE_UNCHANGED;
+    return dirty ? RECONCILE_MERGE : RECONCILE_PULL;
+}
+
+/* value NULL: the server has no copy (never written or deleted). */
+static void reconcile_note(
+    reconcile_t *r, reconcile_action_t action, const char *key, const char *item_id, const char *value, size_t value_len, long long updated_at) {
+    if (action == RECONCILE_UNCHANGED) {
+        r->unchanged++;
+        return;
+    }
+    strbuf_t *sb = action == RECONCILE_PUSH ? &r->push : action == RECONCILE_PULL ? &r->pull : &r->merge;
+    int *count = action == RECONCILE_PUSH ? &r->pushes : action == RECONCILE_PULL ? &r->pulls : &r->merges;
+    char version[32] = {0};
+    if (value) {
+        content_version(value, value_len, version, sizeof(version));
+    } else {
+        snprintf(version, sizeof(version), "%s", SYNC_MISSING_VERSION);
+    }
+    if (*count > 0) strbuf_append(sb, ",", 1);
+    strbuf_append(sb, "{\"key\":", 7);
+    strbuf_append_json_string(sb, key);
+    if (item_id) {
+        strbuf_append(sb, ",\"id\":", 6);
+        strbuf_append_json_string(sb, item_id);
+    }
+    strbuf_appendf(sb, ",\"version\":\"%s\"", version);
+    if (action != RECONCILE_PUSH) {
+        if (value) {
+            strbuf_appendf(sb, ",\"updated_at\":%lld,\"value\":", updated_at);
+            strbuf_append(sb, value, value_len);
+        } else {
+            strbuf_append(sb, ",\"deleted\":true", 15);
+        }
+    }
+    strbuf_append(sb, "}", 1);
+    (*count)++;
+}
+
+/* Loads the stored document of storage_key; -1 on error, 0 when absent, 1 with *out set (free it). */
+static int reconcile_load_document(sqlite3 *db, const char *storage_key, char **out, size_t *out_len, long long *updated_at) {
+    *out = NULL;
+    *out_len = 0;
+    *updated_at = 0;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, "SELECT data_value, updated_at FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    int rc = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW) {
+        size_t len = (size_t)sqlite3_column_bytes(stmt, 0);
+        *out = (char *)malloc(len + 1);
+        if (*out) {
+            memcpy(*out, sqlite3_column_text(stmt, 0), len);
+            (*out)[len] = '\0';
+            *out_len = len;
+            *updated_at = sqlite3_column_int64(stmt, 1);
+            rc = 1;
+        } else {
+            rc = -1;
+        }
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+/* Compares the client's items of one collection with item_changes; 1 when an item id is invalid.
+ * Ids are compared as text, the way item_changes stores them. */
+static int reconcile_items(sqlite3 *db, const char *key, const char *storage_key, const char *items_json, reconcile_t *r) {
+    const char *sql =
+        "WITH client AS (SELECT CAST(json_extract(value, '$.id') AS TEXT) AS id, json_type(value, '$.id') IN ('text', 'integer') AS id_ok,"
+        " json_extract(value, '$.version') AS version, json_extract(value, '$.dirty') AS dirty FROM json_each(?1)),"
+        " server AS (SELECT item_id, item_value, deleted, updated_at FROM item_changes WHERE data_key = ?2)"
+        " SELECT 1, c.id, c.id_ok, c.version, c.dirty, s.item_value, s.updated_at FROM client c"
+        " LEFT JOIN server s ON s.item_id = c.id AND s.deleted = 0"
+        " UNION ALL SELECT 0, s.item_id, 1, NULL, 0, s.item_value, s.updated_at FROM server s"
+        " WHERE s.deleted = 0 AND s.item_id NOT IN (SELECT id FROM client WHERE id_ok)";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, items_json, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, storage_key, -1, SQLITE_TRANSIENT);
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        int client_has = sqlite3_column_int(stmt, 0);
+        const char *id = (const char *)sqlite3_column_text(stmt, 1);
+        if (!id || !sqlite3_column_int(stmt, 2) || !is_valid_item_id(id)) {
+            rc = 1;
+            break;
+        }
+        const char *value = (const char *)sqlite3_column_text(stmt, 5);
+        size_t value_len = (size_t)sqlite3_column_bytes(stmt, 5);
+        char server_version[32] = {0};
+        if (value) content_version(value, value_len, server_version, sizeof(server_version));
+        reconcile_action_t action = client_has ? reconcile_decide(value != NULL, server_version, (const char *)sqlite3_column_text(stmt, 3),
+                                                                  sqlite3_column_int(stmt, 4))
+                                               : RECONCILE_PULL;
+        reconcile_note(r, action, key, id, value, value_len, sqlite3_column_int64(stmt, 6));
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+/* Server keys the manifest does not mention: the client has none of them. */
+static int reconcile_missing_keys(sqlite3 *db, const char *account_id, const char *manifest, size_t manifest_len, reconcile_t *r) {
+    char prefix[160] = {0};
+    snprintf(prefix, sizeof(prefix), "%s::", account_id);
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT substr(data_key, length(?1) + 1) AS key, data_value, updated_at FROM kv_store"
+            " WHERE substr(data_key, 1, length(?1)) = ?1"
+            " AND substr(data_key, length(?1) + 1) NOT IN (SELECT json_extract(value, '$.key') FROM json_each(?2, '$.keys'))"
+            " ORDER BY data_key",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, prefix, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, manifest, (int)manifest_len, SQLITE_STATIC);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        if (!key || !is_valid_key(key)) continue;
+        reconcile_note(
+            r,
+            RECONCILE_PULL,
+            key,
+            NULL,
+            (const char *)sqlite3_column_text(stmt, 1),
+            (size_t)sqlite3_column_bytes(stmt, 1),
+            sqlite3_column_int64(stmt, 2));
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+/* 0 on success, -1 on a database error, or 400 with *error set for a malformed manifest. */
+static int reconcile_manifest(sqlite3 *db, const char *account_id, const char *manifest, size_t manifest_len, reconcile_t *r, const char **error) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_extract(value, '$.key'), json_type(value, '$.key'), json_extract(value, '$.version'),"
+            " json_extract(value, '$.dirty'), json_type(value, '$.items'), json_extract(value, '$.items')"
+            " FROM json_each(?1, '$.keys')",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, manifest, (int)manifest_len, SQLITE_STATIC);
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *key = (const char *)sqlite3_column_text(stmt, 0);
+        const char *key_type = (const char *)sqlite3_column_text(stmt, 1);
+        const char *items_type = (const char *)sqlite3_column_text(stmt, 4);
+        if (!key || !key_type || strcmp(key_type, "text") != 0 || !is_valid_key(key)) {
+            *error = "{\"error\":\"invalid manifest\",\"detail\":\"every entry needs a known key\"}";
+            rc = 400;
+            break;
+        }
+        if (items_type && (strcmp(items_type, "array") != 0 || !api_key_is_collection(key))) {
+            *error = "{\"error\":\"invalid manifest\",\"detail\":\"items must be an array, and only for collection keys\"}";
+            rc = 400;
+            break;
+        }
+        char storage_key[256] = {0};
+        if (build_storage_key(account_id, key, storage_key, sizeof(storage_key)) != 0) {
+            rc = -1;
+            break;
+        }
+        char *doc = NULL;
+        size_t doc_len = 0;
+        long long updated_at = 0;
+        int exists = reconcile_load_document(db, storage_key, &doc, &doc_len, &updated_at);
+        if (exists < 0) {
+            rc = -1;
+            break;
+        }
+        if (items_type && (!exists || document_has_item_ids(db, doc, doc_len))) {
+            int items_rc = reconcile_items(db, key, storage_key, (const char *)sqlite3_column_text(stmt, 5), r);
+            if (items_rc == 1) {
+                *error = "{\"error\":\"invalid manifest\",\"detail\":\"every item needs a valid string id\"}";
+                rc = 400;
+            } else {
+                rc = items_rc;
+            }
+        } else {
+            char server_version[32] = {0};
+            if (exists) content_version(doc, doc_len, server_version, sizeof(server_version));
+            reconcile_action_t action = reconcile_decide(exists, server_version, (const char *)sqlite3_column_text(stmt, 2), sqlite3_column_int(stmt, 3));
+            reconcile_note(r, action, key, NULL, exists ? doc : NULL, doc_len, updated_at);
+        }
+        free(doc);
+    }
+    sqlite3_finalize(stmt);
+    if (rc == 0) rc = reconcile_missing_keys(db, account_id, manifest, manifest_len, r);
+    return rc;
+}
+
+static int reconcile_manifest_shape(sqlite3 *db, const http_request_t *req) {
+    sqlite3_stmt *stmt = NULL;
+    int ok = 0;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT json_valid(?1) AND json_type(?1, '$.keys') = 'array' AND"
+            " (SELECT count(*) = count(DISTINCT json_extract(value, '$.key')) FROM json_each(?1, '$.keys'))",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, req->body, (int)req->body_len, SQLITE_STATIC);
+        ok = sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0);
+    }
+    sqlite3_finalize(stmt);
+    return ok;
+}
+
+int handle_post_sync_reconcile(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int status = sync_negotiate_protocol(req, fd, ctx);
+    if (status != 0) return status;
+    if (req->body_len == 0 || !reconcile_manifest_shape(db->db, req)) {
+        send_response_with_log_context(
+            fd, 400, "Bad Request", "{\"error\":\"invalid manifest\",\"detail\":\"expected {\\\"keys\\\":[...]} with each key once\"}", ctx);
+        return 400;
+    }
+
+    reconcile_t r;
+    memset(&r, 0, sizeof(r));
+    strbuf_init(&r.push);
+    strbuf_init(&r.pull);
+    strbuf_init(&r.merge);
+    const char *error = NULL;
+    long long seq = 0;
+    int in_snapshot = db_read_snapshot_begin(db->db, &seq) == 0;
+    long long now = (long long)time(NULL);
+    int rc = reconcile_manifest(db->db, ctx->account_id, req->body, req->body_len, &r, &error);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    if (rc == 0) {
+        strbuf_appendf(&sb, "{\"protocol\":%d,\"server_time\":%lld,\"next_since\":%lld,\"push\":[", SYNC_PROTOCOL_VERSION, now, now - 1);
+        strbuf_append(&sb, strbuf_cstr(&r.push), r.push.len);
+        strbuf_append(&sb, "],\"pull\":[", 10);
+        strbuf_append(&sb, strbuf_cstr(&r.pull), r.pull.len);
+        strbuf_append(&sb, "],\"merge\":[", 11);
+        strbuf_append(&sb, strbuf_cstr(&r.merge), r.merge.len);
+        strbuf_appendf(&sb, "],\"unchanged\":%d,\"locked_keys\":", r.unchanged);
+        locks_append_keys(db->db, ctx->account_id, &sb);
+        strbuf_append(&sb, "}", 1);
+    }
+    if (in_snapshot) db_read_snapshot_end(db->db);
+    int failed = r.push.failed || r.pull.failed || r.merge.failed || sb.failed;
+    strbuf_free(&r.push);
+    strbuf_free(&r.pull);
+    strbuf_free(&r.merge);
+
+    if (rc == 400) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+    if (rc != 0 || failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", rc != 0 ? "{\"error\":\"database error\"}" : "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    if (response_guard(fd, -1, sb.len, "reconcile fewer keys per request, or fetch the largest keys in pages with GET /v2/data/<key>/items", ctx) != 0) {
+        strbuf_free(&sb);
+        return 413;
+    }
+    log_info(
+        "SYNC RECONCILE push=%d pull=%d merge=%d unchanged=%d account=%s logid=%s", r.pushes, r.pulls, r.merges, r.unchanged, ctx->account_id,
+        ctx->log_id);
+    char headers[64] = {0};
+    snprintf(headers, sizeof(headers), "%s: %d\r\n", SYNC_PROTOCOL_HEADER, SYNC_PROTOCOL_VERSION);
+    send_http_response(fd, 200, "OK", "application/json", headers, strbuf_cstr(&sb), sb.len, ctx);
+    strbuf_free(&sb);
+    return 200;
+}
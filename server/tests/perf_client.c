@@ -9,16 +9,63 @@
 #include <string.h>
 #include <sys/socket.h>
 #include <sys/time.h>
+#include <time.h>
 #include <unistd.h>
 
+#define MAX_TARGETS 16
+#define MAX_RESPONSE_BYTES (1024 * 1024)
+
 typedef struct {
-    const char *host;
+    char host[64];
     int port;
+    int weight;
+    atomic_int success;
+    atomic_int failed;
+    atomic_int samples_len;
+    double *samples_ms;
+} target_t;
+
+typedef struct {
+    target_t targets[MAX_TARGETS];
+    int count;
+    int total_weight;
+    bool weighted;
+    atomic_uint next;
+} target_set_t;
+
+#define TIMING_STAGE_COUNT 5
+
+static const char *TIMING_STAGES[TIMING_STAGE_COUNT] = {"queue", "checkout", "db", "serialize", "total"};
+
+typedef struct {
+    double client_ms;
+    double stages_ms[TIMING_STAGE_COUNT];
+} timing_sample_t;
+
+typedef struct {
+    timing_sample_t *samples;
+    atomic_int len;
+} timing_log_t;
+
+typedef struct {
+    target_set_t *set;
     int requests;
     atomic_int *success;
     atomic_int *failed;
+    timing_log_t *timing;
 } worker_args_t;
 
+typedef struct {
+    int status;
+    char *body;
+} response_t;
+
+static double now_ms(void) {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return ts.tv_sec * 1000.0 + ts.tv_nsec / 1000000.0;
+}
+
 static int send_all(int fd, const char *buf, size_t len) {
     size_t sent = 0;
     while (sent < len) {
@@ -29,15 +76,17 @@ static int send_all(int fd, const char *buf, size_t len) {
     return 0;
 }
 
-static int request_once(const char *host, int port, const char *req) {
+/* Sends one Connection: close request and returns the status code, or -1 on a transport error. */
+static int request_capped(const target_t *t, const char *req, response_t *out, size_t max_bytes) {
+    if (out) memset(out, 0, sizeof(*out));
     int fd = socket(AF_INET, SOCK_STREAM, 0);
     if (fd < 0) return -1;
 
     struct sockaddr_in addr;
     memset(&addr, 0, sizeof(addr));
     addr.sin_family = AF_INET;
-    addr.sin_port = htons((uint16_t)port);
-    if (inet_pton(AF_INET, host, &addr.sin_addr) <= 0) {
+    addr.sin_port = htons((uint16_t)t->port);
+    if (inet_pton(AF_INET, t->host, &addr.sin_addr) <= 0) {
         close(fd);
         return -1;
     }
@@ -51,42 +100,586 @@ static int request_once(const char *host, int port, const char *req) {
         return -1;
     }
 
-    char buf[1024];
-    ssize_t n = recv(fd, buf, sizeof(buf) - 1, 0);
+    size_t cap = out ? max_bytes : 1024;
+    char *buf = malloc(cap);
+    if (!buf) {
+        close(fd);
+        return -1;
+    }
+    size_t len = 0;
+    for (;;) {
+        ssize_t n = recv(fd, buf + len, cap - 1 - len, 0);
+        if (n <= 0) break;
+        len += (size_t)n;
+        /* Load requests only need the status line. */
+        if (!out || len == cap - 1) break;
+    }
     close(fd);
-    if (n <= 0) return -1;
-    buf[n] = '\0';
-    return (strstr(buf, "HTTP/1.1 200") == buf || strstr(buf, "HTTP/1.1 204") == buf) ? 0 : -1;
+    buf[len] = '\0';
+    int status = -1;
+    if (len > 0 && sscanf(buf, "HTTP/1.1 %d", &status) != 1) status = -1;
+    if (out) {
+        out->status = status;
+        out->body = buf;
+    } else {
+        free(buf);
+    }
+    return status;
+}
+
+static int request_full(const target_t *t, const char *req, response_t *out) {
+    return request_capped(t, req, out, MAX_RESPONSE_BYTES);
+}
+
+static int request_once(const target_t *t, const char *req) {
+    int status = request_full(t, req, NULL);
+    return status == 200 || status == 204 ? 0 : -1;
+}
+
+static target_t *pick_target(target_set_t *set) {
+    unsigned n = atomic_fetch_add(&set->next, 1);
+    if (!set->weighted) return &set->targets[n % (unsigned)set->count];
+    int slot = (int)(n % (unsigned)set->total_weight);
+    for (int i = 0; i < set->count; i++) {
+        if (slot < set->targets[i].weight) return &set->targets[i];
+        slot -= set->targets[i].weight;
+    }
+    return &set->targets[0];
+}
+
+/* Parses "Server-Timing: name;dur=1.2, other;dur=0.3;desc=..." into the known stages. */
+static bool parse_server_timing(const char *response, timing_sample_t *out) {
+    const char *header = strstr(response, "\r\nServer-Timing: ");
+    const char *end = strstr(response, "\r\n\r\n");
+    if (!header || !end || header > end) return false;
+    const char *line_end = strstr(header + 2, "\r\n");
+    int found = 0;
+    for (int i = 0; i < TIMING_STAGE_COUNT; i++) {
+        char needle[32];
+        snprintf(needle, sizeof(needle), "%s;dur=", TIMING_STAGES[i]);
+        const char *p = strstr(header, needle);
+        if (!p || p > line_end) continue;
+        out->stages_ms[i] = strtod(p + strlen(needle), NULL);
+        found++;
+    }
+    return found == TIMING_STAGE_COUNT;
 }
 
 static void *worker(void *arg) {
     worker_args_t *w = (worker_args_t *)arg;
-    const char *get_req =
+    const char *get_req = w->timing ?
+        "GET /v1/data/activities HTTP/1.1\r\n"
+        "Host: 127.0.0.1\r\n"
+        "X-Account-Id: perf\r\n"
+        "X-Fricu-Debug-Timing: 1\r\n"
+        "Connection: close\r\n\r\n" :
         "GET /v1/data/activities HTTP/1.1\r\n"
         "Host: 127.0.0.1\r\n"
         "X-Account-Id: perf\r\n"
         "Connection: close\r\n\r\n";
     for (int i = 0; i < w->requests; i++) {
-        if (request_once(w->host, w->port, get_req) == 0) {
-            atomic_fetch_add(w->success, 1);
+        target_t *t = pick_target(w->set);
+        double start = now_ms();
+        int ok = 0;
+        if (w->timing) {
+            response_t resp;
+            int status = request_capped(t, get_req, &resp, 16384);
+            double client_ms = now_ms() - start;
+            timing_sample_t sample = {.client_ms = client_ms};
+            ok = status == 200;
+            if (ok && parse_server_timing(resp.body, &sample)) {
+                w->timing->samples[atomic_fetch_add(&w->timing->len, 1)] = sample;
+            }
+            free(resp.body);
         } else {
-            atomic_fetch_add(w->failed, 1);
+            ok = request_once(t, get_req) == 0;
         }
+        double elapsed = now_ms() - start;
+        int slot = atomic_fetch_add(&t->samples_len, 1);
+        t->samples_ms[slot] = elapsed;
+        atomic_fetch_add(ok ? &t->success : &t->failed, 1);
+        atomic_fetch_add(ok ? w->success : w->failed, 1);
     }
     return NULL;
 }
 
+static int compare_double(const void *a, const void *b) {
+    double x = *(const double *)a;
+    double y = *(const double *)b;
+    return (x > y) - (x < y);
+}
+
+static double percentile(const double *sorted, int n, double p) {
+    if (n <= 0) return 0.0;
+    int idx = (int)(p * (n - 1) + 0.5);
+    return sorted[idx];
+}
+
+static int compare_sample_client_ms(const void *a, const void *b) {
+    return compare_double(&((const timing_sample_t *)a)->client_ms, &((const timing_sample_t *)b)->client_ms);
+}
+
+/* Prints per-stage percentiles, then the average stage split of requests at or above the client-side p99. */
+static void report_server_timing(timing_log_t *log) {
+    int n = atomic_load(&log->len);
+    printf("server_timing_samples=%d\n", n);
+    if (n == 0) return;
+    double *values = calloc((size_t)n, sizeof(double));
+    if (!values) return;
+    for (int s = 0; s < TIMING_STAGE_COUNT; s++) {
+        double sum = 0.0;
+        for (int i = 0; i < n; i++) {
+            values[i] = log->samples[i].stages_ms[s];
+            sum += values[i];
+        }
+        qsort(values, (size_t)n, sizeof(double), compare_double);
+        printf(
+            "stage[%s] avg_ms=%.3f p50_ms=%.3f p95_ms=%.3f p99_ms=%.3f max_ms=%.3f\n",
+            TIMING_STAGES[s],
+            sum / n,
+            percentile(values, n, 0.50),
+            percentile(values, n, 0.95),
+            percentile(values, n, 0.99),
+            values[n - 1]);
+    }
+    free(values);
+
+    qsort(log->samples, (size_t)n, sizeof(timing_sample_t), compare_sample_client_ms);
+    int first = (int)(0.99 * (n - 1) + 0.5);
+    int tail = n - first;
+    double split[TIMING_STAGE_COUNT] = {0};
+    double client = 0.0;
+    for (int i = first; i < n; i++) {
+        client += log->samples[i].client_ms;
+        for (int s = 0; s < TIMING_STAGE_COUNT; s++) split[s] += log->samples[i].stages_ms[s];
+    }
+    /* Whatever the server did not account for was spent on the wire or in the client. */
+    double network = (client - split[TIMING_STAGE_COUNT - 1]) / tail;
+    int dominant = -1;
+    double dominant_ms = network;
+    for (int s = 0; s < TIMING_STAGE_COUNT - 1; s++) {
+        if (split[s] / tail > dominant_ms) {
+            dominant = s;
+            dominant_ms = split[s] / tail;
+        }
+    }
+    printf(
+        "p99_breakdown requests=%d client_ms=%.3f queue_ms=%.3f checkout_ms=%.3f db_ms=%.3f serialize_ms=%.3f network_ms=%.3f dominant=%s\n",
+        tail,
+        client / tail,
+        split[0] / tail,
+        split[1] / tail,
+        split[2] / tail,
+        split[3] / tail,
+        network > 0 ? network : 0.0,
+        dominant >= 0 ? TIMING_STAGES[dominant] : "network");
+}
+
+/* Accepts "host:port", "http://host:port" and an optional "@weight" suffix. */
+static int parse_target(const char *spec, target_t *out) {
+    memset(out, 0, sizeof(*out));
+    out->weight = 1;
+    if (strncmp(spec, "http://", 7) == 0) spec += 7;
+    char host[64] = {0};
+    int port = 0;
+    int weight = 1;
+    int fields = sscanf(spec, "%63[^:]:%d@%d", host, &port, &weight);
+    if (fields < 2 || port <= 0 || port > 65535 || weight <= 0) return -1;
+    snprintf(out->host, sizeof(out->host), "%s", host);
+    out->port = port;
+    out->weight = weight;
+    return 0;
+}
+
+/* Writes a unique marker through whichever instance accepts writes, then polls every target until it reads it back. */
+static int run_consistency_checks(target_set_t *set, int checks, int timeout_ms) {
+    int violations = 0;
+    int stale_first_reads = 0;
+    int writes_failed = 0;
+    double max_lag_ms = 0.0;
+    for (int c = 0; c < checks; c++) {
+        char marker[64];
+        snprintf(marker, sizeof(marker), "perf-%ld-%d", (long)getpid(), c);
+        char body[128];
+        int body_len = snprintf(body, sizeof(body), "[{\"marker\":\"%s\"}]", marker);
+        char put_req[512];
+        snprintf(
+            put_req,
+            sizeof(put_req),
+            "PUT /v1/data/exported_file_perf_consistency HTTP/1.1\r\n"
+            "Host: 127.0.0.1\r\n"
+            "X-Account-Id: perf\r\n"
+            "Content-Type: application/json\r\n"
+            "Content-Length: %d\r\n"
+            "Connection: close\r\n\r\n%s",
+            body_len,
+            body);
+        int writer = -1;
+        for (int i = 0; i < set->count && writer < 0; i++) {
+            if (request_full(&set->targets[(c + i) % set->count], put_req, NULL) == 204) writer = (c + i) % set->count;
+        }
+        if (writer < 0) {
+            writes_failed++;
+            continue;
+        }
+        double acked = now_ms();
+        const char *get_req =
+            "GET /v1/data/exported_file_perf_consistency HTTP/1.1\r\n"
+            "Host: 127.0.0.1\r\n"
+            "X-Account-Id: perf\r\n"
+            "Connection: close\r\n\r\n";
+        for (int i = 0; i < set->count; i++) {
+            int attempt = 0;
+            bool seen = false;
+            for (;;) {
+                response_t resp;
+                request_full(&set->targets[i], get_req, &resp);
+                seen = resp.status == 200 && resp.body && strstr(resp.body, marker) != NULL;
+                free(resp.body);
+                if (seen || now_ms() - acked > timeout_ms) break;
+                attempt++;
+                struct timespec pause = {.tv_sec = 0, .tv_nsec = 5000000};
+                nanosleep(&pause, NULL);
+            }
+            double lag = now_ms() - acked;
+            if (attempt > 0) stale_first_reads++;
+            if (!seen) {
+                violations++;
+                fprintf(stderr, "consistency violation: write via %s:%d not visible on %s:%d after %d ms\n",
+                    set->targets[writer].host, set->targets[writer].port, set->targets[i].host, set->targets[i].port, timeout_ms);
+            } else if (attempt > 0 && lag > max_lag_ms) {
+                max_lag_ms = lag;
+            }
+        }
+    }
+    printf("consistency_checks=%d\n", checks);
+    printf("consistency_write_failures=%d\n", writes_failed);
+    printf("consistency_stale_first_reads=%d\n", stale_first_reads);
+    printf("consistency_violations=%d\n", violations);
+    printf("consistency_max_lag_ms=%.1f\n", max_lag_ms);
+    return violations + writes_failed;
+}
+
+#define VERIFY_ITEM_POOL_MAX 1000
+#define VERIFY_MAX_CONFLICT_RETRIES 64
+#define VERIFY_COUNTER_KEY "exported_file_perf_verify_counter"
+#define VERIFY_ITEMS_KEY "exported_file_perf_verify_items"
+
+enum { OUTCOME_NONE, OUTCOME_ACKED, OUTCOME_UNKNOWN, OUTCOME_GAVE_UP };
+
+typedef struct {
+    target_set_t *set;
+    const char *account;
+    int worker_id;
+    int ops;
+    unsigned seed;
+    unsigned char *appends;
+    atomic_uchar *items_acked;
+    atomic_uchar *items_attempted;
+    int item_pool;
+    atomic_int *conflicts;
+    atomic_int *unexpected;
+} verify_args_t;
+
+static const char *response_body(const response_t *resp) {
+    const char *sep = resp->body ? strstr(resp->body, "\r\n\r\n") : NULL;
+    return sep ? sep + 4 : NULL;
+}
+
+static bool response_header(const response_t *resp, const char *name, char *out, size_t out_len) {
+    char needle[64];
+    snprintf(needle, sizeof(needle), "\r\n%s: ", name);
+    const char *p = resp->body ? strstr(resp->body, needle) : NULL;
+    const char *end = resp->body ? strstr(resp->body, "\r\n\r\n") : NULL;
+    if (!p || !end || p > end) return false;
+    p += strlen(needle);
+    size_t n = strcspn(p, "\r");
+    if (n >= out_len) return false;
+    memcpy(out, p, n);
+    out[n] = '\0';
+    return true;
+}
+
+/* Writes go to the picked instance first; followers answering 503 hand the request on to the next one. */
+static int send_write(target_set_t *set, const char *req) {
+    int first = (int)(pick_target(set) - set->targets);
+    int status = -1;
+    for (int i = 0; i < set->count; i++) {
+        status = request_full(&set->targets[(first + i) % set->count], req, NULL);
+        if (status != 503) break;
+    }
+    return status;
+}
+
+/* Appends {"w":worker,"i":seq} to a shared array with read-then-conditional-PUT, retrying on 409. */
+static int verify_append(verify_args_t *a, int seq) {
+    char get_req[256];
+    snprintf(get_req, sizeof(get_req),
+        "GET /v1/data/" VERIFY_COUNTER_KEY " HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Account-Id: %s\r\nConnection: close\r\n\r\n", a->account);
+    for (int attempt = 0; attempt < VERIFY_MAX_CONFLICT_RETRIES; attempt++) {
+        response_t current;
+        char version[64] = {0};
+        const char *doc = NULL;
+        if (request_full(pick_target(a->set), get_req, &current) == 200) doc = response_body(&current);
+        if (!doc || doc[0] != '[' || !response_header(&current, "X-Fricu-Version", version, sizeof(version))) {
+            free(current.body);
+            continue;
+        }
+        size_t doc_len = strlen(doc);
+        while (doc_len > 0 && doc[doc_len - 1] != ']') doc_len--;
+        char entry[64];
+        snprintf(entry, sizeof(entry), "{\"w\":%d,\"i\":%d}", a->worker_id, seq);
+        /* "[...]" becomes "[...,entry]" and "[]" becomes "[entry]". */
+        size_t prefix_len = doc_len > 0 ? doc_len - 1 : 0;
+        size_t body_len = prefix_len + (doc_len > 2 ? 1 : 0) + strlen(entry) + 1;
+        size_t req_cap = body_len + 512;
+        char *req = malloc(req_cap);
+        if (!req || doc_len < 2) {
+            free(req);
+            free(current.body);
+            return OUTCOME_GAVE_UP;
+        }
+        snprintf(req, req_cap,
+            "PUT /v1/data/" VERIFY_COUNTER_KEY " HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Account-Id: %s\r\n"
+            "X-Fricu-Base-Version: %s\r\nContent-Type: application/json\r\nContent-Length: %zu\r\nConnection: close\r\n\r\n"
+            "%.*s%s%s]",
+            a->account, version, body_len, (int)prefix_len, doc, doc_len > 2 ? "," : "", entry);
+        free(current.body);
+
+        int status = send_write(a->set, req);
+        free(req);
+        if (status == 204) return OUTCOME_ACKED;
+        if (status == 409) {
+            atomic_fetch_add(a->conflicts, 1);
+            continue;
+        }
+        if (status < 0) return OUTCOME_UNKNOWN;
+        atomic_fetch_add(a->unexpected, 1);
+        return OUTCOME_GAVE_UP;
+    }
+    return OUTCOME_GAVE_UP;
+}
+
+/* Upserts a randomly chosen item id; replaying the same PUT after a transport error must not create a second copy. */
+static void verify_upsert_item(verify_args_t *a) {
+    int id = (int)(rand_r(&a->seed) % (unsigned)a->item_pool);
+    char body[64];
+    int body_len = snprintf(body, sizeof(body), "{\"id\":\"vid-%d\",\"w\":%d}", id, a->worker_id);
+    char req[512];
+    snprintf(req, sizeof(req),
+        "PUT /v2/data/" VERIFY_ITEMS_KEY "/items/vid-%d HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Account-Id: %s\r\n"
+        "Content-Type: application/json\r\nContent-Length: %d\r\nConnection: close\r\n\r\n%s",
+        id, a->account, body_len, body);
+    atomic_store(&a->items_attempted[id], 1);
+    for (int attempt = 0; attempt < 3; attempt++) {
+        int status = send_write(a->set, req);
+        if (status == 200 || status == 201) {
+            atomic_store(&a->items_acked[id], 1);
+            return;
+        }
+        if (status >= 0) {
+            atomic_fetch_add(a->unexpected, 1);
+            return;
+        }
+    }
+}
+
+static void *verify_worker(void *arg) {
+    verify_args_t *a = (verify_args_t *)arg;
+    for (int i = 0; i < a->ops; i++) {
+        if (rand_r(&a->seed) % 2 == 0) {
+            a->appends[i] = (unsigned char)verify_append(a, i);
+        } else {
+            verify_upsert_item(a);
+        }
+    }
+    return NULL;
+}
+
+/* Runs randomized concurrent writes with known expected outcomes, then audits the stored state. */
+static int run_verify(target_set_t *set, int total, int concurrency, unsigned seed) {
+    char account[64];
+    snprintf(account, sizeof(account), "perf-verify-%ld-%u", (long)getpid(), seed);
+    int ops_per_worker = (total + concurrency - 1) / concurrency;
+    int item_pool = total / 4 < 8 ? 8 : (total / 4 > VERIFY_ITEM_POOL_MAX ? VERIFY_ITEM_POOL_MAX : total / 4);
+
+    pthread_t *threads = calloc((size_t)concurrency, sizeof(pthread_t));
+    verify_args_t *args = calloc((size_t)concurrency, sizeof(verify_args_t));
+    unsigned char *appends = calloc((size_t)concurrency * (size_t)ops_per_worker, 1);
+    int *seen = calloc((size_t)concurrency * (size_t)ops_per_worker, sizeof(int));
+    static atomic_uchar items_acked[VERIFY_ITEM_POOL_MAX];
+    static atomic_uchar items_attempted[VERIFY_ITEM_POOL_MAX];
+    int item_seen[VERIFY_ITEM_POOL_MAX] = {0};
+    if (!threads || !args || !appends || !seen) return 1;
+    atomic_int conflicts = 0;
+    atomic_int unexpected = 0;
+
+    for (int w = 0; w < concurrency; w++) {
+        args[w] = (verify_args_t){
+            .set = set,
+            .account = account,
+            .worker_id = w,
+            .ops = ops_per_worker,
+            .seed = seed + (unsigned)w * 7919u,
+            .appends = appends + (size_t)w * (size_t)ops_per_worker,
+            .items_acked = items_acked,
+            .items_attempted = items_attempted,
+            .item_pool = item_pool,
+            .conflicts = &conflicts,
+            .unexpected = &unexpected,
+        };
+        pthread_create(&threads[w], NULL, verify_worker, &args[w]);
+    }
+    for (int w = 0; w < concurrency; w++) pthread_join(threads[w], NULL);
+
+    char req[256];
+    response_t resp;
+    int audit_failed = 0;
+    snprintf(req, sizeof(req),
+        "GET /v1/data/" VERIFY_COUNTER_KEY " HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Account-Id: %s\r\nConnection: close\r\n\r\n", account);
+    const char *doc = request_full(&set->targets[0], req, &resp) == 200 ? response_body(&resp) : NULL;
+    if (!doc) audit_failed = 1;
+    for (const char *p = doc ? strstr(doc, "{\"w\":") : NULL; p; p = strstr(p + 1, "{\"w\":")) {
+        int w = -1;
+        int i = -1;
+        if (sscanf(p, "{\"w\":%d,\"i\":%d}", &w, &i) == 2 && w >= 0 && w < concurrency && i >= 0 && i < ops_per_worker) {
+            seen[w * ops_per_worker + i]++;
+        } else {
+            audit_failed = 1;
+        }
+    }
+    free(resp.body);
+
+    snprintf(req, sizeof(req),
+        "GET /v2/data/" VERIFY_ITEMS_KEY "/items?limit=1000 HTTP/1.1\r\nHost: 127.0.0.1\r\nX-Account-Id: %s\r\nConnection: close\r\n\r\n", account);
+    doc = request_full(&set->targets[0], req, &resp) == 200 ? response_body(&resp) : NULL;
+    if (!doc) audit_failed = 1;
+    for (const char *p = doc ? strstr(doc, "\"id\":\"vid-") : NULL; p; p = strstr(p + 1, "\"id\":\"vid-")) {
+        int id = -1;
+        if (sscanf(p, "\"id\":\"vid-%d\"", &id) == 1 && id >= 0 && id < item_pool) {
+            item_seen[id]++;
+        } else {
+            audit_failed = 1;
+        }
+    }
+    free(resp.body);
+
+    int acked = 0, unknown = 0, gave_up = 0, lost = 0, duplicates = 0, phantoms = 0;
+    for (int k = 0; k < concurrency * ops_per_worker; k++) {
+        if (appends[k] == OUTCOME_ACKED) acked++;
+        if (appends[k] == OUTCOME_UNKNOWN) unknown++;
+        if (appends[k] == OUTCOME_GAVE_UP) gave_up++;
+        if (appends[k] == OUTCOME_ACKED && seen[k] == 0) lost++;
+        if (seen[k] > 1) duplicates++;
+        if (seen[k] > 0 && (appends[k] == OUTCOME_NONE || appends[k] == OUTCOME_GAVE_UP)) phantoms++;
+    }
+    int items_acked_count = 0, missing_items = 0, duplicate_items = 0, phantom_items = 0;
+    for (int id = 0; id < item_pool; id++) {
+        if (atomic_load(&items_acked[id])) items_acked_count++;
+        if (atomic_load(&items_acked[id]) && item_seen[id] == 0) missing_items++;
+        if (item_seen[id] > 1) duplicate_items++;
+        if (item_seen[id] > 0 && !atomic_load(&items_attempted[id])) phantom_items++;
+    }
+
+    int violations = lost + duplicates + phantoms + missing_items + duplicate_items + phantom_items + audit_failed;
+    printf("verify_seed=%u\n", seed);
+    printf("verify_account=%s\n", account);
+    printf("verify_operations=%d\n", concurrency * ops_per_worker);
+    printf("verify_appends_acked=%d\n", acked);
+    printf("verify_appends_unknown=%d\n", unknown);
+    printf("verify_appends_gave_up=%d\n", gave_up);
+    printf("verify_conflicts_retried=%d\n", atomic_load(&conflicts));
+    printf("verify_items_acked=%d\n", items_acked_count);
+    printf("verify_unexpected_status=%d\n", atomic_load(&unexpected));
+    printf("verify_lost_updates=%d\n", lost);
+    printf("verify_duplicates=%d\n", duplicates);
+    printf("verify_phantoms=%d\n", phantoms);
+    printf("verify_missing_items=%d\n", missing_items);
+    printf("verify_duplicate_items=%d\n", duplicate_items);
+    printf("verify_phantom_items=%d\n", phantom_items);
+    printf("verify_result=%s\n", violations == 0 ? "ok" : "failed");
+
+    free(threads);
+    free(args);
+    free(appends);
+    free(seen);
+    return violations;
+}
+
+static void usage(void) {
+    fprintf(stderr,
+        "usage: perf-client [total] [concurrency] [host] [port]\n"
+        "       [--target host:port[@weight] ...] [--balance round-robin|weighted]\n"
+        "       [--consistency-checks N] [--consistency-timeout-ms MS]\n"
+        "       [--verify [--seed N]] [--server-timing]\n");
+}
+
 int main(int argc, char **argv) {
-    int total = argc > 1 ? atoi(argv[1]) : 50000;
-    int concurrency = argc > 2 ? atoi(argv[2]) : 512;
-    const char *host = argc > 3 ? argv[3] : "127.0.0.1";
-    int port = argc > 4 ? atoi(argv[4]) : 8080;
+    const char *positional[4] = {NULL, NULL, NULL, NULL};
+    int positional_count = 0;
+    static target_set_t set;
+    int consistency_checks = 0;
+    int consistency_timeout_ms = 1000;
+    bool verify = false;
+    bool server_timing = false;
+    unsigned seed = (unsigned)time(NULL);
+    for (int i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "--target") == 0 && i + 1 < argc) {
+            if (set.count == MAX_TARGETS || parse_target(argv[++i], &set.targets[set.count]) != 0) {
+                fprintf(stderr, "invalid target: %s\n", argv[i]);
+                return 1;
+            }
+            set.count++;
+        } else if (strcmp(argv[i], "--balance") == 0 && i + 1 < argc) {
+            i++;
+            if (strcmp(argv[i], "weighted") == 0) {
+                set.weighted = true;
+            } else if (strcmp(argv[i], "round-robin") != 0) {
+                usage();
+                return 1;
+            }
+        } else if (strcmp(argv[i], "--consistency-checks") == 0 && i + 1 < argc) {
+            consistency_checks = atoi(argv[++i]);
+        } else if (strcmp(argv[i], "--consistency-timeout-ms") == 0 && i + 1 < argc) {
+            consistency_timeout_ms = atoi(argv[++i]);
+        } else if (strcmp(argv[i], "--server-timing") == 0) {
+            server_timing = true;
+        } else if (strcmp(argv[i], "--verify") == 0) {
+            verify = true;
+        } else if (strcmp(argv[i], "--seed") == 0 && i + 1 < argc) {
+            seed = (unsigned)strtoul(argv[++i], NULL, 10);
+        } else if (argv[i][0] != '-' && positional_count < 4) {
+            positional[positional_count++] = argv[i];
+        } else {
+            usage();
+            return 1;
+        }
+    }
+
+    int total = positional[0] ? atoi(positional[0]) : (verify ? 400 : 50000);
+    int concurrency = positional[1] ? atoi(positional[1]) : (verify ? 16 : 512);
+    if (set.count == 0) {
+        snprintf(set.targets[0].host, sizeof(set.targets[0].host), "%s", positional[2] ? positional[2] : "127.0.0.1");
+        set.targets[0].port = positional[3] ? atoi(positional[3]) : 8080;
+        set.targets[0].weight = 1;
+        set.count = 1;
+    }
 
-    if (total <= 0 || concurrency <= 0) {
+    if (total <= 0 || concurrency <= 0 || consistency_checks < 0 || consistency_timeout_ms <= 0) {
         fprintf(stderr, "invalid args\n");
         return 1;
     }
+    if (verify) {
+        for (int i = 0; i < set.count; i++) set.total_weight += set.targets[i].weight;
+        return run_verify(&set, total, concurrency, seed) == 0 ? 0 : 1;
+    }
+    for (int i = 0; i < set.count; i++) {
+        set.total_weight += set.targets[i].weight;
+        set.targets[i].samples_ms = calloc((size_t)total, sizeof(double));
+        if (!set.targets[i].samples_ms) return 1;
+    }
 
+    /* Followers in lease mode reject writes, so warm up through the first instance that accepts. */
     const char *put_req =
         "PUT /v1/data/activities HTTP/1.1\r\n"
         "Host: 127.0.0.1\r\n"
@@ -95,7 +688,9 @@ int main(int argc, char **argv) {
         "Content-Length: 21\r\n"
         "Connection: close\r\n\r\n"
         "[{\"sport\":\"cycling\"}]";
-    if (request_once(host, port, put_req) != 0) {
+    bool warmed = false;
+    for (int i = 0; i < set.count && !warmed; i++) warmed = request_once(&set.targets[i], put_req) == 0;
+    if (!warmed) {
         fprintf(stderr, "warmup put failed\n");
         return 1;
     }
@@ -106,6 +701,11 @@ int main(int argc, char **argv) {
 
     atomic_int success = 0;
     atomic_int failed = 0;
+    timing_log_t timing = {.samples = NULL, .len = 0};
+    if (server_timing) {
+        timing.samples = calloc((size_t)total, sizeof(timing_sample_t));
+        if (!timing.samples) return 1;
+    }
 
     int base = total / concurrency;
     int rem = total % concurrency;
@@ -114,11 +714,11 @@ int main(int argc, char **argv) {
     gettimeofday(&start, NULL);
 
     for (int i = 0; i < concurrency; i++) {
-        args[i].host = host;
-        args[i].port = port;
+        args[i].set = &set;
         args[i].requests = base + (i < rem ? 1 : 0);
         args[i].success = &success;
         args[i].failed = &failed;
+        args[i].timing = server_timing ? &timing : NULL;
         pthread_create(&threads[i], NULL, worker, &args[i]);
     }
 
@@ -135,7 +735,34 @@ int main(int argc, char **argv) {
     printf("elapsed_ms=%.0f\n", elapsed * 1000);
     printf("rps=%.2f\n", elapsed > 0 ? (double)s / elapsed : 0.0);
 
+    if (set.count > 1) {
+        for (int i = 0; i < set.count; i++) {
+            target_t *t = &set.targets[i];
+            int n = atomic_load(&t->samples_len);
+            qsort(t->samples_ms, (size_t)n, sizeof(double), compare_double);
+            printf(
+                "target[%d]=%s:%d weight=%d requests=%d success=%d failed=%d p50_ms=%.2f p95_ms=%.2f p99_ms=%.2f max_ms=%.2f\n",
+                i,
+                t->host,
+                t->port,
+                t->weight,
+                n,
+                atomic_load(&t->success),
+                atomic_load(&t->failed),
+                percentile(t->samples_ms, n, 0.50),
+                percentile(t->samples_ms, n, 0.95),
+                percentile(t->samples_ms, n, 0.99),
+                n > 0 ? t->samples_ms[n - 1] : 0.0);
+        }
+    }
+
+    if (server_timing) report_server_timing(&timing);
+
+    int consistency_failures = consistency_checks > 0 ? run_consistency_checks(&set, consistency_checks, consistency_timeout_ms) : 0;
+
+    for (int i = 0; i < set.count; i++) free(set.targets[i].samples_ms);
+    free(timing.samples);
     free(threads);
     free(args);
-    return f == 0 ? 0 : 1;
+    return f == 0 && consistency_failures == 0 ? 0 : 1;
 }
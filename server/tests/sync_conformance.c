@@ -0,0 +1,225 @@
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <strings.h>
+#include <sys/socket.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Scripted client for docs/sync-protocol.md. Run it against a live server:
+ *   ./sync-conformance [host] [port]
+ * Every step prints PASS/FAIL; the exit code is the number of violations (capped at 100).
+ */
+
+#define PROTOCOL_VERSION 1
+#define RESPONSE_MAX (1024 * 1024)
+
+typedef struct {
+    int status;
+    char *raw;
+    const char *body;
+} response_t;
+
+static const char *g_host = "127.0.0.1";
+static int g_port = 8080;
+static char g_account[96];
+static int g_failures = 0;
+static int g_steps = 0;
+
+static void check(int ok, const char *step, const char *detail) {
+    g_steps++;
+    if (ok) {
+        printf("PASS %s\n", step);
+    } else {
+        g_failures++;
+        printf("FAIL %s: %s\n", step, detail);
+    }
+}
+
+static int send_all(int fd, const char *buf, size_t len) {
+    size_t sent = 0;
+    while (sent < len) {
+        ssize_t n = send(fd, buf + sent, len - sent, 0);
+        if (n <= 0) return -1;
+        sent += (size_t)n;
+    }
+    return 0;
+}
+
+static int request(const char *method, const char *path, const char *extra_headers, const char *body, response_t *out) {
+    memset(out, 0, sizeof(*out));
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0) return -1;
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons((uint16_t)g_port);
+    if (inet_pton(AF_INET, g_host, &addr.sin_addr) <= 0 || connect(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) {
+        close(fd);
+        return -1;
+    }
+
+    size_t body_len = body ? strlen(body) : 0;
+    char header[2048];
+    int n = snprintf(
+        header,
+        sizeof(header),
+        "%s %s HTTP/1.1\r\nHost: %s\r\n%sContent-Length: %zu\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        g_host,
+        extra_headers ? extra_headers : "",
+        body_len);
+    if (n <= 0 || (size_t)n >= sizeof(header) || send_all(fd, header, (size_t)n) != 0 || (body_len > 0 && send_all(fd, body, body_len) != 0)) {
+        close(fd);
+        return -1;
+    }
+
+    out->raw = (char *)malloc(RESPONSE_MAX + 1);
+    if (!out->raw) {
+        close(fd);
+        return -1;
+    }
+    size_t off = 0;
+    while (off < RESPONSE_MAX) {
+        ssize_t got = recv(fd, out->raw + off, RESPONSE_MAX - off, 0);
+        if (got <= 0) break;
+        off += (size_t)got;
+    }
+    close(fd);
+    out->raw[off] = '\0';
+    if (sscanf(out->raw, "HTTP/1.1 %d", &out->status) != 1) return -1;
+    const char *sep = strstr(out->raw, "\r\n\r\n");
+    out->body = sep ? sep + 4 : "";
+    return 0;
+}
+
+static void response_free(response_t *resp) {
+    free(resp->raw);
+    resp->raw = NULL;
+}
+
+static int header_value(const response_t *resp, const char *name, char *out, size_t out_len) {
+    size_t name_len = strlen(name);
+    const char *line = strstr(resp->raw, "\r\n");
+    while (line && line < resp->body) {
+        line += 2;
+        if (strncasecmp(line, name, name_len) == 0 && line[name_len] == ':') {
+            const char *v = line + name_len + 1;
+            while (*v == ' ') v++;
+            size_t len = strcspn(v, "\r\n");
+            if (len >= out_len) len = out_len - 1;
+            memcpy(out, v, len);
+            out[len] = '\0';
+            return 1;
+        }
+        line = strstr(line, "\r\n");
+    }
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc > 1) g_host = argv[1];
+    if (argc > 2) g_port = atoi(argv[2]);
+    snprintf(g_account, sizeof(g_account), "conformance-%ld-%d", (long)time(NULL), (int)getpid());
+
+    char auth[256];
+    snprintf(auth, sizeof(auth), "X-Account-Id: %s\r\nX-Fricu-Sync-Protocol: %d\r\n", g_account, PROTOCOL_VERSION);
+    response_t resp;
+    char detail[256];
+
+    if (request("GET", "/health", NULL, NULL, &resp) != 0) {
+        printf("FAIL connect: cannot reach %s:%d\n", g_host, g_port);
+        return 1;
+    }
+    check(resp.status == 200, "health", "GET /health must return 200");
+    response_free(&resp);
+
+    request("GET", "/v1/sync/manifest", NULL, NULL, &resp);
+    check(resp.status == 401, "auth.required", "manifest without X-Account-Id must return 401");
+    response_free(&resp);
+
+    char bad_version[256];
+    snprintf(bad_version, sizeof(bad_version), "X-Account-Id: %s\r\nX-Fricu-Sync-Protocol: 999\r\n", g_account);
+    request("GET", "/v1/sync/manifest", bad_version, NULL, &resp);
+    check(resp.status == 400 && strstr(resp.body, "\"supported\"") != NULL, "protocol.unsupported", "unknown protocol versions must return 400 with supported list");
+    response_free(&resp);
+
+    request("GET", "/v1/sync/manifest", auth, NULL, &resp);
+    char value[64] = {0};
+    check(resp.status == 200, "manifest.status", "manifest must return 200");
+    check(header_value(&resp, "X-Fricu-Sync-Protocol", value, sizeof(value)) && atoi(value) == PROTOCOL_VERSION, "manifest.protocol_header", "manifest must echo X-Fricu-Sync-Protocol");
+    check(strstr(resp.body, "\"keys\":[]") != NULL, "manifest.empty", "a fresh account must have an empty manifest");
+    long long server_time = 0;
+    const char *st = strstr(resp.body, "\"server_time\":");
+    if (st) server_time = atoll(st + 14);
+    check(server_time > 0, "manifest.server_time", "manifest must report server_time");
+    response_free(&resp);
+
+    request("GET", "/v1/data/workouts", auth, NULL, &resp);
+    check(resp.status == 200 && header_value(&resp, "X-Fricu-Version", value, sizeof(value)) && strcmp(value, "0") == 0, "fetch.missing_version", "missing keys must report X-Fricu-Version: 0");
+    response_free(&resp);
+
+    char headers[512];
+    snprintf(headers, sizeof(headers), "%sX-Fricu-Base-Version: 0\r\n", auth);
+    const char *first = "[{\"name\":\"conformance\",\"segments\":[]}]";
+    request("PUT", "/v1/data/workouts", headers, first, &resp);
+    char version1[64] = {0};
+    check(resp.status == 204 && header_value(&resp, "X-Fricu-Version", version1, sizeof(version1)), "push.create", "create with base version 0 must return 204 and X-Fricu-Version");
+    response_free(&resp);
+
+    request("GET", "/v1/data/workouts", auth, NULL, &resp);
+    check(resp.status == 200 && strcmp(resp.body, first) == 0, "fetch.body", "fetch must return the stored document byte-for-byte");
+    check(header_value(&resp, "X-Fricu-Version", value, sizeof(value)) && strcmp(value, version1) == 0, "fetch.version", "fetch version must equal the version returned by push");
+    response_free(&resp);
+
+    request("PUT", "/v1/data/workouts", headers, first, &resp);
+    snprintf(detail, sizeof(detail), "stale base version must return 409, got %d", resp.status);
+    check(resp.status == 409 && strstr(resp.body, version1) != NULL, "push.conflict", detail);
+    response_free(&resp);
+
+    snprintf(headers, sizeof(headers), "%sX-Fricu-Base-Version: %s\r\n", auth, version1);
+    const char *second = "[{\"name\":\"conformance v2\",\"segments\":[]}]";
+    request("PUT", "/v1/data/workouts", headers, second, &resp);
+    char version2[64] = {0};
+    check(resp.status == 204 && header_value(&resp, "X-Fricu-Version", version2, sizeof(version2)) && strcmp(version1, version2) != 0, "push.update", "update with current base version must return a new version");
+    response_free(&resp);
+
+    request("GET", "/v1/data/workouts", auth, NULL, &resp);
+    char etag[80] = {0};
+    snprintf(detail, sizeof(detail), "\"%s\"", version2);
+    check(header_value(&resp, "ETag", etag, sizeof(etag)) && strcmp(etag, detail) == 0, "fetch.etag", "fetch must return the version as a strong ETag");
+    response_free(&resp);
+
+    snprintf(headers, sizeof(headers), "%sIf-Match: \"%s\"\r\n", auth, version1);
+    request("PUT", "/v1/data/workouts", headers, first, &resp);
+    snprintf(detail, sizeof(detail), "If-Match with a stale ETag must return 412, got %d", resp.status);
+    check(resp.status == 412 && header_value(&resp, "ETag", value, sizeof(value)) && strcmp(value, etag) == 0, "push.if_match_stale", detail);
+    response_free(&resp);
+
+    snprintf(headers, sizeof(headers), "%sIf-Match: %s\r\n", auth, etag);
+    request("PUT", "/v1/data/workouts", headers, second, &resp);
+    check(resp.status == 204, "push.if_match_current", "If-Match with the current ETag must be accepted");
+    response_free(&resp);
+
+    char path[128];
+    snprintf(path, sizeof(path), "/v1/sync/manifest?since=%lld", server_time - 1);
+    request("GET", path, auth, NULL, &resp);
+    check(resp.status == 200 && strstr(resp.body, "\"key\":\"workouts\"") != NULL && strstr(resp.body, version2) != NULL, "delta.listed", "manifest since an earlier time must list the changed key at its current version");
+    response_free(&resp);
+
+    snprintf(path, sizeof(path), "/v1/sync/manifest?since=%lld", server_time + 3600);
+    request("GET", path, auth, NULL, &resp);
+    check(resp.status == 200 && strstr(resp.body, "\"keys\":[]") != NULL, "delta.empty", "manifest since a later time must list nothing");
+    response_free(&resp);
+
+    request("PUT", "/v1/data/workouts", auth, first, &resp);
+    check(resp.status == 204, "push.last_writer_wins", "push without base version must overwrite (last writer wins)");
+    response_free(&resp);
+
+    printf("%d steps, %d violations\n", g_steps, g_failures);
+    return g_failures > 100 ? 100 : g_failures;
+}
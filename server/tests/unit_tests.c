@@ -1,19 +1,41 @@
 #define _GNU_SOURCE
 
 #include <assert.h>
+#include <ctype.h>
 #include <dirent.h>
 #include <errno.h>
 #include <fcntl.h>
+#include <math.h>
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <pthread.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include <sys/socket.h>
 #include <sys/stat.h>
+#include <sys/time.h>
 #include <sys/types.h>
+#include <time.h>
 #include <unistd.h>
 
 #include <sqlite3.h>
+#ifdef FRICU_HAVE_ZLIB
+#include <zlib.h>
+#endif
+#ifdef FRICU_HAVE_BROTLI
+#include <brotli/decode.h>
+#endif
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/bn.h>
+#include <openssl/evp.h>
+#include <openssl/pem.h>
+#include <openssl/rsa.h>
+#include <openssl/ssl.h>
+#include <openssl/x509.h>
+#endif
 
+#include "../fricu.h"
 #include "../server.h"
 #include "../server_internal.h"
 
@@ -94,6 +116,78 @@ static void must_write_all(int fd, const char *buf, size_t len) {
     }
 }
 
+typedef struct {
+    char dir[64];
+    int old_cwd;
+    worker_db_t db;
+} test_env_t;
+
+static void test_env_open(test_env_t *env, const char *dir_template) {
+    memset(env, 0, sizeof(*env));
+    snprintf(env->dir, sizeof(env->dir), "%s", dir_template);
+    assert(mkdtemp(env->dir) != NULL);
+    env->old_cwd = open(".", O_RDONLY);
+    assert(env->old_cwd >= 0);
+    assert(chdir(env->dir) == 0);
+    assert(init_db("state.db") == 0);
+    assert(worker_db_open(&env->db, "state.db") == 0);
+}
+
+static void test_env_close(test_env_t *env) {
+    worker_db_close(&env->db);
+    assert(fchdir(env->old_cwd) == 0);
+    close(env->old_cwd);
+    char cleanup_cmd[512] = {0};
+    assert(snprintf(cleanup_cmd, sizeof(cleanup_cmd), "rm -rf '%s' >/dev/null 2>&1", env->dir) > 0);
+    assert(system(cleanup_cmd) == 0);
+}
+
+static void run_request_bytes(worker_db_t *db, const char *req, size_t req_len, char *resp, size_t resp_len) {
+    int fds[2] = {-1, -1};
+    assert(socketpair(AF_UNIX, SOCK_STREAM, 0, fds) == 0);
+    conn_t conn = {0};
+    conn.cap = REQ_BUF_SIZE;
+    conn.buf = (char *)malloc(conn.cap + 1);
+    assert(conn.buf != NULL);
+    conn.len = req_len;
+    memcpy(conn.buf, req, conn.len);
+    assert(try_process_client(fds[0], db, &conn) == 1);
+    /* Derived tables refresh on the writer thread after the response; let the next request see them. */
+    assert(write_dispatch_drain(2000) == 0);
+    close(fds[0]);
+
+    size_t off = 0;
+    while (off + 1 < resp_len) {
+        ssize_t n = read(fds[1], resp + off, resp_len - off - 1);
+        if (n <= 0) break;
+        off += (size_t)n;
+    }
+    resp[off] = '\0';
+    close(fds[1]);
+    free(conn.buf);
+}
+
+static void run_request(worker_db_t *db, const char *req, char *resp, size_t resp_len) {
+    run_request_bytes(db, req, strlen(req), resp, resp_len);
+}
+
+static void put_json(worker_db_t *db, const char *account, const char *key, const char *json, char *resp, size_t resp_len) {
+    char req[16384] = {0};
+    int n = snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/%s HTTP/1.1\r\n"
+        "Host: localhost\r\n"
+        "X-Account-Id: %s\r\n"
+        "Content-Length: %zu\r\n\r\n%s",
+        key,
+        account,
+        strlen(json),
+        json);
+    assert(n > 0 && (size_t)n < sizeof(req));
+    run_request(db, req, resp, resp_len);
+}
+
 static void test_put_is_journaled_and_persisted(void) {
     char dir_template[] = "/tmp/fricu-test-put-XXXXXX";
     char *tmpdir = mkdtemp(dir_template);
@@ -242,6 +336,8 @@ static void test_write_queue_diagnostics_endpoint(void) {
     ssize_t put_n = read(put_fds[1], put_resp, sizeof(put_resp) - 1);
     assert(put_n > 0);
     assert(strstr(put_resp, "204 No Content") != NULL);
+    /* The profile write queued a VO2max refresh behind it. */
+    assert(write_dispatch_drain(2000) == 0);
 
     int diag_fds[2] = {-1, -1};
     assert(socketpair(AF_UNIX, SOCK_STREAM, 0, diag_fds) == 0);
@@ -405,17 +501,6817 @@ static void test_replay_pending_write_on_restart(void) {
     assert(system(cleanup_cmd) == 0);
 }
 
-int main(void) {
-    test_valid_key();
-    test_parse_bind_addr();
-    test_read_content_length();
-    test_socket_send_flags();
-    test_configure_socket_after_accept();
-    test_put_is_journaled_and_persisted();
-    test_missing_account_id_rejected();
-    test_write_queue_diagnostics_endpoint();
-    test_replay_pending_write_on_restart();
-    test_put_lock_is_queued_in_pending_writes();
+static void test_query_param(void) {
+    char value[64] = {0};
+    assert(query_param("from=2025-01-01&to=2025-02-01", "to", value, sizeof(value)) == 1);
+    assert(strcmp(value, "2025-02-01") == 0);
+    assert(query_param("a=1&name=hello%20world+x", "name", value, sizeof(value)) == 1);
+    assert(strcmp(value, "hello world x") == 0);
+    assert(query_param("flag&b=2", "flag", value, sizeof(value)) == 1);
+    assert(value[0] == '\0');
+    assert(query_param("ab=1", "a", value, sizeof(value)) == 0);
+    assert(query_param("", "a", value, sizeof(value)) == 0);
+}
+
+static void test_iso_day_round_trip(void) {
+    int day = -1;
+    assert(parse_iso_day("1970-01-01", &day) == 0 && day == 0);
+    assert(parse_iso_day("2025-03-01T08:00:00Z", &day) == 0);
+    char text[16] = {0};
+    format_iso_day(day, text, sizeof(text));
+    assert(strcmp(text, "2025-03-01") == 0);
+    format_iso_day(day - 1, text, sizeof(text));
+    assert(strcmp(text, "2025-02-28") == 0);
+    assert(parse_iso_day("2025/03/01", &day) != 0);
+    assert(parse_iso_day("2025-13-01", &day) != 0);
+}
+
+static void test_training_risk_flags_load_spike(void) {
+    int first_day = 0;
+    assert(parse_iso_day("2025-01-06", &first_day) == 0);
+    double daily[56] = {0};
+    for (int i = 0; i < 42; i++) daily[i] = (i % 7 == 2 || i % 7 == 5) ? 80.0 : 30.0;
+    for (int i = 42; i < 49; i++) daily[i] = 180.0;
+    training_risk_week_t weeks[8];
+    size_t count = compute_training_risk(daily, 49, first_day, 0, weeks, 8);
+    assert(count == 7);
+    char label[16] = {0};
+    format_iso_day(weeks[0].week_start_day, label, sizeof(label));
+    assert(strcmp(label, "2025-01-06") == 0);
+    assert(weeks[5].flags == 0);
+    assert(weeks[6].flags & RISK_FLAG_ACWR);
+    assert(weeks[6].flags & RISK_FLAG_RAMP_RATE);
+    assert(weeks[6].flags & RISK_FLAG_MONOTONY);
+    assert(weeks[6].acwr > 1.5);
+    assert(weeks[6].tss > 1259.0 && weeks[6].tss < 1261.0);
+
+    assert(compute_training_risk(daily, 49, first_day, 0, weeks, 2) == 2);
+    assert(weeks[1].flags & RISK_FLAG_ACWR);
+}
+
+static void test_training_week_rollover_and_start_day(void) {
+    int monday = 0;
+    assert(parse_iso_day("2025-01-06", &monday) == 0);
+    assert(week_start_on(monday + 2, 0) == monday);
+    assert(week_start_on(monday + 2, 6) == monday - 1);
+    assert(week_start_on(monday - 1, 6) == monday - 1);
+    assert(week_start_for_day(monday + 6) == monday);
+    assert(weekday_from_name("sunday") == 6 && weekday_from_name("Sun") == -1);
+
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-week-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "profile", "{\"weekStartDay\":\"someday\"}", resp, sizeof(resp));
+    assert(strstr(resp, "422") != NULL && strstr(resp, "\"expected\":\"weekday name\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"weekRolloverHour\":24}", resp, sizeof(resp));
+    assert(strstr(resp, "422") != NULL && strstr(resp, "\"expected\":\"hour 0..23\",\"got\":\"24\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"weekRolloverHour\":4.5}", resp, sizeof(resp));
+    assert(strstr(resp, "422") != NULL);
+    training_week_t week;
+    load_training_week(env.db.db, "tester", &week);
+    assert(week.start_weekday == 0 && week.rollover_hour == 0);
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":250,\"weekStartDay\":\"sunday\",\"weekRolloverHour\":4}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    load_training_week(env.db.db, "tester", &week);
+    assert(week.start_weekday == 6 && week.rollover_hour == 4);
+
+    /* A late Sunday ride finishing after midnight still counts on Sunday; date-only entries keep their day. */
+    char body[1024] = {0};
+    char today[16] = {0};
+    char next[16] = {0};
+    format_iso_day(training_today(&week), today, sizeof(today));
+    format_iso_day(training_today(&week) + 1, next, sizeof(next));
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"a1\",\"date\":\"2025-01-06T02:30:00Z\",\"sport\":\"cycling\",\"tss\":50},"
+        "{\"id\":\"a2\",\"date\":\"2025-01-06T05:00:00Z\",\"sport\":\"cycling\",\"tss\":30},"
+        "{\"id\":\"a3\",\"date\":\"2025-01-06\",\"sport\":\"cycling\",\"tss\":20},"
+        "{\"id\":\"a4\",\"date\":\"%sT03:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":70}]",
+        next);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    daily_load_t *loads = NULL;
+    size_t count = 0;
+    assert(load_daily_tss(env.db.db, "tester", &loads, &count) == 0);
+    assert(count == 3);
+    assert(loads[0].day == monday - 1 && loads[0].tss > 49.9 && loads[0].tss < 50.1);
+    assert(loads[1].day == monday && loads[1].tss > 49.9 && loads[1].tss < 50.1);
+    assert(loads[2].day == training_today(&week));
+    free(loads);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    bots_answer(env.db.db, "tester", "/week", &sb);
+    char expected[64] = {0};
+    format_iso_day(week_start_on(training_today(&week), 6), body, sizeof(body));
+    snprintf(expected, sizeof(expected), "Week of %s: 1 activity", body);
+    assert(strstr(strbuf_cstr(&sb), expected) != NULL);
+    assert(strstr(strbuf_cstr(&sb), "TSS 70") != NULL);
+    strbuf_free(&sb);
+
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"w1\",\"name\":\"Long ride\",\"sport\":\"cycling\",\"scheduledDate\":\"2025-01-05\",\"segments\":[{\"minutes\":60}]}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/workouts/w1/compliance HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"activity_id\":\"a1\"") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/risk?weeks=1 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    char week_start[48] = {0};
+    snprintf(week_start, sizeof(week_start), "{\"week_start\":\"%s\"", body);
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, week_start) != NULL);
+    format_iso_day(training_today(&week), body, sizeof(body));
+    snprintf(week_start, sizeof(week_start), "{\"as_of\":\"%s\"", body);
+    assert(strstr(resp, week_start) != NULL);
+
+    /* The season report buckets by the same training weeks: the early Sunday ride closes the week of Feb 23. */
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"r1\",\"date\":\"2025-03-02T02:00:00Z\",\"durationSec\":7200,\"tss\":90},"
+        "{\"id\":\"r2\",\"date\":\"2025-03-02T10:00:00Z\",\"durationSec\":3600,\"tss\":40}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    static char report[64 * 1024];
+    run_request(
+        &env.db,
+        "GET /v1/reports/season?year=2025 HTTP/1.1\r\nX-Account-Id: tester\r\n\r\n",
+        report,
+        sizeof(report));
+    const char *weeks = strstr(report, "<h2>Biggest weeks</h2>");
+    assert(weeks != NULL && strstr(weeks, "<tr><td>2025-02-23</td><td>2.0</td><td>90</td></tr>") != NULL);
+    assert(strstr(weeks, "<tr><td>2025-03-02</td><td>1.0</td><td>40</td></tr>") != NULL);
+    test_env_close(&env);
+}
+
+static void test_analytics_risk_endpoint_and_write_notification(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-risk-XXXXXX");
+
+    strbuf_t activities;
+    strbuf_init(&activities);
+    strbuf_append(&activities, "[", 1);
+    int today = today_day();
+    for (int i = 0; i < 35; i++) {
+        int day = today - 34 + i;
+        char date[16] = {0};
+        format_iso_day(day, date, sizeof(date));
+        int tss = i >= 28 ? 200 : 40;
+        strbuf_appendf(&activities, "%s{\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"tss\":%d}", i == 0 ? "" : ",", date, tss);
+    }
+    strbuf_append(&activities, "]", 1);
+
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "activities", strbuf_cstr(&activities), resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    strbuf_free(&activities);
+
+    /* The write posts the notification; reading the risk does not write. */
+    run_request(
+        &env.db,
+        "GET /v1/notifications?kind=training_risk HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"kind\":\"training_risk\"") != NULL);
+
+    run_request(
+        &env.db,
+        "GET /v1/analytics/risk?weeks=4 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"weeks\":[{\"week_start\"") != NULL);
+    assert(strstr(resp, "\"acwr\"]") != NULL || strstr(resp, "\"acwr\",") != NULL);
+    assert(strstr(resp, "Acute:chronic ratio") != NULL);
+
+    run_request(
+        &env.db,
+        "GET /v1/notifications HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "{\"notifications\":[]}") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/risk?weeks=0 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_analytics_simulate_projects_taper(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sim-XXXXXX");
+
+    char resp[16384] = {0};
+    char date[16] = {0};
+    char activities[512] = {0};
+    format_iso_day(today_day() - 1, date, sizeof(date));
+    snprintf(activities, sizeof(activities), "[{\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"tss\":420}]", date);
+    put_json(&env.db, "tester", "activities", activities, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    char planned_date[16] = {0};
+    char workouts[512] = {0};
+    format_iso_day(today_day() + 2, planned_date, sizeof(planned_date));
+    snprintf(
+        workouts,
+        sizeof(workouts),
+        "[{\"name\":\"Threshold\",\"scheduledDate\":\"%sT06:00:00Z\",\"segments\":[{\"minutes\":60,\"intensityPercentFTP\":100}]}]",
+        planned_date);
+    put_json(&env.db, "tester", "workouts", workouts, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    char target[16] = {0};
+    char workout_date[16] = {0};
+    format_iso_day(today_day() + 7, target, sizeof(target));
+    format_iso_day(today_day() + 1, workout_date, sizeof(workout_date));
+    char body[512] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "{\"target_date\":\"%s\",\"use_planned_workouts\":true,\"workouts\":[{\"date\":\"%s\",\"tss\":50}]}",
+        target,
+        workout_date);
+    char req[1024] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/analytics/simulate HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(body),
+        body);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"hypothetical_workouts\":1") != NULL);
+    assert(strstr(resp, "\"planned_workouts\":1") != NULL);
+    assert(strstr(resp, "\"tss\":100.0") != NULL);
+    char expected_final[64] = {0};
+    snprintf(expected_final, sizeof(expected_final), "\"final\":{\"date\":\"%s\"", target);
+    assert(strstr(resp, expected_final) != NULL);
+
+    const char *bad =
+        "POST /v1/analytics/simulate HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 28\r\n\r\n"
+        "{\"target_date\":\"2001-01-01\"}";
+    run_request(&env.db, bad, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    assert(strstr(resp, "target_date must be") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_analytics_compare_seasons(void) {
+    int from = 0;
+    int to = 0;
+    assert(parse_analytics_period("2024", &from, &to) == 0);
+    assert(to - from == 365);
+    assert(parse_analytics_period("2025-01-01..2025-03-31", &from, &to) == 0);
+    assert(to - from == 89);
+    assert(parse_analytics_period("2025-03-31..2025-01-01", &from, &to) != 0);
+    assert(parse_analytics_period("25", &from, &to) != 0);
+
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-compare-XXXXXX");
+    char resp[32768] = {0};
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"date\":\"2024-03-05T07:00:00Z\",\"durationSec\":3600,\"tss\":60,\"distanceKm\":30,\"normalizedPower\":220,"
+        "\"intervals\":[{\"name\":\"20m\",\"durationSec\":1200,\"actualPower\":250}]},"
+        "{\"date\":\"2025-03-05T07:00:00Z\",\"durationSec\":7200,\"tss\":120,\"distanceKm\":60,\"normalizedPower\":230,"
+        "\"intervals\":[{\"name\":\"20m\",\"durationSec\":1200,\"actualPower\":270}]},"
+        "{\"date\":\"2025-03-06T07:00:00Z\",\"durationSec\":3600,\"tss\":50,\"distanceKm\":25}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/compare?periods=2024,2025 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"label\":\"2024\",\"from\":\"2024-01-01\",\"to\":\"2024-12-31\",\"activities\":1,\"hours\":1.0") != NULL);
+    assert(strstr(resp, "\"label\":\"2025\",\"from\":\"2025-01-01\",\"to\":\"2025-12-31\",\"activities\":2,\"hours\":3.0") != NULL);
+    assert(strstr(resp, "\"deltas\":{\"activities\":1,\"hours\":2.0,\"tss\":110") != NULL);
+    assert(strstr(resp, "\"pr_changes\":[{\"duration_sec\":5,\"from\":250,\"to\":270}") != NULL);
+    assert(strstr(resp, "{\"duration_sec\":3600,\"watts\":10,\"pct\":4.5}") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/compare?periods=2024 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_wbal_depletes_and_recovers(void) {
+    double power[120];
+    for (int i = 0; i < 60; i++) power[i] = 400.0;
+    for (int i = 60; i < 120; i++) power[i] = 100.0;
+    double wbal[120];
+    wbal_summary_t summary;
+    compute_wbal_series(power, 120, 250.0, 6000.0, wbal, &summary);
+    assert(wbal[59] < -2999.0 && wbal[59] > -3001.0);
+    assert(summary.min_wbal < -2999.0);
+    assert(summary.time_below_zero_sec > 0 && summary.time_below_zero_sec < 120);
+    assert(wbal[119] > wbal[59]);
+
+    compute_wbal_series(power + 60, 60, 250.0, 20000.0, NULL, &summary);
+    assert(summary.min_wbal == 20000.0 && summary.time_below_zero_sec == 0);
+}
+
+static void test_activity_wbal_endpoint_and_stored_metrics(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-wbal-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "profile", "{\"criticalPowerWatts\":200,\"wPrimeJoules\":1000}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"A1\",\"date\":\"2025-05-01T07:00:00Z\",\"tss\":10,\"powerSamples\":[300,300,300,300,300,300,300,300,300,300,300,300,100,100]}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/activities/A1/wbal?step=2 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"cp\":200,\"w_prime\":1000,\"samples\":14,\"min_wbal\":-200,\"time_below_zero_sec\":3") != NULL);
+    assert(strstr(resp, "\"series\":[[0,900],[2,700]") != NULL);
+
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(
+               env.db.db,
+               "SELECT value FROM activity_metrics WHERE account_id='tester' AND activity_id='A1' AND metric='wbal_time_below_zero_sec'",
+               -1,
+               &stmt,
+               NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW);
+    assert(sqlite3_column_double(stmt, 0) == 3.0);
+    sqlite3_finalize(stmt);
+
+    run_request(&env.db, "GET /v1/analytics/activities/missing/wbal HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    test_env_close(&env);
+}
+
+static void test_cp_fit_recovers_model(void) {
+    mmp_point_t points[4];
+    const double durations[] = {180, 300, 720, 1200};
+    for (size_t i = 0; i < 4; i++) {
+        points[i].duration_sec = durations[i];
+        points[i].watts = 250.0 + 20000.0 / durations[i];
+    }
+    cp_fit_t fit;
+    assert(fit_cp_model(points, 4, 2, &fit) == 0);
+    assert(fabs(fit.cp - 250.0) < 0.01);
+    assert(fabs(fit.w_prime - 20000.0) < 1.0);
+    assert(fit.cp_ci_low <= fit.cp && fit.cp <= fit.cp_ci_high);
+
+    for (size_t i = 0; i < 4; i++) points[i].watts = 250.0 + 20000.0 / (durations[i] + 30.0);
+    assert(fit_cp_model(points, 4, 3, &fit) == 0);
+    assert(fabs(fit.cp - 250.0) < 0.5);
+    assert(fabs(fit.k + 30.0) < 1.0);
+    assert(fit_cp_model(points, 2, 2, &fit) != 0);
+}
+
+static void test_analytics_cp_fit_endpoint(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-cp-XXXXXX");
+    char resp[16384] = {0};
+    char today[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+
+    run_request(&env.db, "POST /v1/analytics/cp/fit HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL);
+
+    char activities[1024] = {0};
+    snprintf(
+        activities,
+        sizeof(activities),
+        "[{\"id\":\"A1\",\"date\":\"%sT07:00:00Z\",\"tss\":80,\"intervals\":["
+        "{\"name\":\"3m\",\"durationSec\":180,\"actualPower\":350},"
+        "{\"name\":\"5m\",\"durationSec\":300,\"actualPower\":310},"
+        "{\"name\":\"12m\",\"durationSec\":720,\"actualPower\":275}]}]",
+        today);
+    put_json(&env.db, "tester", "activities", activities, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/cp HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"latest_fit\":{\"model\":2") != NULL);
+    assert(strstr(resp, "\"zones\":[{\"name\":\"Z1 Recovery\"") != NULL);
+
+    /* The fit is stored by the writer, so a worker connection that cannot write still gets one. */
+    assert(sqlite3_exec(env.db.db, "PRAGMA query_only = 1", NULL, NULL, NULL) == SQLITE_OK);
+    run_request(&env.db, "POST /v1/analytics/cp/fit?model=3 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"stored\":true,\"weeks\":12,\"fit\":{\"model\":3") != NULL);
+    assert(sqlite3_exec(env.db.db, "PRAGMA query_only = 0", NULL, NULL, NULL) == SQLITE_OK);
+    run_request(&env.db, "GET /v1/analytics/cp HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"latest_fit\":{\"model\":3") != NULL);
+
+    run_request(&env.db, "POST /v1/analytics/cp/fit?model=4 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    put_json(&env.db, "tester", "profile", "{\"criticalPowerWatts\":240}", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/analytics/cp HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"cp\":240.0,") != NULL);
+    test_env_close(&env);
+}
+
+static void test_vo2max_estimate_heuristics(void) {
+    assert(fabs(estimate_vo2max("cycling", 3600, 30, 200, 150, 190, 70) - 57.75) < 0.05);
+    assert(fabs(estimate_vo2max("running", 3000, 10, 0, 160, 190, 70) - 58.97) < 0.05);
+    assert(estimate_vo2max("cycling", 600, 5, 200, 150, 190, 70) == 0.0);
+    assert(estimate_vo2max("cycling", 3600, 30, 200, 185, 190, 70) == 0.0);
+    assert(estimate_vo2max("cycling", 3600, 30, 200, 150, 190, 0) == 0.0);
+    assert(estimate_vo2max("swimming", 3600, 3, 0, 150, 190, 70) == 0.0);
+}
+
+static void test_analytics_profile_vo2max_trend(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-vo2-XXXXXX");
+    char resp[16384] = {0};
+    char first[16] = {0};
+    char second[16] = {0};
+    format_iso_day(today_day() - 14, first, sizeof(first));
+    format_iso_day(today_day(), second, sizeof(second));
+
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":260,\"athleteWeightKg\":70,\"cyclingMaxHeartRate\":190}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    char activities[1024] = {0};
+    snprintf(
+        activities,
+        sizeof(activities),
+        "[{\"id\":\"A1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"normalizedPower\":200,\"avgHeartRate\":150},"
+        "{\"id\":\"A2\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"normalizedPower\":230,\"avgHeartRate\":150},"
+        "{\"id\":\"A3\",\"date\":\"%sT09:00:00Z\",\"sport\":\"strength\",\"durationSec\":1800}]",
+        first,
+        second,
+        second);
+    put_json(&env.db, "tester", "activities", activities, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"ftp_watts\":260,\"cp\":260.0") != NULL);
+    char expected[128] = {0};
+    snprintf(expected, sizeof(expected), "\"vo2max_trend\":[{\"date\":\"%s\",\"estimate\":57.8,\"smoothed\":57.8,\"activities\":1}", first);
+    assert(strstr(resp, expected) != NULL);
+    snprintf(expected, sizeof(expected), "{\"date\":\"%s\",\"estimate\":64.8,\"smoothed\":62.2,\"activities\":1}]", second);
+    assert(strstr(resp, expected) != NULL);
+    snprintf(expected, sizeof(expected), "\"vo2max\":{\"value\":62.2,\"as_of\":\"%s\"}", second);
+    assert(strstr(resp, expected) != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/profile?days=0 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    /* A refresh that cannot take the write lock is skipped and leaves the trend as it was. */
+    sqlite3 *holder = NULL;
+    assert(sqlite3_open("state.db", &holder) == SQLITE_OK);
+    assert(sqlite3_exec(holder, "BEGIN IMMEDIATE; DELETE FROM vo2max_trend;", NULL, NULL, NULL) == SQLITE_OK);
+    sqlite3_busy_timeout(env.db.db, 0);
+    assert(physiology_refresh_vo2max(env.db.db, "tester") == -1);
+    assert(sqlite3_get_autocommit(env.db.db));
+    sqlite3_exec(holder, "ROLLBACK;", NULL, NULL, NULL);
+    sqlite3_close(holder);
+    sqlite3_busy_timeout(env.db.db, 5000);
+    run_request(&env.db, "GET /v1/analytics/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, expected) != NULL);
+    test_env_close(&env);
+}
+
+static void test_heart_rate_recovery_and_drift(void) {
+    static double hr[1300];
+    static double power[1300];
+    heart_metrics_t metrics;
+
+    for (size_t i = 0; i < 600; i++) hr[i] = 130.0;
+    for (size_t i = 200; i < 300; i++) hr[i] = 170.0;
+    hr[300] = 175.0;
+    for (size_t i = 301; i < 360; i++) hr[i] = 175.0 - (double)(i - 300) * 0.75;
+    compute_heart_rate_metrics(hr, 600, NULL, 0, &metrics);
+    assert(metrics.hrr_efforts == 1);
+    assert(fabs(metrics.hrr60_bpm - 45.0) < 1e-9);
+    assert(!metrics.has_drift);
+
+    for (size_t i = 0; i < 1300; i++) {
+        hr[i] = i < 650 ? 130.0 : 143.0;
+        power[i] = 200.0;
+    }
+    compute_heart_rate_metrics(hr, 1300, NULL, 0, &metrics);
+    assert(metrics.hrr_efforts == 0);
+    assert(metrics.has_drift && !metrics.drift_uses_power);
+    assert(fabs(metrics.cardiac_drift_pct - 10.0) < 1e-9);
+    compute_heart_rate_metrics(hr, 1300, power, 1300, &metrics);
+    assert(metrics.drift_uses_power);
+    assert(fabs(metrics.cardiac_drift_pct - (1.0 - 130.0 / 143.0) * 100.0) < 1e-9);
+}
+
+static void test_analytics_heart_endpoint(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-heart-XXXXXX");
+    static char activities[16384];
+    char resp[16384] = {0};
+    char today[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+
+    int len = snprintf(activities, sizeof(activities), "[{\"id\":\"R1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"running\",\"heartRateSamples\":[", today);
+    for (int i = 0; i < 1300; i++) len += snprintf(activities + len, sizeof(activities) - (size_t)len, "%s%d", i == 0 ? "" : ",", i < 650 ? 130 : 143);
+    snprintf(activities + len, sizeof(activities) - (size_t)len, "]}]");
+    put_json(&env.db, "tester", "activities", activities, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/heart?days=30 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    char expected[160] = {0};
+    snprintf(
+        expected,
+        sizeof(expected),
+        "{\"days\":30,\"activities\":[{\"id\":\"R1\",\"date\":\"%s\",\"hrr60_bpm\":null,\"hrr60_efforts\":0,\"cardiac_drift_pct\":10.0}],\"weeks\":[{\"week_start\":",
+        today);
+    assert(strstr(resp, expected) != NULL);
+    assert(strstr(resp, "\"cardiac_drift_pct\":10.0}]}") != NULL);
+
+    run_request(&env.db, "GET /v1/analytics/heart?days=9999 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    test_env_close(&env);
+}
+
+static void test_today_workout_device_token(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-today-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+    char today[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":200}", resp, sizeof(resp));
+    char workouts[1024] = {0};
+    snprintf(
+        workouts,
+        sizeof(workouts),
+        "[{\"name\":\"Tomorrow\",\"scheduledDate\":\"2001-01-01T07:00:00Z\",\"segments\":[{\"minutes\":60,\"intensityPercentFTP\":60}]},"
+        "{\"name\":\"Sweet Spot\",\"sport\":\"cycling\",\"scheduledDate\":\"%sT07:00:00Z\",\"segments\":["
+        "{\"minutes\":10,\"intensityPercentFTP\":55,\"cadence\":90,\"note\":\"warm up\"},{\"minutes\":20,\"intensityPercentFTP\":90}]}]",
+        today);
+    put_json(&env.db, "tester", "workouts", workouts, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    const char *issue = "{\"name\":\"garage pc\"}";
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/devices HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(issue),
+        issue);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *token_start = strstr(resp, "\"token\":\"");
+    assert(token_start != NULL);
+    token_start += 9;
+    char token[64] = {0};
+    size_t token_len = (size_t)(strchr(token_start, '"') - token_start);
+    assert(token_len == 52);
+    memcpy(token, token_start, token_len);
+    char hash[65] = {0};
+    sha256_hex(token, token_len, hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT token_hash FROM device_tokens", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), hash) == 0);
+    sqlite3_finalize(stmt);
+
+    snprintf(req, sizeof(req), "GET /v1/today/workout?format=json HTTP/1.1\r\nHost: localhost\r\nX-Device-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"name\":\"Sweet Spot\",\"sport\":\"cycling\",\"ftp_watts\":200,\"segments\":[") != NULL);
+    assert(strstr(resp, "{\"start_sec\":0,\"duration_sec\":600,\"percent_ftp\":55.0,\"target_watts\":110,\"cadence\":90,\"note\":\"warm up\"}") != NULL);
+    assert(strstr(resp, "\"total_sec\":1800}") != NULL);
+
+    snprintf(req, sizeof(req), "GET /v1/today/workout?format=erg&token=%s HTTP/1.1\r\nHost: localhost\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Type: text/plain") != NULL);
+    assert(strstr(resp, "FTP = 200\r\n") != NULL);
+    assert(strstr(resp, "[COURSE DATA]\r\n0.00\t110\r\n10.00\t110\r\n10.00\t180\r\n30.00\t180\r\n[END COURSE DATA]") != NULL);
+
+    snprintf(req, sizeof(req), "GET /v1/today/workout?date=2001-01-02 HTTP/1.1\r\nHost: localhost\r\nX-Device-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    run_request(&env.db, "GET /v1/today/workout HTTP/1.1\r\nHost: localhost\r\nX-Device-Token: dev_bogus\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    snprintf(req, sizeof(req), "DELETE /v1/devices/%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/today/workout HTTP/1.1\r\nHost: localhost\r\nX-Device-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    /* Plaintext device tokens from an older build are hashed when the database is opened. */
+    sqlite3 *legacy = NULL;
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    assert(sqlite3_exec(
+               legacy,
+               "CREATE TABLE device_tokens (token TEXT PRIMARY KEY, account_id TEXT NOT NULL, name TEXT NOT NULL, created_at INTEGER NOT NULL,"
+               " last_used_at INTEGER);"
+               "INSERT INTO device_tokens VALUES ('dev_legacy', 'tester', 'garage pc', 0, NULL);",
+               NULL,
+               NULL,
+               NULL) == SQLITE_OK);
+    sqlite3_close(legacy);
+    assert(init_db("legacy.db") == 0);
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    sha256_hex("dev_legacy", 10, hash, sizeof(hash));
+    assert(sqlite3_prepare_v2(legacy, "SELECT token_hash FROM device_tokens WHERE account_id = 'tester'", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), hash) == 0);
+    sqlite3_finalize(stmt);
+    sqlite3_close(legacy);
+    test_env_close(&env);
+}
+
+typedef struct {
+    unsigned count;
+    unsigned sport;
+    uint32_t fields[16][23];
+} fit_workout_steps_t;
+
+/* Walks a FIT workout file and keeps fields 0..22 of each workout_step plus the workout's sport. */
+static void decode_fit_workout(const unsigned char *file, size_t len, fit_workout_steps_t *out) {
+    memset(out, 0xFF, sizeof(*out));
+    out->count = 0;
+    assert(len > 16 && file[0] == 14 && memcmp(file + 8, ".FIT", 4) == 0);
+    size_t data_size = (size_t)file[4] | (size_t)file[5] << 8 | (size_t)file[6] << 16 | (size_t)file[7] << 24;
+    assert(data_size + 16 == len);
+    uint16_t crc = 0;
+    static const uint16_t table[16] = {
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    };
+    for (size_t i = 0; i < len; i++) {
+        uint16_t tmp = table[crc & 0xF];
+        crc = (uint16_t)(((crc >> 4) & 0x0FFF) ^ tmp ^ table[file[i] & 0xF]);
+        tmp = table[crc & 0xF];
+        crc = (uint16_t)(((crc >> 4) & 0x0FFF) ^ tmp ^ table[(file[i] >> 4) & 0xF]);
+    }
+    assert(crc == 0);
+
+    unsigned global[16] = {0};
+    unsigned char defs[16][32][2];
+    unsigned field_count[16] = {0};
+    size_t off = 14;
+    while (off < len - 2) {
+        unsigned header = file[off++];
+        unsigned local = header & 0x0F;
+        if (header & 0x40) {
+            global[local] = (unsigned)file[off + 2] | (unsigned)file[off + 3] << 8;
+            field_count[local] = file[off + 4];
+            assert(field_count[local] <= 32);
+            off += 5;
+            for (unsigned f = 0; f < field_count[local]; f++, off += 3) {
+                defs[local][f][0] = file[off];
+                defs[local][f][1] = file[off + 1];
+            }
+            continue;
+        }
+        for (unsigned f = 0; f < field_count[local]; f++) {
+            unsigned number = defs[local][f][0];
+            unsigned size = defs[local][f][1];
+            uint32_t value = 0;
+            for (unsigned b = 0; b < size && b < 4; b++) value |= (uint32_t)file[off + b] << (8 * b);
+            if (global[local] == 26 && number == 4) out->sport = value;
+            if (global[local] == 27 && number == 254) {
+                assert(value == out->count && out->count < 16);
+                out->count++;
+            }
+            if (global[local] == 27 && number <= 22) out->fields[out->count - 1][number] = value;
+            off += size;
+        }
+    }
+}
+
+/* The bytes of a response body whose length comes from its Content-Length header. */
+static const unsigned char *fit_response_body(const char *resp, size_t *out_len) {
+    const char *length = strstr(resp, "Content-Length: ");
+    const char *body = strstr(resp, "\r\n\r\n");
+    assert(length != NULL && body != NULL);
+    *out_len = (size_t)strtoul(length + 16, NULL, 10);
+    return (const unsigned char *)body + 4;
+}
+
+static void test_workout_timer(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-workout-timer-XXXXXX");
+    static char resp[131072];
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":250,\"sports\":{\"running\":{\"thresholdPaceSecPerKm\":300}}}", resp, sizeof(resp));
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"t1\",\"name\":\"Over-unders\",\"sport\":\"ride\",\"segments\":["
+        "{\"minutes\":10,\"intensityPercentFTP\":55,\"cadence\":90,\"note\":\"easy spin\"},"
+        "{\"repeat\":3,\"segments\":[{\"minutes\":3,\"intensityPercentFTP\":110},{\"minutes\":0.5,\"intensityPercentFTP\":50}]},"
+        "{\"minutes\":10,\"intensityPercentFTP\":100,\"sport\":\"run\"},{\"minutes\":5,\"intensityPercentFTP\":50}]},"
+        "{\"id\":\"nested\",\"segments\":[{\"repeat\":2,\"segments\":[{\"repeat\":2,\"segments\":[{\"minutes\":1}]}]}]},"
+        "{\"id\":\"empty\",\"segments\":[{\"note\":\"rest day\"}]}]",
+        resp,
+        sizeof(resp));
+
+    run_request(&env.db, "GET /v1/workouts/t1/timer HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"workout_id\":\"t1\",\"name\":\"Over-unders\",\"sport\":\"cycling\",\"lang\":\"en\",\"total_sec\":2130,\"countdown\":3,\"steps\":[") != NULL);
+    assert(strstr(
+               resp,
+               "{\"index\":0,\"start_sec\":0,\"end_sec\":600,\"duration_sec\":600,\"sport\":\"cycling\",\"kind\":\"warmup\","
+               "\"target\":{\"type\":\"power\",\"percent_ftp\":55,\"watts\":138},\"cadence\":90,\"note\":\"easy spin\","
+               "\"text\":\"Warm-up · 10:00 · 138 W (55% FTP) · 90 rpm\",\"speech\":\"Warm-up, 10 minutes at 138 watts, cadence 90.\","
+               "\"countdown_sec\":[597,598,599]}") != NULL);
+    /* Repeats are unrolled with their round, and every offset is absolute. */
+    assert(strstr(
+               resp,
+               "{\"index\":1,\"start_sec\":600,\"end_sec\":780,\"duration_sec\":180,\"sport\":\"cycling\",\"kind\":\"work\",\"round\":1,\"rounds\":3,"
+               "\"target\":{\"type\":\"power\",\"percent_ftp\":110,\"watts\":275},\"note\":null,\"text\":\"Work 1/3 · 3:00 · 275 W (110% FTP)\","
+               "\"speech\":\"Work 1 of 3, 3 minutes at 275 watts.\",\"countdown_sec\":[777,778,779]}") != NULL);
+    assert(strstr(resp, "{\"index\":6,\"start_sec\":1200,\"end_sec\":1230,\"duration_sec\":30,\"sport\":\"cycling\",\"kind\":\"recovery\",\"round\":3,\"rounds\":3,") != NULL);
+    assert(strstr(resp, "\"speech\":\"Recovery 3 of 3, 30 seconds at 125 watts.\"") != NULL);
+    /* A brick's run leg is paced from the running threshold. */
+    assert(strstr(
+               resp,
+               "{\"index\":7,\"start_sec\":1230,\"end_sec\":1830,\"duration_sec\":600,\"sport\":\"running\",\"kind\":\"work\","
+               "\"target\":{\"type\":\"pace\",\"sec_per_km\":300},\"note\":null,\"text\":\"Work · 10:00 · 5:00/km\","
+               "\"speech\":\"Work, 10 minutes at 5:00 per kilometer.\"") != NULL);
+    assert(strstr(resp, "{\"index\":8,\"start_sec\":1830,\"end_sec\":2130,\"duration_sec\":300,\"sport\":\"cycling\",\"kind\":\"cooldown\",") != NULL);
+    assert(strstr(resp, "\"index\":9,") == NULL);
+
+    assert(strstr(resp, "\"cues\":[{\"at_sec\":0,\"step\":0,\"type\":\"start\",\"sound\":\"long_beep\",\"text\":\"Warm-up · 10:00 · 138 W (55% FTP) · 90 rpm\",") != NULL);
+    assert(strstr(resp, "{\"at_sec\":300,\"step\":0,\"type\":\"halfway\",\"sound\":\"chime\",\"text\":\"Halfway\",\"speech\":\"Halfway\"}") != NULL);
+    assert(strstr(
+               resp,
+               "{\"at_sec\":590,\"step\":0,\"type\":\"next\",\"sound\":null,\"text\":\"Next: Work 1/3 · 3:00 · 275 W (110% FTP)\","
+               "\"speech\":\"Next: Work 1 of 3, 3 minutes at 275 watts.\"},"
+               "{\"at_sec\":597,\"step\":0,\"type\":\"countdown\",\"sound\":\"beep\",\"text\":\"3\",\"speech\":null},"
+               "{\"at_sec\":598,\"step\":0,\"type\":\"countdown\",\"sound\":\"beep\",\"text\":\"2\",\"speech\":null}") != NULL);
+    assert(strstr(resp, "{\"at_sec\":800,\"step\":2,\"type\":\"next\",") != NULL);
+    assert(strstr(resp, "{\"at_sec\":2130,\"step\":8,\"type\":\"finish\",\"sound\":\"finish\",\"text\":\"Workout complete\",\"speech\":\"Workout complete\"}]}") != NULL);
+
+    run_request(&env.db, "GET /v1/workouts/t1/timer?lang=zh&countdown=0 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"lang\":\"zh\"") != NULL && strstr(resp, "\"countdown_sec\":[]") != NULL && strstr(resp, "\"type\":\"countdown\"") == NULL);
+    assert(strstr(resp, "\"speech\":\"训练第 1 组（共 3 组），3 分钟，功率 275 瓦。\"") != NULL);
+    run_request(&env.db, "GET /v1/workouts/t1/timer?countdown=11 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/workouts/nested/timer HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "cannot be nested") != NULL);
+    run_request(&env.db, "GET /v1/workouts/empty/timer HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "no timed segments") != NULL);
+    run_request(&env.db, "GET /v1/workouts/missing/timer HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    test_env_close(&env);
+}
+
+static unsigned zip_u16(const unsigned char *p) { return (unsigned)p[0] | ((unsigned)p[1] << 8); }
+
+static uint32_t zip_u32(const unsigned char *p) { return (uint32_t)zip_u16(p) | ((uint32_t)zip_u16(p + 2) << 16); }
+
+/* Finds an entry through the central directory and unpacks it into out; returns its length, or -1. */
+static long zip_entry(const unsigned char *zip, size_t zip_len, const char *name, char *out, size_t out_len, int *out_entries) {
+    assert(zip_len >= 22 && zip_u32(zip + zip_len - 22) == 0x06054b50u);
+    const unsigned char *end = zip + zip_len - 22;
+    *out_entries = (int)zip_u16(end + 10);
+    const unsigned char *p = zip + zip_u32(end + 16);
+    for (int i = 0; i < *out_entries; i++) {
+        assert(zip_u32(p) == 0x02014b50u);
+        unsigned name_len = zip_u16(p + 28);
+        size_t entry_len = 46 + name_len + zip_u16(p + 30) + zip_u16(p + 32);
+        if (name_len == strlen(name) && memcmp(p + 46, name, name_len) == 0) {
+            unsigned method = zip_u16(p + 10);
+            uint32_t packed_len = zip_u32(p + 20);
+            uint32_t len = zip_u32(p + 24);
+            const unsigned char *local = zip + zip_u32(p + 42);
+            assert(zip_u32(local) == 0x04034b50u && len < out_len);
+            const unsigned char *data = local + 30 + zip_u16(local + 26) + zip_u16(local + 28);
+            if (method == 0) {
+                memcpy(out, data, len);
+            } else {
+#ifdef FRICU_HAVE_ZLIB
+                z_stream stream;
+                memset(&stream, 0, sizeof(stream));
+                assert(method == 8 && inflateInit2(&stream, -15) == Z_OK);
+                stream.next_in = (Bytef *)data;
+                stream.avail_in = (uInt)packed_len;
+                stream.next_out = (Bytef *)out;
+                stream.avail_out = (uInt)out_len;
+                assert(inflate(&stream, Z_FINISH) == Z_STREAM_END && stream.total_out == len);
+                inflateEnd(&stream);
+#else
+                (void)packed_len;
+                assert(0 && "deflated entry without zlib");
+#endif
+            }
+            out[len] = '\0';
+            assert(zip_crc32(out, len) == zip_u32(p + 16));
+            return (long)len;
+        }
+        p += entry_len;
+    }
+    return -1;
+}
+
+static void test_archive_export(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-archive-XXXXXX");
+    static char resp[262144];
+    char entry[65536] = {0};
+    int entries = 0;
+    const char *profile = "{\"ftp\":240,\"name\":\"Zoë\"}";
+    strbuf_t activities;
+    strbuf_init(&activities);
+    strbuf_append(&activities, "[", 1);
+    for (int i = 0; i < 40; i++) {
+        strbuf_appendf(&activities, "%s{\"id\":\"a%d\",\"date\":\"2025-03-%02d\",\"sport\":\"cycling\",\"tss\":%d}", i ? "," : "", i, i % 28 + 1, 40 + i);
+    }
+    strbuf_append(&activities, "]", 1);
+    put_json(&env.db, "tester", "profile", profile, resp, sizeof(resp));
+    put_json(&env.db, "tester", "activities", strbuf_cstr(&activities), resp, sizeof(resp));
+    put_json(&env.db, "someone-else", "profile", "{\"ftp\":999,\"name\":\"other-account-marker\"}", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    const char *version = strstr(resp, SYNC_VERSION_HEADER ": ");
+    assert(version != NULL);
+    char expected[128] = {0};
+    snprintf(expected, sizeof(expected), "{\"key\":\"profile\",\"file\":\"data/profile.json\",\"version\":\"%.*s\",\"updated_at\":", (int)strcspn(version + 17, "\r"), version + 17);
+
+    run_request(&env.db, "GET /v1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Content-Type: application/zip\r\n") != NULL);
+    assert(strstr(resp, "Content-Disposition: attachment; filename=\"fricu-export-") != NULL);
+    size_t zip_len = 0;
+    const unsigned char *zip = fit_response_body(resp, &zip_len);
+    assert(memcmp(zip, "PK\x03\x04", 4) == 0);
+    assert(zip_entry(zip, zip_len, "data/profile.json", entry, sizeof(entry), &entries) == (long)strlen(profile) && strcmp(entry, profile) == 0);
+    assert(entries == 3);
+    assert(zip_entry(zip, zip_len, "data/activities.json", entry, sizeof(entry), &entries) == (long)activities.len);
+    assert(strcmp(entry, strbuf_cstr(&activities)) == 0);
+    assert(zip_entry(zip, zip_len, "manifest.json", entry, sizeof(entry), &entries) > 0);
+    assert(strncmp(entry, "{\"format\":\"fricu-archive-v1\",\"account_id\":\"tester\",\"exported_at\":", 65) == 0);
+    assert(strstr(entry, "\"keys\":[{\"key\":\"activities\",\"file\":\"data/activities.json\",") != NULL && strstr(entry, expected) != NULL);
+    /* Nothing of another account ends up in any entry. */
+    const char *names[] = {"data/profile.json", "data/activities.json", "manifest.json"};
+    for (size_t i = 0; i < sizeof(names) / sizeof(names[0]); i++) {
+        assert(zip_entry(zip, zip_len, names[i], entry, sizeof(entry), &entries) > 0);
+        assert(strstr(entry, "other-account-marker") == NULL && strstr(entry, "someone-else") == NULL);
+    }
+
+    /* An account with nothing stored still gets a manifest. */
+    run_request(&env.db, "GET /v1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: newcomer\r\n\r\n", resp, sizeof(resp));
+    zip = fit_response_body(resp, &zip_len);
+    assert(zip_entry(zip, zip_len, "manifest.json", entry, sizeof(entry), &entries) > 0 && entries == 1 && strstr(entry, "\"keys\":[]}") != NULL);
+    run_request(&env.db, "POST /v1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+    strbuf_free(&activities);
+    test_env_close(&env);
+}
+
+static void post_archive_import(worker_db_t *db, const char *account, const char *query, const char *body, size_t body_len, char *resp, size_t resp_len) {
+    size_t cap = body_len + 512;
+    char *req = (char *)malloc(cap);
+    assert(req != NULL);
+    int n = snprintf(
+        req,
+        cap,
+        "POST /v1/import%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: %s\r\nContent-Type: application/zip\r\nContent-Length: %zu\r\n\r\n",
+        query,
+        account,
+        body_len);
+    assert(n > 0 && (size_t)n + body_len < cap);
+    memcpy(req + n, body, body_len);
+    run_request_bytes(db, req, (size_t)n + body_len, resp, resp_len);
+    free(req);
+}
+
+static void test_archive_import(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-archive-import-XXXXXX");
+    static char resp[262144];
+    const char *profile = "{\"ftp\":240,\"name\":\"Zoë\"}";
+    const char *activities = "[{\"id\":\"a1\",\"date\":\"2025-03-01\",\"sport\":\"cycling\",\"tss\":60}]";
+    put_json(&env.db, "tester", "profile", profile, resp, sizeof(resp));
+    put_json(&env.db, "tester", "activities", activities, resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    size_t zip_len = 0;
+    const unsigned char *zip = fit_response_body(resp, &zip_len);
+    char *archive = (char *)malloc(zip_len);
+    assert(archive != NULL);
+    memcpy(archive, zip, zip_len);
+
+    /* A dry run reports what would happen and leaves the other account empty. */
+    post_archive_import(&env.db, "copy", "?dry_run=true", archive, zip_len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"dry_run\":true,\"keys\":[{\"key\":\"activities\",\"action\":\"create\",") != NULL);
+    assert(strstr(resp, "\"created\":2,\"replaced\":0,\"unchanged\":0}") != NULL);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: copy\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Zoë") == NULL);
+
+    post_archive_import(&env.db, "copy", "", archive, zip_len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"imported\":true,") != NULL && strstr(resp, "\"created\":2,") != NULL);
+    /* The import went through the writer thread as one journaled batch, and its journal is cleared. */
+    char log_id[96] = {0};
+    const char *log_header = strstr(resp, "X-Log-Id: ");
+    assert(log_header != NULL && sscanf(log_header, "X-Log-Id: %95[^\r]", log_id) == 1);
+    run_request(&env.db, "GET /debug/write-queue HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    char expected[160] = {0};
+    snprintf(expected, sizeof(expected), "\"last_success_logid\":\"%s\"", log_id);
+    assert(strstr(resp, expected) != NULL);
+    DIR *pending = opendir("pending_writes");
+    assert(pending != NULL);
+    int journaled = 0;
+    for (struct dirent *ent = readdir(pending); ent; ent = readdir(pending)) journaled += ent->d_name[0] != '.';
+    closedir(pending);
+    assert(journaled == 0);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: copy\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, profile) != NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: copy\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"a1\"") != NULL);
+    post_archive_import(&env.db, "copy", "", archive, zip_len, resp, sizeof(resp));
+    assert(strstr(resp, "\"created\":0,\"replaced\":0,\"unchanged\":2}") != NULL);
+
+    /* One bad file rejects the whole archive: a stale manifest version and a document of the wrong shape. */
+    zip_writer_t zw;
+    zip_writer_init(&zw);
+    zip_writer_add(&zw, "data/profile.json", "{\"ftp\":300}", 11, 0);
+    zip_writer_add(&zw, "data/activities.json", "{\"id\":\"a2\"}", 11, 0);
+    const char *manifest = "{\"format\":\"fricu-archive-v1\",\"keys\":["
+                           "{\"key\":\"profile\",\"file\":\"data/profile.json\",\"version\":\"stale\"},"
+                           "{\"key\":\"activities\",\"file\":\"data/activities.json\"},"
+                           "{\"key\":\"workouts\",\"file\":\"data/workouts.json\"}]}";
+    zip_writer_add(&zw, "manifest.json", manifest, strlen(manifest), 0);
+    assert(zip_writer_finish(&zw) == 0);
+    post_archive_import(&env.db, "copy", "", strbuf_cstr(&zw.out), zw.out.len, resp, sizeof(resp));
+    assert(strstr(resp, "422 ") != NULL);
+    assert(strstr(resp, "{\"key\":\"profile\",\"file\":\"data/profile.json\",\"error\":\"file does not match the manifest version\"}") != NULL);
+    assert(strstr(resp, "{\"key\":\"activities\",\"file\":\"data/activities.json\",\"error\":\"document is invalid\",\"detail\":{") != NULL);
+    assert(strstr(resp, "{\"key\":\"workouts\",\"file\":\"data/workouts.json\",\"error\":\"file is missing from the archive\"}") != NULL);
+    zip_writer_free(&zw);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: copy\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, profile) != NULL);
+
+    post_archive_import(&env.db, "copy", "", "{\"profile\":{}}", 14, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "body must be an archive from GET /v1/export") != NULL);
+    free(archive);
+    test_env_close(&env);
+}
+
+static void test_workout_fit_export(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-workout-fit-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":250,\"sports\":{\"running\":{\"thresholdPaceSecPerKm\":300}}}", resp, sizeof(resp));
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"w1\",\"name\":\"Sweet Spot!\",\"sport\":\"cycling\",\"segments\":["
+        "{\"minutes\":10,\"intensityPercentFTP\":55,\"cadence\":90,\"note\":\"warm up\"},"
+        "{\"minutes\":5,\"intensityPercentFTP\":105},{\"minutes\":3,\"intensityPercentFTP\":50},"
+        "{\"minutes\":5,\"intensityPercentFTP\":105},{\"minutes\":3,\"intensityPercentFTP\":50},"
+        "{\"minutes\":5,\"intensityPercentFTP\":105},{\"minutes\":3,\"intensityPercentFTP\":50},"
+        "{\"minutes\":10,\"intensityPercentFTP\":50}]},"
+        "{\"id\":\"w2\",\"name\":\"Track\",\"sport\":\"running\",\"segments\":["
+        "{\"repeat\":4,\"segments\":[{\"minutes\":1,\"targetPaceSecPerKm\":250},{\"minutes\":2,\"targetHeartRate\":130}]},"
+        "{\"minutes\":20,\"intensityPercentFTP\":100}]},"
+        "{\"id\":\"w3\",\"name\":\"Nested\",\"segments\":[{\"repeat\":2,\"segments\":[{\"repeat\":2,\"segments\":[]}]}]}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/workouts/w1/export?format=fit HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "Content-Type: application/vnd.ant.fit") != NULL);
+    assert(strstr(resp, "Content-Disposition: attachment; filename=\"Sweet-Spot.fit\"") != NULL);
+    size_t len = 0;
+    const unsigned char *file = fit_response_body(resp, &len);
+    static fit_workout_steps_t steps;
+    decode_fit_workout(file, len, &steps);
+    assert(steps.sport == 2);
+    /* Warm-up, the 5/3 pair folded into a repeat of three, cool-down. */
+    assert(steps.count == 5);
+    assert(steps.fields[0][1] == 0 && steps.fields[0][2] == 600000 && steps.fields[0][7] == 2);
+    assert(steps.fields[0][3] == 4 && steps.fields[0][5] == 50 && steps.fields[0][6] == 60);
+    assert(steps.fields[0][19] == 3 && steps.fields[0][21] == 85 && steps.fields[0][22] == 95);
+    assert(steps.fields[1][2] == 300000 && steps.fields[1][5] == 100 && steps.fields[1][6] == 110 && steps.fields[1][7] == 0);
+    assert(steps.fields[1][19] == 0xFF);
+    assert(steps.fields[2][2] == 180000 && steps.fields[2][7] == 1);
+    assert(steps.fields[3][1] == 6 && steps.fields[3][2] == 1 && steps.fields[3][4] == 3);
+    assert(steps.fields[4][2] == 600000 && steps.fields[4][7] == 3);
+
+    run_request(&env.db, "GET /v1/workouts/w2/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    file = fit_response_body(resp, &len);
+    decode_fit_workout(file, len, &steps);
+    assert(steps.sport == 1);
+    assert(steps.count == 4);
+    /* 250 s/km is 4 m/s; speeds are mm/s, heart rates are offset by 100. */
+    assert(steps.fields[0][3] == 0 && steps.fields[0][5] == 3900 && steps.fields[0][6] == 4100);
+    assert(steps.fields[1][3] == 1 && steps.fields[1][5] == 225 && steps.fields[1][6] == 235);
+    assert(steps.fields[2][1] == 6 && steps.fields[2][2] == 0 && steps.fields[2][4] == 4);
+    assert(steps.fields[3][2] == 1200000 && steps.fields[3][3] == 0 && steps.fields[3][5] == 3250 && steps.fields[3][6] == 3417);
+
+    run_request(&env.db, "GET /v1/workouts/w1/export?format=zwo HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "format must be fit") != NULL);
+    run_request(&env.db, "GET /v1/workouts/w3/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "cannot be nested") != NULL);
+    run_request(&env.db, "GET /v1/workouts/w9/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    run_request(&env.db, "GET /v1/workouts/w1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    test_env_close(&env);
+}
+
+static void test_brick_workout_compliance(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-brick-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":250,\"sports\":{\"running\":{\"thresholdPaceSecPerKm\":300}}}", resp, sizeof(resp));
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"b1\",\"name\":\"Brick\",\"sport\":\"cycling\",\"scheduledDate\":\"2026-05-02T06:00:00Z\",\"segments\":["
+        "{\"minutes\":60,\"intensityPercentFTP\":80},{\"sport\":\"run\",\"minutes\":20,\"intensityPercentFTP\":90},"
+        "{\"repeat\":2,\"sport\":\"running\",\"segments\":[{\"minutes\":5,\"intensityPercentFTP\":100}]}]},"
+        "{\"id\":\"m1\",\"name\":\"Swim\",\"sport\":\"swimming\",\"scheduledDate\":\"2026-05-02\",\"segments\":[{\"minutes\":30}]},"
+        "{\"id\":\"u1\",\"name\":\"Someday\",\"segments\":[{\"minutes\":30}]}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    /* The early run comes before the bike leg, so the brick's run leg takes the later one. */
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"r0\",\"sport\":\"running\",\"date\":\"2026-05-02T05:00:00Z\",\"durationSec\":1800},"
+        "{\"id\":\"b1\",\"sport\":\"ride\",\"date\":\"2026-05-02T06:00:00Z\",\"durationSec\":3600,\"tss\":48},"
+        "{\"id\":\"r1\",\"sport\":\"running\",\"date\":\"2026-05-02T07:03:00Z\",\"durationSec\":1500},"
+        "{\"id\":\"x1\",\"sport\":\"strength\",\"date\":\"2026-05-02T18:00:00Z\",\"durationSec\":2400},"
+        "{\"id\":\"b0\",\"sport\":\"cycling\",\"date\":\"2026-05-01T06:00:00Z\",\"durationSec\":3600}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/workouts/b1/compliance HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"workout_id\":\"b1\",\"name\":\"Brick\",\"date\":\"2026-05-02\",\"legs\":[") != NULL);
+    assert(strstr(
+               resp,
+               "{\"index\":0,\"sport\":\"cycling\",\"planned_sec\":3600,\"planned_load\":64.0,\"activity_id\":\"b1\",\"status\":\"completed\","
+               "\"score\":90.0,\"actual_sec\":3600,\"actual_load\":48.0}") != NULL);
+    assert(strstr(
+               resp,
+               "{\"index\":1,\"sport\":\"running\",\"planned_sec\":1800,\"planned_load\":43.7,\"activity_id\":\"r1\",\"status\":\"completed\","
+               "\"score\":83.3,\"actual_sec\":1500,\"actual_load\":0.0,\"transition_sec\":180}") != NULL);
+    assert(strstr(resp, "],\"multisport\":true,\"matched\":2,\"score\":87.8,\"status\":\"completed\"}") != NULL);
+
+    run_request(&env.db, "GET /v1/workouts/m1/compliance HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"activity_id\":null,\"status\":\"missed\",\"score\":0}],\"multisport\":false,\"matched\":0,\"score\":0.0,\"status\":\"missed\"}") != NULL);
+    run_request(&env.db, "GET /v1/workouts/u1/compliance HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    run_request(&env.db, "GET /v1/workouts/zz/compliance HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    /* In the FIT file the run steps get pace targets from the running threshold, not power. */
+    run_request(&env.db, "GET /v1/workouts/b1/export HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    size_t len = 0;
+    const unsigned char *file = fit_response_body(resp, &len);
+    static fit_workout_steps_t steps;
+    decode_fit_workout(file, len, &steps);
+    assert(steps.sport == 2 && steps.count == 4);
+    assert(steps.fields[0][3] == 4 && steps.fields[0][5] == 75 && steps.fields[0][6] == 85);
+    assert(steps.fields[1][3] == 0 && steps.fields[1][5] == 2925 && steps.fields[1][6] == 3075);
+    assert(steps.fields[2][3] == 0 && steps.fields[3][1] == 6 && steps.fields[3][4] == 2);
+    test_env_close(&env);
+}
+
+static void live_ingest_batch(worker_db_t *db, long long first_t, int count, int skip_from, int skip_to, const char *extra, char *resp, size_t resp_len) {
+    static char body[131072];
+    static char req[140000];
+    int len = snprintf(body, sizeof(body), "{\"session_id\":\"trainer-1\",\"sport\":\"cycling\"%s,\"samples\":[", extra);
+    int written = 0;
+    for (int i = 0; i < count; i++) {
+        if (i >= skip_from && i < skip_to) continue;
+        len += snprintf(
+            body + len, sizeof(body) - (size_t)len, "%s{\"t\":%lld,\"power\":200,\"hr\":140,\"cadence\":90}", written == 0 ? "" : ",", first_t + i);
+        written++;
+    }
+    snprintf(body + len, sizeof(body) - (size_t)len, "]}");
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/live/ingest HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_live_ingest_finalizes_activity(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-live-XXXXXX");
+    static char resp[262144];
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":200}", resp, sizeof(resp));
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"existing\",\"date\":\"2024-04-01T07:00:00Z\",\"tss\":50}]", resp, sizeof(resp));
+
+    live_ingest_batch(&env.db, 1714550000, 600, 300, 303, "", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"session_id\":\"trainer-1\",\"status\":\"open\",\"accepted\":597,\"buffered\":597}") != NULL);
+    live_ingest_batch(&env.db, 1714550600, 600, 0, 0, ",\"close\":true", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "\"status\":\"finalized\"") != NULL);
+    assert(strstr(resp, "\"date\":\"2024-05-01T07:53:20Z\",\"durationSec\":1200,\"tss\":33,\"normalizedPower\":200,\"avgPower\":200,\"avgHeartRate\":140,\"samples\":1197}") != NULL);
+
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"existing\"") != NULL);
+    assert(strstr(resp, "\"externalID\":\"live:trainer-1\"") != NULL);
+    assert(strstr(resp, "\"powerSamples\":[200,200") != NULL);
+
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT COUNT(*) FROM live_samples", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW);
+    assert(sqlite3_column_int(stmt, 0) == 0);
+    sqlite3_finalize(stmt);
+
+    live_ingest_batch(&env.db, 1714550000, 0, 0, 0, ",\"close\":true", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    run_request(
+        &env.db,
+        "POST /v1/live/ingest HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 33\r\n\r\n{\"session_id\":\"x y\",\"samples\":[]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    test_env_close(&env);
+}
+
+static void test_sync_manifest_and_version_conflict(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sync-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "X-Fricu-Version: 0\r\n") != NULL);
+
+    const char *body = "[{\"name\":\"a\"}]";
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Base-Version: 0\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(body),
+        body);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    char version[32] = {0};
+    content_version(body, strlen(body), version, sizeof(version));
+    char expected[64] = {0};
+    snprintf(expected, sizeof(expected), "X-Fricu-Version: %s\r\n", version);
+    assert(strstr(resp, expected) != NULL);
+
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    assert(strstr(resp, version) != NULL);
+
+    run_request(&env.db, "GET /v1/sync/manifest HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "X-Fricu-Sync-Protocol: 1\r\n") != NULL);
+    snprintf(expected, sizeof(expected), "{\"key\":\"workouts\",\"version\":\"%s\"", version);
+    assert(strstr(resp, expected) != NULL);
+    run_request(&env.db, "GET /v1/sync/manifest?since=99999999999 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"keys\":[]") != NULL);
+    run_request(&env.db, "GET /v1/sync/manifest HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"keys\":[]") != NULL);
+    run_request(
+        &env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Sync-Protocol: 2\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    test_env_close(&env);
+}
+
+static int deny_kv_store_reads(void *arg, int action, const char *table, const char *column, const char *db_name, const char *trigger) {
+    (void)arg;
+    (void)column;
+    (void)db_name;
+    (void)trigger;
+    return action == SQLITE_READ && table && strcmp(table, "kv_store") == 0 ? SQLITE_DENY : SQLITE_OK;
+}
+
+static void test_version_check_fails_closed(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sync-closed-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+    put_json(&env.db, "tester", "workouts", "[{\"name\":\"a\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* A base version that cannot be compared must not let a stale write through. */
+    const char *body = "[{\"name\":\"stale\"}]";
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Base-Version: 0\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(body),
+        body);
+    sqlite3_set_authorizer(env.db.db, deny_kv_store_reads, NULL);
+    run_request(&env.db, req, resp, sizeof(resp));
+    sqlite3_set_authorizer(env.db.db, NULL, NULL);
+    assert(strstr(resp, "500 Internal Server Error") != NULL && strstr(resp, "{\"error\":\"database error\"}") != NULL);
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"name\":\"a\"") != NULL && strstr(resp, "stale") == NULL);
+    test_env_close(&env);
+}
+
+static void send_item_request(worker_db_t *db, const char *method, const char *path, const char *body, char *resp, size_t resp_len) {
+    char req[4096] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "%s %s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n%s",
+        method,
+        path,
+        body ? strlen(body) : 0,
+        body ? body : "");
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_v2_items_share_v1_documents(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-v2-XXXXXX");
+    char resp[16384] = {0};
+
+    run_request(&env.db, "GET /v2/data/workouts/items HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"a\",\"name\":\"Sweet spot\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "Deprecation: true\r\n") != NULL);
+    assert(strstr(resp, "Sunset: " API_V1_DATA_SUNSET "\r\n") != NULL);
+
+    send_item_request(&env.db, "PUT", "/v2/data/workouts/items/b", "{\"name\":\"VO2\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "X-Fricu-Version: ") != NULL);
+    send_item_request(&env.db, "PUT", "/v2/data/workouts/items/a", "{\"id\":\"a\",\"name\":\"Threshold\"}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "PUT", "/v2/data/workouts/items/a", "{\"id\":\"z\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PUT", "/v2/data/workouts/items/a", "[1]", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "[{\"id\":\"a\",\"name\":\"Threshold\"},{\"name\":\"VO2\",\"id\":\"b\"}]") != NULL);
+    assert(strstr(resp, "rel=\"successor-version\"") != NULL);
+
+    send_item_request(&env.db, "GET", "/v2/data/workouts/items/b", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"name\":\"VO2\",\"id\":\"b\"}") != NULL);
+    send_item_request(&env.db, "GET", "/v2/data/workouts/items?offset=1&limit=1", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"total\":2,\"offset\":1,\"limit\":1,\"items\":[{\"name\":\"VO2\",\"id\":\"b\"}]}") != NULL);
+
+    send_item_request(&env.db, "DELETE", "/v2/data/workouts/items/a", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "DELETE", "/v2/data/workouts/items/a", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v2/data/profile/items", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Deprecation:") == NULL);
+    test_env_close(&env);
+}
+
+static void test_v1_items_mutate_single_records(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-v1-items-XXXXXX");
+    char resp[16384] = {0};
+    char path[128] = {0};
+
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"tss\":40}]", resp, sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/data/activities/items", "{\"tss\":55}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "Deprecation:") == NULL);
+    const char *id = strstr(resp, "{\"id\":\"");
+    assert(id != NULL && strstr(resp, "\"created\":true}") != NULL);
+    snprintf(path, sizeof(path), "/v1/data/activities/items/%.36s", id + 7);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"tss\":55,\"id\":\"") != NULL);
+
+    send_item_request(&env.db, "POST", "/v1/data/activities/items", "{\"id\":\"a2\",\"tss\":70}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "{\"id\":\"a2\",\"created\":true}") != NULL);
+    send_item_request(&env.db, "POST", "/v1/data/activities/items", "{\"id\":\"a1\",\"tss\":99}", resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    send_item_request(&env.db, "POST", "/v1/data/activities/items", "{\"id\":\"bad id\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "POST", "/v1/data/activities/items/a1", "{\"tss\":1}", resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+
+    send_item_request(&env.db, "PUT", "/v1/data/activities/items/a1", "{\"tss\":45}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "[{\"tss\":45,\"id\":\"a1\"},{\"id\":\"a2\",\"tss\":70}]") != NULL);
+
+    /* The remaining collections named in the docs, plus POST on v2. */
+    static const char *const keys[] = {"workouts", "events", "meal_plans", "custom_foods"};
+    for (size_t i = 0; i < sizeof(keys) / sizeof(keys[0]); i++) {
+        snprintf(path, sizeof(path), "/v1/data/%s/items", keys[i]);
+        send_item_request(&env.db, "POST", path, "{\"id\":\"x1\",\"name\":\"n\"}", resp, sizeof(resp));
+        assert(strstr(resp, "201 Created") != NULL);
+        send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+        assert(strstr(resp, "\"total\":1,\"offset\":0,\"limit\":100,\"items\":[{\"id\":\"x1\",\"name\":\"n\"}]}") != NULL);
+    }
+    send_item_request(&env.db, "POST", "/v2/data/workouts/items", "{\"name\":\"VO2\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    send_item_request(&env.db, "POST", "/v1/data/profile/items", "{\"name\":\"n\"}", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    test_env_close(&env);
+}
+
+static void test_admin_capture_ring_buffer(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-capture-XXXXXX");
+    char resp[16384] = {0};
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    run_request(&env.db, "GET /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    run_request(&env.db, "GET /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: wrong!\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    const char *enable = "{\"account_id\":\"watch\",\"key\":\"activities\",\"limit\":2}";
+    char req[2048] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(enable),
+        enable);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    put_json(&env.db, "other", "activities", "[]", resp, sizeof(resp));
+    put_json(&env.db, "watch", "workouts", "[]", resp, sizeof(resp));
+    run_request(
+        &env.db,
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: watch\r\nAuthorization: Bearer abc\r\nContent-Length: 6\r\n\r\n[{\"x\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    put_json(&env.db, "watch", "activities", "[]", resp, sizeof(resp));
+    put_json(&env.db, "watch", "activities", "[1]", resp, sizeof(resp));
+
+    run_request(&env.db, "GET /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"enabled\":false,\"remaining\":0") != NULL);
+    assert(strstr(resp, "\"seq\":1,") != NULL);
+    assert(strstr(resp, "\"seq\":2,") != NULL);
+    assert(strstr(resp, "\"seq\":3,") == NULL);
+    assert(strstr(resp, "Authorization: [redacted]") != NULL);
+    assert(strstr(resp, "Bearer abc") == NULL);
+    assert(strstr(resp, "\"request_body\":\"[{\\\"x\\\"}\"") != NULL);
+    assert(strstr(resp, "\"status\":400") != NULL);
+    assert(strstr(resp, "\"status\":204") != NULL);
+    assert(strstr(resp, "\"account_id\":\"other\"") == NULL);
+
+    run_request(&env.db, "DELETE /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"entries\":[]") != NULL);
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_admin_capture_redacts_secrets(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-capture-secrets-XXXXXX");
+    static char resp[65536];
+    char req[2048] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    const char *enable = "{\"account_id\":\"leaky\",\"limit\":10}";
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(enable),
+        enable);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    const char *profile = "{\"ftp\":250,\"password\" : \"hunter2\",\"sync\":{\"refresh_token\":\"rt-\\\"42\",\"client_secret\":\"cs-9\"},\"tokens\":3}";
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: leaky\r\nX-Coach-Token: ct-abc\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(profile),
+        profile);
+    run_request(&env.db, req, resp, sizeof(resp));
+    run_request(&env.db, "POST /v1/devices HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: leaky\r\nContent-Length: 16\r\n\r\n{\"name\":\"watch\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL || strstr(resp, "200 OK") != NULL);
+    char device_token[96] = {0};
+    assert(sscanf(strstr(resp, "{\"token\":\""), "{\"token\":\"%95[^\"]\"", device_token) == 1);
+    run_request(&env.db, "POST /inbound/email/inboxsecret42 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: leaky\r\nContent-Length: 2\r\n\r\n{}", resp, sizeof(resp));
+
+    run_request(&env.db, "GET /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"seq\":3,") != NULL);
+    assert(strstr(resp, "hunter2") == NULL && strstr(resp, "rt-") == NULL && strstr(resp, "cs-9") == NULL && strstr(resp, "ct-abc") == NULL);
+    assert(strstr(resp, "\\\"password\\\" : \\\"[redacted]\\\"") != NULL && strstr(resp, "\\\"tokens\\\":3") != NULL);
+    assert(strstr(resp, "X-Coach-Token: [redacted]") != NULL);
+    assert(strstr(resp, device_token) == NULL && strstr(resp, "[omitted: response carries a credential]") != NULL);
+    assert(strstr(resp, "inboxsecret42") == NULL && strstr(resp, "\"path\":\"/inbound/email/[redacted]\"") != NULL);
+
+    run_request(&env.db, "DELETE /v1/admin/captures HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_admin_stats_tracks_slow_endpoints(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-slowlog-XXXXXX");
+    char resp[16384] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    slowlog_configure(0, 0);
+
+    put_json(&env.db, "tester", "activities", "[]", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    run_request(&env.db, "DELETE /v1/devices/dev_0123 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    run_request(&env.db, "DELETE /v1/devices/dev_4567 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+
+    run_request(&env.db, "GET /v1/admin/stats?top=5 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"slow_request_ms\":0.0") != NULL);
+    assert(strstr(resp, "\"slow_requests\":4") != NULL);
+    assert(strstr(resp, "\"slow_queries\":0") == NULL);
+    assert(strstr(resp, "{\"endpoint\":\"PUT /v1/data/activities\",\"count\":1,\"slow_count\":1") != NULL);
+    assert(strstr(resp, "{\"endpoint\":\"DELETE /v1/devices/:id\",\"count\":2,") != NULL);
+    assert(strstr(resp, "dev_0123") == NULL);
+
+    slowlog_configure(-1, -1);
+    run_request(&env.db, "GET /v1/admin/stats HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"slow_request_ms\":500.0,\"slow_query_ms\":100.0},\"slow_requests\":0") != NULL);
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_debug_profiling_endpoints(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-profiling-XXXXXX");
+    char resp[16384] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+
+    run_request(&env.db, "GET /v1/admin/debug/heap HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    assert(strstr(resp, "--debug-profiling") != NULL);
+
+    profiling_enable(3);
+    run_request(&env.db, "GET /v1/admin/debug/heap HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(&env.db, "GET /v1/admin/debug/heap HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"sqlite\":{\"memory_used_bytes\":") != NULL);
+    assert(strstr(resp, "\"allocator\":") != NULL);
+    run_request(&env.db, "GET /v1/admin/debug/runtime HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"worker_threads\":3,") != NULL);
+    assert(strstr(resp, "\"in_flight_requests\":1,") != NULL);
+    assert(strstr(resp, "\"write_queue\":{\"running\":") != NULL);
+
+    profiling_disable();
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_json_stream_validate(void) {
+    json_stream_result_t r;
+    const char *ok = " [{\"a\":[1,-2.5e3,true,null],\"b\":\"x\\u00e9\\n\"},{},[]] ";
+    assert(json_stream_validate(ok, strlen(ok), &r) == 0);
+    assert(r.valid && r.root == '[' && r.top_level_items == 3 && r.max_depth == 3);
+    assert(json_stream_validate("{}", 2, &r) == 0 && r.root == '{' && r.top_level_items == 0);
+
+    const char *bad[] = {"", "[1,]", "[01]", "{\"a\" 1}", "[\"\\x\"]", "[1] x", "[tru]", "{\"a\":1,}", "[\"a"};
+    for (size_t i = 0; i < sizeof(bad) / sizeof(bad[0]); i++) {
+        assert(json_stream_validate(bad[i], strlen(bad[i]), &r) != 0);
+        assert(!r.valid && r.error != NULL);
+    }
+    assert(json_stream_validate("[1,]", 4, &r) != 0 && r.error_offset == 3);
+
+    char deep[JSON_STREAM_MAX_DEPTH * 2 + 4] = {0};
+    memset(deep, '[', JSON_STREAM_MAX_DEPTH + 1);
+    memset(deep + JSON_STREAM_MAX_DEPTH + 1, ']', JSON_STREAM_MAX_DEPTH + 1);
+    assert(json_stream_validate(deep, strlen(deep), &r) != 0);
+    assert(strcmp(r.error, "nesting too deep") == 0);
+}
+
+static void test_large_put_uses_streaming_validation(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-stream-XXXXXX");
+    char resp[16384] = {0};
+
+    strbuf_t body;
+    strbuf_init(&body);
+    strbuf_append(&body, "[", 1);
+    for (int i = 0; i < 4000; i++) {
+        strbuf_appendf(&body, "%s{\"id\":\"w%d\",\"name\":\"Endurance block\",\"tss\":%d}", i == 0 ? "" : ",", i, i % 120);
+    }
+    strbuf_append(&body, "]", 1);
+    assert(body.len >= JSON_STREAM_MIN_BYTES);
+
+    strbuf_t req;
+    strbuf_init(&req);
+    strbuf_appendf(&req, "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n", body.len);
+    strbuf_append(&req, strbuf_cstr(&body), body.len);
+    run_request(&env.db, strbuf_cstr(&req), resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Same size, broken near the end: the streaming path reports where parsing stopped. */
+    req.data[req.len - 2] = ',';
+    run_request(&env.db, strbuf_cstr(&req), resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    assert(strstr(resp, "\"detail\":\"expected object key\"") != NULL);
+
+    put_json(&env.db, "tester", "workouts", "{\"name\":\"x\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    assert(strstr(resp, "workouts must be a JSON array") != NULL);
+    put_json(&env.db, "tester", "profile", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "profile must be a JSON object") != NULL);
+
+    strbuf_free(&req);
+    strbuf_free(&body);
+    test_env_close(&env);
+}
+
+static void send_delta(worker_db_t *db, const char *base, const char *patch, char *resp, size_t resp_len) {
+    char req[4096] = {0};
+    char base_header[96] = {0};
+    if (base) snprintf(base_header, sizeof(base_header), "X-Fricu-Base-Version: %s\r\n", base);
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Type: application/x-fricu-delta\r\n%s"
+        "Content-Length: %zu\r\n\r\n%s",
+        base_header,
+        strlen(patch),
+        patch);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_delta_upload_applies_json_patch(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-delta-XXXXXX");
+    char resp[16384] = {0};
+
+    const char *base_doc = "[{\"id\":\"a\",\"tss\":1},{\"id\":\"b\"}]";
+    put_json(&env.db, "tester", "workouts", base_doc, resp, sizeof(resp));
+    char base[32] = {0};
+    content_version(base_doc, strlen(base_doc), base, sizeof(base));
+
+    const char *patch =
+        "[{\"op\":\"test\",\"path\":\"/0/id\",\"value\":\"a\"},"
+        "{\"op\":\"replace\",\"path\":\"/0/tss\",\"value\":5},"
+        "{\"op\":\"add\",\"path\":\"/1\",\"value\":{\"id\":\"mid\"}},"
+        "{\"op\":\"add\",\"path\":\"/-\",\"value\":{\"id\":\"z\"}},"
+        "{\"op\":\"remove\",\"path\":\"/2\"},"
+        "{\"op\":\"copy\",\"from\":\"/0/tss\",\"path\":\"/1/tss\"},"
+        "{\"op\":\"move\",\"from\":\"/2\",\"path\":\"/0\"}]";
+    send_delta(&env.db, NULL, patch, resp, sizeof(resp));
+    assert(strstr(resp, "428 Precondition Required") != NULL);
+    send_delta(&env.db, "0", patch, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+
+    send_delta(&env.db, base, patch, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    const char *expected = "[{\"id\":\"z\"},{\"id\":\"a\",\"tss\":5},{\"id\":\"mid\",\"tss\":5}]";
+    char version[32] = {0};
+    char header[64] = {0};
+    content_version(expected, strlen(expected), version, sizeof(version));
+    snprintf(header, sizeof(header), "X-Fricu-Version: %s\r\n", version);
+    assert(strstr(resp, header) != NULL);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, expected) != NULL);
+
+    send_delta(&env.db, version, "[{\"op\":\"remove\",\"path\":\"/0\"},{\"op\":\"test\",\"path\":\"/0/id\",\"value\":\"nope\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL);
+    assert(strstr(resp, "\"op_index\":1") != NULL);
+    send_delta(&env.db, version, "[{\"op\":\"add\",\"path\":\"/9\",\"value\":1}]", resp, sizeof(resp));
+    assert(strstr(resp, "array index out of range") != NULL);
+    send_delta(&env.db, version, "[{\"op\":\"replace\",\"path\":\"\",\"value\":{}}]", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, expected) != NULL);
+    test_env_close(&env);
+}
+
+static void test_cluster_lease_roles(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-cluster-XXXXXX");
+    char resp[16384] = {0};
+
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"status\":\"ok\",\"role\":\"standalone\"}") != NULL);
+
+    cluster_configure("replica-a", "http://10.0.0.1:8080", 15);
+    assert(cluster_renew(env.db.db) == 1);
+    cluster_configure("replica-b", "http://10.0.0.2:8080", 15);
+    assert(cluster_renew(env.db.db) == 0);
+    assert(!cluster_is_writer());
+
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"role\":\"follower\",\"instance\":\"replica-b\",\"leader\":\"replica-a\",\"leader_url\":\"http://10.0.0.1:8080\"") != NULL);
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "503 Service Unavailable") != NULL);
+    assert(strstr(resp, "Retry-After: 1\r\n") != NULL);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    /* Once the leader stops renewing, the follower takes the lease over. */
+    assert(sqlite3_exec(env.db.db, "UPDATE cluster_lease SET expires_at = 0", NULL, NULL, NULL) == SQLITE_OK);
+    assert(cluster_renew(env.db.db) == 1);
+    assert(cluster_is_writer());
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    cluster_configure("replica-a", "", 15);
+    assert(cluster_renew(env.db.db) == 0);
+
+    cluster_configure(NULL, NULL, 0);
+    assert(cluster_is_writer());
+    test_env_close(&env);
+}
+
+static void test_server_timing_on_debug_header(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-timing-XXXXXX");
+    char resp[16384] = {0};
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "Server-Timing:") == NULL);
+
+    const char *put_req =
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Debug-Timing: 1\r\n"
+        "Content-Type: application/json\r\nContent-Length: 2\r\n\r\n[]";
+    run_request(&env.db, put_req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    const char *timing = strstr(resp, "\r\nServer-Timing: queue;dur=");
+    assert(timing != NULL);
+    const char *timing_end = strstr(timing + 2, "\r\n");
+    const char *stages[] = {", checkout;dur=", ", db;dur=", " statements\", serialize;dur=", ", total;dur="};
+    for (size_t i = 0; i < sizeof(stages) / sizeof(stages[0]); i++) {
+        const char *stage = strstr(timing, stages[i]);
+        assert(stage != NULL && stage < timing_end);
+    }
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Debug-Timing: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Server-Timing:") == NULL);
+    test_env_close(&env);
+}
+
+typedef struct {
+    int count;
+    change_event_t last;
+} captured_changes_t;
+
+static void capture_change(const change_event_t *event, void *user) {
+    captured_changes_t *captured = (captured_changes_t *)user;
+    captured->count++;
+    captured->last = *event;
+}
+
+static void test_change_events_and_redis_bridge(void) {
+    redis_config_t config;
+    assert(redis_parse_url("redis://cache.internal", &config) == 0);
+    assert(strcmp(config.host, "cache.internal") == 0 && config.port == 6379 && config.db == 0);
+    assert(redis_parse_url("redis://:s3cret@10.0.0.5:6380/2", &config) == 0);
+    assert(strcmp(config.host, "10.0.0.5") == 0 && config.port == 6380 && config.db == 2);
+    assert(strcmp(config.password, "s3cret") == 0 && config.username[0] == '\0');
+    assert(redis_parse_url("redis://fricu:pw@localhost/", &config) == 0);
+    assert(strcmp(config.username, "fricu") == 0 && strcmp(config.password, "pw") == 0);
+    assert(redis_parse_url("http://localhost:6379", &config) != 0);
+    assert(redis_parse_url("redis://localhost:0", &config) != 0);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    const char *argv[] = {"PUBLISH", "fricu:changes", "{}"};
+    assert(redis_encode_command(&sb, 3, argv) == 0);
+    assert(strcmp(strbuf_cstr(&sb), "*3\r\n$7\r\nPUBLISH\r\n$13\r\nfricu:changes\r\n$2\r\n{}\r\n") == 0);
+    strbuf_free(&sb);
+
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-events-XXXXXX");
+    char resp[16384] = {0};
+    captured_changes_t captured;
+    memset(&captured, 0, sizeof(captured));
+    int listener = change_events_subscribe(capture_change, &captured);
+    assert(listener > 0);
+
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    assert(captured.count == 1);
+    assert(strcmp(captured.last.account_id, "tester") == 0 && strcmp(captured.last.key, "workouts") == 0);
+    assert(strcmp(captured.last.origin, server_instance_id()) == 0 && !captured.last.remote);
+    char version[32] = {0};
+    content_version("[{\"id\":\"w1\"}]", 13, version, sizeof(version));
+    assert(strcmp(captured.last.version, version) == 0);
+
+    /* Events written by other instances are dispatched as remote; our own echo is dropped. */
+    const char *remote = "{\"v\":1,\"origin\":\"replica-b\",\"account_id\":\"tester\",\"key\":\"profile\",\"version\":\"abc\",\"updated_at\":42}";
+    assert(redis_handle_message(remote, "replica-a") == 1);
+    assert(captured.count == 2 && captured.last.remote && captured.last.updated_at == 42);
+    assert(strcmp(captured.last.key, "profile") == 0 && strcmp(captured.last.origin, "replica-b") == 0);
+    assert(redis_handle_message(remote, "replica-b") == 0);
+    assert(redis_handle_message("not json", "replica-a") == -1);
+    assert(redis_handle_message("{\"v\":2}", "replica-a") == -1);
+    assert(captured.count == 2);
+
+    change_events_unsubscribe(listener);
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    assert(captured.count == 2);
+
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"redis\"") == NULL);
+    test_env_close(&env);
+}
+
+static void upload_attachment(worker_db_t *db, const char *query, const char *content_type, const unsigned char *data, size_t len, char *resp, size_t resp_len) {
+    char *req = (char *)malloc(len + 1024);
+    assert(req != NULL);
+    int header_len = snprintf(
+        req,
+        1024,
+        "POST /v1/attachments?%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Type: %s\r\nContent-Length: %zu\r\n\r\n",
+        query,
+        content_type,
+        len);
+    memcpy(req + header_len, data, len);
+    run_request_bytes(db, req, (size_t)header_len + len, resp, resp_len);
+    free(req);
+}
+
+static void test_attachments_upload_thumbnail_and_sharing(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-attachments-XXXXXX");
+    static char resp[256 * 1024];
+    char id[64] = {0};
+
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"ride-1\",\"sport\":\"cycling\"}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "events", "[{\"id\":42,\"name\":\"Gran Fondo\"}]", resp, sizeof(resp));
+
+    const unsigned char pdf[] = "%PDF-1.4\n% race plan\n";
+    upload_attachment(&env.db, "owner_type=event&owner_id=42&filename=race%20plan.pdf", "application/pdf", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "\"owner_type\":\"event\",\"owner_id\":\"42\",\"filename\":\"race plan.pdf\",\"content_type\":\"application/pdf\",\"size\":21") != NULL);
+    assert(strstr(resp, "\"width\":null,\"height\":null,\"has_thumbnail\":false,\"shared\":false") != NULL);
+    const char *id_field = strstr(resp, "{\"id\":\"att_");
+    assert(id_field != NULL);
+    assert(sscanf(id_field, "{\"id\":\"%63[^\"]\"", id) == 1);
+
+    char path[160] = {0};
+    snprintf(path, sizeof(path), "/v1/attachments/%s", id);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "Content-Type: application/pdf\r\n") != NULL);
+    assert(strstr(resp, "Content-Disposition: inline; filename=\"race plan.pdf\"\r\n") != NULL);
+    assert(strstr(resp, "\r\n\r\n%PDF-1.4\n% race plan\n") != NULL);
+
+    snprintf(path, sizeof(path), "/v1/attachments/%s/thumbnail", id);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL && strstr(resp, "no thumbnail") != NULL);
+
+    /* The bytes sit in a file named by their hash; the same upload twice shares it until both are gone. */
+    char hash[65] = {0};
+    char blob[256] = {0};
+    struct stat st;
+    sha256_hex(pdf, sizeof(pdf) - 1, hash, sizeof(hash));
+    snprintf(blob, sizeof(blob), "state.db-attachments/%.2s/%s", hash, hash);
+    assert(stat(blob, &st) == 0 && st.st_size == 21);
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT blob_sha256, (SELECT COUNT(*) FROM pragma_table_info('attachments') WHERE type = 'BLOB') FROM attachments", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), hash) == 0 && sqlite3_column_int(stmt, 1) == 0);
+    sqlite3_finalize(stmt);
+    char dup_id[64] = {0};
+    upload_attachment(&env.db, "owner_type=event&owner_id=42&filename=copy.pdf", "application/pdf", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && sscanf(strstr(resp, "{\"id\":\"att_"), "{\"id\":\"%63[^\"]\"", dup_id) == 1);
+    snprintf(path, sizeof(path), "/v1/attachments/%s", dup_id);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && stat(blob, &st) == 0);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    /* Rejected uploads: unknown media type, mismatched body, unknown owner. */
+    upload_attachment(&env.db, "owner_type=event&owner_id=42", "text/plain", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "415 Unsupported Media Type") != NULL);
+    upload_attachment(&env.db, "owner_type=event&owner_id=42", "image/png", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "does not match content type") != NULL);
+    upload_attachment(&env.db, "owner_type=activity&owner_id=missing", "application/pdf", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL && strstr(resp, "unknown activity") != NULL);
+    upload_attachment(&env.db, "owner_type=workout&owner_id=1", "application/pdf", pdf, sizeof(pdf) - 1, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+#ifdef FRICU_HAVE_IMAGE_CODECS
+    int width = 640;
+    int height = 400;
+    unsigned char *rgb = (unsigned char *)malloc((size_t)width * (size_t)height * 3);
+    assert(rgb != NULL);
+    for (int i = 0; i < width * height; i++) {
+        rgb[i * 3] = (unsigned char)(i % width * 255 / width);
+        rgb[i * 3 + 1] = 90;
+        rgb[i * 3 + 2] = 200;
+    }
+    unsigned char *jpeg = NULL;
+    size_t jpeg_len = 0;
+    assert(image_encode_jpeg(rgb, width, height, 90, &jpeg, &jpeg_len) == 0);
+    free(rgb);
+    upload_attachment(&env.db, "owner_type=activity&owner_id=ride-1&filename=summit.jpg&shared=1", "image/jpeg", jpeg, jpeg_len, resp, sizeof(resp));
+    free(jpeg);
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "\"width\":640,\"height\":400,\"has_thumbnail\":true,\"shared\":true") != NULL);
+    char photo_id[64] = {0};
+    assert(sscanf(strstr(resp, "{\"id\":\"att_"), "{\"id\":\"%63[^\"]\"", photo_id) == 1);
+
+    snprintf(path, sizeof(path), "/v1/attachments/%s/thumbnail", photo_id);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Content-Type: image/jpeg\r\n") != NULL);
+    const char *thumb = strstr(resp, "\r\n\r\n") + 4;
+    assert((unsigned char)thumb[0] == 0xff && (unsigned char)thumb[1] == 0xd8);
+    int thumb_len = atoi(strstr(resp, "Content-Length: ") + 16);
+    assert(thumb_len > 0 && (size_t)thumb_len < jpeg_len);
+
+    /* The shared filter is what share links use to pick attachments. */
+    send_item_request(&env.db, "GET", "/v1/attachments?shared=1", NULL, resp, sizeof(resp));
+    assert(strstr(resp, photo_id) != NULL && strstr(resp, id) == NULL);
+    send_item_request(&env.db, "PATCH", path, "{\"shared\":false}", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    snprintf(path, sizeof(path), "/v1/attachments/%s", photo_id);
+    send_item_request(&env.db, "PATCH", path, "{\"shared\":false}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"shared\":false") != NULL);
+    send_item_request(&env.db, "GET", "/v1/attachments?shared=1", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"attachments\":[]}") != NULL);
+#endif
+
+    snprintf(path, sizeof(path), "/v1/attachments/%s", id);
+    send_item_request(&env.db, "PATCH", path, "{\"shared\":\"yes\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PATCH", path, "{\"shared\":true,\"filename\":\"plan\\\"v2.pdf\"}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"filename\":\"planv2.pdf\"") != NULL && strstr(resp, "\"shared\":true") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/attachments?owner_type=event&owner_id=42", NULL, resp, sizeof(resp));
+    assert(strstr(resp, id) != NULL);
+    send_item_request(&env.db, "GET", "/v1/attachments?owner_type=activity&owner_id=ride-1&shared=1", NULL, resp, sizeof(resp));
+    assert(strstr(resp, id) == NULL);
+
+    run_request(&env.db, "GET /v1/attachments HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: someone-else\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"attachments\":[]}") != NULL);
+
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && stat(blob, &st) != 0);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL && strstr(resp, "unknown attachment") != NULL);
+    test_env_close(&env);
+}
+
+static void test_attachment_blobs_move_out_of_database(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-attachment-blobs-XXXXXX");
+    sqlite3 *legacy = NULL;
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    assert(
+        sqlite3_exec(
+            legacy,
+            "CREATE TABLE attachments (id TEXT PRIMARY KEY, account_id TEXT NOT NULL, owner_type TEXT NOT NULL, owner_id TEXT NOT NULL,"
+            " filename TEXT NOT NULL, content_type TEXT NOT NULL, size INTEGER NOT NULL, width INTEGER, height INTEGER,"
+            " data BLOB NOT NULL, thumbnail BLOB, shared INTEGER NOT NULL DEFAULT 0, created_at INTEGER NOT NULL);"
+            "INSERT INTO attachments VALUES ('att_a', 'tester', 'event', '42', 'plan.pdf', 'application/pdf', 5, NULL, NULL, x'255044462d', NULL, 0, 0);"
+            "INSERT INTO attachments VALUES ('att_b', 'tester', 'activity', 'r1', 'p.jpg', 'image/jpeg', 3, 2, 2, x'ffd8ff', x'ffd8', 1, 0);",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+    sqlite3_close(legacy);
+
+    setenv("FRICU_ATTACHMENT_DIR", "blobs", 1);
+    assert(init_db("legacy.db") == 0);
+    assert(init_db("legacy.db") == 0);
+    unsetenv("FRICU_ATTACHMENT_DIR");
+
+    char hash[65] = {0};
+    char blob[256] = {0};
+    struct stat st;
+    sha256_hex("%PDF-", 5, hash, sizeof(hash));
+    snprintf(blob, sizeof(blob), "blobs/%.2s/%s", hash, hash);
+    assert(stat(blob, &st) == 0 && st.st_size == 5);
+    sha256_hex("\xff\xd8", 2, hash, sizeof(hash));
+    snprintf(blob, sizeof(blob), "blobs/%.2s/%s", hash, hash);
+    assert(stat(blob, &st) == 0 && st.st_size == 2);
+
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(
+               legacy,
+               "SELECT (SELECT COUNT(*) FROM pragma_table_info('attachments') WHERE name IN ('data', 'thumbnail')),"
+               " (SELECT thumbnail_sha256 FROM attachments WHERE id = 'att_b'), (SELECT thumbnail_sha256 IS NULL FROM attachments WHERE id = 'att_a')",
+               -1,
+               &stmt,
+               NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) == 0);
+    assert(strcmp((const char *)sqlite3_column_text(stmt, 1), hash) == 0 && sqlite3_column_int(stmt, 2) == 1);
+    sqlite3_finalize(stmt);
+    sqlite3_close(legacy);
+    test_env_close(&env);
+}
+
+static void test_journal_entries_search_and_calendar(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-journal-XXXXXX");
+    char resp[16384] = {0};
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"ride-1\",\"date\":\"2025-03-01T07:30:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":70,\"notes\":\"Windy hill repeats\"}]",
+        resp,
+        sizeof(resp));
+    put_json(&env.db, "tester", "events", "[{\"id\":\"ev-1\",\"startDate\":\"2025-03-02T08:00:00Z\",\"name\":\"Spring Classic\",\"type\":\"race\",\"category\":\"A\"}]", resp, sizeof(resp));
+
+    send_item_request(&env.db, "PUT", "/v1/journal/2025-03-01", "{\"body\":\"# Legs\\nHeavy after the **hill** session\",\"mood\":2,\"tags\":[\"fatigue\",\"hills\",\"fatigue\"]}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"date\":\"2025-03-01\",\"body\":\"# Legs\\nHeavy after the **hill** session\",\"mood\":2,\"tags\":[\"fatigue\",\"hills\"]") != NULL);
+    assert(strstr(resp, "\"activities\":[{\"id\":\"ride-1\",\"sport\":\"cycling\",\"duration_sec\":3600,\"tss\":70}]") != NULL);
+
+    /* Invalid mood, tag types and dates are rejected. */
+    send_item_request(&env.db, "PUT", "/v1/journal/2025-03-01", "{\"mood\":9}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PUT", "/v1/journal/2025-03-01", "{\"tags\":[1]}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/journal/yesterday", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/journal?from=2025-02-01&to=2025-03-31", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"entries\":[{\"date\":\"2025-03-01\"") != NULL);
+
+    /* Search spans journal bodies/tags and activity notes; all terms must match. */
+    send_item_request(&env.db, "GET", "/v1/search?q=HILL", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"type\":\"activity\",\"id\":\"ride-1\",\"date\":\"2025-03-01\"") != NULL);
+    assert(strstr(resp, "\"type\":\"journal\",\"id\":\"2025-03-01\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/search?q=hill+fatigue", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"type\":\"journal\"") != NULL && strstr(resp, "\"type\":\"activity\"") == NULL);
+    send_item_request(&env.db, "GET", "/v1/search?q=classic", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"type\":\"event\",\"id\":\"ev-1\",\"date\":\"2025-03-02\",\"title\":\"Spring Classic\"") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/calendar?from=2025-03-01&to=2025-03-07", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"from\":\"2025-03-01\",\"to\":\"2025-03-07\",\"days\":[{\"date\":\"2025-03-01\",\"activities\":[{\"id\":\"ride-1\"") != NULL);
+    assert(strstr(resp, "],\"journal\":{\"body\":\"# Legs") != NULL);
+    assert(strstr(resp, "{\"date\":\"2025-03-02\",\"events\":[{\"id\":\"ev-1\",\"name\":\"Spring Classic\",\"type\":\"race\",\"category\":\"A\"}]}]}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/calendar?from=2025-01-01&to=2025-12-31", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    send_item_request(&env.db, "DELETE", "/v1/journal/2025-03-01", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "GET", "/v1/journal/2025-03-01", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_season_report_html_and_pdf(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-report-XXXXXX");
+    static char resp[64 * 1024];
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-01-02T08:00:00Z\",\"durationSec\":7200,\"tss\":120,\"distanceKm\":60,\"normalizedPower\":250,"
+        "\"intervals\":[{\"durationSec\":300,\"actualPower\":330}]},"
+        "{\"id\":\"a2\",\"date\":\"2025-01-03T08:00:00Z\",\"durationSec\":3600,\"tss\":60,\"distanceKm\":30},"
+        "{\"id\":\"a3\",\"date\":\"2025-06-01T07:00:00Z\",\"durationSec\":14400,\"tss\":210,\"distanceKm\":95,\"normalizedPower\":262},"
+        "{\"id\":\"old\",\"date\":\"2024-12-31T08:00:00Z\",\"durationSec\":3600,\"tss\":50}]",
+        resp,
+        sizeof(resp));
+    put_json(
+        &env.db,
+        "tester",
+        "events",
+        "[{\"id\":\"e1\",\"startDate\":\"2025-06-01T06:00:00Z\",\"name\":\"Tour <Lake>\",\"type\":\"Race\",\"category\":\"A\"},"
+        "{\"id\":\"e2\",\"startDate\":\"2025-03-01T06:00:00Z\",\"name\":\"Camp\",\"type\":\"camp\"}]",
+        resp,
+        sizeof(resp));
+
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=2025", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "Content-Type: text/html; charset=utf-8\r\n") != NULL);
+    assert(strstr(resp, "<p>3 activities &middot; 7.0 h &middot; 390 TSS &middot; 185.0 km</p>") != NULL);
+    assert(strstr(resp, "<tr><td>5min</td><td>330 W</td><td>2025-01-02</td></tr>") != NULL);
+    assert(strstr(resp, "<tr><td>1h</td><td>262 W</td><td>2025-06-01</td></tr>") != NULL);
+    /* Biggest week first; the race picks up that day's activity and its name is escaped. */
+    const char *weeks = strstr(resp, "<h2>Biggest weeks</h2>");
+    assert(weeks != NULL && strstr(weeks, "<tr><td>2025-05-26</td><td>4.0</td><td>210</td></tr>") < strstr(weeks, "<tr><td>2024-12-30</td><td>3.0</td>"));
+    assert(strstr(resp, "<tr><td>2025-06-01</td><td>Tour &lt;Lake&gt;</td><td>A</td><td>4:00:00</td><td>210</td></tr>") != NULL);
+    assert(strstr(resp, "Camp") == NULL);
+
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=2025&format=pdf", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Type: application/pdf\r\n") != NULL);
+    const char *pdf = strstr(resp, "\r\n\r\n%PDF-1.4\n");
+    assert(pdf != NULL);
+    pdf += 4;
+    assert(strstr(pdf, "(Season report 2025) Tj") != NULL);
+    assert(strstr(pdf, "2025-06-01  Tour <Lake>  [A]  4:00:00") != NULL);
+    const char *startxref = strstr(pdf, "startxref\n");
+    assert(startxref != NULL);
+    long xref_offset = atol(startxref + 10);
+    assert(strncmp(pdf + xref_offset, "xref\n0 6\n", 9) == 0);
+    assert(strstr(pdf, "%%EOF\n") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=25", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=2025&format=docx", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_anonymized_export_requires_opt_in(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-anon-XXXXXX");
+    static char resp[32 * 1024];
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a2\",\"date\":\"2025-03-04T09:00:00Z\",\"sport\":\"running\",\"athleteName\":\"Jane Doe\",\"durationSec\":1800,\"notes\":\"Park loop with Bob\","
+        "\"track\":[{\"lat\":47.1,\"lon\":8.5}],\"intervals\":[]},"
+        "{\"id\":\"a1\",\"date\":\"2025-03-01T07:30:00Z\",\"sport\":\"cycling\",\"athleteName\":\"Jane Doe\",\"durationSec\":3600,\"tss\":70,"
+        "\"externalID\":\"strava-99\",\"sourceFileBase64\":\"AAAA\",\"intervals\":[{\"name\":\"Climb to Jane's house\",\"durationSec\":300,\"actualPower\":310}]}]",
+        resp,
+        sizeof(resp));
+    put_json(&env.db, "tester", "wellness_samples", "[{\"date\":\"2025-02-27T06:00:00Z\",\"athleteName\":\"Jane Doe\",\"hrv\":61.5,\"restingHR\":48}]", resp, sizeof(resp));
+
+    send_item_request(&env.db, "GET", "/v1/export/anonymized", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+
+    send_item_request(&env.db, "PUT", "/v1/export/anonymized/consent", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"opted_in\":true") != NULL);
+    send_item_request(&env.db, "GET", "/v1/export/anonymized", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"format\":\"fricu-anonymized-v1\"") != NULL);
+    /* Day offsets are relative to the earliest record (the wellness sample), oldest activity first. */
+    assert(strstr(resp, "\"activities\":[{\"day_offset\":2,\"sport\":\"cycling\",\"duration_sec\":3600") != NULL);
+    assert(strstr(resp, "\"intervals\":[{\"duration_sec\":300,\"target_power\":null,\"actual_power\":310}]},{\"day_offset\":5,\"sport\":\"running\"") != NULL);
+    assert(strstr(resp, "\"wellness\":[{\"day_offset\":0,\"hrv\":61.5,\"resting_hr\":48") != NULL);
+    const char *body = strstr(resp, "\r\n\r\n");
+    assert(body != NULL);
+    assert(strstr(body, "Jane") == NULL && strstr(body, "Bob") == NULL && strstr(body, "strava") == NULL);
+    assert(strstr(body, "lat") == NULL && strstr(body, "AAAA") == NULL && strstr(body, "2025-") == NULL && strstr(body, "\"a1\"") == NULL);
+
+    send_item_request(&env.db, "DELETE", "/v1/export/anonymized/consent", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"opted_in\":false") != NULL);
+    send_item_request(&env.db, "GET", "/v1/export/anonymized", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_coach_locks_reject_athlete_writes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-locks-XXXXXX");
+    char resp[16384] = {0};
+    char req[4096] = {0};
+    char token[96] = {0};
+
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\",\"name\":\"Sweet spot\"}]", resp, sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/coach/tokens", "{\"name\":\"Coach Kim\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1);
+
+    /* Only the coach may lock; the athlete's own request is refused. */
+    send_item_request(&env.db, "PUT", "/v1/locks/workouts", "{\"reason\":\"build block\"}", resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/locks/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\nContent-Length: 24\r\n\r\n{\"reason\":\"build block\"}",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"locks\":[{\"key\":\"workouts\",\"locked_by\":\"Coach Kim\",\"reason\":\"build block\"") != NULL);
+
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "423 Locked") != NULL && strstr(resp, "\"key\":\"workouts\",\"locked_by\":\"Coach Kim\"") != NULL);
+    send_item_request(&env.db, "PUT", "/v2/data/workouts/items/w2", "{\"name\":\"VO2\"}", resp, sizeof(resp));
+    assert(strstr(resp, "423 Locked") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/workouts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Sweet spot") != NULL);
+    put_json(&env.db, "tester", "activities", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/sync/manifest HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"key\":\"workouts\"") != NULL && strstr(resp, "\"locked\":true}") != NULL);
+    assert(strstr(resp, "\"locked_keys\":[\"workouts\"]}") != NULL);
+
+    /* The coach can still edit the plan, then unlock it. */
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\nContent-Length: 2\r\n\r\n[]",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(
+        &env.db,
+        "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: coach_forged\r\nContent-Length: 2\r\n\r\n[]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    snprintf(req, sizeof(req), "DELETE /v1/locks/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "{\"locks\":[]}") != NULL);
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_data_key_snapshots_and_diff(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-snapshots-XXXXXX");
+    char resp[16384] = {0};
+
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"sport\":\"Ride\",\"tss\":70},{\"id\":\"a2\",\"sport\":\"Run\"}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250,\"zones\":[1,2]}", resp, sizeof(resp));
+    assert(snapshots_take(env.db.db, "2025-05-01") >= 2);
+    assert(snapshots_take(env.db.db, "2025-05-01") == 0);
+
+    /* Overnight the client rewrote a1, dropped a2 and added a3. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"sport\":\"Ride\",\"tss\":80},{\"id\":\"a3\",\"sport\":\"Swim\"}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "profile", "{\"ftp\":260,\"zones\":[1,2]}", resp, sizeof(resp));
+    assert(snapshots_take(env.db.db, "2025-05-02") == 2);
+
+    send_item_request(&env.db, "GET", "/v1/data/activities/diff?from=2025-05-01&to=2025-05-02", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"from\":{\"date\":\"2025-05-01\",\"snapshot\":\"2025-05-01\"}") != NULL);
+    assert(strstr(resp, "{\"op\":\"replace\",\"id\":\"a1\",\"path\":\"$.tss\",\"old\":70,\"new\":80}") != NULL);
+    assert(strstr(resp, "{\"op\":\"remove\",\"id\":\"a2\",\"value\":{\"id\":\"a2\",\"sport\":\"Run\"}}") != NULL);
+    assert(strstr(resp, "{\"op\":\"add\",\"id\":\"a3\",\"value\":{\"id\":\"a3\",\"sport\":\"Swim\"}}") != NULL);
+    assert(strstr(resp, "\"truncated\":false}") != NULL);
+
+    /* A later day resolves to the newest snapshot before it; without to the live value is used. */
+    send_item_request(&env.db, "GET", "/v1/data/profile/diff?from=2025-05-01&to=2025-05-09", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"to\":{\"date\":\"2025-05-09\",\"snapshot\":\"2025-05-02\"}") != NULL);
+    assert(strstr(resp, "\"changes\":[{\"op\":\"replace\",\"path\":\"$.ftp\",\"old\":250,\"new\":260}]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile/diff?from=2025-05-02", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"snapshot\":\"current\"},\"changes\":[]") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/data/profile/diff?from=2025-04-30", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile/diff?from=2025-05-02&to=2025-05-01", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/bogus/diff?from=2025-05-01", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    /* Pruning keeps the newest snapshot before the cutoff as each key's baseline. */
+    assert(snapshots_prune(env.db.db, "2025-05-03") == 2);
+    send_item_request(&env.db, "GET", "/v1/data/activities/diff?from=2025-05-01", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities/diff?from=2025-05-05", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"snapshot\":\"2025-05-02\"") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_data_as_of_reads_history(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-as-of-XXXXXX");
+    char resp[16384] = {0};
+
+    /* FTP 230 captured 2025-05-01T00:00Z, raised to 250 on 2025-06-01T00:00Z. */
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":230}", resp, sizeof(resp));
+    assert(snapshots_take(env.db.db, "2025-05-01") >= 1);
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":250}", resp, sizeof(resp));
+    assert(
+        sqlite3_exec(
+            env.db.db,
+            "UPDATE key_snapshots SET created_at = 1746057600 WHERE data_key = 'tester::profile';"
+            "UPDATE kv_store SET updated_at = 1748736000 WHERE data_key = 'tester::profile';",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=2025-06-02T00:00:00Z", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\r\n\r\n{\"ftpWatts\":250}") != NULL);
+    assert(strstr(resp, "X-Fricu-As-Of: 2025-06-01T00:00:00Z\r\nX-Fricu-As-Of-Source: current") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=2025-06-01T01:30:00%2B02:00", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"ftpWatts\":230}") != NULL && strstr(resp, "X-Fricu-As-Of: 2025-05-01T00:00:00Z\r\nX-Fricu-As-Of-Source: snapshot") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=2025-05-15", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"ftpWatts\":230}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=2025-04-30T23:59:59Z", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    /* A key never stored reads as its default; bad instants are rejected. */
+    send_item_request(&env.db, "GET", "/v1/data/workouts?as_of=2025-05-15", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "X-Fricu-As-Of-Source: default") != NULL && strstr(resp, "\r\n\r\n[]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=2025-05-15T25:00:00Z", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/profile?as_of=yesterday", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    test_env_close(&env);
+}
+
+static void post_import(worker_db_t *db, const char *source, const char *body, char *resp, size_t resp_len) {
+    char req[16384] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/import/%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n%s",
+        source,
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_goldencheetah_and_wger_imports(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-import-XXXXXX");
+    char resp[65536] = {0};
+    char ride[12288] = {0};
+
+    put_json(&env.db, "tester", "profile", "{\"cyclingFTPWatts\":250}", resp, sizeof(resp));
+    size_t off = (size_t)snprintf(
+        ride,
+        sizeof(ride),
+        "{\"RIDE\":{\"STARTTIME\":\"2025\\/05\\/01 07:00:00 UTC \",\"RECINTSECS\":2,\"TAGS\":{\"Sport\":\"Bike\",\"Notes\":\"Hill reps\"},"
+        "\"INTERVALS\":[{\"NAME\":\"Climb 1\",\"START\":60,\"STOP\":120}],\"SAMPLES\":[");
+    for (int i = 0; i < 120; i++) {
+        off += (size_t)snprintf(
+            ride + off, sizeof(ride) - off, "%s{\"SECS\":%d,\"KM\":%.3f,\"WATTS\":%d,\"HR\":150,\"CAD\":90}", i ? "," : "", i * 2, i / 60.0, i >= 30 && i < 60 ? 300 : 200);
+    }
+    snprintf(ride + off, sizeof(ride) - off, "]}}");
+
+    post_import(&env.db, "goldencheetah", ride, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"key\":\"activities\"") != NULL && strstr(resp, "\"externalID\":\"goldencheetah:2025-05-01T07:00:00Z\"}],\"duplicates\":[]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\"") != NULL);
+    assert(strstr(resp, "\"durationSec\":240,\"distanceKm\":1.983") != NULL);
+    assert(strstr(resp, "\"avgHeartRate\":150") != NULL && strstr(resp, "\"notes\":\"Hill reps\"") != NULL);
+    assert(strstr(resp, "\"name\":\"Climb 1\",\"durationSec\":60,\"actualPower\":300}") != NULL);
+
+    /* Re-importing is a no-op; rideDB summaries and unknown sports are handled per entry. */
+    post_import(&env.db, "goldencheetah", ride, resp, sizeof(resp));
+    assert(strstr(resp, "\"imported\":[],\"duplicates\":[\"goldencheetah:2025-05-01T07:00:00Z\"]") != NULL);
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/03 06:30:00 UTC\",\"sport\":\"Run\",\"METRICS\":{\"workout_time\":\"1800.0\",\"total_distance\":[\"6.2\",1],"
+        "\"coggan_tss\":\"41\",\"average_hr\":\"155\"}},{\"date\":\"2025/05/04 06:30:00 UTC\",\"sport\":\"Row\",\"METRICS\":{\"workout_time\":\"600\"}}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "\"skipped\":[{\"index\":1,\"reason\":\"unsupported sport\"}]") != NULL);
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/06 -1:30:00 UTC\",\"sport\":\"Run\"},{\"date\":\"12025/05/06 06:30:00 UTC\",\"sport\":\"Run\"},"
+        "{\"date\":\"2025/05/06 06:30:60 UTC\",\"sport\":\"Run\"}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "\"skipped\":[{\"index\":0,\"reason\":\"missing or invalid date\"},{\"index\":1,\"reason\":\"missing or invalid date\"},"
+                        "{\"index\":2,\"reason\":\"missing or invalid date\"}]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"sport\":\"running\",\"athleteName\":\"\",\"durationSec\":1800,\"distanceKm\":6.200,\"tss\":41,\"avgHeartRate\":155") != NULL);
+
+    post_import(
+        &env.db,
+        "wger",
+        "{\"obj_id\":7,\"obj\":{\"name\":\"5x5\"},\"day_list\":[{\"obj\":{\"id\":11,\"description\":\"Legs\"},\"set_list\":["
+        "{\"obj\":{\"sets\":5},\"exercise_list\":[{\"obj\":{\"name\":\"Squat\"},\"setting_obj_list\":[{\"reps\":5,\"weight\":\"100\"}]}]},"
+        "{\"obj\":{\"sets\":3},\"exercise_list\":[{\"obj\":{\"name\":\"Lunge\"},\"setting_text\":\"3 \\u00d7 12\"}]}]},"
+        "{\"obj\":{\"id\":12,\"description\":\"Rest\"},\"set_list\":[]}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "\"key\":\"workouts\"") != NULL && strstr(resp, "\"externalID\":\"wger:7:11\"") != NULL);
+    assert(strstr(resp, "\"skipped\":[{\"index\":1,\"reason\":\"day has no exercises\"}]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/workouts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"name\":\"5x5 - Legs\",\"sport\":\"strength\"") != NULL);
+    assert(strstr(resp, "\"minutes\":10,\"intensityPercentFTP\":0,\"note\":\"Squat 5x5 @ 100 kg\"}") != NULL);
+    assert(strstr(resp, "\"note\":\"Lunge 3 \u00d7 12\"}") != NULL);
+
+    post_import(&env.db, "wger", "{\"name\":\"nothing\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    post_import(&env.db, "strava", "{}", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void copy_import_run_id(const char *resp, char *out, size_t out_len) {
+    const char *start = strstr(resp, "\"import_run_id\":\"");
+    assert(start != NULL);
+    start += 17;
+    const char *end = strchr(start, '"');
+    assert(end != NULL && (size_t)(end - start) < out_len);
+    memcpy(out, start, (size_t)(end - start));
+    out[end - start] = '\0';
+}
+
+static void test_import_runs_history_and_rollback(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-import-runs-XXXXXX");
+    char resp[65536] = {0};
+    char first[64] = {0};
+    char second[64] = {0};
+    char path[256] = {0};
+    char expected[256] = {0};
+
+    post_import(
+        &env.db,
+        "goldencheetah?filename=rideDB.json",
+        "{\"RIDES\":[{\"date\":\"2025/05/03 06:30:00 UTC\",\"sport\":\"Run\",\"METRICS\":{\"workout_time\":\"1800\"}},"
+        "{\"date\":\"2025/05/04 06:30:00 UTC\",\"sport\":\"Bike\",\"METRICS\":{\"workout_time\":\"3600\"}}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    copy_import_run_id(resp, first, sizeof(first));
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/04 06:30:00 UTC\",\"sport\":\"Bike\",\"METRICS\":{\"workout_time\":\"3600\"}},"
+        "{\"date\":\"2025/05/05 06:30:00 UTC\",\"sport\":\"Bike\",\"METRICS\":{\"workout_time\":\"2400\"}}]}",
+        resp,
+        sizeof(resp));
+    copy_import_run_id(resp, second, sizeof(second));
+    assert(strcmp(first, second) != 0);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    snprintf(expected, sizeof(expected), "\"importRunID\":\"%s\"", first);
+    assert(strstr(resp, expected) != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/imports", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    snprintf(expected, sizeof(expected), "{\"imports\":[{\"id\":\"%s\",\"source\":\"goldencheetah\",\"key\":\"activities\",\"files\":[],\"imported\":1,\"duplicates\":1", second);
+    assert(strstr(resp, expected) != NULL);
+    snprintf(expected, sizeof(expected), "{\"id\":\"%s\",\"source\":\"goldencheetah\",\"key\":\"activities\",\"files\":[\"rideDB.json\"],\"imported\":2,\"duplicates\":0", first);
+    assert(strstr(resp, expected) != NULL && strstr(resp, "\"rolled_back_at\":null") != NULL);
+
+    /* Rolling back the first run leaves the ride only the second run added. */
+    snprintf(path, sizeof(path), "/v1/imports/%s/rollback", first);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+    send_item_request(&env.db, "POST", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"removed\":2,\"removed_ids\":[") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "2025-05-03") == NULL && strstr(resp, "2025-05-04") == NULL && strstr(resp, "\"date\":\"2025-05-05T06:30:00Z\"") != NULL);
+    send_item_request(&env.db, "POST", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    send_item_request(&env.db, "POST", "/v1/imports/nope/rollback", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    run_request(&env.db, "GET /v1/imports HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"imports\":[]}") != NULL);
+
+    /* The run id also works as a batch-delete source. */
+    snprintf(expected, sizeof(expected), "{\"source\":\"%s\"}", second);
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", expected, resp, sizeof(resp));
+    assert(strstr(resp, "\"matched\":1") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_profile_sport_sections_drive_import_tss(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sports-XXXXXX");
+    char resp[65536] = {0};
+
+    put_json(&env.db, "tester", "profile", "{\"sports\":[]}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "\"field\":\"sports\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"rowing\":{}}}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "\"field\":\"sports.rowing\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"running\":{\"ftpWatts\":250}}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"error\":\"ftpWatts does not apply to running\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"running\":{\"thresholdPaceSecPerKm\":\"4:30\"}}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"field\":\"sports.running.thresholdPaceSecPerKm\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"swimming\":{\"tssModel\":\"tss\"}}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"error\":\"tssModel is not available for swimming\"") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"cycling\":{\"color\":\"red\"}}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"error\":\"unknown field\",\"field\":\"sports.cycling.color\"") != NULL);
+
+    put_json(
+        &env.db,
+        "tester",
+        "profile",
+        "{\"cyclingFTPWatts\":400,\"sports\":{\"cycling\":{\"ftpWatts\":250,\"defaultGear\":\"Road bike\"},"
+        "\"running\":{\"thresholdPaceSecPerKm\":270,\"defaultGear\":\"Trail shoes\"},\"swimming\":{\"cssSecPer100m\":96}}}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Runs and swims are scored from their own thresholds; the cycling FTP only applies to rides. */
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/03 06:30:00 UTC\",\"sport\":\"Run\",\"METRICS\":{\"workout_time\":\"1800\",\"total_distance\":[\"6.0\",1],\"coggan_np\":\"250\"}},"
+        "{\"date\":\"2025/05/04 06:30:00 UTC\",\"sport\":\"Swim\",\"METRICS\":{\"workout_time\":\"1800\",\"total_distance\":[\"1.5\",1]}},"
+        "{\"date\":\"2025/05/05 06:30:00 UTC\",\"sport\":\"Bike\",\"METRICS\":{\"workout_time\":\"3600\",\"coggan_np\":\"200\"}}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"sport\":\"running\",\"athleteName\":\"\",\"durationSec\":1800,\"distanceKm\":6.000,\"tss\":41,\"normalizedPower\":250,\"gear\":\"Trail shoes\"") != NULL);
+    assert(strstr(resp, "\"sport\":\"swimming\",\"athleteName\":\"\",\"durationSec\":1800,\"distanceKm\":1.500,\"tss\":26,") != NULL);
+    assert(strstr(resp, "\"sport\":\"cycling\",\"athleteName\":\"\",\"durationSec\":3600,\"distanceKm\":0.000,\"tss\":64,\"normalizedPower\":200,\"gear\":\"Road bike\"") != NULL);
+
+    /* Without pace or heart-rate thresholds a run gets no TSS rather than a power-based one. */
+    put_json(&env.db, "tester", "profile", "{\"cyclingFTPWatts\":250}", resp, sizeof(resp));
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/06 06:30:00 UTC\",\"sport\":\"Run\",\"METRICS\":{\"workout_time\":\"1800\",\"total_distance\":[\"6.0\",1],\"coggan_np\":\"250\"}}]}",
+        resp,
+        sizeof(resp));
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"2025-05-06T06:30:00Z\",\"sport\":\"running\",\"athleteName\":\"\",\"durationSec\":1800,\"distanceKm\":6.000,\"tss\":0,") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_i18n_notifications_and_reports_follow_accept_language(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-i18n-XXXXXX");
+    char resp[65536] = {0};
+
+    const i18n_arg_t args[] = {{"duration", "5 s"}, {"date", "2025-05-03"}, {"watts", "900"}, {"previous", "850"}};
+    assert(notification_post_message(env.db.db, "tester", "pr", "pr-5s", "pr.power", args, 4) == 1);
+    assert(notification_post(env.db.db, "tester", "test", "plain", "Hello from the server") == 1);
+
+    /* Stored English text is what clients without a preference get. */
+    send_item_request(&env.db, "GET", "/v1/notifications?kind=pr", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Language: en\r\n") != NULL);
+    assert(strstr(resp, "\"message\":\"New 5 s power record on 2025-05-03: 900 W (previous best 850 W)\"") != NULL);
+
+    run_request(
+        &env.db,
+        "GET /v1/notifications HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Language: fr-FR, zh-CN;q=0.8, de;q=0.5\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "Content-Language: zh\r\n") != NULL);
+    assert(strstr(resp, "\"message\":\"2025-05-03 创下 5 s 功率新纪录：900 W（此前最佳 850 W）\"") != NULL);
+    assert(strstr(resp, "\"message\":\"Hello from the server\"") != NULL);
+
+    /* ?lang= wins over the header; the profile language applies when neither is sent. */
+    run_request(
+        &env.db, "GET /v1/notifications?kind=pr&lang=de HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Language: zh\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Language: de\r\n") != NULL && strstr(resp, "Neuer 5 s-Leistungsrekord am 2025-05-03") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"language\":\"zh-Hans\"}", resp, sizeof(resp));
+    send_item_request(&env.db, "GET", "/v1/notifications?kind=pr", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Language: zh\r\n") != NULL && strstr(resp, "功率新纪录") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=2025", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Language: zh\r\n") != NULL);
+    assert(strstr(resp, "<html lang=\"zh\">") != NULL && strstr(resp, "<h1>2025 赛季报告</h1>") != NULL);
+    assert(strstr(resp, "<p>0 次训练 &middot; 0.0 小时 &middot; 0 TSS &middot; 0.0 公里</p>") != NULL);
+    send_item_request(&env.db, "GET", "/v1/reports/season?year=2025&format=pdf", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Content-Language: en\r\n") != NULL && strstr(resp, "(Season report 2025) Tj") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_load_models_computed_at_import_and_selectable_in_analytics(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-loadmodels-XXXXXX");
+    char resp[65536] = {0};
+
+    put_json(&env.db, "tester", "profile", "{\"sports\":{\"cycling\":{\"tssModel\":\"banister\"}}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"error\":\"tssModel is not available for cycling\"") != NULL);
+    put_json(
+        &env.db,
+        "tester",
+        "profile",
+        "{\"sports\":{\"cycling\":{\"ftpWatts\":250,\"thresholdHeartRate\":170,\"restingHeartRate\":50,\"maxHeartRate\":190,\"tssModel\":\"trimp\"}}}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Every model with inputs is kept; the profile's choice becomes the activity's tss. */
+    post_import(
+        &env.db,
+        "goldencheetah",
+        "{\"RIDES\":[{\"date\":\"2025/05/05 06:30:00 UTC\",\"sport\":\"Bike\",\"METRICS\":{\"workout_time\":\"3600\",\"coggan_np\":\"200\",\"average_hr\":\"150\"}}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"tss\":108,\"normalizedPower\":200,\"avgHeartRate\":150,\"loadModel\":\"trimp\",\"loads\":{\"tss\":64,\"hrss\":78,\"trimp\":108},") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/analytics/compare?periods=2024,2025", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"label\":\"2025\",\"from\":\"2025-01-01\",\"to\":\"2025-12-31\",\"activities\":1,\"hours\":1.0,\"tss\":108,") != NULL);
+    assert(strstr(resp, "\"load_model\":\"primary\",\"available_models\":[\"hrss\",\"trimp\",\"tss\"]}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/analytics/compare?periods=2024,2025&model=tss", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"activities\":1,\"hours\":1.0,\"tss\":64,") != NULL && strstr(resp, "\"load_model\":\"tss\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/analytics/risk?model=srpe", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"ctl\":0.0,") != NULL && strstr(resp, "\"load_model\":\"srpe\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/analytics/risk?model=watts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "unknown load model") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_activity_feedback_feeds_srpe_and_readiness(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-feedback-XXXXXX");
+    char resp[65536] = {0};
+    char days[4][16] = {{0}};
+    const int offsets[4] = {2, 1, 10, 12};
+    for (int i = 0; i < 4; i++) format_iso_day(today_day() - offsets[i], days[i], sizeof(days[i]));
+    char body[2048] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"a1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":0},"
+        "{\"id\":\"a2\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":80,\"loadModel\":\"tss\",\"loads\":{\"tss\":80}},"
+        "{\"id\":\"b1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":60,\"feel\":4,\"rpe\":5},"
+        "{\"id\":\"b2\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":60,\"feel\":4,\"rpe\":5}]",
+        days[0],
+        days[1],
+        days[2],
+        days[3]);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{\"rpe\":11}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "\"field\":\"rpe\"") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{\"feel\":2.5}", resp, sizeof(resp));
+    assert(strstr(resp, "\"field\":\"feel\"") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{\"mood\":1}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/nope/feedback", "{\"rpe\":5}", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v1/activities/a1/feedback", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+
+    /* Without a sensor-based load the session RPE becomes the primary load. */
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{\"rpe\":6,\"feel\":2,\"comments\":\"heavy legs\"}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"id\":\"a1\",\"rpe\":6,\"feel\":2,\"comments\":\"heavy legs\",\"tss\":360,\"loadModel\":\"srpe\",\"loads\":{\"srpe\":360}}") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/a2/feedback", "{\"rpe\":8,\"feel\":2}", resp, sizeof(resp));
+    assert(strstr(resp, "\"tss\":80,\"loadModel\":\"tss\",\"loads\":{\"tss\":80,\"srpe\":480}}") != NULL);
+    send_item_request(&env.db, "PATCH", "/v1/activities/a1/feedback", "{\"rpe\":null,\"comments\":null}", resp, sizeof(resp));
+    assert(strstr(resp, "{\"id\":\"a1\",\"rpe\":null,\"feel\":2,\"comments\":null,\"tss\":0,\"loadModel\":null,\"loads\":{}}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"a1\",") != NULL && strstr(resp, "heavy legs") == NULL && strstr(resp, "\"feel\":2") != NULL);
+
+    /* Feel down two points and sessions rated three points harder than the prior four weeks. */
+    send_item_request(&env.db, "GET", "/v1/analytics/readiness", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"subjective\":{\"recent\":{\"rated\":2,\"feel\":2.00,\"rpe\":8.00},\"baseline\":{\"rated\":2,\"feel\":4.00,\"rpe\":5.00},\"used\":true}") != NULL);
+    assert(strstr(resp, "\"feel_declining\",\"rpe_rising\"]") != NULL);
+    assert(strstr(resp, "\"hrv_ratio\":null") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_cycle_tracking_infers_phase_for_readiness_and_plans(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-cycle-XXXXXX");
+    char resp[65536] = {0};
+    char path[64] = {0};
+    char day[16] = {0};
+
+    format_iso_day(today_day(), day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle/%s", day);
+    send_item_request(&env.db, "PUT", path, "{\"flow\":\"gushing\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PUT", path, "{\"symptoms\":[\"cramps\",3]}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PUT", path, "{\"flow\":\"light\",\"mood\":2}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/cycle/phase", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"cycle\":null}") != NULL);
+
+    /* Two 5-day periods 29 days apart, then a marked start yesterday. */
+    const int flow_days[] = {58, 57, 56, 55, 54, 29, 28, 27, 26, 25, 1, 0};
+    for (size_t i = 0; i < sizeof(flow_days) / sizeof(flow_days[0]); i++) {
+        format_iso_day(today_day() - flow_days[i], day, sizeof(day));
+        snprintf(path, sizeof(path), "/v1/cycle/%s", day);
+        const char *body = flow_days[i] == 1 ? "{\"flow\":\"heavy\",\"period_start\":true,\"symptoms\":[\"cramps\",\"fatigue\",\"cramps\"],\"notes\":\"day one\"}"
+                                             : "{\"flow\":\"medium\"}";
+        send_item_request(&env.db, "PUT", path, body, resp, sizeof(resp));
+        assert(strstr(resp, "200 OK") != NULL);
+    }
+    format_iso_day(today_day() - 1, day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle/%s", day);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"flow\":\"heavy\",\"period_start\":true,\"symptoms\":[\"cramps\",\"fatigue\"],\"notes\":\"day one\"") != NULL);
+
+    char next[16] = {0};
+    char expected[256] = {0};
+    format_iso_day(today_day() + 28, next, sizeof(next));
+    snprintf(
+        expected,
+        sizeof(expected),
+        "\"cycle\":{\"phase\":\"menstrual\",\"cycle_day\":2,\"cycle_length\":29,\"period_length\":4,\"next_period\":\"%s\",\"predicted\":false}",
+        next);
+    send_item_request(&env.db, "GET", "/v1/cycle/phase", NULL, resp, sizeof(resp));
+    assert(strstr(resp, expected) != NULL);
+    format_iso_day(today_day() + 14, day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle/phase?date=%s", day);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"phase\":\"ovulatory\",\"cycle_day\":16") != NULL);
+    format_iso_day(today_day() + 40, day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle/phase?date=%s", day);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"phase\":\"follicular\",\"cycle_day\":13") != NULL && strstr(resp, "\"predicted\":true") != NULL);
+
+    char from[16] = {0};
+    format_iso_day(today_day() - 30, from, sizeof(from));
+    format_iso_day(today_day(), day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle?from=%s&to=%s", from, day);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    size_t entries = 0;
+    for (const char *p = strstr(resp, "\"date\":"); p; p = strstr(p + 1, "\"date\":")) entries++;
+    assert(entries == 7);
+    send_item_request(&env.db, "GET", "/v1/cycle", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/analytics/readiness", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"cycle\":{\"phase\":\"menstrual\",\"cycle_day\":2") != NULL);
+    send_item_request(&env.db, "GET", "/v1/analytics/risk", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"cycle\":{\"phase\":\"menstrual\"") != NULL);
+
+    /* The plan halves load on the remaining menstrual days; day 5 is follicular. */
+    char body[1024] = {0};
+    char dates[3][16] = {{0}};
+    for (int i = 0; i < 3; i++) format_iso_day(today_day() + 1 + i, dates[i], sizeof(dates[i]));
+    snprintf(
+        body,
+        sizeof(body),
+        "{\"target_date\":\"%s\",\"workouts\":[{\"date\":\"%s\",\"tss\":100},{\"date\":\"%s\",\"tss\":100},{\"date\":\"%s\",\"tss\":100}],"
+        "\"cycle_load_factors\":{\"menstrual\":0.5}}",
+        dates[2],
+        dates[0],
+        dates[1],
+        dates[2]);
+    send_item_request(&env.db, "POST", "/v1/analytics/simulate", body, resp, sizeof(resp));
+    assert(strstr(resp, "\"cycle_adjusted\":true") != NULL);
+    snprintf(expected, sizeof(expected), "{\"date\":\"%s\",\"tss\":50.0,", dates[1]);
+    assert(strstr(resp, expected) != NULL);
+    snprintf(expected, sizeof(expected), "{\"date\":\"%s\",\"tss\":100.0,", dates[2]);
+    assert(strstr(resp, expected) != NULL);
+    assert(strstr(resp, "\"cycle_phase\":\"menstrual\"}") != NULL && strstr(resp, "\"cycle_phase\":\"follicular\"}") != NULL);
+    snprintf(body, sizeof(body), "{\"target_date\":\"%s\",\"cycle_load_factors\":{\"luteal\":3}}", dates[2]);
+    send_item_request(&env.db, "POST", "/v1/analytics/simulate", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    format_iso_day(today_day() - 1, day, sizeof(day));
+    snprintf(path, sizeof(path), "/v1/cycle/%s", day);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_travel_log_corrects_power_recorded_at_altitude(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-travel-XXXXXX");
+    char resp[65536] = {0};
+    char days[4][16] = {{0}};
+    const int offsets[4] = {20, 10, 5, 1};
+    for (int i = 0; i < 4; i++) format_iso_day(today_day() - offsets[i], days[i], sizeof(days[i]));
+    char body[1024] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"A1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":80,\"intervals\":["
+        "{\"durationSec\":180,\"actualPower\":350},{\"durationSec\":300,\"actualPower\":310},{\"durationSec\":720,\"actualPower\":275}]},"
+        "{\"id\":\"B1\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":3600,\"tss\":80,\"normalizedPower\":234,\"intervals\":["
+        "{\"durationSec\":180,\"actualPower\":330},{\"durationSec\":300,\"actualPower\":295},{\"durationSec\":720,\"actualPower\":262}]}]",
+        days[0],
+        days[2]);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\"}", days[1], days[3]);
+    send_item_request(&env.db, "POST", "/v1/travel", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\",\"altitude_m\":2000}", days[3], days[1]);
+    send_item_request(&env.db, "POST", "/v1/travel", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\",\"altitude_m\":2000,\"power_factor\":1.2}", days[1], days[3]);
+    send_item_request(&env.db, "POST", "/v1/travel", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/analytics/cp", NULL, resp, sizeof(resp));
+    const char *fit_start = strstr(resp, "\"latest_fit\":{");
+    assert(fit_start != NULL);
+    char sea_level_fit[512] = {0};
+    snprintf(sea_level_fit, sizeof(sea_level_fit), "%.*s", (int)(strstr(fit_start, "},") - fit_start), fit_start);
+
+    /* Without a stay factor or profile table, Bassett's acclimatized curve applies. */
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\",\"location\":\"Livigno\",\"altitude_m\":2000}", days[1], days[3]);
+    send_item_request(&env.db, "POST", "/v1/travel", body, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "\"location\":\"Livigno\",\"altitude_m\":2000,\"power_factor\":0.917,\"power_factor_source\":\"default\"") != NULL);
+    const char *id_start = strstr(resp, "{\"id\":\"");
+    assert(id_start != NULL);
+    char path[96] = {0};
+    snprintf(path, sizeof(path), "/v1/travel/%.36s", id_start + 7);
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\",\"altitude_m\":1800}", days[2], days[2]);
+    send_item_request(&env.db, "POST", "/v1/travel", body, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+
+    /* The writer refits CP once the stay is stored. */
+    send_item_request(&env.db, "GET", "/v1/analytics/cp", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"latest_fit\":{") != NULL && strstr(resp, sea_level_fit) == NULL);
+
+    put_json(&env.db, "tester", "profile", "{\"altitudePowerFactors\":[{\"altitudeM\":2000,\"factor\":0.9}]}", resp, sizeof(resp));
+    send_item_request(&env.db, "GET", "/v1/travel", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"power_factor\":0.900,\"power_factor_source\":\"profile\"") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/analytics/altitude", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"A1\"") == NULL);
+    assert(strstr(resp, "{\"id\":\"B1\",") != NULL && strstr(resp, "\"location\":\"Livigno\",\"altitude_m\":2000,\"power_factor\":0.900") != NULL);
+    assert(strstr(resp, "\"normalized_power\":234,\"normalized_power_sea_level\":260,\"avg_power\":null") != NULL);
+
+    /* The camp's efforts beat the sea-level ride once corrected. */
+    send_item_request(&env.db, "POST", "/v1/analytics/cp/fit", "", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"altitude_corrected_points\":5}") != NULL);
+    char query[128] = {0};
+    char from[16] = {0};
+    format_iso_day(today_day() - 30, from, sizeof(from));
+    format_iso_day(today_day(), days[3], sizeof(days[3]));
+    snprintf(query, sizeof(query), "/v1/analytics/compare?periods=%s..%s,%s..%s", from, days[0], days[1], days[3]);
+    send_item_request(&env.db, "GET", query, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"duration_sec\":300,\"watts\":328}") != NULL);
+    assert(strstr(resp, "\"altitude_corrected_efforts\":0,") != NULL && strstr(resp, "\"altitude_corrected_efforts\":4,") != NULL);
+
+    /* Interpolated from 1.0 at sea level to the profile's 0.9 at 2,000 m. */
+    format_iso_day(today_day() - 1, days[3], sizeof(days[3]));
+    snprintf(body, sizeof(body), "{\"start_date\":\"%s\",\"end_date\":\"%s\",\"altitude_m\":1000}", days[1], days[3]);
+    send_item_request(&env.db, "PUT", path, body, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"location\":\"\",\"altitude_m\":1000,\"power_factor\":0.950") != NULL);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_gear_calibration_log_explains_power_jumps(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-calibration-XXXXXX");
+    char resp[65536] = {0};
+    char days[5][16] = {{0}};
+    const int offsets[5] = {14, 12, 10, 8, 6};
+    for (int i = 0; i < 5; i++) format_iso_day(today_day() - offsets[i], days[i], sizeof(days[i]));
+    char body[2048] = {0};
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"a1\",\"date\":\"%sT07:00:00Z\",\"gear\":\"Road bike\",\"normalizedPower\":240},"
+        "{\"id\":\"a2\",\"date\":\"%sT07:00:00Z\",\"gear\":\"Road bike\",\"normalizedPower\":246},"
+        "{\"id\":\"a3\",\"date\":\"%sT07:00:00Z\",\"gear\":\"Road bike\",\"normalizedPower\":270},"
+        "{\"id\":\"a4\",\"date\":\"%sT07:00:00Z\",\"gear\":\"Road bike\",\"avgPower\":268},"
+        "{\"id\":\"a5\",\"date\":\"%sT07:00:00Z\",\"gear\":\"TT bike\",\"normalizedPower\":300}]",
+        days[0],
+        days[1],
+        days[2],
+        days[3],
+        days[4]);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    snprintf(body, sizeof(body), "{\"gear\":\"Road bike\",\"date\":\"%s\",\"kind\":\"recalibrated\"}", days[2]);
+    send_item_request(&env.db, "POST", "/v1/gear/calibrations", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    snprintf(body, sizeof(body), "{\"gear\":\"Road bike\",\"date\":\"%s\",\"kind\":\"battery_swap\",\"value\":3}", days[2]);
+    send_item_request(&env.db, "POST", "/v1/gear/calibrations", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    snprintf(body, sizeof(body), "{\"date\":\"%s\",\"kind\":\"zero_offset\"}", days[2]);
+    send_item_request(&env.db, "POST", "/v1/gear/calibrations", body, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    /* A zero offset right before recorded power stepped up by about 11%. */
+    snprintf(body, sizeof(body), "{\"gear\":\"Road bike\",\"date\":\"%s\",\"kind\":\"zero_offset\",\"value\":512,\"notes\":\"after crank swap\"}", days[2]);
+    send_item_request(&env.db, "POST", "/v1/gear/calibrations", body, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(strstr(resp, "\"kind\":\"zero_offset\",\"value\":512,\"notes\":\"after crank swap\"") != NULL);
+    assert(strstr(resp, "\"power_before\":{\"watts\":243,\"rides\":2},\"power_after\":{\"watts\":269,\"rides\":2},\"change_pct\":10.7,\"suspicious_jump\":true}") !=
+           NULL);
+    const char *id_start = strstr(resp, "{\"id\":\"");
+    assert(id_start != NULL);
+    char path[96] = {0};
+    snprintf(path, sizeof(path), "/v1/gear/calibrations/%.36s", id_start + 7);
+    snprintf(body, sizeof(body), "{\"gear\":\"TT bike\",\"date\":\"%s\",\"kind\":\"battery_swap\"}", days[0]);
+    send_item_request(&env.db, "POST", "/v1/gear/calibrations", body, resp, sizeof(resp));
+    assert(strstr(resp, "\"power_before\":null,\"power_after\":{\"watts\":300,\"rides\":1},\"change_pct\":null,\"suspicious_jump\":false}") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/gear/calibrations?gear=Road+bike", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"gear\":\"Road bike\"") != NULL && strstr(resp, "\"gear\":\"TT bike\"") == NULL);
+    send_item_request(&env.db, "GET", "/v1/gear/calibrations", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"gear\":\"TT bike\"") < strstr(resp, "\"gear\":\"Road bike\""));
+
+    send_item_request(&env.db, "GET", "/v1/analytics/profile?days=30", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"calibrations\":[{\"id\":") != NULL && strstr(resp, "\"kind\":\"zero_offset\",\"value\":512,\"power_before\"") != NULL);
+    char query[128] = {0};
+    snprintf(query, sizeof(query), "/v1/analytics/compare?periods=%s..%s,%s..%s", days[1], days[1], days[2], days[4]);
+    send_item_request(&env.db, "GET", query, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"calibrations\":[]}") != NULL && strstr(resp, "\"calibrations\":[{\"id\":") != NULL);
+
+    snprintf(body, sizeof(body), "{\"gear\":\"Road bike\",\"date\":\"%s\",\"kind\":\"slope_change\",\"value\":1.02}", days[2]);
+    send_item_request(&env.db, "PUT", path, body, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"kind\":\"slope_change\",\"value\":1.02,\"notes\":\"\"") != NULL);
+    send_item_request(&env.db, "DELETE", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "GET", path, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_coach_tokens_are_stored_hashed(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-coach-hash-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+    char token[96] = {0};
+    char hash[65] = {0};
+
+    send_item_request(&env.db, "POST", "/v1/coach/tokens", "{\"name\":\"Coach Kim\"}", resp, sizeof(resp));
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1);
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT token_hash FROM coach_tokens WHERE account_id = 'tester'", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), hash) == 0);
+    sqlite3_finalize(stmt);
+
+    /* Only the token itself grants access; presenting the stored hash does not. */
+    snprintf(req, sizeof(req), "GET /v1/users/tester/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Coach-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/users/tester/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Coach-Token: %s\r\n\r\n", hash);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") == NULL);
+
+    snprintf(req, sizeof(req), "DELETE /v1/coach/tokens/%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* A database from an older build keeps plaintext tokens; opening it hashes them in place. */
+    sqlite3 *legacy = NULL;
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    assert(sqlite3_exec(
+               legacy,
+               "CREATE TABLE coach_tokens (token TEXT PRIMARY KEY, account_id TEXT NOT NULL, name TEXT NOT NULL, created_at INTEGER NOT NULL);"
+               "INSERT INTO coach_tokens VALUES ('coach_legacy', 'alice', 'Old coach', 0);",
+               NULL,
+               NULL,
+               NULL) == SQLITE_OK);
+    sqlite3_close(legacy);
+    assert(init_db("legacy.db") == 0);
+    assert(init_db("legacy.db") == 0);
+    assert(sqlite3_open("legacy.db", &legacy) == SQLITE_OK);
+    sha256_hex("coach_legacy", 12, hash, sizeof(hash));
+    assert(sqlite3_prepare_v2(legacy, "SELECT token_hash, name FROM coach_tokens", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), hash) == 0);
+    assert(strcmp((const char *)sqlite3_column_text(stmt, 1), "Old coach") == 0);
+    assert(sqlite3_step(stmt) == SQLITE_DONE);
+    sqlite3_finalize(stmt);
+    sqlite3_close(legacy);
+    test_env_close(&env);
+}
+
+static void test_coach_compare_overlays_granted_athletes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-coach-compare-XXXXXX");
+    char resp[65536] = {0};
+    char req[4096] = {0};
+    char body[512] = {0};
+    char token[96] = {0};
+    char days[2][16] = {{0}};
+    format_iso_day(today_day() - 2, days[0], sizeof(days[0]));
+    format_iso_day(today_day() - 1, days[1], sizeof(days[1]));
+
+    snprintf(body, sizeof(body), "[{\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"tss\":50}]", days[0]);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    snprintf(body, sizeof(body), "[{\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"tss\":100}]", days[1]);
+    put_json(&env.db, "rider-b", "activities", body, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Without rider-b's grant the coach only sees their own account. */
+    send_item_request(&env.db, "GET", "/v1/coach/compare?athletes=tester,rider-b&metric=tss&days=3", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    assert(strstr(resp, "{\"error\":\"no sharing grant for athlete\",\"athlete\":\"rider-b\"}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/coach/compare?athletes=tester&metric=watts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/coach/compare?athletes=tester,b%2Fc", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/coach/compare", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    run_request(
+        &env.db,
+        "POST /v1/coach/tokens HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: rider-b\r\nContent-Length: 20\r\n\r\n{\"name\":\"Coach Kim\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1);
+
+    snprintf(
+        req,
+        sizeof(req),
+        "GET /v1/coach/compare?athletes=tester,rider-b&metric=tss&days=3 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n"
+        "X-Coach-Token: not-a-token, %s\r\n\r\n",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    snprintf(body, sizeof(body), "{\"metric\":\"tss\",\"days\":3,\"from\":\"%s\",", days[0]);
+    assert(strstr(resp, body) != NULL);
+    snprintf(body, sizeof(body), "\"dates\":[\"%s\",\"%s\",", days[0], days[1]);
+    assert(strstr(resp, body) != NULL);
+    assert(
+        strstr(resp, "\"athletes\":[{\"athlete\":\"tester\",\"values\":[50.0,0.0,0.0]},{\"athlete\":\"rider-b\",\"values\":[0.0,100.0,0.0]}]}") !=
+        NULL);
+
+    /* A token only grants the athlete who issued it. */
+    snprintf(
+        req,
+        sizeof(req),
+        "GET /v1/coach/compare?athletes=rider-c HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\n\r\n",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    snprintf(
+        req,
+        sizeof(req),
+        "GET /v1/coach/compare?athletes=rider-b HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\n\r\n",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "\"metric\":\"ctl\",\"days\":90,") != NULL && strstr(resp, "{\"athlete\":\"rider-b\",\"values\":[0.0,") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_plan_template_round_trips_between_accounts(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-plan-template-XXXXXX");
+    char resp[65536] = {0};
+    char template_json[4096] = {0};
+
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"w1\",\"name\":\"Endurance\",\"sport\":\"cycling\",\"scheduledDate\":\"2025-03-04T06:00:00Z\","
+        "\"segments\":[{\"id\":\"s1\",\"minutes\":60,\"intensityPercentFTP\":65,\"cadence\":90}]},"
+        "{\"id\":\"w2\",\"name\":\"Threshold\",\"scheduledDate\":\"2025-03-15T07:00:00Z\",\"athleteName\":\"Kim\","
+        "\"segments\":[{\"id\":\"s2\",\"minutes\":10,\"intensityPercentFTP\":60},{\"id\":\"s3\",\"minutes\":20,\"intensityPercentFTP\":95,\"note\":\"steady\"}]},"
+        "{\"id\":\"w3\",\"name\":\"Rest\",\"scheduledDate\":\"2025-03-05T00:00:00Z\",\"segments\":[]},"
+        "{\"id\":\"w4\",\"name\":\"Later\",\"scheduledDate\":\"2025-04-01T00:00:00Z\",\"segments\":[{\"minutes\":30,\"intensityPercentFTP\":70}]}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* The block snaps to its Monday; dates, ids and athlete names do not leave the account. */
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?from=2025-03-05&weeks=2&name=Base+block", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "filename=\"fricu-plan-template.json\"") != NULL);
+    const char *body = strstr(resp, "\r\n\r\n");
+    assert(body != NULL);
+    snprintf(template_json, sizeof(template_json), "%s", body + 4);
+    assert(
+        strcmp(
+            template_json,
+            "{\"format\":\"fricu-plan-template-v1\",\"name\":\"Base block\",\"description\":null,"
+            "\"reference\":{\"ftpWatts\":null,\"thresholdPaceSecPerKm\":null,\"cssSecPer100m\":null},\"weeks\":2,\"workouts\":["
+            "{\"week\":1,\"day\":2,\"name\":\"Endurance\",\"sport\":\"cycling\",\"segments\":[{\"minutes\":60,\"intensityPercentFTP\":65,\"cadence\":90,\"note\":null}]},"
+            "{\"week\":2,\"day\":6,\"name\":\"Threshold\",\"sport\":\"cycling\",\"segments\":[{\"minutes\":10,\"intensityPercentFTP\":60,\"cadence\":null,\"note\":null},"
+            "{\"minutes\":20,\"intensityPercentFTP\":95,\"cadence\":null,\"note\":\"steady\"}]}]}") == 0);
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?from=2025-05-05&weeks=4", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?weeks=53", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    send_item_request(
+        &env.db,
+        "POST",
+        "/v1/import/plan-template?start_date=2025-06-04",
+        "{\"format\":\"fricu-plan-template-v1\",\"name\":\"Bad\",\"weeks\":1,\"extra\":1,\"workouts\":[{\"week\":2,\"day\":8,\"name\":\"X\","
+        "\"segments\":[{\"minutes\":0,\"intensityPercentFTP\":350,\"watts\":200}]}]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "{\"error\":\"invalid plan template\",\"problems\":[") != NULL);
+    assert(strstr(resp, "{\"path\":\"$.extra\",\"error\":\"unknown field\"}") != NULL);
+    assert(strstr(resp, "\"path\":\"$.workouts[0].week\"") != NULL && strstr(resp, "\"path\":\"$.workouts[0].day\"") != NULL);
+    assert(strstr(resp, "\"path\":\"$.workouts[0].segments[0].minutes\"") != NULL);
+    assert(strstr(resp, "\"path\":\"$.workouts[0].segments[0].intensityPercentFTP\"") != NULL);
+    assert(strstr(resp, "\"path\":\"$.workouts[0].segments[0].watts\"") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/plan-template", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "start_date") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?validate_only=true", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "{\"valid\":true,\"workouts\":2}") != NULL);
+
+    /* Week 1 starts on the Monday of start_date's week; re-importing adds nothing. */
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-04", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"plan-template\",\"key\":\"workouts\"") != NULL);
+    assert(strstr(resp, "\"externalID\":\"plan-template:Base block:2025-06-02:0\"") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-02", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "\"imported\":[],\"duplicates\":[\"plan-template:Base block:2025-06-02:0\",\"plan-template:Base block:2025-06-02:1\"]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/workouts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"scheduledDate\":\"2025-06-03T00:00:00Z\",\"name\":\"Endurance\",\"sport\":\"cycling\"") != NULL);
+    assert(strstr(resp, "\"scheduledDate\":\"2025-06-14T00:00:00Z\",\"name\":\"Threshold\"") != NULL);
+    assert(strstr(resp, "\"minutes\":20,\"intensityPercentFTP\":95,\"note\":\"steady\"}") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_plan_template_scales_targets_to_current_fitness(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-plan-scaling-XXXXXX");
+    char resp[65536] = {0};
+    const char *template_json =
+        "{\"format\":\"fricu-plan-template-v1\",\"name\":\"VO2 block\",\"reference\":{\"ftpWatts\":250},\"weeks\":1,\"workouts\":["
+        "{\"week\":1,\"day\":1,\"name\":\"VO2\",\"segments\":[{\"minutes\":10,\"intensityPercentFTP\":60},{\"minutes\":4,\"intensityPercentFTP\":120}]},"
+        "{\"week\":1,\"day\":3,\"name\":\"Tempo run\",\"sport\":\"running\",\"segments\":[{\"minutes\":20,\"intensityPercentFTP\":90}]}]}";
+
+    put_json(
+        &env.db, "tester", "profile", "{\"sports\":{\"cycling\":{\"ftpWatts\":300},\"running\":{\"thresholdPaceSecPerKm\":300}}}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-02&cap=cp", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-02&cap=cp&scale=false", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    assert(
+        sqlite3_exec(
+            env.db.db,
+            "INSERT INTO cp_history (account_id, fitted_at, model, weeks, cp, w_prime, k, cp_ci_low, cp_ci_high, w_prime_ci_low, w_prime_ci_high, points)"
+            " VALUES ('tester', strftime('%s', 'now'), 2, 12, 280, 18000, 0, 270, 290, 15000, 21000, 6)",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+
+    /* 120% of a 300 W FTP for 4 min is above CP + W'/t (355 W), so that step is capped. */
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-02&cap=cp", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/workouts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"minutes\":10,\"intensityPercentFTP\":60,\"targetWatts\":180}") != NULL);
+    assert(strstr(resp, "\"minutes\":4,\"intensityPercentFTP\":118.3,\"cappedFromPercentFTP\":120,\"targetWatts\":355}") != NULL);
+    assert(
+        strstr(
+            resp,
+            "\"targetScaling\":{\"basis\":\"ftp\",\"ftpWatts\":300,\"referenceFtpWatts\":250,\"factor\":1.200,"
+            "\"cap\":{\"model\":\"cp\",\"cp\":280,\"wPrimeJoules\":18000,\"cappedSegments\":1}}") != NULL);
+    assert(strstr(resp, "\"minutes\":20,\"intensityPercentFTP\":90,\"targetPaceSecPerKm\":333}") != NULL);
+    assert(
+        strstr(resp, "\"targetScaling\":{\"basis\":\"threshold_pace\",\"thresholdPaceSecPerKm\":300,\"referenceThresholdPaceSecPerKm\":null,\"factor\":null,\"cap\":null}") !=
+        NULL);
+
+    /* Unscaled imports keep only the portable %FTP anchors. */
+    put_json(&env.db, "tester", "workouts", "[]", resp, sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/import/plan-template?start_date=2025-06-02&scale=false", template_json, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/workouts", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"minutes\":4,\"intensityPercentFTP\":120}") != NULL && strstr(resp, "targetScaling") == NULL);
+
+    test_env_close(&env);
+}
+
+static void test_schema_registry_rejects_mistyped_fields(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-schema-XXXXXX");
+    char resp[65536] = {0};
+
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":\"high\"},\"oops\",{\"id\":7,\"tags\":null}]", resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL);
+    assert(strstr(resp, "\"error_count\":2") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[0].tss\",\"expected\":\"number\",\"got\":\"string\"}") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[1]\",\"expected\":\"object\",\"got\":\"string\"}") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"ftpWatts\":true}", resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL && strstr(resp, "\"path\":\"$.ftpWatts\"") != NULL);
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2026-13-01\",\"durationSec\":-60,\"sport\":\"quidditch\"},{\"id\":\"a2\",\"scheduledDate\":\"soon\",\"date\":\"2026-03\"}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL && strstr(resp, "\"error_count\":4") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[0].date\",\"expected\":\"YYYY-MM-DD date\",\"got\":\"2026-13-01\"}") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[0].durationSec\",\"expected\":\"non-negative number\",\"got\":\"-60\"}") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[0].sport\",\"expected\":\"known sport\",\"got\":\"quidditch\"}") != NULL);
+    assert(strstr(resp, "{\"path\":\"$[1].date\"") != NULL && strstr(resp, "scheduledDate") == NULL);
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"tss\":80,\"custom\":\"kept\",\"date\":\"2026-03-02T07:00:00Z\",\"sport\":\"Ride\"},{\"id\":7,\"tss\":null,\"sport\":\"\"}]",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    run_request(&env.db, "GET /v1/schemas/wellness_samples HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"type\":\"array\",\"items\":{\"type\":\"object\",\"properties\":{\"date\":{\"type\":[\"string\",\"null\"],\"pattern\":") != NULL);
+    assert(strstr(resp, "\"hrv\":{\"type\":[\"number\",\"null\"],\"minimum\":0}") != NULL);
+    run_request(&env.db, "GET /v1/schemas HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"$id\":\"/v1/schemas/archived_activities\"") != NULL);
+    run_request(&env.db, "GET /v1/schemas/nope HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_secrets_load_from_files_and_stay_out_of_reports(void) {
+    char path[] = "/tmp/fricu-test-secret-XXXXXX";
+    int fd = mkstemp(path);
+    assert(fd >= 0);
+    assert(write(fd, "file-token-123\n", 15) == 15);
+    close(fd);
+    char bundle[] = "/tmp/fricu-test-secrets-env-XXXXXX";
+    fd = mkstemp(bundle);
+    assert(fd >= 0);
+    const char *dotenv = "# rendered by sops\nexport FRICU_TEST_BUNDLE_TOKEN=\"bundle-token-456\"\nFRICU_TEST_OTHER=x\n";
+    assert(write(fd, dotenv, strlen(dotenv)) == (ssize_t)strlen(dotenv));
+    close(fd);
+
+    unsetenv("FRICU_TEST_FILE_TOKEN");
+    setenv("FRICU_TEST_FILE_TOKEN_FILE", path, 1);
+    setenv("FRICU_SECRETS_FILE", bundle, 1);
+    assert(strcmp(config_secret("FRICU_TEST_FILE_TOKEN"), "file-token-123") == 0);
+    assert(strcmp(config_secret("FRICU_TEST_BUNDLE_TOKEN"), "bundle-token-456") == 0);
+    assert(config_secret("FRICU_TEST_MISSING_TOKEN") == NULL);
+    setenv("FRICU_TEST_FILE_TOKEN", "env-wins", 1);
+    assert(strcmp(config_secret("FRICU_TEST_FILE_TOKEN"), "env-wins") == 0);
+    unsetenv("FRICU_TEST_FILE_TOKEN");
+
+    char line[512] = {0};
+    assert(config_secret_describe("FRICU_TEST_BUNDLE_TOKEN", line, sizeof(line)) == 0);
+    assert(strstr(line, "bundle-token") == NULL && strstr(line, "from secrets_file") != NULL);
+    setenv("FRICU_TEST_BROKEN_TOKEN_FILE", "/nonexistent/fricu-secret", 1);
+    assert(config_secret_describe("FRICU_TEST_BROKEN_TOKEN", line, sizeof(line)) == -1);
+    assert(strstr(line, "<error: cannot open") != NULL);
+
+    setenv("FRICU_ADMIN_TOKEN", "admin-secret-789", 1);
+    char *report = NULL;
+    size_t report_len = 0;
+    FILE *out = open_memstream(&report, &report_len);
+    assert(out != NULL);
+    assert(config_check(out) == 0);
+    fclose(out);
+    assert(strstr(report, "admin-secret") == NULL);
+    assert(strstr(report, "FRICU_ADMIN_TOKEN=<redacted, 16 bytes, from env>") != NULL);
+    assert(strstr(report, "config ok") != NULL);
+    free(report);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    unsetenv("FRICU_SECRETS_FILE");
+    unsetenv("FRICU_TEST_FILE_TOKEN_FILE");
+    unsetenv("FRICU_TEST_BROKEN_TOKEN_FILE");
+    unlink(path);
+    unlink(bundle);
+}
+
+static void test_data_minimization_strips_samples_and_source_files(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-minimize-XXXXXX");
+    char resp[16384] = {0};
+    const char *ride = "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"avgHeartRate\":150,\"heartRateSamples\":[140,150,160],"
+                       "\"sourceFileName\":\"ride.fit\",\"sourceFileBase64\":\"AAAA\"}]";
+
+    put_json(&env.db, "other", "activities", ride, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    put_json(&env.db, "tester", "app_settings", "{\"dataMinimization\":true}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    put_json(&env.db, "tester", "activities", ride, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    const char *stored = "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"avgHeartRate\":150,\"sourceFileName\":\"ride.fit\"}]";
+    char version[32] = {0};
+    content_version(stored, strlen(stored), version, sizeof(version));
+    char expected[64] = {0};
+    snprintf(expected, sizeof(expected), "X-Fricu-Version: %s\r\n", version);
+    assert(strstr(resp, expected) != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, stored) != NULL && strstr(resp, expected) != NULL);
+
+    /* Items written before the mode was switched on are reported and stripped by the validator. */
+    assert(
+        sqlite3_exec(
+            env.db.db,
+            "INSERT INTO kv_store(data_key, data_value, updated_at) VALUES ('tester::archived_activities',"
+            " '[{\"id\":\"old\",\"heartRateSamples\":[120]},{\"id\":\"clean\"}]', 0);",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    run_request(&env.db, "GET /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"privacy\":1,") != NULL);
+    assert(strstr(resp, "\"category\":\"privacy\",\"check\":\"minimization_violation\",\"account\":\"tester\",\"key\":\"archived_activities\",\"id\":\"old\"") != NULL);
+    assert(strstr(resp, "\"account\":\"other\"") == NULL);
+    run_request(&env.db, "POST /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"minimization_violation\"") != NULL && strstr(resp, "\"fixed\":true") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/archived_activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "[{\"id\":\"old\"},{\"id\":\"clean\"}]") != NULL);
+    unsetenv("FRICU_ADMIN_TOKEN");
+
+    /* Accounts that did not opt in keep everything. */
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"heartRateSamples\":[140,150,160]") != NULL && strstr(resp, "\"sourceFileBase64\":\"AAAA\"") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_users_scope_data_routes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-users-XXXXXX");
+    char resp[16384] = {0};
+    char req[4096] = {0};
+    char token[96] = {0};
+
+    put_json(&env.db, "rider-b", "activities", "[{\"id\":\"b1\",\"date\":\"2026-03-02\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Another account needs a grant from rider-b. */
+    send_item_request(&env.db, "GET", "/v1/users/rider-b/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    assert(strstr(resp, "{\"error\":\"no access to user\",\"user\":\"rider-b\"}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/users/rider%2Fb/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    run_request(
+        &env.db,
+        "PUT /v1/users/rider-b HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: rider-b\r\nContent-Length: 18\r\n\r\n{\"name\":\"Rider B\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"id\":\"rider-b\",\"name\":\"Rider B\",\"registered_at\":") != NULL);
+    assert(strstr(resp, "\"self\":true,\"keys\":[\"activities\"]}") != NULL);
+    run_request(
+        &env.db,
+        "PUT /v1/users/rider-b HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: rider-b\r\nContent-Length: 11\r\n\r\n{\"name\":42}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    run_request(
+        &env.db,
+        "POST /v1/coach/tokens HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: rider-b\r\nContent-Length: 20\r\n\r\n{\"name\":\"Coach Kim\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1);
+
+    /* The coach token alone reaches rider-b's documents and items. */
+    snprintf(req, sizeof(req), "GET /v1/users/rider-b/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Coach-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"b1\",\"date\":\"2026-03-02\"}]") != NULL);
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/users/rider-b/data/activities/items/b2 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s\r\n"
+        "Content-Length: 31\r\n\r\n{\"id\":\"b2\",\"date\":\"2026-03-03\"}",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "HTTP/1.1 20") != NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: rider-b\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"b2\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"b2\"") == NULL);
+
+    snprintf(req, sizeof(req), "GET /v1/users HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Coach-Token: %s, %s\r\n\r\n", token, token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"users\":[{\"id\":\"tester\",\"name\":null,\"registered_at\":null,\"self\":true,\"keys\":[]},{\"id\":\"rider-b\",\"name\":\"Rider B\",") != NULL);
+    assert(strstr(strstr(resp, "\"rider-b\"") + 1, "\"rider-b\"") == NULL);
+    run_request(&env.db, "GET /v1/users HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_coach_reads_athlete_data_under_bearer_auth(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-coach-bearer-XXXXXX");
+    char resp[16384] = {0};
+    char req[4096] = {0};
+    char coach_token[96] = {0};
+    char api_token[96] = {0};
+
+    put_json(&env.db, "alice", "profile", "{\"ftpWatts\":230}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(
+        &env.db,
+        "POST /v1/coach/tokens HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: alice\r\nContent-Length: 16\r\n\r\n{\"name\":\"Coach\"}",
+        resp,
+        sizeof(resp));
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", coach_token) == 1);
+
+    /* Issuing the first API token turns bearer auth on; the coach authenticates as their own account. */
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 35\r\n\r\n{\"account\":\"coach\",\"name\":\"tablet\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", api_token) == 1);
+
+    snprintf(
+        req,
+        sizeof(req),
+        "GET /v1/users/alice/data/profile HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\nX-Coach-Token: %s\r\n\r\n",
+        api_token,
+        coach_token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"ftpWatts\":230}") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/users/alice/data/profile HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", api_token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL && strstr(resp, "no access to user") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/users/alice/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Coach-Token: %s\r\n\r\n", coach_token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_bearer_tokens_guard_api_routes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-bearer-XXXXXX");
+    char resp[16384] = {0};
+    char req[4096] = {0};
+    char token[96] = {0};
+    char token_id[32] = {0};
+    char hash[65] = {0};
+
+    sha256_hex("abc", 3, hash, sizeof(hash));
+    assert(strcmp(hash, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad") == 0);
+    sha256_hex("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", 56, hash, sizeof(hash));
+    assert(strcmp(hash, "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1") == 0);
+
+    /* Without any token the API stays open. */
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\n{\"a\":1",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 13\r\n\r\n{\"name\":\"ci\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "account is required") != NULL);
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 36\r\n\r\n{\"account\":\"tester\",\"name\":\"laptop\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\",\"id\":\"%31[^\"]\"", token, token_id) == 2);
+    assert(strncmp(token, "fat_", 4) == 0 && strncmp(token_id, "tok_", 4) == 0);
+    assert(strstr(resp, "\"account\":\"tester\",\"name\":\"laptop\"}") != NULL);
+
+    /* Only the hash is kept. */
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT COUNT(*) FROM api_tokens WHERE token_hash = ?1", -1, &stmt, NULL) == SQLITE_OK);
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) == 1);
+    sqlite3_finalize(stmt);
+    run_request(&env.db, "GET /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"required\":true,\"tokens\":[{\"id\":\"tok_") != NULL);
+    assert(strstr(resp, "\"last_used_at\":null}]}") != NULL && strstr(resp, token) == NULL);
+
+    /* Now every API route needs the token, /health does not. */
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "WWW-Authenticate: Bearer") != NULL);
+    assert(strstr(resp, "{\"error\":\"missing bearer token\"}") != NULL);
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    run_request(&env.db, "GET /v2/data/activities/items HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer fat_nope\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "invalid bearer token") != NULL);
+
+    /* last_used_at is touched by the writer, never on the worker connection. */
+    assert(sqlite3_exec(env.db.db, "PRAGMA query_only = 1", NULL, NULL, NULL) == SQLITE_OK);
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\nContent-Length: 12\r\n\r\n[{\"id\":\"a\"}]",
+        token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    assert(sqlite3_exec(env.db.db, "PRAGMA query_only = 0", NULL, NULL, NULL) == SQLITE_OK);
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT last_used_at FROM api_tokens WHERE token_hash = ?1", -1, &stmt, NULL) == SQLITE_OK);
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_type(stmt, 0) == SQLITE_INTEGER);
+    sqlite3_finalize(stmt);
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT data_value FROM kv_store WHERE data_key = 'tester::activities'", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), "[{\"id\":\"a\"}]") == 0);
+    sqlite3_finalize(stmt);
+    snprintf(req, sizeof(req), "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAuthorization: bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"a\"}]") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL);
+    run_request(&env.db, "GET /v1/admin/tokens?account=tester HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"last_used_at\":null") == NULL);
+
+    snprintf(req, sizeof(req), "DELETE /v1/admin/tokens/%s HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", token_id);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    /* FRICU_API_AUTH=required closes the API before the first token is issued. */
+    setenv("FRICU_API_AUTH", "required", 1);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    unsetenv("FRICU_API_AUTH");
+    unsetenv("FRICU_ADMIN_TOKEN");
+
+    test_env_close(&env);
+}
+
+static void kv_store_dump(sqlite3 *db, const char *account_prefix, char *out, size_t out_len) {
+    sqlite3_stmt *stmt = NULL;
+    assert(
+        sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE(group_concat(data_key || '=' || data_value, '\n'), '') FROM (SELECT data_key, data_value FROM kv_store"
+            " WHERE substr(data_key, 1, length(?1)) = ?1 ORDER BY data_key)",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK);
+    sqlite3_bind_text(stmt, 1, account_prefix, -1, SQLITE_TRANSIENT);
+    assert(sqlite3_step(stmt) == SQLITE_ROW);
+    snprintf(out, out_len, "%s", (const char *)sqlite3_column_text(stmt, 0));
+    sqlite3_finalize(stmt);
+}
+
+static void test_event_sourcing_projection_matches_store(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-events-XXXXXX");
+    char resp[16384] = {0};
+    char before[8192] = {0};
+    char after[8192] = {0};
+
+    /* Written while the mode is off: picked up as a baseline event at the next start. */
+    put_json(&env.db, "tester", "profile", "{\"cyclingFTPWatts\":250}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    setenv("FRICU_EVENT_SOURCING", "1", 1);
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    assert(init_db("state.db") == 0);
+    run_request(&env.db, "GET /v1/admin/events?account=tester HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"enabled\":true,\"events\":[{\"seq\":") != NULL);
+    assert(strstr(resp, "\"account\":\"tester\",\"key\":\"profile\",\"op\":\"baseline\",\"bytes\":23,") != NULL);
+
+    /* Every write path appends an event; identical rewrites do not. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":50}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":50}]", resp, sizeof(resp));
+    send_item_request(&env.db, "PUT", "/v2/data/activities/items/a2", "{\"id\":\"a2\",\"date\":\"2026-03-03\",\"tss\":70}", resp, sizeof(resp));
+    assert(strstr(resp, "HTTP/1.1 20") != NULL);
+    send_item_request(&env.db, "DELETE", "/v2/data/activities/items/a1", NULL, resp, sizeof(resp));
+    put_json(&env.db, "other", "workouts", "[{\"id\":\"w1\"}]", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/admin/events?account=tester&key=activities HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    const char *first = strstr(resp, "\"key\":\"activities\",\"op\":\"put\"");
+    assert(first != NULL);
+    const char *second = strstr(first + 1, "\"key\":\"activities\",\"op\":\"put\"");
+    assert(second != NULL && strstr(second + 1, "\"key\":\"activities\",\"op\":\"put\"") != NULL);
+    assert(strstr(resp, "\"workouts\"") == NULL && strstr(resp, "\"profile\"") == NULL);
+    assert(sqlite3_exec(env.db.db, "UPDATE data_events SET payload = '[]'", NULL, NULL, NULL) != SQLITE_OK);
+    assert(sqlite3_exec(env.db.db, "DELETE FROM data_events", NULL, NULL, NULL) != SQLITE_OK);
+
+    /* The projection equals the live store, and a rebuild restores it exactly. */
+    run_request(&env.db, "POST /v1/admin/events/rebuild?dry_run=1 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"dry_run\":true,") != NULL);
+    assert(strstr(resp, "\"unprojected\":0,\"mismatches\":[],\"mismatch_count\":0,\"rebuilt\":0}") != NULL);
+    kv_store_dump(env.db.db, "tester::", before, sizeof(before));
+    assert(strstr(before, "\"id\":\"a2\"") != NULL && strstr(before, "\"id\":\"a1\"") == NULL);
+    assert(sqlite3_exec(env.db.db, "DELETE FROM kv_store WHERE data_key LIKE 'tester::%'", NULL, NULL, NULL) == SQLITE_OK);
+    run_request(&env.db, "POST /v1/admin/events/rebuild?dry_run=1 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"mismatches\":[\"tester::activities\",\"tester::profile\"],\"mismatch_count\":2,\"rebuilt\":0}") != NULL);
+    run_request(&env.db, "POST /v1/admin/events/rebuild HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"dry_run\":false,") != NULL && strstr(resp, "\"mismatch_count\":2,\"rebuilt\":2}") != NULL);
+    kv_store_dump(env.db.db, "tester::", after, sizeof(after));
+    assert(strcmp(before, after) == 0);
+    run_request(&env.db, "GET /v1/admin/events?account=tester&key=activities HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    first = strstr(resp, "\"op\":\"put\"");
+    assert(first && (second = strstr(first + 1, "\"op\":\"put\"")) && (second = strstr(second + 1, "\"op\":\"put\"")) && !strstr(second + 1, "\"op\""));
+
+    /* Turning the mode off stops recording at the next start. */
+    unsetenv("FRICU_EVENT_SOURCING");
+    assert(init_db("state.db") == 0);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w2\"}]", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/admin/events?account=tester&key=workouts HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"enabled\":false,\"events\":[],") != NULL);
+    unsetenv("FRICU_ADMIN_TOKEN");
+
+    test_env_close(&env);
+}
+
+static long long snapshot_seq_header(const char *resp) {
+    const char *h = strstr(resp, "X-Snapshot-Seq: ");
+    return h ? atoll(h + strlen("X-Snapshot-Seq: ")) : -1;
+}
+
+static void test_analytics_reads_use_one_snapshot(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-snapshot-XXXXXX");
+    char resp[16384] = {0};
+
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":50}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/analytics/risk?weeks=4 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    long long first = snapshot_seq_header(resp);
+    assert(strstr(resp, "200 OK") != NULL && first > 0);
+    run_request(&env.db, "GET /v1/analytics/heart HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(snapshot_seq_header(resp) == first);
+    assert(sqlite3_get_autocommit(env.db.db));
+
+    /* Any write moves the sequence; other routes never report one. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":60}]", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/analytics/risk?weeks=4 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(snapshot_seq_header(resp) > first);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && snapshot_seq_header(resp) == -1);
+
+    /* A commit from another connection stays invisible until the snapshot ends. */
+    long long seq = 0;
+    assert(db_read_snapshot_begin(env.db.db, &seq) == 0);
+    sqlite3 *writer = NULL;
+    assert(sqlite3_open("state.db", &writer) == SQLITE_OK);
+    assert(sqlite3_exec(writer, "UPDATE kv_store SET data_value = '[]' WHERE data_key = 'tester::activities'", NULL, NULL, NULL) == SQLITE_OK);
+    sqlite3_close(writer);
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = "SELECT (SELECT seq FROM store_sequence), (SELECT data_value FROM kv_store WHERE data_key = 'tester::activities')";
+    assert(sqlite3_prepare_v2(env.db.db, sql, -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW);
+    assert(sqlite3_column_int64(stmt, 0) == seq && strstr((const char *)sqlite3_column_text(stmt, 1), "\"a1\"") != NULL);
+    sqlite3_finalize(stmt);
+    db_read_snapshot_end(env.db.db);
+    assert(sqlite3_prepare_v2(env.db.db, sql, -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW);
+    assert(sqlite3_column_int64(stmt, 0) == seq + 1 && strcmp((const char *)sqlite3_column_text(stmt, 1), "[]") == 0);
+    sqlite3_finalize(stmt);
+
+    test_env_close(&env);
+}
+
+static void test_tls_redirect_location(void) {
+    char location[256] = {0};
+    assert(tls_redirect_location("GET /v1/sync/manifest?since=0 HTTP/1.1\r\nHost: fricu.example:8080\r\n\r\n", 8443, location, sizeof(location)) == 0);
+    assert(strcmp(location, "https://fricu.example:8443/v1/sync/manifest?since=0") == 0);
+    assert(tls_redirect_location("GET / HTTP/1.1\r\nhost: [::1]:80\r\n\r\n", 443, location, sizeof(location)) == 0);
+    assert(strcmp(location, "https://[::1]/") == 0);
+    assert(tls_redirect_location("GET / HTTP/1.1\r\nHost: evil.example\r\nX: y\r\n\r\n", 443, location, sizeof(location)) == 0);
+    assert(strcmp(location, "https://evil.example/") == 0);
+    assert(tls_redirect_location("GET / HTTP/1.1\r\n\r\n", 443, location, sizeof(location)) != 0);
+    assert(tls_redirect_location("GET http://a/ HTTP/1.1\r\nHost: a\r\n\r\n", 443, location, sizeof(location)) != 0);
+    assert(tls_redirect_location("GET / HTTP/1.1\r\nHost: a\"b\r\n\r\n", 443, location, sizeof(location)) != 0);
+}
+
+static void test_admin_index_advisor(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-indexes-XXXXXX");
+    char resp[32768] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"date\":\"2026-03-02\",\"tss\":50}]", resp, sizeof(resp));
+
+    run_request(&env.db, "GET /v1/admin/indexes HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(&env.db, "GET /v1/admin/indexes HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"suspicions\":4}") != NULL);
+    const char *activities = strstr(resp, "{\"name\":\"activities_by_date\",\"table\":\"kv_store\",\"rows\":");
+    assert(activities != NULL);
+    assert(strstr(activities, "\"full_scan\":true,\"temp_sort\":false,\"recommendation\":null,\"note\":\"activities are one JSON array") != NULL);
+    assert(strstr(resp, "\"recommendation\":{\"index\":\"idx_key_snapshots_day\",") != NULL && strstr(resp, "\"present\":false}") != NULL);
+
+    /* Without the confirmation flag nothing is created. */
+    run_request(&env.db, "POST /v1/admin/indexes HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"confirmed\":false,\"would_create\":[\"idx_notifications_recent\",\"idx_key_snapshots_day\"],\"queries\":[") != NULL);
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT COUNT(*) FROM sqlite_master WHERE name IN ('idx_notifications_recent', 'idx_key_snapshots_day')", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) == 0);
+    sqlite3_reset(stmt);
+
+    run_request(&env.db, "POST /v1/admin/indexes?confirm=1 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"confirmed\":true,\"created\":[\"idx_notifications_recent\",\"idx_key_snapshots_day\"],") != NULL);
+    assert(strstr(resp, "\"SEARCH key_snapshots USING INDEX idx_key_snapshots_day (day<?)\"") != NULL && strstr(resp, "\"suspicions\":2}") != NULL);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) == 2);
+    sqlite3_finalize(stmt);
+    run_request(&env.db, "POST /v1/admin/indexes?confirm=1 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"confirmed\":true,\"created\":[],") != NULL);
+
+    test_env_close(&env);
+    unsetenv("FRICU_ADMIN_TOKEN");
+}
+
+static void test_if_match_guards_data_writes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-ifmatch-XXXXXX");
+    char resp[16384] = {0};
+    char req[2048] = {0};
+    char etag[64] = {0};
+
+    /* Nothing matches a missing document, not even "*". */
+    run_request(&env.db, "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: *\r\nContent-Length: 2\r\n\r\n[]", resp, sizeof(resp));
+    assert(strstr(resp, "412 Precondition Failed") != NULL && strstr(resp, "ETag:") == NULL);
+    assert(strstr(resp, "{\"error\":\"precondition failed\",\"key\":\"workouts\",\"current_version\":\"0\",\"updated_at\":0}") != NULL);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\"}]", resp, sizeof(resp));
+    const char *tag = strstr(resp, "ETag: \"");
+    assert(strstr(resp, "204 No Content") != NULL && tag != NULL);
+    snprintf(etag, sizeof(etag), "%.*s", (int)strcspn(tag + 6, "\r"), tag + 6);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, etag) != NULL);
+
+    /* A stale or weak tag is refused with the current ETag; the store is untouched. */
+    run_request(
+        &env.db, "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: \"stale\"\r\nContent-Length: 2\r\n\r\n[]", resp, sizeof(resp));
+    assert(strstr(resp, "412 Precondition Failed") != NULL && strstr(resp, etag) != NULL);
+    snprintf(req, sizeof(req), "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: W/%s\r\nContent-Length: 2\r\n\r\n[]", etag);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "412 Precondition Failed") != NULL);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "[{\"id\":\"w1\"}]") != NULL);
+
+    /* Any listed strong tag matches, on full writes, deltas and item writes alike. */
+    snprintf(req, sizeof(req), "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: \"old\", %s\r\nContent-Length: 13\r\n\r\n[{\"id\":\"w2\"}]", etag);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, etag) == NULL);
+    snprintf(req, sizeof(req), "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: %s\r\nContent-Type: application/x-fricu-delta\r\nX-Fricu-Base-Version: 0\r\nContent-Length: 2\r\n\r\n[]", etag);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL);
+    run_request(&env.db, "PUT /v2/data/workouts/items/w3 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: \"stale\"\r\nContent-Length: 11\r\n\r\n{\"id\":\"w3\"}", resp, sizeof(resp));
+    assert(strstr(resp, "412 Precondition Failed") != NULL);
+    run_request(&env.db, "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Match: *\r\nContent-Length: 2\r\n\r\n[]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_response_guardrails_refuse_unbounded_reads(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-guard-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\"},{\"id\":\"a2\"},{\"id\":\"a3\"},{\"id\":\"a4\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    setenv("FRICU_RESPONSE_MAX_ITEMS", "3", 1);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "413 Payload Too Large") != NULL);
+    assert(strstr(resp, "{\"error\":\"response too large\",\"items\":4,\"bytes\":49,\"max_items\":3,\"max_bytes\":33554432,") != NULL);
+    assert(strstr(resp, "\"hint\":\"fetch this key in pages with GET /v2/data/activities/items?offset=0&limit=1000\"}") != NULL);
+    run_request(&env.db, "GET /v2/data/activities/items?limit=2 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"total\":4,\"offset\":0,\"limit\":2,") != NULL);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    /* Byte limits apply to pages too; 0 turns a limit off. */
+    setenv("FRICU_RESPONSE_MAX_ITEMS", "0", 1);
+    setenv("FRICU_RESPONSE_MAX_BYTES", "40", 1);
+    run_request(&env.db, "GET /v2/data/activities/items?limit=4 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "413 Payload Too Large") != NULL && strstr(resp, "\"items\":-1,") != NULL);
+    assert(strstr(resp, "\"hint\":\"request fewer items per page with a smaller limit\"}") != NULL);
+    setenv("FRICU_RESPONSE_MAX_BYTES", "0", 1);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"id\":\"a4\"") != NULL);
+
+    unsetenv("FRICU_RESPONSE_MAX_ITEMS");
+    unsetenv("FRICU_RESPONSE_MAX_BYTES");
+    test_env_close(&env);
+}
+
+static void test_conditional_get_honors_if_modified_since(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-ims-XXXXXX");
+    char resp[16384] = {0};
+    char req[2048] = {0};
+    char etag[64] = {0};
+
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Last-Modified:") == NULL);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    assert(sqlite3_exec(env.db.db, "UPDATE kv_store SET updated_at = 1760000000", NULL, NULL, NULL) == SQLITE_OK);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    const char *tag = strstr(resp, "ETag: \"");
+    assert(strstr(resp, "200 OK") != NULL && tag != NULL);
+    assert(strstr(resp, "Last-Modified: Thu, 09 Oct 2025 08:53:20 GMT\r\n") != NULL);
+    snprintf(etag, sizeof(etag), "%.*s", (int)strcspn(tag + 6, "\r"), tag + 6);
+
+    /* Unchanged since the given second: 304 with the validators and no body. */
+    run_request(
+        &env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Modified-Since: Thu, 09 Oct 2025 08:53:20 GMT\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "304 Not Modified") != NULL && strstr(resp, etag) != NULL && strstr(resp, "Last-Modified:") != NULL);
+    assert(strstr(resp, "Content-Length: 0\r\n") != NULL && strstr(resp, "w1") == NULL);
+    run_request(
+        &env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Modified-Since: Thu, 09 Oct 2025 08:53:19 GMT\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"w1\"}]") != NULL);
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Modified-Since: yesterday\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    /* If-None-Match wins over If-Modified-Since when both are sent. */
+    run_request(
+        &env.db,
+        "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-None-Match: \"stale\"\r\nIf-Modified-Since: Thu, 09 Oct 2025 08:53:20 GMT\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-None-Match: W/%s\r\n\r\n", etag);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "304 Not Modified") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_deadline_interrupts_stuck_requests(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-deadline-XXXXXX");
+    char resp[16384] = {0};
+
+    assert(deadline_budget_ms("GET", "/v1/data/workouts") == 2000);
+    assert(deadline_budget_ms("PUT", "/v1/data/workouts") == 10000);
+    assert(deadline_budget_ms("POST", "/v1/import/zwift") == 0);
+    assert(deadline_budget_ms("POST", "/v1/import") == 0);
+    assert(deadline_budget_ms("POST", "/v1/importer") == 10000);
+    assert(deadline_budget_ms("GET", "/v1/imports") == 0);
+    setenv("FRICU_READ_TIMEOUT_MS", "25", 1);
+    assert(deadline_budget_ms("HEAD", "/v1/profile") == 25);
+
+    /* A statement that would never finish is interrupted once the budget is spent. */
+    http_request_t req = {.method = "GET", .path = "/v1/data/workouts", .query = ""};
+    request_log_context_t ctx = {0};
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c", -1, &stmt, NULL) == SQLITE_OK);
+    deadline_begin(&req);
+    assert(sqlite3_step(stmt) == SQLITE_INTERRUPT && deadline_expired());
+    sqlite3_finalize(stmt);
+    deadline_end(env.db.db, &req, &ctx);
+    assert(!deadline_expired());
+
+    /* Over HTTP the handler's answer becomes a 504, and the connection keeps serving. */
+    assert(sqlite3_exec(
+               env.db.db,
+               "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 200000)"
+               " INSERT INTO kv_store (data_key, data_value, updated_at) SELECT 'tester::activities', json_group_array(json_object('id', x)), 1 FROM c",
+               NULL,
+               NULL,
+               NULL) == SQLITE_OK);
+    setenv("FRICU_READ_TIMEOUT_MS", "1", 1);
+    run_request(&env.db, "GET /v2/data/activities/items?limit=1 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "504 Gateway Timeout") != NULL && strstr(resp, "{\"error\":\"timeout\",\"budget_ms\":1}") != NULL);
+    assert(sqlite3_get_autocommit(env.db.db));
+    setenv("FRICU_READ_TIMEOUT_MS", "0", 1);
+    run_request(&env.db, "GET /v2/data/activities/items?limit=1 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"total\":200000,") != NULL);
+    unsetenv("FRICU_READ_TIMEOUT_MS");
+
+    test_env_close(&env);
+}
+
+static void put_with_clock(worker_db_t *db, const char *date_header, const char *json, char *resp, size_t resp_len) {
+    char req[4096] = {0};
+    int n = snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Device-Id: ios-1\r\n%sContent-Length: %zu\r\n\r\n%s",
+        date_header,
+        strlen(json),
+        json);
+    assert(n > 0 && (size_t)n < sizeof(req));
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_clock_skew_warnings_are_counted_per_device(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-skew-XXXXXX");
+    char resp[16384] = {0};
+    char header[96] = {0};
+    char req[512] = {0};
+    char date[64] = {0};
+    time_t behind = time(NULL) - 3600;
+    struct tm tm_utc;
+    gmtime_r(&behind, &tm_utc);
+    strftime(date, sizeof(date), "%a, %d %b %Y %H:%M:%S GMT", &tm_utc);
+
+    /* Within the tolerance nothing is reported or recorded. */
+    put_with_clock(&env.db, "", "[{\"id\":\"a1\",\"date\":\"2024-05-01\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "X-Fricu-Warnings") == NULL);
+
+    snprintf(header, sizeof(header), "Date: %s\r\n", date);
+    put_with_clock(&env.db, header, "[{\"id\":\"a1\",\"date\":\"2024-05-01\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "X-Fricu-Warnings: clock_skew\r\n") != NULL);
+    assert(strstr(resp, "X-Fricu-Clock-Offset: -360") != NULL);
+    put_with_clock(&env.db, "", "[{\"id\":\"a1\",\"date\":\"2024-05-01\"},{\"id\":\"a2\",\"date\":\"2999-01-01T07:00:00Z\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "X-Fricu-Warnings: future_activity_date\r\n") != NULL);
+    assert(strstr(resp, "X-Fricu-Clock-Offset") == NULL);
+
+    /* If-Unmodified-Since is a client clock too; reads report skew just like writes. */
+    snprintf(req, sizeof(req), "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nIf-Unmodified-Since: %s\r\n\r\n", date);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "X-Fricu-Warnings: clock_skew\r\n") != NULL);
+
+    run_request(&env.db, "GET /v1/devices HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"clock_skew_tolerance_seconds\":300,") != NULL);
+    assert(strstr(resp, "{\"device_id\":\"ios-1\",\"skewed_requests\":1,\"future_dates\":1,\"last_offset_seconds\":-360") != NULL);
+    run_request(&env.db, "GET /v1/devices HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: someone-else\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"devices\":[]}") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_sync_changes_since_timestamp(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sync-changes-XXXXXX");
+    char resp[16384] = {0};
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"tss\":40},{\"id\":\"a2\",\"tss\":50},{\"id\":\"a3\",\"tss\":60}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    put_json(&env.db, "tester", "events", "[{\"title\":\"race\"}]", resp, sizeof(resp));
+    assert(sqlite3_exec(env.db.db, "UPDATE kv_store SET updated_at = 1000; UPDATE item_changes SET updated_at = 1000", NULL, NULL, NULL) == SQLITE_OK);
+
+    /* A cursor before the last write still gets whole documents when it is 0. */
+    run_request(&env.db, "GET /v1/sync HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"since\":0,\"next_since\":") != NULL);
+    assert(strstr(resp, "\"key\":\"activities\"") != NULL && strstr(resp, "\"value\":[{\"id\":\"a1\",\"tss\":40},") != NULL);
+    run_request(&env.db, "GET /v1/sync?since=1000 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"keys\":[]}") != NULL);
+
+    /* Only the changed and added items come back, with removed ids as tombstones. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"tss\":40},{\"id\":\"a2\",\"tss\":55},{\"id\":\"a4\",\"tss\":70}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/sync?since=1500 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"items\":[{\"id\":\"a2\",\"tss\":55},{\"id\":\"a4\",\"tss\":70}],\"deleted\":[\"a3\"]}") != NULL);
+    assert(strstr(resp, "\"a1\"") == NULL && strstr(resp, "\"events\"") == NULL);
+
+    /* Documents without item ids, and object keys, are sent whole. */
+    put_json(&env.db, "tester", "events", "[{\"title\":\"race day\"}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250}", resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/sync?since=1500 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"key\":\"events\"") != NULL && strstr(resp, "\"value\":[{\"title\":\"race day\"}]}") != NULL);
+    assert(strstr(resp, "\"value\":{\"ftp\":250}}") != NULL);
+    run_request(&env.db, "GET /v1/sync?since=later HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/sync?since=1500 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: someone-else\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"keys\":[]}") != NULL);
+
+    test_env_close(&env);
+}
+
+static void post_reconcile(worker_db_t *db, const char *account, const char *body, char *resp, size_t resp_len) {
+    char req[8192] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/sync/reconcile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: %s\r\nContent-Length: %zu\r\n\r\n%s",
+        account,
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_sync_reconcile_from_full_manifest(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-reconcile-XXXXXX");
+    char resp[16384] = {0};
+    char manifest[4096] = {0};
+    char profile_version[32] = {0};
+    char a1[32] = {0};
+    char a2[32] = {0};
+    char a3[32] = {0};
+    content_version("{\"ftp\":250}", 11, profile_version, sizeof(profile_version));
+    content_version("{\"id\":\"a1\",\"tss\":40}", 20, a1, sizeof(a1));
+    content_version("{\"id\":\"a2\",\"tss\":50}", 20, a2, sizeof(a2));
+    content_version("{\"id\":\"a3\",\"tss\":60}", 20, a3, sizeof(a3));
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250}", resp, sizeof(resp));
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"tss\":40},{\"id\":\"a2\",\"tss\":50},{\"id\":\"a3\",\"tss\":60}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* While the client is offline the server edits a2, deletes a3, gains a4 and a new key. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\",\"tss\":40},{\"id\":\"a2\",\"tss\":55},{\"id\":\"a4\",\"tss\":70}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "events", "[{\"title\":\"race\"}]", resp, sizeof(resp));
+
+    /* The client edited profile and a3, created a5 and app_settings, and still holds workouts the server dropped. */
+    snprintf(
+        manifest,
+        sizeof(manifest),
+        "{\"keys\":[{\"key\":\"profile\",\"version\":\"%s\",\"dirty\":true},"
+        "{\"key\":\"activities\",\"items\":[{\"id\":\"a1\",\"version\":\"%s\"},{\"id\":\"a2\",\"version\":\"%s\"},"
+        "{\"id\":\"a3\",\"version\":\"%s\",\"dirty\":true},{\"id\":\"a5\",\"dirty\":true}]},"
+        "{\"key\":\"app_settings\",\"version\":\"0\",\"dirty\":true},{\"key\":\"workouts\",\"version\":\"00000000000000ff\"}]}",
+        profile_version,
+        a1,
+        a2,
+        a3);
+    post_reconcile(&env.db, "tester", manifest, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "X-Fricu-Sync-Protocol: 1\r\n") != NULL);
+    const char *pull = strstr(resp, "],\"pull\":[");
+    const char *merge = strstr(resp, "],\"merge\":[");
+    assert(strstr(resp, "\"next_since\":") != NULL && pull != NULL && merge != NULL && pull < merge);
+    char expected[128] = {0};
+    snprintf(expected, sizeof(expected), "{\"key\":\"profile\",\"version\":\"%s\"}", profile_version);
+    const char *hit = strstr(resp, expected);
+    assert(hit != NULL && hit < pull);
+    hit = strstr(resp, "{\"key\":\"activities\",\"id\":\"a5\",\"version\":\"0\"}");
+    assert(hit != NULL && hit < pull);
+    hit = strstr(resp, "{\"key\":\"app_settings\",\"version\":\"0\"}");
+    assert(hit != NULL && hit < pull);
+    hit = strstr(resp, "\"value\":{\"id\":\"a2\",\"tss\":55}}");
+    assert(hit != NULL && hit > pull && hit < merge);
+    hit = strstr(resp, "\"value\":{\"id\":\"a4\",\"tss\":70}}");
+    assert(hit != NULL && hit > pull && hit < merge);
+    hit = strstr(resp, "\"value\":[{\"title\":\"race\"}]}");
+    assert(hit != NULL && hit > pull && hit < merge);
+    hit = strstr(resp, "{\"key\":\"workouts\",\"version\":\"0\",\"deleted\":true}");
+    assert(hit != NULL && hit > pull && hit < merge);
+    assert(strstr(resp, "\"merge\":[{\"key\":\"activities\",\"id\":\"a3\",\"version\":\"0\",\"deleted\":true}],\"unchanged\":1,\"locked_keys\":[]}") != NULL);
+    assert(strstr(resp, "\"a1\"") == NULL);
+
+    /* Both sides changed the same document: merge, with the server copy to merge against. */
+    post_reconcile(&env.db, "tester", "{\"keys\":[{\"key\":\"profile\",\"version\":\"0123456789abcdef\",\"dirty\":true}]}", resp, sizeof(resp));
+    assert(strstr(resp, "\"merge\":[{\"key\":\"profile\",\"version\":\"") != NULL && strstr(resp, "\"value\":{\"ftp\":250}}],\"unchanged\":0") != NULL);
+    post_reconcile(&env.db, "someone-else", "{\"keys\":[]}", resp, sizeof(resp));
+    assert(strstr(resp, "\"push\":[],\"pull\":[],\"merge\":[],\"unchanged\":0,") != NULL);
+
+    post_reconcile(&env.db, "tester", "{\"keys\":{}}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "\"error\":\"invalid manifest\"") != NULL);
+    post_reconcile(&env.db, "tester", "{\"keys\":[{\"key\":\"profile\"},{\"key\":\"profile\"}]}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    post_reconcile(&env.db, "tester", "{\"keys\":[{\"key\":\"nope\"}]}", resp, sizeof(resp));
+    assert(strstr(resp, "every entry needs a known key") != NULL);
+    post_reconcile(&env.db, "tester", "{\"keys\":[{\"key\":\"profile\",\"items\":[]}]}", resp, sizeof(resp));
+    assert(strstr(resp, "only for collection keys") != NULL);
+    post_reconcile(&env.db, "tester", "{\"keys\":[{\"key\":\"activities\",\"items\":[{\"id\":\"a b\"}]}]}", resp, sizeof(resp));
+    assert(strstr(resp, "every item needs a valid string id") != NULL);
+    run_request(
+        &env.db,
+        "POST /v1/sync/reconcile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Fricu-Sync-Protocol: 9\r\nContent-Length: 11\r\n\r\n{\"keys\":[]}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "unsupported sync protocol") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_out_of_range_activities_are_quarantined(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-quarantine-XXXXXX");
+    char resp[16384] = {0};
+    char doc[512] = {0};
+    char recent[40] = {0};
+    time_t hour_ago = time(NULL) - 3600;
+    struct tm tm_utc;
+    gmtime_r(&hour_ago, &tm_utc);
+    /* An hour ago written as UTC+14 is often tomorrow's date locally but never a future instant. */
+    tm_utc.tm_hour += 14;
+    time_t shifted = timegm(&tm_utc);
+    gmtime_r(&shifted, &tm_utc);
+    strftime(recent, sizeof(recent), "%Y-%m-%dT%H:%M:%S+14:00", &tm_utc);
+
+    snprintf(
+        doc,
+        sizeof(doc),
+        "[{\"id\":\"a1\",\"date\":\"%s\"},{\"id\":\"a2\",\"date\":\"2999-01-01T07:00:00Z\"},{\"id\":\"a3\",\"date\":\"1980-06-01\"}]",
+        recent);
+    put_json(&env.db, "tester", "activities", doc, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "X-Fricu-Quarantined: 2\r\n") != NULL);
+    assert(strstr(resp, "X-Fricu-Warnings: future_activity_date\r\n") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"a1\"") != NULL && strstr(resp, "\"a2\"") == NULL && strstr(resp, "\"a3\"") == NULL);
+
+    send_item_request(&env.db, "GET", "/v1/quarantine/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"id\":1,\"item_id\":\"a2\",\"activity_date\":\"2999-01-01\",\"reason\":\"in_future\",\"item\":{\"id\":\"a2\",") != NULL);
+    assert(strstr(resp, "{\"id\":2,\"item_id\":\"a3\",\"activity_date\":\"1980-06-01\",\"reason\":\"before_min_date\",") != NULL);
+
+    /* Releasing needs a date inside the bounds; the corrected activity joins the document. */
+    send_item_request(&env.db, "POST", "/v1/quarantine/activities/1/release", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL);
+    send_item_request(&env.db, "POST", "/v1/quarantine/activities/1/release", "{\"date\":\"2024-05-02\"}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"released\":1}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"id\":\"a2\",\"date\":\"2024-05-02\"}") != NULL);
+    send_item_request(&env.db, "POST", "/v1/quarantine/activities/1/release", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    send_item_request(&env.db, "DELETE", "/v1/quarantine/activities/2", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "GET", "/v1/quarantine/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"items\":[]}") != NULL);
+
+    /* A failed write parks nothing, and a bound set to off lets such dates through. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a4\",\"date\":\"1970-01-01\"},{\"id\":\"a5\",\"tss\":-1}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") == NULL && strstr(resp, "X-Fricu-Quarantined") == NULL);
+    send_item_request(&env.db, "GET", "/v1/quarantine/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "{\"items\":[]}") != NULL);
+    setenv("FRICU_ACTIVITY_MIN_DATE", "off", 1);
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a4\",\"date\":\"1970-01-01\"}]", resp, sizeof(resp));
+    unsetenv("FRICU_ACTIVITY_MIN_DATE");
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "X-Fricu-Quarantined") == NULL);
+
+    test_env_close(&env);
+}
+
+/* Reads one unmasked server frame; returns its opcode and copies the payload into out. */
+static int read_ws_frame(int fd, char *out, size_t out_len) {
+    unsigned char header[4] = {0};
+    assert(read(fd, header, 2) == 2);
+    size_t len = header[1] & 0x7f;
+    if (len == 126) {
+        assert(read(fd, header + 2, 2) == 2);
+        len = (size_t)header[2] << 8 | header[3];
+    }
+    assert(len < out_len);
+    size_t off = 0;
+    while (off < len) {
+        ssize_t n = read(fd, out + off, len - off);
+        assert(n > 0);
+        off += (size_t)n;
+    }
+    out[len] = '\0';
+    return header[0] & 0x0f;
+}
+
+static void test_websocket_pushes_change_events(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-ws-XXXXXX");
+    char resp[16384] = {0};
+    char frame[1024] = {0};
+
+    char accept[32] = {0};
+    ws_accept_key("dGhlIHNhbXBsZSBub25jZQ==", accept, sizeof(accept));
+    assert(strcmp(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=") == 0);
+
+    send_item_request(&env.db, "GET", "/v1/ws", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(
+        &env.db,
+        "GET /v1/ws HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n"
+        "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "426 Upgrade Required") != NULL && strstr(resp, "Sec-WebSocket-Version: 13\r\n") != NULL);
+
+    const char *upgrade =
+        "GET /v1/ws HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n"
+        "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+    int fds[2] = {-1, -1};
+    assert(socketpair(AF_UNIX, SOCK_STREAM, 0, fds) == 0);
+    struct timeval timeout = {.tv_sec = 5, .tv_usec = 0};
+    setsockopt(fds[1], SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+    conn_t conn = {0};
+    conn.cap = REQ_BUF_SIZE;
+    conn.buf = (char *)malloc(conn.cap + 1);
+    assert(conn.buf != NULL);
+    conn.len = strlen(upgrade);
+    memcpy(conn.buf, upgrade, conn.len);
+    assert(try_process_client(fds[0], &env.db, &conn) == 1);
+    close(fds[0]);
+    free(conn.buf);
+
+    size_t off = 0;
+    while (off + 1 < sizeof(resp) && (off < 4 || memcmp(resp + off - 4, "\r\n\r\n", 4) != 0)) {
+        assert(read(fds[1], resp + off, 1) == 1);
+        off++;
+    }
+    resp[off] = '\0';
+    assert(strstr(resp, "HTTP/1.1 101 Switching Protocols\r\n") != NULL);
+    assert(strstr(resp, "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n") != NULL);
+
+    /* Writes of other accounts are not delivered; the next frame is this account's own write. */
+    put_json(&env.db, "someone-else", "activities", "[{\"id\":\"x1\"}]", resp, sizeof(resp));
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    assert(read_ws_frame(fds[1], frame, sizeof(frame)) == 0x1);
+    assert(strncmp(frame, "{\"key\":\"profile\",\"updated_at\":", 30) == 0 && strstr(frame, ",\"revision\":\"") != NULL);
+
+    /* Ping is answered with the same payload, a close with a close. */
+    const unsigned char ping[] = {0x89, 0x82, 1, 2, 3, 4, 'h' ^ 1, 'i' ^ 2};
+    assert(write(fds[1], ping, sizeof(ping)) == (ssize_t)sizeof(ping));
+    assert(read_ws_frame(fds[1], frame, sizeof(frame)) == 0xa && strcmp(frame, "hi") == 0);
+    const unsigned char close_frame[] = {0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8};
+    assert(write(fds[1], close_frame, sizeof(close_frame)) == (ssize_t)sizeof(close_frame));
+    assert(read_ws_frame(fds[1], frame, sizeof(frame)) == 0x8);
+    assert(read(fds[1], frame, 1) == 0);
+    close(fds[1]);
+
+    test_env_close(&env);
+}
+
+static void test_deprecated_endpoints_are_reported_per_client(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-deprecation-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+    char token[96] = {0};
+    char token_id[32] = {0};
+    char headers[512] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+
+    deprecation_begin();
+    assert(deprecation_headers(headers, sizeof(headers)) == 0 && headers[0] == '\0');
+    deprecation_declare("/v1/old", API_V1_DATA_SUNSET, "/v2/new", "https://example.com/notes/old");
+    assert(deprecation_headers(headers, sizeof(headers)) > 0);
+    assert(strstr(headers, "Deprecation: true\r\nSunset: " API_V1_DATA_SUNSET "\r\nLink: </v2/new>; rel=\"successor-version\"\r\n") != NULL);
+    assert(strstr(headers, "Link: <https://example.com/notes/old>; rel=\"deprecation\"\r\n") != NULL);
+    deprecation_begin();
+
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 39\r\n\r\n{\"account\":\"dep-user\",\"name\":\"old-app\"}",
+        resp,
+        sizeof(resp));
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\",\"id\":\"%31[^\"]\"", token, token_id) == 2);
+
+    /* Errors from a deprecated route carry the headers too; the v2 successor does not. */
+    snprintf(req, sizeof(req), "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Deprecation: true\r\n") != NULL);
+    assert(strstr(resp, "Link: </v2/data/workouts/items>; rel=\"successor-version\"\r\n") != NULL);
+    snprintf(req, sizeof(req), "PUT /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\nContent-Length: 1\r\n\r\n[", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "Sunset: " API_V1_DATA_SUNSET "\r\n") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    snprintf(req, sizeof(req), "GET /v2/data/workouts/items HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Deprecation:") == NULL);
+
+    run_request(&env.db, "GET /v1/admin/stats HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"deprecated_usage\":{\"untracked_requests\":0,\"clients\":[") != NULL);
+    char expected[256] = {0};
+    snprintf(expected, sizeof(expected), "{\"endpoint\":\"GET /v1/data/workouts\",\"client\":\"%s\",\"account\":\"dep-user\",\"requests\":2,", token_id);
+    assert(strstr(resp, expected) != NULL);
+    snprintf(expected, sizeof(expected), "{\"endpoint\":\"PUT /v1/data/workouts\",\"client\":\"%s\",\"account\":\"dep-user\",\"requests\":1,", token_id);
+    assert(strstr(resp, expected) != NULL);
+    assert(strstr(resp, "/v2/data/workouts/items\",\"client\"") == NULL);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+/* Reads up to and including the blank line that ends an SSE event (or the HTTP headers before it). */
+static void read_sse_event(int fd, char *out, size_t out_len) {
+    size_t off = 0;
+    while (off + 1 < out_len) {
+        assert(read(fd, out + off, 1) == 1);
+        off++;
+        out[off] = '\0';
+        if (off >= 2 && out[off - 1] == '\n' && out[off - 2] == '\n') break;
+    }
+}
+
+/* Opens a stream on a socketpair and returns the client end with the response headers consumed. */
+static int open_event_stream(worker_db_t *db, const char *extra_headers, char *resp, size_t resp_len) {
+    char req[512] = {0};
+    snprintf(req, sizeof(req), "GET /v1/events/stream HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n%s\r\n", extra_headers);
+    int fds[2] = {-1, -1};
+    assert(socketpair(AF_UNIX, SOCK_STREAM, 0, fds) == 0);
+    struct timeval timeout = {.tv_sec = 5, .tv_usec = 0};
+    setsockopt(fds[1], SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+    conn_t conn = {0};
+    conn.cap = REQ_BUF_SIZE;
+    conn.buf = (char *)malloc(conn.cap + 1);
+    assert(conn.buf != NULL);
+    conn.len = strlen(req);
+    memcpy(conn.buf, req, conn.len);
+    assert(try_process_client(fds[0], db, &conn) == 1);
+    close(fds[0]);
+    free(conn.buf);
+    read_sse_event(fds[1], resp, resp_len);
+    return fds[1];
+}
+
+static void test_event_stream_resumes_from_last_event_id(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-sse-XXXXXX");
+    char resp[16384] = {0};
+    char event[1024] = {0};
+    char header[64] = {0};
+
+    run_request(&env.db, "GET /v1/events/stream HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nLast-Event-ID: soon\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250}", resp, sizeof(resp));
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"a1\"}]", resp, sizeof(resp));
+    put_json(&env.db, "someone-else", "workouts", "[]", resp, sizeof(resp));
+    long long profile_seq = 0;
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT seq FROM key_changes WHERE data_key = 'tester::profile'", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW);
+    profile_seq = sqlite3_column_int64(stmt, 0);
+    sqlite3_finalize(stmt);
+
+    /* A fresh stream starts from now; the first thing after the preamble is the next write. */
+    int fd = open_event_stream(&env.db, "", resp, sizeof(resp));
+    assert(strstr(resp, "HTTP/1.1 200 OK\r\n") != NULL && strstr(resp, "Content-Type: text/event-stream\r\n") != NULL);
+    assert(strstr(resp, "retry: 3000\n\n") != NULL);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\"}]", resp, sizeof(resp));
+    read_sse_event(fd, event, sizeof(event));
+    assert(strstr(event, "\nevent: change\ndata: {\"key\":\"workouts\",\"updated_at\":") != NULL);
+    assert(strstr(event, ",\"revision\":\"") != NULL && strstr(event, "\"deleted\":false}\n\n") != NULL);
+    close(fd);
+
+    /* Resuming after the profile event replays what followed it for this account only, in order. */
+    snprintf(header, sizeof(header), "Last-Event-ID: %lld\r\n", profile_seq);
+    fd = open_event_stream(&env.db, header, resp, sizeof(resp));
+    read_sse_event(fd, event, sizeof(event));
+    assert(strncmp(event, "id: ", 4) == 0 && atoll(event + 4) > profile_seq && strstr(event, "\"key\":\"activities\"") != NULL);
+    read_sse_event(fd, event, sizeof(event));
+    assert(strstr(event, "\"key\":\"workouts\"") != NULL);
+    close(fd);
+
+    run_request(&env.db, "GET /v1/events/stream?last_event_id=x HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    test_env_close(&env);
+}
+
+static void test_activities_filtered_and_projected(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-activity-filter-XXXXXX");
+    char resp[16384] = {0};
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2024-12-31\",\"sport\":\"cycling\",\"tss\":50,\"durationSec\":3600,\"notes\":\"x\"},"
+        "{\"id\":\"a2\",\"date\":\"2025-01-05T07:00:00Z\",\"sport\":\"Cycling\",\"tss\":80,\"durationSec\":5400,\"laps\":[1,2],\"indoor\":true},"
+        "{\"id\":\"a3\",\"date\":\"2025-01-20\",\"sport\":\"running\",\"tss\":40,\"durationSec\":2400},"
+        "{\"id\":\"a4\",\"date\":\"2025-02-01\",\"sport\":\"cycling\",\"tss\":90}]",
+        resp,
+        sizeof(resp));
+
+    run_request(
+        &env.db, "GET /v1/data/activities?from=2025-01-01&to=2025-02-01&sport=cycling HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "X-Fricu-Total-Items: 4\r\n") != NULL && strstr(resp, "ETag:") == NULL);
+    assert(strstr(resp, "\"id\":\"a1\"") == NULL && strstr(resp, "\"id\":\"a3\"") == NULL);
+    char *a2 = strstr(resp, "\"id\":\"a2\"");
+    char *a4 = strstr(resp, "\"id\":\"a4\"");
+    assert(a2 != NULL && a4 != NULL && a2 < a4);
+
+    run_request(
+        &env.db, "GET /v1/data/activities?from=2025-01-01&fields=date,tss,laps,indoor HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\r\n\r\n[{\"date\":\"2025-01-05T07:00:00Z\",\"tss\":80,\"laps\":[1,2],\"indoor\":true},{\"date\":\"2025-01-20\",\"tss\":40},{\"date\":\"2025-02-01\",\"tss\":90}]") != NULL);
+
+    run_request(&env.db, "GET /v1/data/activities?sport=swimming HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\r\n\r\n[]") != NULL);
+    run_request(&env.db, "GET /v1/data/activities?fields=date HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: nobody\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\r\n\r\n[]") != NULL);
+
+    run_request(&env.db, "GET /v1/data/activities?from=2025-1-1 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/data/activities?from=2025-02-01&to=2025-01-01 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/data/activities?fields=date,$.tss HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/data/activities?fields=date,,tss HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    /* Without filter parameters the stored document comes back unchanged. */
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "ETag:") != NULL && strstr(resp, "\"notes\":\"x\"") != NULL);
+    test_env_close(&env);
+}
+
+static void test_admin_metrics_histograms_carry_exemplars(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-metrics-XXXXXX");
+    char resp[65536] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    slowlog_configure(-1, -1);
+
+    run_request(
+        &env.db,
+        "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n"
+        "traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\r\n",
+        resp,
+        sizeof(resp));
+    run_request(&env.db, "GET /v1/data/workouts HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Log-Id: ios-42\r\n\r\n", resp, sizeof(resp));
+    run_request(
+        &env.db,
+        "GET /v1/data/plans HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Log-Id: ios-43\r\n"
+        "traceparent: 00-00000000000000000000000000000000-00f067aa0ba902b7-01\r\n\r\n",
+        resp,
+        sizeof(resp));
+
+    run_request(&env.db, "GET /v1/admin/metrics HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401") != NULL || strstr(resp, "403") != NULL);
+    run_request(&env.db, "GET /v1/admin/metrics HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n") != NULL);
+    assert(strstr(resp, "# TYPE fricu_http_request_duration_seconds histogram\n") != NULL);
+    assert(strstr(resp, "fricu_http_request_duration_seconds_bucket{endpoint=\"GET /v1/data/profile\",le=\"+Inf\"} 1\n") != NULL);
+    assert(strstr(resp, "fricu_http_request_duration_seconds_count{endpoint=\"GET /v1/data/profile\"} 1\n") != NULL);
+    assert(strstr(resp, "fricu_http_request_duration_seconds_sum{endpoint=\"GET /v1/data/profile\"} ") != NULL);
+    /* The request sits in exactly one bucket, and only that bucket carries its exemplar. */
+    assert(strstr(resp, "} 1 # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\",span_id=\"00f067aa0ba902b7\"} ") != NULL);
+    assert(strstr(strstr(resp, "span_id=\"00f067aa0ba902b7\"} ") + 1, "span_id=\"00f067aa0ba902b7\"} ") == NULL);
+    assert(strstr(resp, "# {log_id=\"ios-42\"} ") != NULL);
+    /* An all-zero trace id is invalid, so that request falls back to its log id. */
+    assert(strstr(resp, "# {log_id=\"ios-43\"} ") != NULL);
+    assert(strstr(resp, "fricu_slow_requests_total 0\n") != NULL);
+    size_t len = strlen(resp);
+    assert(len > 6 && strcmp(resp + len - 6, "# EOF\n") == 0);
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+/* Splits a raw response into its body and Content-Length; the body may hold NUL bytes. */
+static const char *response_body(const char *resp, size_t *len) {
+    const char *length = strstr(resp, "Content-Length: ");
+    const char *body = strstr(resp, "\r\n\r\n");
+    assert(length != NULL && body != NULL);
+    *len = (size_t)atol(length + 16);
+    return body + 4;
+}
+
+static void test_responses_and_bodies_are_content_coded(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-compression-XXXXXX");
+    char resp[65536] = {0};
+    char doc[8192] = {0};
+    size_t off = 0;
+    doc[off++] = '[';
+    for (int i = 0; i < 60; i++) {
+        off += (size_t)snprintf(doc + off, sizeof(doc) - off, "%s{\"id\":\"a%d\",\"date\":\"2025-01-01\",\"sport\":\"cycling\",\"tss\":%d}", i ? "," : "", i, i);
+    }
+    doc[off++] = ']';
+    put_json(&env.db, "tester", "activities", doc, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* Small bodies and clients that do not ask stay plain, but caches are told the response varies. */
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Vary: Accept-Encoding\r\n") != NULL && strstr(resp, "Content-Encoding") == NULL);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Encoding: gzip, br\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Encoding") == NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Encoding: gzip;q=0, br;q=0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Encoding") == NULL);
+
+    size_t body_len = 0;
+    const char *body = NULL;
+#ifdef FRICU_HAVE_ZLIB
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Encoding: gzip;q=0.8, br;q=0.5\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Encoding: gzip\r\n") != NULL);
+    body = response_body(resp, &body_len);
+    assert(body_len < off);
+    char inflated[8192] = {0};
+    z_stream stream;
+    memset(&stream, 0, sizeof(stream));
+    assert(inflateInit2(&stream, 15 + 16) == Z_OK);
+    stream.next_in = (Bytef *)body;
+    stream.avail_in = (uInt)body_len;
+    stream.next_out = (Bytef *)inflated;
+    stream.avail_out = sizeof(inflated);
+    assert(inflate(&stream, Z_FINISH) == Z_STREAM_END);
+    assert(stream.total_out == off && memcmp(inflated, doc, off) == 0);
+    inflateEnd(&stream);
+
+    /* A gzip-coded PUT body is decoded before the handler sees it. */
+    const char *update = "[{\"id\":\"gz1\",\"date\":\"2025-02-01\",\"sport\":\"running\"}]";
+    unsigned char packed[512] = {0};
+    memset(&stream, 0, sizeof(stream));
+    assert(deflateInit2(&stream, 6, Z_DEFLATED, 15 + 16, 8, Z_DEFAULT_STRATEGY) == Z_OK);
+    stream.next_in = (Bytef *)update;
+    stream.avail_in = (uInt)strlen(update);
+    stream.next_out = packed;
+    stream.avail_out = sizeof(packed);
+    assert(deflate(&stream, Z_FINISH) == Z_STREAM_END);
+    size_t packed_len = stream.total_out;
+    deflateEnd(&stream);
+    char req[2048] = {0};
+    int n = snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Encoding: gzip\r\nContent-Length: %zu\r\n\r\n",
+        packed_len);
+    memcpy(req + n, packed, packed_len);
+    run_request_bytes(&env.db, req, (size_t)n + packed_len, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\r\n\r\n[{\"id\":\"gz1\"") != NULL);
+
+    /* Truncated input is a client error, not an empty body. */
+    n = snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Encoding: gzip\r\nContent-Length: %zu\r\n\r\n",
+        packed_len / 2);
+    memcpy(req + n, packed, packed_len / 2);
+    run_request_bytes(&env.db, req, (size_t)n + packed_len / 2, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "does not match its Content-Encoding") != NULL);
+    put_json(&env.db, "tester", "activities", doc, resp, sizeof(resp));
+#endif
+#ifdef FRICU_HAVE_BROTLI
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nAccept-Encoding: gzip, deflate, br\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Encoding: br\r\n") != NULL);
+    body = response_body(resp, &body_len);
+    uint8_t decoded[8192] = {0};
+    size_t decoded_len = sizeof(decoded);
+    assert(BrotliDecoderDecompress(body_len, (const uint8_t *)body, &decoded_len, decoded) == BROTLI_DECODER_RESULT_SUCCESS);
+    assert(decoded_len == off && memcmp(decoded, doc, off) == 0);
+#endif
+    (void)body;
+    (void)body_len;
+
+    run_request(
+        &env.db,
+        "PUT /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Encoding: zstd\r\nContent-Length: 2\r\n\r\n{}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "415 Unsupported Media Type") != NULL);
+    test_env_close(&env);
+}
+
+static volatile int g_profile_spin;
+
+static void *spin_for_profile(void *arg) {
+    (void)arg;
+    volatile unsigned long long acc = 0;
+    while (__atomic_load_n(&g_profile_spin, __ATOMIC_RELAXED)) acc += acc * 31 + 7;
+    return NULL;
+}
+
+static void test_admin_cpu_profile_formats(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-pprof-XXXXXX");
+    static char resp[1 << 20];
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=1 HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=61 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=1&format=json HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    g_profile_spin = 1;
+    pthread_t spinner;
+    assert(pthread_create(&spinner, NULL, spin_for_profile, NULL) == 0);
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=1&format=folded HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Content-Type: text/plain; charset=utf-8\r\n") != NULL);
+    const char *samples = strstr(resp, "X-Fricu-Profile-Samples: ");
+    assert(samples != NULL && atoll(samples + 25) > 10);
+    /* Every folded line is root-first frames and a count; static functions are named from the symbol table. */
+    const char *body = strstr(resp, "\r\n\r\n") + 4;
+    assert(strchr(body, ';') != NULL && strstr(body, ";spin_for_profile ") != NULL);
+
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=1 HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Type: image/svg+xml\r\n") != NULL);
+    assert(strstr(resp, "\r\n\r\n<?xml version=\"1.0\" standalone=\"no\"?>\n<svg ") != NULL && strstr(resp, "<title>all (") != NULL);
+    assert(strstr(resp, "</svg>\n") != NULL);
+
+    size_t body_len = 0;
+    run_request(&env.db, "GET /v1/admin/pprof/cpu?seconds=1&format=pprof HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "Content-Disposition: attachment; filename=\"cpu.pb\"\r\n") != NULL);
+    const unsigned char *pb = (const unsigned char *)response_body(resp, &body_len);
+    /* Profile.sample_type {type: "samples", unit: "count"} comes first, as string indexes 1 and 2. */
+    assert(body_len > 32 && pb[0] == 0x0a && pb[1] == 4 && pb[2] == 0x08 && pb[3] == 1 && pb[4] == 0x10 && pb[5] == 2);
+    assert(memmem(pb, body_len, "nanoseconds", 11) != NULL);
+    __atomic_store_n(&g_profile_spin, 0, __ATOMIC_RELAXED);
+    pthread_join(spinner, NULL);
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_rate_limits_per_address_and_token(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-ratelimit-XXXXXX");
+    char resp[16384] = {0};
+    char req[1024] = {0};
+    char token[96] = {0};
+
+    /* Socket peers of a socketpair share one "local" bucket; proxied clients get their own. */
+    setenv("FRICU_RATE_LIMIT_IP", "2/m", 1);
+    for (int i = 0; i < 2; i++) {
+        run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+        assert(strstr(resp, "200 OK") != NULL);
+    }
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "429 Too Many Requests") != NULL && strstr(resp, "Retry-After: 30\r\n") != NULL);
+    assert(strstr(resp, "{\"error\":\"rate limit exceeded\",\"scope\":\"ip\",\"retry_after\":30}") != NULL);
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    /* The forwarded address only counts when the proxy is trusted, and then the last hop is used. */
+    const char *forwarded = "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Forwarded-For: 198.51.100.7, 203.0.113.9\r\n\r\n";
+    run_request(&env.db, forwarded, resp, sizeof(resp));
+    assert(strstr(resp, "429 Too Many Requests") != NULL);
+    setenv("FRICU_RATE_LIMIT_TRUST_PROXY", "1", 1);
+    run_request(&env.db, forwarded, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    run_request(
+        &env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    run_request(&env.db, forwarded, resp, sizeof(resp));
+    assert(strstr(resp, "429 Too Many Requests") != NULL);
+    unsetenv("FRICU_RATE_LIMIT_TRUST_PROXY");
+    unsetenv("FRICU_RATE_LIMIT_IP");
+
+    /* A malformed limit is ignored rather than refusing everything. */
+    setenv("FRICU_RATE_LIMIT_IP", "5 per minute", 1);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    unsetenv("FRICU_RATE_LIMIT_IP");
+
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    run_request(
+        &env.db,
+        "POST /v1/admin/tokens HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 34\r\n\r\n{\"account\":\"tester\",\"name\":\"perf\"}",
+        resp,
+        sizeof(resp));
+    const char *field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1);
+    setenv("FRICU_RATE_LIMIT_TOKEN", "1/h", 1);
+    setenv("FRICU_RATE_LIMIT_TOKEN_BURST", "3", 1);
+    snprintf(req, sizeof(req), "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    for (int i = 0; i < 3; i++) {
+        run_request(&env.db, req, resp, sizeof(resp));
+        assert(strstr(resp, "200 OK") != NULL);
+    }
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "429 Too Many Requests") != NULL && strstr(resp, "\"scope\":\"token\"") != NULL && strstr(resp, "Retry-After: 3600\r\n") != NULL);
+    /* Unauthenticated requests are not charged to any token. */
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    run_request(&env.db, "GET /v1/admin/metrics HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "fricu_rate_limited_requests_total{scope=\"token\"} ") != NULL);
+    unsetenv("FRICU_RATE_LIMIT_TOKEN");
+    unsetenv("FRICU_RATE_LIMIT_TOKEN_BURST");
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_request_bodies_limited_per_key(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-body-limit-XXXXXX");
+    char resp[16384] = {0};
+    char req[4096] = {0};
+    const char *profile = "{\"name\":\"Kim\",\"ftp\":250,\"weightKg\":61.5}";
+
+    setenv("FRICU_MAX_BODY_BYTES_PROFILE", "32", 1);
+    put_json(&env.db, "tester", "profile", profile, resp, sizeof(resp));
+    assert(strstr(resp, "413 Payload Too Large") != NULL);
+    assert(
+        strstr(resp, "{\"error\":\"request body too large\",\"key\":\"profile\",\"bytes\":40,\"max_bytes\":32,\"setting\":\"FRICU_MAX_BODY_BYTES_PROFILE\"}") !=
+        NULL);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\",\"name\":\"Endurance ride\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    /* The refusal comes from the headers alone; the body is never waited for. */
+    run_request(
+        &env.db, "PUT /v2/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 5000\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"key\":\"profile\",\"bytes\":5000,\"max_bytes\":32,") != NULL);
+    setenv("FRICU_MAX_BODY_BYTES_PROFILE", "64", 1);
+    put_json(&env.db, "tester", "profile", profile, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    /* The default covers every other route; per-key limits may also raise it above the buffer. */
+    setenv("FRICU_MAX_BODY_BYTES", "16", 1);
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/journal HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: 24\r\n\r\n{\"text\":\"long session\"}");
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "{\"error\":\"request body too large\",\"key\":null,\"bytes\":24,\"max_bytes\":16,\"setting\":\"FRICU_MAX_BODY_BYTES\"}") != NULL);
+    put_json(&env.db, "tester", "workouts", "[{\"id\":\"w1\",\"name\":\"Endurance ride\"}]", resp, sizeof(resp));
+    assert(strstr(resp, "413 Payload Too Large") != NULL && strstr(resp, "\"key\":\"workouts\",\"bytes\":37,\"max_bytes\":16,") != NULL);
+    unsetenv("FRICU_MAX_BODY_BYTES");
+    assert(request_buffer_max() == REQ_BUF_SIZE);
+    setenv("FRICU_MAX_BODY_BYTES_ACTIVITIES", "33554432", 1);
+    assert(request_body_limit("/v1/data/activities/items", NULL, 0) == 33554432 && request_buffer_max() > 33554432);
+    assert(request_body_limit("/v1/data/profile", NULL, 0) == 64);
+    unsetenv("FRICU_MAX_BODY_BYTES_ACTIVITIES");
+
+#ifdef FRICU_HAVE_ZLIB
+    /* A compressed body is held to the limit once decoded. */
+    char plain[200];
+    memset(plain, ' ', sizeof(plain));
+    plain[0] = '{';
+    plain[sizeof(plain) - 1] = '}';
+    unsigned char packed[256];
+    uLongf packed_len = sizeof(packed);
+    assert(compress2(packed, &packed_len, (const unsigned char *)plain, sizeof(plain), 9) == Z_OK && packed_len < 64);
+    int head = snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Encoding: deflate\r\nContent-Length: %lu\r\n\r\n",
+        (unsigned long)packed_len);
+    memcpy(req + head, packed, packed_len);
+    run_request_bytes(&env.db, req, (size_t)head + packed_len, resp, sizeof(resp));
+    assert(strstr(resp, "413 Payload Too Large") != NULL && strstr(resp, "\"key\":\"profile\",\"bytes\":-1,\"max_bytes\":64,") != NULL);
+#endif
+    unsetenv("FRICU_MAX_BODY_BYTES_PROFILE");
+    test_env_close(&env);
+}
+
+static long long restored_count(const char *path, const char *sql) {
+    sqlite3 *restored = NULL;
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_open_v2(path, &restored, SQLITE_OPEN_READONLY, NULL) == SQLITE_OK);
+    assert(sqlite3_prepare_v2(restored, sql, -1, &stmt, NULL) == SQLITE_OK && sqlite3_step(stmt) == SQLITE_ROW);
+    long long count = sqlite3_column_int64(stmt, 0);
+    sqlite3_finalize(stmt);
+    sqlite3_close(restored);
+    return count;
+}
+
+static void test_backups_chain_and_restore(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-backup-XXXXXX");
+    char resp[65536] = {0};
+    char err[256] = {0};
+    const char *auth = "HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n";
+    char req[512] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    /* A few hundred pages that never change again, so the increments below stay small. */
+    assert(sqlite3_exec(
+               env.db.db,
+               "CREATE TABLE filler (blob BLOB); WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 800)"
+               " INSERT INTO filler SELECT randomblob(1000) FROM n",
+               NULL,
+               NULL,
+               NULL) == SQLITE_OK);
+
+    run_request(&env.db, "POST /v1/admin/backups HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    snprintf(req, sizeof(req), "POST /v1/admin/backups?kind=incremental %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL && strstr(resp, "take a full backup first") != NULL);
+    snprintf(req, sizeof(req), "POST /v1/admin/backups?kind=weekly %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    put_json(&env.db, "tester", "profile", "{\"ftp\":240,\"note\":\"first\"}", resp, sizeof(resp));
+    snprintf(req, sizeof(req), "POST /v1/admin/backups %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "{\"id\":\"000001\",\"kind\":\"full\",\"parent\":null,") != NULL);
+    assert(access("state.db-backups/000001.db", F_OK) == 0 && access("state.db-backups/000001.pages", F_OK) == 0);
+
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250,\"note\":\"second\"}", resp, sizeof(resp));
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "{\"id\":\"000002\",\"kind\":\"incremental\",\"parent\":\"000001\",") != NULL);
+    const char *written = strstr(resp, "\"pages_written\":");
+    const char *total = strstr(resp, "\"page_count\":");
+    assert(written && total && atoll(written + 16) >= 1 && atoll(written + 16) * 10 < atoll(total + 13));
+
+    put_json(&env.db, "tester", "profile", "{\"ftp\":255,\"note\":\"third\"}", resp, sizeof(resp));
+    snprintf(req, sizeof(req), "POST /v1/admin/backups?kind=differential %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "{\"id\":\"000003\",\"kind\":\"differential\",\"parent\":\"000001\",") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":260,\"note\":\"fourth\"}", resp, sizeof(resp));
+    snprintf(req, sizeof(req), "POST /v1/admin/backups?kind=incremental %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "{\"id\":\"000004\",\"kind\":\"incremental\",\"parent\":\"000003\",") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/admin/backups %s", auth);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"backups\":[{\"id\":\"000001\"") != NULL && strstr(resp, ",{\"id\":\"000004\"") != NULL);
+
+    /* Each restore is the database as it was when that backup was taken. */
+    static const char *notes[] = {"first", "second", "third", "fourth"};
+    for (int i = 0; i < 4; i++) {
+        char id[16] = {0};
+        char sql[256] = {0};
+        snprintf(id, sizeof(id), "00000%d", i + 1);
+        assert(backup_restore("state.db-backups", id, "restored.db", err, sizeof(err)) == 0);
+        snprintf(sql, sizeof(sql), "SELECT count(*) FROM kv_store WHERE data_key LIKE '%%profile' AND json_extract(data_value, '$.note') = '%s'", notes[i]);
+        assert(restored_count("restored.db", sql) == 1);
+        assert(restored_count("restored.db", "SELECT count(*) FROM filler") == 800);
+        unlink("restored.db");
+    }
+
+    /* A damaged increment or manifest stops the restore before anything is written under the name. */
+    FILE *diff = fopen("state.db-backups/000002.diff", "r+b");
+    assert(diff != NULL && fseek(diff, 100, SEEK_SET) == 0);
+    int byte = fgetc(diff);
+    assert(fseek(diff, 100, SEEK_SET) == 0 && fputc(byte ^ 0x40, diff) != EOF);
+    fclose(diff);
+    assert(backup_restore("state.db-backups", "000002", "restored.db", err, sizeof(err)) != 0);
+    assert(strcmp(err, "backup 000002: payload checksum mismatch") == 0 && access("restored.db", F_OK) != 0);
+    assert(backup_restore("state.db-backups", "000004", "restored.db", err, sizeof(err)) == 0);
+    unlink("restored.db");
+    assert(backup_restore("state.db-backups", "000009", "restored.db", err, sizeof(err)) != 0);
+    assert(strstr(err, "000009 not found") != NULL);
+    FILE *manifest = fopen("state.db-backups/000003.json", "r+b");
+    assert(manifest != NULL && fseek(manifest, -3, SEEK_END) == 0 && fputc('x', manifest) != EOF);
+    fclose(manifest);
+    assert(backup_restore("state.db-backups", "000004", "restored.db", err, sizeof(err)) != 0 && access("restored.db", F_OK) != 0);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_snapshot_backup_and_pruning(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-snapshot-XXXXXX");
+    char resp[16384] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":240}", resp, sizeof(resp));
+
+    run_request(&env.db, "POST /v1/admin/backup HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(&env.db, "GET /v1/admin/backup HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+    run_request(&env.db, "POST /v1/admin/backup HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"checksum\":\"") != NULL);
+    const char *name = strstr(resp, "{\"name\":\"");
+    assert(name != NULL);
+    name += 9;
+    char file[128] = {0};
+    snprintf(file, sizeof(file), "state.db-backups/%.*s", (int)(strchr(name, '"') - name), name);
+    assert(strncmp(file, "state.db-backups/snapshot-", 26) == 0 && strcmp(file + strlen(file) - 4, "Z.db") == 0);
+    assert(strstr(resp, "\"path\":\"/") != NULL && strstr(resp, "/state.db-backups/snapshot-") != NULL);
+    /* The snapshot opens as a database on its own, without the WAL next to it. */
+    assert(restored_count(file, "SELECT count(*) FROM kv_store WHERE data_value LIKE '%\"ftp\":240%'") == 1);
+    char wal[160] = {0};
+    snprintf(wal, sizeof(wal), "%s-wal", file);
+    assert(access(wal, F_OK) != 0);
+    /* Page backups only list their manifests, so snapshots do not show up there. */
+    run_request(&env.db, "GET /v1/admin/backups HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "{\"backups\":[]}") != NULL);
+
+    const char *older[] = {"snapshot-20200101T000000.000Z.db", "snapshot-20200102T000000.000Z.db", "snapshot-20200103T000000.000Z.db"};
+    for (size_t i = 0; i < sizeof(older) / sizeof(older[0]); i++) {
+        char path[128] = {0};
+        snprintf(path, sizeof(path), "state.db-backups/%s", older[i]);
+        FILE *f = fopen(path, "wb");
+        assert(f != NULL);
+        fclose(f);
+    }
+    FILE *other = fopen("state.db-backups/notes.db", "wb");
+    assert(other != NULL);
+    fclose(other);
+    assert(backup_prune_snapshots("state.db-backups", 2) == 2);
+    assert(access("state.db-backups/snapshot-20200101T000000.000Z.db", F_OK) != 0);
+    assert(access("state.db-backups/snapshot-20200102T000000.000Z.db", F_OK) != 0);
+    assert(access("state.db-backups/snapshot-20200103T000000.000Z.db", F_OK) == 0);
+    assert(access(file, F_OK) == 0 && access("state.db-backups/notes.db", F_OK) == 0);
+    assert(backup_prune_snapshots("state.db-backups", 2) == 0);
+    assert(backup_prune_snapshots("missing-dir", 2) == 0);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void post_restore(worker_db_t *db, const char *content_type, const char *body, size_t body_len, char *resp, size_t resp_len) {
+    size_t cap = body_len + 512;
+    char *req = (char *)malloc(cap);
+    assert(req != NULL);
+    int n = snprintf(
+        req,
+        cap,
+        "POST /v1/admin/restore HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Type: %s\r\nContent-Length: %zu\r\n\r\n",
+        content_type,
+        body_len);
+    assert(n > 0 && (size_t)n + body_len < cap);
+    memcpy(req + n, body, body_len);
+    run_request_bytes(db, req, (size_t)n + body_len, resp, resp_len);
+    free(req);
+}
+
+static void assert_profile_ftp(worker_db_t *db, const char *expected) {
+    char resp[16384] = {0};
+    run_request(db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, expected) != NULL);
+}
+
+static void test_restore_swaps_database(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-restore-XXXXXX");
+    char resp[16384] = {0};
+    char body[256] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":240}", resp, sizeof(resp));
+    run_request(&env.db, "POST /v1/admin/backup HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    const char *name = strstr(resp, "{\"name\":\"");
+    assert(name != NULL);
+    name += 9;
+    char first[64] = {0};
+    snprintf(first, sizeof(first), "%.*s", (int)(strchr(name, '"') - name), name);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":300}", resp, sizeof(resp));
+    assert_profile_ftp(&env.db, "\"ftp\":300");
+
+    long long generation = db_pool_generation();
+    snprintf(body, sizeof(body), "{\"snapshot\":\"%s\"}", first);
+    post_restore(&env.db, "application/json", body, strlen(body), resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"restored\":true,\"source\":\"snapshot\",\"previous\":\"snapshot-") != NULL);
+    assert(db_pool_generation() == generation + 1);
+    /* The worker reconnects before its next request, and the write thread before its next write. */
+    assert_profile_ftp(&env.db, "\"ftp\":240");
+    assert(env.db.generation == generation + 1);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":250}", resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    assert_profile_ftp(&env.db, "\"ftp\":250");
+
+    /* The content before the restore was saved, and uploading it puts it back. */
+    char path[512] = {0};
+    DIR *dir = opendir("state.db-backups");
+    assert(dir != NULL);
+    struct dirent *entry = NULL;
+    while ((entry = readdir(dir)) != NULL) {
+        if (strncmp(entry->d_name, "snapshot-", 9) == 0 && strcmp(entry->d_name, first) != 0) snprintf(path, sizeof(path), "state.db-backups/%s", entry->d_name);
+    }
+    closedir(dir);
+    assert(path[0] != '\0');
+    FILE *f = fopen(path, "rb");
+    assert(f != NULL);
+    static char image[4 * 1024 * 1024];
+    size_t image_len = fread(image, 1, sizeof(image), f);
+    fclose(f);
+    assert(image_len > 0 && image_len < sizeof(image));
+    post_restore(&env.db, "application/vnd.sqlite3", image, image_len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"upload\"") != NULL);
+    assert_profile_ftp(&env.db, "\"ftp\":300");
+    assert(access("state.db.restore.tmp", F_OK) != 0);
+
+    run_request(&env.db, "POST /v1/admin/backups?kind=full HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    put_json(&env.db, "tester", "profile", "{\"ftp\":999}", resp, sizeof(resp));
+    post_restore(&env.db, "application/json", "{\"backup\":\"000001\"}", 19, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"backup\"") != NULL);
+    assert_profile_ftp(&env.db, "\"ftp\":300");
+
+    /* Nothing is swapped in unless it is a sound fricu database. */
+    post_restore(&env.db, "application/octet-stream", "not a database", 14, resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL && strstr(resp, "not a SQLite database") != NULL);
+    memset(image + 16, 0x5A, 4096);
+    post_restore(&env.db, "application/octet-stream", image, image_len, resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL);
+    sqlite3 *other = NULL;
+    assert(sqlite3_open("other.db", &other) == SQLITE_OK && sqlite3_exec(other, "CREATE TABLE t (x)", NULL, NULL, NULL) == SQLITE_OK);
+    sqlite3_close(other);
+    post_restore(&env.db, "application/json", "{\"path\":\"other.db\"}", 19, resp, sizeof(resp));
+    assert(strstr(resp, "422 Unprocessable Entity") != NULL && strstr(resp, "no kv_store table") != NULL);
+    post_restore(&env.db, "application/json", "{\"path\":\"state.db\"}", 19, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "live database") != NULL);
+    post_restore(&env.db, "application/json", "{\"snapshot\":\"../state.db\"}", 26, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    post_restore(&env.db, "application/json", "{}", 2, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    assert(db_pool_generation() == generation + 3);
+    assert_profile_ftp(&env.db, "\"ftp\":300");
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_cors_for_browser_clients(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-cors-XXXXXX");
+    char resp[16384] = {0};
+    const char *preflight =
+        "OPTIONS /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nOrigin: https://dash.example.com\r\n"
+        "Access-Control-Request-Method: PUT\r\nAccess-Control-Request-Headers: content-type, x-account-id\r\n\r\n";
+    const char *read = "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nOrigin: http://localhost:5173\r\n\r\n";
+
+    /* Off by default: no CORS headers, and OPTIONS is not special. */
+    run_request(&env.db, read, resp, sizeof(resp));
+    assert(strstr(resp, "Access-Control-") == NULL);
+    run_request(&env.db, preflight, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") == NULL && strstr(resp, "Access-Control-") == NULL);
+
+    setenv("FRICU_CORS_ORIGINS", "https://dash.example.com, http://localhost:5173/", 1);
+    setenv("FRICU_API_AUTH", "required", 1);
+    run_request(&env.db, preflight, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL && strstr(resp, "Access-Control-Allow-Origin: https://dash.example.com\r\nVary: Origin\r\n") != NULL);
+    assert(strstr(resp, "Access-Control-Allow-Methods: GET, HEAD, POST, PUT, PATCH, DELETE\r\n") != NULL);
+    assert(strstr(resp, "Access-Control-Allow-Headers: Authorization, Content-Type, ") != NULL && strstr(resp, "X-Account-Id, ") != NULL);
+    assert(strstr(resp, "Access-Control-Max-Age: 600\r\n") != NULL && strstr(resp, "Access-Control-Allow-Credentials") == NULL);
+    /* The request itself still needs a token, and the refusal is readable by the page. */
+    run_request(&env.db, read, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "Access-Control-Allow-Origin: http://localhost:5173\r\n") != NULL);
+    unsetenv("FRICU_API_AUTH");
+    run_request(&env.db, read, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Access-Control-Allow-Origin: http://localhost:5173\r\n") != NULL);
+    assert(strstr(resp, "Access-Control-Expose-Headers: X-Log-Id, X-Fricu-Version, ETag, ") != NULL);
+
+    run_request(
+        &env.db,
+        "OPTIONS /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.example\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL && strstr(resp, "origin not allowed") != NULL && strstr(resp, "Access-Control-") == NULL);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nOrigin: https://evil.example\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Access-Control-") == NULL);
+    run_request(
+        &env.db,
+        "OPTIONS /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nOrigin: https://dash.example.com\r\nAccess-Control-Request-Method: TRACE\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL && strstr(resp, "method not allowed for cross-origin requests") != NULL);
+    run_request(
+        &env.db,
+        "OPTIONS /v1/admin/stats HTTP/1.1\r\nHost: localhost\r\nOrigin: https://dash.example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "Access-Control-") == NULL);
+
+    setenv("FRICU_CORS_ORIGINS", "*", 1);
+    run_request(&env.db, read, resp, sizeof(resp));
+    assert(strstr(resp, "Access-Control-Allow-Origin: *\r\n") != NULL && strstr(resp, "Vary: Origin") == NULL);
+    setenv("FRICU_CORS_CREDENTIALS", "1", 1);
+    setenv("FRICU_CORS_METHODS", "GET, PUT", 1);
+    run_request(&env.db, preflight, resp, sizeof(resp));
+    assert(strstr(resp, "Access-Control-Allow-Origin: https://dash.example.com\r\n") != NULL);
+    assert(strstr(resp, "Access-Control-Allow-Credentials: true\r\n") != NULL && strstr(resp, "Access-Control-Allow-Methods: GET, PUT\r\n") != NULL);
+
+    unsetenv("FRICU_CORS_ORIGINS");
+    unsetenv("FRICU_CORS_CREDENTIALS");
+    unsetenv("FRICU_CORS_METHODS");
+    test_env_close(&env);
+}
+
+static int embedded_connect(int port) {
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    assert(fd >= 0);
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons((uint16_t)port);
+    inet_pton(AF_INET, "127.0.0.1", &addr.sin_addr);
+    if (connect(fd, (struct sockaddr *)&addr, sizeof(addr)) != 0) {
+        close(fd);
+        return -1;
+    }
+    return fd;
+}
+
+/* Sends one request and reads one response, headers plus Content-Length bytes of body. */
+static void embedded_request(int port, const char *req, char *resp, size_t resp_len) {
+    int fd = embedded_connect(port);
+    assert(fd >= 0);
+    must_write_all(fd, req, strlen(req));
+    size_t off = 0;
+    ssize_t n = 0;
+    resp[0] = '\0';
+    while (off + 1 < resp_len && (n = read(fd, resp + off, resp_len - off - 1)) > 0) {
+        off += (size_t)n;
+        resp[off] = '\0';
+        const char *body = strstr(resp, "\r\n\r\n");
+        const char *length = strstr(resp, "Content-Length: ");
+        if (body && length && off >= (size_t)(body + 4 - resp) + (size_t)atol(length + 16)) break;
+    }
+    close(fd);
+}
+
+static void test_embedded_server_start_stop(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-embedded-XXXXXX");
+    setenv("FRICU_SNAPSHOTS", "0", 1);
+    setenv("FRICU_CONNECTORS", "0", 1);
+    setenv("FRICU_BOTS", "0", 1);
+    char resp[16384] = {0};
+
+    fricu_embedded_config_t config = {.db_path = "state.db"};
+    fricu_server_t *server = fricu_run_embedded(&config);
+    assert(server != NULL);
+    int port = fricu_server_port(server);
+    char expected[64] = {0};
+    snprintf(expected, sizeof(expected), "http://127.0.0.1:%d", port);
+    assert(port > 0 && strcmp(fricu_server_base_url(server), expected) == 0);
+
+    embedded_request(port, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    embedded_request(
+        port,
+        "PUT /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: desk\r\nContent-Type: application/json\r\n"
+        "Content-Length: 18\r\n\r\n{\"notes\":\"in-app\"}",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "HTTP/1.1 2") != NULL);
+    embedded_request(port, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: desk\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "in-app") != NULL);
+
+    /* An idle keep-alive connection does not hold up the stop; it is closed. */
+    int idle = embedded_connect(port);
+    assert(idle >= 0);
+    fricu_server_stop(server);
+    assert(read(idle, resp, sizeof(resp)) <= 0);
+    close(idle);
+    assert(embedded_connect(port) < 0);
+
+    /* A second server in the same process, on a port of the caller's choosing, sees the same data. */
+    config.port = port;
+    config.workers = 1;
+    server = fricu_run_embedded(&config);
+    assert(server != NULL && fricu_server_port(server) == port);
+    embedded_request(port, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: desk\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "in-app") != NULL);
+    fricu_server_stop(server);
+
+    config.db_path = NULL;
+    assert(fricu_run_embedded(&config) == NULL);
+    unsetenv("FRICU_SNAPSHOTS");
+    unsetenv("FRICU_CONNECTORS");
+    unsetenv("FRICU_BOTS");
+    test_env_close(&env);
+}
+
+static void *stop_embedded_server(void *arg) {
+    fricu_server_stop((fricu_server_t *)arg);
+    return NULL;
+}
+
+/* Reads until the server closes the connection, which it does after every response. */
+static void read_until_closed(int fd, char *resp, size_t resp_len) {
+    size_t off = 0;
+    ssize_t n = 0;
+    resp[0] = '\0';
+    while (off + 1 < resp_len && (n = read(fd, resp + off, resp_len - off - 1)) > 0) {
+        off += (size_t)n;
+        resp[off] = '\0';
+    }
+}
+
+static void test_graceful_shutdown_drains_requests(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-drain-XXXXXX");
+    setenv("FRICU_SNAPSHOTS", "0", 1);
+    setenv("FRICU_CONNECTORS", "0", 1);
+    setenv("FRICU_BOTS", "0", 1);
+    char resp[16384] = {0};
+
+    fricu_embedded_config_t config = {.db_path = "state.db", .workers = 1};
+    fricu_server_t *server = fricu_run_embedded(&config);
+    assert(server != NULL);
+    int port = fricu_server_port(server);
+    fricu_server_t *neighbour = fricu_run_embedded(&config);
+    assert(neighbour != NULL);
+
+    /* A PUT whose body is still arriving, a readiness probe half sent, and an idle connection. */
+    int put = embedded_connect(port);
+    int probe = embedded_connect(port);
+    int idle = embedded_connect(port);
+    assert(put >= 0 && probe >= 0 && idle >= 0);
+    const char *put_head =
+        "PUT /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: drain\r\nContent-Type: application/json\r\n"
+        "Content-Length: 20\r\n\r\n{\"notes\":";
+    must_write_all(put, put_head, strlen(put_head));
+    const char *probe_head = "GET /health/ready HTTP/1.1\r\nHost: localhost\r\n";
+    must_write_all(probe, probe_head, strlen(probe_head));
+    usleep(200000);
+    assert(!engine_server_draining(server));
+
+    pthread_t stopper;
+    assert(pthread_create(&stopper, NULL, stop_embedded_server, server) == 0);
+    usleep(200000);
+    assert(engine_server_draining(server));
+    assert(read(idle, resp, sizeof(resp)) <= 0);
+    close(idle);
+
+    /* Another server in the same process keeps reporting ready while this one drains. */
+    assert(!engine_server_draining(neighbour));
+    int neighbour_probe = embedded_connect(fricu_server_port(neighbour));
+    assert(neighbour_probe >= 0);
+    const char *neighbour_head = "GET /health/ready HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    must_write_all(neighbour_probe, neighbour_head, strlen(neighbour_head));
+    read_until_closed(neighbour_probe, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"shutdown\":\"ok\"") != NULL);
+    close(neighbour_probe);
+
+    must_write_all(probe, "\r\n", 2);
+    read_until_closed(probe, resp, sizeof(resp));
+    assert(strstr(resp, "503 Service Unavailable") != NULL && strstr(resp, "\"shutdown\":\"draining\"") != NULL);
+    close(probe);
+    must_write_all(put, "\"mid-stop\"}", 11);
+    read_until_closed(put, resp, sizeof(resp));
+    assert(strstr(resp, "HTTP/1.1 2") != NULL);
+    close(put);
+    pthread_join(stopper, NULL);
+    assert(embedded_connect(port) < 0);
+    fricu_server_stop(neighbour);
+
+    /* The write was committed and the WAL folded into the database file. */
+    struct stat wal;
+    assert(stat("state.db-wal", &wal) != 0 || wal.st_size == 0);
+    run_request(&env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: drain\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "mid-stop") != NULL);
+
+    /* A request that never completes is dropped once FRICU_SHUTDOWN_TIMEOUT_SEC runs out. */
+    setenv("FRICU_SHUTDOWN_TIMEOUT_SEC", "1", 1);
+    server = fricu_run_embedded(&config);
+    assert(server != NULL);
+    port = fricu_server_port(server);
+    int stuck = embedded_connect(port);
+    assert(stuck >= 0);
+    must_write_all(stuck, "PUT /v1/data/profile HTTP/1.1\r\n", 31);
+    usleep(200000);
+    struct timespec started;
+    struct timespec stopped;
+    clock_gettime(CLOCK_MONOTONIC, &started);
+    fricu_server_stop(server);
+    clock_gettime(CLOCK_MONOTONIC, &stopped);
+    double waited = (double)(stopped.tv_sec - started.tv_sec) + (double)(stopped.tv_nsec - started.tv_nsec) / 1e9;
+    assert(waited >= 0.9 && waited < 5);
+    assert(read(stuck, resp, sizeof(resp)) <= 0);
+    close(stuck);
+
+    unsetenv("FRICU_SHUTDOWN_TIMEOUT_SEC");
+    unsetenv("FRICU_SNAPSHOTS");
+    unsetenv("FRICU_CONNECTORS");
+    unsetenv("FRICU_BOTS");
+    test_env_close(&env);
+}
+
+static void test_service_units_rendered(void) {
+    setenv("FRICU_SERVER_BIND", "127.0.0.1:9090", 1);
+    setenv("FRICU_SERVICE_TEST_NOTE", "say \"hi\" at 100% & <go>", 1);
+    setenv("FRICU_ADMIN_TOKEN", "service-secret-token", 1);
+    char cwd[512] = {0};
+    assert(getcwd(cwd, sizeof(cwd)) != NULL);
+    strbuf_t unit;
+    strbuf_init(&unit);
+    assert(service_render("systemd", "./unit-tests", &unit) == 0);
+    const char *text = strbuf_cstr(&unit);
+    assert(strncmp(text, "[Unit]\nDescription=Fricu training data server\n", 46) == 0);
+    assert(strstr(text, "\nExecStart=\"/") != NULL && strstr(text, "unit-tests\"\n") != NULL);
+    assert(strstr(text, "\nWorkingDirectory=\"") != NULL && strstr(text, cwd) != NULL);
+    assert(strstr(text, "\nEnvironment=\"FRICU_SERVER_BIND=127.0.0.1:9090\"\n") != NULL);
+    assert(strstr(text, "\nEnvironment=\"FRICU_SERVICE_TEST_NOTE=say \\\"hi\\\" at 100%% & <go>\"\n") != NULL);
+    assert(strstr(text, "FRICU_ADMIN_TOKEN") == NULL && strstr(text, "service-secret-token") == NULL);
+    assert(strstr(text, "\nRestart=on-failure\n") != NULL && strstr(text, "\nLimitNOFILE=65535\n") != NULL);
+    assert(strstr(text, "\n[Install]\nWantedBy=") != NULL);
+    strbuf_free(&unit);
+
+    strbuf_init(&unit);
+    assert(service_render("launchd", "./unit-tests", &unit) == 0);
+    text = strbuf_cstr(&unit);
+    assert(strstr(text, "<key>Label</key>\n    <string>com.fricu.server</string>\n") != NULL);
+    assert(strstr(text, "<key>FRICU_SERVICE_TEST_NOTE</key>\n        <string>say \"hi\" at 100% &amp; &lt;go&gt;</string>\n") != NULL);
+    assert(strstr(text, "service-secret-token") == NULL && strstr(text, "<key>KeepAlive</key>") != NULL);
+    assert(strstr(text, "<key>StandardErrorPath</key>\n    <string>") != NULL && strstr(text, "Logs/fricu-server.log</string>") != NULL);
+    strbuf_free(&unit);
+
+    strbuf_init(&unit);
+    assert(service_render("windows", "./unit-tests", &unit) != 0);
+    strbuf_free(&unit);
+    unsetenv("FRICU_ADMIN_TOKEN");
+    unsetenv("FRICU_SERVICE_TEST_NOTE");
+    unsetenv("FRICU_SERVER_BIND");
+}
+
+/* Runs one request with stderr sent to log, which receives what the request logged. */
+static void run_request_logged(worker_db_t *db, const char *req, char *resp, size_t resp_len, char *log, size_t log_len) {
+    fflush(stderr);
+    int saved = dup(2);
+    FILE *capture = tmpfile();
+    assert(saved >= 0 && capture != NULL);
+    dup2(fileno(capture), 2);
+    run_request(db, req, resp, resp_len);
+    fflush(stderr);
+    dup2(saved, 2);
+    close(saved);
+    rewind(capture);
+    size_t n = fread(log, 1, log_len - 1, capture);
+    log[n] = '\0';
+    fclose(capture);
+}
+
+static void test_access_log_with_request_ids(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-accesslog-XXXXXX");
+    char resp[16384] = {0};
+    char log[8192] = {0};
+    put_json(&env.db, "tester", "profile", "{\"notes\":\"logged\"}", resp, sizeof(resp));
+
+    /* The caller's id is echoed under both names and is the logid of the request's other lines. */
+    run_request_logged(
+        &env.db, "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nX-Request-Id: sync-42\r\n\r\n", resp, sizeof(resp), log, sizeof(log));
+    assert(strstr(resp, "\r\nX-Log-Id: sync-42\r\nX-Request-Id: sync-42\r\n") != NULL);
+    assert(strstr(log, "logid=sync-42") != NULL);
+    assert(strstr(log, "[INFO] ACCESS GET /v1/data/profile -> 200 latency_ms=") != NULL);
+    assert(strstr(log, " bytes_in=0 bytes_out=18 request_id=sync-42 account=tester client=local\n") != NULL);
+
+    /* Without one an id is generated and returned; JSON lines carry the same fields. */
+    setenv("FRICU_LOG_FORMAT", "json", 1);
+    run_request_logged(&env.db, "GET /v1/data/nope HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp), log, sizeof(log));
+    const char *id = strstr(resp, "\r\nX-Request-Id: srv-");
+    assert(id != NULL);
+    char request_id[96] = {0};
+    sscanf(id + 16, "%95[^\r]", request_id);
+    char expected[256] = {0};
+    snprintf(expected, sizeof(expected), ",\"event\":\"http_request\",\"request_id\":\"%s\",\"method\":\"GET\",\"path\":\"/v1/data/nope\",", request_id);
+    const char *line = strstr(log, expected);
+    assert(line != NULL && strstr(log, "{\"ts\":\"") == log && strstr(log, "\",\"level\":\"warn\",\"msg\":\"ACCESS GET /v1/data/nope -> 4") != NULL);
+    assert(strstr(line, ",\"route\":\"/v1/data/nope\",\"status\":4") != NULL && strstr(line, ",\"bytes_in\":0,\"bytes_out\":") != NULL);
+    assert(strstr(line, ",\"retry\":0,\"account\":\"tester\",\"client\":\"local\"}\n") != NULL);
+    unsetenv("FRICU_LOG_FORMAT");
+
+    setenv("FRICU_ACCESS_LOG", "0", 1);
+    run_request_logged(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp), log, sizeof(log));
+    assert(strstr(resp, "200 OK") != NULL && strstr(log, "ACCESS") == NULL);
+    unsetenv("FRICU_ACCESS_LOG");
+    test_env_close(&env);
+}
+
+static size_t mdns_query(unsigned char *out, unsigned id, const char *const *labels, size_t label_count, unsigned type) {
+    unsigned char header[12] = {(unsigned char)(id >> 8), (unsigned char)id, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0};
+    memcpy(out, header, sizeof(header));
+    size_t len = sizeof(header);
+    for (size_t i = 0; i < label_count; i++) {
+        size_t n = strlen(labels[i]);
+        out[len++] = (unsigned char)n;
+        memcpy(out + len, labels[i], n);
+        len += n;
+    }
+    out[len++] = 0;
+    out[len++] = (unsigned char)(type >> 8);
+    out[len++] = (unsigned char)type;
+    out[len++] = 0;
+    out[len++] = 1;
+    return len;
+}
+
+static int bytes_contain(const unsigned char *data, size_t len, const void *needle, size_t needle_len) {
+    for (size_t i = 0; i + needle_len <= len; i++) {
+        if (memcmp(data + i, needle, needle_len) == 0) return 1;
+    }
+    return 0;
+}
+
+static void test_mdns_answers_service_queries(void) {
+    mdns_service_t svc;
+    memset(&svc, 0, sizeof(svc));
+    snprintf(svc.instance, sizeof(svc.instance), "Fricu on Garage");
+    snprintf(svc.host, sizeof(svc.host), "garage");
+    svc.port = 8443;
+    svc.tls = 1;
+    inet_pton(AF_INET, "192.168.1.20", &svc.addrs[0]);
+    svc.addr_count = 1;
+    unsigned char query[512];
+    unsigned char reply[1500];
+    const unsigned char port_bytes[2] = {0x20, 0xFB};
+    const unsigned char addr_bytes[4] = {192, 168, 1, 20};
+
+    /* Browsing the service type gets the PTR answer with SRV, TXT and A as additional records. */
+    const char *const browse[] = {"_fricu", "_TCP", "local"};
+    size_t qlen = mdns_query(query, 0x1234, browse, 3, 12);
+    size_t len = mdns_answer(query, qlen, 0, &svc, reply, sizeof(reply));
+    assert(len > 12);
+    assert(reply[0] == 0 && reply[1] == 0 && reply[2] == 0x84 && reply[3] == 0x00);
+    assert(reply[7] == 1 && reply[11] == 3);
+    assert(bytes_contain(reply, len, "\x0f" "Fricu on Garage" "\x06_fricu\x04_tcp\x05local", 35));
+    assert(bytes_contain(reply, len, port_bytes, 2) && bytes_contain(reply, len, "\x06garage\x05local", 14));
+    assert(bytes_contain(reply, len, "\x09txtvers=1\x06path=/\x05tls=1\x0aprotocol=1", 34));
+    assert(bytes_contain(reply, len, addr_bytes, 4));
+
+    /* A one-shot querier gets its id back; a resolve of the instance answers SRV and TXT directly. */
+    const char *const resolve[] = {"Fricu on garage", "_fricu", "_tcp", "local"};
+    qlen = mdns_query(query, 0xBEEF, resolve, 4, 33);
+    len = mdns_answer(query, qlen, 1, &svc, reply, sizeof(reply));
+    assert(len > 12 && reply[0] == 0xBE && reply[1] == 0xEF && reply[7] == 2 && reply[11] == 1);
+
+    /* A compressed question name pointing back into the packet resolves like a plain one. */
+    const char *const host[] = {"garage", "local"};
+    qlen = mdns_query(query, 0, host, 2, 1);
+    query[5] = 2;
+    const unsigned char pointer_question[] = {0xC0, 12, 0, 1, 0, 1};
+    memcpy(query + qlen, pointer_question, sizeof(pointer_question));
+    qlen += sizeof(pointer_question);
+    len = mdns_answer(query, qlen, 0, &svc, reply, sizeof(reply));
+    assert(len > 12 && reply[7] == 1 && reply[11] == 0 && bytes_contain(reply, len, addr_bytes, 4));
+
+    const char *const enumeration[] = {"_services", "_dns-sd", "_udp", "local"};
+    qlen = mdns_query(query, 0, enumeration, 4, 12);
+    len = mdns_answer(query, qlen, 0, &svc, reply, sizeof(reply));
+    assert(len > 12 && reply[7] == 1 && bytes_contain(reply, len, "\x06_fricu\x04_tcp\x05local", 19));
+
+    /* Other names, responses and truncated packets are not answered. */
+    const char *const other[] = {"_airplay", "_tcp", "local"};
+    qlen = mdns_query(query, 0, other, 3, 12);
+    assert(mdns_answer(query, qlen, 0, &svc, reply, sizeof(reply)) == 0);
+    qlen = mdns_query(query, 0, browse, 3, 12);
+    query[2] = 0x84;
+    assert(mdns_answer(query, qlen, 0, &svc, reply, sizeof(reply)) == 0);
+    query[2] = 0;
+    assert(mdns_answer(query, 20, 0, &svc, reply, sizeof(reply)) == 0);
+
+    /* The goodbye carries the same records with a zero TTL. */
+    len = mdns_announcement(&svc, 1, reply, sizeof(reply));
+    assert(len > 12 && reply[7] == 4 && reply[11] == 0);
+    assert(bytes_contain(reply, len, "\x00\x0c\x00\x01\x00\x00\x00\x00", 8));
+    len = mdns_announcement(&svc, 0, reply, sizeof(reply));
+    assert(len > 12 && bytes_contain(reply, len, "\x00\x0c\x00\x01\x00\x00\x11\x94", 8));
+    assert(mdns_announcement(&svc, 0, reply, 40) == 0);
+
+    /* Loopback binds are never advertised. */
+    assert(mdns_service_init(&svc, "127.0.0.1", 8080) != 0);
+    assert(mdns_service_init(&svc, "192.168.7.9", 8080) == 0 && svc.addr_count == 1 && svc.port == 8080);
+}
+
+static void post_admin_pairing(worker_db_t *db, const char *body, char *resp, size_t resp_len) {
+    char req[2048] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/admin/pairing HTTP/1.1\r\nHost: fricu.lan:8080\r\nX-Admin-Token: s3cret\r\nContent-Length: %zu\r\n\r\n%s",
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void post_pair(worker_db_t *db, const char *body, char *resp, size_t resp_len) {
+    char req[2048] = {0};
+    snprintf(req, sizeof(req), "POST /v1/pair HTTP/1.1\r\nHost: fricu.lan:8080\r\nContent-Length: %zu\r\n\r\n%s", strlen(body), body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_pairing_codes_issue_device_tokens(void) {
+    /* Reed-Solomon check words of the ISO 18004 "HELLO WORLD" 1-M example. */
+    const unsigned char data[16] = {32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17};
+    const unsigned char expected[10] = {196, 35, 39, 119, 235, 215, 231, 226, 93, 23};
+    unsigned char ecc[10] = {0};
+    qr_rs_remainder(data, sizeof(data), 10, ecc);
+    assert(memcmp(ecc, expected, sizeof(ecc)) == 0);
+    qr_code_t qr;
+    char long_text[215] = {0};
+    memset(long_text, 'x', 214);
+    assert(qr_encode("a", 1, &qr) == 0 && qr.version == 1 && qr.size == 21);
+    assert(qr_encode(long_text, 213, &qr) == 0 && qr.version == 10 && qr.size == 57);
+    assert(qr_encode(long_text, 214, &qr) != 0);
+    assert(qr_encode("fricu://pair?server=http%3A%2F%2F192.168.1.20%3A8080&code=ABCD-EFGH-JKMN", 72, &qr) == 0 && qr.version == 5);
+    for (int i = 0; i < 7; i++) {
+        assert(qr.modules[i] == 1 && qr.modules[6 * qr.size + i] == 1 && qr.modules[qr.size - 1 - i] == 1);
+    }
+    /* Format information M/mask is written twice; the dark module sits above the lower-left finder. */
+    assert(qr.modules[(qr.size - 8) * qr.size + 8] == 1);
+    for (int i = 0; i < 6; i++) {
+        assert(qr.modules[i * qr.size + 8] == qr.modules[8 * qr.size + qr.size - 1 - i]);
+    }
+
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-pairing-XXXXXX");
+    char resp[65536] = {0};
+    char req[2048] = {0};
+    char code[32] = {0};
+    char token[96] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+
+    run_request(&env.db, "POST /v1/admin/pairing HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"scope\":\"admin\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "scope must be api or device") != NULL);
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"ttl_seconds\":5}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "ttl_seconds") != NULL);
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"url\":\"ftp://x\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    /* A phone pairs with scope api and gets a bearer token; the code is typed without dashes. */
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"name\":\"phone\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    const char *field = strstr(resp, "\"code\":\"");
+    assert(field != NULL && sscanf(field, "\"code\":\"%31[^\"]\"", code) == 1 && strlen(code) == 14 && code[4] == '-' && code[9] == '-');
+    assert(strstr(resp, "\"account\":\"tester\",\"scope\":\"api\",\"name\":\"phone\",\"expires_at\":") != NULL);
+    assert(strstr(resp, "\"server\":\"http://fricu.lan:8080\",\"pair_url\":\"fricu://pair?server=http%3A%2F%2Ffricu.lan%3A8080&code=") != NULL);
+    assert(strstr(resp, code) != NULL && strstr(resp, "\"qr_svg\":\"<svg xmlns=") != NULL && strstr(resp, "</svg>\"}") != NULL);
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT code_hash, used_at IS NULL FROM pairing_codes", -1, &stmt, NULL) == SQLITE_OK);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strstr((const char *)sqlite3_column_text(stmt, 0), code) == NULL && sqlite3_column_int(stmt, 1) == 1);
+    sqlite3_finalize(stmt);
+
+    char typed[32] = {0};
+    size_t t = 0;
+    for (const char *p = code; *p; p++) {
+        if (*p != '-') typed[t++] = (char)tolower((unsigned char)*p);
+    }
+    post_pair(&env.db, "{\"name\":\"Pixel\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "code is required") != NULL);
+    snprintf(req, sizeof(req), "{\"code\":\"%s\",\"name\":\"Pixel\"}", typed);
+    post_pair(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1 && strncmp(token, "fat_", 4) == 0);
+    assert(strstr(resp, "\"scope\":\"api\",\"id\":\"tok_") != NULL && strstr(resp, "\"account\":\"tester\",\"name\":\"Pixel\"}") != NULL);
+    snprintf(req, sizeof(req), "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    /* Codes work once, and unknown codes look the same. */
+    snprintf(req, sizeof(req), "{\"code\":\"%s\"}", code);
+    post_pair(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "invalid or expired pairing code") != NULL);
+    post_pair(&env.db, "{\"code\":\"0000-0000-0000\"}", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    /* A head unit pairs with scope device and reads today's workout with the device token. */
+    setenv("FRICU_PUBLIC_URL", "https://fricu.example.com/", 1);
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"scope\":\"device\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"scope\":\"device\",\"name\":\"head unit\"") != NULL);
+    assert(strstr(resp, "\"server\":\"https://fricu.example.com\"") != NULL);
+    field = strstr(resp, "\"code\":\"");
+    assert(field != NULL && sscanf(field, "\"code\":\"%31[^\"]\"", code) == 1);
+    snprintf(req, sizeof(req), "{\"code\":\"%s\"}", code);
+    post_pair(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"id\":") == NULL);
+    field = strstr(resp, "\"token\":\"");
+    assert(field != NULL && sscanf(field, "\"token\":\"%95[^\"]\"", token) == 1 && strncmp(token, "dev_", 4) == 0);
+    char hash[65] = {0};
+    sha256_hex(token, strlen(token), hash, sizeof(hash));
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT name FROM device_tokens WHERE token_hash = ?1", -1, &stmt, NULL) == SQLITE_OK);
+    sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && strcmp((const char *)sqlite3_column_text(stmt, 0), "head unit") == 0);
+    sqlite3_finalize(stmt);
+    snprintf(req, sizeof(req), "GET /v1/today/workout HTTP/1.1\r\nHost: localhost\r\nX-Device-Token: %s\r\n\r\n", token);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL && strstr(resp, "no workout scheduled") != NULL);
+    unsetenv("FRICU_PUBLIC_URL");
+
+    /* Expired codes are refused. */
+    post_admin_pairing(&env.db, "{\"account\":\"tester\",\"ttl_seconds\":30}", resp, sizeof(resp));
+    field = strstr(resp, "\"code\":\"");
+    assert(field != NULL && sscanf(field, "\"code\":\"%31[^\"]\"", code) == 1);
+    assert(sqlite3_exec(env.db.db, "UPDATE pairing_codes SET expires_at = 1 WHERE used_at IS NULL", NULL, NULL, NULL) == SQLITE_OK);
+    snprintf(req, sizeof(req), "{\"code\":\"%s\"}", code);
+    post_pair(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_health_live_and_ready(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-ready-XXXXXX");
+    char resp[4096] = {0};
+    run_request(&env.db, "GET /health/live HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"status\":\"live\"}") != NULL);
+    run_request(&env.db, "GET /health/ready HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "{\"status\":\"ready\",\"checks\":{\"database\":\"ok\",\"select\":\"ok\",\"wal\":\"ok\",\"write_queue\":\"ok\",\"shutdown\":\"ok\"}") != NULL);
+    assert(strstr(resp, "\"write_queue_depth\":0,") != NULL);
+
+    /* An open connection keeps working on a deleted file, so readiness looks at the path. */
+    assert(rename("state.db", "state.db.moved") == 0);
+    run_request(&env.db, "GET /health/ready HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "503 Service Unavailable") != NULL && strstr(resp, "Retry-After: 5") != NULL);
+    assert(strstr(resp, "\"status\":\"not ready\"") != NULL && strstr(resp, "\"database\":\"database file missing\"") != NULL);
+    run_request(&env.db, "GET /health/live HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    run_request(&env.db, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(rename("state.db.moved", "state.db") == 0);
+    run_request(&env.db, "GET /health/ready HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    test_env_close(&env);
+}
+
+static void post_inbound_email(worker_db_t *db, const char *path, const char *content_type, const char *body, char *resp, size_t resp_len) {
+    char req[16384] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST %s HTTP/1.1\r\nHost: localhost\r\nContent-Type: %s\r\nContent-Length: %zu\r\n\r\n%s",
+        path,
+        content_type,
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_email_inbox_imports_fit_and_tcx_attachments(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-mailin-XXXXXX");
+    char resp[65536] = {0};
+
+    send_item_request(&env.db, "GET", "/v1/import/email", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "PUT", "/v1/import/email", "{\"senders\":\"watch@example.com\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "PUT", "/v1/import/email", "{\"senders\":[\" Watch@Example.com\"]}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"senders\":[\"watch@example.com\"]") != NULL);
+    const char *path_start = strstr(resp, "\"path\":\"");
+    assert(path_start != NULL);
+    char path[128] = {0};
+    snprintf(path, sizeof(path), "%.*s", (int)(strchr(path_start + 8, '"') - path_start - 8), path_start + 8);
+    assert(strncmp(path, "/inbound/email/mail_", 20) == 0);
+
+    /* Session message only: start 2021-09-08T01:46:40Z, cycling, 1 h, 30 km, HR 140. */
+    const unsigned char fit[] = {
+        14, 0x20, 0x08, 0x08, 36, 0, 0, 0, '.', 'F', 'I', 'T', 0, 0,
+        0x40, 0, 0, 18, 0, 5, 2, 4, 0x86, 5, 1, 0x00, 8, 4, 0x86, 9, 4, 0x86, 16, 1, 0x02,
+        0x00, 0x00, 0xCA, 0x9A, 0x3B, 2, 0x80, 0xEE, 0x36, 0x00, 0xC0, 0xC6, 0x2D, 0x00, 140,
+    };
+    const char *tcx =
+        "<?xml version=\"1.0\"?><TrainingCenterDatabase><Activities><Activity Sport=\"Running\"><Id>2025-05-02T06:00:00Z</Id>"
+        "<Lap StartTime=\"2025-05-02T06:00:00Z\"><TotalTimeSeconds>1800</TotalTimeSeconds><DistanceMeters>5000</DistanceMeters>"
+        "<AverageHeartRateBpm><Value>150</Value></AverageHeartRateBpm><Track><Trackpoint><HeartRateBpm><Value>149</Value></HeartRateBpm>"
+        "<DistanceMeters>10</DistanceMeters></Trackpoint><Trackpoint><HeartRateBpm><Value>151</Value></HeartRateBpm></Trackpoint></Track>"
+        "</Lap></Activity></Activities></TrainingCenterDatabase>";
+    char fit64[128] = {0};
+    char tcx64[2048] = {0};
+    base64_encode(fit, sizeof(fit), fit64, sizeof(fit64));
+    base64_encode((const unsigned char *)tcx, strlen(tcx), tcx64, sizeof(tcx64));
+    char mime[8192] = {0};
+    snprintf(
+        mime,
+        sizeof(mime),
+        "From: Garmin Watch <watch@example.com>\r\nTo: inbox@fricu.example\r\nDate: Thu, 01 May 2025 20:15:00 +0200\r\n"
+        "Subject: Activity export\r\nAuthentication-Results: mx.example; spf=pass; dkim=pass; dmarc=pass\r\n"
+        "MIME-Version: 1.0\r\nContent-Type: multipart/mixed;\r\n boundary=\"outer\"\r\n\r\n"
+        "preamble\r\n--outer\r\nContent-Type: text/plain\r\n\r\nSee attached.\r\n"
+        "--outer\r\nContent-Type: application/octet-stream; name=\"Morning_Ride.fit\"\r\nContent-Transfer-Encoding: base64\r\n"
+        "Content-Disposition: attachment;\r\n filename=\"Morning_Ride.fit\"\r\n\r\n%s\r\n"
+        "--outer\r\nContent-Type: application/vnd.garmin.tcx+xml\r\nContent-Disposition: attachment; filename=run.TCX\r\n"
+        "Content-Transfer-Encoding: base64\r\n\r\n%s\r\n"
+        "--outer\r\nContent-Type: text/plain; name=notes.txt\r\nContent-Disposition: attachment; filename=notes.txt\r\n\r\nhello\r\n"
+        "--outer--\r\n",
+        fit64,
+        tcx64);
+    post_inbound_email(&env.db, path, "message/rfc822", mime, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"email\"") != NULL);
+    assert(strstr(resp, "\"skipped\":[{\"index\":2,\"reason\":\"unsupported file type (expected .fit or .tcx)\"}]") != NULL);
+    assert(strstr(resp, "\"duplicates\":[]") != NULL && strstr(resp, "\"externalID\":\"email:") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"2021-09-08T01:46:40Z\",\"sport\":\"cycling\",\"athleteName\":\"\",\"durationSec\":3600,\"distanceKm\":30.000") != NULL);
+    assert(strstr(resp, "\"avgHeartRate\":140") != NULL && strstr(resp, "\"sourceFileName\":\"Morning_Ride.fit\",\"sourceFileType\":\"fit\"") != NULL);
+    assert(strstr(resp, "\"date\":\"2025-05-02T06:00:00Z\",\"sport\":\"running\",\"athleteName\":\"\",\"durationSec\":1800,\"distanceKm\":5.000") != NULL);
+    assert(strstr(resp, "\"avgHeartRate\":150") != NULL && strstr(resp, "\"heartRateSamples\":[149,151]") != NULL);
+
+    /* Resending the same mail is a no-op; other senders and failed DMARC are rejected. */
+    post_inbound_email(&env.db, path, "message/rfc822", mime, resp, sizeof(resp));
+    assert(strstr(resp, "\"imported\":[],\"duplicates\":[\"email:") != NULL);
+    post_inbound_email(&env.db, path, "message/rfc822", "From: someone@else.example\r\nSubject: hi\r\n\r\nbody", resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL && strstr(resp, "not a verified address") != NULL);
+    char json[4096] = {0};
+    snprintf(
+        json,
+        sizeof(json),
+        "{\"FromFull\":{\"Email\":\"WATCH@example.com\"},\"Date\":\"Fri, 2 May 2025 08:00:00 +0000\","
+        "\"Headers\":[{\"Name\":\"Authentication-Results\",\"Value\":\"mx; spf=fail; dmarc=fail\"}],"
+        "\"Attachments\":[{\"Name\":\"ride.fit\",\"Content\":\"%s\",\"ContentType\":\"application/octet-stream\"}]}",
+        fit64);
+    post_inbound_email(&env.db, path, "application/json", json, resp, sizeof(resp));
+    assert(strstr(resp, "403 Forbidden") != NULL && strstr(resp, "authentication failed") != NULL);
+    char *dmarc = strstr(json, "spf=fail; dmarc=fail");
+    memcpy(dmarc, "spf=pass; dmarc=pass", 20);
+    post_inbound_email(&env.db, path, "application/json", json, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"imported\":[],\"duplicates\":[\"email:") != NULL);
+    post_inbound_email(&env.db, "/inbound/email/mail_unknown", "message/rfc822", mime, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    send_item_request(&env.db, "GET", "/v1/import/email", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"received\":3") != NULL && strstr(resp, path) != NULL);
+    send_item_request(&env.db, "PUT", "/v1/import/email", "{\"senders\":[\"watch@example.com\"],\"rotate\":true}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, path) == NULL);
+    post_inbound_email(&env.db, path, "message/rfc822", mime, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+    send_item_request(&env.db, "DELETE", "/v1/import/email", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+
+    test_env_close(&env);
+}
+
+static void post_fit_upload(worker_db_t *db, const char *account, const char *query, const char *body, size_t body_len, char *resp, size_t resp_len) {
+    char req[8192] = {0};
+    int head = snprintf(
+        req,
+        sizeof(req),
+        "POST /v1/import/fit%s HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: %s\r\n"
+        "Content-Type: multipart/form-data; boundary=----fricuform\r\nContent-Length: %zu\r\n\r\n",
+        query,
+        account,
+        body_len);
+    assert(head > 0 && (size_t)head + body_len < sizeof(req));
+    memcpy(req + head, body, body_len);
+    run_request_bytes(db, req, (size_t)head + body_len, resp, resp_len);
+}
+
+static void test_fit_upload_imports_activities(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-fit-upload-XXXXXX");
+    char resp[65536] = {0};
+    /* Session message only: start 2021-09-08T01:46:40Z, cycling, 1 h, 30 km, HR 140. */
+    const unsigned char fit[] = {
+        14, 0x20, 0x08, 0x08, 36, 0, 0, 0, '.', 'F', 'I', 'T', 0, 0,
+        0x40, 0, 0, 18, 0, 5, 2, 4, 0x86, 5, 1, 0x00, 8, 4, 0x86, 9, 4, 0x86, 16, 1, 0x02,
+        0x00, 0x00, 0xCA, 0x9A, 0x3B, 2, 0x80, 0xEE, 0x36, 0x00, 0xC0, 0xC6, 0x2D, 0x00, 140,
+    };
+    char body[2048] = {0};
+    size_t len = (size_t)snprintf(
+        body,
+        sizeof(body),
+        "------fricuform\r\nContent-Disposition: form-data; name=\"device\"\r\n\r\nEdge 540\r\n"
+        "------fricuform\r\nContent-Disposition: form-data; name=\"file\"; filename=\"Morning_Ride.fit\"\r\n"
+        "Content-Type: application/octet-stream\r\n\r\n");
+    memcpy(body + len, fit, sizeof(fit));
+    len += sizeof(fit);
+    len += (size_t)snprintf(
+        body + len,
+        sizeof(body) - len,
+        "\r\n------fricuform\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\nContent-Type: text/plain\r\n\r\n"
+        "hello\r\n------fricuform--\r\n");
+
+    post_fit_upload(&env.db, "tester", "", body, len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"fit\",\"key\":\"activities\"") != NULL);
+    assert(strstr(resp, "\"imported\":[{\"id\":") != NULL && strstr(resp, "\"externalID\":\"fit:") != NULL);
+    assert(strstr(resp, "\"skipped\":[{\"index\":1,\"reason\":\"unsupported file type (expected .fit or .tcx)\"}]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"2021-09-08T01:46:40Z\",\"sport\":\"cycling\",\"athleteName\":\"\",\"durationSec\":3600,\"distanceKm\":30.000") != NULL);
+    assert(strstr(resp, "\"avgHeartRate\":140") != NULL && strstr(resp, "\"tss\":") != NULL);
+    assert(strstr(resp, "\"sourceFileName\":\"Morning_Ride.fit\",\"sourceFileType\":\"fit\",\"sourceFileBase64\":\"") != NULL);
+
+    /* The same file again is a duplicate; another account can import it without keeping the file. */
+    post_fit_upload(&env.db, "tester", "", body, len, resp, sizeof(resp));
+    assert(strstr(resp, "\"imported\":[],\"duplicates\":[\"fit:") != NULL);
+    post_fit_upload(&env.db, "other", "?store_file=false", body, len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"duplicates\":[]") != NULL);
+    run_request(&env.db, "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: other\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"durationSec\":3600") != NULL && strstr(resp, "\"sourceFileType\":\"fit\"") != NULL);
+    assert(strstr(resp, "sourceFileBase64") == NULL);
+
+    send_item_request(&env.db, "POST", "/v1/import/fit", "{\"date\":\"2025-05-01\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "multipart/form-data") != NULL);
+    post_fit_upload(&env.db, "tester", "", "------fricuform--\r\n", 19, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    test_env_close(&env);
+}
+
+static void test_import_preview_reports_without_persisting(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-import-preview-XXXXXX");
+    char resp[16384] = {0};
+    char body[1024] = {0};
+    const char *tcx =
+        "<?xml version=\"1.0\"?><TrainingCenterDatabase><Activities><Activity Sport=\"Running\"><Id>2025-05-02T06:00:00Z</Id>"
+        "<Lap StartTime=\"2025-05-02T06:00:00Z\"><TotalTimeSeconds>1800</TotalTimeSeconds><DistanceMeters>5000</DistanceMeters>"
+        "<AverageHeartRateBpm><Value>150</Value></AverageHeartRateBpm></Lap></Activity></Activities></TrainingCenterDatabase>";
+
+    send_item_request(&env.db, "POST", "/v1/import/preview?filename=run.tcx", tcx, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"file\",\"key\":\"activities\"") != NULL);
+    assert(strstr(resp, "\"date\":\"2025-05-02T06:00:00Z\",\"sport\":\"running\",\"duration_sec\":1800,\"distance_km\":5.0") != NULL);
+    assert(strstr(resp, "\"duplicate_of\":null,\"possible_duplicate_of\":null}],\"new\":1,\"duplicates\":0") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "2025-05-02") == NULL);
+
+    /* The same run synced elsewhere is only a possible duplicate; the same file is a duplicate. */
+    put_json(&env.db, "tester", "activities", "[{\"id\":\"strava-1\",\"date\":\"2025-05-02T06:00:40Z\",\"sport\":\"running\",\"durationSec\":1795}]", resp, sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/import/preview?filename=run.tcx", tcx, resp, sizeof(resp));
+    assert(strstr(resp, "\"duplicate_of\":null,\"possible_duplicate_of\":\"strava-1\"}],\"new\":1") != NULL);
+    char hash[32] = {0};
+    content_version(tcx, strlen(tcx), hash, sizeof(hash));
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"strava-1\",\"date\":\"2025-05-02T06:00:40Z\",\"sport\":\"running\",\"durationSec\":1795},"
+        "{\"id\":\"mail-1\",\"date\":\"2025-05-02T06:00:00Z\",\"sport\":\"running\",\"durationSec\":1800,\"externalID\":\"email:%s\"}]",
+        hash);
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/import/preview?filename=run.tcx", tcx, resp, sizeof(resp));
+    assert(strstr(resp, "\"duplicate_of\":\"mail-1\",\"possible_duplicate_of\":null}],\"new\":0,\"duplicates\":1") != NULL);
+
+    send_item_request(&env.db, "POST", "/v1/import/preview?filename=notes.txt", "hello", resp, sizeof(resp));
+    assert(strstr(resp, "\"items\":[],\"new\":0,\"duplicates\":0,\"skipped\":[{\"index\":0,\"reason\":\"unsupported file type") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/preview?source=strava", "{}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/import/preview", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+
+    test_env_close(&env);
+}
+
+static void test_diary_csv_import(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-diary-XXXXXX");
+    static char resp[65536];
+    const char *diary =
+        "Date,Sport,Duration,RPE,Notes\r\n"
+        "1992-03-01,Run,45,6,\"Hills, wet\"\r\n"
+        "1992-03-01,run,0:30,4,\r\n"
+        "1992-03-02,Bike,1:30:00,5,Long ride\r\n"
+        "\r\n"
+        "1992-02-30,Run,30,5,\r\n"
+        "1992-03-03,Curling,30,5,\r\n"
+        "1992-03-04,Run,40,12,\r\n";
+
+    /* The wizard step shows the mapping and the activities without writing them. */
+    send_item_request(&env.db, "POST", "/v1/import/preview?source=diary", diary, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"source\":\"diary\",\"key\":\"activities\",\"header\":[\"Date\",\"Sport\",\"Duration\",\"RPE\",\"Notes\"],"
+                        "\"columns\":{\"date\":\"Date\",\"sport\":\"Sport\",\"duration\":\"Duration\",\"rpe\":\"RPE\",\"notes\":\"Notes\"},"
+                        "\"date_format\":\"ymd\",\"sport\":null,\"items\":[") != NULL);
+    assert(strstr(resp, "\"date\":\"1992-03-01T12:00:00Z\",\"sport\":\"running\",\"duration_sec\":2700,\"distance_km\":0.0,\"tss\":270") != NULL);
+    assert(strstr(resp, "\"new\":3,\"duplicates\":0,\"skipped\":[{\"index\":3,\"reason\":\"missing or invalid date\"},"
+                        "{\"index\":4,\"reason\":\"unsupported sport\"},{\"index\":5,\"reason\":\"rpe must be a number in 0..10\"}]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "1992-03-01") == NULL);
+
+    send_item_request(&env.db, "POST", "/v1/import/diary?filename=logbook.csv", diary, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"source\":\"diary\",\"key\":\"activities\"") != NULL);
+    assert(strstr(resp, "\"externalID\":\"diary:1992-03-01:running:1\"") != NULL && strstr(resp, "\"externalID\":\"diary:1992-03-01:running:2\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"1992-03-01T12:00:00Z\",\"sport\":\"running\",\"athleteName\":\"\",\"durationSec\":2700,\"distanceKm\":0.000,\"tss\":270,") != NULL);
+    assert(strstr(resp, "\"loadModel\":\"srpe\",\"loads\":{\"srpe\":270},\"rpe\":6,\"intervals\":[],\"manualEntry\":true,\"notes\":\"Hills, wet\"") != NULL);
+    assert(strstr(resp, "\"durationSec\":5400,\"distanceKm\":0.000,\"tss\":450,") != NULL && strstr(resp, "\"notes\":\"Long ride\"") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/diary", diary, resp, sizeof(resp));
+    assert(strstr(resp, "\"imported\":[],\"duplicates\":[\"diary:1992-03-01:running:1\",\"diary:1992-03-01:running:2\",\"diary:1992-03-02:cycling:1\"]") != NULL);
+
+    /* Other layouts are mapped by hand: semicolons, day-first dates, custom headers, one sport. */
+    send_item_request(
+        &env.db,
+        "POST",
+        "/v1/import/diary?date_format=dmy&date_column=Tag&duration_column=Minuten&rpe_column=Anstrengung&sport=swim",
+        "Tag;Minuten;Anstrengung\n01.04.1992;60;7\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"externalID\":\"diary:1992-04-01:swimming:1\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"date\":\"1992-04-01T12:00:00Z\",\"sport\":\"swimming\",\"athleteName\":\"\",\"durationSec\":3600,\"distanceKm\":0.000,\"tss\":420,") != NULL);
+
+    send_item_request(&env.db, "POST", "/v1/import/diary", "When,Minutes\n1992-05-01,30\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "\"field\":\"date\"") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/diary?date_column=Day", diary, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "date_column is not a column of the header row") != NULL);
+    send_item_request(&env.db, "POST", "/v1/import/diary", "Date,Sport,Duration\n", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "no diary rows") != NULL);
+
+    test_env_close(&env);
+}
+
+typedef struct {
+    int listen_fd;
+    int port;
+    int fail_next_put;
+    const char *reply_body;
+    volatile int stop;
+    char log[8192];
+    size_t log_len;
+    char bodies[8192];
+    size_t bodies_len;
+} mock_upload_server_t;
+
+/*
+ * Records "METHOD path bytes [auth] [dropbox-arg]" per request and answers 201 (or 500 once), or 200
+ * with reply_body when one is set.
+ */
+static void *mock_upload_server_entry(void *arg) {
+    mock_upload_server_t *m = (mock_upload_server_t *)arg;
+    while (!m->stop) {
+        int fd = accept(m->listen_fd, NULL, NULL);
+        if (fd < 0) continue;
+        char buf[16384] = {0};
+        size_t len = 0;
+        char *head_end = NULL;
+        ssize_t n;
+        while (len < sizeof(buf) - 1 && (n = recv(fd, buf + len, sizeof(buf) - 1 - len, 0)) > 0) {
+            len += (size_t)n;
+            head_end = strstr(buf, "\r\n\r\n");
+            if (!head_end) continue;
+            const char *cl = strstr(buf, "Content-Length: ");
+            size_t body_len = cl ? (size_t)strtoul(cl + 16, NULL, 10) : 0;
+            if (len >= (size_t)(head_end + 4 - buf) + body_len) break;
+        }
+        char method[16] = {0};
+        char path[256] = {0};
+        sscanf(buf, "%15s %255s", method, path);
+        const char *cl = strstr(buf, "Content-Length: ");
+        char auth[64] = {0};
+        char arg_header[256] = {0};
+        const char *a = strstr(buf, "Authorization: ");
+        if (a) sscanf(a + 15, "%63[^\r]", auth);
+        const char *d = strstr(buf, "Dropbox-API-Arg: ");
+        if (d) sscanf(d + 17, "%255[^\r]", arg_header);
+        m->log_len += (size_t)snprintf(
+            m->log + m->log_len, sizeof(m->log) - m->log_len, "%s %s %lu [%s] [%s]\n", method, path, cl ? strtoul(cl + 16, NULL, 10) : 0UL, auth, arg_header);
+        if (head_end) {
+            m->bodies_len += (size_t)snprintf(m->bodies + m->bodies_len, sizeof(m->bodies) - m->bodies_len, "%s\n", head_end + 4);
+        }
+        int fail = strcmp(method, "PUT") == 0 && m->fail_next_put;
+        if (fail) m->fail_next_put = 0;
+        char reply[4096] = {0};
+        if (fail) {
+            snprintf(reply, sizeof(reply), "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        } else if (m->reply_body) {
+            snprintf(reply, sizeof(reply), "HTTP/1.1 200 OK\r\nContent-Length: %zu\r\nConnection: close\r\n\r\n%s", strlen(m->reply_body), m->reply_body);
+        } else {
+            snprintf(reply, sizeof(reply), "HTTP/1.1 201 Created\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+        send(fd, reply, strlen(reply), MSG_NOSIGNAL);
+        close(fd);
+    }
+    return NULL;
+}
+
+static void mock_upload_server_start(mock_upload_server_t *m, pthread_t *thread) {
+    memset(m, 0, sizeof(*m));
+    m->listen_fd = socket(AF_INET, SOCK_STREAM, 0);
+    assert(m->listen_fd >= 0);
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    assert(bind(m->listen_fd, (struct sockaddr *)&addr, sizeof(addr)) == 0 && listen(m->listen_fd, 8) == 0);
+    socklen_t addr_len = sizeof(addr);
+    assert(getsockname(m->listen_fd, (struct sockaddr *)&addr, &addr_len) == 0);
+    m->port = ntohs(addr.sin_port);
+    struct timeval tv = {.tv_sec = 0, .tv_usec = 100000};
+    setsockopt(m->listen_fd, SOL_SOCKET, SO_RCVTIMEO, &tv, sizeof(tv));
+    assert(pthread_create(thread, NULL, mock_upload_server_entry, m) == 0);
+}
+
+static void test_export_connectors_upload_and_retry(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-connectors-XXXXXX");
+    char resp[16384] = {0};
+    char body[512] = {0};
+    char id[64] = {0};
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+    mock.fail_next_put = 1;
+    setenv("FRICU_CONNECTOR_ALLOW_PRIVATE", "1", 1);
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\",\"sourceFileName\":\"Morning ride.fit\",\"sourceFileBase64\":\"SGVsbG8=\"},"
+        "{\"id\":\"a2\",\"date\":\"2025-05-02T07:00:00Z\",\"sport\":\"running\"}]",
+        resp,
+        sizeof(resp));
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"ftp\",\"url\":\"ftp://example\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    snprintf(body, sizeof(body), "{\"kind\":\"webdav\",\"url\":\"http://127.0.0.1:%d/dav/\",\"username\":\"u\",\"password\":\"p\"}", mock.port);
+    send_item_request(&env.db, "POST", "/v1/connectors", body, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"queued\":2}") != NULL);
+    const char *field = strstr(resp, "\"id\":\"");
+    assert(field != NULL && sscanf(field, "\"id\":\"%63[^\"]\"", id) == 1);
+
+    /* The first PUT fails and backs off; the weekly bundle still goes through. */
+    assert(connectors_run_due(env.db.db, 20) == 2);
+    assert(connectors_run_due(env.db.db, 20) == 0);
+    snprintf(body, sizeof(body), "/v1/connectors/%s", id);
+    send_item_request(&env.db, "GET", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"username\":\"u\"") != NULL && strstr(resp, "\"password\"") == NULL && strstr(resp, "\"p\"") == NULL);
+    assert(strstr(resp, "\"jobs\":{\"pending\":1,\"done\":1,\"failed\":0,\"skipped\":0}") != NULL);
+    assert(strstr(resp, "\"ref\":\"a1\",\"state\":\"pending\",\"attempts\":1") != NULL && strstr(resp, "\"last_error\":\"WebDAV PUT returned HTTP 500\"") != NULL);
+    assert(strstr(mock.log, "PUT /dav/weekly/fricu-week-") != NULL);
+
+    snprintf(body, sizeof(body), "/v1/connectors/%s/sync", id);
+    send_item_request(&env.db, "POST", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "202 Accepted") != NULL && strstr(resp, "{\"queued\":0,\"retried\":1,\"worker\":false}") != NULL);
+    assert(connectors_run_due(env.db.db, 20) == 1);
+    assert(strstr(mock.log, "MKCOL /dav/activities/ 0 [Basic dTpw]") != NULL);
+    assert(strstr(mock.log, "PUT /dav/activities/2025-05-01-Morning_ride.fit 5 [Basic dTpw]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/connectors", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"jobs\":{\"pending\":0,\"done\":2,\"failed\":0,\"skipped\":0}") != NULL && strstr(resp, "\"last_error\":null") != NULL);
+
+    /* New files are picked up once; Dropbox uploads carry the folder in Dropbox-API-Arg. */
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\",\"sourceFileName\":\"Morning ride.fit\",\"sourceFileBase64\":\"SGVsbG8=\"},"
+        "{\"id\":\"a3\",\"date\":\"2025-05-03T07:00:00Z\",\"sport\":\"running\",\"sourceFileType\":\"tcx\",\"sourceFileBase64\":\"PFRDWC8+\"}]",
+        resp,
+        sizeof(resp));
+    assert(connectors_enqueue(env.db.db, "tester", NULL) == 1);
+    assert(connectors_run_due(env.db.db, 20) == 1);
+    assert(strstr(mock.log, "PUT /dav/activities/2025-05-03-a3.tcx 6") != NULL);
+    snprintf(body, sizeof(body), "http://127.0.0.1:%d/2/files/upload", mock.port);
+    setenv("FRICU_DROPBOX_CONTENT_URL", body, 1);
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"dropbox\",\"folder\":\"/Fricu/\",\"access_token\":\"t0k\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"queued\":3}") != NULL);
+    assert(connectors_run_due(env.db.db, 20) == 3);
+    assert(strstr(mock.log, "POST /2/files/upload 5 [Bearer t0k] [{\"path\":\"/Fricu/activities/2025-05-01-Morning_ride.fit\",\"mode\":\"overwrite\",\"mute\":true}]") != NULL);
+    unsetenv("FRICU_DROPBOX_CONTENT_URL");
+
+    snprintf(body, sizeof(body), "/v1/connectors/%s", id);
+    send_item_request(&env.db, "DELETE", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "GET", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    unsetenv("FRICU_CONNECTOR_ALLOW_PRIVATE");
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+    test_env_close(&env);
+}
+
+static void test_connectors_refuse_private_targets(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-connectors-private-XXXXXX");
+    char resp[16384] = {0};
+    unsetenv("FRICU_CONNECTOR_ALLOW_PRIVATE");
+
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"webdav\",\"url\":\"http://127.0.0.1/dav/\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "{\"error\":\"webdav url resolves to a private address\"}") != NULL);
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"webdav\",\"url\":\"http://169.254.169.254/latest/\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"webdav\",\"url\":\"http://localhost:8080/dav/\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    /* A target registered while allowed is still refused at connect time once the opt-in is gone. */
+    setenv("FRICU_CONNECTOR_ALLOW_PRIVATE", "1", 1);
+    send_item_request(&env.db, "POST", "/v1/connectors", "{\"kind\":\"webdav\",\"url\":\"http://127.0.0.1:1/dav/\"}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    unsetenv("FRICU_CONNECTOR_ALLOW_PRIVATE");
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\",\"sourceFileName\":\"ride.fit\",\"sourceFileBase64\":\"SGVsbG8=\"}]",
+        resp,
+        sizeof(resp));
+    assert(connectors_enqueue(env.db.db, "tester", NULL) >= 1);
+    assert(connectors_run_due(env.db.db, 20) >= 1);
+    send_item_request(&env.db, "GET", "/v1/connectors", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"last_error\":\"127.0.0.1 resolves to a private address") != NULL);
+    assert(strstr(resp, "\"done\":0") != NULL);
+    test_env_close(&env);
+}
+
+static void test_exports_encrypt_with_passphrase(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-encryption-XXXXXX");
+    char resp[65536] = {0};
+    char body[512] = {0};
+    setenv("FRICU_EXPORT_SCRYPT_WORK_FACTOR", "10", 1);
+
+    /* Empty, exactly one chunk and several chunks; any damage or the wrong passphrase is refused. */
+    static const size_t sizes[] = {0, 65536, 150000};
+    for (size_t i = 0; i < sizeof(sizes) / sizeof(sizes[0]); i++) {
+        unsigned char *plain = (unsigned char *)malloc(sizes[i] + 1);
+        assert(plain != NULL);
+        for (size_t j = 0; j < sizes[i]; j++) plain[j] = (unsigned char)(j * 7 + i);
+        unsigned char *sealed = NULL;
+        size_t sealed_len = 0;
+        assert(age_encrypt("correct horse", 10, plain, sizes[i], &sealed, &sealed_len) == 0);
+        assert(export_is_encrypted((const char *)sealed, sealed_len) && memcmp(sealed + 22, "-> scrypt ", 10) == 0);
+        char *opened = NULL;
+        size_t opened_len = 0;
+        char err[128] = {0};
+        assert(age_decrypt("correct horse", (const char *)sealed, sealed_len, &opened, &opened_len, err, sizeof(err)) == 0);
+        assert(opened_len == sizes[i] && memcmp(opened, plain, sizes[i]) == 0);
+        free(opened);
+        assert(age_decrypt("wrong horse", (const char *)sealed, sealed_len, &opened, &opened_len, err, sizeof(err)) != 0);
+        assert(strcmp(err, "wrong passphrase") == 0 && opened == NULL);
+        assert(age_decrypt("correct horse", (const char *)sealed, sealed_len - 1, &opened, &opened_len, err, sizeof(err)) != 0);
+        sealed[sealed_len - 20] ^= 1;
+        assert(age_decrypt("correct horse", (const char *)sealed, sealed_len, &opened, &opened_len, err, sizeof(err)) != 0);
+        assert(strcmp(err, "payload is corrupt or truncated") == 0);
+        free(sealed);
+        free(plain);
+    }
+
+    put_json(
+        &env.db,
+        "tester",
+        "workouts",
+        "[{\"id\":\"w1\",\"name\":\"Endurance\",\"scheduledDate\":\"2025-03-04T06:00:00Z\",\"segments\":[{\"minutes\":60,\"intensityPercentFTP\":65}]}]",
+        resp,
+        sizeof(resp));
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?from=2025-03-03&weeks=1&encrypt=age", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "409 Conflict") != NULL && strstr(resp, "FRICU_EXPORT_PASSPHRASE") != NULL);
+    setenv("FRICU_EXPORT_PASSPHRASE", "correct horse", 1);
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?from=2025-03-03&weeks=1&encrypt=zip", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/export/plan-template?from=2025-03-03&weeks=1&encrypt=age", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "Content-Type: application/octet-stream\r\n") != NULL);
+    assert(strstr(resp, "filename=\"fricu-plan-template.json.age\"") != NULL);
+    size_t sealed_len = 0;
+    const char *sealed = response_body(resp, &sealed_len);
+    assert(export_is_encrypted(sealed, sealed_len) && memmem(sealed, sealed_len, "Endurance", 9) == NULL);
+    char *plain = NULL;
+    size_t plain_len = 0;
+    char err[128] = {0};
+    assert(export_decrypt(sealed, sealed_len, &plain, &plain_len, err, sizeof(err)) == 0);
+    assert(strncmp(plain, "{\"format\":\"fricu-plan-template-v1\"", 34) == 0 && strstr(plain, "\"name\":\"Endurance\"") != NULL);
+    free(plain);
+
+    /* The encrypted file imports as it is, on any server that knows the passphrase. */
+    char *req = (char *)malloc(sealed_len + 256);
+    assert(req != NULL);
+    int head = snprintf(
+        req, 256, "POST /v1/import/plan-template?start_date=2025-06-02 HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\nContent-Length: %zu\r\n\r\n", sealed_len);
+    memcpy(req + head, sealed, sealed_len);
+    size_t req_len = (size_t)head + sealed_len;
+    run_request_bytes(&env.db, req, req_len, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"externalID\":\"plan-template:Training plan:2025-06-02:0\"") != NULL);
+    setenv("FRICU_EXPORT_PASSPHRASE", "another", 1);
+    run_request_bytes(&env.db, req, req_len, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL && strstr(resp, "{\"error\":\"cannot decrypt export: wrong passphrase\"}") != NULL);
+    free(req);
+
+    /* Connector uploads are sealed too and say so in their names. */
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+    setenv("FRICU_CONNECTOR_ALLOW_PRIVATE", "1", 1);
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\",\"sourceFileName\":\"ride.fit\",\"sourceFileBase64\":\"SGVsbG8=\"}]",
+        resp,
+        sizeof(resp));
+    snprintf(body, sizeof(body), "{\"kind\":\"webdav\",\"url\":\"http://127.0.0.1:%d/dav/\"}", mock.port);
+    send_item_request(&env.db, "POST", "/v1/connectors", body, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL);
+    assert(connectors_run_due(env.db.db, 20) == 2);
+    assert(strstr(mock.log, "PUT /dav/activities/2025-05-01-ride.fit.age ") != NULL);
+    assert(strstr(mock.log, ".json.age ") != NULL && strstr(mock.log, "PUT /dav/activities/2025-05-01-ride.fit 5") == NULL);
+    assert(strstr(mock.bodies, "age-encryption.org/v1\n-> scrypt ") != NULL && strstr(mock.bodies, "Hello") == NULL);
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+
+    unsetenv("FRICU_CONNECTOR_ALLOW_PRIVATE");
+    unsetenv("FRICU_EXPORT_PASSPHRASE");
+    unsetenv("FRICU_EXPORT_SCRYPT_WORK_FACTOR");
+    test_env_close(&env);
+}
+
+static void post_bot_webhook(worker_db_t *db, const char *path, const char *extra_headers, const char *body, char *resp, size_t resp_len) {
+    char req[4096] = {0};
+    snprintf(
+        req,
+        sizeof(req),
+        "POST %s HTTP/1.1\r\nHost: localhost\r\n%sContent-Length: %zu\r\n\r\n%s",
+        path,
+        extra_headers,
+        strlen(body),
+        body);
+    run_request(db, req, resp, resp_len);
+}
+
+static void test_chat_bots_answer_commands_and_push(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-bots-XXXXXX");
+    char resp[16384] = {0};
+    char body[4096] = {0};
+    char path[160] = {0};
+    char id[64] = {0};
+    char today[16] = {0};
+    char yesterday[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+    format_iso_day(today_day() - 1, yesterday, sizeof(yesterday));
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+    snprintf(body, sizeof(body), "http://127.0.0.1:%d", mock.port);
+    setenv("FRICU_TELEGRAM_API_URL", body, 1);
+
+    send_item_request(&env.db, "POST", "/v1/bots", "{\"kind\":\"slack\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "POST", "/v1/bots", "{\"kind\":\"telegram\",\"bot_token\":\"T0K\",\"chat_id\":42,\"reminder_hour\":24}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "POST", "/v1/bots", "{\"kind\":\"telegram\",\"bot_token\":\"T0K\",\"chat_id\":42,\"reminder_hour\":0}", resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"chat_id\":\"42\"") != NULL && strstr(resp, "T0K") == NULL);
+    const char *field = strstr(resp, "\"id\":\"");
+    assert(field != NULL && sscanf(field, "\"id\":\"%63[^\"]\"", id) == 1);
+    field = strstr(resp, "\"webhook_path\":\"");
+    assert(field != NULL && sscanf(field, "\"webhook_path\":\"%159[^\"]\"", path) == 1);
+    assert(strncmp(path, "/bots/telegram/bot_", 19) == 0);
+
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"w1\",\"name\":\"Sweet spot\",\"sport\":\"cycling\",\"scheduledDate\":\"%sT00:00:00Z\","
+        "\"segments\":[{\"id\":\"s1\",\"minutes\":45,\"intensityPercentFTP\":90},{\"id\":\"s2\",\"minutes\":15,\"intensityPercentFTP\":50}]}]",
+        today);
+    put_json(&env.db, "tester", "workouts", body, resp, sizeof(resp));
+    /* Yesterday sets the bar at 200 W; today's 300 W beats it for 5 s and 1 min. */
+    size_t off = (size_t)snprintf(body, sizeof(body), "[");
+    for (int a = 0; a < 2; a++) {
+        off += (size_t)snprintf(
+            body + off,
+            sizeof(body) - off,
+            "%s{\"id\":\"a%d\",\"date\":\"%sT07:00:00Z\",\"sport\":\"cycling\",\"durationSec\":70,\"tss\":50,\"powerSamples\":[",
+            a ? "," : "",
+            a,
+            a ? today : yesterday);
+        for (int i = 0; i < 70; i++) off += (size_t)snprintf(body + off, sizeof(body) - off, "%s%d", i ? "," : "", a ? 300 : 200);
+        off += (size_t)snprintf(body + off, sizeof(body) - off, "]}");
+    }
+    snprintf(body + off, sizeof(body) - off, "]");
+    put_json(&env.db, "tester", "activities", body, resp, sizeof(resp));
+    run_request(&env.db, "GET /v1/notifications?kind=personal_record HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "New 1 min power record") != NULL && strstr(resp, ": 300 W (previous best 200 W)") != NULL);
+
+    assert(bots_run_pushes(env.db.db) >= 3);
+    assert(strstr(mock.log, "POST /botT0K/sendMessage") != NULL);
+    assert(strstr(mock.bodies, "{\"chat_id\":\"42\",\"text\":\"New 5 s power record") != NULL);
+    assert(strstr(mock.bodies, "\"text\":\"Reminder: today's plan is Sweet spot (60 min).\"}") != NULL);
+    assert(bots_run_pushes(env.db.db) == 0);
+
+    post_bot_webhook(&env.db, path, "", "{\"update_id\":1,\"message\":{\"chat\":{\"id\":42},\"text\":\"/tsb@fricu_bot\"}}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "{\"method\":\"sendMessage\",\"chat_id\":\"42\",\"text\":\"Fitness (CTL) ") != NULL);
+    post_bot_webhook(&env.db, path, "", "{\"update_id\":2,\"message\":{\"chat\":{\"id\":42},\"text\":\"/today\"}}", resp, sizeof(resp));
+    assert(strstr(resp, "planned Sweet spot (60 min). Done: cycling 1 min, TSS 50. Fitness") != NULL);
+    post_bot_webhook(&env.db, path, "", "{\"update_id\":3,\"message\":{\"chat\":{\"id\":42},\"text\":\"/week\"}}", resp, sizeof(resp));
+    assert(strstr(resp, "\"text\":\"Week of ") != NULL);
+    post_bot_webhook(&env.db, path, "", "{\"update_id\":4,\"message\":{\"chat\":{\"id\":42},\"text\":\"hello\"}}", resp, sizeof(resp));
+    assert(strstr(resp, "Commands: /today") != NULL);
+    post_bot_webhook(&env.db, path, "", "{\"update_id\":5,\"message\":{\"chat\":{\"id\":7},\"text\":\"/tsb\"}}", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\r\n\r\n{}") != NULL);
+    post_bot_webhook(&env.db, "/bots/telegram/bot_unknown", "", "{}", resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+#ifdef FRICU_HAVE_OPENSSL
+    EVP_PKEY *key = NULL;
+    EVP_PKEY_CTX *kctx = EVP_PKEY_CTX_new_id(EVP_PKEY_ED25519, NULL);
+    assert(kctx && EVP_PKEY_keygen_init(kctx) == 1 && EVP_PKEY_keygen(kctx, &key) == 1);
+    EVP_PKEY_CTX_free(kctx);
+    unsigned char raw_key[32];
+    size_t raw_len = sizeof(raw_key);
+    assert(EVP_PKEY_get_raw_public_key(key, raw_key, &raw_len) == 1);
+    char key_hex[65] = {0};
+    for (size_t i = 0; i < raw_len; i++) snprintf(key_hex + i * 2, 3, "%02x", raw_key[i]);
+    snprintf(body, sizeof(body), "{\"kind\":\"discord\",\"webhook_url\":\"http://127.0.0.1:%d/api/webhooks/1/abc\",\"public_key\":\"%s\",\"reminder_hour\":0}", mock.port, key_hex);
+    send_item_request(&env.db, "POST", "/v1/bots", body, resp, sizeof(resp));
+    assert(strstr(resp, "201 Created") != NULL && strstr(resp, "\"interactions\":true") != NULL && strstr(resp, key_hex) == NULL);
+    char discord_path[160] = {0};
+    field = strstr(resp, "\"webhook_path\":\"");
+    assert(field != NULL && sscanf(field, "\"webhook_path\":\"%159[^\"]\"", discord_path) == 1);
+
+    const char *interactions[] = {"{\"type\":1}", "{\"type\":2,\"data\":{\"name\":\"tsb\"}}"};
+    for (int i = 0; i < 2; i++) {
+        char message[256] = {0};
+        snprintf(message, sizeof(message), "1700000000%s", interactions[i]);
+        unsigned char sig[64];
+        size_t sig_len = sizeof(sig);
+        EVP_MD_CTX *md = EVP_MD_CTX_new();
+        assert(EVP_DigestSignInit(md, NULL, NULL, NULL, key) == 1);
+        assert(EVP_DigestSign(md, sig, &sig_len, (const unsigned char *)message, strlen(message)) == 1);
+        EVP_MD_CTX_free(md);
+        char headers[256] = "X-Signature-Timestamp: 1700000000\r\nX-Signature-Ed25519: ";
+        for (size_t b = 0; b < sig_len; b++) snprintf(headers + strlen(headers), 3, "%02x", sig[b]);
+        strcat(headers, "\r\n");
+        post_bot_webhook(&env.db, discord_path, headers, interactions[i], resp, sizeof(resp));
+        assert(strstr(resp, "200 OK") != NULL);
+        assert(strstr(resp, i == 0 ? "\r\n\r\n{\"type\":1}" : "{\"type\":4,\"data\":{\"content\":\"Fitness (CTL) ") != NULL);
+        if (i == 1) {
+            headers[strlen("X-Signature-Timestamp: 1700000000\r\nX-Signature-Ed25519: ")] ^= 1;
+            post_bot_webhook(&env.db, discord_path, headers, interactions[i], resp, sizeof(resp));
+            assert(strstr(resp, "401 Unauthorized") != NULL);
+        }
+    }
+    EVP_PKEY_free(key);
+
+    notification_post(env.db.db, "tester", "test", "bot-check", "Hello from the server");
+    assert(bots_run_pushes(env.db.db) == 3);
+    assert(strstr(mock.log, "POST /api/webhooks/1/abc") != NULL && strstr(mock.bodies, "{\"content\":\"Hello from the server\"}") != NULL);
+#endif
+
+    send_item_request(&env.db, "GET", "/v1/bots", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"kind\":\"telegram\"") != NULL && strstr(resp, "T0K") == NULL && strstr(resp, "\"last_error\":null") != NULL);
+    snprintf(body, sizeof(body), "/v1/bots/%s", id);
+    send_item_request(&env.db, "DELETE", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    send_item_request(&env.db, "DELETE", body, NULL, resp, sizeof(resp));
+    assert(strstr(resp, "404 Not Found") != NULL);
+
+    unsetenv("FRICU_TELEGRAM_API_URL");
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+    test_env_close(&env);
+}
+
+static void test_assist_briefing_combines_plan_form_and_weather(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-assist-XXXXXX");
+    char resp[8192] = {0};
+    char body[1024] = {0};
+    char today[16] = {0};
+    format_iso_day(today_day(), today, sizeof(today));
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+    mock.reply_body = "{\"current\":{\"temperature_2m\":5.8,\"weather_code\":63}}";
+    snprintf(body, sizeof(body), "http://127.0.0.1:%d/v1/forecast", mock.port);
+    setenv("FRICU_WEATHER_URL", body, 1);
+
+    /* Nothing stored and no location: a rest day without weather. */
+    send_item_request(&env.db, "GET", "/v1/assist/briefing", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "\"text\":\"Today: rest day") != NULL && strstr(resp, "\"weather\":null}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/assist/briefing?lat=91&lon=0", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/assist/briefing?format=xml", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+
+    snprintf(
+        body,
+        sizeof(body),
+        "[{\"id\":\"w1\",\"name\":\"Endurance\",\"sport\":\"cycling\",\"scheduledDate\":\"%sT00:00:00Z\","
+        "\"segments\":[{\"id\":\"s1\",\"minutes\":90,\"intensityPercentFTP\":65}]}]",
+        today);
+    put_json(&env.db, "tester", "workouts", body, resp, sizeof(resp));
+    put_json(&env.db, "tester", "profile", "{\"latitude\":48.137,\"longitude\":11.575}", resp, sizeof(resp));
+    send_item_request(&env.db, "GET", "/v1/assist/briefing", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "Today: 90 min Endurance ride, TSB 0, weather 6\xc2\xb0" "C and rain.") != NULL);
+    assert(strstr(resp, "\"planned\":[{\"name\":\"Endurance\",\"sport\":\"cycling\",\"minutes\":90}]") != NULL);
+    assert(strstr(resp, "\"summary\":\"rain\"") != NULL);
+    assert(strstr(mock.log, "GET /v1/forecast?latitude=48.1370&longitude=11.5750&current=temperature_2m,weather_code") != NULL);
+
+    /* The plain-text form is what TTS speaks; the weather is served from the cache. */
+    size_t requests = mock.log_len;
+    send_item_request(&env.db, "GET", "/v1/assist/briefing?format=text", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "text/plain") != NULL && strstr(resp, "\r\n\r\nToday: 90 min Endurance ride, TSB 0, weather 6") != NULL);
+    assert(mock.log_len == requests);
+
+    unsetenv("FRICU_WEATHER_URL");
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+    test_env_close(&env);
+}
+
+static void test_admin_validate_reports_and_fixes(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-validate-XXXXXX");
+    char resp[16384] = {0};
+    setenv("FRICU_ADMIN_TOKEN", "s3cret", 1);
+
+    /* Writes now reject bad dates, so the legacy document the validator must catch is seeded directly. */
+    assert(
+        sqlite3_exec(
+            env.db.db,
+            "INSERT INTO kv_store(data_key, data_value, updated_at) VALUES ('tester::activities',"
+            " '[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\"},{\"id\":\"a1\",\"date\":\"2025-05-02T07:00:00Z\",\"sport\":\"cycling\"},"
+            "{\"id\":\"a2\",\"date\":\"yesterday\",\"sport\":\"running\"},{\"date\":\"2025-05-03T07:00:00Z\",\"sport\":\"running\"}]', 0);",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+    put_json(
+        &env.db,
+        "tester",
+        "events",
+        "[{\"id\":\"e1\",\"name\":\"Spring Classic\",\"startDate\":\"2020-04-01T08:00:00Z\",\"status\":\"upcoming\"},"
+        "{\"id\":\"e2\",\"name\":\"Autumn TT\",\"startDate\":\"2099-09-01T08:00:00Z\",\"status\":\"upcoming\"}]",
+        resp,
+        sizeof(resp));
+    put_json(
+        &env.db,
+        "tester",
+        "activity_metric_insights",
+        "[{\"activityID\":\"a1\",\"summary\":\"ok\"},{\"activityID\":\"gone\",\"summary\":\"stale\"}]",
+        resp,
+        sizeof(resp));
+    assert(
+        sqlite3_exec(
+            env.db.db,
+            "INSERT INTO activity_metrics(account_id, activity_id, metric, value, computed_at) VALUES ('tester', 'gone', 'np', 200, 0);"
+            "INSERT INTO live_samples(account_id, session_id, t, power) VALUES ('tester', 's9', 0, 150), ('tester', 's9', 1, 151);"
+            "INSERT INTO attachments(id, account_id, owner_type, owner_id, filename, content_type, size, blob_sha256, created_at)"
+            " VALUES ('att1', 'tester', 'activity', 'gone', 'photo.jpg', 'image/jpeg', 1, 'x', 0);",
+            NULL,
+            NULL,
+            NULL) == SQLITE_OK);
+
+    run_request(&env.db, "GET /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    run_request(&env.db, "GET /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    assert(strstr(resp, "\"summary\":{\"issues\":8,\"schema\":3,\"reference\":4,\"stale\":1,\"privacy\":0,\"fixable\":4,\"fixed\":0}") != NULL);
+    assert(strstr(resp, "\"check\":\"duplicate_id\",\"account\":\"tester\",\"key\":\"activities\",\"id\":\"a1\"") != NULL);
+    assert(strstr(resp, "\"check\":\"invalid_date\",\"account\":\"tester\",\"key\":\"activities\",\"id\":\"a2\"") != NULL);
+    assert(strstr(resp, "\"check\":\"missing_id\",\"account\":\"tester\",\"key\":\"activities\",\"id\":\"#3\"") != NULL);
+    assert(strstr(resp, "\"check\":\"orphaned_attachment\",\"account\":\"tester\",\"key\":\"attachments\",\"id\":\"att1\"") != NULL);
+    assert(strstr(resp, "\"check\":\"past_event_upcoming\",\"account\":\"tester\",\"key\":\"events\",\"id\":\"e1\"") != NULL);
+    assert(strstr(resp, "\"id\":\"e2\"") == NULL);
+    run_request(&env.db, "GET /v1/admin/validate?account=nobody HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"accounts\":0,\"keys\":0") != NULL && strstr(resp, "\"issues\":0") != NULL);
+
+    /* POST repairs the safe ones; duplicates, bad dates and attachments stay for a human. */
+    run_request(&env.db, "POST /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\nContent-Length: 0\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"fix\":true") != NULL && strstr(resp, "\"fixable\":4,\"fixed\":4}") != NULL);
+    run_request(&env.db, "GET /v1/admin/validate HTTP/1.1\r\nHost: localhost\r\nX-Admin-Token: s3cret\r\n\r\n", resp, sizeof(resp));
+    assert(strstr(resp, "\"summary\":{\"issues\":4,\"schema\":3,\"reference\":1,\"stale\":0,\"privacy\":0,\"fixable\":0,\"fixed\":0}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/events", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"e1\",\"name\":\"Spring Classic\",\"startDate\":\"2020-04-01T08:00:00Z\",\"status\":\"past\"") != NULL);
+    assert(strstr(resp, "\"status\":\"upcoming\"") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activity_metric_insights", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "[{\"activityID\":\"a1\",\"summary\":\"ok\"}]") != NULL);
+
+    unsetenv("FRICU_ADMIN_TOKEN");
+    test_env_close(&env);
+}
+
+static void test_activity_batch_delete_and_archive(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-batch-XXXXXX");
+    char resp[16384] = {0};
+
+    put_json(
+        &env.db,
+        "tester",
+        "activities",
+        "[{\"id\":\"a1\",\"date\":\"2025-05-01T07:00:00Z\",\"sport\":\"cycling\",\"externalID\":\"goldencheetah:abc\"},"
+        "{\"id\":\"a2\",\"date\":\"2025-05-02T07:00:00Z\",\"sport\":\"running\",\"tags\":[\"race\"]},"
+        "{\"id\":\"a3\",\"date\":\"2025-05-03T07:00:00Z\",\"sport\":\"Cycling\",\"importRunID\":\"run-7\"},"
+        "{\"id\":\"a4\",\"date\":\"2025-06-01T07:00:00Z\",\"sport\":\"cycling\"}]",
+        resp,
+        sizeof(resp));
+
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", "{}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", "{\"from\":\"May 1\"}", resp, sizeof(resp));
+    assert(strstr(resp, "400 Bad Request") != NULL);
+    send_item_request(&env.db, "GET", "/v1/activities/batch-delete", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "405 Method Not Allowed") != NULL);
+
+    /* A dry run reports the match without touching the document. */
+    send_item_request(
+        &env.db, "POST", "/v1/activities/batch-delete", "{\"from\":\"2025-05-01\",\"to\":\"2025-05-31\",\"sport\":\"cycling\",\"dry_run\":true}", resp, sizeof(resp));
+    assert(strstr(resp, "\"action\":\"delete\",\"dry_run\":true,\"matched\":2,\"ids\":[\"a1\",\"a3\"]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "\"id\":\"a1\"") != NULL && strstr(resp, "\"id\":\"a3\"") != NULL);
+
+    send_item_request(&env.db, "POST", "/v1/activities/batch-archive", "{\"tag\":\"race\"}", resp, sizeof(resp));
+    assert(strstr(resp, "\"action\":\"archive\",\"dry_run\":false,\"matched\":1,\"ids\":[\"a2\"]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/archived_activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "[{\"id\":\"a2\",\"date\":\"2025-05-02T07:00:00Z\",\"sport\":\"running\",\"tags\":[\"race\"]}]") != NULL);
+
+    /* source matches either the import run id or the externalID prefix. */
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", "{\"source\":\"run-7\"}", resp, sizeof(resp));
+    assert(strstr(resp, "\"matched\":1,\"ids\":[\"a3\"]") != NULL);
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", "{\"source\":\"goldencheetah\"}", resp, sizeof(resp));
+    assert(strstr(resp, "\"matched\":1,\"ids\":[\"a1\"]") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "[{\"id\":\"a4\",\"date\":\"2025-06-01T07:00:00Z\",\"sport\":\"cycling\"}]") != NULL);
+    send_item_request(&env.db, "POST", "/v1/activities/batch-delete", "{\"source\":\"goldencheetah\"}", resp, sizeof(resp));
+    assert(strstr(resp, "\"matched\":0,\"ids\":[]") != NULL);
+
+    test_env_close(&env);
+}
+
+#if defined(FRICU_HAVE_OPENSSL) && OPENSSL_VERSION_NUMBER >= 0x30000000L
+static void base64url(const unsigned char *in, size_t len, char *out, size_t out_len) {
+    base64_encode(in, len, out, out_len);
+    size_t o = 0;
+    for (size_t i = 0; out[i] != '\0' && out[i] != '='; i++) out[o++] = out[i] == '+' ? '-' : out[i] == '/' ? '_' : out[i];
+    out[o] = '\0';
+}
+
+static void make_jwt(EVP_PKEY *key, const char *alg, const char *kid, const char *claims, char *out, size_t out_len) {
+    char header_json[128] = {0};
+    char part[2048] = {0};
+    snprintf(header_json, sizeof(header_json), "{\"alg\":\"%s\",\"typ\":\"JWT\",\"kid\":\"%s\"}", alg, kid);
+    base64url((const unsigned char *)header_json, strlen(header_json), part, sizeof(part));
+    snprintf(out, out_len, "%s.", part);
+    base64url((const unsigned char *)claims, strlen(claims), part, sizeof(part));
+    strncat(out, part, out_len - strlen(out) - 1);
+    unsigned char sig[512];
+    size_t sig_len = sizeof(sig);
+    EVP_MD_CTX *md = EVP_MD_CTX_new();
+    assert(md && EVP_DigestSignInit(md, NULL, EVP_sha256(), NULL, key) == 1);
+    assert(EVP_DigestSign(md, sig, &sig_len, (const unsigned char *)out, strlen(out)) == 1);
+    EVP_MD_CTX_free(md);
+    base64url(sig, sig_len, part, sizeof(part));
+    strncat(out, ".", out_len - strlen(out) - 1);
+    strncat(out, part, out_len - strlen(out) - 1);
+}
+
+static void oidc_get(worker_db_t *db, const char *jwt, char *resp, size_t resp_len) {
+    char req[8192] = {0};
+    snprintf(req, sizeof(req), "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\n\r\n", jwt);
+    run_request(db, req, resp, resp_len);
+}
+
+static void oidc_hashed_account(const char *issuer, const char *claim, char *out, size_t out_len) {
+    char exact[512] = {0};
+    char hash[65] = {0};
+    int n = snprintf(exact, sizeof(exact), "%s\n%s", issuer, claim);
+    sha256_hex(exact, (size_t)n, hash, sizeof(hash));
+    snprintf(out, out_len, "oidc_%.40s", hash);
+}
+
+static void test_oidc_tokens_authenticate_against_jwks(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-oidc-XXXXXX");
+    char resp[16384] = {0};
+    char req[8192] = {0};
+    char jwt[4096] = {0};
+    char claims[512] = {0};
+    char issuer[64] = {0};
+    char jwks[2048] = {0};
+    long long now = (long long)time(NULL);
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+
+    EVP_PKEY *key = NULL;
+    EVP_PKEY_CTX *kctx = EVP_PKEY_CTX_new_id(EVP_PKEY_RSA, NULL);
+    assert(kctx && EVP_PKEY_keygen_init(kctx) == 1 && EVP_PKEY_CTX_set_rsa_keygen_bits(kctx, 2048) == 1 && EVP_PKEY_keygen(kctx, &key) == 1);
+    EVP_PKEY_CTX_free(kctx);
+    BIGNUM *n = NULL;
+    assert(EVP_PKEY_get_bn_param(key, "n", &n) == 1);
+    unsigned char n_raw[256];
+    assert(BN_bn2bin(n, n_raw) == 256);
+    BN_free(n);
+    char n64[400] = {0};
+    base64url(n_raw, sizeof(n_raw), n64, sizeof(n64));
+
+    /* One body serves both the discovery document and the key set. */
+    snprintf(issuer, sizeof(issuer), "http://127.0.0.1:%d", mock.port);
+    snprintf(
+        jwks,
+        sizeof(jwks),
+        "{\"issuer\":\"%s\",\"jwks_uri\":\"%s/jwks\",\"keys\":[{\"kty\":\"EC\",\"kid\":\"ec\"},{\"kty\":\"RSA\",\"kid\":\"k1\",\"use\":\"sig\",\"n\":\"%s\",\"e\":\"AQAB\"}]}",
+        issuer,
+        issuer,
+        n64);
+    mock.reply_body = jwks;
+    setenv("FRICU_OIDC_ISSUER", issuer, 1);
+    setenv("FRICU_OIDC_AUDIENCE", "fricu-api", 1);
+    oidc_reset_cache();
+
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"auth0|rider-7\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    snprintf(
+        req,
+        sizeof(req),
+        "PUT /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer %s\r\nContent-Length: 12\r\n\r\n[{\"id\":\"o\"}]",
+        jwt);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "204 No Content") != NULL);
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"o\"}]") != NULL);
+    /* A claim outside [A-Za-z0-9._-] is hashed with the issuer rather than flattened. */
+    char account[64] = {0};
+    char storage_key[96] = {0};
+    oidc_hashed_account(issuer, "auth0|rider-7", account, sizeof(account));
+    snprintf(storage_key, sizeof(storage_key), "%s::activities", account);
+    sqlite3_stmt *stmt = NULL;
+    assert(sqlite3_prepare_v2(env.db.db, "SELECT COUNT(*) FROM kv_store WHERE data_key = ?1", -1, &stmt, NULL) == SQLITE_OK);
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    assert(sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) == 1);
+    sqlite3_finalize(stmt);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"auth0#rider-7\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"o\"}]") == NULL);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"auth0_rider-7\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL && strstr(resp, "[{\"id\":\"o\"}]") == NULL);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"%s\",\"exp\":%lld}", issuer, account, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "\"reason\":\"reserved account claim\"") != NULL);
+    assert(strcmp(mock.log, "GET /.well-known/openid-configuration 0 [] []\nGET /jwks 0 [] []\n") == 0);
+
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":[\"other\",\"fricu-api\"],\"sub\":\"r8\",\"nbf\":%lld,\"exp\":%lld}", issuer, now - 10, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"other\",\"sub\":\"r8\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "{\"error\":\"invalid bearer token\",\"reason\":\"wrong audience\"}") != NULL);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"https://evil.example\",\"aud\":\"fricu-api\",\"sub\":\"r8\",\"exp\":%lld}", now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"wrong issuer\"") != NULL);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"r8\",\"exp\":%lld}", issuer, now - 600);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"token expired\"") != NULL);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"r8\",\"nbf\":%lld,\"exp\":%lld}", issuer, now + 600, now + 900);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"token not yet valid\"") != NULL);
+
+    /* Signature, key id and algorithm are all checked. */
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"r8\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    char *sig = strrchr(jwt, '.') + 5;
+    *sig = *sig == 'A' ? 'B' : 'A';
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"bad signature\"") != NULL);
+    make_jwt(key, "RS256", "k2", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"unknown signing key\"") != NULL);
+    make_jwt(key, "RS512", "k1", claims, jwt, sizeof(jwt));
+    oidc_get(&env.db, jwt, resp, sizeof(resp));
+    assert(strstr(resp, "\"reason\":\"unsupported alg\"") != NULL);
+    oidc_get(&env.db, "not-a-jwt", resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL && strstr(resp, "{\"error\":\"invalid bearer token\"}") != NULL);
+    send_item_request(&env.db, "GET", "/v1/data/activities", NULL, resp, sizeof(resp));
+    assert(strstr(resp, "401 Unauthorized") != NULL);
+    assert(strcmp(mock.log, "GET /.well-known/openid-configuration 0 [] []\nGET /jwks 0 [] []\n") == 0);
+
+    setenv("FRICU_OIDC_ACCOUNT_CLAIM", "email", 1);
+    snprintf(claims, sizeof(claims), "{\"iss\":\"%s\",\"aud\":\"fricu-api\",\"sub\":\"r8\",\"email\":\"kim@club.example\",\"exp\":%lld}", issuer, now + 300);
+    make_jwt(key, "RS256", "k1", claims, jwt, sizeof(jwt));
+    oidc_hashed_account(issuer, "kim@club.example", account, sizeof(account));
+    snprintf(req, sizeof(req), "GET /v1/data/activities HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: %s\r\nAuthorization: Bearer %s\r\n\r\n", account, jwt);
+    run_request(&env.db, req, resp, sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+
+    unsetenv("FRICU_OIDC_ACCOUNT_CLAIM");
+    unsetenv("FRICU_OIDC_AUDIENCE");
+    unsetenv("FRICU_OIDC_ISSUER");
+    oidc_reset_cache();
+    EVP_PKEY_free(key);
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+    test_env_close(&env);
+}
+#endif
+
+static void test_otlp_traces_exported(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-otlp-XXXXXX");
+    char resp[16384] = {0};
+    mock_upload_server_t mock;
+    pthread_t thread;
+    mock_upload_server_start(&mock, &thread);
+    char endpoint[64] = {0};
+    snprintf(endpoint, sizeof(endpoint), "http://127.0.0.1:%d/", mock.port);
+
+    /* Off by default: nothing is queued, so a flush sends nothing. */
+    put_json(&env.db, "tester", "profile", "{\"notes\":\"traced\"}", resp, sizeof(resp));
+    setenv("FRICU_OTLP_ENDPOINT", endpoint, 1);
+    assert(otel_flush() == 0 && mock.log_len == 0);
+
+    setenv("FRICU_OTLP_HEADERS", "Authorization=Basic dGVtcG8=", 1);
+    setenv("FRICU_OTLP_SERVICE_NAME", "fricu-test", 1);
+    run_request(
+        &env.db,
+        "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n"
+        "traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(strstr(resp, "200 OK") != NULL);
+    /* A caller that did not sample the trace gets no span. */
+    run_request(
+        &env.db,
+        "GET /v1/data/profile HTTP/1.1\r\nHost: localhost\r\nX-Account-Id: tester\r\n"
+        "traceparent: 00-11111111111111111111111111111111-2222222222222222-00\r\n\r\n",
+        resp,
+        sizeof(resp));
+    assert(otel_flush() == 0);
+    /* One batch for both requests, with the configured header. */
+    assert(strncmp(mock.log, "POST /v1/traces ", 16) == 0 && strstr(mock.log, " [Basic dGVtcG8=] []\n") != NULL);
+    assert(strchr(mock.log, '\n') == mock.log + strlen(mock.log) - 1);
+    assert(strstr(mock.bodies, "\"key\":\"service.name\",\"value\":{\"stringValue\":\"fricu-test\"}") != NULL);
+    assert(strstr(mock.bodies, "{\"traceId\":\"4bf92f3577b34da6a3ce929d0e0e4736\",\"spanId\":\"") != NULL);
+    assert(strstr(mock.bodies, "\"parentSpanId\":\"00f067aa0ba902b7\",\"name\":\"GET /v1/data/profile\",\"kind\":2,") != NULL);
+    assert(strstr(mock.bodies, "\"name\":\"db.read profile\",\"kind\":1,") != NULL);
+    assert(strstr(mock.bodies, "{\"key\":\"fricu.data_key\",\"value\":{\"stringValue\":\"profile\"}}") != NULL);
+    assert(strstr(mock.bodies, "{\"key\":\"fricu.row_bytes\",\"value\":{\"intValue\":\"18\"}}") != NULL);
+    assert(strstr(mock.bodies, "{\"key\":\"http.response.status_code\",\"value\":{\"intValue\":\"200\"}}") != NULL);
+    assert(strstr(mock.bodies, "11111111111111111111111111111111") == NULL);
+
+    /* Without a traceparent the request is a new root; a write adds the dispatcher's span. */
+    unsetenv("FRICU_OTLP_HEADERS");
+    mock.bodies_len = 0;
+    mock.bodies[0] = '\0';
+    put_json(&env.db, "tester", "profile", "{\"notes\":\"again\"}", resp, sizeof(resp));
+    assert(otel_flush() == 0);
+    assert(strstr(mock.bodies, "\"name\":\"PUT /v1/data/profile\",\"kind\":2,") != NULL && strstr(mock.bodies, "parentSpanId\":\"00f0") == NULL);
+    assert(strstr(mock.bodies, "\"name\":\"db.write profile\",\"kind\":1,") != NULL);
+    assert(strstr(mock.bodies, "{\"key\":\"fricu.write_queue_ms\",\"value\":{\"doubleValue\":") != NULL);
+
+    /* New traces are sampled out at ratio 0. */
+    setenv("FRICU_OTLP_SAMPLE_RATIO", "0", 1);
+    size_t posts = mock.log_len;
+    put_json(&env.db, "tester", "profile", "{\"notes\":\"quiet\"}", resp, sizeof(resp));
+    assert(otel_flush() == 0 && mock.log_len == posts);
+    unsetenv("FRICU_OTLP_SAMPLE_RATIO");
+
+    unsetenv("FRICU_OTLP_SERVICE_NAME");
+    unsetenv("FRICU_OTLP_ENDPOINT");
+    mock.stop = 1;
+    pthread_join(thread, NULL);
+    close(mock.listen_fd);
+    test_env_close(&env);
+}
+
+#if defined(FRICU_HAVE_OPENSSL) && OPENSSL_VERSION_NUMBER >= 0x30000000L
+typedef struct {
+    int fd;
+    char resp[4096];
+} tls_test_client_t;
+
+static void *tls_test_client_main(void *arg) {
+    tls_test_client_t *client = (tls_test_client_t *)arg;
+    SSL_CTX *ctx = SSL_CTX_new(TLS_client_method());
+    SSL *ssl = ctx ? SSL_new(ctx) : NULL;
+    if (ssl && SSL_set_fd(ssl, client->fd) == 1 && SSL_connect(ssl) == 1) {
+        const char *req = "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        SSL_write(ssl, req, (int)strlen(req));
+        size_t off = 0;
+        int n = 0;
+        while (off + 1 < sizeof(client->resp) && (n = SSL_read(ssl, client->resp + off, (int)(sizeof(client->resp) - off - 1))) > 0) {
+            off += (size_t)n;
+        }
+        client->resp[off] = '\0';
+    }
+    SSL_free(ssl);
+    SSL_CTX_free(ctx);
+    close(client->fd);
+    return NULL;
+}
+
+static void test_tls_listener_serves_requests(void) {
+    test_env_t env;
+    test_env_open(&env, "/tmp/fricu-test-tls-XXXXXX");
+
+    /* Self-signed localhost certificate written the way an operator would provide it. */
+    EVP_PKEY *key = EVP_RSA_gen(2048);
+    X509 *cert = X509_new();
+    assert(key && cert);
+    ASN1_INTEGER_set(X509_get_serialNumber(cert), 1);
+    X509_gmtime_adj(X509_getm_notBefore(cert), 0);
+    X509_gmtime_adj(X509_getm_notAfter(cert), 3600);
+    X509_set_pubkey(cert, key);
+    X509_NAME_add_entry_by_txt(X509_get_subject_name(cert), "CN", MBSTRING_ASC, (const unsigned char *)"localhost", -1, -1, 0);
+    X509_set_issuer_name(cert, X509_get_subject_name(cert));
+    assert(X509_sign(cert, key, EVP_sha256()) > 0);
+    FILE *fp = fopen("cert.pem", "w");
+    assert(fp && PEM_write_X509(fp, cert) == 1);
+    fclose(fp);
+    fp = fopen("key.pem", "w");
+    assert(fp && PEM_write_PrivateKey(fp, key, NULL, NULL, 0, NULL, NULL) == 1);
+    fclose(fp);
+    X509_free(cert);
+    EVP_PKEY_free(key);
+
+    setenv("FRICU_TLS_CERT", "cert.pem", 1);
+    assert(tls_configure() != 0);
+    setenv("FRICU_TLS_KEY", "missing.pem", 1);
+    assert(tls_configure() != 0 && !tls_enabled());
+    setenv("FRICU_TLS_KEY", "key.pem", 1);
+    assert(tls_configure() == 0 && tls_enabled());
+
+    int fds[2] = {-1, -1};
+    assert(socketpair(AF_UNIX, SOCK_STREAM, 0, fds) == 0);
+    assert(set_nonblocking(fds[0]) == 0);
+    tls_test_client_t client = {.fd = fds[1]};
+    pthread_t thread;
+    assert(pthread_create(&thread, NULL, tls_test_client_main, &client) == 0);
+
+    /* The server side drives the handshake the way the worker loop does: read until a request parses. */
+    conn_t conn = {0};
+    conn.cap = REQ_BUF_SIZE;
+    conn.buf = (char *)malloc(conn.cap + 1);
+    conn.tls = tls_session_new(fds[0]);
+    assert(conn.buf && conn.tls);
+    tls_set_current(fds[0], conn.tls);
+    int done = 0;
+    for (int spins = 0; !done && spins < 5000; spins++) {
+        ssize_t r = tls_recv(conn.tls, conn.buf + conn.len, conn.cap - conn.len);
+        if (r > 0) {
+            conn.len += (size_t)r;
+            done = try_process_client(fds[0], &env.db, &conn) == 1;
+            continue;
+        }
+        assert(r < 0 && errno == EAGAIN);
+        usleep(1000);
+    }
+    assert(done);
+    tls_set_current(-1, NULL);
+    tls_session_free(conn.tls);
+    close(fds[0]);
+    free(conn.buf);
+    pthread_join(thread, NULL);
+    assert(strstr(client.resp, "HTTP/1.1 200 OK") != NULL);
+
+    unsetenv("FRICU_TLS_CERT");
+    unsetenv("FRICU_TLS_KEY");
+    assert(tls_configure() == 0 && !tls_enabled());
+    test_env_close(&env);
+}
+#endif
+
+int main(void) {
+    test_valid_key();
+    test_parse_bind_addr();
+    test_read_content_length();
+    test_socket_send_flags();
+    test_configure_socket_after_accept();
+    test_put_is_journaled_and_persisted();
+    test_missing_account_id_rejected();
+    test_write_queue_diagnostics_endpoint();
+    test_replay_pending_write_on_restart();
+    test_put_lock_is_queued_in_pending_writes();
+    test_query_param();
+    test_iso_day_round_trip();
+    test_training_risk_flags_load_spike();
+    test_training_week_rollover_and_start_day();
+    test_analytics_risk_endpoint_and_write_notification();
+    test_analytics_simulate_projects_taper();
+    test_analytics_compare_seasons();
+    test_wbal_depletes_and_recovers();
+    test_activity_wbal_endpoint_and_stored_metrics();
+    test_cp_fit_recovers_model();
+    test_analytics_cp_fit_endpoint();
+    test_vo2max_estimate_heuristics();
+    test_analytics_profile_vo2max_trend();
+    test_heart_rate_recovery_and_drift();
+    test_analytics_heart_endpoint();
+    test_today_workout_device_token();
+    test_workout_fit_export();
+    test_workout_timer();
+    test_archive_export();
+    test_archive_import();
+    test_brick_workout_compliance();
+    test_live_ingest_finalizes_activity();
+    test_sync_manifest_and_version_conflict();
+    test_version_check_fails_closed();
+    test_v2_items_share_v1_documents();
+    test_v1_items_mutate_single_records();
+    test_schema_registry_rejects_mistyped_fields();
+    test_secrets_load_from_files_and_stay_out_of_reports();
+    test_data_minimization_strips_samples_and_source_files();
+    test_users_scope_data_routes();
+    test_bearer_tokens_guard_api_routes();
+    test_coach_reads_athlete_data_under_bearer_auth();
+    test_event_sourcing_projection_matches_store();
+    test_analytics_reads_use_one_snapshot();
+    test_tls_redirect_location();
+    test_admin_index_advisor();
+    test_if_match_guards_data_writes();
+    test_response_guardrails_refuse_unbounded_reads();
+    test_conditional_get_honors_if_modified_since();
+    test_deadline_interrupts_stuck_requests();
+    test_clock_skew_warnings_are_counted_per_device();
+    test_sync_changes_since_timestamp();
+    test_sync_reconcile_from_full_manifest();
+    test_out_of_range_activities_are_quarantined();
+    test_websocket_pushes_change_events();
+    test_deprecated_endpoints_are_reported_per_client();
+    test_event_stream_resumes_from_last_event_id();
+    test_activities_filtered_and_projected();
+    test_admin_metrics_histograms_carry_exemplars();
+    test_responses_and_bodies_are_content_coded();
+    test_admin_cpu_profile_formats();
+    test_rate_limits_per_address_and_token();
+    test_exports_encrypt_with_passphrase();
+    test_request_bodies_limited_per_key();
+    test_backups_chain_and_restore();
+    test_snapshot_backup_and_pruning();
+    test_restore_swaps_database();
+    test_cors_for_browser_clients();
+    test_embedded_server_start_stop();
+    test_graceful_shutdown_drains_requests();
+    test_otlp_traces_exported();
+    test_service_units_rendered();
+    test_access_log_with_request_ids();
+    test_mdns_answers_service_queries();
+    test_pairing_codes_issue_device_tokens();
+    test_health_live_and_ready();
+#if defined(FRICU_HAVE_OPENSSL) && OPENSSL_VERSION_NUMBER >= 0x30000000L
+    test_oidc_tokens_authenticate_against_jwks();
+    test_tls_listener_serves_requests();
+#endif
+    test_admin_capture_ring_buffer();
+    test_admin_capture_redacts_secrets();
+    test_admin_stats_tracks_slow_endpoints();
+    test_debug_profiling_endpoints();
+    test_json_stream_validate();
+    test_large_put_uses_streaming_validation();
+    test_delta_upload_applies_json_patch();
+    test_cluster_lease_roles();
+    test_change_events_and_redis_bridge();
+    test_server_timing_on_debug_header();
+    test_attachments_upload_thumbnail_and_sharing();
+    test_attachment_blobs_move_out_of_database();
+    test_journal_entries_search_and_calendar();
+    test_season_report_html_and_pdf();
+    test_anonymized_export_requires_opt_in();
+    test_coach_locks_reject_athlete_writes();
+    test_coach_tokens_are_stored_hashed();
+    test_data_key_snapshots_and_diff();
+    test_data_as_of_reads_history();
+    test_goldencheetah_and_wger_imports();
+    test_export_connectors_upload_and_retry();
+    test_connectors_refuse_private_targets();
+    test_email_inbox_imports_fit_and_tcx_attachments();
+    test_fit_upload_imports_activities();
+    test_import_preview_reports_without_persisting();
+    test_diary_csv_import();
+    test_chat_bots_answer_commands_and_push();
+    test_assist_briefing_combines_plan_form_and_weather();
+    test_admin_validate_reports_and_fixes();
+    test_activity_batch_delete_and_archive();
+    test_import_runs_history_and_rollback();
+    test_profile_sport_sections_drive_import_tss();
+    test_i18n_notifications_and_reports_follow_accept_language();
+    test_load_models_computed_at_import_and_selectable_in_analytics();
+    test_activity_feedback_feeds_srpe_and_readiness();
+    test_cycle_tracking_infers_phase_for_readiness_and_plans();
+    test_travel_log_corrects_power_recorded_at_altitude();
+    test_gear_calibration_log_explains_power_jumps();
+    test_coach_compare_overlays_granted_athletes();
+    test_plan_template_round_trips_between_accounts();
+    test_plan_template_scales_targets_to_current_fitness();
     puts("unit tests passed");
     return 0;
 }
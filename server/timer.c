@@ -0,0 +1,518 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * GET /v1/workouts/<id>/timer: a planned workout flattened for a plain interval timer on a phone or
+ * watch, which then needs no workout model of its own. Repeat groups are unrolled, so every step
+ * has absolute start_sec / end_sec offsets, a kind (warmup, work, recovery, cooldown, classified as
+ * in the FIT export), the segment's sport, its target resolved to watts, pace or heart rate from the
+ * athlete's thresholds, and countdown_sec, the seconds at which to beep before it ends
+ * (?countdown=0..10, default 3). "cues" lists everything to play in time order, each with a sound
+ * name the app maps to its own asset (long_beep at a step start, chime at halfway for steps of five
+ * minutes or more, beep for the countdown, finish at the end; null for none), display text, and a
+ * speech string for text-to-speech, with "Next: ..." ten seconds before a step of 30 seconds or more
+ * ends. Text is in the reader's language (?lang=, Accept-Language, the profile's language).
+ */
+
+#define TIMER_MAX_SEGMENTS 200
+#define TIMER_MAX_STEPS 500
+#define TIMER_MAX_REPEAT 99
+#define TIMER_DEFAULT_COUNTDOWN 3
+#define TIMER_MAX_COUNTDOWN 10
+#define TIMER_PREVIEW_SEC 10
+#define TIMER_PREVIEW_MIN_STEP_SEC 30
+#define TIMER_HALFWAY_MIN_STEP_SEC 300
+
+typedef struct {
+    int duration_sec;
+    char sport[32];
+    double percent;
+    double watts;
+    double pace_km;
+    double pace_100m;
+    double heart_rate;
+    int cadence;
+    char note[128];
+    int group;
+    int repeat;
+} timer_segment_t;
+
+typedef struct {
+    const timer_segment_t *segment;
+    int start_sec;
+    const char *kind;
+    int round;
+    int rounds;
+    /* Resolved target; type NULL for an open step. */
+    const char *target_type;
+    double percent;
+    double watts;
+    double sec_per_km;
+    double sec_per_100m;
+    double bpm;
+    char text[256];
+    char speech[384];
+} timer_step_t;
+
+/* Reads segments_json into segments, one row per segment with its repeat group; the count, or -status with *error set. */
+static int load_segments(sqlite3 *db, const char *segments_json, const char *workout_sport, timer_segment_t *segments, const char **error) {
+    sqlite3_stmt *stmt = NULL;
+    const char *sql =
+        "SELECT o.key, i.key IS NOT NULL, COALESCE(json_extract(o.value, '$.repeat'), 1),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.sport'), json_extract(o.value, '$.sport')),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.minutes'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.intensityPercentFTP'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.targetWatts'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.targetPaceSecPerKm'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.targetPaceSecPer100m'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.targetHeartRate'), 0),"
+        " COALESCE(json_extract(COALESCE(i.value, o.value), '$.cadence'), 0),"
+        " json_extract(COALESCE(i.value, o.value), '$.note'),"
+        " i.key IS NOT NULL AND json_type(i.value, '$.segments') = 'array'"
+        " FROM json_each(?1) o"
+        " LEFT JOIN json_each(CASE WHEN json_type(o.value, '$.segments') = 'array' THEN json_extract(o.value, '$.segments') ELSE '[]' END) i"
+        " WHERE json_type(o.value, '$.segments') IS NOT 'array' OR i.key IS NOT NULL"
+        " ORDER BY o.key, i.key";
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return -500;
+    sqlite3_bind_text(stmt, 1, segments_json, -1, SQLITE_TRANSIENT);
+    int count = 0;
+    int status = 0;
+    while (status == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        int grouped = sqlite3_column_int(stmt, 1);
+        int repeat = grouped ? sqlite3_column_int(stmt, 2) : 1;
+        if (sqlite3_column_int(stmt, 12)) {
+            *error = "{\"error\":\"repeat groups cannot be nested\"}";
+            status = 400;
+        } else if (repeat < 1 || repeat > TIMER_MAX_REPEAT) {
+            *error = "{\"error\":\"repeat must be 1..99\"}";
+            status = 400;
+        }
+        int duration = (int)lround(sqlite3_column_double(stmt, 4) * 60.0);
+        if (status != 0 || duration <= 0) continue;
+        if (count >= TIMER_MAX_SEGMENTS) {
+            *error = "{\"error\":\"workout has more than 200 segments\"}";
+            status = 400;
+            break;
+        }
+        timer_segment_t *s = &segments[count++];
+        memset(s, 0, sizeof(*s));
+        s->duration_sec = duration;
+        const char *sport = sport_canonical_name((const char *)sqlite3_column_text(stmt, 3));
+        snprintf(s->sport, sizeof(s->sport), "%s", sport ? sport : workout_sport);
+        s->percent = sqlite3_column_double(stmt, 5);
+        s->watts = sqlite3_column_double(stmt, 6);
+        s->pace_km = sqlite3_column_double(stmt, 7);
+        s->pace_100m = sqlite3_column_double(stmt, 8);
+        s->heart_rate = sqlite3_column_double(stmt, 9);
+        int cadence = sqlite3_column_int(stmt, 10);
+        s->cadence = cadence > 0 && cadence < 250 ? cadence : 0;
+        const char *note = (const char *)sqlite3_column_text(stmt, 11);
+        snprintf(s->note, sizeof(s->note), "%s", note ? note : "");
+        s->group = grouped ? sqlite3_column_int(stmt, 0) : -1;
+        s->repeat = repeat;
+    }
+    sqlite3_finalize(stmt);
+    return status != 0 ? -status : count;
+}
+
+/* The same precedence as the FIT export: heart rate, explicit pace, pace from %threshold, then power. */
+static void resolve_target(timer_step_t *step, const sport_settings_t *settings, double ftp) {
+    const timer_segment_t *s = step->segment;
+    int running = strcmp(s->sport, "running") == 0;
+    int swimming = strcmp(s->sport, "swimming") == 0;
+    if (s->heart_rate > 0.0) {
+        step->target_type = "heart_rate";
+        step->bpm = round(s->heart_rate);
+    } else if (s->pace_km > 0.0 && !swimming) {
+        step->target_type = "pace";
+        step->sec_per_km = round(s->pace_km);
+    } else if (s->pace_100m > 0.0 && swimming) {
+        step->target_type = "pace";
+        step->sec_per_100m = round(s->pace_100m);
+    } else if (running && s->percent > 0.0 && settings->threshold_pace_sec_per_km > 0.0) {
+        step->target_type = "pace";
+        step->sec_per_km = round(settings->threshold_pace_sec_per_km * 100.0 / s->percent);
+    } else if (swimming && s->percent > 0.0 && settings->css_sec_per_100m > 0.0) {
+        step->target_type = "pace";
+        step->sec_per_100m = round(settings->css_sec_per_100m * 100.0 / s->percent);
+    } else if (!running && !swimming && strcmp(s->sport, "strength") != 0 && s->percent > 0.0) {
+        step->target_type = "power";
+        step->percent = round(s->percent);
+        step->watts = ftp > 0.0 ? round(ftp * s->percent / 100.0) : 0.0;
+    } else if (!running && !swimming && s->watts > 0.0) {
+        step->target_type = "power";
+        step->watts = round(s->watts);
+    }
+}
+
+static const char *step_kind(const timer_step_t *steps, int count, int i) {
+    const timer_step_t *step = &steps[i];
+    double percent = step->segment->percent;
+    int easy = percent > 0.0 && percent <= 75.0;
+    if (step->rounds == 1 && easy && i == 0 && count > 1) return "warmup";
+    if (step->rounds == 1 && easy && i == count - 1 && count > 1) return "cooldown";
+    if (percent > 0.0 && percent < 60.0) return "recovery";
+    return "work";
+}
+
+/* 90 -> "1:30"; 3725 -> "1:02:05". */
+static void format_clock(int sec, char *out, size_t out_len) {
+    if (sec >= 3600) {
+        snprintf(out, out_len, "%d:%02d:%02d", sec / 3600, sec / 60 % 60, sec % 60);
+    } else {
+        snprintf(out, out_len, "%d:%02d", sec / 60, sec % 60);
+    }
+}
+
+static void speak_duration(const char *lang, int sec, strbuf_t *out) {
+    char minutes[16] = {0};
+    char seconds[16] = {0};
+    snprintf(minutes, sizeof(minutes), "%d", sec / 60);
+    snprintf(seconds, sizeof(seconds), "%d", sec % 60);
+    i18n_arg_t args[] = {{"minutes", minutes}, {"seconds", seconds}};
+    const char *key = "timer.minutes_seconds";
+    if (sec < 60) {
+        key = "timer.seconds";
+    } else if (sec % 60 == 0) {
+        key = sec == 60 ? "timer.one_minute" : "timer.minutes";
+    }
+    i18n_format(lang, key, args, 2, out);
+}
+
+/* Display text and speech for the target (and cadence); nothing for an open step. */
+static void describe_target(const char *lang, const timer_step_t *step, strbuf_t *text, strbuf_t *speech) {
+    char value[32] = {0};
+    char percent[16] = {0};
+    i18n_arg_t args[3] = {{"value", value}, {"percent", percent}, {"cadence", ""}};
+    const char *type = step->target_type;
+    if (type && strcmp(type, "heart_rate") == 0) {
+        snprintf(value, sizeof(value), "%.0f", step->bpm);
+        strbuf_appendf(text, "%s bpm", value);
+        i18n_format(lang, "timer.at_heart_rate", args, 3, speech);
+    } else if (type && strcmp(type, "pace") == 0) {
+        int swim = step->sec_per_100m > 0.0;
+        format_clock((int)(swim ? step->sec_per_100m : step->sec_per_km), value, sizeof(value));
+        strbuf_appendf(text, "%s%s", value, swim ? "/100m" : "/km");
+        i18n_format(lang, swim ? "timer.at_swim_pace" : "timer.at_pace", args, 3, speech);
+    } else if (type) {
+        snprintf(value, sizeof(value), "%.0f", step->watts);
+        snprintf(percent, sizeof(percent), "%.0f", step->percent);
+        if (step->percent > 0.0 && step->watts > 0.0) {
+            strbuf_appendf(text, "%s W (%s%% FTP)", value, percent);
+        } else if (step->percent > 0.0) {
+            strbuf_appendf(text, "%s%% FTP", percent);
+        } else {
+            strbuf_appendf(text, "%s W", value);
+        }
+        i18n_format(lang, step->watts > 0.0 ? "timer.at_power" : "timer.at_percent", args, 3, speech);
+    }
+    if (step->segment->cadence > 0) {
+        char cadence[16] = {0};
+        snprintf(cadence, sizeof(cadence), "%d", step->segment->cadence);
+        args[2].value = cadence;
+        strbuf_appendf(text, "%s%s rpm", text->len > 0 ? " · " : "", cadence);
+        /* Chinese runs the phrases together with a full-width comma. */
+        const char *separator = strcmp(lang, "zh") == 0 ? "，" : ", ";
+        if (speech->len > 0) strbuf_append(speech, separator, strlen(separator));
+        i18n_format(lang, "timer.at_cadence", args, 3, speech);
+    }
+}
+
+static void describe_step(const char *lang, timer_step_t *step) {
+    strbuf_t label;
+    strbuf_t target_text;
+    strbuf_t target_speech;
+    strbuf_t duration;
+    strbuf_t speech;
+    strbuf_init(&label);
+    strbuf_init(&target_text);
+    strbuf_init(&target_speech);
+    strbuf_init(&duration);
+    strbuf_init(&speech);
+    char round[16] = {0};
+    char rounds[16] = {0};
+    snprintf(round, sizeof(round), "%d", step->round);
+    snprintf(rounds, sizeof(rounds), "%d", step->rounds);
+    char kind_key[32] = {0};
+    snprintf(kind_key, sizeof(kind_key), "timer.%s", step->kind);
+    i18n_format(lang, kind_key, NULL, 0, &label);
+    char clock[16] = {0};
+    format_clock(step->segment->duration_sec, clock, sizeof(clock));
+    describe_target(lang, step, &target_text, &target_speech);
+    speak_duration(lang, step->segment->duration_sec, &duration);
+
+    strbuf_t text;
+    strbuf_init(&text);
+    strbuf_append(&text, strbuf_cstr(&label), label.len);
+    if (step->rounds > 1) strbuf_appendf(&text, " %s/%s", round, rounds);
+    strbuf_appendf(&text, " · %s", clock);
+    if (target_text.len > 0) strbuf_appendf(&text, " · %s", strbuf_cstr(&target_text));
+    snprintf(step->text, sizeof(step->text), "%s", text.failed ? "" : strbuf_cstr(&text));
+
+    if (step->rounds > 1) {
+        i18n_arg_t round_args[] = {{"label", strbuf_cstr(&label)}, {"round", round}, {"rounds", rounds}};
+        strbuf_t numbered;
+        strbuf_init(&numbered);
+        i18n_format(lang, "timer.round", round_args, 3, &numbered);
+        strbuf_free(&label);
+        label = numbered;
+    }
+    i18n_arg_t args[] = {{"label", strbuf_cstr(&label)}, {"duration", strbuf_cstr(&duration)}, {"target", strbuf_cstr(&target_speech)}};
+    i18n_format(lang, target_speech.len > 0 ? "timer.step" : "timer.step_open", args, 3, &speech);
+    snprintf(step->speech, sizeof(step->speech), "%s", speech.failed ? "" : strbuf_cstr(&speech));
+    strbuf_free(&label);
+    strbuf_free(&target_text);
+    strbuf_free(&target_speech);
+    strbuf_free(&duration);
+    strbuf_free(&speech);
+    strbuf_free(&text);
+}
+
+static void append_cue(strbuf_t *sb, int *count, int at_sec, int step, const char *type, const char *sound, const char *text, const char *speech) {
+    strbuf_appendf(sb, "%s{\"at_sec\":%d,\"step\":%d,\"type\":\"%s\",\"sound\":", *count > 0 ? "," : "", at_sec, step, type);
+    if (sound) {
+        strbuf_appendf(sb, "\"%s\"", sound);
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+    strbuf_append(sb, ",\"text\":", 8);
+    strbuf_append_json_string(sb, text);
+    strbuf_append(sb, ",\"speech\":", 10);
+    if (speech) {
+        strbuf_append_json_string(sb, speech);
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+    strbuf_append(sb, "}", 1);
+    (*count)++;
+}
+
+static void append_step(strbuf_t *sb, const timer_step_t *step, int index, int countdown) {
+    const timer_segment_t *s = step->segment;
+    int end = step->start_sec + s->duration_sec;
+    strbuf_appendf(
+        sb,
+        "%s{\"index\":%d,\"start_sec\":%d,\"end_sec\":%d,\"duration_sec\":%d,\"sport\":\"%s\",\"kind\":\"%s\",",
+        index > 0 ? "," : "",
+        index,
+        step->start_sec,
+        end,
+        s->duration_sec,
+        s->sport,
+        step->kind);
+    if (step->rounds > 1) strbuf_appendf(sb, "\"round\":%d,\"rounds\":%d,", step->round, step->rounds);
+    strbuf_append(sb, "\"target\":", 9);
+    if (!step->target_type) {
+        strbuf_append(sb, "null", 4);
+    } else if (strcmp(step->target_type, "heart_rate") == 0) {
+        strbuf_appendf(sb, "{\"type\":\"heart_rate\",\"bpm\":%.0f,\"low_bpm\":%.0f,\"high_bpm\":%.0f}", step->bpm, step->bpm - 5.0, step->bpm + 5.0);
+    } else if (strcmp(step->target_type, "pace") == 0 && step->sec_per_100m > 0.0) {
+        strbuf_appendf(sb, "{\"type\":\"pace\",\"sec_per_100m\":%.0f}", step->sec_per_100m);
+    } else if (strcmp(step->target_type, "pace") == 0) {
+        strbuf_appendf(sb, "{\"type\":\"pace\",\"sec_per_km\":%.0f}", step->sec_per_km);
+    } else {
+        strbuf_append(sb, "{\"type\":\"power\"", 15);
+        if (step->percent > 0.0) strbuf_appendf(sb, ",\"percent_ftp\":%.0f", step->percent);
+        if (step->watts > 0.0) strbuf_appendf(sb, ",\"watts\":%.0f", step->watts);
+        strbuf_append(sb, "}", 1);
+    }
+    if (s->cadence > 0) strbuf_appendf(sb, ",\"cadence\":%d", s->cadence);
+    strbuf_append(sb, ",\"note\":", 8);
+    if (s->note[0] != '\0') {
+        strbuf_append_json_string(sb, s->note);
+    } else {
+        strbuf_append(sb, "null", 4);
+    }
+    strbuf_append(sb, ",\"text\":", 8);
+    strbuf_append_json_string(sb, step->text);
+    strbuf_append(sb, ",\"speech\":", 10);
+    strbuf_append_json_string(sb, step->speech);
+    strbuf_append(sb, ",\"countdown_sec\":[", 18);
+    int first = end - countdown > step->start_sec ? end - countdown : step->start_sec + 1;
+    for (int t = first; t < end; t++) strbuf_appendf(sb, "%s%d", t > first ? "," : "", t);
+    strbuf_append(sb, "]}", 2);
+}
+
+static void append_cues(strbuf_t *sb, const char *lang, const timer_step_t *steps, int count, int countdown) {
+    int cues = 0;
+    for (int i = 0; i < count; i++) {
+        const timer_step_t *step = &steps[i];
+        int duration = step->segment->duration_sec;
+        int end = step->start_sec + duration;
+        append_cue(sb, &cues, step->start_sec, i, "start", "long_beep", step->text, step->speech);
+        if (duration >= TIMER_HALFWAY_MIN_STEP_SEC) {
+            const char *halfway = i18n_text(lang, "timer.halfway");
+            append_cue(sb, &cues, step->start_sec + duration / 2, i, "halfway", "chime", halfway, halfway);
+        }
+        if (i + 1 < count && duration >= TIMER_PREVIEW_MIN_STEP_SEC) {
+            strbuf_t text;
+            strbuf_t speech;
+            strbuf_init(&text);
+            strbuf_init(&speech);
+            i18n_arg_t text_args[] = {{"step", steps[i + 1].text}};
+            i18n_arg_t speech_args[] = {{"step", steps[i + 1].speech}};
+            i18n_format(lang, "timer.next", text_args, 1, &text);
+            i18n_format(lang, "timer.next", speech_args, 1, &speech);
+            append_cue(sb, &cues, end - TIMER_PREVIEW_SEC, i, "next", NULL, strbuf_cstr(&text), strbuf_cstr(&speech));
+            strbuf_free(&text);
+            strbuf_free(&speech);
+        }
+        int first = end - countdown > step->start_sec ? end - countdown : step->start_sec + 1;
+        for (int t = first; t < end; t++) {
+            char number[16] = {0};
+            snprintf(number, sizeof(number), "%d", end - t);
+            append_cue(sb, &cues, t, i, "countdown", "beep", number, NULL);
+        }
+    }
+    int total = count > 0 ? steps[count - 1].start_sec + steps[count - 1].segment->duration_sec : 0;
+    const char *done = i18n_text(lang, "timer.done");
+    append_cue(sb, &cues, total, count - 1, "finish", "finish", done, done);
+}
+
+int handle_get_workout_timer(int fd, worker_db_t *db, const http_request_t *req, const char *workout_id, const request_log_context_t *ctx) {
+    int countdown = TIMER_DEFAULT_COUNTDOWN;
+    char raw[16] = {0};
+    if (query_param(req->query, "countdown", raw, sizeof(raw))) {
+        char *end = NULL;
+        long parsed = strtol(raw, &end, 10);
+        if (raw[0] == '\0' || *end != '\0' || parsed < 0 || parsed > TIMER_MAX_COUNTDOWN) {
+            send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"countdown must be 0..10\"}", ctx);
+            return 400;
+        }
+        countdown = (int)parsed;
+    }
+    if (!is_valid_item_id(workout_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid workout id\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "workouts", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT COALESCE(json_extract(w.value, '$.name'), 'Workout'), COALESCE(json_extract(w.value, '$.sport'), 'cycling'),"
+            " COALESCE(json_extract(w.value, '$.segments'), '[]') FROM kv_store k, json_each(k.data_value) w"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND CAST(json_extract(w.value, '$.id') AS TEXT) = ?2 LIMIT 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, workout_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown workout\"}", ctx);
+        return 404;
+    }
+    char name[128] = {0};
+    char sport[32] = {0};
+    snprintf(name, sizeof(name), "%s", (const char *)sqlite3_column_text(stmt, 0));
+    const char *canonical = sport_canonical_name((const char *)sqlite3_column_text(stmt, 1));
+    snprintf(sport, sizeof(sport), "%s", canonical ? canonical : "cycling");
+    char *segments_json = strdup((const char *)sqlite3_column_text(stmt, 2));
+    sqlite3_finalize(stmt);
+
+    timer_segment_t *segments = (timer_segment_t *)calloc(TIMER_MAX_SEGMENTS, sizeof(timer_segment_t));
+    timer_step_t *steps = (timer_step_t *)calloc(TIMER_MAX_STEPS, sizeof(timer_step_t));
+    if (!segments_json || !segments || !steps) {
+        free(segments_json);
+        free(segments);
+        free(steps);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    const char *error = NULL;
+    int segment_count = load_segments(db->db, segments_json, sport, segments, &error);
+    free(segments_json);
+
+    /* Unroll: a group's segments are consecutive rows sharing its index. */
+    int count = 0;
+    int clock = 0;
+    for (int i = 0; segment_count > 0 && i < segment_count;) {
+        int j = i + 1;
+        while (segments[i].group >= 0 && j < segment_count && segments[j].group == segments[i].group) j++;
+        for (int round = 1; round <= segments[i].repeat && segment_count > 0; round++) {
+            for (int k = i; k < j; k++) {
+                if (count >= TIMER_MAX_STEPS) {
+                    error = "{\"error\":\"workout has more than 500 steps once repeats are unrolled\"}";
+                    segment_count = -400;
+                    break;
+                }
+                timer_step_t *step = &steps[count++];
+                step->segment = &segments[k];
+                step->start_sec = clock;
+                step->round = round;
+                step->rounds = segments[i].repeat;
+                clock += segments[k].duration_sec;
+            }
+        }
+        i = j;
+    }
+    if (segment_count == 0) {
+        error = "{\"error\":\"workout has no timed segments\"}";
+        segment_count = -400;
+    }
+    if (segment_count < 0) {
+        free(segments);
+        free(steps);
+        int status = -segment_count;
+        if (status == 400) {
+            send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        } else {
+            send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        }
+        return status;
+    }
+
+    const char *lang = i18n_negotiate(db->db, req, ctx->account_id);
+    double profile_ftp = load_profile_ftp(db->db, ctx->account_id);
+    sport_settings_t settings;
+    char settings_sport[32] = {0};
+    for (int i = 0; i < count; i++) {
+        if (strcmp(settings_sport, steps[i].segment->sport) != 0) {
+            snprintf(settings_sport, sizeof(settings_sport), "%s", steps[i].segment->sport);
+            load_sport_settings(db->db, ctx->account_id, settings_sport, &settings);
+        }
+        resolve_target(&steps[i], &settings, settings.ftp_watts > 0.0 ? settings.ftp_watts : profile_ftp);
+        steps[i].kind = step_kind(steps, count, i);
+        describe_step(lang, &steps[i]);
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"workout_id\":", 14);
+    strbuf_append_json_string(&sb, workout_id);
+    strbuf_append(&sb, ",\"name\":", 8);
+    strbuf_append_json_string(&sb, name);
+    strbuf_appendf(&sb, ",\"sport\":\"%s\",\"lang\":\"%s\",\"total_sec\":%d,\"countdown\":%d,\"steps\":[", sport, lang, clock, countdown);
+    for (int i = 0; i < count; i++) append_step(&sb, &steps[i], i, countdown);
+    strbuf_append(&sb, "],\"cues\":[", 10);
+    append_cues(&sb, lang, steps, count, countdown);
+    strbuf_append(&sb, "]}", 2);
+    free(segments);
+    free(steps);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    log_info("WORKOUT timer id=%s steps=%d total_sec=%d account=%s logid=%s", workout_id, count, clock, ctx->account_id, ctx->log_id);
+    strbuf_free(&sb);
+    return 200;
+}
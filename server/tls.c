@@ -0,0 +1,282 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netinet/in.h>
+#include <pthread.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/time.h>
+#include <unistd.h>
+
+#ifdef FRICU_HAVE_OPENSSL
+#include <openssl/err.h>
+#include <openssl/ssl.h>
+#endif
+
+/*
+ * Optional TLS termination: with FRICU_TLS_CERT (PEM chain) and FRICU_TLS_KEY (PEM private key)
+ * set, every connection on FRICU_SERVER_BIND is TLS. The worker that owns a connection keeps its
+ * session in conn_t and marks it current while it handles the connection, so send_all encrypts
+ * without every response helper having to carry the session. FRICU_TLS_REDIRECT_BIND starts a
+ * plain-HTTP listener that answers every request with a 308 to the same path over https.
+ */
+
+#define TLS_REDIRECT_REQUEST_MAX 8192
+
+#ifdef FRICU_HAVE_OPENSSL
+static SSL_CTX *g_tls_ctx;
+static __thread int g_current_fd = -1;
+static __thread SSL *g_current_ssl;
+#endif
+
+static int g_redirect_https_port = 443;
+
+int tls_configure(void) {
+    const char *cert = getenv("FRICU_TLS_CERT");
+    const char *key = getenv("FRICU_TLS_KEY");
+    int has_cert = cert && cert[0] != '\0';
+    int has_key = key && key[0] != '\0';
+#ifdef FRICU_HAVE_OPENSSL
+    if (g_tls_ctx) {
+        SSL_CTX_free(g_tls_ctx);
+        g_tls_ctx = NULL;
+    }
+#endif
+    if (!has_cert && !has_key) return 0;
+    if (!has_cert || !has_key) {
+        log_error("FRICU_TLS_CERT and FRICU_TLS_KEY must be set together");
+        return -1;
+    }
+#ifdef FRICU_HAVE_OPENSSL
+    SSL_CTX *ctx = SSL_CTX_new(TLS_server_method());
+    if (!ctx) return -1;
+    SSL_CTX_set_min_proto_version(ctx, TLS1_2_VERSION);
+    SSL_CTX_set_mode(ctx, SSL_MODE_ENABLE_PARTIAL_WRITE | SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER);
+    if (SSL_CTX_use_certificate_chain_file(ctx, cert) != 1) {
+        log_error("cannot load FRICU_TLS_CERT %s: %s", cert, ERR_reason_error_string(ERR_get_error()));
+        SSL_CTX_free(ctx);
+        return -1;
+    }
+    if (SSL_CTX_use_PrivateKey_file(ctx, key, SSL_FILETYPE_PEM) != 1 || SSL_CTX_check_private_key(ctx) != 1) {
+        log_error("cannot load FRICU_TLS_KEY %s: %s", key, ERR_reason_error_string(ERR_get_error()));
+        SSL_CTX_free(ctx);
+        return -1;
+    }
+    g_tls_ctx = ctx;
+    return 0;
+#else
+    log_error("FRICU_TLS_CERT is set but this build has no OpenSSL; rebuild with FRICU_TLS enabled");
+    return -1;
+#endif
+}
+
+int tls_enabled(void) {
+#ifdef FRICU_HAVE_OPENSSL
+    return g_tls_ctx != NULL;
+#else
+    return 0;
+#endif
+}
+
+void *tls_session_new(int fd) {
+#ifdef FRICU_HAVE_OPENSSL
+    SSL *ssl = g_tls_ctx ? SSL_new(g_tls_ctx) : NULL;
+    if (!ssl) return NULL;
+    if (SSL_set_fd(ssl, fd) != 1) {
+        SSL_free(ssl);
+        return NULL;
+    }
+    SSL_set_accept_state(ssl);
+    return ssl;
+#else
+    (void)fd;
+    return NULL;
+#endif
+}
+
+void tls_session_free(void *session) {
+#ifdef FRICU_HAVE_OPENSSL
+    SSL *ssl = (SSL *)session;
+    if (!ssl) return;
+    if (SSL_is_init_finished(ssl)) SSL_shutdown(ssl);
+    SSL_free(ssl);
+    ERR_clear_error();
+#else
+    (void)session;
+#endif
+}
+
+/* Same contract as recv: >0 bytes, 0 on close, -1 with errno (EAGAIN while the handshake waits). */
+ssize_t tls_recv(void *session, char *buf, size_t len) {
+#ifdef FRICU_HAVE_OPENSSL
+    SSL *ssl = (SSL *)session;
+    int n = SSL_read(ssl, buf, len > 1 << 20 ? 1 << 20 : (int)len);
+    if (n > 0) return n;
+    int err = SSL_get_error(ssl, n);
+    ERR_clear_error();
+    if (err == SSL_ERROR_WANT_READ || err == SSL_ERROR_WANT_WRITE) {
+        errno = EAGAIN;
+        return -1;
+    }
+    if (err == SSL_ERROR_ZERO_RETURN) return 0;
+    if (err != SSL_ERROR_SYSCALL || errno == 0) errno = EPROTO;
+    return -1;
+#else
+    (void)session;
+    (void)buf;
+    (void)len;
+    errno = ENOTSUP;
+    return -1;
+#endif
+}
+
+void tls_set_current(int fd, void *session) {
+#ifdef FRICU_HAVE_OPENSSL
+    g_current_fd = session ? fd : -1;
+    g_current_ssl = (SSL *)session;
+#else
+    (void)fd;
+    (void)session;
+#endif
+}
+
+ssize_t tls_send(int fd, const char *buf, size_t len) {
+#ifdef FRICU_HAVE_OPENSSL
+    if (g_current_ssl && fd == g_current_fd) {
+        int n = SSL_write(g_current_ssl, buf, len > 1 << 20 ? 1 << 20 : (int)len);
+        if (n > 0) return n;
+        int err = SSL_get_error(g_current_ssl, n);
+        ERR_clear_error();
+        errno = err == SSL_ERROR_WANT_READ || err == SSL_ERROR_WANT_WRITE ? EAGAIN : EPIPE;
+        return -1;
+    }
+#endif
+    return send(fd, buf, len, socket_send_flags());
+}
+
+static int redirect_host_char(char c) {
+    return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9') || c == '.' || c == '-' || c == '[' ||
+           c == ']' || c == ':';
+}
+
+int tls_redirect_location(const char *request, int https_port, char *out, size_t out_len) {
+    const char *target = strchr(request, ' ');
+    if (!target || target[1] != '/') return -1;
+    target++;
+    size_t target_len = strcspn(target, " \r\n");
+    for (size_t i = 0; i < target_len; i++) {
+        if ((unsigned char)target[i] <= 0x20 || target[i] == 0x7f) return -1;
+    }
+
+    const char *host = strcasestr(request, "\r\nHost:");
+    if (!host) return -1;
+    host += 7;
+    host += strspn(host, " \t");
+    size_t host_len = strcspn(host, "\r\n \t");
+    if (host_len == 0 || host_len > 255) return -1;
+    for (size_t i = 0; i < host_len; i++) {
+        if (!redirect_host_char(host[i])) return -1;
+    }
+    /* Drop the plain-HTTP port; keep IPv6 literals intact. */
+    const char *bracket = memchr(host, ']', host_len);
+    const char *colon = NULL;
+    for (size_t i = bracket ? (size_t)(bracket - host) : 0; i < host_len; i++) {
+        if (host[i] == ':') colon = host + i;
+    }
+    if (colon) host_len = (size_t)(colon - host);
+
+    char port[16] = {0};
+    if (https_port != 443) snprintf(port, sizeof(port), ":%d", https_port);
+    int n = snprintf(out, out_len, "https://%.*s%s%.*s", (int)host_len, host, port, (int)target_len, target);
+    return n > 0 && (size_t)n < out_len ? 0 : -1;
+}
+
+static void serve_redirect(int fd) {
+    struct timeval timeout = {.tv_sec = 5, .tv_usec = 0};
+    setsockopt(fd, SOL_SOCKET, SO_RCVTIMEO, &timeout, sizeof(timeout));
+    char request[TLS_REDIRECT_REQUEST_MAX + 1];
+    size_t len = 0;
+    while (len < TLS_REDIRECT_REQUEST_MAX) {
+        ssize_t n = recv(fd, request + len, TLS_REDIRECT_REQUEST_MAX - len, 0);
+        if (n <= 0) break;
+        len += (size_t)n;
+        request[len] = '\0';
+        if (strstr(request, "\r\n\r\n")) break;
+    }
+    request[len] = '\0';
+
+    char location[TLS_REDIRECT_REQUEST_MAX];
+    char header[TLS_REDIRECT_REQUEST_MAX + 256];
+    int n = 0;
+    if (tls_redirect_location(request, g_redirect_https_port, location, sizeof(location)) == 0) {
+        n = snprintf(
+            header,
+            sizeof(header),
+            "HTTP/1.1 308 Permanent Redirect\r\nLocation: %s\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location);
+    } else {
+        n = snprintf(
+            header,
+            sizeof(header),
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\nContent-Length: 36\r\nConnection: close\r\n\r\n"
+            "{\"error\":\"this port only redirects\"}");
+    }
+    if (n > 0 && (size_t)n < sizeof(header)) send(fd, header, (size_t)n, socket_send_flags());
+    close(fd);
+}
+
+static void *redirect_main(void *arg) {
+    int listen_fd = (int)(intptr_t)arg;
+    while (1) {
+        int fd = accept(listen_fd, NULL, NULL);
+        if (fd < 0) {
+            if (errno != EINTR) log_warn("TLS redirect accept failed: errno=%d", errno);
+            continue;
+        }
+        serve_redirect(fd);
+    }
+    return NULL;
+}
+
+int tls_redirect_start(const char *bind_addr, int https_port) {
+    char host[128] = {0};
+    int port = 0;
+    if (parse_bind_addr(bind_addr, host, sizeof(host), &port) != 0) {
+        log_error("invalid FRICU_TLS_REDIRECT_BIND: %s", bind_addr);
+        return -1;
+    }
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons((uint16_t)port);
+    if (inet_pton(AF_INET, host, &addr.sin_addr) <= 0) {
+        log_error("invalid FRICU_TLS_REDIRECT_BIND host: %s", host);
+        return -1;
+    }
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0) return -1;
+    int opt = 1;
+    setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &opt, sizeof(opt));
+    if (bind(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0 || listen(fd, 1024) < 0) {
+        log_error("TLS redirect listener on %s failed: errno=%d", bind_addr, errno);
+        close(fd);
+        return -1;
+    }
+    g_redirect_https_port = https_port;
+    pthread_t thread;
+    if (pthread_create(&thread, NULL, redirect_main, (void *)(intptr_t)fd) != 0) {
+        close(fd);
+        return -1;
+    }
+    pthread_detach(thread);
+    log_info("TLS redirect listening on %s -> https port %d", bind_addr, https_port);
+    return 0;
+}
@@ -0,0 +1,427 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Travel log: stays at a location and altitude over a date range. Power recorded during a stay is
+ * divided by an altitude factor to give its sea-level equivalent before it feeds the CP fit and the
+ * compare power curve, so a training camp at 2,000 m does not drag the FTP estimate down. The
+ * factor comes from the stay itself ("power_factor"), else from the profile's
+ * "altitudePowerFactors" table ([{"altitudeM": 1500, "factor": 0.95}, ...], interpolated linearly
+ * from 1.0 at sea level), else from Bassett et al.'s curve for acclimatized athletes.
+ */
+
+#define TRAVEL_MAX_SPAN_DAYS 366
+#define TRAVEL_LOCATION_MAX 128
+#define TRAVEL_MIN_ALTITUDE_M (-500)
+#define TRAVEL_MAX_ALTITUDE_M 6000
+#define TRAVEL_MIN_FACTOR 0.5
+#define ALTITUDE_DEFAULT_DAYS 90
+
+#define TRAVEL_COLUMNS "id, start_day, end_day, location, altitude_m, power_factor, created_at, updated_at"
+
+static double bassett_acclimatized_factor(double altitude_m) {
+    /* Bassett et al. (1999): %VO2max = 99.9 - 1.90 x - 1.12 x^2, x in km, relative to sea level. */
+    double km = altitude_m / 1000.0;
+    double factor = (99.9 - 1.90 * km - 1.12 * km * km) / 99.9;
+    return factor < TRAVEL_MIN_FACTOR ? TRAVEL_MIN_FACTOR : factor;
+}
+
+static double curve_factor(const travel_log_t *log, double altitude_m) {
+    if (altitude_m <= 0.0) return 1.0;
+    if (log->curve_points == 0) return bassett_acclimatized_factor(altitude_m);
+    double prev_altitude = 0.0;
+    double prev_factor = 1.0;
+    for (size_t i = 0; i < log->curve_points; i++) {
+        double altitude = log->curve_altitude_m[i];
+        double factor = log->curve_factor[i];
+        if (altitude_m <= altitude) {
+            if (altitude <= prev_altitude) return factor;
+            return prev_factor + (factor - prev_factor) * (altitude_m - prev_altitude) / (altitude - prev_altitude);
+        }
+        prev_altitude = altitude;
+        prev_factor = factor;
+    }
+    return prev_factor;
+}
+
+static double stay_factor(const travel_log_t *log, const travel_stay_t *stay) {
+    return stay->power_factor > 0.0 ? stay->power_factor : curve_factor(log, (double)stay->altitude_m);
+}
+
+int travel_load_log(sqlite3 *db, const char *account_id, travel_log_t *out) {
+    memset(out, 0, sizeof(*out));
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, "profile", storage_key, sizeof(storage_key)) != 0) return -1;
+    sqlite3_stmt *stmt = NULL;
+    const char *curve_sql =
+        "SELECT json_extract(p.value, '$.altitudeM'), json_extract(p.value, '$.factor')"
+        " FROM kv_store k, json_each(k.data_value, '$.altitudePowerFactors') p"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value, '$.altitudePowerFactors') = 'array'"
+        " AND json_type(p.value, '$.altitudeM') IN ('integer', 'real') AND json_type(p.value, '$.factor') IN ('integer', 'real')"
+        " AND json_extract(p.value, '$.altitudeM') > 0 AND json_extract(p.value, '$.factor') BETWEEN ?2 AND 1.0"
+        " ORDER BY json_extract(p.value, '$.altitudeM')";
+    if (sqlite3_prepare_v2(db, curve_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_double(stmt, 2, TRAVEL_MIN_FACTOR);
+    while (out->curve_points < TRAVEL_CURVE_MAX_POINTS && sqlite3_step(stmt) == SQLITE_ROW) {
+        out->curve_altitude_m[out->curve_points] = sqlite3_column_double(stmt, 0);
+        out->curve_factor[out->curve_points] = sqlite3_column_double(stmt, 1);
+        out->curve_points++;
+    }
+    sqlite3_finalize(stmt);
+
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT start_day, end_day, altitude_m, COALESCE(power_factor, 0) FROM travel_stays WHERE account_id = ?1"
+            " ORDER BY start_day DESC LIMIT ?2",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, TRAVEL_MAX_STAYS);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        travel_stay_t *stay = &out->stays[out->count];
+        if (parse_iso_day((const char *)sqlite3_column_text(stmt, 0), &stay->start_day) != 0 ||
+            parse_iso_day((const char *)sqlite3_column_text(stmt, 1), &stay->end_day) != 0) {
+            continue;
+        }
+        stay->altitude_m = sqlite3_column_int(stmt, 2);
+        stay->power_factor = sqlite3_column_double(stmt, 3);
+        out->count++;
+    }
+    sqlite3_finalize(stmt);
+    return 0;
+}
+
+double travel_power_factor(const travel_log_t *log, int day, int *out_altitude_m) {
+    for (size_t i = 0; log && i < log->count; i++) {
+        const travel_stay_t *stay = &log->stays[i];
+        if (day < stay->start_day || day > stay->end_day) continue;
+        if (out_altitude_m) *out_altitude_m = stay->altitude_m;
+        return stay_factor(log, stay);
+    }
+    if (out_altitude_m) *out_altitude_m = 0;
+    return 1.0;
+}
+
+static int parse_day_strict(const char *text, int *out_day) {
+    return text && strlen(text) == 10 && parse_iso_day(text, out_day) == 0 ? 0 : -1;
+}
+
+static int send_strbuf(int fd, int code, const char *status, strbuf_t *sb, const request_log_context_t *ctx) {
+    if (sb->failed) {
+        strbuf_free(sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, code, status, strbuf_cstr(sb), ctx);
+    strbuf_free(sb);
+    return code;
+}
+
+static void append_stay_json(strbuf_t *sb, const travel_log_t *log, sqlite3_stmt *stmt) {
+    /* Columns: TRAVEL_COLUMNS */
+    travel_stay_t stay = {0};
+    stay.altitude_m = sqlite3_column_int(stmt, 4);
+    stay.power_factor = sqlite3_column_double(stmt, 5);
+    const char *source = sqlite3_column_type(stmt, 5) != SQLITE_NULL ? "stay" : log->curve_points > 0 ? "profile" : "default";
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 0));
+    strbuf_append(sb, ",\"start_date\":", 14);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 1));
+    strbuf_append(sb, ",\"end_date\":", 12);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 2));
+    strbuf_append(sb, ",\"location\":", 12);
+    strbuf_append_json_string(sb, (const char *)sqlite3_column_text(stmt, 3));
+    strbuf_appendf(
+        sb,
+        ",\"altitude_m\":%d,\"power_factor\":%.3f,\"power_factor_source\":\"%s\",\"created_at\":%lld,\"updated_at\":%lld}",
+        stay.altitude_m,
+        stay_factor(log, &stay),
+        source,
+        sqlite3_column_int64(stmt, 6),
+        sqlite3_column_int64(stmt, 7));
+}
+
+static int handle_get_stay(int fd, worker_db_t *db, const char *id, int code, const request_log_context_t *ctx) {
+    travel_log_t log;
+    sqlite3_stmt *stmt = NULL;
+    if (travel_load_log(db->db, ctx->account_id, &log) != 0 ||
+        sqlite3_prepare_v2(db->db, "SELECT " TRAVEL_COLUMNS " FROM travel_stays WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"travel stay not found\"}", ctx);
+        return 404;
+    }
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_stay_json(&sb, &log, stmt);
+    sqlite3_finalize(stmt);
+    return send_strbuf(fd, code, code == 201 ? "Created" : "OK", &sb, ctx);
+}
+
+static int handle_list_stays(int fd, worker_db_t *db, const request_log_context_t *ctx) {
+    travel_log_t log;
+    sqlite3_stmt *stmt = NULL;
+    if (travel_load_log(db->db, ctx->account_id, &log) != 0 ||
+        sqlite3_prepare_v2(db->db, "SELECT " TRAVEL_COLUMNS " FROM travel_stays WHERE account_id = ?1 ORDER BY start_day DESC", -1, &stmt, NULL) !=
+            SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"stays\":[", 10);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        append_stay_json(&sb, &log, stmt);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
+
+/* Validates a stay body; fills the dates on success, otherwise sends the error and returns its status. */
+static int validate_stay(int fd, worker_db_t *db, const http_request_t *req, const char *id, char *start, char *end, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    const char *check_sql =
+        "SELECT json_valid(?1) AND json_type(?1) = 'object'"
+        " AND json_type(?1, '$.start_date') = 'text' AND json_type(?1, '$.end_date') = 'text'"
+        " AND json_type(?1, '$.altitude_m') IN ('integer', 'real') AND json_extract(?1, '$.altitude_m') BETWEEN ?2 AND ?3"
+        " AND COALESCE(json_type(?1, '$.location'), 'text') = 'text' AND length(COALESCE(json_extract(?1, '$.location'), '')) <= ?4"
+        " AND (COALESCE(json_type(?1, '$.power_factor'), 'null') = 'null'"
+        "      OR (json_type(?1, '$.power_factor') IN ('integer', 'real') AND json_extract(?1, '$.power_factor') BETWEEN ?5 AND 1.0))"
+        " AND NOT EXISTS (SELECT 1 FROM json_each(?1) WHERE key NOT IN ('start_date', 'end_date', 'location', 'altitude_m', 'power_factor')),"
+        " json_extract(?1, '$.start_date'), json_extract(?1, '$.end_date')";
+    if (sqlite3_prepare_v2(db->db, check_sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    sqlite3_bind_int(stmt, 2, TRAVEL_MIN_ALTITUDE_M);
+    sqlite3_bind_int(stmt, 3, TRAVEL_MAX_ALTITUDE_M);
+    sqlite3_bind_int(stmt, 4, TRAVEL_LOCATION_MAX);
+    sqlite3_bind_double(stmt, 5, TRAVEL_MIN_FACTOR);
+    int valid = 0;
+    int start_day = 0;
+    int end_day = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0)) {
+        snprintf(start, 16, "%s", (const char *)sqlite3_column_text(stmt, 1));
+        snprintf(end, 16, "%s", (const char *)sqlite3_column_text(stmt, 2));
+        valid = parse_day_strict(start, &start_day) == 0 && parse_day_strict(end, &end_day) == 0 && end_day >= start_day &&
+                end_day - start_day < TRAVEL_MAX_SPAN_DAYS;
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        send_response_with_log_context(
+            fd,
+            400,
+            "Bad Request",
+            "{\"error\":\"expected {\\\"start_date\\\": YYYY-MM-DD, \\\"end_date\\\": YYYY-MM-DD within 366 days, "
+            "\\\"altitude_m\\\": -500..6000, \\\"location\\\": string, \\\"power_factor\\\": 0.5..1 or null}\"}",
+            ctx);
+        return 400;
+    }
+
+    /* One stay per day, so an activity maps to a single altitude. */
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT 1 FROM travel_stays WHERE account_id = ?1 AND id <> ?2 AND start_day <= ?4 AND end_day >= ?3 LIMIT 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, start, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, end, -1, SQLITE_TRANSIENT);
+    int overlaps = sqlite3_step(stmt) == SQLITE_ROW;
+    sqlite3_finalize(stmt);
+    if (overlaps) {
+        send_response_with_log_context(fd, 409, "Conflict", "{\"error\":\"stay overlaps another travel stay\"}", ctx);
+        return 409;
+    }
+    return 0;
+}
+
+static int handle_store_stay(int fd, worker_db_t *db, const http_request_t *req, const char *existing_id, const request_log_context_t *ctx) {
+    char id[64] = {0};
+    if (existing_id) {
+        snprintf(id, sizeof(id), "%s", existing_id);
+    } else if (generate_uuid_v4(id, sizeof(id)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"id generation failed\"}", ctx);
+        return 500;
+    }
+    char start[16] = {0};
+    char end[16] = {0};
+    int rc = validate_stay(fd, db, req, id, start, end, ctx);
+    if (rc != 0) return rc;
+
+    sqlite3_stmt *stmt = NULL;
+    const char *sql = existing_id
+                          ? "UPDATE travel_stays SET start_day = ?3, end_day = ?4, location = COALESCE(json_extract(?5, '$.location'), ''),"
+                            " altitude_m = CAST(json_extract(?5, '$.altitude_m') AS INTEGER), power_factor = json_extract(?5, '$.power_factor'),"
+                            " updated_at = strftime('%s', 'now') WHERE account_id = ?1 AND id = ?2"
+                          : "INSERT INTO travel_stays (account_id, id, start_day, end_day, location, altitude_m, power_factor, created_at, updated_at)"
+                            " VALUES (?1, ?2, ?3, ?4, COALESCE(json_extract(?5, '$.location'), ''), CAST(json_extract(?5, '$.altitude_m') AS INTEGER),"
+                            " json_extract(?5, '$.power_factor'), strftime('%s', 'now'), strftime('%s', 'now'))";
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, start, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, end, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 5, req->body, (int)req->body_len, SQLITE_TRANSIENT);
+    rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        log_error("TRAVEL store failed id=%s account=%s err=%s logid=%s", id, ctx->account_id, sqlite3_errmsg(db->db), ctx->log_id);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (existing_id && sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"travel stay not found\"}", ctx);
+        return 404;
+    }
+    log_info("TRAVEL stored id=%s from=%s to=%s account=%s logid=%s", id, start, end, ctx->account_id, ctx->log_id);
+    data_queue_refresh(ctx->account_id, "travel");
+    return handle_get_stay(fd, db, id, existing_id ? 200 : 201, ctx);
+}
+
+static int handle_delete_stay(int fd, worker_db_t *db, const char *id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "DELETE FROM travel_stays WHERE account_id = ?1 AND id = ?2", -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, ctx->account_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, id, -1, SQLITE_TRANSIENT);
+    sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (sqlite3_changes(db->db) == 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"travel stay not found\"}", ctx);
+        return 404;
+    }
+    log_info("TRAVEL deleted id=%s account=%s logid=%s", id, ctx->account_id, ctx->log_id);
+    data_queue_refresh(ctx->account_id, "travel");
+    send_response_with_log_context(fd, 204, "No Content", "", ctx);
+    return 204;
+}
+
+int route_travel(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    const char *method = req->method;
+    const char *collection = "/v1/travel";
+    if (strcmp(req->path, collection) == 0) {
+        if (strcmp(method, "GET") == 0) return handle_list_stays(fd, db, ctx);
+        if (strcmp(method, "POST") == 0) return handle_store_stay(fd, db, req, NULL, ctx);
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+
+    const char *id = req->path + strlen(collection) + 1;
+    if (!is_valid_item_id(id)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"travel stay not found\"}", ctx);
+        return 404;
+    }
+    if (strcmp(method, "GET") == 0) return handle_get_stay(fd, db, id, 200, ctx);
+    if (strcmp(method, "PUT") == 0) return handle_store_stay(fd, db, req, id, ctx);
+    if (strcmp(method, "DELETE") == 0) return handle_delete_stay(fd, db, id, ctx);
+    send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+    return 405;
+}
+
+static void append_corrected_watts(strbuf_t *sb, const char *name, sqlite3_stmt *stmt, int column, double factor) {
+    if (sqlite3_column_type(stmt, column) == SQLITE_NULL) {
+        strbuf_appendf(sb, ",\"%s\":null,\"%s_sea_level\":null", name, name);
+    } else {
+        double watts = sqlite3_column_double(stmt, column);
+        strbuf_appendf(sb, ",\"%s\":%.0f,\"%s_sea_level\":%.0f", name, watts, name, watts / factor);
+    }
+}
+
+int handle_get_analytics_altitude(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    char from[16] = {0};
+    char to[16] = {0};
+    int to_day = today_day();
+    int from_day = to_day - ALTITUDE_DEFAULT_DAYS + 1;
+    if ((query_param(req->query, "from", from, sizeof(from)) && parse_day_strict(from, &from_day) != 0) ||
+        (query_param(req->query, "to", to, sizeof(to)) && parse_day_strict(to, &to_day) != 0) || to_day < from_day ||
+        to_day - from_day >= TRAVEL_MAX_SPAN_DAYS) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"from and to must be YYYY-MM-DD, at most 366 days apart\"}", ctx);
+        return 400;
+    }
+    format_iso_day(from_day, from, sizeof(from));
+    format_iso_day(to_day, to, sizeof(to));
+
+    char storage_key[256] = {0};
+    travel_log_t log;
+    if (build_storage_key(ctx->account_id, "activities", storage_key, sizeof(storage_key)) != 0 ||
+        travel_load_log(db->db, ctx->account_id, &log) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    const char *sql =
+        "SELECT json_extract(a.value, '$.id'), substr(json_extract(a.value, '$.date'), 1, 10) AS day, json_extract(a.value, '$.sport'),"
+        " s.location, json_extract(a.value, '$.normalizedPower'), json_extract(a.value, '$.avgPower')"
+        " FROM kv_store k, json_each(k.data_value) a"
+        " JOIN travel_stays s ON s.account_id = ?4 AND day BETWEEN s.start_day AND s.end_day"
+        " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+        " AND day BETWEEN ?2 AND ?3 ORDER BY day, a.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, sql, -1, &stmt, NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, from, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 3, to, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 4, ctx->account_id, -1, SQLITE_TRANSIENT);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(&sb, "{\"from\":\"%s\",\"to\":\"%s\",\"activities\":[", from, to);
+    int count = 0;
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        int day = 0;
+        if (parse_iso_day((const char *)sqlite3_column_text(stmt, 1), &day) != 0) continue;
+        int altitude_m = 0;
+        double factor = travel_power_factor(&log, day, &altitude_m);
+        if (count++ > 0) strbuf_append(&sb, ",", 1);
+        strbuf_append(&sb, "{\"id\":", 6);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 0));
+        strbuf_append(&sb, ",\"date\":", 8);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 1));
+        strbuf_append(&sb, ",\"sport\":", 9);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 2));
+        strbuf_append(&sb, ",\"location\":", 12);
+        strbuf_append_json_string(&sb, (const char *)sqlite3_column_text(stmt, 3));
+        strbuf_appendf(&sb, ",\"altitude_m\":%d,\"power_factor\":%.3f", altitude_m, factor);
+        append_corrected_watts(&sb, "normalized_power", stmt, 4, factor);
+        append_corrected_watts(&sb, "avg_power", stmt, 5, factor);
+        strbuf_append(&sb, "}", 1);
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    return send_strbuf(fd, 200, "OK", &sb, ctx);
+}
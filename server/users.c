@@ -0,0 +1,264 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <ctype.h>
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Users of a shared deployment. Every row is already scoped by account (kv_store keys are
+ * "<account>::<key>" and the other tables carry account_id), normally chosen by X-Account-Id. The
+ * users table adds a directory for a coaching group: display names, GET /v1/users listing the
+ * caller and every athlete whose coach token the caller presents, and /v1/users/<id>/data/...
+ * serving the normal data routes (documents, /items, /diff) for an explicit user. A caller may act
+ * for a user when it is that user or holds a coach token the user issued (X-Coach-Token).
+ */
+
+#define USER_NAME_MAX 64
+#define USER_TOKENS_HEADER_MAX 1024
+
+static int valid_user_id(const char *id, size_t len) {
+    if (len == 0 || len >= 128) return 0;
+    for (size_t i = 0; i < len; i++) {
+        unsigned char ch = (unsigned char)id[i];
+        if (!isalnum(ch) && ch != '-' && ch != '_' && ch != '.') return 0;
+    }
+    return 1;
+}
+
+/* 1 when the caller may read and write user_id's data, 0 when not, -1 on database errors. */
+static int user_access_allowed(sqlite3 *db, const http_request_t *req, const request_log_context_t *ctx, const char *user_id) {
+    if (ctx->account_id[0] != '\0' && strcmp(ctx->account_id, user_id) == 0) return 1;
+    return coach_grant_allows(db, req, user_id);
+}
+
+static void append_user(strbuf_t *sb, sqlite3 *db, const char *user_id, int self) {
+    sqlite3_stmt *stmt = NULL;
+    const char *name = NULL;
+    long long created_at = 0;
+    if (sqlite3_prepare_v2(db, "SELECT display_name, created_at FROM users WHERE account_id = ?1", -1, &stmt, NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, user_id, -1, SQLITE_TRANSIENT);
+        if (sqlite3_step(stmt) == SQLITE_ROW) {
+            name = (const char *)sqlite3_column_text(stmt, 0);
+            created_at = sqlite3_column_int64(stmt, 1);
+        }
+    }
+    strbuf_append(sb, "{\"id\":", 6);
+    strbuf_append_json_string(sb, user_id);
+    strbuf_append(sb, ",\"name\":", 8);
+    if (name) {
+        strbuf_append_json_string(sb, name);
+        strbuf_appendf(sb, ",\"registered_at\":%lld", created_at);
+    } else {
+        strbuf_append(sb, "null,\"registered_at\":null", 25);
+    }
+    strbuf_appendf(sb, ",\"self\":%s,\"keys\":[", self ? "true" : "false");
+    sqlite3_finalize(stmt);
+    stmt = NULL;
+    char prefix[160] = {0};
+    snprintf(prefix, sizeof(prefix), "%s::", user_id);
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT substr(data_key, length(?1) + 1) FROM kv_store WHERE substr(data_key, 1, length(?1)) = ?1 ORDER BY data_key",
+            -1,
+            &stmt,
+            NULL) == SQLITE_OK) {
+        sqlite3_bind_text(stmt, 1, prefix, -1, SQLITE_TRANSIENT);
+        int count = 0;
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            const char *key = (const char *)sqlite3_column_text(stmt, 0);
+            if (!key || !is_valid_key(key)) continue;
+            if (count++ > 0) strbuf_append(sb, ",", 1);
+            strbuf_append_json_string(sb, key);
+        }
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(sb, "]}", 2);
+}
+
+static int handle_list_users(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"users\":[", 10);
+    int count = 0;
+    if (ctx->account_id[0] != '\0') {
+        append_user(&sb, db->db, ctx->account_id, 1);
+        count++;
+    }
+    char tokens[USER_TOKENS_HEADER_MAX] = {0};
+    sqlite3_stmt *stmt = NULL;
+    if (http_request_header(req, "X-Coach-Token", tokens, sizeof(tokens)) &&
+        sqlite3_prepare_v2(db->db, "SELECT account_id FROM coach_tokens WHERE token_hash = ?1 AND account_id <> ?2", -1, &stmt, NULL) == SQLITE_OK) {
+        /* Remembered so an athlete granted through several tokens is listed once. */
+        char listed[USER_TOKENS_HEADER_MAX] = {0};
+        size_t listed_len = 0;
+        for (char *save = NULL, *token = strtok_r(tokens, ", ", &save); token; token = strtok_r(NULL, ", ", &save)) {
+            char hash[65] = {0};
+            sha256_hex(token, strlen(token), hash, sizeof(hash));
+            sqlite3_reset(stmt);
+            sqlite3_bind_text(stmt, 1, hash, -1, SQLITE_TRANSIENT);
+            sqlite3_bind_text(stmt, 2, ctx->account_id, -1, SQLITE_TRANSIENT);
+            if (sqlite3_step(stmt) != SQLITE_ROW) continue;
+            char user_id[128] = {0};
+            char marker[132] = {0};
+            snprintf(user_id, sizeof(user_id), "%s", (const char *)sqlite3_column_text(stmt, 0));
+            snprintf(marker, sizeof(marker), "\n%s\n", user_id);
+            if (strstr(listed, marker)) continue;
+            if (listed_len + strlen(marker) < sizeof(listed)) {
+                memcpy(listed + listed_len, marker, strlen(marker) + 1);
+                listed_len += strlen(marker);
+            }
+            if (count++ > 0) strbuf_append(&sb, ",", 1);
+            append_user(&sb, db->db, user_id, 0);
+        }
+    }
+    sqlite3_finalize(stmt);
+    strbuf_append(&sb, "]}", 2);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+static int handle_put_user(int fd, worker_db_t *db, const http_request_t *req, const char *user_id, const request_log_context_t *ctx) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, "SELECT json_valid(?1) AND json_type(?1) = 'object', json_type(?1, '$.name'), json_extract(?1, '$.name')", -1, &stmt, NULL) !=
+        SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, req->body ? req->body : "", (int)req->body_len, SQLITE_TRANSIENT);
+    char name[USER_NAME_MAX + 1] = {0};
+    int valid = 0;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_int(stmt, 0) && sqlite3_column_text(stmt, 1) &&
+        strcmp((const char *)sqlite3_column_text(stmt, 1), "text") == 0) {
+        const char *raw = (const char *)sqlite3_column_text(stmt, 2);
+        size_t len = strlen(raw);
+        if (len > 0 && len <= USER_NAME_MAX) {
+            memcpy(name, raw, len);
+            valid = 1;
+        }
+    }
+    sqlite3_finalize(stmt);
+    if (!valid) {
+        char body[96] = {0};
+        snprintf(body, sizeof(body), "{\"error\":\"name must be a string of 1-%d characters\"}", USER_NAME_MAX);
+        send_response_with_log_context(fd, 400, "Bad Request", body, ctx);
+        return 400;
+    }
+    if (sqlite3_prepare_v2(
+            db->db,
+            "INSERT INTO users (account_id, display_name, created_at, updated_at) VALUES (?1, ?2, strftime('%s', 'now'), strftime('%s', 'now'))"
+            " ON CONFLICT(account_id) DO UPDATE SET display_name = excluded.display_name, updated_at = excluded.updated_at",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, user_id, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, name, -1, SQLITE_TRANSIENT);
+    int rc = sqlite3_step(stmt);
+    sqlite3_finalize(stmt);
+    if (rc != SQLITE_DONE) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    log_info("USER registered user=%s by=%s logid=%s", user_id, ctx->account_id[0] ? ctx->account_id : "-", ctx->log_id);
+    strbuf_t sb;
+    strbuf_init(&sb);
+    append_user(&sb, db->db, user_id, strcmp(user_id, ctx->account_id) == 0);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
+
+int route_users(
+    int fd,
+    worker_db_t *db,
+    const http_request_t *req,
+    request_log_context_t *ctx,
+    http_request_t *scoped,
+    char *scoped_path,
+    size_t scoped_path_len) {
+    if (strcmp(req->path, "/v1/users") == 0) {
+        if (strcmp(req->method, "GET") != 0) {
+            send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+            return 405;
+        }
+        char tokens[USER_TOKENS_HEADER_MAX] = {0};
+        if (ctx->account_id[0] == '\0' && !http_request_header(req, "X-Coach-Token", tokens, sizeof(tokens))) {
+            send_response_with_log_context(fd, 401, "Unauthorized", "{\"error\":\"missing X-Account-Id\"}", ctx);
+            return 401;
+        }
+        return handle_list_users(fd, db, req, ctx);
+    }
+
+    const char *id = req->path + strlen("/v1/users/");
+    size_t id_len = strcspn(id, "/");
+    char user_id[128] = {0};
+    if (!valid_user_id(id, id_len)) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown user\"}", ctx);
+        return 404;
+    }
+    memcpy(user_id, id, id_len);
+    const char *rest = id + id_len;
+    if (rest[0] != '\0' && strncmp(rest, "/data/", 6) != 0) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    int allowed = user_access_allowed(db->db, req, ctx, user_id);
+    if (allowed < 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    if (!allowed) {
+        char body[192] = {0};
+        snprintf(body, sizeof(body), "{\"error\":\"no access to user\",\"user\":\"%s\"}", user_id);
+        send_response_with_log_context(fd, 403, "Forbidden", body, ctx);
+        return 403;
+    }
+
+    if (rest[0] == '\0') {
+        if (strcmp(req->method, "PUT") == 0) {
+            if (!cluster_is_writer()) return cluster_reject_write(fd, ctx);
+            return handle_put_user(fd, db, req, user_id, ctx);
+        }
+        if (strcmp(req->method, "GET") != 0) {
+            send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+            return 405;
+        }
+        strbuf_t sb;
+        strbuf_init(&sb);
+        append_user(&sb, db->db, user_id, strcmp(user_id, ctx->account_id) == 0);
+        int status = sb.failed ? 500 : 200;
+        send_response_with_log_context(fd, status, status == 200 ? "OK" : "Internal Server Error", sb.failed ? "{\"error\":\"oom\"}" : strbuf_cstr(&sb), ctx);
+        strbuf_free(&sb);
+        return status;
+    }
+
+    /* Hand the data route back to the dispatcher as the target user. */
+    int n = snprintf(scoped_path, scoped_path_len, "/v1%s", rest);
+    if (n < 0 || (size_t)n >= scoped_path_len) {
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"not found\"}", ctx);
+        return 404;
+    }
+    *scoped = *req;
+    scoped->path = scoped_path;
+    snprintf(ctx->account_id, sizeof(ctx->account_id), "%s", user_id);
+    return 0;
+}
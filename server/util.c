@@ -4,12 +4,15 @@
 #include <ctype.h>
 #include <errno.h>
 #include <fcntl.h>
+#include <stdarg.h>
+#include <stdint.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include <strings.h>
 #include <sys/resource.h>
 #include <sys/socket.h>
+#include <unistd.h>
 
 const char *DATA_KEYS[] = {
     "activities",
@@ -22,6 +25,7 @@ const char *DATA_KEYS[] = {
     "profile",
     "app_settings",
     "lactate_history_records",
+    "archived_activities",
 };
 const size_t DATA_KEYS_COUNT = sizeof(DATA_KEYS) / sizeof(DATA_KEYS[0]);
 
@@ -116,3 +120,380 @@ int configure_socket_after_accept(int fd) {
 #endif
     return 0;
 }
+
+static int hex_value(char ch) {
+    if (ch >= '0' && ch <= '9') return ch - '0';
+    if (ch >= 'a' && ch <= 'f') return ch - 'a' + 10;
+    if (ch >= 'A' && ch <= 'F') return ch - 'A' + 10;
+    return -1;
+}
+
+int query_param(const char *query, const char *name, char *out, size_t out_len) {
+    if (!query || !name || !out || out_len == 0) return 0;
+    out[0] = '\0';
+    size_t name_len = strlen(name);
+    const char *cursor = query;
+    while (*cursor != '\0') {
+        const char *end = strchr(cursor, '&');
+        if (!end) end = cursor + strlen(cursor);
+        const char *eq = memchr(cursor, '=', (size_t)(end - cursor));
+        const char *name_end = eq ? eq : end;
+        if ((size_t)(name_end - cursor) == name_len && strncmp(cursor, name, name_len) == 0) {
+            size_t idx = 0;
+            const char *p = eq ? eq + 1 : end;
+            while (p < end && idx + 1 < out_len) {
+                if (*p == '+') {
+                    out[idx++] = ' ';
+                    p++;
+                } else if (*p == '%' && p + 2 < end && hex_value(p[1]) >= 0 && hex_value(p[2]) >= 0) {
+                    out[idx++] = (char)(hex_value(p[1]) * 16 + hex_value(p[2]));
+                    p += 3;
+                } else {
+                    out[idx++] = *p++;
+                }
+            }
+            out[idx] = '\0';
+            return 1;
+        }
+        cursor = *end == '&' ? end + 1 : end;
+    }
+    return 0;
+}
+
+static int days_from_civil(int y, int m, int d) {
+    y -= m <= 2;
+    int era = (y >= 0 ? y : y - 399) / 400;
+    int yoe = y - era * 400;
+    int doy = (153 * (m + (m > 2 ? -3 : 9)) + 2) / 5 + d - 1;
+    int doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+int parse_iso_day(const char *s, int *out_day) {
+    if (!s || !out_day) return -1;
+    int y = 0, m = 0, d = 0;
+    for (int i = 0; i < 10; i++) {
+        if (i == 4 || i == 7) {
+            if (s[i] != '-') return -1;
+        } else if (!isdigit((unsigned char)s[i])) {
+            return -1;
+        }
+    }
+    if (sscanf(s, "%4d-%2d-%2d", &y, &m, &d) != 3) return -1;
+    if (m < 1 || m > 12 || d < 1 || d > 31) return -1;
+    *out_day = days_from_civil(y, m, d);
+    return 0;
+}
+
+void format_iso_day(int day, char *out, size_t out_len) {
+    int z = day + 719468;
+    int era = (z >= 0 ? z : z - 146096) / 146097;
+    int doe = z - era * 146097;
+    int yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    int y = yoe + era * 400;
+    int doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    int mp = (5 * doy + 2) / 153;
+    int d = doy - (153 * mp + 2) / 5 + 1;
+    int m = mp + (mp < 10 ? 3 : -9);
+    snprintf(out, out_len, "%04d-%02d-%02d", y + (m <= 2), m, d);
+}
+
+void strbuf_init(strbuf_t *sb) {
+    memset(sb, 0, sizeof(*sb));
+}
+
+static int strbuf_reserve(strbuf_t *sb, size_t extra) {
+    if (sb->failed) return -1;
+    if (sb->len + extra + 1 <= sb->cap) return 0;
+    size_t next = sb->cap ? sb->cap : 256;
+    while (next < sb->len + extra + 1) next *= 2;
+    char *nb = (char *)realloc(sb->data, next);
+    if (!nb) {
+        sb->failed = 1;
+        return -1;
+    }
+    sb->data = nb;
+    sb->cap = next;
+    return 0;
+}
+
+int strbuf_append(strbuf_t *sb, const char *s, size_t len) {
+    if (strbuf_reserve(sb, len) != 0) return -1;
+    memcpy(sb->data + sb->len, s, len);
+    sb->len += len;
+    sb->data[sb->len] = '\0';
+    return 0;
+}
+
+int strbuf_appendf(strbuf_t *sb, const char *fmt, ...) {
+    va_list ap;
+    va_start(ap, fmt);
+    va_list copy;
+    va_copy(copy, ap);
+    int needed = vsnprintf(NULL, 0, fmt, copy);
+    va_end(copy);
+    if (needed < 0 || strbuf_reserve(sb, (size_t)needed) != 0) {
+        va_end(ap);
+        sb->failed = 1;
+        return -1;
+    }
+    vsnprintf(sb->data + sb->len, (size_t)needed + 1, fmt, ap);
+    va_end(ap);
+    sb->len += (size_t)needed;
+    return 0;
+}
+
+int strbuf_append_json_string(strbuf_t *sb, const char *s) {
+    if (strbuf_append(sb, "\"", 1) != 0) return -1;
+    for (const unsigned char *p = (const unsigned char *)(s ? s : ""); *p != '\0'; p++) {
+        if (*p == '"' || *p == '\\') {
+            char esc[2] = {'\\', (char)*p};
+            strbuf_append(sb, esc, 2);
+        } else if (*p == '\n') {
+            strbuf_append(sb, "\\n", 2);
+        } else if (*p == '\r') {
+            strbuf_append(sb, "\\r", 2);
+        } else if (*p == '\t') {
+            strbuf_append(sb, "\\t", 2);
+        } else if (*p < 0x20) {
+            strbuf_appendf(sb, "\\u%04x", *p);
+        } else {
+            strbuf_append(sb, (const char *)p, 1);
+        }
+    }
+    return strbuf_append(sb, "\"", 1);
+}
+
+const char *strbuf_cstr(const strbuf_t *sb) {
+    return (sb->data && !sb->failed) ? sb->data : "";
+}
+
+void strbuf_free(strbuf_t *sb) {
+    free(sb->data);
+    memset(sb, 0, sizeof(*sb));
+}
+
+int fill_random_bytes(void *out, size_t len) {
+    int fd = open("/dev/urandom", O_RDONLY);
+    if (fd < 0) return -1;
+    size_t got = 0;
+    while (got < len) {
+        ssize_t n = read(fd, (unsigned char *)out + got, len - got);
+        if (n <= 0) {
+            close(fd);
+            return -1;
+        }
+        got += (size_t)n;
+    }
+    close(fd);
+    return 0;
+}
+
+int generate_uuid_v4(char *out, size_t out_len) {
+    unsigned char b[16];
+    if (out_len < 37 || fill_random_bytes(b, sizeof(b)) != 0) return -1;
+    b[6] = (unsigned char)((b[6] & 0x0F) | 0x40);
+    b[8] = (unsigned char)((b[8] & 0x3F) | 0x80);
+    snprintf(
+        out,
+        out_len,
+        "%02X%02X%02X%02X-%02X%02X-%02X%02X-%02X%02X-%02X%02X%02X%02X%02X%02X",
+        b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]);
+    return 0;
+}
+
+static const char BASE64_ALPHABET[] = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+void base64_encode(const unsigned char *in, size_t len, char *out, size_t out_len) {
+    size_t o = 0;
+    for (size_t i = 0; i < len && o + 4 < out_len; i += 3) {
+        unsigned v = (unsigned)in[i] << 16 | (i + 1 < len ? (unsigned)in[i + 1] << 8 : 0) | (i + 2 < len ? in[i + 2] : 0);
+        out[o++] = BASE64_ALPHABET[(v >> 18) & 63];
+        out[o++] = BASE64_ALPHABET[(v >> 12) & 63];
+        out[o++] = i + 1 < len ? BASE64_ALPHABET[(v >> 6) & 63] : '=';
+        out[o++] = i + 2 < len ? BASE64_ALPHABET[v & 63] : '=';
+    }
+    if (out_len > 0) out[o < out_len ? o : out_len - 1] = '\0';
+}
+
+/* Decodes standard base64, ignoring whitespace; returns a malloc'd buffer or NULL when malformed. */
+unsigned char *base64_decode(const char *in, size_t len, size_t *out_len) {
+    unsigned char *out = malloc(len / 4 * 3 + 3);
+    if (!out) return NULL;
+    unsigned v = 0;
+    int bits = 0;
+    size_t o = 0;
+    for (size_t i = 0; i < len; i++) {
+        char c = in[i];
+        if (c == '=') break;
+        if (c == '\r' || c == '\n' || c == ' ' || c == '\t') continue;
+        const char *p = c ? strchr(BASE64_ALPHABET, c) : NULL;
+        if (!p) {
+            free(out);
+            return NULL;
+        }
+        v = (v << 6) | (unsigned)(p - BASE64_ALPHABET);
+        bits += 6;
+        if (bits >= 8) {
+            bits -= 8;
+            out[o++] = (unsigned char)((v >> bits) & 0xFF);
+        }
+    }
+    *out_len = o;
+    return out;
+}
+
+void content_version(const char *data, size_t len, char *out, size_t out_len) {
+    uint64_t hash = 1469598103934665603ULL;
+    for (size_t i = 0; i < len; i++) {
+        hash ^= (unsigned char)data[i];
+        hash *= 1099511628211ULL;
+    }
+    snprintf(out, out_len, "%016llx", (unsigned long long)hash);
+}
+
+static const uint32_t SHA256_K[64] = {
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be,
+    0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa,
+    0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85,
+    0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3,
+    0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f,
+    0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+};
+
+#define SHA256_ROR(x, n) (((x) >> (n)) | ((x) << (32 - (n))))
+
+static void sha256_block(uint32_t state[8], const unsigned char *block) {
+    uint32_t w[64];
+    for (int i = 0; i < 16; i++) {
+        w[i] = (uint32_t)block[i * 4] << 24 | (uint32_t)block[i * 4 + 1] << 16 | (uint32_t)block[i * 4 + 2] << 8 | block[i * 4 + 3];
+    }
+    for (int i = 16; i < 64; i++) {
+        uint32_t s0 = SHA256_ROR(w[i - 15], 7) ^ SHA256_ROR(w[i - 15], 18) ^ (w[i - 15] >> 3);
+        uint32_t s1 = SHA256_ROR(w[i - 2], 17) ^ SHA256_ROR(w[i - 2], 19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16] + s0 + w[i - 7] + s1;
+    }
+    uint32_t a = state[0], b = state[1], c = state[2], d = state[3], e = state[4], f = state[5], g = state[6], h = state[7];
+    for (int i = 0; i < 64; i++) {
+        uint32_t t1 = h + (SHA256_ROR(e, 6) ^ SHA256_ROR(e, 11) ^ SHA256_ROR(e, 25)) + ((e & f) ^ (~e & g)) + SHA256_K[i] + w[i];
+        uint32_t t2 = (SHA256_ROR(a, 2) ^ SHA256_ROR(a, 13) ^ SHA256_ROR(a, 22)) + ((a & b) ^ (a & c) ^ (b & c));
+        h = g;
+        g = f;
+        f = e;
+        e = d + t1;
+        d = c;
+        c = b;
+        b = a;
+        a = t1 + t2;
+    }
+    state[0] += a;
+    state[1] += b;
+    state[2] += c;
+    state[3] += d;
+    state[4] += e;
+    state[5] += f;
+    state[6] += g;
+    state[7] += h;
+}
+
+void sha256_init(sha256_ctx_t *ctx) {
+    static const uint32_t initial[8] = {0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19};
+    memcpy(ctx->state, initial, sizeof(initial));
+    ctx->block_len = 0;
+    ctx->total = 0;
+}
+
+void sha256_update(sha256_ctx_t *ctx, const void *data, size_t len) {
+    const unsigned char *p = (const unsigned char *)data;
+    ctx->total += len;
+    if (ctx->block_len > 0) {
+        size_t take = 64 - ctx->block_len < len ? 64 - ctx->block_len : len;
+        memcpy(ctx->block + ctx->block_len, p, take);
+        ctx->block_len += take;
+        p += take;
+        len -= take;
+        if (ctx->block_len < 64) return;
+        sha256_block(ctx->state, ctx->block);
+        ctx->block_len = 0;
+    }
+    for (; len >= 64; len -= 64, p += 64) sha256_block(ctx->state, p);
+    memcpy(ctx->block, p, len);
+    ctx->block_len = len;
+}
+
+void sha256_final_hex(sha256_ctx_t *ctx, char *out, size_t out_len) {
+    unsigned char tail[128] = {0};
+    memcpy(tail, ctx->block, ctx->block_len);
+    tail[ctx->block_len] = 0x80;
+    size_t tail_len = ctx->block_len < 56 ? 64 : 128;
+    uint64_t bits = ctx->total * 8;
+    for (int i = 0; i < 8; i++) tail[tail_len - 1 - i] = (unsigned char)(bits >> (i * 8));
+    sha256_block(ctx->state, tail);
+    if (tail_len == 128) sha256_block(ctx->state, tail + 64);
+    size_t o = 0;
+    for (int i = 0; i < 8 && o + 8 < out_len; i++, o += 8) snprintf(out + o, 9, "%08x", ctx->state[i]);
+    if (out_len > 0) out[o < out_len ? o : out_len - 1] = '\0';
+}
+
+void sha256_hex(const void *data, size_t len, char *out, size_t out_len) {
+    sha256_ctx_t ctx;
+    sha256_init(&ctx);
+    sha256_update(&ctx, data, len);
+    sha256_final_hex(&ctx, out, out_len);
+}
+
+/* SHA-1 is only used where a protocol demands it (the WebSocket handshake), never for integrity. */
+#define SHA1_ROL(x, n) (((x) << (n)) | ((x) >> (32 - (n))))
+
+static void sha1_block(uint32_t state[5], const unsigned char *block) {
+    uint32_t w[80];
+    for (int i = 0; i < 16; i++) {
+        w[i] = (uint32_t)block[i * 4] << 24 | (uint32_t)block[i * 4 + 1] << 16 | (uint32_t)block[i * 4 + 2] << 8 | block[i * 4 + 3];
+    }
+    for (int i = 16; i < 80; i++) w[i] = SHA1_ROL(w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16], 1);
+    uint32_t a = state[0], b = state[1], c = state[2], d = state[3], e = state[4];
+    for (int i = 0; i < 80; i++) {
+        uint32_t f = 0;
+        uint32_t k = 0;
+        if (i < 20) {
+            f = (b & c) | (~b & d);
+            k = 0x5a827999;
+        } else if (i < 40) {
+            f = b ^ c ^ d;
+            k = 0x6ed9eba1;
+        } else if (i < 60) {
+            f = (b & c) | (b & d) | (c & d);
+            k = 0x8f1bbcdc;
+        } else {
+            f = b ^ c ^ d;
+            k = 0xca62c1d6;
+        }
+        uint32_t t = SHA1_ROL(a, 5) + f + e + k + w[i];
+        e = d;
+        d = c;
+        c = SHA1_ROL(b, 30);
+        b = a;
+        a = t;
+    }
+    state[0] += a;
+    state[1] += b;
+    state[2] += c;
+    state[3] += d;
+    state[4] += e;
+}
+
+void sha1_digest(const void *data, size_t len, unsigned char out[20]) {
+    uint32_t state[5] = {0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0};
+    const unsigned char *p = (const unsigned char *)data;
+    size_t remaining = len;
+    for (; remaining >= 64; remaining -= 64, p += 64) sha1_block(state, p);
+    unsigned char tail[128] = {0};
+    memcpy(tail, p, remaining);
+    tail[remaining] = 0x80;
+    size_t tail_len = remaining < 56 ? 64 : 128;
+    uint64_t bits = (uint64_t)len * 8;
+    for (int i = 0; i < 8; i++) tail[tail_len - 1 - i] = (unsigned char)(bits >> (i * 8));
+    sha1_block(state, tail);
+    if (tail_len == 128) sha1_block(state, tail + 64);
+    for (int i = 0; i < 20; i++) out[i] = (unsigned char)(state[i / 4] >> (24 - 8 * (i % 4)));
+}
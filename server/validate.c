@@ -0,0 +1,471 @@
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <sqlite3.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Data validation report. GET /v1/admin/validate walks every account's stored keys and the tables
+ * that hang off them and reports, by category:
+ *   schema     - values that are not JSON, have the wrong root, items that are not objects, lack an
+ *                id, repeat an id or carry an unreadable date;
+ *   reference  - rows pointing at items that no longer exist (metrics and AI insights for deleted
+ *                activities, live samples without a session, attachments whose owner is gone);
+ *   stale      - events in the past still marked upcoming;
+ *   privacy    - activities of accounts under data minimization that still hold raw heart-rate
+ *                samples or an original device file.
+ * POST runs the same checks and repairs the issues flagged fixable: derived rows and insights of
+ * deleted activities are dropped, past events are marked "past" and minimized fields are stripped.
+ * Anything else that would lose user data (duplicates, attachments, malformed values) is only
+ * reported.
+ */
+
+#define VALIDATE_MAX_LISTED 500
+
+typedef struct {
+    const char *key;
+    const char *id_path;
+    const char *date_path;
+    int date_required;
+} validate_schema_t;
+
+static const validate_schema_t VALIDATE_SCHEMAS[] = {
+    {"activities", "$.id", "$.date", 1},
+    {"activity_metric_insights", "$.activityID", NULL, 0},
+    {"meal_plans", "$.id", NULL, 0},
+    {"custom_foods", "$.id", NULL, 0},
+    {"workouts", "$.id", "$.scheduledDate", 0},
+    {"events", "$.id", "$.startDate", 1},
+    {"wellness_samples", "$.date", "$.date", 1},
+    {"lactate_history_records", "$.id", "$.createdAt", 1},
+    {"archived_activities", "$.id", "$.date", 1},
+};
+
+typedef struct {
+    strbuf_t issues;
+    int total;
+    int listed;
+    int schema;
+    int reference;
+    int stale;
+    int privacy;
+    int fixable;
+    int fixed;
+    int fix;
+} validate_report_t;
+
+static void add_issue(
+    validate_report_t *r,
+    const char *category,
+    const char *check,
+    const char *account_id,
+    const char *key,
+    const char *id,
+    const char *message,
+    int fixable,
+    int fixed) {
+    r->total++;
+    if (strcmp(category, "schema") == 0) r->schema++;
+    if (strcmp(category, "reference") == 0) r->reference++;
+    if (strcmp(category, "stale") == 0) r->stale++;
+    if (strcmp(category, "privacy") == 0) r->privacy++;
+    if (fixable) r->fixable++;
+    if (fixed) r->fixed++;
+    if (r->listed >= VALIDATE_MAX_LISTED) return;
+    if (r->listed++ > 0) strbuf_append(&r->issues, ",", 1);
+    strbuf_append(&r->issues, "{\"category\":\"", 13);
+    strbuf_append(&r->issues, category, strlen(category));
+    strbuf_append(&r->issues, "\",\"check\":\"", 11);
+    strbuf_append(&r->issues, check, strlen(check));
+    strbuf_append(&r->issues, "\",\"account\":", 12);
+    strbuf_append_json_string(&r->issues, account_id);
+    strbuf_append(&r->issues, ",\"key\":", 7);
+    strbuf_append_json_string(&r->issues, key);
+    strbuf_append(&r->issues, ",\"id\":", 6);
+    if (id) {
+        strbuf_append_json_string(&r->issues, id);
+    } else {
+        strbuf_append(&r->issues, "null", 4);
+    }
+    strbuf_append(&r->issues, ",\"message\":", 11);
+    strbuf_append_json_string(&r->issues, message);
+    strbuf_appendf(&r->issues, ",\"fixable\":%s,\"fixed\":%s}", fixable ? "true" : "false", fixed ? "true" : "false");
+}
+
+static const validate_schema_t *find_schema(const char *key) {
+    for (size_t i = 0; i < sizeof(VALIDATE_SCHEMAS) / sizeof(VALIDATE_SCHEMAS[0]); i++) {
+        if (strcmp(VALIDATE_SCHEMAS[i].key, key) == 0) return &VALIDATE_SCHEMAS[i];
+    }
+    return NULL;
+}
+
+static void check_collection_items(sqlite3 *db, validate_report_t *r, const char *account_id, const validate_schema_t *schema, sqlite3_stmt *row) {
+    const char *sql =
+        "SELECT e.key, e.type,"
+        " CASE WHEN e.type = 'object' THEN CAST(json_extract(e.value, ?2) AS TEXT) END,"
+        " CASE WHEN e.type = 'object' AND ?3 IS NOT NULL THEN json_type(e.value, ?3) END,"
+        " CASE WHEN e.type = 'object' AND ?3 IS NOT NULL THEN json_extract(e.value, ?3) END"
+        " FROM json_each(?1) e ORDER BY e.key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return;
+    sqlite3_bind_value(stmt, 1, sqlite3_column_value(row, 2));
+    sqlite3_bind_text(stmt, 2, schema->id_path, -1, SQLITE_STATIC);
+    if (schema->date_path) sqlite3_bind_text(stmt, 3, schema->date_path, -1, SQLITE_STATIC);
+    char message[256] = {0};
+    char index[32] = {0};
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *type = (const char *)sqlite3_column_text(stmt, 1);
+        const char *id = (const char *)sqlite3_column_text(stmt, 2);
+        snprintf(index, sizeof(index), "#%d", sqlite3_column_int(stmt, 0));
+        if (!type || strcmp(type, "object") != 0) {
+            snprintf(message, sizeof(message), "item %s is a JSON %s, not an object", index, type ? type : "null");
+            add_issue(r, "schema", "item_not_object", account_id, schema->key, index, message, 0, 0);
+            continue;
+        }
+        if (!id || id[0] == '\0') {
+            snprintf(message, sizeof(message), "item %s has no %s", index, schema->id_path + 2);
+            add_issue(r, "schema", "missing_id", account_id, schema->key, index, message, 0, 0);
+            id = index;
+        }
+        if (!schema->date_path) continue;
+        const char *date_type = (const char *)sqlite3_column_text(stmt, 3);
+        const char *date = (const char *)sqlite3_column_text(stmt, 4);
+        int day = 0;
+        if (!date_type) {
+            if (!schema->date_required) continue;
+            snprintf(message, sizeof(message), "%s is missing", schema->date_path + 2);
+        } else if (strcmp(date_type, "text") != 0 || parse_iso_day(date, &day) != 0) {
+            snprintf(message, sizeof(message), "%s is not an ISO-8601 date", schema->date_path + 2);
+        } else {
+            continue;
+        }
+        add_issue(r, "schema", "invalid_date", account_id, schema->key, id, message, 0, 0);
+    }
+    sqlite3_finalize(stmt);
+
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT CAST(json_extract(e.value, ?2) AS TEXT) AS item_id, COUNT(*) FROM json_each(?1) e"
+            " WHERE e.type = 'object' AND item_id IS NOT NULL AND item_id <> '' GROUP BY item_id HAVING COUNT(*) > 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return;
+    }
+    sqlite3_bind_value(stmt, 1, sqlite3_column_value(row, 2));
+    sqlite3_bind_text(stmt, 2, schema->id_path, -1, SQLITE_STATIC);
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *id = (const char *)sqlite3_column_text(stmt, 0);
+        snprintf(message, sizeof(message), "%s appears %d times", schema->id_path + 2, sqlite3_column_int(stmt, 1));
+        add_issue(r, "schema", "duplicate_id", account_id, schema->key, id, message, 0, 0);
+    }
+    sqlite3_finalize(stmt);
+}
+
+static void check_schemas(sqlite3 *db, validate_report_t *r, const char *only_account, int *accounts, int *keys) {
+    const char *sql =
+        "SELECT substr(data_key, 1, instr(data_key, '::') - 1) AS account, substr(data_key, instr(data_key, '::') + 2), data_value,"
+        " json_valid(data_value), CASE WHEN json_valid(data_value) THEN json_type(data_value) END"
+        " FROM kv_store WHERE instr(data_key, '::') > 1 AND (?1 IS NULL OR account = ?1) ORDER BY data_key";
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db, sql, -1, &stmt, NULL) != SQLITE_OK) return;
+    if (only_account) sqlite3_bind_text(stmt, 1, only_account, -1, SQLITE_TRANSIENT);
+    char last_account[128] = {0};
+    while (sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *account_id = (const char *)sqlite3_column_text(stmt, 0);
+        const char *key = (const char *)sqlite3_column_text(stmt, 1);
+        if (!account_id || !key || !is_valid_key(key) || strncmp(key, "exported_file_", 14) == 0) continue;
+        if (strcmp(last_account, account_id) != 0) {
+            snprintf(last_account, sizeof(last_account), "%s", account_id);
+            (*accounts)++;
+        }
+        (*keys)++;
+        if (!sqlite3_column_int(stmt, 3)) {
+            add_issue(r, "schema", "invalid_json", account_id, key, NULL, "stored value is not valid JSON", 0, 0);
+            continue;
+        }
+        const char *root = (const char *)sqlite3_column_text(stmt, 4);
+        const char *expected = api_key_is_collection(key) ? "array" : "object";
+        if (!root || strcmp(root, expected) != 0) {
+            char message[128] = {0};
+            snprintf(message, sizeof(message), "stored value is a JSON %s, expected %s", root ? root : "null", expected);
+            add_issue(r, "schema", "wrong_root_type", account_id, key, NULL, message, 0, 0);
+            continue;
+        }
+        const validate_schema_t *schema = find_schema(key);
+        if (schema) check_collection_items(db, r, account_id, schema, stmt);
+    }
+    sqlite3_finalize(stmt);
+}
+
+#define VALIDATE_KEY_ACCOUNT "substr(k.data_key, 1, instr(k.data_key, '::') - 1)"
+
+/* Re-embeds a json_each value (with its type column) in a rebuilt array without quoting objects. */
+#define VALIDATE_ITEM_JSON "CASE WHEN type IN ('object', 'array') THEN json(value) ELSE value END"
+
+/* Activity ids of the account named by the SQL expression, for NOT IN checks. */
+#define VALIDATE_ACTIVITY_IDS_SQL(account)                                                                       \
+    "(SELECT CAST(json_extract(a.value, '$.id') AS TEXT) FROM kv_store ak, json_each(ak.data_value) a"           \
+    " WHERE ak.data_key = " account " || '::activities' AND json_valid(ak.data_value)"                          \
+    " AND json_type(ak.data_value) = 'array' AND json_extract(a.value, '$.id') IS NOT NULL)"
+
+/* An event object whose end (or start) day is before today but whose status still says upcoming. */
+#define VALIDATE_PAST_UPCOMING(item)                                                                             \
+    "(json_valid(" item ") AND json_type(" item ") = 'object' AND lower(COALESCE(json_extract(" item ", '$.status'), '')) IN ('upcoming', 'planned', 'scheduled')" \
+    " AND substr(COALESCE(json_extract(" item ", '$.endDate'), json_extract(" item ", '$.startDate')), 1, 10) < date('now'))"
+
+static void check_table_references(sqlite3 *db, validate_report_t *r, const char *only_account) {
+    static const struct {
+        const char *check;
+        const char *key;
+        const char *sql;
+        const char *delete_sql;
+        const char *message;
+    } checks[] = {
+        {"orphaned_activity_metrics",
+         "activities",
+         "SELECT m.account_id, m.activity_id, COUNT(*) FROM activity_metrics m"
+         " WHERE (?1 IS NULL OR m.account_id = ?1) AND m.activity_id NOT IN " VALIDATE_ACTIVITY_IDS_SQL("m.account_id")
+             " GROUP BY m.account_id, m.activity_id",
+         "DELETE FROM activity_metrics WHERE account_id = ?1 AND activity_id = ?2",
+         "%d derived metric rows for a deleted activity"},
+        {"orphaned_live_samples",
+         "live_sessions",
+         "SELECT s.account_id, s.session_id, COUNT(*) FROM live_samples s"
+         " WHERE (?1 IS NULL OR s.account_id = ?1)"
+         " AND NOT EXISTS (SELECT 1 FROM live_sessions l WHERE l.account_id = s.account_id AND l.session_id = s.session_id)"
+         " GROUP BY s.account_id, s.session_id",
+         "DELETE FROM live_samples WHERE account_id = ?1 AND session_id = ?2",
+         "%d live samples without a session"},
+        {"orphaned_attachment",
+         "attachments",
+         "SELECT t.account_id, t.id, 1 FROM attachments t"
+         " WHERE (?1 IS NULL OR t.account_id = ?1) AND t.owner_type IN ('activity', 'event') AND NOT EXISTS ("
+         "  SELECT 1 FROM kv_store k, json_each(k.data_value) e"
+         "  WHERE k.data_key = t.account_id || '::' || CASE t.owner_type WHEN 'activity' THEN 'activities' ELSE 'events' END"
+         "  AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND CAST(json_extract(e.value, '$.id') AS TEXT) = t.owner_id)"
+         " ORDER BY t.account_id, t.created_at",
+         NULL,
+         "attachment belongs to a deleted item"},
+    };
+    for (size_t i = 0; i < sizeof(checks) / sizeof(checks[0]); i++) {
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db, checks[i].sql, -1, &stmt, NULL) != SQLITE_OK) continue;
+        if (only_account) sqlite3_bind_text(stmt, 1, only_account, -1, SQLITE_TRANSIENT);
+        while (sqlite3_step(stmt) == SQLITE_ROW) {
+            const char *account_id = (const char *)sqlite3_column_text(stmt, 0);
+            const char *id = (const char *)sqlite3_column_text(stmt, 1);
+            char message[128] = {0};
+            snprintf(message, sizeof(message), checks[i].message, sqlite3_column_int(stmt, 2));
+            int fixed = 0;
+            sqlite3_stmt *del = NULL;
+            if (r->fix && checks[i].delete_sql && sqlite3_prepare_v2(db, checks[i].delete_sql, -1, &del, NULL) == SQLITE_OK) {
+                sqlite3_bind_text(del, 1, account_id, -1, SQLITE_TRANSIENT);
+                sqlite3_bind_text(del, 2, id, -1, SQLITE_TRANSIENT);
+                fixed = sqlite3_step(del) == SQLITE_DONE;
+                sqlite3_finalize(del);
+            }
+            add_issue(r, "reference", checks[i].check, account_id, checks[i].key, id, message, checks[i].delete_sql != NULL, fixed);
+        }
+        sqlite3_finalize(stmt);
+    }
+}
+
+/*
+ * Item-level checks inside one key. select_sql yields (account, id) per issue, limited to account ?1
+ * when it is not NULL; rewrite_sql maps the stored array of key ?1 to its repaired form for account
+ * ?2. Repairs go through the normal store path so versions, snapshots and the change feed see them.
+ */
+static int rewrite_key(worker_db_t *db, const char *account_id, const char *key, const char *rewrite_sql) {
+    char storage_key[256] = {0};
+    if (build_storage_key(account_id, key, storage_key, sizeof(storage_key)) != 0) return -1;
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(db->db, rewrite_sql, -1, &stmt, NULL) != SQLITE_OK) return -1;
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, account_id, -1, SQLITE_TRANSIENT);
+    int status = -1;
+    if (sqlite3_step(stmt) == SQLITE_ROW && sqlite3_column_text(stmt, 0)) {
+        request_log_context_t fix_ctx;
+        memset(&fix_ctx, 0, sizeof(fix_ctx));
+        snprintf(fix_ctx.log_id, sizeof(fix_ctx.log_id), "admin-validate");
+        snprintf(fix_ctx.account_id, sizeof(fix_ctx.account_id), "%s", account_id);
+        char out[512] = {0};
+        status = store_account_data(
+            db, key, (const char *)sqlite3_column_text(stmt, 0), (size_t)sqlite3_column_bytes(stmt, 0), &fix_ctx, out, sizeof(out));
+        if (status != 204) log_warn("event=validate_fix_failed account=%s key=%s status=%d body=%s", account_id, key, status, out);
+    }
+    sqlite3_finalize(stmt);
+    return status == 204 ? 0 : -1;
+}
+
+static void check_item_rule(
+    worker_db_t *db,
+    validate_report_t *r,
+    const char *only_account,
+    const char *category,
+    const char *check,
+    const char *key,
+    const char *select_sql,
+    const char *rewrite_sql,
+    const char *message) {
+    char accounts_sql[4096] = {0};
+    snprintf(accounts_sql, sizeof(accounts_sql), "SELECT DISTINCT account FROM (%s)", select_sql);
+    sqlite3_stmt *accounts = NULL;
+    if (sqlite3_prepare_v2(db->db, accounts_sql, -1, &accounts, NULL) != SQLITE_OK) return;
+    if (only_account) sqlite3_bind_text(accounts, 1, only_account, -1, SQLITE_TRANSIENT);
+    while (sqlite3_step(accounts) == SQLITE_ROW) {
+        char account_id[128] = {0};
+        snprintf(account_id, sizeof(account_id), "%s", (const char *)sqlite3_column_text(accounts, 0));
+        strbuf_t ids;
+        strbuf_init(&ids);
+        int count = 0;
+        sqlite3_stmt *stmt = NULL;
+        if (sqlite3_prepare_v2(db->db, select_sql, -1, &stmt, NULL) == SQLITE_OK) {
+            sqlite3_bind_text(stmt, 1, account_id, -1, SQLITE_TRANSIENT);
+            while (sqlite3_step(stmt) == SQLITE_ROW) {
+                const char *id = (const char *)sqlite3_column_text(stmt, 1);
+                strbuf_append(&ids, id ? id : "", id ? (size_t)sqlite3_column_bytes(stmt, 1) : 0);
+                strbuf_append(&ids, "\0", 1);
+                count++;
+            }
+            sqlite3_finalize(stmt);
+        }
+        int fixed = r->fix && count > 0 && rewrite_key(db, account_id, key, rewrite_sql) == 0;
+        const char *id = ids.data;
+        for (int i = 0; i < count && id && !ids.failed; i++) {
+            add_issue(r, category, check, account_id, key, id, message, 1, fixed);
+            id += strlen(id) + 1;
+        }
+        strbuf_free(&ids);
+    }
+    sqlite3_finalize(accounts);
+}
+
+static void check_items_against_activities(worker_db_t *db, validate_report_t *r, const char *only_account) {
+    check_item_rule(
+        db,
+        r,
+        only_account,
+        "reference",
+        "orphaned_insight",
+        "activity_metric_insights",
+        "SELECT " VALIDATE_KEY_ACCOUNT " AS account, CAST(json_extract(i.value, '$.activityID') AS TEXT)"
+        " FROM kv_store k, json_each(k.data_value) i"
+        " WHERE k.data_key LIKE '%::activity_metric_insights' AND (?1 IS NULL OR " VALIDATE_KEY_ACCOUNT " = ?1)"
+        " AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND i.type = 'object'"
+        " AND json_extract(i.value, '$.activityID') IS NOT NULL"
+        " AND CAST(json_extract(i.value, '$.activityID') AS TEXT) NOT IN " VALIDATE_ACTIVITY_IDS_SQL(VALIDATE_KEY_ACCOUNT)
+        " ORDER BY k.data_key, i.key",
+        "SELECT json_group_array(" VALIDATE_ITEM_JSON ") FROM (SELECT i.value, i.type FROM kv_store k, json_each(k.data_value) i WHERE k.data_key = ?1"
+        " AND (i.type <> 'object' OR json_extract(i.value, '$.activityID') IS NULL"
+        "  OR CAST(json_extract(i.value, '$.activityID') AS TEXT) IN " VALIDATE_ACTIVITY_IDS_SQL("?2") ") ORDER BY i.key)",
+        "AI insight for a deleted activity");
+
+    check_item_rule(
+        db,
+        r,
+        only_account,
+        "stale",
+        "past_event_upcoming",
+        "events",
+        "SELECT " VALIDATE_KEY_ACCOUNT " AS account, CAST(json_extract(e.value, '$.id') AS TEXT)"
+        " FROM kv_store k, json_each(k.data_value) e"
+        " WHERE k.data_key LIKE '%::events' AND (?1 IS NULL OR " VALIDATE_KEY_ACCOUNT " = ?1)"
+        " AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND e.type = 'object'"
+        " AND " VALIDATE_PAST_UPCOMING("e.value") " ORDER BY k.data_key, e.key",
+        "SELECT json_group_array(CASE WHEN " VALIDATE_PAST_UPCOMING("value") " THEN json_set(value, '$.status', 'past') ELSE " VALIDATE_ITEM_JSON " END)"
+        " FROM (SELECT i.value, i.type FROM kv_store k, json_each(k.data_value) i WHERE k.data_key = ?1 ORDER BY i.key)",
+        "event date has passed but it is still marked upcoming");
+}
+
+/* The account named by the SQL expression opted into data minimization in its app settings. */
+#define VALIDATE_MINIMIZED_ACCOUNT(account)                                                                      \
+    "EXISTS (SELECT 1 FROM kv_store s WHERE s.data_key = " account " || '::app_settings'"                        \
+    " AND json_valid(s.data_value) AND json_type(s.data_value, '$.dataMinimization') = 'true')"
+
+static void check_minimized_items(worker_db_t *db, validate_report_t *r, const char *only_account) {
+    static const char *KEYS[] = {"activities", "archived_activities"};
+    for (size_t i = 0; i < sizeof(KEYS) / sizeof(KEYS[0]); i++) {
+        char select_sql[1024] = {0};
+        snprintf(
+            select_sql,
+            sizeof(select_sql),
+            "SELECT " VALIDATE_KEY_ACCOUNT " AS account, COALESCE(CAST(json_extract(a.value, '$.id') AS TEXT), '#' || a.key)"
+            " FROM kv_store k, json_each(k.data_value) a"
+            " WHERE k.data_key LIKE '%%::%s' AND (?1 IS NULL OR " VALIDATE_KEY_ACCOUNT " = ?1)"
+            " AND json_valid(k.data_value) AND json_type(k.data_value) = 'array' AND " DATA_MINIMIZED_ITEM_SQL("a.value", "a.type")
+            " AND %s ORDER BY k.data_key, a.key",
+            KEYS[i],
+            data_minimization_forced() ? "1" : VALIDATE_MINIMIZED_ACCOUNT(VALIDATE_KEY_ACCOUNT));
+        check_item_rule(
+            db,
+            r,
+            only_account,
+            "privacy",
+            "minimization_violation",
+            KEYS[i],
+            select_sql,
+            "SELECT json_group_array(CASE WHEN type = 'object' THEN json_remove(value, " DATA_MINIMIZED_PATHS_SQL ") ELSE " VALIDATE_ITEM_JSON " END)"
+            " FROM (SELECT i.value, i.type FROM kv_store k, json_each(k.data_value) i WHERE k.data_key = ?1 ORDER BY i.key)",
+            "raw heart-rate samples or an original device file are stored despite data minimization");
+    }
+}
+
+int handle_admin_validate(int fd, worker_db_t *db, const http_request_t *req, const request_log_context_t *ctx) {
+    int denied = admin_authorize(fd, req, ctx);
+    if (denied != 0) return denied;
+    int fix = strcmp(req->method, "POST") == 0;
+    if (!fix && strcmp(req->method, "GET") != 0) {
+        send_response_with_log_context(fd, 405, "Method Not Allowed", "{\"error\":\"method not allowed\"}", ctx);
+        return 405;
+    }
+    if (fix && !cluster_is_writer()) return cluster_reject_write(fd, ctx);
+    char account[128] = {0};
+    query_param(req->query, "account", account, sizeof(account));
+    const char *only_account = account[0] != '\0' ? account : NULL;
+
+    validate_report_t report;
+    memset(&report, 0, sizeof(report));
+    strbuf_init(&report.issues);
+    report.fix = fix;
+    int accounts = 0;
+    int keys = 0;
+    check_schemas(db->db, &report, only_account, &accounts, &keys);
+    check_table_references(db->db, &report, only_account);
+    check_items_against_activities(db, &report, only_account);
+    check_minimized_items(db, &report, only_account);
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_appendf(
+        &sb,
+        "{\"accounts\":%d,\"keys\":%d,\"fix\":%s,\"summary\":{\"issues\":%d,\"schema\":%d,\"reference\":%d,\"stale\":%d,"
+        "\"privacy\":%d,\"fixable\":%d,\"fixed\":%d},\"truncated\":%s,\"issues\":[",
+        accounts,
+        keys,
+        fix ? "true" : "false",
+        report.total,
+        report.schema,
+        report.reference,
+        report.stale,
+        report.privacy,
+        report.fixable,
+        report.fixed,
+        report.total > report.listed ? "true" : "false");
+    strbuf_append(&sb, strbuf_cstr(&report.issues), report.issues.len);
+    strbuf_append(&sb, "]}", 2);
+    int failed = sb.failed || report.issues.failed;
+    strbuf_free(&report.issues);
+    if (failed) {
+        strbuf_free(&sb);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    log_info("event=admin_validate accounts=%d keys=%d issues=%d fixed=%d logid=%s", accounts, keys, report.total, report.fixed, ctx->log_id);
+    send_response_with_log_context(fd, 200, "OK", strbuf_cstr(&sb), ctx);
+    strbuf_free(&sb);
+    return 200;
+}
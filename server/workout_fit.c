@@ -0,0 +1,532 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <math.h>
+#include <sqlite3.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/*
+ * GET /v1/workouts/<id>/export?format=fit: a planned workout (an entry of the workouts document)
+ * as a FIT workout file, which Garmin and Suunto watches load from their NewFiles drop folder and
+ * Garmin Connect imports. Every segment becomes a timed step. Its target is taken from the segment
+ * in this order: targetHeartRate (bpm, +-5), targetPaceSecPerKm / targetPaceSecPer100m (+-2.5 %
+ * of the speed), then intensityPercentFTP as a power range of +-5 points of FTP for cycling (the
+ * watch applies its own FTP; targetWatts in absolute watts when there is no percentage), or as a
+ * pace from the sport's threshold pace or CSS for running and swimming. Anything else is an open
+ * step; a cadence becomes the secondary target. Repeats come from {"repeat": N, "segments": [...]}
+ * groups, and a run of two to four segments that is repeated back to back in a flat list (the
+ * app's interval sets) is folded into a repeat step too. A FIT workout has one sport, so a brick
+ * keeps the workout's, but each step's target follows its segment's "sport".
+ */
+
+#define FIT_EPOCH_OFFSET 631065600LL
+#define FIT_PROFILE_VERSION 2132
+#define FIT_MESG_FILE_ID 0
+#define FIT_MESG_WORKOUT 26
+#define FIT_MESG_WORKOUT_STEP 27
+#define FIT_FILE_WORKOUT 5
+#define FIT_MANUFACTURER_DEVELOPMENT 255
+
+#define FIT_DURATION_TIME 0
+#define FIT_DURATION_REPEAT_UNTIL_STEPS_CMPLT 6
+#define FIT_TARGET_SPEED 0
+#define FIT_TARGET_HEART_RATE 1
+#define FIT_TARGET_OPEN 2
+#define FIT_TARGET_CADENCE 3
+#define FIT_TARGET_POWER 4
+#define FIT_INTENSITY_ACTIVE 0
+#define FIT_INTENSITY_REST 1
+#define FIT_INTENSITY_WARMUP 2
+#define FIT_INTENSITY_COOLDOWN 3
+
+#define FIT_INVALID_ENUM 0xFFu
+#define FIT_INVALID_UINT32 0xFFFFFFFFu
+#define FIT_STEP_NAME_BYTES 32
+#define FIT_STEP_NOTES_BYTES 64
+#define FIT_WORKOUT_NAME_BYTES 32
+
+#define WORKOUT_FIT_MAX_STEPS 200
+#define WORKOUT_FIT_MAX_REPEAT 99
+
+typedef struct {
+    uint32_t duration_ms;
+    double percent;
+    unsigned target_type;
+    uint32_t low;
+    uint32_t high;
+    uint32_t cadence;
+    char note[FIT_STEP_NOTES_BYTES];
+} fit_step_t;
+
+/* A run of steps played repeat times; plain segments are blocks of one step played once. */
+typedef struct {
+    int first;
+    int count;
+    int repeat;
+} fit_block_t;
+
+typedef struct {
+    const char *account_id;
+    const char *sport;
+    sport_settings_t settings;
+    fit_step_t steps[WORKOUT_FIT_MAX_STEPS];
+    int step_count;
+    fit_block_t blocks[WORKOUT_FIT_MAX_STEPS];
+    int block_count;
+} fit_plan_t;
+
+static uint16_t fit_crc16(uint16_t crc, const unsigned char *data, size_t len) {
+    static const uint16_t table[16] = {
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    };
+    for (size_t i = 0; i < len; i++) {
+        uint16_t tmp = table[crc & 0xF];
+        crc = (uint16_t)((crc >> 4) & 0x0FFF);
+        crc = (uint16_t)(crc ^ tmp ^ table[data[i] & 0xF]);
+        tmp = table[crc & 0xF];
+        crc = (uint16_t)((crc >> 4) & 0x0FFF);
+        crc = (uint16_t)(crc ^ tmp ^ table[(data[i] >> 4) & 0xF]);
+    }
+    return crc;
+}
+
+static void put_u8(strbuf_t *sb, unsigned v) {
+    char byte = (char)(v & 0xFF);
+    strbuf_append(sb, &byte, 1);
+}
+
+static void put_u16(strbuf_t *sb, unsigned v) {
+    char bytes[2] = {(char)(v & 0xFF), (char)((v >> 8) & 0xFF)};
+    strbuf_append(sb, bytes, 2);
+}
+
+static void put_u32(strbuf_t *sb, uint32_t v) {
+    char bytes[4] = {(char)(v & 0xFF), (char)((v >> 8) & 0xFF), (char)((v >> 16) & 0xFF), (char)((v >> 24) & 0xFF)};
+    strbuf_append(sb, bytes, 4);
+}
+
+/* A NUL-padded string field, cut at a UTF-8 character boundary. */
+static void put_string(strbuf_t *sb, const char *s, size_t size) {
+    char field[FIT_STEP_NOTES_BYTES] = {0};
+    size_t len = strlen(s);
+    if (len > size - 1) {
+        len = size - 1;
+        while (len > 0 && ((unsigned char)s[len] & 0xC0) == 0x80) len--;
+    }
+    memcpy(field, s, len);
+    strbuf_append(sb, field, size);
+}
+
+/* Definition message for local type local of global message global; fields are {number, size, base type}. */
+static void put_definition(strbuf_t *sb, unsigned local, unsigned global, const unsigned char (*fields)[3], size_t count) {
+    put_u8(sb, 0x40 | local);
+    put_u8(sb, 0);
+    put_u8(sb, 0);
+    put_u16(sb, global);
+    put_u8(sb, (unsigned)count);
+    for (size_t i = 0; i < count; i++) {
+        put_u8(sb, fields[i][0]);
+        put_u8(sb, fields[i][1]);
+        put_u8(sb, fields[i][2]);
+    }
+}
+
+static const unsigned char FILE_ID_FIELDS[][3] = {{0, 1, 0x00}, {1, 2, 0x84}, {2, 2, 0x84}, {3, 4, 0x8C}, {4, 4, 0x86}};
+static const unsigned char WORKOUT_FIELDS[][3] = {{8, FIT_WORKOUT_NAME_BYTES, 0x07}, {4, 1, 0x00}, {11, 1, 0x00}, {6, 2, 0x84}};
+static const unsigned char STEP_FIELDS[][3] = {
+    {254, 2, 0x84}, {0, FIT_STEP_NAME_BYTES, 0x07}, {1, 1, 0x00}, {2, 4, 0x86}, {3, 1, 0x00}, {4, 4, 0x86}, {5, 4, 0x86},
+    {6, 4, 0x86}, {7, 1, 0x00}, {8, FIT_STEP_NOTES_BYTES, 0x07}, {19, 1, 0x00}, {20, 4, 0x86}, {21, 4, 0x86}, {22, 4, 0x86},
+};
+
+static void put_step(
+    strbuf_t *sb, unsigned index, const char *name, unsigned duration_type, uint32_t duration_value, unsigned target_type, uint32_t target_value,
+    uint32_t low, uint32_t high, unsigned intensity, uint32_t cadence) {
+    put_u8(sb, 2);
+    put_u16(sb, index);
+    put_string(sb, name, FIT_STEP_NAME_BYTES);
+    put_u8(sb, duration_type);
+    put_u32(sb, duration_value);
+    put_u8(sb, target_type);
+    put_u32(sb, target_value);
+    put_u32(sb, low);
+    put_u32(sb, high);
+    put_u8(sb, intensity);
+    put_string(sb, name, FIT_STEP_NOTES_BYTES);
+    int secondary = cadence > 0 && target_type != FIT_TARGET_CADENCE;
+    put_u8(sb, secondary ? FIT_TARGET_CADENCE : FIT_INVALID_ENUM);
+    put_u32(sb, secondary ? 0 : FIT_INVALID_UINT32);
+    put_u32(sb, secondary ? (cadence > 5 ? cadence - 5 : 0) : FIT_INVALID_UINT32);
+    put_u32(sb, secondary ? cadence + 5 : FIT_INVALID_UINT32);
+}
+
+static void fit_sport_codes(const char *sport, unsigned *out_sport, unsigned *out_sub_sport) {
+    *out_sub_sport = 0;
+    if (strcmp(sport, "running") == 0) {
+        *out_sport = 1;
+    } else if (strcmp(sport, "swimming") == 0) {
+        *out_sport = 5;
+        *out_sub_sport = 17;
+    } else if (strcmp(sport, "strength") == 0) {
+        *out_sport = 10;
+        *out_sub_sport = 20;
+    } else {
+        *out_sport = 2;
+    }
+}
+
+static void speed_range(fit_step_t *step, double meters, double seconds) {
+    double speed = meters / seconds;
+    step->target_type = FIT_TARGET_SPEED;
+    step->low = (uint32_t)lround(speed * 0.975 * 1000.0);
+    step->high = (uint32_t)lround(speed * 1.025 * 1000.0);
+}
+
+/* Columns of a segment row: minutes, intensityPercentFTP, cadence, note, targetWatts, targetPaceSecPerKm, targetPaceSecPer100m, targetHeartRate. */
+static void fit_step_targets(const char *sport, const sport_settings_t *settings, sqlite3_stmt *row, fit_step_t *step) {
+    double percent = sqlite3_column_double(row, 1);
+    double watts = sqlite3_column_double(row, 4);
+    double pace_km = sqlite3_column_double(row, 5);
+    double pace_100m = sqlite3_column_double(row, 6);
+    double heart_rate = sqlite3_column_double(row, 7);
+    int running = strcmp(sport, "running") == 0;
+    int swimming = strcmp(sport, "swimming") == 0;
+    step->target_type = FIT_TARGET_OPEN;
+    step->low = FIT_INVALID_UINT32;
+    step->high = FIT_INVALID_UINT32;
+    if (heart_rate > 0.0) {
+        step->target_type = FIT_TARGET_HEART_RATE;
+        step->low = (uint32_t)lround(heart_rate) + 100 - 5;
+        step->high = (uint32_t)lround(heart_rate) + 100 + 5;
+    } else if (pace_km > 0.0 && !swimming) {
+        speed_range(step, 1000.0, pace_km);
+    } else if (pace_100m > 0.0 && swimming) {
+        speed_range(step, 100.0, pace_100m);
+    } else if (running && percent > 0.0 && settings->threshold_pace_sec_per_km > 0.0) {
+        speed_range(step, 1000.0, settings->threshold_pace_sec_per_km * 100.0 / percent);
+    } else if (swimming && percent > 0.0 && settings->css_sec_per_100m > 0.0) {
+        speed_range(step, 100.0, settings->css_sec_per_100m * 100.0 / percent);
+    } else if (!running && !swimming && strcmp(sport, "strength") != 0 && percent > 0.0) {
+        long rounded = lround(percent);
+        step->target_type = FIT_TARGET_POWER;
+        step->low = (uint32_t)(rounded > 5 ? rounded - 5 : 0);
+        step->high = (uint32_t)(rounded + 5);
+    } else if (!running && !swimming && watts > 0.0) {
+        long rounded = lround(watts);
+        step->target_type = FIT_TARGET_POWER;
+        step->low = (uint32_t)(1000 + (rounded > 5 ? rounded - 5 : 0));
+        step->high = (uint32_t)(1000 + rounded + 5);
+    }
+    if (step->target_type == FIT_TARGET_OPEN && step->cadence > 0) {
+        step->target_type = FIT_TARGET_CADENCE;
+        step->low = step->cadence > 5 ? step->cadence - 5 : 0;
+        step->high = step->cadence + 5;
+    }
+}
+
+/*
+ * Adds the segments of segments_json (a JSON array) to plan; nested holds inside a repeat group. A
+ * segment's own "sport" (a brick's run leg, say) picks its targets, else the group's or the workout's.
+ */
+static int fit_collect(sqlite3 *db, const char *segments_json, const char *sport, int nested, fit_plan_t *plan, const char **error) {
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db,
+            "SELECT COALESCE(json_extract(value, '$.minutes'), 0), COALESCE(json_extract(value, '$.intensityPercentFTP'), 0),"
+            " COALESCE(json_extract(value, '$.cadence'), 0), json_extract(value, '$.note'), json_extract(value, '$.targetWatts'),"
+            " json_extract(value, '$.targetPaceSecPerKm'), json_extract(value, '$.targetPaceSecPer100m'), json_extract(value, '$.targetHeartRate'),"
+            " json_type(value, '$.segments'), json_extract(value, '$.segments'), COALESCE(json_extract(value, '$.repeat'), 1),"
+            " json_extract(value, '$.sport') FROM json_each(?1) ORDER BY key",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        return -1;
+    }
+    sqlite3_bind_text(stmt, 1, segments_json, -1, SQLITE_TRANSIENT);
+    int rc = 0;
+    while (rc == 0 && sqlite3_step(stmt) == SQLITE_ROW) {
+        const char *row_sport = sport_canonical_name((const char *)sqlite3_column_text(stmt, 11));
+        if (!row_sport) row_sport = sport;
+        const char *group_type = (const char *)sqlite3_column_text(stmt, 8);
+        if (group_type && strcmp(group_type, "array") == 0) {
+            int repeat = sqlite3_column_int(stmt, 10);
+            if (nested) {
+                *error = "{\"error\":\"repeat groups cannot be nested\"}";
+                rc = 400;
+            } else if (repeat < 1 || repeat > WORKOUT_FIT_MAX_REPEAT) {
+                *error = "{\"error\":\"repeat must be 1..99\"}";
+                rc = 400;
+            } else {
+                int first = plan->step_count;
+                rc = fit_collect(db, (const char *)sqlite3_column_text(stmt, 9), row_sport, 1, plan, error);
+                if (rc == 0 && plan->step_count > first) plan->blocks[plan->block_count++] = (fit_block_t){first, plan->step_count - first, repeat};
+            }
+            continue;
+        }
+        double minutes = sqlite3_column_double(stmt, 0);
+        if (minutes <= 0.0) continue;
+        if (plan->step_count >= WORKOUT_FIT_MAX_STEPS) {
+            *error = "{\"error\":\"workout has more than 200 steps\"}";
+            rc = 400;
+            break;
+        }
+        fit_step_t *step = &plan->steps[plan->step_count];
+        memset(step, 0, sizeof(*step));
+        step->duration_ms = (uint32_t)lround(minutes * 60.0) * 1000u;
+        step->percent = sqlite3_column_double(stmt, 1);
+        int cadence = sqlite3_column_int(stmt, 2);
+        step->cadence = cadence > 0 && cadence < 250 ? (uint32_t)cadence : 0;
+        const char *note = (const char *)sqlite3_column_text(stmt, 3);
+        snprintf(step->note, sizeof(step->note), "%s", note ? note : "");
+        sport_settings_t other;
+        const sport_settings_t *settings = &plan->settings;
+        if (strcmp(row_sport, plan->sport) != 0) {
+            load_sport_settings(db, plan->account_id, row_sport, &other);
+            settings = &other;
+        }
+        fit_step_targets(row_sport, settings, stmt, step);
+        if (!nested) plan->blocks[plan->block_count++] = (fit_block_t){plan->step_count, 1, 1};
+        plan->step_count++;
+    }
+    sqlite3_finalize(stmt);
+    return rc;
+}
+
+static int steps_equal(const fit_step_t *a, const fit_step_t *b) {
+    return a->duration_ms == b->duration_ms && a->target_type == b->target_type && a->low == b->low && a->high == b->high &&
+           a->cadence == b->cadence && strcmp(a->note, b->note) == 0;
+}
+
+/* Blocks i..i+len, all plain, repeated back to back: how many times (1 when not at all). */
+static int pattern_repeats(const fit_plan_t *plan, int i, int len) {
+    for (int j = i; j < i + len; j++) {
+        if (j >= plan->block_count || plan->blocks[j].repeat != 1 || plan->blocks[j].count != 1) return 1;
+    }
+    int times = 1;
+    while (i + (times + 1) * len <= plan->block_count) {
+        int k = 0;
+        while (k < len) {
+            const fit_block_t *b = &plan->blocks[i + times * len + k];
+            if (b->repeat != 1 || b->count != 1 || !steps_equal(&plan->steps[b->first], &plan->steps[plan->blocks[i + k].first])) break;
+            k++;
+        }
+        if (k < len) break;
+        times++;
+    }
+    return times;
+}
+
+static void fold_repeats(fit_plan_t *plan) {
+    int out = 0;
+    int i = 0;
+    while (i < plan->block_count) {
+        int best_len = 0;
+        int best_times = 1;
+        for (int len = 2; len <= 4; len++) {
+            int times = pattern_repeats(plan, i, len);
+            if (times >= 2 && len * times > best_len * best_times) {
+                best_len = len;
+                best_times = times;
+            }
+        }
+        if (best_len > 0) {
+            /* Plain blocks are consecutive steps, so the first repetition is one block. */
+            plan->blocks[out++] = (fit_block_t){plan->blocks[i].first, best_len, best_times};
+            i += best_len * best_times;
+        } else {
+            plan->blocks[out++] = plan->blocks[i++];
+        }
+    }
+    plan->block_count = out;
+}
+
+static unsigned step_intensity(const fit_plan_t *plan, int block, const fit_step_t *step) {
+    const fit_block_t *b = &plan->blocks[block];
+    int easy = step->percent > 0.0 && step->percent <= 75.0;
+    if (b->repeat == 1 && easy && block == 0 && plan->block_count > 1) return FIT_INTENSITY_WARMUP;
+    if (b->repeat == 1 && easy && block == plan->block_count - 1 && plan->block_count > 1) return FIT_INTENSITY_COOLDOWN;
+    if (step->percent > 0.0 && step->percent < 60.0) return FIT_INTENSITY_REST;
+    return FIT_INTENSITY_ACTIVE;
+}
+
+static int fit_encode(const fit_plan_t *plan, const char *name, strbuf_t *out) {
+    unsigned sport = 0;
+    unsigned sub_sport = 0;
+    fit_sport_codes(plan->sport, &sport, &sub_sport);
+    unsigned step_total = 0;
+    for (int i = 0; i < plan->block_count; i++) {
+        step_total += (unsigned)plan->blocks[i].count + (plan->blocks[i].repeat > 1 ? 1u : 0u);
+    }
+
+    strbuf_t sb;
+    strbuf_init(&sb);
+    unsigned char header[14] = {14, 0x20, FIT_PROFILE_VERSION & 0xFF, FIT_PROFILE_VERSION >> 8, 0, 0, 0, 0, '.', 'F', 'I', 'T', 0, 0};
+    strbuf_append(&sb, (const char *)header, sizeof(header));
+    put_definition(&sb, 0, FIT_MESG_FILE_ID, FILE_ID_FIELDS, sizeof(FILE_ID_FIELDS) / sizeof(FILE_ID_FIELDS[0]));
+    put_u8(&sb, 0);
+    put_u8(&sb, FIT_FILE_WORKOUT);
+    put_u16(&sb, FIT_MANUFACTURER_DEVELOPMENT);
+    put_u16(&sb, 0);
+    put_u32(&sb, 1);
+    put_u32(&sb, (uint32_t)((long long)time(NULL) - FIT_EPOCH_OFFSET));
+    put_definition(&sb, 1, FIT_MESG_WORKOUT, WORKOUT_FIELDS, sizeof(WORKOUT_FIELDS) / sizeof(WORKOUT_FIELDS[0]));
+    put_u8(&sb, 1);
+    put_string(&sb, name, FIT_WORKOUT_NAME_BYTES);
+    put_u8(&sb, sport);
+    put_u8(&sb, sub_sport);
+    put_u16(&sb, step_total);
+    put_definition(&sb, 2, FIT_MESG_WORKOUT_STEP, STEP_FIELDS, sizeof(STEP_FIELDS) / sizeof(STEP_FIELDS[0]));
+
+    unsigned index = 0;
+    for (int i = 0; i < plan->block_count; i++) {
+        const fit_block_t *b = &plan->blocks[i];
+        unsigned first = index;
+        for (int j = b->first; j < b->first + b->count; j++) {
+            const fit_step_t *s = &plan->steps[j];
+            put_step(
+                &sb, index++, s->note, FIT_DURATION_TIME, s->duration_ms, s->target_type, 0, s->low, s->high, step_intensity(plan, i, s), s->cadence);
+        }
+        if (b->repeat > 1) {
+            put_step(
+                &sb,
+                index++,
+                "",
+                FIT_DURATION_REPEAT_UNTIL_STEPS_CMPLT,
+                first,
+                FIT_INVALID_ENUM,
+                (uint32_t)b->repeat,
+                FIT_INVALID_UINT32,
+                FIT_INVALID_UINT32,
+                FIT_INVALID_ENUM,
+                0);
+        }
+    }
+    if (sb.failed) {
+        strbuf_free(&sb);
+        return -1;
+    }
+    uint32_t data_size = (uint32_t)(sb.len - sizeof(header));
+    unsigned char *bytes = (unsigned char *)sb.data;
+    for (int i = 0; i < 4; i++) bytes[4 + i] = (unsigned char)((data_size >> (8 * i)) & 0xFF);
+    uint16_t header_crc = fit_crc16(0, bytes, 12);
+    bytes[12] = (unsigned char)(header_crc & 0xFF);
+    bytes[13] = (unsigned char)(header_crc >> 8);
+    put_u16(&sb, fit_crc16(0, (const unsigned char *)sb.data, sb.len));
+    if (sb.failed) {
+        strbuf_free(&sb);
+        return -1;
+    }
+    *out = sb;
+    return 0;
+}
+
+/* "Tempo Tuesday!" -> "Tempo-Tuesday"; empty names fall back to "workout". */
+static void fit_filename(const char *name, char *out, size_t out_len) {
+    size_t n = 0;
+    int dash = 0;
+    for (const char *p = name; *p && n + 5 < out_len; p++) {
+        char c = *p;
+        if ((c >= 'A' && c <= 'Z') || (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || c == '_') {
+            if (dash && n > 0) out[n++] = '-';
+            out[n++] = c;
+            dash = 0;
+        } else {
+            dash = 1;
+        }
+    }
+    if (n == 0) n = (size_t)snprintf(out, out_len, "workout");
+    snprintf(out + n, out_len - n, ".fit");
+}
+
+int handle_get_workout_export(int fd, worker_db_t *db, const http_request_t *req, const char *workout_id, const request_log_context_t *ctx) {
+    char format[16] = {0};
+    if (query_param(req->query, "format", format, sizeof(format)) && strcmp(format, "fit") != 0) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"format must be fit\"}", ctx);
+        return 400;
+    }
+    if (!is_valid_item_id(workout_id)) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"invalid workout id\"}", ctx);
+        return 400;
+    }
+    char storage_key[256] = {0};
+    if (build_storage_key(ctx->account_id, "workouts", storage_key, sizeof(storage_key)) != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"invalid account key\"}", ctx);
+        return 500;
+    }
+    sqlite3_stmt *stmt = NULL;
+    if (sqlite3_prepare_v2(
+            db->db,
+            "SELECT COALESCE(json_extract(w.value, '$.name'), 'Workout'), COALESCE(json_extract(w.value, '$.sport'), 'cycling'),"
+            " COALESCE(json_extract(w.value, '$.segments'), '[]') FROM kv_store k, json_each(k.data_value) w"
+            " WHERE k.data_key = ?1 AND json_valid(k.data_value) AND json_type(k.data_value) = 'array'"
+            " AND CAST(json_extract(w.value, '$.id') AS TEXT) = ?2 LIMIT 1",
+            -1,
+            &stmt,
+            NULL) != SQLITE_OK) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"database error\"}", ctx);
+        return 500;
+    }
+    sqlite3_bind_text(stmt, 1, storage_key, -1, SQLITE_TRANSIENT);
+    sqlite3_bind_text(stmt, 2, workout_id, -1, SQLITE_TRANSIENT);
+    if (sqlite3_step(stmt) != SQLITE_ROW) {
+        sqlite3_finalize(stmt);
+        send_response_with_log_context(fd, 404, "Not Found", "{\"error\":\"unknown workout\"}", ctx);
+        return 404;
+    }
+    char name[128] = {0};
+    char sport[32] = {0};
+    snprintf(name, sizeof(name), "%s", (const char *)sqlite3_column_text(stmt, 0));
+    const char *canonical = sport_canonical_name((const char *)sqlite3_column_text(stmt, 1));
+    snprintf(sport, sizeof(sport), "%s", canonical ? canonical : "cycling");
+    char *segments = strdup((const char *)sqlite3_column_text(stmt, 2));
+    sqlite3_finalize(stmt);
+
+    fit_plan_t *plan = (fit_plan_t *)calloc(1, sizeof(fit_plan_t));
+    if (!segments || !plan) {
+        free(segments);
+        free(plan);
+        send_response_with_log_context(fd, 500, "Internal Server Error", "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+    plan->account_id = ctx->account_id;
+    plan->sport = sport;
+    load_sport_settings(db->db, ctx->account_id, sport, &plan->settings);
+    const char *error = NULL;
+    int rc = fit_collect(db->db, segments, sport, 0, plan, &error);
+    free(segments);
+    if (rc == 0 && plan->step_count == 0) {
+        error = "{\"error\":\"workout has no timed segments\"}";
+        rc = 400;
+    }
+    strbuf_t file;
+    strbuf_init(&file);
+    if (rc == 0) {
+        fold_repeats(plan);
+        rc = fit_encode(plan, name, &file);
+    }
+    int steps = plan->step_count;
+    free(plan);
+    if (rc == 400) {
+        send_response_with_log_context(fd, 400, "Bad Request", error, ctx);
+        return 400;
+    }
+    if (rc != 0) {
+        send_response_with_log_context(fd, 500, "Internal Server Error", rc < 0 ? "{\"error\":\"database error\"}" : "{\"error\":\"oom\"}", ctx);
+        return 500;
+    }
+
+    char filename[96] = {0};
+    fit_filename(name, filename, sizeof(filename));
+    char headers[160] = {0};
+    snprintf(headers, sizeof(headers), "Content-Disposition: attachment; filename=\"%s\"\r\n", filename);
+    send_http_response(fd, 200, "OK", "application/vnd.ant.fit", headers, file.data, file.len, ctx);
+    log_info("WORKOUT export format=fit id=%s steps=%d bytes=%zu account=%s logid=%s", workout_id, steps, file.len, ctx->account_id, ctx->log_id);
+    strbuf_free(&file);
+    return 200;
+}
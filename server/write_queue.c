@@ -18,7 +18,30 @@
 #include <time.h>
 #include <unistd.h>
 
+/*
+ * A store upserts a document and a batch several in one transaction; a refresh recomputes what
+ * derives from a key once earlier writes committed; a call runs a caller's function on the writer
+ * connection for tables outside kv_store; a barrier only waits.
+ */
+typedef enum {
+    WRITE_JOB_STORE = 0,
+    WRITE_JOB_BATCH,
+    WRITE_JOB_REFRESH,
+    WRITE_JOB_CALL,
+    WRITE_JOB_BARRIER,
+} write_job_kind_t;
+
+typedef struct {
+    char logical_key[128];
+    char storage_key[256];
+    char pending_path[512];
+    char *payload;
+    size_t payload_len;
+    write_batch_action_t action;
+} write_batch_entry_t;
+
 typedef struct write_job {
+    write_job_kind_t kind;
     char logical_key[128];
     char storage_key[256];
     char account_id[128];
@@ -26,6 +49,11 @@ typedef struct write_job {
     char pending_path[512];
     char *payload;
     size_t payload_len;
+    write_batch_entry_t *batch;
+    size_t batch_count;
+    write_call_fn call;
+    void *call_arg;
+    void (*call_free)(void *arg);
     int refcount;
     int completed;
     int abandoned;
@@ -33,6 +61,8 @@ typedef struct write_job {
     int sqlite_rc;
     int sqlite_ext;
     int retry_count;
+    double enqueued_ms;
+    double started_ms;
     char backup_path[512];
     pthread_mutex_t mutex;
     pthread_cond_t cond;
@@ -48,6 +78,7 @@ typedef struct {
     int refcount;
     sqlite3 *db;
     sqlite3_stmt *upsert_stmt;
+    long long db_generation;
     char db_path[512];
     int queue_depth;
     char last_success_logid[96];
@@ -135,7 +166,10 @@ static void write_job_release(write_job_t *job) {
 
     pthread_cond_destroy(&job->cond);
     pthread_mutex_destroy(&job->mutex);
+    for (size_t i = 0; i < job->batch_count; i++) free(job->batch[i].payload);
+    free(job->batch);
     free(job->payload);
+    if (job->call_free) job->call_free(job->call_arg);
     free(job);
 }
 
@@ -153,8 +187,13 @@ static void abandon_job(write_job_t *job) {
     pthread_mutex_unlock(&job->mutex);
 }
 
+static void dispatcher_close_db(write_dispatcher_t *dispatcher);
+
 static int dispatcher_open_db(write_dispatcher_t *dispatcher) {
+    /* A restore swapped the database since this connection was opened. */
+    if (dispatcher->db && dispatcher->db_generation != db_pool_generation()) dispatcher_close_db(dispatcher);
     if (dispatcher->db) return 0;
+    dispatcher->db_generation = db_pool_generation();
 
     if (sqlite3_open_v2(
             dispatcher->db_path,
@@ -167,6 +206,7 @@ static int dispatcher_open_db(write_dispatcher_t *dispatcher) {
         return -1;
     }
 
+    slowlog_attach(dispatcher->db);
     sqlite3_exec(dispatcher->db, "PRAGMA busy_timeout=250;", NULL, NULL, NULL);
     sqlite3_exec(dispatcher->db, "PRAGMA synchronous=FULL;", NULL, NULL, NULL);
     sqlite3_exec(dispatcher->db, "PRAGMA fullfsync=ON;", NULL, NULL, NULL);
@@ -196,6 +236,123 @@ static void dispatcher_close_db(write_dispatcher_t *dispatcher) {
     dispatcher->db = NULL;
 }
 
+static int sqlite_is_busy(int rc, int ext) {
+    return rc == SQLITE_BUSY || rc == SQLITE_LOCKED || ext == SQLITE_BUSY_SNAPSHOT || ext == SQLITE_BUSY_TIMEOUT;
+}
+
+/* One attempt at a batch: SQLITE_DONE once committed, otherwise the failing rc with nothing written. */
+static int dispatcher_apply_batch(write_dispatcher_t *dispatcher, write_job_t *job) {
+    int rc = sqlite3_exec(dispatcher->db, "BEGIN IMMEDIATE", NULL, NULL, NULL);
+    if (rc != SQLITE_OK) return rc;
+    sqlite3_stmt *probe = NULL;
+    rc = sqlite3_prepare_v2(dispatcher->db, "SELECT data_value FROM kv_store WHERE data_key = ?1", -1, &probe, NULL);
+    if (rc == SQLITE_OK) rc = SQLITE_DONE;
+    /* The actions are decided inside the transaction, so "unchanged" cannot race another writer. */
+    for (size_t i = 0; i < job->batch_count && rc == SQLITE_DONE; i++) {
+        write_batch_entry_t *entry = &job->batch[i];
+        sqlite3_reset(probe);
+        sqlite3_bind_text(probe, 1, entry->storage_key, -1, SQLITE_TRANSIENT);
+        int step = sqlite3_step(probe);
+        if (step == SQLITE_ROW) {
+            const char *stored = (const char *)sqlite3_column_text(probe, 0);
+            size_t stored_len = (size_t)sqlite3_column_bytes(probe, 0);
+            int same = stored && stored_len == entry->payload_len && memcmp(stored, entry->payload, stored_len) == 0;
+            entry->action = same ? WRITE_BATCH_UNCHANGED : WRITE_BATCH_REPLACED;
+        } else if (step == SQLITE_DONE) {
+            entry->action = WRITE_BATCH_CREATED;
+        } else {
+            rc = step;
+            break;
+        }
+        if (entry->action == WRITE_BATCH_UNCHANGED) continue;
+        sqlite3_reset(dispatcher->upsert_stmt);
+        sqlite3_clear_bindings(dispatcher->upsert_stmt);
+        sqlite3_bind_text(dispatcher->upsert_stmt, 1, entry->storage_key, -1, SQLITE_TRANSIENT);
+        sqlite3_bind_text(dispatcher->upsert_stmt, 2, entry->payload, -1, SQLITE_STATIC);
+        rc = sqlite3_step(dispatcher->upsert_stmt);
+    }
+    sqlite3_finalize(probe);
+    sqlite3_reset(dispatcher->upsert_stmt);
+    if (rc == SQLITE_DONE) {
+        int commit_rc = sqlite3_exec(dispatcher->db, "COMMIT", NULL, NULL, NULL);
+        if (commit_rc != SQLITE_OK) rc = commit_rc;
+    }
+    if (rc != SQLITE_DONE && !sqlite3_get_autocommit(dispatcher->db)) sqlite3_exec(dispatcher->db, "ROLLBACK", NULL, NULL, NULL);
+    return rc;
+}
+
+/*
+ * Runs a batch to completion, retrying while the database is busy. Its journal entries go either
+ * way: replaying part of a batch the caller was told failed would break its all-or-nothing.
+ */
+static void dispatcher_run_batch(write_dispatcher_t *dispatcher, write_job_t *job) {
+    while (1) {
+        int rc = dispatcher_apply_batch(dispatcher, job);
+        int ext = sqlite3_extended_errcode(dispatcher->db);
+        if (rc != SQLITE_DONE && sqlite_is_busy(rc, ext)) {
+            job->retry_count++;
+            log_warn("DATA WRITE retrying batch keys=%zu account=%s logid=%s attempt=%d rc=%d", job->batch_count, job->account_id, job->log_id, job->retry_count, rc);
+            pthread_mutex_lock(&dispatcher->mutex);
+            int should_stop = dispatcher->stopping;
+            pthread_mutex_unlock(&dispatcher->mutex);
+            if (should_stop) {
+                abandon_job(job);
+                return;
+            }
+            struct timespec ts = {.tv_sec = 0, .tv_nsec = (long)(20000000 * (job->retry_count < 10 ? job->retry_count : 10))};
+            nanosleep(&ts, NULL);
+            continue;
+        }
+
+        int cleaned = 1;
+        for (size_t i = 0; i < job->batch_count; i++) {
+            if (remove_pending_write(job->batch[i].pending_path) != 0) cleaned = 0;
+        }
+        if (rc != SQLITE_DONE) {
+            job->status_code = 500;
+            job->sqlite_rc = rc;
+            job->sqlite_ext = ext;
+            log_error(
+                "DATA WRITE failed batch keys=%zu reason=sqlite_step_error rc=%d rc_name=%s ext=%d errmsg=%s account=%s logid=%s retries=%d",
+                job->batch_count,
+                rc,
+                sqlite3_errstr(rc),
+                ext,
+                sqlite3_errmsg(dispatcher->db),
+                job->account_id,
+                job->log_id,
+                job->retry_count);
+        } else if (!cleaned) {
+            job->status_code = 500;
+            log_error("DATA WRITE failed batch keys=%zu reason=pending_write_cleanup_failed account=%s logid=%s", job->batch_count, job->account_id, job->log_id);
+        } else {
+            job->status_code = 204;
+            size_t written = 0;
+            for (size_t i = 0; i < job->batch_count; i++) {
+                if (job->batch[i].action == WRITE_BATCH_UNCHANGED) continue;
+                change_events_emit_local(job->account_id, job->batch[i].logical_key, job->batch[i].payload, job->batch[i].payload_len);
+                written++;
+            }
+            log_info(
+                "DATA WRITE batch status=stored keys=%zu written=%zu account=%s logid=%s retries=%d",
+                job->batch_count,
+                written,
+                job->account_id,
+                job->log_id,
+                job->retry_count);
+        }
+        pthread_mutex_lock(&dispatcher->mutex);
+        if (job->status_code == 204) {
+            snprintf(dispatcher->last_success_logid, sizeof(dispatcher->last_success_logid), "%s", job->log_id);
+        } else {
+            snprintf(dispatcher->last_error_logid, sizeof(dispatcher->last_error_logid), "%s", job->log_id);
+        }
+        pthread_mutex_unlock(&dispatcher->mutex);
+        finalize_job(job);
+        return;
+    }
+}
+
 static void *write_dispatcher_thread_entry(void *arg) {
     write_dispatcher_t *dispatcher = (write_dispatcher_t *)arg;
 
@@ -223,6 +380,7 @@ static void *write_dispatcher_thread_entry(void *arg) {
         pthread_mutex_unlock(&dispatcher->mutex);
 
         if (!job) continue;
+        job->started_ms = slowlog_now_ms();
         if (stopping) {
             abandon_job(job);
             write_job_release(job);
@@ -239,6 +397,32 @@ static void *write_dispatcher_thread_entry(void *arg) {
             continue;
         }
 
+        if (job->kind == WRITE_JOB_BATCH) {
+            dispatcher_run_batch(dispatcher, job);
+            write_job_release(job);
+            continue;
+        }
+        if (job->kind == WRITE_JOB_CALL) {
+            job->status_code = job->call(dispatcher->db, job->call_arg);
+            pthread_mutex_lock(&dispatcher->mutex);
+            if (job->status_code >= 500) {
+                snprintf(dispatcher->last_error_logid, sizeof(dispatcher->last_error_logid), "%s", job->log_id);
+            } else {
+                snprintf(dispatcher->last_success_logid, sizeof(dispatcher->last_success_logid), "%s", job->log_id);
+            }
+            pthread_mutex_unlock(&dispatcher->mutex);
+            finalize_job(job);
+            write_job_release(job);
+            continue;
+        }
+        if (job->kind != WRITE_JOB_STORE) {
+            if (job->kind == WRITE_JOB_REFRESH) data_refresh_derived(dispatcher->db, job->account_id, job->logical_key);
+            job->status_code = 204;
+            finalize_job(job);
+            write_job_release(job);
+            continue;
+        }
+
         while (1) {
             sqlite3_reset(dispatcher->upsert_stmt);
             sqlite3_clear_bindings(dispatcher->upsert_stmt);
@@ -262,6 +446,7 @@ static void *write_dispatcher_thread_entry(void *arg) {
                         job->log_id);
                 } else {
                     job->status_code = 204;
+                    change_events_emit_local(job->account_id, job->logical_key, job->payload, job->payload_len);
                     pthread_mutex_lock(&dispatcher->mutex);
                     snprintf(dispatcher->last_success_logid, sizeof(dispatcher->last_success_logid), "%s", job->log_id);
                     pthread_mutex_unlock(&dispatcher->mutex);
@@ -277,7 +462,7 @@ static void *write_dispatcher_thread_entry(void *arg) {
                 break;
             }
 
-            if (rc == SQLITE_BUSY || rc == SQLITE_LOCKED || ext == SQLITE_BUSY_SNAPSHOT || ext == SQLITE_BUSY_TIMEOUT) {
+            if (sqlite_is_busy(rc, ext)) {
                 job->retry_count++;
                 log_warn(
                     "DATA WRITE retrying key=%s account=%s logid=%s attempt=%d rc=%d bytes=%zu",
@@ -412,44 +597,25 @@ void write_dispatcher_release(void) {
     pthread_mutex_unlock(&g_dispatcher.mutex);
 }
 
-int write_dispatch_submit(
-    const char *logical_key,
-    const char *storage_key,
-    const char *payload,
-    size_t payload_len,
-    const char *pending_path,
-    const char *account_id,
-    const char *log_id,
-    int wait_timeout_ms,
-    write_dispatch_result_t *out_result) {
-    if (!logical_key || !storage_key || !payload || !pending_path || !account_id || !log_id || !out_result) return -1;
-    memset(out_result, 0, sizeof(*out_result));
-
+static write_job_t *write_job_new(write_job_kind_t kind, const char *logical_key, const char *account_id, int refcount) {
     write_job_t *job = (write_job_t *)calloc(1, sizeof(write_job_t));
-    if (!job) return -1;
-
-    job->payload = (char *)malloc(payload_len + 1);
-    if (!job->payload) {
-        free(job);
-        return -1;
-    }
-
-    memcpy(job->payload, payload, payload_len);
-    job->payload[payload_len] = '\0';
-    job->payload_len = payload_len;
-    job->refcount = 2;
+    if (!job) return NULL;
+    job->kind = kind;
+    job->refcount = refcount;
     snprintf(job->logical_key, sizeof(job->logical_key), "%s", logical_key);
-    snprintf(job->storage_key, sizeof(job->storage_key), "%s", storage_key);
     snprintf(job->account_id, sizeof(job->account_id), "%s", account_id);
-    snprintf(job->log_id, sizeof(job->log_id), "%s", log_id);
-    snprintf(job->pending_path, sizeof(job->pending_path), "%s", pending_path);
     pthread_mutex_init(&job->mutex, NULL);
     pthread_cond_init(&job->cond, NULL);
+    return job;
+}
 
+/* Appends a job to the queue, or releases every reference to it when the dispatcher is not running. */
+static int dispatcher_enqueue(write_job_t *job) {
+    job->enqueued_ms = slowlog_now_ms();
     pthread_mutex_lock(&g_dispatcher.mutex);
     if (!g_dispatcher.running || g_dispatcher.stopping) {
         pthread_mutex_unlock(&g_dispatcher.mutex);
-        write_job_release(job);
+        while (job->refcount > 1) write_job_release(job);
         write_job_release(job);
         return -1;
     }
@@ -463,7 +629,11 @@ int write_dispatch_submit(
     g_dispatcher.queue_depth++;
     pthread_cond_signal(&g_dispatcher.cond);
     pthread_mutex_unlock(&g_dispatcher.mutex);
+    return 0;
+}
 
+/* Waits for a queued job and drops the caller's reference; 0 when it completed, 1 on timeout. */
+static int write_job_wait(write_job_t *job, int wait_timeout_ms, write_dispatch_result_t *out_result, write_batch_item_t *items) {
     pthread_mutex_lock(&job->mutex);
     if (!job->completed && !job->abandoned && wait_timeout_ms > 0) {
         struct timespec ts;
@@ -488,7 +658,11 @@ int write_dispatch_submit(
         out_result->sqlite_rc = job->sqlite_rc;
         out_result->sqlite_ext = job->sqlite_ext;
         out_result->retry_count = job->retry_count;
+        out_result->queue_ms = job->started_ms > job->enqueued_ms ? job->started_ms - job->enqueued_ms : 0.0;
         snprintf(out_result->backup_path, sizeof(out_result->backup_path), "%s", job->backup_path);
+        for (size_t i = 0; items && i < job->batch_count; i++) items[i].action = job->batch[i].action;
+        /* A call that ran hands its argument, and whatever it wrote there, back to the caller. */
+        job->call_free = NULL;
     }
     pthread_mutex_unlock(&job->mutex);
 
@@ -496,6 +670,141 @@ int write_dispatch_submit(
     return completed ? 0 : 1;
 }
 
+int write_dispatch_submit(
+    const char *logical_key,
+    const char *storage_key,
+    const char *payload,
+    size_t payload_len,
+    const char *pending_path,
+    const char *account_id,
+    const char *log_id,
+    int wait_timeout_ms,
+    write_dispatch_result_t *out_result) {
+    if (!logical_key || !storage_key || !payload || !pending_path || !account_id || !log_id || !out_result) return -1;
+    memset(out_result, 0, sizeof(*out_result));
+
+    write_job_t *job = write_job_new(WRITE_JOB_STORE, logical_key, account_id, 2);
+    if (!job) return -1;
+
+    job->payload = (char *)malloc(payload_len + 1);
+    if (!job->payload) {
+        write_job_release(job);
+        write_job_release(job);
+        return -1;
+    }
+
+    memcpy(job->payload, payload, payload_len);
+    job->payload[payload_len] = '\0';
+    job->payload_len = payload_len;
+    snprintf(job->storage_key, sizeof(job->storage_key), "%s", storage_key);
+    snprintf(job->log_id, sizeof(job->log_id), "%s", log_id);
+    snprintf(job->pending_path, sizeof(job->pending_path), "%s", pending_path);
+
+    if (dispatcher_enqueue(job) != 0) return -1;
+    return write_job_wait(job, wait_timeout_ms, out_result, NULL);
+}
+
+int write_dispatch_submit_batch(
+    write_batch_item_t *items,
+    size_t count,
+    const char *account_id,
+    const char *log_id,
+    int wait_timeout_ms,
+    write_dispatch_result_t *out_result) {
+    if (!items || count == 0 || !account_id || !log_id || !out_result) return -1;
+    memset(out_result, 0, sizeof(*out_result));
+
+    write_job_t *job = write_job_new(WRITE_JOB_BATCH, "", account_id, 2);
+    if (!job) return -1;
+    snprintf(job->log_id, sizeof(job->log_id), "%s", log_id);
+    job->batch = (write_batch_entry_t *)calloc(count, sizeof(write_batch_entry_t));
+    int copied = job->batch != NULL;
+    for (size_t i = 0; copied && i < count; i++) {
+        write_batch_entry_t *entry = &job->batch[i];
+        job->batch_count++;
+        entry->payload = (char *)malloc(items[i].payload_len + 1);
+        if (!entry->payload) {
+            copied = 0;
+            break;
+        }
+        memcpy(entry->payload, items[i].payload, items[i].payload_len);
+        entry->payload[items[i].payload_len] = '\0';
+        entry->payload_len = items[i].payload_len;
+        snprintf(entry->logical_key, sizeof(entry->logical_key), "%s", items[i].logical_key);
+        snprintf(entry->storage_key, sizeof(entry->storage_key), "%s", items[i].storage_key);
+        snprintf(entry->pending_path, sizeof(entry->pending_path), "%s", items[i].pending_path);
+    }
+    if (!copied) {
+        write_job_release(job);
+        write_job_release(job);
+        return -1;
+    }
+
+    if (dispatcher_enqueue(job) != 0) return -1;
+    return write_job_wait(job, wait_timeout_ms, out_result, items);
+}
+
+/*
+ * Queues a recompute of what derives from an account's key behind the writes already queued, so it
+ * sees them committed. Nobody waits for it, and one still queued for the same key absorbs the next.
+ */
+int write_dispatch_refresh(const char *account_id, const char *logical_key) {
+    if (!account_id || !logical_key) return -1;
+    /* Only a refresh queued behind the last store of the key sees it. */
+    int queued_behind = 0;
+    pthread_mutex_lock(&g_dispatcher.mutex);
+    for (write_job_t *queued = g_dispatcher.head; queued; queued = queued->next) {
+        if (queued->kind == WRITE_JOB_BARRIER || strcmp(queued->account_id, account_id) != 0 ||
+            strcmp(queued->logical_key, logical_key) != 0) {
+            continue;
+        }
+        queued_behind = queued->kind == WRITE_JOB_REFRESH;
+    }
+    pthread_mutex_unlock(&g_dispatcher.mutex);
+    if (queued_behind) return 0;
+
+    write_job_t *job = write_job_new(WRITE_JOB_REFRESH, logical_key, account_id, 1);
+    if (!job) return -1;
+    return dispatcher_enqueue(job);
+}
+
+int write_dispatch_call(
+    write_call_fn call,
+    void *arg,
+    void (*free_arg)(void *arg),
+    const char *account_id,
+    const char *log_id,
+    int wait_timeout_ms,
+    write_dispatch_result_t *out_result) {
+    if (!call || !account_id || !log_id || !out_result) {
+        if (free_arg) free_arg(arg);
+        return -1;
+    }
+    memset(out_result, 0, sizeof(*out_result));
+
+    write_job_t *job = write_job_new(WRITE_JOB_CALL, "", account_id, 2);
+    if (!job) {
+        if (free_arg) free_arg(arg);
+        return -1;
+    }
+    job->call = call;
+    job->call_arg = arg;
+    job->call_free = free_arg;
+    snprintf(job->log_id, sizeof(job->log_id), "%s", log_id);
+
+    if (dispatcher_enqueue(job) != 0) return -1;
+    return write_job_wait(job, wait_timeout_ms, out_result, NULL);
+}
+
+int write_dispatch_drain(int wait_timeout_ms) {
+    write_job_t *job = write_job_new(WRITE_JOB_BARRIER, "", "", 2);
+    if (!job) return -1;
+    if (dispatcher_enqueue(job) != 0) return -1;
+    write_dispatch_result_t result;
+    memset(&result, 0, sizeof(result));
+    return write_job_wait(job, wait_timeout_ms, &result, NULL);
+}
+
 void write_dispatch_diagnostics_snapshot(write_dispatch_diagnostics_t *out_diag) {
     if (!out_diag) return;
     memset(out_diag, 0, sizeof(*out_diag));
@@ -0,0 +1,307 @@
+#define _GNU_SOURCE
+
+#include "server.h"
+#include "server_internal.h"
+#include "logger.h"
+
+#include <errno.h>
+#include <poll.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * Change notifications over WebSocket. GET /v1/ws completes the RFC 6455 handshake on the worker,
+ * then hands a duplicate of the socket to one hub thread and lets the worker close its own copy as
+ * for any other request. The hub subscribes to the change-event hub, so every stored write of the
+ * account (local or, with Redis, from another instance) is pushed as a text frame
+ * {"key","updated_at","revision"}; revision is the document version the write produced. The channel
+ * is server-to-client only: clients may ping and close, any data frame closes it with 1003.
+ * A client that cannot take a frame without blocking is dropped rather than stalling the writer,
+ * and one that answers no ping for WS_IDLE_TIMEOUT_SEC is closed. Not offered through the built-in
+ * TLS listener; terminate TLS in front of the server to use it over wss://.
+ */
+
+#define WS_DEFAULT_MAX_CLIENTS 256
+#define WS_CLIENTS_CAP 4096
+#define WS_PING_INTERVAL_SEC 30
+#define WS_IDLE_TIMEOUT_SEC 90
+#define WS_CONTROL_MAX 125
+#define WS_GUID "258EAFA5-E914-47DA-95CA-C5AB0DC85B11"
+
+typedef struct {
+    int fd;
+    char account_id[128];
+    unsigned char buf[2 + 4 + 8 + WS_CONTROL_MAX];
+    size_t len;
+    time_t opened_at;
+    time_t last_seen;
+} ws_client_t;
+
+static pthread_mutex_t g_ws_mutex = PTHREAD_MUTEX_INITIALIZER;
+static pthread_once_t g_ws_once = PTHREAD_ONCE_INIT;
+static ws_client_t *g_clients;
+static size_t g_max_clients;
+static int g_ws_running;
+
+static size_t ws_max_clients(void) {
+    const char *raw = getenv("FRICU_WS_MAX_CLIENTS");
+    if (!raw || raw[0] == '\0') return WS_DEFAULT_MAX_CLIENTS;
+    char *end = NULL;
+    long value = strtol(raw, &end, 10);
+    return end && *end == '\0' && value > 0 && value <= WS_CLIENTS_CAP ? (size_t)value : WS_DEFAULT_MAX_CLIENTS;
+}
+
+/* Whole frame or nothing: a short or would-block write fails so the caller can drop the client. */
+static int ws_send_frame(int fd, int opcode, const void *payload, size_t len) {
+    unsigned char frame[4 + 1024];
+    size_t header = len < 126 ? 2 : 4;
+    if (len > 0xffff || header + len > sizeof(frame)) return -1;
+    frame[0] = (unsigned char)(0x80 | opcode);
+    if (len < 126) {
+        frame[1] = (unsigned char)len;
+    } else {
+        frame[1] = 126;
+        frame[2] = (unsigned char)(len >> 8);
+        frame[3] = (unsigned char)len;
+    }
+    if (len > 0) memcpy(frame + header, payload, len);
+    ssize_t n = send(fd, frame, header + len, MSG_DONTWAIT | socket_send_flags());
+    return n == (ssize_t)(header + len) ? 0 : -1;
+}
+
+/* Called with g_ws_mutex held. */
+static void ws_drop(ws_client_t *client, const char *reason) {
+    log_info("WS closed reason=%s account=%s open_for=%llds", reason, client->account_id, (long long)(time(NULL) - client->opened_at));
+    close(client->fd);
+    memset(client, 0, sizeof(*client));
+    client->fd = -1;
+}
+
+static void ws_close(ws_client_t *client, int code, const char *reason) {
+    unsigned char payload[2] = {(unsigned char)(code >> 8), (unsigned char)code};
+    ws_send_frame(client->fd, 0x8, payload, sizeof(payload));
+    ws_drop(client, reason);
+}
+
+static void on_change_event(const change_event_t *event, void *user) {
+    (void)user;
+    strbuf_t sb;
+    strbuf_init(&sb);
+    strbuf_append(&sb, "{\"key\":", 7);
+    strbuf_append_json_string(&sb, event->key);
+    strbuf_appendf(&sb, ",\"updated_at\":%lld,\"revision\":", event->updated_at);
+    strbuf_append_json_string(&sb, event->version);
+    strbuf_append(&sb, "}", 1);
+    if (sb.failed) {
+        strbuf_free(&sb);
+        return;
+    }
+    pthread_mutex_lock(&g_ws_mutex);
+    for (size_t i = 0; i < g_max_clients; i++) {
+        ws_client_t *client = &g_clients[i];
+        if (client->fd < 0 || strcmp(client->account_id, event->account_id) != 0) continue;
+        if (ws_send_frame(client->fd, 0x1, strbuf_cstr(&sb), sb.len) != 0) ws_drop(client, "slow_consumer");
+    }
+    pthread_mutex_unlock(&g_ws_mutex);
+    strbuf_free(&sb);
+}
+
+/* Consumes complete frames from the client's buffer; returns -1 once the client has been closed. */
+static int ws_process_frames(ws_client_t *client) {
+    while (client->len >= 2) {
+        unsigned char *p = client->buf;
+        int opcode = p[0] & 0x0f;
+        int masked = (p[1] & 0x80) != 0;
+        size_t len = p[1] & 0x7f;
+        if (!masked) {
+            ws_close(client, 1002, "unmasked_frame");
+            return -1;
+        }
+        if (opcode != 0x8 && opcode != 0x9 && opcode != 0xa) {
+            ws_close(client, 1003, "data_frame");
+            return -1;
+        }
+        if (len > WS_CONTROL_MAX || !(p[0] & 0x80)) {
+            ws_close(client, 1002, "bad_control_frame");
+            return -1;
+        }
+        size_t frame_len = 2 + 4 + len;
+        if (client->len < frame_len) return 0;
+        unsigned char payload[WS_CONTROL_MAX];
+        for (size_t i = 0; i < len; i++) payload[i] = p[6 + i] ^ p[2 + i % 4];
+        memmove(client->buf, client->buf + frame_len, client->len - frame_len);
+        client->len -= frame_len;
+        if (opcode == 0x8) {
+            ws_send_frame(client->fd, 0x8, payload, len >= 2 ? 2 : 0);
+            ws_drop(client, "client_close");
+            return -1;
+        }
+        if (opcode == 0x9 && ws_send_frame(client->fd, 0xa, payload, len) != 0) {
+            ws_drop(client, "slow_consumer");
+            return -1;
+        }
+    }
+    return 0;
+}
+
+static void ws_read_client(ws_client_t *client) {
+    for (;;) {
+        ssize_t n = recv(client->fd, client->buf + client->len, sizeof(client->buf) - client->len, MSG_DONTWAIT);
+        if (n > 0) {
+            client->len += (size_t)n;
+            client->last_seen = time(NULL);
+            if (ws_process_frames(client) != 0) return;
+            continue;
+        }
+        if (n < 0 && errno == EINTR) continue;
+        if (n < 0 && (errno == EAGAIN || errno == EWOULDBLOCK)) return;
+        ws_drop(client, "disconnected");
+        return;
+    }
+}
+
+static void *ws_thread_entry(void *arg) {
+    (void)arg;
+    struct pollfd *pfds = (struct pollfd *)calloc(g_max_clients, sizeof(struct pollfd));
+    if (!pfds) return NULL;
+    time_t last_ping = time(NULL);
+    for (;;) {
+        size_t count = 0;
+        pthread_mutex_lock(&g_ws_mutex);
+        for (size_t i = 0; i < g_max_clients; i++) {
+            if (g_clients[i].fd < 0) continue;
+            pfds[count].fd = g_clients[i].fd;
+            pfds[count].events = POLLIN;
+            pfds[count].revents = 0;
+            count++;
+        }
+        pthread_mutex_unlock(&g_ws_mutex);
+
+        /* New clients join on the next round, so keep the wait short. */
+        int ready = poll(pfds, (nfds_t)count, 1000);
+        time_t now = time(NULL);
+        pthread_mutex_lock(&g_ws_mutex);
+        for (size_t i = 0; ready > 0 && i < count; i++) {
+            if (pfds[i].revents == 0) continue;
+            for (size_t j = 0; j < g_max_clients; j++) {
+                if (g_clients[j].fd == pfds[i].fd) ws_read_client(&g_clients[j]);
+            }
+        }
+        if (now - last_ping >= WS_PING_INTERVAL_SEC) {
+            last_ping = now;
+            for (size_t i = 0; i < g_max_clients; i++) {
+                ws_client_t *client = &g_clients[i];
+                if (client->fd < 0) continue;
+                if (now - client->last_seen > WS_IDLE_TIMEOUT_SEC) {
+                    ws_close(client, 1001, "idle");
+                } else if (ws_send_frame(client->fd, 0x9, NULL, 0) != 0) {
+                    ws_drop(client, "slow_consumer");
+                }
+            }
+        }
+        pthread_mutex_unlock(&g_ws_mutex);
+    }
+    return NULL;
+}
+
+static void ws_start(void) {
+    size_t max_clients = ws_max_clients();
+    ws_client_t *clients = (ws_client_t *)calloc(max_clients, sizeof(ws_client_t));
+    if (!clients) return;
+    for (size_t i = 0; i < max_clients; i++) clients[i].fd = -1;
+    g_clients = clients;
+    g_max_clients = max_clients;
+    pthread_t thread;
+    if (pthread_create(&thread, NULL, ws_thread_entry, NULL) != 0) {
+        log_error("failed to start websocket thread");
+        return;
+    }
+    pthread_detach(thread);
+    change_events_subscribe(on_change_event, NULL);
+    g_ws_running = 1;
+}
+
+static int ws_header_has_token(const char *value, const char *token) {
+    size_t token_len = strlen(token);
+    const char *p = value;
+    while (*p) {
+        p += strspn(p, " \t,");
+        size_t len = strcspn(p, ",");
+        while (len > 0 && (p[len - 1] == ' ' || p[len - 1] == '\t')) len--;
+        if (len == token_len && strncasecmp(p, token, token_len) == 0) return 1;
+        p += strcspn(p, ",");
+    }
+    return 0;
+}
+
+/* Sec-WebSocket-Accept: base64(SHA-1(key + GUID)). */
+void ws_accept_key(const char *key, char *out, size_t out_len) {
+    char joined[128] = {0};
+    unsigned char digest[20];
+    snprintf(joined, sizeof(joined), "%s" WS_GUID, key);
+    sha1_digest(joined, strlen(joined), digest);
+    base64_encode(digest, sizeof(digest), out, out_len);
+}
+
+int handle_get_ws(int fd, const http_request_t *req, const request_log_context_t *ctx) {
+    char upgrade[32] = {0};
+    char connection[128] = {0};
+    char version[8] = {0};
+    char key[64] = {0};
+    if (!http_request_header(req, "Upgrade", upgrade, sizeof(upgrade)) || strcasecmp(upgrade, "websocket") != 0 ||
+        !http_request_header(req, "Connection", connection, sizeof(connection)) || !ws_header_has_token(connection, "upgrade") ||
+        !http_request_header(req, "Sec-WebSocket-Key", key, sizeof(key)) || strlen(key) != 24) {
+        send_response_with_log_context(fd, 400, "Bad Request", "{\"error\":\"websocket upgrade required\"}", ctx);
+        return 400;
+    }
+    if (!http_request_header(req, "Sec-WebSocket-Version", version, sizeof(version)) || strcmp(version, "13") != 0) {
+        send_http_response(fd, 426, "Upgrade Required", "application/json", "Sec-WebSocket-Version: 13\r\n", "{\"error\":\"websocket version 13 required\"}", 41, ctx);
+        return 426;
+    }
+    if (tls_enabled()) {
+        send_response_with_log_context(fd, 501, "Not Implemented", "{\"error\":\"websocket is not offered over the built-in TLS listener\"}", ctx);
+        return 501;
+    }
+
+    pthread_once(&g_ws_once, ws_start);
+    int client_fd = -1;
+    ws_client_t *slot = NULL;
+    pthread_mutex_lock(&g_ws_mutex);
+    for (size_t i = 0; g_ws_running && i < g_max_clients && !slot; i++) {
+        if (g_clients[i].fd < 0) slot = &g_clients[i];
+    }
+    if (slot) client_fd = dup(fd);
+    if (client_fd < 0) {
+        pthread_mutex_unlock(&g_ws_mutex);
+        send_response_with_log_context(fd, 503, "Service Unavailable", "{\"error\":\"too many websocket clients\"}", ctx);
+        return 503;
+    }
+
+    char accept[32] = {0};
+    char response[256] = {0};
+    ws_accept_key(key, accept, sizeof(accept));
+    int n = snprintf(
+        response,
+        sizeof(response),
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: %s\r\n\r\n",
+        accept);
+    accesslog_note_response(101, 0);
+    if (send(client_fd, response, (size_t)n, socket_send_flags()) != n) {
+        pthread_mutex_unlock(&g_ws_mutex);
+        close(client_fd);
+        return 500;
+    }
+    slot->fd = client_fd;
+    snprintf(slot->account_id, sizeof(slot->account_id), "%s", ctx->account_id);
+    slot->len = 0;
+    slot->opened_at = time(NULL);
+    slot->last_seen = slot->opened_at;
+    pthread_mutex_unlock(&g_ws_mutex);
+    log_info("WS open account=%s logid=%s", ctx->account_id, ctx->log_id);
+    return 101;
+}